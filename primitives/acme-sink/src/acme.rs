@@ -0,0 +1,478 @@
+//! A small ACME (RFC 8555) client.
+//!
+//! Covers what issuance needs and nothing more: registering (or finding)
+//! the account for a P-256 key, placing an order, answering one challenge
+//! per authorization through the [`Solvers`], finalizing with a CSR for a
+//! fresh P-256 certificate key, and downloading the chain.
+
+use crate::challenge::{ChallengeType, Solvers};
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use rcgen::{CertificateParams, DistinguishedName};
+use reqwest::{Client, Response, header};
+use ring::digest::{SHA256, digest};
+use ring::rand::SystemRandom;
+use ring::signature::{ECDSA_P256_SHA256_FIXED_SIGNING, EcdsaKeyPair, KeyPair};
+use serde::Deserialize;
+use serde::de::DeserializeOwned;
+use serde_json::{Value, json};
+use std::fs;
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+use std::sync::{Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+/// Delay between polls of pending authorizations and orders.
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Attempts per request when the server rejects the nonce.
+const NONCE_ATTEMPTS: usize = 3;
+
+pub(crate) fn b64(bytes: &[u8]) -> String {
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+/// Write `contents` to `path` readable by the owner only, replacing it atomically.
+pub(crate) fn write_private(path: &Path, contents: &[u8]) -> Result<(), String> {
+    let tmp = path.with_extension("tmp");
+    fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(&tmp)
+        .and_then(|mut file| file.write_all(contents))
+        .and_then(|()| fs::rename(&tmp, path))
+        .map_err(|e| format!("{}: {e}", path.display()))
+}
+
+/// The account key, signing every request.
+pub struct AccountKey {
+    pair: EcdsaKeyPair,
+    rng: SystemRandom,
+}
+
+impl AccountKey {
+    /// Load the PKCS#8 PEM key at `path`, generating it first if missing.
+    pub fn load_or_create(path: &Path) -> Result<Self, String> {
+        let pem = match fs::read_to_string(path) {
+            Ok(pem) => pem,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                let pem = rcgen::KeyPair::generate()
+                    .map_err(|e| format!("generate account key: {e}"))?
+                    .serialize_pem();
+                write_private(path, pem.as_bytes())?;
+                eprintln!("Generated account key {}", path.display());
+                pem
+            }
+            Err(e) => return Err(format!("{}: {e}", path.display())),
+        };
+        Self::from_pem(&pem).map_err(|e| format!("{}: {e}", path.display()))
+    }
+
+    pub fn from_pem(pem: &str) -> Result<Self, String> {
+        let der = rcgen::KeyPair::from_pem(pem)
+            .map_err(|e| e.to_string())?
+            .serialize_der();
+        let rng = SystemRandom::new();
+        let pair = EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &der, &rng)
+            .map_err(|_| "not a P-256 key".to_string())?;
+        Ok(Self { pair, rng })
+    }
+
+    fn jwk(&self) -> Value {
+        // An uncompressed point: 0x04, then x and y
+        let point = self.pair.public_key().as_ref();
+        json!({
+            "crv": "P-256",
+            "kty": "EC",
+            "x": b64(&point[1..33]),
+            "y": b64(&point[33..]),
+        })
+    }
+
+    /// The RFC 7638 thumbprint that key authorizations end with.
+    pub fn thumbprint(&self) -> String {
+        // serde_json orders object members, giving the required canonical form
+        b64(digest(&SHA256, self.jwk().to_string().as_bytes()).as_ref())
+    }
+
+    fn sign(&self, message: &[u8]) -> Result<Vec<u8>, String> {
+        self.pair
+            .sign(&self.rng, message)
+            .map(|signature| signature.as_ref().to_vec())
+            .map_err(|_| "signing failed".to_string())
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Directory {
+    new_nonce: String,
+    new_account: String,
+    new_order: String,
+}
+
+#[derive(Deserialize)]
+struct Order {
+    status: String,
+    #[serde(default)]
+    authorizations: Vec<String>,
+    finalize: String,
+    certificate: Option<String>,
+    error: Option<Value>,
+}
+
+#[derive(Deserialize)]
+struct Identifier {
+    value: String,
+}
+
+#[derive(Deserialize)]
+struct Authorization {
+    status: String,
+    identifier: Identifier,
+    challenges: Vec<Challenge>,
+}
+
+#[derive(Deserialize)]
+struct Challenge {
+    #[serde(rename = "type")]
+    kind: String,
+    url: String,
+    #[serde(default)]
+    token: String,
+    error: Option<Value>,
+}
+
+/// A certificate chain and its private key, both PEM.
+pub struct Issued {
+    pub chain: String,
+    pub key: String,
+}
+
+/// Why an ACME object failed, from its `error` problem document.
+fn problem(error: Option<&Value>) -> String {
+    match error {
+        Some(error) => error["detail"]
+            .as_str()
+            .or_else(|| error["type"].as_str())
+            .unwrap_or("no detail")
+            .to_string(),
+        None => "no detail".to_string(),
+    }
+}
+
+fn location(response: &Response) -> Option<String> {
+    header_value(response, header::LOCATION.as_str())
+}
+
+fn header_value(response: &Response, name: &str) -> Option<String> {
+    response
+        .headers()
+        .get(name)
+        .and_then(|v| v.to_str().ok())
+        .map(str::to_string)
+}
+
+/// A registered account at one ACME server.
+pub struct Acme {
+    http: Client,
+    directory: Directory,
+    key: AccountKey,
+    /// The account URL, once registered.
+    kid: Option<String>,
+    nonce: Mutex<Option<String>>,
+}
+
+impl Acme {
+    /// Register the account for `key` at the server behind `directory_url`,
+    /// or look it up if it exists.
+    pub async fn connect(
+        http: Client,
+        directory_url: &str,
+        key: AccountKey,
+        email: Option<&str>,
+    ) -> Result<Self, String> {
+        let directory = http
+            .get(directory_url)
+            .send()
+            .await
+            .and_then(Response::error_for_status)
+            .map_err(|e| format!("directory: {e}"))?
+            .json()
+            .await
+            .map_err(|e| format!("directory: {e}"))?;
+        let mut acme = Self {
+            http,
+            directory,
+            key,
+            kid: None,
+            nonce: Mutex::new(None),
+        };
+
+        let mut account = json!({ "termsOfServiceAgreed": true });
+        if let Some(email) = email {
+            account["contact"] = json!([format!("mailto:{email}")]);
+        }
+        let url = acme.directory.new_account.clone();
+        let response = acme.post(&url, Some(&account)).await?;
+        acme.kid = Some(location(&response).ok_or("newAccount: no account URL")?);
+        Ok(acme)
+    }
+
+    async fn nonce(&self) -> Result<String, String> {
+        let saved = self
+            .nonce
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .take();
+        if let Some(nonce) = saved {
+            return Ok(nonce);
+        }
+        let response = self
+            .http
+            .head(&self.directory.new_nonce)
+            .send()
+            .await
+            .map_err(|e| format!("newNonce: {e}"))?;
+        header_value(&response, "replay-nonce").ok_or_else(|| "newNonce: no nonce".to_string())
+    }
+
+    /// POST a JWS carrying `payload` to `url`; `None` is a POST-as-GET.
+    async fn post(&self, url: &str, payload: Option<&Value>) -> Result<Response, String> {
+        let payload = payload
+            .map(|p| b64(p.to_string().as_bytes()))
+            .unwrap_or_default();
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            let mut protected = json!({ "alg": "ES256", "nonce": self.nonce().await?, "url": url });
+            match &self.kid {
+                Some(kid) => protected["kid"] = json!(kid),
+                None => protected["jwk"] = self.key.jwk(),
+            }
+            let protected = b64(protected.to_string().as_bytes());
+            let signature = self.key.sign(format!("{protected}.{payload}").as_bytes())?;
+            let body = json!({
+                "protected": protected,
+                "payload": payload,
+                "signature": b64(&signature),
+            });
+            let response = self
+                .http
+                .post(url)
+                .header(header::CONTENT_TYPE, "application/jose+json")
+                .body(body.to_string())
+                .send()
+                .await
+                .map_err(|e| format!("{url}: {e}"))?;
+            if let Some(nonce) = header_value(&response, "replay-nonce") {
+                *self.nonce.lock().unwrap_or_else(PoisonError::into_inner) = Some(nonce);
+            }
+
+            let status = response.status();
+            if status.is_success() {
+                return Ok(response);
+            }
+            let error: Value = response.json().await.unwrap_or_default();
+            // Nonces expire; the server sent a fresh one with the rejection
+            if error["type"] == "urn:ietf:params:acme:error:badNonce" && attempt < NONCE_ATTEMPTS {
+                continue;
+            }
+            return Err(format!("{url}: {status} {}", problem(Some(&error))));
+        }
+    }
+
+    async fn fetch<T: DeserializeOwned>(&self, url: &str) -> Result<T, String> {
+        self.post(url, None)
+            .await?
+            .json()
+            .await
+            .map_err(|e| format!("{url}: {e}"))
+    }
+
+    /// Fetch `url` until `done` holds for it or `deadline` passes.
+    async fn poll<T: DeserializeOwned>(
+        &self,
+        url: &str,
+        deadline: Instant,
+        done: impl Fn(&T) -> bool,
+    ) -> Result<T, String> {
+        loop {
+            let object = self.fetch(url).await?;
+            if done(&object) {
+                return Ok(object);
+            }
+            if Instant::now() + POLL_INTERVAL > deadline {
+                return Err(format!("{url}: timed out"));
+            }
+            tokio::time::sleep(POLL_INTERVAL).await;
+        }
+    }
+
+    /// Answer the `kind` challenge of the authorization at `url`.
+    async fn authorize(
+        &self,
+        url: &str,
+        kind: ChallengeType,
+        solvers: &Solvers,
+        deadline: Instant,
+    ) -> Result<(), String> {
+        let authorization: Authorization = self.fetch(url).await?;
+        if authorization.status == "valid" {
+            return Ok(());
+        }
+        let domain = authorization.identifier.value;
+        let Some(challenge) = authorization
+            .challenges
+            .iter()
+            .find(|c| c.kind == kind.as_str())
+        else {
+            return Err(format!("{domain}: no {} challenge offered", kind.as_str()));
+        };
+        let key_authorization = format!("{}.{}", challenge.token, self.key.thumbprint());
+
+        solvers
+            .present(kind, &domain, &challenge.token, &key_authorization)
+            .await?;
+        let result = async {
+            self.post(&challenge.url, Some(&json!({}))).await?;
+            let authorization: Authorization = self
+                .poll(url, deadline, |a: &Authorization| a.status != "pending")
+                .await?;
+            if authorization.status == "valid" {
+                return Ok(());
+            }
+            let error = authorization
+                .challenges
+                .iter()
+                .find(|c| c.kind == kind.as_str())
+                .and_then(|c| c.error.as_ref());
+            Err(format!(
+                "{domain}: authorization {}: {}",
+                authorization.status,
+                problem(error)
+            ))
+        }
+        .await;
+        solvers
+            .cleanup(kind, &domain, &challenge.token, &key_authorization)
+            .await;
+        result
+    }
+
+    /// Issue a certificate for `domains`, answering `kind` challenges.
+    pub async fn issue(
+        &self,
+        domains: &[String],
+        kind: ChallengeType,
+        solvers: &Solvers,
+        timeout: Duration,
+    ) -> Result<Issued, String> {
+        let deadline = Instant::now() + timeout;
+        let identifiers: Vec<Value> = domains
+            .iter()
+            .map(|domain| json!({ "type": "dns", "value": domain }))
+            .collect();
+        let response = self
+            .post(
+                &self.directory.new_order,
+                Some(&json!({ "identifiers": identifiers })),
+            )
+            .await?;
+        let order_url = location(&response).ok_or("newOrder: no order URL")?;
+        let order: Order = response
+            .json()
+            .await
+            .map_err(|e| format!("newOrder: {e}"))?;
+
+        for url in &order.authorizations {
+            self.authorize(url, kind, solvers, deadline).await?;
+        }
+        let order: Order = self
+            .poll(&order_url, deadline, |o: &Order| o.status != "pending")
+            .await?;
+        if order.status != "ready" {
+            return Err(format!(
+                "order {}: {}",
+                order.status,
+                problem(order.error.as_ref())
+            ));
+        }
+
+        let key = rcgen::KeyPair::generate().map_err(|e| format!("generate key: {e}"))?;
+        let mut params = CertificateParams::new(domains.to_vec()).map_err(|e| e.to_string())?;
+        params.distinguished_name = DistinguishedName::new();
+        let csr = params
+            .serialize_request(&key)
+            .map_err(|e| format!("CSR: {e}"))?;
+        self.post(&order.finalize, Some(&json!({ "csr": b64(csr.der()) })))
+            .await?;
+        let order: Order = self
+            .poll(&order_url, deadline, |o: &Order| {
+                o.status != "ready" && o.status != "processing"
+            })
+            .await?;
+        let Some(certificate) = order.certificate.filter(|_| order.status == "valid") else {
+            return Err(format!(
+                "order {}: {}",
+                order.status,
+                problem(order.error.as_ref())
+            ));
+        };
+
+        let chain = self
+            .post(&certificate, None)
+            .await?
+            .text()
+            .await
+            .map_err(|e| format!("{certificate}: {e}"))?;
+        Ok(Issued {
+            chain,
+            key: key.serialize_pem(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ring::signature::{ECDSA_P256_SHA256_FIXED, UnparsedPublicKey};
+
+    #[test]
+    fn signatures_verify_against_the_jwk() {
+        let pem = rcgen::KeyPair::generate()
+            .unwrap_or_else(|e| panic!("{e}"))
+            .serialize_pem();
+        let key = AccountKey::from_pem(&pem).unwrap_or_else(|e| panic!("{e}"));
+        let signature = key
+            .sign(b"header.payload")
+            .unwrap_or_else(|e| panic!("{e}"));
+
+        let jwk = key.jwk();
+        let coordinate = |name: &str| {
+            URL_SAFE_NO_PAD
+                .decode(jwk[name].as_str().unwrap_or_default())
+                .unwrap_or_else(|e| panic!("{e}"))
+        };
+        let point = [vec![4], coordinate("x"), coordinate("y")].concat();
+        assert!(
+            UnparsedPublicKey::new(&ECDSA_P256_SHA256_FIXED, &point)
+                .verify(b"header.payload", &signature)
+                .is_ok()
+        );
+
+        // Thumbprints hash the members in lexicographic order without whitespace
+        let canonical = format!(
+            r#"{{"crv":"P-256","kty":"EC","x":"{}","y":"{}"}}"#,
+            jwk["x"].as_str().unwrap_or_default(),
+            jwk["y"].as_str().unwrap_or_default()
+        );
+        assert_eq!(
+            key.thumbprint(),
+            b64(digest(&SHA256, canonical.as_bytes()).as_ref())
+        );
+    }
+}
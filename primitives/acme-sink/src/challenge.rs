@@ -0,0 +1,242 @@
+//! Answering ACME challenges.
+//!
+//! - `http-01` - the key authorization is served at
+//!   `http://<domain>/.well-known/acme-challenge/<token>`, either as a file
+//!   written under `--webroot` for an existing web server, or by a listener
+//!   the sink runs on `--http01-port`.
+//! - `dns-01` - a TXT record is created and removed by `--dns-hook`, a
+//!   command that gets the record as JSON on stdin, so any DNS provider's
+//!   API or CLI can be plugged in:
+//!
+//! ```json
+//! {"action": "present", "domain": "example.com", "record": "_acme-challenge.example.com", "value": "..."}
+//! ```
+//!
+//! The hook is run again with `"action": "cleanup"` once the challenge is
+//! decided. After `present`, the sink waits `--dns-propagation` seconds
+//! before asking the CA to look.
+
+use crate::acme::b64;
+use axum::{
+    Router,
+    extract::{Path as UrlPath, State},
+    http::StatusCode,
+    routing::get,
+};
+use clap::{Args, ValueEnum};
+use exec_common::{error_to_json, execute_command};
+use primitive_common::doctor::{Report, check_executable, check_writable_dir};
+use ring::digest::{SHA256, digest};
+use serde_json::json;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Duration;
+
+/// How domain control is proven.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ChallengeType {
+    #[value(name = "http-01")]
+    Http01,
+    #[value(name = "dns-01")]
+    Dns01,
+}
+
+impl ChallengeType {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Http01 => "http-01",
+            Self::Dns01 => "dns-01",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        <Self as ValueEnum>::from_str(s, true).ok()
+    }
+}
+
+/// Challenge settings.
+#[derive(Args, Debug, Clone)]
+pub struct ChallengeArgs {
+    /// Challenge type used unless a payload names one.
+    #[arg(long, env = "ACME_SINK_CHALLENGE", value_enum, default_value_t = ChallengeType::Http01)]
+    pub challenge: ChallengeType,
+
+    /// Answer http-01 challenges with files under this web root.
+    #[arg(long, env = "ACME_SINK_WEBROOT")]
+    pub webroot: Option<PathBuf>,
+
+    /// Answer http-01 challenges from a listener on this port.
+    #[arg(long, env = "ACME_SINK_HTTP01_PORT", conflicts_with = "webroot")]
+    pub http01_port: Option<u16>,
+
+    /// Host the http-01 listener binds to.
+    #[arg(long, env = "ACME_SINK_HTTP01_HOST", default_value = "0.0.0.0")]
+    pub http01_host: String,
+
+    /// Command creating and removing dns-01 TXT records.
+    #[arg(long, env = "ACME_SINK_DNS_HOOK")]
+    pub dns_hook: Option<String>,
+
+    /// Seconds to wait for a new TXT record to propagate.
+    #[arg(long, env = "ACME_SINK_DNS_PROPAGATION", default_value = "30")]
+    pub dns_propagation: u64,
+
+    /// Milliseconds each run of the DNS hook may take.
+    #[arg(long, env = "ACME_SINK_DNS_HOOK_TIMEOUT", default_value = "60000")]
+    pub dns_hook_timeout: u64,
+}
+
+/// Key authorizations the http-01 listener serves, by token.
+type Tokens = Arc<Mutex<HashMap<String, String>>>;
+
+/// The configured ways of answering challenges.
+pub struct Solvers {
+    webroot: Option<PathBuf>,
+    tokens: Option<Tokens>,
+    dns_hook: Option<Vec<String>>,
+    dns_propagation: Duration,
+    dns_hook_timeout: u64,
+}
+
+impl Solvers {
+    pub fn new(args: &ChallengeArgs) -> Self {
+        Self {
+            webroot: args.webroot.clone(),
+            tokens: args.http01_port.map(|_| Tokens::default()),
+            dns_hook: args
+                .dns_hook
+                .as_deref()
+                .map(|hook| hook.split_whitespace().map(str::to_string).collect())
+                .filter(|hook: &Vec<String>| !hook.is_empty()),
+            dns_propagation: Duration::from_secs(args.dns_propagation),
+            dns_hook_timeout: args.dns_hook_timeout,
+        }
+    }
+
+    /// Whether `kind` challenges can be answered.
+    pub fn supports(&self, kind: ChallengeType) -> bool {
+        match kind {
+            ChallengeType::Http01 => self.webroot.is_some() || self.tokens.is_some(),
+            ChallengeType::Dns01 => self.dns_hook.is_some(),
+        }
+    }
+
+    /// The http-01 listener's routes, with `--http01-port`.
+    pub fn router(&self) -> Option<Router> {
+        let tokens = Arc::clone(self.tokens.as_ref()?);
+        Some(
+            Router::new()
+                .route("/.well-known/acme-challenge/{token}", get(serve_token))
+                .with_state(tokens),
+        )
+    }
+
+    pub fn self_test(&self, report: &mut Report) {
+        if let Some(webroot) = &self.webroot {
+            report.check("webroot", check_writable_dir(&challenge_dir(webroot)));
+        }
+        if let Some(hook) = &self.dns_hook {
+            report.check("dns hook", check_executable(&hook[0]));
+        }
+    }
+
+    /// Make `key_authorization` visible to the CA.
+    pub async fn present(
+        &self,
+        kind: ChallengeType,
+        domain: &str,
+        token: &str,
+        key_authorization: &str,
+    ) -> Result<(), String> {
+        match kind {
+            ChallengeType::Http01 => {
+                if let Some(tokens) = &self.tokens {
+                    tokens
+                        .lock()
+                        .unwrap_or_else(PoisonError::into_inner)
+                        .insert(token.to_string(), key_authorization.to_string());
+                }
+                if let Some(webroot) = &self.webroot {
+                    let dir = challenge_dir(webroot);
+                    std::fs::create_dir_all(&dir)
+                        .and_then(|()| std::fs::write(dir.join(token), key_authorization))
+                        .map_err(|e| format!("{}: {e}", dir.display()))?;
+                }
+                Ok(())
+            }
+            ChallengeType::Dns01 => {
+                self.dns("present", domain, key_authorization).await?;
+                tokio::time::sleep(self.dns_propagation).await;
+                Ok(())
+            }
+        }
+    }
+
+    /// Withdraw what [`present`](Self::present) set up; failures are only logged.
+    pub async fn cleanup(
+        &self,
+        kind: ChallengeType,
+        domain: &str,
+        token: &str,
+        key_authorization: &str,
+    ) {
+        match kind {
+            ChallengeType::Http01 => {
+                if let Some(tokens) = &self.tokens {
+                    tokens
+                        .lock()
+                        .unwrap_or_else(PoisonError::into_inner)
+                        .remove(token);
+                }
+                if let Some(webroot) = &self.webroot {
+                    let _ = std::fs::remove_file(challenge_dir(webroot).join(token));
+                }
+            }
+            ChallengeType::Dns01 => {
+                if let Err(e) = self.dns("cleanup", domain, key_authorization).await {
+                    eprintln!("DNS cleanup for {domain} failed: {e}");
+                }
+            }
+        }
+    }
+
+    async fn dns(&self, action: &str, domain: &str, key_authorization: &str) -> Result<(), String> {
+        let Some(hook) = &self.dns_hook else {
+            return Err("no --dns-hook configured".to_string());
+        };
+        let record = json!({
+            "action": action,
+            "domain": domain,
+            "record": format!("_acme-challenge.{domain}"),
+            "value": b64(digest(&SHA256, key_authorization.as_bytes()).as_ref()),
+        });
+        execute_command(&record, hook, self.dns_hook_timeout)
+            .await
+            .map(|_| ())
+            .map_err(|e| {
+                let error = error_to_json(&e);
+                format!(
+                    "{}: {}",
+                    error["command"].as_str().unwrap_or_default(),
+                    error["stderr"].as_str().unwrap_or_default().trim()
+                )
+            })
+    }
+}
+
+fn challenge_dir(webroot: &std::path::Path) -> PathBuf {
+    webroot.join(".well-known").join("acme-challenge")
+}
+
+async fn serve_token(
+    State(tokens): State<Tokens>,
+    UrlPath(token): UrlPath<String>,
+) -> Result<String, StatusCode> {
+    tokens
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .get(&token)
+        .cloned()
+        .ok_or(StatusCode::NOT_FOUND)
+}
@@ -0,0 +1,846 @@
+//! ACME Sink - Issue and Renew Certificates
+//!
+//! Each event asks for a certificate: `cert.request` for a new one,
+//! `cert.expiring` (as a certificate monitor emits it) for a renewal. The
+//! sink orders it from an ACME CA (`--directory`, Let's Encrypt by
+//! default), proves control of the domains with `http-01` or `dns-01` (see
+//! [`challenge`]), stores the chain and key (see [`store`]) and publishes
+//! `cert.renewed`.
+//!
+//! A payload names the domains as `domains`, or one as `domain` or `host`:
+//!
+//! ```json
+//! {"domains": ["example.com", "www.example.com"], "name": "example.com", "challenge": "dns-01"}
+//! ```
+//!
+//! `name` (default: the first domain, `*` replaced by `_`) is where the
+//! certificate is stored; `challenge` overrides `--challenge`. Wildcard
+//! domains need `dns-01`.
+//!
+//! A name issued less than `--min-interval` seconds ago is not issued again
+//! unless the payload has `"force": true`, so a burst of `cert.expiring`
+//! events does not run into the CA's rate limits.
+//!
+//! # Examples
+//!
+//! ```bash
+//! # Renew certificates behind an existing web server
+//! acme-sink -s cert.expiring -s cert.request --email ops@example.com \
+//!   --webroot /var/www/html --cert-dir /etc/emergent/certs
+//!
+//! # Wildcards via a DNS provider script, stored in Vault
+//! acme-sink -s cert.request --challenge dns-01 --dns-hook ./cloudflare-dns.sh \
+//!   --vault-addr https://vault.example.com:8200 --vault-token $VAULT_TOKEN
+//! ```
+
+pub mod acme;
+pub mod challenge;
+pub mod store;
+
+use acme::{AccountKey, Acme};
+use challenge::{ChallengeArgs, ChallengeType, Solvers};
+use clap::Parser;
+use emergent_client::EmergentMessage;
+use primitive_common::capabilities::VERSION;
+use primitive_common::doctor::{Report, check_writable_dir};
+use primitive_common::errors::{ErrorCategory, HandlerError};
+use primitive_common::{Publisher, SinkArgs, SinkConfig, SinkContext, SinkHandler, run_sink};
+use reqwest::Client;
+use serde_json::{Value, json};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock, PoisonError};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use store::StoreArgs;
+use tokio::sync::OnceCell;
+
+pub const RENEWED_EVENT_TYPE: &str = "cert.renewed";
+
+const LETS_ENCRYPT: &str = "https://acme-v02.api.letsencrypt.org/directory";
+
+/// ACME Sink — issue and renew certificates.
+#[derive(Parser, Debug)]
+#[command(name = "acme_sink", version = VERSION)]
+#[command(about = "Issue and renew certificates from an ACME CA")]
+struct Args {
+    /// Message types to subscribe to.
+    #[arg(short, long = "subscribe", required = true)]
+    subscribe: Vec<String>,
+
+    /// ACME directory URL of the CA.
+    #[arg(long, env = "ACME_SINK_DIRECTORY", default_value = LETS_ENCRYPT)]
+    directory: String,
+
+    /// Contact address registered with the account.
+    #[arg(long, env = "ACME_SINK_EMAIL")]
+    email: Option<String>,
+
+    /// Account key (PKCS#8 PEM), generated if missing.
+    #[arg(
+        long,
+        env = "ACME_SINK_ACCOUNT_KEY",
+        default_value = "acme-account.pem"
+    )]
+    account_key: PathBuf,
+
+    /// Seconds an issuance may take, challenges included.
+    #[arg(short, long, env = "ACME_SINK_TIMEOUT", default_value = "300")]
+    timeout: u64,
+
+    /// Seconds after issuing a name during which further requests for it are skipped.
+    #[arg(long, env = "ACME_SINK_MIN_INTERVAL", default_value = "3600")]
+    min_interval: u64,
+
+    #[command(flatten)]
+    challenge: ChallengeArgs,
+
+    #[command(flatten)]
+    store: StoreArgs,
+
+    #[command(flatten)]
+    sink: SinkArgs,
+}
+
+/// What a payload asks for.
+#[derive(Debug, PartialEq)]
+struct Request {
+    name: String,
+    domains: Vec<String>,
+    challenge: Option<ChallengeType>,
+    force: bool,
+}
+
+impl Request {
+    fn parse(payload: &Value) -> Result<Self, String> {
+        let domains: Vec<String> = match &payload["domains"] {
+            Value::Array(domains) => domains
+                .iter()
+                .map(|d| d.as_str().map(str::to_string))
+                .collect::<Option<_>>()
+                .ok_or("'domains' must be strings")?,
+            _ => payload["domain"]
+                .as_str()
+                .or_else(|| payload["host"].as_str())
+                .map(|d| vec![d.to_string()])
+                .ok_or("payload names no 'domains', 'domain' or 'host'")?,
+        };
+        let domains: Vec<String> = domains
+            .iter()
+            .map(|d| d.trim().trim_end_matches('.').to_ascii_lowercase())
+            .collect();
+        let Some(first) = domains.first() else {
+            return Err("'domains' is empty".to_string());
+        };
+        let name = match payload["name"].as_str() {
+            Some(name) => name.to_string(),
+            None => first.replace('*', "_"),
+        };
+        // The name becomes a directory and a secret path
+        if name.is_empty()
+            || name.starts_with('.')
+            || !name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_'))
+        {
+            return Err(format!("'{name}' is not a valid certificate name"));
+        }
+        let challenge = match payload["challenge"].as_str() {
+            Some(challenge) => Some(
+                ChallengeType::parse(challenge)
+                    .ok_or_else(|| format!("unknown challenge '{challenge}'"))?,
+            ),
+            None => None,
+        };
+        Ok(Self {
+            name,
+            domains,
+            challenge,
+            force: payload["force"].as_bool().unwrap_or(false),
+        })
+    }
+}
+
+/// Issues certificates named by events.
+struct AcmeSink {
+    http: Client,
+    directory: String,
+    email: Option<String>,
+    account_key: PathBuf,
+    /// Registered on first use, so a CA outage fails messages, not startup.
+    acme: OnceCell<Acme>,
+    solvers: Solvers,
+    default_challenge: ChallengeType,
+    store: StoreArgs,
+    timeout: Duration,
+    min_interval: Duration,
+    issued: Mutex<HashMap<String, Instant>>,
+    publisher: OnceLock<Publisher>,
+}
+
+impl AcmeSink {
+    fn recently_issued(&self, name: &str) -> bool {
+        let issued = self.issued.lock().unwrap_or_else(PoisonError::into_inner);
+        issued
+            .get(name)
+            .is_some_and(|at| at.elapsed() < self.min_interval)
+    }
+
+    async fn acme(&self) -> Result<&Acme, String> {
+        self.acme
+            .get_or_try_init(|| async {
+                let key = AccountKey::load_or_create(&self.account_key)?;
+                Acme::connect(
+                    self.http.clone(),
+                    &self.directory,
+                    key,
+                    self.email.as_deref(),
+                )
+                .await
+            })
+            .await
+    }
+}
+
+impl SinkHandler for AcmeSink {
+    async fn handle(
+        &self,
+        msg: &EmergentMessage,
+        ctx: &SinkContext<'_>,
+    ) -> Result<(), HandlerError> {
+        let request = Request::parse(ctx.payload())
+            .map_err(|e| HandlerError::new(ErrorCategory::Parse, e))?;
+        let kind = request.challenge.unwrap_or(self.default_challenge);
+        if kind == ChallengeType::Http01 && request.domains.iter().any(|d| d.starts_with("*.")) {
+            return Err(HandlerError::new(
+                ErrorCategory::Rejected,
+                "wildcard domains need dns-01",
+            ));
+        }
+        if !self.solvers.supports(kind) {
+            return Err(HandlerError::new(
+                ErrorCategory::Rejected,
+                format!("no {} solver configured", kind.as_str()),
+            ));
+        }
+        if !request.force && self.recently_issued(&request.name) {
+            eprintln!(
+                "Skipping {}: issued less than {}s ago",
+                request.name,
+                self.min_interval.as_secs()
+            );
+            return Ok(());
+        }
+
+        if ctx.is_dry_run() {
+            ctx.would_have(
+                "issue",
+                json!({
+                    "name": request.name,
+                    "domains": request.domains,
+                    "challenge": kind.as_str(),
+                    "directory": self.directory,
+                }),
+            )
+            .await;
+            return Ok(());
+        }
+
+        let acme = self
+            .acme()
+            .await
+            .map_err(|e| HandlerError::new(ErrorCategory::Request, e))?;
+        let issued = acme
+            .issue(&request.domains, kind, &self.solvers, self.timeout)
+            .await
+            .map_err(|e| HandlerError::new(ErrorCategory::Request, e))?;
+        let locations = self
+            .store
+            .store(&self.http, &request.name, &request.domains, &issued)
+            .await
+            .map_err(|e| HandlerError::new(ErrorCategory::Internal, e))?;
+        self.issued
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(request.name.clone(), Instant::now());
+        eprintln!("Issued {} for {}", request.name, request.domains.join(", "));
+
+        let issued_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64;
+        let mut payload = json!({
+            "name": request.name,
+            "domains": request.domains,
+            "certificate": issued.chain,
+            "issued_at": issued_at,
+            "directory": self.directory,
+            "message_id": ctx.message_id(),
+            "message_type": msg.message_type.as_str(),
+        });
+        if let (Some(payload), Value::Object(locations)) = (payload.as_object_mut(), locations) {
+            payload.extend(locations);
+        }
+        if let Some(publisher) = self.publisher.get() {
+            publisher
+                .publish(EmergentMessage::new(RENEWED_EVENT_TYPE).with_payload(payload))
+                .map_err(|e| HandlerError::new(ErrorCategory::Internal, e))?;
+        }
+        Ok(())
+    }
+
+    fn self_test(&self, report: &mut Report) {
+        // An existing key must load; a missing one is generated on first use
+        let key = if self.account_key.exists() {
+            std::fs::read_to_string(&self.account_key)
+                .map_err(|e| e.to_string())
+                .and_then(|pem| AccountKey::from_pem(&pem))
+                .map(|_| self.account_key.display().to_string())
+        } else {
+            let dir = self.account_key.parent().unwrap_or(Path::new(""));
+            check_writable_dir(if dir.as_os_str().is_empty() {
+                Path::new(".")
+            } else {
+                dir
+            })
+        };
+        report.check("account key", key);
+        self.solvers.self_test(report);
+        self.store.self_test(report);
+    }
+
+    fn publishes(&self) -> &'static [&'static str] {
+        &[RENEWED_EVENT_TYPE]
+    }
+
+    fn attach(&self, publisher: Publisher) {
+        let _ = self.publisher.set(publisher);
+    }
+}
+
+/// Run the primitive with `args` as its command line (the first item is
+/// the program name, as with `std::env::args_os`).
+pub async fn run<I, T>(args: I) -> Result<(), Box<dyn std::error::Error>>
+where
+    I: IntoIterator<Item = T>,
+    T: Into<std::ffi::OsString> + Clone,
+{
+    let args = Args::parse_from(args);
+
+    if !args.store.is_configured() {
+        eprintln!("Error: set --cert-dir, --vault-addr or both");
+        std::process::exit(1);
+    }
+    let solvers = Solvers::new(&args.challenge);
+    if !solvers.supports(ChallengeType::Http01) && !solvers.supports(ChallengeType::Dns01) {
+        eprintln!("Error: set --webroot, --http01-port or --dns-hook");
+        std::process::exit(1);
+    }
+
+    // The http-01 listener runs for as long as the sink does
+    if let Some(router) = solvers.router()
+        && let Some(port) = args.challenge.http01_port
+        && !args.sink.self_test
+    {
+        let addr: SocketAddr = format!("{}:{port}", args.challenge.http01_host).parse()?;
+        let listener = tokio::net::TcpListener::bind(addr).await?;
+        tokio::spawn(async move { axum::serve(listener, router).await });
+    }
+
+    let config = SinkConfig {
+        name: "acme_sink",
+        subscribe: &args.subscribe,
+        would_have_as: "acme.would_have",
+        dead_letter_as: "acme.dead_letter",
+        settings: &args,
+    };
+    let handler = AcmeSink {
+        http: Client::builder().timeout(Duration::from_secs(30)).build()?,
+        directory: args.directory.clone(),
+        email: args.email.clone(),
+        account_key: args.account_key.clone(),
+        acme: OnceCell::new(),
+        solvers,
+        default_challenge: args.challenge.challenge,
+        store: args.store.clone(),
+        timeout: Duration::from_secs(args.timeout),
+        min_interval: Duration::from_secs(args.min_interval),
+        issued: Mutex::new(HashMap::new()),
+        publisher: OnceLock::new(),
+    };
+
+    run_sink(config, &args.sink, handler).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{
+        Json, Router,
+        extract::{Path as UrlPath, State},
+        http::{HeaderMap, StatusCode},
+        response::{IntoResponse, Response},
+        routing::{get, post},
+    };
+    use base64::Engine;
+    use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+    use emergent_testkit::fixtures::TempDir;
+    use emergent_testkit::{SinkFixture, fixtures, spawn_sink};
+    use primitive_common::errors::ErrorArgs;
+    use std::sync::Arc;
+
+    #[test]
+    fn requests_name_their_domains() {
+        let request =
+            Request::parse(&json!({"host": "Example.COM."})).unwrap_or_else(|e| panic!("{e}"));
+        assert_eq!(request.domains, ["example.com"]);
+        assert_eq!(request.name, "example.com");
+
+        let request = Request::parse(&json!({
+            "domains": ["*.example.com", "example.com"], "challenge": "dns-01", "force": true
+        }))
+        .unwrap_or_else(|e| panic!("{e}"));
+        assert_eq!(request.name, "_.example.com");
+        assert_eq!(request.challenge, Some(ChallengeType::Dns01));
+        assert!(request.force);
+
+        assert!(Request::parse(&json!({"domains": []})).is_err());
+        assert!(Request::parse(&json!({"domain": "example.com", "name": "../etc"})).is_err());
+        assert!(
+            Request::parse(&json!({"domain": "example.com", "challenge": "tls-alpn-01"})).is_err()
+        );
+    }
+
+    /// A CA that checks the http-01 file under the web root.
+    #[derive(Default)]
+    struct MockCa {
+        base: String,
+        webroot: PathBuf,
+        validated: bool,
+        invalid: bool,
+        finalized: bool,
+        /// Requests to answer with `badNonce` before accepting one.
+        bad_nonces: u32,
+        /// Account registrations to refuse before accepting one.
+        refused_accounts: u32,
+        /// Expected key authorization prefix, so a wrong one fails validation.
+        token_prefix: &'static str,
+        orders: u32,
+    }
+
+    type Ca = Arc<Mutex<MockCa>>;
+
+    fn reply(status: StatusCode, location: Option<String>, body: Value) -> Response {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "replay-nonce",
+            "n0nce".parse().unwrap_or_else(|e| panic!("{e}")),
+        );
+        if let Some(location) = location {
+            headers.insert(
+                "location",
+                location.parse().unwrap_or_else(|e| panic!("{e}")),
+            );
+        }
+        (status, headers, Json(body)).into_response()
+    }
+
+    fn jws_payload(body: &Value) -> Value {
+        let payload = URL_SAFE_NO_PAD
+            .decode(body["payload"].as_str().unwrap_or_default())
+            .unwrap_or_else(|e| panic!("{e}"));
+        serde_json::from_slice(&payload).unwrap_or_default()
+    }
+
+    async fn ca_post(
+        State(ca): State<Ca>,
+        UrlPath(resource): UrlPath<String>,
+        Json(body): Json<Value>,
+    ) -> Response {
+        let mut ca = ca.lock().unwrap_or_else(PoisonError::into_inner);
+        let base = ca.base.clone();
+        if ca.bad_nonces > 0 {
+            ca.bad_nonces -= 1;
+            return reply(
+                StatusCode::BAD_REQUEST,
+                None,
+                json!({"type": "urn:ietf:params:acme:error:badNonce", "detail": "stale nonce"}),
+            );
+        }
+        let order = |ca: &MockCa| {
+            let status = match (ca.validated, ca.invalid, ca.finalized) {
+                (_, true, _) => "invalid",
+                (_, _, true) => "valid",
+                (true, _, false) => "ready",
+                _ => "pending",
+            };
+            json!({
+                "status": status,
+                "authorizations": [format!("{base}/authz")],
+                "finalize": format!("{base}/finalize"),
+                "certificate": format!("{base}/cert"),
+            })
+        };
+        match resource.as_str() {
+            "account" if ca.refused_accounts > 0 => {
+                ca.refused_accounts -= 1;
+                reply(
+                    StatusCode::FORBIDDEN,
+                    None,
+                    json!({"type": "urn:ietf:params:acme:error:unauthorized",
+                           "detail": "registrations are paused"}),
+                )
+            }
+            "account" => reply(
+                StatusCode::CREATED,
+                Some(format!("{base}/acct/1")),
+                json!({}),
+            ),
+            "order" => {
+                // Every order starts over with a fresh authorization
+                ca.orders += 1;
+                (ca.validated, ca.invalid, ca.finalized) = (false, false, false);
+                reply(
+                    StatusCode::CREATED,
+                    Some(format!("{base}/order-1")),
+                    order(&ca),
+                )
+            }
+            "order-1" => reply(StatusCode::OK, None, order(&ca)),
+            "authz" => {
+                let status = match (ca.validated, ca.invalid) {
+                    (_, true) => "invalid",
+                    (true, _) => "valid",
+                    _ => "pending",
+                };
+                let mut challenge = json!({"type": "http-01", "url": format!("{base}/chall"),
+                                           "token": "t0ken", "status": status});
+                if ca.invalid {
+                    challenge["error"] = json!({"type": "urn:ietf:params:acme:error:unauthorized",
+                                                "detail": "key authorization mismatch"});
+                }
+                reply(
+                    StatusCode::OK,
+                    None,
+                    json!({
+                        "status": status,
+                        "identifier": {"type": "dns", "value": "example.com"},
+                        "challenges": [challenge],
+                    }),
+                )
+            }
+            "chall" => {
+                let file = ca.webroot.join(".well-known/acme-challenge/t0ken");
+                let content = std::fs::read_to_string(file).unwrap_or_default();
+                ca.validated = content.starts_with(ca.token_prefix);
+                ca.invalid = !ca.validated;
+                reply(StatusCode::OK, None, json!({}))
+            }
+            "finalize" => {
+                ca.finalized = jws_payload(&body)["csr"].is_string();
+                reply(StatusCode::OK, None, order(&ca))
+            }
+            "cert" => {
+                let mut headers = HeaderMap::new();
+                headers.insert(
+                    "replay-nonce",
+                    "n0nce".parse().unwrap_or_else(|e| panic!("{e}")),
+                );
+                (
+                    headers,
+                    "-----BEGIN CERTIFICATE-----\nMIIB\n-----END CERTIFICATE-----\n",
+                )
+                    .into_response()
+            }
+            _ => reply(
+                StatusCode::NOT_FOUND,
+                None,
+                json!({"type": "urn:ietf:params:acme:error:malformed"}),
+            ),
+        }
+    }
+
+    /// Serve `ca`, checking challenge files under `webroot`; returns its base URL.
+    async fn serve(webroot: &TempDir, ca: MockCa) -> (String, Ca) {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap_or_else(|e| panic!("bind: {e}"));
+        let base = format!(
+            "http://{}",
+            listener
+                .local_addr()
+                .unwrap_or_else(|e| panic!("local_addr: {e}"))
+        );
+        let ca: Ca = Arc::new(Mutex::new(MockCa {
+            base: base.clone(),
+            webroot: webroot.path().to_path_buf(),
+            token_prefix: if ca.token_prefix.is_empty() {
+                "t0ken."
+            } else {
+                ca.token_prefix
+            },
+            ..ca
+        }));
+        let directory = json!({
+            "newNonce": format!("{base}/nonce"),
+            "newAccount": format!("{base}/account"),
+            "newOrder": format!("{base}/order"),
+        });
+        let app = Router::new()
+            .route("/directory", get(move || async move { Json(directory) }))
+            .route(
+                "/nonce",
+                get(|| async { reply(StatusCode::OK, None, json!({})) }),
+            )
+            .route("/{resource}", post(ca_post))
+            .with_state(Arc::clone(&ca));
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        (base, ca)
+    }
+
+    /// A sink answering http-01 challenges from `webroot`, keeping its
+    /// account key and certificates under `state`.
+    fn acme_sink(base: &str, webroot: &TempDir, state: &TempDir) -> AcmeSink {
+        AcmeSink {
+            http: Client::new(),
+            directory: format!("{base}/directory"),
+            email: Some("ops@example.com".to_string()),
+            account_key: state.path().join("account.pem"),
+            acme: OnceCell::new(),
+            solvers: Solvers::new(&ChallengeArgs {
+                challenge: ChallengeType::Http01,
+                webroot: Some(webroot.path().to_path_buf()),
+                http01_port: None,
+                http01_host: "0.0.0.0".to_string(),
+                dns_hook: None,
+                dns_propagation: 0,
+                dns_hook_timeout: 1000,
+            }),
+            default_challenge: ChallengeType::Http01,
+            store: StoreArgs {
+                cert_dir: Some(state.path().join("certs")),
+                vault_addr: None,
+                vault_token: None,
+                vault_mount: "secret".to_string(),
+                vault_path: "certs/{name}".to_string(),
+            },
+            timeout: Duration::from_secs(10),
+            min_interval: Duration::from_secs(3600),
+            issued: Mutex::new(HashMap::new()),
+            publisher: OnceLock::new(),
+        }
+    }
+
+    fn failing() -> SinkArgs {
+        SinkArgs {
+            max_attempts: 2,
+            retry_delay: 0,
+            dead_letter: true,
+            errors: ErrorArgs { emit_errors: true },
+            ..Default::default()
+        }
+    }
+
+    fn expiring(payload: Value) -> EmergentMessage {
+        fixtures::message("cert.expiring", payload)
+    }
+
+    #[tokio::test]
+    async fn issues_and_stores_certificates() {
+        let webroot = TempDir::new("acme-sink-webroot");
+        let state = TempDir::new("acme-sink-state");
+        // A stale nonce is retried with the fresh one the CA sends back
+        let (base, _ca) = serve(
+            &webroot,
+            MockCa {
+                bad_nonces: 1,
+                ..Default::default()
+            },
+        )
+        .await;
+        let cert_dir = state.path().join("certs");
+        let (mut engine, run) = spawn_sink(
+            SinkFixture::new("acme_sink", "acme"),
+            SinkArgs::default(),
+            acme_sink(&base, &webroot, &state),
+        );
+
+        engine
+            .inject_message(expiring(json!({"host": "example.com", "days_left": 14})))
+            .await;
+        let renewed = engine.expect_published(RENEWED_EVENT_TYPE).await;
+        assert_eq!(renewed.payload()["name"], "example.com");
+        assert_eq!(renewed.payload()["domains"], json!(["example.com"]));
+        assert_eq!(renewed.payload()["message_type"], "cert.expiring");
+
+        let chain = std::fs::read_to_string(cert_dir.join("example.com/fullchain.pem"))
+            .unwrap_or_else(|e| panic!("{e}"));
+        assert!(chain.starts_with("-----BEGIN CERTIFICATE-----"));
+        let key = std::fs::read_to_string(cert_dir.join("example.com/privkey.pem"))
+            .unwrap_or_else(|e| panic!("{e}"));
+        assert!(key.contains("PRIVATE KEY"));
+        // The challenge file is gone and the account key kept for next time
+        assert!(
+            !webroot
+                .path()
+                .join(".well-known/acme-challenge/t0ken")
+                .exists()
+        );
+        assert!(state.path().join("account.pem").exists());
+        assert_eq!(renewed.payload()["certificate"], chain);
+
+        engine.close();
+        assert_eq!(run.await.ok(), Some(Ok(())));
+    }
+
+    #[tokio::test]
+    async fn repeated_renewals_wait_for_the_interval_unless_forced() {
+        let webroot = TempDir::new("acme-sink-webroot");
+        let state = TempDir::new("acme-sink-state");
+        let (base, ca) = serve(&webroot, MockCa::default()).await;
+        let (mut engine, run) = spawn_sink(
+            SinkFixture::new("acme_sink", "acme"),
+            SinkArgs::default(),
+            acme_sink(&base, &webroot, &state),
+        );
+
+        engine
+            .inject_message(expiring(json!({"host": "example.com"})))
+            .await;
+        engine.expect_published(RENEWED_EVENT_TYPE).await;
+        // Every monitor alert for the same certificate is not a new order
+        engine
+            .inject_message(expiring(json!({"host": "example.com"})))
+            .await;
+        engine.expect_quiet(Duration::from_millis(300)).await;
+        engine
+            .inject_message(expiring(json!({"host": "example.com", "force": true})))
+            .await;
+        let renewed = engine.expect_published(RENEWED_EVENT_TYPE).await;
+        assert_eq!(renewed.payload()["name"], "example.com");
+        assert_eq!(ca.lock().unwrap_or_else(PoisonError::into_inner).orders, 2);
+
+        engine.close();
+        assert_eq!(run.await.ok(), Some(Ok(())));
+    }
+
+    #[tokio::test]
+    async fn refused_registrations_are_retried_on_the_next_attempt() {
+        let webroot = TempDir::new("acme-sink-webroot");
+        let state = TempDir::new("acme-sink-state");
+        let (base, _ca) = serve(
+            &webroot,
+            MockCa {
+                refused_accounts: 1,
+                ..Default::default()
+            },
+        )
+        .await;
+        let (mut engine, run) = spawn_sink(
+            SinkFixture::new("acme_sink", "acme"),
+            failing(),
+            acme_sink(&base, &webroot, &state),
+        );
+
+        engine
+            .inject_message(expiring(json!({"host": "example.com"})))
+            .await;
+        let error = engine.expect_published("primitive.error").await;
+        assert_eq!(error.payload()["category"], "request");
+        assert_eq!(error.payload()["disposition"], "retrying");
+        assert_eq!(
+            error.payload()["error"],
+            format!("{base}/account: 403 Forbidden registrations are paused")
+        );
+        engine.expect_published(RENEWED_EVENT_TYPE).await;
+
+        engine.close();
+        assert_eq!(run.await.ok(), Some(Ok(())));
+    }
+
+    #[tokio::test]
+    async fn failed_validations_are_dead_lettered_and_cleaned_up() {
+        let webroot = TempDir::new("acme-sink-webroot");
+        let state = TempDir::new("acme-sink-state");
+        let (base, _ca) = serve(
+            &webroot,
+            MockCa {
+                token_prefix: "other.",
+                ..Default::default()
+            },
+        )
+        .await;
+        let args = SinkArgs {
+            max_attempts: 1,
+            ..failing()
+        };
+        let (mut engine, run) = spawn_sink(
+            SinkFixture::new("acme_sink", "acme"),
+            args,
+            acme_sink(&base, &webroot, &state),
+        );
+
+        engine
+            .inject_message(expiring(json!({"host": "example.com"})))
+            .await;
+        let dead = engine.expect_published("acme.dead_letter").await;
+        assert_eq!(
+            dead.payload()["error"],
+            "example.com: authorization invalid: key authorization mismatch"
+        );
+        assert!(
+            !webroot
+                .path()
+                .join(".well-known/acme-challenge/t0ken")
+                .exists()
+        );
+        assert!(!state.path().join("certs/example.com").exists());
+
+        engine.close();
+        assert_eq!(run.await.ok(), Some(Ok(())));
+    }
+
+    #[tokio::test]
+    async fn unanswerable_requests_are_rejected_before_contacting_the_ca() {
+        let webroot = TempDir::new("acme-sink-webroot");
+        let state = TempDir::new("acme-sink-state");
+        let args = SinkArgs {
+            max_attempts: 1,
+            ..failing()
+        };
+        // Nothing listens here, so any contact with the CA would fail differently
+        let (mut engine, run) = spawn_sink(
+            SinkFixture::new("acme_sink", "acme"),
+            args,
+            acme_sink("http://127.0.0.1:9", &webroot, &state),
+        );
+
+        for (payload, category, expected) in [
+            (
+                json!({"days_left": 3}),
+                "parse",
+                "payload names no 'domains', 'domain' or 'host'",
+            ),
+            (
+                json!({"domains": ["*.example.com"]}),
+                "rejected",
+                "wildcard domains need dns-01",
+            ),
+            (
+                json!({"domain": "example.com", "challenge": "dns-01"}),
+                "rejected",
+                "no dns-01 solver configured",
+            ),
+        ] {
+            engine.inject_message(expiring(payload)).await;
+            let error = engine.expect_published("primitive.error").await;
+            assert_eq!(error.payload()["category"], category);
+            let dead = engine.expect_published("acme.dead_letter").await;
+            assert_eq!(dead.payload()["error"], expected);
+        }
+        assert!(!state.path().join("account.pem").exists());
+
+        engine.close();
+        assert_eq!(run.await.ok(), Some(Ok(())));
+    }
+}
@@ -0,0 +1,99 @@
+//! Where issued certificates go.
+//!
+//! - `--cert-dir` - `<cert-dir>/<name>/fullchain.pem` and `privkey.pem`
+//!   (owner-only), each replaced atomically.
+//! - `--vault-addr` - a Vault KV version 2 secret at
+//!   `<vault-mount>/<vault-path>` with the fields `certificate`,
+//!   `private_key` and `domains`.
+//!
+//! Both may be set; at least one must be.
+
+use crate::acme::{Issued, write_private};
+use clap::Args;
+use primitive_common::doctor::{Report, check_writable_dir};
+use reqwest::Client;
+use serde_json::{Value, json};
+use std::fs;
+use std::path::PathBuf;
+
+/// Certificate destinations.
+#[derive(Args, Debug, Clone)]
+pub struct StoreArgs {
+    /// Directory certificates are written to, one subdirectory per name.
+    #[arg(long, env = "ACME_SINK_CERT_DIR")]
+    pub cert_dir: Option<PathBuf>,
+
+    /// Vault server certificates are written to (KV version 2).
+    #[arg(long, env = "VAULT_ADDR", requires = "vault_token")]
+    pub vault_addr: Option<String>,
+
+    /// Vault token allowed to write the secrets.
+    #[arg(long, env = "VAULT_TOKEN", hide_env_values = true)]
+    pub vault_token: Option<String>,
+
+    /// KV version 2 mount the secrets are written to.
+    #[arg(long, env = "ACME_SINK_VAULT_MOUNT", default_value = "secret")]
+    pub vault_mount: String,
+
+    /// Secret path under the mount; `{name}` is the certificate name.
+    #[arg(long, env = "ACME_SINK_VAULT_PATH", default_value = "certs/{name}")]
+    pub vault_path: String,
+}
+
+impl StoreArgs {
+    pub fn is_configured(&self) -> bool {
+        self.cert_dir.is_some() || self.vault_addr.is_some()
+    }
+
+    pub fn self_test(&self, report: &mut Report) {
+        if let Some(dir) = &self.cert_dir {
+            report.check("cert dir", check_writable_dir(dir));
+        }
+    }
+
+    /// Store `issued` under `name`, returning where it went.
+    pub async fn store(
+        &self,
+        http: &Client,
+        name: &str,
+        domains: &[String],
+        issued: &Issued,
+    ) -> Result<Value, String> {
+        let mut locations = json!({});
+        if let Some(dir) = &self.cert_dir {
+            let dir = dir.join(name);
+            fs::create_dir_all(&dir).map_err(|e| format!("{}: {e}", dir.display()))?;
+            let (chain, key) = (dir.join("fullchain.pem"), dir.join("privkey.pem"));
+            // The key first, so the new chain never sits next to the old key
+            write_private(&key, issued.key.as_bytes())?;
+            let tmp = chain.with_extension("tmp");
+            fs::write(&tmp, &issued.chain)
+                .and_then(|()| fs::rename(&tmp, &chain))
+                .map_err(|e| format!("{}: {e}", chain.display()))?;
+            locations["certificate_path"] = json!(chain.display().to_string());
+            locations["key_path"] = json!(key.display().to_string());
+        }
+        if let (Some(addr), Some(token)) = (&self.vault_addr, &self.vault_token) {
+            let path = self.vault_path.replace("{name}", name);
+            let url = format!(
+                "{}/v1/{}/data/{}",
+                addr.trim_end_matches('/'),
+                self.vault_mount.trim_matches('/'),
+                path.trim_start_matches('/')
+            );
+            http.post(&url)
+                .header("X-Vault-Token", token)
+                .json(&json!({ "data": {
+                    "certificate": issued.chain,
+                    "private_key": issued.key,
+                    "domains": domains,
+                }}))
+                .send()
+                .await
+                .and_then(reqwest::Response::error_for_status)
+                .map_err(|e| format!("vault: {e}"))?;
+            locations["vault_path"] = json!(format!("{}/{path}", self.vault_mount));
+        }
+        Ok(locations)
+    }
+}
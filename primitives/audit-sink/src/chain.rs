@@ -0,0 +1,229 @@
+//! The hash chain.
+//!
+//! A record is one line of JSON. Its `hash` is the SHA-256 of the record
+//! without that field, serialized with sorted keys and no whitespace, and
+//! its `prev` is the hash of the record before it (64 zeros for the
+//! first). Changing, inserting or removing a record breaks every link
+//! after it; only the head can be cut off unnoticed, which is what
+//! anchors, copies of the head kept elsewhere, are for.
+
+use serde_json::{Map, Value};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::BufRead;
+
+/// The `prev` of the first record.
+pub const GENESIS: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+/// The end of the chain: the last record's sequence number and hash.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Head {
+    pub seq: u64,
+    pub hash: String,
+}
+
+impl Default for Head {
+    fn default() -> Self {
+        Self {
+            seq: 0,
+            hash: GENESIS.to_string(),
+        }
+    }
+}
+
+/// The hash of `record`, leaving out its own `hash`.
+pub fn hash(record: &Map<String, Value>) -> String {
+    let mut unsealed = record.clone();
+    unsealed.remove("hash");
+    hex::encode(Sha256::digest(Value::Object(unsealed).to_string()))
+}
+
+impl Head {
+    /// Link `fields` onto the chain as its next record, moving the head.
+    pub fn seal(&mut self, mut fields: Map<String, Value>) -> Value {
+        self.seq += 1;
+        fields.insert("seq".to_string(), Value::from(self.seq));
+        fields.insert("prev".to_string(), Value::from(self.hash.clone()));
+        self.hash = hash(&fields);
+        fields.insert("hash".to_string(), Value::from(self.hash.clone()));
+        Value::Object(fields)
+    }
+
+    /// The head after `record`, if `record` follows on from this one.
+    pub fn follow(&self, record: &Value) -> Result<Head, String> {
+        let fields = record
+            .as_object()
+            .ok_or_else(|| "not a JSON object".to_string())?;
+        let seq = fields.get("seq").and_then(Value::as_u64);
+        if seq != Some(self.seq + 1) {
+            return Err(format!(
+                "sequence {} follows {}",
+                fields.get("seq").unwrap_or(&Value::Null),
+                self.seq
+            ));
+        }
+        let prev = fields
+            .get("prev")
+            .and_then(Value::as_str)
+            .unwrap_or_default();
+        if prev != self.hash {
+            return Err(format!("prev {prev} is not the hash of the record before"));
+        }
+        let stated = fields
+            .get("hash")
+            .and_then(Value::as_str)
+            .unwrap_or_default();
+        let actual = hash(fields);
+        if stated != actual {
+            return Err(format!(
+                "hash {stated} does not match its contents ({actual})"
+            ));
+        }
+        Ok(Head {
+            seq: self.seq + 1,
+            hash: actual,
+        })
+    }
+}
+
+/// Where and how a chain was found broken.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Tamper {
+    pub line: usize,
+    pub reason: String,
+}
+
+impl fmt::Display for Tamper {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.reason)
+    }
+}
+
+/// Read an anchor file: one JSON object per line with the `seq` and
+/// `hash` of the head when it was anchored.
+pub fn anchors(reader: impl BufRead) -> Result<BTreeMap<u64, String>, String> {
+    let mut anchors = BTreeMap::new();
+    for (number, line) in reader.lines().enumerate() {
+        let line = line.map_err(|e| e.to_string())?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let anchor: Value =
+            serde_json::from_str(&line).map_err(|e| format!("anchor line {}: {e}", number + 1))?;
+        match (anchor["seq"].as_u64(), anchor["hash"].as_str()) {
+            (Some(seq), Some(hash)) => {
+                anchors.insert(seq, hash.to_string());
+            }
+            _ => return Err(format!("anchor line {}: needs seq and hash", number + 1)),
+        }
+    }
+    Ok(anchors)
+}
+
+/// Walk the log, checking every link and every anchor, and return its
+/// head.
+pub fn verify(log: impl BufRead, anchors: &BTreeMap<u64, String>) -> Result<Head, Tamper> {
+    let mut head = Head::default();
+    let mut line_number = 0;
+    for line in log.lines() {
+        line_number += 1;
+        let tamper = |reason: String| Tamper {
+            line: line_number,
+            reason,
+        };
+        let line = line.map_err(|e| tamper(e.to_string()))?;
+        let record: Value = serde_json::from_str(&line).map_err(|e| tamper(e.to_string()))?;
+        head = head.follow(&record).map_err(tamper)?;
+        if let Some(anchored) = anchors.get(&head.seq)
+            && *anchored != head.hash
+        {
+            return Err(tamper(format!(
+                "hash {} differs from the anchored {anchored}",
+                head.hash
+            )));
+        }
+    }
+    if let Some((seq, _)) = anchors.range(head.seq + 1..).next_back() {
+        return Err(Tamper {
+            line: line_number,
+            reason: format!(
+                "the log ends at record {} but record {seq} was anchored",
+                head.seq
+            ),
+        });
+    }
+    Ok(head)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn chain(payloads: &[Value]) -> Vec<String> {
+        let mut head = Head::default();
+        payloads
+            .iter()
+            .map(|payload| {
+                let mut fields = Map::new();
+                fields.insert("payload".to_string(), payload.clone());
+                head.seal(fields).to_string()
+            })
+            .collect()
+    }
+
+    fn check(lines: &[String], anchors: &BTreeMap<u64, String>) -> Result<Head, Tamper> {
+        verify((lines.join("\n") + "\n").as_bytes(), anchors)
+    }
+
+    #[test]
+    fn tampering_breaks_the_chain() {
+        let lines = chain(&[
+            json!({"amount": 10}),
+            json!({"amount": 20}),
+            json!({"amount": 30}),
+        ]);
+        let head = check(&lines, &BTreeMap::new()).unwrap_or_else(|e| panic!("{e}"));
+        assert_eq!(head.seq, 3);
+        let first: Value = serde_json::from_str(&lines[0]).unwrap_or_default();
+        assert_eq!(first["prev"], GENESIS);
+        assert_eq!(first["seq"], 1);
+
+        let mut edited = lines.clone();
+        edited[1] = edited[1].replace("20", "25");
+        let tamper = check(&edited, &BTreeMap::new()).err();
+        assert_eq!(tamper.map(|t| t.line), Some(2));
+
+        let mut removed = lines.clone();
+        removed.remove(1);
+        let tamper = check(&removed, &BTreeMap::new()).err();
+        assert_eq!(
+            tamper.map(|t| t.to_string()),
+            Some("line 2: sequence 3 follows 1".to_string())
+        );
+
+        // A rewritten chain is self-consistent but contradicts its anchors
+        let rewritten = chain(&[
+            json!({"amount": 10}),
+            json!({"amount": 99}),
+            json!({"amount": 30}),
+        ]);
+        let anchored =
+            anchors(format!(r#"{{"seq": 2, "hash": "{}"}}"#, head_at(&lines, 2)).as_bytes())
+                .unwrap_or_else(|e| panic!("{e}"));
+        assert!(check(&lines, &anchored).is_ok());
+        assert_eq!(check(&rewritten, &anchored).err().map(|t| t.line), Some(2));
+        // So does a truncated one
+        let tamper = check(&lines[..1], &anchored).err();
+        assert_eq!(
+            tamper.map(|t| t.reason),
+            Some("the log ends at record 1 but record 2 was anchored".to_string())
+        );
+    }
+
+    fn head_at(lines: &[String], seq: usize) -> String {
+        let record: Value = serde_json::from_str(&lines[seq - 1]).unwrap_or_default();
+        record["hash"].as_str().unwrap_or_default().to_string()
+    }
+}
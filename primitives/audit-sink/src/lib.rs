@@ -0,0 +1,382 @@
+//! Audit Sink - Tamper-Evident Event Log
+//!
+//! Every event is appended to `--log` as one line of JSON holding its id,
+//! type and payload, the time it was logged, a sequence number, and a
+//! SHA-256 hash chaining it to the record before (see [`chain`]). A record
+//! is synced to disk before its message is acknowledged.
+//!
+//! With `--anchor-interval`, the head of the chain is anchored outside the
+//! log on a schedule: published as `audit.anchor`, appended to
+//! `--anchor-file`, and POSTed to `--anchor-url`. Anchors catch what the
+//! chain alone can't: a log truncated, or rewritten from some record on.
+//!
+//! `audit-sink verify` walks a log, checking every link and, with
+//! `--anchors`, every anchor, and exits 1 at the first sign of tampering.
+//!
+//! # Examples
+//!
+//! ```bash
+//! # Keep an audit trail of access and payment events, anchored hourly to
+//! # a separate volume
+//! audit-sink -s 'auth.*' -s 'payment.*' --log /var/log/emergent/audit.log \
+//!   --anchor-interval 3600 --anchor-file /mnt/worm/audit-anchors.jsonl
+//!
+//! # Check it
+//! audit-sink verify --log /var/log/emergent/audit.log \
+//!   --anchors /mnt/worm/audit-anchors.jsonl
+//! ```
+
+pub mod chain;
+pub mod log;
+
+use chain::Head;
+use clap::{Parser, Subcommand};
+use emergent_client::EmergentMessage;
+use log::AuditLog;
+use primitive_common::capabilities::VERSION;
+use primitive_common::doctor::Report;
+use primitive_common::errors::{ErrorCategory, HandlerError};
+use primitive_common::time;
+use primitive_common::{Publisher, SinkArgs, SinkConfig, SinkContext, SinkHandler, run_sink};
+use reqwest::Client;
+use serde_json::{Map, Value, json};
+use std::io::{BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+
+pub const ANCHOR_EVENT_TYPE: &str = "audit.anchor";
+
+/// Audit Sink — append events to a tamper-evident log.
+#[derive(Parser, Debug)]
+#[command(name = "audit_sink", version = VERSION, subcommand_negates_reqs = true)]
+#[command(about = "Append events to a tamper-evident, hash-chained audit log")]
+struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Message types to log.
+    #[arg(short, long = "subscribe", required = true)]
+    subscribe: Vec<String>,
+
+    /// The audit log, one JSON record per line.
+    #[arg(long, env = "AUDIT_SINK_LOG", default_value = "audit.log")]
+    log: PathBuf,
+
+    /// Seconds between anchors of the chain's head (0 = no anchoring).
+    #[arg(long, env = "AUDIT_SINK_ANCHOR_INTERVAL", default_value = "0")]
+    anchor_interval: u64,
+
+    /// File anchors are appended to, one JSON object per line.
+    #[arg(long, env = "AUDIT_SINK_ANCHOR_FILE")]
+    anchor_file: Option<PathBuf>,
+
+    /// URL anchors are POSTed to as JSON.
+    #[arg(long, env = "AUDIT_SINK_ANCHOR_URL")]
+    anchor_url: Option<String>,
+
+    /// Bearer token for `--anchor-url`.
+    #[arg(long, env = "AUDIT_SINK_ANCHOR_TOKEN", hide_env_values = true)]
+    anchor_token: Option<String>,
+
+    /// Timeout for `--anchor-url` requests, in milliseconds.
+    #[arg(long, env = "AUDIT_SINK_TIMEOUT", default_value = "30000")]
+    timeout: u64,
+
+    #[command(flatten)]
+    sink: SinkArgs,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Check a log's hash chain, and its anchors, exiting 1 if it was
+    /// tampered with.
+    Verify {
+        /// The audit log.
+        #[arg(long, env = "AUDIT_SINK_LOG", default_value = "audit.log")]
+        log: PathBuf,
+
+        /// Anchor file to hold the log to.
+        #[arg(long)]
+        anchors: Option<PathBuf>,
+    },
+}
+
+/// Where anchors go.
+struct Anchors {
+    file: Option<PathBuf>,
+    url: Option<String>,
+    token: Option<String>,
+    client: Client,
+    timeout: Duration,
+}
+
+/// The anchor of `head` in `log`.
+fn anchor(log: &Path, head: &Head) -> Value {
+    json!({
+        "log": log.display().to_string(),
+        "seq": head.seq,
+        "hash": head.hash,
+        "time": time::format(time::now()),
+    })
+}
+
+impl Anchors {
+    /// Record `anchor` in the file and at the URL.
+    async fn send(&self, anchor: &Value) -> Result<(), String> {
+        if let Some(path) = &self.file {
+            std::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .and_then(|mut file| {
+                    file.write_all(format!("{anchor}\n").as_bytes())?;
+                    file.sync_data()
+                })
+                .map_err(|e| format!("{}: {e}", path.display()))?;
+        }
+        if let Some(url) = &self.url {
+            let mut request = self.client.post(url).timeout(self.timeout).json(anchor);
+            if let Some(token) = &self.token {
+                request = request.bearer_auth(token);
+            }
+            let response = request
+                .send()
+                .await
+                .map_err(|e| format!("{url}: {}", e.without_url()))?;
+            if !response.status().is_success() {
+                return Err(format!("{url}: HTTP {}", response.status()));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Appends events to the log.
+struct AuditSink {
+    log: Arc<AuditLog>,
+    publisher: Arc<OnceLock<Publisher>>,
+}
+
+impl SinkHandler for AuditSink {
+    async fn handle(
+        &self,
+        msg: &EmergentMessage,
+        ctx: &SinkContext<'_>,
+    ) -> Result<(), HandlerError> {
+        let mut fields = Map::new();
+        fields.insert("time".to_string(), json!(time::format(time::now())));
+        fields.insert("message_id".to_string(), json!(ctx.message_id()));
+        fields.insert("message_type".to_string(), json!(msg.message_type.as_str()));
+        fields.insert("payload".to_string(), ctx.payload().clone());
+        if ctx.is_dry_run() {
+            ctx.would_have("append", Value::Object(fields)).await;
+            return Ok(());
+        }
+
+        self.log
+            .append(fields)
+            .map(|_| ())
+            .map_err(|e| HandlerError::new(ErrorCategory::Internal, e))
+    }
+
+    fn self_test(&self, report: &mut Report) {
+        report.check(
+            "log",
+            self.log
+                .head()
+                .map(|head| format!("{} at record {}", self.log.path().display(), head.seq)),
+        );
+    }
+
+    fn publishes(&self) -> &'static [&'static str] {
+        &[ANCHOR_EVENT_TYPE]
+    }
+
+    fn attach(&self, publisher: Publisher) {
+        let _ = self.publisher.set(publisher);
+    }
+}
+
+/// Anchor the head every `interval`, whenever it has moved.
+async fn schedule(
+    log: Arc<AuditLog>,
+    anchors: Anchors,
+    publisher: Arc<OnceLock<Publisher>>,
+    interval: Duration,
+) {
+    let mut anchored = Head::default();
+    let mut ticks = tokio::time::interval_at(tokio::time::Instant::now() + interval, interval);
+    ticks.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    loop {
+        ticks.tick().await;
+        let head = match log.head() {
+            Ok(head) if head != anchored => head,
+            Ok(_) => continue,
+            Err(e) => {
+                eprintln!("Anchor failed: {e}");
+                continue;
+            }
+        };
+        let anchor = anchor(log.path(), &head);
+        if let Some(publisher) = publisher.get() {
+            let message = EmergentMessage::new(ANCHOR_EVENT_TYPE).with_payload(anchor.clone());
+            if let Err(e) = publisher.publish(message) {
+                eprintln!("Failed to publish {ANCHOR_EVENT_TYPE}: {e}");
+            }
+        }
+        match anchors.send(&anchor).await {
+            Ok(()) => anchored = head,
+            // The next tick anchors the head as it is then
+            Err(e) => eprintln!("Anchor failed: {e}"),
+        }
+    }
+}
+
+/// Verify `log` against `anchors`, printing the outcome.
+fn verify(log: &Path, anchors: Option<&Path>) -> Result<Head, String> {
+    let anchors = match anchors {
+        Some(path) => {
+            let file = std::fs::File::open(path).map_err(|e| format!("{}: {e}", path.display()))?;
+            chain::anchors(BufReader::new(file)).map_err(|e| format!("{}: {e}", path.display()))?
+        }
+        None => Default::default(),
+    };
+    let file = std::fs::File::open(log).map_err(|e| format!("{}: {e}", log.display()))?;
+    chain::verify(BufReader::new(file), &anchors)
+        .map_err(|tamper| format!("{}: tampered with at {tamper}", log.display()))
+}
+
+/// Run the primitive with `args` as its command line (the first item is
+/// the program name, as with `std::env::args_os`).
+pub async fn run<I, T>(args: I) -> Result<(), Box<dyn std::error::Error>>
+where
+    I: IntoIterator<Item = T>,
+    T: Into<std::ffi::OsString> + Clone,
+{
+    let args = Args::parse_from(args);
+
+    if let Some(Command::Verify { log, anchors }) = &args.command {
+        match verify(log, anchors.as_deref()) {
+            Ok(head) => {
+                println!(
+                    "{}: {} records verified, head {}",
+                    log.display(),
+                    head.seq,
+                    head.hash
+                );
+                return Ok(());
+            }
+            Err(e) => {
+                eprintln!("{e}");
+                std::process::exit(1);
+            }
+        }
+    }
+
+    let log = match AuditLog::open(&args.log) {
+        Ok(log) => Arc::new(log),
+        Err(e) => {
+            eprintln!("Error: {e}");
+            std::process::exit(1);
+        }
+    };
+
+    let config = SinkConfig {
+        name: "audit_sink",
+        subscribe: &args.subscribe,
+        would_have_as: "audit.would_have",
+        dead_letter_as: "audit.dead_letter",
+        settings: &args,
+    };
+    let publisher = Arc::new(OnceLock::new());
+
+    if args.anchor_interval > 0 && !args.sink.self_test {
+        if args.sink.dry_run {
+            eprintln!("Dry run: anchoring is skipped");
+        } else {
+            let anchors = Anchors {
+                file: args.anchor_file.clone(),
+                url: args.anchor_url.clone(),
+                token: args.anchor_token.clone(),
+                client: Client::new(),
+                timeout: Duration::from_millis(args.timeout),
+            };
+            tokio::spawn(schedule(
+                Arc::clone(&log),
+                anchors,
+                Arc::clone(&publisher),
+                Duration::from_secs(args.anchor_interval),
+            ));
+        }
+    }
+
+    run_sink(config, &args.sink, AuditSink { log, publisher }).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use emergent_testkit::fixtures::TempDir;
+    use emergent_testkit::{SinkFixture, fixtures, spawn_sink};
+
+    #[tokio::test]
+    async fn events_are_chained_into_the_log() {
+        let dir = TempDir::new("audit-sink");
+        let path = dir.path().join("audit.log");
+        let log = Arc::new(AuditLog::open(&path).unwrap_or_else(|e| panic!("{e}")));
+        let (mut engine, run) = spawn_sink(
+            SinkFixture::new("audit_sink", "audit"),
+            SinkArgs::default(),
+            AuditSink {
+                log: Arc::clone(&log),
+                publisher: Arc::new(OnceLock::new()),
+            },
+        );
+
+        for user in ["ada", "grace"] {
+            engine
+                .inject_message(fixtures::message("auth.login", json!({ "user": user })))
+                .await;
+        }
+        for _ in 0..100 {
+            if log.head().map(|head| head.seq).unwrap_or_default() == 2 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        engine.close();
+        assert_eq!(run.await.ok(), Some(Ok(())));
+
+        let head = verify(&path, None).unwrap_or_else(|e| panic!("{e}"));
+        assert_eq!(head.seq, 2);
+        let text = std::fs::read_to_string(&path).unwrap_or_default();
+        let second: Value =
+            serde_json::from_str(text.lines().nth(1).unwrap_or_default()).unwrap_or_default();
+        assert_eq!(second["message_type"], "auth.login");
+        assert_eq!(second["payload"], json!({"user": "grace"}));
+        assert!(second["message_id"].is_string());
+
+        // Anchors written to a file are held against the log
+        let anchors = Anchors {
+            file: Some(dir.path().join("anchors.jsonl")),
+            url: None,
+            token: None,
+            client: Client::new(),
+            timeout: Duration::from_secs(1),
+        };
+        anchors
+            .send(&anchor(&path, &head))
+            .await
+            .unwrap_or_else(|e| panic!("{e}"));
+        let anchor_file = dir.path().join("anchors.jsonl");
+        assert!(verify(&path, Some(&anchor_file)).is_ok());
+        std::fs::write(&path, text.replace("grace", "mallory")).unwrap_or_else(|e| panic!("{e}"));
+        let error = verify(&path, Some(&anchor_file)).err().unwrap_or_default();
+        assert!(error.contains("tampered with at line 2: hash"), "{error}");
+
+        let args = Args::try_parse_from(["audit-sink", "verify", "--log", "x.log"])
+            .unwrap_or_else(|e| panic!("{e}"));
+        assert!(matches!(args.command, Some(Command::Verify { .. })));
+    }
+}
@@ -0,0 +1,153 @@
+//! The append-only log file.
+//!
+//! Records are appended one line at a time and synced to disk before the
+//! message is acknowledged. On open, the chain continues from the last
+//! record, which must be complete and hash to what it says; anything else
+//! is left for `verify` to explain rather than chained onto.
+
+use crate::chain::Head;
+use serde_json::{Map, Value};
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// An open audit log.
+pub struct AuditLog {
+    path: PathBuf,
+    state: Mutex<(File, Head)>,
+}
+
+/// The head of the log at `path`, from its last record.
+fn recover(path: &Path) -> Result<Head, String> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Head::default()),
+        Err(e) => return Err(format!("{}: {e}", path.display())),
+    };
+    let mut reader = BufReader::new(file);
+    let mut last = String::new();
+    let mut line = String::new();
+    loop {
+        line.clear();
+        let read = reader
+            .read_line(&mut line)
+            .map_err(|e| format!("{}: {e}", path.display()))?;
+        if read == 0 {
+            break;
+        }
+        std::mem::swap(&mut last, &mut line);
+    }
+    if last.is_empty() {
+        return Ok(Head::default());
+    }
+    let broken = |reason: &str| {
+        format!(
+            "{}: the last record {reason}; run `audit-sink verify` before appending",
+            path.display()
+        )
+    };
+    if !last.ends_with('\n') {
+        return Err(broken("is incomplete"));
+    }
+    let record: Value = serde_json::from_str(&last).map_err(|_| broken("is not JSON"))?;
+    let seq = record["seq"]
+        .as_u64()
+        .filter(|seq| *seq > 0)
+        .ok_or_else(|| broken("has no seq"))?;
+    let previous = Head {
+        seq: seq - 1,
+        hash: record["prev"].as_str().unwrap_or_default().to_string(),
+    };
+    previous
+        .follow(&record)
+        .map_err(|_| broken("does not match its hash"))
+}
+
+impl AuditLog {
+    /// Open the log at `path` for appending, creating it if need be.
+    pub fn open(path: &Path) -> Result<Self, String> {
+        let head = recover(path)?;
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(|e| format!("{}: {e}", path.display()))?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            state: Mutex::new((file, head)),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The current head.
+    pub fn head(&self) -> Result<Head, String> {
+        let state = self
+            .state
+            .lock()
+            .map_err(|_| "audit log lock poisoned".to_string())?;
+        Ok(state.1.clone())
+    }
+
+    /// Append `fields` as the next record, returning the new head.
+    pub fn append(&self, fields: Map<String, Value>) -> Result<Head, String> {
+        let mut state = self
+            .state
+            .lock()
+            .map_err(|_| "audit log lock poisoned".to_string())?;
+        let (file, head) = &mut *state;
+        let mut next = head.clone();
+        let record = next.seal(fields);
+        let mut line = record.to_string();
+        line.push('\n');
+        file.write_all(line.as_bytes())
+            .and_then(|()| file.sync_data())
+            .map_err(|e| format!("{}: {e}", self.path.display()))?;
+        *head = next;
+        Ok(head.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chain;
+    use serde_json::json;
+
+    #[test]
+    fn the_chain_continues_across_reopens() {
+        let path = std::env::temp_dir().join(format!("audit-sink-{}.log", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let fields = |n: i64| {
+            let mut fields = Map::new();
+            fields.insert("payload".to_string(), json!({ "n": n }));
+            fields
+        };
+
+        let log = AuditLog::open(&path).unwrap_or_else(|e| panic!("{e}"));
+        log.append(fields(1)).unwrap_or_else(|e| panic!("{e}"));
+        let head = log.append(fields(2)).unwrap_or_else(|e| panic!("{e}"));
+        drop(log);
+        let log = AuditLog::open(&path).unwrap_or_else(|e| panic!("{e}"));
+        assert_eq!(log.head().ok(), Some(head));
+        log.append(fields(3)).unwrap_or_else(|e| panic!("{e}"));
+        drop(log);
+
+        let text = std::fs::read_to_string(&path).unwrap_or_default();
+        let verified = chain::verify(text.as_bytes(), &Default::default());
+        assert_eq!(verified.map(|head| head.seq), Ok(3));
+
+        // A torn write is not chained onto
+        std::fs::write(&path, text + r#"{"seq": 4, "pa"#).unwrap_or_else(|e| panic!("{e}"));
+        let error = AuditLog::open(&path).err().unwrap_or_default();
+        assert!(
+            error.ends_with(
+                "the last record is incomplete; run `audit-sink verify` before appending"
+            )
+        );
+        let _ = std::fs::remove_file(&path);
+    }
+}
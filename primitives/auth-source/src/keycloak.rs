@@ -0,0 +1,283 @@
+//! Polling Keycloak's admin events API.
+//!
+//! With `--keycloak-url`, auth-source also reads a realm's stored login
+//! events (`GET /admin/realms/{realm}/events`), so no event-listener
+//! extension is needed. The realm must have "Save events" enabled for
+//! `LOGIN` and `LOGIN_ERROR`, and the client given by `--keycloak-client-id`
+//! needs the `realm-management` `view-events` role and client credentials
+//! enabled.
+//!
+//! The API returns events newest first without a cursor, so each poll reads
+//! pages until it reaches events it has already seen. The first poll only
+//! records where the stream stands; events stored while the source was not
+//! running are not replayed.
+
+use clap::Args;
+use reqwest::Client;
+use serde_json::Value;
+use std::collections::HashSet;
+use std::time::Duration;
+
+/// Events requested per page.
+const PAGE_SIZE: usize = 100;
+
+/// Pages read per poll at most, bounding the catch-up after a burst.
+const MAX_PAGES: usize = 10;
+
+/// Keycloak admin events API settings.
+#[derive(Args, Debug, Clone)]
+pub struct KeycloakArgs {
+    /// Keycloak base URL; polls the realm's login events when set.
+    #[arg(long, env = "KEYCLOAK_URL", requires_all = ["keycloak_client_id", "keycloak_client_secret"])]
+    pub keycloak_url: Option<String>,
+
+    /// Realm whose events are read.
+    #[arg(long, env = "KEYCLOAK_REALM", default_value = "master")]
+    pub keycloak_realm: String,
+
+    /// Client used to read events (client credentials grant).
+    #[arg(long, env = "KEYCLOAK_CLIENT_ID")]
+    pub keycloak_client_id: Option<String>,
+
+    /// Secret of `--keycloak-client-id`.
+    #[arg(long, env = "KEYCLOAK_CLIENT_SECRET", hide_env_values = true)]
+    pub keycloak_client_secret: Option<String>,
+
+    /// Milliseconds between polls.
+    #[arg(long, env = "KEYCLOAK_POLL_INTERVAL", default_value = "30000")]
+    pub keycloak_interval: u64,
+}
+
+/// Where the event stream stood at the last poll.
+struct Position {
+    /// Time of the newest event seen.
+    time: i64,
+    /// Events seen with exactly that time, which the next poll returns again.
+    seen: HashSet<String>,
+}
+
+/// Reads new login events from one realm.
+pub struct Poller {
+    client: Client,
+    base_url: String,
+    realm: String,
+    client_id: String,
+    client_secret: String,
+    position: Option<Position>,
+}
+
+/// Identity of an event for de-duplication: its id where Keycloak has one.
+fn key(event: &Value) -> String {
+    match event["id"].as_str() {
+        Some(id) => id.to_string(),
+        None => event.to_string(),
+    }
+}
+
+impl Poller {
+    /// `None` without `--keycloak-url`.
+    pub fn new(args: &KeycloakArgs) -> Option<Self> {
+        Some(Self {
+            client: Client::builder()
+                .timeout(Duration::from_secs(30))
+                .build()
+                .ok()?,
+            base_url: args
+                .keycloak_url
+                .as_deref()?
+                .trim_end_matches('/')
+                .to_string(),
+            realm: args.keycloak_realm.clone(),
+            client_id: args.keycloak_client_id.clone()?,
+            client_secret: args.keycloak_client_secret.clone()?,
+            position: None,
+        })
+    }
+
+    /// An access token for the admin API.
+    pub async fn token(&self) -> Result<String, String> {
+        let url = format!(
+            "{}/realms/{}/protocol/openid-connect/token",
+            self.base_url, self.realm
+        );
+        let response: Value = self
+            .client
+            .post(&url)
+            .form(&[
+                ("grant_type", "client_credentials"),
+                ("client_id", self.client_id.as_str()),
+                ("client_secret", self.client_secret.as_str()),
+            ])
+            .send()
+            .await
+            .and_then(reqwest::Response::error_for_status)
+            .map_err(|e| format!("token: {e}"))?
+            .json()
+            .await
+            .map_err(|e| format!("token: {e}"))?;
+        response["access_token"]
+            .as_str()
+            .map(str::to_string)
+            .ok_or_else(|| "token: no access_token in response".to_string())
+    }
+
+    /// Events stored since the last poll, oldest first.
+    pub async fn poll(&mut self) -> Result<Vec<Value>, String> {
+        let token = self.token().await?;
+        let url = format!("{}/admin/realms/{}/events", self.base_url, self.realm);
+
+        let mut fresh = Vec::new();
+        let mut pages = Vec::new();
+        'pages: for page in 0..MAX_PAGES {
+            let first = (page * PAGE_SIZE).to_string();
+            let max = PAGE_SIZE.to_string();
+            let events: Vec<Value> = self
+                .client
+                .get(&url)
+                .query(&[
+                    ("type", "LOGIN"),
+                    ("type", "LOGIN_ERROR"),
+                    ("first", &first),
+                    ("max", &max),
+                ])
+                .bearer_auth(&token)
+                .send()
+                .await
+                .and_then(reqwest::Response::error_for_status)
+                .map_err(|e| format!("events: {e}"))?
+                .json()
+                .await
+                .map_err(|e| format!("events: {e}"))?;
+            let full = events.len() == PAGE_SIZE;
+            pages.push(events);
+            let Some(position) = &self.position else {
+                // First poll: only the newest event matters
+                break;
+            };
+            for event in pages.last().into_iter().flatten() {
+                let time = event["time"].as_i64().unwrap_or_default();
+                if time < position.time
+                    || (time == position.time && position.seen.contains(&key(event)))
+                {
+                    break 'pages;
+                }
+                fresh.push(event.clone());
+            }
+            if !full {
+                break;
+            }
+        }
+
+        // Remember the newest time and every event seen at it
+        let newest = pages
+            .iter()
+            .flatten()
+            .filter_map(|e| e["time"].as_i64())
+            .max();
+        if let Some(newest) = newest {
+            let seen = pages
+                .iter()
+                .flatten()
+                .filter(|e| e["time"].as_i64() == Some(newest))
+                .map(key);
+            match &mut self.position {
+                Some(position) if position.time == newest => position.seen.extend(seen),
+                _ => {
+                    self.position = Some(Position {
+                        time: newest,
+                        seen: seen.collect(),
+                    })
+                }
+            }
+        } else if self.position.is_none() {
+            // An empty realm: everything from now on is new
+            self.position = Some(Position {
+                time: i64::MIN,
+                seen: HashSet::new(),
+            });
+        }
+
+        fresh.reverse();
+        Ok(fresh)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{
+        Json, Router,
+        routing::{get, post},
+    };
+    use serde_json::json;
+    use std::sync::{Arc, Mutex, PoisonError};
+
+    #[tokio::test]
+    async fn each_event_is_returned_once() {
+        let stored = Arc::new(Mutex::new(vec![
+            json!({"id": "e1", "time": 1000, "type": "LOGIN", "realmId": "acme"}),
+        ]));
+        let events = Arc::clone(&stored);
+        let app = Router::new()
+            .route(
+                "/realms/acme/protocol/openid-connect/token",
+                post(|| async { Json(json!({"access_token": "t0k3n"})) }),
+            )
+            .route(
+                "/admin/realms/acme/events",
+                get(move || {
+                    // Newest first, as Keycloak returns them
+                    let mut events = events
+                        .lock()
+                        .unwrap_or_else(PoisonError::into_inner)
+                        .clone();
+                    events.reverse();
+                    async move { Json(events) }
+                }),
+            );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap_or_else(|e| panic!("bind: {e}"));
+        let addr = listener
+            .local_addr()
+            .unwrap_or_else(|e| panic!("local_addr: {e}"));
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let mut poller = Poller::new(&KeycloakArgs {
+            keycloak_url: Some(format!("http://{addr}")),
+            keycloak_realm: "acme".to_string(),
+            keycloak_client_id: Some("emergent".to_string()),
+            keycloak_client_secret: Some("s3cret".to_string()),
+            keycloak_interval: 1000,
+        })
+        .unwrap_or_else(|| panic!("no poller"));
+        let poll = async |poller: &mut Poller| -> Vec<String> {
+            poller
+                .poll()
+                .await
+                .unwrap_or_else(|e| panic!("poll: {e}"))
+                .iter()
+                .map(key)
+                .collect()
+        };
+
+        // The first poll only records the position
+        assert!(poll(&mut poller).await.is_empty());
+
+        stored
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .extend([
+                json!({"id": "e2", "time": 2000, "type": "LOGIN_ERROR", "realmId": "acme"}),
+                json!({"id": "e3", "time": 2000, "type": "LOGIN", "realmId": "acme"}),
+            ]);
+        assert_eq!(poll(&mut poller).await, ["e2", "e3"]);
+
+        stored
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push(json!({"id": "e4", "time": 2000, "type": "LOGIN", "realmId": "acme"}));
+        assert_eq!(poll(&mut poller).await, ["e4"]);
+        assert!(poll(&mut poller).await.is_empty());
+    }
+}
@@ -0,0 +1,411 @@
+//! Auth Source - Normalized Authentication Events
+//!
+//! A Source that ingests authentication events from identity providers and
+//! emits them in one shape, whatever their origin: `auth.login`,
+//! `auth.login_failed` and `auth.mfa_challenge`, each with the user, IP
+//! address and user agent (see [`normalize`]). Security pipelines (lockouts,
+//! alerts on logins from new places) can then react without knowing which
+//! provider is in use.
+//!
+//! Events arrive two ways:
+//!
+//! - **Webhook** - Auth0 log streams (custom webhook, JSON array) and
+//!   Keycloak event-listener extensions POST to `--path`. The provider is
+//!   recognised from each event's shape.
+//! - **Keycloak admin events** - with `--keycloak-url`, a realm's stored
+//!   login events are polled (see [`keycloak`]).
+//!
+//! With `--token`, webhook requests must send it in `Authorization`, either
+//! bare or as `Bearer <token>` (Auth0 sends the configured header value
+//! verbatim).
+//!
+//! `--emit-type-template` can also use `{provider}`. A delivery whose
+//! events cannot be published or spooled is answered with 503 so the
+//! provider retries it.
+//!
+//! Sources are SILENT - they only produce domain messages.
+//! All lifecycle events are published by the engine.
+//!
+//! # Usage
+//!
+//! ```bash
+//! # Auth0 log stream webhook
+//! auth-source --path /auth0 --token $AUTH_SOURCE_TOKEN
+//!
+//! # Keycloak, polling the realm's events
+//! auth-source --keycloak-url https://sso.example.com --keycloak-realm acme \
+//!   --keycloak-client-id emergent --keycloak-client-secret $KEYCLOAK_CLIENT_SECRET
+//! ```
+//!
+//! On SIGTERM the listener closes immediately and polling stops; deliveries
+//! and a Keycloak poll already being handled get `--drain-timeout`
+//! milliseconds to finish publishing.
+
+pub mod keycloak;
+pub mod normalize;
+
+use axum::{
+    Router,
+    body::Bytes,
+    extract::State,
+    http::{HeaderMap, StatusCode, header},
+    response::IntoResponse,
+    routing::post,
+};
+use clap::Parser;
+use emergent_client::EmergentSource;
+use keycloak::{KeycloakArgs, Poller};
+use normalize::{AuthEvent, EVENT_TYPES};
+use primitive_common::capabilities::{self, CapabilityArgs, Role, VERSION, capabilities_message};
+use primitive_common::doctor::Report;
+use primitive_common::source::{Outlet, SourceArgs};
+use serde_json::Value;
+use std::{future::IntoFuture, net::SocketAddr, sync::Arc, time::Duration};
+use subtle::ConstantTimeEq;
+use tokio::{
+    signal::unix::{SignalKind, signal},
+    sync::Notify,
+};
+
+/// Authentication event receiver that emits auth.* events.
+#[derive(Parser, Debug, Clone)]
+#[command(name = "auth-source", version = VERSION)]
+#[command(about = "Receives identity provider events and emits normalized auth events")]
+struct Args {
+    /// Port to listen on.
+    #[arg(short, long, env = "AUTH_SOURCE_PORT", default_value = "8080")]
+    port: u16,
+
+    /// Host to bind to.
+    #[arg(long, env = "AUTH_SOURCE_HOST", default_value = "0.0.0.0")]
+    host: String,
+
+    /// Path to accept webhook deliveries on.
+    #[arg(long, env = "AUTH_SOURCE_PATH", default_value = "/")]
+    path: String,
+
+    /// Token webhook requests must send in Authorization.
+    #[arg(long, env = "AUTH_SOURCE_TOKEN", hide_env_values = true)]
+    token: Option<String>,
+
+    #[command(flatten)]
+    keycloak: KeycloakArgs,
+
+    /// Verify external dependencies and exit without connecting to the engine.
+    #[arg(long)]
+    self_test: bool,
+
+    #[command(flatten)]
+    source: SourceArgs,
+
+    #[command(flatten)]
+    capabilities: CapabilityArgs,
+}
+
+/// Variables available to `--emit-type-template`.
+const TEMPLATE_VARIABLES: &[&str] = &["source", "provider"];
+
+/// Shared application state.
+struct AppState {
+    outlet: Arc<Outlet>,
+    token: Option<String>,
+}
+
+/// Whether an `Authorization` header carries `token`, bare or as a bearer token.
+fn authorized(header: Option<&str>, token: &str) -> bool {
+    header
+        .map(|h| h.strip_prefix("Bearer ").unwrap_or(h))
+        .is_some_and(|t| bool::from(t.as_bytes().ct_eq(token.as_bytes())))
+}
+
+/// Publish normalized events, stopping at the first that is lost.
+async fn publish(outlet: &Outlet, events: Vec<AuthEvent>) -> Result<(), String> {
+    for event in events {
+        let provider = event.payload["provider"]
+            .as_str()
+            .unwrap_or_default()
+            .to_string();
+        outlet
+            .publish(event.event_type, &[("provider", &provider)], event.payload)
+            .await?;
+    }
+    Ok(())
+}
+
+/// Handles one webhook delivery.
+async fn handle_delivery(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> impl IntoResponse {
+    if let Some(token) = &state.token {
+        let header = headers
+            .get(header::AUTHORIZATION)
+            .and_then(|h| h.to_str().ok());
+        if !authorized(header, token) {
+            return (StatusCode::UNAUTHORIZED, "Invalid or missing token");
+        }
+    }
+    let Ok(body) = serde_json::from_slice::<Value>(&body) else {
+        return (StatusCode::BAD_REQUEST, "Body is not JSON");
+    };
+
+    // Other events (token refreshes, API calls, ...) are acknowledged and dropped
+    let events = normalize::events(body)
+        .iter()
+        .filter_map(normalize::normalize)
+        .collect();
+    // The outlet logs and reports what it cannot deliver; the sender retries
+    match publish(&state.outlet, events).await {
+        Ok(()) => (StatusCode::OK, ""),
+        Err(_) => (StatusCode::SERVICE_UNAVAILABLE, "Failed to publish event"),
+    }
+}
+
+/// Polls Keycloak's admin events API until `stop` is notified; a poll in
+/// progress finishes publishing first.
+async fn poll_keycloak(
+    mut poller: Poller,
+    interval: Duration,
+    outlet: Arc<Outlet>,
+    stop: Arc<Notify>,
+) {
+    let mut interval = tokio::time::interval(interval);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {}
+            () = stop.notified() => return,
+        }
+        let events = match poller.poll().await {
+            Ok(events) => events,
+            Err(e) => {
+                eprintln!("Keycloak poll failed: {e}");
+                continue;
+            }
+        };
+        let events = events.iter().filter_map(normalize::normalize).collect();
+        let _ = publish(&outlet, events).await;
+    }
+}
+
+/// Runs `--self-test` checks and exits.
+async fn self_test(args: &Args, name: &str) -> ! {
+    let mut report = Report::new(name);
+    let addr = format!("{}:{}", args.host, args.port);
+    let bind = addr
+        .parse::<SocketAddr>()
+        .map_err(|e| format!("{addr}: {e}"))
+        .and_then(|a| {
+            std::net::TcpListener::bind(a)
+                .map(|_| addr.clone())
+                .map_err(|e| format!("{addr}: {e}"))
+        });
+    report.check("bind", bind);
+    report.check("path", check_path(&args.path));
+    if let Some(poller) = Poller::new(&args.keycloak) {
+        let token = poller
+            .token()
+            .await
+            .map(|_| format!("realm {}", args.keycloak.keycloak_realm));
+        report.check("keycloak", token);
+    }
+    args.source.self_test(&mut report, TEMPLATE_VARIABLES);
+    report.finish()
+}
+
+/// Check that the webhook path is absolute.
+fn check_path(path: &str) -> Result<String, String> {
+    if path.starts_with('/') {
+        Ok(path.to_string())
+    } else {
+        Err(format!("{path} must start with '/'"))
+    }
+}
+
+/// Run the primitive with `args` as its command line (the first item is
+/// the program name, as with `std::env::args_os`).
+pub async fn run<I, T>(args: I) -> Result<(), Box<dyn std::error::Error>>
+where
+    I: IntoIterator<Item = T>,
+    T: Into<std::ffi::OsString> + Clone,
+{
+    let args = Args::parse_from(args);
+
+    // Get the source name from environment (set by engine) or use default
+    let name = std::env::var("EMERGENT_NAME").unwrap_or_else(|_| "auth-source".to_string());
+
+    if args.self_test {
+        self_test(&args, &name).await;
+    }
+    if let Err(e) = check_path(&args.path).and_then(|_| args.source.validate(TEMPLATE_VARIABLES)) {
+        eprintln!("Error: {e}");
+        std::process::exit(1);
+    }
+
+    let produces = args.source.produces(&EVENT_TYPES);
+    let produces: Vec<&str> = produces.iter().map(String::as_str).collect();
+    let descriptor = args
+        .capabilities
+        .describe(&name, Role::Source, &[], &produces, &args);
+    if args.capabilities.capabilities {
+        capabilities::print(&descriptor);
+    }
+
+    // Connect to the Emergent engine (silently - lifecycle events come from engine)
+    let source = match EmergentSource::connect(&name).await {
+        Ok(s) => s,
+        Err(e) => {
+            eprintln!("Failed to connect to Emergent engine: {e}");
+            std::process::exit(1);
+        }
+    };
+    let outlet = Arc::new(Outlet::new(source, &name, &args.source)?);
+    if args.capabilities.announce {
+        outlet.announce(capabilities_message(&descriptor)).await;
+    }
+    tokio::spawn(Arc::clone(&outlet).drain_every(args.source.spool.retry_interval()));
+
+    let state = Arc::new(AppState {
+        outlet: Arc::clone(&outlet),
+        token: args.token.clone(),
+    });
+    let stop_polling = Arc::new(Notify::new());
+    let poller = Poller::new(&args.keycloak).map(|poller| {
+        let interval = Duration::from_millis(args.keycloak.keycloak_interval);
+        let (outlet, stop) = (Arc::clone(&outlet), Arc::clone(&stop_polling));
+        tokio::spawn(poll_keycloak(poller, interval, outlet, stop))
+    });
+    let app = Router::new()
+        .route(&args.path, post(handle_delivery))
+        .with_state(state);
+
+    let addr: SocketAddr = format!("{}:{}", args.host, args.port).parse()?;
+
+    // Set up SIGTERM handler for graceful shutdown
+    let mut sigterm = signal(SignalKind::terminate())?;
+
+    let shutdown = Arc::new(Notify::new());
+    let server = axum::serve(
+        tokio::net::TcpListener::bind(&addr).await?,
+        app.into_make_service(),
+    )
+    .with_graceful_shutdown({
+        let shutdown = Arc::clone(&shutdown);
+        async move { shutdown.notified().await }
+    })
+    .into_future();
+    tokio::pin!(server);
+
+    tokio::select! {
+        result = &mut server => {
+            result?;
+        }
+        _ = sigterm.recv() => {
+            // Stop accepting connections and let in-flight deliveries finish
+            shutdown.notify_one();
+            stop_polling.notify_one();
+            let in_flight = async {
+                let _ = (&mut server).await;
+                if let Some(poller) = poller {
+                    let _ = poller.await;
+                }
+            };
+            args.source.drain.drain("in-flight deliveries", in_flight).await;
+            outlet.disconnect().await;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use emergent_testkit::{MockEngine, fixtures};
+    use serde_json::json;
+    use std::path::Path;
+
+    /// Connect to the engine on `socket` as the source would.
+    async fn connect(socket: &Path, args: &Args) -> Arc<AppState> {
+        let source = EmergentSource::connect_to("auth-source", socket)
+            .await
+            .unwrap_or_else(|e| panic!("connect: {e}"));
+        let outlet = Outlet::new(source, "auth-source", &args.source)
+            .unwrap_or_else(|e| panic!("open spool: {e}"));
+        Arc::new(AppState {
+            outlet: Arc::new(outlet),
+            token: args.token.clone(),
+        })
+    }
+
+    async fn deliver(state: &Arc<AppState>, body: &Value) -> StatusCode {
+        handle_delivery(
+            State(Arc::clone(state)),
+            HeaderMap::new(),
+            Bytes::from(body.to_string()),
+        )
+        .await
+        .into_response()
+        .status()
+    }
+
+    fn failed_login() -> Value {
+        json!({"log_id": "900", "data": {
+            "date": "2023-10-16T11:43:20.123Z", "type": "fp", "ip": "203.0.113.7",
+            "user_id": "auth0|1", "user_name": "ada@example.com"
+        }})
+    }
+
+    #[tokio::test]
+    async fn events_survive_the_engine_being_down() {
+        let dir = fixtures::TempDir::new("auth-source-spool");
+        let socket = dir.path().join("engine.sock");
+        let spool = dir.path().join("spool");
+        let args = Args::parse_from([
+            "auth-source",
+            "--emit-type-template",
+            "{provider}.{type}",
+            "--spool-dir",
+            spool.to_str().unwrap_or_default(),
+        ]);
+
+        let mut engine = MockEngine::serve(&socket);
+        let state = connect(&socket, &args).await;
+        engine.shut_down().await;
+        assert_eq!(
+            deliver(&state, &json!([failed_login()])).await,
+            StatusCode::OK
+        );
+        drop(state);
+
+        let mut engine = MockEngine::serve(&socket);
+        let state = connect(&socket, &args).await;
+        state.outlet.drain_spool().await;
+        let published = engine.expect_published("auth0.auth.login_failed").await;
+        assert_eq!(published.payload()["ip"], "203.0.113.7");
+        engine.shut_down().await;
+    }
+
+    #[tokio::test]
+    async fn undeliverable_batches_are_refused_for_a_retry() {
+        let dir = fixtures::TempDir::new("auth-source-down");
+        let socket = dir.path().join("engine.sock");
+        let args = Args::parse_from(["auth-source"]);
+
+        let mut engine = MockEngine::serve(&socket);
+        let state = connect(&socket, &args).await;
+        engine.shut_down().await;
+        let status = deliver(&state, &failed_login()).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    }
+
+    #[test]
+    fn tokens_are_accepted_bare_or_as_bearer() {
+        assert!(authorized(Some("Bearer s3cret"), "s3cret"));
+        assert!(authorized(Some("s3cret"), "s3cret"));
+        assert!(!authorized(Some("Bearer s3cre"), "s3cret"));
+        assert!(!authorized(Some("Basic s3cret"), "s3cret"));
+        assert!(!authorized(None, "s3cret"));
+    }
+}
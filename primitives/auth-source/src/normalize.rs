@@ -0,0 +1,256 @@
+//! Turning provider events into `auth.*` events.
+//!
+//! Keycloak events (as its admin API returns them, or as event-listener
+//! extensions post them) and Auth0 log events (as log streams post them)
+//! are recognised by shape and mapped to:
+//!
+//! - `auth.login` - Keycloak `LOGIN`, Auth0 `s`
+//! - `auth.login_failed` - Keycloak `LOGIN_ERROR`, Auth0 `f`, `fp`, `fu`,
+//!   `fcoa`, `limit_wc`, `limit_mu`
+//! - `auth.mfa_challenge` - Auth0 `mfar`, `gd_start_auth` and `gd_send_*`
+//!
+//! Every event has the same fields, `null` where the provider has no value:
+//!
+//! ```json
+//! {
+//!   "provider": "auth0",
+//!   "event_id": "90020231016114320123000000000000001223372036854775807",
+//!   "time": 1697456600123,
+//!   "user_id": "auth0|64f1c0a3e5b2d4f0e9b8a1d2",
+//!   "username": "ada@example.com",
+//!   "ip": "203.0.113.7",
+//!   "user_agent": "Mozilla/5.0 ...",
+//!   "client_id": "AaiyAPdpYdesoKnqjj8HJqRn4T5titww",
+//!   "realm": null,
+//!   "session_id": null,
+//!   "reason": "Wrong email or password.",
+//!   "method": null,
+//!   "raw": {...}
+//! }
+//! ```
+//!
+//! `time` is in Unix milliseconds. `reason` is set on failures and `method`
+//! (`push`, `sms`, `voice`, `email`) on MFA challenges where known. `raw`
+//! is the provider's event as received.
+
+use primitive_common::time::days_from_civil;
+use serde_json::{Value, json};
+
+pub const LOGIN_EVENT_TYPE: &str = "auth.login";
+pub const LOGIN_FAILED_EVENT_TYPE: &str = "auth.login_failed";
+pub const MFA_CHALLENGE_EVENT_TYPE: &str = "auth.mfa_challenge";
+
+/// Every type the source publishes.
+pub const EVENT_TYPES: [&str; 3] = [
+    LOGIN_EVENT_TYPE,
+    LOGIN_FAILED_EVENT_TYPE,
+    MFA_CHALLENGE_EVENT_TYPE,
+];
+
+/// Auth0 log types of failed logins.
+const AUTH0_FAILURES: [&str; 6] = ["f", "fp", "fu", "fcoa", "limit_wc", "limit_mu"];
+
+/// A normalized event ready to publish.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AuthEvent {
+    pub event_type: &'static str,
+    pub payload: Value,
+}
+
+/// The events in a webhook body: one event or an array of them.
+pub fn events(body: Value) -> Vec<Value> {
+    match body {
+        Value::Array(events) => events,
+        event => vec![event],
+    }
+}
+
+/// The `auth.*` event for a provider event, or `None` for events of other
+/// kinds or from unknown providers.
+pub fn normalize(event: &Value) -> Option<AuthEvent> {
+    if event["data"].is_object() && event["log_id"].is_string() {
+        auth0(event)
+    } else if event["type"].is_string() && event["realmId"].is_string() {
+        keycloak(event)
+    } else {
+        None
+    }
+}
+
+fn keycloak(event: &Value) -> Option<AuthEvent> {
+    let details = &event["details"];
+    let event_type = match event["type"].as_str()? {
+        "LOGIN" => LOGIN_EVENT_TYPE,
+        "LOGIN_ERROR" => LOGIN_FAILED_EVENT_TYPE,
+        _ => return None,
+    };
+    let payload = json!({
+        "provider": "keycloak",
+        "event_id": event["id"],
+        "time": event["time"],
+        "user_id": event["userId"],
+        "username": details["username"],
+        "ip": event["ipAddress"],
+        "user_agent": details["user_agent"],
+        "client_id": event["clientId"],
+        "realm": event["realmId"],
+        "session_id": event["sessionId"],
+        "reason": event["error"],
+        "method": null,
+        "raw": event,
+    });
+    Some(AuthEvent {
+        event_type,
+        payload,
+    })
+}
+
+fn auth0(event: &Value) -> Option<AuthEvent> {
+    let data = &event["data"];
+    let log_type = data["type"].as_str()?;
+    let (event_type, method) = match log_type {
+        "s" => (LOGIN_EVENT_TYPE, None),
+        t if AUTH0_FAILURES.contains(&t) => (LOGIN_FAILED_EVENT_TYPE, None),
+        "mfar" | "gd_start_auth" => (MFA_CHALLENGE_EVENT_TYPE, None),
+        "gd_send_pn" => (MFA_CHALLENGE_EVENT_TYPE, Some("push")),
+        "gd_send_sms" => (MFA_CHALLENGE_EVENT_TYPE, Some("sms")),
+        "gd_send_voice" => (MFA_CHALLENGE_EVENT_TYPE, Some("voice")),
+        "gd_send_email" => (MFA_CHALLENGE_EVENT_TYPE, Some("email")),
+        _ => return None,
+    };
+    let reason = match event_type {
+        LOGIN_FAILED_EVENT_TYPE => data["description"].clone(),
+        _ => Value::Null,
+    };
+    let payload = json!({
+        "provider": "auth0",
+        "event_id": event["log_id"],
+        "time": data["date"].as_str().and_then(unix_millis),
+        "user_id": data["user_id"],
+        "username": data["user_name"],
+        "ip": data["ip"],
+        "user_agent": data["user_agent"],
+        "client_id": data["client_id"],
+        "realm": null,
+        "session_id": data["session_id"],
+        "reason": reason,
+        "method": method,
+        "raw": event,
+    });
+    Some(AuthEvent {
+        event_type,
+        payload,
+    })
+}
+
+/// Unix milliseconds of an RFC 3339 timestamp such as
+/// `2023-10-16T11:43:20.123Z` or `2023-10-16T13:43:20+02:00`.
+pub fn unix_millis(timestamp: &str) -> Option<i64> {
+    let number = |s: &str| s.parse::<i64>().ok();
+    let (date, time) = timestamp.split_once(['T', 't', ' '])?;
+    let mut date = date.splitn(3, '-');
+    let (year, month, day) = (
+        number(date.next()?)?,
+        number(date.next()?)?,
+        number(date.next()?)?,
+    );
+
+    let (clock, offset) = match time.find(['Z', 'z', '+', '-']) {
+        Some(at) => time.split_at(at),
+        None => return None,
+    };
+    let offset_minutes = match offset {
+        "Z" | "z" => 0,
+        _ => {
+            let sign = if offset.starts_with('-') { -1 } else { 1 };
+            let (hours, minutes) = offset[1..].split_once(':')?;
+            sign * (number(hours)? * 60 + number(minutes)?)
+        }
+    };
+    let (clock, fraction) = clock.split_once('.').unwrap_or((clock, ""));
+    let mut clock = clock.splitn(3, ':');
+    let (hour, minute, second) = (
+        number(clock.next()?)?,
+        number(clock.next()?)?,
+        number(clock.next()?)?,
+    );
+    let millis = match fraction {
+        "" => 0,
+        digits if digits.bytes().all(|b| b.is_ascii_digit()) => {
+            number(&format!("{digits:0<3}")[..3])?
+        }
+        _ => return None,
+    };
+
+    let seconds = days_from_civil(year, month, day) * 86_400 + hour * 3600 + minute * 60 + second
+        - offset_minutes * 60;
+    Some(seconds * 1000 + millis)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keycloak_events_are_normalized() {
+        let event = json!({
+            "id": "4b4c2f", "time": 1697456600123i64, "type": "LOGIN_ERROR", "realmId": "acme",
+            "clientId": "portal", "userId": "f3a1", "ipAddress": "203.0.113.7",
+            "error": "invalid_user_credentials", "details": {"username": "ada"}
+        });
+        let normalized = normalize(&event).unwrap_or_else(|| panic!("not normalized"));
+        assert_eq!(normalized.event_type, LOGIN_FAILED_EVENT_TYPE);
+        assert_eq!(normalized.payload["username"], "ada");
+        assert_eq!(normalized.payload["ip"], "203.0.113.7");
+        assert_eq!(normalized.payload["reason"], "invalid_user_credentials");
+        assert_eq!(normalized.payload["realm"], "acme");
+        assert_eq!(normalized.payload["raw"], event);
+
+        let refresh = json!({"type": "REFRESH_TOKEN", "realmId": "acme"});
+        assert_eq!(normalize(&refresh), None);
+    }
+
+    #[test]
+    fn auth0_logs_are_normalized() {
+        let log = |log_type: &str| {
+            json!({"log_id": "900", "data": {
+                "date": "2023-10-16T11:43:20.123Z", "type": log_type,
+                "description": "Wrong email or password.", "ip": "203.0.113.7",
+                "user_agent": "Mozilla/5.0", "user_id": "auth0|1", "user_name": "ada@example.com"
+            }})
+        };
+        let failed = normalize(&log("fp")).unwrap_or_else(|| panic!("not normalized"));
+        assert_eq!(failed.event_type, LOGIN_FAILED_EVENT_TYPE);
+        assert_eq!(failed.payload["time"], 1697456600123i64);
+        assert_eq!(failed.payload["user_agent"], "Mozilla/5.0");
+        assert_eq!(failed.payload["reason"], "Wrong email or password.");
+
+        let login = normalize(&log("s")).unwrap_or_else(|| panic!("not normalized"));
+        assert_eq!(login.event_type, LOGIN_EVENT_TYPE);
+        assert_eq!(login.payload["reason"], Value::Null);
+
+        let challenge = normalize(&log("gd_send_sms")).unwrap_or_else(|| panic!("not normalized"));
+        assert_eq!(challenge.event_type, MFA_CHALLENGE_EVENT_TYPE);
+        assert_eq!(challenge.payload["method"], "sms");
+
+        assert_eq!(normalize(&log("sapi")), None);
+        assert_eq!(normalize(&json!({"hello": "world"})), None);
+        assert_eq!(events(json!([log("s"), log("f")])).len(), 2);
+        assert_eq!(events(log("s")).len(), 1);
+    }
+
+    #[test]
+    fn timestamps_become_unix_millis() {
+        assert_eq!(unix_millis("1970-01-01T00:00:00Z"), Some(0));
+        assert_eq!(unix_millis("2023-10-16T11:43:20.123Z"), Some(1697456600123));
+        assert_eq!(
+            unix_millis("2023-10-16T13:43:20.1+02:00"),
+            Some(1697456600100)
+        );
+        assert_eq!(
+            unix_millis("2024-02-29T00:00:00-00:30"),
+            Some(1709166600000)
+        );
+        assert_eq!(unix_millis("yesterday"), None);
+    }
+}
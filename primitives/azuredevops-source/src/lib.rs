@@ -0,0 +1,366 @@
+//! Azure DevOps Source - Azure DevOps Service Hook Receiver
+//!
+//! A Source that receives Azure DevOps service hook notifications ("Web
+//! Hooks" subscriptions) and emits each as `azuredevops.<eventType>` (e.g.
+//! `azuredevops.git.push`, `azuredevops.git.pullrequest.created`,
+//! `azuredevops.build.complete`), with the notification's JSON body as
+//! payload. With `--username` and `--password`, notifications must carry
+//! them as HTTP Basic credentials, as set on the subscription.
+//!
+//! Pushes, pull request changes and completed builds are also published as
+//! provider-agnostic `scm.push`, `scm.pull_request` and `scm.pipeline`
+//! events (see [`scm`]), the same schemas github-source and gitlab-source
+//! emit.
+//!
+//! `--emit-type-template` can also use `{event}`, the notification's
+//! `eventType`. A notification that cannot be published or spooled is
+//! answered with 503 so Azure DevOps retries it.
+//!
+//! Sources are SILENT - they only produce domain messages.
+//! All lifecycle events are published by the engine.
+//!
+//! # Usage
+//!
+//! ```bash
+//! # Receive notifications on :8080/
+//! azuredevops-source --username emergent --password $HOOK_PASSWORD
+//!
+//! azuredevops-source --port 9000 --path /azure --username emergent --password $HOOK_PASSWORD
+//! ```
+//!
+//! On SIGTERM the listener closes immediately and notifications already
+//! being handled get `--drain-timeout` milliseconds to finish publishing.
+
+pub mod scm;
+
+use axum::{
+    Router,
+    body::Bytes,
+    extract::State,
+    http::{HeaderMap, StatusCode},
+    response::IntoResponse,
+    routing::post,
+};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use clap::Parser;
+use emergent_client::EmergentSource;
+use primitive_common::capabilities::{self, CapabilityArgs, Role, VERSION, capabilities_message};
+use primitive_common::doctor::Report;
+use primitive_common::source::{Outlet, SourceArgs};
+use serde_json::Value;
+use std::{future::IntoFuture, net::SocketAddr, sync::Arc};
+use subtle::ConstantTimeEq;
+use tokio::{
+    signal::unix::{SignalKind, signal},
+    sync::Notify,
+};
+
+/// Azure DevOps service hook receiver that emits azuredevops.* and scm.* events.
+#[derive(Parser, Debug, Clone)]
+#[command(name = "azuredevops-source", version = VERSION)]
+#[command(about = "Receives Azure DevOps service hooks and emits events")]
+struct Args {
+    /// Port to listen on.
+    #[arg(short, long, env = "AZUREDEVOPS_SOURCE_PORT", default_value = "8080")]
+    port: u16,
+
+    /// Host to bind to.
+    #[arg(long, env = "AZUREDEVOPS_SOURCE_HOST", default_value = "0.0.0.0")]
+    host: String,
+
+    /// Path to accept notifications on.
+    #[arg(long, env = "AZUREDEVOPS_SOURCE_PATH", default_value = "/")]
+    path: String,
+
+    /// Basic authentication username set on the subscription.
+    #[arg(long, env = "AZUREDEVOPS_WEBHOOK_USERNAME", requires = "password")]
+    username: Option<String>,
+
+    /// Basic authentication password set on the subscription.
+    #[arg(
+        long,
+        env = "AZUREDEVOPS_WEBHOOK_PASSWORD",
+        hide_env_values = true,
+        requires = "username"
+    )]
+    password: Option<String>,
+
+    /// Verify external dependencies and exit without connecting to the engine.
+    #[arg(long)]
+    self_test: bool,
+
+    #[command(flatten)]
+    source: SourceArgs,
+
+    #[command(flatten)]
+    capabilities: CapabilityArgs,
+}
+
+/// Variables available to `--emit-type-template`.
+const TEMPLATE_VARIABLES: &[&str] = &["source", "event"];
+
+/// Shared application state.
+struct AppState {
+    outlet: Arc<Outlet>,
+    /// The expected `Authorization` header, with `--username`.
+    authorization: Option<String>,
+}
+
+impl AppState {
+    fn new(outlet: Arc<Outlet>, args: &Args) -> Self {
+        Self {
+            outlet,
+            authorization: args
+                .username
+                .as_deref()
+                .zip(args.password.as_deref())
+                .map(|(username, password)| basic_authorization(username, password)),
+        }
+    }
+}
+
+/// The `Authorization` header carrying `username` and `password`.
+fn basic_authorization(username: &str, password: &str) -> String {
+    format!(
+        "Basic {}",
+        STANDARD.encode(format!("{username}:{password}"))
+    )
+}
+
+/// Runs `--self-test` checks and exits.
+fn self_test(args: &Args, name: &str) -> ! {
+    let mut report = Report::new(name);
+    let addr = format!("{}:{}", args.host, args.port);
+    let bind = addr
+        .parse::<SocketAddr>()
+        .map_err(|e| format!("{addr}: {e}"))
+        .and_then(|a| {
+            std::net::TcpListener::bind(a)
+                .map(|_| addr.clone())
+                .map_err(|e| format!("{addr}: {e}"))
+        });
+    report.check("bind", bind);
+    report.check("path", check_path(&args.path));
+    args.source.self_test(&mut report, TEMPLATE_VARIABLES);
+    report.finish()
+}
+
+/// Check that the webhook path is absolute.
+fn check_path(path: &str) -> Result<String, String> {
+    if path.starts_with('/') {
+        Ok(path.to_string())
+    } else {
+        Err(format!("{path} must start with '/'"))
+    }
+}
+
+/// Handles incoming service hook notifications.
+async fn handle_notification(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> impl IntoResponse {
+    let header = |name: &str| headers.get(name).and_then(|h| h.to_str().ok());
+
+    if let Some(expected) = &state.authorization {
+        match header("authorization") {
+            Some(given) if bool::from(given.as_bytes().ct_eq(expected.as_bytes())) => {}
+            Some(_) => return (StatusCode::UNAUTHORIZED, "Invalid credentials"),
+            None => return (StatusCode::UNAUTHORIZED, "Missing credentials"),
+        }
+    }
+
+    let Ok(payload) = serde_json::from_slice::<Value>(&body) else {
+        return (StatusCode::BAD_REQUEST, "Body is not JSON");
+    };
+    let Some(event) = payload["eventType"].as_str().filter(|e| !e.is_empty()) else {
+        return (StatusCode::BAD_REQUEST, "Missing eventType");
+    };
+
+    let vars = [("event", event)];
+    // The outlet logs and reports what it cannot deliver
+    if state
+        .outlet
+        .publish(&format!("azuredevops.{event}"), &vars, payload.clone())
+        .await
+        .is_err()
+    {
+        return (StatusCode::SERVICE_UNAVAILABLE, "Failed to publish event");
+    }
+    for (message_type, event) in scm::events(event, &payload) {
+        let _ = state.outlet.publish(message_type, &vars, event).await;
+    }
+
+    (StatusCode::ACCEPTED, "")
+}
+
+/// Run the primitive with `args` as its command line (the first item is
+/// the program name, as with `std::env::args_os`).
+pub async fn run<I, T>(args: I) -> Result<(), Box<dyn std::error::Error>>
+where
+    I: IntoIterator<Item = T>,
+    T: Into<std::ffi::OsString> + Clone,
+{
+    let args = Args::parse_from(args);
+
+    // Get the source name from environment (set by engine) or use default
+    let name = std::env::var("EMERGENT_NAME").unwrap_or_else(|_| "azuredevops-source".to_string());
+
+    if args.self_test {
+        self_test(&args, &name);
+    }
+    if let Err(e) = check_path(&args.path).and_then(|_| args.source.validate(TEMPLATE_VARIABLES)) {
+        eprintln!("Error: {e}");
+        std::process::exit(1);
+    }
+
+    // Event types are only known per notification, so the namespace is listed
+    let produces = args.source.produces(&[
+        "azuredevops.*",
+        "scm.push",
+        "scm.pull_request",
+        "scm.pipeline",
+    ]);
+    let produces: Vec<&str> = produces.iter().map(String::as_str).collect();
+    let descriptor = args
+        .capabilities
+        .describe(&name, Role::Source, &[], &produces, &args);
+    if args.capabilities.capabilities {
+        capabilities::print(&descriptor);
+    }
+
+    // Connect to the Emergent engine (silently - lifecycle events come from engine)
+    let source = match EmergentSource::connect(&name).await {
+        Ok(s) => s,
+        Err(e) => {
+            eprintln!("Failed to connect to Emergent engine: {e}");
+            std::process::exit(1);
+        }
+    };
+    let outlet = Arc::new(Outlet::new(source, &name, &args.source)?);
+    if args.capabilities.announce {
+        outlet.announce(capabilities_message(&descriptor)).await;
+    }
+    tokio::spawn(Arc::clone(&outlet).drain_every(args.source.spool.retry_interval()));
+
+    let state = Arc::new(AppState::new(Arc::clone(&outlet), &args));
+    let app = Router::new()
+        .route(&args.path, post(handle_notification))
+        .with_state(state);
+
+    let addr: SocketAddr = format!("{}:{}", args.host, args.port).parse()?;
+
+    // Set up SIGTERM handler for graceful shutdown
+    let mut sigterm = signal(SignalKind::terminate())?;
+
+    let shutdown = Arc::new(Notify::new());
+    let server = axum::serve(
+        tokio::net::TcpListener::bind(&addr).await?,
+        app.into_make_service(),
+    )
+    .with_graceful_shutdown({
+        let shutdown = Arc::clone(&shutdown);
+        async move { shutdown.notified().await }
+    })
+    .into_future();
+    tokio::pin!(server);
+
+    tokio::select! {
+        result = &mut server => {
+            result?;
+        }
+        _ = sigterm.recv() => {
+            // Stop accepting connections and let in-flight notifications finish
+            shutdown.notify_one();
+            args.source.drain.drain("in-flight notifications", &mut server).await;
+            outlet.disconnect().await;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use emergent_testkit::{MockEngine, fixtures};
+    use serde_json::json;
+    use std::path::Path;
+
+    /// Connect to the engine on `socket` as the source would.
+    async fn connect(socket: &Path, args: &Args) -> Arc<AppState> {
+        let source = EmergentSource::connect_to("azuredevops-source", socket)
+            .await
+            .unwrap_or_else(|e| panic!("connect: {e}"));
+        let outlet = Outlet::new(source, "azuredevops-source", &args.source)
+            .unwrap_or_else(|e| panic!("open spool: {e}"));
+        Arc::new(AppState::new(Arc::new(outlet), args))
+    }
+
+    async fn notify_push(state: &Arc<AppState>) -> StatusCode {
+        let body = json!({
+            "eventType": "git.push",
+            "resource": {
+                "repository": {"name": "api", "project": {"name": "acme"}},
+                "refUpdates": [{"name": "refs/heads/main", "newObjectId": "abc123"}],
+            },
+        });
+        handle_notification(
+            State(Arc::clone(state)),
+            HeaderMap::new(),
+            Bytes::from(body.to_string()),
+        )
+        .await
+        .into_response()
+        .status()
+    }
+
+    #[tokio::test]
+    async fn notifications_survive_the_engine_being_down() {
+        let dir = fixtures::TempDir::new("azuredevops-source-spool");
+        let socket = dir.path().join("engine.sock");
+        let spool = dir.path().join("spool");
+        let args = Args::parse_from([
+            "azuredevops-source",
+            "--spool-dir",
+            spool.to_str().unwrap_or_default(),
+        ]);
+
+        let mut engine = MockEngine::serve(&socket);
+        let state = connect(&socket, &args).await;
+        engine.shut_down().await;
+        assert_eq!(notify_push(&state).await, StatusCode::ACCEPTED);
+        drop(state);
+
+        let mut engine = MockEngine::serve(&socket);
+        let state = connect(&socket, &args).await;
+        state.outlet.drain_spool().await;
+        let published = engine.expect_published("azuredevops.git.push").await;
+        assert_eq!(published.payload()["resource"]["repository"]["name"], "api");
+        let push = engine.expect_published("scm.push").await;
+        assert_eq!(push.payload()["repository"], "acme/api");
+        engine.shut_down().await;
+    }
+
+    #[tokio::test]
+    async fn undeliverable_notifications_are_refused_for_a_retry() {
+        let dir = fixtures::TempDir::new("azuredevops-source-down");
+        let socket = dir.path().join("engine.sock");
+        let args = Args::parse_from(["azuredevops-source"]);
+
+        let mut engine = MockEngine::serve(&socket);
+        let state = connect(&socket, &args).await;
+        engine.shut_down().await;
+        assert_eq!(notify_push(&state).await, StatusCode::SERVICE_UNAVAILABLE);
+    }
+
+    #[test]
+    fn basic_credentials_are_encoded() {
+        // Example from RFC 7617
+        assert_eq!(
+            basic_authorization("Aladdin", "open sesame"),
+            "Basic QWxhZGRpbjpvcGVuIHNlc2FtZQ=="
+        );
+    }
+}
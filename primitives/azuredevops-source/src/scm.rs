@@ -0,0 +1,197 @@
+//! Provider-agnostic `scm.*` events.
+//!
+//! | Azure DevOps event | `scm` event |
+//! |--------------------|-------------|
+//! | `git.push` | one `scm.push` per updated ref |
+//! | `git.pullrequest.created`, `git.pullrequest.updated` | `scm.pull_request` (`opened`; `updated`, or `merged`/`closed` once completed/abandoned) |
+//! | `build.complete` | `scm.pipeline` |
+//!
+//! `git.pullrequest.merged` ("merge attempted") is not normalized: it also
+//! fires for the trial merges Azure DevOps makes while a pull request is
+//! open, and the completion itself arrives as `git.pullrequest.updated`.
+//! Repositories are named `project/repo`.
+
+use event_schemas::{EventPayload, ScmPipeline, ScmPullRequest, ScmPush};
+use serde_json::Value;
+
+const PROVIDER: &str = "azuredevops";
+
+/// The `scm.*` events for a notification of `event_type`, as (message
+/// type, payload) pairs; none for events without an `scm` equivalent.
+pub fn events(event_type: &str, payload: &Value) -> Vec<(&'static str, Value)> {
+    let resource = &payload["resource"];
+    match event_type {
+        "git.push" => pushes(resource)
+            .into_iter()
+            .map(|e| (ScmPush::MESSAGE_TYPE, e.to_payload()))
+            .collect(),
+        "git.pullrequest.created" | "git.pullrequest.updated" => pull_request(event_type, resource)
+            .map(|e| (ScmPullRequest::MESSAGE_TYPE, e.to_payload()))
+            .into_iter()
+            .collect(),
+        "build.complete" => build(resource)
+            .map(|e| (ScmPipeline::MESSAGE_TYPE, e.to_payload()))
+            .into_iter()
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+fn string(value: &Value) -> Option<String> {
+    value.as_str().map(str::to_string)
+}
+
+/// A commit id, with the all-zero id Azure DevOps uses for a missing side
+/// as `None`.
+fn commit(value: &Value) -> Option<String> {
+    string(value).filter(|sha| sha.bytes().any(|b| b != b'0'))
+}
+
+/// `project/repo` for a git repository resource.
+fn repository(repository: &Value) -> Option<String> {
+    let name = repository["name"].as_str()?;
+    Some(match repository["project"]["name"].as_str() {
+        Some(project) => format!("{project}/{name}"),
+        None => name.to_string(),
+    })
+}
+
+/// Who acted: their unique (sign-in) name, or display name.
+fn identity(identity: &Value) -> Option<String> {
+    string(&identity["uniqueName"]).or_else(|| string(&identity["displayName"]))
+}
+
+fn pushes(resource: &Value) -> Vec<ScmPush> {
+    let Some(name) = repository(&resource["repository"]) else {
+        return Vec::new();
+    };
+    let Some(updates) = resource["refUpdates"].as_array() else {
+        return Vec::new();
+    };
+    let commits = resource["commits"].as_array().map_or(0, |c| c.len() as u64);
+    updates
+        .iter()
+        .filter_map(|update| {
+            let reference = update["name"].as_str()?;
+            Some(ScmPush {
+                provider: PROVIDER.to_string(),
+                repository: name.clone(),
+                r#ref: reference.to_string(),
+                branch: reference.strip_prefix("refs/heads/").map(str::to_string),
+                tag: reference.strip_prefix("refs/tags/").map(str::to_string),
+                before: commit(&update["oldObjectId"]),
+                after: commit(&update["newObjectId"]),
+                commits,
+                sender: identity(&resource["pushedBy"]),
+                url: string(&resource["repository"]["remoteUrl"]),
+            })
+        })
+        .collect()
+}
+
+fn pull_request(event_type: &str, resource: &Value) -> Option<ScmPullRequest> {
+    let action = match (event_type, resource["status"].as_str()) {
+        ("git.pullrequest.created", _) => "opened",
+        (_, Some("completed")) => "merged",
+        (_, Some("abandoned")) => "closed",
+        _ => "updated",
+    };
+    let branch = |field: &str| {
+        resource[field]
+            .as_str()
+            .map(|r| r.strip_prefix("refs/heads/").unwrap_or(r).to_string())
+    };
+    let number = resource["pullRequestId"].as_u64()?;
+    Some(ScmPullRequest {
+        provider: PROVIDER.to_string(),
+        repository: repository(&resource["repository"])?,
+        number,
+        action: action.to_string(),
+        title: string(&resource["title"]).unwrap_or_default(),
+        source_branch: branch("sourceRefName")?,
+        target_branch: branch("targetRefName")?,
+        head_sha: string(&resource["lastMergeSourceCommit"]["commitId"]),
+        sender: identity(&resource["createdBy"]),
+        url: resource["repository"]["remoteUrl"]
+            .as_str()
+            .map(|repo| format!("{repo}/pullrequest/{number}")),
+    })
+}
+
+fn build(resource: &Value) -> Option<ScmPipeline> {
+    let status = match (resource["status"].as_str(), resource["result"].as_str()) {
+        (_, Some("succeeded")) => "success",
+        (_, Some("canceled")) => "canceled",
+        (_, Some(_)) => "failed",
+        (Some("inProgress" | "cancelling"), None) => "running",
+        _ => "queued",
+    };
+    let definition = &resource["definition"];
+    let repository = repository(&resource["repository"]).or_else(|| {
+        let project = resource["project"]["name"]
+            .as_str()
+            .or_else(|| definition["project"]["name"].as_str())?;
+        Some(format!("{project}/{}", definition["name"].as_str()?))
+    })?;
+    Some(ScmPipeline {
+        provider: PROVIDER.to_string(),
+        repository,
+        id: resource["id"].as_u64()?.to_string(),
+        name: string(&definition["name"]),
+        branch: resource["sourceBranch"]
+            .as_str()
+            .map(|r| r.strip_prefix("refs/heads/").unwrap_or(r).to_string()),
+        sha: string(&resource["sourceVersion"]),
+        status: status.to_string(),
+        url: string(&resource["_links"]["web"]["href"]),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn notifications_normalize_to_scm_events() {
+        let repo = json!({"name": "api", "project": {"name": "Fabrikam"},
+                          "remoteUrl": "https://dev.azure.com/acme/Fabrikam/_git/api"});
+        let push = json!({"resource": {
+            "refUpdates": [{"name": "refs/heads/main",
+                            "oldObjectId": "aad331d8d3b131fa9ae03cf5e53965b51942618a",
+                            "newObjectId": "33b55f7cb7e7e245323987634f960cf4a6e6bc74"}],
+            "commits": [{}],
+            "repository": repo,
+            "pushedBy": {"uniqueName": "jamal@example.com"},
+        }});
+        let events = events("git.push", &push);
+        assert_eq!(events[0].0, "scm.push");
+        assert_eq!(events[0].1["repository"], "Fabrikam/api");
+        assert_eq!(events[0].1["branch"], "main");
+        assert_eq!(events[0].1["sender"], "jamal@example.com");
+
+        let completed = json!({"resource": {
+            "pullRequestId": 1, "status": "completed", "title": "Fix",
+            "sourceRefName": "refs/heads/fix", "targetRefName": "refs/heads/main",
+            "repository": repo,
+        }});
+        let events = super::events("git.pullrequest.updated", &completed);
+        assert_eq!(events[0].1["action"], "merged");
+        assert_eq!(events[0].1["source_branch"], "fix");
+        assert_eq!(
+            events[0].1["url"],
+            "https://dev.azure.com/acme/Fabrikam/_git/api/pullrequest/1"
+        );
+        assert!(super::events("git.pullrequest.merged", &completed).is_empty());
+
+        let build = json!({"resource": {
+            "id": 2, "status": "completed", "result": "partiallySucceeded",
+            "definition": {"name": "api-ci"}, "project": {"name": "Fabrikam"},
+            "sourceBranch": "refs/heads/main",
+        }});
+        let events = super::events("build.complete", &build);
+        assert_eq!(events[0].1["status"], "failed");
+        assert_eq!(events[0].1["repository"], "Fabrikam/api-ci");
+        assert_eq!(events[0].1["branch"], "main");
+    }
+}
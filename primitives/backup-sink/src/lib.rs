@@ -0,0 +1,477 @@
+//! Backup Sink - Run restic or borg Backups
+//!
+//! Each event backs up the configured `--path`s with restic or borg (see
+//! [`tool`]); with `--interval`, backups also run on a schedule. Every run
+//! is reported as `backup.completed`, with the snapshot id and statistics,
+//! or `backup.failed`, with the tool's error.
+//!
+//! A payload of `{"op": "prune"}` applies the retention policy (`--keep-*`)
+//! instead, reported as `backup.pruned` with the snapshots kept and
+//! removed; `--prune` does so after every successful backup.
+//!
+//! Runs never overlap: a trigger arriving during a run waits for it.
+//!
+//! # Examples
+//!
+//! ```bash
+//! # Back up on demand and nightly, keeping a week of dailies
+//! RESTIC_PASSWORD_FILE=/etc/restic.pass backup-sink -s backup.requested \
+//!   --repository s3:s3.amazonaws.com/acme-backups --path /etc --path /srv \
+//!   --interval 86400 --prune --keep-daily 7 --keep-weekly 4
+//!
+//! # borg
+//! BORG_PASSPHRASE=... backup-sink -s deploy.completed --tool borg \
+//!   --repository ssh://backup@nas/./web1 --path /var/lib/app
+//! ```
+
+pub mod tool;
+
+use clap::Parser;
+use emergent_client::EmergentMessage;
+use primitive_common::capabilities::VERSION;
+use primitive_common::doctor::{Report, check_executable};
+use primitive_common::errors::{ErrorCategory, HandlerError};
+use primitive_common::{Publisher, SinkArgs, SinkConfig, SinkContext, SinkHandler, run_sink};
+use serde_json::{Value, json};
+use std::path::Path;
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, Instant};
+use tokio::process::Command;
+use tool::{Outcome, Plan, Retention, Tool};
+
+pub const COMPLETED_EVENT_TYPE: &str = "backup.completed";
+pub const FAILED_EVENT_TYPE: &str = "backup.failed";
+pub const PRUNED_EVENT_TYPE: &str = "backup.pruned";
+
+/// Backup Sink — run restic or borg backups.
+#[derive(Parser, Debug)]
+#[command(name = "backup_sink", version = VERSION)]
+#[command(about = "Run restic or borg backups when events arrive or on a schedule")]
+struct Args {
+    /// Message types to subscribe to.
+    #[arg(short, long = "subscribe", required = true)]
+    subscribe: Vec<String>,
+
+    /// Backup program.
+    #[arg(long, env = "BACKUP_SINK_TOOL", value_enum, default_value_t = Tool::Restic)]
+    tool: Tool,
+
+    /// Path or name of the program (default: the tool's name).
+    #[arg(long, env = "BACKUP_SINK_BINARY")]
+    binary: Option<String>,
+
+    /// Repository to back up to (default: the tool's RESTIC_REPOSITORY or BORG_REPO).
+    #[arg(long, env = "BACKUP_SINK_REPOSITORY")]
+    repository: Option<String>,
+
+    /// Paths to back up (comma-separated or repeated).
+    #[arg(
+        long = "path",
+        env = "BACKUP_SINK_PATHS",
+        value_delimiter = ',',
+        required = true
+    )]
+    paths: Vec<String>,
+
+    /// Exclude patterns (comma-separated or repeated).
+    #[arg(long = "exclude", env = "BACKUP_SINK_EXCLUDES", value_delimiter = ',')]
+    excludes: Vec<String>,
+
+    /// Tags given to restic snapshots, and limiting pruning to them (comma-separated or repeated).
+    #[arg(long = "tag", env = "BACKUP_SINK_TAGS", value_delimiter = ',')]
+    tags: Vec<String>,
+
+    /// borg archive name, with borg's placeholders.
+    #[arg(long, env = "BACKUP_SINK_ARCHIVE", default_value = "{hostname}-{now}")]
+    archive: String,
+
+    /// Seconds between scheduled backups (0 = only on events).
+    #[arg(long, env = "BACKUP_SINK_INTERVAL", default_value = "0")]
+    interval: u64,
+
+    /// Apply the retention policy after every successful backup.
+    #[arg(long, env = "BACKUP_SINK_PRUNE")]
+    prune: bool,
+
+    #[command(flatten)]
+    retention: Retention,
+
+    /// Seconds a run may take before it is killed.
+    #[arg(short, long, env = "BACKUP_SINK_TIMEOUT", default_value = "21600")]
+    timeout: u64,
+
+    #[command(flatten)]
+    sink: SinkArgs,
+}
+
+/// Runs backups and reports on them.
+struct Backups {
+    plan: Plan,
+    retention: Retention,
+    prune_after_backup: bool,
+    timeout: Duration,
+    /// Held for the length of a run, so runs never overlap.
+    running: tokio::sync::Mutex<()>,
+    publisher: OnceLock<Publisher>,
+}
+
+impl Backups {
+    async fn execute(&self, argv: &[String]) -> Result<Outcome, String> {
+        let command_line = argv.join(" ");
+        let child = Command::new(&argv[0])
+            .args(&argv[1..])
+            .stdin(std::process::Stdio::null())
+            .stdout(std::process::Stdio::piped())
+            .stderr(std::process::Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(|e| format!("{command_line}: {e}"))?;
+        let output = tokio::time::timeout(self.timeout, child.wait_with_output())
+            .await
+            .map_err(|_| {
+                format!(
+                    "{command_line}: timed out after {}s",
+                    self.timeout.as_secs()
+                )
+            })?
+            .map_err(|e| format!("{command_line}: {e}"))?;
+        Ok(Outcome {
+            code: output.status.code(),
+            stdout: String::from_utf8_lossy(&output.stdout).into_owned(),
+            stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+        })
+    }
+
+    fn publish(&self, event_type: &str, mut payload: Value, trigger: &Value) {
+        let Some(publisher) = self.publisher.get() else {
+            return;
+        };
+        if let (Some(payload), Some(trigger)) = (payload.as_object_mut(), trigger.as_object()) {
+            payload.extend(trigger.clone());
+        }
+        let message = EmergentMessage::new(event_type).with_payload(payload);
+        if let Err(e) = publisher.publish(message) {
+            eprintln!("Failed to publish {event_type}: {e}");
+        }
+    }
+
+    /// Report a failed `operation` and hand its error back.
+    fn failed(&self, operation: &str, error: String, code: Option<i32>, trigger: &Value) -> String {
+        eprintln!("{operation} failed: {error}");
+        self.publish(
+            FAILED_EVENT_TYPE,
+            json!({
+                "operation": operation,
+                "tool": self.plan.tool.as_str(),
+                "repository": self.plan.repository,
+                "paths": self.plan.paths,
+                "error": error,
+                "exit_code": code,
+            }),
+            trigger,
+        );
+        error
+    }
+
+    /// Back up the paths, then prune if configured.
+    async fn backup(&self, trigger: &Value) -> Result<(), String> {
+        let _running = self.running.lock().await;
+        let started = Instant::now();
+        let outcome = match self.execute(&self.plan.backup()).await {
+            Ok(outcome) => outcome,
+            Err(e) => return Err(self.failed("backup", e, None, trigger)),
+        };
+        let Some(partial) = self.plan.finished(&outcome) else {
+            let error = self.plan.error(&outcome);
+            return Err(self.failed("backup", error, outcome.code, trigger));
+        };
+        let stats = match self.plan.backup_stats(&outcome) {
+            Ok(stats) => stats,
+            Err(e) => return Err(self.failed("backup", e, outcome.code, trigger)),
+        };
+        eprintln!(
+            "Backed up {} as {}",
+            self.plan.paths.join(", "),
+            stats["snapshot_id"]
+        );
+        let mut payload = json!({
+            "tool": self.plan.tool.as_str(),
+            "repository": self.plan.repository,
+            "paths": self.plan.paths,
+            "partial": partial,
+            "elapsed_ms": started.elapsed().as_millis() as u64,
+        });
+        if partial {
+            payload["warnings"] = json!(self.plan.error(&outcome));
+        }
+        if let (Some(payload), Value::Object(stats)) = (payload.as_object_mut(), stats) {
+            payload.extend(stats);
+        }
+        self.publish(COMPLETED_EVENT_TYPE, payload, trigger);
+
+        if self.prune_after_backup {
+            self.prune_locked(trigger).await?;
+        }
+        Ok(())
+    }
+
+    /// Apply the retention policy.
+    async fn prune(&self, trigger: &Value) -> Result<(), String> {
+        let _running = self.running.lock().await;
+        self.prune_locked(trigger).await
+    }
+
+    async fn prune_locked(&self, trigger: &Value) -> Result<(), String> {
+        let mut stats = json!({});
+        for argv in self.plan.prune(&self.retention) {
+            let outcome = match self.execute(&argv).await {
+                Ok(outcome) => outcome,
+                Err(e) => return Err(self.failed("prune", e, None, trigger)),
+            };
+            if self.plan.finished(&outcome).is_none() {
+                let error = self.plan.error(&outcome);
+                return Err(self.failed("prune", error, outcome.code, trigger));
+            }
+            // The first command reports what it removed
+            if stats.as_object().is_some_and(serde_json::Map::is_empty) {
+                stats = self.plan.prune_stats(&outcome);
+            }
+        }
+        eprintln!("Pruned: {stats}");
+        let mut payload = json!({
+            "tool": self.plan.tool.as_str(),
+            "repository": self.plan.repository,
+        });
+        if let (Some(payload), Value::Object(stats)) = (payload.as_object_mut(), stats) {
+            payload.extend(stats);
+        }
+        self.publish(PRUNED_EVENT_TYPE, payload, trigger);
+        Ok(())
+    }
+}
+
+/// Triggers backups from events.
+struct BackupSink {
+    backups: Arc<Backups>,
+}
+
+impl SinkHandler for BackupSink {
+    async fn handle(
+        &self,
+        msg: &EmergentMessage,
+        ctx: &SinkContext<'_>,
+    ) -> Result<(), HandlerError> {
+        let op = ctx.payload()["op"].as_str().unwrap_or("backup");
+        let plan = &self.backups.plan;
+        let commands = match op {
+            "backup" => vec![plan.backup()],
+            "prune" if self.backups.retention.is_set() => plan.prune(&self.backups.retention),
+            "prune" => {
+                return Err(HandlerError::new(
+                    ErrorCategory::Rejected,
+                    "no retention policy configured (--keep-*)",
+                ));
+            }
+            other => {
+                return Err(HandlerError::new(
+                    ErrorCategory::Parse,
+                    format!("unknown op '{other}'"),
+                ));
+            }
+        };
+        if ctx.is_dry_run() {
+            ctx.would_have(op, json!({ "commands": commands })).await;
+            return Ok(());
+        }
+
+        let trigger = json!({
+            "trigger": msg.message_type.as_str(),
+            "message_id": ctx.message_id(),
+        });
+        let result = match op {
+            "prune" => self.backups.prune(&trigger).await,
+            _ => self.backups.backup(&trigger).await,
+        };
+        result.map_err(|e| HandlerError::new(ErrorCategory::Request, e))
+    }
+
+    fn self_test(&self, report: &mut Report) {
+        let plan = &self.backups.plan;
+        report.check("binary", check_executable(&plan.binary));
+        for path in &plan.paths {
+            let exists = if Path::new(path).exists() {
+                Ok(path.clone())
+            } else {
+                Err(format!("{path} does not exist"))
+            };
+            report.check("path", exists);
+        }
+    }
+
+    fn publishes(&self) -> &'static [&'static str] {
+        &[COMPLETED_EVENT_TYPE, FAILED_EVENT_TYPE, PRUNED_EVENT_TYPE]
+    }
+
+    fn attach(&self, publisher: Publisher) {
+        let _ = self.backups.publisher.set(publisher);
+    }
+}
+
+/// Back up every `interval`, first one interval after startup.
+async fn schedule(backups: Arc<Backups>, interval: Duration) {
+    let mut ticks = tokio::time::interval_at(tokio::time::Instant::now() + interval, interval);
+    ticks.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    loop {
+        ticks.tick().await;
+        // The failure is already published; the next tick tries again
+        let _ = backups.backup(&json!({ "trigger": "schedule" })).await;
+    }
+}
+
+/// Run the primitive with `args` as its command line (the first item is
+/// the program name, as with `std::env::args_os`).
+pub async fn run<I, T>(args: I) -> Result<(), Box<dyn std::error::Error>>
+where
+    I: IntoIterator<Item = T>,
+    T: Into<std::ffi::OsString> + Clone,
+{
+    let args = Args::parse_from(args);
+
+    if args.prune && !args.retention.is_set() {
+        eprintln!("Error: --prune needs at least one --keep-* option");
+        std::process::exit(1);
+    }
+
+    let config = SinkConfig {
+        name: "backup_sink",
+        subscribe: &args.subscribe,
+        would_have_as: "backup.would_have",
+        dead_letter_as: "backup.dead_letter",
+        settings: &args,
+    };
+    let binary = args
+        .binary
+        .clone()
+        .unwrap_or_else(|| args.tool.as_str().to_string());
+    let backups = Arc::new(Backups {
+        plan: Plan {
+            tool: args.tool,
+            binary,
+            repository: args.repository.clone(),
+            paths: args.paths.clone(),
+            excludes: args.excludes.clone(),
+            tags: args.tags.clone(),
+            archive: args.archive.clone(),
+        },
+        retention: args.retention.clone(),
+        prune_after_backup: args.prune,
+        timeout: Duration::from_secs(args.timeout),
+        running: tokio::sync::Mutex::new(()),
+        publisher: OnceLock::new(),
+    });
+
+    if args.interval > 0 && !args.sink.self_test {
+        if args.sink.dry_run {
+            eprintln!("Dry run: scheduled backups are skipped");
+        } else {
+            tokio::spawn(schedule(
+                Arc::clone(&backups),
+                Duration::from_secs(args.interval),
+            ));
+        }
+    }
+
+    run_sink(config, &args.sink, BackupSink { backups }).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use emergent_testkit::fixtures::TempDir;
+    use emergent_testkit::{SinkFixture, fixtures, spawn_sink};
+    use std::os::unix::fs::PermissionsExt;
+
+    /// A stand-in for restic: records its arguments and prints a summary,
+    /// or fails for `forget`.
+    fn fake_restic(dir: &Path) -> String {
+        let script = dir.join("restic");
+        let body = format!(
+            "#!/bin/sh\n\
+             echo \"$@\" >> {log}\n\
+             case \"$*\" in\n\
+             *forget*) echo '{{\"message_type\":\"exit_error\",\"code\":1,\"message\":\"Fatal: repository is already locked\"}}' >&2; exit 1 ;;\n\
+             esac\n\
+             echo '{{\"message_type\":\"summary\",\"files_new\":2,\"data_added\":512,\"total_files_processed\":9,\"total_bytes_processed\":4096,\"total_duration\":0.2,\"snapshot_id\":\"5e1f\"}}'\n",
+            log = dir.join("calls").display()
+        );
+        std::fs::write(&script, body).unwrap_or_else(|e| panic!("{e}"));
+        std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755))
+            .unwrap_or_else(|e| panic!("{e}"));
+        script.display().to_string()
+    }
+
+    #[tokio::test]
+    async fn runs_are_reported_as_events() {
+        let dir = TempDir::new("backup-sink");
+        let backups = Arc::new(Backups {
+            plan: Plan {
+                tool: Tool::Restic,
+                binary: fake_restic(dir.path()),
+                repository: Some("/srv/backup".to_string()),
+                paths: vec!["/etc".to_string()],
+                excludes: Vec::new(),
+                tags: Vec::new(),
+                archive: "{hostname}-{now}".to_string(),
+            },
+            retention: Retention {
+                keep_last: Some(3),
+                ..Default::default()
+            },
+            prune_after_backup: false,
+            timeout: Duration::from_secs(10),
+            running: tokio::sync::Mutex::new(()),
+            publisher: OnceLock::new(),
+        });
+        let (mut engine, run) = spawn_sink(
+            SinkFixture::new("backup_sink", "backup"),
+            SinkArgs::default(),
+            BackupSink { backups },
+        );
+
+        engine
+            .inject_message(fixtures::message("backup.requested", json!({})))
+            .await;
+        let completed = engine.expect_published(COMPLETED_EVENT_TYPE).await;
+        assert_eq!(completed.payload()["snapshot_id"], "5e1f");
+        assert_eq!(completed.payload()["files"], 9);
+        assert_eq!(completed.payload()["added_bytes"], 512);
+        assert_eq!(completed.payload()["partial"], false);
+        assert_eq!(completed.payload()["trigger"], "backup.requested");
+
+        engine
+            .inject_message(fixtures::message(
+                "backup.requested",
+                json!({"op": "prune"}),
+            ))
+            .await;
+        let failed = engine.expect_published(FAILED_EVENT_TYPE).await;
+        assert_eq!(failed.payload()["operation"], "prune");
+        assert_eq!(
+            failed.payload()["error"],
+            "Fatal: repository is already locked"
+        );
+        assert_eq!(failed.payload()["exit_code"], 1);
+
+        let calls = std::fs::read_to_string(dir.path().join("calls")).unwrap_or_default();
+        assert_eq!(
+            calls.lines().collect::<Vec<_>>(),
+            [
+                "-r /srv/backup backup --json /etc",
+                "-r /srv/backup forget --json --prune --keep-last 3"
+            ]
+        );
+
+        engine.close();
+        assert_eq!(run.await.ok(), Some(Ok(())));
+    }
+}
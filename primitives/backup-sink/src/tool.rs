@@ -0,0 +1,397 @@
+//! restic and borg command lines, and reading their output.
+//!
+//! Both tools are asked for JSON where they offer it (`restic backup
+//! --json`, `restic forget --json`, `borg create --json`); `borg prune`
+//! has none, so its `--list` lines are counted. Passwords and other
+//! settings reach the tools through their own environment variables
+//! (`RESTIC_PASSWORD`, `BORG_PASSPHRASE`, ...), which they inherit.
+
+use clap::{Args, ValueEnum};
+use serde_json::{Value, json};
+
+/// The backup program.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Tool {
+    Restic,
+    Borg,
+}
+
+impl Tool {
+    /// The program's name, also its default binary.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Restic => "restic",
+            Self::Borg => "borg",
+        }
+    }
+}
+
+/// Snapshots kept when pruning; the flags mean the same to both tools.
+#[derive(Args, Debug, Clone, Default)]
+pub struct Retention {
+    /// Keep the last N snapshots.
+    #[arg(long, env = "BACKUP_SINK_KEEP_LAST")]
+    pub keep_last: Option<u32>,
+
+    /// Keep the last snapshot of each of the last N hours.
+    #[arg(long, env = "BACKUP_SINK_KEEP_HOURLY")]
+    pub keep_hourly: Option<u32>,
+
+    /// Keep the last snapshot of each of the last N days.
+    #[arg(long, env = "BACKUP_SINK_KEEP_DAILY")]
+    pub keep_daily: Option<u32>,
+
+    /// Keep the last snapshot of each of the last N weeks.
+    #[arg(long, env = "BACKUP_SINK_KEEP_WEEKLY")]
+    pub keep_weekly: Option<u32>,
+
+    /// Keep the last snapshot of each of the last N months.
+    #[arg(long, env = "BACKUP_SINK_KEEP_MONTHLY")]
+    pub keep_monthly: Option<u32>,
+
+    /// Keep the last snapshot of each of the last N years.
+    #[arg(long, env = "BACKUP_SINK_KEEP_YEARLY")]
+    pub keep_yearly: Option<u32>,
+}
+
+impl Retention {
+    pub fn is_set(&self) -> bool {
+        !self.flags().is_empty()
+    }
+
+    fn flags(&self) -> Vec<String> {
+        [
+            ("--keep-last", self.keep_last),
+            ("--keep-hourly", self.keep_hourly),
+            ("--keep-daily", self.keep_daily),
+            ("--keep-weekly", self.keep_weekly),
+            ("--keep-monthly", self.keep_monthly),
+            ("--keep-yearly", self.keep_yearly),
+        ]
+        .into_iter()
+        .filter_map(|(flag, n)| Some([flag.to_string(), n?.to_string()]))
+        .flatten()
+        .collect()
+    }
+}
+
+/// What to back up and where.
+#[derive(Debug, Clone)]
+pub struct Plan {
+    pub tool: Tool,
+    pub binary: String,
+    pub repository: Option<String>,
+    pub paths: Vec<String>,
+    pub excludes: Vec<String>,
+    pub tags: Vec<String>,
+    /// borg archive name, with borg's placeholders.
+    pub archive: String,
+}
+
+/// How a run ended.
+pub struct Outcome {
+    /// The exit code, `None` if killed by a signal.
+    pub code: Option<i32>,
+    pub stdout: String,
+    pub stderr: String,
+}
+
+impl Plan {
+    fn restic(&self, command: &str) -> Vec<String> {
+        let mut argv = vec![self.binary.clone()];
+        if let Some(repository) = &self.repository {
+            argv.extend(["-r".to_string(), repository.clone()]);
+        }
+        argv.extend([command.to_string(), "--json".to_string()]);
+        argv
+    }
+
+    /// The borg repository, or `""` to leave it to `BORG_REPO`.
+    fn borg_repository(&self) -> &str {
+        self.repository.as_deref().unwrap_or_default()
+    }
+
+    /// The command backing up the paths.
+    pub fn backup(&self) -> Vec<String> {
+        match self.tool {
+            Tool::Restic => {
+                let mut argv = self.restic("backup");
+                for tag in &self.tags {
+                    argv.extend(["--tag".to_string(), tag.clone()]);
+                }
+                for exclude in &self.excludes {
+                    argv.extend(["--exclude".to_string(), exclude.clone()]);
+                }
+                argv.extend(self.paths.iter().cloned());
+                argv
+            }
+            Tool::Borg => {
+                let mut argv = vec![
+                    self.binary.clone(),
+                    "create".to_string(),
+                    "--json".to_string(),
+                ];
+                for exclude in &self.excludes {
+                    argv.extend(["--exclude".to_string(), exclude.clone()]);
+                }
+                argv.push(format!("{}::{}", self.borg_repository(), self.archive));
+                argv.extend(self.paths.iter().cloned());
+                argv
+            }
+        }
+    }
+
+    /// The commands applying `retention`, in order.
+    pub fn prune(&self, retention: &Retention) -> Vec<Vec<String>> {
+        match self.tool {
+            Tool::Restic => {
+                let mut argv = self.restic("forget");
+                argv.push("--prune".to_string());
+                for tag in &self.tags {
+                    argv.extend(["--tag".to_string(), tag.clone()]);
+                }
+                argv.extend(retention.flags());
+                vec![argv]
+            }
+            Tool::Borg => {
+                let mut prune = vec![
+                    self.binary.clone(),
+                    "prune".to_string(),
+                    "--list".to_string(),
+                ];
+                prune.extend(retention.flags());
+                prune.push(self.borg_repository().to_string());
+                // Since borg 1.2, pruning only marks space as free
+                let compact = vec![
+                    self.binary.clone(),
+                    "compact".to_string(),
+                    self.borg_repository().to_string(),
+                ];
+                vec![prune, compact]
+            }
+        }
+    }
+
+    /// `Some(partial)` if a run produced a snapshot, `partial` when it is
+    /// missing files that could not be read (restic exit 3, borg exit 1).
+    pub fn finished(&self, outcome: &Outcome) -> Option<bool> {
+        match (self.tool, outcome.code) {
+            (_, Some(0)) => Some(false),
+            (Tool::Restic, Some(3)) | (Tool::Borg, Some(1)) => Some(true),
+            _ => None,
+        }
+    }
+
+    /// Statistics of a finished backup.
+    pub fn backup_stats(&self, outcome: &Outcome) -> Result<Value, String> {
+        match self.tool {
+            Tool::Restic => {
+                let summary = json_lines(&outcome.stdout)
+                    .find(|line| line["message_type"] == "summary")
+                    .ok_or("restic printed no summary")?;
+                Ok(json!({
+                    "snapshot_id": summary["snapshot_id"],
+                    "files": summary["total_files_processed"],
+                    "bytes": summary["total_bytes_processed"],
+                    "added_bytes": summary["data_added"],
+                    "duration_seconds": summary["total_duration"],
+                    "stats": summary,
+                }))
+            }
+            Tool::Borg => {
+                let output: Value = serde_json::from_str(outcome.stdout.trim())
+                    .map_err(|e| format!("borg output: {e}"))?;
+                let archive = &output["archive"];
+                let stats = &archive["stats"];
+                Ok(json!({
+                    "snapshot_id": archive["name"],
+                    "files": stats["nfiles"],
+                    "bytes": stats["original_size"],
+                    "added_bytes": stats["deduplicated_size"],
+                    "duration_seconds": archive["duration"],
+                    "stats": stats,
+                }))
+            }
+        }
+    }
+
+    /// Snapshots kept and removed by a prune run.
+    pub fn prune_stats(&self, outcome: &Outcome) -> Value {
+        match self.tool {
+            Tool::Restic => {
+                // Prune progress may follow the forget report on stdout
+                let groups: Vec<Value> = outcome
+                    .stdout
+                    .lines()
+                    .filter(|line| line.starts_with('['))
+                    .find_map(|line| serde_json::from_str(line).ok())
+                    .unwrap_or_default();
+                let count = |field: &str| -> usize {
+                    groups
+                        .iter()
+                        .filter_map(|group| group[field].as_array())
+                        .map(Vec::len)
+                        .sum()
+                };
+                json!({ "kept": count("keep"), "removed": count("remove") })
+            }
+            Tool::Borg => {
+                let count = |prefix: &str| {
+                    outcome
+                        .stderr
+                        .lines()
+                        .filter(|line| line.starts_with(prefix))
+                        .count()
+                };
+                json!({ "kept": count("Keeping archive"), "removed": count("Pruning archive") })
+            }
+        }
+    }
+
+    /// Why a run failed, as the tool put it.
+    pub fn error(&self, outcome: &Outcome) -> String {
+        let reported: Vec<String> = match self.tool {
+            Tool::Restic => json_lines(&outcome.stdout)
+                .chain(json_lines(&outcome.stderr))
+                .filter(|line| {
+                    line["message_type"] == "error" || line["message_type"] == "exit_error"
+                })
+                .filter_map(|line| {
+                    line["error"]["message"]
+                        .as_str()
+                        .or_else(|| line["message"].as_str())
+                        .map(str::to_string)
+                })
+                .collect(),
+            Tool::Borg => Vec::new(),
+        };
+        if !reported.is_empty() {
+            return reported.join("; ");
+        }
+        let stderr = outcome.stderr.trim();
+        if stderr.is_empty() {
+            match outcome.code {
+                Some(code) => format!("exited with {code}"),
+                None => "killed by a signal".to_string(),
+            }
+        } else {
+            // The last lines say why; earlier ones are progress
+            let lines: Vec<&str> = stderr.lines().collect();
+            lines[lines.len().saturating_sub(5)..].join("\n")
+        }
+    }
+}
+
+fn json_lines(output: &str) -> impl Iterator<Item = Value> + '_ {
+    output
+        .lines()
+        .filter_map(|line| serde_json::from_str::<Value>(line).ok())
+        .filter(Value::is_object)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn plan(tool: Tool) -> Plan {
+        Plan {
+            tool,
+            binary: tool.as_str().to_string(),
+            repository: Some("/srv/backup".to_string()),
+            paths: vec!["/etc".to_string(), "/home".to_string()],
+            excludes: vec!["*.cache".to_string()],
+            tags: vec!["nightly".to_string()],
+            archive: "{hostname}-{now}".to_string(),
+        }
+    }
+
+    fn outcome(code: i32, stdout: &str, stderr: &str) -> Outcome {
+        Outcome {
+            code: Some(code),
+            stdout: stdout.to_string(),
+            stderr: stderr.to_string(),
+        }
+    }
+
+    #[test]
+    fn restic_runs_are_read_from_json_lines() {
+        let restic = plan(Tool::Restic);
+        assert_eq!(
+            restic.backup().join(" "),
+            "restic -r /srv/backup backup --json --tag nightly --exclude *.cache /etc /home"
+        );
+        let retention = Retention {
+            keep_daily: Some(7),
+            keep_weekly: Some(4),
+            ..Default::default()
+        };
+        assert_eq!(
+            restic.prune(&retention)[0].join(" "),
+            "restic -r /srv/backup forget --json --prune --tag nightly --keep-daily 7 --keep-weekly 4"
+        );
+
+        let stdout = r#"{"message_type":"status","percent_done":0.5}
+{"message_type":"summary","files_new":3,"files_changed":1,"files_unmodified":40,"data_added":2048,"total_files_processed":44,"total_bytes_processed":90000,"total_duration":1.5,"snapshot_id":"4f3c2a1b"}"#;
+        let run = outcome(3, stdout, "");
+        assert_eq!(restic.finished(&run), Some(true));
+        let stats = restic.backup_stats(&run).unwrap_or_else(|e| panic!("{e}"));
+        assert_eq!(stats["snapshot_id"], "4f3c2a1b");
+        assert_eq!(stats["files"], 44);
+        assert_eq!(stats["added_bytes"], 2048);
+        assert_eq!(stats["stats"]["files_new"], 3);
+
+        let forget = r#"[{"host":"web1","paths":["/etc"],"keep":[{"id":"a"},{"id":"b"}],"remove":[{"id":"c"}]},{"host":"web2","keep":[{"id":"d"}],"remove":null}]
+loading indexes..."#;
+        assert_eq!(
+            restic.prune_stats(&outcome(0, forget, "")),
+            json!({"kept": 3, "removed": 1})
+        );
+
+        let failed = outcome(
+            1,
+            "",
+            r#"{"message_type":"exit_error","code":1,"message":"Fatal: unable to open config file"}"#,
+        );
+        assert_eq!(restic.finished(&failed), None);
+        assert_eq!(restic.error(&failed), "Fatal: unable to open config file");
+    }
+
+    #[test]
+    fn borg_runs_are_read_from_create_json_and_prune_lists() {
+        let borg = plan(Tool::Borg);
+        assert_eq!(
+            borg.backup().join(" "),
+            "borg create --json --exclude *.cache /srv/backup::{hostname}-{now} /etc /home"
+        );
+        let retention = Retention {
+            keep_last: Some(3),
+            ..Default::default()
+        };
+        let prune = borg.prune(&retention);
+        assert_eq!(
+            prune[0].join(" "),
+            "borg prune --list --keep-last 3 /srv/backup"
+        );
+        assert_eq!(prune[1].join(" "), "borg compact /srv/backup");
+
+        let stdout = r#"{"archive": {"name": "web1-2026-10-16T02:00:00", "duration": 12.5,
+            "stats": {"nfiles": 120, "original_size": 500000, "compressed_size": 200000, "deduplicated_size": 4096}},
+            "repository": {"location": "/srv/backup"}}"#;
+        let stats = borg
+            .backup_stats(&outcome(0, stdout, ""))
+            .unwrap_or_else(|e| panic!("{e}"));
+        assert_eq!(stats["snapshot_id"], "web1-2026-10-16T02:00:00");
+        assert_eq!(stats["files"], 120);
+        assert_eq!(stats["added_bytes"], 4096);
+
+        let stderr = "Keeping archive (rule: daily #1):  web1-3\nPruning archive (1/2):  web1-1\nPruning archive (2/2):  web1-2\n";
+        assert_eq!(
+            borg.prune_stats(&outcome(0, "", stderr)),
+            json!({"kept": 1, "removed": 2})
+        );
+        assert_eq!(
+            borg.error(&outcome(2, "", "Repository /srv/backup does not exist.")),
+            "Repository /srv/backup does not exist."
+        );
+    }
+}
@@ -0,0 +1,360 @@
+//! Bitbucket Source - Bitbucket Cloud Webhook Receiver
+//!
+//! A Source that receives Bitbucket Cloud webhook deliveries and emits each
+//! as `bitbucket.<event>`, its `X-Event-Key` with `:` as `.` (e.g.
+//! `bitbucket.repo.push`, `bitbucket.pullrequest.created`), with the
+//! delivery's JSON body as payload. With `--secret`, deliveries must carry
+//! a valid `X-Hub-Signature` (`sha256=` HMAC of the body).
+//!
+//! Pushes, pull request changes and commit statuses are also published as
+//! provider-agnostic `scm.push`, `scm.pull_request` and `scm.pipeline`
+//! events (see [`scm`]), the same schemas github-source and gitlab-source
+//! emit.
+//!
+//! `--emit-type-template` can also use `{event}`, the event key with `:`
+//! as `.`. A delivery that cannot be published or spooled is answered with
+//! 503 so Bitbucket retries it.
+//!
+//! Sources are SILENT - they only produce domain messages.
+//! All lifecycle events are published by the engine.
+//!
+//! # Usage
+//!
+//! ```bash
+//! # Receive deliveries on :8080/
+//! bitbucket-source --secret $WEBHOOK_SECRET
+//!
+//! bitbucket-source --port 9000 --path /bitbucket --secret $WEBHOOK_SECRET
+//! ```
+//!
+//! On SIGTERM the listener closes immediately and deliveries already being
+//! handled get `--drain-timeout` milliseconds to finish publishing.
+
+pub mod scm;
+
+use axum::{
+    Router,
+    body::Bytes,
+    extract::State,
+    http::{HeaderMap, StatusCode},
+    response::IntoResponse,
+    routing::post,
+};
+use clap::Parser;
+use emergent_client::EmergentSource;
+use hmac::{Hmac, Mac};
+use primitive_common::capabilities::{self, CapabilityArgs, Role, VERSION, capabilities_message};
+use primitive_common::doctor::Report;
+use primitive_common::source::{Outlet, SourceArgs};
+use serde_json::Value;
+use sha2::Sha256;
+use std::{future::IntoFuture, net::SocketAddr, sync::Arc};
+use tokio::{
+    signal::unix::{SignalKind, signal},
+    sync::Notify,
+};
+
+/// Bitbucket webhook receiver that emits bitbucket.* and scm.* events.
+#[derive(Parser, Debug, Clone)]
+#[command(name = "bitbucket-source", version = VERSION)]
+#[command(about = "Receives Bitbucket Cloud webhooks and emits events")]
+struct Args {
+    /// Port to listen on.
+    #[arg(short, long, env = "BITBUCKET_SOURCE_PORT", default_value = "8080")]
+    port: u16,
+
+    /// Host to bind to.
+    #[arg(long, env = "BITBUCKET_SOURCE_HOST", default_value = "0.0.0.0")]
+    host: String,
+
+    /// Path to accept deliveries on.
+    #[arg(long, env = "BITBUCKET_SOURCE_PATH", default_value = "/")]
+    path: String,
+
+    /// Webhook secret; deliveries must then carry a valid X-Hub-Signature.
+    #[arg(long, env = "BITBUCKET_WEBHOOK_SECRET", hide_env_values = true)]
+    secret: Option<String>,
+
+    /// Verify external dependencies and exit without connecting to the engine.
+    #[arg(long)]
+    self_test: bool,
+
+    #[command(flatten)]
+    source: SourceArgs,
+
+    #[command(flatten)]
+    capabilities: CapabilityArgs,
+}
+
+/// Variables available to `--emit-type-template`.
+const TEMPLATE_VARIABLES: &[&str] = &["source", "event"];
+
+/// Shared application state.
+struct AppState {
+    outlet: Arc<Outlet>,
+    secret: Option<String>,
+}
+
+/// Validates an `X-Hub-Signature` header (`sha256=<hex>`) against `body`.
+fn validate_signature(secret: &str, body: &[u8], signature: &str) -> bool {
+    let Ok(mut mac) = Hmac::<Sha256>::new_from_slice(secret.as_bytes()) else {
+        return false;
+    };
+    mac.update(body);
+    let Some(Ok(expected)) = signature.strip_prefix("sha256=").map(hex::decode) else {
+        return false;
+    };
+    mac.verify_slice(&expected).is_ok()
+}
+
+/// Runs `--self-test` checks and exits.
+fn self_test(args: &Args, name: &str) -> ! {
+    let mut report = Report::new(name);
+    let addr = format!("{}:{}", args.host, args.port);
+    let bind = addr
+        .parse::<SocketAddr>()
+        .map_err(|e| format!("{addr}: {e}"))
+        .and_then(|a| {
+            std::net::TcpListener::bind(a)
+                .map(|_| addr.clone())
+                .map_err(|e| format!("{addr}: {e}"))
+        });
+    report.check("bind", bind);
+    report.check("path", check_path(&args.path));
+    args.source.self_test(&mut report, TEMPLATE_VARIABLES);
+    report.finish()
+}
+
+/// Check that the webhook path is absolute.
+fn check_path(path: &str) -> Result<String, String> {
+    if path.starts_with('/') {
+        Ok(path.to_string())
+    } else {
+        Err(format!("{path} must start with '/'"))
+    }
+}
+
+/// Handles incoming webhook deliveries.
+async fn handle_delivery(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> impl IntoResponse {
+    let header = |name: &str| headers.get(name).and_then(|h| h.to_str().ok());
+
+    if let Some(secret) = &state.secret {
+        match header("x-hub-signature") {
+            Some(signature) if validate_signature(secret, &body, signature) => {}
+            Some(_) => return (StatusCode::UNAUTHORIZED, "Invalid signature"),
+            None => return (StatusCode::UNAUTHORIZED, "Missing signature"),
+        }
+    }
+
+    let Some(event) = header("x-event-key") else {
+        return (StatusCode::BAD_REQUEST, "Missing X-Event-Key header");
+    };
+    let Ok(payload) = serde_json::from_slice::<Value>(&body) else {
+        return (StatusCode::BAD_REQUEST, "Body is not JSON");
+    };
+
+    let key = event.replace(':', ".");
+    let vars = [("event", key.as_str())];
+    // The outlet logs and reports what it cannot deliver
+    if state
+        .outlet
+        .publish(&format!("bitbucket.{key}"), &vars, payload.clone())
+        .await
+        .is_err()
+    {
+        return (StatusCode::SERVICE_UNAVAILABLE, "Failed to publish event");
+    }
+    for (message_type, event) in scm::events(event, &payload) {
+        let _ = state.outlet.publish(message_type, &vars, event).await;
+    }
+
+    (StatusCode::ACCEPTED, "")
+}
+
+/// Run the primitive with `args` as its command line (the first item is
+/// the program name, as with `std::env::args_os`).
+pub async fn run<I, T>(args: I) -> Result<(), Box<dyn std::error::Error>>
+where
+    I: IntoIterator<Item = T>,
+    T: Into<std::ffi::OsString> + Clone,
+{
+    let args = Args::parse_from(args);
+
+    // Get the source name from environment (set by engine) or use default
+    let name = std::env::var("EMERGENT_NAME").unwrap_or_else(|_| "bitbucket-source".to_string());
+
+    if args.self_test {
+        self_test(&args, &name);
+    }
+    if let Err(e) = check_path(&args.path).and_then(|_| args.source.validate(TEMPLATE_VARIABLES)) {
+        eprintln!("Error: {e}");
+        std::process::exit(1);
+    }
+
+    // Event keys are only known per delivery, so the namespace is listed
+    let produces = args.source.produces(&[
+        "bitbucket.*",
+        "scm.push",
+        "scm.pull_request",
+        "scm.pipeline",
+    ]);
+    let produces: Vec<&str> = produces.iter().map(String::as_str).collect();
+    let descriptor = args
+        .capabilities
+        .describe(&name, Role::Source, &[], &produces, &args);
+    if args.capabilities.capabilities {
+        capabilities::print(&descriptor);
+    }
+
+    // Connect to the Emergent engine (silently - lifecycle events come from engine)
+    let source = match EmergentSource::connect(&name).await {
+        Ok(s) => s,
+        Err(e) => {
+            eprintln!("Failed to connect to Emergent engine: {e}");
+            std::process::exit(1);
+        }
+    };
+    let outlet = Arc::new(Outlet::new(source, &name, &args.source)?);
+    if args.capabilities.announce {
+        outlet.announce(capabilities_message(&descriptor)).await;
+    }
+    tokio::spawn(Arc::clone(&outlet).drain_every(args.source.spool.retry_interval()));
+
+    let state = Arc::new(AppState {
+        outlet: Arc::clone(&outlet),
+        secret: args.secret.clone(),
+    });
+    let app = Router::new()
+        .route(&args.path, post(handle_delivery))
+        .with_state(state);
+
+    let addr: SocketAddr = format!("{}:{}", args.host, args.port).parse()?;
+
+    // Set up SIGTERM handler for graceful shutdown
+    let mut sigterm = signal(SignalKind::terminate())?;
+
+    let shutdown = Arc::new(Notify::new());
+    let server = axum::serve(
+        tokio::net::TcpListener::bind(&addr).await?,
+        app.into_make_service(),
+    )
+    .with_graceful_shutdown({
+        let shutdown = Arc::clone(&shutdown);
+        async move { shutdown.notified().await }
+    })
+    .into_future();
+    tokio::pin!(server);
+
+    tokio::select! {
+        result = &mut server => {
+            result?;
+        }
+        _ = sigterm.recv() => {
+            // Stop accepting connections and let in-flight deliveries finish
+            shutdown.notify_one();
+            args.source.drain.drain("in-flight deliveries", &mut server).await;
+            outlet.disconnect().await;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use emergent_testkit::{MockEngine, fixtures};
+    use serde_json::json;
+    use std::path::Path;
+
+    /// Connect to the engine on `socket` as the source would.
+    async fn connect(socket: &Path, args: &Args) -> Arc<AppState> {
+        let source = EmergentSource::connect_to("bitbucket-source", socket)
+            .await
+            .unwrap_or_else(|e| panic!("connect: {e}"));
+        let outlet = Outlet::new(source, "bitbucket-source", &args.source)
+            .unwrap_or_else(|e| panic!("open spool: {e}"));
+        Arc::new(AppState {
+            outlet: Arc::new(outlet),
+            secret: args.secret.clone(),
+        })
+    }
+
+    async fn deliver_push(state: &Arc<AppState>) -> StatusCode {
+        let body = json!({
+            "repository": {"full_name": "acme/api"},
+            "push": {"changes": [{"new": {"type": "branch", "name": "main", "target": {"hash": "abc123"}}}]},
+        });
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "x-event-key",
+            "repo:push".parse().unwrap_or_else(|e| panic!("{e}")),
+        );
+        handle_delivery(
+            State(Arc::clone(state)),
+            headers,
+            Bytes::from(body.to_string()),
+        )
+        .await
+        .into_response()
+        .status()
+    }
+
+    #[tokio::test]
+    async fn deliveries_survive_the_engine_being_down() {
+        let dir = fixtures::TempDir::new("bitbucket-source-spool");
+        let socket = dir.path().join("engine.sock");
+        let spool = dir.path().join("spool");
+        let args = Args::parse_from([
+            "bitbucket-source",
+            "--spool-dir",
+            spool.to_str().unwrap_or_default(),
+        ]);
+
+        let mut engine = MockEngine::serve(&socket);
+        let state = connect(&socket, &args).await;
+        engine.shut_down().await;
+        assert_eq!(deliver_push(&state).await, StatusCode::ACCEPTED);
+        drop(state);
+
+        let mut engine = MockEngine::serve(&socket);
+        let state = connect(&socket, &args).await;
+        state.outlet.drain_spool().await;
+        let published = engine.expect_published("bitbucket.repo.push").await;
+        assert_eq!(published.payload()["repository"]["full_name"], "acme/api");
+        engine.expect_published("scm.push").await;
+        engine.shut_down().await;
+    }
+
+    #[tokio::test]
+    async fn undeliverable_events_are_refused_for_a_retry() {
+        let dir = fixtures::TempDir::new("bitbucket-source-down");
+        let socket = dir.path().join("engine.sock");
+        let args = Args::parse_from(["bitbucket-source"]);
+
+        let mut engine = MockEngine::serve(&socket);
+        let state = connect(&socket, &args).await;
+        engine.shut_down().await;
+        assert_eq!(deliver_push(&state).await, StatusCode::SERVICE_UNAVAILABLE);
+    }
+
+    #[test]
+    fn hub_signatures_are_checked() {
+        let signature = "sha256=757107ea0eb2509fc211221cce984b8a37570b6d7586c22c46f4379c8b043e17";
+        assert!(validate_signature(
+            "It's a Secret to Everybody",
+            b"Hello, World!",
+            signature
+        ));
+        assert!(!validate_signature("wrong", b"Hello, World!", signature));
+        assert!(!validate_signature(
+            "It's a Secret to Everybody",
+            b"Hello, World!",
+            "sha1=757107ea0eb2509fc211221cce984b8a37570b6d7586c22c46f4379c8b043e17"
+        ));
+    }
+}
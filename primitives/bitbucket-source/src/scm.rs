@@ -0,0 +1,180 @@
+//! Provider-agnostic `scm.*` events.
+//!
+//! | Bitbucket event | `scm` event |
+//! |-----------------|-------------|
+//! | `repo:push` | one `scm.push` per changed branch or tag |
+//! | `pullrequest:created`, `updated`, `fulfilled`, `rejected` | `scm.pull_request` (`opened`, `updated`, `merged`, `closed`) |
+//! | `repo:commit_status_created`, `repo:commit_status_updated` | `scm.pipeline` |
+//!
+//! Commit statuses are how Bitbucket reports builds, whether from
+//! Bitbucket Pipelines or an outside CI system.
+
+use event_schemas::{EventPayload, ScmPipeline, ScmPullRequest, ScmPush};
+use serde_json::Value;
+
+const PROVIDER: &str = "bitbucket";
+
+/// The `scm.*` events for a delivery of `event` (its `X-Event-Key`), as
+/// (message type, payload) pairs; none for events without an `scm`
+/// equivalent.
+pub fn events(event: &str, payload: &Value) -> Vec<(&'static str, Value)> {
+    match event {
+        "repo:push" => pushes(payload)
+            .into_iter()
+            .map(|e| (ScmPush::MESSAGE_TYPE, e.to_payload()))
+            .collect(),
+        "pullrequest:created"
+        | "pullrequest:updated"
+        | "pullrequest:fulfilled"
+        | "pullrequest:rejected" => pull_request(event, payload)
+            .map(|e| (ScmPullRequest::MESSAGE_TYPE, e.to_payload()))
+            .into_iter()
+            .collect(),
+        "repo:commit_status_created" | "repo:commit_status_updated" => pipeline(payload)
+            .map(|e| (ScmPipeline::MESSAGE_TYPE, e.to_payload()))
+            .into_iter()
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+fn string(value: &Value) -> Option<String> {
+    value.as_str().map(str::to_string)
+}
+
+/// Who acted: their nickname, or display name if they have none.
+fn actor(payload: &Value) -> Option<String> {
+    let actor = &payload["actor"];
+    string(&actor["nickname"]).or_else(|| string(&actor["display_name"]))
+}
+
+/// One push per ref in `push.changes`; `new` is null for a deleted ref and
+/// `old` for a created one.
+fn pushes(payload: &Value) -> Vec<ScmPush> {
+    let Some(repository) = string(&payload["repository"]["full_name"]) else {
+        return Vec::new();
+    };
+    let Some(changes) = payload["push"]["changes"].as_array() else {
+        return Vec::new();
+    };
+    changes
+        .iter()
+        .filter_map(|change| {
+            let side = if change["new"].is_object() {
+                &change["new"]
+            } else {
+                &change["old"]
+            };
+            let name = side["name"].as_str()?;
+            let (reference, branch, tag) = match side["type"].as_str()? {
+                "branch" => (format!("refs/heads/{name}"), Some(name.to_string()), None),
+                "tag" | "annotated_tag" => {
+                    (format!("refs/tags/{name}"), None, Some(name.to_string()))
+                }
+                _ => return None,
+            };
+            Some(ScmPush {
+                provider: PROVIDER.to_string(),
+                repository: repository.clone(),
+                r#ref: reference,
+                branch,
+                tag,
+                before: string(&change["old"]["target"]["hash"]),
+                after: string(&change["new"]["target"]["hash"]),
+                commits: change["commits"].as_array().map_or(0, |c| c.len() as u64),
+                sender: actor(payload),
+                url: string(&change["links"]["html"]["href"])
+                    .or_else(|| string(&payload["repository"]["links"]["html"]["href"])),
+            })
+        })
+        .collect()
+}
+
+fn pull_request(event: &str, payload: &Value) -> Option<ScmPullRequest> {
+    let pr = &payload["pullrequest"];
+    let action = match event {
+        "pullrequest:created" => "opened",
+        "pullrequest:fulfilled" => "merged",
+        "pullrequest:rejected" => "closed",
+        _ => "updated",
+    };
+    Some(ScmPullRequest {
+        provider: PROVIDER.to_string(),
+        repository: string(&payload["repository"]["full_name"])?,
+        number: pr["id"].as_u64()?,
+        action: action.to_string(),
+        title: string(&pr["title"]).unwrap_or_default(),
+        source_branch: string(&pr["source"]["branch"]["name"])?,
+        target_branch: string(&pr["destination"]["branch"]["name"])?,
+        head_sha: string(&pr["source"]["commit"]["hash"]),
+        sender: actor(payload),
+        url: string(&pr["links"]["html"]["href"]),
+    })
+}
+
+fn pipeline(payload: &Value) -> Option<ScmPipeline> {
+    let status = &payload["commit_status"];
+    let state = match status["state"].as_str()? {
+        "SUCCESSFUL" => "success",
+        "FAILED" => "failed",
+        "STOPPED" => "canceled",
+        _ => "running",
+    };
+    Some(ScmPipeline {
+        provider: PROVIDER.to_string(),
+        repository: string(&payload["repository"]["full_name"])?,
+        id: string(&status["key"])?,
+        name: string(&status["name"]),
+        branch: string(&status["refname"]),
+        sha: string(&status["commit"]["hash"]),
+        status: state.to_string(),
+        url: string(&status["url"]),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn deliveries_normalize_to_scm_events() {
+        let push = json!({
+            "actor": {"nickname": "evzijst"},
+            "repository": {"full_name": "acme/api"},
+            "push": {"changes": [
+                {"new": {"type": "branch", "name": "main", "target": {"hash": "709d658"}},
+                 "old": {"type": "branch", "name": "main", "target": {"hash": "1e65c05"}},
+                 "commits": [{}]},
+                {"new": null,
+                 "old": {"type": "tag", "name": "v1.0", "target": {"hash": "1e65c05"}},
+                 "commits": []},
+            ]},
+        });
+        let events = events("repo:push", &push);
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].1["ref"], "refs/heads/main");
+        assert_eq!(events[0].1["before"], "1e65c05");
+        assert_eq!(events[0].1["sender"], "evzijst");
+        assert_eq!(events[1].1["tag"], "v1.0");
+        assert!(events[1].1["after"].is_null());
+
+        let merged = json!({
+            "pullrequest": {"id": 5, "title": "Fix",
+                            "source": {"branch": {"name": "fix"}, "commit": {"hash": "abc"}},
+                            "destination": {"branch": {"name": "main"}}},
+            "repository": {"full_name": "acme/api"},
+        });
+        let events = super::events("pullrequest:fulfilled", &merged);
+        assert_eq!(events[0].0, "scm.pull_request");
+        assert_eq!(events[0].1["action"], "merged");
+
+        let status = json!({
+            "commit_status": {"key": "pipeline-42", "state": "INPROGRESS", "refname": "main"},
+            "repository": {"full_name": "acme/api"},
+        });
+        let events = super::events("repo:commit_status_updated", &status);
+        assert_eq!(events[0].1["status"], "running");
+        assert!(super::events("pullrequest:approved", &merged).is_empty());
+    }
+}
@@ -0,0 +1,256 @@
+//! Spend per budget key for the current period, optionally kept in a state
+//! file so a restart does not hand out a fresh budget.
+//!
+//! Periods follow the UTC calendar: hours, days, weeks starting on Monday,
+//! or months. Spend recorded in an earlier period is dropped as the next
+//! one starts. The file is rewritten after every change, through a
+//! temporary file so a crash never leaves half of it.
+
+use clap::ValueEnum;
+use primitive_common::time::{civil_from_days, days_from_civil};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const HOUR_MS: u64 = 3_600_000;
+const DAY_MS: u64 = 24 * HOUR_MS;
+
+/// How often budgets start over.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Period {
+    Hour,
+    Day,
+    /// Monday to Sunday.
+    Week,
+    Month,
+}
+
+impl Period {
+    /// When the period holding `now` started, in milliseconds since the
+    /// Unix epoch.
+    pub fn start(self, now: u64) -> u64 {
+        let days = (now / DAY_MS) as i64;
+        let start_day = match self {
+            Period::Hour => return now - now % HOUR_MS,
+            Period::Day => days,
+            // 1970-01-01 was a Thursday
+            Period::Week => days - (days + 3).rem_euclid(7),
+            Period::Month => {
+                let (year, month, _) = civil_from_days(days);
+                days_from_civil(year, month, 1)
+            }
+        };
+        start_day as u64 * DAY_MS
+    }
+
+    /// When the period starting at `start` ends.
+    pub fn end(self, start: u64) -> u64 {
+        match self {
+            Period::Hour => start + HOUR_MS,
+            Period::Day => start + DAY_MS,
+            Period::Week => start + 7 * DAY_MS,
+            Period::Month => {
+                let (year, month, _) = civil_from_days((start / DAY_MS) as i64);
+                let (year, month) = if month == 12 {
+                    (year + 1, 1)
+                } else {
+                    (year, month + 1)
+                };
+                days_from_civil(year, month, 1) as u64 * DAY_MS
+            }
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Period::Hour => "hour",
+            Period::Day => "day",
+            Period::Week => "week",
+            Period::Month => "month",
+        }
+    }
+}
+
+/// One key's spend in a period.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Account {
+    /// Start of the period, in milliseconds since the Unix epoch.
+    pub period_start: u64,
+    pub spent: f64,
+    /// Whether `budget.exhausted` went out this period.
+    pub exhausted: bool,
+}
+
+/// The spend of every key.
+#[derive(Debug)]
+pub struct Ledger {
+    period: Period,
+    accounts: BTreeMap<String, Account>,
+    path: Option<PathBuf>,
+}
+
+impl Ledger {
+    /// The ledger in `path`, or an empty one without it; a missing file is
+    /// empty.
+    pub fn load(path: Option<&Path>, period: Period) -> Result<Self, String> {
+        let Some(path) = path else {
+            return Ok(Self {
+                period,
+                accounts: BTreeMap::new(),
+                path: None,
+            });
+        };
+        let accounts = match fs::read_to_string(path) {
+            Ok(text) => {
+                serde_json::from_str(&text).map_err(|e| format!("{}: {e}", path.display()))?
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => return Err(format!("{}: {e}", path.display())),
+        };
+        Ok(Self {
+            period,
+            accounts,
+            path: Some(path.to_path_buf()),
+        })
+    }
+
+    pub fn period(&self) -> Period {
+        self.period
+    }
+
+    /// How many keys have spent this period.
+    pub fn len(&self) -> usize {
+        self.accounts.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.accounts.is_empty()
+    }
+
+    /// `key`'s account, if it has spent this period.
+    pub fn account(&self, key: &str) -> Option<&Account> {
+        self.accounts.get(key)
+    }
+
+    /// Add `amount` to `key`'s spend at `now`, opening an account for it;
+    /// accounts of ended periods must have been rolled first.
+    pub fn spend(&mut self, key: &str, amount: f64, now: u64) -> &mut Account {
+        let period_start = self.period.start(now);
+        let account = self.accounts.entry(key.to_string()).or_insert(Account {
+            period_start,
+            spent: 0.0,
+            exhausted: false,
+        });
+        account.spent += amount;
+        account
+    }
+
+    /// Close the accounts of periods ended by `now`, returning them.
+    pub fn roll(&mut self, now: u64) -> Vec<(String, Account)> {
+        let current = self.period.start(now);
+        let ended: Vec<String> = self
+            .accounts
+            .iter()
+            .filter(|(_, account)| account.period_start < current)
+            .map(|(key, _)| key.clone())
+            .collect();
+        ended
+            .into_iter()
+            .filter_map(|key| self.accounts.remove(&key).map(|account| (key, account)))
+            .collect()
+    }
+
+    /// When the earliest open account's period ends, if any are open.
+    pub fn next_roll(&self) -> Option<u64> {
+        self.accounts
+            .values()
+            .map(|account| account.period_start)
+            .min()
+            .map(|start| self.period.end(start))
+    }
+
+    /// Write the accounts to the state file, if there is one.
+    pub fn save(&self) -> Result<(), String> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let state = serde_json::to_vec_pretty(&self.accounts).map_err(|e| e.to_string())?;
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, state)
+            .and_then(|()| fs::rename(&tmp, path))
+            .map_err(|e| format!("{}: {e}", path.display()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use emergent_testkit::fixtures::TempDir;
+
+    // 2024-02-29T13:45:00Z, a Thursday
+    const NOW: u64 = 1_709_214_300_000;
+
+    fn format(ms: u64) -> String {
+        primitive_common::time::format((ms / 1000) as i64)
+    }
+
+    #[test]
+    fn periods_follow_the_utc_calendar() {
+        let span = |period: Period| {
+            let start = period.start(NOW);
+            (format(start), format(period.end(start)))
+        };
+        assert_eq!(
+            span(Period::Hour),
+            ("2024-02-29T13:00:00Z".into(), "2024-02-29T14:00:00Z".into())
+        );
+        assert_eq!(
+            span(Period::Day),
+            ("2024-02-29T00:00:00Z".into(), "2024-03-01T00:00:00Z".into())
+        );
+        assert_eq!(
+            span(Period::Week),
+            ("2024-02-26T00:00:00Z".into(), "2024-03-04T00:00:00Z".into())
+        );
+        assert_eq!(
+            span(Period::Month),
+            ("2024-02-01T00:00:00Z".into(), "2024-03-01T00:00:00Z".into())
+        );
+        let december = Period::Month.start(1_703_980_800_000);
+        assert_eq!(format(Period::Month.end(december)), "2024-01-01T00:00:00Z");
+    }
+
+    #[test]
+    fn spend_rolls_over_and_survives_restarts() {
+        let dir = TempDir::new("budget");
+        let path = dir.path().join("ledger.json");
+        let mut ledger = Ledger::load(Some(&path), Period::Day).unwrap_or_else(|e| panic!("{e}"));
+        assert!(ledger.is_empty());
+        ledger.spend("acme", 40.0, NOW);
+        ledger.spend("acme", 2.5, NOW).exhausted = true;
+        ledger.spend("globex", 1.0, NOW);
+        ledger.save().unwrap_or_else(|e| panic!("{e}"));
+
+        let mut restored = Ledger::load(Some(&path), Period::Day).unwrap_or_else(|e| panic!("{e}"));
+        assert_eq!(restored.len(), 2);
+        let acme = restored.account("acme").cloned();
+        assert_eq!(acme.as_ref().map(|a| a.spent), Some(42.5));
+        assert_eq!(acme.map(|a| a.exhausted), Some(true));
+        let midnight = Period::Day.end(Period::Day.start(NOW));
+        assert_eq!(restored.next_roll(), Some(midnight));
+        assert!(restored.roll(midnight - 1).is_empty());
+
+        let ended: Vec<String> = restored
+            .roll(midnight)
+            .into_iter()
+            .map(|(k, _)| k)
+            .collect();
+        assert_eq!(ended, ["acme", "globex"]);
+        assert_eq!(restored.next_roll(), None);
+        assert_eq!(restored.spend("acme", 1.0, midnight).spent, 1.0);
+
+        std::fs::write(&path, "not json").unwrap_or_else(|e| panic!("{e}"));
+        assert!(Ledger::load(Some(&path), Period::Day).is_err());
+    }
+}
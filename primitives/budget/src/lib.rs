@@ -0,0 +1,663 @@
+//! Budget
+//!
+//! A Handler that guards cost-incurring sinks with spend budgets. It adds
+//! up spend reported by events — LLM tokens, SMS messages, API credits —
+//! per period, and once a budget is used up stops passing on the events
+//! that would spend more, until the next period starts.
+//!
+//! # Data Flow
+//!
+//! 1. Receive a gated event (`--subscribe`) and republish it as
+//!    `--publish-as` while its budget has room; once the budget is
+//!    exhausted, drop it (`--action block`) or republish it as
+//!    `--reroute-as` (`--action reroute`)
+//! 2. Receive a spend event (`--spend`) and add its `--amount` field to
+//!    the budget; without `--spend`, every gated event that passes counts
+//!    instead, costing its `--amount` or 1
+//! 3. Publish `budget.exhausted` once spend reaches `--limit`, and
+//!    `budget.reset` for exhausted budgets when the next period starts
+//!
+//! Periods follow the UTC calendar (see [`ledger`]). With `--key`, each
+//! value of a payload field, such as a tenant id, gets a budget of its
+//! own. Spend is only known after the fact, so the event that crosses
+//! the limit and any already in flight still pass: budgets can overshoot
+//! by one event's cost. With `--state-file`, spend survives restarts.
+//!
+//! # Messages Published
+//!
+//! - The gated type, renamed by `--publish-as` (default:
+//!   `budgeted.{type}`), while the budget has room
+//! - The gated type, renamed by `--reroute-as` (default:
+//!   `over-budget.{type}`), once it is exhausted, with `--action reroute`
+//! - `budget.exhausted`: the budget's handler name, key, limit, spend,
+//!   period, when the period started and when it resets
+//! - `budget.reset`: the same for a budget that was exhausted, as the new
+//!   period starts
+//!
+//! # Usage
+//!
+//! ```bash
+//! # A million LLM tokens a day, counted from the completions
+//! budget -s llm.request --spend llm.completed --amount usage.total_tokens \
+//!   --limit 1000000 --period day --state-file /var/lib/emergent/llm-budget.json
+//!
+//! # 500 text messages per tenant a month; the rest go out by email
+//! budget -s sms.send --key tenant_id --limit 500 --period month \
+//!   --action reroute --reroute-as email.send
+//! ```
+
+pub mod ledger;
+
+use clap::{Parser, ValueEnum};
+use emergent_client::EmergentMessage;
+use ledger::{Account, Ledger, Period};
+use primitive_common::capabilities::{self, CapabilityArgs, Role, VERSION};
+use primitive_common::crypto::{KeyArgs, Keyring};
+use primitive_common::doctor::{Report, check_writable_dir};
+use primitive_common::errors::{ERROR_EVENT_TYPE, ErrorArgs, ErrorCategory};
+use primitive_common::glob;
+use primitive_common::handler::{Bus, Flow, HandlerConfig, MessageHandler, run_handler};
+use primitive_common::key;
+use primitive_common::payload::{self, PayloadArgs};
+use primitive_common::time;
+use serde_json::{Value, json};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Message type published when a budget is used up.
+pub const EXHAUSTED_EVENT_TYPE: &str = "budget.exhausted";
+
+/// Message type published when an exhausted budget starts over.
+pub const RESET_EVENT_TYPE: &str = "budget.reset";
+
+/// What happens to gated messages once their budget is exhausted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Action {
+    /// Drop them.
+    Block,
+    /// Republish them as `--reroute-as`.
+    Reroute,
+}
+
+/// Budget — gate cost-incurring events on per-period spend budgets.
+#[derive(Parser, Debug)]
+#[command(name = "budget", version = VERSION)]
+#[command(
+    about = "Track spend from events and block or reroute triggering events once a per-period budget is exhausted"
+)]
+struct Args {
+    /// Message types to gate.
+    #[arg(short, long = "subscribe", required = true)]
+    subscribe: Vec<String>,
+
+    /// Message types reporting spend (default: the gated messages that pass); repeatable.
+    #[arg(long, env = "BUDGET_SPEND", value_delimiter = ',')]
+    spend: Vec<String>,
+
+    /// Payload field (dotted path) holding each message's spend (default: 1 per message).
+    #[arg(long, env = "BUDGET_AMOUNT")]
+    amount: Option<String>,
+
+    /// Spend allowed per period, for each key.
+    #[arg(long, env = "BUDGET_LIMIT")]
+    limit: f64,
+
+    /// How often budgets start over, on the UTC calendar.
+    #[arg(long, env = "BUDGET_PERIOD", value_enum, default_value = "day")]
+    period: Period,
+
+    /// Payload field (dotted path) giving each of its values a budget of its own (default: one budget).
+    #[arg(long, env = "BUDGET_KEY")]
+    key: Option<String>,
+
+    /// What to do with gated messages once their budget is exhausted.
+    #[arg(long, env = "BUDGET_ACTION", value_enum, default_value = "block")]
+    action: Action,
+
+    /// Message type to republish gated messages as; `{type}` stands for the incoming type.
+    #[arg(
+        long,
+        env = "BUDGET_PUBLISH_AS",
+        default_value = "budgeted.{type}",
+        value_parser = parse_publish_as
+    )]
+    publish_as: String,
+
+    /// Message type over-budget messages are rerouted as; `{type}` stands for the incoming type.
+    #[arg(
+        long,
+        env = "BUDGET_REROUTE_AS",
+        default_value = "over-budget.{type}",
+        value_parser = parse_publish_as
+    )]
+    reroute_as: String,
+
+    /// File keeping this period's spend across restarts.
+    #[arg(long, env = "BUDGET_STATE_FILE")]
+    state_file: Option<PathBuf>,
+
+    /// Most keys tracked at once; spend for further keys is dropped.
+    #[arg(long, env = "BUDGET_MAX_KEYS", default_value = "10000")]
+    max_keys: usize,
+
+    #[command(flatten)]
+    payload: PayloadArgs,
+
+    /// Verify the configuration and exit without connecting to the engine.
+    #[arg(long)]
+    self_test: bool,
+
+    #[command(flatten)]
+    errors: ErrorArgs,
+
+    #[command(flatten)]
+    capabilities: CapabilityArgs,
+
+    #[command(flatten)]
+    keys: KeyArgs,
+}
+
+fn parse_publish_as(value: &str) -> Result<String, String> {
+    match value {
+        "" => Err("must not be empty".to_string()),
+        // Republishing under the same type would feed straight back in
+        "{type}" => Err("must differ from the incoming type".to_string()),
+        _ => Ok(value.to_string()),
+    }
+}
+
+/// Whether `message_type` is `template` with `{type}` filled in.
+fn renamed(template: &str, message_type: &str) -> bool {
+    match template.split_once("{type}") {
+        Some((prefix, suffix)) => {
+            message_type.len() > prefix.len() + suffix.len()
+                && message_type.starts_with(prefix)
+                && message_type.ends_with(suffix)
+        }
+        None => message_type == template,
+    }
+}
+
+/// Whether `message_type` is one this handler published.
+fn is_own(args: &Args, message_type: &str) -> bool {
+    matches!(message_type, EXHAUSTED_EVENT_TYPE | RESET_EVENT_TYPE)
+        || renamed(&args.publish_as, message_type)
+        || (args.action == Action::Reroute && renamed(&args.reroute_as, message_type))
+}
+
+/// The budget key of `input`: the `--key` field's value, or `""` for the
+/// one budget without it.
+fn budget_key(args: &Args, input: &Value) -> Result<String, String> {
+    let Some(field) = &args.key else {
+        return Ok(String::new());
+    };
+    match key::lookup(input, field) {
+        None | Some(Value::Null) => Err(format!("payload has no '{field}' to budget by")),
+        Some(Value::String(s)) => Ok(s.clone()),
+        Some(other) => Ok(other.to_string()),
+    }
+}
+
+/// The spend `input` reports: its `--amount` field, or 1 without one.
+fn amount(args: &Args, input: &Value) -> Result<f64, String> {
+    let Some(field) = &args.amount else {
+        return Ok(1.0);
+    };
+    let value = key::lookup(input, field).ok_or_else(|| format!("payload has no '{field}'"))?;
+    match value {
+        Value::Number(n) => n.as_f64(),
+        Value::String(s) => s.trim().parse().ok(),
+        _ => None,
+    }
+    .ok_or_else(|| format!("'{field}' is not a number: {value}"))
+}
+
+/// `value` as JSON, whole numbers without a fraction.
+fn number(value: f64) -> Value {
+    if value.fract() == 0.0 && value.abs() < 9e15 {
+        Value::from(value as i64)
+    } else {
+        Value::from(value)
+    }
+}
+
+/// A `budget.*` payload for `key`'s `account`.
+fn report(args: &Args, name: &str, key: &str, account: &Account, period_start: u64) -> Value {
+    let period = args.period;
+    json!({
+        "budget": name,
+        "key": args.key.as_ref().map(|_| key),
+        "limit": number(args.limit),
+        "spent": number(account.spent),
+        "period": period.name(),
+        "period_start": time::format((period_start / 1000) as i64),
+        "resets_at": time::format((period.end(period_start) / 1000) as i64),
+    })
+}
+
+/// What a message does.
+#[derive(Debug, Default, PartialEq)]
+struct Decision {
+    /// The type to republish it as, if any.
+    forward: Option<String>,
+    /// The `budget.exhausted` payload, when it used the budget up.
+    exhausted: Option<Value>,
+    /// Whether spend was recorded.
+    spent: bool,
+}
+
+/// Gate and count the message of `message_type` with payload `input` at
+/// `now` (milliseconds since the Unix epoch); `ledger` must have been
+/// rolled to `now`.
+fn decide(
+    args: &Args,
+    name: &str,
+    ledger: &mut Ledger,
+    message_type: &str,
+    input: &Value,
+    now: u64,
+) -> Result<Decision, String> {
+    let mut decision = Decision::default();
+    if is_own(args, message_type) {
+        return Ok(decision);
+    }
+    let gated = args
+        .subscribe
+        .iter()
+        .any(|pattern| glob::matches(pattern, message_type));
+    let spends = if args.spend.is_empty() {
+        gated
+    } else {
+        args.spend
+            .iter()
+            .any(|pattern| glob::matches(pattern, message_type))
+    };
+    if !gated && !spends {
+        return Ok(decision);
+    }
+    let key = budget_key(args, input)?;
+    let cost = if spends { amount(args, input)? } else { 0.0 };
+
+    if gated {
+        let exhausted = ledger
+            .account(&key)
+            .is_some_and(|account| account.spent >= args.limit);
+        if exhausted {
+            decision.forward = match args.action {
+                Action::Block => None,
+                Action::Reroute => Some(args.reroute_as.replace("{type}", message_type)),
+            };
+            return Ok(decision);
+        }
+        decision.forward = Some(args.publish_as.replace("{type}", message_type));
+    }
+
+    if spends {
+        if ledger.account(&key).is_none() && ledger.len() >= args.max_keys {
+            return Err(format!(
+                "{} keys tracked (--max-keys); not counting spend for {key}",
+                ledger.len()
+            ));
+        }
+        let account = ledger.spend(&key, cost, now);
+        decision.spent = true;
+        if account.spent >= args.limit && !account.exhausted {
+            account.exhausted = true;
+            let account = account.clone();
+            decision.exhausted = Some(report(args, name, &key, &account, account.period_start));
+        }
+    }
+    Ok(decision)
+}
+
+/// Run the primitive with `args` as its command line (the first item is
+/// the program name, as with `std::env::args_os`).
+pub async fn run<I, T>(args: I) -> Result<(), Box<dyn std::error::Error>>
+where
+    I: IntoIterator<Item = T>,
+    T: Into<std::ffi::OsString> + Clone,
+{
+    let args = Args::parse_from(args);
+
+    // Get the handler name from environment (set by engine) or use default
+    let name = std::env::var("EMERGENT_NAME").unwrap_or_else(|_| "budget".to_string());
+
+    let ledger = Ledger::load(args.state_file.as_deref(), args.period);
+    if args.self_test {
+        let mut report = Report::new(&name);
+        if let Some(path) = &args.state_file {
+            let dir = path
+                .parent()
+                .filter(|p| !p.as_os_str().is_empty())
+                .unwrap_or(Path::new("."));
+            report.check("state-file", check_writable_dir(dir));
+        }
+        report.check(
+            "ledger",
+            ledger
+                .as_ref()
+                .map(|ledger| format!("{} key(s) with spend", ledger.len()))
+                .map_err(Clone::clone),
+        );
+        args.payload.self_test(&mut report);
+        args.keys.self_test(&mut report);
+        report.finish();
+    }
+    let ledger = match ledger {
+        Ok(ledger) => ledger,
+        Err(e) => {
+            eprintln!("Error: invalid state file {e}");
+            std::process::exit(1);
+        }
+    };
+
+    if let Err(e) = args.payload.encryption.validate() {
+        eprintln!("Error: {e}");
+        std::process::exit(1);
+    }
+    let keys = match args.keys.load() {
+        Ok(keys) => keys,
+        Err(e) => {
+            eprintln!("Error: failed to load identity file: {e}");
+            std::process::exit(1);
+        }
+    };
+
+    let mut topics: Vec<String> = Vec::new();
+    for topic in args.subscribe.iter().chain(&args.spend) {
+        if !topics.contains(topic) {
+            topics.push(topic.clone());
+        }
+    }
+    let topics_refs: Vec<&str> = topics.iter().map(String::as_str).collect();
+    let mut produces = vec![
+        args.publish_as.as_str(),
+        EXHAUSTED_EVENT_TYPE,
+        RESET_EVENT_TYPE,
+    ];
+    if args.action == Action::Reroute {
+        produces.push(args.reroute_as.as_str());
+    }
+    if args.errors.emit_errors {
+        produces.push(ERROR_EVENT_TYPE);
+    }
+    let descriptor =
+        args.capabilities
+            .describe(&name, Role::Handler, &topics_refs, &produces, &args);
+    if args.capabilities.capabilities {
+        capabilities::print(&descriptor);
+    }
+
+    let config = HandlerConfig {
+        name: &name,
+        subscribe: &topics,
+        announce: args.capabilities.announce.then_some(&descriptor),
+        emit_errors: args.errors.emit_errors,
+    };
+    let mut budget = Budget {
+        args: &args,
+        ledger,
+        keys,
+    };
+    run_handler(config, &mut budget).await
+}
+
+/// Close ended periods, publishing `budget.reset` for exhausted budgets.
+async fn roll(bus: &Bus, args: &Args, ledger: &mut Ledger) {
+    let now = time::now_ms();
+    let ended = ledger.roll(now);
+    if ended.is_empty() {
+        return;
+    }
+    save(ledger);
+    let period_start = args.period.start(now);
+    for (key, account) in ended.into_iter().filter(|(_, account)| account.exhausted) {
+        eprintln!("budget: {} budget reset", display_key(&key));
+        let payload = report(args, bus.name(), &key, &account, period_start);
+        publish(bus, args, RESET_EVENT_TYPE, payload, None).await;
+    }
+}
+
+fn display_key(key: &str) -> String {
+    if key.is_empty() {
+        "the".to_string()
+    } else {
+        format!("{key}'s")
+    }
+}
+
+/// Write the ledger to the state file, reporting failures.
+fn save(ledger: &Ledger) {
+    if let Err(e) = ledger.save() {
+        eprintln!("budget: failed to save spend: {e}");
+    }
+}
+
+/// The ledger and what it needs to read payloads.
+struct Budget<'a> {
+    args: &'a Args,
+    ledger: Ledger,
+    keys: Option<Keyring>,
+}
+
+impl MessageHandler for Budget<'_> {
+    type Wake = ();
+
+    /// Gate and count one message.
+    async fn handle(&mut self, msg: &EmergentMessage, bus: &Bus) {
+        let (args, ledger) = (self.args, &mut self.ledger);
+        if is_own(args, msg.message_type.as_str()) {
+            return;
+        }
+        let input = match payload::decode(msg.payload(), self.keys.as_ref()).await {
+            Ok(p) => p.into_owned(),
+            Err(e) => {
+                let error = format!("failed to decode payload: {e}");
+                eprintln!("budget: {error}");
+                bus.report_error(msg, ErrorCategory::Parse, &error).await;
+                return;
+            }
+        };
+        roll(bus, args, ledger).await;
+        let message_type = msg.message_type.as_str();
+        let now = time::now_ms();
+        let decision = match decide(args, bus.name(), ledger, message_type, &input, now) {
+            Ok(decision) => decision,
+            Err(error) => {
+                eprintln!("budget: {error}");
+                bus.report_error(msg, ErrorCategory::Parse, &error).await;
+                return;
+            }
+        };
+        if decision.spent {
+            save(ledger);
+        }
+        if let Some(exhausted) = decision.exhausted {
+            eprintln!(
+                "budget: {} budget of {} exhausted",
+                display_key(exhausted["key"].as_str().unwrap_or_default()),
+                exhausted["limit"]
+            );
+            publish(bus, args, EXHAUSTED_EVENT_TYPE, exhausted, Some(msg)).await;
+        }
+        if let Some(forward) = decision.forward {
+            publish(bus, args, &forward, input, Some(msg)).await;
+        }
+    }
+
+    /// Sleep until the current period ends.
+    async fn wake(&mut self) {
+        match self.ledger.next_roll() {
+            Some(at) => {
+                let wait = at.saturating_sub(time::now_ms());
+                tokio::time::sleep(Duration::from_millis(wait)).await;
+            }
+            None => std::future::pending().await,
+        }
+    }
+
+    async fn woken(&mut self, (): (), bus: &Bus) -> Flow {
+        roll(bus, self.args, &mut self.ledger).await;
+        Flow::Continue
+    }
+}
+
+/// Publish `payload` as `message_type`, caused by `cause`.
+async fn publish(
+    bus: &Bus,
+    args: &Args,
+    message_type: &str,
+    payload: Value,
+    cause: Option<&EmergentMessage>,
+) {
+    let payload = match payload::encode(message_type, payload, &args.payload).await {
+        Ok(p) => p,
+        Err(e) => {
+            eprintln!("budget: failed to encode {message_type}: {e}");
+            return;
+        }
+    };
+    let mut message = EmergentMessage::new(message_type).with_payload(payload);
+    if let Some(cause) = cause {
+        message = message.with_causation_id(cause.id());
+    }
+    bus.publish(message).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 2024-02-29T13:45:00Z
+    const NOW: u64 = 1_709_214_300_000;
+
+    fn args(extra: &[&str]) -> Args {
+        let mut argv = vec!["budget", "-s", "llm.request"];
+        argv.extend_from_slice(extra);
+        Args::try_parse_from(argv).unwrap_or_else(|e| panic!("{e}"))
+    }
+
+    fn ledger() -> Ledger {
+        Ledger::load(None, Period::Day).unwrap_or_else(|e| panic!("{e}"))
+    }
+
+    #[test]
+    fn spend_events_exhaust_the_budget_and_block_gated_ones() {
+        let args = args(&[
+            "--limit",
+            "100",
+            "--spend",
+            "llm.completed",
+            "--amount",
+            "usage.tokens",
+        ]);
+        let mut ledger = ledger();
+        let mut handle = |message_type: &str, input: Value| {
+            decide(&args, "budget", &mut ledger, message_type, &input, NOW)
+        };
+
+        let request = json!({"prompt": "hi"});
+        let passed = handle("llm.request", request.clone()).unwrap_or_else(|e| panic!("{e}"));
+        assert_eq!(passed.forward.as_deref(), Some("budgeted.llm.request"));
+        assert!(!passed.spent);
+
+        let spent = handle("llm.completed", json!({"usage": {"tokens": 60}}))
+            .unwrap_or_else(|e| panic!("{e}"));
+        assert_eq!(spent.forward, None);
+        assert_eq!(spent.exhausted, None);
+        let crossed = handle("llm.completed", json!({"usage": {"tokens": "45"}}))
+            .unwrap_or_else(|e| panic!("{e}"));
+        assert_eq!(
+            crossed.exhausted,
+            Some(json!({
+                "budget": "budget",
+                "key": null,
+                "limit": 100,
+                "spent": 105,
+                "period": "day",
+                "period_start": "2024-02-29T00:00:00Z",
+                "resets_at": "2024-03-01T00:00:00Z",
+            }))
+        );
+        // Reported once per period
+        let more = handle("llm.completed", json!({"usage": {"tokens": 1}}))
+            .unwrap_or_else(|e| panic!("{e}"));
+        assert_eq!(more.exhausted, None);
+
+        let blocked = handle("llm.request", request).unwrap_or_else(|e| panic!("{e}"));
+        assert_eq!(blocked, Decision::default());
+        assert!(handle("llm.completed", json!({"usage": {}})).is_err());
+        // Our own output is left alone
+        let own = handle("budgeted.llm.request", json!({})).unwrap_or_else(|e| panic!("{e}"));
+        assert_eq!(own, Decision::default());
+    }
+
+    #[test]
+    fn gated_events_count_themselves_per_key_and_reroute_when_over() {
+        let args = args(&[
+            "--key",
+            "tenant",
+            "--limit",
+            "2",
+            "--action",
+            "reroute",
+            "--reroute-as",
+            "email.send",
+        ]);
+        let mut ledger = ledger();
+        let mut send = |tenant: &str| {
+            decide(
+                &args,
+                "budget",
+                &mut ledger,
+                "llm.request",
+                &json!({"tenant": tenant}),
+                NOW,
+            )
+            .unwrap_or_else(|e| panic!("{e}"))
+        };
+
+        assert_eq!(
+            send("acme").forward.as_deref(),
+            Some("budgeted.llm.request")
+        );
+        let second = send("acme");
+        assert_eq!(second.forward.as_deref(), Some("budgeted.llm.request"));
+        assert_eq!(
+            second.exhausted.as_ref().map(|e| e["key"].clone()),
+            Some(json!("acme"))
+        );
+        assert_eq!(send("acme").forward.as_deref(), Some("email.send"));
+        // Other tenants have budgets of their own
+        assert_eq!(
+            send("globex").forward.as_deref(),
+            Some("budgeted.llm.request")
+        );
+
+        // Next day, the budget starts over
+        let tomorrow = Period::Day.end(Period::Day.start(NOW));
+        let ended = ledger.roll(tomorrow);
+        assert_eq!(ended.len(), 2);
+        let passed = decide(
+            &args,
+            "budget",
+            &mut ledger,
+            "llm.request",
+            &json!({"tenant": "acme"}),
+            tomorrow,
+        )
+        .unwrap_or_else(|e| panic!("{e}"));
+        assert_eq!(passed.forward.as_deref(), Some("budgeted.llm.request"));
+        assert!(
+            decide(
+                &args,
+                "budget",
+                &mut ledger,
+                "llm.request",
+                &json!({}),
+                tomorrow
+            )
+            .is_err()
+        );
+    }
+}
@@ -0,0 +1,125 @@
+//! Reading frames and taking snapshots with `ffmpeg`.
+//!
+//! For motion detection `ffmpeg` decodes the camera's stream and writes
+//! small grayscale frames (`--analysis-width` by `--analysis-height`, one
+//! byte per pixel) at `--fps` to its standard output, so the source never
+//! handles the full-size video. Snapshots are taken with a second, short
+//! `ffmpeg` run that saves one full-size frame as a JPEG.
+//!
+//! RTSP streams are read over TCP, which survives lossy networks better
+//! than the UDP default; other URLs (HTTP MJPEG streams, files) are passed
+//! to `ffmpeg` as they are.
+
+use std::path::Path;
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::io::AsyncReadExt;
+use tokio::process::{Child, ChildStdout, Command};
+
+/// The size and rate of analysis frames.
+#[derive(Debug, Clone, Copy)]
+pub struct FrameFormat {
+    pub width: u32,
+    pub height: u32,
+    pub fps: f64,
+}
+
+impl FrameFormat {
+    /// Bytes in one frame.
+    pub fn frame_size(&self) -> usize {
+        self.width as usize * self.height as usize
+    }
+}
+
+/// Arguments reading `url`, quietly.
+fn input(url: &str) -> Vec<String> {
+    let mut args = vec![
+        "-nostdin".to_string(),
+        "-loglevel".to_string(),
+        "error".to_string(),
+    ];
+    if url.starts_with("rtsp://") || url.starts_with("rtsps://") {
+        args.extend(["-rtsp_transport".to_string(), "tcp".to_string()]);
+    }
+    args.extend(["-i".to_string(), url.to_string()]);
+    args
+}
+
+/// A running `ffmpeg` decoding a camera's stream.
+pub struct Frames {
+    // Killed when dropped
+    _child: Child,
+    stdout: ChildStdout,
+    frame: Vec<u8>,
+}
+
+impl Frames {
+    pub fn spawn(ffmpeg: &str, url: &str, format: FrameFormat) -> Result<Self, String> {
+        let filter = format!(
+            "fps={},scale={}:{}",
+            format.fps, format.width, format.height
+        );
+        let mut child = Command::new(ffmpeg)
+            .args(input(url))
+            .args([
+                "-an", "-vf", &filter, "-pix_fmt", "gray", "-f", "rawvideo", "pipe:1",
+            ])
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::inherit())
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(|e| format!("{ffmpeg}: {e}"))?;
+        let stdout = child
+            .stdout
+            .take()
+            .ok_or_else(|| format!("{ffmpeg}: no output"))?;
+        Ok(Self {
+            _child: child,
+            stdout,
+            frame: vec![0; format.frame_size()],
+        })
+    }
+
+    /// The next frame; an error when the stream ends.
+    pub async fn next(&mut self) -> Result<&[u8], String> {
+        self.stdout
+            .read_exact(&mut self.frame)
+            .await
+            .map_err(|e| match e.kind() {
+                std::io::ErrorKind::UnexpectedEof => "stream ended".to_string(),
+                _ => e.to_string(),
+            })?;
+        Ok(&self.frame)
+    }
+}
+
+/// Save one frame of `url` to `path` as a JPEG.
+pub async fn snapshot(
+    ffmpeg: &str,
+    url: &str,
+    path: &Path,
+    timeout: Duration,
+) -> Result<(), String> {
+    let output = Command::new(ffmpeg)
+        .args(input(url))
+        .args(["-an", "-frames:v", "1", "-q:v", "2", "-y"])
+        .arg(path)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .output();
+    let output = tokio::time::timeout(timeout, output)
+        .await
+        .map_err(|_| format!("snapshot timed out after {}ms", timeout.as_millis()))?
+        .map_err(|e| format!("{ffmpeg}: {e}"))?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(format!("snapshot failed: {}", stderr.trim()));
+    }
+    if !path.exists() {
+        return Err(format!("snapshot failed: {} not written", path.display()));
+    }
+    Ok(())
+}
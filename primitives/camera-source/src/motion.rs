@@ -0,0 +1,105 @@
+//! Motion detection by frame differencing.
+//!
+//! Frames are small grayscale images (one byte per pixel). Each is
+//! compared with a background that slowly follows the scene, so gradual
+//! changes such as daylight fading are absorbed while something moving
+//! stands out. A pixel has changed when it differs from the background by
+//! more than the pixel threshold; a frame shows motion when the changed
+//! share of its pixels is at least the minimum area. A frame where nearly
+//! everything changed is taken as a lighting change (an infrared switch,
+//! a light turned on) rather than motion, and becomes the new background.
+
+/// How far the background moves towards each frame, out of 256.
+const BACKGROUND_RATE: u16 = 32;
+
+/// Tuning for a [`Detector`].
+#[derive(Debug, Clone, Copy)]
+pub struct Sensitivity {
+    /// Difference from the background, 0-255, at which a pixel has changed.
+    pub pixel_threshold: u8,
+    /// Share of changed pixels, 0-1, that counts as motion.
+    pub min_area: f64,
+    /// Share of changed pixels, 0-1, above which the frame is taken as a
+    /// lighting change.
+    pub max_area: f64,
+}
+
+/// Compares each frame of one camera with its background.
+#[derive(Debug)]
+pub struct Detector {
+    sensitivity: Sensitivity,
+    background: Vec<u8>,
+}
+
+impl Detector {
+    pub fn new(sensitivity: Sensitivity) -> Self {
+        Self {
+            sensitivity,
+            background: Vec::new(),
+        }
+    }
+
+    /// The share of pixels that changed if `frame` shows motion. The first
+    /// frame, and any of a different size, only sets the background.
+    pub fn feed(&mut self, frame: &[u8]) -> Option<f64> {
+        if self.background.len() != frame.len() || frame.is_empty() {
+            self.background = frame.to_vec();
+            return None;
+        }
+        let threshold = self.sensitivity.pixel_threshold;
+        let changed = frame
+            .iter()
+            .zip(&self.background)
+            .filter(|(pixel, background)| pixel.abs_diff(**background) > threshold)
+            .count();
+        let area = changed as f64 / frame.len() as f64;
+        if area > self.sensitivity.max_area {
+            self.background = frame.to_vec();
+            return None;
+        }
+        for (background, pixel) in self.background.iter_mut().zip(frame) {
+            let moved = (u16::from(*background) * (256 - BACKGROUND_RATE)
+                + u16::from(*pixel) * BACKGROUND_RATE
+                + 128)
+                / 256;
+            *background = moved as u8;
+        }
+        (area >= self.sensitivity.min_area).then_some(area)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn moving_objects_are_motion_and_lighting_changes_are_not() {
+        let mut detector = Detector::new(Sensitivity {
+            pixel_threshold: 25,
+            min_area: 0.05,
+            max_area: 0.9,
+        });
+        let still = vec![100u8; 100];
+        assert_eq!(detector.feed(&still), None);
+        assert_eq!(detector.feed(&still), None);
+
+        // A small flicker stays under the pixel threshold
+        let flicker: Vec<u8> = still.iter().map(|p| p + 10).collect();
+        assert_eq!(detector.feed(&flicker), None);
+
+        // Something covering a tenth of the frame
+        let mut object = still.clone();
+        object[..10].fill(200);
+        assert_eq!(detector.feed(&object), Some(0.1));
+
+        // Too little of the frame
+        let mut speck = still.clone();
+        speck[..2].fill(200);
+        assert_eq!(detector.feed(&speck), None);
+
+        // The lights come on
+        let bright = vec![220u8; 100];
+        assert_eq!(detector.feed(&bright), None);
+        assert_eq!(detector.feed(&bright), None);
+    }
+}
@@ -0,0 +1,147 @@
+//! Field corruption.
+//!
+//! A corrupted field is nulled, given another type, or garbled within its
+//! type — the ways a misbehaving producer usually breaks a contract.
+//! Fields are named with the dotted paths used elsewhere (`payload.` is
+//! optional); without any, one top-level field is picked at random.
+
+use crate::rng::Rng;
+use serde_json::Value;
+
+/// What was done to a field.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mutation {
+    /// Replaced with `null`.
+    Null,
+    /// Replaced with a value of another type: a string's length, a
+    /// number's digits as a string.
+    Retype,
+    /// Changed within its type: a string reversed, a number negated, a
+    /// boolean flipped.
+    Garble,
+}
+
+impl Mutation {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Null => "null",
+            Self::Retype => "retype",
+            Self::Garble => "garble",
+        }
+    }
+}
+
+const MUTATIONS: [Mutation; 3] = [Mutation::Null, Mutation::Retype, Mutation::Garble];
+
+fn lookup_mut<'a>(payload: &'a mut Value, path: &str) -> Option<&'a mut Value> {
+    let path = path.strip_prefix("payload").unwrap_or(path);
+    path.split('.')
+        .filter(|s| !s.is_empty())
+        .try_fold(payload, |value, segment| match value {
+            Value::Object(map) => map.get_mut(segment),
+            Value::Array(items) => segment.parse::<usize>().ok().and_then(|i| items.get_mut(i)),
+            _ => None,
+        })
+}
+
+fn apply(value: &mut Value, mutation: Mutation) {
+    *value = match (mutation, &*value) {
+        (Mutation::Null, _) => Value::Null,
+        (Mutation::Retype, Value::String(s)) => Value::from(s.chars().count()),
+        (Mutation::Retype, Value::Null) => Value::from(0),
+        (Mutation::Retype, Value::Object(_) | Value::Array(_)) => Value::from(value.to_string()),
+        (Mutation::Retype, other) => Value::from(other.to_string()),
+        (Mutation::Garble, Value::String(s)) => {
+            let reversed: String = s.chars().rev().collect();
+            // A palindrome (or an empty string) would survive reversal
+            Value::from(if reversed == *s {
+                format!("{s}\u{fffd}")
+            } else {
+                reversed
+            })
+        }
+        (Mutation::Garble, Value::Number(n)) => match (n.as_i64(), n.as_f64()) {
+            (Some(0), _) => Value::from(-1),
+            (Some(i), _) => Value::from(i.wrapping_neg()),
+            (None, Some(f)) => Value::from(-f),
+            (None, None) => Value::from(-1),
+        },
+        (Mutation::Garble, Value::Bool(b)) => Value::Bool(!b),
+        (Mutation::Garble, Value::Array(items)) => {
+            Value::Array(items.iter().rev().cloned().collect())
+        }
+        (Mutation::Garble, Value::Object(_)) => Value::Object(Default::default()),
+        (Mutation::Garble, Value::Null) => Value::from(""),
+    };
+}
+
+/// Corrupt one of `fields` in `payload` (or one of its top-level fields,
+/// when none are given). Returns the field and the mutation, or `None`
+/// when none of the fields is present.
+pub fn corrupt(
+    payload: &mut Value,
+    fields: &[String],
+    rng: &mut Rng,
+) -> Option<(String, Mutation)> {
+    let candidates: Vec<String> = if fields.is_empty() {
+        payload
+            .as_object()
+            .map(|map| map.keys().cloned().collect())
+            .unwrap_or_default()
+    } else {
+        fields
+            .iter()
+            .filter(|field| primitive_common::key::lookup(payload, field).is_some())
+            .cloned()
+            .collect()
+    };
+    let field = candidates.get(rng.below(candidates.len() as u64) as usize)?;
+    let mutation = MUTATIONS[rng.below(MUTATIONS.len() as u64) as usize];
+    apply(lookup_mut(payload, field)?, mutation);
+    Some((field.clone(), mutation))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn fields_are_nulled_retyped_or_garbled() {
+        let cases = [
+            (json!("abc"), [json!(null), json!(3), json!("cba")]),
+            (json!("aba"), [json!(null), json!(3), json!("aba\u{fffd}")]),
+            (json!(42), [json!(null), json!("42"), json!(-42)]),
+            (json!(1.5), [json!(null), json!("1.5"), json!(-1.5)]),
+            (json!(true), [json!(null), json!("true"), json!(false)]),
+            (json!([1, 2]), [json!(null), json!("[1,2]"), json!([2, 1])]),
+            (
+                json!({"a": 1}),
+                [json!(null), json!("{\"a\":1}"), json!({})],
+            ),
+        ];
+        for (value, expected) in cases {
+            for (mutation, expected) in MUTATIONS.into_iter().zip(expected) {
+                let mut corrupted = value.clone();
+                apply(&mut corrupted, mutation);
+                assert_eq!(corrupted, expected, "{value} {mutation:?}");
+            }
+        }
+
+        let mut rng = Rng::new(Some(1));
+        let fields = ["payload.order.total".to_string(), "missing".to_string()];
+        let mut payload = json!({"order": {"id": "o-1", "total": 20}});
+        let (field, mutation) =
+            corrupt(&mut payload, &fields, &mut rng).unwrap_or_else(|| panic!("not corrupted"));
+        assert_eq!(field, "payload.order.total");
+        assert_ne!(payload["order"]["total"], json!(20), "{mutation:?}");
+        assert_eq!(payload["order"]["id"], "o-1");
+
+        let mut payload = json!({"id": "o-1"});
+        assert_eq!(corrupt(&mut payload, &fields[1..], &mut rng), None);
+        assert_eq!(corrupt(&mut json!("text"), &[], &mut rng), None);
+        let (field, _) =
+            corrupt(&mut payload, &[], &mut rng).unwrap_or_else(|| panic!("not corrupted"));
+        assert_eq!(field, "id");
+    }
+}
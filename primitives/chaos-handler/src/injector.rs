@@ -0,0 +1,327 @@
+//! Fault injection.
+//!
+//! Each message takes independent draws against the configured
+//! probabilities: first whether it is dropped, then whether a field is
+//! corrupted, whether it is duplicated, and last whether it is delayed or
+//! held back until the next message has gone out (reordered). Every fault
+//! injected yields a report alongside the messages to publish.
+
+use crate::corrupt::corrupt;
+use crate::rng::Rng;
+use emergent_client::types::CausationId;
+use serde_json::{Value, json};
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// How often each fault is injected, with its settings.
+#[derive(Debug, Clone, Default)]
+pub struct Faults {
+    pub drop: f64,
+    pub delay: f64,
+    /// Delays are picked evenly from this range.
+    pub delay_range: (Duration, Duration),
+    pub duplicate: f64,
+    pub reorder: f64,
+    /// How long a held message waits for another to overtake it.
+    pub reorder_window: Duration,
+    pub corrupt: f64,
+    /// Fields to corrupt; any top-level field when empty.
+    pub corrupt_fields: Vec<String>,
+}
+
+/// A message to publish.
+#[derive(Clone)]
+pub struct Outgoing {
+    pub message_type: String,
+    pub payload: Value,
+    pub cause: CausationId,
+}
+
+/// What came of one incoming message.
+#[derive(Default)]
+pub struct Verdict {
+    /// Messages to publish now, in order.
+    pub publish: Vec<Outgoing>,
+    /// A report per fault injected.
+    pub faults: Vec<Value>,
+}
+
+pub struct Injector {
+    faults: Faults,
+    publish_as: String,
+    rng: Rng,
+    delayed: Vec<(Instant, Outgoing)>,
+    held: Option<(Instant, Vec<Outgoing>)>,
+}
+
+impl Injector {
+    pub fn new(faults: Faults, publish_as: &str, rng: Rng) -> Self {
+        Self {
+            faults,
+            publish_as: publish_as.to_string(),
+            rng,
+            delayed: Vec::new(),
+            held: None,
+        }
+    }
+
+    /// The type `message_type` is republished as.
+    pub fn output_type(&self, message_type: &str) -> String {
+        self.publish_as.replace("{type}", message_type)
+    }
+
+    /// Whether `message_type` is one of this handler's own outputs, which a
+    /// broad subscription would otherwise feed back in.
+    pub fn is_own(&self, message_type: &str) -> bool {
+        match self.publish_as.split_once("{type}") {
+            Some((prefix, suffix)) => {
+                message_type.len() > prefix.len() + suffix.len()
+                    && message_type.starts_with(prefix)
+                    && message_type.ends_with(suffix)
+            }
+            None => message_type == self.publish_as,
+        }
+    }
+
+    fn delay(&mut self) -> Duration {
+        let (min, max) = self.faults.delay_range;
+        let span = max.saturating_sub(min).as_millis() as u64;
+        min + Duration::from_millis(self.rng.below(span + 1))
+    }
+
+    /// Run `payload`, received as `message_type` in message `message_id`,
+    /// past the faults; what is published is caused by `cause`.
+    pub fn admit(
+        &mut self,
+        message_type: &str,
+        message_id: &str,
+        cause: CausationId,
+        mut payload: Value,
+        now: Instant,
+    ) -> Verdict {
+        let output_type = self.output_type(message_type);
+        let report = |fault: &str, detail: Value| {
+            let mut report = json!({
+                "fault": fault,
+                "message_type": message_type,
+                "message_id": message_id,
+                "published_as": output_type,
+            });
+            if let (Some(report), Value::Object(detail)) = (report.as_object_mut(), detail) {
+                report.extend(detail);
+            }
+            report
+        };
+        let mut verdict = Verdict::default();
+        // A held message goes out behind whatever arrives next
+        let held = self.held.take().map(|(_, held)| held);
+
+        if self.rng.chance(self.faults.drop) {
+            verdict.faults.push(report("drop", json!({})));
+        } else {
+            if self.rng.chance(self.faults.corrupt)
+                && let Some((field, mutation)) =
+                    corrupt(&mut payload, &self.faults.corrupt_fields, &mut self.rng)
+            {
+                verdict.faults.push(report(
+                    "corrupt",
+                    json!({"field": field, "mutation": mutation.as_str()}),
+                ));
+            }
+            let copies = if self.rng.chance(self.faults.duplicate) {
+                verdict
+                    .faults
+                    .push(report("duplicate", json!({"copies": 2})));
+                2
+            } else {
+                1
+            };
+            let outgoing = vec![
+                Outgoing {
+                    message_type: output_type.clone(),
+                    payload,
+                    cause,
+                };
+                copies
+            ];
+            if self.rng.chance(self.faults.delay) {
+                let delay = self.delay();
+                verdict.faults.push(report(
+                    "delay",
+                    json!({"delay_ms": delay.as_millis() as u64}),
+                ));
+                self.delayed
+                    .extend(outgoing.into_iter().map(|out| (now + delay, out)));
+            } else if held.is_none() && self.rng.chance(self.faults.reorder) {
+                verdict.faults.push(report("reorder", json!({})));
+                self.held = Some((now + self.faults.reorder_window, outgoing));
+            } else {
+                verdict.publish.extend(outgoing);
+            }
+        }
+        verdict.publish.extend(held.into_iter().flatten());
+        verdict
+    }
+
+    /// When the next delayed or held message is due.
+    pub fn next_due(&self) -> Option<Instant> {
+        self.delayed
+            .iter()
+            .map(|(at, _)| *at)
+            .chain(self.held.as_ref().map(|(at, _)| *at))
+            .min()
+    }
+
+    /// Take the messages due by `now`.
+    pub fn due(&mut self, now: Instant) -> Vec<Outgoing> {
+        let mut due = Vec::new();
+        if self.held.as_ref().is_some_and(|(at, _)| *at <= now)
+            && let Some((_, held)) = self.held.take()
+        {
+            due.extend(held);
+        }
+        let (mut ready, waiting): (Vec<_>, Vec<_>) = std::mem::take(&mut self.delayed)
+            .into_iter()
+            .partition(|(at, _)| *at <= now);
+        self.delayed = waiting;
+        ready.sort_by_key(|(at, _)| *at);
+        due.extend(ready.into_iter().map(|(_, out)| out));
+        due
+    }
+
+    /// Take every message still waiting, for shutdown.
+    pub fn drain(&mut self) -> Vec<Outgoing> {
+        let mut all: Vec<Outgoing> = self
+            .held
+            .take()
+            .into_iter()
+            .flat_map(|(_, held)| held)
+            .collect();
+        self.delayed.sort_by_key(|(at, _)| *at);
+        all.extend(self.delayed.drain(..).map(|(_, out)| out));
+        all
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use emergent_client::EmergentMessage;
+
+    fn admit(injector: &mut Injector, message_type: &str, payload: Value, now: Instant) -> Verdict {
+        let msg = EmergentMessage::new(message_type);
+        let id = msg.id().to_string();
+        injector.admit(message_type, &id, CausationId::from(msg.id()), payload, now)
+    }
+
+    fn types(outgoing: &[Outgoing]) -> Vec<(&str, &Value)> {
+        outgoing
+            .iter()
+            .map(|out| (out.message_type.as_str(), &out.payload))
+            .collect()
+    }
+
+    #[test]
+    fn messages_pass_through_unless_faults_are_drawn() {
+        let mut injector = Injector::new(Faults::default(), "chaos.{type}", Rng::new(Some(1)));
+        let now = Instant::now();
+        let verdict = admit(&mut injector, "order.created", json!({"id": 1}), now);
+        assert_eq!(
+            types(&verdict.publish),
+            [("chaos.order.created", &json!({"id": 1}))]
+        );
+        assert!(verdict.faults.is_empty());
+        assert_eq!(injector.next_due(), None);
+
+        assert!(injector.is_own("chaos.order.created"));
+        assert!(!injector.is_own("order.created"));
+        assert!(!injector.is_own("chaos."));
+        let suffixed = Injector::new(Faults::default(), "{type}.chaos", Rng::new(None));
+        assert!(suffixed.is_own("order.created.chaos"));
+        assert!(!suffixed.is_own("chaos.order.created"));
+
+        let faults = Faults {
+            drop: 1.0,
+            ..Faults::default()
+        };
+        let mut injector = Injector::new(faults, "chaos.{type}", Rng::new(Some(1)));
+        let verdict = admit(&mut injector, "order.created", json!({"id": 1}), now);
+        assert!(verdict.publish.is_empty());
+        let mut fault = verdict.faults[0].clone();
+        assert!(fault["message_id"].is_string());
+        fault
+            .as_object_mut()
+            .map(|fault| fault.remove("message_id"));
+        assert_eq!(
+            fault,
+            json!({
+                "fault": "drop",
+                "message_type": "order.created",
+                "published_as": "chaos.order.created",
+            })
+        );
+    }
+
+    #[test]
+    fn faults_corrupt_duplicate_delay_and_reorder() {
+        let faults = Faults {
+            corrupt: 1.0,
+            corrupt_fields: vec!["total".to_string()],
+            duplicate: 1.0,
+            delay: 1.0,
+            delay_range: (Duration::from_millis(100), Duration::from_millis(200)),
+            ..Faults::default()
+        };
+        let mut injector = Injector::new(faults, "chaos.{type}", Rng::new(Some(3)));
+        let now = Instant::now();
+        let verdict = admit(&mut injector, "order.created", json!({"total": 20}), now);
+        assert!(verdict.publish.is_empty());
+        let kinds: Vec<&str> = verdict
+            .faults
+            .iter()
+            .map(|fault| fault["fault"].as_str().unwrap_or_default())
+            .collect();
+        assert_eq!(kinds, ["corrupt", "duplicate", "delay"]);
+        assert_eq!(verdict.faults[0]["field"], "total");
+        let delay = verdict.faults[2]["delay_ms"].as_u64().unwrap_or_default();
+        assert!((100..=200).contains(&delay), "{delay}");
+
+        let due = injector
+            .next_due()
+            .unwrap_or_else(|| panic!("nothing delayed"));
+        assert_eq!(due, now + Duration::from_millis(delay));
+        assert!(injector.due(due - Duration::from_millis(1)).is_empty());
+        let released = injector.due(due);
+        assert_eq!(released.len(), 2);
+        assert_ne!(released[0].payload, json!({"total": 20}));
+        assert_eq!(injector.next_due(), None);
+
+        let faults = Faults {
+            reorder: 1.0,
+            reorder_window: Duration::from_secs(1),
+            ..Faults::default()
+        };
+        let mut injector = Injector::new(faults, "chaos.{type}", Rng::new(Some(3)));
+        let first = admit(&mut injector, "a", json!(1), now);
+        assert!(first.publish.is_empty());
+        assert_eq!(first.faults[0]["fault"], "reorder");
+        // The next message overtakes the held one, and is not held itself
+        let second = admit(&mut injector, "a", json!(2), now);
+        assert_eq!(
+            types(&second.publish),
+            [("chaos.a", &json!(2)), ("chaos.a", &json!(1))]
+        );
+        assert!(second.faults.is_empty());
+
+        // Without a next message, the window releases it
+        admit(&mut injector, "a", json!(3), now);
+        assert_eq!(injector.next_due(), Some(now + Duration::from_secs(1)));
+        assert_eq!(
+            types(&injector.due(now + Duration::from_secs(1))),
+            [("chaos.a", &json!(3))]
+        );
+
+        admit(&mut injector, "a", json!(4), now);
+        assert_eq!(types(&injector.drain()), [("chaos.a", &json!(4))]);
+    }
+}
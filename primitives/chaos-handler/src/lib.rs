@@ -0,0 +1,399 @@
+//! Chaos Handler
+//!
+//! A Handler that sits between topics and injects faults into the messages
+//! passing through, so a pipeline's resilience can be tested before
+//! production tests it instead.
+//!
+//! # Data Flow
+//!
+//! 1. Receive an event matching configured subscriptions
+//! 2. Draw against each fault's probability: drop it, corrupt a field,
+//!    duplicate it, delay it, or hold it back until the next message has
+//!    overtaken it
+//! 3. Republish what survives as `chaos.<type>` (see `--publish-as`)
+//! 4. Report every fault injected on `chaos.injected`
+//!
+//! Consumers under test subscribe to the republished types in place of the
+//! originals. Duplicates are new messages carrying the same payload and
+//! causation, as a retrying producer would send them.
+//!
+//! # Messages Published
+//!
+//! - The incoming type, renamed by `--publish-as` (default: `chaos.{type}`)
+//! - `chaos.injected` — one per fault, naming the fault and the message
+//!
+//! # Usage
+//!
+//! ```bash
+//! # Drop 5% of orders and delay 20% by 0.5-3s
+//! chaos-handler -s 'order.*' --drop 0.05 --delay 0.2 --delay-ms 500-3000
+//!
+//! # Break the total of one order in ten, replayably
+//! chaos-handler -s order.created --corrupt 0.1 --corrupt-field total --seed 42
+//! ```
+
+mod corrupt;
+mod injector;
+mod rng;
+
+use clap::Parser;
+use emergent_client::EmergentMessage;
+use emergent_client::types::CausationId;
+use injector::{Faults, Injector, Outgoing};
+use primitive_common::capabilities::{self, CapabilityArgs, Role, VERSION};
+use primitive_common::crypto::{KeyArgs, Keyring};
+use primitive_common::doctor::Report;
+use primitive_common::errors::{ERROR_EVENT_TYPE, ErrorArgs, ErrorCategory};
+use primitive_common::handler::{Bus, Flow, HandlerConfig, MessageHandler, run_handler};
+use primitive_common::payload::{self, PayloadArgs};
+use rng::Rng;
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// Chaos Handler — inject faults between topics.
+#[derive(Parser, Debug)]
+#[command(name = "chaos_handler", version = VERSION)]
+#[command(about = "Delay, duplicate, reorder, corrupt or drop messages between topics")]
+struct Args {
+    /// Message types to subscribe to.
+    #[arg(short, long = "subscribe", required = true)]
+    subscribe: Vec<String>,
+
+    /// Message type to republish as; `{type}` stands for the incoming type.
+    #[arg(
+        long,
+        env = "CHAOS_HANDLER_PUBLISH_AS",
+        default_value = "chaos.{type}",
+        value_parser = parse_publish_as
+    )]
+    publish_as: String,
+
+    /// Message type injected faults are reported as.
+    #[arg(
+        long,
+        env = "CHAOS_HANDLER_REPORT_AS",
+        default_value = "chaos.injected"
+    )]
+    report_as: String,
+
+    /// Probability (0-1) of dropping a message.
+    #[arg(long, env = "CHAOS_HANDLER_DROP", default_value = "0", value_parser = parse_probability)]
+    drop: f64,
+
+    /// Probability (0-1) of delaying a message.
+    #[arg(long, env = "CHAOS_HANDLER_DELAY", default_value = "0", value_parser = parse_probability)]
+    delay: f64,
+
+    /// Delay in milliseconds, fixed (`500`) or picked from a range
+    /// (`100-2000`).
+    #[arg(
+        long,
+        env = "CHAOS_HANDLER_DELAY_MS",
+        default_value = "100-1000",
+        value_parser = parse_range
+    )]
+    delay_ms: (u64, u64),
+
+    /// Probability (0-1) of publishing a message twice.
+    #[arg(
+        long,
+        env = "CHAOS_HANDLER_DUPLICATE",
+        default_value = "0",
+        value_parser = parse_probability
+    )]
+    duplicate: f64,
+
+    /// Probability (0-1) of holding a message back until the next one has
+    /// gone out.
+    #[arg(long, env = "CHAOS_HANDLER_REORDER", default_value = "0", value_parser = parse_probability)]
+    reorder: f64,
+
+    /// Milliseconds a held message waits for another to overtake it.
+    #[arg(long, env = "CHAOS_HANDLER_REORDER_WINDOW", default_value = "1000")]
+    reorder_window: u64,
+
+    /// Probability (0-1) of corrupting a field.
+    #[arg(long, env = "CHAOS_HANDLER_CORRUPT", default_value = "0", value_parser = parse_probability)]
+    corrupt: f64,
+
+    /// Payload fields to corrupt, as dotted paths (default: any top-level
+    /// field).
+    #[arg(
+        long = "corrupt-field",
+        env = "CHAOS_HANDLER_CORRUPT_FIELDS",
+        value_delimiter = ','
+    )]
+    corrupt_fields: Vec<String>,
+
+    /// Seed for the fault draws, to replay the same faults.
+    #[arg(long, env = "CHAOS_HANDLER_SEED")]
+    seed: Option<u64>,
+
+    #[command(flatten)]
+    payload: PayloadArgs,
+
+    /// Verify the configuration and exit without connecting to the engine.
+    #[arg(long)]
+    self_test: bool,
+
+    #[command(flatten)]
+    errors: ErrorArgs,
+
+    #[command(flatten)]
+    capabilities: CapabilityArgs,
+
+    #[command(flatten)]
+    keys: KeyArgs,
+}
+
+fn parse_publish_as(value: &str) -> Result<String, String> {
+    match value {
+        "" => Err("must not be empty".to_string()),
+        // Republishing under the same type would feed straight back in
+        "{type}" => Err("must differ from the incoming type".to_string()),
+        _ => Ok(value.to_string()),
+    }
+}
+
+fn parse_probability(value: &str) -> Result<f64, String> {
+    match value.parse::<f64>() {
+        Ok(p) if (0.0..=1.0).contains(&p) => Ok(p),
+        _ => Err(format!("'{value}': expected a probability from 0 to 1")),
+    }
+}
+
+fn parse_range(value: &str) -> Result<(u64, u64), String> {
+    let error = || format!("'{value}': expected MS or MIN-MAX");
+    let (min, max) = value.split_once('-').unwrap_or((value, value));
+    let min = min.trim().parse::<u64>().map_err(|_| error())?;
+    let max = max.trim().parse::<u64>().map_err(|_| error())?;
+    if min > max {
+        return Err(error());
+    }
+    Ok((min, max))
+}
+
+impl Args {
+    fn faults(&self) -> Faults {
+        Faults {
+            drop: self.drop,
+            delay: self.delay,
+            delay_range: (
+                Duration::from_millis(self.delay_ms.0),
+                Duration::from_millis(self.delay_ms.1),
+            ),
+            duplicate: self.duplicate,
+            reorder: self.reorder,
+            reorder_window: Duration::from_millis(self.reorder_window),
+            corrupt: self.corrupt,
+            corrupt_fields: self.corrupt_fields.clone(),
+        }
+    }
+
+    /// The faults configured, as `drop 5%, delay 20% (500-3000ms)`.
+    fn summary(&self) -> String {
+        let percent = |p: f64| format!("{}%", (p * 1000.0).round() / 10.0);
+        let (min, max) = self.delay_ms;
+        let faults: Vec<String> = [
+            ("drop", self.drop, String::new()),
+            ("delay", self.delay, format!(" ({min}-{max}ms)")),
+            ("duplicate", self.duplicate, String::new()),
+            (
+                "reorder",
+                self.reorder,
+                format!(" ({}ms)", self.reorder_window),
+            ),
+            ("corrupt", self.corrupt, String::new()),
+        ]
+        .into_iter()
+        .filter(|(_, p, _)| *p > 0.0)
+        .map(|(fault, p, detail)| format!("{fault} {}{detail}", percent(p)))
+        .collect();
+        if faults.is_empty() {
+            "no faults configured; messages pass through".to_string()
+        } else {
+            faults.join(", ")
+        }
+    }
+}
+
+/// Run the primitive with `args` as its command line (the first item is
+/// the program name, as with `std::env::args_os`).
+pub async fn run<I, T>(args: I) -> Result<(), Box<dyn std::error::Error>>
+where
+    I: IntoIterator<Item = T>,
+    T: Into<std::ffi::OsString> + Clone,
+{
+    let args = Args::parse_from(args);
+
+    // Get the handler name from environment (set by engine) or use default
+    let name = std::env::var("EMERGENT_NAME").unwrap_or_else(|_| "chaos_handler".to_string());
+
+    if args.self_test {
+        let mut report = Report::new(&name);
+        report.check("faults", Ok(args.summary()));
+        args.payload.self_test(&mut report);
+        args.keys.self_test(&mut report);
+        report.finish();
+    }
+
+    if let Err(e) = args.payload.encryption.validate() {
+        eprintln!("Error: {e}");
+        std::process::exit(1);
+    }
+    let keys = match args.keys.load() {
+        Ok(keys) => keys,
+        Err(e) => {
+            eprintln!("Error: failed to load identity file: {e}");
+            std::process::exit(1);
+        }
+    };
+
+    let topics_refs: Vec<&str> = args.subscribe.iter().map(String::as_str).collect();
+    let mut produces = vec![args.publish_as.as_str(), args.report_as.as_str()];
+    if args.errors.emit_errors {
+        produces.push(ERROR_EVENT_TYPE);
+    }
+    let descriptor =
+        args.capabilities
+            .describe(&name, Role::Handler, &topics_refs, &produces, &args);
+    if args.capabilities.capabilities {
+        capabilities::print(&descriptor);
+    }
+
+    let injector = Injector::new(args.faults(), &args.publish_as, Rng::new(args.seed));
+    eprintln!("chaos-handler: {}", args.summary());
+
+    let config = HandlerConfig {
+        name: &name,
+        subscribe: &args.subscribe,
+        announce: args.capabilities.announce.then_some(&descriptor),
+        emit_errors: args.errors.emit_errors,
+    };
+    let mut chaos = Chaos {
+        args: &args,
+        injector,
+        keys,
+    };
+    run_handler(config, &mut chaos).await
+}
+
+/// The injector and what it needs to read and write payloads.
+struct Chaos<'a> {
+    args: &'a Args,
+    injector: Injector,
+    keys: Option<Keyring>,
+}
+
+impl MessageHandler for Chaos<'_> {
+    type Wake = ();
+
+    /// Run one message past the injector, publishing what it lets through
+    /// and a report per fault.
+    async fn handle(&mut self, msg: &EmergentMessage, bus: &Bus) {
+        let args = self.args;
+        let message_type = msg.message_type.as_str();
+        if self.injector.is_own(message_type) || message_type == args.report_as {
+            return;
+        }
+        let input = match payload::decode(msg.payload(), self.keys.as_ref()).await {
+            Ok(p) => p.into_owned(),
+            Err(e) => {
+                let error = format!("failed to decode payload: {e}");
+                eprintln!("chaos-handler: {error}");
+                bus.report_error(msg, ErrorCategory::Parse, &error).await;
+                return;
+            }
+        };
+
+        let message_id = msg.id().to_string();
+        let verdict = self.injector.admit(
+            message_type,
+            &message_id,
+            CausationId::from(msg.id()),
+            input,
+            Instant::now(),
+        );
+        for fault in verdict.faults {
+            let report = EmergentMessage::new(&args.report_as)
+                .with_causation_id(msg.id())
+                .with_payload(fault);
+            bus.publish(report).await;
+        }
+        for outgoing in verdict.publish {
+            publish(bus, args, outgoing).await;
+        }
+    }
+
+    /// Sleep until the next delayed or held message is due.
+    async fn wake(&mut self) {
+        match self.injector.next_due() {
+            Some(at) => tokio::time::sleep_until(at).await,
+            None => std::future::pending().await,
+        }
+    }
+
+    async fn woken(&mut self, (): (), bus: &Bus) -> Flow {
+        for outgoing in self.injector.due(Instant::now()) {
+            publish(bus, self.args, outgoing).await;
+        }
+        Flow::Continue
+    }
+
+    /// Delayed and held messages are late, not lost.
+    async fn finish(&mut self, bus: &Bus) {
+        for outgoing in self.injector.drain() {
+            publish(bus, self.args, outgoing).await;
+        }
+    }
+}
+
+async fn publish(bus: &Bus, args: &Args, outgoing: Outgoing) {
+    let payload =
+        match payload::encode(&outgoing.message_type, outgoing.payload, &args.payload).await {
+            Ok(p) => p,
+            Err(e) => {
+                eprintln!("chaos-handler: failed to encode output: {e}");
+                return;
+            }
+        };
+    let message = EmergentMessage::new(&outgoing.message_type)
+        .with_causation_id(outgoing.cause)
+        .with_payload(payload);
+    bus.publish(message).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fault_flags_are_validated_and_summarized() {
+        let args = Args::try_parse_from([
+            "chaos-handler",
+            "-s",
+            "order.*",
+            "--drop",
+            "0.05",
+            "--delay",
+            "0.2",
+            "--delay-ms",
+            "500-3000",
+            "--corrupt-field",
+            "total,payload.customer.id",
+        ])
+        .unwrap_or_else(|e| panic!("{e}"));
+        assert_eq!(args.summary(), "drop 5%, delay 20% (500-3000ms)");
+        assert_eq!(args.corrupt_fields, ["total", "payload.customer.id"]);
+        let faults = args.faults();
+        assert_eq!(faults.delay_range.1, Duration::from_secs(3));
+
+        assert_eq!(parse_range("250"), Ok((250, 250)));
+        assert!(parse_range("3000-500").is_err());
+        assert!(parse_probability("1.5").is_err());
+        assert!(parse_probability("-0.1").is_err());
+        assert!(
+            Args::try_parse_from(["chaos-handler", "-s", "a", "--publish-as", "{type}"]).is_err()
+        );
+    }
+}
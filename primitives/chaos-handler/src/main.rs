@@ -0,0 +1,6 @@
+//! `chaos-handler` binary; the primitive itself lives in the library.
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    chaos_handler::run(std::env::args_os()).await
+}
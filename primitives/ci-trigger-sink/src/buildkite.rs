@@ -0,0 +1,69 @@
+//! Buildkite builds.
+//!
+//! A build is created on the branch and commit with the parameters as
+//! build environment variables, and followed until it passes, fails, is
+//! canceled or is skipped. Builds waiting on a block step keep being
+//! polled.
+
+use crate::registry::Buildkite;
+use crate::run::{Conclusion, Phase, Run, Status, Trigger, id, missing, send};
+use primitive_common::errors::HandlerError;
+use reqwest::Client;
+use serde_json::json;
+
+fn builds_url(buildkite: &Buildkite) -> String {
+    format!(
+        "{}/v2/organizations/{}/pipelines/{}/builds",
+        buildkite.url.trim_end_matches('/'),
+        buildkite.organization,
+        buildkite.pipeline
+    )
+}
+
+pub async fn trigger(
+    client: &Client,
+    buildkite: &Buildkite,
+    trigger: &Trigger,
+) -> Result<Run, HandlerError> {
+    let target = format!(
+        "{}/{}: create build",
+        buildkite.organization, buildkite.pipeline
+    );
+    let request = client
+        .post(builds_url(buildkite))
+        .bearer_auth(&buildkite.token)
+        .json(&json!({
+            "commit": trigger.commit,
+            "branch": trigger.reference,
+            "message": trigger.message,
+            "env": trigger.params,
+        }));
+    let response = send(request, &target).await?;
+    let number = id(&response.body["number"]).ok_or_else(|| missing(&target, "number"))?;
+    Ok(Run {
+        url: response.body["web_url"].as_str().map(str::to_string),
+        poll: format!("{}/{number}", builds_url(buildkite)),
+        id: number,
+    })
+}
+
+pub async fn poll(
+    client: &Client,
+    buildkite: &Buildkite,
+    run: &Run,
+) -> Result<Status, HandlerError> {
+    let target = format!("{} build {}", buildkite.pipeline, run.id);
+    let request = client.get(&run.poll).bearer_auth(&buildkite.token);
+    let response = send(request, &target).await?;
+    let state = response.body["state"]
+        .as_str()
+        .ok_or_else(|| missing(&target, "state"))?;
+    let phase = match state {
+        "passed" => Phase::Finished(Conclusion::Success),
+        "failed" => Phase::Finished(Conclusion::Failed),
+        "canceled" | "skipped" | "not_run" => Phase::Finished(Conclusion::Canceled),
+        "scheduled" | "creating" => Phase::Queued,
+        _ => Phase::Running,
+    };
+    Ok(Status::new(phase, state))
+}
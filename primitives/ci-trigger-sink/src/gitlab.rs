@@ -0,0 +1,64 @@
+//! GitLab pipelines.
+//!
+//! A pipeline is created on the ref with the parameters as pipeline
+//! variables, and followed until it succeeds, fails, is canceled or is
+//! skipped. Pipelines blocked on a manual job keep being polled.
+
+use crate::registry::Gitlab;
+use crate::run::{Conclusion, Phase, Run, Status, Trigger, id, missing, send};
+use primitive_common::errors::HandlerError;
+use reqwest::Client;
+use serde_json::{Value, json};
+
+/// The project API base, with a `group/project` path encoded as one segment.
+fn project_url(gitlab: &Gitlab) -> String {
+    format!(
+        "{}/api/v4/projects/{}",
+        gitlab.url.trim_end_matches('/'),
+        gitlab.project.replace('/', "%2F")
+    )
+}
+
+pub async fn trigger(
+    client: &Client,
+    gitlab: &Gitlab,
+    trigger: &Trigger,
+) -> Result<Run, HandlerError> {
+    let variables: Vec<Value> = trigger
+        .params
+        .iter()
+        .map(|(key, value)| json!({"key": key, "value": value}))
+        .collect();
+    let target = format!("{}@{}: create pipeline", gitlab.project, trigger.reference);
+    let request = client
+        .post(format!("{}/pipeline", project_url(gitlab)))
+        .header("PRIVATE-TOKEN", &gitlab.token)
+        .json(&json!({"ref": trigger.reference, "variables": variables}));
+    let response = send(request, &target).await?;
+    let pipeline = id(&response.body["id"]).ok_or_else(|| missing(&target, "id"))?;
+    Ok(Run {
+        url: response.body["web_url"].as_str().map(str::to_string),
+        poll: format!("{}/pipelines/{pipeline}", project_url(gitlab)),
+        id: pipeline,
+    })
+}
+
+pub async fn poll(client: &Client, gitlab: &Gitlab, run: &Run) -> Result<Status, HandlerError> {
+    let target = format!("{} pipeline {}", gitlab.project, run.id);
+    let request = client.get(&run.poll).header("PRIVATE-TOKEN", &gitlab.token);
+    let response = send(request, &target).await?;
+    let state = response.body["status"]
+        .as_str()
+        .ok_or_else(|| missing(&target, "status"))?;
+    Ok(Status::new(phase(state), state))
+}
+
+fn phase(state: &str) -> Phase {
+    match state {
+        "success" => Phase::Finished(Conclusion::Success),
+        "failed" => Phase::Finished(Conclusion::Failed),
+        "canceled" | "skipped" => Phase::Finished(Conclusion::Canceled),
+        "created" | "waiting_for_resource" | "preparing" | "pending" | "scheduled" => Phase::Queued,
+        _ => Phase::Running,
+    }
+}
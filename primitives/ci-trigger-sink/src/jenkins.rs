@@ -0,0 +1,127 @@
+//! Jenkins jobs.
+//!
+//! A build is requested through `buildWithParameters` (or `build`, for a
+//! job without parameters) with the user's API token, which needs no CSRF
+//! crumb. Jenkins answers with a queue item; once an executor picks it up
+//! the item names the build, which is then followed until it has a result.
+
+use crate::registry::Jenkins;
+use crate::run::{Conclusion, Phase, Run, Status, Trigger, id, missing, send};
+use primitive_common::errors::HandlerError;
+use reqwest::{Client, RequestBuilder};
+
+/// The job URL, with folders as nested `job/` segments.
+fn job_url(jenkins: &Jenkins) -> String {
+    let path: String = jenkins
+        .job
+        .split('/')
+        .filter(|segment| !segment.is_empty())
+        .map(|segment| format!("/job/{segment}"))
+        .collect();
+    format!("{}{path}", jenkins.url.trim_end_matches('/'))
+}
+
+fn authenticate(request: RequestBuilder, jenkins: &Jenkins) -> RequestBuilder {
+    match &jenkins.user {
+        Some(user) => request.basic_auth(user, jenkins.token.as_ref()),
+        None => request,
+    }
+}
+
+/// The JSON API of a Jenkins page.
+fn api(url: &str) -> String {
+    format!("{}/api/json", url.trim_end_matches('/'))
+}
+
+pub async fn trigger(
+    client: &Client,
+    jenkins: &Jenkins,
+    trigger: &Trigger,
+) -> Result<Run, HandlerError> {
+    let target = format!("{}: build", jenkins.job);
+    let request = if trigger.params.is_empty() {
+        client.post(format!("{}/build", job_url(jenkins)))
+    } else {
+        client
+            .post(format!("{}/buildWithParameters", job_url(jenkins)))
+            .form(&trigger.params)
+    };
+    let response = send(authenticate(request, jenkins), &target).await?;
+    let queue_item = response
+        .location
+        .ok_or_else(|| missing(&target, "Location"))?;
+    let number = queue_item
+        .trim_end_matches('/')
+        .rsplit('/')
+        .next()
+        .unwrap_or_default();
+    Ok(Run {
+        id: format!("queue/{number}"),
+        url: None,
+        poll: api(&queue_item),
+    })
+}
+
+/// Poll `run`; once the queue item has become a build, `run` is updated
+/// to follow the build instead.
+pub async fn poll(
+    client: &Client,
+    jenkins: &Jenkins,
+    run: &mut Run,
+) -> Result<Status, HandlerError> {
+    let target = format!("{} {}", jenkins.job, run.id);
+    if run.url.is_none() {
+        let request = authenticate(client.get(&run.poll), jenkins);
+        let item = send(request, &target).await?.body;
+        if item["cancelled"].as_bool() == Some(true) {
+            return Ok(Status::new(
+                Phase::Finished(Conclusion::Canceled),
+                "cancelled",
+            ));
+        }
+        let Some(build) = item["executable"]["url"].as_str() else {
+            return Ok(Status::new(Phase::Queued, "queued"));
+        };
+        run.id = id(&item["executable"]["number"]).unwrap_or_else(|| build.to_string());
+        run.url = Some(build.to_string());
+        run.poll = api(build);
+    }
+
+    let request = authenticate(client.get(&run.poll), jenkins);
+    let build = send(request, &target).await?.body;
+    if build["building"].as_bool() == Some(true) {
+        return Ok(Status::new(Phase::Running, "building"));
+    }
+    let Some(result) = build["result"].as_str() else {
+        return Ok(Status::new(Phase::Running, "building"));
+    };
+    let phase = match result {
+        "SUCCESS" => Phase::Finished(Conclusion::Success),
+        "ABORTED" | "NOT_BUILT" => Phase::Finished(Conclusion::Canceled),
+        _ => Phase::Finished(Conclusion::Failed),
+    };
+    Ok(Status::new(phase, result))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn folders_become_nested_job_segments() {
+        let jenkins = Jenkins {
+            url: "https://jenkins.example.com/".to_string(),
+            job: "deploy/prod".to_string(),
+            user: None,
+            token: None,
+        };
+        assert_eq!(
+            job_url(&jenkins),
+            "https://jenkins.example.com/job/deploy/job/prod"
+        );
+        assert_eq!(
+            api("https://jenkins.example.com/queue/item/42/"),
+            "https://jenkins.example.com/queue/item/42/api/json"
+        );
+    }
+}
@@ -0,0 +1,738 @@
+//! CI Trigger Sink - Start Terraform, GitLab, Jenkins and Buildkite Runs
+//!
+//! A Sink that starts runs on the CI targets in `--config` (see
+//! [`registry`]): Terraform Cloud workspaces ([`terraform`]), GitLab
+//! pipelines ([`gitlab`]), Jenkins jobs ([`jenkins`]) and Buildkite
+//! pipelines ([`buildkite`]). A payload picks its target by name:
+//!
+//! ```json
+//! {"target": "deploy", "version": "1.4.2"}
+//! ```
+//!
+//! and payloads naming none trigger every target whose `on` matches the
+//! message type. Parameters are mapped from the payload by the target's
+//! templates.
+//!
+//! A trigger identical (same target and parameters) to a run still queued
+//! is not sent again: the event is folded into that run. Each run is polled
+//! until it finishes, which is published as `ci.run.finished` with the
+//! `status` (`success`, `failed`, `canceled` or `timed_out`), the
+//! provider's own `state`, and the `message_id`s of the events folded in
+//! as `deduplicated`.
+//!
+//! # Examples
+//!
+//! ```bash
+//! ci-trigger-sink -s release.tagged -s infra.changed --config /etc/emergent/ci.json
+//! ```
+
+pub mod buildkite;
+pub mod gitlab;
+pub mod jenkins;
+pub mod registry;
+pub mod run;
+pub mod terraform;
+
+use clap::Parser;
+use emergent_client::EmergentMessage;
+use futures::future::join_all;
+use primitive_common::capabilities::VERSION;
+use primitive_common::doctor::Report;
+use primitive_common::errors::{ErrorCategory, HandlerError};
+use primitive_common::reload::HotConfig;
+use primitive_common::template::render_strict;
+use primitive_common::{Publisher, SinkArgs, SinkConfig, SinkContext, SinkHandler, run_sink};
+use registry::{Provider, Target};
+use reqwest::Client;
+use run::{Phase, Run, Status, Trigger};
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex, OnceLock, PoisonError};
+use std::time::{Duration, Instant};
+
+pub const FINISHED_EVENT_TYPE: &str = "ci.run.finished";
+
+/// CI Trigger Sink — start Terraform, GitLab, Jenkins and Buildkite runs.
+#[derive(Parser, Debug)]
+#[command(name = "ci_trigger_sink", version = VERSION)]
+#[command(
+    about = "Trigger Terraform Cloud runs, GitLab pipelines, Jenkins jobs and Buildkite builds"
+)]
+struct Args {
+    /// Message types to subscribe to.
+    #[arg(short, long = "subscribe", required = true)]
+    subscribe: Vec<String>,
+
+    /// Payload field naming the target.
+    #[arg(long, env = "CI_TRIGGER_SINK_TARGET_FIELD", default_value = "target")]
+    target_field: String,
+
+    /// Per-request timeout in milliseconds.
+    #[arg(short, long, env = "CI_TRIGGER_SINK_TIMEOUT", default_value = "30000")]
+    timeout: u64,
+
+    /// Milliseconds between status polls of a run.
+    #[arg(long, env = "CI_TRIGGER_SINK_POLL_INTERVAL", default_value = "15000")]
+    poll_interval: u64,
+
+    /// Milliseconds after which a run still going is reported `timed_out`.
+    #[arg(long, env = "CI_TRIGGER_SINK_POLL_TIMEOUT", default_value = "21600000")]
+    poll_timeout: u64,
+
+    #[command(flatten)]
+    sink: SinkArgs,
+}
+
+/// Settings that can be swapped on SIGHUP.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Settings {
+    targets: BTreeMap<String, Target>,
+}
+
+/// Fill in `target`'s templates from `payload`; a placeholder the
+/// payload lacks is an error.
+fn fill(target: &Target, payload: &Value) -> Result<Trigger, String> {
+    let mut trigger = Trigger {
+        message: match &target.message {
+            Some(message) => render_strict(message, payload)?,
+            None => "Triggered by emergent".to_string(),
+        },
+        ..Default::default()
+    };
+    for (param, template) in &target.params {
+        trigger
+            .params
+            .insert(param.clone(), render_strict(template, payload)?);
+    }
+    match &target.provider {
+        Provider::Gitlab(gitlab) => trigger.reference = render_strict(&gitlab.reference, payload)?,
+        Provider::Buildkite(buildkite) => {
+            trigger.reference = render_strict(&buildkite.branch, payload)?;
+            trigger.commit = render_strict(&buildkite.commit, payload)?;
+        }
+        Provider::Terraform(_) | Provider::Jenkins(_) => {}
+    }
+    Ok(trigger)
+}
+
+/// What a run is deduplicated on.
+fn dedup_key(name: &str, trigger: &Trigger) -> String {
+    json!([name, trigger.reference, trigger.commit, trigger.params]).to_string()
+}
+
+/// A run still queued, and the events folded into it.
+struct Queued {
+    run_id: String,
+    deduplicated: Vec<String>,
+}
+
+/// A run being followed.
+struct Follow {
+    name: String,
+    target: Target,
+    key: String,
+    run: Run,
+    message_id: String,
+    message_type: String,
+    started: Instant,
+}
+
+/// State shared with the pollers.
+struct Ci {
+    client: Client,
+    poll_interval: Duration,
+    poll_timeout: Duration,
+    queued: Mutex<HashMap<String, Queued>>,
+    publisher: OnceLock<Publisher>,
+}
+
+impl Ci {
+    async fn trigger(&self, target: &Target, trigger: &Trigger) -> Result<Run, HandlerError> {
+        match &target.provider {
+            Provider::Terraform(terraform) => {
+                terraform::trigger(&self.client, terraform, trigger).await
+            }
+            Provider::Gitlab(gitlab) => gitlab::trigger(&self.client, gitlab, trigger).await,
+            Provider::Jenkins(jenkins) => jenkins::trigger(&self.client, jenkins, trigger).await,
+            Provider::Buildkite(buildkite) => {
+                buildkite::trigger(&self.client, buildkite, trigger).await
+            }
+        }
+    }
+
+    async fn poll(&self, target: &Target, run: &mut Run) -> Result<Status, HandlerError> {
+        match &target.provider {
+            Provider::Terraform(terraform) => terraform::poll(&self.client, terraform, run).await,
+            Provider::Gitlab(gitlab) => gitlab::poll(&self.client, gitlab, run).await,
+            Provider::Jenkins(jenkins) => jenkins::poll(&self.client, jenkins, run).await,
+            Provider::Buildkite(buildkite) => buildkite::poll(&self.client, buildkite, run).await,
+        }
+    }
+
+    /// Fold `message_id` into the queued run under `key`, returning the
+    /// run's id, if there is one.
+    fn deduplicate(&self, key: &str, message_id: &str) -> Option<String> {
+        let mut queued = self.queued.lock().unwrap_or_else(PoisonError::into_inner);
+        let entry = queued.get_mut(key)?;
+        entry.deduplicated.push(message_id.to_string());
+        Some(entry.run_id.clone())
+    }
+
+    /// Stop deduplicating into run `run_id`, returning the events folded in.
+    fn dequeue(&self, key: &str, run_id: &str) -> Vec<String> {
+        let mut queued = self.queued.lock().unwrap_or_else(PoisonError::into_inner);
+        match queued.get(key) {
+            Some(entry) if entry.run_id == run_id => queued
+                .remove(key)
+                .map(|entry| entry.deduplicated)
+                .unwrap_or_default(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// Poll `follow.run` until it finishes or `--poll-timeout` passes, then
+    /// publish how it ended.
+    async fn follow(&self, mut follow: Follow) {
+        let run_id = follow.run.id.clone();
+        let deadline = follow.started + self.poll_timeout;
+        let mut deduplicated = Vec::new();
+        let mut state = String::new();
+        let mut error: Option<String>;
+        let conclusion = loop {
+            tokio::time::sleep(self.poll_interval).await;
+            match self.poll(&follow.target, &mut follow.run).await {
+                Ok(status) => {
+                    error = None;
+                    state = status.state;
+                    if status.phase != Phase::Queued {
+                        deduplicated.extend(self.dequeue(&follow.key, &run_id));
+                    }
+                    if let Phase::Finished(conclusion) = status.phase {
+                        break Some(conclusion);
+                    }
+                }
+                Err(e) => {
+                    eprintln!("{} run {}: {}", follow.name, follow.run.id, e.message);
+                    error = Some(e.message);
+                }
+            }
+            if Instant::now() >= deadline {
+                break None;
+            }
+        };
+        deduplicated.extend(self.dequeue(&follow.key, &run_id));
+
+        let status = conclusion.map_or("timed_out", |c| c.as_str());
+        eprintln!("{} run {}: {status}", follow.name, follow.run.id);
+        let Some(publisher) = self.publisher.get() else {
+            return;
+        };
+        let payload = json!({
+            "target": follow.name,
+            "provider": follow.target.provider.as_str(),
+            "run_id": follow.run.id,
+            "url": follow.run.url,
+            "status": status,
+            "state": state,
+            "error": error,
+            "duration_ms": u64::try_from(follow.started.elapsed().as_millis()).unwrap_or(u64::MAX),
+            "deduplicated": deduplicated,
+            "message_id": follow.message_id,
+            "message_type": follow.message_type,
+        });
+        let message = EmergentMessage::new(FINISHED_EVENT_TYPE).with_payload(payload);
+        if let Err(e) = publisher.publish(message) {
+            eprintln!("Failed to publish {FINISHED_EVENT_TYPE}: {e}");
+        }
+    }
+}
+
+/// Triggers CI runs.
+struct CiTriggerSink {
+    settings: HotConfig<Settings>,
+    target_field: String,
+    ci: Arc<Ci>,
+}
+
+impl CiTriggerSink {
+    /// Trigger `target` unless an identical run is still queued, and follow
+    /// the run it starts.
+    async fn start(
+        &self,
+        msg: &EmergentMessage,
+        message_id: &str,
+        name: &str,
+        target: &Target,
+        trigger: Trigger,
+    ) -> Result<(), HandlerError> {
+        let key = dedup_key(name, &trigger);
+        if let Some(run_id) = self.ci.deduplicate(&key, message_id) {
+            eprintln!("{name}: run {run_id} is still queued; not triggering again");
+            return Ok(());
+        }
+
+        let run = self.ci.trigger(target, &trigger).await?;
+        eprintln!("{name}: triggered run {}", run.id);
+        self.ci
+            .queued
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(
+                key.clone(),
+                Queued {
+                    run_id: run.id.clone(),
+                    deduplicated: Vec::new(),
+                },
+            );
+        let follow = Follow {
+            name: name.to_string(),
+            target: target.clone(),
+            key,
+            run,
+            message_id: message_id.to_string(),
+            message_type: msg.message_type.as_str().to_string(),
+            started: Instant::now(),
+        };
+        let ci = Arc::clone(&self.ci);
+        tokio::spawn(async move { ci.follow(follow).await });
+        Ok(())
+    }
+}
+
+impl SinkHandler for CiTriggerSink {
+    async fn handle(
+        &self,
+        msg: &EmergentMessage,
+        ctx: &SinkContext<'_>,
+    ) -> Result<(), HandlerError> {
+        let payload = ctx.payload();
+        let message_type = msg.message_type.as_str();
+        let settings = self.settings.current();
+        let targets: Vec<(&String, &Target)> = match payload[&self.target_field].as_str() {
+            Some(name) => match settings.targets.get_key_value(name) {
+                Some(target) => vec![target],
+                None => {
+                    return Err(HandlerError::new(
+                        ErrorCategory::Rejected,
+                        format!("unknown target '{name}'"),
+                    ));
+                }
+            },
+            None => settings
+                .targets
+                .iter()
+                .filter(|(_, target)| target.matches(message_type))
+                .collect(),
+        };
+        if targets.is_empty() {
+            return Err(HandlerError::new(
+                ErrorCategory::Rejected,
+                format!("no target for '{message_type}'"),
+            ));
+        }
+        let triggers = targets
+            .iter()
+            .map(|(name, target)| {
+                fill(target, payload).map_err(|e| {
+                    HandlerError::new(ErrorCategory::Parse, format!("target '{name}': {e}"))
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        if ctx.is_dry_run() {
+            for ((name, target), trigger) in targets.iter().zip(&triggers) {
+                let detail = json!({
+                    "target": name,
+                    "provider": target.provider.as_str(),
+                    "ref": trigger.reference,
+                    "params": trigger.params,
+                    "message": trigger.message,
+                });
+                ctx.would_have("trigger", detail).await;
+            }
+            return Ok(());
+        }
+
+        let started = targets
+            .into_iter()
+            .zip(triggers)
+            .map(|((name, target), trigger)| {
+                self.start(msg, ctx.message_id(), name, target, trigger)
+            });
+        join_all(started).await.into_iter().collect()
+    }
+
+    fn self_test(&self, report: &mut Report) {
+        let settings = self.settings.current();
+        report.check(
+            "targets",
+            Ok(format!("{} configured", settings.targets.len())),
+        );
+    }
+
+    fn reload(&self) -> Result<Vec<String>, String> {
+        self.settings.reload()
+    }
+
+    fn publishes(&self) -> &'static [&'static str] {
+        &[FINISHED_EVENT_TYPE]
+    }
+
+    fn attach(&self, publisher: Publisher) {
+        let _ = self.ci.publisher.set(publisher);
+    }
+}
+
+/// Run the primitive with `args` as its command line (the first item is
+/// the program name, as with `std::env::args_os`).
+pub async fn run<I, T>(args: I) -> Result<(), Box<dyn std::error::Error>>
+where
+    I: IntoIterator<Item = T>,
+    T: Into<std::ffi::OsString> + Clone,
+{
+    let args = Args::parse_from(args);
+
+    let config = SinkConfig {
+        name: "ci_trigger_sink",
+        subscribe: &args.subscribe,
+        would_have_as: "ci.would_have",
+        dead_letter_as: "ci.dead_letter",
+        settings: &args,
+    };
+    let settings = match HotConfig::load(&args.sink.reload, Settings::default()) {
+        Ok(settings) => settings,
+        Err(e) => {
+            eprintln!("Error: invalid config: {e}");
+            std::process::exit(1);
+        }
+    };
+    let handler = CiTriggerSink {
+        settings,
+        target_field: args.target_field.clone(),
+        ci: Arc::new(Ci {
+            client: Client::builder()
+                .timeout(Duration::from_millis(args.timeout))
+                .user_agent("emergent-ci-trigger-sink")
+                .build()?,
+            poll_interval: Duration::from_millis(args.poll_interval),
+            poll_timeout: Duration::from_millis(args.poll_timeout),
+            queued: Mutex::new(HashMap::new()),
+            publisher: OnceLock::new(),
+        }),
+    };
+
+    run_sink(config, &args.sink, handler).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::extract::{Path, State};
+    use axum::http::StatusCode;
+    use axum::routing::{get, post};
+    use axum::{Json, Router};
+    use emergent_testkit::{SinkFixture, fixtures, spawn_sink};
+    use primitive_common::errors::ErrorArgs;
+    use primitive_common::reload::ReloadArgs;
+    use registry::Gitlab;
+    use tokio::sync::mpsc;
+
+    /// The pipeline status the fake GitLab reports, or `None` for a 502.
+    type Pipeline = Arc<Mutex<Option<&'static str>>>;
+
+    #[derive(Clone)]
+    struct FakeGitlab {
+        triggered: mpsc::UnboundedSender<(String, Value)>,
+        created: (StatusCode, &'static str),
+        pipeline: Pipeline,
+    }
+
+    async fn create(
+        State(gitlab): State<FakeGitlab>,
+        Path(project): Path<String>,
+        Json(body): Json<Value>,
+    ) -> (StatusCode, &'static str) {
+        let _ = gitlab.triggered.send((project, body));
+        gitlab.created
+    }
+
+    async fn status(
+        State(gitlab): State<FakeGitlab>,
+        Path((_, id)): Path<(String, u64)>,
+    ) -> (StatusCode, Json<Value>) {
+        match *gitlab
+            .pipeline
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+        {
+            Some(status) => (StatusCode::OK, Json(json!({"id": id, "status": status}))),
+            None => (
+                StatusCode::BAD_GATEWAY,
+                Json(json!({"message": "502 Bad Gateway"})),
+            ),
+        }
+    }
+
+    const CREATED: (StatusCode, &str) = (
+        StatusCode::CREATED,
+        r#"{"id": 7, "web_url": "https://gitlab.example.com/p/-/pipelines/7", "status": "created"}"#,
+    );
+
+    /// A fake GitLab answering pipeline creation with `created`; returns its
+    /// URL, the pipelines created and the status it reports for them.
+    async fn gitlab(
+        created: (StatusCode, &'static str),
+    ) -> (String, mpsc::UnboundedReceiver<(String, Value)>, Pipeline) {
+        let (triggered, rx) = mpsc::unbounded_channel();
+        let pipeline: Pipeline = Arc::new(Mutex::new(Some("pending")));
+        let app = Router::new()
+            .route("/api/v4/projects/{project}/pipeline", post(create))
+            .route("/api/v4/projects/{project}/pipelines/{id}", get(status))
+            .with_state(FakeGitlab {
+                triggered,
+                created,
+                pipeline: Arc::clone(&pipeline),
+            });
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap_or_else(|e| panic!("bind: {e}"));
+        let addr = listener
+            .local_addr()
+            .unwrap_or_else(|e| panic!("local_addr: {e}"));
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        (format!("http://{addr}"), rx, pipeline)
+    }
+
+    /// A sink whose `app` target creates pipelines on `url`.
+    fn ci_trigger_sink(url: &str, poll_timeout: Duration) -> CiTriggerSink {
+        let target = Target {
+            provider: Provider::Gitlab(Gitlab {
+                url: url.to_string(),
+                token: "glpat-test".to_string(),
+                project: "platform/app".to_string(),
+                reference: "{branch}".to_string(),
+            }),
+            on: vec!["release.*".to_string()],
+            params: BTreeMap::from([("VERSION".to_string(), "{version}".to_string())]),
+            message: None,
+        };
+        let settings = HotConfig::load(
+            &ReloadArgs::default(),
+            Settings {
+                targets: BTreeMap::from([("app".to_string(), target)]),
+            },
+        )
+        .unwrap_or_else(|e| panic!("load settings: {e}"));
+        CiTriggerSink {
+            settings,
+            target_field: "target".to_string(),
+            ci: Arc::new(Ci {
+                client: Client::new(),
+                poll_interval: Duration::from_millis(20),
+                poll_timeout,
+                queued: Mutex::new(HashMap::new()),
+                publisher: OnceLock::new(),
+            }),
+        }
+    }
+
+    fn failing() -> SinkArgs {
+        SinkArgs {
+            dead_letter: true,
+            errors: ErrorArgs { emit_errors: true },
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn queued_duplicates_are_folded_into_the_run() {
+        let (url, mut rx, pipeline) = gitlab(CREATED).await;
+        let (mut engine, run) = spawn_sink(
+            SinkFixture::new("ci_trigger_sink", "ci"),
+            SinkArgs {
+                dead_letter: true,
+                ..Default::default()
+            },
+            ci_trigger_sink(&url, Duration::from_secs(30)),
+        );
+
+        let release = json!({"branch": "main", "version": "1.4.2"});
+        let first = fixtures::message("release.tagged", release.clone());
+        let first_id = first.id().to_string();
+        engine.inject_message(first).await;
+        let (project, body) = rx.recv().await.unwrap_or_else(|| panic!("no trigger"));
+        assert_eq!(project, "platform/app");
+        assert_eq!(
+            body,
+            json!({"ref": "main", "variables": [{"key": "VERSION", "value": "1.4.2"}]})
+        );
+
+        let second = fixtures::message("release.tagged", release);
+        let second_id = second.id().to_string();
+        engine.inject_message(second).await;
+        // Handled after the duplicate, so its dead letter means the duplicate was seen
+        engine
+            .inject_message(fixtures::message(
+                "release.tagged",
+                json!({"target": "docs"}),
+            ))
+            .await;
+        let dead = engine.expect_published("ci.dead_letter").await;
+        assert_eq!(dead.payload()["error"], "unknown target 'docs'");
+
+        *pipeline.lock().unwrap_or_else(PoisonError::into_inner) = Some("success");
+        let finished = engine.expect_published(FINISHED_EVENT_TYPE).await;
+        assert_eq!(finished.payload()["target"], "app");
+        assert_eq!(finished.payload()["provider"], "gitlab");
+        assert_eq!(finished.payload()["run_id"], "7");
+        assert_eq!(finished.payload()["status"], "success");
+        assert_eq!(finished.payload()["message_id"], first_id.as_str());
+        assert_eq!(finished.payload()["deduplicated"], json!([second_id]));
+        assert!(rx.try_recv().is_err(), "the duplicate was triggered");
+
+        engine.close();
+        assert_eq!(run.await.ok(), Some(Ok(())));
+    }
+
+    #[tokio::test]
+    async fn refused_and_garbled_triggers_are_dead_lettered() {
+        for (created, category, expected) in [
+            (
+                (
+                    StatusCode::UNAUTHORIZED,
+                    r#"{"message": "401 Unauthorized"}"#,
+                ),
+                "rejected",
+                r#"platform/app@main: create pipeline: HTTP 401 Unauthorized: {"message": "401 Unauthorized"}"#,
+            ),
+            (
+                (StatusCode::CREATED, "<html>maintenance</html>"),
+                "parse",
+                "platform/app@main: create pipeline: expected value at line 1 column 1",
+            ),
+            (
+                (StatusCode::CREATED, r#"{"status": "created"}"#),
+                "parse",
+                "platform/app@main: create pipeline: response has no 'id'",
+            ),
+        ] {
+            let (url, _rx, _pipeline) = gitlab(created).await;
+            let (mut engine, run) = spawn_sink(
+                SinkFixture::new("ci_trigger_sink", "ci"),
+                failing(),
+                ci_trigger_sink(&url, Duration::from_secs(30)),
+            );
+
+            engine
+                .inject_message(fixtures::message(
+                    "release.tagged",
+                    json!({"branch": "main", "version": "1.4.2"}),
+                ))
+                .await;
+            let error = engine.expect_published("primitive.error").await;
+            assert_eq!(error.payload()["category"], category);
+            let dead = engine.expect_published("ci.dead_letter").await;
+            assert_eq!(dead.payload()["error"], expected);
+
+            engine.close();
+            assert_eq!(run.await.ok(), Some(Ok(())));
+        }
+    }
+
+    #[tokio::test]
+    async fn unfillable_and_unrouted_events_trigger_nothing() {
+        let (url, mut rx, _pipeline) = gitlab(CREATED).await;
+        let (mut engine, run) = spawn_sink(
+            SinkFixture::new("ci_trigger_sink", "ci"),
+            failing(),
+            ci_trigger_sink(&url, Duration::from_secs(30)),
+        );
+
+        for (message_type, payload, category, expected) in [
+            (
+                "release.tagged",
+                json!({"branch": "main"}),
+                "parse",
+                "target 'app': payload has no 'version'",
+            ),
+            (
+                "infra.changed",
+                json!({"branch": "main", "version": "1.4.2"}),
+                "rejected",
+                "no target for 'infra.changed'",
+            ),
+        ] {
+            engine
+                .inject_message(fixtures::message(message_type, payload))
+                .await;
+            let error = engine.expect_published("primitive.error").await;
+            assert_eq!(error.payload()["category"], category);
+            let dead = engine.expect_published("ci.dead_letter").await;
+            assert_eq!(dead.payload()["error"], expected);
+        }
+        assert!(rx.try_recv().is_err(), "a pipeline was created");
+
+        engine.close();
+        assert_eq!(run.await.ok(), Some(Ok(())));
+    }
+
+    #[tokio::test]
+    async fn runs_that_stop_answering_are_reported_timed_out() {
+        let (url, mut rx, pipeline) = gitlab(CREATED).await;
+        let (mut engine, run) = spawn_sink(
+            SinkFixture::new("ci_trigger_sink", "ci"),
+            SinkArgs::default(),
+            ci_trigger_sink(&url, Duration::from_millis(300)),
+        );
+
+        engine
+            .inject_message(fixtures::message(
+                "release.tagged",
+                json!({"branch": "main", "version": "1.4.2"}),
+            ))
+            .await;
+        rx.recv().await.unwrap_or_else(|| panic!("no trigger"));
+        // Polls fail from here on; the run is given up at the poll timeout
+        *pipeline.lock().unwrap_or_else(PoisonError::into_inner) = None;
+        let finished = engine.expect_published(FINISHED_EVENT_TYPE).await;
+        assert_eq!(finished.payload()["status"], "timed_out");
+        assert_eq!(
+            finished.payload()["error"],
+            r#"platform/app pipeline 7: HTTP 502 Bad Gateway: {"message":"502 Bad Gateway"}"#
+        );
+
+        engine.close();
+        assert_eq!(run.await.ok(), Some(Ok(())));
+    }
+
+    #[tokio::test]
+    async fn dry_run_reports_the_filled_trigger() {
+        let (url, mut rx, _pipeline) = gitlab(CREATED).await;
+        let (mut engine, run) = spawn_sink(
+            SinkFixture::new("ci_trigger_sink", "ci"),
+            SinkArgs {
+                dry_run: true,
+                ..Default::default()
+            },
+            ci_trigger_sink(&url, Duration::from_secs(30)),
+        );
+
+        engine
+            .inject_message(fixtures::message(
+                "release.tagged",
+                json!({"branch": "main", "version": "1.4.2"}),
+            ))
+            .await;
+        let report = engine.expect_published("ci.would_have").await;
+        assert_eq!(
+            report.payload()["detail"],
+            json!({"target": "app", "provider": "gitlab", "ref": "main",
+                   "params": {"VERSION": "1.4.2"}, "message": "Triggered by emergent"})
+        );
+        assert!(rx.try_recv().is_err(), "a pipeline was created");
+
+        engine.close();
+        assert_eq!(run.await.ok(), Some(Ok(())));
+    }
+}
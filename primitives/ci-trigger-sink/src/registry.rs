@@ -0,0 +1,179 @@
+//! The CI targets the sink can trigger, as listed in `--config`.
+//!
+//! ```json
+//! {"targets": {
+//!   "infra":  {"provider": "terraform", "token": "...", "workspace": "ws-2Qhk7LHgbMrm3grF",
+//!              "on": ["infra.changed"], "params": {"image_tag": "{version}"}},
+//!   "app":    {"provider": "gitlab", "url": "https://gitlab.example.com", "token": "...",
+//!              "project": "platform/app", "ref": "{branch}", "params": {"DEPLOY_ENV": "{env}"}},
+//!   "deploy": {"provider": "jenkins", "url": "https://jenkins.example.com", "job": "deploy/prod",
+//!              "user": "emergent", "token": "...", "params": {"VERSION": "{version}"}},
+//!   "docs":   {"provider": "buildkite", "token": "...", "organization": "acme", "pipeline": "docs",
+//!              "branch": "main", "on": ["docs.*"]}
+//! }}
+//! ```
+//!
+//! `params` are filled from the payload with `{dotted.path}` placeholders,
+//! as are GitLab's `ref`, Buildkite's `branch` and `commit`, and the run
+//! `message`. They become Terraform run variables, GitLab pipeline
+//! variables, Jenkins build parameters or Buildkite build environment
+//! variables. `on` lists the message types that trigger a target when the
+//! payload names none (`deploy.*` matches a namespace). The file is
+//! re-read on SIGHUP.
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Terraform Cloud or Enterprise.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Terraform {
+    #[serde(default = "default_terraform_url")]
+    pub url: String,
+    pub token: String,
+    /// Workspace id, e.g. `ws-2Qhk7LHgbMrm3grF`.
+    pub workspace: String,
+    /// Apply without confirmation (default: the workspace's setting).
+    #[serde(default)]
+    pub auto_apply: Option<bool>,
+}
+
+fn default_terraform_url() -> String {
+    "https://app.terraform.io".to_string()
+}
+
+/// A GitLab project's pipelines.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Gitlab {
+    #[serde(default = "default_gitlab_url")]
+    pub url: String,
+    /// Access token with the `api` scope.
+    pub token: String,
+    /// Project path (`group/project`) or id.
+    pub project: String,
+    #[serde(rename = "ref", default = "default_branch")]
+    pub reference: String,
+}
+
+fn default_gitlab_url() -> String {
+    "https://gitlab.com".to_string()
+}
+
+fn default_branch() -> String {
+    "main".to_string()
+}
+
+/// A Jenkins job.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Jenkins {
+    pub url: String,
+    /// Job path, with folders separated by `/`.
+    pub job: String,
+    #[serde(default)]
+    pub user: Option<String>,
+    /// The user's API token.
+    #[serde(default)]
+    pub token: Option<String>,
+}
+
+/// A Buildkite pipeline.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Buildkite {
+    #[serde(default = "default_buildkite_url")]
+    pub url: String,
+    pub token: String,
+    pub organization: String,
+    pub pipeline: String,
+    #[serde(default = "default_branch")]
+    pub branch: String,
+    #[serde(default = "default_commit")]
+    pub commit: String,
+}
+
+fn default_buildkite_url() -> String {
+    "https://api.buildkite.com".to_string()
+}
+
+fn default_commit() -> String {
+    "HEAD".to_string()
+}
+
+/// Where a target's runs happen.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "provider", rename_all = "lowercase")]
+pub enum Provider {
+    Terraform(Terraform),
+    Gitlab(Gitlab),
+    Jenkins(Jenkins),
+    Buildkite(Buildkite),
+}
+
+impl Provider {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Terraform(_) => "terraform",
+            Self::Gitlab(_) => "gitlab",
+            Self::Jenkins(_) => "jenkins",
+            Self::Buildkite(_) => "buildkite",
+        }
+    }
+}
+
+/// One triggerable pipeline, job or workspace.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Target {
+    #[serde(flatten)]
+    pub provider: Provider,
+    /// Message type patterns that trigger the target.
+    #[serde(default)]
+    pub on: Vec<String>,
+    /// Parameter templates.
+    #[serde(default)]
+    pub params: BTreeMap<String, String>,
+    /// Run message template.
+    #[serde(default)]
+    pub message: Option<String>,
+}
+
+impl Target {
+    /// Whether `message_type` triggers this target.
+    pub fn matches(&self, message_type: &str) -> bool {
+        self.on
+            .iter()
+            .any(|pattern| match pattern.strip_suffix('*') {
+                Some(prefix) => message_type.starts_with(prefix),
+                None => pattern == message_type,
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn targets_are_tagged_by_provider() {
+        let target: Target = serde_json::from_value(json!({
+            "provider": "gitlab",
+            "token": "glpat-x",
+            "project": "platform/app",
+            "on": ["release.*"],
+            "params": {"VERSION": "{version}"},
+        }))
+        .unwrap_or_else(|e| panic!("parse: {e}"));
+        let Provider::Gitlab(gitlab) = &target.provider else {
+            panic!("expected gitlab, got {:?}", target.provider);
+        };
+        assert_eq!(gitlab.url, "https://gitlab.com");
+        assert_eq!(gitlab.reference, "main");
+        assert!(target.matches("release.tagged"));
+        assert!(!target.matches("deploy.requested"));
+
+        let unknown = serde_json::from_value::<Target>(json!({"provider": "travis", "token": "x"}));
+        assert!(unknown.is_err());
+    }
+}
@@ -0,0 +1,133 @@
+//! What the providers have in common: a triggered run, its phases, and the
+//! HTTP calls that start and follow it.
+//!
+//! Failures map onto handler error categories as in the other API sinks:
+//! transport errors are `request`, timeouts `timeout`, and non-2xx
+//! responses `rejected`.
+
+use primitive_common::errors::{ErrorCategory, HandlerError};
+use reqwest::RequestBuilder;
+use reqwest::header::LOCATION;
+use serde_json::Value;
+use std::collections::BTreeMap;
+
+/// A run's filled-in parameters.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Trigger {
+    pub params: BTreeMap<String, String>,
+    /// GitLab ref or Buildkite branch.
+    pub reference: String,
+    /// Buildkite commit.
+    pub commit: String,
+    pub message: String,
+}
+
+/// A run the provider accepted.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Run {
+    /// Provider id: Terraform run id, pipeline id, build number.
+    pub id: String,
+    /// Web page of the run, where the provider gives one.
+    pub url: Option<String>,
+    /// API resource polled for its status.
+    pub poll: String,
+}
+
+/// How a finished run ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Conclusion {
+    Success,
+    Failed,
+    Canceled,
+}
+
+impl Conclusion {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Success => "success",
+            Self::Failed => "failed",
+            Self::Canceled => "canceled",
+        }
+    }
+}
+
+/// Where a run has got to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    /// Waiting to start; identical triggers are deduplicated into it.
+    Queued,
+    Running,
+    Finished(Conclusion),
+}
+
+/// A run's phase and the provider's own name for its state.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Status {
+    pub phase: Phase,
+    pub state: String,
+}
+
+impl Status {
+    pub fn new(phase: Phase, state: &str) -> Self {
+        Self {
+            phase,
+            state: state.to_string(),
+        }
+    }
+}
+
+/// A successful response: its JSON body (`Null` if empty) and `Location`.
+pub struct Response {
+    pub body: Value,
+    pub location: Option<String>,
+}
+
+/// Send `request`, failing on transport errors and non-2xx responses.
+pub async fn send(request: RequestBuilder, target: &str) -> Result<Response, HandlerError> {
+    let response = request.send().await.map_err(|e| transport(target, &e))?;
+    let status = response.status();
+    let location = response
+        .headers()
+        .get(LOCATION)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string);
+    let text = response.text().await.map_err(|e| transport(target, &e))?;
+    if !status.is_success() {
+        return Err(HandlerError::new(
+            ErrorCategory::Rejected,
+            format!("{target}: HTTP {status}: {}", text.trim()),
+        ));
+    }
+    let body = if text.trim().is_empty() {
+        Value::Null
+    } else {
+        serde_json::from_str(&text)
+            .map_err(|e| HandlerError::new(ErrorCategory::Parse, format!("{target}: {e}")))?
+    };
+    Ok(Response { body, location })
+}
+
+fn transport(target: &str, e: &reqwest::Error) -> HandlerError {
+    if e.is_timeout() {
+        HandlerError::new(ErrorCategory::Timeout, format!("{target}: timed out"))
+    } else {
+        HandlerError::new(ErrorCategory::Request, format!("{target}: {e}"))
+    }
+}
+
+/// `value` as a string id, whether the API gives a number or a string.
+pub fn id(value: &Value) -> Option<String> {
+    match value {
+        Value::String(s) => Some(s.clone()),
+        Value::Number(n) => Some(n.to_string()),
+        _ => None,
+    }
+}
+
+/// A response field the caller cannot do without.
+pub fn missing(target: &str, field: &str) -> HandlerError {
+    HandlerError::new(
+        ErrorCategory::Parse,
+        format!("{target}: response has no '{field}'"),
+    )
+}
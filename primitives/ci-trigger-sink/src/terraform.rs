@@ -0,0 +1,84 @@
+//! Terraform Cloud and Enterprise runs.
+//!
+//! A run is created in the workspace through the JSON:API `runs` endpoint,
+//! with the parameters as run-specific variables (HCL string values), and
+//! followed until it is applied, errored, discarded or canceled. A run that
+//! stops at `planned` is waiting for someone to confirm it and keeps being
+//! polled.
+
+use crate::registry::Terraform;
+use crate::run::{Conclusion, Phase, Run, Status, Trigger, missing, send};
+use primitive_common::errors::HandlerError;
+use reqwest::Client;
+use serde_json::{Value, json};
+
+const CONTENT_TYPE: &str = "application/vnd.api+json";
+
+pub async fn trigger(
+    client: &Client,
+    terraform: &Terraform,
+    trigger: &Trigger,
+) -> Result<Run, HandlerError> {
+    let base = terraform.url.trim_end_matches('/');
+    let variables: Vec<Value> = trigger
+        .params
+        .iter()
+        .map(|(key, value)| json!({"key": key, "value": json!(value).to_string()}))
+        .collect();
+    let mut attributes = json!({"message": trigger.message, "variables": variables});
+    if let Some(auto_apply) = terraform.auto_apply {
+        attributes["auto-apply"] = json!(auto_apply);
+    }
+    let body = json!({
+        "data": {
+            "type": "runs",
+            "attributes": attributes,
+            "relationships": {
+                "workspace": {"data": {"type": "workspaces", "id": terraform.workspace}},
+            },
+        },
+    });
+    let target = format!("{}: create run", terraform.workspace);
+    let request = client
+        .post(format!("{base}/api/v2/runs"))
+        .bearer_auth(&terraform.token)
+        .header("Content-Type", CONTENT_TYPE)
+        .body(body.to_string());
+    let response = send(request, &target).await?;
+    let id = response.body["data"]["id"]
+        .as_str()
+        .ok_or_else(|| missing(&target, "data.id"))?;
+    Ok(Run {
+        id: id.to_string(),
+        url: None,
+        poll: format!("{base}/api/v2/runs/{id}"),
+    })
+}
+
+pub async fn poll(
+    client: &Client,
+    terraform: &Terraform,
+    run: &Run,
+) -> Result<Status, HandlerError> {
+    let request = client
+        .get(&run.poll)
+        .bearer_auth(&terraform.token)
+        .header("Accept", CONTENT_TYPE);
+    let response = send(request, &run.id).await?;
+    let state = response.body["data"]["attributes"]["status"]
+        .as_str()
+        .ok_or_else(|| missing(&run.id, "data.attributes.status"))?;
+    Ok(Status::new(phase(state), state))
+}
+
+fn phase(state: &str) -> Phase {
+    match state {
+        "applied" | "planned_and_finished" | "planned_and_saved" => {
+            Phase::Finished(Conclusion::Success)
+        }
+        "errored" => Phase::Finished(Conclusion::Failed),
+        "discarded" | "canceled" | "force_canceled" => Phase::Finished(Conclusion::Canceled),
+        "pending" | "fetching" | "queuing" | "plan_queued" => Phase::Queued,
+        _ => Phase::Running,
+    }
+}
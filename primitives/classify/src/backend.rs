@@ -0,0 +1,338 @@
+//! Classification backends.
+//!
+//! Both classify a batch of texts at a time:
+//!
+//! - `llm`: an OpenAI-compatible `POST /chat/completions` endpoint — OpenAI
+//!   itself, or Ollama, vLLM or llama.cpp serving a local model. The
+//!   taxonomy is described in the instructions and the model answers with a
+//!   label and confidence for each dimension of each text.
+//! - `onnx`: a sequence-classification model exported to ONNX, run
+//!   in-process (see [`crate::onnx`]). The model's best label is mapped
+//!   through the taxonomy's aliases; the taxonomy must have a single
+//!   dimension.
+//!
+//! Labels a backend returns that are not in the taxonomy are left out.
+
+use crate::onnx::Model;
+use crate::taxonomy::{self, Taxonomy};
+use clap::ValueEnum;
+use reqwest::Client;
+use serde::Serialize;
+use serde_json::{Value, json};
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// A classification backend.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Backend {
+    Llm,
+    Onnx,
+}
+
+/// The label given for one dimension.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Label {
+    pub label: String,
+    pub confidence: f64,
+}
+
+/// A text's labels, by dimension.
+pub type Labels = BTreeMap<String, Label>;
+
+/// The instructions given to an LLM.
+fn instructions(taxonomy: &Taxonomy) -> String {
+    format!(
+        "Classify each of the texts you are given along these dimensions, choosing exactly one \
+         label per dimension:\n\n{}\nReply with only a JSON object of the form \
+         {{\"results\": [{{\"<dimension>\": {{\"label\": \"<label>\", \"confidence\": <0 to 1>}}}}]}}, \
+         with one result per text, in the order given.",
+        taxonomy::describe(taxonomy)
+    )
+}
+
+/// The chat-completions request classifying `texts`.
+fn llm_body(model: &str, taxonomy: &Taxonomy, texts: &[&str]) -> Value {
+    let texts: Vec<Value> = texts
+        .iter()
+        .enumerate()
+        .map(|(i, text)| json!({"index": i, "text": text}))
+        .collect();
+    json!({
+        "model": model,
+        "temperature": 0,
+        "response_format": {"type": "json_object"},
+        "messages": [
+            {"role": "system", "content": instructions(taxonomy)},
+            {"role": "user", "content": json!({"texts": texts}).to_string()},
+        ],
+    })
+}
+
+/// A confidence as a number from 0 to 1.
+fn confidence(value: &Value) -> Option<f64> {
+    value.as_f64().map(|c| c.clamp(0.0, 1.0))
+}
+
+/// The labels in one of an LLM's results.
+fn llm_labels(taxonomy: &Taxonomy, result: &Value) -> Labels {
+    taxonomy
+        .iter()
+        .filter_map(|(name, dimension)| {
+            let given = &result[name];
+            let label = dimension.resolve(given["label"].as_str()?)?;
+            let confidence = confidence(&given["confidence"])?;
+            Some((
+                name.clone(),
+                Label {
+                    label: label.to_string(),
+                    confidence,
+                },
+            ))
+        })
+        .collect()
+}
+
+/// The labels of each text in a chat-completions response.
+fn llm_results(taxonomy: &Taxonomy, response: &Value, count: usize) -> Option<Vec<Labels>> {
+    let content = response["choices"][0]["message"]["content"]
+        .as_str()?
+        .trim();
+    // Some models fence their JSON despite being asked not to
+    let content = content
+        .strip_prefix("```json")
+        .or_else(|| content.strip_prefix("```"))
+        .and_then(|rest| rest.strip_suffix("```"))
+        .unwrap_or(content);
+    let answer: Value = serde_json::from_str(content).ok()?;
+    let results = answer["results"].as_array()?;
+    (results.len() == count).then(|| {
+        results
+            .iter()
+            .map(|result| llm_labels(taxonomy, result))
+            .collect()
+    })
+}
+
+/// The labels of each text from a model's predictions, for the
+/// taxonomy's one dimension.
+fn onnx_results(taxonomy: &Taxonomy, predictions: &[(&str, f64)]) -> Vec<Labels> {
+    let Some((name, dimension)) = taxonomy.iter().next() else {
+        return predictions.iter().map(|_| Labels::new()).collect();
+    };
+    predictions
+        .iter()
+        .map(|(label, confidence)| {
+            dimension
+                .resolve(label)
+                .map(|label| {
+                    (
+                        name.clone(),
+                        Label {
+                            label: label.to_string(),
+                            confidence: confidence.clamp(0.0, 1.0),
+                        },
+                    )
+                })
+                .into_iter()
+                .collect()
+        })
+        .collect()
+}
+
+/// Where texts are classified.
+enum Engine {
+    Llm {
+        client: Client,
+        url: String,
+        key: Option<String>,
+        model: String,
+    },
+    Onnx(Arc<Model>),
+}
+
+/// A configured classification backend.
+pub struct Classifier {
+    engine: Engine,
+    taxonomy: Taxonomy,
+}
+
+impl Classifier {
+    /// Classify with the chat-completions API at `url`.
+    pub fn llm(
+        url: &str,
+        key: Option<&str>,
+        model: Option<&str>,
+        taxonomy: Taxonomy,
+        timeout: Duration,
+    ) -> Result<Self, String> {
+        let model = model.ok_or("--model is required for the llm backend")?;
+        let client = Client::builder()
+            .timeout(timeout)
+            .build()
+            .map_err(|e| format!("failed to build HTTP client: {e}"))?;
+        Ok(Self {
+            engine: Engine::Llm {
+                client,
+                url: url.trim_end_matches('/').to_string(),
+                key: key.map(str::to_string),
+                model: model.to_string(),
+            },
+            taxonomy,
+        })
+    }
+
+    /// Classify with the ONNX model in `dir`.
+    pub fn onnx(dir: &Path, taxonomy: Taxonomy) -> Result<Self, String> {
+        if taxonomy.len() != 1 {
+            return Err(format!(
+                "the onnx backend classifies one dimension, but the taxonomy has {}",
+                taxonomy.len()
+            ));
+        }
+        Ok(Self {
+            engine: Engine::Onnx(Arc::new(Model::load(dir)?)),
+            taxonomy,
+        })
+    }
+
+    /// What classifies texts, for reporting: the API endpoint, or the
+    /// model's labels.
+    pub fn describe(&self) -> String {
+        match &self.engine {
+            Engine::Llm { url, .. } => format!("{url}/chat/completions"),
+            Engine::Onnx(model) => format!("onnx model of {}", model.labels().join(", ")),
+        }
+    }
+
+    /// The taxonomy's dimensions, for reporting.
+    pub fn dimensions(&self) -> Vec<&str> {
+        self.taxonomy.keys().map(String::as_str).collect()
+    }
+
+    /// Classify `texts`, giving their labels in the same order.
+    pub async fn classify(&self, texts: &[&str]) -> Result<Vec<Labels>, String> {
+        match &self.engine {
+            Engine::Llm {
+                client,
+                url,
+                key,
+                model,
+            } => {
+                let endpoint = format!("{url}/chat/completions");
+                let mut request =
+                    client
+                        .post(&endpoint)
+                        .json(&llm_body(model, &self.taxonomy, texts));
+                if let Some(key) = key {
+                    request = request.bearer_auth(key);
+                }
+                let response = request
+                    .send()
+                    .await
+                    .map_err(|e| format!("{endpoint}: {e}"))?;
+                let status = response.status();
+                if !status.is_success() {
+                    let body = response.text().await.unwrap_or_default();
+                    return Err(format!("{endpoint}: HTTP {status}: {}", body.trim()));
+                }
+                let response: Value = response
+                    .json()
+                    .await
+                    .map_err(|e| format!("{endpoint}: {e}"))?;
+                llm_results(&self.taxonomy, &response, texts.len())
+                    .ok_or_else(|| format!("{endpoint}: unexpected response {response}"))
+            }
+            Engine::Onnx(model) => {
+                // Inference is CPU-bound; keep it off the runtime's workers
+                let model = Arc::clone(model);
+                let texts: Vec<String> = texts.iter().map(|text| (*text).to_string()).collect();
+                let taxonomy = self.taxonomy.clone();
+                tokio::task::spawn_blocking(move || {
+                    let texts: Vec<&str> = texts.iter().map(String::as_str).collect();
+                    model
+                        .predict(&texts)
+                        .map(|predictions| onnx_results(&taxonomy, &predictions))
+                })
+                .await
+                .map_err(|e| format!("inference failed: {e}"))?
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn taxonomy() -> Taxonomy {
+        taxonomy::parse(
+            "sentiment:\n  labels: {positive: '', negative: ''}\n  aliases: {LABEL_0: negative, LABEL_1: positive}\n",
+        )
+        .unwrap_or_else(|e| panic!("{e}"))
+    }
+
+    #[test]
+    fn llm_answers_are_checked_against_the_taxonomy() {
+        let body = llm_body("gpt-4o-mini", &taxonomy(), &["Love it"]);
+        assert_eq!(body["model"], "gpt-4o-mini");
+        assert_eq!(
+            body["messages"][1]["content"],
+            r#"{"texts":[{"index":0,"text":"Love it"}]}"#
+        );
+
+        let answer = |content: &str| json!({"choices": [{"message": {"content": content}}]});
+        let results = llm_results(
+            &taxonomy(),
+            &answer(
+                "```json\n{\"results\": [{\"sentiment\": {\"label\": \"Positive\", \"confidence\": 1.2}}, {\"sentiment\": {\"label\": \"mixed\", \"confidence\": 0.5}}]}\n```",
+            ),
+            2,
+        );
+        assert_eq!(
+            results,
+            Some(vec![
+                Labels::from([(
+                    "sentiment".to_string(),
+                    Label {
+                        label: "positive".to_string(),
+                        confidence: 1.0
+                    }
+                )]),
+                Labels::new(),
+            ])
+        );
+        // One result per text, or none
+        assert_eq!(
+            llm_results(&taxonomy(), &answer(r#"{"results": []}"#), 1),
+            None
+        );
+        assert!(
+            Classifier::llm("http://localhost", None, None, taxonomy(), Duration::ZERO).is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn onnx_predictions_are_mapped_through_the_aliases() {
+        let dir = emergent_testkit::fixtures::TempDir::new("classify-backend");
+        crate::onnx::tests::write_model(dir.path());
+        let two = taxonomy::parse(
+            "sentiment: {labels: {positive: '', negative: ''}}\nintent: {labels: {question: '', complaint: ''}}\n",
+        )
+        .unwrap_or_else(|e| panic!("{e}"));
+        assert!(Classifier::onnx(dir.path(), two).is_err());
+
+        let classifier = Classifier::onnx(dir.path(), taxonomy()).unwrap_or_else(|e| panic!("{e}"));
+        assert_eq!(classifier.describe(), "onnx model of LABEL_0, LABEL_1");
+        let labels = classifier
+            .classify(&["great service", "awful"])
+            .await
+            .unwrap_or_else(|e| panic!("{e}"));
+        let sentiments: Vec<&str> = labels
+            .iter()
+            .map(|labels| labels["sentiment"].label.as_str())
+            .collect();
+        assert_eq!(sentiments, ["positive", "negative"]);
+    }
+}
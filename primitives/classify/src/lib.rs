@@ -0,0 +1,611 @@
+//! Classify
+//!
+//! A Handler that labels payload text — sentiment, intent, category, or
+//! any other dimension in a taxonomy file (see [`taxonomy`]) — with a
+//! confidence for each label, so later primitives can route on them.
+//! Texts go to an LLM or through a classification model exported to ONNX,
+//! run in-process (see [`backend`] and [`onnx`]), in batches.
+//!
+//! # Data Flow
+//!
+//! 1. Receive an event matching configured subscriptions
+//! 2. Join the `--field` texts present in its payload
+//! 3. Hold it until `--batch-size` messages are waiting or the oldest has
+//!    waited `--batch-wait` milliseconds, then classify the batch in one
+//!    request or model run
+//! 4. Republish each payload with its labels under `--output-field`:
+//!    `{"sentiment": {"label": "negative", "confidence": 0.91}, ...}`
+//!
+//! Labels below `--min-confidence` are left out. A message with no text in
+//! its fields is republished with no labels; the messages of a batch whose
+//! classification fails are reported and dropped.
+//!
+//! # Messages Published
+//!
+//! - The incoming type, renamed by `--publish-as` (default:
+//!   `classified.{type}`), with the labels added
+//!
+//! # Usage
+//!
+//! ```bash
+//! # Sentiment and intent of support tickets, via a local Ollama model
+//! classify -s ticket.created --field subject --field body \
+//!   --taxonomy support.yaml --backend llm \
+//!   --api-url http://localhost:11434/v1 --model llama3.1
+//!
+//! # Sentiment from a model exported to ONNX, run locally
+//! classify -s 'review.*' --field text --taxonomy sentiment.yaml \
+//!   --backend onnx --model-dir /var/lib/models/sentiment --batch-size 64
+//! ```
+
+pub mod backend;
+pub mod onnx;
+pub mod taxonomy;
+
+use backend::{Backend, Classifier, Labels};
+use clap::Parser;
+use emergent_client::EmergentMessage;
+use emergent_client::types::CausationId;
+use primitive_common::capabilities::{self, CapabilityArgs, Role, VERSION};
+use primitive_common::crypto::{KeyArgs, Keyring};
+use primitive_common::doctor::Report;
+use primitive_common::errors::{ERROR_EVENT_TYPE, ErrorArgs, ErrorCategory};
+use primitive_common::handler::{Bus, Flow, HandlerConfig, MessageHandler, run_handler};
+use primitive_common::key;
+use primitive_common::payload::{self, PayloadArgs};
+use serde_json::Value;
+use std::path::PathBuf;
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// The API URL of the llm backend when no `--api-url` is given.
+const OPENAI_URL: &str = "https://api.openai.com/v1";
+
+/// Classify — label payload text with a taxonomy's labels.
+#[derive(Parser, Debug)]
+#[command(name = "classify", version = VERSION)]
+#[command(
+    about = "Label payload text with sentiment, intent or category via an LLM or local model"
+)]
+struct Args {
+    /// Message types to subscribe to.
+    #[arg(short, long = "subscribe", required = true)]
+    subscribe: Vec<String>,
+
+    /// Payload text field to classify, as a dotted path (e.g. `ticket.body`); repeatable, joined in order.
+    #[arg(
+        long = "field",
+        env = "CLASSIFY_FIELDS",
+        value_delimiter = ',',
+        required = true
+    )]
+    fields: Vec<String>,
+
+    /// Taxonomy file (YAML or JSON) of the dimensions and labels to classify into.
+    #[arg(long, env = "CLASSIFY_TAXONOMY")]
+    taxonomy: PathBuf,
+
+    /// Classification backend.
+    #[arg(long, env = "CLASSIFY_BACKEND", value_enum, default_value = "llm")]
+    backend: Backend,
+
+    /// API base URL of the llm backend (default: OpenAI's).
+    #[arg(long, env = "CLASSIFY_API_URL")]
+    api_url: Option<String>,
+
+    /// API key, sent as a bearer token.
+    #[arg(long, env = "CLASSIFY_API_KEY")]
+    api_key: Option<String>,
+
+    /// Model name (llm backend).
+    #[arg(long, env = "CLASSIFY_MODEL")]
+    model: Option<String>,
+
+    /// Directory of a classification model exported to ONNX, with its
+    /// `model.onnx`, `tokenizer.json` and `config.json` (onnx backend).
+    #[arg(long, env = "CLASSIFY_MODEL_DIR")]
+    model_dir: Option<PathBuf>,
+
+    /// Payload field the labels are added under.
+    #[arg(long, env = "CLASSIFY_OUTPUT_FIELD", default_value = "classification")]
+    output_field: String,
+
+    /// Leave out labels with a lower confidence (0 to 1).
+    #[arg(long, env = "CLASSIFY_MIN_CONFIDENCE", default_value = "0")]
+    min_confidence: f64,
+
+    /// Most messages classified in one request or model run.
+    #[arg(
+        long,
+        env = "CLASSIFY_BATCH_SIZE",
+        default_value = "16",
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    batch_size: u64,
+
+    /// Longest a message waits for its batch to fill, in milliseconds.
+    #[arg(long, env = "CLASSIFY_BATCH_WAIT", default_value = "200")]
+    batch_wait: u64,
+
+    /// Message type to republish as; `{type}` stands for the incoming type.
+    #[arg(
+        long,
+        env = "CLASSIFY_PUBLISH_AS",
+        default_value = "classified.{type}",
+        value_parser = parse_publish_as
+    )]
+    publish_as: String,
+
+    /// Per-request timeout in milliseconds (llm backend).
+    #[arg(long, env = "CLASSIFY_TIMEOUT", default_value = "30000")]
+    timeout: u64,
+
+    #[command(flatten)]
+    payload: PayloadArgs,
+
+    /// Verify the configuration and exit without connecting to the engine.
+    #[arg(long)]
+    self_test: bool,
+
+    #[command(flatten)]
+    errors: ErrorArgs,
+
+    #[command(flatten)]
+    capabilities: CapabilityArgs,
+
+    #[command(flatten)]
+    keys: KeyArgs,
+}
+
+fn parse_publish_as(value: &str) -> Result<String, String> {
+    match value {
+        "" => Err("must not be empty".to_string()),
+        // Republishing under the same type would feed straight back in
+        "{type}" => Err("must differ from the incoming type".to_string()),
+        _ => Ok(value.to_string()),
+    }
+}
+
+/// The message type `message_type` is republished as.
+fn publish_type(publish_as: &str, message_type: &str) -> String {
+    publish_as.replace("{type}", message_type)
+}
+
+/// Whether `message_type` is one this handler published.
+fn is_own(publish_as: &str, message_type: &str) -> bool {
+    match publish_as.split_once("{type}") {
+        Some((prefix, suffix)) => {
+            message_type.len() > prefix.len() + suffix.len()
+                && message_type.starts_with(prefix)
+                && message_type.ends_with(suffix)
+        }
+        None => message_type == publish_as,
+    }
+}
+
+/// The non-empty texts at `fields` in `input`, joined by blank lines;
+/// fields that are absent or not strings are skipped.
+fn text(input: &Value, fields: &[String]) -> Option<String> {
+    let texts: Vec<&str> = fields
+        .iter()
+        .filter_map(|field| key::lookup(input, field)?.as_str())
+        .map(str::trim)
+        .filter(|text| !text.is_empty())
+        .collect();
+    (!texts.is_empty()).then(|| texts.join("\n\n"))
+}
+
+/// `labels` as the output field's value, without those below
+/// `min_confidence`.
+fn labels_value(labels: Labels, min_confidence: f64) -> Value {
+    let kept: Labels = labels
+        .into_iter()
+        .filter(|(_, label)| label.confidence >= min_confidence)
+        .collect();
+    serde_json::to_value(kept).unwrap_or_default()
+}
+
+/// A message waiting for its batch.
+struct Pending {
+    message_id: String,
+    message_type: String,
+    cause: CausationId,
+    input: Value,
+    text: Option<String>,
+}
+
+/// Messages waiting to be classified together.
+struct Batch {
+    pending: Vec<Pending>,
+    /// When the oldest waiting message must be classified.
+    deadline: Option<Instant>,
+    batches: u64,
+    classified: u64,
+}
+
+impl Batch {
+    fn new() -> Self {
+        Self {
+            pending: Vec::new(),
+            deadline: None,
+            batches: 0,
+            classified: 0,
+        }
+    }
+
+    fn push(&mut self, pending: Pending, wait: Duration) {
+        if self.pending.is_empty() {
+            self.deadline = Some(Instant::now() + wait);
+        }
+        self.pending.push(pending);
+    }
+
+    /// Classify the waiting messages, giving each with its labels, or the
+    /// error that failed them all.
+    async fn classify(
+        &mut self,
+        classifier: &Classifier,
+    ) -> (Vec<Pending>, Result<Vec<Option<Labels>>, String>) {
+        self.deadline = None;
+        let pending = std::mem::take(&mut self.pending);
+        let texts: Vec<&str> = pending.iter().filter_map(|p| p.text.as_deref()).collect();
+        if texts.is_empty() {
+            let none = pending.iter().map(|_| None).collect();
+            return (pending, Ok(none));
+        }
+        self.batches += 1;
+        let result = classifier.classify(&texts).await.map(|labels| {
+            self.classified += labels.len() as u64;
+            let mut labels = labels.into_iter();
+            pending
+                .iter()
+                .map(|p| p.text.as_ref().and_then(|_| labels.next()))
+                .collect()
+        });
+        (pending, result)
+    }
+}
+
+/// Run the primitive with `args` as its command line (the first item is
+/// the program name, as with `std::env::args_os`).
+pub async fn run<I, T>(args: I) -> Result<(), Box<dyn std::error::Error>>
+where
+    I: IntoIterator<Item = T>,
+    T: Into<std::ffi::OsString> + Clone,
+{
+    let args = Args::parse_from(args);
+
+    // Get the handler name from environment (set by engine) or use default
+    let name = std::env::var("EMERGENT_NAME").unwrap_or_else(|_| "classify".to_string());
+
+    let classifier = taxonomy::load(&args.taxonomy).and_then(|taxonomy| match args.backend {
+        Backend::Llm => Classifier::llm(
+            args.api_url.as_deref().unwrap_or(OPENAI_URL),
+            args.api_key.as_deref(),
+            args.model.as_deref(),
+            taxonomy,
+            Duration::from_millis(args.timeout),
+        ),
+        Backend::Onnx => match &args.model_dir {
+            Some(dir) => Classifier::onnx(dir, taxonomy),
+            None => Err("--model-dir is required for the onnx backend".to_string()),
+        },
+    });
+    if args.self_test {
+        let mut report = Report::new(&name);
+        report.check(
+            "classifier",
+            classifier
+                .as_ref()
+                .map(|classifier| {
+                    format!(
+                        "{} for {}",
+                        classifier.describe(),
+                        classifier.dimensions().join(", ")
+                    )
+                })
+                .map_err(Clone::clone),
+        );
+        args.payload.self_test(&mut report);
+        args.keys.self_test(&mut report);
+        report.finish();
+    }
+    let classifier = match classifier {
+        Ok(classifier) => classifier,
+        Err(e) => {
+            eprintln!("Error: {e}");
+            std::process::exit(1);
+        }
+    };
+
+    if let Err(e) = args.payload.encryption.validate() {
+        eprintln!("Error: {e}");
+        std::process::exit(1);
+    }
+    let keys = match args.keys.load() {
+        Ok(keys) => keys,
+        Err(e) => {
+            eprintln!("Error: failed to load identity file: {e}");
+            std::process::exit(1);
+        }
+    };
+
+    let topics_refs: Vec<&str> = args.subscribe.iter().map(String::as_str).collect();
+    let mut produces = vec![args.publish_as.as_str()];
+    if args.errors.emit_errors {
+        produces.push(ERROR_EVENT_TYPE);
+    }
+    let descriptor =
+        args.capabilities
+            .describe(&name, Role::Handler, &topics_refs, &produces, &args);
+    if args.capabilities.capabilities {
+        capabilities::print(&descriptor);
+    }
+
+    let config = HandlerConfig {
+        name: &name,
+        subscribe: &args.subscribe,
+        announce: args.capabilities.announce.then_some(&descriptor),
+        emit_errors: args.errors.emit_errors,
+    };
+    let mut classify = Classify {
+        args: &args,
+        classifier,
+        batch: Batch::new(),
+        keys,
+    };
+    run_handler(config, &mut classify).await?;
+
+    eprintln!(
+        "classify: {} messages classified in {} batches",
+        classify.batch.classified, classify.batch.batches
+    );
+    Ok(())
+}
+
+/// The classifier and the batch being filled for it.
+struct Classify<'a> {
+    args: &'a Args,
+    classifier: Classifier,
+    batch: Batch,
+    keys: Option<Keyring>,
+}
+
+impl MessageHandler for Classify<'_> {
+    type Wake = ();
+
+    /// Add one message to the batch, classifying it once full.
+    async fn handle(&mut self, msg: &EmergentMessage, bus: &Bus) {
+        let args = self.args;
+        if let Some(pending) = admit(msg, args, bus, self.keys.as_ref()).await {
+            self.batch
+                .push(pending, Duration::from_millis(args.batch_wait));
+            if self.batch.pending.len() as u64 >= args.batch_size {
+                flush(&mut self.batch, args, bus, &self.classifier).await;
+            }
+        }
+    }
+
+    /// Sleep until the oldest waiting message is due.
+    async fn wake(&mut self) {
+        match self.batch.deadline {
+            Some(at) => tokio::time::sleep_until(at).await,
+            None => std::future::pending().await,
+        }
+    }
+
+    async fn woken(&mut self, (): (), bus: &Bus) -> Flow {
+        flush(&mut self.batch, self.args, bus, &self.classifier).await;
+        Flow::Continue
+    }
+
+    /// Waiting messages are classified, not lost.
+    async fn finish(&mut self, bus: &Bus) {
+        flush(&mut self.batch, self.args, bus, &self.classifier).await;
+    }
+}
+
+/// Decode one message for its batch.
+async fn admit(
+    msg: &EmergentMessage,
+    args: &Args,
+    bus: &Bus,
+    keys: Option<&Keyring>,
+) -> Option<Pending> {
+    let message_type = msg.message_type.as_str();
+    if is_own(&args.publish_as, message_type) {
+        return None;
+    }
+    let error = match payload::decode(msg.payload(), keys).await {
+        Ok(input) if input.is_object() => {
+            let input = input.into_owned();
+            return Some(Pending {
+                message_id: msg.id().to_string(),
+                message_type: message_type.to_string(),
+                cause: CausationId::from(msg.id()),
+                text: text(&input, &args.fields),
+                input,
+            });
+        }
+        Ok(_) => "payload is not a JSON object".to_string(),
+        Err(e) => format!("failed to decode payload: {e}"),
+    };
+    eprintln!("classify: {error}");
+    bus.report_error(msg, ErrorCategory::Parse, &error).await;
+    None
+}
+
+/// Classify the waiting messages and republish them with their labels.
+async fn flush(batch: &mut Batch, args: &Args, bus: &Bus, classifier: &Classifier) {
+    if batch.pending.is_empty() {
+        return;
+    }
+    let (pending, result) = batch.classify(classifier).await;
+    let labels = match result {
+        Ok(labels) => labels,
+        Err(e) => {
+            let error = format!("classification failed: {e}");
+            eprintln!("classify: {error} ({} messages dropped)", pending.len());
+            for p in pending {
+                bus.report_dropped(
+                    &p.message_id,
+                    &p.message_type,
+                    p.cause,
+                    ErrorCategory::Request,
+                    &error,
+                )
+                .await;
+            }
+            return;
+        }
+    };
+
+    for (mut p, labels) in pending.into_iter().zip(labels) {
+        let value = labels_value(labels.unwrap_or_default(), args.min_confidence);
+        if let Value::Object(map) = &mut p.input {
+            map.insert(args.output_field.clone(), value);
+        }
+        let publish_as = publish_type(&args.publish_as, &p.message_type);
+        let payload = match payload::encode(&publish_as, p.input, &args.payload).await {
+            Ok(payload) => payload,
+            Err(e) => {
+                eprintln!("classify: failed to encode output: {e}");
+                continue;
+            }
+        };
+        let message = EmergentMessage::new(&publish_as)
+            .with_causation_id(p.cause)
+            .with_payload(payload);
+        bus.publish(message).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{Json, Router, routing::post};
+    use serde_json::json;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn pending(input: Value, fields: &[String]) -> Pending {
+        Pending {
+            message_id: "m".to_string(),
+            message_type: "ticket.created".to_string(),
+            cause: CausationId::from(EmergentMessage::new("ticket.created").id()),
+            text: text(&input, fields),
+            input,
+        }
+    }
+
+    #[tokio::test]
+    async fn a_batch_is_classified_in_one_request() {
+        let requests = Arc::new(AtomicUsize::new(0));
+        let app = {
+            let requests = Arc::clone(&requests);
+            Router::new().route(
+                "/chat/completions",
+                post(move |Json(body): Json<Value>| {
+                    let requests = Arc::clone(&requests);
+                    async move {
+                        requests.fetch_add(1, Ordering::SeqCst);
+                        let content = body["messages"][1]["content"].as_str().unwrap_or_default();
+                        let texts: Value =
+                            serde_json::from_str(content).unwrap_or_else(|e| panic!("{e}"));
+                        let results: Vec<Value> = texts["texts"]
+                            .as_array()
+                            .map(|texts| {
+                                texts
+                                    .iter()
+                                    .map(|t| {
+                                        let angry = t["text"].as_str().unwrap_or_default().contains('!');
+                                        json!({
+                                            "sentiment": {
+                                                "label": if angry { "negative" } else { "positive" },
+                                                "confidence": if angry { 0.9 } else { 0.4 },
+                                            }
+                                        })
+                                    })
+                                    .collect()
+                            })
+                            .unwrap_or_default();
+                        let answer = json!({"results": results}).to_string();
+                        Json(json!({"choices": [{"message": {"role": "assistant", "content": answer}}]}))
+                    }
+                }),
+            )
+        };
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap_or_else(|e| panic!("bind: {e}"));
+        let addr = listener
+            .local_addr()
+            .unwrap_or_else(|e| panic!("local_addr: {e}"));
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let args = Args::try_parse_from([
+            "classify",
+            "-s",
+            "ticket.created",
+            "--field",
+            "subject,payload.detail.body",
+            "--taxonomy",
+            "support.yaml",
+            "--model",
+            "local",
+            "--min-confidence",
+            "0.5",
+        ])
+        .unwrap_or_else(|e| panic!("{e}"));
+        let taxonomy = taxonomy::parse("sentiment: {labels: {positive: '', negative: ''}}")
+            .unwrap_or_else(|e| panic!("{e}"));
+        let classifier = Classifier::llm(
+            &format!("http://{addr}"),
+            None,
+            args.model.as_deref(),
+            taxonomy,
+            Duration::from_secs(5),
+        )
+        .unwrap_or_else(|e| panic!("{e}"));
+
+        let mut batch = Batch::new();
+        let wait = Duration::from_millis(args.batch_wait);
+        batch.push(
+            pending(
+                json!({"subject": "Refund", "detail": {"body": "Still waiting!"}}),
+                &args.fields,
+            ),
+            wait,
+        );
+        batch.push(pending(json!({"id": 7}), &args.fields), wait);
+        batch.push(pending(json!({"subject": "Thanks"}), &args.fields), wait);
+        assert_eq!(
+            batch.pending[0].text.as_deref(),
+            Some("Refund\n\nStill waiting!")
+        );
+        assert!(batch.deadline.is_some());
+
+        let (pending, result) = batch.classify(&classifier).await;
+        let labels: Vec<Value> = result
+            .unwrap_or_else(|e| panic!("{e}"))
+            .into_iter()
+            .map(|labels| labels_value(labels.unwrap_or_default(), args.min_confidence))
+            .collect();
+        assert_eq!(
+            labels,
+            [
+                json!({"sentiment": {"label": "negative", "confidence": 0.9}}),
+                json!({}),
+                // Below --min-confidence
+                json!({}),
+            ]
+        );
+        assert_eq!(pending.len(), 3);
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+        assert_eq!((batch.batches, batch.classified), (1, 2));
+        assert!(batch.deadline.is_none());
+
+        assert!(is_own(&args.publish_as, "classified.ticket.created"));
+        assert!(!is_own(&args.publish_as, "ticket.created"));
+    }
+}
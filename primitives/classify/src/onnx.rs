@@ -0,0 +1,377 @@
+//! Local inference with an exported sequence-classification model.
+//!
+//! `--model-dir` holds a model exported to ONNX the way Hugging Face
+//! Optimum exports one (`optimum-cli export onnx --task text-classification`):
+//!
+//! - `model.onnx` — takes `input_ids` and `attention_mask` (and
+//!   `token_type_ids`, if it has them) and gives logits for each label
+//! - `tokenizer.json` — the model's tokenizer
+//! - `config.json` — the label names, as `id2label`
+//!
+//! The model runs in-process with tract, a batch per run, padded to its
+//! longest text; texts longer than the model takes are truncated. A text's
+//! label is the one with the highest logit, and its confidence that
+//! label's softmax probability.
+
+use std::collections::BTreeMap;
+use std::path::Path;
+use tokenizers::{PaddingParams, Tokenizer, TruncationParams};
+use tract_onnx::prelude::*;
+
+/// Most tokens of a text, unless the tokenizer sets its own limit.
+const MAX_TOKENS: usize = 512;
+
+/// A model input, by what it is fed.
+#[derive(Debug, Clone, Copy)]
+enum Input {
+    Ids,
+    Mask,
+    TypeIds,
+}
+
+impl Input {
+    fn named(name: &str) -> Result<Self, String> {
+        match name {
+            "input_ids" => Ok(Self::Ids),
+            "attention_mask" => Ok(Self::Mask),
+            "token_type_ids" => Ok(Self::TypeIds),
+            _ => Err(format!("unexpected model input '{name}'")),
+        }
+    }
+}
+
+/// A loaded classification model and its tokenizer.
+pub struct Model {
+    plan: TypedRunnableModel<TypedModel>,
+    inputs: Vec<Input>,
+    tokenizer: Tokenizer,
+    labels: Vec<String>,
+}
+
+/// The label names in a model's `config.json`, in logit order.
+fn labels(config: &str) -> Result<Vec<String>, String> {
+    #[derive(serde::Deserialize)]
+    struct Config {
+        id2label: BTreeMap<String, String>,
+    }
+    let config: Config = serde_json::from_str(config).map_err(|e| e.to_string())?;
+    let mut labels = config
+        .id2label
+        .into_iter()
+        .map(|(id, label)| {
+            id.parse::<usize>()
+                .map(|id| (id, label))
+                .map_err(|_| format!("'{id}' is not a label id"))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    labels.sort();
+    if labels.iter().enumerate().any(|(i, (id, _))| i != *id) {
+        return Err("id2label must number labels from 0".to_string());
+    }
+    Ok(labels.into_iter().map(|(_, label)| label).collect())
+}
+
+/// The index and softmax probability of the highest of `logits`.
+fn best(logits: &[f32]) -> Option<(usize, f64)> {
+    let (index, max) = logits
+        .iter()
+        .copied()
+        .enumerate()
+        .max_by(|(_, a), (_, b)| a.total_cmp(b))?;
+    let sum: f64 = logits.iter().map(|l| f64::from(l - max).exp()).sum();
+    Some((index, 1.0 / sum))
+}
+
+impl Model {
+    /// Load the model in `dir`.
+    pub fn load(dir: &Path) -> Result<Self, String> {
+        let config = dir.join("config.json");
+        let labels = std::fs::read_to_string(&config)
+            .map_err(|e| e.to_string())
+            .and_then(|config| labels(&config))
+            .map_err(|e| format!("{}: {e}", config.display()))?;
+
+        let path = dir.join("tokenizer.json");
+        let mut tokenizer =
+            Tokenizer::from_file(&path).map_err(|e| format!("{}: {e}", path.display()))?;
+        if tokenizer.get_padding().is_none() {
+            tokenizer.with_padding(Some(PaddingParams::default()));
+        }
+        if tokenizer.get_truncation().is_none() {
+            tokenizer
+                .with_truncation(Some(TruncationParams {
+                    max_length: MAX_TOKENS,
+                    ..TruncationParams::default()
+                }))
+                .map_err(|e| format!("{}: {e}", path.display()))?;
+        }
+
+        let path = dir.join("model.onnx");
+        let model = tract_onnx::onnx()
+            .model_for_path(&path)
+            .map_err(|e| format!("{}: {e}", path.display()))?;
+        let inputs = model
+            .input_outlets()
+            .map_err(|e| e.to_string())?
+            .iter()
+            .map(|outlet| Input::named(&model.node(outlet.node).name))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| format!("{}: {e}", path.display()))?;
+        let plan = model
+            .into_optimized()
+            .and_then(TypedModel::into_runnable)
+            .map_err(|e| format!("{}: {e}", path.display()))?;
+        Ok(Self {
+            plan,
+            inputs,
+            tokenizer,
+            labels,
+        })
+    }
+
+    /// The model's label names.
+    pub fn labels(&self) -> &[String] {
+        &self.labels
+    }
+
+    /// The best label of each of `texts`, with its confidence.
+    pub fn predict(&self, texts: &[&str]) -> Result<Vec<(&str, f64)>, String> {
+        let encodings = self
+            .tokenizer
+            .encode_batch(texts.to_vec(), true)
+            .map_err(|e| format!("failed to tokenize: {e}"))?;
+        let width = encodings.first().map_or(0, |e| e.len());
+        let tensor = |ids: fn(&tokenizers::Encoding) -> &[u32]| -> Tensor {
+            tract_ndarray::Array2::from_shape_fn((encodings.len(), width), |(row, col)| {
+                i64::from(ids(&encodings[row]).get(col).copied().unwrap_or_default())
+            })
+            .into()
+        };
+        let inputs: TVec<TValue> = self
+            .inputs
+            .iter()
+            .map(|input| {
+                match input {
+                    Input::Ids => tensor(tokenizers::Encoding::get_ids),
+                    Input::Mask => tensor(tokenizers::Encoding::get_attention_mask),
+                    Input::TypeIds => tensor(tokenizers::Encoding::get_type_ids),
+                }
+                .into()
+            })
+            .collect();
+        let outputs = self
+            .plan
+            .run(inputs)
+            .map_err(|e| format!("inference failed: {e}"))?;
+        let logits = outputs
+            .first()
+            .ok_or("the model gave no output")?
+            .to_array_view::<f32>()
+            .map_err(|e| format!("unexpected model output: {e}"))?
+            .into_dimensionality::<tract_ndarray::Ix2>()
+            .map_err(|e| format!("unexpected model output: {e}"))?;
+        if logits.nrows() != texts.len() || logits.ncols() != self.labels.len() {
+            return Err(format!(
+                "the model gave {:?} logits for {} texts and {} labels",
+                logits.shape(),
+                texts.len(),
+                self.labels.len()
+            ));
+        }
+        Ok(logits
+            .rows()
+            .into_iter()
+            .filter_map(|row| {
+                let (index, confidence) = best(&row.to_vec())?;
+                Some((self.labels[index].as_str(), confidence))
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use prost::Message;
+    use tokenizers::models::wordlevel::WordLevel;
+    use tokenizers::pre_tokenizers::whitespace::Whitespace;
+    use tract_onnx::pb::{
+        AttributeProto, GraphProto, ModelProto, NodeProto, OperatorSetIdProto, TensorProto,
+        TensorShapeProto, TypeProto, ValueInfoProto, attribute_proto, tensor_proto,
+        tensor_shape_proto, type_proto,
+    };
+
+    /// A tensor of `elem_type` named `name`, with `dims` given as sizes or
+    /// symbols.
+    fn value_info(name: &str, elem_type: tensor_proto::DataType, dims: &[&str]) -> ValueInfoProto {
+        use tensor_shape_proto::dimension::Value;
+        let dim = dims
+            .iter()
+            .map(|dim| tensor_shape_proto::Dimension {
+                value: Some(
+                    dim.parse()
+                        .map_or_else(|_| Value::DimParam((*dim).to_string()), Value::DimValue),
+                ),
+                ..Default::default()
+            })
+            .collect();
+        ValueInfoProto {
+            name: name.to_string(),
+            r#type: Some(TypeProto {
+                value: Some(type_proto::Value::TensorType(type_proto::Tensor {
+                    elem_type: elem_type as i32,
+                    shape: Some(TensorShapeProto { dim }),
+                })),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+
+    /// Write a sentiment model to `dir`: each word has a score per label
+    /// (`LABEL_0` negative, `LABEL_1` positive), summed over the words the
+    /// attention mask keeps.
+    pub(crate) fn write_model(dir: &Path) {
+        let vocab = [
+            ("[PAD]", [0.0, 0.0]),
+            ("[UNK]", [0.0, 0.0]),
+            ("great", [0.0, 3.0]),
+            ("awful", [3.0, 0.0]),
+            ("service", [0.5, 0.5]),
+        ];
+        let node = |op: &str, inputs: &[&str], output: &str, attribute| NodeProto {
+            op_type: op.to_string(),
+            input: inputs.iter().map(|i| (*i).to_string()).collect(),
+            output: vec![output.to_string()],
+            name: output.to_string(),
+            attribute,
+            ..Default::default()
+        };
+        let ints = |name: &str, ints: Vec<i64>| AttributeProto {
+            name: name.to_string(),
+            r#type: attribute_proto::AttributeType::Ints as i32,
+            ints,
+            ..Default::default()
+        };
+        let int = |name: &str, i: i64| AttributeProto {
+            name: name.to_string(),
+            r#type: attribute_proto::AttributeType::Int as i32,
+            i,
+            ..Default::default()
+        };
+        let graph = GraphProto {
+            name: "sentiment".to_string(),
+            initializer: vec![TensorProto {
+                name: "scores".to_string(),
+                dims: vec![vocab.len() as i64, 2],
+                data_type: tensor_proto::DataType::Float as i32,
+                float_data: vocab
+                    .iter()
+                    .flat_map(|(_, scores)| scores)
+                    .copied()
+                    .collect(),
+                ..Default::default()
+            }],
+            node: vec![
+                node("Gather", &["scores", "input_ids"], "embedded", vec![]),
+                node(
+                    "Cast",
+                    &["attention_mask"],
+                    "mask",
+                    vec![int("to", tensor_proto::DataType::Float as i64)],
+                ),
+                node("Unsqueeze", &["mask"], "mask3", vec![ints("axes", vec![2])]),
+                node("Mul", &["embedded", "mask3"], "kept", vec![]),
+                node(
+                    "ReduceSum",
+                    &["kept"],
+                    "logits",
+                    vec![ints("axes", vec![1]), int("keepdims", 0)],
+                ),
+            ],
+            input: vec![
+                value_info(
+                    "input_ids",
+                    tensor_proto::DataType::Int64,
+                    &["batch", "sequence"],
+                ),
+                value_info(
+                    "attention_mask",
+                    tensor_proto::DataType::Int64,
+                    &["batch", "sequence"],
+                ),
+            ],
+            output: vec![value_info(
+                "logits",
+                tensor_proto::DataType::Float,
+                &["batch", "2"],
+            )],
+            ..Default::default()
+        };
+        let model = ModelProto {
+            ir_version: 7,
+            opset_import: vec![OperatorSetIdProto {
+                domain: String::new(),
+                version: 11,
+            }],
+            graph: Some(graph),
+            ..Default::default()
+        };
+        let write = |name: &str, bytes: &[u8]| {
+            std::fs::write(dir.join(name), bytes).unwrap_or_else(|e| panic!("{name}: {e}"));
+        };
+        write("model.onnx", &model.encode_to_vec());
+        write(
+            "config.json",
+            br#"{"id2label": {"0": "LABEL_0", "1": "LABEL_1"}}"#,
+        );
+
+        let words = vocab
+            .iter()
+            .enumerate()
+            .map(|(id, (word, _))| ((*word).to_string(), id as u32))
+            .collect();
+        let model = WordLevel::builder()
+            .vocab(words)
+            .unk_token("[UNK]".to_string())
+            .build()
+            .unwrap_or_else(|e| panic!("{e}"));
+        let mut tokenizer = Tokenizer::new(model);
+        tokenizer.with_pre_tokenizer(Some(Whitespace {}));
+        tokenizer
+            .save(dir.join("tokenizer.json"), false)
+            .unwrap_or_else(|e| panic!("tokenizer.json: {e}"));
+    }
+
+    #[test]
+    fn labels_follow_the_model_config() {
+        assert_eq!(
+            labels(r#"{"id2label": {"1": "POSITIVE", "0": "NEGATIVE"}, "model_type": "bert"}"#),
+            Ok(vec!["NEGATIVE".to_string(), "POSITIVE".to_string()])
+        );
+        assert!(labels(r#"{"id2label": {"1": "POSITIVE"}}"#).is_err());
+        assert!(labels(r#"{"model_type": "bert"}"#).is_err());
+
+        let (index, confidence) = best(&[0.0, 3.0]).unwrap_or_else(|| panic!("no label"));
+        assert_eq!(index, 1);
+        assert!((confidence - 0.9526).abs() < 1e-4);
+    }
+
+    #[test]
+    fn batches_are_padded_and_classified_in_one_run() {
+        let dir = emergent_testkit::fixtures::TempDir::new("classify-onnx");
+        write_model(dir.path());
+        let model = Model::load(dir.path()).unwrap_or_else(|e| panic!("{e}"));
+        assert_eq!(model.labels(), ["LABEL_0", "LABEL_1"]);
+
+        let predictions = model
+            .predict(&["great service", "awful awful service, really"])
+            .unwrap_or_else(|e| panic!("{e}"));
+        let labels: Vec<&str> = predictions.iter().map(|(label, _)| *label).collect();
+        assert_eq!(labels, ["LABEL_1", "LABEL_0"]);
+        // Padding adds nothing: 0.5 + 3.0 against 0.5
+        assert!((predictions[0].1 - 0.9526).abs() < 1e-4);
+
+        assert!(Model::load(&dir.path().join("missing")).is_err());
+    }
+}
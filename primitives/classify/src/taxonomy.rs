@@ -0,0 +1,129 @@
+//! The labels messages are classified into, from `--taxonomy`.
+//!
+//! Each dimension lists its labels, with descriptions that guide an LLM,
+//! and optionally aliases mapping a model's own label names onto them:
+//!
+//! ```yaml
+//! sentiment:
+//!   description: How the writer feels
+//!   labels:
+//!     positive: Pleased, thankful or relieved
+//!     neutral: Matter-of-fact
+//!     negative: Unhappy, angry or worried
+//!   aliases: {POSITIVE: positive, NEGATIVE: negative, LABEL_1: neutral}
+//! intent:
+//!   labels:
+//!     question: Asks for information
+//!     complaint: Reports a problem
+//!     cancellation: Wants to end a subscription or order
+//! ```
+
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// One way of labelling a text.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Dimension {
+    #[serde(default)]
+    pub description: Option<String>,
+    /// Label names with their descriptions.
+    pub labels: BTreeMap<String, String>,
+    /// A model's label names, mapped onto `labels`.
+    #[serde(default)]
+    pub aliases: BTreeMap<String, String>,
+}
+
+impl Dimension {
+    /// The label `raw` (a label or alias, in any case) stands for.
+    pub fn resolve(&self, raw: &str) -> Option<&str> {
+        let raw = raw.trim();
+        let label = self
+            .aliases
+            .iter()
+            .find(|(alias, _)| alias.eq_ignore_ascii_case(raw))
+            .map_or(raw, |(_, label)| label.as_str());
+        self.labels
+            .keys()
+            .find(|known| known.eq_ignore_ascii_case(label))
+            .map(String::as_str)
+    }
+}
+
+/// Every dimension, by name.
+pub type Taxonomy = BTreeMap<String, Dimension>;
+
+/// Parse a taxonomy (YAML, or JSON) and check it.
+pub fn parse(text: &str) -> Result<Taxonomy, String> {
+    let taxonomy: Taxonomy = serde_yaml_ng::from_str(text).map_err(|e| e.to_string())?;
+    if taxonomy.is_empty() {
+        return Err("no dimensions".to_string());
+    }
+    for (name, dimension) in &taxonomy {
+        if dimension.labels.len() < 2 {
+            return Err(format!("{name}: needs at least two labels"));
+        }
+        if let Some((alias, label)) = dimension
+            .aliases
+            .iter()
+            .find(|(_, label)| !dimension.labels.contains_key(*label))
+        {
+            return Err(format!(
+                "{name}: alias {alias} names unknown label '{label}'"
+            ));
+        }
+    }
+    Ok(taxonomy)
+}
+
+/// Read the `--taxonomy` file.
+pub fn load(path: &Path) -> Result<Taxonomy, String> {
+    let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {e}", path.display()))?;
+    parse(&text).map_err(|e| format!("{}: {e}", path.display()))
+}
+
+/// The taxonomy described for an LLM's instructions.
+pub fn describe(taxonomy: &Taxonomy) -> String {
+    let mut text = String::new();
+    for (name, dimension) in taxonomy {
+        text.push_str(name);
+        if let Some(description) = &dimension.description {
+            text.push_str(&format!(" ({description})"));
+        }
+        text.push_str(":\n");
+        for (label, description) in &dimension.labels {
+            if description.is_empty() {
+                text.push_str(&format!("- {label}\n"));
+            } else {
+                text.push_str(&format!("- {label}: {description}\n"));
+            }
+        }
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn taxonomies_parse_and_aliases_resolve() {
+        let taxonomy = parse(
+            "sentiment:\n  description: How the writer feels\n  labels:\n    positive: Pleased\n    negative: Unhappy\n  aliases: {LABEL_0: negative, LABEL_1: positive}\nintent:\n  labels: {question: '', complaint: Reports a problem}\n",
+        )
+        .unwrap_or_else(|e| panic!("{e}"));
+        let sentiment = &taxonomy["sentiment"];
+        assert_eq!(sentiment.resolve("label_0"), Some("negative"));
+        assert_eq!(sentiment.resolve("Positive"), Some("positive"));
+        assert_eq!(sentiment.resolve("mixed"), None);
+        assert_eq!(
+            describe(&taxonomy),
+            "intent:\n- complaint: Reports a problem\n- question\nsentiment (How the writer feels):\n- negative: Unhappy\n- positive: Pleased\n"
+        );
+
+        assert!(parse("{}").is_err());
+        assert!(parse("sentiment: {labels: {positive: ''}}").is_err());
+        assert!(parse("sentiment: {labels: {a: '', b: ''}, aliases: {X: c}}").is_err());
+    }
+}
@@ -0,0 +1,702 @@
+//! The expression language of rules.
+//!
+//! - Literals: numbers (`2`, `0.5`, `1e3`), strings (`'kg'` or `"kg"`),
+//!   `true`, `false`, `null`
+//! - Fields: dotted payload paths (`qty`, `order.lines.0.price`); a
+//!   missing field is `null`
+//! - Operators, loosest first: `c ? a : b`, `||`, `&&`, `==` `!=`,
+//!   `<` `<=` `>` `>=`, `+` `-`, `*` `/` `%`, `^` (power), unary `-` `!`
+//! - Functions: `abs`, `ceil`, `floor`, `round(x[, digits])`, `sqrt`,
+//!   `pow`, `ln`, `log10`, `exp`, `min`, `max`, `clamp(x, lo, hi)`,
+//!   `coalesce(a, b, ...)`, `number`, `string`, `len`, `sum`, `avg`,
+//!   `convert(x, 'from', 'to')` (see [`crate::units`]), and over the
+//!   previous message with the same key: `prev(field)`, `delta(field)` and
+//!   `pct_change(field)`
+//!
+//! Arithmetic on `null` gives `null`, as does dividing by zero, so a rule
+//! whose inputs are missing leaves its field unset. `+` joins strings when
+//! either side is one. Using a value of the wrong type (multiplying a
+//! string) is an error.
+
+use crate::units;
+use serde_json::Value;
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Op {
+    Or,
+    And,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+    Pow,
+    Neg,
+    Not,
+}
+
+impl Op {
+    fn symbol(self) -> &'static str {
+        match self {
+            Self::Or => "||",
+            Self::And => "&&",
+            Self::Eq => "==",
+            Self::Ne => "!=",
+            Self::Lt => "<",
+            Self::Le => "<=",
+            Self::Gt => ">",
+            Self::Ge => ">=",
+            Self::Add => "+",
+            Self::Sub => "-",
+            Self::Mul => "*",
+            Self::Div => "/",
+            Self::Rem => "%",
+            Self::Pow => "^",
+            Self::Neg => "-",
+            Self::Not => "!",
+        }
+    }
+
+    /// Binding power of a binary operator: (left, right).
+    fn binding(self) -> (u8, u8) {
+        match self {
+            Self::Or => (3, 4),
+            Self::And => (5, 6),
+            Self::Eq | Self::Ne => (7, 8),
+            Self::Lt | Self::Le | Self::Gt | Self::Ge => (9, 10),
+            Self::Add | Self::Sub => (11, 12),
+            Self::Mul | Self::Div | Self::Rem => (13, 14),
+            // Right-associative: 2^3^2 is 2^9
+            Self::Pow => (18, 17),
+            Self::Neg | Self::Not => (0, 15),
+        }
+    }
+}
+
+/// A parsed expression.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Literal(Value),
+    Field(String),
+    Unary(Op, Box<Expr>),
+    Binary(Op, Box<Expr>, Box<Expr>),
+    Cond(Box<Expr>, Box<Expr>, Box<Expr>),
+    Call(String, Vec<Expr>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Num(f64),
+    Str(String),
+    Ident(String),
+    Op(&'static str),
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Num(n) => write!(f, "{n}"),
+            Self::Str(s) => write!(f, "'{s}'"),
+            Self::Ident(s) => f.write_str(s),
+            Self::Op(s) => f.write_str(s),
+        }
+    }
+}
+
+const SYMBOLS: [&str; 20] = [
+    "||", "&&", "==", "!=", "<=", ">=", "<", ">", "+", "-", "*", "/", "%", "^", "!", "(", ")", ",",
+    "?", ":",
+];
+
+fn tokenize(text: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut rest = text;
+    while let Some(c) = rest.chars().next() {
+        if c.is_whitespace() {
+            rest = &rest[c.len_utf8()..];
+        } else if c.is_ascii_digit()
+            || (c == '.' && rest[1..].starts_with(|c: char| c.is_ascii_digit()))
+        {
+            let mut end = rest
+                .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+                .unwrap_or(rest.len());
+            // An exponent: 1e3, 2.5E-4
+            let tail = &rest[end..];
+            if tail.starts_with(['e', 'E']) {
+                let digits = tail[1..].strip_prefix(['+', '-']).unwrap_or(&tail[1..]);
+                let count = digits
+                    .find(|c: char| !c.is_ascii_digit())
+                    .unwrap_or(digits.len());
+                if count > 0 {
+                    end += tail.len() - digits.len() + count;
+                }
+            }
+            let number = rest[..end]
+                .parse()
+                .map_err(|_| format!("invalid number '{}'", &rest[..end]))?;
+            tokens.push(Token::Num(number));
+            rest = &rest[end..];
+        } else if c == '\'' || c == '"' {
+            let close = rest[1..]
+                .find(c)
+                .ok_or_else(|| format!("unterminated string {rest}"))?;
+            tokens.push(Token::Str(rest[1..=close].to_string()));
+            rest = &rest[close + 2..];
+        } else if c.is_alphabetic() || c == '_' {
+            let end = rest
+                .find(|c: char| !(c.is_alphanumeric() || c == '_' || c == '.'))
+                .unwrap_or(rest.len());
+            let name = rest[..end].trim_end_matches('.');
+            tokens.push(Token::Ident(name.to_string()));
+            rest = &rest[name.len()..];
+        } else if let Some(symbol) = SYMBOLS.iter().find(|s| rest.starts_with(**s)) {
+            tokens.push(Token::Op(symbol));
+            rest = &rest[symbol.len()..];
+        } else {
+            return Err(format!("unexpected '{c}'"));
+        }
+    }
+    Ok(tokens)
+}
+
+/// The functions and how many arguments each takes (at least, at most).
+fn arity(name: &str) -> Option<(usize, usize)> {
+    Some(match name {
+        "abs" | "ceil" | "floor" | "sqrt" | "ln" | "log10" | "exp" | "number" | "string"
+        | "len" | "sum" | "avg" | "prev" | "delta" | "pct_change" => (1, 1),
+        "round" => (1, 2),
+        "pow" => (2, 2),
+        "clamp" | "convert" => (3, 3),
+        "min" | "max" | "coalesce" => (1, usize::MAX),
+        _ => return None,
+    })
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn eat(&mut self, symbol: &str) -> bool {
+        if matches!(self.peek(), Some(Token::Op(op)) if *op == symbol) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, symbol: &str) -> Result<(), String> {
+        if self.eat(symbol) {
+            Ok(())
+        } else {
+            Err(match self.peek() {
+                Some(token) => format!("expected '{symbol}', found '{token}'"),
+                None => format!("expected '{symbol}' at the end"),
+            })
+        }
+    }
+
+    fn expr(&mut self, min: u8) -> Result<Expr, String> {
+        let mut left = self.operand()?;
+        loop {
+            if min <= 1 && self.eat("?") {
+                let then = self.expr(0)?;
+                self.expect(":")?;
+                let otherwise = self.expr(1)?;
+                left = Expr::Cond(Box::new(left), Box::new(then), Box::new(otherwise));
+                continue;
+            }
+            let op = match self.peek() {
+                Some(Token::Op(symbol)) => match binary(symbol) {
+                    Some(op) => op,
+                    None => break,
+                },
+                _ => break,
+            };
+            let (lbp, rbp) = op.binding();
+            if lbp < min {
+                break;
+            }
+            self.pos += 1;
+            let right = self.expr(rbp)?;
+            left = Expr::Binary(op, Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn operand(&mut self) -> Result<Expr, String> {
+        match self.next() {
+            Some(Token::Num(n)) => Ok(Expr::Literal(Value::from(n))),
+            Some(Token::Str(s)) => Ok(Expr::Literal(Value::from(s))),
+            Some(Token::Op("(")) => {
+                let inner = self.expr(0)?;
+                self.expect(")")?;
+                Ok(inner)
+            }
+            Some(Token::Op("-")) => {
+                let operand = self.expr(Op::Neg.binding().1)?;
+                Ok(Expr::Unary(Op::Neg, Box::new(operand)))
+            }
+            Some(Token::Op("!")) => {
+                let operand = self.expr(Op::Not.binding().1)?;
+                Ok(Expr::Unary(Op::Not, Box::new(operand)))
+            }
+            Some(Token::Ident(name)) => match name.as_str() {
+                "true" => Ok(Expr::Literal(Value::Bool(true))),
+                "false" => Ok(Expr::Literal(Value::Bool(false))),
+                "null" => Ok(Expr::Literal(Value::Null)),
+                _ if self.eat("(") => self.call(name),
+                _ => Ok(Expr::Field(name)),
+            },
+            Some(token) => Err(format!("unexpected '{token}'")),
+            None => Err("unexpected end of expression".to_string()),
+        }
+    }
+
+    fn call(&mut self, name: String) -> Result<Expr, String> {
+        let (least, most) = arity(&name).ok_or_else(|| format!("unknown function {name}"))?;
+        let mut args = Vec::new();
+        if !self.eat(")") {
+            loop {
+                args.push(self.expr(0)?);
+                if self.eat(")") {
+                    break;
+                }
+                self.expect(",")?;
+            }
+        }
+        if args.len() < least || args.len() > most {
+            return Err(format!(
+                "{name} takes {}",
+                match (least, most) {
+                    (l, m) if l == m => format!("{l} argument(s)"),
+                    (l, usize::MAX) => format!("at least {l} argument(s)"),
+                    (l, m) => format!("{l} to {m} arguments"),
+                }
+            ));
+        }
+        if matches!(name.as_str(), "prev" | "delta" | "pct_change")
+            && !matches!(args[0], Expr::Field(_))
+        {
+            return Err(format!("{name} takes a field"));
+        }
+        Ok(Expr::Call(name, args))
+    }
+}
+
+fn binary(symbol: &str) -> Option<Op> {
+    Some(match symbol {
+        "||" => Op::Or,
+        "&&" => Op::And,
+        "==" => Op::Eq,
+        "!=" => Op::Ne,
+        "<" => Op::Lt,
+        "<=" => Op::Le,
+        ">" => Op::Gt,
+        ">=" => Op::Ge,
+        "+" => Op::Add,
+        "-" => Op::Sub,
+        "*" => Op::Mul,
+        "/" => Op::Div,
+        "%" => Op::Rem,
+        "^" => Op::Pow,
+        _ => return None,
+    })
+}
+
+/// Parse an expression.
+pub fn parse(text: &str) -> Result<Expr, String> {
+    let mut parser = Parser {
+        tokens: tokenize(text)?,
+        pos: 0,
+    };
+    let expr = parser.expr(0)?;
+    match parser.peek() {
+        None => Ok(expr),
+        Some(token) => Err(format!("unexpected '{token}'")),
+    }
+}
+
+/// What an expression is evaluated against.
+pub struct Scope<'a> {
+    /// The payload, with the results of earlier rules.
+    pub input: &'a Value,
+    /// The payload of the previous message with the same key.
+    pub previous: Option<&'a Value>,
+}
+
+/// A whole number as an integer, so `2 * 3` gives `6` rather than `6.0`.
+pub fn number(n: f64) -> Value {
+    const EXACT: f64 = 9_007_199_254_740_992.0;
+    if !n.is_finite() {
+        Value::Null
+    } else if n.fract() == 0.0 && n.abs() < EXACT {
+        Value::from(n as i64)
+    } else {
+        Value::from(n)
+    }
+}
+
+fn truthy(value: &Value) -> bool {
+    match value {
+        Value::Null => false,
+        Value::Bool(b) => *b,
+        Value::Number(n) => n.as_f64().is_some_and(|n| n != 0.0),
+        Value::String(s) => !s.is_empty(),
+        Value::Array(items) => !items.is_empty(),
+        Value::Object(map) => !map.is_empty(),
+    }
+}
+
+fn text(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+/// `value` as a number; strings of digits count.
+fn to_number(value: &Value) -> Option<f64> {
+    match value {
+        Value::Number(n) => n.as_f64(),
+        Value::String(s) => s.trim().parse().ok(),
+        Value::Bool(b) => Some(f64::from(u8::from(*b))),
+        _ => None,
+    }
+}
+
+fn numeric(what: &str, value: &Value) -> Result<Option<f64>, String> {
+    match value {
+        Value::Null => Ok(None),
+        Value::Number(n) => Ok(n.as_f64()),
+        other => Err(format!("{what} needs a number, not {other}")),
+    }
+}
+
+/// The numbers of an array, skipping nulls.
+fn numbers(what: &str, value: &Value) -> Result<Vec<f64>, String> {
+    match value {
+        Value::Array(items) => items
+            .iter()
+            .filter_map(|item| numeric(what, item).transpose())
+            .collect(),
+        Value::Null => Ok(Vec::new()),
+        other => Err(format!("{what} needs an array, not {other}")),
+    }
+}
+
+fn compare(op: Op, left: &Value, right: &Value) -> Result<Value, String> {
+    let ordering = match (left, right) {
+        (Value::Number(_), Value::Number(_)) => {
+            let (l, r) = (to_number(left), to_number(right));
+            l.zip(r).and_then(|(l, r)| l.partial_cmp(&r))
+        }
+        (Value::String(l), Value::String(r)) => Some(l.cmp(r)),
+        (Value::Null, _) | (_, Value::Null) => return Ok(Value::Null),
+        _ => {
+            return Err(format!("cannot compare {left} {} {right}", op.symbol()));
+        }
+    };
+    let Some(ordering) = ordering else {
+        return Ok(Value::Null);
+    };
+    Ok(Value::Bool(match op {
+        Op::Lt => ordering.is_lt(),
+        Op::Le => ordering.is_le(),
+        Op::Gt => ordering.is_gt(),
+        _ => ordering.is_ge(),
+    }))
+}
+
+fn equal(left: &Value, right: &Value) -> bool {
+    match (left, right) {
+        (Value::Number(_), Value::Number(_)) => to_number(left) == to_number(right),
+        _ => left == right,
+    }
+}
+
+impl Expr {
+    /// Evaluate the expression in `scope`.
+    pub fn eval(&self, scope: &Scope) -> Result<Value, String> {
+        match self {
+            Self::Literal(value) => Ok(value.clone()),
+            Self::Field(path) => Ok(primitive_common::key::lookup(scope.input, path)
+                .cloned()
+                .unwrap_or(Value::Null)),
+            Self::Unary(Op::Not, operand) => Ok(Value::Bool(!truthy(&operand.eval(scope)?))),
+            Self::Unary(_, operand) => {
+                Ok(numeric("-", &operand.eval(scope)?)?.map_or(Value::Null, |n| number(-n)))
+            }
+            Self::Cond(condition, then, otherwise) => {
+                if truthy(&condition.eval(scope)?) {
+                    then.eval(scope)
+                } else {
+                    otherwise.eval(scope)
+                }
+            }
+            Self::Binary(Op::And, left, right) => {
+                let left = left.eval(scope)?;
+                if truthy(&left) {
+                    right.eval(scope)
+                } else {
+                    Ok(left)
+                }
+            }
+            Self::Binary(Op::Or, left, right) => {
+                let left = left.eval(scope)?;
+                if truthy(&left) {
+                    Ok(left)
+                } else {
+                    right.eval(scope)
+                }
+            }
+            Self::Binary(op, left, right) => {
+                let (left, right) = (left.eval(scope)?, right.eval(scope)?);
+                binary_op(*op, &left, &right)
+            }
+            Self::Call(name, args) => self.call(name, args, scope),
+        }
+    }
+
+    fn call(&self, name: &str, args: &[Expr], scope: &Scope) -> Result<Value, String> {
+        if let ("prev" | "delta" | "pct_change", [Expr::Field(path)]) = (name, args) {
+            let previous = scope
+                .previous
+                .and_then(|previous| primitive_common::key::lookup(previous, path))
+                .cloned()
+                .unwrap_or(Value::Null);
+            if name == "prev" {
+                return Ok(previous);
+            }
+            let current = numeric(name, &args[0].eval(scope)?)?;
+            let previous = numeric(name, &previous)?;
+            return Ok(match (current, previous) {
+                (Some(current), Some(previous)) if name == "delta" => number(current - previous),
+                (Some(_), Some(0.0)) => Value::Null,
+                (Some(current), Some(previous)) => {
+                    number((current - previous) / previous.abs() * 100.0)
+                }
+                _ => Value::Null,
+            });
+        }
+        let values = args
+            .iter()
+            .map(|arg| arg.eval(scope))
+            .collect::<Result<Vec<_>, _>>()?;
+        let math = |f: fn(f64) -> f64| -> Result<Value, String> {
+            Ok(numeric(name, &values[0])?.map_or(Value::Null, |n| number(f(n))))
+        };
+        match name {
+            "abs" => math(f64::abs),
+            "ceil" => math(f64::ceil),
+            "floor" => math(f64::floor),
+            "sqrt" => math(f64::sqrt),
+            "ln" => math(f64::ln),
+            "log10" => math(f64::log10),
+            "exp" => math(f64::exp),
+            "round" => {
+                let digits = match values.get(1) {
+                    Some(digits) => numeric(name, digits)?.unwrap_or_default(),
+                    None => 0.0,
+                };
+                let scale = 10f64.powi(digits as i32);
+                Ok(numeric(name, &values[0])?
+                    .map_or(Value::Null, |n| number((n * scale).round() / scale)))
+            }
+            "pow" => binary_op(Op::Pow, &values[0], &values[1]),
+            "clamp" => {
+                let [x, lo, hi] = [&values[0], &values[1], &values[2]].map(|v| numeric(name, v));
+                Ok(match (x?, lo?, hi?) {
+                    (Some(x), Some(lo), Some(hi)) => number(x.max(lo).min(hi)),
+                    _ => Value::Null,
+                })
+            }
+            "min" | "max" => {
+                let mut found = Vec::new();
+                for value in &values {
+                    match value {
+                        Value::Array(_) => found.extend(numbers(name, value)?),
+                        other => found.extend(numeric(name, other)?),
+                    }
+                }
+                let pick: fn(f64, f64) -> f64 = if name == "min" { f64::min } else { f64::max };
+                Ok(found.into_iter().reduce(pick).map_or(Value::Null, number))
+            }
+            "coalesce" => Ok(values
+                .into_iter()
+                .find(|v| !v.is_null())
+                .unwrap_or_default()),
+            "number" => Ok(to_number(&values[0]).map_or(Value::Null, number)),
+            "string" => Ok(match &values[0] {
+                Value::Null => Value::Null,
+                other => Value::from(text(other)),
+            }),
+            "len" => Ok(match &values[0] {
+                Value::String(s) => Value::from(s.chars().count()),
+                Value::Array(items) => Value::from(items.len()),
+                Value::Object(map) => Value::from(map.len()),
+                Value::Null => Value::Null,
+                other => return Err(format!("len needs a string or array, not {other}")),
+            }),
+            "sum" => Ok(number(numbers(name, &values[0])?.iter().sum())),
+            "avg" => {
+                let found = numbers(name, &values[0])?;
+                Ok(match found.len() {
+                    0 => Value::Null,
+                    n => number(found.iter().sum::<f64>() / n as f64),
+                })
+            }
+            "convert" => {
+                let unit = |value: &Value| match value {
+                    Value::String(unit) => Ok(unit.clone()),
+                    other => Err(format!("convert needs unit names, not {other}")),
+                };
+                let (from, to) = (unit(&values[1])?, unit(&values[2])?);
+                match numeric(name, &values[0])? {
+                    Some(n) => units::convert(n, &from, &to).map(number),
+                    None => Ok(Value::Null),
+                }
+            }
+            _ => Err(format!("unknown function {name}")),
+        }
+    }
+}
+
+fn binary_op(op: Op, left: &Value, right: &Value) -> Result<Value, String> {
+    match op {
+        Op::Eq => return Ok(Value::Bool(equal(left, right))),
+        Op::Ne => return Ok(Value::Bool(!equal(left, right))),
+        Op::Lt | Op::Le | Op::Gt | Op::Ge => return compare(op, left, right),
+        Op::Add if left.is_string() || right.is_string() => {
+            if left.is_null() || right.is_null() {
+                return Ok(Value::Null);
+            }
+            return Ok(Value::from(text(left) + &text(right)));
+        }
+        _ => {}
+    }
+    let what = op.symbol();
+    let (Some(l), Some(r)) = (numeric(what, left)?, numeric(what, right)?) else {
+        return Ok(Value::Null);
+    };
+    let n = match op {
+        Op::Add => l + r,
+        Op::Sub => l - r,
+        Op::Mul => l * r,
+        Op::Div | Op::Rem if r == 0.0 => return Ok(Value::Null),
+        Op::Div => l / r,
+        Op::Rem => l % r,
+        _ => l.powf(r),
+    };
+    Ok(number(n))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn eval(text: &str, input: &Value) -> Result<Value, String> {
+        let scope = Scope {
+            input,
+            previous: None,
+        };
+        parse(text)?.eval(&scope)
+    }
+
+    #[test]
+    fn operators_follow_precedence() {
+        let input = json!({"qty": 3, "unit_price": 2.5, "order": {"lines": [{"price": 4}]}});
+        let cases = [
+            ("qty * unit_price", json!(7.5)),
+            ("1 + 2 * 3", json!(7)),
+            ("(1 + 2) * 3", json!(9)),
+            ("2 ^ 3 ^ 2", json!(512)),
+            ("-2 ^ 2", json!(-4)),
+            ("10 % 4 - 1", json!(1)),
+            ("order.lines.0.price * 2", json!(8)),
+            ("qty > 2 && unit_price < 3", json!(true)),
+            ("qty == 3.0", json!(true)),
+            ("!(qty >= 3) || false", json!(false)),
+            ("qty > 5 ? 'bulk' : qty > 1 ? 'few' : 'one'", json!("few")),
+            ("'#' + qty", json!("#3")),
+            ("1.5e3 / 2", json!(750)),
+            ("missing * 2", json!(null)),
+            ("qty / 0", json!(null)),
+        ];
+        for (text, expected) in cases {
+            assert_eq!(eval(text, &input), Ok(expected), "{text}");
+        }
+    }
+
+    #[test]
+    fn functions_compute_and_check_arguments() {
+        let input = json!({"prices": [2, 4, null, 6], "name": "Ada", "temp": 212});
+        let cases = [
+            ("round(2 / 3, 2)", json!(0.67)),
+            ("sum(prices)", json!(12)),
+            ("avg(prices)", json!(4)),
+            ("max(prices, 9)", json!(9)),
+            ("clamp(15, 0, 10)", json!(10)),
+            ("coalesce(missing, name)", json!("Ada")),
+            ("len(name) + len(prices)", json!(7)),
+            ("number('42') + 1", json!(43)),
+            ("convert(temp, 'f', 'c')", json!(100)),
+            ("round(convert(5, 'km', 'mi'), 3)", json!(3.107)),
+        ];
+        for (text, expected) in cases {
+            assert_eq!(eval(text, &input), Ok(expected), "{text}");
+        }
+
+        assert!(eval("name * 2", &input).is_err());
+        assert!(eval("convert(1, 'kg', 'km')", &input).is_err());
+        assert!(parse("frobnicate(1)").is_err());
+        assert!(parse("round()").is_err());
+        assert!(parse("prev(1 + 2)").is_err());
+        assert!(parse("1 +").is_err());
+        assert!(parse("(1").is_err());
+        assert!(parse("'open").is_err());
+    }
+
+    #[test]
+    fn previous_values_give_changes() {
+        let input = json!({"price": 110});
+        let previous = json!({"price": 100});
+        let scope = Scope {
+            input: &input,
+            previous: Some(&previous),
+        };
+        let eval = |text: &str| parse(text).and_then(|expr| expr.eval(&scope));
+        assert_eq!(eval("prev(price)"), Ok(json!(100)));
+        assert_eq!(eval("delta(price)"), Ok(json!(10)));
+        assert_eq!(eval("pct_change(price)"), Ok(json!(10)));
+
+        let first = Scope {
+            input: &input,
+            previous: None,
+        };
+        assert_eq!(
+            parse("pct_change(price)").and_then(|expr| expr.eval(&first)),
+            Ok(Value::Null)
+        );
+    }
+}
@@ -0,0 +1,348 @@
+//! Compute
+//!
+//! A Handler that derives payload fields from arithmetic rules — totals
+//! from quantities and prices, unit conversions, percentage changes from
+//! the previous message with the same key — and republishes the enriched
+//! payload, covering simple derivations without a script engine.
+//!
+//! # Data Flow
+//!
+//! 1. Receive an event matching configured subscriptions
+//! 2. Apply each `field = expression` rule in order (see [`rules`] and
+//!    [`expr`]); later rules see the fields earlier ones set
+//! 3. Republish the payload with the derived fields
+//!
+//! `prev`, `delta` and `pct_change` read the last message whose `--key`
+//! field had the same value (or the last message, without `--key`); only
+//! the fields they read are remembered, for up to `--max-keys` keys.
+//! A rule that fails to evaluate drops the message.
+//!
+//! # Messages Published
+//!
+//! - The incoming type, renamed by `--publish-as` (default:
+//!   `computed.{type}`)
+//!
+//! # Usage
+//!
+//! ```bash
+//! # Order totals
+//! compute -s 'order.*' \
+//!   --rule 'subtotal = qty * unit_price' \
+//!   --rule 'total = round(subtotal * (1 + tax_rate), 2)'
+//!
+//! # Imperial readings and the change since the sensor's last one
+//! compute -s 'sensor.reading' --key sensor_id \
+//!   --rule "temp_f = convert(temp_c, 'c', 'f')" \
+//!   --rule 'temp_change_pct = round(pct_change(temp_c), 1)'
+//!
+//! # Rules from a file
+//! compute -s 'metrics.*' --rules /etc/emergent/compute.yaml
+//! ```
+
+pub mod expr;
+pub mod rules;
+pub mod units;
+
+use clap::Parser;
+use emergent_client::EmergentMessage;
+use primitive_common::capabilities::{self, CapabilityArgs, Role, VERSION};
+use primitive_common::crypto::{KeyArgs, Keyring};
+use primitive_common::doctor::Report;
+use primitive_common::errors::{ERROR_EVENT_TYPE, ErrorArgs, ErrorCategory};
+use primitive_common::handler::{Bus, HandlerConfig, MessageHandler, run_handler};
+use primitive_common::key;
+use primitive_common::payload::{self, PayloadArgs};
+use rules::{History, Rule};
+use serde_json::Value;
+use std::path::PathBuf;
+
+/// Compute — derive payload fields from arithmetic rules.
+#[derive(Parser, Debug)]
+#[command(name = "compute", version = VERSION)]
+#[command(about = "Derive payload fields from arithmetic rules and republish the enriched payload")]
+struct Args {
+    /// Message types to subscribe to.
+    #[arg(short, long = "subscribe", required = true)]
+    subscribe: Vec<String>,
+
+    /// Rule as `field = expression` (e.g. `total = qty * unit_price`); repeatable, applied in order.
+    #[arg(long = "rule", env = "COMPUTE_RULES", value_delimiter = ';', value_parser = rules::parse)]
+    rules: Vec<Rule>,
+
+    /// YAML or JSON file listing rules; applied after `--rule`.
+    #[arg(long = "rules", env = "COMPUTE_RULES_FILE")]
+    rules_file: Option<PathBuf>,
+
+    /// Payload field (dotted path) whose value groups messages for `prev`, `delta` and `pct_change`.
+    #[arg(long, env = "COMPUTE_KEY")]
+    key: Option<String>,
+
+    /// Most keys whose previous values are remembered; the oldest is forgotten first.
+    #[arg(long, env = "COMPUTE_MAX_KEYS", default_value = "10000")]
+    max_keys: usize,
+
+    /// Message type to republish as; `{type}` stands for the incoming type.
+    #[arg(
+        long,
+        env = "COMPUTE_PUBLISH_AS",
+        default_value = "computed.{type}",
+        value_parser = parse_publish_as
+    )]
+    publish_as: String,
+
+    #[command(flatten)]
+    payload: PayloadArgs,
+
+    /// Verify the configuration and exit without connecting to the engine.
+    #[arg(long)]
+    self_test: bool,
+
+    #[command(flatten)]
+    errors: ErrorArgs,
+
+    #[command(flatten)]
+    capabilities: CapabilityArgs,
+
+    #[command(flatten)]
+    keys: KeyArgs,
+}
+
+fn parse_publish_as(value: &str) -> Result<String, String> {
+    match value {
+        "" => Err("must not be empty".to_string()),
+        // Republishing under the same type would feed straight back in
+        "{type}" => Err("must differ from the incoming type".to_string()),
+        _ => Ok(value.to_string()),
+    }
+}
+
+/// The message type `message_type` is republished as.
+fn publish_type(publish_as: &str, message_type: &str) -> String {
+    publish_as.replace("{type}", message_type)
+}
+
+/// Whether `message_type` is one this handler published.
+fn is_own(publish_as: &str, message_type: &str) -> bool {
+    match publish_as.split_once("{type}") {
+        Some((prefix, suffix)) => {
+            message_type.len() > prefix.len() + suffix.len()
+                && message_type.starts_with(prefix)
+                && message_type.ends_with(suffix)
+        }
+        None => message_type == publish_as,
+    }
+}
+
+/// The `--rule` rules followed by the `--rules` file's.
+fn load_rules(args: &Args) -> Result<Vec<Rule>, String> {
+    let mut all = args.rules.clone();
+    if let Some(path) = &args.rules_file {
+        all.extend(rules::load(path)?);
+    }
+    if all.is_empty() {
+        return Err("no rules: give --rule or --rules".to_string());
+    }
+    Ok(all)
+}
+
+/// The history key of `input`: the `--key` field's value, or `None` when
+/// the message has no such field.
+fn history_key(key_field: Option<&str>, input: &Value) -> Option<String> {
+    let Some(field) = key_field else {
+        return Some(String::new());
+    };
+    match key::lookup(input, field)? {
+        Value::Null => None,
+        Value::String(s) => Some(s.clone()),
+        other => Some(other.to_string()),
+    }
+}
+
+/// Apply `rules` to `input`, remembering the fields later messages with
+/// the same key read.
+fn compute(
+    rules: &[Rule],
+    history: &mut History,
+    key_field: Option<&str>,
+    mut input: Value,
+) -> Result<Value, String> {
+    let key = history_key(key_field, &input);
+    let previous = key.as_deref().and_then(|key| history.get(key)).cloned();
+    rules::apply(rules, &mut input, previous.as_ref())?;
+    if let Some(key) = key {
+        history.record(key, &input);
+    }
+    Ok(input)
+}
+
+/// Run the primitive with `args` as its command line (the first item is
+/// the program name, as with `std::env::args_os`).
+pub async fn run<I, T>(args: I) -> Result<(), Box<dyn std::error::Error>>
+where
+    I: IntoIterator<Item = T>,
+    T: Into<std::ffi::OsString> + Clone,
+{
+    let args = Args::parse_from(args);
+
+    // Get the handler name from environment (set by engine) or use default
+    let name = std::env::var("EMERGENT_NAME").unwrap_or_else(|_| "compute".to_string());
+
+    let rules = load_rules(&args);
+    if args.self_test {
+        let mut report = Report::new(&name);
+        report.check(
+            "rules",
+            rules
+                .as_ref()
+                .map(|rules| {
+                    let targets: Vec<&str> = rules.iter().map(|r| r.target.as_str()).collect();
+                    format!("{} rule(s): {}", rules.len(), targets.join(", "))
+                })
+                .map_err(Clone::clone),
+        );
+        args.payload.self_test(&mut report);
+        args.keys.self_test(&mut report);
+        report.finish();
+    }
+    let rules = match rules {
+        Ok(rules) => rules,
+        Err(e) => {
+            eprintln!("Error: {e}");
+            std::process::exit(1);
+        }
+    };
+    let history = History::new(rules::remembered(&rules), args.max_keys);
+
+    if let Err(e) = args.payload.encryption.validate() {
+        eprintln!("Error: {e}");
+        std::process::exit(1);
+    }
+    let keys = match args.keys.load() {
+        Ok(keys) => keys,
+        Err(e) => {
+            eprintln!("Error: failed to load identity file: {e}");
+            std::process::exit(1);
+        }
+    };
+
+    let topics_refs: Vec<&str> = args.subscribe.iter().map(String::as_str).collect();
+    let mut produces = vec![args.publish_as.as_str()];
+    if args.errors.emit_errors {
+        produces.push(ERROR_EVENT_TYPE);
+    }
+    let descriptor =
+        args.capabilities
+            .describe(&name, Role::Handler, &topics_refs, &produces, &args);
+    if args.capabilities.capabilities {
+        capabilities::print(&descriptor);
+    }
+
+    let config = HandlerConfig {
+        name: &name,
+        subscribe: &args.subscribe,
+        announce: args.capabilities.announce.then_some(&descriptor),
+        emit_errors: args.errors.emit_errors,
+    };
+    let mut compute = Compute {
+        args: &args,
+        rules,
+        history,
+        keys,
+    };
+    run_handler(config, &mut compute).await
+}
+
+/// The rules and what they remember between messages.
+struct Compute<'a> {
+    args: &'a Args,
+    rules: Vec<Rule>,
+    history: History,
+    keys: Option<Keyring>,
+}
+
+impl MessageHandler for Compute<'_> {
+    type Wake = ();
+
+    /// Apply the rules to one message and republish it.
+    async fn handle(&mut self, msg: &EmergentMessage, bus: &Bus) {
+        let args = self.args;
+        let message_type = msg.message_type.as_str();
+        if is_own(&args.publish_as, message_type) {
+            return;
+        }
+        let input = match payload::decode(msg.payload(), self.keys.as_ref()).await {
+            Ok(p) => p.into_owned(),
+            Err(e) => {
+                let error = format!("failed to decode payload: {e}");
+                eprintln!("compute: {error}");
+                bus.report_error(msg, ErrorCategory::Parse, &error).await;
+                return;
+            }
+        };
+
+        let output = match compute(&self.rules, &mut self.history, args.key.as_deref(), input) {
+            Ok(output) => output,
+            Err(e) => {
+                let error = format!("rule failed: {e}");
+                eprintln!("compute: {error} ({message_type} {})", msg.id());
+                bus.report_error(msg, ErrorCategory::Parse, &error).await;
+                return;
+            }
+        };
+        let publish_as = publish_type(&args.publish_as, message_type);
+        let payload = match payload::encode(&publish_as, output, &args.payload).await {
+            Ok(p) => p,
+            Err(e) => {
+                eprintln!("compute: failed to encode output: {e}");
+                return;
+            }
+        };
+        let message = EmergentMessage::new(&publish_as)
+            .with_causation_id(msg.id())
+            .with_payload(payload);
+        bus.publish(message).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn args(extra: &[&str]) -> Args {
+        let mut argv = vec!["compute", "-s", "sensor.*"];
+        argv.extend_from_slice(extra);
+        Args::try_parse_from(argv).unwrap_or_else(|e| panic!("{e}"))
+    }
+
+    #[test]
+    fn changes_are_computed_per_key() {
+        let cli = args(&[
+            "--key",
+            "sensor_id",
+            "--rule",
+            "temp_f = convert(temp_c, 'c', 'f'); change = delta(temp_c)",
+        ]);
+        let rules = load_rules(&cli).unwrap_or_else(|e| panic!("{e}"));
+        let mut history = History::new(rules::remembered(&rules), cli.max_keys);
+        let mut reading = |input: Value| {
+            compute(&rules, &mut history, cli.key.as_deref(), input)
+                .unwrap_or_else(|e| panic!("{e}"))
+        };
+
+        let first = reading(json!({"sensor_id": "a", "temp_c": 20}));
+        assert_eq!(first, json!({"sensor_id": "a", "temp_c": 20, "temp_f": 68}));
+        let other = reading(json!({"sensor_id": "b", "temp_c": 30}));
+        assert_eq!(other["change"], Value::Null);
+        let second = reading(json!({"sensor_id": "a", "temp_c": 25}));
+        assert_eq!(second["change"], 5);
+        // Without the key field there is no previous message to compare with
+        let unkeyed = reading(json!({"temp_c": 25}));
+        assert_eq!(unkeyed["change"], Value::Null);
+
+        assert!(load_rules(&args(&[])).is_err());
+        assert!(Args::try_parse_from(["compute", "-s", "a", "--rule", "x ="]).is_err());
+        assert!(is_own("computed.{type}", "computed.sensor.reading"));
+        assert!(!is_own("computed.{type}", "sensor.reading"));
+    }
+}
@@ -0,0 +1,6 @@
+//! `compute` binary; the primitive itself lives in the library.
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    compute::run(std::env::args_os()).await
+}
@@ -0,0 +1,245 @@
+//! Rules: `field = expression`, applied in order.
+//!
+//! Each rule sets a dotted payload field from an expression (see
+//! [`crate::expr`]), creating parent objects as needed. Later rules see the
+//! fields earlier ones set:
+//!
+//! ```yaml
+//! # --rules file: a list of rules
+//! - subtotal = qty * unit_price
+//! - total = round(subtotal * (1 + tax_rate), 2)
+//! - shipping.weight_lb = convert(shipping.weight_kg, 'kg', 'lb')
+//! - price_change_pct = round(pct_change(price), 2)
+//! ```
+
+use crate::expr::{self, Expr, Scope};
+use serde_json::{Map, Value};
+use std::collections::{HashMap, VecDeque};
+use std::path::Path;
+
+/// One `field = expression` rule.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rule {
+    pub target: String,
+    pub expr: Expr,
+}
+
+/// Parse `field = expression`.
+pub fn parse(text: &str) -> Result<Rule, String> {
+    let (target, expression) = text
+        .split_once('=')
+        .ok_or_else(|| format!("'{text}': expected field = expression"))?;
+    let target = target.trim();
+    let target = target.strip_prefix("payload.").unwrap_or(target);
+    let valid = !target.is_empty()
+        && target
+            .split('.')
+            .all(|s| !s.is_empty() && s.chars().all(|c| c.is_alphanumeric() || c == '_'));
+    if !valid {
+        return Err(format!("'{text}': invalid field '{target}'"));
+    }
+    let expr = expr::parse(expression).map_err(|e| format!("'{text}': {e}"))?;
+    Ok(Rule {
+        target: target.to_string(),
+        expr,
+    })
+}
+
+/// Read a `--rules` file: a YAML (or JSON) list of rules.
+pub fn load(path: &Path) -> Result<Vec<Rule>, String> {
+    let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {e}", path.display()))?;
+    let rules: Vec<String> =
+        serde_yaml_ng::from_str(&text).map_err(|e| format!("{}: {e}", path.display()))?;
+    rules
+        .iter()
+        .map(|rule| parse(rule).map_err(|e| format!("{}: {e}", path.display())))
+        .collect()
+}
+
+/// Set the dotted `path` in `input`, creating objects along the way.
+fn set(input: &mut Value, path: &str, value: Value) -> Result<(), String> {
+    let mut current = input;
+    let mut segments = path.split('.').peekable();
+    while let Some(segment) = segments.next() {
+        let Value::Object(map) = current else {
+            return Err(format!("cannot set {path}: a parent is not an object"));
+        };
+        if segments.peek().is_none() {
+            map.insert(segment.to_string(), value);
+            return Ok(());
+        }
+        current = map
+            .entry(segment)
+            .or_insert_with(|| Value::Object(Map::new()));
+    }
+    Ok(())
+}
+
+/// Apply `rules` to `input` in order, returning how many set a field.
+pub fn apply(rules: &[Rule], input: &mut Value, previous: Option<&Value>) -> Result<usize, String> {
+    let mut set_count = 0;
+    for rule in rules {
+        let scope = Scope {
+            input: &*input,
+            previous,
+        };
+        let value = rule
+            .expr
+            .eval(&scope)
+            .map_err(|e| format!("{}: {e}", rule.target))?;
+        // Missing inputs leave the field as it was
+        if value.is_null() {
+            continue;
+        }
+        set(input, &rule.target, value)?;
+        set_count += 1;
+    }
+    Ok(set_count)
+}
+
+/// The fields `prev`, `delta` and `pct_change` read, so only they are kept.
+pub fn remembered(rules: &[Rule]) -> Vec<String> {
+    fn walk(expr: &Expr, found: &mut Vec<String>) {
+        match expr {
+            Expr::Call(name, args) => {
+                if let ("prev" | "delta" | "pct_change", [Expr::Field(path)]) =
+                    (name.as_str(), args.as_slice())
+                    && !found.contains(path)
+                {
+                    found.push(path.clone());
+                }
+                args.iter().for_each(|arg| walk(arg, found));
+            }
+            Expr::Unary(_, operand) => walk(operand, found),
+            Expr::Binary(_, left, right) => {
+                walk(left, found);
+                walk(right, found);
+            }
+            Expr::Cond(condition, then, otherwise) => {
+                walk(condition, found);
+                walk(then, found);
+                walk(otherwise, found);
+            }
+            Expr::Literal(_) | Expr::Field(_) => {}
+        }
+    }
+    let mut found = Vec::new();
+    for rule in rules {
+        walk(&rule.expr, &mut found);
+    }
+    found
+}
+
+/// The remembered fields of the last message per key, for `prev` and
+/// friends, evicting the oldest key past `capacity`.
+pub struct History {
+    fields: Vec<String>,
+    capacity: usize,
+    entries: HashMap<String, Value>,
+    order: VecDeque<String>,
+}
+
+impl History {
+    pub fn new(fields: Vec<String>, capacity: usize) -> Self {
+        Self {
+            fields,
+            capacity,
+            entries: HashMap::new(),
+            order: VecDeque::new(),
+        }
+    }
+
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        self.entries.get(key)
+    }
+
+    /// Remember `output`'s fields under `key`.
+    pub fn record(&mut self, key: String, output: &Value) {
+        if self.fields.is_empty() || self.capacity == 0 {
+            return;
+        }
+        let mut kept = Value::Object(Map::new());
+        for field in &self.fields {
+            if let Some(value) = primitive_common::key::lookup(output, field) {
+                let path = field.strip_prefix("payload.").unwrap_or(field);
+                let _ = set(&mut kept, path, value.clone());
+            }
+        }
+        if self.entries.insert(key.clone(), kept).is_none() {
+            self.order.push_back(key);
+        }
+        while self.entries.len() > self.capacity {
+            let Some(oldest) = self.order.pop_front() else {
+                break;
+            };
+            self.entries.remove(&oldest);
+        }
+    }
+
+    /// How many keys are remembered.
+    pub fn keys(&self) -> usize {
+        self.entries.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn rules_set_fields_in_order() {
+        let rules: Vec<Rule> = [
+            "subtotal = qty * unit_price",
+            "payload.order.total = round(subtotal * (1 + tax_rate), 2)",
+            "discount = coupon * 2",
+        ]
+        .iter()
+        .map(|rule| parse(rule).unwrap_or_else(|e| panic!("{e}")))
+        .collect();
+        let mut input = json!({"qty": 3, "unit_price": 2.5, "tax_rate": 0.2});
+        assert_eq!(apply(&rules, &mut input, None), Ok(2));
+        assert_eq!(
+            input,
+            json!({
+                "qty": 3,
+                "unit_price": 2.5,
+                "tax_rate": 0.2,
+                "subtotal": 7.5,
+                "order": {"total": 9},
+            })
+        );
+
+        let mut scalar = json!({"order": 5, "subtotal": 1, "tax_rate": 0});
+        assert!(apply(&rules[1..2], &mut scalar, None).is_err());
+
+        assert!(parse("total qty * 2").is_err());
+        assert!(parse("a..b = 1").is_err());
+        assert!(parse(" = 1").is_err());
+        assert!(parse("x = 1 +").is_err());
+    }
+
+    #[test]
+    fn history_remembers_only_the_fields_rules_read() {
+        let rules = vec![
+            parse("change = delta(price)").unwrap_or_else(|e| panic!("{e}")),
+            parse("was = prev(stats.count) + prev(price)").unwrap_or_else(|e| panic!("{e}")),
+        ];
+        assert_eq!(remembered(&rules), ["price", "stats.count"]);
+
+        let mut history = History::new(remembered(&rules), 2);
+        history.record(
+            "a".to_string(),
+            &json!({"price": 10, "stats": {"count": 2}, "name": "x"}),
+        );
+        assert_eq!(
+            history.get("a"),
+            Some(&json!({"price": 10, "stats": {"count": 2}}))
+        );
+
+        history.record("b".to_string(), &json!({"price": 1}));
+        history.record("c".to_string(), &json!({"price": 1}));
+        assert_eq!(history.keys(), 2);
+        assert_eq!(history.get("a"), None);
+    }
+}
@@ -0,0 +1,109 @@
+//! Unit conversions for `convert(x, 'from', 'to')`.
+//!
+//! Units convert within their dimension: length (`mm`, `cm`, `m`, `km`,
+//! `in`, `ft`, `yd`, `mi`, `nmi`), mass (`mg`, `g`, `kg`, `t`, `oz`, `lb`),
+//! time (`ms`, `s`, `min`, `h`, `d`), data (`b`, `kb`, `mb`, `gb`, `tb`,
+//! `kib`, `mib`, `gib`, `tib`), speed (`m/s`, `km/h`, `mph`, `kn`), volume
+//! (`ml`, `l`, `gal`, `floz`), energy (`wh`, `kwh`, `mwh`, `j`, `kj`) and
+//! temperature (`c`, `f`, `k`). Names are case-insensitive.
+
+/// The dimension of a unit and its size in the dimension's base unit.
+fn unit(name: &str) -> Option<(&'static str, f64)> {
+    Some(match name.to_ascii_lowercase().as_str() {
+        "mm" => ("length", 0.001),
+        "cm" => ("length", 0.01),
+        "m" => ("length", 1.0),
+        "km" => ("length", 1000.0),
+        "in" => ("length", 0.0254),
+        "ft" => ("length", 0.3048),
+        "yd" => ("length", 0.9144),
+        "mi" => ("length", 1609.344),
+        "nmi" => ("length", 1852.0),
+        "mg" => ("mass", 0.001),
+        "g" => ("mass", 1.0),
+        "kg" => ("mass", 1000.0),
+        "t" => ("mass", 1_000_000.0),
+        "oz" => ("mass", 28.349_523_125),
+        "lb" => ("mass", 453.592_37),
+        "ms" => ("time", 0.001),
+        "s" => ("time", 1.0),
+        "min" => ("time", 60.0),
+        "h" => ("time", 3600.0),
+        "d" => ("time", 86_400.0),
+        "b" => ("data", 1.0),
+        "kb" => ("data", 1e3),
+        "mb" => ("data", 1e6),
+        "gb" => ("data", 1e9),
+        "tb" => ("data", 1e12),
+        "kib" => ("data", 1024.0),
+        "mib" => ("data", 1_048_576.0),
+        "gib" => ("data", 1_073_741_824.0),
+        "tib" => ("data", 1_099_511_627_776.0),
+        "m/s" => ("speed", 1.0),
+        "km/h" => ("speed", 1000.0 / 3600.0),
+        "mph" => ("speed", 1609.344 / 3600.0),
+        "kn" => ("speed", 1852.0 / 3600.0),
+        "ml" => ("volume", 0.001),
+        "l" => ("volume", 1.0),
+        "gal" => ("volume", 3.785_411_784),
+        "floz" => ("volume", 0.029_573_529_562_5),
+        "j" => ("energy", 1.0),
+        "kj" => ("energy", 1000.0),
+        "wh" => ("energy", 3600.0),
+        "kwh" => ("energy", 3.6e6),
+        "mwh" => ("energy", 3.6e9),
+        _ => return None,
+    })
+}
+
+/// A temperature in `unit` as degrees Celsius, or back.
+fn celsius(value: f64, unit: &str, to: bool) -> Option<f64> {
+    Some(match (unit.to_ascii_lowercase().as_str(), to) {
+        ("c", _) => value,
+        ("f", false) => (value - 32.0) * 5.0 / 9.0,
+        ("f", true) => value * 9.0 / 5.0 + 32.0,
+        ("k", false) => value - 273.15,
+        ("k", true) => value + 273.15,
+        _ => return None,
+    })
+}
+
+/// `value` in `from` units, converted to `to` units.
+pub fn convert(value: f64, from: &str, to: &str) -> Result<f64, String> {
+    if let Some(c) = celsius(value, from, false) {
+        return celsius(c, to, true).ok_or_else(|| format!("cannot convert temperature to '{to}'"));
+    }
+    let (from_dimension, from_size) = unit(from).ok_or_else(|| format!("unknown unit '{from}'"))?;
+    let (to_dimension, to_size) = unit(to).ok_or_else(|| format!("unknown unit '{to}'"))?;
+    if from_dimension != to_dimension {
+        return Err(format!(
+            "cannot convert {from_dimension} ('{from}') to {to_dimension} ('{to}')"
+        ));
+    }
+    Ok(value * from_size / to_size)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn units_convert_within_their_dimension() {
+        let close = |got: Result<f64, String>, want: f64| {
+            let got = got.unwrap_or_else(|e| panic!("{e}"));
+            assert!((got - want).abs() < 1e-9, "{got} != {want}");
+        };
+        close(convert(1.0, "mi", "km"), 1.609344);
+        close(convert(2.0, "lb", "kg"), 0.90718474);
+        close(convert(90.0, "min", "h"), 1.5);
+        close(convert(1.0, "GiB", "MB"), 1073.741824);
+        close(convert(36.0, "km/h", "m/s"), 10.0);
+        close(convert(3.6, "kWh", "kJ"), 12960.0);
+        close(convert(100.0, "c", "f"), 212.0);
+        close(convert(0.0, "k", "c"), -273.15);
+
+        assert!(convert(1.0, "kg", "m").is_err());
+        assert!(convert(1.0, "c", "kg").is_err());
+        assert!(convert(1.0, "furlong", "m").is_err());
+    }
+}
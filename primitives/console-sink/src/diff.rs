@@ -0,0 +1,215 @@
+//! Diff mode (`--diff`).
+//!
+//! State-like topics (configs, metric snapshots) repeat mostly unchanged
+//! payloads, so instead of printing each one whole, diff mode prints what
+//! changed since the previous message with the same key:
+//!
+//! ```text
+//! config.updated [svc-a]
+//!   ~ replicas: 2 → 3
+//!   + labels.tier: "gold"
+//!   - debug: true
+//! ```
+//!
+//! The key is the message type plus, with `--diff-key payload.id`, the
+//! value of that field. The first message for a key is printed in full.
+//! Objects are compared field by field and arrays element by element; any
+//! other difference replaces the value. At most `--diff-max-keys` previous
+//! payloads are kept; the oldest key is forgotten beyond that.
+
+use crate::style::Style;
+use primitive_common::key;
+use serde_json::Value;
+use std::collections::{HashMap, VecDeque};
+
+/// One difference between two payloads, at a dotted path.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Change {
+    Added(String, Value),
+    Removed(String, Value),
+    Changed(String, Value, Value),
+}
+
+/// The changes turning `old` into `new`.
+pub fn diff(old: &Value, new: &Value) -> Vec<Change> {
+    let mut changes = Vec::new();
+    walk("", old, new, &mut changes);
+    changes
+}
+
+fn walk(path: &str, old: &Value, new: &Value, changes: &mut Vec<Change>) {
+    match (old, new) {
+        (Value::Object(before), Value::Object(after)) => {
+            for (field, was) in before {
+                let at = join(path, field);
+                match after.get(field) {
+                    Some(now) => walk(&at, was, now, changes),
+                    None => changes.push(Change::Removed(at, was.clone())),
+                }
+            }
+            for (field, now) in after {
+                if !before.contains_key(field) {
+                    changes.push(Change::Added(join(path, field), now.clone()));
+                }
+            }
+        }
+        (Value::Array(before), Value::Array(after)) => {
+            for (i, was) in before.iter().enumerate() {
+                let at = join(path, &i.to_string());
+                match after.get(i) {
+                    Some(now) => walk(&at, was, now, changes),
+                    None => changes.push(Change::Removed(at, was.clone())),
+                }
+            }
+            for (i, now) in after.iter().enumerate().skip(before.len()) {
+                changes.push(Change::Added(join(path, &i.to_string()), now.clone()));
+            }
+        }
+        _ if old != new => {
+            changes.push(Change::Changed(path.to_string(), old.clone(), new.clone()))
+        }
+        _ => {}
+    }
+}
+
+fn join(path: &str, segment: &str) -> String {
+    if path.is_empty() {
+        segment.to_string()
+    } else {
+        format!("{path}.{segment}")
+    }
+}
+
+/// Render changes one per line, indented under the message header.
+pub fn render(changes: &[Change], style: Style) -> String {
+    if changes.is_empty() {
+        return format!("  {}", style.dim("(no changes)"));
+    }
+    let label = |path: &str| if path.is_empty() { "." } else { path }.to_string();
+    changes
+        .iter()
+        .map(|change| match change {
+            Change::Added(path, value) => style.green(&format!("  + {}: {value}", label(path))),
+            Change::Removed(path, value) => style.red(&format!("  - {}: {value}", label(path))),
+            Change::Changed(path, old, new) => {
+                style.yellow(&format!("  ~ {}: {old} → {new}", label(path)))
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// The last payload seen per key.
+pub struct Tracker {
+    key: Option<String>,
+    capacity: usize,
+    previous: HashMap<String, Value>,
+    /// Keys in first-seen order, for eviction.
+    order: VecDeque<String>,
+}
+
+impl Tracker {
+    pub fn new(key: Option<String>, capacity: usize) -> Self {
+        Self {
+            key,
+            capacity: capacity.max(1),
+            previous: HashMap::new(),
+            order: VecDeque::new(),
+        }
+    }
+
+    /// The key `payload` is compared under: its type, plus the `--diff-key`
+    /// value when one is configured and present.
+    pub fn key_of(&self, message_type: &str, payload: &Value) -> String {
+        match self
+            .key
+            .as_deref()
+            .and_then(|path| key::extract(payload, path))
+        {
+            Some(value) => format!("{message_type} [{value}]"),
+            None => message_type.to_string(),
+        }
+    }
+
+    /// Remember `payload` under `key`, returning the changes since the
+    /// previous one, or `None` if this is the first.
+    pub fn observe(&mut self, key: &str, payload: &Value) -> Option<Vec<Change>> {
+        if let Some(previous) = self.previous.get_mut(key) {
+            let changes = diff(previous, payload);
+            *previous = payload.clone();
+            return Some(changes);
+        }
+        if self.previous.len() >= self.capacity
+            && let Some(oldest) = self.order.pop_front()
+        {
+            self.previous.remove(&oldest);
+        }
+        self.order.push_back(key.to_string());
+        self.previous.insert(key.to_string(), payload.clone());
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn nested_changes_are_reported_by_path() {
+        let old = json!({"replicas": 2, "debug": true, "labels": {"app": "a"}, "ports": [80]});
+        let new =
+            json!({"replicas": 3, "labels": {"app": "a", "tier": "gold"}, "ports": [80, 443]});
+        assert_eq!(
+            diff(&old, &new),
+            vec![
+                Change::Removed("debug".to_string(), json!(true)),
+                Change::Added("labels.tier".to_string(), json!("gold")),
+                Change::Added("ports.1".to_string(), json!(443)),
+                Change::Changed("replicas".to_string(), json!(2), json!(3)),
+            ]
+        );
+        assert!(diff(&old, &old).is_empty());
+        assert_eq!(
+            diff(&json!(1), &json!("1")),
+            vec![Change::Changed(String::new(), json!(1), json!("1"))]
+        );
+    }
+
+    #[test]
+    fn changes_render_one_per_line() {
+        let changes = vec![
+            Change::Changed("replicas".to_string(), json!(2), json!(3)),
+            Change::Added("tier".to_string(), json!("gold")),
+        ];
+        assert_eq!(
+            render(&changes, Style::plain()),
+            "  ~ replicas: 2 → 3\n  + tier: \"gold\""
+        );
+        assert_eq!(render(&[], Style::plain()), "  (no changes)");
+        assert_eq!(
+            render(&changes[1..], Style::colored()),
+            "\x1b[32m  + tier: \"gold\"\x1b[0m"
+        );
+    }
+
+    #[test]
+    fn payloads_are_compared_per_key() {
+        let mut tracker = Tracker::new(Some("payload.id".to_string()), 2);
+        let a1 = json!({"id": "a", "n": 1});
+        let key = tracker.key_of("svc", &a1);
+        assert_eq!(key, "svc [a]");
+        assert_eq!(tracker.observe(&key, &a1), None);
+        assert_eq!(tracker.observe("svc [b]", &json!({"id": "b"})), None);
+        let changes = tracker.observe(&key, &json!({"id": "a", "n": 2}));
+        assert_eq!(
+            changes,
+            Some(vec![Change::Changed("n".to_string(), json!(1), json!(2))])
+        );
+
+        // A third key evicts the oldest
+        assert_eq!(tracker.observe("svc [c]", &json!({})), None);
+        assert_eq!(tracker.observe(&key, &a1), None);
+        assert_eq!(tracker.key_of("svc", &json!({})), "svc");
+    }
+}
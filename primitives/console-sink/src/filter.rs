@@ -0,0 +1,181 @@
+//! Message filter expressions (`--trigger-filter`).
+//!
+//! An expression is one or more clauses joined by `&&`, all of which must
+//! hold. A clause compares a field with a value, or on its own requires the
+//! field to be present and not `null`:
+//!
+//! ```text
+//! payload.level == "error"
+//! payload.status >= 500 && payload.path ~= /checkout
+//! type == alert.fired && payload.ack
+//! ```
+//!
+//! Fields are payload paths (`payload.` is optional); `type` is the message
+//! type. Values are JSON literals, or bare strings when they are not valid
+//! JSON. `<`, `<=`, `>`, `>=` compare numbers; `~=` tests whether the field's
+//! text contains the value.
+
+use primitive_common::key;
+use serde_json::Value;
+use std::cmp::Ordering;
+
+/// Comparison operators and their spellings.
+const OPERATORS: &[(&str, Op)] = &[
+    ("==", Op::Eq),
+    ("!=", Op::Ne),
+    (">=", Op::Ge),
+    ("<=", Op::Le),
+    ("~=", Op::Contains),
+    (">", Op::Gt),
+    ("<", Op::Lt),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Op {
+    Eq,
+    Ne,
+    Gt,
+    Ge,
+    Lt,
+    Le,
+    Contains,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Clause {
+    field: String,
+    /// `None` for a bare presence test.
+    test: Option<(Op, Value)>,
+}
+
+/// A parsed filter expression.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Filter {
+    clauses: Vec<Clause>,
+}
+
+impl Filter {
+    /// Parse an expression; used as a clap value parser.
+    pub fn parse(expression: &str) -> Result<Self, String> {
+        let clauses = expression
+            .split("&&")
+            .map(parse_clause)
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Self { clauses })
+    }
+
+    /// Whether a message of `message_type` carrying `payload` matches.
+    pub fn matches(&self, message_type: &str, payload: &Value) -> bool {
+        let message_type = Value::String(message_type.to_string());
+        self.clauses.iter().all(|clause| {
+            let field = if clause.field == "type" {
+                Some(&message_type)
+            } else {
+                key::lookup(payload, &clause.field)
+            };
+            match (&clause.test, field) {
+                (None, field) => field.is_some_and(|v| !v.is_null()),
+                (Some((Op::Ne, expected)), field) => field != Some(expected),
+                (Some(_), None) => false,
+                (Some((op, expected)), Some(actual)) => compare(*op, actual, expected),
+            }
+        })
+    }
+}
+
+fn parse_clause(clause: &str) -> Result<Clause, String> {
+    let clause = clause.trim();
+    if clause.is_empty() {
+        return Err("empty clause in filter".to_string());
+    }
+    // The first operator wins; at the same position the longer spelling, so `>=` is not read as `>`
+    let found = OPERATORS
+        .iter()
+        .filter_map(|(token, op)| clause.find(token).map(|at| (at, *token, *op)))
+        .min_by_key(|(at, token, _)| (*at, usize::MAX - token.len()));
+    let Some((at, token, op)) = found else {
+        return Ok(Clause {
+            field: field_name(clause)?,
+            test: None,
+        });
+    };
+    let value = clause[at + token.len()..].trim();
+    if value.is_empty() {
+        return Err(format!("missing value after '{token}' in '{clause}'"));
+    }
+    let value = serde_json::from_str(value).unwrap_or_else(|_| Value::String(value.to_string()));
+    if matches!(op, Op::Gt | Op::Ge | Op::Lt | Op::Le) && !value.is_number() {
+        return Err(format!("'{token}' needs a number in '{clause}'"));
+    }
+    Ok(Clause {
+        field: field_name(&clause[..at])?,
+        test: Some((op, value)),
+    })
+}
+
+fn field_name(field: &str) -> Result<String, String> {
+    let field = field.trim();
+    if field.is_empty() || field.contains(char::is_whitespace) {
+        return Err(format!("invalid field '{field}' in filter"));
+    }
+    Ok(field.to_string())
+}
+
+fn compare(op: Op, actual: &Value, expected: &Value) -> bool {
+    let ordering = || match (actual.as_f64(), expected.as_f64()) {
+        (Some(a), Some(b)) => a.partial_cmp(&b),
+        _ => None,
+    };
+    match op {
+        Op::Eq => actual == expected,
+        Op::Ne => actual != expected,
+        Op::Gt => ordering() == Some(Ordering::Greater),
+        Op::Ge => matches!(ordering(), Some(Ordering::Greater | Ordering::Equal)),
+        Op::Lt => ordering() == Some(Ordering::Less),
+        Op::Le => matches!(ordering(), Some(Ordering::Less | Ordering::Equal)),
+        Op::Contains => text(actual).contains(&text(expected)),
+    }
+}
+
+fn text(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn matches(expression: &str, payload: &Value) -> bool {
+        Filter::parse(expression)
+            .unwrap_or_else(|e| panic!("parse {expression}: {e}"))
+            .matches("http.request", payload)
+    }
+
+    #[test]
+    fn clauses_compare_fields_with_values() {
+        let payload = json!({"level": "error", "status": 503, "path": "/checkout/pay"});
+        assert!(matches(r#"payload.level == "error""#, &payload));
+        assert!(matches("level == error", &payload));
+        assert!(matches(
+            "payload.status >= 500 && payload.path ~= /checkout",
+            &payload
+        ));
+        assert!(!matches("payload.status < 500", &payload));
+        assert!(matches("payload.missing != 1", &payload));
+        assert!(matches("type == http.request && payload.level", &payload));
+        assert!(!matches("payload.missing", &payload));
+    }
+
+    #[test]
+    fn malformed_expressions_are_rejected() {
+        assert!(Filter::parse("").is_err());
+        assert!(Filter::parse("payload.level ==").is_err());
+        assert!(Filter::parse("payload.status > high").is_err());
+        assert!(Filter::parse("a == 1 &&").is_err());
+        assert!(Filter::parse("two words").is_err());
+    }
+}
@@ -0,0 +1,397 @@
+//! Console Sink - Print Events to the Terminal
+//!
+//! A Sink that prints each message it receives to stdout: the message type
+//! and id, then the payload. `exec-sink -- jq .` covers plain printing;
+//! console-sink adds views that need memory across messages, such as
+//! `--diff` (see [`diff`]), and quick field projection with `--select` and
+//! `--flatten` (see [`projection`]). With `--buffer` it keeps the last
+//! messages in memory and dumps them on SIGUSR1 or when a message matches
+//! `--trigger-filter` (see [`ring`]).
+//!
+//! # Examples
+//!
+//! ```bash
+//! # One line per event
+//! console-sink -s 'order.*'
+//!
+//! # Pretty-printed payloads
+//! console-sink -s alert.fired --pretty
+//!
+//! # What changed in each service's config since its last update
+//! console-sink -s config.updated --diff --diff-key payload.service
+//!
+//! # Just who did what
+//! console-sink -s 'user.*' --select payload.user.id --select payload.action --join ' '
+//!
+//! # Stay silent, but dump the last 1000 requests when one fails
+//! console-sink -s 'http.*' --buffer 1000 --quiet \
+//!   --trigger-filter 'payload.status >= 500' --dump-file /var/log/failures.log
+//! ```
+
+pub mod diff;
+pub mod filter;
+pub mod projection;
+pub mod ring;
+pub mod style;
+
+use clap::Parser;
+use diff::Tracker;
+use emergent_client::EmergentMessage;
+use filter::Filter;
+use primitive_common::capabilities::VERSION;
+use primitive_common::errors::{ErrorCategory, HandlerError};
+use primitive_common::{SinkArgs, SinkConfig, SinkContext, SinkHandler, run_sink};
+use projection::Projection;
+use ring::Ring;
+use serde_json::{Value, json};
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, PoisonError};
+use style::{ColorChoice, Style};
+use tokio::signal::unix::{SignalKind, signal};
+
+/// Console Sink — print events to the terminal.
+#[derive(Parser, Debug)]
+#[command(name = "console_sink", version = VERSION)]
+#[command(about = "Print events to the terminal")]
+struct Args {
+    /// Message types to subscribe to.
+    #[arg(short, long = "subscribe", required = true)]
+    subscribe: Vec<String>,
+
+    /// Pretty-print payloads over several lines.
+    #[arg(long, env = "CONSOLE_SINK_PRETTY")]
+    pretty: bool,
+
+    /// When to colour output.
+    #[arg(long, env = "CONSOLE_SINK_COLOR", value_enum, default_value_t = ColorChoice::Auto)]
+    color: ColorChoice,
+
+    /// Print what changed since the previous message with the same key instead of the whole payload.
+    #[arg(long, env = "CONSOLE_SINK_DIFF")]
+    diff: bool,
+
+    /// Payload field (e.g. `payload.id`) distinguishing the messages `--diff` compares; default: the message type alone.
+    #[arg(long, env = "CONSOLE_SINK_DIFF_KEY", requires = "diff")]
+    diff_key: Option<String>,
+
+    /// Most keys `--diff` remembers a previous payload for; the oldest is forgotten beyond this.
+    #[arg(
+        long,
+        env = "CONSOLE_SINK_DIFF_MAX_KEYS",
+        default_value = "10000",
+        requires = "diff"
+    )]
+    diff_max_keys: usize,
+
+    /// Print only this payload field (e.g. `payload.user.id`); repeatable.
+    #[arg(long = "select", env = "CONSOLE_SINK_SELECT", value_delimiter = ',')]
+    select: Vec<String>,
+
+    /// Print selected values joined by this separator instead of as an object.
+    #[arg(long, env = "CONSOLE_SINK_JOIN", requires = "select")]
+    join: Option<String>,
+
+    /// Turn nested objects and arrays into dotted keys.
+    #[arg(long, env = "CONSOLE_SINK_FLATTEN")]
+    flatten: bool,
+
+    /// Keep the last this many messages in memory, to dump on SIGUSR1 or a `--trigger-filter` match.
+    #[arg(long, env = "CONSOLE_SINK_BUFFER")]
+    buffer: Option<usize>,
+
+    /// Print nothing as messages arrive; only dumps of the `--buffer`.
+    #[arg(short, long, env = "CONSOLE_SINK_QUIET", requires = "buffer")]
+    quiet: bool,
+
+    /// Dump the buffer when a message matches this expression (e.g. `payload.status >= 500`).
+    #[arg(long, env = "CONSOLE_SINK_TRIGGER_FILTER", value_parser = Filter::parse, requires = "buffer")]
+    trigger_filter: Option<Filter>,
+
+    /// Append dumps to this file instead of printing them.
+    #[arg(long, env = "CONSOLE_SINK_DUMP_FILE", requires = "buffer")]
+    dump_file: Option<PathBuf>,
+
+    #[command(flatten)]
+    sink: SinkArgs,
+}
+
+/// Prints each message.
+struct ConsoleSink {
+    style: Style,
+    pretty: bool,
+    projection: Projection,
+    diffs: Option<Mutex<Tracker>>,
+    ring: Option<Arc<Ring>>,
+    quiet: bool,
+    trigger: Option<Filter>,
+}
+
+impl ConsoleSink {
+    /// The text printed for one message, delivered as `message_id`.
+    fn format(&self, msg: &EmergentMessage, message_id: &str, payload: &Value) -> String {
+        let message_type = msg.message_type.as_str();
+        let shown = self.projection.apply(payload);
+        if let Some(diffs) = &self.diffs {
+            let mut tracker = diffs.lock().unwrap_or_else(PoisonError::into_inner);
+            // Keys come from the full payload, so they need not be selected
+            let key = tracker.key_of(message_type, payload);
+            let body = match tracker.observe(&key, &shown) {
+                Some(changes) => diff::render(&changes, self.style),
+                None => indent(
+                    &self
+                        .projection
+                        .joined(payload)
+                        .unwrap_or_else(|| pretty(&shown)),
+                ),
+            };
+            return format!("{}\n{body}", self.style.bold(&key));
+        }
+
+        let header = format!(
+            "{} {}",
+            self.style.bold(message_type),
+            self.style.dim(message_id)
+        );
+        match self.projection.joined(payload) {
+            Some(joined) => format!("{header} {joined}"),
+            None if self.pretty => format!("{header}\n{}", pretty(&shown)),
+            None => format!("{header} {shown}"),
+        }
+    }
+}
+
+fn pretty(payload: &Value) -> String {
+    serde_json::to_string_pretty(payload).unwrap_or_else(|_| payload.to_string())
+}
+
+fn indent(text: &str) -> String {
+    text.lines()
+        .map(|line| format!("  {line}"))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+impl SinkHandler for ConsoleSink {
+    async fn handle(
+        &self,
+        msg: &EmergentMessage,
+        ctx: &SinkContext<'_>,
+    ) -> Result<(), HandlerError> {
+        let text = self.format(msg, ctx.message_id(), ctx.payload());
+        let message_type = msg.message_type.as_str();
+        let triggered = self
+            .trigger
+            .as_ref()
+            .is_some_and(|filter| filter.matches(message_type, ctx.payload()));
+        if let Some(ring) = &self.ring {
+            ring.push(text.clone());
+        }
+
+        if ctx.is_dry_run() {
+            if !self.quiet {
+                ctx.would_have("print", json!({ "output": text })).await;
+            }
+            if triggered && let Some(ring) = &self.ring {
+                let detail = json!({ "reason": "trigger", "messages": ring.take() });
+                ctx.would_have("dump", detail).await;
+            }
+            return Ok(());
+        }
+
+        let stdout_error =
+            |e: std::io::Error| HandlerError::new(ErrorCategory::Internal, format!("stdout: {e}"));
+        if !self.quiet {
+            writeln!(std::io::stdout().lock(), "{text}").map_err(stdout_error)?;
+        }
+        if triggered && let Some(ring) = &self.ring {
+            let reason = format!("trigger: {message_type} {}", ctx.message_id());
+            ring.dump(&reason)
+                .map_err(|e| HandlerError::new(ErrorCategory::Internal, format!("dump: {e}")))?;
+        }
+        Ok(())
+    }
+}
+
+/// Run the primitive with `args` as its command line (the first item is
+/// the program name, as with `std::env::args_os`).
+pub async fn run<I, T>(args: I) -> Result<(), Box<dyn std::error::Error>>
+where
+    I: IntoIterator<Item = T>,
+    T: Into<std::ffi::OsString> + Clone,
+{
+    let args = Args::parse_from(args);
+
+    let config = SinkConfig {
+        name: "console_sink",
+        subscribe: &args.subscribe,
+        would_have_as: "console.would_have",
+        dead_letter_as: "console.dead_letter",
+        settings: &args,
+    };
+    let ring = args
+        .buffer
+        .map(|capacity| Arc::new(Ring::new(capacity, args.dump_file.clone())));
+    if let Some(ring) = &ring {
+        let ring = Arc::clone(ring);
+        let mut usr1 = signal(SignalKind::user_defined1())?;
+        tokio::spawn(async move {
+            while usr1.recv().await.is_some() {
+                if let Err(e) = ring.dump("SIGUSR1") {
+                    eprintln!("Failed to dump buffer: {e}");
+                }
+            }
+        });
+    }
+
+    let handler = ConsoleSink {
+        style: args.color.style(),
+        pretty: args.pretty,
+        projection: Projection {
+            select: args.select.clone(),
+            join: args.join.clone(),
+            flatten: args.flatten,
+        },
+        diffs: args
+            .diff
+            .then(|| Mutex::new(Tracker::new(args.diff_key.clone(), args.diff_max_keys))),
+        ring,
+        quiet: args.quiet,
+        trigger: args.trigger_filter.clone(),
+    };
+
+    run_sink(config, &args.sink, handler).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use emergent_testkit::{SinkFixture, fixtures, spawn_sink};
+
+    #[tokio::test]
+    async fn diff_mode_prints_changes_since_the_previous_message() {
+        let args = SinkArgs {
+            dry_run: true,
+            ..Default::default()
+        };
+        let handler = ConsoleSink {
+            style: Style::plain(),
+            pretty: false,
+            projection: Projection::default(),
+            diffs: Some(Mutex::new(Tracker::new(Some("payload.id".to_string()), 10))),
+            ring: None,
+            quiet: false,
+            trigger: None,
+        };
+        let (mut engine, run) =
+            spawn_sink(SinkFixture::new("console_sink", "console"), args, handler);
+
+        engine
+            .inject_message(fixtures::message(
+                "config.updated",
+                json!({"id": "a", "n": 1}),
+            ))
+            .await;
+        let first = engine.expect_published("console.would_have").await;
+        assert_eq!(
+            first.payload()["detail"]["output"],
+            "config.updated [a]\n  {\n    \"id\": \"a\",\n    \"n\": 1\n  }"
+        );
+
+        engine
+            .inject_message(fixtures::message(
+                "config.updated",
+                json!({"id": "a", "n": 2}),
+            ))
+            .await;
+        let second = engine.expect_published("console.would_have").await;
+        assert_eq!(
+            second.payload()["detail"]["output"],
+            "config.updated [a]\n  ~ n: 1 → 2"
+        );
+
+        engine.close();
+        assert_eq!(run.await.ok(), Some(Ok(())));
+    }
+
+    #[tokio::test]
+    async fn quiet_buffers_are_dumped_when_the_trigger_matches() {
+        let args = SinkArgs {
+            dry_run: true,
+            ..Default::default()
+        };
+        let handler = ConsoleSink {
+            style: Style::plain(),
+            pretty: false,
+            projection: Projection {
+                select: vec!["payload.status".to_string()],
+                join: Some(" ".to_string()),
+                flatten: false,
+            },
+            diffs: None,
+            ring: Some(Arc::new(Ring::new(2, None))),
+            quiet: true,
+            trigger: Some(
+                Filter::parse("payload.status >= 500").unwrap_or_else(|e| panic!("filter: {e}")),
+            ),
+        };
+        let (mut engine, run) =
+            spawn_sink(SinkFixture::new("console_sink", "console"), args, handler);
+
+        for status in [200, 201, 204, 503] {
+            engine
+                .inject_message(fixtures::message(
+                    "http.response",
+                    json!({"status": status}),
+                ))
+                .await;
+        }
+        let dump = engine.expect_published("console.would_have").await;
+        assert_eq!(dump.payload()["action"], "dump");
+        let messages: Vec<String> = dump.payload()["detail"]["messages"]
+            .as_array()
+            .map(|m| {
+                m.iter()
+                    .filter_map(|t| t.as_str())
+                    .map(str::to_string)
+                    .collect()
+            })
+            .unwrap_or_default();
+        assert_eq!(messages.len(), 2);
+        assert!(messages[0].ends_with(" 204"));
+        assert!(messages[1].ends_with(" 503"));
+
+        engine.close();
+        assert_eq!(run.await.ok(), Some(Ok(())));
+    }
+
+    #[test]
+    fn selected_fields_replace_the_payload() {
+        let mut sink = ConsoleSink {
+            style: Style::plain(),
+            pretty: false,
+            projection: Projection {
+                select: vec!["payload.user.id".to_string(), "payload.action".to_string()],
+                join: None,
+                flatten: false,
+            },
+            diffs: None,
+            ring: None,
+            quiet: false,
+            trigger: None,
+        };
+        let msg = fixtures::message(
+            "user.login",
+            json!({"user": {"id": 42, "name": "ada"}, "action": "login"}),
+        );
+        let line = sink.format(&msg, "msg_1", msg.payload());
+        assert!(line.starts_with("user.login "));
+        assert!(line.ends_with(r#" {"action":"login","user.id":42}"#));
+
+        sink.projection.join = Some(" ".to_string());
+        assert!(
+            sink.format(&msg, "msg_1", msg.payload())
+                .ends_with(" 42 login")
+        );
+    }
+}
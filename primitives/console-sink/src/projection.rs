@@ -0,0 +1,158 @@
+//! Field projection (`--select`, `--flatten`).
+//!
+//! `--select payload.user.id --select payload.action` prints only those
+//! fields, as a compact object keyed by path (`{"action":"login","user.id":42}`)
+//! or, with `--join`, as their values joined by a separator
+//! (`42 login`). Missing fields are `null` in objects and empty when joined.
+//!
+//! `--flatten` turns nested objects and arrays into dotted keys, both in
+//! selected values and, without `--select`, in the whole payload:
+//! `{"user": {"id": 42}}` becomes `{"user.id": 42}`.
+
+use primitive_common::key;
+use serde_json::{Map, Value};
+
+/// Which parts of a payload to print.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Projection {
+    pub select: Vec<String>,
+    pub join: Option<String>,
+    pub flatten: bool,
+}
+
+impl Projection {
+    /// Whether the payload is printed unchanged.
+    pub fn is_identity(&self) -> bool {
+        self.select.is_empty() && !self.flatten
+    }
+
+    /// The selected fields in `--select` order, flattened if asked.
+    pub fn fields(&self, payload: &Value) -> Vec<(String, Value)> {
+        let selected: Vec<(String, Value)> = if self.select.is_empty() {
+            vec![(String::new(), payload.clone())]
+        } else {
+            self.select
+                .iter()
+                .map(|path| {
+                    let label = path.strip_prefix("payload.").unwrap_or(path).to_string();
+                    let value = key::lookup(payload, path).cloned().unwrap_or(Value::Null);
+                    (label, value)
+                })
+                .collect()
+        };
+        if !self.flatten {
+            return selected;
+        }
+        let mut flat = Vec::new();
+        for (label, value) in selected {
+            flatten_into(&label, value, &mut flat);
+        }
+        flat
+    }
+
+    /// The projected payload: the whole payload when nothing is selected
+    /// and nothing flattened, otherwise an object of the selected fields.
+    pub fn apply(&self, payload: &Value) -> Value {
+        if self.is_identity() {
+            return payload.clone();
+        }
+        if self.select.is_empty() && !payload.is_object() && !payload.is_array() {
+            return payload.clone();
+        }
+        Value::Object(self.fields(payload).into_iter().collect::<Map<_, _>>())
+    }
+
+    /// The `--join` rendering of `payload`, if a separator is configured.
+    pub fn joined(&self, payload: &Value) -> Option<String> {
+        let separator = self.join.as_deref()?;
+        let values: Vec<String> = self
+            .fields(payload)
+            .into_iter()
+            .map(|(_, value)| match value {
+                Value::Null => String::new(),
+                Value::String(s) => s,
+                other => other.to_string(),
+            })
+            .collect();
+        Some(values.join(separator))
+    }
+}
+
+fn flatten_into(prefix: &str, value: Value, out: &mut Vec<(String, Value)>) {
+    let at = |segment: &str| {
+        if prefix.is_empty() {
+            segment.to_string()
+        } else {
+            format!("{prefix}.{segment}")
+        }
+    };
+    match value {
+        Value::Object(map) if !map.is_empty() => {
+            for (field, nested) in map {
+                flatten_into(&at(&field), nested, out);
+            }
+        }
+        Value::Array(items) if !items.is_empty() => {
+            for (i, nested) in items.into_iter().enumerate() {
+                flatten_into(&at(&i.to_string()), nested, out);
+            }
+        }
+        other => out.push((prefix.to_string(), other)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn projection(select: &[&str], join: Option<&str>, flatten: bool) -> Projection {
+        Projection {
+            select: select.iter().map(|s| s.to_string()).collect(),
+            join: join.map(str::to_string),
+            flatten,
+        }
+    }
+
+    #[test]
+    fn selected_fields_form_a_compact_object() {
+        let payload = json!({"user": {"id": 42, "name": "ada"}, "action": "login"});
+        let selected = projection(
+            &["payload.user.id", "payload.action", "payload.missing"],
+            None,
+            false,
+        );
+        assert_eq!(
+            selected.apply(&payload),
+            json!({"user.id": 42, "action": "login", "missing": null})
+        );
+        assert_eq!(projection(&[], None, false).apply(&payload), payload);
+    }
+
+    #[test]
+    fn joined_values_follow_select_order() {
+        let payload = json!({"user": {"id": 42}, "action": "login"});
+        let joined = projection(
+            &["payload.user.id", "payload.action", "payload.missing"],
+            Some(" "),
+            false,
+        );
+        assert_eq!(joined.joined(&payload).as_deref(), Some("42 login "));
+        assert_eq!(projection(&["action"], None, false).joined(&payload), None);
+    }
+
+    #[test]
+    fn flatten_expands_nested_structures() {
+        let payload = json!({"user": {"id": 42, "roles": ["admin", "dev"]}, "tags": {}});
+        assert_eq!(
+            projection(&[], None, true).apply(&payload),
+            json!({"user.id": 42, "user.roles.0": "admin", "user.roles.1": "dev", "tags": {}})
+        );
+        assert_eq!(
+            projection(&["payload.user"], Some(","), true)
+                .joined(&payload)
+                .as_deref(),
+            Some("42,admin,dev")
+        );
+    }
+}
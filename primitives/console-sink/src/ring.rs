@@ -0,0 +1,110 @@
+//! Ring buffer mode (`--buffer`).
+//!
+//! For intermittent problems the interesting output is the last few
+//! messages before something went wrong. With `--buffer 1000` the sink
+//! keeps the last 1000 printed messages in memory, and `--quiet` stops it
+//! printing them as they arrive. The buffer is dumped — to stdout, or
+//! appended to `--dump-file` — when the process receives SIGUSR1 or when a
+//! message matches `--trigger-filter` (see [`crate::filter`]); the
+//! triggering message is the last one dumped. A dump empties the buffer.
+
+use crate::style;
+use std::{
+    collections::VecDeque,
+    fs::OpenOptions,
+    io::{self, Write},
+    path::PathBuf,
+    sync::{Mutex, PoisonError},
+};
+
+/// The last `capacity` formatted messages.
+pub struct Ring {
+    capacity: usize,
+    entries: Mutex<VecDeque<String>>,
+    dump_file: Option<PathBuf>,
+}
+
+impl Ring {
+    pub fn new(capacity: usize, dump_file: Option<PathBuf>) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            entries: Mutex::new(VecDeque::new()),
+            dump_file,
+        }
+    }
+
+    /// Keep `text`, forgetting the oldest entry when full.
+    pub fn push(&self, text: String) {
+        let mut entries = self.entries.lock().unwrap_or_else(PoisonError::into_inner);
+        if entries.len() >= self.capacity {
+            entries.pop_front();
+        }
+        entries.push_back(text);
+    }
+
+    /// Remove and return everything buffered, oldest first.
+    pub fn take(&self) -> Vec<String> {
+        let mut entries = self.entries.lock().unwrap_or_else(PoisonError::into_inner);
+        entries.drain(..).collect()
+    }
+
+    /// Write `entries` out under a header naming `reason`. Files get the
+    /// text without colour codes.
+    pub fn write(&self, reason: &str, entries: &[String]) -> io::Result<()> {
+        let mut dump = format!("--- {} buffered messages ({reason}) ---\n", entries.len());
+        for entry in entries {
+            dump.push_str(entry);
+            dump.push('\n');
+        }
+        dump.push_str("--- end of dump ---\n");
+        match &self.dump_file {
+            Some(path) => OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)?
+                .write_all(style::strip(&dump).as_bytes()),
+            None => io::stdout().lock().write_all(dump.as_bytes()),
+        }
+    }
+
+    /// Dump and empty the buffer.
+    pub fn dump(&self, reason: &str) -> io::Result<usize> {
+        let entries = self.take();
+        self.write(reason, &entries)?;
+        Ok(entries.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use emergent_testkit::fixtures::TempDir;
+
+    #[test]
+    fn the_newest_entries_are_kept_and_dumps_empty_the_buffer() {
+        let ring = Ring::new(2, None);
+        for n in 1..=3 {
+            ring.push(format!("msg-{n}"));
+        }
+        assert_eq!(ring.take(), ["msg-2", "msg-3"]);
+        assert!(ring.take().is_empty());
+    }
+
+    #[test]
+    fn dumps_append_to_the_file_without_colour() {
+        let dir = TempDir::new("console-sink-ring");
+        let path = dir.path().join("dump.log");
+        let ring = Ring::new(10, Some(path.clone()));
+        ring.push("\x1b[1malert.fired\x1b[0m {}".to_string());
+        let dumped = ring.dump("SIGUSR1").unwrap_or_else(|e| panic!("dump: {e}"));
+        assert_eq!(dumped, 1);
+        ring.dump("trigger").unwrap_or_else(|e| panic!("dump: {e}"));
+
+        let written = std::fs::read_to_string(&path).unwrap_or_else(|e| panic!("read: {e}"));
+        assert_eq!(
+            written,
+            "--- 1 buffered messages (SIGUSR1) ---\nalert.fired {}\n--- end of dump ---\n\
+             --- 0 buffered messages (trigger) ---\n--- end of dump ---\n"
+        );
+    }
+}
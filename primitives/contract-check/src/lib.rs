@@ -0,0 +1,383 @@
+//! Contract Check
+//!
+//! A Handler that samples live topics, infers a JSON Schema for each
+//! message type, and holds every sample to the type's committed baseline —
+//! the repository's `schemas/<message.type>.json` — so a producer that
+//! adds, removes or retypes a field is caught before its consumers break.
+//!
+//! # Data Flow
+//!
+//! 1. Receive an event matching configured subscriptions (by default,
+//!    every type with a baseline)
+//! 2. Every `--sample-every`th message of a type is sampled: its schema is
+//!    inferred and merged into the type's, and it is checked against the
+//!    baseline
+//! 3. Each distinct difference is published once as `contract.violation`
+//!
+//! With `--report`, the topics are sampled for `--duration` instead, and a
+//! report is printed; the exit status is 1 if anything differs, so the check
+//! can gate a deploy. `--infer-dir` writes the inferred schemas out as
+//! starting points for new baselines.
+//!
+//! # Messages Published
+//!
+//! - `contract.violation` — the type, field, kind of change (`added`,
+//!   `removed` or `retyped`), expected and actual types, and the first
+//!   message showing it
+//!
+//! # Usage
+//!
+//! ```bash
+//! # Watch every type with a baseline in ./schemas
+//! contract-check
+//!
+//! # Check orders for five minutes and report
+//! contract-check -s 'order.*' --schemas contracts --report --duration 300000
+//! ```
+
+mod schema;
+mod tracker;
+
+use clap::Parser;
+use emergent_client::EmergentMessage;
+use primitive_common::capabilities::{self, CapabilityArgs, Role, VERSION};
+use primitive_common::crypto::{KeyArgs, Keyring};
+use primitive_common::doctor::Report;
+use primitive_common::errors::{ERROR_EVENT_TYPE, ErrorArgs, ErrorCategory};
+use primitive_common::handler::{Bus, Flow, HandlerConfig, MessageHandler, run_handler};
+use primitive_common::payload;
+use schema::Change;
+use serde_json::{Value, json};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::signal::unix::{Signal, SignalKind, signal};
+use tokio::time::Instant;
+use tracker::Tracker;
+
+/// Contract Check — hold live payloads to committed schemas.
+#[derive(Parser, Debug)]
+#[command(name = "contract_check", version = VERSION)]
+#[command(about = "Check live payloads against committed JSON Schema baselines")]
+struct Args {
+    /// Message types to sample (default: every type with a baseline).
+    #[arg(short, long = "subscribe")]
+    subscribe: Vec<String>,
+
+    /// Directory of baseline schemas, one `<message.type>.json` per type.
+    #[arg(long, env = "CONTRACT_CHECK_SCHEMAS", default_value = "schemas")]
+    schemas: PathBuf,
+
+    /// Message type for violations.
+    #[arg(
+        long,
+        env = "CONTRACT_CHECK_PUBLISH_AS",
+        default_value = "contract.violation"
+    )]
+    publish_as: String,
+
+    /// Sample one message in every N of each type.
+    #[arg(
+        long,
+        env = "CONTRACT_CHECK_SAMPLE_EVERY",
+        default_value = "1",
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    sample_every: u64,
+
+    /// Sample for `--duration`, print a report and exit (1 if anything
+    /// differs from its baseline), instead of publishing violations.
+    #[arg(long)]
+    report: bool,
+
+    /// How long `--report` samples, in milliseconds.
+    #[arg(long, env = "CONTRACT_CHECK_DURATION", default_value = "60000")]
+    duration: u64,
+
+    /// Write the schema inferred for each sampled type to this directory,
+    /// as `<message.type>.json`.
+    #[arg(long, requires = "report")]
+    infer_dir: Option<PathBuf>,
+
+    /// Verify external dependencies and exit without connecting to the engine.
+    #[arg(long)]
+    self_test: bool,
+
+    #[command(flatten)]
+    errors: ErrorArgs,
+
+    #[command(flatten)]
+    capabilities: CapabilityArgs,
+
+    #[command(flatten)]
+    keys: KeyArgs,
+}
+
+/// Whether a subscription to `pattern` (`order.*` matches a namespace)
+/// receives `message_type`.
+fn subscribed(pattern: &str, message_type: &str) -> bool {
+    match pattern.strip_suffix('*') {
+        Some(prefix) => message_type.starts_with(prefix),
+        None => pattern == message_type,
+    }
+}
+
+/// The `contract.violation` payload for `change` in `message_type`.
+fn violation(
+    message_type: &str,
+    baseline: Option<&Path>,
+    change: &Change,
+    message_id: &str,
+) -> Value {
+    json!({
+        "message_type": message_type,
+        "field": change.path,
+        "change": change.kind.as_str(),
+        "expected": change.expected,
+        "actual": change.actual,
+        "breaking": change.kind.breaking(),
+        "baseline": baseline.map(|path| path.display().to_string()),
+        "message_id": message_id,
+    })
+}
+
+/// Write the inferred schemas to `dir`.
+fn write_inferred(tracker: &Tracker, dir: &Path) -> std::io::Result<()> {
+    std::fs::create_dir_all(dir)?;
+    for (message_type, schema) in tracker.inferred() {
+        let text = serde_json::to_string_pretty(&schema).map_err(std::io::Error::other)?;
+        std::fs::write(dir.join(format!("{message_type}.json")), text + "\n")?;
+    }
+    Ok(())
+}
+
+/// Run the primitive with `args` as its command line (the first item is
+/// the program name, as with `std::env::args_os`).
+pub async fn run<I, T>(args: I) -> Result<(), Box<dyn std::error::Error>>
+where
+    I: IntoIterator<Item = T>,
+    T: Into<std::ffi::OsString> + Clone,
+{
+    let args = Args::parse_from(args);
+
+    // Get the handler name from environment (set by engine) or use default
+    let name = std::env::var("EMERGENT_NAME").unwrap_or_else(|_| "contract_check".to_string());
+
+    let baselines = tracker::load(&args.schemas);
+    if args.self_test {
+        let mut report = Report::new(&name);
+        report.check(
+            "schemas",
+            baselines
+                .as_ref()
+                .map(|baselines| {
+                    format!(
+                        "{} baselines in {}",
+                        baselines.len(),
+                        args.schemas.display()
+                    )
+                })
+                .map_err(|e| format!("{}: {e}", args.schemas.display())),
+        );
+        args.keys.self_test(&mut report);
+        report.finish();
+    }
+    let mut baselines = match baselines {
+        Ok(baselines) => baselines,
+        Err(e) => {
+            eprintln!(
+                "Error: failed to load schemas from {}: {e}",
+                args.schemas.display()
+            );
+            std::process::exit(1);
+        }
+    };
+    // Baselines for types outside the subscription would only read as unseen
+    if !args.subscribe.is_empty() {
+        baselines.retain(|message_type, _| {
+            args.subscribe
+                .iter()
+                .any(|pattern| subscribed(pattern, message_type))
+        });
+    }
+    let tracker = Tracker::new(baselines, args.sample_every);
+    let topics = if args.subscribe.is_empty() {
+        tracker.baseline_types()
+    } else {
+        args.subscribe.clone()
+    };
+    if topics.is_empty() {
+        eprintln!(
+            "Error: no --subscribe given and no baselines in {}",
+            args.schemas.display()
+        );
+        std::process::exit(1);
+    }
+    let keys = match args.keys.load() {
+        Ok(keys) => keys,
+        Err(e) => {
+            eprintln!("Error: failed to load identity file: {e}");
+            std::process::exit(1);
+        }
+    };
+
+    let topics_refs: Vec<&str> = topics.iter().map(String::as_str).collect();
+    let mut produces = Vec::new();
+    if !args.report {
+        produces.push(args.publish_as.as_str());
+    }
+    if args.errors.emit_errors {
+        produces.push(ERROR_EVENT_TYPE);
+    }
+    let descriptor =
+        args.capabilities
+            .describe(&name, Role::Handler, &topics_refs, &produces, &args);
+    if args.capabilities.capabilities {
+        capabilities::print(&descriptor);
+    }
+
+    let config = HandlerConfig {
+        name: &name,
+        subscribe: &topics,
+        announce: args.capabilities.announce.then_some(&descriptor),
+        emit_errors: args.errors.emit_errors,
+    };
+    let mut check = Check {
+        args: &args,
+        tracker,
+        keys,
+        // SIGINT cuts a report short
+        sigint: signal(SignalKind::interrupt())?,
+        deadline: args
+            .report
+            .then(|| Instant::now() + Duration::from_millis(args.duration)),
+    };
+    run_handler(config, &mut check).await?;
+
+    if args.report {
+        let tracker = &check.tracker;
+        print!("{}", tracker.report());
+        if let Some(dir) = &args.infer_dir
+            && let Err(e) = write_inferred(tracker, dir)
+        {
+            eprintln!(
+                "Error: failed to write inferred schemas to {}: {e}",
+                dir.display()
+            );
+            std::process::exit(1);
+        }
+        if tracker.violations() > 0 {
+            std::process::exit(1);
+        }
+    }
+
+    Ok(())
+}
+
+/// The baselines being checked against and when checking stops.
+struct Check<'a> {
+    args: &'a Args,
+    tracker: Tracker,
+    keys: Option<Keyring>,
+    sigint: Signal,
+    /// When a `--report` run ends.
+    deadline: Option<Instant>,
+}
+
+impl MessageHandler for Check<'_> {
+    type Wake = ();
+
+    /// Sample one message, publishing any violation not seen before.
+    async fn handle(&mut self, msg: &EmergentMessage, bus: &Bus) {
+        let args = self.args;
+        let input = match payload::decode(msg.payload(), self.keys.as_ref()).await {
+            Ok(p) => p,
+            Err(e) => {
+                let error = format!("failed to decode payload: {e}");
+                eprintln!("contract-check: {error}");
+                bus.report_error(msg, ErrorCategory::Parse, &error).await;
+                return;
+            }
+        };
+
+        let message_type = msg.message_type.as_str();
+        let message_id = msg.id().to_string();
+        let changes = self.tracker.observe(message_type, &message_id, &input);
+        if args.report {
+            return;
+        }
+        for change in changes {
+            let payload = violation(
+                message_type,
+                self.tracker.baseline_path(message_type),
+                &change,
+                &message_id,
+            );
+            eprintln!(
+                "contract-check: {message_type} {} {}",
+                change.kind.as_str(),
+                change.path
+            );
+            let violation = EmergentMessage::new(&args.publish_as)
+                .with_causation_id(msg.id())
+                .with_payload(payload);
+            bus.publish(violation).await;
+        }
+    }
+
+    /// Resolves on SIGINT or at the end of a `--report` run.
+    async fn wake(&mut self) {
+        let deadline = self.deadline;
+        let elapsed = async {
+            match deadline {
+                Some(at) => tokio::time::sleep_until(at).await,
+                None => std::future::pending().await,
+            }
+        };
+        tokio::select! {
+            _ = self.sigint.recv() => {}
+            _ = elapsed => {}
+        }
+    }
+
+    async fn woken(&mut self, (): (), _bus: &Bus) -> Flow {
+        Flow::Stop
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use schema::ChangeKind;
+
+    #[test]
+    fn violations_name_the_field_and_baseline() {
+        let change = Change {
+            path: "order.total".to_string(),
+            kind: ChangeKind::Retyped,
+            expected: Some("number".to_string()),
+            actual: Some("string".to_string()),
+        };
+        assert_eq!(
+            violation(
+                "order.created",
+                Some(Path::new("schemas/order.created.json")),
+                &change,
+                "msg_1"
+            ),
+            json!({
+                "message_type": "order.created",
+                "field": "order.total",
+                "change": "retyped",
+                "expected": "number",
+                "actual": "string",
+                "breaking": true,
+                "baseline": "schemas/order.created.json",
+                "message_id": "msg_1",
+            })
+        );
+        assert!(subscribed("order.*", "order.created"));
+        assert!(!subscribed("order.created", "order.updated"));
+        let args = Args::try_parse_from(["contract-check", "--infer-dir", "out"]);
+        assert!(args.is_err(), "--infer-dir needs --report");
+    }
+}
@@ -0,0 +1,6 @@
+//! `contract-check` binary; the primitive itself lives in the library.
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    contract_check::run(std::env::args_os()).await
+}
@@ -0,0 +1,363 @@
+//! Schema inference and checking.
+//!
+//! Schemas use the subset of JSON Schema the repository's `schemas/`
+//! baselines are written in: `type` (one name, or several such as
+//! `["string", "null"]`), `properties`, `required`, `items` and
+//! `additionalProperties`. An integer satisfies `number`, and a schema
+//! without a `type` accepts anything.
+
+use serde_json::{Map, Value, json};
+
+/// How a payload differs from its baseline.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ChangeKind {
+    /// A field the baseline doesn't declare.
+    Added,
+    /// A required field that is missing.
+    Removed,
+    /// A field of a type the baseline doesn't allow.
+    Retyped,
+}
+
+impl ChangeKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Added => "added",
+            Self::Removed => "removed",
+            Self::Retyped => "retyped",
+        }
+    }
+
+    /// Whether consumers written against the baseline may break. Extra
+    /// fields are ignored by most of them.
+    pub fn breaking(self) -> bool {
+        self != Self::Added
+    }
+}
+
+/// One difference, at a dotted `path` (`items[]` for array elements).
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Change {
+    pub path: String,
+    pub kind: ChangeKind,
+    /// The type the baseline declares, if it declares the field.
+    pub expected: Option<String>,
+    /// The type found, if the field is present.
+    pub actual: Option<String>,
+}
+
+/// The JSON Schema type name of `value`.
+pub fn type_of(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(n) if n.is_i64() || n.is_u64() => "integer",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
+
+/// The types `schema` allows, or `None` for any.
+fn types(schema: &Value) -> Option<Vec<&str>> {
+    match schema.get("type")? {
+        Value::String(name) => Some(vec![name.as_str()]),
+        Value::Array(names) => Some(names.iter().filter_map(Value::as_str).collect()),
+        _ => None,
+    }
+}
+
+fn join(path: &str, field: &str) -> String {
+    if path.is_empty() {
+        field.to_string()
+    } else {
+        format!("{path}.{field}")
+    }
+}
+
+fn declared(schema: &Value) -> Option<String> {
+    types(schema).map(|types| types.join("|"))
+}
+
+/// Compare `value` against `schema`, collecting the differences.
+pub fn check(schema: &Value, value: &Value) -> Vec<Change> {
+    let mut changes = Vec::new();
+    check_at(schema, value, "", &mut changes);
+    changes
+}
+
+fn check_at(schema: &Value, value: &Value, path: &str, changes: &mut Vec<Change>) {
+    let actual = type_of(value);
+    if let Some(allowed) = types(schema)
+        && !allowed
+            .iter()
+            .any(|t| *t == actual || (*t == "number" && actual == "integer"))
+    {
+        changes.push(Change {
+            path: path.to_string(),
+            kind: ChangeKind::Retyped,
+            expected: Some(allowed.join("|")),
+            actual: Some(actual.to_string()),
+        });
+        return;
+    }
+    match value {
+        Value::Object(fields) => {
+            let properties = schema.get("properties").and_then(Value::as_object);
+            let required = schema.get("required").and_then(Value::as_array);
+            for name in required.into_iter().flatten().filter_map(Value::as_str) {
+                if !fields.contains_key(name) {
+                    changes.push(Change {
+                        path: join(path, name),
+                        kind: ChangeKind::Removed,
+                        expected: properties
+                            .and_then(|properties| properties.get(name))
+                            .and_then(declared),
+                        actual: None,
+                    });
+                }
+            }
+            for (name, field) in fields {
+                let path = join(path, name);
+                match (
+                    properties.and_then(|properties| properties.get(name)),
+                    schema.get("additionalProperties"),
+                ) {
+                    (Some(property), _) => check_at(property, field, &path, changes),
+                    (None, Some(additional)) if additional.is_object() => {
+                        check_at(additional, field, &path, changes)
+                    }
+                    (None, Some(Value::Bool(true))) => {}
+                    // An object schema that lists its fields lists them all
+                    (None, _) if properties.is_some() => changes.push(Change {
+                        path,
+                        kind: ChangeKind::Added,
+                        expected: None,
+                        actual: Some(type_of(field).to_string()),
+                    }),
+                    (None, _) => {}
+                }
+            }
+        }
+        Value::Array(items) => {
+            if let Some(item) = schema.get("items") {
+                let path = format!("{path}[]");
+                for value in items {
+                    check_at(item, value, &path, changes);
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+/// A schema describing `value` alone.
+pub fn infer(value: &Value) -> Value {
+    match value {
+        Value::Object(fields) => json!({
+            "type": "object",
+            "properties": fields
+                .iter()
+                .map(|(name, field)| (name.clone(), infer(field)))
+                .collect::<Map<_, _>>(),
+            "required": fields.keys().collect::<Vec<_>>(),
+        }),
+        Value::Array(items) => {
+            let mut schema = json!({"type": "array"});
+            if let Some(item) = items.iter().map(infer).reduce(|a, b| merge(&a, &b)) {
+                schema["items"] = item;
+            }
+            schema
+        }
+        other => json!({"type": type_of(other)}),
+    }
+}
+
+/// A schema describing everything `a` or `b` describes: types and
+/// properties are united, and only fields both require stay required.
+pub fn merge(a: &Value, b: &Value) -> Value {
+    let mut merged = Map::new();
+    if let (Some(a_types), Some(b_types)) = (types(a), types(b)) {
+        let mut names: Vec<&str> = a_types;
+        names.extend(b_types);
+        if names.contains(&"number") {
+            names.retain(|name| *name != "integer");
+        }
+        // Nullable types read as `["string", "null"]`, as in the baselines
+        names.sort_by_key(|name| (*name == "null", *name));
+        names.dedup();
+        merged.insert(
+            "type".to_string(),
+            match names.as_slice() {
+                [name] => Value::from(*name),
+                _ => Value::from(names),
+            },
+        );
+    }
+
+    match (a.get("properties"), b.get("properties")) {
+        (Some(Value::Object(a_props)), Some(Value::Object(b_props))) => {
+            let mut properties = a_props.clone();
+            for (name, schema) in b_props {
+                let schema = match a_props.get(name) {
+                    Some(existing) => merge(existing, schema),
+                    None => schema.clone(),
+                };
+                properties.insert(name.clone(), schema);
+            }
+            let b_required = b.get("required").and_then(Value::as_array);
+            let required: Vec<Value> = a
+                .get("required")
+                .and_then(Value::as_array)
+                .into_iter()
+                .flatten()
+                .filter(|name| b_required.is_some_and(|required| required.contains(name)))
+                .cloned()
+                .collect();
+            merged.insert("properties".to_string(), Value::Object(properties));
+            merged.insert("required".to_string(), Value::from(required));
+        }
+        // Only one side was ever an object, so its fields stand
+        (Some(properties), _) | (_, Some(properties)) => {
+            let side = if a.get("properties").is_some() { a } else { b };
+            merged.insert("properties".to_string(), properties.clone());
+            if let Some(required) = side.get("required") {
+                merged.insert("required".to_string(), required.clone());
+            }
+        }
+        (None, None) => {}
+    }
+
+    match (a.get("items"), b.get("items")) {
+        (Some(a_items), Some(b_items)) => {
+            merged.insert("items".to_string(), merge(a_items, b_items));
+        }
+        (Some(items), None) | (None, Some(items)) => {
+            merged.insert("items".to_string(), items.clone());
+        }
+        (None, None) => {}
+    }
+    Value::Object(merged)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn baseline() -> Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "command": {"type": "string"},
+                "exit_code": {"type": "integer", "format": "int32"},
+                "duration": {"type": "number"},
+                "note": {"type": ["string", "null"]},
+                "tags": {"type": "array", "items": {"type": "string"}},
+                "env": {"type": "object", "additionalProperties": {"type": "string"}},
+                "raw": {},
+            },
+            "required": ["command", "exit_code"],
+        })
+    }
+
+    #[test]
+    fn payloads_are_checked_for_added_removed_and_retyped_fields() {
+        let conforming = json!({
+            "command": "date",
+            "exit_code": 0,
+            "duration": 3,
+            "note": null,
+            "tags": ["a"],
+            "env": {"TZ": "UTC"},
+            "raw": [1, "x"],
+        });
+        assert_eq!(check(&baseline(), &conforming), []);
+
+        let drifted = json!({
+            "exit_code": "0",
+            "note": 5,
+            "tags": ["a", 2],
+            "env": {"TZ": 1},
+            "host": "web-1",
+        });
+        let change = |path: &str, kind, expected: Option<&str>, actual: Option<&str>| Change {
+            path: path.to_string(),
+            kind,
+            expected: expected.map(str::to_string),
+            actual: actual.map(str::to_string),
+        };
+        let mut changes = check(&baseline(), &drifted);
+        changes.sort();
+        assert_eq!(
+            changes,
+            [
+                change("command", ChangeKind::Removed, Some("string"), None),
+                change(
+                    "env.TZ",
+                    ChangeKind::Retyped,
+                    Some("string"),
+                    Some("integer")
+                ),
+                change(
+                    "exit_code",
+                    ChangeKind::Retyped,
+                    Some("integer"),
+                    Some("string")
+                ),
+                change("host", ChangeKind::Added, None, Some("string")),
+                change(
+                    "note",
+                    ChangeKind::Retyped,
+                    Some("string|null"),
+                    Some("integer")
+                ),
+                change(
+                    "tags[]",
+                    ChangeKind::Retyped,
+                    Some("string"),
+                    Some("integer")
+                ),
+            ]
+        );
+        assert!(!ChangeKind::Added.breaking() && ChangeKind::Removed.breaking());
+        assert_eq!(check(&baseline(), &json!([])).len(), 1);
+    }
+
+    #[test]
+    fn samples_merge_into_one_schema() {
+        let samples = [
+            json!({"id": 1, "total": 20, "items": [{"sku": "a"}], "note": "gift"}),
+            json!({"id": 2, "total": 20.5, "items": [], "note": null}),
+            json!({"id": 3, "total": 7, "items": [{"sku": "b", "qty": 2}]}),
+        ];
+        let schema = samples
+            .iter()
+            .map(infer)
+            .reduce(|a, b| merge(&a, &b))
+            .unwrap_or_default();
+        assert_eq!(
+            schema,
+            json!({
+                "type": "object",
+                "properties": {
+                    "id": {"type": "integer"},
+                    "total": {"type": "number"},
+                    "items": {"type": "array", "items": {
+                        "type": "object",
+                        "properties": {"sku": {"type": "string"}, "qty": {"type": "integer"}},
+                        "required": ["sku"],
+                    }},
+                    "note": {"type": ["string", "null"]},
+                },
+                "required": ["id", "items", "total"],
+            })
+        );
+        // The inferred schema accepts every sample it came from
+        assert!(
+            samples
+                .iter()
+                .all(|sample| check(&schema, sample).is_empty())
+        );
+    }
+}
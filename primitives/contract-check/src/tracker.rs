@@ -0,0 +1,299 @@
+//! Per-type sampling state: the baseline each type is held to, the schema
+//! inferred from what was sampled, and the violations found so far.
+
+use crate::schema::{self, Change};
+use serde_json::{Value, json};
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// A committed schema a message type is held to.
+pub struct Baseline {
+    pub path: PathBuf,
+    pub schema: Value,
+}
+
+/// How often a violation was seen, and where first.
+struct Violation {
+    count: u64,
+    first_message_id: String,
+}
+
+#[derive(Default)]
+struct TypeStats {
+    seen: u64,
+    sampled: u64,
+    inferred: Option<Value>,
+    violations: BTreeMap<Change, Violation>,
+}
+
+pub struct Tracker {
+    baselines: BTreeMap<String, Baseline>,
+    types: BTreeMap<String, TypeStats>,
+    sample_every: u64,
+}
+
+/// Load `<message.type>.json` baselines from `dir`.
+pub fn load(dir: &Path) -> io::Result<BTreeMap<String, Baseline>> {
+    let mut baselines = BTreeMap::new();
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        let Some(message_type) = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(|name| name.strip_suffix(".json"))
+        else {
+            continue;
+        };
+        let text = std::fs::read_to_string(&path)?;
+        let schema: Value = serde_json::from_str(&text).map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{}: {e}", path.display()),
+            )
+        })?;
+        baselines.insert(message_type.to_string(), Baseline { path, schema });
+    }
+    Ok(baselines)
+}
+
+/// The struct name the schemas README gives a type: `exec.exit` is
+/// `ExecExit`.
+fn title(message_type: &str) -> String {
+    message_type
+        .split(['.', '_', '-'])
+        .map(|word| {
+            let mut chars = word.chars();
+            chars
+                .next()
+                .map(|first| first.to_uppercase().chain(chars).collect::<String>())
+                .unwrap_or_default()
+        })
+        .collect()
+}
+
+impl Tracker {
+    pub fn new(baselines: BTreeMap<String, Baseline>, sample_every: u64) -> Self {
+        Self {
+            baselines,
+            types: BTreeMap::new(),
+            sample_every: sample_every.max(1),
+        }
+    }
+
+    /// The types with a baseline.
+    pub fn baseline_types(&self) -> Vec<String> {
+        self.baselines.keys().cloned().collect()
+    }
+
+    /// The baseline file for `message_type`.
+    pub fn baseline_path(&self, message_type: &str) -> Option<&Path> {
+        self.baselines
+            .get(message_type)
+            .map(|baseline| baseline.path.as_path())
+    }
+
+    /// Take `payload` into account when it falls due for sampling, and
+    /// return the violations it shows that were not seen before.
+    pub fn observe(
+        &mut self,
+        message_type: &str,
+        message_id: &str,
+        payload: &Value,
+    ) -> Vec<Change> {
+        let stats = self.types.entry(message_type.to_string()).or_default();
+        stats.seen += 1;
+        if !(stats.seen - 1).is_multiple_of(self.sample_every) {
+            return Vec::new();
+        }
+        stats.sampled += 1;
+        let inferred = schema::infer(payload);
+        stats.inferred = Some(match &stats.inferred {
+            Some(existing) => schema::merge(existing, &inferred),
+            None => inferred,
+        });
+
+        let Some(baseline) = self.baselines.get(message_type) else {
+            return Vec::new();
+        };
+        let mut changes = schema::check(&baseline.schema, payload);
+        // Array elements can repeat a change
+        changes.sort();
+        changes.dedup();
+        let mut new = Vec::new();
+        for change in changes {
+            stats
+                .violations
+                .entry(change.clone())
+                .and_modify(|violation| violation.count += 1)
+                .or_insert_with(|| {
+                    new.push(change);
+                    Violation {
+                        count: 1,
+                        first_message_id: message_id.to_string(),
+                    }
+                });
+        }
+        new
+    }
+
+    /// Distinct violations found so far.
+    pub fn violations(&self) -> usize {
+        self.types
+            .values()
+            .map(|stats| stats.violations.len())
+            .sum()
+    }
+
+    /// The schemas inferred per type, as documents ready to be reviewed
+    /// and committed as baselines.
+    pub fn inferred(&self) -> Vec<(String, Value)> {
+        self.types
+            .iter()
+            .filter_map(|(message_type, stats)| {
+                let inferred = stats.inferred.as_ref()?;
+                let mut document = json!({
+                    "$schema": "https://json-schema.org/draft/2020-12/schema",
+                    "$id": message_type,
+                    "title": title(message_type),
+                    "description": format!("Inferred from {} sampled {message_type} payloads.", stats.sampled),
+                });
+                if let (Some(document), Some(inferred)) =
+                    (document.as_object_mut(), inferred.as_object())
+                {
+                    document.extend(inferred.clone());
+                }
+                Some((message_type.clone(), document))
+            })
+            .collect()
+    }
+
+    /// A plain-text report of every type subscribed to or sampled.
+    pub fn report(&self) -> String {
+        let mut names: Vec<&String> = self.baselines.keys().chain(self.types.keys()).collect();
+        names.sort();
+        names.dedup();
+        let count = |n: usize, noun: &str| match n {
+            1 => format!("1 {noun}"),
+            n => format!("{n} {noun}s"),
+        };
+        let mut out = format!(
+            "{} sampled, {}\n",
+            count(self.types.len(), "type"),
+            count(self.violations(), "violation")
+        );
+        for name in names {
+            let baseline = self.baselines.get(name);
+            let _ = write!(out, "{name}");
+            if let Some(baseline) = baseline {
+                let _ = write!(out, " ({})", baseline.path.display());
+            }
+            let Some(stats) = self.types.get(name) else {
+                out.push_str(": not seen\n");
+                continue;
+            };
+            let _ = write!(out, ": {} sampled", stats.sampled);
+            if baseline.is_none() {
+                out.push_str(", no baseline\n");
+                continue;
+            }
+            if stats.violations.is_empty() {
+                out.push_str(", matches\n");
+                continue;
+            }
+            out.push('\n');
+            for (change, violation) in &stats.violations {
+                let detail = match (&change.expected, &change.actual) {
+                    (Some(expected), Some(actual)) => {
+                        format!("expected {expected}, found {actual}")
+                    }
+                    (Some(expected), None) => format!("expected {expected}, missing"),
+                    (None, Some(actual)) => format!("found {actual}"),
+                    (None, None) => String::new(),
+                };
+                let _ = writeln!(
+                    out,
+                    "  {:<8} {}: {detail} ({} of {} samples, first {}){}",
+                    change.kind.as_str(),
+                    change.path,
+                    violation.count,
+                    stats.sampled,
+                    violation.first_message_id,
+                    if change.kind.breaking() {
+                        ", breaking"
+                    } else {
+                        ""
+                    }
+                );
+            }
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn violations_are_reported_once_and_summarized() {
+        let dir = std::env::temp_dir().join(format!("contract-check-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap_or_else(|e| panic!("{e}"));
+        std::fs::write(
+            dir.join("exec.exit.json"),
+            r#"{"title": "ExecExit", "type": "object",
+                "properties": {"command": {"type": "string"}, "exit_code": {"type": "integer"}},
+                "required": ["command", "exit_code"]}"#,
+        )
+        .unwrap_or_else(|e| panic!("{e}"));
+        std::fs::write(dir.join("http.request.json"), r#"{"type": "object"}"#)
+            .unwrap_or_else(|e| panic!("{e}"));
+        std::fs::write(dir.join("README.md"), "# Schemas").unwrap_or_else(|e| panic!("{e}"));
+        let baselines = load(&dir).unwrap_or_else(|e| panic!("{e}"));
+        let mut tracker = Tracker::new(baselines, 1);
+        assert_eq!(tracker.baseline_types(), ["exec.exit", "http.request"]);
+
+        assert!(
+            tracker
+                .observe(
+                    "exec.exit",
+                    "m1",
+                    &json!({"command": "date", "exit_code": 0})
+                )
+                .is_empty()
+        );
+        let new = tracker.observe("exec.exit", "m2", &json!({"command": "date", "code": 0}));
+        let kinds: Vec<&str> = new.iter().map(|change| change.kind.as_str()).collect();
+        assert_eq!(kinds, ["added", "removed"]);
+        assert!(
+            tracker
+                .observe("exec.exit", "m3", &json!({"command": "ls", "code": 2}))
+                .is_empty()
+        );
+        tracker.observe("order.created", "m4", &json!({"id": 1}));
+
+        let report = tracker.report().replace(&dir.display().to_string(), "DIR");
+        assert_eq!(
+            report,
+            "2 types sampled, 2 violations\n\
+             exec.exit (DIR/exec.exit.json): 3 sampled\n  \
+             added    code: found integer (2 of 3 samples, first m2)\n  \
+             removed  exit_code: expected integer, missing (2 of 3 samples, first m2), breaking\n\
+             http.request (DIR/http.request.json): not seen\n\
+             order.created: 1 sampled, no baseline\n"
+        );
+
+        let inferred = tracker.inferred();
+        assert_eq!(inferred[1].0, "order.created");
+        assert_eq!(inferred[1].1["title"], "OrderCreated");
+        assert_eq!(inferred[1].1["required"], json!(["id"]));
+
+        let mut sampling = Tracker::new(BTreeMap::new(), 3);
+        for _ in 0..7 {
+            sampling.observe("a", "m", &json!({}));
+        }
+        assert!(sampling.report().contains("a: 3 sampled"));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,132 @@
+//! Certificates and the domain patterns they are matched against.
+//!
+//! A pattern without `*` matches that domain and every name under it, so
+//! `example.com` matches `example.com`, `www.example.com` and
+//! `*.example.com`. A pattern with `*` must match the whole name, with `*`
+//! standing for any characters: `*paypal*` matches `paypal-login.example`.
+//! Matching ignores case and a trailing dot.
+
+use primitive_common::glob;
+use sha2::{Digest, Sha256};
+use x509_parser::extensions::GeneralName;
+use x509_parser::prelude::{FromDer, X509Certificate};
+
+/// What a certificate says about itself.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cert {
+    pub subject: String,
+    pub issuer: String,
+    /// Hex serial number, as `01:ab:...`.
+    pub serial: String,
+    /// Validity bounds, in seconds since the Unix epoch.
+    pub not_before: i64,
+    pub not_after: i64,
+    /// DNS names from the subject alternative names and the common name.
+    pub domains: Vec<String>,
+    /// SHA-256 of the DER certificate, in hex.
+    pub fingerprint: String,
+}
+
+fn normalize(name: &str) -> String {
+    name.trim().trim_end_matches('.').to_lowercase()
+}
+
+impl Cert {
+    pub fn from_der(der: &[u8]) -> Result<Self, String> {
+        let (_, cert) = X509Certificate::from_der(der).map_err(|e| e.to_string())?;
+        let mut domains: Vec<String> = Vec::new();
+        if let Ok(Some(names)) = cert.subject_alternative_name() {
+            for name in &names.value.general_names {
+                if let GeneralName::DNSName(name) = name {
+                    domains.push(normalize(name));
+                }
+            }
+        }
+        for name in cert.subject().iter_common_name() {
+            if let Ok(name) = name.as_str() {
+                domains.push(normalize(name));
+            }
+        }
+        let mut seen = std::collections::HashSet::new();
+        domains.retain(|domain| !domain.is_empty() && seen.insert(domain.clone()));
+        Ok(Self {
+            subject: cert.subject().to_string(),
+            issuer: cert.issuer().to_string(),
+            serial: cert.raw_serial_as_string(),
+            not_before: cert.validity().not_before.timestamp(),
+            not_after: cert.validity().not_after.timestamp(),
+            domains,
+            fingerprint: hex::encode(Sha256::digest(der)),
+        })
+    }
+}
+
+/// Domain patterns.
+#[derive(Debug, Clone, Default)]
+pub struct Patterns(Vec<String>);
+
+impl Patterns {
+    pub fn new(patterns: &[String]) -> Self {
+        Self(patterns.iter().map(|p| normalize(p)).collect())
+    }
+
+    /// Whether `name` matches any pattern.
+    pub fn matches(&self, name: &str) -> bool {
+        self.0.iter().any(|pattern| {
+            if pattern.contains('*') {
+                glob::matches(pattern, name)
+            } else {
+                name == pattern
+                    || name
+                        .strip_suffix(pattern.as_str())
+                        .is_some_and(|label| label.ends_with('.'))
+            }
+        })
+    }
+
+    /// The names in `domains` that match.
+    pub fn matching(&self, domains: &[String]) -> Vec<String> {
+        domains
+            .iter()
+            .filter(|domain| self.matches(domain))
+            .cloned()
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn patterns_match_domains_and_globs() {
+        let patterns = Patterns::new(&["Example.com.".to_string(), "*paypal*".to_string()]);
+        assert!(patterns.matches("example.com"));
+        assert!(patterns.matches("*.example.com"));
+        assert!(patterns.matches("a.b.example.com"));
+        assert!(!patterns.matches("badexample.com"));
+        assert!(!patterns.matches("example.com.evil.net"));
+        assert!(patterns.matches("paypal-login.example"));
+        assert!(patterns.matches("secure.paypal"));
+        assert!(!patterns.matches("paypa.l"));
+    }
+
+    #[test]
+    fn certificates_yield_their_names() {
+        let key = rcgen::KeyPair::generate().unwrap_or_else(|e| panic!("{e}"));
+        let mut params = rcgen::CertificateParams::new(vec![
+            "www.example.com".to_string(),
+            "Example.com".to_string(),
+        ])
+        .unwrap_or_else(|e| panic!("{e}"));
+        params
+            .distinguished_name
+            .push(rcgen::DnType::CommonName, "www.example.com");
+        let cert = params.self_signed(&key).unwrap_or_else(|e| panic!("{e}"));
+        let parsed = Cert::from_der(cert.der()).unwrap_or_else(|e| panic!("{e}"));
+        assert_eq!(parsed.domains, ["www.example.com", "example.com"]);
+        assert!(parsed.subject.contains("CN=www.example.com"));
+        assert_eq!(parsed.fingerprint.len(), 64);
+        assert!(Cert::from_der(b"not a certificate").is_err());
+    }
+}
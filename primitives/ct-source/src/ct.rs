@@ -0,0 +1,223 @@
+//! Certificate Transparency logs (RFC 6962).
+//!
+//! A log is read with `get-sth`, for its current size, and `get-entries`,
+//! for a range of entries. Each entry is a `MerkleTreeLeaf`: an
+//! `x509_entry` carries the certificate, and a `precert_entry` only the
+//! TBS part of a precertificate, so for those the whole precertificate is
+//! read from the entry's `extra_data` instead.
+
+use base64::{Engine, engine::general_purpose::STANDARD};
+use reqwest::Client;
+use serde_json::Value;
+use std::time::Duration;
+
+/// Where the certificate of an entry came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EntryType {
+    X509,
+    Precert,
+}
+
+impl EntryType {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::X509 => "x509",
+            Self::Precert => "precert",
+        }
+    }
+}
+
+/// A log entry.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entry {
+    /// When the log accepted it, in milliseconds since the Unix epoch.
+    pub timestamp: u64,
+    pub entry_type: EntryType,
+    /// The DER certificate or precertificate.
+    pub certificate: Vec<u8>,
+}
+
+/// Reads `N`-byte big-endian lengths and the data they prefix.
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], String> {
+        if self.0.len() < n {
+            return Err("entry is truncated".to_string());
+        }
+        let (taken, rest) = self.0.split_at(n);
+        self.0 = rest;
+        Ok(taken)
+    }
+
+    fn number(&mut self, n: usize) -> Result<u64, String> {
+        Ok(self
+            .take(n)?
+            .iter()
+            .fold(0, |acc, byte| (acc << 8) | u64::from(*byte)))
+    }
+
+    fn prefixed(&mut self, n: usize) -> Result<&'a [u8], String> {
+        let len = self.number(n)? as usize;
+        self.take(len)
+    }
+}
+
+impl Entry {
+    /// Read an entry from its `leaf_input` and `extra_data`.
+    pub fn parse(leaf_input: &[u8], extra_data: &[u8]) -> Result<Self, String> {
+        let mut leaf = Reader(leaf_input);
+        let (version, leaf_type) = (leaf.number(1)?, leaf.number(1)?);
+        if version != 0 || leaf_type != 0 {
+            return Err(format!("unsupported leaf v{version} type {leaf_type}"));
+        }
+        let timestamp = leaf.number(8)?;
+        let (entry_type, certificate) = match leaf.number(2)? {
+            0 => (EntryType::X509, leaf.prefixed(3)?),
+            // PrecertChainEntry: the precertificate, then its chain
+            1 => (EntryType::Precert, Reader(extra_data).prefixed(3)?),
+            other => return Err(format!("unknown entry type {other}")),
+        };
+        Ok(Self {
+            timestamp,
+            entry_type,
+            certificate: certificate.to_vec(),
+        })
+    }
+}
+
+/// A CT log.
+pub struct Log {
+    client: Client,
+    url: String,
+    timeout: Duration,
+}
+
+impl Log {
+    pub fn new(client: Client, url: &str, timeout: Duration) -> Self {
+        Self {
+            client,
+            url: url.trim_end_matches('/').to_string(),
+            timeout,
+        }
+    }
+
+    async fn get(&self, path: &str) -> Result<Value, String> {
+        let url = format!("{}/ct/v1/{path}", self.url);
+        let response = self
+            .client
+            .get(&url)
+            .timeout(self.timeout)
+            .send()
+            .await
+            .map_err(|e| format!("{url}: {e}"))?;
+        let status = response.status();
+        if !status.is_success() {
+            return Err(format!("{url}: HTTP {status}"));
+        }
+        response.json().await.map_err(|e| format!("{url}: {e}"))
+    }
+
+    /// The number of entries in the log.
+    pub async fn tree_size(&self) -> Result<u64, String> {
+        self.get("get-sth").await?["tree_size"]
+            .as_u64()
+            .ok_or_else(|| format!("{}: get-sth has no tree_size", self.url))
+    }
+
+    /// Entries `start..=end`; logs may return fewer than asked for. An
+    /// entry that cannot be read is `Err`, so indexes still line up.
+    pub async fn entries(
+        &self,
+        start: u64,
+        end: u64,
+    ) -> Result<Vec<Result<Entry, String>>, String> {
+        let body = self
+            .get(&format!("get-entries?start={start}&end={end}"))
+            .await?;
+        let decode = |value: &Value| {
+            STANDARD
+                .decode(value.as_str().unwrap_or_default())
+                .map_err(|e| e.to_string())
+        };
+        Ok(body["entries"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .map(|entry| {
+                Entry::parse(
+                    &decode(&entry["leaf_input"])?,
+                    &decode(&entry["extra_data"])?,
+                )
+            })
+            .collect())
+    }
+}
+
+/// The URLs of the usable RFC 6962 logs in a v3 log list, such as
+/// Chrome's.
+pub async fn usable_logs(
+    client: &Client,
+    url: &str,
+    timeout: Duration,
+) -> Result<Vec<String>, String> {
+    let response = client
+        .get(url)
+        .timeout(timeout)
+        .send()
+        .await
+        .map_err(|e| format!("{url}: {e}"))?;
+    let status = response.status();
+    if !status.is_success() {
+        return Err(format!("{url}: HTTP {status}"));
+    }
+    let list: Value = response.json().await.map_err(|e| format!("{url}: {e}"))?;
+    Ok(list["operators"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .flat_map(|operator| operator["logs"].as_array().into_iter().flatten())
+        .filter(|log| log["state"]["usable"].is_object())
+        .filter_map(|log| log["url"].as_str().map(str::to_string))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A `MerkleTreeLeaf` of `entry_type` wrapping `body`.
+    fn leaf(timestamp: u64, entry_type: u16, body: &[u8]) -> Vec<u8> {
+        let mut leaf = vec![0, 0];
+        leaf.extend(timestamp.to_be_bytes());
+        leaf.extend(entry_type.to_be_bytes());
+        leaf.extend(body);
+        leaf
+    }
+
+    fn prefixed(data: &[u8]) -> Vec<u8> {
+        let mut prefixed = (data.len() as u32).to_be_bytes()[1..].to_vec();
+        prefixed.extend(data);
+        prefixed
+    }
+
+    #[test]
+    fn entries_yield_their_certificate() {
+        let x509 = Entry::parse(&leaf(1_700_000_000_000, 0, &prefixed(b"cert")), &[])
+            .unwrap_or_else(|e| panic!("{e}"));
+        assert_eq!(x509.timestamp, 1_700_000_000_000);
+        assert_eq!(x509.entry_type, EntryType::X509);
+        assert_eq!(x509.certificate, b"cert");
+
+        let mut precert = vec![0; 32];
+        precert.extend(prefixed(b"tbs"));
+        let mut extra = prefixed(b"precert");
+        extra.extend(prefixed(&prefixed(b"issuer")));
+        let precert = Entry::parse(&leaf(1, 1, &precert), &extra).unwrap_or_else(|e| panic!("{e}"));
+        assert_eq!(precert.entry_type, EntryType::Precert);
+        assert_eq!(precert.certificate, b"precert");
+
+        assert!(Entry::parse(&leaf(1, 0, &[0, 0, 9, 1]), &[]).is_err());
+        assert!(Entry::parse(&leaf(1, 7, &[]), &[]).is_err());
+    }
+}
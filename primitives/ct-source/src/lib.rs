@@ -0,0 +1,616 @@
+//! CT Source - Certificate Transparency Events
+//!
+//! A Source that tails Certificate Transparency logs and publishes
+//! `ct.cert_issued` for each certificate or precertificate naming a domain
+//! that matches a `--domain` pattern (see [`cert`]), carrying the log,
+//! entry index, matched and all names, subject, issuer, serial, validity
+//! and SHA-256 fingerprint - so certificates issued for your domains that
+//! you did not ask for can be caught.
+//!
+//! The logs are each `--log`, or else every usable log in `--log-list-url`
+//! (Chrome's by default), read as described in [`ct`]. Each log is polled
+//! every `--interval` milliseconds from where it was last read; a log seen
+//! for the first time is read from its current end. A log more than
+//! `--max-backlog` entries behind skips ahead to that many before its end.
+//! With `--state-file` each log's position survives restarts.
+//!
+//! A certificate is usually logged as a precertificate and again as the
+//! final certificate, in several logs; repeats of a recent issuer and
+//! serial are not published again.
+//!
+//! A match that can be neither published nor spooled stops its log at that
+//! entry, to be read again on the next poll.
+//!
+//! Sources are SILENT - they only produce domain messages.
+//! All lifecycle events are published by the engine.
+//!
+//! # Usage
+//!
+//! ```bash
+//! ct-source --domain example.com --domain '*example-login*' \
+//!   --state-file /var/lib/emergent/ct.json
+//!
+//! # Only two logs
+//! ct-source --domain example.com \
+//!   --log https://ct.googleapis.com/logs/us1/argon2025h2/ \
+//!   --log https://ct.cloudflare.com/logs/nimbus2025/
+//! ```
+//!
+//! On SIGTERM each log finishes the batch it is reading within
+//! `--drain-timeout` milliseconds; positions are saved after every batch.
+
+pub mod cert;
+pub mod ct;
+
+use cert::{Cert, Patterns};
+use clap::Parser;
+use ct::{Entry, Log};
+use emergent_client::EmergentSource;
+use primitive_common::capabilities::{self, CapabilityArgs, Role, VERSION, capabilities_message};
+use primitive_common::doctor::{Report, check_writable_dir};
+use primitive_common::source::{Outlet, SourceArgs};
+use reqwest::Client;
+use serde_json::{Value, json};
+use std::collections::{BTreeMap, HashSet, VecDeque};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Duration;
+use tokio::signal::unix::{SignalKind, signal};
+use tokio::sync::watch;
+
+/// The event type published for matching certificates.
+pub const EVENT_TYPE: &str = "ct.cert_issued";
+
+/// Issuer and serial pairs remembered to skip repeats.
+const RECENT: usize = 10_000;
+
+/// Variables available to `--emit-type-template`.
+const TEMPLATE_VARIABLES: &[&str] = &["source"];
+
+/// Certificate Transparency log watcher that emits ct.cert_issued events.
+#[derive(Parser, Debug, Clone)]
+#[command(name = "ct-source", version = VERSION)]
+#[command(about = "Tails Certificate Transparency logs and emits events for matching certificates")]
+struct Args {
+    /// Domain pattern to match (repeatable or comma-separated).
+    #[arg(
+        long = "domain",
+        env = "CT_SOURCE_DOMAINS",
+        value_delimiter = ',',
+        required = true
+    )]
+    domains: Vec<String>,
+
+    /// Log URL to tail, e.g. https://ct.googleapis.com/logs/us1/argon2025h2/
+    /// (repeatable or comma-separated; default: every usable log in
+    /// `--log-list-url`).
+    #[arg(long = "log", env = "CT_SOURCE_LOGS", value_delimiter = ',')]
+    logs: Vec<String>,
+
+    /// Log list (v3 format) to take logs from when no `--log` is given.
+    #[arg(
+        long,
+        env = "CT_SOURCE_LOG_LIST_URL",
+        default_value = "https://www.gstatic.com/ct/log_list/v3/log_list.json"
+    )]
+    log_list_url: String,
+
+    /// Milliseconds between polls of each log.
+    #[arg(short, long, env = "CT_SOURCE_INTERVAL", default_value = "10000")]
+    interval: u64,
+
+    /// Entries to ask a log for at once.
+    #[arg(long, env = "CT_SOURCE_BATCH", default_value = "256")]
+    batch: u64,
+
+    /// Most entries a log may fall behind before skipping ahead.
+    #[arg(long, env = "CT_SOURCE_MAX_BACKLOG", default_value = "100000")]
+    max_backlog: u64,
+
+    /// Per-request timeout in milliseconds.
+    #[arg(short, long, env = "CT_SOURCE_TIMEOUT", default_value = "30000")]
+    timeout: u64,
+
+    /// File to keep each log's position in across restarts.
+    #[arg(long, env = "CT_SOURCE_STATE_FILE")]
+    state_file: Option<PathBuf>,
+
+    /// Verify external dependencies and exit without connecting to the engine.
+    #[arg(long)]
+    self_test: bool,
+
+    #[command(flatten)]
+    source: SourceArgs,
+
+    #[command(flatten)]
+    capabilities: CapabilityArgs,
+}
+
+/// Recently published issuer and serial pairs, oldest first.
+#[derive(Default)]
+struct Recent {
+    order: VecDeque<(String, String)>,
+    set: HashSet<(String, String)>,
+}
+
+impl Recent {
+    /// Remember `cert`, returning whether it is new.
+    fn insert(&mut self, cert: &Cert) -> bool {
+        let key = (cert.issuer.clone(), cert.serial.clone());
+        if !self.set.insert(key.clone()) {
+            return false;
+        }
+        self.order.push_back(key);
+        while self.order.len() > RECENT {
+            if let Some(oldest) = self.order.pop_front() {
+                self.set.remove(&oldest);
+            }
+        }
+        true
+    }
+
+    fn forget(&mut self, cert: &Cert) {
+        self.set.remove(&(cert.issuer.clone(), cert.serial.clone()));
+    }
+}
+
+/// State shared by the log readers.
+struct Shared {
+    outlet: Arc<Outlet>,
+    patterns: Patterns,
+    /// Next entry to read, per log URL.
+    positions: Mutex<BTreeMap<String, u64>>,
+    recent: Mutex<Recent>,
+    state_file: Option<PathBuf>,
+    /// Set on SIGTERM; readers stop after the batch they are on.
+    stop: watch::Sender<bool>,
+}
+
+impl Shared {
+    fn positions(&self) -> std::sync::MutexGuard<'_, BTreeMap<String, u64>> {
+        self.positions
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn recent(&self) -> std::sync::MutexGuard<'_, Recent> {
+        self.recent.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn stopping(&self) -> bool {
+        *self.stop.borrow()
+    }
+
+    fn advance(&self, log: &str, next: u64) {
+        let positions = {
+            let mut positions = self.positions();
+            positions.insert(log.to_string(), next);
+            positions.clone()
+        };
+        if let Some(path) = &self.state_file
+            && let Err(e) = save_state(path, &positions)
+        {
+            eprintln!("Failed to save state: {e}");
+        }
+    }
+}
+
+/// The event payload for `cert`, entry `index` of `log`, if it names a
+/// matching domain.
+fn event(patterns: &Patterns, log: &str, index: u64, entry: &Entry, cert: &Cert) -> Option<Value> {
+    let matched = patterns.matching(&cert.domains);
+    if matched.is_empty() {
+        return None;
+    }
+    Some(json!({
+        "log": log,
+        "index": index,
+        "logged_at": entry.timestamp,
+        "entry_type": entry.entry_type.as_str(),
+        "matched": matched,
+        "domains": cert.domains,
+        "subject": cert.subject,
+        "issuer": cert.issuer,
+        "serial": cert.serial,
+        "not_before": cert.not_before,
+        "not_after": cert.not_after,
+        "fingerprint_sha256": cert.fingerprint,
+    }))
+}
+
+/// The state saved in `path`, if any.
+fn load_state(path: &Path) -> Result<Option<BTreeMap<String, u64>>, String> {
+    match std::fs::read(path) {
+        Ok(bytes) => serde_json::from_slice(&bytes)
+            .map(Some)
+            .map_err(|e| format!("{}: {e}", path.display())),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(format!("{}: {e}", path.display())),
+    }
+}
+
+/// Save `positions` to `path`, replacing it atomically.
+fn save_state(path: &Path, positions: &BTreeMap<String, u64>) -> Result<(), String> {
+    let temp = path.with_extension("tmp");
+    let bytes = serde_json::to_vec(positions).map_err(|e| e.to_string())?;
+    std::fs::write(&temp, bytes)
+        .and_then(|()| std::fs::rename(&temp, path))
+        .map_err(|e| format!("{}: {e}", path.display()))
+}
+
+/// The logs to tail.
+async fn logs(args: &Args, client: &Client) -> Result<Vec<String>, String> {
+    if !args.logs.is_empty() {
+        return Ok(args.logs.clone());
+    }
+    let logs = ct::usable_logs(
+        client,
+        &args.log_list_url,
+        Duration::from_millis(args.timeout),
+    )
+    .await?;
+    if logs.is_empty() {
+        return Err(format!("{}: no usable logs", args.log_list_url));
+    }
+    Ok(logs)
+}
+
+/// Runs `--self-test` checks and exits.
+async fn self_test(args: &Args, name: &str) -> ! {
+    let mut report = Report::new(name);
+    let client = Client::new();
+    match logs(args, &client).await {
+        Ok(urls) => {
+            report.check("logs", Ok(format!("{} logs", urls.len())));
+            for url in &urls {
+                let log = Log::new(client.clone(), url, Duration::from_millis(args.timeout));
+                let size = log.tree_size().await.map(|size| format!("{size} entries"));
+                report.check(url, size);
+            }
+        }
+        Err(e) => report.check("logs", Err(e)),
+    }
+    if let Some(path) = &args.state_file {
+        let dir = path.parent().filter(|p| !p.as_os_str().is_empty());
+        report.check(
+            "state_file",
+            check_writable_dir(dir.unwrap_or(Path::new("."))),
+        );
+    }
+    args.source.self_test(&mut report, TEMPLATE_VARIABLES);
+    report.finish()
+}
+
+/// Read `log` from its position to its current end, publishing matches.
+async fn catch_up(args: &Args, shared: &Shared, url: &str, log: &Log) -> Result<(), String> {
+    let size = log.tree_size().await?;
+    let known = shared.positions().get(url).copied();
+    let Some(mut next) = known else {
+        eprintln!("Tailing {url} from entry {size}");
+        shared.advance(url, size);
+        return Ok(());
+    };
+    if size.saturating_sub(next) > args.max_backlog {
+        let skip_to = size - args.max_backlog;
+        eprintln!(
+            "{url} is {} entries behind; skipping to {skip_to}",
+            size - next
+        );
+        next = skip_to;
+    }
+    while next < size && !shared.stopping() {
+        let end = (next + args.batch.max(1)).min(size) - 1;
+        let entries = log.entries(next, end).await?;
+        if entries.is_empty() {
+            break;
+        }
+        for (offset, entry) in entries.iter().enumerate() {
+            let index = next + offset as u64;
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(e) => {
+                    eprintln!("Skipping {url} entry {index}: {e}");
+                    continue;
+                }
+            };
+            let Ok(cert) = Cert::from_der(&entry.certificate) else {
+                continue;
+            };
+            let Some(payload) = event(&shared.patterns, url, index, entry, &cert) else {
+                continue;
+            };
+            if !shared.recent().insert(&cert) {
+                continue;
+            }
+            if let Err(e) = shared.outlet.publish(EVENT_TYPE, &[], payload).await {
+                shared.recent().forget(&cert);
+                shared.advance(url, index);
+                return Err(e);
+            }
+        }
+        next += entries.len() as u64;
+        shared.advance(url, next);
+    }
+    Ok(())
+}
+
+/// Tail `url` until SIGTERM.
+async fn tail(args: Arc<Args>, shared: Arc<Shared>, url: String, client: Client) {
+    let log = Log::new(client, &url, Duration::from_millis(args.timeout));
+    let mut interval = tokio::time::interval(Duration::from_millis(args.interval));
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    let mut stop = shared.stop.subscribe();
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {}
+            _ = stop.wait_for(|stop| *stop) => return,
+        }
+        if let Err(e) = catch_up(&args, &shared, &url, &log).await {
+            eprintln!("Reading {url} failed: {e}");
+        }
+    }
+}
+
+/// Run the primitive with `args` as its command line (the first item is
+/// the program name, as with `std::env::args_os`).
+pub async fn run<I, T>(args: I) -> Result<(), Box<dyn std::error::Error>>
+where
+    I: IntoIterator<Item = T>,
+    T: Into<std::ffi::OsString> + Clone,
+{
+    let args = Args::parse_from(args);
+
+    // Get the source name from environment (set by engine) or use default
+    let name = std::env::var("EMERGENT_NAME").unwrap_or_else(|_| "ct-source".to_string());
+
+    if args.self_test {
+        self_test(&args, &name).await;
+    }
+    if let Err(e) = args.source.validate(TEMPLATE_VARIABLES) {
+        eprintln!("Error: {e}");
+        std::process::exit(1);
+    }
+    let positions = match args.state_file.as_deref().map(load_state).transpose() {
+        Ok(state) => state.flatten().unwrap_or_default(),
+        Err(e) => {
+            eprintln!("Error: cannot read state file {e}");
+            std::process::exit(1);
+        }
+    };
+    let client = Client::new();
+    let urls = match logs(&args, &client).await {
+        Ok(urls) => urls,
+        Err(e) => {
+            eprintln!("Error: cannot list logs: {e}");
+            std::process::exit(1);
+        }
+    };
+
+    let produces = args.source.produces(&[EVENT_TYPE]);
+    let produces: Vec<&str> = produces.iter().map(String::as_str).collect();
+    let descriptor = args
+        .capabilities
+        .describe(&name, Role::Source, &[], &produces, &args);
+    if args.capabilities.capabilities {
+        capabilities::print(&descriptor);
+    }
+
+    // Connect to the Emergent engine (silently - lifecycle events come from engine)
+    let source = match EmergentSource::connect(&name).await {
+        Ok(s) => s,
+        Err(e) => {
+            eprintln!("Failed to connect to Emergent engine: {e}");
+            std::process::exit(1);
+        }
+    };
+    let outlet = Arc::new(Outlet::new(source, &name, &args.source)?);
+    if args.capabilities.announce {
+        outlet.announce(capabilities_message(&descriptor)).await;
+    }
+    tokio::spawn(Arc::clone(&outlet).drain_every(args.source.spool.retry_interval()));
+
+    let shared = Arc::new(Shared {
+        outlet: Arc::clone(&outlet),
+        patterns: Patterns::new(&args.domains),
+        positions: Mutex::new(positions),
+        recent: Mutex::new(Recent::default()),
+        state_file: args.state_file.clone(),
+        stop: watch::Sender::new(false),
+    });
+    let args = Arc::new(args);
+    let mut sigterm = signal(SignalKind::terminate())?;
+    let mut readers = tokio::task::JoinSet::new();
+    for url in urls {
+        readers.spawn(tail(
+            Arc::clone(&args),
+            Arc::clone(&shared),
+            url,
+            client.clone(),
+        ));
+    }
+    sigterm.recv().await;
+    shared.stop.send_replace(true);
+    args.source
+        .drain
+        .drain("batches in progress", async {
+            while readers.join_next().await.is_some() {}
+        })
+        .await;
+    readers.shutdown().await;
+    outlet.disconnect().await;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{Json, Router, routing::get};
+    use base64::{Engine, engine::general_purpose::STANDARD};
+    use ct::EntryType;
+    use emergent_testkit::{MockEngine, fixtures};
+
+    fn cert(names: &[&str], serial: &str) -> Cert {
+        Cert {
+            subject: String::new(),
+            issuer: "CN=R11".to_string(),
+            serial: serial.to_string(),
+            not_before: 0,
+            not_after: 0,
+            domains: names.iter().map(|n| n.to_string()).collect(),
+            fingerprint: String::new(),
+        }
+    }
+
+    #[test]
+    fn only_matching_certificates_are_events() {
+        let patterns = Patterns::new(&["example.com".to_string()]);
+        let entry = Entry {
+            timestamp: 1_700_000_000_000,
+            entry_type: EntryType::Precert,
+            certificate: Vec::new(),
+        };
+        let matching = cert(&["shop.example.com", "cdn.other.net"], "01");
+        let payload = event(&patterns, "https://log/", 7, &entry, &matching)
+            .unwrap_or_else(|| panic!("no event"));
+        assert_eq!(payload["matched"], json!(["shop.example.com"]));
+        assert_eq!(payload["entry_type"], "precert");
+        assert_eq!(payload["index"], 7);
+        assert_eq!(
+            event(
+                &patterns,
+                "https://log/",
+                8,
+                &entry,
+                &cert(&["x.org"], "02")
+            ),
+            None
+        );
+
+        let mut recent = Recent::default();
+        assert!(recent.insert(&matching));
+        assert!(!recent.insert(&matching));
+        recent.forget(&matching);
+        assert!(recent.insert(&matching));
+    }
+
+    /// Serve a fake CT log holding one certificate for www.example.com.
+    async fn log_server() -> String {
+        let key = rcgen::KeyPair::generate().unwrap_or_else(|e| panic!("{e}"));
+        let params = rcgen::CertificateParams::new(vec!["www.example.com".to_string()])
+            .unwrap_or_else(|e| panic!("{e}"));
+        let der = params
+            .self_signed(&key)
+            .unwrap_or_else(|e| panic!("{e}"))
+            .der()
+            .to_vec();
+        // An x509_entry MerkleTreeLeaf
+        let mut leaf = vec![0, 0];
+        leaf.extend(1_700_000_000_000u64.to_be_bytes());
+        leaf.extend([0, 0]);
+        leaf.extend(&(der.len() as u32).to_be_bytes()[1..]);
+        leaf.extend(der);
+        let entry = json!({"leaf_input": STANDARD.encode(leaf), "extra_data": ""});
+
+        let app = Router::new()
+            .route(
+                "/ct/v1/get-sth",
+                get(|| async { Json(json!({"tree_size": 1})) }),
+            )
+            .route(
+                "/ct/v1/get-entries",
+                get(move || {
+                    let entry = entry.clone();
+                    async move { Json(json!({"entries": [entry]})) }
+                }),
+            );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap_or_else(|e| panic!("bind: {e}"));
+        let addr = listener
+            .local_addr()
+            .unwrap_or_else(|e| panic!("local_addr: {e}"));
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        format!("http://{addr}/")
+    }
+
+    /// Connect to the engine on `socket` as the source would, with `url`
+    /// read up to its first entry.
+    async fn connect(socket: &Path, args: &Args, url: &str) -> Shared {
+        let source = EmergentSource::connect_to("ct-source", socket)
+            .await
+            .unwrap_or_else(|e| panic!("connect: {e}"));
+        let outlet = Outlet::new(source, "ct-source", &args.source)
+            .unwrap_or_else(|e| panic!("open spool: {e}"));
+        Shared {
+            outlet: Arc::new(outlet),
+            patterns: Patterns::new(&args.domains),
+            positions: Mutex::new(BTreeMap::from([(url.to_string(), 0)])),
+            recent: Mutex::new(Recent::default()),
+            state_file: None,
+            stop: watch::Sender::new(false),
+        }
+    }
+
+    #[tokio::test]
+    async fn matches_survive_the_engine_being_down() {
+        let dir = fixtures::TempDir::new("ct-source-spool");
+        let socket = dir.path().join("engine.sock");
+        let spool = dir.path().join("spool");
+        let url = log_server().await;
+        let args = Args::parse_from([
+            "ct-source",
+            "--domain",
+            "example.com",
+            "--spool-dir",
+            spool.to_str().unwrap_or_default(),
+        ]);
+        let log = Log::new(Client::new(), &url, Duration::from_secs(5));
+
+        let mut engine = MockEngine::serve(&socket);
+        let shared = connect(&socket, &args, &url).await;
+        engine.shut_down().await;
+        assert_eq!(catch_up(&args, &shared, &url, &log).await, Ok(()));
+        assert_eq!(shared.positions().get(&url), Some(&1));
+        drop(shared);
+
+        let mut engine = MockEngine::serve(&socket);
+        connect(&socket, &args, &url)
+            .await
+            .outlet
+            .drain_spool()
+            .await;
+        let published = engine.expect_published(EVENT_TYPE).await;
+        assert_eq!(published.payload()["matched"], json!(["www.example.com"]));
+        engine.shut_down().await;
+    }
+
+    #[tokio::test]
+    async fn unpublished_matches_are_read_again() {
+        let dir = fixtures::TempDir::new("ct-source-down");
+        let socket = dir.path().join("engine.sock");
+        let url = log_server().await;
+        let args = Args::parse_from(["ct-source", "--domain", "example.com"]);
+        let log = Log::new(Client::new(), &url, Duration::from_secs(5));
+
+        let mut engine = MockEngine::serve(&socket);
+        let shared = connect(&socket, &args, &url).await;
+        engine.shut_down().await;
+        assert!(catch_up(&args, &shared, &url, &log).await.is_err());
+        assert_eq!(shared.positions().get(&url), Some(&0));
+        assert!(shared.recent().set.is_empty());
+    }
+
+    #[test]
+    fn state_survives_a_round_trip() {
+        let dir = std::env::temp_dir().join(format!("ct-source-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap_or_else(|e| panic!("mkdir: {e}"));
+        let path = dir.join("state.json");
+        assert_eq!(load_state(&path), Ok(None));
+
+        let positions = BTreeMap::from([("https://log/".to_string(), 42)]);
+        save_state(&path, &positions).unwrap_or_else(|e| panic!("save: {e}"));
+        assert_eq!(load_state(&path), Ok(Some(positions)));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,131 @@
+//! Emergent Compose - Pipeline Runner
+//!
+//! Launches every primitive of a pipeline from one YAML manifest (see
+//! [`manifest`]), supervises them, and tears them down together.
+//!
+//! Each primitive runs as a child process with `EMERGENT_NAME`, its topic
+//! wiring (`EMERGENT_PUBLISHES` / `EMERGENT_SUBSCRIBES`) and the engine
+//! socket (`EMERGENT_SOCKET`) in its environment. Output is prefixed with
+//! the primitive's name. Exited primitives are restarted according to their
+//! policy with exponential backoff.
+//!
+//! On SIGTERM or SIGINT every child gets SIGTERM and `--drain-timeout`
+//! milliseconds to finish before it is killed.
+//!
+//! # Usage
+//!
+//! ```bash
+//! # Validate the manifest and print its wiring
+//! emergent-compose -f pipeline.yaml --check
+//!
+//! # Run the pipeline
+//! emergent-compose -f pipeline.yaml
+//! ```
+
+mod manifest;
+mod supervisor;
+
+use clap::Parser;
+use manifest::Manifest;
+use primitive_common::{capabilities::VERSION, shutdown::DrainArgs};
+use std::{path::PathBuf, process::ExitCode};
+use supervisor::{Launch, Outcome, supervise};
+use tokio::{
+    signal::unix::{SignalKind, signal},
+    sync::watch,
+    task::JoinSet,
+};
+
+#[derive(Parser, Debug)]
+#[command(name = "emergent-compose", version = VERSION)]
+#[command(about = "Launch and supervise an Emergent pipeline from one manifest")]
+struct Args {
+    /// Pipeline manifest (YAML).
+    #[arg(
+        short,
+        long,
+        env = "EMERGENT_COMPOSE_FILE",
+        default_value = "emergent-compose.yaml"
+    )]
+    file: PathBuf,
+
+    /// Validate the manifest, print its wiring and exit.
+    #[arg(long)]
+    check: bool,
+
+    #[command(flatten)]
+    drain: DrainArgs,
+}
+
+#[tokio::main]
+async fn main() -> Result<ExitCode, Box<dyn std::error::Error>> {
+    let args = Args::parse();
+
+    let manifest = match Manifest::load(&args.file) {
+        Ok(manifest) => manifest,
+        Err(e) => {
+            eprintln!("Invalid manifest {e}");
+            return Ok(ExitCode::FAILURE);
+        }
+    };
+    for (name, topic) in manifest.unwired() {
+        eprintln!("Warning: '{name}' subscribes to {topic}, which no primitive publishes");
+    }
+
+    if args.check {
+        for primitive in &manifest.primitives {
+            println!(
+                "{}: {} (publishes: [{}], subscribes: [{}], restart: {:?})",
+                primitive.name,
+                primitive.command,
+                primitive.publishes.join(", "),
+                primitive.subscribes.join(", "),
+                manifest.policy(primitive).policy,
+            );
+        }
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    let mut sigterm = signal(SignalKind::terminate())?;
+    let mut sigint = signal(SignalKind::interrupt())?;
+    let (shutdown, stopped) = watch::channel(false);
+
+    let mut running = JoinSet::new();
+    for primitive in &manifest.primitives {
+        let launch = Launch {
+            env: manifest.env(primitive),
+            policy: manifest.policy(primitive),
+            primitive: primitive.clone(),
+            grace: args.drain.deadline(),
+        };
+        let name = primitive.name.clone();
+        let stopped = stopped.clone();
+        running.spawn(async move { (name, supervise(launch, stopped).await) });
+    }
+
+    let mut failed = false;
+    loop {
+        tokio::select! {
+            _ = sigterm.recv() => { let _ = shutdown.send(true); }
+            _ = sigint.recv() => { let _ = shutdown.send(true); }
+            done = running.join_next() => match done {
+                Some(Ok((_, Outcome::Stopped))) => {}
+                Some(Ok((name, Outcome::Failed(reason)))) => {
+                    eprintln!("'{name}' failed and will not be restarted: {reason}");
+                    failed = true;
+                }
+                Some(Err(e)) => {
+                    eprintln!("Supervisor task failed: {e}");
+                    failed = true;
+                }
+                None => break,
+            }
+        }
+    }
+
+    Ok(if failed {
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    })
+}
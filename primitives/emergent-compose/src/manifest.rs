@@ -0,0 +1,327 @@
+//! The pipeline manifest.
+//!
+//! ```yaml
+//! socket: /run/emergent/engine.sock
+//! restart:
+//!   policy: on-failure
+//!   backoff: 500
+//!   max_backoff: 30000
+//! primitives:
+//!   - name: ticker
+//!     command: exec-source
+//!     args: ["--interval", "5000", "--", "date"]
+//!     publishes: [exec.output]
+//!   - name: pager
+//!     command: exec-sink
+//!     args: ["--", "./page-oncall.sh"]
+//!     subscribes: [exec.output]
+//!     restart:
+//!       policy: always
+//! ```
+//!
+//! A primitive's `restart` block replaces the top-level one.
+
+use serde::Deserialize;
+use std::{
+    collections::{BTreeMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+/// A whole pipeline.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Manifest {
+    /// Engine socket, passed to every primitive as `EMERGENT_SOCKET`.
+    #[serde(default)]
+    pub socket: Option<String>,
+
+    /// Restart policy for primitives that don't set their own.
+    #[serde(default)]
+    pub restart: RestartPolicy,
+
+    /// The primitives to launch, in start order.
+    pub primitives: Vec<Primitive>,
+}
+
+/// One primitive process.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Primitive {
+    /// Unique name, passed as `EMERGENT_NAME` and used to prefix its output.
+    pub name: String,
+
+    /// Executable to run (looked up on `PATH`).
+    pub command: String,
+
+    /// Command-line arguments.
+    #[serde(default)]
+    pub args: Vec<String>,
+
+    /// Extra environment; wins over the variables set by compose.
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+
+    /// Message types this primitive publishes (`EMERGENT_PUBLISHES`).
+    #[serde(default)]
+    pub publishes: Vec<String>,
+
+    /// Message types this primitive subscribes to (`EMERGENT_SUBSCRIBES`).
+    #[serde(default)]
+    pub subscribes: Vec<String>,
+
+    /// Working directory, relative to the manifest.
+    #[serde(default)]
+    pub working_dir: Option<PathBuf>,
+
+    /// Overrides the top-level restart policy.
+    #[serde(default)]
+    pub restart: Option<RestartPolicy>,
+}
+
+/// When a primitive is restarted and how long to wait first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields, default)]
+pub struct RestartPolicy {
+    /// Which exits trigger a restart.
+    pub policy: Restart,
+
+    /// Milliseconds before the first restart; doubles on each crash.
+    pub backoff: u64,
+
+    /// Upper bound for the backoff in milliseconds.
+    pub max_backoff: u64,
+}
+
+impl Default for RestartPolicy {
+    fn default() -> Self {
+        Self {
+            policy: Restart::OnFailure,
+            backoff: 500,
+            max_backoff: 30_000,
+        }
+    }
+}
+
+impl RestartPolicy {
+    /// The delay before restart number `attempt` (0-based).
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let factor = 1u64.checked_shl(attempt).unwrap_or(u64::MAX);
+        Duration::from_millis(self.backoff.saturating_mul(factor).min(self.max_backoff))
+    }
+
+    /// Whether an exit (successful or not) should be followed by a restart.
+    pub fn restarts(&self, success: bool) -> bool {
+        match self.policy {
+            Restart::Always => true,
+            Restart::OnFailure => !success,
+            Restart::Never => false,
+        }
+    }
+}
+
+/// Which exits trigger a restart.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Restart {
+    /// Restart on every exit.
+    Always,
+    /// Restart only on a non-zero exit or a signal.
+    OnFailure,
+    /// Never restart.
+    Never,
+}
+
+impl Manifest {
+    /// Read and validate a manifest. Relative working directories are
+    /// resolved against the manifest's directory.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("{}: {e}", path.display()))?;
+        let mut manifest = Self::parse(&text).map_err(|e| format!("{}: {e}", path.display()))?;
+        let base = path.parent().unwrap_or(Path::new("."));
+        for primitive in &mut manifest.primitives {
+            if let Some(dir) = &primitive.working_dir {
+                primitive.working_dir = Some(base.join(dir));
+            }
+        }
+        Ok(manifest)
+    }
+
+    /// Parse and validate manifest text.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let manifest: Self = serde_yaml_ng::from_str(text).map_err(|e| e.to_string())?;
+        manifest.validate()?;
+        Ok(manifest)
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if self.primitives.is_empty() {
+            return Err("no primitives".to_string());
+        }
+        let mut names = HashSet::new();
+        for primitive in &self.primitives {
+            if primitive.name.trim().is_empty() {
+                return Err("a primitive has an empty name".to_string());
+            }
+            if !names.insert(primitive.name.as_str()) {
+                return Err(format!("duplicate primitive name '{}'", primitive.name));
+            }
+            if primitive.command.trim().is_empty() {
+                return Err(format!("'{}' has no command", primitive.name));
+            }
+            let policy = self.policy(primitive);
+            if policy.max_backoff < policy.backoff {
+                return Err(format!(
+                    "'{}': max_backoff {} is below backoff {}",
+                    primitive.name, policy.max_backoff, policy.backoff
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// The restart policy in effect for `primitive`.
+    pub fn policy(&self, primitive: &Primitive) -> RestartPolicy {
+        primitive.restart.unwrap_or(self.restart)
+    }
+
+    /// The environment compose sets for `primitive`, before its own `env`.
+    pub fn env(&self, primitive: &Primitive) -> Vec<(String, String)> {
+        let mut env = vec![("EMERGENT_NAME".to_string(), primitive.name.clone())];
+        if !primitive.publishes.is_empty() {
+            env.push((
+                "EMERGENT_PUBLISHES".to_string(),
+                primitive.publishes.join(","),
+            ));
+        }
+        if !primitive.subscribes.is_empty() {
+            env.push((
+                "EMERGENT_SUBSCRIBES".to_string(),
+                primitive.subscribes.join(","),
+            ));
+        }
+        if let Some(socket) = &self.socket {
+            env.push(("EMERGENT_SOCKET".to_string(), socket.clone()));
+        }
+        env.extend(primitive.env.iter().map(|(k, v)| (k.clone(), v.clone())));
+        env
+    }
+
+    /// Subscriptions no primitive in the manifest publishes. Wildcard
+    /// subscriptions are matched by prefix.
+    pub fn unwired(&self) -> Vec<(&str, &str)> {
+        let published: Vec<&str> = self
+            .primitives
+            .iter()
+            .flat_map(|p| p.publishes.iter().map(String::as_str))
+            .collect();
+        self.primitives
+            .iter()
+            .flat_map(|p| {
+                p.subscribes
+                    .iter()
+                    .map(move |s| (p.name.as_str(), s.as_str()))
+            })
+            .filter(|(_, topic)| {
+                !published.iter().any(|p| match topic.strip_suffix('*') {
+                    Some(prefix) => p.starts_with(prefix),
+                    None => p == topic,
+                })
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PIPELINE: &str = r#"
+socket: /tmp/engine.sock
+restart:
+  policy: on-failure
+  backoff: 100
+  max_backoff: 1000
+primitives:
+  - name: ticker
+    command: exec-source
+    args: ["--interval", "5000", "--", "date"]
+    publishes: [exec.output]
+  - name: pager
+    command: exec-sink
+    subscribes: [exec.*, alert.fired]
+    env:
+      EMERGENT_SOCKET: /tmp/other.sock
+    restart:
+      policy: always
+"#;
+
+    fn manifest(text: &str) -> Manifest {
+        Manifest::parse(text).unwrap_or_else(|e| panic!("parse: {e}"))
+    }
+
+    #[test]
+    fn parses_primitives_and_policies() {
+        let manifest = manifest(PIPELINE);
+        assert_eq!(manifest.primitives.len(), 2);
+        let ticker = &manifest.primitives[0];
+        assert_eq!(ticker.args, ["--interval", "5000", "--", "date"]);
+        assert_eq!(manifest.policy(ticker).policy, Restart::OnFailure);
+        assert_eq!(manifest.policy(ticker).backoff, 100);
+
+        let pager = &manifest.primitives[1];
+        assert_eq!(manifest.policy(pager).policy, Restart::Always);
+        assert_eq!(manifest.policy(pager).backoff, 500);
+    }
+
+    #[test]
+    fn env_carries_name_wiring_and_socket_with_overrides_last() {
+        let manifest = manifest(PIPELINE);
+        let env = manifest.env(&manifest.primitives[1]);
+        let value = |key: &str| {
+            env.iter()
+                .rev()
+                .find(|(k, _)| k == key)
+                .map(|(_, v)| v.as_str())
+        };
+        assert_eq!(value("EMERGENT_NAME"), Some("pager"));
+        assert_eq!(value("EMERGENT_SUBSCRIBES"), Some("exec.*,alert.fired"));
+        assert_eq!(value("EMERGENT_PUBLISHES"), None);
+        assert_eq!(value("EMERGENT_SOCKET"), Some("/tmp/other.sock"));
+    }
+
+    #[test]
+    fn unwired_subscriptions_are_reported() {
+        let manifest = manifest(PIPELINE);
+        assert_eq!(manifest.unwired(), [("pager", "alert.fired")]);
+    }
+
+    #[test]
+    fn invalid_manifests_are_rejected() {
+        assert!(Manifest::parse("primitives: []").is_err());
+        assert!(
+            Manifest::parse("primitives:\n  - {name: a, command: x}\n  - {name: a, command: y}")
+                .is_err()
+        );
+        assert!(Manifest::parse("primitives:\n  - {name: a, command: x, bogus: 1}").is_err());
+        assert!(
+            Manifest::parse(
+                "restart: {backoff: 100, max_backoff: 10}\nprimitives:\n  - {name: a, command: x}"
+            )
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_the_cap() {
+        let policy = RestartPolicy {
+            policy: Restart::Always,
+            backoff: 100,
+            max_backoff: 1000,
+        };
+        let delays: Vec<u64> = (0..6).map(|n| policy.delay(n).as_millis() as u64).collect();
+        assert_eq!(delays, [100, 200, 400, 800, 1000, 1000]);
+        assert_eq!(policy.delay(200), Duration::from_millis(1000));
+    }
+}
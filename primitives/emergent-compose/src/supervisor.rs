@@ -0,0 +1,216 @@
+//! Running one primitive: spawn, prefix its output, restart with backoff,
+//! and stop it on shutdown.
+
+use crate::manifest::{Primitive, RestartPolicy};
+use nix::{
+    sys::signal::{Signal, kill},
+    unistd::Pid,
+};
+use std::{
+    process::{ExitStatus, Stdio},
+    time::{Duration, Instant},
+};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncRead, BufReader},
+    process::{Child, Command},
+    sync::watch,
+};
+
+/// A run lasting at least this long resets the backoff.
+const STABLE_AFTER: Duration = Duration::from_secs(10);
+
+/// How a supervised primitive ended.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Outcome {
+    /// Exited successfully and was not restarted, or was stopped by shutdown.
+    Stopped,
+    /// Failed and its policy does not restart it.
+    Failed(String),
+}
+
+/// Everything needed to launch one primitive.
+pub struct Launch {
+    pub primitive: Primitive,
+    pub env: Vec<(String, String)>,
+    pub policy: RestartPolicy,
+    /// How long the child gets after SIGTERM before it is killed.
+    pub grace: Duration,
+}
+
+/// Run `launch` until its restart policy gives up or `shutdown` flips to
+/// `true`.
+pub async fn supervise(launch: Launch, mut shutdown: watch::Receiver<bool>) -> Outcome {
+    let name = launch.primitive.name.as_str();
+    let mut attempt = 0;
+    loop {
+        if *shutdown.borrow() {
+            return Outcome::Stopped;
+        }
+        let started = Instant::now();
+        let result = match spawn(&launch) {
+            Ok(mut child) => {
+                tokio::select! {
+                    status = child.wait() => status.map_err(|e| e.to_string()),
+                    _ = shutdown.changed() => {
+                        stop(name, &mut child, launch.grace).await;
+                        return Outcome::Stopped;
+                    }
+                }
+            }
+            Err(e) => Err(format!("failed to start {}: {e}", launch.primitive.command)),
+        };
+
+        let success = matches!(&result, Ok(status) if status.success());
+        let reason = describe(&result);
+        if !launch.policy.restarts(success) {
+            eprintln!("[{name}] {reason}");
+            return if success {
+                Outcome::Stopped
+            } else {
+                Outcome::Failed(reason)
+            };
+        }
+
+        if started.elapsed() >= STABLE_AFTER {
+            attempt = 0;
+        }
+        let delay = launch.policy.delay(attempt);
+        attempt = attempt.saturating_add(1);
+        eprintln!("[{name}] {reason}; restarting in {}ms", delay.as_millis());
+        tokio::select! {
+            _ = tokio::time::sleep(delay) => {}
+            _ = shutdown.changed() => return Outcome::Stopped,
+        }
+    }
+}
+
+fn spawn(launch: &Launch) -> std::io::Result<Child> {
+    let primitive = &launch.primitive;
+    let mut command = Command::new(&primitive.command);
+    command
+        .args(&primitive.args)
+        .envs(launch.env.iter().map(|(k, v)| (k, v)))
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true);
+    if let Some(dir) = &primitive.working_dir {
+        command.current_dir(dir);
+    }
+
+    let mut child = command.spawn()?;
+    if let Some(stdout) = child.stdout.take() {
+        tokio::spawn(forward(primitive.name.clone(), stdout, false));
+    }
+    if let Some(stderr) = child.stderr.take() {
+        tokio::spawn(forward(primitive.name.clone(), stderr, true));
+    }
+    eprintln!(
+        "[{}] started {} (pid {})",
+        primitive.name,
+        primitive.command,
+        child.id().unwrap_or_default()
+    );
+    Ok(child)
+}
+
+/// Copy a child's output line by line, prefixed with its name.
+async fn forward(name: String, stream: impl AsyncRead + Unpin, stderr: bool) {
+    let mut lines = BufReader::new(stream).lines();
+    while let Ok(Some(line)) = lines.next_line().await {
+        if stderr {
+            eprintln!("[{name}] {line}");
+        } else {
+            println!("[{name}] {line}");
+        }
+    }
+}
+
+/// SIGTERM the child, then SIGKILL it if it outlives `grace`.
+async fn stop(name: &str, child: &mut Child, grace: Duration) {
+    if let Some(pid) = child.id().and_then(|id| i32::try_from(id).ok()) {
+        let _ = kill(Pid::from_raw(pid), Signal::SIGTERM);
+    }
+    match tokio::time::timeout(grace, child.wait()).await {
+        Ok(result) => eprintln!("[{name}] {}", describe(&result.map_err(|e| e.to_string()))),
+        Err(_) => {
+            eprintln!(
+                "[{name}] still running {}ms after SIGTERM; killing",
+                grace.as_millis()
+            );
+            let _ = child.kill().await;
+        }
+    }
+}
+
+fn describe(result: &Result<ExitStatus, String>) -> String {
+    match result {
+        Ok(status) => format!("exited ({status})"),
+        Err(e) => e.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::manifest::Restart;
+    use std::collections::BTreeMap;
+
+    fn launch(script: &str, policy: Restart) -> Launch {
+        Launch {
+            primitive: Primitive {
+                name: "test".to_string(),
+                command: "sh".to_string(),
+                args: vec!["-c".to_string(), script.to_string()],
+                env: BTreeMap::new(),
+                publishes: Vec::new(),
+                subscribes: Vec::new(),
+                working_dir: None,
+                restart: None,
+            },
+            env: Vec::new(),
+            policy: RestartPolicy {
+                policy,
+                backoff: 10,
+                max_backoff: 20,
+            },
+            grace: Duration::from_millis(200),
+        }
+    }
+
+    #[tokio::test]
+    async fn failures_restart_until_the_child_succeeds() {
+        let dir = std::env::temp_dir().join(format!("compose-restart-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap_or_else(|e| panic!("mkdir: {e}"));
+        let count = dir.join("count");
+        let script = format!("echo run >> {0}; [ $(wc -l < {0}) -ge 3 ]", count.display());
+
+        let (_tx, rx) = watch::channel(false);
+        let outcome = supervise(launch(&script, Restart::OnFailure), rx).await;
+        assert_eq!(outcome, Outcome::Stopped);
+        let runs = std::fs::read_to_string(&count).unwrap_or_default();
+        assert_eq!(runs.lines().count(), 3);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn failures_are_reported_when_not_restarted() {
+        let (_tx, rx) = watch::channel(false);
+        let outcome = supervise(launch("exit 3", Restart::Never), rx).await;
+        assert!(matches!(outcome, Outcome::Failed(_)));
+    }
+
+    #[tokio::test]
+    async fn shutdown_stops_a_running_child() {
+        let (tx, rx) = watch::channel(false);
+        let task = tokio::spawn(supervise(launch("sleep 30", Restart::Always), rx));
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let _ = tx.send(true);
+        let outcome = tokio::time::timeout(Duration::from_secs(5), task)
+            .await
+            .unwrap_or_else(|_| panic!("child was not stopped"))
+            .unwrap_or_else(|e| panic!("join: {e}"));
+        assert_eq!(outcome, Outcome::Stopped);
+    }
+}
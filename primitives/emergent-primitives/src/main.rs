@@ -0,0 +1,279 @@
+//! Emergent Primitives - Multi-Call Binary
+//!
+//! Bundles every primitive into one executable, busybox style. The primitive
+//! is chosen by the name the binary was invoked under, so a symlink named
+//! after a primitive behaves exactly like the standalone binary:
+//!
+//! ```bash
+//! # Explicit subcommand
+//! emergent-primitives exec-sink -s alert.fired -- ./page-oncall.sh
+//!
+//! # argv[0] dispatch
+//! ln -s emergent-primitives /usr/local/bin/exec-sink
+//! exec-sink -s alert.fired -- ./page-oncall.sh
+//! ```
+//!
+//! All primitives share one tokio runtime setup; the individual binaries
+//! remain available from their own crates.
+
+use std::{
+    ffi::{OsStr, OsString},
+    path::Path,
+    process::ExitCode,
+};
+
+/// Name of this binary, used to tell argv[0] dispatch from subcommands.
+const MULTI_CALL: &str = "emergent-primitives";
+
+/// Every bundled primitive, by its standalone binary name.
+const PRIMITIVES: &[&str] = &[
+    "acme-sink",
+    "audit-sink",
+    "auth-source",
+    "azuredevops-source",
+    "backup-sink",
+    "bitbucket-source",
+    "budget",
+    "camera-source",
+    "chaos-handler",
+    "ci-trigger-sink",
+    "classify",
+    "compute",
+    "console-sink",
+    "contract-check",
+    "ct-source",
+    "energy-source",
+    "erasure",
+    "event-browser",
+    "exec-handler",
+    "exec-sink",
+    "exec-source",
+    "exposure-source",
+    "firewall-sink",
+    "gate",
+    "github-sink",
+    "github-source",
+    "gitlab-sink",
+    "gitlab-source",
+    "http-sink",
+    "http-source",
+    "irc-sink",
+    "irc-source",
+    "jenkins-source",
+    "lag-source",
+    "ldap-source",
+    "leader",
+    "market-source",
+    "matrix-sink",
+    "matrix-source",
+    "metering-sink",
+    "monitoring-sink",
+    "notion-sink",
+    "ntfy-sink",
+    "package-watch-source",
+    "pii-detect",
+    "power-sink",
+    "print-sink",
+    "push-sink",
+    "runbook-sink",
+    "sheet-sink",
+    "site-sink",
+    "sla",
+    "slack-sink",
+    "slack-source",
+    "snmp-sink",
+    "sse-sink",
+    "statuspage-sink",
+    "stream-runner",
+    "translate",
+    "vuln-source",
+    "webdav-sink",
+    "ws-broadcast-sink",
+    "xmpp-sink",
+    "zigbee-source",
+];
+
+/// Select the primitive named by argv[0] or, failing that, by the first
+/// argument. Returns the primitive and the command line it should parse.
+fn select(args: Vec<OsString>) -> Result<(&'static str, Vec<OsString>), String> {
+    if let Some(primitive) = lookup_invoked(&args) {
+        return Ok((primitive, args));
+    }
+
+    let requested = args.get(1).and_then(|a| a.to_str()).unwrap_or_default();
+    match lookup(requested) {
+        Some(primitive) => Ok((primitive, args.into_iter().skip(1).collect())),
+        None if requested.is_empty() => Err("no primitive given".to_string()),
+        None => Err(format!("unknown primitive '{requested}'")),
+    }
+}
+
+/// The primitive argv[0] names, if any.
+fn lookup_invoked(args: &[OsString]) -> Option<&'static str> {
+    args.first()
+        .map(Path::new)
+        .and_then(Path::file_stem)
+        .and_then(OsStr::to_str)
+        .and_then(lookup)
+}
+
+/// Find a primitive by name; `_` and `-` are interchangeable.
+fn lookup(name: &str) -> Option<&'static str> {
+    let name = name.replace('_', "-");
+    PRIMITIVES.iter().copied().find(|p| *p == name)
+}
+
+async fn dispatch(primitive: &str, args: Vec<OsString>) -> Result<(), Box<dyn std::error::Error>> {
+    match primitive {
+        "acme-sink" => acme_sink::run(args).await,
+        "audit-sink" => audit_sink::run(args).await,
+        "auth-source" => auth_source::run(args).await,
+        "azuredevops-source" => azuredevops_source::run(args).await,
+        "backup-sink" => backup_sink::run(args).await,
+        "bitbucket-source" => bitbucket_source::run(args).await,
+        "budget" => budget::run(args).await,
+        "camera-source" => camera_source::run(args).await,
+        "chaos-handler" => chaos_handler::run(args).await,
+        "ci-trigger-sink" => ci_trigger_sink::run(args).await,
+        "classify" => classify::run(args).await,
+        "compute" => compute::run(args).await,
+        "console-sink" => console_sink::run(args).await,
+        "contract-check" => contract_check::run(args).await,
+        "ct-source" => ct_source::run(args).await,
+        "energy-source" => energy_source::run(args).await,
+        "erasure" => erasure::run(args).await,
+        "event-browser" => event_browser::run(args).await,
+        "exec-handler" => exec_handler::run(args).await,
+        "exec-sink" => exec_sink::run(args).await,
+        "exec-source" => exec_source::run(args).await,
+        "exposure-source" => exposure_source::run(args).await,
+        "firewall-sink" => firewall_sink::run(args).await,
+        "gate" => gate::run(args).await,
+        "github-sink" => github_sink::run(args).await,
+        "github-source" => github_source::run(args).await,
+        "gitlab-sink" => gitlab_sink::run(args).await,
+        "gitlab-source" => gitlab_source::run(args).await,
+        "http-sink" => http_sink::run(args).await,
+        "http-source" => http_source::run(args).await,
+        "irc-sink" => irc_sink::run(args).await,
+        "irc-source" => irc_source::run(args).await,
+        "jenkins-source" => jenkins_source::run(args).await,
+        "lag-source" => lag_source::run(args).await,
+        "ldap-source" => ldap_source::run(args).await,
+        "leader" => leader::run(args).await,
+        "market-source" => market_source::run(args).await,
+        "matrix-sink" => matrix_sink::run(args).await,
+        "matrix-source" => matrix_source::run(args).await,
+        "metering-sink" => metering_sink::run(args).await,
+        "monitoring-sink" => monitoring_sink::run(args).await,
+        "notion-sink" => notion_sink::run(args).await,
+        "ntfy-sink" => ntfy_sink::run(args).await,
+        "package-watch-source" => package_watch_source::run(args).await,
+        "pii-detect" => pii_detect::run(args).await,
+        "power-sink" => power_sink::run(args).await,
+        "print-sink" => print_sink::run(args).await,
+        "push-sink" => push_sink::run(args).await,
+        "runbook-sink" => runbook_sink::run(args).await,
+        "sheet-sink" => sheet_sink::run(args).await,
+        "site-sink" => site_sink::run(args).await,
+        "sla" => sla::run(args).await,
+        "slack-sink" => slack_sink::run(args).await,
+        "slack-source" => slack_source::run(args).await,
+        "snmp-sink" => snmp_sink::run(args).await,
+        "sse-sink" => sse_sink::run(args).await,
+        "statuspage-sink" => statuspage_sink::run(args).await,
+        "stream-runner" => stream_runner::run(args).await,
+        "translate" => translate::run(args).await,
+        "vuln-source" => vuln_source::run(args).await,
+        "webdav-sink" => webdav_sink::run(args).await,
+        "ws-broadcast-sink" => ws_broadcast_sink::run(args).await,
+        "xmpp-sink" => xmpp_sink::run(args).await,
+        "zigbee-source" => zigbee_source::run(args).await,
+        other => Err(format!("unknown primitive '{other}'").into()),
+    }
+}
+
+fn usage() -> String {
+    format!(
+        "Usage: {MULTI_CALL} <PRIMITIVE> [ARGS]...\n\nPrimitives:\n  {}\n\nOr invoke through a symlink named after a primitive.",
+        PRIMITIVES.join("\n  ")
+    )
+}
+
+fn main() -> ExitCode {
+    let args: Vec<OsString> = std::env::args_os().collect();
+    match args.get(1).and_then(|a| a.to_str()) {
+        Some("--help" | "-h") if lookup_invoked(&args).is_none() => {
+            println!("{}", usage());
+            return ExitCode::SUCCESS;
+        }
+        Some("--list") if lookup_invoked(&args).is_none() => {
+            println!("{}", PRIMITIVES.join("\n"));
+            return ExitCode::SUCCESS;
+        }
+        _ => {}
+    }
+
+    let (primitive, args) = match select(args) {
+        Ok(selected) => selected,
+        Err(e) => {
+            eprintln!("Error: {e}\n\n{}", usage());
+            return ExitCode::from(2);
+        }
+    };
+
+    let runtime = match tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+    {
+        Ok(runtime) => runtime,
+        Err(e) => {
+            eprintln!("Error: failed to start runtime: {e}");
+            return ExitCode::FAILURE;
+        }
+    };
+    match runtime.block_on(dispatch(primitive, args)) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Error: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn argv(args: &[&str]) -> Vec<OsString> {
+        args.iter().map(OsString::from).collect()
+    }
+
+    #[test]
+    fn symlink_name_selects_the_primitive() {
+        let selected = select(argv(&["/usr/bin/exec-sink", "-s", "a.b"]));
+        assert_eq!(
+            selected,
+            Ok(("exec-sink", argv(&["/usr/bin/exec-sink", "-s", "a.b"])))
+        );
+    }
+
+    #[test]
+    fn first_argument_selects_the_primitive() {
+        let selected = select(argv(&[
+            "emergent-primitives",
+            "stream_runner",
+            "--self-test",
+        ]));
+        assert_eq!(
+            selected,
+            Ok(("stream-runner", argv(&["stream_runner", "--self-test"])))
+        );
+    }
+
+    #[test]
+    fn unknown_primitives_are_rejected() {
+        assert!(select(argv(&["emergent-primitives", "kafka-sink"])).is_err());
+        assert!(select(argv(&["emergent-primitives"])).is_err());
+    }
+}
@@ -0,0 +1,564 @@
+//! Test harness for Emergent primitives.
+//!
+//! Runs primitives end to end inside the test process: the [`MockEngine`]
+//! stands in for the engine, so tests inject messages and assert on what
+//! a primitive publishes without a running engine.
+//!
+//! ```no_run
+//! # async fn example(handler: impl primitive_common::SinkHandler) {
+//! use emergent_testkit::{SinkFixture, fixtures, spawn_sink};
+//! use primitive_common::SinkArgs;
+//! use serde_json::json;
+//!
+//! let args = SinkArgs { dry_run: true, ..Default::default() };
+//! let (mut engine, run) = spawn_sink(SinkFixture::new("exec_sink", "exec"), args, handler);
+//!
+//! engine.inject_message(fixtures::message("alert.fired", json!({"level": "high"}))).await;
+//! let report = engine.expect_published("exec.would_have").await;
+//! assert_eq!(report.payload()["message_type"], "alert.fired");
+//!
+//! engine.close();
+//! run.await.ok();
+//! # }
+//! ```
+//!
+//! Sources and handlers connect to a real socket instead:
+//! [`MockEngine::serve`] listens where `EmergentSource::connect_to` (or
+//! `EmergentHandler::connect_to`) is pointed, speaking the engine's IPC
+//! protocol through the same `acton-reactive` module `emergent-client`
+//! uses. What they publish reaches `expect_published` and any subscribers;
+//! injected messages are pushed to subscribers. [`MockEngine::shut_down`]
+//! drops every connection, for testing what a primitive does while the
+//! engine is gone, and serving the same path again brings it back.
+//!
+//! Provides:
+//! - `MockEngine` — `inject_message`, `expect_published`, `expect_quiet`
+//! - `MockEngine::serve` — the engine's unix socket, for sources and handlers
+//! - `spawn_sink` — run a [`SinkHandler`] against a fresh `MockEngine`
+//! - `fixtures` — message builders and self-cleaning temp directories
+//!
+//! # Scope
+//!
+//! Sinks attach one layer up, at the sink harness's connection:
+//! [`run_sink_loopback`] is the same code path as `run_sink` (inbox,
+//! retries, flush, shutdown) with only the client swapped out, which lets
+//! tests drive one message at a time without a socket. The socket mock
+//! routes publishes and subscriptions only; engine features beyond that
+//! (discovery, streams, `system.*` lifecycle events) are not served.
+
+pub mod fixtures;
+mod socket;
+
+use emergent_client::EmergentMessage;
+use event_schemas::EventPayload;
+use primitive_common::{Loopback, SinkArgs, SinkConfig, SinkHandler, run_sink_loopback};
+use std::path::Path;
+use std::time::Duration;
+use tokio::{sync::mpsc, task::JoinHandle};
+
+/// How long `expect_published` waits by default.
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// In-process stand-in for the Emergent engine.
+pub struct MockEngine {
+    inject: Option<mpsc::Sender<EmergentMessage>>,
+    published: mpsc::UnboundedReceiver<EmergentMessage>,
+    unclaimed: Vec<EmergentMessage>,
+    timeout: Duration,
+    /// The socket server, with [`serve`](Self::serve).
+    server: Option<JoinHandle<()>>,
+}
+
+impl MockEngine {
+    /// Create an engine and the [`Loopback`] a sink runs against.
+    pub fn new() -> (Self, Loopback) {
+        let (inject, inbound) = mpsc::channel(64);
+        let (outbound, published) = mpsc::unbounded_channel();
+        let engine = Self {
+            inject: Some(inject),
+            published,
+            unclaimed: Vec::new(),
+            timeout: DEFAULT_TIMEOUT,
+            server: None,
+        };
+        (engine, Loopback { inbound, outbound })
+    }
+
+    /// Serve the engine's unix socket at `path`, replacing a stale socket
+    /// file left there by an earlier engine.
+    ///
+    /// # Panics
+    ///
+    /// If the socket cannot be bound, or outside a Tokio runtime.
+    pub fn serve(path: &Path) -> Self {
+        let _ = std::fs::remove_file(path);
+        let listener = tokio::net::UnixListener::bind(path)
+            .unwrap_or_else(|e| panic!("bind {}: {e}", path.display()));
+        let (mut engine, loopback) = Self::new();
+        engine.server = Some(tokio::spawn(socket::serve(
+            listener,
+            path.to_path_buf(),
+            loopback.inbound,
+            loopback.outbound,
+        )));
+        engine
+    }
+
+    /// Change how long `expect_published` waits before failing.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Deliver `msg` to the sink (or, when serving the socket, to every
+    /// subscriber) as if it had been published on the bus.
+    ///
+    /// # Panics
+    ///
+    /// If the engine was closed or the sink has stopped.
+    pub async fn inject_message(&self, msg: EmergentMessage) {
+        let Some(inject) = &self.inject else {
+            panic!("inject_message after close()");
+        };
+        if inject.send(msg).await.is_err() {
+            panic!("sink stopped before the message could be injected");
+        }
+    }
+
+    /// Wait for the sink to publish a message of `message_type` and return
+    /// it. Messages of other types are kept for later expectations.
+    ///
+    /// # Panics
+    ///
+    /// If nothing matching arrives within the timeout; the panic lists the
+    /// types that were published instead.
+    pub async fn expect_published(&mut self, message_type: &str) -> EmergentMessage {
+        if let Some(i) = self
+            .unclaimed
+            .iter()
+            .position(|m| m.message_type.as_str() == message_type)
+        {
+            return self.unclaimed.remove(i);
+        }
+
+        let deadline = tokio::time::Instant::now() + self.timeout;
+        loop {
+            match tokio::time::timeout_at(deadline, self.published.recv()).await {
+                Ok(Some(msg)) if msg.message_type.as_str() == message_type => return msg,
+                Ok(Some(msg)) => self.unclaimed.push(msg),
+                Ok(None) | Err(_) => panic!(
+                    "expected a {message_type} message within {:?}; published instead: {:?}",
+                    self.timeout,
+                    self.unclaimed_types()
+                ),
+            }
+        }
+    }
+
+    /// Like [`expect_published`](Self::expect_published) for a schema type,
+    /// returning the decoded payload.
+    ///
+    /// # Panics
+    ///
+    /// If nothing arrives in time or the payload does not match the schema.
+    pub async fn expect_published_as<T: EventPayload>(&mut self) -> T {
+        let msg = self.expect_published(T::MESSAGE_TYPE).await;
+        T::from_payload(msg.payload()).unwrap_or_else(|e| {
+            panic!("{} payload does not match its schema: {e}", T::MESSAGE_TYPE)
+        })
+    }
+
+    /// Assert that nothing (not yet claimed) is published within `window`.
+    ///
+    /// # Panics
+    ///
+    /// If a message arrives, or unclaimed messages are already buffered.
+    pub async fn expect_quiet(&mut self, window: Duration) {
+        if let Ok(Some(msg)) = tokio::time::timeout(window, self.published.recv()).await {
+            self.unclaimed.push(msg);
+        }
+        assert!(
+            self.unclaimed.is_empty(),
+            "expected no publications; got {:?}",
+            self.unclaimed_types()
+        );
+    }
+
+    /// End the subscription so the sink drains and returns. When serving
+    /// the socket, the server starts shutting down too.
+    pub fn close(&mut self) {
+        self.inject = None;
+    }
+
+    /// Stop serving the socket, as an engine that went down: returns once
+    /// every connection is dropped and the socket file is gone. Messages
+    /// published before the shutdown can still be expected.
+    pub async fn shut_down(&mut self) {
+        self.close();
+        if let Some(server) = self.server.take() {
+            let _ = server.await;
+        }
+    }
+
+    fn unclaimed_types(&self) -> Vec<&str> {
+        self.unclaimed
+            .iter()
+            .map(|m| m.message_type.as_str())
+            .collect()
+    }
+}
+
+/// Owned counterpart of [`SinkConfig`] for sinks spawned onto a task.
+pub struct SinkFixture {
+    pub name: String,
+    pub subscribe: Vec<String>,
+    pub would_have_as: String,
+    pub dead_letter_as: String,
+}
+
+impl SinkFixture {
+    /// A fixture for sink `name` whose reports use `prefix` (e.g. `exec`
+    /// gives `exec.would_have` and `exec.dead_letter`).
+    pub fn new(name: &str, prefix: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            subscribe: Vec::new(),
+            would_have_as: format!("{prefix}.would_have"),
+            dead_letter_as: format!("{prefix}.dead_letter"),
+        }
+    }
+}
+
+/// Run `handler` under the sink harness against a new [`MockEngine`].
+///
+/// The returned task finishes once the engine is closed and the sink has
+/// drained; its error, if any, is stringified.
+pub fn spawn_sink<H: SinkHandler>(
+    fixture: SinkFixture,
+    args: SinkArgs,
+    handler: H,
+) -> (MockEngine, JoinHandle<Result<(), String>>) {
+    let (engine, loopback) = MockEngine::new();
+    let task = tokio::spawn(async move {
+        let config = SinkConfig {
+            name: &fixture.name,
+            subscribe: &fixture.subscribe,
+            would_have_as: &fixture.would_have_as,
+            dead_letter_as: &fixture.dead_letter_as,
+            settings: &args,
+        };
+        run_sink_loopback(config, &args, handler, loopback)
+            .await
+            .map_err(|e| e.to_string())
+    });
+    (engine, task)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use emergent_client::{EmergentHandler, EmergentSource};
+    use event_schemas::PrimitiveError;
+    use primitive_common::SinkContext;
+    use primitive_common::errors::{ErrorArgs, ErrorCategory, HandlerError};
+    use primitive_common::inbox::Inbox;
+    use primitive_common::shard::ShardArgs;
+    use primitive_common::shutdown::DrainArgs;
+    use serde_json::{Value, json};
+
+    /// Fails messages whose payload has `"fail": true`, dry-runs the rest.
+    struct Flaky;
+
+    impl SinkHandler for Flaky {
+        async fn handle(
+            &self,
+            _msg: &EmergentMessage,
+            ctx: &SinkContext<'_>,
+        ) -> Result<(), HandlerError> {
+            if ctx.payload()["fail"] == true {
+                return Err(HandlerError::new(ErrorCategory::Rejected, "refused"));
+            }
+            ctx.would_have("test", ctx.payload().clone()).await;
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn dry_run_reports_reach_the_engine() {
+        let args = SinkArgs {
+            dry_run: true,
+            ..Default::default()
+        };
+        let (mut engine, run) = spawn_sink(SinkFixture::new("flaky", "test"), args, Flaky);
+
+        engine
+            .inject_message(fixtures::message("job.created", json!({"id": 1})))
+            .await;
+        let report = engine.expect_published("test.would_have").await;
+        assert_eq!(report.payload()["detail"]["id"], 1);
+
+        engine.close();
+        assert_eq!(run.await.ok(), Some(Ok(())));
+    }
+
+    #[tokio::test]
+    async fn failures_are_dead_lettered_with_error_events() {
+        let args = SinkArgs {
+            max_attempts: 2,
+            retry_delay: 0,
+            dead_letter: true,
+            errors: ErrorArgs { emit_errors: true },
+            ..Default::default()
+        };
+        let (mut engine, run) = spawn_sink(SinkFixture::new("flaky", "test"), args, Flaky);
+
+        engine
+            .inject_message(fixtures::message("job.created", json!({"fail": true})))
+            .await;
+        let dead = engine.expect_published("test.dead_letter").await;
+        assert_eq!(dead.payload()["attempts"], 2);
+
+        let first = engine.expect_published_as::<PrimitiveError>().await;
+        let second = engine.expect_published_as::<PrimitiveError>().await;
+        assert_eq!(first.disposition, "retrying");
+        assert_eq!(second.disposition, "dead_lettered");
+
+        engine.close();
+        assert_eq!(run.await.ok(), Some(Ok(())));
+    }
+
+    #[tokio::test]
+    async fn messages_for_other_shards_are_skipped() {
+        let shard = ShardArgs {
+            shard_key: Some("payload.id".to_string()),
+            shard_index: 1,
+            shard_count: 2,
+            ..Default::default()
+        };
+        let owner = shard
+            .resolve("flaky")
+            .unwrap_or_else(|e| panic!("resolve: {e}"))
+            .unwrap_or_else(|| panic!("sharding should be on"));
+        let args = SinkArgs {
+            dry_run: true,
+            shard,
+            ..Default::default()
+        };
+        let (mut engine, run) = spawn_sink(SinkFixture::new("flaky", "test"), args, Flaky);
+
+        let payloads: Vec<Value> = (0..8).map(|id| json!({"id": id})).collect();
+        for payload in &payloads {
+            engine
+                .inject_message(fixtures::message("job.created", payload.clone()))
+                .await;
+        }
+        for payload in payloads.iter().filter(|p| owner.owns(p)) {
+            let report = engine.expect_published("test.would_have").await;
+            assert_eq!(&report.payload()["detail"], payload);
+        }
+        engine.expect_quiet(Duration::from_millis(50)).await;
+
+        engine.close();
+        assert_eq!(run.await.ok(), Some(Ok(())));
+    }
+
+    #[tokio::test]
+    async fn persisted_backoff_is_honoured_and_full_inbox_drops_messages() {
+        let dir = fixtures::TempDir::new("testkit-inbox");
+        let inbox = Inbox::open(dir.path()).unwrap_or_else(|e| panic!("open: {e}"));
+        let mut entry = inbox
+            .append("job.created", "msg_old", &json!({"id": 0}))
+            .unwrap_or_else(|e| panic!("append: {e}"));
+        inbox
+            .record_failure(&mut entry, "refused", Duration::from_secs(60))
+            .unwrap_or_else(|e| panic!("record: {e}"));
+
+        let args = SinkArgs {
+            dry_run: true,
+            inbox_dir: Some(dir.path().to_path_buf()),
+            max_attempts: 3,
+            max_inbox_size: 1,
+            drain: DrainArgs { drain_timeout: 50 },
+            ..Default::default()
+        };
+        let (mut engine, run) = spawn_sink(SinkFixture::new("flaky", "test"), args, Flaky);
+
+        engine
+            .inject_message(fixtures::message("job.created", json!({"id": 1})))
+            .await;
+        engine.expect_quiet(Duration::from_millis(100)).await;
+        engine.close();
+        assert_eq!(run.await.ok(), Some(Ok(())));
+
+        let pending = Inbox::open(dir.path())
+            .and_then(|inbox| inbox.pending())
+            .unwrap_or_else(|e| panic!("pending: {e}"));
+        let ids: Vec<&str> = pending.iter().map(|e| e.message_id.as_str()).collect();
+        assert_eq!(ids, ["msg_old"]);
+        assert_eq!(pending[0].attempts, 1);
+    }
+
+    #[tokio::test]
+    async fn expired_inbox_entries_are_dead_lettered() {
+        let dir = fixtures::TempDir::new("testkit-inbox");
+        let inbox = Inbox::open(dir.path()).unwrap_or_else(|e| panic!("open: {e}"));
+        inbox
+            .append("job.created", "msg_old", &json!({"id": 0}))
+            .unwrap_or_else(|e| panic!("append: {e}"));
+        tokio::time::sleep(Duration::from_millis(20)).await;
+
+        let args = SinkArgs {
+            dry_run: true,
+            dead_letter: true,
+            inbox_dir: Some(dir.path().to_path_buf()),
+            inbox_max_age: 10,
+            ..Default::default()
+        };
+        let (mut engine, run) = spawn_sink(SinkFixture::new("flaky", "test"), args, Flaky);
+
+        let dead = engine.expect_published("test.dead_letter").await;
+        assert_eq!(dead.payload()["message_id"], "msg_old");
+        assert_eq!(dead.payload()["attempts"], 0);
+        let error = dead.payload()["error"].as_str().unwrap_or_default();
+        assert!(error.starts_with("expired after"), "{error}");
+
+        engine.close();
+        assert_eq!(run.await.ok(), Some(Ok(())));
+    }
+
+    #[tokio::test]
+    async fn replayed_messages_keep_their_original_id() {
+        let dir = fixtures::TempDir::new("testkit-inbox");
+        let inbox = Inbox::open(dir.path()).unwrap_or_else(|e| panic!("open: {e}"));
+        inbox
+            .append("job.created", "msg_old", &json!({"id": 0}))
+            .unwrap_or_else(|e| panic!("append: {e}"));
+
+        let args = SinkArgs {
+            dry_run: true,
+            inbox_dir: Some(dir.path().to_path_buf()),
+            ..Default::default()
+        };
+        let (mut engine, run) = spawn_sink(SinkFixture::new("flaky", "test"), args, Flaky);
+
+        let report = engine.expect_published("test.would_have").await;
+        assert_eq!(report.payload()["message_id"], "msg_old");
+
+        engine.close();
+        assert_eq!(run.await.ok(), Some(Ok(())));
+    }
+
+    #[tokio::test]
+    async fn expect_quiet_passes_when_nothing_is_published() {
+        let (mut engine, run) = spawn_sink(
+            SinkFixture::new("flaky", "test"),
+            SinkArgs::default(),
+            Flaky,
+        );
+        engine.expect_quiet(Duration::from_millis(50)).await;
+        engine.close();
+        assert_eq!(run.await.ok(), Some(Ok(())));
+    }
+
+    /// Publishes one `test.flushed` event on flush.
+    #[derive(Default)]
+    struct Flushing(std::sync::OnceLock<primitive_common::Publisher>);
+
+    impl SinkHandler for Flushing {
+        async fn handle(
+            &self,
+            _msg: &EmergentMessage,
+            _ctx: &SinkContext<'_>,
+        ) -> Result<(), HandlerError> {
+            Ok(())
+        }
+
+        async fn flush(&self) {
+            if let Some(publisher) = self.0.get() {
+                let msg = fixtures::message("test.flushed", json!({}));
+                publisher
+                    .publish(msg)
+                    .unwrap_or_else(|e| panic!("publish: {e}"));
+            }
+        }
+
+        fn publishes(&self) -> &'static [&'static str] {
+            &["test.flushed"]
+        }
+
+        fn attach(&self, publisher: primitive_common::Publisher) {
+            let _ = self.0.set(publisher);
+        }
+    }
+
+    #[tokio::test]
+    async fn publishes_queued_while_flushing_are_sent_before_disconnecting() {
+        let (mut engine, run) = spawn_sink(
+            SinkFixture::new("flushing", "test"),
+            SinkArgs::default(),
+            Flushing::default(),
+        );
+        engine.close();
+        assert_eq!(run.await.ok(), Some(Ok(())));
+        engine.expect_published("test.flushed").await;
+    }
+
+    #[tokio::test]
+    async fn primitives_publish_and_subscribe_over_the_socket() {
+        let dir = fixtures::TempDir::new("testkit-socket");
+        let socket = dir.path().join("engine.sock");
+        let mut engine = MockEngine::serve(&socket);
+
+        let mut handler = EmergentHandler::connect_to("watcher", &socket)
+            .await
+            .unwrap_or_else(|e| panic!("connect handler: {e}"));
+        let mut stream = handler
+            .subscribe(["job.*"])
+            .await
+            .unwrap_or_else(|e| panic!("subscribe: {e}"));
+        let source = EmergentSource::connect_to("jobs", &socket)
+            .await
+            .unwrap_or_else(|e| panic!("connect source: {e}"));
+
+        // What the source publishes reaches the engine and the subscriber
+        source
+            .publish_ack(fixtures::message("job.created", json!({"id": 1})))
+            .await
+            .unwrap_or_else(|e| panic!("publish: {e}"));
+        let published = engine.expect_published("job.created").await;
+        assert_eq!(published.source.to_string(), "jobs");
+        let received = stream.next().await.unwrap_or_else(|| panic!("no push"));
+        assert_eq!(received.payload()["id"], 1);
+
+        // Injected messages are pushed to matching subscriptions only
+        engine
+            .inject_message(fixtures::message("other.created", json!({})))
+            .await;
+        engine
+            .inject_message(fixtures::message("job.done", json!({"id": 1})))
+            .await;
+        let received = stream.next().await.unwrap_or_else(|| panic!("no push"));
+        assert_eq!(received.message_type.as_str(), "job.done");
+
+        engine.shut_down().await;
+        assert!(!socket.exists());
+        let down = source
+            .publish_ack(fixtures::message("job.created", json!({"id": 2})))
+            .await;
+        assert!(down.is_err(), "published with the engine down");
+        assert!(stream.next().await.is_none());
+
+        // Serving the path again lets primitives reconnect
+        let mut engine = MockEngine::serve(&socket);
+        let source = EmergentSource::connect_to("jobs", &socket)
+            .await
+            .unwrap_or_else(|e| panic!("reconnect: {e}"));
+        source
+            .publish_ack(fixtures::message("job.created", json!({"id": 3})))
+            .await
+            .unwrap_or_else(|e| panic!("publish: {e}"));
+        let published = engine.expect_published("job.created").await;
+        assert_eq!(published.payload()["id"], 3);
+        engine.shut_down().await;
+    }
+}
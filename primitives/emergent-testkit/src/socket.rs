@@ -0,0 +1,238 @@
+//! The engine's unix socket, served for [`MockEngine::serve`](crate::MockEngine::serve).
+//!
+//! Frames are read and written with `acton-reactive`'s IPC protocol
+//! module, the same code `emergent-client` speaks through, so primitives
+//! connect with `EmergentSource::connect_to` (or `EMERGENT_SOCKET`) exactly
+//! as they would to the engine. The mock answers what primitives send:
+//! publishes (acknowledged when asked), subscribe and unsubscribe, and
+//! heartbeats; discovery is refused.
+
+use acton_reactive::ipc::protocol::{
+    Format, MAX_FRAME_SIZE, MSG_TYPE_DISCOVER, MSG_TYPE_ERROR, MSG_TYPE_PUSH, MSG_TYPE_REQUEST,
+    MSG_TYPE_RESPONSE, MSG_TYPE_SUBSCRIBE, MSG_TYPE_UNSUBSCRIBE, read_frame, write_frame,
+};
+use acton_reactive::ipc::{
+    IpcDiscoverRequest, IpcDiscoverResponse, IpcEnvelope, IpcPushNotification, IpcResponse,
+    IpcSubscribeRequest, IpcSubscriptionResponse, IpcUnsubscribeRequest,
+};
+use emergent_client::EmergentMessage;
+use primitive_common::glob;
+use serde::Deserialize;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use tokio::net::{UnixListener, UnixStream};
+use tokio::sync::mpsc;
+use tokio::task::JoinSet;
+
+/// The envelope payload `emergent-client` publishes with.
+#[derive(Deserialize)]
+struct Published {
+    inner: EmergentMessage,
+}
+
+/// A frame on its way to a client.
+type Frame = (u8, Format, Vec<u8>);
+
+/// A connected primitive, as the accept loop sees it.
+struct Client {
+    subscriptions: Arc<Mutex<Vec<String>>>,
+    frames: mpsc::UnboundedSender<Frame>,
+}
+
+impl Client {
+    /// Push `msg` if it matches a subscription. False once the client is gone.
+    fn push(&self, msg: &EmergentMessage) -> bool {
+        let subscribed = self
+            .subscriptions
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .iter()
+            .any(|pattern| glob::matches(pattern, msg.message_type.as_str()));
+        if !subscribed {
+            return !self.frames.is_closed();
+        }
+        let push = IpcPushNotification::new(
+            msg.message_type.as_str(),
+            Some(msg.source.to_string()),
+            serde_json::to_value(msg).unwrap_or_default(),
+        );
+        match Format::Json.serialize(&push) {
+            Ok(bytes) => self
+                .frames
+                .send((MSG_TYPE_PUSH, Format::Json, bytes))
+                .is_ok(),
+            Err(_) => true,
+        }
+    }
+}
+
+/// Accept primitives on `listener` until `inbound` closes, routing what
+/// they publish to `published` and to matching subscribers, along with
+/// everything sent on `inbound`. Connections are dropped and the socket
+/// file removed on the way out, as when the engine stops.
+pub(crate) async fn serve(
+    listener: UnixListener,
+    path: PathBuf,
+    mut inbound: mpsc::Receiver<EmergentMessage>,
+    published: mpsc::UnboundedSender<EmergentMessage>,
+) {
+    let (bus, mut from_clients) = mpsc::unbounded_channel();
+    let mut clients: Vec<Client> = Vec::new();
+    let mut connections = JoinSet::new();
+    loop {
+        tokio::select! {
+            accepted = listener.accept() => {
+                let Ok((stream, _)) = accepted else {
+                    continue;
+                };
+                let subscriptions = Arc::new(Mutex::new(Vec::new()));
+                let (frames, outgoing) = mpsc::unbounded_channel();
+                clients.push(Client {
+                    subscriptions: Arc::clone(&subscriptions),
+                    frames: frames.clone(),
+                });
+                connections.spawn(connection(stream, subscriptions, frames, outgoing, bus.clone()));
+            }
+            msg = from_clients.recv() => {
+                let Some(msg) = msg else {
+                    continue;
+                };
+                clients.retain(|client| client.push(&msg));
+                let _ = published.send(msg);
+            }
+            msg = inbound.recv() => {
+                let Some(msg) = msg else {
+                    break;
+                };
+                clients.retain(|client| client.push(&msg));
+            }
+        }
+    }
+    connections.shutdown().await;
+    // Publishes already acknowledged still count
+    while let Ok(msg) = from_clients.try_recv() {
+        let _ = published.send(msg);
+    }
+    let _ = std::fs::remove_file(&path);
+}
+
+/// Serve one primitive until it disconnects.
+async fn connection(
+    stream: UnixStream,
+    subscriptions: Arc<Mutex<Vec<String>>>,
+    frames: mpsc::UnboundedSender<Frame>,
+    mut outgoing: mpsc::UnboundedReceiver<Frame>,
+    bus: mpsc::UnboundedSender<EmergentMessage>,
+) {
+    let (mut reader, mut writer) = stream.into_split();
+    let read = async {
+        while let Ok((msg_type, format, payload)) = read_frame(&mut reader, MAX_FRAME_SIZE).await {
+            let reply = match msg_type {
+                MSG_TYPE_REQUEST => request(&payload, format, &bus),
+                MSG_TYPE_SUBSCRIBE => subscribe(&payload, format, &subscriptions, true),
+                MSG_TYPE_UNSUBSCRIBE => subscribe(&payload, format, &subscriptions, false),
+                MSG_TYPE_DISCOVER => discover(&payload, format),
+                // Heartbeats, and anything else a primitive has no business sending
+                _ => None,
+            };
+            if let Some(reply) = reply {
+                let _ = frames.send(reply);
+            }
+        }
+    };
+    let write = async {
+        while let Some((msg_type, format, bytes)) = outgoing.recv().await {
+            if write_frame(&mut writer, msg_type, format, &bytes)
+                .await
+                .is_err()
+            {
+                break;
+            }
+        }
+    };
+    tokio::select! {
+        () = read => {}
+        () = write => {}
+    }
+}
+
+/// A response frame for `response`, typed by whether it succeeded.
+fn respond<T: serde::Serialize>(response: &T, success: bool, format: Format) -> Option<Frame> {
+    let msg_type = if success {
+        MSG_TYPE_RESPONSE
+    } else {
+        MSG_TYPE_ERROR
+    };
+    format
+        .serialize(response)
+        .ok()
+        .map(|bytes| (msg_type, format, bytes))
+}
+
+/// Route a published message, acknowledging it when the client waits.
+fn request(
+    payload: &[u8],
+    format: Format,
+    bus: &mpsc::UnboundedSender<EmergentMessage>,
+) -> Option<Frame> {
+    let envelope: IpcEnvelope = format.deserialize(payload).ok()?;
+    let published = (envelope.message_type == "EmergentMessage")
+        .then(|| serde_json::from_value::<Published>(envelope.payload).ok())
+        .flatten();
+    let response = match published {
+        Some(Published { inner }) => {
+            let _ = bus.send(inner);
+            IpcResponse::success(&envelope.correlation_id, None)
+        }
+        None => IpcResponse::error_with_message(
+            &envelope.correlation_id,
+            "UNSUPPORTED",
+            format!("the mock engine does not handle {}", envelope.message_type),
+        ),
+    };
+    if !envelope.expects_reply {
+        return None;
+    }
+    respond(&response, response.success, format)
+}
+
+/// Add (or remove) subscriptions, answering with the resulting set.
+fn subscribe(
+    payload: &[u8],
+    format: Format,
+    subscriptions: &Mutex<Vec<String>>,
+    add: bool,
+) -> Option<Frame> {
+    let mut subscriptions = subscriptions
+        .lock()
+        .unwrap_or_else(std::sync::PoisonError::into_inner);
+    let correlation_id = if add {
+        let request: IpcSubscribeRequest = format.deserialize(payload).ok()?;
+        for message_type in request.message_types {
+            if !subscriptions.contains(&message_type) {
+                subscriptions.push(message_type);
+            }
+        }
+        request.correlation_id
+    } else {
+        let request: IpcUnsubscribeRequest = format.deserialize(payload).ok()?;
+        if request.message_types.is_empty() {
+            subscriptions.clear();
+        } else {
+            subscriptions.retain(|t| !request.message_types.contains(t));
+        }
+        request.correlation_id
+    };
+    let response = IpcSubscriptionResponse::success(correlation_id, subscriptions.clone());
+    respond(&response, true, format)
+}
+
+/// Refuse discovery, which no primitive under test relies on.
+fn discover(payload: &[u8], format: Format) -> Option<Frame> {
+    let request: IpcDiscoverRequest = format.deserialize(payload).ok()?;
+    let response = IpcDiscoverResponse::error(
+        request.correlation_id,
+        "discovery is not supported by the mock engine",
+    );
+    respond(&response, false, format)
+}
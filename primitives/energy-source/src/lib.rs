@@ -0,0 +1,593 @@
+//! Energy Source - Inverter and Meter Readings
+//!
+//! A Source that reads solar inverters and grid meters every `--interval`
+//! milliseconds and publishes `energy.reading` with production and
+//! consumption in watts and lifetime kWh (see [`reading`]). Meters are
+//! given as `--meter NAME=URI`, where the URI names the protocol:
+//!
+//! - `sunspec://HOST[:PORT][/UNIT]` — a SunSpec inverter or meter over
+//!   Modbus TCP, port 502 and unit 1 by default (see [`sunspec`])
+//! - `p1:/dev/ttyUSB0` or `p1+tcp://HOST:PORT` — a DSMR smart meter's P1
+//!   port, on a serial device or through a serial-to-network bridge (see
+//!   [`p1`])
+//! - `shelly://HOST` — a Shelly energy meter's HTTP API (see [`shelly`])
+//!
+//! With `--threshold [METER.]FIELD=VALUE`, `energy.threshold_crossed` is
+//! published whenever a reading goes above the value or falls back.
+//!
+//! `--emit-type-template` can also use `{protocol}`, `sunspec`, `p1` or
+//! `shelly`.
+//!
+//! Sources are SILENT - they only produce domain messages.
+//! All lifecycle events are published by the engine.
+//!
+//! # Usage
+//!
+//! ```bash
+//! energy-source --meter roof=sunspec://10.0.0.30 --meter grid=p1:/dev/ttyUSB0 \
+//!   --threshold grid.consumption_w=4000 --threshold roof.production_w=5000
+//! ```
+//!
+//! On SIGTERM the meters are closed and readings already taken get
+//! `--drain-timeout` milliseconds to be published.
+
+pub mod modbus;
+pub mod p1;
+pub mod reading;
+pub mod shelly;
+pub mod sunspec;
+
+use clap::Parser;
+use emergent_client::EmergentSource;
+use p1::{P1, Port};
+use primitive_common::capabilities::{self, CapabilityArgs, Role, VERSION, capabilities_message};
+use primitive_common::doctor::Report;
+use primitive_common::source::{Outlet, SourceArgs};
+use primitive_common::time;
+use reading::{Alerts, Reading, Threshold};
+use reqwest::Client;
+use serde_json::{Value, json};
+use shelly::Shelly;
+use std::sync::Arc;
+use std::time::Duration;
+use sunspec::SunSpec;
+use tokio::signal::unix::{SignalKind, signal};
+use tokio::sync::mpsc;
+
+/// The event type published with each reading.
+pub const READING_EVENT_TYPE: &str = "energy.reading";
+
+/// The event type published when a reading crosses a threshold.
+pub const THRESHOLD_EVENT_TYPE: &str = "energy.threshold_crossed";
+
+/// Variables available to `--emit-type-template`.
+const TEMPLATE_VARIABLES: &[&str] = &["source", "protocol"];
+
+/// The Modbus TCP port.
+const MODBUS_PORT: u16 = 502;
+
+/// Inverter and meter reader that emits energy.reading events.
+#[derive(Parser, Debug, Clone)]
+#[command(name = "energy-source", version = VERSION)]
+#[command(about = "Reads SunSpec, P1 and Shelly energy meters and emits energy readings")]
+struct Args {
+    /// Meter to read as NAME=URI, where URI is sunspec://HOST[:PORT][/UNIT],
+    /// p1:DEVICE, p1+tcp://HOST:PORT or shelly://HOST (repeatable or
+    /// comma-separated).
+    #[arg(
+        long = "meter",
+        env = "ENERGY_SOURCE_METERS",
+        value_delimiter = ',',
+        required = true
+    )]
+    meters: Vec<String>,
+
+    /// Milliseconds between readings.
+    #[arg(long, env = "ENERGY_SOURCE_INTERVAL", default_value = "10000")]
+    interval: u64,
+
+    /// Alert when a field crosses a value, as [METER.]FIELD=VALUE, where
+    /// FIELD is production_w, consumption_w, net_w, production_kwh or
+    /// consumption_kwh (repeatable or comma-separated).
+    #[arg(
+        long = "threshold",
+        env = "ENERGY_SOURCE_THRESHOLDS",
+        value_delimiter = ','
+    )]
+    thresholds: Vec<String>,
+
+    /// Baud rate of serial P1 ports: 115200 (DSMR 4 and later) or 9600
+    /// (DSMR 2.2 and 3).
+    #[arg(long, env = "ENERGY_SOURCE_P1_BAUD", default_value = "115200")]
+    p1_baud: u32,
+
+    /// Timeout for Modbus and HTTP requests in milliseconds.
+    #[arg(long, env = "ENERGY_SOURCE_TIMEOUT", default_value = "5000")]
+    timeout: u64,
+
+    /// Verify external dependencies and exit without connecting to the engine.
+    #[arg(long)]
+    self_test: bool,
+
+    #[command(flatten)]
+    source: SourceArgs,
+
+    #[command(flatten)]
+    capabilities: CapabilityArgs,
+}
+
+/// Where and how a meter is read.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Target {
+    SunSpec { addr: String, unit: u8 },
+    P1(Port),
+    Shelly(String),
+}
+
+impl Target {
+    fn protocol(&self) -> &'static str {
+        match self {
+            Target::SunSpec { .. } => "sunspec",
+            Target::P1(_) => "p1",
+            Target::Shelly(_) => "shelly",
+        }
+    }
+}
+
+/// Split a `NAME=URI` meter spec.
+fn parse_meter(spec: &str, p1_baud: u32) -> Result<(String, Target), String> {
+    let (name, uri) = spec
+        .split_once('=')
+        .map(|(name, uri)| (name.trim(), uri.trim()))
+        .filter(|(name, uri)| !name.is_empty() && !uri.is_empty())
+        .ok_or_else(|| format!("expected NAME=URI, got '{spec}'"))?;
+    if name.contains('.') {
+        return Err(format!("'{name}': meter names cannot contain '.'"));
+    }
+    let target = if let Some(rest) = uri.strip_prefix("sunspec://") {
+        let (host, unit) = match rest.split_once('/') {
+            Some((host, unit)) => (
+                host,
+                unit.parse()
+                    .map_err(|_| format!("'{uri}': '{unit}' is not a unit ID"))?,
+            ),
+            None => (rest, 1),
+        };
+        let addr = if host.contains(':') {
+            host.to_string()
+        } else {
+            format!("{host}:{MODBUS_PORT}")
+        };
+        Target::SunSpec { addr, unit }
+    } else if let Some(addr) = uri.strip_prefix("p1+tcp://") {
+        Target::P1(Port::Tcp(addr.trim_end_matches('/').to_string()))
+    } else if let Some(path) = uri.strip_prefix("p1:") {
+        Target::P1(Port::Serial {
+            path: path.to_string(),
+            baud: p1_baud,
+        })
+    } else if let Some(host) = uri.strip_prefix("shelly://") {
+        Target::Shelly(format!("http://{}", host.trim_end_matches('/')))
+    } else {
+        return Err(format!(
+            "'{uri}': expected sunspec://, p1:, p1+tcp:// or shelly://"
+        ));
+    };
+    Ok((name.to_string(), target))
+}
+
+/// A meter's connection.
+enum Device {
+    SunSpec(SunSpec),
+    P1(P1),
+    Shelly(Shelly),
+}
+
+impl Device {
+    fn open(meter: &str, target: &Target, client: &Client, timeout: Duration) -> Self {
+        match target {
+            Target::SunSpec { addr, unit } => {
+                Device::SunSpec(SunSpec::new(addr.clone(), *unit, timeout))
+            }
+            Target::P1(port) => Device::P1(P1::spawn(meter.to_string(), port.clone())),
+            Target::Shelly(base) => {
+                Device::Shelly(Shelly::new(client.clone(), base.clone(), timeout))
+            }
+        }
+    }
+
+    async fn read(&mut self) -> Result<Reading, String> {
+        match self {
+            Device::SunSpec(sunspec) => sunspec.read().await,
+            Device::P1(p1) => p1.read(),
+            Device::Shelly(shelly) => shelly.read().await,
+        }
+    }
+}
+
+/// A reading of one meter, for the main loop to publish.
+struct Sample {
+    meter: String,
+    protocol: &'static str,
+    read_at: i64,
+    reading: Reading,
+}
+
+/// Read a meter every `interval` until the task is aborted. Failures are
+/// logged once until the meter recovers.
+async fn poll(
+    meter: String,
+    target: Target,
+    client: Client,
+    interval: Duration,
+    timeout: Duration,
+    tx: mpsc::UnboundedSender<Sample>,
+) {
+    let mut device = Device::open(&meter, &target, &client, timeout);
+    let mut ticks = tokio::time::interval(interval);
+    ticks.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    if matches!(target, Target::P1(_)) {
+        // Give the port time for a first telegram
+        ticks.tick().await;
+    }
+    let mut failing = false;
+    loop {
+        ticks.tick().await;
+        match device.read().await {
+            Ok(reading) => {
+                if failing {
+                    eprintln!("{meter}: reading again");
+                    failing = false;
+                }
+                let sample = Sample {
+                    meter: meter.clone(),
+                    protocol: target.protocol(),
+                    read_at: time::now(),
+                    reading,
+                };
+                if tx.send(sample).is_err() {
+                    return;
+                }
+            }
+            Err(e) => {
+                if !failing {
+                    eprintln!("{meter}: {e}");
+                    failing = true;
+                }
+            }
+        }
+    }
+}
+
+/// The `energy.reading` payload of a sample.
+fn reading_payload(sample: &Sample) -> Value {
+    let mut payload = json!({
+        "meter": sample.meter,
+        "protocol": sample.protocol,
+        "read_at": time::format(sample.read_at),
+    });
+    if let Some(payload) = payload.as_object_mut() {
+        payload.extend(sample.reading.fields());
+    }
+    payload
+}
+
+/// Publish a sample's reading and the thresholds it crosses; the outlet
+/// logs and reports what is lost.
+async fn publish(outlet: &Outlet, alerts: &mut Alerts, thresholds: &[Threshold], sample: Sample) {
+    let vars = [("protocol", sample.protocol)];
+    let _ = outlet
+        .publish(READING_EVENT_TYPE, &vars, reading_payload(&sample))
+        .await;
+    for mut crossing in alerts.check(&sample.meter, &sample.reading, thresholds) {
+        crossing["meter"] = Value::from(sample.meter.as_str());
+        crossing["read_at"] = Value::from(time::format(sample.read_at));
+        let _ = outlet.publish(THRESHOLD_EVENT_TYPE, &vars, crossing).await;
+    }
+}
+
+/// Runs `--self-test` checks and exits.
+async fn self_test(
+    meters: &[(String, Target)],
+    client: &Client,
+    timeout: Duration,
+    source: &SourceArgs,
+    name: &str,
+) -> ! {
+    let mut report = Report::new(name);
+    for (meter, target) in meters {
+        let result = match target {
+            // A P1 port may take seconds to send a telegram; opening it is enough
+            Target::P1(port) => P1::probe(port).await,
+            _ => Device::open(meter, target, client, timeout)
+                .read()
+                .await
+                .map(|reading| {
+                    let fields = reading.fields();
+                    format!(
+                        "production {} W, consumption {} W",
+                        fields["production_w"], fields["consumption_w"]
+                    )
+                }),
+        };
+        report.check(meter, result);
+    }
+    source.self_test(&mut report, TEMPLATE_VARIABLES);
+    report.finish()
+}
+
+/// Run the primitive with `args` as its command line (the first item is
+/// the program name, as with `std::env::args_os`).
+pub async fn run<I, T>(args: I) -> Result<(), Box<dyn std::error::Error>>
+where
+    I: IntoIterator<Item = T>,
+    T: Into<std::ffi::OsString> + Clone,
+{
+    let args = Args::parse_from(args);
+
+    // Get the source name from environment (set by engine) or use default
+    let name = std::env::var("EMERGENT_NAME").unwrap_or_else(|_| "energy-source".to_string());
+
+    let exit = |e: String| -> ! {
+        eprintln!("Error: {e}");
+        std::process::exit(1);
+    };
+    if args.interval == 0 {
+        exit("--interval must be positive".to_string());
+    }
+    if args.p1_baud != 115_200 && args.p1_baud != 9600 {
+        exit(format!("--p1-baud: {} is not 115200 or 9600", args.p1_baud));
+    }
+    if let Err(e) = args.source.validate(TEMPLATE_VARIABLES) {
+        exit(e);
+    }
+    let mut meters: Vec<(String, Target)> = Vec::new();
+    for spec in &args.meters {
+        let (meter, target) =
+            parse_meter(spec, args.p1_baud).unwrap_or_else(|e| exit(format!("--meter: {e}")));
+        if meters.iter().any(|(known, _)| *known == meter) {
+            exit(format!("--meter: '{meter}' given twice"));
+        }
+        meters.push((meter, target));
+    }
+    let thresholds = args
+        .thresholds
+        .iter()
+        .map(|spec| Threshold::parse(spec))
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap_or_else(|e| exit(format!("--threshold: {e}")));
+    if let Some(threshold) = thresholds.iter().find(|threshold| {
+        threshold
+            .meter
+            .as_ref()
+            .is_some_and(|meter| !meters.iter().any(|(known, _)| known == meter))
+    }) {
+        exit(format!(
+            "--threshold: no --meter named '{}'",
+            threshold.meter.as_deref().unwrap_or_default()
+        ));
+    }
+    let client = Client::new();
+    let interval = Duration::from_millis(args.interval);
+    let timeout = Duration::from_millis(args.timeout);
+
+    if args.self_test {
+        self_test(&meters, &client, timeout, &args.source, &name).await;
+    }
+
+    let produces = args
+        .source
+        .produces(&[READING_EVENT_TYPE, THRESHOLD_EVENT_TYPE]);
+    let produces: Vec<&str> = produces.iter().map(String::as_str).collect();
+    let descriptor = args
+        .capabilities
+        .describe(&name, Role::Source, &[], &produces, &args);
+    if args.capabilities.capabilities {
+        capabilities::print(&descriptor);
+    }
+
+    // Connect to the Emergent engine (silently - lifecycle events come from engine)
+    let source = match EmergentSource::connect(&name).await {
+        Ok(s) => s,
+        Err(e) => {
+            eprintln!("Failed to connect to Emergent engine: {e}");
+            std::process::exit(1);
+        }
+    };
+    let outlet = Arc::new(Outlet::new(source, &name, &args.source)?);
+    if args.capabilities.announce {
+        outlet.announce(capabilities_message(&descriptor)).await;
+    }
+    tokio::spawn(Arc::clone(&outlet).drain_every(args.source.spool.retry_interval()));
+
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let pollers: Vec<_> = meters
+        .into_iter()
+        .map(|(meter, target)| {
+            tokio::spawn(poll(
+                meter,
+                target,
+                client.clone(),
+                interval,
+                timeout,
+                tx.clone(),
+            ))
+        })
+        .collect();
+
+    let mut alerts = Alerts::default();
+    let mut sigterm = signal(SignalKind::terminate())?;
+    loop {
+        tokio::select! {
+            _ = sigterm.recv() => break,
+
+            Some(sample) = rx.recv() => {
+                publish(&outlet, &mut alerts, &thresholds, sample).await;
+            }
+        }
+    }
+    // Close the meters, then publish what was already read
+    for poller in pollers {
+        poller.abort();
+    }
+    rx.close();
+    let queued = async {
+        while let Some(sample) = rx.recv().await {
+            publish(&outlet, &mut alerts, &thresholds, sample).await;
+        }
+    };
+    args.source.drain.drain("queued readings", queued).await;
+    outlet.disconnect().await;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn meter_uris_name_the_protocol() {
+        let parse = |spec| parse_meter(spec, 115_200).unwrap_or_else(|e| panic!("{e}"));
+        assert_eq!(
+            parse("roof=sunspec://10.0.0.30"),
+            (
+                "roof".to_string(),
+                Target::SunSpec {
+                    addr: "10.0.0.30:502".to_string(),
+                    unit: 1
+                }
+            )
+        );
+        assert_eq!(
+            parse("roof = sunspec://inverter.lan:1502/126").1,
+            Target::SunSpec {
+                addr: "inverter.lan:1502".to_string(),
+                unit: 126
+            }
+        );
+        assert_eq!(
+            parse("grid=p1:/dev/ttyUSB0").1,
+            Target::P1(Port::Serial {
+                path: "/dev/ttyUSB0".to_string(),
+                baud: 115_200
+            })
+        );
+        assert_eq!(
+            parse("grid=p1+tcp://10.0.0.40:2001").1,
+            Target::P1(Port::Tcp("10.0.0.40:2001".to_string()))
+        );
+        assert_eq!(
+            parse("house=shelly://10.0.0.20/").1,
+            Target::Shelly("http://10.0.0.20".to_string())
+        );
+        assert!(parse_meter("roof=modbus://10.0.0.30", 115_200).is_err());
+        assert!(parse_meter("roof=sunspec://10.0.0.30/x", 115_200).is_err());
+        assert!(parse_meter("roof.top=shelly://10.0.0.20", 115_200).is_err());
+    }
+
+    #[tokio::test]
+    async fn meters_are_polled_at_the_interval() {
+        use axum::{Json, Router, routing::get};
+
+        let app = Router::new().route(
+            "/rpc/Shelly.GetStatus",
+            get(|| async {
+                Json(json!({
+                    "em:0": {"total_act_power": -2100.0},
+                    "emdata:0": {"total_act": 1000.0, "total_act_ret": 5000.0},
+                }))
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap_or_else(|e| panic!("bind: {e}"));
+        let addr = listener
+            .local_addr()
+            .unwrap_or_else(|e| panic!("local_addr: {e}"));
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let poller = tokio::spawn(poll(
+            "house".to_string(),
+            Target::Shelly(format!("http://{addr}")),
+            Client::new(),
+            Duration::from_millis(50),
+            Duration::from_secs(5),
+            tx,
+        ));
+        for _ in 0..2 {
+            let sample = tokio::time::timeout(Duration::from_secs(5), rx.recv())
+                .await
+                .ok()
+                .flatten()
+                .unwrap_or_else(|| panic!("no reading"));
+            let payload = reading_payload(&sample);
+            assert_eq!(payload["meter"], "house");
+            assert_eq!(payload["protocol"], "shelly");
+            assert_eq!(payload["production_w"], 2100.0);
+            assert_eq!(payload["net_w"], -2100.0);
+            assert_eq!(payload["production_kwh"], 5.0);
+        }
+        poller.abort();
+    }
+
+    /// Connect to the engine on `socket` as the source would.
+    async fn connect(socket: &std::path::Path, args: &[&str]) -> Outlet {
+        let mut argv = vec!["energy-source", "--meter", "house=shelly://10.0.0.20"];
+        argv.extend_from_slice(args);
+        let args = Args::parse_from(argv);
+        let source = EmergentSource::connect_to("energy-source", socket)
+            .await
+            .unwrap_or_else(|e| panic!("connect: {e}"));
+        Outlet::new(source, "energy-source", &args.source)
+            .unwrap_or_else(|e| panic!("open spool: {e}"))
+    }
+
+    /// A Shelly sample producing `watts`.
+    fn sample(watts: f64) -> Sample {
+        Sample {
+            meter: "house".to_string(),
+            protocol: "shelly",
+            read_at: time::now(),
+            reading: Reading {
+                production_w: Some(watts),
+                ..Reading::default()
+            },
+        }
+    }
+
+    #[tokio::test]
+    async fn readings_survive_the_engine_being_down() {
+        use emergent_testkit::MockEngine;
+        use emergent_testkit::fixtures::TempDir;
+
+        let dir = TempDir::new("energy-source-spool");
+        let socket = dir.path().join("engine.sock");
+        let spool = dir.path().join("spool");
+        let args = [
+            "--spool-dir",
+            spool.to_str().unwrap_or_default(),
+            "--emit-type-template",
+            "{protocol}.{type}",
+        ];
+        let thresholds = [Threshold::parse("production_w=5000").unwrap_or_else(|e| panic!("{e}"))];
+
+        let mut engine = MockEngine::serve(&socket);
+        let outlet = connect(&socket, &args).await;
+        engine.shut_down().await;
+        let mut alerts = Alerts::default();
+        publish(&outlet, &mut alerts, &thresholds, sample(6000.0)).await;
+        drop(outlet);
+
+        let mut engine = MockEngine::serve(&socket);
+        connect(&socket, &args).await.drain_spool().await;
+        let reading = engine.expect_published("shelly.energy.reading").await;
+        assert_eq!(reading.payload()["production_w"], 6000.0);
+        let crossed = engine
+            .expect_published("shelly.energy.threshold_crossed")
+            .await;
+        assert_eq!(crossed.payload()["meter"], "house");
+        engine.shut_down().await;
+    }
+}
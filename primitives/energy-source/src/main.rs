@@ -0,0 +1,6 @@
+//! `energy-source` binary; the primitive itself lives in the library.
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    energy_source::run(std::env::args_os()).await
+}
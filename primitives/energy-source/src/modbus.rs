@@ -0,0 +1,181 @@
+//! A minimal Modbus TCP client: reading holding registers (function 3),
+//! which is all SunSpec needs.
+
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+/// The most registers one request may read.
+pub const MAX_REGISTERS: u16 = 125;
+
+const READ_HOLDING_REGISTERS: u8 = 0x03;
+
+/// What a Modbus exception code means.
+fn exception(code: u8) -> &'static str {
+    match code {
+        1 => "illegal function",
+        2 => "illegal data address",
+        3 => "illegal data value",
+        4 => "server device failure",
+        6 => "server device busy",
+        10 => "gateway path unavailable",
+        11 => "gateway target device failed to respond",
+        _ => "unknown exception",
+    }
+}
+
+/// A connection to one device.
+pub struct Modbus {
+    stream: TcpStream,
+    unit: u8,
+    timeout: Duration,
+    transaction: u16,
+}
+
+impl Modbus {
+    pub async fn connect(addr: &str, unit: u8, timeout: Duration) -> Result<Self, String> {
+        let stream = tokio::time::timeout(timeout, TcpStream::connect(addr))
+            .await
+            .map_err(|_| format!("{addr}: connection timed out"))?
+            .map_err(|e| format!("{addr}: {e}"))?;
+        Ok(Self {
+            stream,
+            unit,
+            timeout,
+            transaction: 0,
+        })
+    }
+
+    /// Read `count` holding registers from `address`, in as many requests
+    /// as it takes.
+    pub async fn read(&mut self, address: u16, count: u16) -> Result<Vec<u16>, String> {
+        let mut registers = Vec::with_capacity(usize::from(count));
+        let mut done = 0;
+        while done < count {
+            let chunk = (count - done).min(MAX_REGISTERS);
+            let start = address
+                .checked_add(done)
+                .ok_or_else(|| format!("register {address}+{done} is out of range"))?;
+            registers.extend(self.request(start, chunk).await?);
+            done += chunk;
+        }
+        Ok(registers)
+    }
+
+    async fn request(&mut self, address: u16, count: u16) -> Result<Vec<u16>, String> {
+        self.transaction = self.transaction.wrapping_add(1);
+        let mut frame = Vec::with_capacity(12);
+        frame.extend(self.transaction.to_be_bytes());
+        frame.extend(0u16.to_be_bytes()); // protocol
+        frame.extend(6u16.to_be_bytes()); // length of what follows
+        frame.push(self.unit);
+        frame.push(READ_HOLDING_REGISTERS);
+        frame.extend(address.to_be_bytes());
+        frame.extend(count.to_be_bytes());
+
+        let exchange = async {
+            self.stream.write_all(&frame).await?;
+            let mut header = [0u8; 7];
+            self.stream.read_exact(&mut header).await?;
+            let length = usize::from(u16::from_be_bytes([header[4], header[5]]));
+            let mut body = vec![0u8; length.saturating_sub(1)];
+            self.stream.read_exact(&mut body).await?;
+            Ok::<_, std::io::Error>((header, body))
+        };
+        let (header, body) = tokio::time::timeout(self.timeout, exchange)
+            .await
+            .map_err(|_| format!("reading register {address} timed out"))?
+            .map_err(|e| format!("reading register {address}: {e}"))?;
+
+        if u16::from_be_bytes([header[0], header[1]]) != self.transaction {
+            return Err(format!("reading register {address}: mismatched response"));
+        }
+        match body.as_slice() {
+            [function, code] if *function == READ_HOLDING_REGISTERS | 0x80 => Err(format!(
+                "reading register {address}: {} ({code})",
+                exception(*code)
+            )),
+            [READ_HOLDING_REGISTERS, bytes, data @ ..]
+                if usize::from(*bytes) == data.len() && data.len() == usize::from(count) * 2 =>
+            {
+                Ok(data
+                    .chunks_exact(2)
+                    .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+                    .collect())
+            }
+            _ => Err(format!("reading register {address}: malformed response")),
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::sync::Arc;
+    use tokio::net::TcpListener;
+
+    /// Serve holding registers over Modbus TCP; unmapped registers are an
+    /// illegal data address, as real devices answer. Returns the address.
+    pub(crate) async fn serve(registers: HashMap<u16, u16>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap_or_else(|e| panic!("bind: {e}"));
+        let addr = listener
+            .local_addr()
+            .unwrap_or_else(|e| panic!("local_addr: {e}"));
+        let registers = Arc::new(registers);
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let registers = Arc::clone(&registers);
+                tokio::spawn(async move {
+                    let mut request = [0u8; 12];
+                    while stream.read_exact(&mut request).await.is_ok() {
+                        let address = u16::from_be_bytes([request[8], request[9]]);
+                        let count = u16::from_be_bytes([request[10], request[11]]);
+                        let values: Option<Vec<u16>> = (0..count)
+                            .map(|i| registers.get(&(address + i)).copied())
+                            .collect();
+                        let pdu = match values {
+                            Some(values) => {
+                                let mut pdu = vec![3, (count * 2) as u8];
+                                pdu.extend(values.iter().flat_map(|v| v.to_be_bytes()));
+                                pdu
+                            }
+                            None => vec![0x83, 2],
+                        };
+                        let mut reply = request[..4].to_vec();
+                        reply.extend((pdu.len() as u16 + 1).to_be_bytes());
+                        reply.push(request[6]);
+                        reply.extend(pdu);
+                        if stream.write_all(&reply).await.is_err() {
+                            break;
+                        }
+                    }
+                });
+            }
+        });
+        addr.to_string()
+    }
+
+    #[tokio::test]
+    async fn registers_are_read_in_chunks() {
+        let registers = (0..300).map(|i| (1000 + i, i * 2)).collect();
+        let addr = serve(registers).await;
+        let mut modbus = Modbus::connect(&addr, 1, Duration::from_secs(5))
+            .await
+            .unwrap_or_else(|e| panic!("{e}"));
+        let values = modbus
+            .read(1000, 300)
+            .await
+            .unwrap_or_else(|e| panic!("{e}"));
+        assert_eq!(values.len(), 300);
+        assert_eq!(values[299], 598);
+
+        let error = modbus.read(1290, 20).await.err();
+        assert_eq!(
+            error.as_deref(),
+            Some("reading register 1290: illegal data address (2)")
+        );
+    }
+}
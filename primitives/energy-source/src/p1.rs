@@ -0,0 +1,325 @@
+//! DSMR smart meters' P1 port.
+//!
+//! The meter pushes a telegram every second (every ten on older meters):
+//! a header line starting `/`, lines of OBIS codes and values, and a
+//! closing `!` with a CRC16 of everything from `/` on (absent before DSMR
+//! 4). The port is read in the background, straight from a serial device
+//! or through a serial-to-network bridge like ser2net, and each reading
+//! takes the latest telegram.
+//!
+//! - `1-0:1.7.0` / `1-0:2.7.0` — power drawn from and returned to the grid
+//! - `1-0:1.8.1`, `1-0:1.8.2` / `1-0:2.8.1`, `1-0:2.8.2` — lifetime energy
+//!   drawn and returned, on the low and normal tariff
+//! - `0-n:24.2.1` — the gas meter on the meter's M-Bus, as `gas_m3`
+//! - `0-0:96.14.0` — the current tariff, as `tariff`
+
+use crate::reading::Reading;
+use nix::sys::termios::{self, BaudRate, ControlFlags, SetArg};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncBufReadExt, AsyncRead, BufReader};
+
+/// Delay before a closed or failed port is reopened.
+const RETRY: Duration = Duration::from_secs(5);
+
+/// Age at which the latest telegram no longer counts.
+const STALE: Duration = Duration::from_secs(60);
+
+/// CRC-16/ARC, as DSMR uses.
+pub fn crc16(data: &[u8]) -> u16 {
+    let mut crc = 0u16;
+    for byte in data {
+        crc ^= u16::from(*byte);
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0xa001
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    crc
+}
+
+/// A telegram's values, by OBIS code.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Telegram {
+    values: HashMap<String, String>,
+}
+
+impl Telegram {
+    /// Parse a whole telegram, from `/` to the line starting `!`.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let start = text.find('/').ok_or("no telegram header")?;
+        let end = text[start..]
+            .find('!')
+            .map(|end| start + end)
+            .ok_or("no telegram end")?;
+        let crc = text[end + 1..].lines().next().unwrap_or_default().trim();
+        if !crc.is_empty() {
+            let expected =
+                u16::from_str_radix(crc, 16).map_err(|_| format!("malformed CRC '{crc}'"))?;
+            let actual = crc16(&text.as_bytes()[start..=end]);
+            if actual != expected {
+                return Err(format!(
+                    "CRC mismatch: {actual:04X}, expected {expected:04X}"
+                ));
+            }
+        }
+
+        let mut values = HashMap::new();
+        for line in text[start..end].lines().skip(1) {
+            let line = line.trim();
+            if let Some(open) = line.find('(') {
+                let code = &line[..open];
+                // The last group holds the value, after any timestamp
+                let value = line
+                    .rsplit('(')
+                    .next()
+                    .and_then(|group| group.strip_suffix(')'))
+                    .unwrap_or_default();
+                values.insert(code.to_string(), value.to_string());
+            }
+        }
+        Ok(Self { values })
+    }
+
+    /// A value without its unit, like `1.193` for `01.193*kW`.
+    pub fn number(&self, code: &str) -> Option<f64> {
+        let value = self.values.get(code)?;
+        value.split('*').next()?.parse().ok()
+    }
+
+    /// The sum of whichever of `codes` the telegram has.
+    fn total(&self, codes: &[&str]) -> Option<f64> {
+        codes
+            .iter()
+            .filter_map(|code| self.number(code))
+            .reduce(|a, b| a + b)
+    }
+
+    pub fn reading(&self) -> Reading {
+        let kw = |code| self.number(code).map(|kw| kw * 1000.0);
+        let mut reading = Reading {
+            consumption_w: kw("1-0:1.7.0"),
+            production_w: kw("1-0:2.7.0"),
+            consumption_kwh: self.total(&["1-0:1.8.1", "1-0:1.8.2"]),
+            production_kwh: self.total(&["1-0:2.8.1", "1-0:2.8.2"]),
+            ..Reading::default()
+        };
+        let gas = self
+            .values
+            .keys()
+            .filter(|code| code.ends_with(":24.2.1"))
+            .min()
+            .and_then(|code| self.number(code));
+        if let Some(gas) = gas {
+            reading.extra.insert("gas_m3".to_string(), Value::from(gas));
+        }
+        if let Some(tariff) = self.number("0-0:96.14.0") {
+            reading
+                .extra
+                .insert("tariff".to_string(), Value::from(tariff as u64));
+        }
+        reading
+    }
+}
+
+/// Where a P1 port is read from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Port {
+    Serial { path: String, baud: u32 },
+    Tcp(String),
+}
+
+/// Configure a serial port: 115200 baud 8N1 for DSMR 4 and later, 9600
+/// baud 7E1 before.
+fn configure(file: &std::fs::File, baud: u32) -> Result<(), String> {
+    let mut settings = termios::tcgetattr(file).map_err(|e| e.to_string())?;
+    termios::cfmakeraw(&mut settings);
+    let speed = match baud {
+        9600 => BaudRate::B9600,
+        115_200 => BaudRate::B115200,
+        _ => {
+            return Err(format!(
+                "unsupported baud rate {baud}; expected 115200 or 9600"
+            ));
+        }
+    };
+    termios::cfsetspeed(&mut settings, speed).map_err(|e| e.to_string())?;
+    if baud == 9600 {
+        settings.control_flags.remove(ControlFlags::CSIZE);
+        settings.control_flags |= ControlFlags::CS7 | ControlFlags::PARENB;
+    }
+    settings.control_flags |= ControlFlags::CREAD | ControlFlags::CLOCAL;
+    termios::tcsetattr(file, SetArg::TCSANOW, &settings).map_err(|e| e.to_string())
+}
+
+impl Port {
+    async fn open(&self) -> Result<Box<dyn AsyncRead + Send + Unpin>, String> {
+        match self {
+            Port::Serial { path, baud } => {
+                let file = std::fs::File::open(path).map_err(|e| format!("{path}: {e}"))?;
+                configure(&file, *baud).map_err(|e| format!("{path}: {e}"))?;
+                Ok(Box::new(tokio::fs::File::from_std(file)))
+            }
+            Port::Tcp(addr) => {
+                let stream = tokio::net::TcpStream::connect(addr)
+                    .await
+                    .map_err(|e| format!("{addr}: {e}"))?;
+                Ok(Box::new(stream))
+            }
+        }
+    }
+}
+
+/// Collect telegrams from `reader` into `latest` until it closes.
+async fn follow(
+    reader: impl AsyncRead + Unpin,
+    latest: &Mutex<Option<(Instant, Telegram)>>,
+    meter: &str,
+) -> Result<(), String> {
+    let mut lines = BufReader::new(reader).lines();
+    let mut text = String::new();
+    while let Some(line) = lines.next_line().await.map_err(|e| e.to_string())? {
+        if line.starts_with('/') {
+            text.clear();
+        }
+        text.push_str(&line);
+        text.push_str("\r\n");
+        if line.starts_with('!') {
+            match Telegram::parse(&text) {
+                Ok(telegram) => {
+                    *latest.lock().unwrap_or_else(PoisonError::into_inner) =
+                        Some((Instant::now(), telegram));
+                }
+                Err(e) => eprintln!("{meter}: dropped telegram: {e}"),
+            }
+            text.clear();
+        }
+    }
+    Err("closed".to_string())
+}
+
+/// A P1 port read in the background.
+pub struct P1 {
+    latest: Arc<Mutex<Option<(Instant, Telegram)>>>,
+    task: tokio::task::JoinHandle<()>,
+}
+
+impl P1 {
+    pub fn spawn(meter: String, port: Port) -> Self {
+        let latest = Arc::new(Mutex::new(None));
+        let task = tokio::spawn({
+            let latest = Arc::clone(&latest);
+            async move {
+                loop {
+                    let result = match port.open().await {
+                        Ok(reader) => follow(reader, &latest, &meter).await,
+                        Err(e) => Err(e),
+                    };
+                    if let Err(e) = result {
+                        eprintln!("{meter}: {e}");
+                    }
+                    tokio::time::sleep(RETRY).await;
+                }
+            }
+        });
+        Self { latest, task }
+    }
+
+    pub fn read(&self) -> Result<Reading, String> {
+        match &*self.latest.lock().unwrap_or_else(PoisonError::into_inner) {
+            Some((at, telegram)) if at.elapsed() < STALE => Ok(telegram.reading()),
+            Some(_) => Err(format!("no telegram in the last {}s", STALE.as_secs())),
+            None => Err("no telegram yet".to_string()),
+        }
+    }
+
+    /// Open the port once, for `--self-test`.
+    pub async fn probe(port: &Port) -> Result<String, String> {
+        port.open().await.map(|_| "opened".to_string())
+    }
+}
+
+impl Drop for P1 {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::AsyncWriteExt;
+
+    const TELEGRAM: &str = concat!(
+        "/ISk5\\2MT382-1000\r\n",
+        "\r\n",
+        "1-3:0.2.8(50)\r\n",
+        "0-0:1.0.0(240520161558S)\r\n",
+        "1-0:1.8.1(001234.567*kWh)\r\n",
+        "1-0:1.8.2(000765.433*kWh)\r\n",
+        "1-0:2.8.1(000100.250*kWh)\r\n",
+        "1-0:2.8.2(000200.750*kWh)\r\n",
+        "0-0:96.14.0(0002)\r\n",
+        "1-0:1.7.0(01.193*kW)\r\n",
+        "1-0:2.7.0(00.000*kW)\r\n",
+        "0-1:24.1.0(003)\r\n",
+        "0-1:24.2.1(240520161500S)(01234.567*m3)\r\n",
+        "!935A\r\n",
+    );
+
+    #[test]
+    fn telegrams_are_checked_and_read() {
+        let telegram = Telegram::parse(TELEGRAM).unwrap_or_else(|e| panic!("{e}"));
+        let reading = telegram.reading();
+        assert_eq!(reading.consumption_w, Some(1193.0));
+        assert_eq!(reading.production_w, Some(0.0));
+        assert_eq!(reading.consumption_kwh, Some(2000.0));
+        assert_eq!(reading.production_kwh, Some(301.0));
+        assert_eq!(reading.extra["gas_m3"], 1234.567);
+        assert_eq!(reading.extra["tariff"], 2);
+
+        let corrupted = TELEGRAM.replace("01.193", "01.198");
+        assert_eq!(
+            Telegram::parse(&corrupted).err().as_deref(),
+            Some("CRC mismatch: 074E, expected 935A")
+        );
+
+        // DSMR 2.2 and 3 have no CRC
+        let unchecked = corrupted.replace("!935A", "!");
+        assert!(Telegram::parse(&unchecked).is_ok());
+    }
+
+    #[tokio::test]
+    async fn telegrams_are_followed_over_tcp() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap_or_else(|e| panic!("bind: {e}"));
+        let addr = listener
+            .local_addr()
+            .unwrap_or_else(|e| panic!("local_addr: {e}"));
+        tokio::spawn(async move {
+            if let Ok((mut stream, _)) = listener.accept().await {
+                // Join mid-telegram, as a bridge does
+                let _ = stream.write_all(&TELEGRAM.as_bytes()[40..]).await;
+                let _ = stream.write_all(TELEGRAM.as_bytes()).await;
+                tokio::time::sleep(Duration::from_secs(10)).await;
+            }
+        });
+
+        let p1 = P1::spawn("grid".to_string(), Port::Tcp(addr.to_string()));
+        assert_eq!(p1.read().err().as_deref(), Some("no telegram yet"));
+        for _ in 0..50 {
+            if let Ok(reading) = p1.read() {
+                assert_eq!(reading.consumption_w, Some(1193.0));
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        panic!("no telegram read");
+    }
+}
@@ -0,0 +1,222 @@
+//! Readings and threshold alerts.
+//!
+//! Every meter's reading is normalized to the same fields, each `null`
+//! when the meter does not report it:
+//!
+//! - `production_w` / `production_kwh` — power and lifetime energy an
+//!   inverter produces; for a grid meter, what is returned to the grid
+//! - `consumption_w` / `consumption_kwh` — power and lifetime energy
+//!   drawn from the grid
+//! - `net_w` — `consumption_w` less `production_w`, negative while
+//!   exporting
+//!
+//! A threshold, `[METER.]FIELD=VALUE`, publishes
+//! `energy.threshold_crossed` when the field rises above the value and
+//! again when it falls back to it or below. Without a meter it applies
+//! to every meter reporting the field.
+
+use serde_json::{Map, Value, json};
+use std::collections::HashMap;
+
+/// The fields a threshold can watch.
+pub const FIELDS: [&str; 5] = [
+    "production_w",
+    "consumption_w",
+    "net_w",
+    "production_kwh",
+    "consumption_kwh",
+];
+
+/// One reading of a meter.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Reading {
+    pub production_w: Option<f64>,
+    pub consumption_w: Option<f64>,
+    pub production_kwh: Option<f64>,
+    pub consumption_kwh: Option<f64>,
+    /// Anything else the meter reports, like the gas meter on a P1 port.
+    pub extra: Map<String, Value>,
+}
+
+/// Round to a resolution meters actually have, so scale factors do not
+/// leave values like `1234.5000000001`.
+fn round(value: f64, places: i32) -> f64 {
+    let factor = 10f64.powi(places);
+    (value * factor).round() / factor
+}
+
+impl Reading {
+    /// A grid meter's reading from its net power, positive while
+    /// importing, and its import and export totals.
+    pub fn grid(net_w: Option<f64>, imported_kwh: Option<f64>, exported_kwh: Option<f64>) -> Self {
+        Self {
+            consumption_w: net_w.map(|w| w.max(0.0)),
+            production_w: net_w.map(|w| (-w).max(0.0)),
+            consumption_kwh: imported_kwh,
+            production_kwh: exported_kwh,
+            extra: Map::new(),
+        }
+    }
+
+    pub fn net_w(&self) -> Option<f64> {
+        match (self.consumption_w, self.production_w) {
+            (None, None) => None,
+            (consumption, production) => {
+                Some(consumption.unwrap_or_default() - production.unwrap_or_default())
+            }
+        }
+    }
+
+    pub fn field(&self, name: &str) -> Option<f64> {
+        match name {
+            "production_w" => self.production_w,
+            "consumption_w" => self.consumption_w,
+            "net_w" => self.net_w(),
+            "production_kwh" => self.production_kwh,
+            "consumption_kwh" => self.consumption_kwh,
+            _ => None,
+        }
+    }
+
+    /// The reading as payload fields.
+    pub fn fields(&self) -> Map<String, Value> {
+        let watts = |value: Option<f64>| value.map(|w| round(w, 1));
+        let kwh = |value: Option<f64>| value.map(|e| round(e, 3));
+        let mut fields = match json!({
+            "production_w": watts(self.production_w),
+            "consumption_w": watts(self.consumption_w),
+            "net_w": watts(self.net_w()),
+            "production_kwh": kwh(self.production_kwh),
+            "consumption_kwh": kwh(self.consumption_kwh),
+        }) {
+            Value::Object(fields) => fields,
+            _ => Map::new(),
+        };
+        fields.extend(self.extra.clone());
+        fields
+    }
+}
+
+/// A threshold on a field of one meter, or of every meter.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Threshold {
+    pub meter: Option<String>,
+    pub field: String,
+    pub value: f64,
+}
+
+impl Threshold {
+    /// Parse `[METER.]FIELD=VALUE`.
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let (target, value) = spec
+            .split_once('=')
+            .ok_or_else(|| format!("expected [METER.]FIELD=VALUE, got '{spec}'"))?;
+        let value: f64 = value
+            .trim()
+            .parse()
+            .map_err(|_| format!("'{spec}': '{}' is not a number", value.trim()))?;
+        let target = target.trim();
+        let (meter, field) = match target.rsplit_once('.') {
+            Some((meter, field)) => (Some(meter.to_string()), field),
+            None => (None, target),
+        };
+        if !FIELDS.contains(&field) {
+            return Err(format!(
+                "'{spec}': unknown field '{field}', expected one of {}",
+                FIELDS.join(", ")
+            ));
+        }
+        Ok(Self {
+            meter,
+            field: field.to_string(),
+            value,
+        })
+    }
+}
+
+/// A threshold crossing, as the payload of `energy.threshold_crossed`.
+pub fn crossing(threshold: &Threshold, value: f64, above: bool) -> Value {
+    json!({
+        "field": threshold.field,
+        "threshold": threshold.value,
+        "value": round(value, 3),
+        "direction": if above { "above" } else { "below" },
+    })
+}
+
+/// Whether each meter's fields were last above their thresholds.
+#[derive(Debug, Default)]
+pub struct Alerts {
+    above: HashMap<(String, usize), bool>,
+}
+
+impl Alerts {
+    /// The crossings `reading` makes. A first reading only counts as a
+    /// crossing when it is above the threshold.
+    pub fn check(
+        &mut self,
+        meter: &str,
+        reading: &Reading,
+        thresholds: &[Threshold],
+    ) -> Vec<Value> {
+        let mut crossings = Vec::new();
+        for (index, threshold) in thresholds.iter().enumerate() {
+            if threshold.meter.as_deref().is_some_and(|name| name != meter) {
+                continue;
+            }
+            let Some(value) = reading.field(&threshold.field) else {
+                continue;
+            };
+            let above = value > threshold.value;
+            let was = self.above.insert((meter.to_string(), index), above);
+            if was.unwrap_or(false) != above {
+                crossings.push(crossing(threshold, value, above));
+            }
+        }
+        crossings
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn thresholds_alert_on_crossings() {
+        let thresholds = [
+            Threshold::parse("consumption_w=3000").unwrap_or_else(|e| panic!("{e}")),
+            Threshold::parse("roof.production_w = 500").unwrap_or_else(|e| panic!("{e}")),
+        ];
+        assert_eq!(thresholds[1].meter.as_deref(), Some("roof"));
+        assert!(Threshold::parse("voltage=230").is_err());
+        assert!(Threshold::parse("net_w=lots").is_err());
+
+        let mut alerts = Alerts::default();
+        let grid = |net_w: f64| Reading::grid(Some(net_w), None, None);
+        assert!(alerts.check("grid", &grid(1200.0), &thresholds).is_empty());
+        let crossings = alerts.check("grid", &grid(3400.0), &thresholds);
+        assert_eq!(
+            crossings,
+            vec![
+                json!({"field": "consumption_w", "threshold": 3000.0, "value": 3400.0, "direction": "above"})
+            ]
+        );
+        assert!(alerts.check("grid", &grid(3500.0), &thresholds).is_empty());
+        let crossings = alerts.check("grid", &grid(-800.0), &thresholds);
+        assert_eq!(crossings[0]["direction"], "below");
+        assert_eq!(crossings.len(), 1, "production_w only applies to roof");
+
+        // Already above on the first reading
+        let roof = Reading {
+            production_w: Some(2500.0),
+            ..Reading::default()
+        };
+        assert_eq!(alerts.check("roof", &roof, &thresholds).len(), 1);
+
+        let fields = grid(-812.34).fields();
+        assert_eq!(fields["consumption_w"], 0.0);
+        assert_eq!(fields["production_w"], 812.3);
+        assert_eq!(fields["net_w"], -812.3);
+        assert_eq!(fields["production_kwh"], Value::Null);
+    }
+}
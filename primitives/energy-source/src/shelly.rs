@@ -0,0 +1,204 @@
+//! Shelly energy meters over their local HTTP API.
+//!
+//! Second-generation devices (Pro 3EM, Pro EM, EM Gen3) answer
+//! `/rpc/Shelly.GetStatus` with `em:0` and `emdata:0` (three-phase) or
+//! `em1:N` and `em1data:N` (one component per clamp); first-generation
+//! devices (EM, 3EM) answer `/status` with `emeters`. Power is positive
+//! while importing and energy is in Wh either way. The generation is
+//! found on the first reading, trying the second first.
+
+use crate::reading::Reading;
+use reqwest::{Client, StatusCode};
+use serde_json::Value;
+use std::time::Duration;
+
+const GEN2_STATUS: &str = "/rpc/Shelly.GetStatus";
+const GEN1_STATUS: &str = "/status";
+
+fn wh(value: &Value) -> Option<f64> {
+    value.as_f64().map(|wh| wh / 1000.0)
+}
+
+/// Sum `field` over `items`, or `None` when none have it.
+fn sum<'a>(items: impl Iterator<Item = &'a Value>, field: &str) -> Option<f64> {
+    items
+        .filter_map(|item| item[field].as_f64())
+        .reduce(|a, b| a + b)
+}
+
+/// A reading from a second-generation status.
+pub fn gen2(status: &Value) -> Result<Reading, String> {
+    if status["em:0"].is_object() {
+        let data = &status["emdata:0"];
+        return Ok(Reading::grid(
+            status["em:0"]["total_act_power"].as_f64(),
+            wh(&data["total_act"]),
+            wh(&data["total_act_ret"]),
+        ));
+    }
+    let components = |prefix: &str| -> Vec<&Value> {
+        status
+            .as_object()
+            .into_iter()
+            .flatten()
+            .filter(|(key, _)| {
+                key.strip_prefix(prefix)
+                    .is_some_and(|n| n.parse::<u32>().is_ok())
+            })
+            .map(|(_, value)| value)
+            .collect()
+    };
+    let meters = components("em1:");
+    if meters.is_empty() {
+        return Err("no em or em1 component in the status".to_string());
+    }
+    let data = components("em1data:");
+    let kwh = |field| sum(data.iter().copied(), field).map(|wh| wh / 1000.0);
+    Ok(Reading::grid(
+        sum(meters.into_iter(), "act_power"),
+        kwh("total_act_energy"),
+        kwh("total_act_ret_energy"),
+    ))
+}
+
+/// A reading from a first-generation status.
+pub fn gen1(status: &Value) -> Result<Reading, String> {
+    let emeters = status["emeters"]
+        .as_array()
+        .ok_or("no emeters in the status")?;
+    let kwh = |field| sum(emeters.iter(), field).map(|wh| wh / 1000.0);
+    Ok(Reading::grid(
+        sum(emeters.iter(), "power"),
+        kwh("total"),
+        kwh("total_returned"),
+    ))
+}
+
+/// A Shelly device, and its generation once known.
+pub struct Shelly {
+    client: Client,
+    base: String,
+    timeout: Duration,
+    gen1: Option<bool>,
+}
+
+impl Shelly {
+    /// A device at `base`, like `http://10.0.0.20`.
+    pub fn new(client: Client, base: String, timeout: Duration) -> Self {
+        Self {
+            client,
+            base,
+            timeout,
+            gen1: None,
+        }
+    }
+
+    /// GET `path`; `None` when the device does not have it.
+    async fn status(&self, path: &str) -> Result<Option<Value>, String> {
+        let url = format!("{}{path}", self.base);
+        let response = self
+            .client
+            .get(&url)
+            .timeout(self.timeout)
+            .send()
+            .await
+            .map_err(|e| format!("{url}: {e}"))?;
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        if !response.status().is_success() {
+            return Err(format!("{url}: {}", response.status()));
+        }
+        let status = response.json().await.map_err(|e| format!("{url}: {e}"))?;
+        Ok(Some(status))
+    }
+
+    pub async fn read(&mut self) -> Result<Reading, String> {
+        if self.gen1 != Some(true) {
+            match self.status(GEN2_STATUS).await? {
+                Some(status) => {
+                    self.gen1 = Some(false);
+                    return gen2(&status);
+                }
+                None if self.gen1 == Some(false) => {
+                    return Err(format!("{}{GEN2_STATUS}: 404 Not Found", self.base));
+                }
+                None => {}
+            }
+        }
+        let status = self
+            .status(GEN1_STATUS)
+            .await?
+            .ok_or_else(|| format!("{}: not a Shelly energy meter", self.base))?;
+        self.gen1 = Some(true);
+        gen1(&status)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{Json, Router, routing::get};
+    use serde_json::json;
+
+    async fn serve(app: Router) -> String {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap_or_else(|e| panic!("bind: {e}"));
+        let addr = listener
+            .local_addr()
+            .unwrap_or_else(|e| panic!("local_addr: {e}"));
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        format!("http://{addr}")
+    }
+
+    #[tokio::test]
+    async fn both_generations_are_read() {
+        let pro3em = serve(Router::new().route(
+            GEN2_STATUS,
+            get(|| async {
+                Json(json!({
+                    "em:0": {"a_act_power": 800.0, "total_act_power": 1234.5},
+                    "emdata:0": {"total_act": 4_567_890.0, "total_act_ret": 123_000.0},
+                    "sys": {"uptime": 1000}
+                }))
+            }),
+        ))
+        .await;
+        let mut shelly = Shelly::new(Client::new(), pro3em, Duration::from_secs(5));
+        let reading = shelly.read().await.unwrap_or_else(|e| panic!("{e}"));
+        assert_eq!(reading.consumption_w, Some(1234.5));
+        assert_eq!(reading.production_w, Some(0.0));
+        assert_eq!(reading.consumption_kwh, Some(4567.89));
+        assert_eq!(reading.production_kwh, Some(123.0));
+        assert_eq!(shelly.gen1, Some(false));
+
+        let em = serve(Router::new().route(
+            GEN1_STATUS,
+            get(|| async {
+                Json(json!({"emeters": [
+                    {"power": -1500.0, "total": 1000.0, "total_returned": 2000.0},
+                    {"power": 300.0, "total": 500.0, "total_returned": 0.0},
+                ]}))
+            }),
+        ))
+        .await;
+        let mut shelly = Shelly::new(Client::new(), em, Duration::from_secs(5));
+        let reading = shelly.read().await.unwrap_or_else(|e| panic!("{e}"));
+        assert_eq!(reading.production_w, Some(1200.0));
+        assert_eq!(reading.consumption_kwh, Some(1.5));
+        assert_eq!(reading.production_kwh, Some(2.0));
+        assert_eq!(shelly.gen1, Some(true));
+
+        let pro_em = json!({
+            "em1:0": {"act_power": 400.0},
+            "em1:1": {"act_power": -100.0},
+            "em1data:0": {"total_act_energy": 1000.0, "total_act_ret_energy": 0.0},
+            "em1data:1": {"total_act_energy": 0.0, "total_act_ret_energy": 500.0},
+        });
+        let reading = gen2(&pro_em).unwrap_or_else(|e| panic!("{e}"));
+        assert_eq!(reading.consumption_w, Some(300.0));
+        assert_eq!(reading.production_kwh, Some(0.5));
+        assert!(gen2(&json!({"switch:0": {}})).is_err());
+    }
+}
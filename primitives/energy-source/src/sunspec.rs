@@ -0,0 +1,268 @@
+//! SunSpec inverters and meters over Modbus TCP.
+//!
+//! A SunSpec device marks its register map with `SunS` at register 40000
+//! (or 0, or 50000), followed by a chain of models, each an ID, a length
+//! and that many registers, ending with ID `0xFFFF`. The chain is walked
+//! once and remembered; each reading then reads only the models used:
+//!
+//! - inverters (models 101-103): `W` is production, `WH` lifetime
+//!   production
+//! - meters (models 201-204): `W` is positive while importing;
+//!   `TotWhImp` and `TotWhExp` are lifetime import and export
+//!
+//! Values are integers with a scale factor, a power of ten kept in another
+//! register. A device with both reports production from its inverter and
+//! consumption from its meter.
+
+use crate::modbus::Modbus;
+use crate::reading::Reading;
+use std::time::Duration;
+
+/// Registers the `SunS` marker may be found at.
+const BASES: [u16; 3] = [40000, 0, 50000];
+
+/// `SunS`, as two registers.
+const MARKER: [u16; 2] = [0x5375, 0x6e53];
+
+/// The ID that ends the model chain.
+const END: u16 = 0xffff;
+
+/// Registers of an inverter model, from the start of its data.
+mod inverter {
+    pub const W: usize = 12;
+    pub const W_SF: usize = 13;
+    pub const WH: usize = 22;
+    pub const WH_SF: usize = 24;
+    pub const LEN: u16 = 25;
+}
+
+/// Registers of a meter model, from the start of its data.
+mod meter {
+    pub const W: usize = 16;
+    pub const W_SF: usize = 20;
+    pub const TOT_WH_EXP: usize = 36;
+    pub const TOT_WH_IMP: usize = 44;
+    pub const TOT_WH_SF: usize = 52;
+    pub const LEN: u16 = 53;
+}
+
+/// A model in a device's chain.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Model {
+    pub id: u16,
+    /// The first register of the model's data, after its ID and length.
+    pub address: u16,
+    pub length: u16,
+}
+
+/// Walk the model chain.
+pub async fn discover(modbus: &mut Modbus) -> Result<Vec<Model>, String> {
+    let mut base = None;
+    for address in BASES {
+        if modbus.read(address, 2).await.ok().as_deref() == Some(&MARKER[..]) {
+            base = Some(address);
+            break;
+        }
+    }
+    let base = base.ok_or("no SunSpec marker at register 40000, 0 or 50000")?;
+
+    let mut models = Vec::new();
+    let mut address = base + 2;
+    loop {
+        let header = modbus.read(address, 2).await?;
+        let (id, length) = (header[0], header[1]);
+        if id == END {
+            return Ok(models);
+        }
+        let data = address + 2;
+        models.push(Model {
+            id,
+            address: data,
+            length,
+        });
+        address = data
+            .checked_add(length)
+            .ok_or("the model chain runs past the last register")?;
+    }
+}
+
+/// A scaled integer register, or `None` where it is not implemented.
+fn scaled(value: Option<f64>, sf: u16) -> Option<f64> {
+    if sf == 0x8000 {
+        return None;
+    }
+    let sf = i32::from(sf as i16);
+    // Dividing keeps values like 0.1 W exact where multiplying would not
+    value.map(|value| {
+        if sf < 0 {
+            value / 10f64.powi(-sf)
+        } else {
+            value * 10f64.powi(sf)
+        }
+    })
+}
+
+fn int16(value: u16) -> Option<f64> {
+    (value != 0x8000).then_some(f64::from(value as i16))
+}
+
+/// An `acc32` (accumulated, unsigned 32-bit) register pair.
+fn acc32(registers: &[u16], at: usize) -> Option<f64> {
+    let value = (u32::from(registers[at]) << 16) | u32::from(registers[at + 1]);
+    (value != 0).then_some(f64::from(value))
+}
+
+/// Production in watts and lifetime kWh from an inverter model's data.
+pub fn inverter(registers: &[u16]) -> (Option<f64>, Option<f64>) {
+    use inverter::*;
+    let w = scaled(int16(registers[W]), registers[W_SF]);
+    let wh = scaled(acc32(registers, WH), registers[WH_SF]);
+    (w, wh.map(|wh| wh / 1000.0))
+}
+
+/// A grid reading from a meter model's data.
+pub fn meter(registers: &[u16]) -> Reading {
+    use meter::*;
+    let net_w = scaled(int16(registers[W]), registers[W_SF]);
+    let kwh = |at| scaled(acc32(registers, at), registers[TOT_WH_SF]).map(|wh| wh / 1000.0);
+    Reading::grid(net_w, kwh(TOT_WH_IMP), kwh(TOT_WH_EXP))
+}
+
+/// A SunSpec device, and its model chain once walked.
+pub struct SunSpec {
+    addr: String,
+    unit: u8,
+    timeout: Duration,
+    models: Option<Vec<Model>>,
+}
+
+impl SunSpec {
+    pub fn new(addr: String, unit: u8, timeout: Duration) -> Self {
+        Self {
+            addr,
+            unit,
+            timeout,
+            models: None,
+        }
+    }
+
+    pub async fn read(&mut self) -> Result<Reading, String> {
+        let mut modbus = Modbus::connect(&self.addr, self.unit, self.timeout).await?;
+        let models = match &self.models {
+            Some(models) => models.clone(),
+            None => {
+                let models = discover(&mut modbus).await?;
+                self.models = Some(models.clone());
+                models
+            }
+        };
+        let inverter_model = models
+            .iter()
+            .find(|model| (101..=103).contains(&model.id) && model.length >= inverter::LEN);
+        let meter_model = models
+            .iter()
+            .find(|model| (201..=204).contains(&model.id) && model.length >= meter::LEN);
+
+        let mut reading = match meter_model {
+            Some(model) => meter(&modbus.read(model.address, meter::LEN).await?),
+            None => Reading::default(),
+        };
+        match inverter_model {
+            Some(model) => {
+                let (w, kwh) = inverter(&modbus.read(model.address, inverter::LEN).await?);
+                reading.production_w = w;
+                reading.production_kwh = kwh;
+            }
+            None if meter_model.is_none() => {
+                // The device may have been replaced by one without them
+                self.models = None;
+                return Err("no SunSpec inverter (101-103) or meter (201-204) model".to_string());
+            }
+            None => {}
+        }
+        Ok(reading)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modbus::tests::serve;
+    use std::collections::HashMap;
+
+    /// Registers of a device with a common model, an inverter and a meter.
+    fn device() -> HashMap<u16, u16> {
+        let mut registers = HashMap::new();
+        let mut put = |address: u16, values: &[u16]| {
+            for (i, value) in values.iter().enumerate() {
+                registers.insert(address + i as u16, *value);
+            }
+        };
+        put(40000, &MARKER);
+        put(40002, &[1, 66]);
+        put(40004, &[0; 66]);
+
+        // Inverter: 4321 W (sf 0), 12345678 Wh * 10^-1
+        let mut inverter = [0u16; 50];
+        inverter[inverter::W] = 4321;
+        inverter[inverter::WH] = (12_345_678u32 >> 16) as u16;
+        inverter[inverter::WH + 1] = 12_345_678u32 as u16;
+        inverter[inverter::WH_SF] = (-1i16) as u16;
+        put(40070, &[103, 50]);
+        put(40072, &inverter);
+
+        // Meter: exporting 1500 W (-150 * 10^1), 2500000 Wh in, 1200000 Wh out
+        let mut meter = [0x8000u16; 105];
+        meter[meter::W] = (-150i16) as u16;
+        meter[meter::W_SF] = 1;
+        meter[meter::TOT_WH_EXP] = (1_200_000u32 >> 16) as u16;
+        meter[meter::TOT_WH_EXP + 1] = 1_200_000u32 as u16;
+        meter[meter::TOT_WH_IMP] = (2_500_000u32 >> 16) as u16;
+        meter[meter::TOT_WH_IMP + 1] = 2_500_000u32 as u16;
+        meter[meter::TOT_WH_SF] = 0;
+        put(40122, &[203, 105]);
+        put(40124, &meter);
+
+        put(40229, &[END, 0]);
+        registers
+    }
+
+    #[tokio::test]
+    async fn inverters_and_meters_are_found_and_read() {
+        let addr = serve(device()).await;
+        let mut sunspec = SunSpec::new(addr, 1, Duration::from_secs(5));
+        let reading = sunspec.read().await.unwrap_or_else(|e| panic!("{e}"));
+        assert_eq!(
+            sunspec
+                .models
+                .as_ref()
+                .map(|models| models.iter().map(|m| m.id).collect::<Vec<_>>()),
+            Some(vec![1, 103, 203])
+        );
+        assert_eq!(reading.production_w, Some(4321.0));
+        assert_eq!(reading.fields()["production_kwh"], 1234.568);
+        assert_eq!(reading.consumption_w, Some(0.0));
+        assert_eq!(reading.consumption_kwh, Some(2500.0));
+
+        // Without an inverter, export counts as production
+        let mut registers = device();
+        registers.insert(40070, 120);
+        let addr = serve(registers).await;
+        let reading = SunSpec::new(addr, 1, Duration::from_secs(5))
+            .read()
+            .await
+            .unwrap_or_else(|e| panic!("{e}"));
+        assert_eq!(reading.production_w, Some(1500.0));
+        assert_eq!(reading.production_kwh, Some(1200.0));
+
+        let addr = serve(HashMap::from([(40000, 1), (40001, 2)])).await;
+        let error = SunSpec::new(addr, 1, Duration::from_secs(5))
+            .read()
+            .await
+            .err();
+        assert_eq!(
+            error.as_deref(),
+            Some("no SunSpec marker at register 40000, 0 or 50000")
+        );
+    }
+}
@@ -0,0 +1,345 @@
+//! Erasure
+//!
+//! A Handler that carries out right-to-erasure requests: for each
+//! `privacy.erasure_requested` event it deletes everything the subject has
+//! in the stores listed in `--stores` — SQLite archives, S3 objects, Redis
+//! keys and files — and confirms each store separately, so an auditor can
+//! see where the subject's data was and that it is gone.
+//!
+//! # Data Flow
+//!
+//! 1. Receive an erasure request naming the subject (the payload's
+//!    `--subject-field`, by default `subject`)
+//! 2. Refuse subjects that would widen a match or climb out of a
+//!    directory (empty, `..`, `/`, glob characters)
+//! 3. Erase the subject from each store in turn, publishing
+//!    `erasure.completed` or `erasure.failed` for it
+//! 4. Publish `erasure.summary`, `complete` only if every store succeeded
+//!
+//! Erasing is idempotent: a request that failed part-way can be sent again.
+//! With `--dry-run`, nothing is deleted and the counts are what would be.
+//!
+//! # Messages Published
+//!
+//! - `erasure.completed` — subject, store, kind, count deleted, the
+//!   request's `request_id` and whether it was a dry run
+//! - `erasure.failed` — subject, store, kind, error and `request_id`
+//! - `erasure.summary` — subject, `request_id`, `complete`, total deleted
+//!   and the outcome per store
+//!
+//! # Usage
+//!
+//! ```bash
+//! # Erase subjects from the stores in stores.json
+//! erasure --stores stores.json
+//!
+//! # See what a request would delete
+//! erasure --stores stores.json --dry-run
+//! ```
+
+mod redis;
+mod s3;
+mod stores;
+
+use clap::Parser;
+use emergent_client::EmergentMessage;
+use primitive_common::capabilities::{self, CapabilityArgs, Role, VERSION};
+use primitive_common::crypto::{KeyArgs, Keyring};
+use primitive_common::doctor::Report;
+use primitive_common::errors::{ERROR_EVENT_TYPE, ErrorArgs, ErrorCategory};
+use primitive_common::handler::{Bus, HandlerConfig, MessageHandler, run_handler};
+use primitive_common::{key, payload};
+use serde_json::{Map, Value, json};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::time::Duration;
+use stores::Store;
+
+/// Message type for each store erased.
+const COMPLETED: &str = "erasure.completed";
+/// Message type for each store that could not be erased.
+const FAILED: &str = "erasure.failed";
+/// Message type for the outcome of a request.
+const SUMMARY: &str = "erasure.summary";
+
+/// Erasure — delete a subject's data across stores.
+#[derive(Parser, Debug)]
+#[command(name = "erasure", version = VERSION)]
+#[command(about = "Erase a subject's data from SQLite, S3, Redis and files on request")]
+struct Args {
+    /// Message types carrying erasure requests.
+    #[arg(short, long = "subscribe", default_value = "privacy.erasure_requested")]
+    subscribe: Vec<String>,
+
+    /// JSON file listing the stores to erase from.
+    #[arg(long, env = "ERASURE_STORES")]
+    stores: PathBuf,
+
+    /// Payload field (e.g. `user.id`) holding the subject key.
+    #[arg(long, env = "ERASURE_SUBJECT_FIELD", default_value = "subject")]
+    subject_field: String,
+
+    /// Count what would be deleted without deleting it.
+    #[arg(long, env = "ERASURE_DRY_RUN")]
+    dry_run: bool,
+
+    /// Timeout for each store, in milliseconds.
+    #[arg(long, env = "ERASURE_TIMEOUT", default_value = "30000")]
+    timeout: u64,
+
+    /// Verify external dependencies and exit without connecting to the engine.
+    #[arg(long)]
+    self_test: bool,
+
+    #[command(flatten)]
+    errors: ErrorArgs,
+
+    #[command(flatten)]
+    capabilities: CapabilityArgs,
+
+    #[command(flatten)]
+    keys: KeyArgs,
+}
+
+/// The subject key at `field`; numbers are taken as their digits.
+fn subject(input: &Value, field: &str) -> Result<String, String> {
+    let subject = match key::lookup(input, field) {
+        Some(Value::String(subject)) => subject.clone(),
+        Some(Value::Number(number)) => number.to_string(),
+        Some(_) => return Err(format!("'{field}' is not a string")),
+        None => return Err(format!("no '{field}' in the request")),
+    };
+    stores::check_subject(&subject)?;
+    Ok(subject)
+}
+
+/// The `erasure.summary` payload for `outcomes`, each store's count deleted
+/// or error.
+fn summary(
+    subject: &str,
+    request_id: &Value,
+    dry_run: bool,
+    outcomes: &BTreeMap<&str, Result<u64, String>>,
+) -> Value {
+    let stores: Map<String, Value> = outcomes
+        .iter()
+        .map(|(name, outcome)| {
+            let outcome = match outcome {
+                Ok(deleted) => json!({"status": "completed", "deleted": deleted}),
+                Err(error) => json!({"status": "failed", "error": error}),
+            };
+            (name.to_string(), outcome)
+        })
+        .collect();
+    json!({
+        "subject": subject,
+        "request_id": request_id,
+        "complete": outcomes.values().all(Result::is_ok),
+        "deleted": outcomes.values().flatten().sum::<u64>(),
+        "dry_run": dry_run,
+        "stores": stores,
+    })
+}
+
+/// Run the primitive with `args` as its command line (the first item is
+/// the program name, as with `std::env::args_os`).
+pub async fn run<I, T>(args: I) -> Result<(), Box<dyn std::error::Error>>
+where
+    I: IntoIterator<Item = T>,
+    T: Into<std::ffi::OsString> + Clone,
+{
+    let args = Args::parse_from(args);
+
+    // Get the handler name from environment (set by engine) or use default
+    let name = std::env::var("EMERGENT_NAME").unwrap_or_else(|_| "erasure".to_string());
+
+    let stores = stores::load(&args.stores);
+    if args.self_test {
+        let mut report = Report::new(&name);
+        report.check(
+            "stores",
+            stores
+                .as_ref()
+                .map(|stores| {
+                    let names: Vec<String> = stores
+                        .iter()
+                        .map(|(name, store)| format!("{name} ({})", store.kind()))
+                        .collect();
+                    names.join(", ")
+                })
+                .map_err(Clone::clone),
+        );
+        args.keys.self_test(&mut report);
+        report.finish();
+    }
+    let stores = match stores {
+        Ok(stores) if stores.is_empty() => {
+            eprintln!("Error: no stores in {}", args.stores.display());
+            std::process::exit(1);
+        }
+        Ok(stores) => stores,
+        Err(e) => {
+            eprintln!("Error: failed to load stores: {e}");
+            std::process::exit(1);
+        }
+    };
+    let keys = match args.keys.load() {
+        Ok(keys) => keys,
+        Err(e) => {
+            eprintln!("Error: failed to load identity file: {e}");
+            std::process::exit(1);
+        }
+    };
+    let client = reqwest::Client::builder()
+        .timeout(Duration::from_millis(args.timeout))
+        .build()?;
+
+    let topics_refs: Vec<&str> = args.subscribe.iter().map(String::as_str).collect();
+    let mut produces = vec![COMPLETED, FAILED, SUMMARY];
+    if args.errors.emit_errors {
+        produces.push(ERROR_EVENT_TYPE);
+    }
+    let descriptor =
+        args.capabilities
+            .describe(&name, Role::Handler, &topics_refs, &produces, &args);
+    if args.capabilities.capabilities {
+        capabilities::print(&descriptor);
+    }
+
+    let config = HandlerConfig {
+        name: &name,
+        subscribe: &args.subscribe,
+        announce: args.capabilities.announce.then_some(&descriptor),
+        emit_errors: args.errors.emit_errors,
+    };
+    let mut erasure = Erasure {
+        args: &args,
+        stores,
+        client,
+        keys,
+    };
+    run_handler(config, &mut erasure).await
+}
+
+/// The stores to erase from and how to reach them.
+struct Erasure<'a> {
+    args: &'a Args,
+    stores: BTreeMap<String, Store>,
+    client: reqwest::Client,
+    keys: Option<Keyring>,
+}
+
+impl MessageHandler for Erasure<'_> {
+    type Wake = ();
+
+    /// Carry out one erasure request.
+    async fn handle(&mut self, msg: &EmergentMessage, bus: &Bus) {
+        let args = self.args;
+        let input = match payload::decode(msg.payload(), self.keys.as_ref()).await {
+            Ok(p) => p,
+            Err(e) => {
+                let error = format!("failed to decode payload: {e}");
+                eprintln!("erasure: {error}");
+                bus.report_error(msg, ErrorCategory::Parse, &error).await;
+                return;
+            }
+        };
+        let subject = match subject(&input, &args.subject_field) {
+            Ok(subject) => subject,
+            Err(e) => {
+                let error = format!("refusing request: {e}");
+                eprintln!("erasure: {error}");
+                bus.report_error(msg, ErrorCategory::Parse, &error).await;
+                return;
+            }
+        };
+        let request_id = input.get("request_id").cloned().unwrap_or(Value::Null);
+        let timeout = Duration::from_millis(args.timeout);
+
+        let mut outcomes = BTreeMap::new();
+        for (store_name, store) in &self.stores {
+            let outcome = match tokio::time::timeout(
+                timeout,
+                store.erase(&subject, args.dry_run, &self.client, timeout),
+            )
+            .await
+            {
+                Ok(outcome) => outcome,
+                Err(_) => Err("timed out".to_string()),
+            };
+            let (message_type, payload) = match &outcome {
+                Ok(deleted) => {
+                    eprintln!("erasure: {store_name}: {deleted} deleted for {subject}");
+                    (
+                        COMPLETED,
+                        json!({
+                            "subject": subject,
+                            "store": store_name,
+                            "kind": store.kind(),
+                            "deleted": deleted,
+                            "request_id": request_id,
+                            "dry_run": args.dry_run,
+                        }),
+                    )
+                }
+                Err(error) => {
+                    eprintln!("erasure: {store_name}: {error}");
+                    (
+                        FAILED,
+                        json!({
+                            "subject": subject,
+                            "store": store_name,
+                            "kind": store.kind(),
+                            "error": error,
+                            "request_id": request_id,
+                        }),
+                    )
+                }
+            };
+            let event = EmergentMessage::new(message_type)
+                .with_causation_id(msg.id())
+                .with_payload(payload);
+            bus.publish(event).await;
+            outcomes.insert(store_name.as_str(), outcome);
+        }
+
+        let summary = EmergentMessage::new(SUMMARY)
+            .with_causation_id(msg.id())
+            .with_payload(summary(&subject, &request_id, args.dry_run, &outcomes));
+        bus.publish(summary).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn subjects_are_read_and_the_summary_adds_up() {
+        let request = json!({"user": {"id": 42}, "request_id": "dsr-7"});
+        assert_eq!(subject(&request, "user.id"), Ok("42".to_string()));
+        assert!(subject(&request, "subject").is_err());
+        assert!(subject(&json!({"subject": "../etc"}), "subject").is_err());
+
+        let outcomes = BTreeMap::from([
+            ("events", Ok(3)),
+            ("uploads", Ok(2)),
+            ("sessions", Err("connection refused".to_string())),
+        ]);
+        assert_eq!(
+            summary("42", &json!("dsr-7"), false, &outcomes),
+            json!({
+                "subject": "42",
+                "request_id": "dsr-7",
+                "complete": false,
+                "deleted": 5,
+                "dry_run": false,
+                "stores": {
+                    "events": {"status": "completed", "deleted": 3},
+                    "sessions": {"status": "failed", "error": "connection refused"},
+                    "uploads": {"status": "completed", "deleted": 2},
+                },
+            })
+        );
+    }
+}
@@ -0,0 +1,242 @@
+//! Deleting objects from S3, or any S3-compatible store (MinIO, R2, ...).
+//!
+//! A subject's objects are found with `ListObjectsV2` under a key prefix
+//! and removed one `DeleteObject` at a time. Requests are addressed
+//! path-style (`<endpoint>/<bucket>/<key>`) so custom endpoints work
+//! without DNS for every bucket, and signed with AWS Signature Version 4.
+
+use primitive_common::aws::{CredentialChain, SigV4, uri_encode, uri_encode_path};
+use reqwest::{Client, Method};
+use std::time::SystemTime;
+
+/// A bucket objects are deleted from.
+pub struct Bucket {
+    client: Client,
+    endpoint: String,
+    bucket: String,
+    sigv4: SigV4,
+    credentials: CredentialChain,
+}
+
+/// A query string in SigV4's canonical form: encoded, sorted by name.
+fn canonical_query(query: &[(&str, &str)]) -> String {
+    let mut pairs: Vec<String> = query
+        .iter()
+        .map(|(name, value)| {
+            format!(
+                "{}={}",
+                uri_encode(name.as_bytes()),
+                uri_encode(value.as_bytes())
+            )
+        })
+        .collect();
+    pairs.sort();
+    pairs.join("&")
+}
+
+/// The text of every `<tag>` element in `xml`, unescaped.
+fn elements(xml: &str, tag: &str) -> Vec<String> {
+    let (open, close) = (format!("<{tag}>"), format!("</{tag}>"));
+    let mut found = Vec::new();
+    let mut rest = xml;
+    while let Some(start) = rest.find(&open) {
+        rest = &rest[start + open.len()..];
+        let Some(end) = rest.find(&close) else {
+            break;
+        };
+        found.push(
+            rest[..end]
+                .replace("&lt;", "<")
+                .replace("&gt;", ">")
+                .replace("&quot;", "\"")
+                .replace("&apos;", "'")
+                .replace("&amp;", "&"),
+        );
+        rest = &rest[end + close.len()..];
+    }
+    found
+}
+
+impl Bucket {
+    /// `endpoint` defaults to AWS's for `region`.
+    pub fn new(
+        client: Client,
+        endpoint: Option<&str>,
+        bucket: &str,
+        region: &str,
+        credentials: CredentialChain,
+    ) -> Self {
+        let endpoint = endpoint.map_or_else(
+            || format!("https://s3.{region}.amazonaws.com"),
+            |e| e.trim_end_matches('/').to_string(),
+        );
+        Self {
+            client,
+            endpoint,
+            bucket: bucket.to_string(),
+            sigv4: SigV4::new(region, "s3"),
+            credentials,
+        }
+    }
+
+    /// Send a signed, bodiless request for `key` (the bucket itself when
+    /// empty), returning the response body.
+    async fn send(
+        &self,
+        method: Method,
+        key: &str,
+        query: &[(&str, &str)],
+    ) -> Result<String, String> {
+        let bucket = uri_encode(self.bucket.as_bytes());
+        let path = if key.is_empty() {
+            format!("/{bucket}")
+        } else {
+            format!("/{bucket}/{}", uri_encode_path(key))
+        };
+        let query = canonical_query(query);
+        let url = if query.is_empty() {
+            format!("{}{path}", self.endpoint)
+        } else {
+            format!("{}{path}?{query}", self.endpoint)
+        };
+        let mut request = self
+            .client
+            .request(method, &url)
+            .build()
+            .map_err(|e| format!("{url}: {e}"))?;
+        let credentials = self.credentials.credentials().await?;
+        self.sigv4
+            .sign(&mut request, &credentials, SystemTime::now())?;
+        let response = self
+            .client
+            .execute(request)
+            .await
+            .map_err(|e| format!("{url}: {e}"))?;
+        let status = response.status();
+        let body = response.text().await.unwrap_or_default();
+        if !status.is_success() {
+            return Err(format!("{url}: {status}: {}", body.trim()));
+        }
+        Ok(body)
+    }
+
+    /// The keys of every object under `prefix`.
+    pub async fn list(&self, prefix: &str) -> Result<Vec<String>, String> {
+        let mut keys = Vec::new();
+        let mut token: Option<String> = None;
+        loop {
+            let mut query = vec![("list-type", "2"), ("prefix", prefix)];
+            if let Some(token) = &token {
+                query.push(("continuation-token", token.as_str()));
+            }
+            let body = self.send(Method::GET, "", &query).await?;
+            keys.extend(elements(&body, "Key"));
+            let truncated =
+                elements(&body, "IsTruncated").first().map(String::as_str) == Some("true");
+            token = elements(&body, "NextContinuationToken").into_iter().next();
+            if !truncated || token.is_none() {
+                return Ok(keys);
+            }
+        }
+    }
+
+    /// Delete the object at `key`.
+    pub async fn delete(&self, key: &str) -> Result<(), String> {
+        self.send(Method::DELETE, key, &[]).await.map(|_| ())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::Router;
+    use axum::extract::{Query, Request};
+    use axum::routing::any;
+    use primitive_common::aws::Credentials;
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn listings_parse_and_queries_sign_in_canonical_order() {
+        let xml = "<ListBucketResult><IsTruncated>true</IsTruncated>\
+                   <Contents><Key>users/42/a&amp;b.png</Key></Contents>\
+                   <Contents><Key>users/42/cv.pdf</Key></Contents>\
+                   <NextContinuationToken>1/xyz=</NextContinuationToken></ListBucketResult>";
+        assert_eq!(
+            elements(xml, "Key"),
+            ["users/42/a&b.png", "users/42/cv.pdf"]
+        );
+        assert_eq!(elements(xml, "NextContinuationToken"), ["1/xyz="]);
+        assert_eq!(
+            canonical_query(&[("prefix", "users/42/"), ("list-type", "2")]),
+            "list-type=2&prefix=users%2F42%2F"
+        );
+    }
+
+    #[tokio::test]
+    async fn objects_under_a_prefix_are_listed_page_by_page_and_deleted() {
+        let deleted = Arc::new(Mutex::new(Vec::new()));
+        let app = {
+            let deleted = Arc::clone(&deleted);
+            Router::new().fallback(any(
+                move |Query(query): Query<HashMap<String, String>>, request: Request| {
+                    let deleted = Arc::clone(&deleted);
+                    async move {
+                        if request.method() == "DELETE" {
+                            if let Ok(mut deleted) = deleted.lock() {
+                                deleted.push(request.uri().path().to_string());
+                            }
+                            return String::new();
+                        }
+                        match query.get("continuation-token").map(String::as_str) {
+                            None => "<ListBucketResult><IsTruncated>true</IsTruncated>\
+                                     <Contents><Key>users/42/a.png</Key></Contents>\
+                                     <NextContinuationToken>page2</NextContinuationToken>\
+                                     </ListBucketResult>"
+                                .to_string(),
+                            Some(_) => "<ListBucketResult><IsTruncated>false</IsTruncated>\
+                                        <Contents><Key>users/42/b c.pdf</Key></Contents>\
+                                        </ListBucketResult>"
+                                .to_string(),
+                        }
+                    }
+                },
+            ))
+        };
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap_or_else(|e| panic!("bind: {e}"));
+        let addr = listener
+            .local_addr()
+            .unwrap_or_else(|e| panic!("local_addr: {e}"));
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let bucket = Bucket::new(
+            Client::new(),
+            Some(&format!("http://{addr}")),
+            "uploads",
+            "eu-west-1",
+            CredentialChain::new(
+                Client::new(),
+                Some(Credentials {
+                    access_key_id: "AKIDEXAMPLE".to_string(),
+                    secret_access_key: "secret".to_string(),
+                    session_token: None,
+                }),
+                None,
+            ),
+        );
+        let keys = bucket
+            .list("users/42/")
+            .await
+            .unwrap_or_else(|e| panic!("{e}"));
+        assert_eq!(keys, ["users/42/a.png", "users/42/b c.pdf"]);
+        for key in &keys {
+            bucket.delete(key).await.unwrap_or_else(|e| panic!("{e}"));
+        }
+        assert_eq!(
+            *deleted.lock().unwrap_or_else(|e| panic!("{e}")),
+            ["/uploads/users/42/a.png", "/uploads/users/42/b%20c.pdf"]
+        );
+    }
+}
@@ -0,0 +1,364 @@
+//! The stores erasure is coordinated across, as listed in `--stores`.
+//!
+//! ```json
+//! {"stores": {
+//!   "events":   {"sqlite": {"path": "/var/lib/emergent/events.db", "table": "events",
+//!                           "column": "payload", "json_path": "$.user_id"}},
+//!   "usage":    {"sqlite": {"path": "/var/lib/emergent/metering.db", "table": "usage",
+//!                           "column": "key"}},
+//!   "uploads":  {"s3": {"bucket": "acme-uploads", "region": "eu-west-1",
+//!                       "prefix": "users/{subject}/"}},
+//!   "sessions": {"redis": {"url": "redis://localhost/0", "pattern": "session:{subject}:*"}},
+//!   "exports":  {"files": {"path": "/srv/exports/{subject}"}}
+//! }}
+//! ```
+//!
+//! `{subject}` stands for the subject key. SQLite rows match when the
+//! column, or the value at `json_path` in a JSON column, equals the key; S3
+//! objects when they sit under the prefix; Redis keys when they match the
+//! glob pattern; files when they are at the path (a directory is removed
+//! with everything in it). S3 credentials come from the AWS credential
+//! chain: `AWS_ACCESS_KEY_ID` and `AWS_SECRET_ACCESS_KEY`, a profile, a web
+//! identity, the container endpoint or instance metadata (see
+//! [`CredentialChain`]).
+
+use crate::redis;
+use crate::s3::Bucket;
+use primitive_common::aws::{self, CredentialChain};
+use rusqlite::{Connection, params};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::OnceLock;
+use std::time::Duration;
+
+/// Rows of a SQLite table.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Sqlite {
+    pub path: String,
+    pub table: String,
+    pub column: String,
+    /// Path into a JSON column (e.g. `$.user_id`).
+    #[serde(default)]
+    pub json_path: Option<String>,
+}
+
+/// Objects in an S3 bucket.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct S3 {
+    pub bucket: String,
+    #[serde(default = "default_region")]
+    pub region: String,
+    /// Endpoint of an S3-compatible store; defaults to AWS's for the region.
+    #[serde(default)]
+    pub endpoint: Option<String>,
+    pub prefix: String,
+}
+
+fn default_region() -> String {
+    aws::default_region()
+}
+
+/// Credentials for every S3 store, kept across requests so temporary ones
+/// are only fetched again as they expire.
+static CREDENTIALS: OnceLock<CredentialChain> = OnceLock::new();
+
+/// Keys in Redis.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Redis {
+    pub url: String,
+    pub pattern: String,
+}
+
+/// Files or directories on disk.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Files {
+    pub path: String,
+}
+
+/// One store holding personal data.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Store {
+    Sqlite(Sqlite),
+    S3(S3),
+    Redis(Redis),
+    Files(Files),
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Config {
+    stores: BTreeMap<String, Store>,
+}
+
+/// Read the `--stores` file.
+pub fn load(path: &Path) -> Result<BTreeMap<String, Store>, String> {
+    let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {e}", path.display()))?;
+    parse(&text).map_err(|e| format!("{}: {e}", path.display()))
+}
+
+/// Parse the stores file and check every entry.
+pub fn parse(text: &str) -> Result<BTreeMap<String, Store>, String> {
+    let config: Config = serde_json::from_str(text).map_err(|e| e.to_string())?;
+    for (name, store) in &config.stores {
+        store.validate().map_err(|e| format!("{name}: {e}"))?;
+    }
+    Ok(config.stores)
+}
+
+/// Whether `name` is a plain SQL identifier, safe to put in a statement.
+fn identifier(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with(|c: char| c.is_ascii_digit())
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Check that a subject key can stand in a prefix, pattern or path: it
+/// must not widen a match or climb out of a directory.
+pub fn check_subject(subject: &str) -> Result<(), String> {
+    if subject.trim().is_empty() {
+        return Err("subject is empty".to_string());
+    }
+    if subject == "." || subject == ".." {
+        return Err(format!("subject '{subject}' is not allowed"));
+    }
+    if let Some(c) = subject
+        .chars()
+        .find(|c| c.is_control() || matches!(c, '/' | '\\' | '*' | '?' | '[' | ']'))
+    {
+        return Err(format!("subject may not contain {c:?}"));
+    }
+    Ok(())
+}
+
+/// `template` with `{subject}` filled in.
+fn fill(template: &str, subject: &str) -> String {
+    template.replace("{subject}", subject)
+}
+
+impl Store {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::Sqlite(_) => "sqlite",
+            Self::S3(_) => "s3",
+            Self::Redis(_) => "redis",
+            Self::Files(_) => "files",
+        }
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        let template = match self {
+            Self::Sqlite(sqlite) => {
+                for name in [&sqlite.table, &sqlite.column] {
+                    if !identifier(name) {
+                        return Err(format!("'{name}' is not a plain table or column name"));
+                    }
+                }
+                return Ok(());
+            }
+            Self::S3(s3) => &s3.prefix,
+            Self::Redis(redis) => {
+                redis::Server::parse(&redis.url)?;
+                &redis.pattern
+            }
+            Self::Files(files) => &files.path,
+        };
+        // Without the subject, every subject's data would match
+        if !template.contains("{subject}") {
+            return Err(format!("'{template}' does not contain {{subject}}"));
+        }
+        Ok(())
+    }
+
+    /// Delete everything `subject` has in this store, or with `dry_run`
+    /// only count it, returning how much there was (rows, objects, keys or
+    /// files).
+    pub async fn erase(
+        &self,
+        subject: &str,
+        dry_run: bool,
+        client: &reqwest::Client,
+        timeout: Duration,
+    ) -> Result<u64, String> {
+        match self {
+            Self::Sqlite(sqlite) => {
+                let sqlite = sqlite.clone();
+                let subject = subject.to_string();
+                tokio::task::spawn_blocking(move || erase_rows(&sqlite, &subject, dry_run))
+                    .await
+                    .map_err(|e| e.to_string())?
+            }
+            Self::S3(s3) => {
+                let credentials = CREDENTIALS
+                    .get_or_init(|| CredentialChain::new(client.clone(), None, None))
+                    .clone();
+                let bucket = Bucket::new(
+                    client.clone(),
+                    s3.endpoint.as_deref(),
+                    &s3.bucket,
+                    &s3.region,
+                    credentials,
+                );
+                let keys = bucket.list(&fill(&s3.prefix, subject)).await?;
+                if !dry_run {
+                    for key in &keys {
+                        bucket.delete(key).await?;
+                    }
+                }
+                Ok(keys.len() as u64)
+            }
+            Self::Redis(redis) => {
+                redis::Server::parse(&redis.url)?
+                    .erase(&fill(&redis.pattern, subject), dry_run, timeout)
+                    .await
+            }
+            Self::Files(files) => erase_files(Path::new(&fill(&files.path, subject)), dry_run),
+        }
+    }
+}
+
+/// Delete (or count) the rows of `sqlite` belonging to `subject`.
+fn erase_rows(sqlite: &Sqlite, subject: &str, dry_run: bool) -> Result<u64, String> {
+    let db = Connection::open(&sqlite.path).map_err(|e| format!("{}: {e}", sqlite.path))?;
+    // Identifiers were checked when the stores were loaded
+    let matches = match &sqlite.json_path {
+        Some(_) => format!("json_extract({}, ?2) = ?1", sqlite.column),
+        None => format!("{} = ?1", sqlite.column),
+    };
+    let json_path = sqlite.json_path.as_deref().unwrap_or_default();
+    let result = if dry_run {
+        let sql = format!("SELECT COUNT(*) FROM {} WHERE {matches}", sqlite.table);
+        match &sqlite.json_path {
+            Some(_) => db.query_row(&sql, params![subject, json_path], |row| {
+                row.get::<_, i64>(0)
+            }),
+            None => db.query_row(&sql, params![subject], |row| row.get::<_, i64>(0)),
+        }
+        .map(|count| count.max(0) as u64)
+    } else {
+        let sql = format!("DELETE FROM {} WHERE {matches}", sqlite.table);
+        match &sqlite.json_path {
+            Some(_) => db.execute(&sql, params![subject, json_path]),
+            None => db.execute(&sql, params![subject]),
+        }
+        .map(|deleted| deleted as u64)
+    };
+    result.map_err(|e| format!("{}: {e}", sqlite.path))
+}
+
+/// Delete (or count) the file or directory at `path`.
+fn erase_files(path: &Path, dry_run: bool) -> Result<u64, String> {
+    let metadata = match std::fs::symlink_metadata(path) {
+        Ok(metadata) => metadata,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(format!("{}: {e}", path.display())),
+    };
+    let count = if metadata.is_dir() {
+        count_files(path).map_err(|e| format!("{}: {e}", path.display()))?
+    } else {
+        1
+    };
+    if !dry_run {
+        if metadata.is_dir() {
+            std::fs::remove_dir_all(path)
+        } else {
+            std::fs::remove_file(path)
+        }
+        .map_err(|e| format!("{}: {e}", path.display()))?;
+    }
+    Ok(count)
+}
+
+fn count_files(dir: &Path) -> std::io::Result<u64> {
+    let mut count = 0;
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        count += if entry.file_type()?.is_dir() {
+            count_files(&entry.path())?
+        } else {
+            1
+        };
+    }
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stores_parse_and_unsafe_settings_are_refused() {
+        let stores = parse(
+            r#"{"stores": {
+                "events": {"sqlite": {"path": "events.db", "table": "events", "column": "payload", "json_path": "$.user_id"}},
+                "uploads": {"s3": {"bucket": "acme", "prefix": "users/{subject}/"}},
+                "sessions": {"redis": {"url": "redis://localhost/0", "pattern": "session:{subject}:*"}}
+            }}"#,
+        )
+        .unwrap_or_else(|e| panic!("{e}"));
+        let kinds: Vec<&str> = stores.values().map(Store::kind).collect();
+        assert_eq!(kinds, ["sqlite", "redis", "s3"]);
+
+        assert!(parse(r#"{"stores": {"x": {"sqlite": {"path": "a.db", "table": "t; DROP TABLE t", "column": "c"}}}}"#).is_err());
+        assert!(parse(r#"{"stores": {"x": {"files": {"path": "/srv/exports"}}}}"#).is_err());
+        assert!(
+            parse(r#"{"stores": {"x": {"redis": {"url": "http://x", "pattern": "{subject}"}}}}"#)
+                .is_err()
+        );
+
+        assert_eq!(check_subject("user-42@example.com"), Ok(()));
+        for subject in ["", "..", "a/b", "user*", "a\nb"] {
+            assert!(check_subject(subject).is_err(), "{subject:?}");
+        }
+    }
+
+    #[tokio::test]
+    async fn rows_and_files_of_the_subject_are_erased() {
+        let dir = std::env::temp_dir().join(format!("erasure-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("exports/u42/2024")).unwrap_or_else(|e| panic!("{e}"));
+        std::fs::write(dir.join("exports/u42/2024/a.csv"), "x").unwrap_or_else(|e| panic!("{e}"));
+        std::fs::write(dir.join("exports/u42/b.csv"), "x").unwrap_or_else(|e| panic!("{e}"));
+        std::fs::create_dir_all(dir.join("exports/u7")).unwrap_or_else(|e| panic!("{e}"));
+
+        let db_path = dir.join("events.db");
+        let db = Connection::open(&db_path).unwrap_or_else(|e| panic!("{e}"));
+        db.execute_batch(
+            r#"CREATE TABLE events (id INTEGER PRIMARY KEY, payload TEXT);
+               INSERT INTO events (payload) VALUES ('{"user_id": "u42"}'), ('{"user_id": "u7"}'), ('{"user_id": "u42"}');"#,
+        )
+        .unwrap_or_else(|e| panic!("{e}"));
+        drop(db);
+
+        let stores = parse(&format!(
+            r#"{{"stores": {{
+                "events": {{"sqlite": {{"path": "{db}", "table": "events", "column": "payload", "json_path": "$.user_id"}}}},
+                "exports": {{"files": {{"path": "{dir}/exports/{{subject}}"}}}}
+            }}}}"#,
+            db = db_path.display(),
+            dir = dir.display()
+        ))
+        .unwrap_or_else(|e| panic!("{e}"));
+        let client = reqwest::Client::new();
+        let erase = |name: &str, dry_run| {
+            stores[name].erase("u42", dry_run, &client, Duration::from_secs(5))
+        };
+
+        assert_eq!(erase("events", true).await, Ok(2));
+        assert_eq!(erase("exports", true).await, Ok(2));
+        assert!(dir.join("exports/u42").exists());
+        assert_eq!(erase("events", false).await, Ok(2));
+        assert_eq!(erase("exports", false).await, Ok(2));
+        assert!(!dir.join("exports/u42").exists());
+        assert!(dir.join("exports/u7").exists());
+        // Erasing again finds nothing left
+        assert_eq!(erase("events", false).await, Ok(0));
+        assert_eq!(erase("exports", false).await, Ok(0));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
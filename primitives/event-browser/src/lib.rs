@@ -0,0 +1,273 @@
+//! Event Browser - A Debugging Console for the Bus
+//!
+//! A Sink that keeps every event it receives in a local SQLite database
+//! (see [`store`]) and serves a small web UI to search them by type, time
+//! and payload text, read their payloads, and publish a selected event
+//! again (see [`web`]). Subscribe it to every type of interest and it is a
+//! self-contained view of what went over the bus.
+//!
+//! A republished event keeps its type and payload; its metadata records
+//! the id of the original as `republished_from`.
+//!
+//! # Examples
+//!
+//! ```bash
+//! event-browser -s deploy.started -s deploy.finished -s alert.fired \
+//!   --database /var/lib/emergent/events.db --port 8790
+//! event-browser -s http.request --host 0.0.0.0 --token "$BROWSER_TOKEN" --read-only
+//! ```
+
+pub mod store;
+pub mod web;
+
+use clap::Parser;
+use emergent_client::EmergentMessage;
+use primitive_common::capabilities::VERSION;
+use primitive_common::doctor::Report;
+use primitive_common::errors::{ErrorCategory, HandlerError};
+use primitive_common::{Publisher, SinkArgs, SinkConfig, SinkContext, SinkHandler, run_sink};
+use std::path::PathBuf;
+use std::sync::{Arc, OnceLock};
+use store::{Store, now_ms};
+use web::Web;
+
+/// Event Browser — a debugging console for the bus.
+#[derive(Parser, Debug)]
+#[command(name = "event_browser", version = VERSION)]
+#[command(about = "Store events in SQLite and search, view and republish them from a web UI")]
+struct Args {
+    /// Message types to subscribe to.
+    #[arg(short, long = "subscribe", required = true)]
+    subscribe: Vec<String>,
+
+    /// SQLite database the events are kept in.
+    #[arg(
+        long,
+        env = "EVENT_BROWSER_DATABASE",
+        default_value = "event-browser.db"
+    )]
+    database: PathBuf,
+
+    /// Events kept; the oldest are dropped beyond this.
+    #[arg(long, env = "EVENT_BROWSER_KEEP", default_value = "100000")]
+    keep: usize,
+
+    /// Port the web UI listens on.
+    #[arg(short, long, env = "EVENT_BROWSER_PORT", default_value = "8790")]
+    port: u16,
+
+    /// Host the web UI binds to.
+    #[arg(long, env = "EVENT_BROWSER_HOST", default_value = "127.0.0.1")]
+    host: String,
+
+    /// Bearer token the API requires.
+    #[arg(long, env = "EVENT_BROWSER_TOKEN", hide_env_values = true)]
+    token: Option<String>,
+
+    /// Do not allow republishing events.
+    #[arg(long, env = "EVENT_BROWSER_READ_ONLY")]
+    read_only: bool,
+
+    #[command(flatten)]
+    sink: SinkArgs,
+}
+
+/// Stores each event for the web UI.
+struct EventBrowser {
+    store: Arc<Store>,
+    web: Arc<Web>,
+    /// The subscribed types, which are the types that can be republished.
+    republishes: &'static [&'static str],
+}
+
+impl SinkHandler for EventBrowser {
+    async fn handle(
+        &self,
+        msg: &EmergentMessage,
+        ctx: &SinkContext<'_>,
+    ) -> Result<(), HandlerError> {
+        self.store
+            .insert(
+                ctx.message_id(),
+                msg.message_type.as_str(),
+                now_ms(),
+                ctx.payload(),
+            )
+            .map_err(|e| HandlerError::new(ErrorCategory::Internal, e))
+    }
+
+    fn self_test(&self, report: &mut Report) {
+        report.check(
+            "database",
+            self.store.count().map(|n| format!("{n} events stored")),
+        );
+    }
+
+    fn publishes(&self) -> &'static [&'static str] {
+        if self.web.read_only {
+            &[]
+        } else {
+            self.republishes
+        }
+    }
+
+    fn attach(&self, publisher: Publisher) {
+        let _ = self.web.publisher.set(publisher);
+    }
+}
+
+/// Run the primitive with `args` as its command line (the first item is
+/// the program name, as with `std::env::args_os`).
+pub async fn run<I, T>(args: I) -> Result<(), Box<dyn std::error::Error>>
+where
+    I: IntoIterator<Item = T>,
+    T: Into<std::ffi::OsString> + Clone,
+{
+    let args = Args::parse_from(args);
+
+    let exit = |e: String| -> ! {
+        eprintln!("Error: {e}");
+        std::process::exit(1);
+    };
+    let store = Arc::new(Store::open(&args.database, args.keep).unwrap_or_else(|e| exit(e)));
+    let web = Arc::new(Web {
+        store: Arc::clone(&store),
+        publisher: OnceLock::new(),
+        token: args.token.clone(),
+        read_only: args.read_only,
+        dry_run: args.sink.dry_run,
+    });
+    let addr = format!("{}:{}", args.host, args.port);
+    let listener = tokio::net::TcpListener::bind(&addr)
+        .await
+        .unwrap_or_else(|e| exit(format!("cannot listen on {addr}: {e}")));
+    let app = Arc::clone(&web).router();
+    tokio::spawn(async move { axum::serve(listener, app).await });
+    eprintln!("Event browser on http://{addr}/");
+
+    // Capabilities need the types as static strings; they live as long
+    // as the process anyway
+    let republishes: Vec<&'static str> = args
+        .subscribe
+        .iter()
+        .map(|t| &*String::leak(t.clone()))
+        .collect();
+
+    let config = SinkConfig {
+        name: "event_browser",
+        subscribe: &args.subscribe,
+        would_have_as: "event_browser.would_have",
+        dead_letter_as: "event_browser.dead_letter",
+        settings: &args,
+    };
+    let handler = EventBrowser {
+        store,
+        web,
+        republishes: republishes.leak(),
+    };
+
+    run_sink(config, &args.sink, handler).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use emergent_testkit::{SinkFixture, fixtures, spawn_sink};
+    use serde_json::{Value, json};
+
+    async fn serve(web: Arc<Web>) -> String {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap_or_else(|e| panic!("bind: {e}"));
+        let addr = listener
+            .local_addr()
+            .unwrap_or_else(|e| panic!("local_addr: {e}"));
+        tokio::spawn(async move { axum::serve(listener, web.router()).await });
+        format!("http://{addr}")
+    }
+
+    #[tokio::test]
+    async fn events_are_stored_searched_and_republished() {
+        let store = Arc::new(Store::in_memory(100).unwrap_or_else(|e| panic!("{e}")));
+        let web = Arc::new(Web {
+            store: Arc::clone(&store),
+            publisher: OnceLock::new(),
+            token: Some("t0k".to_string()),
+            read_only: false,
+            dry_run: false,
+        });
+        let url = serve(Arc::clone(&web)).await;
+        let (mut engine, run) = spawn_sink(
+            SinkFixture::new("event_browser", "event_browser"),
+            SinkArgs::default(),
+            EventBrowser {
+                store: Arc::clone(&store),
+                web,
+                republishes: &["deploy.finished"],
+            },
+        );
+
+        engine
+            .inject_message(fixtures::message(
+                "deploy.finished",
+                json!({"service": "billing-api", "ok": true}),
+            ))
+            .await;
+        engine
+            .inject_message(fixtures::message("alert.fired", json!({"host": "web1"})))
+            .await;
+        // Wait until both are stored
+        for _ in 0..100 {
+            if store.count() == Ok(2) {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+
+        let client = reqwest::Client::new();
+        let get = |path: &str| client.get(format!("{url}{path}")).bearer_auth("t0k").send();
+        let denied = client
+            .get(format!("{url}/api/types"))
+            .send()
+            .await
+            .map(|r| r.status().as_u16());
+        assert_eq!(denied.ok(), Some(401));
+        let ui = get("/").await.map(|r| r.status().as_u16());
+        assert_eq!(ui.ok(), Some(200));
+
+        let json = |response: reqwest::Result<reqwest::Response>| async move {
+            match response {
+                Ok(r) => r.json::<Value>().await.unwrap_or_default(),
+                Err(e) => panic!("{e}"),
+            }
+        };
+        let found = json(get("/api/events?q=billing").await).await;
+        assert_eq!(found["events"].as_array().map(Vec::len), Some(1));
+        assert_eq!(found["events"][0]["message_type"], "deploy.finished");
+        assert_eq!(found["next"], Value::Null);
+        let id = found["events"][0]["id"].as_i64().unwrap_or_default();
+        let event = json(get(&format!("/api/events/{id}")).await).await;
+        assert_eq!(
+            event["payload"],
+            json!({"service": "billing-api", "ok": true})
+        );
+        let types = json(get("/api/types").await).await;
+        assert_eq!(
+            types["types"][0],
+            json!({"type": "alert.fired", "count": 1})
+        );
+
+        let republished = client
+            .post(format!("{url}/api/events/{id}/republish"))
+            .bearer_auth("t0k")
+            .send()
+            .await;
+        let republished = json(republished).await;
+        assert_eq!(republished["republished"], true);
+        let again = engine.expect_published("deploy.finished").await;
+        assert_eq!(again.payload()["service"], "billing-api");
+
+        engine.close();
+        assert_eq!(run.await.ok(), Some(Ok(())));
+    }
+}
@@ -0,0 +1,6 @@
+//! `event-browser` binary; the primitive itself lives in the library.
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    event_browser::run(std::env::args_os()).await
+}
@@ -0,0 +1,425 @@
+//! Received events in a local SQLite database.
+//!
+//! Every event is a row of the `events` table, with its payload as JSON
+//! text. An FTS5 index over type and payload, kept in step by triggers,
+//! answers text searches; a search is every word given, each matched as a
+//! prefix. Only the newest `keep` events are kept.
+
+use rusqlite::types::Value as SqlValue;
+use rusqlite::{Connection, OptionalExtension, params, params_from_iter};
+use serde::Serialize;
+use serde_json::Value;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS events (
+    id           INTEGER PRIMARY KEY AUTOINCREMENT,
+    message_id   TEXT NOT NULL UNIQUE,
+    message_type TEXT NOT NULL,
+    received_at  INTEGER NOT NULL,
+    payload      TEXT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS events_by_type ON events (message_type, id);
+CREATE INDEX IF NOT EXISTS events_by_time ON events (received_at);
+CREATE VIRTUAL TABLE IF NOT EXISTS events_fts USING fts5(
+    message_type, payload, content = 'events', content_rowid = 'id'
+);
+CREATE TRIGGER IF NOT EXISTS events_fts_insert AFTER INSERT ON events BEGIN
+    INSERT INTO events_fts (rowid, message_type, payload)
+    VALUES (new.id, new.message_type, new.payload);
+END;
+CREATE TRIGGER IF NOT EXISTS events_fts_delete AFTER DELETE ON events BEGIN
+    INSERT INTO events_fts (events_fts, rowid, message_type, payload)
+    VALUES ('delete', old.id, old.message_type, old.payload);
+END;
+";
+
+/// An event as listed: everything but the payload, which may be large.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Summary {
+    pub id: i64,
+    pub message_id: String,
+    pub message_type: String,
+    /// Unix milliseconds.
+    pub received_at: i64,
+    /// The start of the payload JSON.
+    pub preview: String,
+}
+
+/// An event as stored.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Event {
+    pub id: i64,
+    pub message_id: String,
+    pub message_type: String,
+    pub received_at: i64,
+    pub payload: Value,
+}
+
+/// What to list; every field given narrows the result.
+#[derive(Debug, Clone, Default)]
+pub struct Query {
+    /// Message type, where `*` is any run of characters.
+    pub message_type: Option<String>,
+    /// Received at or after, in Unix milliseconds.
+    pub since: Option<i64>,
+    /// Received before, in Unix milliseconds.
+    pub until: Option<i64>,
+    /// Words the type or payload must contain.
+    pub text: Option<String>,
+    /// Only events older than this id, to page back through results.
+    pub before: Option<i64>,
+    pub limit: usize,
+}
+
+/// Message types with the number of events of each.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TypeCount {
+    pub message_type: String,
+    pub count: i64,
+}
+
+/// Characters of payload JSON in a [`Summary::preview`].
+const PREVIEW_CHARS: usize = 160;
+
+/// The event database.
+pub struct Store {
+    db: Mutex<Connection>,
+    keep: usize,
+}
+
+pub fn now_ms() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| i64::try_from(d.as_millis()).unwrap_or(i64::MAX))
+}
+
+/// `text` as an FTS5 query: each word quoted, as a prefix, all required.
+fn match_expression(text: &str) -> Option<String> {
+    let words: Vec<String> = text
+        .split_whitespace()
+        .map(|word| format!("\"{}\"*", word.replace('"', "\"\"")))
+        .collect();
+    (!words.is_empty()).then(|| words.join(" "))
+}
+
+impl Store {
+    /// Open (creating if need be) the database at `path`.
+    pub fn open(path: &Path, keep: usize) -> Result<Self, String> {
+        let db = Connection::open(path).map_err(|e| format!("{}: {e}", path.display()))?;
+        Self::init(db, keep).map_err(|e| format!("{}: {e}", path.display()))
+    }
+
+    /// An in-memory database, for tests.
+    pub fn in_memory(keep: usize) -> Result<Self, String> {
+        let db = Connection::open_in_memory().map_err(|e| e.to_string())?;
+        Self::init(db, keep)
+    }
+
+    fn init(db: Connection, keep: usize) -> Result<Self, String> {
+        db.execute_batch(SCHEMA).map_err(|e| e.to_string())?;
+        Ok(Self {
+            db: Mutex::new(db),
+            keep,
+        })
+    }
+
+    fn with<T>(&self, f: impl FnOnce(&Connection) -> rusqlite::Result<T>) -> Result<T, String> {
+        let db = self
+            .db
+            .lock()
+            .map_err(|_| "event store lock poisoned".to_string())?;
+        f(&db).map_err(|e| format!("event store: {e}"))
+    }
+
+    /// Store an event, and drop the oldest beyond `keep`. An event already
+    /// stored (a redelivery) is left as it is.
+    pub fn insert(
+        &self,
+        message_id: &str,
+        message_type: &str,
+        received_at: i64,
+        payload: &Value,
+    ) -> Result<(), String> {
+        let keep = i64::try_from(self.keep).unwrap_or(i64::MAX);
+        self.with(|db| {
+            // Not `INSERT OR IGNORE`, which uses up an id even when it
+            // ignores; ids without gaps make `MAX(id) - keep` exact
+            db.execute(
+                "INSERT INTO events (message_id, message_type, received_at, payload)
+                 SELECT ?1, ?2, ?3, ?4
+                 WHERE NOT EXISTS (SELECT 1 FROM events WHERE message_id = ?1)",
+                params![message_id, message_type, received_at, payload.to_string()],
+            )?;
+            db.execute(
+                "DELETE FROM events WHERE id <= (SELECT MAX(id) FROM events) - ?1",
+                params![keep],
+            )?;
+            Ok(())
+        })
+    }
+
+    /// Events matching `query`, newest first.
+    pub fn search(&self, query: &Query) -> Result<Vec<Summary>, String> {
+        let mut conditions = Vec::new();
+        let mut values: Vec<SqlValue> = Vec::new();
+        if let Some(message_type) = query.message_type.as_deref().filter(|t| !t.is_empty()) {
+            // GLOB would also give `?` and `[` meaning; only `*` is a wildcard here
+            conditions.push("message_type LIKE ? ESCAPE '\\'");
+            let pattern = message_type
+                .replace('\\', "\\\\")
+                .replace('%', "\\%")
+                .replace('_', "\\_")
+                .replace('*', "%");
+            values.push(pattern.into());
+        }
+        if let Some(since) = query.since {
+            conditions.push("received_at >= ?");
+            values.push(since.into());
+        }
+        if let Some(until) = query.until {
+            conditions.push("received_at < ?");
+            values.push(until.into());
+        }
+        if let Some(text) = query.text.as_deref().and_then(match_expression) {
+            conditions.push("id IN (SELECT rowid FROM events_fts WHERE events_fts MATCH ?)");
+            values.push(text.into());
+        }
+        if let Some(before) = query.before {
+            conditions.push("id < ?");
+            values.push(before.into());
+        }
+        let filter = if conditions.is_empty() {
+            String::new()
+        } else {
+            format!("WHERE {}", conditions.join(" AND "))
+        };
+        values.push(i64::try_from(query.limit).unwrap_or(i64::MAX).into());
+        let sql = format!(
+            "SELECT id, message_id, message_type, received_at, substr(payload, 1, {PREVIEW_CHARS})
+             FROM events {filter} ORDER BY id DESC LIMIT ?"
+        );
+        self.with(|db| {
+            let mut statement = db.prepare(&sql)?;
+            let rows = statement.query_map(params_from_iter(values), |row| {
+                Ok(Summary {
+                    id: row.get(0)?,
+                    message_id: row.get(1)?,
+                    message_type: row.get(2)?,
+                    received_at: row.get(3)?,
+                    preview: row.get(4)?,
+                })
+            })?;
+            rows.collect()
+        })
+    }
+
+    /// Event `id`, if it is still stored.
+    pub fn get(&self, id: i64) -> Result<Option<Event>, String> {
+        let row = self.with(|db| {
+            db.query_row(
+                "SELECT id, message_id, message_type, received_at, payload FROM events WHERE id = ?1",
+                params![id],
+                |row| {
+                    Ok((
+                        row.get(0)?,
+                        row.get(1)?,
+                        row.get(2)?,
+                        row.get(3)?,
+                        row.get::<_, String>(4)?,
+                    ))
+                },
+            )
+            .optional()
+        })?;
+        Ok(row.map(
+            |(id, message_id, message_type, received_at, payload)| Event {
+                id,
+                message_id,
+                message_type,
+                received_at,
+                payload: serde_json::from_str(&payload).unwrap_or(Value::String(payload)),
+            },
+        ))
+    }
+
+    /// Every stored message type, by name.
+    pub fn types(&self) -> Result<Vec<TypeCount>, String> {
+        self.with(|db| {
+            let mut statement = db.prepare(
+                "SELECT message_type, COUNT(*) FROM events GROUP BY message_type ORDER BY message_type",
+            )?;
+            let rows = statement.query_map([], |row| {
+                Ok(TypeCount {
+                    message_type: row.get(0)?,
+                    count: row.get(1)?,
+                })
+            })?;
+            rows.collect()
+        })
+    }
+
+    /// The number of events stored, for `--self-test`.
+    pub fn count(&self) -> Result<i64, String> {
+        self.with(|db| db.query_row("SELECT COUNT(*) FROM events", [], |row| row.get(0)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn events_are_searched_and_pruned() {
+        let store = Store::in_memory(4).unwrap_or_else(|e| panic!("open: {e}"));
+        let events = [
+            (
+                "m1",
+                "deploy.started",
+                1000,
+                json!({"service": "billing-api", "version": "2.1"}),
+            ),
+            (
+                "m2",
+                "deploy.finished",
+                2000,
+                json!({"service": "billing-api", "ok": true}),
+            ),
+            (
+                "m3",
+                "alert.fired",
+                3000,
+                json!({"host": "web1", "check": "disk_usage"}),
+            ),
+            ("m4", "deploy_x.started", 4000, json!({"service": "search"})),
+        ];
+        for (id, message_type, at, payload) in &events {
+            store
+                .insert(id, message_type, *at, payload)
+                .unwrap_or_else(|e| panic!("insert: {e}"));
+        }
+        // A redelivery is not stored twice
+        store
+            .insert("m1", "deploy.started", 9000, &json!({}))
+            .unwrap_or_else(|e| panic!("insert: {e}"));
+
+        let ids = |query: Query| -> Vec<String> {
+            store
+                .search(&Query {
+                    limit: if query.limit == 0 { 10 } else { query.limit },
+                    ..query
+                })
+                .unwrap_or_else(|e| panic!("search: {e}"))
+                .into_iter()
+                .map(|s| s.message_id)
+                .collect()
+        };
+        assert_eq!(ids(Query::default()), ["m4", "m3", "m2", "m1"]);
+        let by_type = |t: &str| Query {
+            message_type: Some(t.to_string()),
+            ..Query::default()
+        };
+        assert_eq!(ids(by_type("deploy.*")), ["m2", "m1"]);
+        assert_eq!(ids(by_type("alert.fired")), ["m3"]);
+        let text = |t: &str| Query {
+            text: Some(t.to_string()),
+            ..Query::default()
+        };
+        assert_eq!(ids(text("billing")), ["m2", "m1"]);
+        assert_eq!(ids(text("billing 2.1")), ["m1"]);
+        assert_eq!(ids(text("disk_usage \"web1")), ["m3"]);
+        assert_eq!(
+            ids(Query {
+                since: Some(2000),
+                until: Some(4000),
+                ..Query::default()
+            }),
+            ["m3", "m2"]
+        );
+        assert_eq!(
+            ids(Query {
+                before: Some(3),
+                limit: 1,
+                ..Query::default()
+            }),
+            ["m2"]
+        );
+
+        let first = store
+            .search(&Query {
+                limit: 1,
+                ..by_type("alert.*")
+            })
+            .unwrap_or_else(|e| panic!("search: {e}"));
+        let event = store
+            .get(first[0].id)
+            .unwrap_or_else(|e| panic!("get: {e}"))
+            .unwrap_or_else(|| panic!("not stored"));
+        assert_eq!(
+            event.payload,
+            json!({"host": "web1", "check": "disk_usage"})
+        );
+        assert_eq!(first[0].preview, event.payload.to_string());
+
+        store
+            .insert("m5", "alert.fired", 5000, &json!({"host": "web2"}))
+            .unwrap_or_else(|e| panic!("insert: {e}"));
+        assert_eq!(store.count(), Ok(4));
+        // The oldest is gone, from the index too
+        assert!(ids(text("version")).is_empty());
+        assert_eq!(
+            store.types().map(|types| types
+                .into_iter()
+                .map(|t| (t.message_type, t.count))
+                .collect::<Vec<_>>()),
+            Ok(vec![
+                ("alert.fired".to_string(), 2),
+                ("deploy.finished".to_string(), 1),
+                ("deploy_x.started".to_string(), 1),
+            ])
+        );
+    }
+
+    #[test]
+    fn events_outlive_a_reopen_and_bad_paths_are_reported() {
+        let dir = std::env::temp_dir().join(format!("event-browser-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let missing = dir.join("no-such-dir/events.db");
+        let error = Store::open(&missing, 10).err().unwrap_or_default();
+        assert!(error.starts_with(&missing.display().to_string()), "{error}");
+
+        std::fs::create_dir_all(&dir).unwrap_or_else(|e| panic!("mkdir: {e}"));
+        let path = dir.join("events.db");
+        let store = Store::open(&path, 10).unwrap_or_else(|e| panic!("open: {e}"));
+        store
+            .insert("m1", "alert.fired", 1000, &json!({"host": "web1"}))
+            .unwrap_or_else(|e| panic!("insert: {e}"));
+        drop(store);
+
+        // The index comes back with the events, and redeliveries stay single
+        let store = Store::open(&path, 10).unwrap_or_else(|e| panic!("reopen: {e}"));
+        store
+            .insert("m1", "alert.fired", 2000, &json!({"host": "web1"}))
+            .unwrap_or_else(|e| panic!("insert: {e}"));
+        let found = store
+            .search(&Query {
+                text: Some("web1".to_string()),
+                limit: 10,
+                ..Query::default()
+            })
+            .unwrap_or_else(|e| panic!("search: {e}"));
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].received_at, 1000);
+
+        std::fs::write(
+            dir.join("garbage.db"),
+            b"not a database, just text that is long enough",
+        )
+        .unwrap_or_else(|e| panic!("write: {e}"));
+        assert!(Store::open(&dir.join("garbage.db"), 10).is_err());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,312 @@
+//! The web UI and the JSON API behind it.
+//!
+//! - `GET /`: the UI, a single embedded page
+//! - `GET /api/events?type=&since=&until=&q=&before=&limit=`: events,
+//!   newest first (see [`Query`](crate::store::Query)); `next` is the
+//!   `before` of the following page
+//! - `GET /api/events/{id}`: one event with its payload
+//! - `POST /api/events/{id}/republish`: publish the event again
+//! - `GET /api/types`: stored message types with their counts
+//!
+//! With `--token`, API calls need `Authorization: Bearer <token>`; the UI
+//! asks for it once per browser session.
+
+use crate::store::{Query, Store, now_ms};
+use axum::Router;
+use axum::extract::{Path, Query as UrlQuery, State};
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::{Html, IntoResponse, Response};
+use axum::routing::{get, post};
+use emergent_client::EmergentMessage;
+use primitive_common::Publisher;
+use serde::Deserialize;
+use serde_json::{Value, json};
+use std::sync::{Arc, OnceLock};
+
+const UI: &str = include_str!("ui.html");
+
+/// Events per page unless `limit` says otherwise, and the most allowed.
+const DEFAULT_LIMIT: usize = 50;
+const MAX_LIMIT: usize = 500;
+
+/// What the API serves from and publishes with.
+pub struct Web {
+    pub store: Arc<Store>,
+    pub publisher: OnceLock<Publisher>,
+    pub token: Option<String>,
+    /// Refuse to republish.
+    pub read_only: bool,
+    /// Report republishing instead of doing it.
+    pub dry_run: bool,
+}
+
+/// `GET /api/events` parameters; times are Unix milliseconds.
+#[derive(Debug, Default, Deserialize)]
+struct Params {
+    #[serde(rename = "type")]
+    message_type: Option<String>,
+    since: Option<i64>,
+    until: Option<i64>,
+    q: Option<String>,
+    before: Option<i64>,
+    limit: Option<usize>,
+}
+
+fn error(status: StatusCode, message: &str) -> Response {
+    (status, axum::Json(json!({ "error": message }))).into_response()
+}
+
+impl Web {
+    pub fn router(self: Arc<Self>) -> Router {
+        Router::new()
+            .route("/", get(|| async { Html(UI) }))
+            .route("/api/events", get(events))
+            .route("/api/events/{id}", get(event))
+            .route("/api/events/{id}/republish", post(republish))
+            .route("/api/types", get(types))
+            .with_state(self)
+    }
+
+    /// Whether the request carries the token, if one is required.
+    fn authorized(&self, headers: &HeaderMap) -> bool {
+        let Some(token) = &self.token else {
+            return true;
+        };
+        let given = headers
+            .get("authorization")
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.strip_prefix("Bearer "));
+        given == Some(token.as_str())
+    }
+}
+
+fn unauthorized() -> Response {
+    error(StatusCode::UNAUTHORIZED, "missing or wrong token")
+}
+
+fn respond<T: serde::Serialize>(result: Result<T, String>) -> Response {
+    match result {
+        Ok(body) => axum::Json(body).into_response(),
+        Err(e) => error(StatusCode::INTERNAL_SERVER_ERROR, &e),
+    }
+}
+
+async fn events(
+    State(web): State<Arc<Web>>,
+    headers: HeaderMap,
+    UrlQuery(params): UrlQuery<Params>,
+) -> Response {
+    if !web.authorized(&headers) {
+        return unauthorized();
+    }
+    let query = Query {
+        message_type: params.message_type,
+        since: params.since,
+        until: params.until,
+        text: params.q,
+        before: params.before,
+        limit: params.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT),
+    };
+    respond(web.store.search(&query).map(|events| {
+        let next = (events.len() == query.limit)
+            .then(|| events.last().map(|e| e.id))
+            .flatten();
+        json!({ "events": events, "next": next })
+    }))
+}
+
+async fn event(State(web): State<Arc<Web>>, headers: HeaderMap, Path(id): Path<i64>) -> Response {
+    if !web.authorized(&headers) {
+        return unauthorized();
+    }
+    match web.store.get(id) {
+        Ok(Some(event)) => axum::Json(event).into_response(),
+        Ok(None) => error(StatusCode::NOT_FOUND, "no such event"),
+        Err(e) => error(StatusCode::INTERNAL_SERVER_ERROR, &e),
+    }
+}
+
+async fn republish(
+    State(web): State<Arc<Web>>,
+    headers: HeaderMap,
+    Path(id): Path<i64>,
+) -> Response {
+    if !web.authorized(&headers) {
+        return unauthorized();
+    }
+    if web.read_only {
+        return error(
+            StatusCode::FORBIDDEN,
+            "republishing is disabled (--read-only)",
+        );
+    }
+    let event = match web.store.get(id) {
+        Ok(Some(event)) => event,
+        Ok(None) => return error(StatusCode::NOT_FOUND, "no such event"),
+        Err(e) => return error(StatusCode::INTERNAL_SERVER_ERROR, &e),
+    };
+    if web.dry_run {
+        eprintln!(
+            "[dry-run] republish {} {} {}",
+            event.message_type, event.message_id, event.payload
+        );
+        return axum::Json(json!({ "republished": false, "dry_run": true })).into_response();
+    }
+    let Some(publisher) = web.publisher.get() else {
+        return error(StatusCode::SERVICE_UNAVAILABLE, "not connected yet");
+    };
+    let message = EmergentMessage::new(&event.message_type)
+        .with_payload(event.payload)
+        .with_metadata(json!({
+            "republished_from": event.message_id,
+            "republished_at": now_ms(),
+        }));
+    let message_id = message.id().to_string();
+    match publisher.publish(message) {
+        Ok(()) => axum::Json(json!({
+            "republished": true,
+            "message_type": event.message_type,
+            "message_id": message_id,
+        }))
+        .into_response(),
+        Err(e) => error(StatusCode::SERVICE_UNAVAILABLE, &e),
+    }
+}
+
+async fn types(State(web): State<Arc<Web>>, headers: HeaderMap) -> Response {
+    if !web.authorized(&headers) {
+        return unauthorized();
+    }
+    respond(web.store.types().map(|types| {
+        let types: Vec<Value> = types
+            .into_iter()
+            .map(|t| json!({ "type": t.message_type, "count": t.count }))
+            .collect();
+        json!({ "types": types })
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Serve a `Web` over a store holding two `deploy.finished` events.
+    async fn serve(configure: impl FnOnce(&mut Web)) -> String {
+        let store = Store::in_memory(100).unwrap_or_else(|e| panic!("{e}"));
+        for (id, service) in [("m1", "billing-api"), ("m2", "search")] {
+            store
+                .insert(id, "deploy.finished", 1000, &json!({ "service": service }))
+                .unwrap_or_else(|e| panic!("insert: {e}"));
+        }
+        let mut web = Web {
+            store: Arc::new(store),
+            publisher: OnceLock::new(),
+            token: Some("t0k".to_string()),
+            read_only: false,
+            dry_run: false,
+        };
+        configure(&mut web);
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap_or_else(|e| panic!("bind: {e}"));
+        let addr = listener
+            .local_addr()
+            .unwrap_or_else(|e| panic!("local_addr: {e}"));
+        tokio::spawn(async move { axum::serve(listener, Arc::new(web).router()).await });
+        format!("http://{addr}")
+    }
+
+    /// The status and JSON body of `request`.
+    async fn call(request: reqwest::RequestBuilder) -> (u16, Value) {
+        let response = request.send().await.unwrap_or_else(|e| panic!("{e}"));
+        let status = response.status().as_u16();
+        (status, response.json().await.unwrap_or_default())
+    }
+
+    #[tokio::test]
+    async fn the_api_needs_the_token_but_the_page_does_not() {
+        let url = serve(|_| {}).await;
+        let client = reqwest::Client::new();
+        for (method, path) in [
+            ("GET", "/api/events"),
+            ("GET", "/api/events/1"),
+            ("GET", "/api/types"),
+            ("POST", "/api/events/1/republish"),
+        ] {
+            let request = |method: &str| {
+                let url = format!("{url}{path}");
+                match method {
+                    "POST" => client.post(url),
+                    _ => client.get(url),
+                }
+            };
+            for authorization in [None, Some("Bearer wrong"), Some("Basic t0k"), Some("t0k")] {
+                let mut request = request(method);
+                if let Some(authorization) = authorization {
+                    request = request.header("authorization", authorization);
+                }
+                let (status, body) = call(request).await;
+                assert_eq!(status, 401, "{method} {path} with {authorization:?}");
+                assert_eq!(body["error"], "missing or wrong token");
+            }
+        }
+        let page = client.get(&url).send().await.map(|r| r.status().as_u16());
+        assert_eq!(page.ok(), Some(200));
+    }
+
+    #[tokio::test]
+    async fn republishing_is_refused_until_it_can_be_done() {
+        let client = reqwest::Client::new();
+        let republish = |url: &str, id: i64| {
+            client
+                .post(format!("{url}/api/events/{id}/republish"))
+                .bearer_auth("t0k")
+        };
+
+        let read_only = serve(|web| web.read_only = true).await;
+        let (status, body) = call(republish(&read_only, 1)).await;
+        assert_eq!(status, 403);
+        assert_eq!(body["error"], "republishing is disabled (--read-only)");
+
+        let url = serve(|_| {}).await;
+        let (status, body) = call(republish(&url, 99)).await;
+        assert_eq!(status, 404);
+        assert_eq!(body["error"], "no such event");
+        // The sink has not connected to the engine yet
+        let (status, body) = call(republish(&url, 1)).await;
+        assert_eq!(status, 503);
+        assert_eq!(body["error"], "not connected yet");
+
+        let dry_run = serve(|web| web.dry_run = true).await;
+        let (status, body) = call(republish(&dry_run, 1)).await;
+        assert_eq!(status, 200);
+        assert_eq!(body, json!({"republished": false, "dry_run": true}));
+    }
+
+    #[tokio::test]
+    async fn malformed_queries_are_refused_and_limits_clamped() {
+        let url = serve(|_| {}).await;
+        let client = reqwest::Client::new();
+        let get = |path: &str| client.get(format!("{url}{path}")).bearer_auth("t0k");
+
+        for path in ["/api/events?limit=many", "/api/events?since=yesterday"] {
+            let status = get(path).send().await.map(|r| r.status().as_u16());
+            assert_eq!(status.ok(), Some(400), "{path}");
+        }
+        let status = get("/api/events/first")
+            .send()
+            .await
+            .map(|r| r.status().as_u16());
+        assert_eq!(status.ok(), Some(400));
+
+        // A limit of 0 still returns a page, and links to the next one
+        let (status, page) = call(get("/api/events?limit=0")).await;
+        assert_eq!(status, 200);
+        assert_eq!(page["events"][0]["message_id"], "m2");
+        assert_eq!(page["next"], 2);
+        let (_, page) = call(get("/api/events?limit=1&before=2")).await;
+        assert_eq!(page["events"][0]["message_id"], "m1");
+        let (_, page) = call(get("/api/events?before=1")).await;
+        assert_eq!(page, json!({"events": [], "next": null}));
+    }
+}
@@ -0,0 +1,160 @@
+//! Generates payload structs from the JSON Schemas in `schemas/`.
+//!
+//! Each `schemas/<message.type>.json` becomes one struct (named by the
+//! schema's `title`) plus an `EventPayload` impl carrying its message type.
+//! Only the subset of JSON Schema the registry needs is supported:
+//!
+//! - `string`, `integer` (`format`: `int32`, `uint32`, `uint64`; default
+//!   `i64`), `number`, `boolean`
+//! - `array` with `items`
+//! - `object` with `additionalProperties` → `HashMap<String, _>`
+//! - a `["<type>", "null"]` union → `Option<_>`
+//! - no `type` → `serde_json::Value`
+//!
+//! Properties missing from `required` become `Option<_>` and are omitted
+//! when `None`. Anything else is a build error, so an unsupported schema is
+//! caught before it ships.
+
+use serde_json::{Map, Value};
+use std::{env, error::Error, fmt::Write as _, fs, path::Path};
+
+type BuildResult<T> = Result<T, Box<dyn Error>>;
+
+fn main() -> BuildResult<()> {
+    let schemas = Path::new(env!("CARGO_MANIFEST_DIR")).join("../../schemas");
+    println!("cargo:rerun-if-changed={}", schemas.display());
+
+    let mut paths: Vec<_> = fs::read_dir(&schemas)?
+        .map(|entry| entry.map(|e| e.path()))
+        .collect::<Result<_, _>>()?;
+    paths.retain(|p| p.extension().is_some_and(|ext| ext == "json"));
+    paths.sort();
+
+    let mut out = String::new();
+    for path in &paths {
+        println!("cargo:rerun-if-changed={}", path.display());
+        let message_type = path
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .ok_or_else(|| format!("{}: invalid file name", path.display()))?;
+        let schema: Value = serde_json::from_str(&fs::read_to_string(path)?)?;
+        generate(&mut out, message_type, &schema)
+            .map_err(|e| format!("{}: {e}", path.display()))?;
+    }
+
+    let dest = Path::new(&env::var("OUT_DIR")?).join("payloads.rs");
+    fs::write(dest, out)?;
+    Ok(())
+}
+
+/// Emit the struct and `EventPayload` impl for one schema.
+fn generate(out: &mut String, message_type: &str, schema: &Value) -> BuildResult<()> {
+    let title = schema
+        .get("title")
+        .and_then(Value::as_str)
+        .ok_or("schema has no title")?;
+    if schema.get("type").and_then(Value::as_str) != Some("object") {
+        return Err("top-level schema must be an object".into());
+    }
+    let empty = Map::new();
+    let properties = schema
+        .get("properties")
+        .and_then(Value::as_object)
+        .unwrap_or(&empty);
+    let required: Vec<&str> = schema
+        .get("required")
+        .and_then(Value::as_array)
+        .map(|r| r.iter().filter_map(Value::as_str).collect())
+        .unwrap_or_default();
+
+    doc(out, "", schema);
+    writeln!(
+        out,
+        "#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]"
+    )?;
+    writeln!(out, "pub struct {title} {{")?;
+    for (name, property) in properties {
+        let mut ty = rust_type(property).map_err(|e| format!("property '{name}': {e}"))?;
+        doc(out, "    ", property);
+        if !required.contains(&name.as_str()) {
+            ty = format!("Option<{ty}>");
+            writeln!(
+                out,
+                "    #[serde(default, skip_serializing_if = \"Option::is_none\")]"
+            )?;
+        }
+        writeln!(out, "    pub {}: {ty},", field_name(name))?;
+    }
+    writeln!(out, "}}\n")?;
+
+    writeln!(out, "impl EventPayload for {title} {{")?;
+    writeln!(
+        out,
+        "    const MESSAGE_TYPE: &'static str = {message_type:?};"
+    )?;
+    writeln!(out, "}}\n")?;
+    Ok(())
+}
+
+/// Map a property schema to a Rust type.
+fn rust_type(schema: &Value) -> BuildResult<String> {
+    let Some(ty) = schema.get("type") else {
+        return Ok("serde_json::Value".to_string());
+    };
+    if let Some(union) = ty.as_array() {
+        let types: Vec<&str> = union.iter().filter_map(Value::as_str).collect();
+        return match types.as_slice() {
+            [inner, "null"] | ["null", inner] => {
+                let mut inner_schema = schema.clone();
+                inner_schema["type"] = Value::from(*inner);
+                Ok(format!("Option<{}>", rust_type(&inner_schema)?))
+            }
+            _ => Err(format!("unsupported type union {ty}").into()),
+        };
+    }
+    let format = schema.get("format").and_then(Value::as_str);
+    match (ty.as_str(), format) {
+        (Some("string"), _) => Ok("String".to_string()),
+        (Some("integer"), Some("int32")) => Ok("i32".to_string()),
+        (Some("integer"), Some("uint32")) => Ok("u32".to_string()),
+        (Some("integer"), Some("uint64")) => Ok("u64".to_string()),
+        (Some("integer"), None) => Ok("i64".to_string()),
+        (Some("number"), _) => Ok("f64".to_string()),
+        (Some("boolean"), _) => Ok("bool".to_string()),
+        (Some("array"), _) => {
+            let items = schema.get("items").ok_or("array without items")?;
+            Ok(format!("Vec<{}>", rust_type(items)?))
+        }
+        (Some("object"), _) => match schema.get("additionalProperties") {
+            Some(values) if values.is_object() && schema.get("properties").is_none() => Ok(
+                format!("std::collections::HashMap<String, {}>", rust_type(values)?),
+            ),
+            _ => Err("nested objects must be maps (additionalProperties only)".into()),
+        },
+        _ => Err(format!("unsupported type {ty}").into()),
+    }
+}
+
+/// Emit a schema's `description` as a doc comment.
+fn doc(out: &mut String, indent: &str, schema: &Value) {
+    if let Some(description) = schema.get("description").and_then(Value::as_str) {
+        for line in description.lines() {
+            let _ = writeln!(out, "{indent}/// {line}");
+        }
+    }
+}
+
+/// Escape property names that collide with Rust keywords.
+fn field_name(name: &str) -> String {
+    const KEYWORDS: &[&str] = &[
+        "as", "async", "await", "break", "const", "continue", "crate", "dyn", "else", "enum",
+        "extern", "false", "fn", "for", "if", "impl", "in", "let", "loop", "match", "mod", "move",
+        "mut", "pub", "ref", "return", "static", "struct", "trait", "true", "type", "unsafe",
+        "use", "where", "while",
+    ];
+    if KEYWORDS.contains(&name) {
+        format!("r#{name}")
+    } else {
+        name.to_string()
+    }
+}
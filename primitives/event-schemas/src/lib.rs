@@ -0,0 +1,86 @@
+//! Typed event payloads.
+//!
+//! Structs here are generated at build time from the JSON Schemas in the
+//! repository's `schemas/` directory, one per message type (see
+//! `schemas/README.md`). Sources build payloads from them instead of ad-hoc
+//! `json!` literals, and consumers deserialize into them instead of
+//! probing `payload.get("field")`:
+//!
+//! ```
+//! use event_schemas::{EventPayload, ExecExit};
+//!
+//! let exit = ExecExit { command: "date".to_string(), exit_code: 0 };
+//! let payload = exit.to_payload();
+//! assert_eq!(ExecExit::MESSAGE_TYPE, "exec.exit");
+//! assert_eq!(ExecExit::from_payload(&payload).ok(), Some(exit));
+//! ```
+
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use serde_json::Value;
+
+/// A payload type bound to the message type its schema describes.
+pub trait EventPayload: Serialize + DeserializeOwned {
+    /// Message type the schema is registered under (e.g. `exec.exit`).
+    const MESSAGE_TYPE: &'static str;
+
+    /// Serialize into a message payload.
+    fn to_payload(&self) -> Value {
+        // Generated structs contain only strings, numbers, and JSON values,
+        // all of which serialize infallibly.
+        serde_json::to_value(self).unwrap_or(Value::Null)
+    }
+
+    /// Deserialize from a message payload, validating it against the schema.
+    fn from_payload(payload: &Value) -> Result<Self, serde_json::Error> {
+        Self::deserialize(payload)
+    }
+}
+
+include!(concat!(env!("OUT_DIR"), "/payloads.rs"));
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn nullable_fields_serialize_as_null() {
+        let event = PrimitiveError {
+            primitive: "exec-source".to_string(),
+            message_id: None,
+            message_type: None,
+            category: "request".to_string(),
+            disposition: "dropped".to_string(),
+            attempt: None,
+            error: "spawn failed".to_string(),
+        };
+        let payload = event.to_payload();
+
+        assert!(payload["message_id"].is_null());
+        assert!(payload["attempt"].is_null());
+        assert_eq!(PrimitiveError::MESSAGE_TYPE, "primitive.error");
+    }
+
+    #[test]
+    fn maps_and_untyped_values_round_trip() {
+        let payload = json!({
+            "method": "POST",
+            "path": "/hook",
+            "headers": {"content-type": "application/json"},
+            "body": {"ok": true},
+            "remote_addr": null,
+        });
+        let request =
+            HttpRequest::from_payload(&payload).unwrap_or_else(|e| panic!("deserialize: {e}"));
+
+        assert_eq!(request.headers["content-type"], "application/json");
+        assert_eq!(request.body, json!({"ok": true}));
+        assert_eq!(request.to_payload(), payload);
+    }
+
+    #[test]
+    fn missing_required_fields_are_rejected() {
+        let payload = json!({"command": "date"});
+        assert!(ExecExit::from_payload(&payload).is_err());
+    }
+}
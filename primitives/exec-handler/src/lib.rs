@@ -0,0 +1,242 @@
+//! Exec Handler
+//!
+//! A Handler that subscribes to events, pipes the incoming payload through an
+//! external executable's stdin, and publishes the executable's stdout as a new event.
+//!
+//! This enables arbitrary command-line tools (jq, claude, python scripts, etc.)
+//! to participate in Emergent workflows without writing a custom primitive.
+//!
+//! # Data Flow
+//!
+//! 1. Receive an event matching configured subscriptions
+//! 2. Serialize the event payload as JSON
+//! 3. Spawn the command and write the payload to its stdin
+//! 4. Capture stdout and publish as a new event
+//! 5. On failure, publish an error event
+//!
+//! # Messages Published
+//!
+//! - Configurable success type (default: `exec.output`) — stdout from the command
+//! - Configurable error type (default: `exec.error`) — on non-zero exit or timeout
+//!
+//! Payloads sealed with `--encrypt-topic` elsewhere are decrypted with
+//! `--identity-file`; this handler's own output can be sealed the same way.
+//!
+//! # Usage
+//!
+//! ```bash
+//! # Pipe events through jq
+//! exec-handler --publish-as data.transformed -- jq '.data | keys'
+//!
+//! # Pipe events through claude
+//! exec-handler --publish-as ai.analysis --timeout 60000 -- claude -p "Analyze this"
+//!
+//! # Use defaults
+//! exec-handler -- my-transform-script
+//! ```
+
+use clap::Parser;
+use emergent_client::EmergentMessage;
+use exec_common::{ExecError, error_to_json, execute_command};
+use primitive_common::capabilities::{self, CapabilityArgs, Role, VERSION};
+use primitive_common::crypto::{self, KeyArgs, Keyring};
+use primitive_common::doctor::{Report, check_executable};
+use primitive_common::errors::{ERROR_EVENT_TYPE, ErrorArgs, ErrorCategory};
+use primitive_common::handler::{Bus, HandlerConfig, MessageHandler, run_handler};
+use primitive_common::payload::{self, PayloadArgs};
+use serde_json::json;
+
+/// Exec Handler — pipe event payloads through an executable.
+///
+/// Everything after `--` is the command and its arguments.
+#[derive(Parser, Debug)]
+#[command(name = "exec_handler", version = VERSION)]
+#[command(about = "Pipe event payloads through an executable and publish results")]
+#[command(trailing_var_arg = true)]
+struct Args {
+    /// Message types to subscribe to.
+    #[arg(short, long = "subscribe", required = true)]
+    subscribe: Vec<String>,
+
+    /// Message type for successful output.
+    #[arg(long, default_value = "exec.output")]
+    publish_as: String,
+
+    /// Message type for error output.
+    #[arg(short, long, default_value = "exec.error")]
+    error_as: String,
+
+    /// Per-execution timeout in milliseconds.
+    #[arg(short, long, default_value = "30000")]
+    timeout: u64,
+
+    #[command(flatten)]
+    payload: PayloadArgs,
+
+    /// Verify external dependencies and exit without connecting to the engine.
+    #[arg(long)]
+    self_test: bool,
+
+    #[command(flatten)]
+    errors: ErrorArgs,
+
+    #[command(flatten)]
+    capabilities: CapabilityArgs,
+
+    #[command(flatten)]
+    keys: KeyArgs,
+
+    /// The command and arguments to execute (after --).
+    #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
+    command: Vec<String>,
+}
+
+/// Run the primitive with `args` as its command line (the first item is
+/// the program name, as with `std::env::args_os`).
+pub async fn run<I, T>(args: I) -> Result<(), Box<dyn std::error::Error>>
+where
+    I: IntoIterator<Item = T>,
+    T: Into<std::ffi::OsString> + Clone,
+{
+    let args = Args::parse_from(args);
+
+    // Resolve publish types: EMERGENT_PUBLISHES env > CLI args > defaults
+    let publish_types =
+        exec_common::resolve_publish_types_from_env(&[&args.publish_as, &args.error_as]);
+    let publish_as = &publish_types[0];
+    let error_as = &publish_types[1];
+
+    // Validate that a command was provided after --
+    if args.command.is_empty() {
+        eprintln!("Error: no command specified. Provide a command after '--'.");
+        eprintln!("Example: exec-handler --publish-as data.out -- jq '.'");
+        std::process::exit(1);
+    }
+
+    // Get the handler name from environment (set by engine) or use default
+    let name = std::env::var("EMERGENT_NAME").unwrap_or_else(|_| "exec_handler".to_string());
+
+    if args.self_test {
+        let mut report = Report::new(&name);
+        report.check("command", check_executable(&args.command[0]));
+        args.payload.self_test(&mut report);
+        args.keys.self_test(&mut report);
+        report.finish();
+    }
+
+    if let Err(e) = args.payload.encryption.validate() {
+        eprintln!("Error: {e}");
+        std::process::exit(1);
+    }
+    let keys = match args.keys.load() {
+        Ok(keys) => keys,
+        Err(e) => {
+            eprintln!("Error: failed to load identity file: {e}");
+            std::process::exit(1);
+        }
+    };
+
+    let topics_refs: Vec<&str> = args.subscribe.iter().map(String::as_str).collect();
+    let mut produces = vec![publish_as.as_str(), error_as.as_str()];
+    if args.errors.emit_errors {
+        produces.push(ERROR_EVENT_TYPE);
+    }
+    let descriptor =
+        args.capabilities
+            .describe(&name, Role::Handler, &topics_refs, &produces, &args);
+    if args.capabilities.capabilities {
+        capabilities::print(&descriptor);
+    }
+
+    let config = HandlerConfig {
+        name: &name,
+        subscribe: &args.subscribe,
+        announce: args.capabilities.announce.then_some(&descriptor),
+        emit_errors: args.errors.emit_errors,
+    };
+    let mut exec = Exec {
+        args: &args,
+        publish_as,
+        error_as,
+        keys,
+    };
+    run_handler(config, &mut exec).await
+}
+
+/// The command to run and where its results go.
+struct Exec<'a> {
+    args: &'a Args,
+    publish_as: &'a str,
+    error_as: &'a str,
+    keys: Option<Keyring>,
+}
+
+impl MessageHandler for Exec<'_> {
+    type Wake = ();
+
+    /// Run the command for one message and publish its output or error.
+    async fn handle(&mut self, msg: &EmergentMessage, bus: &Bus) {
+        let (args, publish_as, error_as) = (self.args, self.publish_as, self.error_as);
+        let input = match payload::decode(msg.payload(), self.keys.as_ref()).await {
+            Ok(p) => p,
+            Err(e) => {
+                let error = format!("failed to decode payload: {e}");
+                eprintln!("exec-handler: {error}");
+                bus.report_error(msg, ErrorCategory::Parse, &error).await;
+                return;
+            }
+        };
+
+        match execute_command(&input, &args.command, args.timeout).await {
+            Ok(Some(result)) => {
+                let stdout_payload =
+                    match payload::encode(publish_as, result.stdout_payload, &args.payload).await {
+                        Ok(p) => p,
+                        Err(e) => {
+                            let error = format!("failed to encode output: {e}");
+                            eprintln!("exec-handler: {error}");
+                            bus.report_error(msg, ErrorCategory::Internal, &error).await;
+                            return;
+                        }
+                    };
+                let mut output = EmergentMessage::new(publish_as)
+                    .with_causation_id(msg.id())
+                    .with_payload(stdout_payload);
+
+                if let Some(stderr) = result.stderr {
+                    output = output.with_metadata(json!({"stderr": stderr}));
+                }
+
+                bus.publish(output).await;
+            }
+            Ok(None) => {
+                // Command produced no output — silent filter, skip publishing
+            }
+            Err(ExecError::Failed { ref stderr, .. }) if stderr.trim().is_empty() => {
+                // Non-zero exit with no stderr — silent filter (e.g., jq select)
+            }
+            Err(exec_err) => {
+                let error_payload = error_to_json(&exec_err);
+                match crypto::seal(error_as, error_payload.clone(), &args.payload.encryption) {
+                    Ok(sealed) => {
+                        let error_msg = EmergentMessage::new(error_as)
+                            .with_causation_id(msg.id())
+                            .with_payload(sealed);
+                        bus.publish(error_msg).await;
+                    }
+                    Err(e) => eprintln!("exec-handler: failed to encrypt error output: {e}"),
+                }
+
+                let category = match exec_err {
+                    ExecError::Failed { .. } => ErrorCategory::Rejected,
+                    ExecError::Timeout { .. } => ErrorCategory::Timeout,
+                    ExecError::SpawnFailed { .. } | ExecError::StdinFailed { .. } => {
+                        ErrorCategory::Request
+                    }
+                };
+                let error = error_payload["stderr"].as_str().unwrap_or_default();
+                bus.report_error(msg, category, error).await;
+            }
+        }
+    }
+}
@@ -0,0 +1,229 @@
+//! Exec Sink - Pipe Event Payloads Through Executables
+//!
+//! A Sink that subscribes to events and pipes each payload through an external
+//! command's stdin. The command's output is discarded (sink pattern — terminal
+//! consumer). This makes any command-line tool a valid event consumer.
+//!
+//! # Examples
+//!
+//! ```bash
+//! # Print payloads with jq (replaces console-sink)
+//! exec-sink -s timer.tick -- jq .
+//!
+//! # POST payloads to a webhook (replaces http-sink for simple cases)
+//! exec-sink -s alert.fired -- curl -s -X POST -H "Content-Type: application/json" -d @- https://hooks.example.com/webhook
+//!
+//! # Append payloads to a file
+//! exec-sink -s data.processed -- tee -a /var/log/events.jsonl
+//!
+//! # Pipe through any script
+//! exec-sink -s user.created -- ./scripts/send-welcome-email.sh
+//!
+//! # Validate wiring without running the command
+//! exec-sink -s alert.fired --dry-run -- ./scripts/page-oncall.sh
+//!
+//! # Swap the command or timeout on SIGHUP without reconnecting
+//! exec-sink -s alert.fired --config /etc/emergent/pager.json -- ./scripts/page-oncall.sh
+//! ```
+//!
+//! `--config` may override `command` and `timeout`.
+
+use clap::Parser;
+use emergent_client::EmergentMessage;
+use exec_common::{ExecError, execute_command_passthrough};
+use primitive_common::capabilities::VERSION;
+use primitive_common::doctor::{Report, check_executable};
+use primitive_common::errors::{ErrorCategory, HandlerError};
+use primitive_common::reload::HotConfig;
+use primitive_common::{SinkArgs, SinkConfig, SinkContext, SinkHandler, run_sink};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+/// Exec Sink — pipe event payloads through an executable.
+///
+/// Everything after `--` is the command and its arguments.
+#[derive(Parser, Debug)]
+#[command(name = "exec_sink", version = VERSION)]
+#[command(about = "Pipe event payloads through an executable (fire-and-forget)")]
+#[command(trailing_var_arg = true)]
+struct Args {
+    /// Message types to subscribe to.
+    #[arg(short, long = "subscribe", required = true)]
+    subscribe: Vec<String>,
+
+    /// Per-execution timeout in milliseconds.
+    #[arg(short, long, default_value = "30000")]
+    timeout: u64,
+
+    #[command(flatten)]
+    sink: SinkArgs,
+
+    /// The command and arguments to execute (after --).
+    #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
+    command: Vec<String>,
+}
+
+/// Settings that can be swapped on SIGHUP.
+#[derive(Debug, Serialize, Deserialize)]
+struct Settings {
+    command: Vec<String>,
+    timeout: u64,
+}
+
+/// Pipes each payload through the configured command.
+struct ExecSink {
+    settings: HotConfig<Settings>,
+}
+
+impl SinkHandler for ExecSink {
+    async fn handle(
+        &self,
+        _msg: &EmergentMessage,
+        ctx: &SinkContext<'_>,
+    ) -> Result<(), HandlerError> {
+        let settings = self.settings.current();
+        if settings.command.is_empty() {
+            // Only reachable through a config file overriding `command`
+            return Err(HandlerError::new(
+                ErrorCategory::Internal,
+                "no command configured",
+            ));
+        }
+        if ctx.is_dry_run() {
+            let detail = json!({
+                "command": settings.command.join(" "),
+                "stdin": ctx.payload(),
+            });
+            ctx.would_have("exec", detail).await;
+            return Ok(());
+        }
+
+        execute_command_passthrough(ctx.payload(), &settings.command, settings.timeout)
+            .await
+            .map_err(|err| match err {
+                ExecError::Failed {
+                    command, exit_code, ..
+                } => HandlerError::new(
+                    ErrorCategory::Rejected,
+                    format!("{command}: exit code {exit_code}"),
+                ),
+                ExecError::Timeout { command } => {
+                    HandlerError::new(ErrorCategory::Timeout, format!("{command}: timed out"))
+                }
+                ExecError::SpawnFailed { error, command } => {
+                    HandlerError::new(ErrorCategory::Request, format!("{command}: {error}"))
+                }
+                ExecError::StdinFailed { error, command } => {
+                    HandlerError::new(ErrorCategory::Request, format!("{command}: stdin: {error}"))
+                }
+            })
+    }
+
+    fn self_test(&self, report: &mut Report) {
+        let settings = self.settings.current();
+        let program = settings.command.first().map_or("", String::as_str);
+        report.check("command", check_executable(program));
+    }
+
+    fn reload(&self) -> Result<Vec<String>, String> {
+        self.settings.reload()
+    }
+}
+
+/// Run the primitive with `args` as its command line (the first item is
+/// the program name, as with `std::env::args_os`).
+pub async fn run<I, T>(args: I) -> Result<(), Box<dyn std::error::Error>>
+where
+    I: IntoIterator<Item = T>,
+    T: Into<std::ffi::OsString> + Clone,
+{
+    let args = Args::parse_from(args);
+
+    if args.command.is_empty() {
+        eprintln!("Error: no command specified. Provide a command after '--'.");
+        eprintln!("Example: exec-sink --subscribe timer.tick -- jq .");
+        std::process::exit(1);
+    }
+
+    let config = SinkConfig {
+        name: "exec_sink",
+        subscribe: &args.subscribe,
+        would_have_as: "exec.would_have",
+        dead_letter_as: "exec.dead_letter",
+        settings: &args,
+    };
+    let defaults = Settings {
+        command: args.command.clone(),
+        timeout: args.timeout,
+    };
+    let settings = match HotConfig::load(&args.sink.reload, defaults) {
+        Ok(settings) => settings,
+        Err(e) => {
+            eprintln!("Error: invalid config: {e}");
+            std::process::exit(1);
+        }
+    };
+    let handler = ExecSink { settings };
+
+    run_sink(config, &args.sink, handler).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use emergent_testkit::{SinkFixture, fixtures, spawn_sink};
+    use primitive_common::reload::ReloadArgs;
+
+    fn exec_sink(command: &[&str]) -> ExecSink {
+        let defaults = Settings {
+            command: command.iter().map(|s| s.to_string()).collect(),
+            timeout: 5000,
+        };
+        let settings = HotConfig::load(&ReloadArgs::default(), defaults)
+            .unwrap_or_else(|e| panic!("load settings: {e}"));
+        ExecSink { settings }
+    }
+
+    #[tokio::test]
+    async fn dry_run_reports_the_command_instead_of_running_it() {
+        let args = SinkArgs {
+            dry_run: true,
+            ..Default::default()
+        };
+        let handler = exec_sink(&["false"]);
+        let (mut engine, run) = spawn_sink(SinkFixture::new("exec_sink", "exec"), args, handler);
+
+        engine
+            .inject_message(fixtures::message("alert.fired", json!({"level": "high"})))
+            .await;
+        let report = engine.expect_published("exec.would_have").await;
+        assert_eq!(report.payload()["detail"]["command"], "false");
+        assert_eq!(report.payload()["detail"]["stdin"]["level"], "high");
+
+        engine.close();
+        assert_eq!(run.await.ok(), Some(Ok(())));
+    }
+
+    #[tokio::test]
+    async fn failing_commands_are_dead_lettered() {
+        let args = SinkArgs {
+            dead_letter: true,
+            ..Default::default()
+        };
+        let handler = exec_sink(&["sh", "-c", "cat >/dev/null; exit 3"]);
+        let (mut engine, run) = spawn_sink(SinkFixture::new("exec_sink", "exec"), args, handler);
+
+        engine
+            .inject_message(fixtures::message("alert.fired", json!({"level": "high"})))
+            .await;
+        let dead = engine.expect_published("exec.dead_letter").await;
+        assert_eq!(dead.payload()["attempts"], 1);
+        assert_eq!(
+            dead.payload()["error"],
+            "sh -c cat >/dev/null; exit 3: exit code 3"
+        );
+
+        engine.close();
+        assert_eq!(run.await.ok(), Some(Ok(())));
+    }
+}
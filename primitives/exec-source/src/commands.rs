@@ -0,0 +1,207 @@
+//! Running several commands from one process (`--commands`).
+//!
+//! The file lists named commands, each with its own schedule, output
+//! parser and event type prefix:
+//!
+//! ```json
+//! {"commands": [
+//!   {"name": "disk", "command": "df", "args": "-h /", "interval": 60000},
+//!   {"name": "load", "command": "./load.sh", "interval": 5000, "parser": "json", "prefix": "host.load"}
+//! ]}
+//! ```
+//!
+//! A command publishes `<prefix>.output`, `<prefix>.error` and
+//! `<prefix>.exit`, where the prefix defaults to its name. Commands run
+//! concurrently; one that fails (or fails to parse) is reported on its own
+//! and does not stop the others.
+
+use crate::output::Captured;
+use clap::ValueEnum;
+use event_schemas::{EventPayload, ExecOutput};
+use serde::Deserialize;
+use serde_json::Value;
+use std::{collections::HashSet, fs, path::Path};
+
+/// How a command's stdout becomes output events.
+#[derive(ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputParser {
+    /// One event carrying stdout as text.
+    #[default]
+    Text,
+    /// Stdout is one JSON value, published as the payload.
+    Json,
+    /// Each line of stdout is a JSON value, published as its own event.
+    Jsonl,
+}
+
+impl OutputParser {
+    /// Payloads of the output events for one run.
+    pub fn parse(
+        self,
+        command: &str,
+        stdout: Captured,
+        exit_code: i32,
+    ) -> Result<Vec<Value>, String> {
+        match self {
+            Self::Text => Ok(vec![
+                ExecOutput {
+                    command: command.to_string(),
+                    stdout: stdout.text,
+                    exit_code,
+                    encoding: stdout.encoding,
+                    truncated: stdout.truncated,
+                    total_bytes: stdout.total_bytes,
+                }
+                .to_payload(),
+            ]),
+            Self::Json => serde_json::from_str(&stdout.text)
+                .map(|value| vec![value])
+                .map_err(|e| format!("stdout is not JSON: {e}")),
+            Self::Jsonl => stdout
+                .text
+                .lines()
+                .enumerate()
+                .filter(|(_, line)| !line.trim().is_empty())
+                .map(|(n, line)| {
+                    serde_json::from_str(line)
+                        .map_err(|e| format!("stdout line {} is not JSON: {e}", n + 1))
+                })
+                .collect(),
+        }
+    }
+}
+
+/// One command to run.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CommandSpec {
+    /// Name used in logs and as the default type prefix.
+    pub name: String,
+    pub command: String,
+    /// Arguments, space-separated.
+    #[serde(default)]
+    pub args: Option<String>,
+    /// Milliseconds between runs (0 = run once).
+    #[serde(default)]
+    pub interval: u64,
+    #[serde(default)]
+    pub working_dir: Option<String>,
+    #[serde(default)]
+    pub shell: Option<String>,
+    #[serde(default)]
+    pub parser: OutputParser,
+    /// Event type prefix; defaults to `name`.
+    #[serde(default)]
+    pub prefix: Option<String>,
+}
+
+impl CommandSpec {
+    /// The output, error and exit types this command publishes.
+    pub fn event_types(&self) -> Vec<String> {
+        let prefix = self.prefix.as_deref().unwrap_or(&self.name);
+        ["output", "error", "exit"]
+            .iter()
+            .map(|kind| format!("{prefix}.{kind}"))
+            .collect()
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CommandsFile {
+    commands: Vec<CommandSpec>,
+}
+
+/// Read and validate a `--commands` file.
+pub fn load(path: &Path) -> Result<Vec<CommandSpec>, String> {
+    let text =
+        fs::read_to_string(path).map_err(|e| format!("cannot read {}: {e}", path.display()))?;
+    parse(&text).map_err(|e| format!("{}: {e}", path.display()))
+}
+
+/// Parse and validate the contents of a `--commands` file.
+pub fn parse(text: &str) -> Result<Vec<CommandSpec>, String> {
+    let file: CommandsFile = serde_json::from_str(text).map_err(|e| e.to_string())?;
+    if file.commands.is_empty() {
+        return Err("no commands defined".to_string());
+    }
+    let mut names = HashSet::new();
+    for spec in &file.commands {
+        if spec.name.trim().is_empty() || spec.command.trim().is_empty() {
+            return Err("every command needs a name and a command".to_string());
+        }
+        if !names.insert(spec.name.as_str()) {
+            return Err(format!("duplicate command name '{}'", spec.name));
+        }
+    }
+    Ok(file.commands)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn commands_files_parse_with_defaults() {
+        let specs = parse(
+            r#"{"commands": [
+                {"name": "disk", "command": "df", "args": "-h", "interval": 60000},
+                {"name": "load", "command": "./load.sh", "parser": "jsonl", "prefix": "host.load"}
+            ]}"#,
+        )
+        .unwrap_or_else(|e| panic!("parse: {e}"));
+
+        assert_eq!(specs[0].parser, OutputParser::Text);
+        assert_eq!(
+            specs[0].event_types(),
+            ["disk.output", "disk.error", "disk.exit"]
+        );
+        assert_eq!(specs[1].interval, 0);
+        assert_eq!(specs[1].event_types()[0], "host.load.output");
+    }
+
+    #[test]
+    fn invalid_commands_files_are_rejected() {
+        assert!(parse(r#"{"commands": []}"#).is_err());
+        assert!(parse(r#"{"commands": [{"name": "a", "command": ""}]}"#).is_err());
+        let duplicate = r#"{"commands": [
+            {"name": "a", "command": "date"},
+            {"name": "a", "command": "uptime"}
+        ]}"#;
+        assert!(parse(duplicate).is_err());
+        assert!(parse(r#"{"commands": [{"name": "a", "command": "date", "every": 5}]}"#).is_err());
+    }
+
+    fn text(stdout: &str) -> Captured {
+        Captured {
+            text: stdout.to_string(),
+            encoding: None,
+            truncated: None,
+            total_bytes: None,
+        }
+    }
+
+    #[test]
+    fn parsers_shape_output_events() {
+        let output = OutputParser::Text.parse("date", text("Mon\n"), 0);
+        assert_eq!(
+            output.map(|p| p[0].clone()),
+            Ok(json!({"command": "date", "stdout": "Mon\n", "exit_code": 0}))
+        );
+        assert_eq!(
+            OutputParser::Json.parse("x", text(r#"{"load": 0.3}"#), 0),
+            Ok(vec![json!({"load": 0.3})])
+        );
+        assert_eq!(
+            OutputParser::Jsonl.parse("x", text("1\n\n{\"a\": 2}\n"), 0),
+            Ok(vec![json!(1), json!({"a": 2})])
+        );
+        assert!(
+            OutputParser::Jsonl
+                .parse("x", text("1\noops\n"), 0)
+                .is_err()
+        );
+    }
+}
@@ -0,0 +1,598 @@
+//! Exec Source - Shell Command Executor
+//!
+//! A Source that executes shell commands and emits events for stdout, stderr, and exit codes.
+//! Can run commands once or repeatedly on an interval.
+//!
+//! Sources are SILENT - they only produce domain messages.
+//! All lifecycle events are published by the engine.
+//!
+//! # Usage
+//!
+//! ```bash
+//! # Run command once
+//! exec-source --command "ls -la"
+//!
+//! # Run command every 5 seconds
+//! exec-source --command "date" --interval 5000
+//!
+//! # Run with arguments and custom working directory
+//! exec-source --command "git" --args "status" --working-dir /path/to/repo
+//!
+//! # Capture colored output as a terminal would show it
+//! exec-source --command "ls" --args "--color=auto" --pty --keep-ansi --pty-size 120x40
+//!
+//! # Keep one Python worker running and trigger it every 10 seconds
+//! exec-source --command "python3" --args "worker.py" --interval 10000 --persistent
+//!
+//! # Run every command listed in a file, each on its own schedule
+//! exec-source --commands /etc/emergent/probes.json
+//! ```
+//!
+//! # Events Published
+//!
+//! - `exec.output` - stdout from command
+//! - `exec.error` - stderr from command
+//! - `exec.exit` - exit code
+//!
+//! Types can be renamed with `--emit-type-map` (e.g. `exec.exit=job.finished`)
+//! or built with `--emit-type-template`, which may use `{type}`, `{source}`,
+//! and `{command}`. Spooled events are delivered in order before each run.
+//!
+//! With `--pty`, the command runs under a pseudo-terminal so TTY-detecting
+//! programs keep their interactive behaviour (see [`pty`]).
+//!
+//! With `--persistent`, the command is started once and driven through its
+//! stdin and stdout instead of being spawned per run (see [`persistent`]).
+//!
+//! `--parser json` or `--parser jsonl` publishes stdout as JSON payloads
+//! instead of text. With `--commands`, several commands with their own
+//! schedules, parsers and type prefixes run concurrently (see [`commands`]).
+//!
+//! # Shutdown
+//!
+//! On SIGTERM a command that is still running gets `--drain-timeout`
+//! milliseconds to finish (its output is still published); after that it is
+//! killed and the source disconnects. A `--persistent` worker has its stdin
+//! closed and gets the same deadline to exit.
+
+pub mod commands;
+pub mod output;
+pub mod persistent;
+pub mod pty;
+
+use clap::Parser;
+use commands::{CommandSpec, OutputParser};
+use emergent_client::EmergentSource;
+use event_schemas::{EventPayload, ExecError, ExecExit};
+use output::OutputArgs;
+use persistent::{PersistentArgs, Worker};
+use primitive_common::capabilities::{self, CapabilityArgs, Role, VERSION, capabilities_message};
+use primitive_common::doctor::{Report, check_dir_exists, check_executable};
+use primitive_common::errors::{ERROR_EVENT_TYPE, ErrorCategory};
+use primitive_common::source::{Outlet, SourceArgs};
+use primitive_common::spool::SPOOL_EVENT_TYPE;
+use pty::PtyArgs;
+use std::{path::PathBuf, time::Duration};
+use tokio::{
+    process::Command,
+    signal::unix::{Signal, SignalKind, signal},
+    sync::watch,
+};
+
+/// Command executor that emits output events.
+#[derive(Parser, Debug, Clone)]
+#[command(name = "exec-source", version = VERSION)]
+#[command(about = "Executes shell commands and emits output events")]
+struct Args {
+    /// Command to execute.
+    #[arg(
+        short,
+        long,
+        env = "EXEC_SOURCE_COMMAND",
+        required_unless_present = "commands"
+    )]
+    command: Option<String>,
+
+    /// Command arguments (space-separated).
+    #[arg(short, long, env = "EXEC_SOURCE_ARGS")]
+    args: Option<String>,
+
+    /// Optional interval in milliseconds for repeated execution (0 = run once).
+    #[arg(short, long, env = "EXEC_SOURCE_INTERVAL", default_value = "0")]
+    interval: u64,
+
+    /// Working directory for command execution.
+    #[arg(short = 'd', long, env = "EXEC_SOURCE_WORKING_DIR")]
+    working_dir: Option<String>,
+
+    /// Shell to use (e.g., "bash", "sh").
+    #[arg(short, long, env = "EXEC_SOURCE_SHELL")]
+    shell: Option<String>,
+
+    /// How stdout becomes `exec.output` events: as text, one JSON value, or one JSON value per line.
+    #[arg(long, env = "EXEC_SOURCE_PARSER", value_enum, default_value_t = OutputParser::Text)]
+    parser: OutputParser,
+
+    /// JSON file of named commands to run concurrently, each with its own schedule, parser and type prefix.
+    #[arg(
+        long,
+        env = "EXEC_SOURCE_COMMANDS",
+        conflicts_with_all = ["command", "args", "interval", "working_dir", "shell", "parser", "persistent"]
+    )]
+    commands: Option<PathBuf>,
+
+    #[command(flatten)]
+    output: OutputArgs,
+
+    #[command(flatten)]
+    pty: PtyArgs,
+
+    #[command(flatten)]
+    persistent: PersistentArgs,
+
+    /// Verify external dependencies and exit without connecting to the engine.
+    #[arg(long)]
+    self_test: bool,
+
+    #[command(flatten)]
+    source: SourceArgs,
+
+    #[command(flatten)]
+    capabilities: CapabilityArgs,
+}
+
+/// Variables available to `--emit-type-template`.
+const TEMPLATE_VARIABLES: &[&str] = &["source", "command"];
+
+/// A command together with the event types it publishes.
+struct Job {
+    spec: CommandSpec,
+    /// Built-in output, error and exit types, before `--emit-type-map` / `--emit-type-template`.
+    types: Vec<String>,
+}
+
+impl Job {
+    /// Publish `payload` as the `index`th of the job's types; the outlet
+    /// logs and reports what is lost.
+    async fn publish(&self, outlet: &Outlet, index: usize, payload: serde_json::Value) {
+        let vars = [("command", self.spec.command.as_str())];
+        let _ = outlet.publish(&self.types[index], &vars, payload).await;
+    }
+}
+
+impl Args {
+    /// The commands to run: those in the `--commands` file, or the single
+    /// one given on the command line.
+    fn specs(&self) -> Result<Vec<CommandSpec>, String> {
+        if let Some(path) = &self.commands {
+            return commands::load(path);
+        }
+        let command = self.command.clone().unwrap_or_default();
+        Ok(vec![CommandSpec {
+            name: command.clone(),
+            command,
+            args: self.args.clone(),
+            interval: self.interval,
+            working_dir: self.working_dir.clone(),
+            shell: self.shell.clone(),
+            parser: self.parser,
+            prefix: None,
+        }])
+    }
+}
+
+/// Builds a tokio Command from a command spec.
+fn build_command(spec: &CommandSpec) -> Command {
+    let mut cmd = if let Some(ref shell) = spec.shell {
+        let mut c = Command::new(shell);
+        c.arg("-c");
+
+        // Build full command string
+        let full_cmd = if let Some(ref cmd_args) = spec.args {
+            format!("{} {}", spec.command, cmd_args)
+        } else {
+            spec.command.clone()
+        };
+
+        c.arg(full_cmd);
+        c
+    } else {
+        let mut c = Command::new(&spec.command);
+
+        // Add arguments if provided
+        if let Some(ref cmd_args) = spec.args {
+            for arg in cmd_args.split_whitespace() {
+                c.arg(arg);
+            }
+        }
+
+        c
+    };
+
+    // Set working directory if provided
+    if let Some(ref working_dir) = spec.working_dir {
+        cmd.current_dir(working_dir);
+    }
+
+    // Abandoning a run at the drain deadline must not leave the child behind
+    cmd.kill_on_drop(true);
+
+    cmd
+}
+
+/// Runs `--self-test` checks and exits.
+fn self_test(args: &Args, specs: &Result<Vec<CommandSpec>, String>, name: &str) -> ! {
+    let mut report = Report::new(name);
+    match specs {
+        Ok(specs) => {
+            for spec in specs {
+                // Checks are named after the command when there are several
+                let label = |check: &str| match args.commands {
+                    Some(_) => format!("{}.{check}", spec.name),
+                    None => check.to_string(),
+                };
+                match &spec.shell {
+                    Some(shell) => report.check(&label("shell"), check_executable(shell)),
+                    None => report.check(&label("command"), check_executable(&spec.command)),
+                }
+                if let Some(dir) = &spec.working_dir {
+                    report.check(
+                        &label("working-dir"),
+                        check_dir_exists(std::path::Path::new(dir)),
+                    );
+                }
+            }
+        }
+        Err(e) => report.check("commands", Err(e.clone())),
+    }
+    args.source.self_test(&mut report, TEMPLATE_VARIABLES);
+    report.finish()
+}
+
+/// Executes a command once and publishes output events.
+async fn execute_command(
+    args: &Args,
+    job: &Job,
+    outlet: &Outlet,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let spec = &job.spec;
+    let cmd = build_command(spec);
+    let output = if args.pty.pty {
+        pty::output(cmd, &args.pty).await?
+    } else {
+        let mut cmd = cmd;
+        cmd.output().await?
+    };
+
+    let exit_code = output.status.code().unwrap_or(-1);
+
+    // Publish stdout if non-empty; stderr and the exit code still go out if it fails to parse
+    let stdout = args.output.capture(&output.stdout);
+    let parsed = stdout.map(|stdout| spec.parser.parse(&spec.command, stdout, exit_code));
+    let parse_error = match parsed.unwrap_or_else(|| Ok(Vec::new())) {
+        Ok(payloads) => {
+            for payload in payloads {
+                job.publish(outlet, 0, payload).await;
+            }
+            None
+        }
+        Err(e) => Some(e),
+    };
+
+    // Publish stderr if non-empty
+    if let Some(stderr) = args.output.capture(&output.stderr) {
+        let payload = ExecError {
+            command: spec.command.clone(),
+            stderr: stderr.text,
+            exit_code,
+            encoding: stderr.encoding,
+            truncated: stderr.truncated,
+            total_bytes: stderr.total_bytes,
+        };
+        job.publish(outlet, 1, payload.to_payload()).await;
+    }
+
+    // Always publish exit event
+    let payload = ExecExit {
+        command: spec.command.clone(),
+        exit_code,
+    };
+    job.publish(outlet, 2, payload.to_payload()).await;
+
+    match parse_error {
+        Some(e) => Err(e.into()),
+        None => Ok(()),
+    }
+}
+
+/// Executes the command once, honouring shutdown while it runs.
+///
+/// If shutdown is requested mid-run the command gets the drain deadline to
+/// finish and is killed otherwise. Returns the run's result (`None` if it
+/// was killed) and whether shutdown was requested.
+async fn execute_draining(
+    args: &Args,
+    job: &Job,
+    outlet: &Outlet,
+    stop: &mut watch::Receiver<bool>,
+) -> (Option<Result<(), Box<dyn std::error::Error>>>, bool) {
+    let run = execute_command(args, job, outlet);
+    tokio::pin!(run);
+
+    tokio::select! {
+        result = &mut run => (Some(result), false),
+        _ = stop.wait_for(|stop| *stop) => (args.source.drain.drain("running command", &mut run).await, true),
+    }
+}
+
+/// Runs one command on its schedule until it is done or shutdown is
+/// requested. Failures are reported here; only a run-once command returns
+/// its error.
+async fn run_job(
+    args: &Args,
+    job: &Job,
+    outlet: &Outlet,
+    mut stop: watch::Receiver<bool>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if job.spec.interval == 0 {
+        // Run once
+        outlet.drain_spool().await;
+        let (result, _) = execute_draining(args, job, outlet, &mut stop).await;
+        if let Some(Err(e)) = result {
+            outlet
+                .report_error(ErrorCategory::Request, &format!("{}: {e}", job.spec.name))
+                .await;
+            return Err(e);
+        }
+        return Ok(());
+    }
+
+    // Run repeatedly on interval
+    let mut interval = tokio::time::interval(Duration::from_millis(job.spec.interval));
+    let mut idle = stop.clone();
+    loop {
+        tokio::select! {
+            _ = idle.wait_for(|stop| *stop) => break,
+
+            _ = interval.tick() => {
+                outlet.drain_spool().await;
+                let (result, terminated) = execute_draining(args, job, outlet, &mut stop).await;
+                if let Some(Err(e)) = result {
+                    outlet
+                        .report_error(ErrorCategory::Request, &format!("{}: {e}", job.spec.name))
+                        .await;
+                }
+                if terminated {
+                    break;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Publish one line of worker output as an `exec.output` event.
+async fn publish_line(outlet: &Outlet, job: &Job, line: &str) {
+    if line.trim().is_empty() {
+        return;
+    }
+    match persistent::parse_line(line) {
+        Ok(payload) => job.publish(outlet, 0, payload).await,
+        Err(e) => eprintln!("Skipping worker output: {e}"),
+    }
+}
+
+/// Wait for the next interval tick, or forever without an interval.
+async fn tick(interval: &mut Option<tokio::time::Interval>) {
+    match interval {
+        Some(interval) => {
+            interval.tick().await;
+        }
+        None => std::future::pending().await,
+    }
+}
+
+/// Runs the command as a `--persistent` worker until SIGTERM, restarting it
+/// whenever it exits.
+async fn run_persistent(
+    args: &Args,
+    outlet: &Outlet,
+    job: &Job,
+    mut stop: watch::Receiver<bool>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut interval = (job.spec.interval > 0)
+        .then(|| tokio::time::interval(Duration::from_millis(job.spec.interval)));
+    let mut seq = 0;
+
+    loop {
+        let mut worker = match Worker::spawn(build_command(&job.spec)) {
+            Ok(worker) => worker,
+            Err(e) => {
+                outlet
+                    .report_error(ErrorCategory::Request, &e.to_string())
+                    .await;
+                return Err(e.into());
+            }
+        };
+
+        let status = loop {
+            tokio::select! {
+                _ = stop.wait_for(|stop| *stop) => {
+                    // Closing stdin asks the worker to finish; what it prints meanwhile is still published
+                    worker.close_stdin();
+                    let finish = async {
+                        while let Ok(Some(line)) = worker.next_line().await {
+                            publish_line(outlet, job, &line).await;
+                        }
+                        worker.wait().await
+                    };
+                    args.source.drain.drain("persistent worker", finish).await;
+                    return Ok(());
+                }
+
+                _ = tick(&mut interval) => {
+                    outlet.drain_spool().await;
+                    seq += 1;
+                    if !worker.trigger(seq) {
+                        eprintln!("Worker is not reading its input; skipped trigger {seq}");
+                    }
+                }
+
+                line = worker.next_line() => match line {
+                    Ok(Some(line)) => publish_line(outlet, job, &line).await,
+                    Ok(None) => break worker.wait().await?,
+                    Err(e) => {
+                        eprintln!("Failed to read worker output: {e}");
+                        break worker.wait().await?;
+                    }
+                },
+            }
+        };
+
+        let exit_code = status.code().unwrap_or(-1);
+        let payload = ExecExit {
+            command: job.spec.command.clone(),
+            exit_code,
+        };
+        job.publish(outlet, 2, payload.to_payload()).await;
+        eprintln!(
+            "Worker exited with code {exit_code}; restarting in {}ms",
+            args.persistent.restart_delay
+        );
+
+        tokio::select! {
+            _ = stop.wait_for(|stop| *stop) => return Ok(()),
+            _ = tokio::time::sleep(Duration::from_millis(args.persistent.restart_delay)) => {}
+        }
+    }
+}
+
+/// Drive `work` to completion, flagging `stop` when SIGTERM arrives so it
+/// can wind down.
+async fn until_sigterm<F: Future>(
+    work: F,
+    sigterm: &mut Signal,
+    stop: &watch::Sender<bool>,
+) -> F::Output {
+    tokio::pin!(work);
+    tokio::select! {
+        output = &mut work => output,
+        _ = sigterm.recv() => {
+            let _ = stop.send(true);
+            work.await
+        }
+    }
+}
+
+/// Run the primitive with `args` as its command line (the first item is
+/// the program name, as with `std::env::args_os`).
+pub async fn run<I, T>(args: I) -> Result<(), Box<dyn std::error::Error>>
+where
+    I: IntoIterator<Item = T>,
+    T: Into<std::ffi::OsString> + Clone,
+{
+    let args = Args::parse_from(args);
+
+    // Get the source name from environment (set by engine) or use default
+    let name = std::env::var("EMERGENT_NAME").unwrap_or_else(|_| "exec-source".to_string());
+
+    let specs = args.specs();
+    if args.self_test {
+        self_test(&args, &specs, &name);
+    }
+
+    // Apply --emit-type-map / --emit-type-template
+    let specs = match specs.and_then(|specs| {
+        args.source.validate(TEMPLATE_VARIABLES)?;
+        if !args.output.is_text() && specs.iter().any(|s| s.parser != OutputParser::Text) {
+            return Err("json and jsonl parsers need --output-encoding utf8".to_string());
+        }
+        Ok(specs)
+    }) {
+        Ok(specs) => specs,
+        Err(e) => {
+            eprintln!("Error: {e}");
+            std::process::exit(1);
+        }
+    };
+    let jobs: Vec<Job> = specs
+        .into_iter()
+        .map(|spec| {
+            // Resolve publish types from EMERGENT_PUBLISHES env var or use defaults;
+            // commands from a file publish under their own prefix
+            let types = match args.commands {
+                Some(_) => spec.event_types(),
+                None => exec_common::resolve_publish_types_from_env(&[
+                    "exec.output",
+                    "exec.error",
+                    "exec.exit",
+                ]),
+            };
+            Job { spec, types }
+        })
+        .collect();
+
+    // Commands are known up front, so templated types are listed as rendered
+    let mut produces: Vec<String> = jobs
+        .iter()
+        .flat_map(|job| {
+            let vars = [
+                ("source", name.as_str()),
+                ("command", job.spec.command.as_str()),
+            ];
+            job.types
+                .iter()
+                .map(|t| args.source.topics.emit_type(t, &vars))
+                .collect::<Vec<_>>()
+        })
+        .collect();
+    if args.source.errors.emit_errors {
+        produces.push(ERROR_EVENT_TYPE.to_string());
+    }
+    if args.source.spool.spool_dir.is_some() {
+        produces.push(SPOOL_EVENT_TYPE.to_string());
+    }
+    let produces: Vec<&str> = produces.iter().map(String::as_str).collect();
+    let descriptor = args
+        .capabilities
+        .describe(&name, Role::Source, &[], &produces, &args);
+    if args.capabilities.capabilities {
+        capabilities::print(&descriptor);
+    }
+
+    // Connect to the Emergent engine (silently - lifecycle events come from engine)
+    let source = match EmergentSource::connect(&name).await {
+        Ok(s) => s,
+        Err(e) => {
+            eprintln!("Failed to connect to Emergent engine: {e}");
+            std::process::exit(1);
+        }
+    };
+    let outlet = Outlet::new(source, &name, &args.source)?;
+    if args.capabilities.announce {
+        outlet.announce(capabilities_message(&descriptor)).await;
+    }
+
+    // Set up SIGTERM handler for graceful shutdown
+    let mut sigterm = signal(SignalKind::terminate())?;
+    let (stop_tx, stop) = watch::channel(false);
+
+    let result = if args.persistent.persistent {
+        outlet.drain_spool().await;
+        let work = run_persistent(&args, &outlet, &jobs[0], stop);
+        until_sigterm(work, &mut sigterm, &stop_tx).await
+    } else {
+        let runs = jobs
+            .iter()
+            .map(|job| run_job(&args, job, &outlet, stop.clone()));
+        let results = until_sigterm(futures::future::join_all(runs), &mut sigterm, &stop_tx).await;
+        // With --commands a failed command does not fail the others or the process
+        match args.commands {
+            Some(_) => Ok(()),
+            None => results.into_iter().collect(),
+        }
+    };
+    outlet.disconnect().await;
+
+    result
+}
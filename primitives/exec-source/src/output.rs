@@ -0,0 +1,157 @@
+//! Capturing stdout and stderr into payload fields.
+//!
+//! By default output is decoded as UTF-8 (invalid sequences become
+//! U+FFFD), which mangles binary data. `--output-encoding base64` or `hex`
+//! publishes the raw bytes instead and sets the payload's `encoding` field.
+//! `--max-output-bytes` caps what is published; longer output is cut and
+//! marked with `truncated: true` and its full size in `total_bytes`. UTF-8
+//! output is cut on a character boundary.
+
+use base64::{Engine, engine::general_purpose::STANDARD};
+use clap::{Args, ValueEnum};
+
+/// How captured bytes are written into the payload.
+#[derive(ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OutputEncoding {
+    /// Text, with invalid UTF-8 replaced.
+    #[default]
+    Utf8,
+    /// Raw bytes, base64 encoded.
+    Base64,
+    /// Raw bytes, hex encoded.
+    Hex,
+}
+
+/// CLI flags controlling how output is captured.
+#[derive(Args, Debug, Clone, Default)]
+pub struct OutputArgs {
+    /// Encoding of stdout/stderr in payloads; use base64 or hex for binary output.
+    #[arg(
+        long,
+        env = "EXEC_SOURCE_OUTPUT_ENCODING",
+        value_enum,
+        default_value_t = OutputEncoding::Utf8,
+        conflicts_with = "persistent"
+    )]
+    pub output_encoding: OutputEncoding,
+
+    /// Publish at most this many bytes of stdout and of stderr; the rest is dropped and the payload marked truncated.
+    #[arg(
+        long,
+        env = "EXEC_SOURCE_MAX_OUTPUT_BYTES",
+        conflicts_with = "persistent"
+    )]
+    pub max_output_bytes: Option<usize>,
+}
+
+/// Output ready to go into a payload.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Captured {
+    pub text: String,
+    /// `base64` or `hex`; `None` for UTF-8 text.
+    pub encoding: Option<String>,
+    pub truncated: Option<bool>,
+    /// Size of the full output, set when it was truncated.
+    pub total_bytes: Option<u64>,
+}
+
+impl OutputArgs {
+    /// Whether output reaches payloads as text that parsers can read.
+    pub fn is_text(&self) -> bool {
+        self.output_encoding == OutputEncoding::Utf8
+    }
+
+    /// Encode (and cap) `bytes`; `None` when there is nothing worth
+    /// publishing (empty, or only whitespace for UTF-8).
+    pub fn capture(&self, bytes: &[u8]) -> Option<Captured> {
+        let mut kept = match self.max_output_bytes {
+            Some(max) if bytes.len() > max => &bytes[..max],
+            _ => bytes,
+        };
+        let (text, encoding) = match self.output_encoding {
+            OutputEncoding::Utf8 => {
+                // Don't leave half a character at the cut
+                if kept.len() < bytes.len()
+                    && let Err(e) = std::str::from_utf8(kept)
+                    && e.error_len().is_none()
+                {
+                    kept = &kept[..e.valid_up_to()];
+                }
+                let text = String::from_utf8_lossy(kept).into_owned();
+                if text.trim().is_empty() {
+                    return None;
+                }
+                (text, None)
+            }
+            OutputEncoding::Base64 if !bytes.is_empty() => {
+                (STANDARD.encode(kept), Some("base64".to_string()))
+            }
+            OutputEncoding::Hex if !bytes.is_empty() => {
+                (hex::encode(kept), Some("hex".to_string()))
+            }
+            OutputEncoding::Base64 | OutputEncoding::Hex => return None,
+        };
+        let truncated = kept.len() < bytes.len();
+        Some(Captured {
+            text,
+            encoding,
+            truncated: truncated.then_some(true),
+            total_bytes: truncated.then_some(bytes.len() as u64),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(output_encoding: OutputEncoding, max_output_bytes: Option<usize>) -> OutputArgs {
+        OutputArgs {
+            output_encoding,
+            max_output_bytes,
+        }
+    }
+
+    #[test]
+    fn binary_output_survives_encoding() {
+        let png = [0x89, b'P', b'N', b'G', 0x00, 0xff];
+        let base64 = args(OutputEncoding::Base64, None).capture(&png);
+        assert_eq!(
+            base64.map(|c| (c.text, c.encoding)),
+            Some(("iVBORwD/".to_string(), Some("base64".to_string())))
+        );
+        let hex = args(OutputEncoding::Hex, None).capture(&png);
+        assert_eq!(hex.map(|c| c.text), Some("89504e4700ff".to_string()));
+        assert_eq!(args(OutputEncoding::Hex, None).capture(b""), None);
+    }
+
+    #[test]
+    fn oversized_output_is_truncated_with_metadata() {
+        let captured = args(OutputEncoding::Hex, Some(2)).capture(b"abcd");
+        assert_eq!(
+            captured,
+            Some(Captured {
+                text: "6162".to_string(),
+                encoding: Some("hex".to_string()),
+                truncated: Some(true),
+                total_bytes: Some(4),
+            })
+        );
+        let whole = args(OutputEncoding::Utf8, Some(4)).capture(b"abcd");
+        assert_eq!(
+            whole.map(|c| (c.truncated, c.total_bytes)),
+            Some((None, None))
+        );
+    }
+
+    #[test]
+    fn utf8_truncation_respects_character_boundaries() {
+        // "héllo": the cut at 2 bytes falls inside "é"
+        let captured = args(OutputEncoding::Utf8, Some(2)).capture("héllo".as_bytes());
+        assert_eq!(
+            captured.map(|c| (c.text, c.total_bytes)),
+            Some(("h".to_string(), Some(6)))
+        );
+        assert_eq!(args(OutputEncoding::Utf8, None).capture(b" \n"), None);
+    }
+}
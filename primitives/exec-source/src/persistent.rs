@@ -0,0 +1,181 @@
+//! Keeping one worker process running (`--persistent`).
+//!
+//! Instead of spawning the command on every tick, the command is started
+//! once and fed a trigger line on its stdin each `--interval`:
+//!
+//! ```text
+//! {"seq":1,"timestamp":1718000000000}
+//! ```
+//!
+//! Every line the worker writes to stdout must be a JSON value and is
+//! published as the payload of one `exec.output` event; other lines are
+//! logged and skipped. The worker's stderr passes through to ours. When the
+//! worker exits, an `exec.exit` event is published and it is started again
+//! after `--restart-delay`. With `--interval 0` no triggers are written and
+//! the worker emits on its own schedule.
+//!
+//! Triggers are written without waiting on the worker, so one that stops
+//! reading stdin does not hold up publishing; once its stdin pipe is full,
+//! further triggers are dropped until it catches up.
+
+use clap::Args;
+use serde_json::{Value, json};
+use std::{
+    io,
+    process::{ExitStatus, Stdio},
+    time::{SystemTime, UNIX_EPOCH},
+};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader, Lines},
+    process::{Child, ChildStdout, Command},
+    sync::mpsc::{self, error::TrySendError},
+};
+
+/// CLI flags for persistent mode.
+#[derive(Args, Debug, Clone)]
+pub struct PersistentArgs {
+    /// Keep the command running, write a trigger line to its stdin each interval and publish each JSON line it prints.
+    #[arg(long, env = "EXEC_SOURCE_PERSISTENT", conflicts_with = "pty")]
+    pub persistent: bool,
+
+    /// Milliseconds to wait before restarting a persistent worker that exited.
+    #[arg(
+        long,
+        env = "EXEC_SOURCE_RESTART_DELAY",
+        default_value = "1000",
+        requires = "persistent"
+    )]
+    pub restart_delay: u64,
+}
+
+/// A running worker process.
+pub struct Worker {
+    child: Child,
+    triggers: Option<mpsc::Sender<String>>,
+    lines: Lines<BufReader<ChildStdout>>,
+}
+
+impl Worker {
+    /// Start `cmd` with piped stdin and stdout.
+    pub fn spawn(mut cmd: Command) -> io::Result<Self> {
+        cmd.stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::inherit());
+        let mut child = cmd.spawn()?;
+        let (Some(mut stdin), Some(stdout)) = (child.stdin.take(), child.stdout.take()) else {
+            return Err(io::Error::other("worker pipes unavailable"));
+        };
+
+        // Writes happen off the read loop so a worker that stops reading
+        // stdin cannot stall the publishing of what it prints
+        let (triggers, mut pending) = mpsc::channel::<String>(1);
+        tokio::spawn(async move {
+            while let Some(line) = pending.recv().await {
+                if stdin.write_all(line.as_bytes()).await.is_err() || stdin.flush().await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        Ok(Self {
+            child,
+            triggers: Some(triggers),
+            lines: BufReader::new(stdout).lines(),
+        })
+    }
+
+    /// Queue trigger `seq` for the worker. Returns `false` if the worker
+    /// is not keeping up with its stdin (or has closed it), in which case
+    /// the trigger is dropped.
+    pub fn trigger(&self, seq: u64) -> bool {
+        let Some(triggers) = &self.triggers else {
+            return false;
+        };
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or(0);
+        let line = format!("{}\n", json!({ "seq": seq, "timestamp": timestamp }));
+        match triggers.try_send(line) {
+            Ok(()) => true,
+            Err(TrySendError::Full(_) | TrySendError::Closed(_)) => false,
+        }
+    }
+
+    /// The next line the worker prints, or `None` once its stdout closes.
+    pub async fn next_line(&mut self) -> io::Result<Option<String>> {
+        self.lines.next_line().await
+    }
+
+    /// Close the worker's stdin, asking it to finish.
+    pub fn close_stdin(&mut self) {
+        self.triggers = None;
+    }
+
+    /// Wait for the worker to exit.
+    pub async fn wait(&mut self) -> io::Result<ExitStatus> {
+        self.child.wait().await
+    }
+}
+
+/// Parse one line of worker output into an event payload.
+pub fn parse_line(line: &str) -> Result<Value, String> {
+    serde_json::from_str(line).map_err(|e| format!("worker printed a non-JSON line ({e}): {line}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn echo_worker() -> Worker {
+        let mut cmd = Command::new("sh");
+        cmd.arg("-c")
+            .arg("while read line; do echo \"$line\"; done")
+            .kill_on_drop(true);
+        Worker::spawn(cmd).unwrap_or_else(|e| panic!("spawn: {e}"))
+    }
+
+    #[tokio::test]
+    async fn triggers_reach_the_worker_and_lines_come_back() {
+        let mut worker = echo_worker();
+        assert!(worker.trigger(1));
+        let line = worker
+            .next_line()
+            .await
+            .unwrap_or_else(|e| panic!("read: {e}"))
+            .unwrap_or_else(|| panic!("worker exited early"));
+        let payload = parse_line(&line).unwrap_or_else(|e| panic!("parse: {e}"));
+        assert_eq!(payload["seq"], 1);
+        assert!(payload["timestamp"].as_u64().is_some_and(|t| t > 0));
+
+        worker.close_stdin();
+        assert!(!worker.trigger(2));
+        let rest = worker
+            .next_line()
+            .await
+            .unwrap_or_else(|e| panic!("read: {e}"));
+        assert_eq!(rest, None);
+        let status = worker.wait().await.unwrap_or_else(|e| panic!("wait: {e}"));
+        assert!(status.success());
+    }
+
+    #[tokio::test]
+    async fn stalled_workers_drop_triggers() {
+        let mut cmd = Command::new("sh");
+        cmd.arg("-c").arg("sleep 5").kill_on_drop(true);
+        let worker = Worker::spawn(cmd).unwrap_or_else(|e| panic!("spawn: {e}"));
+        let mut queued = 0;
+        while worker.trigger(queued) {
+            queued += 1;
+            assert!(queued < 100_000, "stdin pipe never filled");
+            tokio::task::yield_now().await;
+        }
+        assert!(queued > 0);
+    }
+
+    #[test]
+    fn non_json_lines_are_rejected() {
+        assert_eq!(parse_line(r#"{"cpu": 0.4}"#), Ok(json!({"cpu": 0.4})));
+        assert!(parse_line("Loading model...").is_err());
+    }
+}
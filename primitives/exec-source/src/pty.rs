@@ -0,0 +1,195 @@
+//! Running the command under a pseudo-terminal (`--pty`).
+//!
+//! Programs that check `isatty` (colored output, progress bars, prompts)
+//! behave as they would in a terminal. The terminal merges stdout and
+//! stderr into one stream, published as `exec.output`; no `exec.error` is
+//! emitted. ANSI escape sequences are stripped unless `--keep-ansi` is
+//! given, and the terminal's `\r\n` line endings are normalised to `\n`.
+//!
+//! The child gets the terminal as stdin/stdout/stderr but not as its
+//! controlling terminal, so programs that open `/dev/tty` directly still
+//! fail.
+
+use clap::Args;
+use nix::pty::{Winsize, openpty};
+use std::{io, process::Output, process::Stdio};
+use tokio::{io::AsyncReadExt, process::Command};
+
+/// CLI flags for PTY mode.
+#[derive(Args, Debug, Clone)]
+pub struct PtyArgs {
+    /// Run the command under a pseudo-terminal; stdout and stderr arrive merged in `exec.output`.
+    #[arg(long, env = "EXEC_SOURCE_PTY")]
+    pub pty: bool,
+
+    /// Keep ANSI escape sequences (colors, cursor movement) in PTY output.
+    #[arg(long, env = "EXEC_SOURCE_KEEP_ANSI", requires = "pty")]
+    pub keep_ansi: bool,
+
+    /// Terminal size as COLUMNSxROWS.
+    #[arg(long, env = "EXEC_SOURCE_PTY_SIZE", default_value = "80x24", value_parser = parse_size)]
+    pub pty_size: PtySize,
+}
+
+/// Terminal dimensions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PtySize {
+    pub cols: u16,
+    pub rows: u16,
+}
+
+impl PtySize {
+    fn winsize(self) -> Winsize {
+        Winsize {
+            ws_row: self.rows,
+            ws_col: self.cols,
+            ws_xpixel: 0,
+            ws_ypixel: 0,
+        }
+    }
+}
+
+/// Parse a `--pty-size` value such as `120x40`.
+fn parse_size(s: &str) -> Result<PtySize, String> {
+    let invalid = || format!("expected COLUMNSxROWS, got '{s}'");
+    let (cols, rows) = s.split_once('x').ok_or_else(invalid)?;
+    let cols: u16 = cols.trim().parse().map_err(|_| invalid())?;
+    let rows: u16 = rows.trim().parse().map_err(|_| invalid())?;
+    if cols == 0 || rows == 0 {
+        return Err(invalid());
+    }
+    Ok(PtySize { cols, rows })
+}
+
+/// Run `cmd` to completion under a new pseudo-terminal and collect its
+/// output. `stderr` of the result is always empty.
+pub async fn output(mut cmd: Command, args: &PtyArgs) -> io::Result<Output> {
+    let pty = openpty(Some(&args.pty_size.winsize()), None).map_err(io::Error::from)?;
+    cmd.stdin(Stdio::from(pty.slave.try_clone()?))
+        .stdout(Stdio::from(pty.slave.try_clone()?))
+        .stderr(Stdio::from(pty.slave))
+        .env("COLUMNS", args.pty_size.cols.to_string())
+        .env("LINES", args.pty_size.rows.to_string());
+    if std::env::var_os("TERM").is_none() {
+        cmd.env("TERM", "xterm-256color");
+    }
+    let mut child = cmd.spawn()?;
+    // Release our copies of the terminal so reads end when the child exits
+    drop(cmd);
+
+    let mut master = tokio::fs::File::from_std(std::fs::File::from(pty.master));
+    let mut raw = Vec::new();
+    match master.read_to_end(&mut raw).await {
+        Ok(_) => {}
+        // Linux reports a closed terminal as EIO rather than end of file
+        Err(e) if e.raw_os_error() == Some(nix::libc::EIO) => {}
+        Err(e) => return Err(e),
+    }
+    let status = child.wait().await?;
+
+    let text = String::from_utf8_lossy(&raw).replace("\r\n", "\n");
+    let stdout = if args.keep_ansi {
+        text
+    } else {
+        strip_ansi(&text)
+    };
+    Ok(Output {
+        status,
+        stdout: stdout.into_bytes(),
+        stderr: Vec::new(),
+    })
+}
+
+/// Remove ANSI escape sequences: CSI (`ESC [ ... final`), OSC
+/// (`ESC ] ... BEL` or `ESC ] ... ESC \`), and the short `ESC x` forms.
+pub fn strip_ansi(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '\u{1b}' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('[') => {
+                // Parameters and intermediates, then one final byte in @..~
+                for c in chars.by_ref() {
+                    if ('@'..='~').contains(&c) {
+                        break;
+                    }
+                }
+            }
+            Some(']') => {
+                while let Some(c) = chars.next() {
+                    if c == '\u{7}' {
+                        break;
+                    }
+                    if c == '\u{1b}' && chars.peek() == Some(&'\\') {
+                        chars.next();
+                        break;
+                    }
+                }
+            }
+            // Character set selection and similar: intermediates, then a final byte
+            Some(c) if (' '..='/').contains(&c) => {
+                for c in chars.by_ref() {
+                    if !(' '..='/').contains(&c) {
+                        break;
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escape_sequences_are_stripped() {
+        let colored = "\u{1b}[1;31merror\u{1b}[0m: disk \u{1b}[Kfull";
+        assert_eq!(strip_ansi(colored), "error: disk full");
+        let titled = "\u{1b}]0;build\u{7}done \u{1b}]8;;http://x\u{1b}\\link";
+        assert_eq!(strip_ansi(titled), "done link");
+        assert_eq!(strip_ansi("\u{1b}(Bplain\u{1b}="), "plain");
+    }
+
+    #[test]
+    fn sizes_parse() {
+        let size = parse_size("120x40").unwrap_or_else(|e| panic!("size: {e}"));
+        assert_eq!(
+            size,
+            PtySize {
+                cols: 120,
+                rows: 40
+            }
+        );
+        assert!(parse_size("120").is_err());
+        assert!(parse_size("0x40").is_err());
+    }
+
+    #[tokio::test]
+    async fn commands_see_a_terminal() {
+        let args = PtyArgs {
+            pty: true,
+            keep_ansi: false,
+            pty_size: parse_size("100x30").unwrap_or_else(|e| panic!("size: {e}")),
+        };
+        let mut cmd = Command::new("sh");
+        cmd.arg("-c")
+            .arg("[ -t 1 ] && printf '\\033[32mtty\\033[0m\\n'; stty size; echo oops >&2");
+        let output = output(cmd, &args)
+            .await
+            .unwrap_or_else(|e| panic!("run: {e}"));
+
+        assert!(output.status.success());
+        assert_eq!(
+            String::from_utf8_lossy(&output.stdout),
+            "tty\n30 100\noops\n"
+        );
+        assert!(output.stderr.is_empty());
+    }
+}
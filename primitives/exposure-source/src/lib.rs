@@ -0,0 +1,490 @@
+//! Exposure Source - Open Port Transitions
+//!
+//! A Source that periodically TCP connect-scans configured hosts and
+//! networks and publishes `exposure.port_opened` and
+//! `exposure.port_closed` when what is open changes compared with the
+//! stored baseline (see [`watch`]), carrying the host, address, port and
+//! usual service - for watching an attack surface.
+//!
+//! Targets are host names, addresses or CIDR networks (see [`targets`]);
+//! host names are resolved on every scan and their first address scanned.
+//! Scans start at most `--rate` connections per second with at most
+//! `--concurrency` in flight (see [`scan`]). With `--state-file` the
+//! baseline survives restarts.
+//!
+//! Sources are SILENT - they only produce domain messages.
+//! All lifecycle events are published by the engine.
+//!
+//! # Usage
+//!
+//! ```bash
+//! exposure-source --target 203.0.113.0/28 --target www.example.com \
+//!   --ports default,8000-8100 --interval 3600000 --rate 100 \
+//!   --state-file /var/lib/emergent/exposure.json
+//! ```
+//!
+//! Only scan hosts and networks you are permitted to. On SIGTERM a scan
+//! in progress gets `--drain-timeout` milliseconds to finish.
+
+pub mod scan;
+pub mod targets;
+pub mod watch;
+
+use clap::Parser;
+use emergent_client::EmergentSource;
+use primitive_common::capabilities::{self, CapabilityArgs, Role, VERSION, capabilities_message};
+use primitive_common::doctor::{Report, check_writable_dir};
+use primitive_common::source::{Outlet, SourceArgs};
+use scan::{Limits, Probe};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use targets::Target;
+use tokio::signal::unix::{SignalKind, signal};
+use watch::{Baseline, EVENT_TYPES};
+
+/// Port exposure monitor that emits exposure.* events.
+#[derive(Parser, Debug, Clone)]
+#[command(name = "exposure-source", version = VERSION)]
+#[command(about = "Scans hosts for open ports and emits events when they open or close")]
+struct Args {
+    /// Host name, address or CIDR network to scan (repeatable or
+    /// comma-separated).
+    #[arg(
+        long = "target",
+        env = "EXPOSURE_SOURCE_TARGETS",
+        value_delimiter = ',',
+        value_parser = Target::parse,
+        required = true
+    )]
+    targets: Vec<Target>,
+
+    /// Ports to scan, e.g. `22,443,8000-8100`; `default` is a list of
+    /// well-known service ports (comma-separated, mixable).
+    #[arg(long, env = "EXPOSURE_SOURCE_PORTS", default_value = "default")]
+    ports: String,
+
+    /// Milliseconds between scans.
+    #[arg(
+        short,
+        long,
+        env = "EXPOSURE_SOURCE_INTERVAL",
+        default_value = "3600000"
+    )]
+    interval: u64,
+
+    /// Connection attempts started per second at most.
+    #[arg(long, env = "EXPOSURE_SOURCE_RATE", default_value = "200")]
+    rate: u32,
+
+    /// Connection attempts in flight at most.
+    #[arg(long, env = "EXPOSURE_SOURCE_CONCURRENCY", default_value = "100")]
+    concurrency: usize,
+
+    /// Milliseconds a connection may take before the port counts as closed.
+    #[arg(long, env = "EXPOSURE_SOURCE_CONNECT_TIMEOUT", default_value = "1000")]
+    connect_timeout: u64,
+
+    /// Scans in a row an open port must be found closed in before it is
+    /// reported closed.
+    #[arg(long, env = "EXPOSURE_SOURCE_CLOSE_AFTER", default_value = "2")]
+    close_after: u32,
+
+    /// Most addresses in all networks together.
+    #[arg(long, env = "EXPOSURE_SOURCE_MAX_HOSTS", default_value = "4096")]
+    max_hosts: u128,
+
+    /// File to keep the baseline in across restarts.
+    #[arg(long, env = "EXPOSURE_SOURCE_STATE_FILE")]
+    state_file: Option<PathBuf>,
+
+    /// Verify external dependencies and exit without connecting to the engine.
+    #[arg(long)]
+    self_test: bool,
+
+    #[command(flatten)]
+    source: SourceArgs,
+
+    #[command(flatten)]
+    capabilities: CapabilityArgs,
+}
+
+/// Variables available to `--emit-type-template`.
+const TEMPLATE_VARIABLES: &[&str] = &["source"];
+
+/// The ports `args` asks for, after expanding `default`.
+fn ports(args: &Args) -> Result<Vec<u16>, String> {
+    let mut ports = Vec::new();
+    for part in args.ports.split(',') {
+        ports.extend(targets::parse_ports(part)?);
+    }
+    ports.sort_unstable();
+    ports.dedup();
+    Ok(ports)
+}
+
+/// Check that the networks fit within `--max-hosts`.
+fn check_size(args: &Args) -> Result<u128, String> {
+    let size = args
+        .targets
+        .iter()
+        .filter_map(Target::size)
+        .fold(0u128, u128::saturating_add);
+    if size > args.max_hosts {
+        return Err(format!(
+            "networks hold {size} addresses, more than --max-hosts {}",
+            args.max_hosts
+        ));
+    }
+    Ok(size)
+}
+
+/// Every port of every target; host names that do not resolve are
+/// reported and left out.
+async fn probes(targets: &[Target], ports: &[u16]) -> Vec<Probe> {
+    let mut hosts = Vec::new();
+    for target in targets {
+        match target {
+            Target::Host(name) => {
+                let address = match name.parse() {
+                    Ok(address) => Some(address),
+                    Err(_) => tokio::net::lookup_host((name.as_str(), 0))
+                        .await
+                        .map_err(|e| eprintln!("Cannot resolve {name}: {e}"))
+                        .ok()
+                        .and_then(|mut found| found.next())
+                        .map(|found| found.ip()),
+                };
+                hosts.extend(address.map(|address| (name.clone(), address)));
+            }
+            Target::Network(..) => hosts.extend(
+                target
+                    .addresses()
+                    .into_iter()
+                    .map(|address| (address.to_string(), address)),
+            ),
+        }
+    }
+    hosts
+        .into_iter()
+        .flat_map(|(host, address)| {
+            ports.iter().map(move |port| Probe {
+                host: host.clone(),
+                address,
+                port: *port,
+            })
+        })
+        .collect()
+}
+
+/// The state saved in `path`, if any.
+fn load_state(path: &Path) -> Result<Option<Baseline>, String> {
+    match std::fs::read(path) {
+        Ok(bytes) => serde_json::from_slice(&bytes)
+            .map(Some)
+            .map_err(|e| format!("{}: {e}", path.display())),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(format!("{}: {e}", path.display())),
+    }
+}
+
+/// Save `baseline` to `path`, replacing it atomically.
+fn save_state(path: &Path, baseline: &Baseline) -> Result<(), String> {
+    let temp = path.with_extension("tmp");
+    let bytes = serde_json::to_vec(baseline).map_err(|e| e.to_string())?;
+    std::fs::write(&temp, bytes)
+        .and_then(|()| std::fs::rename(&temp, path))
+        .map_err(|e| format!("{}: {e}", path.display()))
+}
+
+/// Runs `--self-test` checks and exits.
+async fn self_test(args: &Args, name: &str) -> ! {
+    let mut report = Report::new(name);
+    report.check("ports", ports(args).map(|p| format!("{} ports", p.len())));
+    report.check(
+        "networks",
+        check_size(args).map(|size| format!("{size} addresses")),
+    );
+    for target in &args.targets {
+        if let Target::Host(host) = target {
+            let resolved = tokio::net::lookup_host((host.as_str(), 0))
+                .await
+                .map_err(|e| format!("{host}: {e}"))
+                .and_then(|mut found| {
+                    found
+                        .next()
+                        .map(|found| found.ip().to_string())
+                        .ok_or_else(|| format!("{host}: no addresses"))
+                });
+            report.check(&format!("resolve {host}"), resolved);
+        }
+    }
+    if let Some(path) = &args.state_file {
+        let dir = path.parent().filter(|p| !p.as_os_str().is_empty());
+        report.check(
+            "state_file",
+            check_writable_dir(dir.unwrap_or(Path::new("."))),
+        );
+    }
+    args.source.self_test(&mut report, TEMPLATE_VARIABLES);
+    report.finish()
+}
+
+/// Scan once and publish the transitions; those that are lost are undone,
+/// so the next scan reports them again.
+async fn scan_once(args: &Args, outlet: &Outlet, ports: &[u16], baseline: &mut Baseline) {
+    let limits = Limits {
+        rate: args.rate,
+        concurrency: args.concurrency,
+        timeout: Duration::from_millis(args.connect_timeout),
+    };
+    let probed = probes(&args.targets, ports).await;
+    let open = scan::scan(probed.clone(), limits).await;
+    let first = baseline.clone() == Baseline::default();
+    let transitions = baseline.apply(&probed, &open, args.close_after);
+    if first {
+        eprintln!("Baseline recorded: {} open ports", open.len());
+    }
+    for transition in &transitions {
+        // The outlet logs and reports what it cannot deliver
+        if outlet
+            .publish(transition.event_type, &[], transition.payload.clone())
+            .await
+            .is_err()
+        {
+            baseline.undo(transition, args.close_after);
+        }
+    }
+}
+
+/// Scan until SIGTERM.
+async fn run_scanner(
+    args: &Args,
+    outlet: &Outlet,
+    ports: &[u16],
+    mut baseline: Baseline,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut sigterm = signal(SignalKind::terminate())?;
+    let mut interval = tokio::time::interval(Duration::from_millis(args.interval));
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+    loop {
+        tokio::select! {
+            _ = sigterm.recv() => break,
+
+            _ = interval.tick() => {
+                // Whether SIGTERM arrived, once the scan finished; an
+                // abandoned scan leaves the saved baseline as it was
+                let stopping = {
+                    let scan = scan_once(args, outlet, ports, &mut baseline);
+                    tokio::pin!(scan);
+                    tokio::select! {
+                        () = &mut scan => Some(false),
+                        _ = sigterm.recv() => {
+                            args.source.drain.drain("scan in progress", &mut scan).await.map(|()| true)
+                        }
+                    }
+                };
+                let Some(stopping) = stopping else {
+                    break;
+                };
+                if let Some(path) = &args.state_file
+                    && let Err(e) = save_state(path, &baseline)
+                {
+                    eprintln!("Failed to save state: {e}");
+                }
+                if stopping {
+                    break;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Run the primitive with `args` as its command line (the first item is
+/// the program name, as with `std::env::args_os`).
+pub async fn run<I, T>(args: I) -> Result<(), Box<dyn std::error::Error>>
+where
+    I: IntoIterator<Item = T>,
+    T: Into<std::ffi::OsString> + Clone,
+{
+    let args = Args::parse_from(args);
+
+    // Get the source name from environment (set by engine) or use default
+    let name = std::env::var("EMERGENT_NAME").unwrap_or_else(|_| "exposure-source".to_string());
+
+    if args.self_test {
+        self_test(&args, &name).await;
+    }
+    let ports = match ports(&args)
+        .and_then(|ports| check_size(&args).map(|_| ports))
+        .and_then(|ports| args.source.validate(TEMPLATE_VARIABLES).map(|()| ports))
+    {
+        Ok(ports) => ports,
+        Err(e) => {
+            eprintln!("Error: {e}");
+            std::process::exit(1);
+        }
+    };
+    let baseline = match args.state_file.as_deref().map(load_state).transpose() {
+        Ok(state) => state.flatten().unwrap_or_default(),
+        Err(e) => {
+            eprintln!("Error: cannot read state file {e}");
+            std::process::exit(1);
+        }
+    };
+
+    let produces = args.source.produces(&EVENT_TYPES);
+    let produces: Vec<&str> = produces.iter().map(String::as_str).collect();
+    let descriptor = args
+        .capabilities
+        .describe(&name, Role::Source, &[], &produces, &args);
+    if args.capabilities.capabilities {
+        capabilities::print(&descriptor);
+    }
+
+    // Connect to the Emergent engine (silently - lifecycle events come from engine)
+    let source = match EmergentSource::connect(&name).await {
+        Ok(s) => s,
+        Err(e) => {
+            eprintln!("Failed to connect to Emergent engine: {e}");
+            std::process::exit(1);
+        }
+    };
+    let outlet = Arc::new(Outlet::new(source, &name, &args.source)?);
+    if args.capabilities.announce {
+        outlet.announce(capabilities_message(&descriptor)).await;
+    }
+    tokio::spawn(Arc::clone(&outlet).drain_every(args.source.spool.retry_interval()));
+
+    run_scanner(&args, &outlet, &ports, baseline).await?;
+    outlet.disconnect().await;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use emergent_testkit::{MockEngine, fixtures};
+
+    /// Connect to the engine on `socket` as the source would.
+    async fn connect(socket: &Path, args: &Args) -> Outlet {
+        let source = EmergentSource::connect_to("exposure-source", socket)
+            .await
+            .unwrap_or_else(|e| panic!("connect: {e}"));
+        Outlet::new(source, "exposure-source", &args.source)
+            .unwrap_or_else(|e| panic!("open spool: {e}"))
+    }
+
+    /// Record a baseline with a local port open, then close it and scan
+    /// again with the engine on `socket` shut down. Returns the port.
+    async fn close_while_down(socket: &Path, args: &Args, baseline: &mut Baseline) -> u16 {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap_or_else(|e| panic!("bind: {e}"));
+        let port = listener
+            .local_addr()
+            .unwrap_or_else(|e| panic!("local_addr: {e}"))
+            .port();
+        let mut engine = MockEngine::serve(socket);
+        let outlet = connect(socket, args).await;
+        scan_once(args, &outlet, &[port], baseline).await;
+        drop(listener);
+        engine.shut_down().await;
+        scan_once(args, &outlet, &[port], baseline).await;
+        port
+    }
+
+    fn local_args(extra: &[&str]) -> Args {
+        let mut argv = vec![
+            "exposure-source",
+            "--target",
+            "127.0.0.1",
+            "--close-after",
+            "1",
+        ];
+        argv.extend_from_slice(extra);
+        Args::parse_from(argv)
+    }
+
+    #[tokio::test]
+    async fn transitions_survive_the_engine_being_down() {
+        let dir = fixtures::TempDir::new("exposure-source-spool");
+        let socket = dir.path().join("engine.sock");
+        let spool = dir.path().join("spool");
+        let args = local_args(&["--spool-dir", spool.to_str().unwrap_or_default()]);
+
+        let port = close_while_down(&socket, &args, &mut Baseline::default()).await;
+
+        let mut engine = MockEngine::serve(&socket);
+        connect(&socket, &args).await.drain_spool().await;
+        let published = engine.expect_published("exposure.port_closed").await;
+        assert_eq!(published.payload()["port"], port);
+        engine.shut_down().await;
+    }
+
+    #[tokio::test]
+    async fn unpublished_transitions_are_reported_again() {
+        let dir = fixtures::TempDir::new("exposure-source-down");
+        let socket = dir.path().join("engine.sock");
+        let args = local_args(&[]);
+        let mut baseline = Baseline::default();
+
+        let port = close_while_down(&socket, &args, &mut baseline).await;
+
+        let mut engine = MockEngine::serve(&socket);
+        let outlet = connect(&socket, &args).await;
+        scan_once(&args, &outlet, &[port], &mut baseline).await;
+        let published = engine.expect_published("exposure.port_closed").await;
+        assert_eq!(published.payload()["port"], port);
+        engine.shut_down().await;
+    }
+
+    #[tokio::test]
+    async fn targets_expand_to_probes() {
+        let args = Args::parse_from([
+            "exposure-source",
+            "--target",
+            "127.0.0.1,10.0.0.0/30",
+            "--ports",
+            "22,8000-8001",
+        ]);
+        assert_eq!(ports(&args), Ok(vec![22, 8000, 8001]));
+        assert_eq!(check_size(&args), Ok(4));
+        let keys: Vec<String> = probes(&args.targets, &[22])
+            .await
+            .iter()
+            .map(Probe::key)
+            .collect();
+        assert_eq!(keys, ["127.0.0.1:22", "10.0.0.1:22", "10.0.0.2:22"]);
+
+        let wide = Args::parse_from(["exposure-source", "--target", "10.0.0.0/8"]);
+        assert!(check_size(&wide).is_err());
+        assert!(ports(&wide).is_ok_and(|p| p.contains(&22)));
+    }
+
+    #[test]
+    fn state_survives_a_round_trip() {
+        let dir = std::env::temp_dir().join(format!("exposure-source-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap_or_else(|e| panic!("mkdir: {e}"));
+        let path = dir.join("state.json");
+        assert_eq!(load_state(&path), Ok(None));
+
+        let mut baseline = Baseline::default();
+        let probe = Probe {
+            host: "db".to_string(),
+            address: std::net::IpAddr::from([10, 0, 0, 5]),
+            port: 5432,
+        };
+        baseline.apply(
+            std::slice::from_ref(&probe),
+            std::slice::from_ref(&probe),
+            2,
+        );
+        save_state(&path, &baseline).unwrap_or_else(|e| panic!("save: {e}"));
+        assert_eq!(load_state(&path), Ok(Some(baseline)));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
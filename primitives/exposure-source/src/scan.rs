@@ -0,0 +1,99 @@
+//! TCP connect scanning, bounded in rate and concurrency.
+//!
+//! A port is open if a connection to it is accepted within the timeout;
+//! refused, unreachable and timed-out connections all count as closed.
+
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::TcpStream;
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+
+/// One port of one host.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Probe {
+    /// The host as configured, or the address for networks.
+    pub host: String,
+    pub address: IpAddr,
+    pub port: u16,
+}
+
+impl Probe {
+    /// `host:port`, the key transitions are tracked under.
+    pub fn key(&self) -> String {
+        format!("{}:{}", self.host, self.port)
+    }
+}
+
+/// Limits on a scan.
+#[derive(Debug, Clone, Copy)]
+pub struct Limits {
+    /// Connection attempts started per second at most.
+    pub rate: u32,
+    /// Connection attempts in flight at most.
+    pub concurrency: usize,
+    pub timeout: Duration,
+}
+
+/// Try every probe, returning the open ones.
+pub async fn scan(probes: Vec<Probe>, limits: Limits) -> Vec<Probe> {
+    let permits = Arc::new(Semaphore::new(limits.concurrency.max(1)));
+    let mut pacing = tokio::time::interval(Duration::from_secs(1) / limits.rate.max(1));
+    pacing.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    let mut attempts = JoinSet::new();
+    for probe in probes {
+        pacing.tick().await;
+        let Ok(permit) = Arc::clone(&permits).acquire_owned().await else {
+            break;
+        };
+        attempts.spawn(async move {
+            let connect = TcpStream::connect(SocketAddr::new(probe.address, probe.port));
+            let open = matches!(
+                tokio::time::timeout(limits.timeout, connect).await,
+                Ok(Ok(_))
+            );
+            drop(permit);
+            open.then_some(probe)
+        });
+    }
+    let mut open: Vec<Probe> = attempts.join_all().await.into_iter().flatten().collect();
+    open.sort();
+    open
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn only_listening_ports_are_open() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap_or_else(|e| panic!("bind: {e}"));
+        let open_port = listener
+            .local_addr()
+            .unwrap_or_else(|e| panic!("local_addr: {e}"))
+            .port();
+        let closed_port = {
+            let closed =
+                std::net::TcpListener::bind("127.0.0.1:0").unwrap_or_else(|e| panic!("bind: {e}"));
+            closed
+                .local_addr()
+                .unwrap_or_else(|e| panic!("local_addr: {e}"))
+                .port()
+        };
+        let probe = |port| Probe {
+            host: "localhost".to_string(),
+            address: IpAddr::from([127, 0, 0, 1]),
+            port,
+        };
+        let limits = Limits {
+            rate: 1000,
+            concurrency: 4,
+            timeout: Duration::from_secs(2),
+        };
+        let open = scan(vec![probe(closed_port), probe(open_port)], limits).await;
+        assert_eq!(open, [probe(open_port)]);
+    }
+}
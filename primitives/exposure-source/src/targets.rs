@@ -0,0 +1,192 @@
+//! What is scanned: hosts and networks, and ports.
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+/// Well-known ports scanned by default, with their service names.
+const SERVICES: [(u16, &str); 36] = [
+    (21, "ftp"),
+    (22, "ssh"),
+    (23, "telnet"),
+    (25, "smtp"),
+    (53, "dns"),
+    (80, "http"),
+    (110, "pop3"),
+    (111, "rpcbind"),
+    (135, "msrpc"),
+    (139, "netbios-ssn"),
+    (143, "imap"),
+    (443, "https"),
+    (445, "microsoft-ds"),
+    (465, "smtps"),
+    (587, "submission"),
+    (993, "imaps"),
+    (995, "pop3s"),
+    (1433, "mssql"),
+    (1521, "oracle"),
+    (2049, "nfs"),
+    (2375, "docker"),
+    (2379, "etcd"),
+    (3306, "mysql"),
+    (3389, "rdp"),
+    (5432, "postgresql"),
+    (5672, "amqp"),
+    (5900, "vnc"),
+    (6379, "redis"),
+    (6443, "kubernetes"),
+    (8080, "http-alt"),
+    (8443, "https-alt"),
+    (9092, "kafka"),
+    (9200, "elasticsearch"),
+    (11211, "memcached"),
+    (27017, "mongodb"),
+    (50000, "jenkins-agent"),
+];
+
+/// The service usually found on `port`.
+pub fn service(port: u16) -> Option<&'static str> {
+    SERVICES
+        .iter()
+        .find(|(known, _)| *known == port)
+        .map(|(_, name)| *name)
+}
+
+/// Ports from a list such as `22,80,8000-8100`, or the well-known ports
+/// for `default`.
+pub fn parse_ports(spec: &str) -> Result<Vec<u16>, String> {
+    if spec.trim() == "default" {
+        return Ok(SERVICES.iter().map(|(port, _)| *port).collect());
+    }
+    let port = |s: &str| {
+        s.trim()
+            .parse::<u16>()
+            .ok()
+            .filter(|p| *p > 0)
+            .ok_or_else(|| format!("{s}: not a port"))
+    };
+    let mut ports = Vec::new();
+    for part in spec.split(',').filter(|p| !p.trim().is_empty()) {
+        match part.split_once('-') {
+            Some((from, to)) => {
+                let (from, to) = (port(from)?, port(to)?);
+                if from > to {
+                    return Err(format!("{part}: range is backwards"));
+                }
+                ports.extend(from..=to);
+            }
+            None => ports.push(port(part)?),
+        }
+    }
+    ports.sort_unstable();
+    ports.dedup();
+    if ports.is_empty() {
+        return Err("no ports".to_string());
+    }
+    Ok(ports)
+}
+
+/// A host name or address, or a network in CIDR notation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Target {
+    Host(String),
+    Network(IpAddr, u8),
+}
+
+impl Target {
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let spec = spec.trim();
+        let Some((address, prefix)) = spec.split_once('/') else {
+            if spec.is_empty() {
+                return Err("empty target".to_string());
+            }
+            return Ok(Self::Host(spec.to_string()));
+        };
+        let address: IpAddr = address.parse().map_err(|e| format!("{spec}: {e}"))?;
+        let bits = if address.is_ipv4() { 32 } else { 128 };
+        let prefix = prefix
+            .parse::<u8>()
+            .ok()
+            .filter(|p| *p <= bits)
+            .ok_or_else(|| format!("{spec}: prefix must be 0-{bits}"))?;
+        Ok(Self::Network(address, prefix))
+    }
+
+    /// Addresses a network holds (all of them, or `None` for a host).
+    pub fn size(&self) -> Option<u128> {
+        match self {
+            Self::Host(_) => None,
+            Self::Network(address, prefix) => {
+                let bits = if address.is_ipv4() { 32 } else { 128 };
+                Some(
+                    1u128
+                        .checked_shl(u32::from(bits - prefix))
+                        .unwrap_or(u128::MAX),
+                )
+            }
+        }
+    }
+
+    /// The addresses to scan in a network; an IPv4 network larger than
+    /// /31 leaves out its network and broadcast addresses. Callers bound
+    /// the size with [`size`](Self::size) first.
+    pub fn addresses(&self) -> Vec<IpAddr> {
+        match *self {
+            Self::Host(_) => Vec::new(),
+            Self::Network(IpAddr::V4(address), prefix) => {
+                let mask = u32::MAX.checked_shl(u32::from(32 - prefix)).unwrap_or(0);
+                let first = u32::from(address) & mask;
+                let last = first | !mask;
+                let (first, last) = if prefix < 31 {
+                    (first + 1, last - 1)
+                } else {
+                    (first, last)
+                };
+                (first..=last)
+                    .map(|a| IpAddr::V4(Ipv4Addr::from(a)))
+                    .collect()
+            }
+            Self::Network(IpAddr::V6(address), prefix) => {
+                let mask = u128::MAX.checked_shl(u32::from(128 - prefix)).unwrap_or(0);
+                let first = u128::from(address) & mask;
+                let last = first | !mask;
+                (first..=last)
+                    .map(|a| IpAddr::V6(Ipv6Addr::from(a)))
+                    .collect()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ports_and_targets_parse() {
+        assert_eq!(
+            parse_ports("443, 22,8000-8002,22"),
+            Ok(vec![22, 443, 8000, 8001, 8002])
+        );
+        assert!(parse_ports("default").is_ok_and(|p| p.contains(&3389)));
+        assert!(parse_ports("90-80").is_err());
+        assert!(parse_ports("0").is_err());
+        assert_eq!(service(6379), Some("redis"));
+
+        let net = Target::parse("192.168.1.77/30").unwrap_or_else(|e| panic!("{e}"));
+        assert_eq!(net.size(), Some(4));
+        let addresses: Vec<String> = net.addresses().iter().map(IpAddr::to_string).collect();
+        assert_eq!(addresses, ["192.168.1.77", "192.168.1.78"]);
+        let pair = Target::parse("10.0.0.0/31").unwrap_or_else(|e| panic!("{e}"));
+        assert_eq!(pair.addresses().len(), 2);
+        let v6 = Target::parse("2001:db8::/126").unwrap_or_else(|e| panic!("{e}"));
+        assert_eq!(v6.addresses().len(), 4);
+        assert_eq!(
+            Target::parse("db.internal"),
+            Ok(Target::Host("db.internal".to_string()))
+        );
+        assert!(Target::parse("10.0.0.0/33").is_err());
+        assert_eq!(
+            Target::parse("::/0").ok().and_then(|t| t.size()),
+            Some(u128::MAX)
+        );
+    }
+}
@@ -0,0 +1,158 @@
+//! Open ports compared with the stored baseline.
+//!
+//! [`Baseline`] holds the ports seen open and the hosts scanned before. A
+//! port newly found open is `exposure.port_opened`; an open port is
+//! `exposure.port_closed` once it has been found closed in `close_after`
+//! scans in a row, so one dropped connection does not report a flap. The
+//! first scan of a host only records what is open.
+
+use crate::scan::Probe;
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use std::collections::{BTreeMap, BTreeSet};
+
+pub const OPENED_EVENT_TYPE: &str = "exposure.port_opened";
+pub const CLOSED_EVENT_TYPE: &str = "exposure.port_closed";
+pub const EVENT_TYPES: [&str; 2] = [OPENED_EVENT_TYPE, CLOSED_EVENT_TYPE];
+
+/// A port seen open.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct Open {
+    address: String,
+    /// Scans in a row it has since been found closed in.
+    misses: u32,
+}
+
+/// Open ports by `host:port`, and the hosts scanned before.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Baseline {
+    #[serde(default)]
+    open: BTreeMap<String, Open>,
+    #[serde(default)]
+    hosts: BTreeSet<String>,
+}
+
+/// An event to publish.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Transition {
+    pub event_type: &'static str,
+    pub payload: Value,
+}
+
+fn payload(host: &str, address: &str, port: u16) -> Value {
+    json!({
+        "host": host,
+        "address": address,
+        "port": port,
+        "service": crate::targets::service(port),
+    })
+}
+
+impl Baseline {
+    /// Apply a scan of `probed` that found `open` open, returning the
+    /// transitions. Ports not probed this time are left as they were.
+    pub fn apply(&mut self, probed: &[Probe], open: &[Probe], close_after: u32) -> Vec<Transition> {
+        let open_keys: BTreeSet<String> = open.iter().map(Probe::key).collect();
+        let mut transitions = Vec::new();
+        for probe in probed {
+            let key = probe.key();
+            let known = self.hosts.contains(&probe.host);
+            if open_keys.contains(&key) {
+                let previous = self.open.insert(
+                    key,
+                    Open {
+                        address: probe.address.to_string(),
+                        misses: 0,
+                    },
+                );
+                if known && previous.is_none() {
+                    transitions.push(Transition {
+                        event_type: OPENED_EVENT_TYPE,
+                        payload: payload(&probe.host, &probe.address.to_string(), probe.port),
+                    });
+                }
+            } else if let Some(was) = self.open.get_mut(&key) {
+                was.misses += 1;
+                if was.misses >= close_after.max(1) {
+                    let address = was.address.clone();
+                    self.open.remove(&key);
+                    transitions.push(Transition {
+                        event_type: CLOSED_EVENT_TYPE,
+                        payload: payload(&probe.host, &address, probe.port),
+                    });
+                }
+            }
+        }
+        self.hosts.extend(probed.iter().map(|p| p.host.clone()));
+        transitions
+    }
+
+    /// Undo a transition that could not be published, so the next scan
+    /// finds it again.
+    pub fn undo(&mut self, transition: &Transition, close_after: u32) {
+        let payload = &transition.payload;
+        let key = format!(
+            "{}:{}",
+            payload["host"].as_str().unwrap_or_default(),
+            payload["port"]
+        );
+        if transition.event_type == OPENED_EVENT_TYPE {
+            self.open.remove(&key);
+        } else {
+            let open = Open {
+                address: payload["address"].as_str().unwrap_or_default().to_string(),
+                misses: close_after.max(1) - 1,
+            };
+            self.open.insert(key, open);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::IpAddr;
+
+    fn probe(host: &str, port: u16) -> Probe {
+        Probe {
+            host: host.to_string(),
+            address: IpAddr::from([10, 0, 0, 5]),
+            port,
+        }
+    }
+
+    #[test]
+    fn transitions_follow_the_baseline() {
+        let mut baseline = Baseline::default();
+        let probed = [probe("db", 22), probe("db", 5432)];
+        assert!(baseline.apply(&probed, &[probe("db", 22)], 2).is_empty());
+
+        let opened = baseline.apply(&probed, &[probe("db", 5432)], 2);
+        assert_eq!(opened.len(), 1);
+        assert_eq!(opened[0].event_type, OPENED_EVENT_TYPE);
+        assert_eq!(opened[0].payload["service"], "postgresql");
+
+        // 22 has missed once; a second miss closes it
+        let closed = baseline.apply(&probed, &[probe("db", 5432)], 2);
+        assert_eq!(closed.len(), 1);
+        assert_eq!(closed[0].event_type, CLOSED_EVENT_TYPE);
+        assert_eq!(closed[0].payload["port"], 22);
+        baseline.undo(&closed[0], 2);
+        assert_eq!(baseline.apply(&probed, &[probe("db", 5432)], 2), closed);
+
+        // A host not scanned before only adds to the baseline
+        assert!(
+            baseline
+                .apply(&[probe("web", 443)], &[probe("web", 443)], 2)
+                .is_empty()
+        );
+        // A miss is forgiven once the port answers again
+        assert!(baseline.apply(&[probe("web", 443)], &[], 2).is_empty());
+        assert!(
+            baseline
+                .apply(&[probe("web", 443)], &[probe("web", 443)], 2)
+                .is_empty()
+        );
+        assert!(baseline.apply(&[probe("web", 443)], &[], 2).is_empty());
+    }
+}
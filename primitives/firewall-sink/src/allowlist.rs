@@ -0,0 +1,89 @@
+//! Addresses that are never banned.
+
+use std::net::IpAddr;
+
+/// An address block such as `10.0.0.0/8` or `2001:db8::/32`; a bare
+/// address is a block of one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cidr {
+    network: IpAddr,
+    prefix: u8,
+}
+
+impl std::str::FromStr for Cidr {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (address, prefix) = s.split_once('/').unwrap_or((s, ""));
+        let network: IpAddr = address
+            .trim()
+            .parse()
+            .map_err(|_| format!("{s}: not an address"))?;
+        let width = if network.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            "" => width,
+            prefix => prefix
+                .parse::<u8>()
+                .ok()
+                .filter(|p| *p <= width)
+                .ok_or_else(|| format!("{s}: prefix must be 0-{width}"))?,
+        };
+        Ok(Self { network, prefix })
+    }
+}
+
+impl Cidr {
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        let mask = |width: u32| match self.prefix {
+            0 => 0,
+            prefix => u128::MAX << (width - u32::from(prefix)),
+        };
+        match (self.network, ip.to_canonical()) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                let mask = mask(32);
+                u128::from(network.to_bits()) & mask == u128::from(ip.to_bits()) & mask
+            }
+            (IpAddr::V6(network), IpAddr::V6(ip)) => {
+                let mask = mask(128);
+                network.to_bits() & mask == ip.to_bits() & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Whether any block in `allowlist` contains `ip`.
+pub fn allowed(allowlist: &[Cidr], ip: IpAddr) -> bool {
+    allowlist.iter().any(|cidr| cidr.contains(ip))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cidr(s: &str) -> Cidr {
+        s.parse().unwrap_or_else(|e| panic!("{e}"))
+    }
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap_or_else(|e| panic!("{s}: {e}"))
+    }
+
+    #[test]
+    fn blocks_contain_their_addresses() {
+        let list = [
+            cidr("10.0.0.0/8"),
+            cidr("192.168.1.7"),
+            cidr("2001:db8::/32"),
+        ];
+        assert!(allowed(&list, ip("10.200.3.4")));
+        assert!(allowed(&list, ip("::ffff:10.0.0.1")));
+        assert!(allowed(&list, ip("192.168.1.7")));
+        assert!(!allowed(&list, ip("192.168.1.8")));
+        assert!(allowed(&list, ip("2001:db8:ffff::1")));
+        assert!(!allowed(&list, ip("2001:db9::1")));
+        assert!(cidr("0.0.0.0/0").contains(ip("203.0.113.7")));
+        assert!("10.0.0.0/33".parse::<Cidr>().is_err());
+        assert!("example.com".parse::<Cidr>().is_err());
+    }
+}
@@ -0,0 +1,289 @@
+//! The nftables and iptables commands behind bans.
+//!
+//! Both backends keep banned addresses in kernel sets with per-element
+//! timeouts, so a ban expires on time even if the sink has stopped:
+//!
+//! - `nft` - a table (`--set-name`, default `emergent`) with the sets
+//!   `banned4` and `banned6` and an input chain dropping their members.
+//! - `iptables` - the ipsets `<set-name>4` and `<set-name>6`, matched by a
+//!   DROP rule at the top of `--chain` in iptables and ip6tables.
+//!
+//! Setup is idempotent: existing bans survive a restart of the sink.
+
+use clap::{Args, ValueEnum};
+use std::net::IpAddr;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+
+/// How bans are applied.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Kind {
+    Nft,
+    Iptables,
+}
+
+/// Firewall backend settings.
+#[derive(Args, Debug, Clone)]
+pub struct BackendArgs {
+    /// Firewall to apply bans with.
+    #[arg(long, env = "FIREWALL_SINK_BACKEND", value_enum, default_value_t = Kind::Nft)]
+    pub backend: Kind,
+
+    /// nftables table, or prefix of the ipsets, holding the bans.
+    #[arg(long, env = "FIREWALL_SINK_SET_NAME", default_value = "emergent")]
+    pub set_name: String,
+
+    /// iptables chain the ipset rules are inserted into.
+    #[arg(long, env = "FIREWALL_SINK_CHAIN", default_value = "INPUT")]
+    pub chain: String,
+
+    /// Run firewall commands through this wrapper (e.g. "sudo -n").
+    #[arg(long, env = "FIREWALL_SINK_COMMAND_WRAPPER")]
+    pub command_wrapper: Option<String>,
+}
+
+/// One command, with input for its stdin.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Step {
+    pub argv: Vec<String>,
+    pub stdin: Option<String>,
+    /// Skip the step when this command succeeds.
+    pub unless: Option<Vec<String>>,
+}
+
+fn argv(words: &[&str]) -> Vec<String> {
+    words.iter().map(|w| w.to_string()).collect()
+}
+
+fn step(words: &[&str]) -> Step {
+    Step {
+        argv: argv(words),
+        stdin: None,
+        unless: None,
+    }
+}
+
+impl BackendArgs {
+    /// The executables the backend runs.
+    pub fn programs(&self) -> &'static [&'static str] {
+        match self.backend {
+            Kind::Nft => &["nft"],
+            Kind::Iptables => &["ipset", "iptables", "ip6tables"],
+        }
+    }
+
+    /// Commands creating the sets and the rules matching them.
+    pub fn setup(&self) -> Vec<Step> {
+        let name = &self.set_name;
+        match self.backend {
+            Kind::Nft => {
+                let script = format!(
+                    "table inet {name} {{\n\
+                     \x20 set banned4 {{ type ipv4_addr; flags timeout; }}\n\
+                     \x20 set banned6 {{ type ipv6_addr; flags timeout; }}\n\
+                     \x20 chain input {{ type filter hook input priority filter - 10; policy accept; }}\n\
+                     }}\n\
+                     flush chain inet {name} input\n\
+                     add rule inet {name} input ip saddr @banned4 drop\n\
+                     add rule inet {name} input ip6 saddr @banned6 drop\n"
+                );
+                vec![Step {
+                    argv: argv(&["nft", "-f", "/dev/stdin"]),
+                    stdin: Some(script),
+                    unless: None,
+                }]
+            }
+            Kind::Iptables => {
+                let (set4, set6) = (format!("{name}4"), format!("{name}6"));
+                let rule = |program: &str, action: &str, set: &str| {
+                    argv(&[
+                        program,
+                        action,
+                        &self.chain,
+                        "-m",
+                        "set",
+                        "--match-set",
+                        set,
+                        "src",
+                        "-j",
+                        "DROP",
+                    ])
+                };
+                vec![
+                    step(&[
+                        "ipset", "create", &set4, "hash:ip", "timeout", "0", "-exist",
+                    ]),
+                    step(&[
+                        "ipset", "create", &set6, "hash:ip", "family", "inet6", "timeout", "0",
+                        "-exist",
+                    ]),
+                    Step {
+                        argv: rule("iptables", "-I", &set4),
+                        stdin: None,
+                        unless: Some(rule("iptables", "-C", &set4)),
+                    },
+                    Step {
+                        argv: rule("ip6tables", "-I", &set6),
+                        stdin: None,
+                        unless: Some(rule("ip6tables", "-C", &set6)),
+                    },
+                ]
+            }
+        }
+    }
+
+    /// The command banning `ip` for `ban_time`.
+    pub fn ban(&self, ip: IpAddr, ban_time: Duration) -> Step {
+        let (address, seconds) = (ip.to_string(), ban_time.as_secs().max(1).to_string());
+        match self.backend {
+            Kind::Nft => {
+                let element = format!("{{ {address} timeout {seconds}s }}");
+                step(&[
+                    "nft",
+                    "add",
+                    "element",
+                    "inet",
+                    &self.set_name,
+                    nft_set(ip),
+                    &element,
+                ])
+            }
+            Kind::Iptables => step(&[
+                "ipset",
+                "add",
+                &self.ipset(ip),
+                &address,
+                "timeout",
+                &seconds,
+                "-exist",
+            ]),
+        }
+    }
+
+    /// The command lifting a ban on `ip`.
+    pub fn unban(&self, ip: IpAddr) -> Step {
+        let address = ip.to_string();
+        match self.backend {
+            Kind::Nft => {
+                let element = format!("{{ {address} }}");
+                step(&[
+                    "nft",
+                    "delete",
+                    "element",
+                    "inet",
+                    &self.set_name,
+                    nft_set(ip),
+                    &element,
+                ])
+            }
+            Kind::Iptables => step(&["ipset", "del", &self.ipset(ip), &address, "-exist"]),
+        }
+    }
+
+    fn ipset(&self, ip: IpAddr) -> String {
+        let family = if ip.is_ipv4() { 4 } else { 6 };
+        format!("{}{family}", self.set_name)
+    }
+
+    /// Run `step`, through the wrapper if there is one.
+    pub async fn run(&self, step: &Step) -> Result<(), String> {
+        if let Some(unless) = &step.unless
+            && self.execute(unless, None).await.is_ok()
+        {
+            return Ok(());
+        }
+        self.execute(&step.argv, step.stdin.as_deref()).await
+    }
+
+    async fn execute(&self, argv: &[String], stdin: Option<&str>) -> Result<(), String> {
+        let wrapper = self.command_wrapper.as_deref().unwrap_or_default();
+        let mut words = wrapper
+            .split_whitespace()
+            .map(str::to_string)
+            .chain(argv.iter().cloned());
+        let Some(program) = words.next() else {
+            return Err("empty command".to_string());
+        };
+        let command_line = argv.join(" ");
+        let mut child = Command::new(&program)
+            .args(words)
+            .stdin(std::process::Stdio::piped())
+            .stdout(std::process::Stdio::null())
+            .stderr(std::process::Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(|e| format!("{command_line}: {e}"))?;
+        if let Some(mut pipe) = child.stdin.take() {
+            // Some wrappers never read their input; a broken pipe is not an error
+            let _ = pipe.write_all(stdin.unwrap_or_default().as_bytes()).await;
+        }
+        let output = child
+            .wait_with_output()
+            .await
+            .map_err(|e| format!("{command_line}: {e}"))?;
+        if output.status.success() {
+            Ok(())
+        } else {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            Err(format!(
+                "{command_line}: {} {}",
+                output.status,
+                stderr.trim()
+            ))
+        }
+    }
+}
+
+fn nft_set(ip: IpAddr) -> &'static str {
+    if ip.is_ipv4() { "banned4" } else { "banned6" }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn backend(kind: Kind) -> BackendArgs {
+        BackendArgs {
+            backend: kind,
+            set_name: "emergent".to_string(),
+            chain: "INPUT".to_string(),
+            command_wrapper: None,
+        }
+    }
+
+    #[test]
+    fn bans_go_into_the_family_set_with_a_timeout() {
+        let v4: IpAddr = [203, 0, 113, 7].into();
+        let v6: IpAddr = "2001:db8::1".parse().unwrap_or_else(|e| panic!("{e}"));
+        let hour = Duration::from_secs(3600);
+
+        let nft = backend(Kind::Nft);
+        assert_eq!(
+            nft.ban(v4, hour).argv.join(" "),
+            "nft add element inet emergent banned4 { 203.0.113.7 timeout 3600s }"
+        );
+        assert_eq!(
+            nft.unban(v6).argv.join(" "),
+            "nft delete element inet emergent banned6 { 2001:db8::1 }"
+        );
+        let script = nft.setup()[0].stdin.clone().unwrap_or_default();
+        assert!(script.contains("set banned4 { type ipv4_addr; flags timeout; }"));
+        assert!(script.contains("add rule inet emergent input ip6 saddr @banned6 drop"));
+
+        let iptables = backend(Kind::Iptables);
+        assert_eq!(
+            iptables.ban(v6, hour).argv.join(" "),
+            "ipset add emergent6 2001:db8::1 timeout 3600 -exist"
+        );
+        assert_eq!(
+            iptables.unban(v4).argv.join(" "),
+            "ipset del emergent4 203.0.113.7 -exist"
+        );
+        let setup = iptables.setup();
+        assert_eq!(
+            setup[2].unless.clone().unwrap_or_default().join(" "),
+            "iptables -C INPUT -m set --match-set emergent4 src -j DROP"
+        );
+    }
+}
@@ -0,0 +1,515 @@
+//! Firewall Sink - Ban Addresses Behind Security Events
+//!
+//! A fail2ban-style responder. Each event names an address (`--ip-field`,
+//! default `ip`); an address with `--max-retry` events within `--find-time`
+//! seconds is banned for `--ban-time` seconds with nftables or iptables
+//! (see [`backend`]), then unbanned automatically. Addresses in `--allow`
+//! are never banned.
+//!
+//! A payload can also ask directly: `{"op": "ban", "ip": "203.0.113.7",
+//! "ban_time": 600, "reason": "..."}` bans at once, and `{"op": "unban",
+//! "ip": "203.0.113.7"}` lifts a ban.
+//!
+//! Every ban and unban is confirmed with a `firewall.banned` or
+//! `firewall.unbanned` event.
+//!
+//! # Examples
+//!
+//! ```bash
+//! # Ban for an hour after 5 failed logins within 10 minutes
+//! firewall-sink -s auth.login_failed
+//!
+//! # iptables with ipset, running commands through sudo
+//! firewall-sink -s auth.login_failed --backend iptables --command-wrapper "sudo -n" \
+//!   --max-retry 3 --find-time 300 --ban-time 86400 --allow 10.0.0.0/8
+//! ```
+
+pub mod allowlist;
+pub mod backend;
+
+use allowlist::Cidr;
+use backend::BackendArgs;
+use clap::Parser;
+use emergent_client::EmergentMessage;
+use primitive_common::capabilities::VERSION;
+use primitive_common::doctor::{Report, check_executable};
+use primitive_common::errors::{ErrorCategory, HandlerError};
+use primitive_common::key;
+use primitive_common::{Publisher, SinkArgs, SinkConfig, SinkContext, SinkHandler, run_sink};
+use serde_json::{Value, json};
+use std::collections::{HashMap, VecDeque};
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex, OnceLock, PoisonError};
+use std::time::{Duration, Instant};
+
+pub const BANNED_EVENT_TYPE: &str = "firewall.banned";
+pub const UNBANNED_EVENT_TYPE: &str = "firewall.unbanned";
+
+/// Firewall Sink — ban addresses behind security events.
+#[derive(Parser, Debug)]
+#[command(name = "firewall_sink", version = VERSION)]
+#[command(about = "Ban addresses with nftables or iptables after repeated security events")]
+struct Args {
+    /// Message types to subscribe to.
+    #[arg(short, long = "subscribe", required = true)]
+    subscribe: Vec<String>,
+
+    /// Payload field holding the address.
+    #[arg(long, env = "FIREWALL_SINK_IP_FIELD", default_value = "ip")]
+    ip_field: String,
+
+    /// Events from one address that trigger a ban.
+    #[arg(long, env = "FIREWALL_SINK_MAX_RETRY", default_value = "5")]
+    max_retry: usize,
+
+    /// Seconds within which `--max-retry` events must occur.
+    #[arg(long, env = "FIREWALL_SINK_FIND_TIME", default_value = "600")]
+    find_time: u64,
+
+    /// Seconds a ban lasts.
+    #[arg(long, env = "FIREWALL_SINK_BAN_TIME", default_value = "3600")]
+    ban_time: u64,
+
+    /// Addresses or CIDR blocks never banned (comma-separated).
+    #[arg(
+        long,
+        env = "FIREWALL_SINK_ALLOW",
+        value_delimiter = ',',
+        default_value = "127.0.0.0/8,::1"
+    )]
+    allow: Vec<Cidr>,
+
+    #[command(flatten)]
+    backend: BackendArgs,
+
+    #[command(flatten)]
+    sink: SinkArgs,
+}
+
+/// An active ban.
+struct Ban {
+    /// Distinguishes this ban from a later one on the same address, so a
+    /// stale expiry timer leaves the later ban alone.
+    generation: u64,
+    since: Instant,
+}
+
+/// State shared with the expiry timers.
+struct Firewall {
+    backend: BackendArgs,
+    allow: Vec<Cidr>,
+    max_retry: usize,
+    find_time: Duration,
+    ban_time: Duration,
+    failures: Mutex<HashMap<IpAddr, VecDeque<Instant>>>,
+    bans: Mutex<HashMap<IpAddr, Ban>>,
+    generation: Mutex<u64>,
+    publisher: OnceLock<Publisher>,
+}
+
+impl Firewall {
+    /// Record an event from `ip`, returning how many fall within the find
+    /// time if that reaches the ban threshold.
+    fn record(&self, ip: IpAddr, now: Instant) -> Option<usize> {
+        let mut failures = self.failures.lock().unwrap_or_else(PoisonError::into_inner);
+        // Forget addresses whose events have all aged out, so the map cannot grow unbounded
+        failures.retain(|_, seen| {
+            seen.back()
+                .is_some_and(|last| now.duration_since(*last) < self.find_time)
+        });
+        let seen = failures.entry(ip).or_default();
+        seen.push_back(now);
+        while seen
+            .front()
+            .is_some_and(|first| now.duration_since(*first) >= self.find_time)
+        {
+            seen.pop_front();
+        }
+        let count = seen.len();
+        if count < self.max_retry {
+            return None;
+        }
+        failures.remove(&ip);
+        Some(count)
+    }
+
+    fn is_banned(&self, ip: IpAddr) -> bool {
+        let bans = self.bans.lock().unwrap_or_else(PoisonError::into_inner);
+        bans.contains_key(&ip)
+    }
+
+    fn publish(&self, event_type: &str, payload: Value) {
+        let Some(publisher) = self.publisher.get() else {
+            return;
+        };
+        let message = EmergentMessage::new(event_type).with_payload(payload);
+        if let Err(e) = publisher.publish(message) {
+            eprintln!("Failed to publish {event_type}: {e}");
+        }
+    }
+
+    /// Ban `ip` and schedule its unban.
+    async fn ban(
+        self: &Arc<Self>,
+        ip: IpAddr,
+        ban_time: Duration,
+        detail: Value,
+    ) -> Result<(), String> {
+        self.backend.run(&self.backend.ban(ip, ban_time)).await?;
+        let generation = {
+            let mut next = self
+                .generation
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner);
+            *next += 1;
+            *next
+        };
+        self.bans
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(
+                ip,
+                Ban {
+                    generation,
+                    since: Instant::now(),
+                },
+            );
+        eprintln!("Banned {ip} for {}s", ban_time.as_secs());
+        let mut payload = json!({ "ip": ip.to_string(), "ban_time": ban_time.as_secs() });
+        if let (Some(payload), Value::Object(detail)) = (payload.as_object_mut(), detail) {
+            payload.extend(detail);
+        }
+        self.publish(BANNED_EVENT_TYPE, payload);
+
+        let firewall = Arc::clone(self);
+        tokio::spawn(async move {
+            tokio::time::sleep(ban_time).await;
+            firewall.expire(ip, generation).await;
+        });
+        Ok(())
+    }
+
+    /// Lift the ban with `generation` on `ip` once it has run out.
+    async fn expire(&self, ip: IpAddr, generation: u64) {
+        let ban = {
+            let mut bans = self.bans.lock().unwrap_or_else(PoisonError::into_inner);
+            match bans.get(&ip) {
+                Some(ban) if ban.generation == generation => bans.remove(&ip),
+                _ => None,
+            }
+        };
+        let Some(ban) = ban else { return };
+        // The kernel drops the element at the same time; this only makes sure
+        if let Err(e) = self.backend.run(&self.backend.unban(ip)).await {
+            eprintln!("Failed to unban {ip}: {e}");
+        }
+        self.unbanned(ip, &ban, "expired");
+    }
+
+    /// Lift a ban on request.
+    async fn unban(&self, ip: IpAddr) -> Result<(), String> {
+        self.backend.run(&self.backend.unban(ip)).await?;
+        let ban = self
+            .bans
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .remove(&ip);
+        match ban {
+            Some(ban) => self.unbanned(ip, &ban, "requested"),
+            None => eprintln!("{ip} was not banned by this sink"),
+        }
+        Ok(())
+    }
+
+    fn unbanned(&self, ip: IpAddr, ban: &Ban, reason: &str) {
+        eprintln!("Unbanned {ip} ({reason})");
+        self.publish(
+            UNBANNED_EVENT_TYPE,
+            json!({
+                "ip": ip.to_string(),
+                "reason": reason,
+                "banned_for": ban.since.elapsed().as_secs(),
+            }),
+        );
+    }
+}
+
+/// Bans addresses named by events.
+struct FirewallSink {
+    firewall: Arc<Firewall>,
+    ip_field: String,
+}
+
+impl FirewallSink {
+    fn address(&self, payload: &Value, field: &str) -> Result<IpAddr, HandlerError> {
+        let Some(text) = key::extract(payload, field) else {
+            return Err(HandlerError::new(
+                ErrorCategory::Parse,
+                format!("payload has no '{field}'"),
+            ));
+        };
+        text.trim()
+            .parse::<IpAddr>()
+            .map(|ip| ip.to_canonical())
+            .map_err(|_| {
+                HandlerError::new(
+                    ErrorCategory::Parse,
+                    format!("'{text}' is not an IP address"),
+                )
+            })
+    }
+}
+
+impl SinkHandler for FirewallSink {
+    async fn handle(
+        &self,
+        msg: &EmergentMessage,
+        ctx: &SinkContext<'_>,
+    ) -> Result<(), HandlerError> {
+        let payload = ctx.payload();
+        let firewall = &self.firewall;
+        let op = payload["op"].as_str();
+        let field = match op {
+            Some(_) => "ip",
+            None => self.ip_field.as_str(),
+        };
+        let ip = self.address(payload, field)?;
+
+        let (ban_time, detail) = match op {
+            Some("unban") => {
+                if ctx.is_dry_run() {
+                    let step = firewall.backend.unban(ip);
+                    ctx.would_have(
+                        "unban",
+                        json!({ "ip": ip.to_string(), "command": step.argv }),
+                    )
+                    .await;
+                    return Ok(());
+                }
+                return firewall
+                    .unban(ip)
+                    .await
+                    .map_err(|e| HandlerError::new(ErrorCategory::Request, e));
+            }
+            Some("ban") => {
+                let ban_time = payload["ban_time"]
+                    .as_u64()
+                    .map_or(firewall.ban_time, Duration::from_secs);
+                let reason = payload["reason"].as_str().unwrap_or("requested");
+                (ban_time, json!({ "reason": reason }))
+            }
+            Some(other) => {
+                return Err(HandlerError::new(
+                    ErrorCategory::Parse,
+                    format!("unknown op '{other}'"),
+                ));
+            }
+            None => {
+                if allowlist::allowed(&firewall.allow, ip) || firewall.is_banned(ip) {
+                    return Ok(());
+                }
+                let Some(failures) = firewall.record(ip, Instant::now()) else {
+                    return Ok(());
+                };
+                let reason = format!("{failures} events within {}s", firewall.find_time.as_secs());
+                (
+                    firewall.ban_time,
+                    json!({ "reason": reason, "failures": failures }),
+                )
+            }
+        };
+        if allowlist::allowed(&firewall.allow, ip) {
+            eprintln!("Not banning allowlisted {ip}");
+            return Ok(());
+        }
+
+        let mut detail = detail;
+        detail["message_id"] = json!(ctx.message_id());
+        detail["message_type"] = json!(msg.message_type.as_str());
+        if ctx.is_dry_run() {
+            let step = firewall.backend.ban(ip, ban_time);
+            detail["ip"] = json!(ip.to_string());
+            detail["command"] = json!(step.argv);
+            ctx.would_have("ban", detail).await;
+            return Ok(());
+        }
+        firewall
+            .ban(ip, ban_time, detail)
+            .await
+            .map_err(|e| HandlerError::new(ErrorCategory::Request, e))
+    }
+
+    fn self_test(&self, report: &mut Report) {
+        for program in self.firewall.backend.programs() {
+            report.check(program, check_executable(program));
+        }
+    }
+
+    fn publishes(&self) -> &'static [&'static str] {
+        &[BANNED_EVENT_TYPE, UNBANNED_EVENT_TYPE]
+    }
+
+    fn attach(&self, publisher: Publisher) {
+        let _ = self.firewall.publisher.set(publisher);
+    }
+}
+
+/// Run the primitive with `args` as its command line (the first item is
+/// the program name, as with `std::env::args_os`).
+pub async fn run<I, T>(args: I) -> Result<(), Box<dyn std::error::Error>>
+where
+    I: IntoIterator<Item = T>,
+    T: Into<std::ffi::OsString> + Clone,
+{
+    let args = Args::parse_from(args);
+
+    let config = SinkConfig {
+        name: "firewall_sink",
+        subscribe: &args.subscribe,
+        would_have_as: "firewall.would_have",
+        dead_letter_as: "firewall.dead_letter",
+        settings: &args,
+    };
+    let handler = FirewallSink {
+        firewall: Arc::new(Firewall {
+            backend: args.backend.clone(),
+            allow: args.allow.clone(),
+            max_retry: args.max_retry.max(1),
+            find_time: Duration::from_secs(args.find_time),
+            ban_time: Duration::from_secs(args.ban_time),
+            failures: Mutex::new(HashMap::new()),
+            bans: Mutex::new(HashMap::new()),
+            generation: Mutex::new(0),
+            publisher: OnceLock::new(),
+        }),
+        ip_field: args.ip_field.clone(),
+    };
+
+    // Create the sets and rules before the first ban
+    if !args.sink.dry_run && !args.sink.self_test {
+        for step in args.backend.setup() {
+            if let Err(e) = args.backend.run(&step).await {
+                eprintln!("Error: firewall setup failed: {e}");
+                std::process::exit(1);
+            }
+        }
+    }
+
+    run_sink(config, &args.sink, handler).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use backend::Kind;
+    use emergent_testkit::{SinkFixture, fixtures, spawn_sink};
+
+    fn firewall(max_retry: usize, ban_time: Duration) -> Arc<Firewall> {
+        Arc::new(Firewall {
+            backend: BackendArgs {
+                backend: Kind::Nft,
+                set_name: "emergent".to_string(),
+                chain: "INPUT".to_string(),
+                // Every command "succeeds" without touching the firewall
+                command_wrapper: Some("true".to_string()),
+            },
+            allow: vec!["10.0.0.0/8".parse().unwrap_or_else(|e| panic!("{e}"))],
+            max_retry,
+            find_time: Duration::from_secs(600),
+            ban_time,
+            failures: Mutex::new(HashMap::new()),
+            bans: Mutex::new(HashMap::new()),
+            generation: Mutex::new(0),
+            publisher: OnceLock::new(),
+        })
+    }
+
+    #[test]
+    fn bans_need_max_retry_events_within_find_time() {
+        let firewall = firewall(3, Duration::from_secs(60));
+        let ip: IpAddr = [203, 0, 113, 7].into();
+        let start = Instant::now();
+        assert_eq!(firewall.record(ip, start), None);
+        assert_eq!(firewall.record(ip, start + Duration::from_secs(10)), None);
+        // The first event has aged out by now
+        assert_eq!(firewall.record(ip, start + Duration::from_secs(605)), None);
+        assert_eq!(
+            firewall.record(ip, start + Duration::from_secs(606)),
+            Some(3)
+        );
+        // Counting starts over after a ban
+        assert_eq!(firewall.record(ip, start + Duration::from_secs(607)), None);
+    }
+
+    #[tokio::test]
+    async fn repeated_failures_ban_until_the_ban_time_runs_out() {
+        let handler = FirewallSink {
+            firewall: firewall(2, Duration::from_millis(50)),
+            ip_field: "ip".to_string(),
+        };
+        let (mut engine, run) = spawn_sink(
+            SinkFixture::new("firewall_sink", "firewall"),
+            SinkArgs::default(),
+            handler,
+        );
+
+        for ip in ["10.1.2.3", "10.1.2.3", "203.0.113.7", "203.0.113.7"] {
+            engine
+                .inject_message(fixtures::message(
+                    "auth.login_failed",
+                    json!({ "ip": ip, "username": "ada" }),
+                ))
+                .await;
+        }
+        let banned = engine.expect_published(BANNED_EVENT_TYPE).await;
+        assert_eq!(banned.payload()["ip"], "203.0.113.7");
+        assert_eq!(banned.payload()["failures"], 2);
+        assert_eq!(banned.payload()["message_type"], "auth.login_failed");
+
+        let unbanned = engine.expect_published(UNBANNED_EVENT_TYPE).await;
+        assert_eq!(unbanned.payload()["ip"], "203.0.113.7");
+        assert_eq!(unbanned.payload()["reason"], "expired");
+
+        engine.close();
+        assert_eq!(run.await.ok(), Some(Ok(())));
+    }
+
+    #[tokio::test]
+    async fn dry_run_reports_the_ban_command() {
+        let args = SinkArgs {
+            dry_run: true,
+            ..Default::default()
+        };
+        let handler = FirewallSink {
+            firewall: firewall(5, Duration::from_secs(3600)),
+            ip_field: "ip".to_string(),
+        };
+        let (mut engine, run) =
+            spawn_sink(SinkFixture::new("firewall_sink", "firewall"), args, handler);
+
+        engine
+            .inject_message(fixtures::message(
+                "ids.alert",
+                json!({"op": "ban", "ip": "2001:db8::1", "ban_time": 600, "reason": "port scan"}),
+            ))
+            .await;
+        let report = engine.expect_published("firewall.would_have").await;
+        assert_eq!(report.payload()["action"], "ban");
+        assert_eq!(report.payload()["detail"]["reason"], "port scan");
+        assert_eq!(
+            report.payload()["detail"]["command"],
+            json!([
+                "nft",
+                "add",
+                "element",
+                "inet",
+                "emergent",
+                "banned6",
+                "{ 2001:db8::1 timeout 600s }"
+            ])
+        );
+
+        engine.close();
+        assert_eq!(run.await.ok(), Some(Ok(())));
+    }
+}
@@ -0,0 +1,102 @@
+//! The `list`, `approve` and `reject` subcommands, which call a running
+//! gate's operator API (see [`crate::web`]).
+
+use clap::Args;
+use reqwest::Client;
+use serde_json::{Value, json};
+
+/// Where the running gate listens.
+#[derive(Args, Debug, Clone)]
+pub struct Remote {
+    /// The gate's operator API.
+    #[arg(long, env = "GATE_URL", default_value = "http://127.0.0.1:8791")]
+    pub url: String,
+
+    /// Bearer token the API requires.
+    #[arg(long, env = "GATE_TOKEN", hide_env_values = true)]
+    pub token: Option<String>,
+}
+
+impl Remote {
+    async fn call(&self, request: reqwest::RequestBuilder) -> Result<Value, String> {
+        let request = match &self.token {
+            Some(token) => request.bearer_auth(token),
+            None => request,
+        };
+        let response = request
+            .send()
+            .await
+            .map_err(|e| format!("{}: {e}", self.url))?;
+        let status = response.status();
+        let body: Value = response
+            .json()
+            .await
+            .map_err(|e| format!("{}: {e}", self.url))?;
+        if status.is_success() {
+            Ok(body)
+        } else {
+            Err(body["error"]
+                .as_str()
+                .map_or_else(|| format!("HTTP {status}"), str::to_string))
+        }
+    }
+}
+
+/// One line per held message: id, type, when it arrived, and its payload.
+pub async fn list(remote: &Remote) -> Result<String, String> {
+    let url = format!("{}/pending", remote.url.trim_end_matches('/'));
+    let body = remote.call(Client::new().get(url)).await?;
+    let lines: Vec<String> = body["pending"]
+        .as_array()
+        .map(Vec::as_slice)
+        .unwrap_or_default()
+        .iter()
+        .map(|held| {
+            format!(
+                "{}  {}  {}  {}",
+                held["id"].as_str().unwrap_or_default(),
+                held["message_type"].as_str().unwrap_or_default(),
+                held["received_at"].as_str().unwrap_or_default(),
+                held["payload"]
+            )
+        })
+        .collect();
+    Ok(if lines.is_empty() {
+        "no messages pending".to_string()
+    } else {
+        lines.join("\n")
+    })
+}
+
+/// Approve or reject each of `ids`, one line per outcome; fails if any
+/// decision failed.
+pub async fn decide(
+    remote: &Remote,
+    ids: &[String],
+    approve: bool,
+    by: Option<&str>,
+    reason: Option<&str>,
+) -> Result<String, String> {
+    let action = if approve { "approve" } else { "reject" };
+    let client = Client::new();
+    let mut lines = Vec::new();
+    let mut failed = false;
+    for id in ids {
+        let url = format!("{}/pending/{id}/{action}", remote.url.trim_end_matches('/'));
+        let request = client
+            .post(url)
+            .json(&json!({ "by": by, "reason": reason }));
+        match remote.call(request).await {
+            Ok(decided) => lines.push(format!(
+                "{id}: {}",
+                decided["decision"].as_str().unwrap_or(action)
+            )),
+            Err(e) => {
+                failed = true;
+                lines.push(format!("{id}: {e}"));
+            }
+        }
+    }
+    let lines = lines.join("\n");
+    if failed { Err(lines) } else { Ok(lines) }
+}
@@ -0,0 +1,757 @@
+//! Gate
+//!
+//! A Handler that holds messages until an operator approves them, for
+//! changes that need a human in the loop: deploys, refunds, bulk deletes.
+//! Held messages wait in a state file, so they survive restarts, and are
+//! approved or rejected through a small HTTP API, the `list`, `approve`
+//! and `reject` subcommands, or approval events.
+//!
+//! # Data Flow
+//!
+//! 1. Receive a message (`--subscribe`), hold it, and publish `gate.pending`
+//! 2. Receive a decision: a call to the operator API (see [`web`]), which
+//!    the subcommands make, or an `approval.granted` or `approval.denied`
+//!    event whose payload names the held message's `id`
+//! 3. On approval, republish the held message as `--publish-as`; either
+//!    way, release it and publish `gate.decided`
+//!
+//! Decisions can say who made them and why (`by` and `reason`), which go
+//! into `gate.decided`. Up to `--max-pending` messages are held; further
+//! ones are dropped with an error.
+//!
+//! # Messages Published
+//!
+//! - `gate.pending`: the held message's id, type, arrival time and payload
+//! - The held type, renamed by `--publish-as` (default: `approved.{type}`),
+//!   with the held payload, once approved
+//! - `gate.decided`: the held message's id and type, the decision
+//!   (`approved` or `rejected`), `by`, `reason`, and how long it was held
+//!
+//! # Usage
+//!
+//! ```bash
+//! # Hold production deploys for sign-off
+//! gate -s deploy.production --publish-as deploy.approved \
+//!   --state-file /var/lib/emergent/deploys.json --token "$GATE_TOKEN"
+//!
+//! # Review and approve from a shell
+//! gate list
+//! gate approve msg_01J2... --reason "change ticket 4411"
+//! ```
+
+pub mod cli;
+pub mod queue;
+pub mod web;
+
+use clap::{Parser, Subcommand};
+use cli::Remote;
+use emergent_client::EmergentMessage;
+use emergent_client::types::CausationId;
+use primitive_common::capabilities::{self, CapabilityArgs, Role, VERSION};
+use primitive_common::crypto::{KeyArgs, Keyring};
+use primitive_common::doctor::{Report, check_writable_dir};
+use primitive_common::errors::{ERROR_EVENT_TYPE, ErrorArgs, ErrorCategory};
+use primitive_common::glob;
+use primitive_common::handler::{Bus, Flow, HandlerConfig, MessageHandler, run_handler};
+use primitive_common::payload::{self, PayloadArgs};
+use primitive_common::time;
+use queue::{Held, Queue};
+use serde_json::{Value, json};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::sync::mpsc;
+use web::{Note, Request, Web};
+
+/// Message type published when a message is held.
+pub const PENDING_EVENT_TYPE: &str = "gate.pending";
+
+/// Message type published when a held message is approved or rejected.
+pub const DECIDED_EVENT_TYPE: &str = "gate.decided";
+
+/// Gate — hold messages until an operator approves them.
+#[derive(Parser, Debug)]
+#[command(name = "gate", version = VERSION, subcommand_negates_reqs = true)]
+#[command(
+    about = "Hold messages in a persistent queue until an operator approves them, then forward them"
+)]
+struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Message types to hold.
+    #[arg(short, long = "subscribe", required = true)]
+    subscribe: Vec<String>,
+
+    /// Message type to republish approved messages as; `{type}` stands for the incoming type.
+    #[arg(
+        long,
+        env = "GATE_PUBLISH_AS",
+        default_value = "approved.{type}",
+        value_parser = parse_publish_as
+    )]
+    publish_as: String,
+
+    /// File keeping the held messages across restarts.
+    #[arg(long, env = "GATE_STATE_FILE", default_value = "gate-pending.json")]
+    state_file: PathBuf,
+
+    /// Most messages held at once; further ones are dropped.
+    #[arg(long, env = "GATE_MAX_PENDING", default_value = "10000")]
+    max_pending: usize,
+
+    /// Port the operator API listens on.
+    #[arg(short, long, env = "GATE_PORT", default_value = "8791")]
+    port: u16,
+
+    /// Host the operator API binds to.
+    #[arg(long, env = "GATE_HOST", default_value = "127.0.0.1")]
+    host: String,
+
+    /// Bearer token the operator API requires.
+    #[arg(long, env = "GATE_TOKEN", hide_env_values = true)]
+    token: Option<String>,
+
+    /// Message type approving the held message whose `id` it names.
+    #[arg(long, env = "GATE_GRANTED_TYPE", default_value = "approval.granted")]
+    granted_type: String,
+
+    /// Message type rejecting the held message whose `id` it names.
+    #[arg(long, env = "GATE_DENIED_TYPE", default_value = "approval.denied")]
+    denied_type: String,
+
+    #[command(flatten)]
+    payload: PayloadArgs,
+
+    /// Verify the configuration and exit without connecting to the engine.
+    #[arg(long)]
+    self_test: bool,
+
+    #[command(flatten)]
+    errors: ErrorArgs,
+
+    #[command(flatten)]
+    capabilities: CapabilityArgs,
+
+    #[command(flatten)]
+    keys: KeyArgs,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// List the messages a running gate holds.
+    List {
+        #[command(flatten)]
+        remote: Remote,
+    },
+    /// Approve held messages, forwarding them downstream.
+    Approve {
+        /// Ids of the held messages.
+        #[arg(required = true)]
+        ids: Vec<String>,
+
+        #[command(flatten)]
+        decision: Decision,
+    },
+    /// Reject held messages, dropping them.
+    Reject {
+        /// Ids of the held messages.
+        #[arg(required = true)]
+        ids: Vec<String>,
+
+        #[command(flatten)]
+        decision: Decision,
+    },
+}
+
+/// Who decides, why, and where the gate is.
+#[derive(clap::Args, Debug)]
+struct Decision {
+    /// Who made the decision.
+    #[arg(long, env = "USER")]
+    by: Option<String>,
+
+    /// Why.
+    #[arg(long)]
+    reason: Option<String>,
+
+    #[command(flatten)]
+    remote: Remote,
+}
+
+fn parse_publish_as(value: &str) -> Result<String, String> {
+    match value {
+        "" => Err("must not be empty".to_string()),
+        // Republishing under the same type would feed straight back in
+        "{type}" => Err("must differ from the incoming type".to_string()),
+        _ => Ok(value.to_string()),
+    }
+}
+
+/// Whether `message_type` is `template` with `{type}` filled in.
+fn renamed(template: &str, message_type: &str) -> bool {
+    match template.split_once("{type}") {
+        Some((prefix, suffix)) => {
+            message_type.len() > prefix.len() + suffix.len()
+                && message_type.starts_with(prefix)
+                && message_type.ends_with(suffix)
+        }
+        None => message_type == template,
+    }
+}
+
+/// Whether `message_type` is one this handler published.
+fn is_own(args: &Args, message_type: &str) -> bool {
+    matches!(message_type, PENDING_EVENT_TYPE | DECIDED_EVENT_TYPE)
+        || renamed(&args.publish_as, message_type)
+}
+
+/// How `held` is shown: in `gate.pending` and the operator API.
+fn view(held: &Held) -> Value {
+    json!({
+        "id": held.id,
+        "message_type": held.message_type,
+        "received_at": time::format((held.received_at / 1000) as i64),
+        "payload": held.payload,
+    })
+}
+
+/// Hold `held`, returning the `gate.pending` payload, or `None` if it was
+/// already held.
+fn hold(args: &Args, queue: &mut Queue, held: Held) -> Result<Option<Value>, String> {
+    if queue.len() >= args.max_pending && !queue.held().iter().any(|h| h.id == held.id) {
+        return Err(format!(
+            "{} messages pending (--max-pending); not holding {}",
+            queue.len(),
+            held.id
+        ));
+    }
+    let pending = view(&held);
+    Ok(queue.hold(held).then_some(pending))
+}
+
+/// A decision on a held message.
+#[derive(Debug)]
+struct Outcome {
+    /// The `gate.decided` payload.
+    decided: Value,
+    /// The held message, when approved.
+    approved: Option<Held>,
+}
+
+/// Approve or reject the held message `id` at `now` (milliseconds since
+/// the Unix epoch), releasing it.
+fn decide(
+    queue: &mut Queue,
+    id: &str,
+    approve: bool,
+    note: Note,
+    now: u64,
+) -> Result<Outcome, String> {
+    let held = queue
+        .take(id)
+        .ok_or_else(|| format!("no message {id} pending"))?;
+    let decided = json!({
+        "id": held.id,
+        "message_type": held.message_type,
+        "decision": if approve { "approved" } else { "rejected" },
+        "by": note.by,
+        "reason": note.reason,
+        "held_ms": now.saturating_sub(held.received_at),
+    });
+    Ok(Outcome {
+        decided,
+        approved: approve.then_some(held),
+    })
+}
+
+/// The held message an approval event decides, and the note it carries.
+fn approval(input: &Value) -> Result<(String, Note), String> {
+    let id = match &input["id"] {
+        Value::String(id) if !id.is_empty() => id.clone(),
+        _ => return Err("approval has no 'id' naming a held message".to_string()),
+    };
+    let text = |field: &str| input[field].as_str().map(str::to_string);
+    let note = Note {
+        by: text("by"),
+        reason: text("reason"),
+    };
+    Ok((id, note))
+}
+
+/// Run the primitive with `args` as its command line (the first item is
+/// the program name, as with `std::env::args_os`).
+pub async fn run<I, T>(args: I) -> Result<(), Box<dyn std::error::Error>>
+where
+    I: IntoIterator<Item = T>,
+    T: Into<std::ffi::OsString> + Clone,
+{
+    let args = Args::parse_from(args);
+
+    if let Some(command) = &args.command {
+        let result = match command {
+            Command::List { remote } => cli::list(remote).await,
+            Command::Approve { ids, decision } | Command::Reject { ids, decision } => {
+                let approve = matches!(command, Command::Approve { .. });
+                cli::decide(
+                    &decision.remote,
+                    ids,
+                    approve,
+                    decision.by.as_deref(),
+                    decision.reason.as_deref(),
+                )
+                .await
+            }
+        };
+        match result {
+            Ok(out) => {
+                println!("{out}");
+                return Ok(());
+            }
+            Err(e) => {
+                eprintln!("{e}");
+                std::process::exit(1);
+            }
+        }
+    }
+
+    // Get the handler name from environment (set by engine) or use default
+    let name = std::env::var("EMERGENT_NAME").unwrap_or_else(|_| "gate".to_string());
+
+    let queue = Queue::load(&args.state_file);
+    if args.self_test {
+        let mut report = Report::new(&name);
+        let dir = args
+            .state_file
+            .parent()
+            .filter(|p| !p.as_os_str().is_empty())
+            .unwrap_or(Path::new("."));
+        report.check("state-file", check_writable_dir(dir));
+        report.check(
+            "queue",
+            queue
+                .as_ref()
+                .map(|queue| format!("{} message(s) pending", queue.len()))
+                .map_err(Clone::clone),
+        );
+        args.payload.self_test(&mut report);
+        args.keys.self_test(&mut report);
+        report.finish();
+    }
+    let exit = |e: String| -> ! {
+        eprintln!("Error: {e}");
+        std::process::exit(1);
+    };
+    let queue = queue.unwrap_or_else(|e| exit(format!("invalid state file {e}")));
+
+    if let Err(e) = args.payload.encryption.validate() {
+        exit(e.to_string());
+    }
+    let keys = args
+        .keys
+        .load()
+        .unwrap_or_else(|e| exit(format!("failed to load identity file: {e}")));
+
+    let (requests, calls) = mpsc::channel(16);
+    let web = Arc::new(Web {
+        token: args.token.clone(),
+        requests,
+    });
+    let addr = format!("{}:{}", args.host, args.port);
+    let listener = tokio::net::TcpListener::bind(&addr)
+        .await
+        .unwrap_or_else(|e| exit(format!("cannot listen on {addr}: {e}")));
+    let app = web.router();
+    tokio::spawn(async move { axum::serve(listener, app).await });
+    eprintln!(
+        "gate: {} message(s) pending, operator API on http://{addr}/",
+        queue.len()
+    );
+
+    let mut topics = args.subscribe.clone();
+    for topic in [&args.granted_type, &args.denied_type] {
+        if !topics.contains(topic) {
+            topics.push(topic.clone());
+        }
+    }
+    let topics_refs: Vec<&str> = topics.iter().map(String::as_str).collect();
+    let mut produces = vec![
+        PENDING_EVENT_TYPE,
+        args.publish_as.as_str(),
+        DECIDED_EVENT_TYPE,
+    ];
+    if args.errors.emit_errors {
+        produces.push(ERROR_EVENT_TYPE);
+    }
+    let descriptor =
+        args.capabilities
+            .describe(&name, Role::Handler, &topics_refs, &produces, &args);
+    if args.capabilities.capabilities {
+        capabilities::print(&descriptor);
+    }
+
+    let config = HandlerConfig {
+        name: &name,
+        subscribe: &topics,
+        announce: args.capabilities.announce.then_some(&descriptor),
+        emit_errors: args.errors.emit_errors,
+    };
+    let mut gate = Gate {
+        args: &args,
+        queue,
+        calls,
+        keys,
+    };
+    run_handler(config, &mut gate).await
+}
+
+/// Write the queue to the state file, reporting failures.
+fn save(queue: &Queue) {
+    if let Err(e) = queue.save() {
+        eprintln!("gate: failed to save pending messages: {e}");
+    }
+}
+
+/// The held messages, the operator API's calls and what is needed to read
+/// payloads.
+struct Gate<'a> {
+    args: &'a Args,
+    queue: Queue,
+    calls: mpsc::Receiver<Request>,
+    keys: Option<Keyring>,
+}
+
+impl MessageHandler for Gate<'_> {
+    type Wake = Request;
+
+    /// Hold a message, or decide on a held one.
+    async fn handle(&mut self, msg: &EmergentMessage, bus: &Bus) {
+        let (args, queue) = (self.args, &mut self.queue);
+        let message_type = msg.message_type.as_str();
+        if is_own(args, message_type) {
+            return;
+        }
+        let input = match payload::decode(msg.payload(), self.keys.as_ref()).await {
+            Ok(p) => p.into_owned(),
+            Err(e) => {
+                let error = format!("failed to decode payload: {e}");
+                eprintln!("gate: {error}");
+                bus.report_error(msg, ErrorCategory::Parse, &error).await;
+                return;
+            }
+        };
+
+        if message_type == args.granted_type || message_type == args.denied_type {
+            let approve = message_type == args.granted_type;
+            match approval(&input) {
+                Ok((id, note)) => {
+                    let cause = Some(CausationId::from(msg.id()));
+                    // Other gates may hold it
+                    if let Err(e) = settle(bus, args, queue, &id, approve, note, cause).await {
+                        eprintln!("gate: {e}");
+                    }
+                }
+                Err(error) => {
+                    eprintln!("gate: {error}");
+                    bus.report_error(msg, ErrorCategory::Parse, &error).await;
+                }
+            }
+            return;
+        }
+        if !args
+            .subscribe
+            .iter()
+            .any(|p| glob::matches(p, message_type))
+        {
+            return;
+        }
+
+        let held = Held {
+            id: msg.id().to_string(),
+            message_type: message_type.to_string(),
+            payload: input,
+            received_at: time::now_ms(),
+            cause: Some(CausationId::from(msg.id())),
+        };
+        match hold(args, queue, held) {
+            Ok(Some(pending)) => {
+                save(queue);
+                eprintln!("gate: holding {} {}", message_type, msg.id());
+                let cause = Some(CausationId::from(msg.id()));
+                publish(bus, args, PENDING_EVENT_TYPE, pending, cause).await;
+            }
+            Ok(None) => {}
+            Err(error) => {
+                eprintln!("gate: {error}");
+                bus.report_error(msg, ErrorCategory::Parse, &error).await;
+            }
+        }
+    }
+
+    /// Wait for a call from the operator API.
+    async fn wake(&mut self) -> Request {
+        match self.calls.recv().await {
+            Some(call) => call,
+            None => std::future::pending().await,
+        }
+    }
+
+    async fn woken(&mut self, call: Request, bus: &Bus) -> Flow {
+        match call {
+            Request::List(reply) => {
+                let _ = reply.send(self.queue.held().iter().map(view).collect());
+            }
+            Request::Decide {
+                id,
+                approve,
+                note,
+                reply,
+            } => {
+                let result =
+                    settle(bus, self.args, &mut self.queue, &id, approve, note, None).await;
+                let _ = reply.send(result);
+            }
+        }
+        Flow::Continue
+    }
+}
+
+/// Decide on the held message `id`, forwarding it if approved; returns the
+/// `gate.decided` payload. `cause` is the approval event, if one decided.
+async fn settle(
+    bus: &Bus,
+    args: &Args,
+    queue: &mut Queue,
+    id: &str,
+    approve: bool,
+    note: Note,
+    cause: Option<CausationId>,
+) -> Result<Value, String> {
+    let outcome = decide(queue, id, approve, note, time::now_ms())?;
+    save(queue);
+    eprintln!(
+        "gate: {id} {}",
+        outcome.decided["decision"].as_str().unwrap_or_default()
+    );
+    if let Some(held) = outcome.approved {
+        let forward = args.publish_as.replace("{type}", &held.message_type);
+        publish(bus, args, &forward, held.payload, held.cause).await;
+    }
+    publish(
+        bus,
+        args,
+        DECIDED_EVENT_TYPE,
+        outcome.decided.clone(),
+        cause,
+    )
+    .await;
+    Ok(outcome.decided)
+}
+
+/// Publish `payload` as `message_type`, caused by `cause`.
+async fn publish(
+    bus: &Bus,
+    args: &Args,
+    message_type: &str,
+    payload: Value,
+    cause: Option<CausationId>,
+) {
+    let payload = match payload::encode(message_type, payload, &args.payload).await {
+        Ok(p) => p,
+        Err(e) => {
+            eprintln!("gate: failed to encode {message_type}: {e}");
+            return;
+        }
+    };
+    let mut message = EmergentMessage::new(message_type).with_payload(payload);
+    if let Some(cause) = cause {
+        message = message.with_causation_id(cause);
+    }
+    bus.publish(message).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use emergent_testkit::fixtures::TempDir;
+
+    // 2024-02-29T13:45:00Z
+    const NOW: u64 = 1_709_214_300_000;
+
+    fn args(extra: &[&str]) -> Args {
+        let mut argv = vec!["gate", "-s", "deploy.*"];
+        argv.extend_from_slice(extra);
+        Args::try_parse_from(argv).unwrap_or_else(|e| panic!("{e}"))
+    }
+
+    fn held(id: &str) -> Held {
+        Held {
+            id: id.to_string(),
+            message_type: "deploy.production".to_string(),
+            payload: json!({"service": "billing-api"}),
+            received_at: NOW,
+            cause: None,
+        }
+    }
+
+    #[test]
+    fn held_messages_are_approved_or_rejected_once() {
+        let args = args(&["--max-pending", "2"]);
+        let dir = TempDir::new("gate");
+        let mut queue =
+            Queue::load(&dir.path().join("pending.json")).unwrap_or_else(|e| panic!("{e}"));
+
+        let pending = hold(&args, &mut queue, held("msg_a")).unwrap_or_else(|e| panic!("{e}"));
+        assert_eq!(
+            pending,
+            Some(json!({
+                "id": "msg_a",
+                "message_type": "deploy.production",
+                "received_at": "2024-02-29T13:45:00Z",
+                "payload": {"service": "billing-api"},
+            }))
+        );
+        assert_eq!(hold(&args, &mut queue, held("msg_a")), Ok(None));
+        assert!(hold(&args, &mut queue, held("msg_b")).is_ok());
+        assert!(hold(&args, &mut queue, held("msg_c")).is_err());
+
+        let note = Note {
+            by: Some("dana".to_string()),
+            reason: Some("change ticket 4411".to_string()),
+        };
+        let approved =
+            decide(&mut queue, "msg_a", true, note, NOW + 90_000).unwrap_or_else(|e| panic!("{e}"));
+        assert_eq!(
+            approved.decided,
+            json!({
+                "id": "msg_a",
+                "message_type": "deploy.production",
+                "decision": "approved",
+                "by": "dana",
+                "reason": "change ticket 4411",
+                "held_ms": 90_000,
+            })
+        );
+        assert_eq!(
+            approved.approved.map(|h| h.payload),
+            Some(json!({"service": "billing-api"}))
+        );
+        assert!(decide(&mut queue, "msg_a", true, Note::default(), NOW).is_err());
+
+        let rejected = decide(&mut queue, "msg_b", false, Note::default(), NOW)
+            .unwrap_or_else(|e| panic!("{e}"));
+        assert_eq!(rejected.decided["decision"], "rejected");
+        assert!(rejected.approved.is_none());
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn approval_events_name_the_held_message() {
+        let (id, note) = approval(&json!({"id": "msg_a", "by": "ci", "reason": "green"}))
+            .unwrap_or_else(|e| panic!("{e}"));
+        assert_eq!(id, "msg_a");
+        assert_eq!(note.by.as_deref(), Some("ci"));
+        assert!(approval(&json!({"by": "ci"})).is_err());
+        assert!(approval(&json!({"id": 7})).is_err());
+
+        let args = args(&["--publish-as", "approved.{type}"]);
+        assert!(is_own(&args, "approved.deploy.production"));
+        assert!(is_own(&args, DECIDED_EVENT_TYPE));
+        assert!(!is_own(&args, "deploy.production"));
+    }
+
+    #[test]
+    fn subcommands_parse_without_subscriptions() {
+        let args = Args::try_parse_from([
+            "gate",
+            "approve",
+            "msg_a",
+            "msg_b",
+            "--by",
+            "dana",
+            "--url",
+            "http://gate:8791",
+        ])
+        .unwrap_or_else(|e| panic!("{e}"));
+        let Some(Command::Approve { ids, decision }) = args.command else {
+            panic!("not an approve command");
+        };
+        assert_eq!(ids, ["msg_a", "msg_b"]);
+        assert_eq!(decision.by.as_deref(), Some("dana"));
+        assert_eq!(decision.remote.url, "http://gate:8791");
+        assert!(Args::try_parse_from(["gate", "reject"]).is_err());
+    }
+
+    /// The operator API, answered from `queue` as the main loop would.
+    async fn serve(mut queue: Queue, token: Option<&str>) -> String {
+        let (requests, mut calls) = mpsc::channel(4);
+        let web = Arc::new(Web {
+            token: token.map(str::to_string),
+            requests,
+        });
+        tokio::spawn(async move {
+            while let Some(call) = calls.recv().await {
+                match call {
+                    Request::List(reply) => {
+                        let _ = reply.send(queue.held().iter().map(view).collect());
+                    }
+                    Request::Decide {
+                        id,
+                        approve,
+                        note,
+                        reply,
+                    } => {
+                        let outcome = decide(&mut queue, &id, approve, note, NOW);
+                        let _ = reply.send(outcome.map(|o| o.decided));
+                    }
+                }
+            }
+        });
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap_or_else(|e| panic!("{e}"));
+        let addr = listener.local_addr().unwrap_or_else(|e| panic!("{e}"));
+        tokio::spawn(async move { axum::serve(listener, web.router()).await });
+        format!("http://{addr}")
+    }
+
+    #[tokio::test]
+    async fn the_cli_lists_and_decides_through_the_api() {
+        let dir = TempDir::new("gate");
+        let mut queue =
+            Queue::load(&dir.path().join("pending.json")).unwrap_or_else(|e| panic!("{e}"));
+        queue.hold(held("msg_a"));
+        queue.hold(held("msg_b"));
+        let url = serve(queue, Some("t0k")).await;
+        let remote = Remote {
+            url,
+            token: Some("t0k".to_string()),
+        };
+
+        let listed = cli::list(&remote).await.unwrap_or_else(|e| panic!("{e}"));
+        assert_eq!(
+            listed,
+            "msg_a  deploy.production  2024-02-29T13:45:00Z  {\"service\":\"billing-api\"}\n\
+             msg_b  deploy.production  2024-02-29T13:45:00Z  {\"service\":\"billing-api\"}"
+        );
+        let ids = ["msg_a".to_string()];
+        let approved = cli::decide(&remote, &ids, true, Some("dana"), None).await;
+        assert_eq!(approved.as_deref(), Ok("msg_a: approved"));
+        let ids = ["msg_b".to_string(), "msg_a".to_string()];
+        let rejected = cli::decide(&remote, &ids, false, None, None).await;
+        assert_eq!(
+            rejected,
+            Err("msg_b: rejected\nmsg_a: no message msg_a pending".to_string())
+        );
+        let empty = cli::list(&remote).await.unwrap_or_else(|e| panic!("{e}"));
+        assert_eq!(empty, "no messages pending");
+
+        let wrong = Remote {
+            url: remote.url.clone(),
+            token: None,
+        };
+        assert_eq!(
+            cli::list(&wrong).await,
+            Err("missing or wrong token".to_string())
+        );
+    }
+}
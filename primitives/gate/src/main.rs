@@ -0,0 +1,6 @@
+//! `gate` binary; the primitive itself lives in the library.
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    gate::run(std::env::args_os()).await
+}
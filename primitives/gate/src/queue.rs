@@ -0,0 +1,125 @@
+//! The messages waiting for a decision, kept in a state file so they
+//! survive restarts.
+//!
+//! The file is rewritten after every change, through a temporary file so a
+//! crash never leaves half of it.
+
+use emergent_client::types::CausationId;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// A message held for approval.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Held {
+    /// The id of the held message, which decisions refer to.
+    pub id: String,
+    pub message_type: String,
+    pub payload: Value,
+    /// Milliseconds since the Unix epoch.
+    pub received_at: u64,
+    /// The held message, for causation; not kept across restarts.
+    #[serde(skip)]
+    pub cause: Option<CausationId>,
+}
+
+/// The held messages, oldest first.
+#[derive(Debug)]
+pub struct Queue {
+    held: Vec<Held>,
+    path: PathBuf,
+}
+
+impl Queue {
+    /// The queue in `path`; a missing file is an empty queue.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let held = match fs::read_to_string(path) {
+            Ok(text) => {
+                serde_json::from_str(&text).map_err(|e| format!("{}: {e}", path.display()))?
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(format!("{}: {e}", path.display())),
+        };
+        Ok(Self {
+            held,
+            path: path.to_path_buf(),
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.held.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.held.is_empty()
+    }
+
+    /// The held messages, oldest first.
+    pub fn held(&self) -> &[Held] {
+        &self.held
+    }
+
+    /// Hold `message`; a message already held is not held twice.
+    pub fn hold(&mut self, message: Held) -> bool {
+        if self.held.iter().any(|held| held.id == message.id) {
+            return false;
+        }
+        self.held.push(message);
+        true
+    }
+
+    /// Release and return the message with `id`.
+    pub fn take(&mut self, id: &str) -> Option<Held> {
+        let at = self.held.iter().position(|held| held.id == id)?;
+        Some(self.held.remove(at))
+    }
+
+    /// Write the held messages to the state file.
+    pub fn save(&self) -> Result<(), String> {
+        let state = serde_json::to_vec_pretty(&self.held).map_err(|e| e.to_string())?;
+        let tmp = self.path.with_extension("tmp");
+        fs::write(&tmp, state)
+            .and_then(|()| fs::rename(&tmp, &self.path))
+            .map_err(|e| format!("{}: {e}", self.path.display()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use emergent_testkit::fixtures::TempDir;
+    use serde_json::json;
+
+    fn held(id: &str, received_at: u64) -> Held {
+        Held {
+            id: id.to_string(),
+            message_type: "deploy.requested".to_string(),
+            payload: json!({"service": "billing-api"}),
+            received_at,
+            cause: None,
+        }
+    }
+
+    #[test]
+    fn held_messages_survive_restarts_in_order() {
+        let dir = TempDir::new("gate");
+        let path = dir.path().join("pending.json");
+        let mut queue = Queue::load(&path).unwrap_or_else(|e| panic!("{e}"));
+        assert!(queue.is_empty());
+        assert!(queue.hold(held("msg_b", 2000)));
+        assert!(queue.hold(held("msg_a", 3000)));
+        assert!(!queue.hold(held("msg_b", 4000)));
+        queue.save().unwrap_or_else(|e| panic!("{e}"));
+
+        let mut restored = Queue::load(&path).unwrap_or_else(|e| panic!("{e}"));
+        let ids: Vec<&str> = restored.held().iter().map(|h| h.id.as_str()).collect();
+        assert_eq!(ids, ["msg_b", "msg_a"]);
+        assert_eq!(restored.take("msg_b").map(|h| h.received_at), Some(2000));
+        assert_eq!(restored.take("msg_b"), None);
+        assert_eq!(restored.len(), 1);
+
+        std::fs::write(&path, "not json").unwrap_or_else(|e| panic!("{e}"));
+        assert!(Queue::load(&path).is_err());
+    }
+}
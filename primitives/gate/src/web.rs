@@ -0,0 +1,150 @@
+//! The operator API.
+//!
+//! - `GET /pending`: the held messages, oldest first
+//! - `POST /pending/{id}/approve`: forward a held message downstream
+//! - `POST /pending/{id}/reject`: drop it
+//!
+//! Decisions take an optional JSON body with `by` and `reason`, recorded in
+//! the `gate.decided` event. With `--token`, every call needs
+//! `Authorization: Bearer <token>`. The handler's main loop owns the queue;
+//! calls reach it as [`Request`]s.
+
+use axum::Router;
+use axum::body::Bytes;
+use axum::extract::{Path, State};
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use serde::Deserialize;
+use serde_json::{Value, json};
+use std::sync::Arc;
+use tokio::sync::{mpsc, oneshot};
+
+/// Who decided and why, as given with a decision.
+#[derive(Debug, Default, Clone, PartialEq, Deserialize)]
+pub struct Note {
+    pub by: Option<String>,
+    pub reason: Option<String>,
+}
+
+/// A call for the main loop to answer.
+#[derive(Debug)]
+pub enum Request {
+    /// List the held messages.
+    List(oneshot::Sender<Vec<Value>>),
+    /// Approve or reject a held message: the `gate.decided` payload, or why
+    /// not.
+    Decide {
+        id: String,
+        approve: bool,
+        note: Note,
+        reply: oneshot::Sender<Result<Value, String>>,
+    },
+}
+
+/// What the API answers from.
+pub struct Web {
+    pub token: Option<String>,
+    pub requests: mpsc::Sender<Request>,
+}
+
+fn error(status: StatusCode, message: &str) -> Response {
+    (status, axum::Json(json!({ "error": message }))).into_response()
+}
+
+impl Web {
+    pub fn router(self: Arc<Self>) -> Router {
+        Router::new()
+            .route("/pending", get(pending))
+            .route("/pending/{id}/approve", post(approve))
+            .route("/pending/{id}/reject", post(reject))
+            .with_state(self)
+    }
+
+    /// Whether the request carries the token, if one is required.
+    fn authorized(&self, headers: &HeaderMap) -> bool {
+        let Some(token) = &self.token else {
+            return true;
+        };
+        let given = headers
+            .get("authorization")
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.strip_prefix("Bearer "));
+        given == Some(token.as_str())
+    }
+}
+
+fn unauthorized() -> Response {
+    error(StatusCode::UNAUTHORIZED, "missing or wrong token")
+}
+
+fn stopped() -> Response {
+    error(StatusCode::SERVICE_UNAVAILABLE, "gate is shutting down")
+}
+
+async fn pending(State(web): State<Arc<Web>>, headers: HeaderMap) -> Response {
+    if !web.authorized(&headers) {
+        return unauthorized();
+    }
+    let (reply, answer) = oneshot::channel();
+    if web.requests.send(Request::List(reply)).await.is_err() {
+        return stopped();
+    }
+    match answer.await {
+        Ok(pending) => axum::Json(json!({ "pending": pending })).into_response(),
+        Err(_) => stopped(),
+    }
+}
+
+async fn approve(
+    State(web): State<Arc<Web>>,
+    headers: HeaderMap,
+    Path(id): Path<String>,
+    body: Bytes,
+) -> Response {
+    decide(web, headers, id, true, body).await
+}
+
+async fn reject(
+    State(web): State<Arc<Web>>,
+    headers: HeaderMap,
+    Path(id): Path<String>,
+    body: Bytes,
+) -> Response {
+    decide(web, headers, id, false, body).await
+}
+
+async fn decide(
+    web: Arc<Web>,
+    headers: HeaderMap,
+    id: String,
+    approve: bool,
+    body: Bytes,
+) -> Response {
+    if !web.authorized(&headers) {
+        return unauthorized();
+    }
+    let note = if body.iter().all(u8::is_ascii_whitespace) {
+        Note::default()
+    } else {
+        match serde_json::from_slice(&body) {
+            Ok(note) => note,
+            Err(e) => return error(StatusCode::BAD_REQUEST, &format!("invalid body: {e}")),
+        }
+    };
+    let (reply, answer) = oneshot::channel();
+    let request = Request::Decide {
+        id,
+        approve,
+        note,
+        reply,
+    };
+    if web.requests.send(request).await.is_err() {
+        return stopped();
+    }
+    match answer.await {
+        Ok(Ok(decided)) => axum::Json(decided).into_response(),
+        Ok(Err(e)) => error(StatusCode::NOT_FOUND, &e),
+        Err(_) => stopped(),
+    }
+}
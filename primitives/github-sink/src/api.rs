@@ -0,0 +1,101 @@
+//! GitHub REST and GraphQL calls.
+//!
+//! Failures map onto handler error categories: transport errors are
+//! `request`, timeouts `timeout`, non-2xx responses and GraphQL `errors`
+//! `rejected`.
+
+use primitive_common::errors::{ErrorCategory, HandlerError};
+use reqwest::{Client, Method};
+use serde_json::{Value, json};
+use std::time::Duration;
+
+/// Authenticated access to one GitHub API endpoint.
+pub struct GitHub {
+    client: Client,
+    api_url: String,
+    token: String,
+    timeout: Duration,
+}
+
+impl GitHub {
+    pub fn new(client: Client, api_url: &str, token: &str, timeout: Duration) -> Self {
+        Self {
+            client,
+            api_url: api_url.trim_end_matches('/').to_string(),
+            token: token.to_string(),
+            timeout,
+        }
+    }
+
+    /// Call a REST endpoint, e.g. `rest(Method::POST, "/repos/o/r/milestones", Some(body))`.
+    pub async fn rest(
+        &self,
+        method: Method,
+        path: &str,
+        body: Option<&Value>,
+    ) -> Result<Value, HandlerError> {
+        let target = format!("{method} {path}");
+        let mut request = self
+            .client
+            .request(method, format!("{}{path}", self.api_url))
+            .timeout(self.timeout)
+            .bearer_auth(&self.token)
+            .header("Accept", "application/vnd.github+json")
+            .header("User-Agent", "emergent-github-sink");
+        if let Some(body) = body {
+            request = request.json(body);
+        }
+        let response = request.send().await.map_err(|e| transport(&target, &e))?;
+        let status = response.status();
+        let text = response.text().await.map_err(|e| transport(&target, &e))?;
+        if !status.is_success() {
+            return Err(HandlerError::new(
+                ErrorCategory::Rejected,
+                format!("{target}: HTTP {status}: {}", api_message(&text)),
+            ));
+        }
+        if text.trim().is_empty() {
+            return Ok(Value::Null);
+        }
+        serde_json::from_str(&text)
+            .map_err(|e| HandlerError::new(ErrorCategory::Parse, format!("{target}: {e}")))
+    }
+
+    /// Run a GraphQL query or mutation, returning its `data`.
+    pub async fn graphql(&self, query: &str, variables: Value) -> Result<Value, HandlerError> {
+        let body = json!({ "query": query, "variables": variables });
+        let mut response = self.rest(Method::POST, "/graphql", Some(&body)).await?;
+        if let Some(errors) = response.get("errors").and_then(Value::as_array)
+            && !errors.is_empty()
+        {
+            let messages: Vec<&str> = errors
+                .iter()
+                .filter_map(|e| e.get("message").and_then(Value::as_str))
+                .collect();
+            return Err(HandlerError::new(
+                ErrorCategory::Rejected,
+                format!("GraphQL: {}", messages.join("; ")),
+            ));
+        }
+        Ok(response
+            .get_mut("data")
+            .map(Value::take)
+            .unwrap_or(Value::Null))
+    }
+}
+
+fn transport(target: &str, e: &reqwest::Error) -> HandlerError {
+    if e.is_timeout() {
+        HandlerError::new(ErrorCategory::Timeout, format!("{target}: timed out"))
+    } else {
+        HandlerError::new(ErrorCategory::Request, format!("{target}: {e}"))
+    }
+}
+
+/// GitHub's `message` field from an error body, or the body itself.
+fn api_message(body: &str) -> String {
+    serde_json::from_str::<Value>(body)
+        .ok()
+        .and_then(|v| v.get("message").and_then(Value::as_str).map(str::to_string))
+        .unwrap_or_else(|| body.trim().to_string())
+}
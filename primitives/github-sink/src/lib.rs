@@ -0,0 +1,333 @@
+//! GitHub Sink - Manage Project Boards and Milestones from Events
+//!
+//! A Sink that turns each payload into one GitHub operation, named in its
+//! `op` field (see [`ops`]): adding issues and pull requests to Projects v2
+//! boards, setting their single-select, iteration and other fields, moving
+//! them between columns (see [`projects`]), and creating, assigning and
+//! closing milestones (see [`milestones`]). Failed API calls fail the
+//! message, so the harness retries and dead-letters it.
+//!
+//! # Examples
+//!
+//! ```bash
+//! # Apply whatever operations upstream transforms emit
+//! github-sink -s github.op --token $GITHUB_TOKEN
+//!
+//! # GitHub Enterprise Server
+//! github-sink -s 'board.*' -s 'release.*' \
+//!   --api-url https://github.example.com/api/v3 --token $GHE_TOKEN
+//! ```
+//!
+//! Board columns are the options of a single-select field, `Status` unless
+//! `project.move_item` names another:
+//!
+//! ```json
+//! {"op": "project.move_item", "project_id": "PVT_kwDO...", "item_id": "PVTI_...", "column": "In Review"}
+//! ```
+
+pub mod api;
+pub mod milestones;
+pub mod ops;
+pub mod projects;
+
+use api::GitHub;
+use clap::Parser;
+use emergent_client::EmergentMessage;
+use ops::Operation;
+use primitive_common::capabilities::VERSION;
+use primitive_common::errors::{ErrorCategory, HandlerError};
+use primitive_common::{SinkArgs, SinkConfig, SinkContext, SinkHandler, run_sink};
+use projects::Projects;
+use reqwest::Client;
+use serde_json::{Value, json};
+use std::time::Duration;
+
+/// GitHub Sink — manage project boards and milestones from events.
+#[derive(Parser, Debug)]
+#[command(name = "github_sink", version = VERSION)]
+#[command(about = "Manage GitHub project boards and milestones from events")]
+struct Args {
+    /// Message types to subscribe to.
+    #[arg(short, long = "subscribe", required = true)]
+    subscribe: Vec<String>,
+
+    /// Token with `project` and `repo` (or fine-grained issues/projects) access.
+    #[arg(long, env = "GITHUB_TOKEN", hide_env_values = true)]
+    token: String,
+
+    /// API root; `https://<host>/api/v3` for GitHub Enterprise Server.
+    #[arg(long, env = "GITHUB_API_URL", default_value = "https://api.github.com")]
+    api_url: String,
+
+    /// Per-request timeout in milliseconds.
+    #[arg(short, long, env = "GITHUB_SINK_TIMEOUT", default_value = "30000")]
+    timeout: u64,
+
+    /// Seconds to cache each project's fields and options.
+    #[arg(long, env = "GITHUB_SINK_FIELD_CACHE_TTL", default_value = "300")]
+    field_cache_ttl: u64,
+
+    #[command(flatten)]
+    sink: SinkArgs,
+}
+
+/// Applies the operation each payload carries.
+struct GitHubSink {
+    github: GitHub,
+    projects: Projects,
+}
+
+impl GitHubSink {
+    async fn apply(&self, op: &Operation) -> Result<(), HandlerError> {
+        let github = &self.github;
+        match op {
+            Operation::ProjectAddItem {
+                project_id,
+                content_id,
+                fields,
+            } => {
+                let item_id = self
+                    .projects
+                    .add_item(github, project_id, content_id)
+                    .await?;
+                for (field, value) in fields {
+                    self.projects
+                        .set_field(github, project_id, &item_id, field, value)
+                        .await?;
+                }
+                Ok(())
+            }
+            Operation::ProjectSetField {
+                project_id,
+                item_id,
+                field,
+                value,
+            } => {
+                self.projects
+                    .set_field(github, project_id, item_id, field, value)
+                    .await
+            }
+            Operation::ProjectMoveItem {
+                project_id,
+                item_id,
+                column,
+                field,
+            } => {
+                let column = Value::String(column.clone());
+                self.projects
+                    .set_field(github, project_id, item_id, field, &column)
+                    .await
+            }
+            Operation::MilestoneCreate {
+                repo,
+                title,
+                description,
+                due_on,
+            } => milestones::create(
+                github,
+                repo,
+                title,
+                description.as_deref(),
+                due_on.as_deref(),
+            )
+            .await
+            .map(|_| ()),
+            Operation::MilestoneAssign {
+                repo,
+                issue,
+                milestone,
+            } => milestones::assign(github, repo, *issue, milestone).await,
+            Operation::MilestoneClose { repo, milestone } => {
+                milestones::close(github, repo, milestone).await
+            }
+        }
+    }
+}
+
+impl SinkHandler for GitHubSink {
+    async fn handle(
+        &self,
+        _msg: &EmergentMessage,
+        ctx: &SinkContext<'_>,
+    ) -> Result<(), HandlerError> {
+        let op = Operation::parse(ctx.payload())
+            .map_err(|e| HandlerError::new(ErrorCategory::Parse, e))?;
+
+        if ctx.is_dry_run() {
+            let detail = serde_json::to_value(&op).unwrap_or_else(|_| json!({}));
+            ctx.would_have("github", detail).await;
+            return Ok(());
+        }
+
+        self.apply(&op)
+            .await
+            .map_err(|e| HandlerError::new(e.category, format!("{}: {}", op.name(), e.message)))
+    }
+}
+
+/// Run the primitive with `args` as its command line (the first item is
+/// the program name, as with `std::env::args_os`).
+pub async fn run<I, T>(args: I) -> Result<(), Box<dyn std::error::Error>>
+where
+    I: IntoIterator<Item = T>,
+    T: Into<std::ffi::OsString> + Clone,
+{
+    let args = Args::parse_from(args);
+
+    let config = SinkConfig {
+        name: "github_sink",
+        subscribe: &args.subscribe,
+        would_have_as: "github.would_have",
+        dead_letter_as: "github.dead_letter",
+        settings: &args,
+    };
+    let handler = GitHubSink {
+        github: GitHub::new(
+            Client::new(),
+            &args.api_url,
+            &args.token,
+            Duration::from_millis(args.timeout),
+        ),
+        projects: Projects::new(Duration::from_secs(args.field_cache_ttl)),
+    };
+
+    run_sink(config, &args.sink, handler).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{Json, Router, extract::Request, routing::any};
+    use emergent_testkit::{SinkFixture, fixtures, spawn_sink};
+    use tokio::sync::mpsc;
+
+    /// Serve a fake GitHub API on a random port, forwarding each request's
+    /// method, path and JSON body.
+    async fn server() -> (String, mpsc::UnboundedReceiver<(String, String, Value)>) {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let app = Router::new().fallback(any(move |request: Request| {
+            let tx = tx.clone();
+            async move {
+                let method = request.method().to_string();
+                let path = request.uri().path().to_string();
+                let body = axum::body::to_bytes(request.into_body(), usize::MAX)
+                    .await
+                    .unwrap_or_default();
+                let body: Value = serde_json::from_slice(&body).unwrap_or(Value::Null);
+                let reply = match (method.as_str(), path.as_str()) {
+                    ("GET", "/repos/acme/api/milestones") => json!([
+                        {"number": 4, "title": "v2.1"}
+                    ]),
+                    ("POST", "/graphql")
+                        if body["query"]
+                            .as_str()
+                            .is_some_and(|q| q.starts_with("query")) =>
+                    {
+                        json!({"data": {"node": {"fields": {"nodes": [
+                            {"id": "F_status", "name": "Status", "dataType": "SINGLE_SELECT",
+                             "options": [{"id": "O_review", "name": "In Review"}]}
+                        ]}}}})
+                    }
+                    ("POST", "/graphql") => json!({"data": {}}),
+                    _ => json!({}),
+                };
+                let _ = tx.send((method, path, body));
+                Json(reply)
+            }
+        }));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap_or_else(|e| panic!("bind: {e}"));
+        let addr = listener
+            .local_addr()
+            .unwrap_or_else(|e| panic!("local_addr: {e}"));
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        (format!("http://{addr}"), rx)
+    }
+
+    fn github_sink(api_url: &str) -> GitHubSink {
+        GitHubSink {
+            github: GitHub::new(Client::new(), api_url, "t0ken", Duration::from_secs(5)),
+            projects: Projects::new(Duration::from_secs(60)),
+        }
+    }
+
+    #[tokio::test]
+    async fn milestones_and_columns_are_resolved_by_name() {
+        let (base, mut received) = server().await;
+        let (mut engine, run) = spawn_sink(
+            SinkFixture::new("github_sink", "github"),
+            SinkArgs::default(),
+            github_sink(&base),
+        );
+        let mut next = async || {
+            received
+                .recv()
+                .await
+                .unwrap_or_else(|| panic!("no request"))
+        };
+
+        engine
+            .inject_message(fixtures::message(
+                "github.op",
+                json!({"op": "milestone.assign", "repo": "acme/api", "issue": 42, "milestone": "V2.1"}),
+            ))
+            .await;
+        let (method, path, _) = next().await;
+        assert_eq!(
+            (method.as_str(), path.as_str()),
+            ("GET", "/repos/acme/api/milestones")
+        );
+        let (method, path, body) = next().await;
+        assert_eq!(
+            (method.as_str(), path.as_str()),
+            ("PATCH", "/repos/acme/api/issues/42")
+        );
+        assert_eq!(body, json!({"milestone": 4}));
+
+        engine
+            .inject_message(fixtures::message(
+                "github.op",
+                json!({"op": "project.move_item", "project_id": "PVT_1", "item_id": "PVTI_1", "column": "in review"}),
+            ))
+            .await;
+        let (_, _, fields) = next().await;
+        assert_eq!(fields["variables"], json!({"project": "PVT_1"}));
+        let (_, _, update) = next().await;
+        assert_eq!(
+            update["variables"],
+            json!({"project": "PVT_1", "item": "PVTI_1", "field": "F_status",
+                   "value": {"singleSelectOptionId": "O_review"}})
+        );
+
+        engine.close();
+        assert_eq!(run.await.ok(), Some(Ok(())));
+    }
+
+    #[tokio::test]
+    async fn dry_run_reports_the_operation() {
+        let args = SinkArgs {
+            dry_run: true,
+            ..Default::default()
+        };
+        let (mut engine, run) = spawn_sink(
+            SinkFixture::new("github_sink", "github"),
+            args,
+            github_sink("http://127.0.0.1:9"),
+        );
+
+        engine
+            .inject_message(fixtures::message(
+                "github.op",
+                json!({"op": "milestone.close", "repo": "acme/api", "milestone": 3}),
+            ))
+            .await;
+        let report = engine.expect_published("github.would_have").await;
+        assert_eq!(report.payload()["action"], "github");
+        assert_eq!(report.payload()["detail"]["op"], "milestone.close");
+        assert_eq!(report.payload()["detail"]["milestone"], 3);
+
+        engine.close();
+        assert_eq!(run.await.ok(), Some(Ok(())));
+    }
+}
@@ -0,0 +1,128 @@
+//! Milestone management through the REST API.
+//!
+//! Milestones given by title are looked up among the repository's open and
+//! closed milestones; titles match case-insensitively.
+
+use crate::api::GitHub;
+use crate::ops::MilestoneRef;
+use primitive_common::errors::{ErrorCategory, HandlerError};
+use reqwest::Method;
+use serde_json::{Map, Value, json};
+
+/// Create a milestone, returning its number.
+pub async fn create(
+    github: &GitHub,
+    repo: &str,
+    title: &str,
+    description: Option<&str>,
+    due_on: Option<&str>,
+) -> Result<u64, HandlerError> {
+    let mut body = Map::new();
+    body.insert("title".to_string(), json!(title));
+    if let Some(description) = description {
+        body.insert("description".to_string(), json!(description));
+    }
+    if let Some(due_on) = due_on {
+        body.insert("due_on".to_string(), json!(due_on));
+    }
+    let created = github
+        .rest(
+            Method::POST,
+            &format!("/repos/{repo}/milestones"),
+            Some(&Value::Object(body)),
+        )
+        .await?;
+    created["number"]
+        .as_u64()
+        .ok_or_else(|| HandlerError::new(ErrorCategory::Parse, "created milestone has no number"))
+}
+
+/// Put issue (or pull request) `issue` on a milestone.
+pub async fn assign(
+    github: &GitHub,
+    repo: &str,
+    issue: u64,
+    milestone: &MilestoneRef,
+) -> Result<(), HandlerError> {
+    let number = number(github, repo, milestone).await?;
+    github
+        .rest(
+            Method::PATCH,
+            &format!("/repos/{repo}/issues/{issue}"),
+            Some(&json!({ "milestone": number })),
+        )
+        .await
+        .map(|_| ())
+}
+
+/// Close a milestone.
+pub async fn close(
+    github: &GitHub,
+    repo: &str,
+    milestone: &MilestoneRef,
+) -> Result<(), HandlerError> {
+    let number = number(github, repo, milestone).await?;
+    github
+        .rest(
+            Method::PATCH,
+            &format!("/repos/{repo}/milestones/{number}"),
+            Some(&json!({ "state": "closed" })),
+        )
+        .await
+        .map(|_| ())
+}
+
+/// The number of a milestone given by number or title.
+async fn number(
+    github: &GitHub,
+    repo: &str,
+    milestone: &MilestoneRef,
+) -> Result<u64, HandlerError> {
+    let title = match milestone {
+        MilestoneRef::Number(number) => return Ok(*number),
+        MilestoneRef::Title(title) => title,
+    };
+    let listed = github
+        .rest(
+            Method::GET,
+            &format!("/repos/{repo}/milestones?state=all&per_page=100"),
+            None,
+        )
+        .await?;
+    find(&listed, title).ok_or_else(|| {
+        HandlerError::new(
+            ErrorCategory::Rejected,
+            format!("{repo} has no milestone '{title}'"),
+        )
+    })
+}
+
+/// The number of the milestone titled `title` in a listing.
+fn find(listed: &Value, title: &str) -> Option<u64> {
+    listed
+        .as_array()?
+        .iter()
+        .find(|m| {
+            m["title"]
+                .as_str()
+                .is_some_and(|t| t.eq_ignore_ascii_case(title))
+        })
+        .and_then(|m| m["number"].as_u64())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn titles_find_milestone_numbers() {
+        let listed = json!([
+            {"number": 3, "title": "v2.0", "state": "closed"},
+            {"number": 7, "title": "v2.1", "state": "open"}
+        ]);
+        assert_eq!(find(&listed, "V2.1"), Some(7));
+        assert_eq!(find(&listed, "v2.0"), Some(3));
+        assert_eq!(find(&listed, "v3"), None);
+        assert_eq!(find(&json!({}), "v3"), None);
+    }
+}
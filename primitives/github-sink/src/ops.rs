@@ -0,0 +1,197 @@
+//! Operations carried in payloads.
+//!
+//! Every payload names its operation in `op`:
+//!
+//! ```json
+//! {"op": "project.add_item", "project_id": "PVT_kwDO...", "content_id": "I_kwDO...",
+//!  "fields": {"Status": "Triage", "Priority": "P1"}}
+//! {"op": "project.set_field", "project_id": "PVT_...", "item_id": "PVTI_...", "field": "Sprint", "value": "Sprint 12"}
+//! {"op": "project.move_item", "project_id": "PVT_...", "item_id": "PVTI_...", "column": "In Progress"}
+//! {"op": "milestone.create", "repo": "acme/api", "title": "v2.1", "due_on": "2026-11-01T00:00:00Z"}
+//! {"op": "milestone.assign", "repo": "acme/api", "issue": 42, "milestone": "v2.1"}
+//! {"op": "milestone.close", "repo": "acme/api", "milestone": "v2.0"}
+//! ```
+//!
+//! Projects v2 ids are GraphQL node ids; fields, single-select options and
+//! iterations are named as they appear on the board (see
+//! [`crate::projects`]). Milestones may be given by number or title (see
+//! [`crate::milestones`]).
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+/// Board column field used by `project.move_item` unless `field` is given.
+pub const DEFAULT_COLUMN_FIELD: &str = "Status";
+
+fn default_column_field() -> String {
+    DEFAULT_COLUMN_FIELD.to_string()
+}
+
+/// A milestone by number or by title.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum MilestoneRef {
+    Number(u64),
+    Title(String),
+}
+
+/// One operation against GitHub.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "op", deny_unknown_fields)]
+pub enum Operation {
+    /// Add an issue or pull request to a project, optionally setting fields.
+    #[serde(rename = "project.add_item")]
+    ProjectAddItem {
+        project_id: String,
+        /// Node id of the issue or pull request.
+        content_id: String,
+        #[serde(default)]
+        fields: Map<String, Value>,
+    },
+    /// Set one field of a project item.
+    #[serde(rename = "project.set_field")]
+    ProjectSetField {
+        project_id: String,
+        item_id: String,
+        field: String,
+        value: Value,
+    },
+    /// Move a project item to another board column.
+    #[serde(rename = "project.move_item")]
+    ProjectMoveItem {
+        project_id: String,
+        item_id: String,
+        column: String,
+        #[serde(default = "default_column_field")]
+        field: String,
+    },
+    #[serde(rename = "milestone.create")]
+    MilestoneCreate {
+        repo: String,
+        title: String,
+        #[serde(default)]
+        description: Option<String>,
+        /// ISO 8601 timestamp.
+        #[serde(default)]
+        due_on: Option<String>,
+    },
+    /// Put an issue or pull request on a milestone.
+    #[serde(rename = "milestone.assign")]
+    MilestoneAssign {
+        repo: String,
+        issue: u64,
+        milestone: MilestoneRef,
+    },
+    #[serde(rename = "milestone.close")]
+    MilestoneClose {
+        repo: String,
+        milestone: MilestoneRef,
+    },
+}
+
+impl Operation {
+    /// Read the operation from a payload.
+    pub fn parse(payload: &Value) -> Result<Self, String> {
+        let op = Self::deserialize(payload).map_err(|e| format!("invalid operation: {e}"))?;
+        op.validate()?;
+        Ok(op)
+    }
+
+    /// The `op` name, for logs and errors.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::ProjectAddItem { .. } => "project.add_item",
+            Self::ProjectSetField { .. } => "project.set_field",
+            Self::ProjectMoveItem { .. } => "project.move_item",
+            Self::MilestoneCreate { .. } => "milestone.create",
+            Self::MilestoneAssign { .. } => "milestone.assign",
+            Self::MilestoneClose { .. } => "milestone.close",
+        }
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        match self {
+            Self::MilestoneCreate { repo, title, .. } => {
+                check_repo(repo)?;
+                if title.trim().is_empty() {
+                    return Err("milestone.create needs a title".to_string());
+                }
+                Ok(())
+            }
+            Self::MilestoneAssign { repo, .. } | Self::MilestoneClose { repo, .. } => {
+                check_repo(repo)
+            }
+            Self::ProjectAddItem { project_id, .. }
+            | Self::ProjectSetField { project_id, .. }
+            | Self::ProjectMoveItem { project_id, .. }
+                if project_id.trim().is_empty() =>
+            {
+                Err(format!("{} needs a project_id", self.name()))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Check that `repo` is `owner/name`.
+fn check_repo(repo: &str) -> Result<(), String> {
+    match repo.split_once('/') {
+        Some((owner, name)) if !owner.is_empty() && !name.is_empty() && !name.contains('/') => {
+            Ok(())
+        }
+        _ => Err(format!("repo must be owner/name, got '{repo}'")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn operations_parse_with_defaults() {
+        let moved = Operation::parse(&json!({
+            "op": "project.move_item", "project_id": "PVT_1", "item_id": "PVTI_1", "column": "Done"
+        }));
+        assert_eq!(
+            moved,
+            Ok(Operation::ProjectMoveItem {
+                project_id: "PVT_1".to_string(),
+                item_id: "PVTI_1".to_string(),
+                column: "Done".to_string(),
+                field: "Status".to_string(),
+            })
+        );
+
+        let assign = Operation::parse(&json!({
+            "op": "milestone.assign", "repo": "acme/api", "issue": 42, "milestone": 3
+        }));
+        assert_eq!(
+            assign.map(|op| match op {
+                Operation::MilestoneAssign { milestone, .. } => Some(milestone),
+                _ => None,
+            }),
+            Ok(Some(MilestoneRef::Number(3)))
+        );
+    }
+
+    #[test]
+    fn invalid_operations_are_rejected() {
+        assert!(Operation::parse(&json!({"op": "repo.delete", "repo": "acme/api"})).is_err());
+        assert!(
+            Operation::parse(&json!({"op": "milestone.close", "repo": "acme", "milestone": 1}))
+                .is_err()
+        );
+        assert!(
+            Operation::parse(&json!({"op": "milestone.create", "repo": "acme/api", "title": " "}))
+                .is_err()
+        );
+        assert!(
+            Operation::parse(&json!({
+                "op": "project.set_field", "project_id": "", "item_id": "i", "field": "f", "value": 1
+            }))
+            .is_err()
+        );
+        assert!(Operation::parse(&json!({"repo": "acme/api"})).is_err());
+    }
+}
@@ -0,0 +1,290 @@
+//! Projects v2 items and fields.
+//!
+//! Boards are addressed by name the way people see them: a field such as
+//! `Status` or `Sprint`, and for single-select fields an option
+//! (`In Progress`), for iteration fields an iteration title (`Sprint 12`).
+//! Names are matched case-insensitively and resolved to node ids with one
+//! query per project, cached for `--field-cache-ttl` seconds; a name that is
+//! not found refreshes the cache once before failing, so newly added
+//! options work without a restart.
+//!
+//! Text, number and date fields take their value as given (`"2026-11-01"`
+//! for dates).
+
+use crate::api::GitHub;
+use primitive_common::errors::{ErrorCategory, HandlerError};
+use serde_json::{Value, json};
+use std::{
+    collections::HashMap,
+    sync::{Mutex, PoisonError},
+    time::{Duration, Instant},
+};
+
+const FIELDS_QUERY: &str = r"query($project: ID!) {
+  node(id: $project) {
+    ... on ProjectV2 {
+      fields(first: 100) {
+        nodes {
+          ... on ProjectV2FieldCommon { id name dataType }
+          ... on ProjectV2SingleSelectField { options { id name } }
+          ... on ProjectV2IterationField {
+            configuration {
+              iterations { id title }
+              completedIterations { id title }
+            }
+          }
+        }
+      }
+    }
+  }
+}";
+
+const ADD_ITEM: &str = r"mutation($project: ID!, $content: ID!) {
+  addProjectV2ItemById(input: {projectId: $project, contentId: $content}) { item { id } }
+}";
+
+const UPDATE_FIELD: &str = r"mutation($project: ID!, $item: ID!, $field: ID!, $value: ProjectV2FieldValue!) {
+  updateProjectV2ItemFieldValue(input: {projectId: $project, itemId: $item, fieldId: $field, value: $value}) {
+    projectV2Item { id }
+  }
+}";
+
+/// One project field and the choices it offers.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Field {
+    pub id: String,
+    pub name: String,
+    /// GraphQL `ProjectV2FieldType`, e.g. `SINGLE_SELECT`.
+    pub data_type: String,
+    /// `(id, name)` of single-select options or iterations.
+    pub choices: Vec<(String, String)>,
+}
+
+impl Field {
+    /// The `ProjectV2FieldValue` setting this field to `value`.
+    pub fn value(&self, value: &Value) -> Result<Value, String> {
+        let text = || match value {
+            Value::String(s) => s.clone(),
+            other => other.to_string(),
+        };
+        let choice = |kind: &str| {
+            let wanted = text();
+            self.choices
+                .iter()
+                .find(|(_, name)| name.eq_ignore_ascii_case(&wanted))
+                .map(|(id, _)| id.clone())
+                .ok_or_else(|| format!("field '{}' has no {kind} '{wanted}'", self.name))
+        };
+        match self.data_type.as_str() {
+            "SINGLE_SELECT" => Ok(json!({ "singleSelectOptionId": choice("option")? })),
+            "ITERATION" => Ok(json!({ "iterationId": choice("iteration")? })),
+            "TEXT" => Ok(json!({ "text": text() })),
+            "DATE" => Ok(json!({ "date": text() })),
+            "NUMBER" => value
+                .as_f64()
+                .or_else(|| value.as_str().and_then(|s| s.parse().ok()))
+                .map(|n| json!({ "number": n }))
+                .ok_or_else(|| format!("field '{}' needs a number", self.name)),
+            other => Err(format!(
+                "field '{}' is of type {other}, which cannot be set",
+                self.name
+            )),
+        }
+    }
+}
+
+/// Parse the `fields` of a [`FIELDS_QUERY`] response.
+pub fn parse_fields(data: &Value) -> Vec<Field> {
+    let nodes = data["node"]["fields"]["nodes"]
+        .as_array()
+        .cloned()
+        .unwrap_or_default();
+    nodes
+        .iter()
+        .filter_map(|node| {
+            let id = node["id"].as_str()?.to_string();
+            let name = node["name"].as_str()?.to_string();
+            let data_type = node["dataType"].as_str().unwrap_or_default().to_string();
+            let config = &node["configuration"];
+            let choices = [
+                &node["options"],
+                &config["iterations"],
+                &config["completedIterations"],
+            ]
+            .into_iter()
+            .filter_map(Value::as_array)
+            .flatten()
+            .filter_map(|choice| {
+                let id = choice["id"].as_str()?;
+                let name = choice["name"].as_str().or(choice["title"].as_str())?;
+                Some((id.to_string(), name.to_string()))
+            })
+            .collect();
+            Some(Field {
+                id,
+                name,
+                data_type,
+                choices,
+            })
+        })
+        .collect()
+}
+
+/// The id of the field named (or with id) `field`, and the value setting it.
+fn lookup(
+    fields: &[Field],
+    project_id: &str,
+    field: &str,
+    value: &Value,
+) -> Result<(String, Value), String> {
+    let field = fields
+        .iter()
+        .find(|f| f.name.eq_ignore_ascii_case(field) || f.id == field)
+        .ok_or_else(|| format!("project {project_id} has no field '{field}'"))?;
+    Ok((field.id.clone(), field.value(value)?))
+}
+
+/// Project fields by project id, with the time they were fetched.
+pub struct Projects {
+    ttl: Duration,
+    fields: Mutex<HashMap<String, (Instant, Vec<Field>)>>,
+}
+
+impl Projects {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            fields: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Add `content_id` to the project, returning the new item's id.
+    pub async fn add_item(
+        &self,
+        github: &GitHub,
+        project_id: &str,
+        content_id: &str,
+    ) -> Result<String, HandlerError> {
+        let data = github
+            .graphql(
+                ADD_ITEM,
+                json!({ "project": project_id, "content": content_id }),
+            )
+            .await?;
+        data["addProjectV2ItemById"]["item"]["id"]
+            .as_str()
+            .map(str::to_string)
+            .ok_or_else(|| {
+                HandlerError::new(
+                    ErrorCategory::Parse,
+                    "addProjectV2ItemById returned no item id",
+                )
+            })
+    }
+
+    /// Set the field named `field` of an item to `value`.
+    pub async fn set_field(
+        &self,
+        github: &GitHub,
+        project_id: &str,
+        item_id: &str,
+        field: &str,
+        value: &Value,
+    ) -> Result<(), HandlerError> {
+        let cached = self.fields(github, project_id, false).await?;
+        let (field_id, value) = match lookup(&cached, project_id, field, value) {
+            Ok(resolved) => resolved,
+            // The board may have changed since it was cached
+            Err(_) => {
+                let fresh = self.fields(github, project_id, true).await?;
+                lookup(&fresh, project_id, field, value)
+                    .map_err(|e| HandlerError::new(ErrorCategory::Rejected, e))?
+            }
+        };
+        let variables = json!({
+            "project": project_id,
+            "item": item_id,
+            "field": field_id,
+            "value": value,
+        });
+        github.graphql(UPDATE_FIELD, variables).await.map(|_| ())
+    }
+
+    async fn fields(
+        &self,
+        github: &GitHub,
+        project_id: &str,
+        refresh: bool,
+    ) -> Result<Vec<Field>, HandlerError> {
+        if !refresh {
+            let cached = self.fields.lock().unwrap_or_else(PoisonError::into_inner);
+            if let Some((fetched, fields)) = cached.get(project_id)
+                && fetched.elapsed() < self.ttl
+            {
+                return Ok(fields.clone());
+            }
+        }
+        let data = github
+            .graphql(FIELDS_QUERY, json!({ "project": project_id }))
+            .await?;
+        if data["node"].is_null() {
+            return Err(HandlerError::new(
+                ErrorCategory::Rejected,
+                format!("project {project_id} not found"),
+            ));
+        }
+        let fields = parse_fields(&data);
+        self.fields
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(project_id.to_string(), (Instant::now(), fields.clone()));
+        Ok(fields)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn board() -> Value {
+        json!({"node": {"fields": {"nodes": [
+            {"id": "F_title", "name": "Title", "dataType": "TITLE"},
+            {"id": "F_status", "name": "Status", "dataType": "SINGLE_SELECT",
+             "options": [{"id": "O_todo", "name": "Todo"}, {"id": "O_wip", "name": "In Progress"}]},
+            {"id": "F_sprint", "name": "Sprint", "dataType": "ITERATION",
+             "configuration": {"iterations": [{"id": "IT_12", "title": "Sprint 12"}],
+                               "completedIterations": [{"id": "IT_11", "title": "Sprint 11"}]}},
+            {"id": "F_points", "name": "Points", "dataType": "NUMBER"},
+            {}
+        ]}}})
+    }
+
+    #[test]
+    fn fields_parse_with_their_choices() {
+        let fields = parse_fields(&board());
+        assert_eq!(fields.len(), 4);
+        assert_eq!(
+            fields[2].choices,
+            vec![
+                ("IT_12".to_string(), "Sprint 12".to_string()),
+                ("IT_11".to_string(), "Sprint 11".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn values_resolve_by_field_type() {
+        let fields = parse_fields(&board());
+        assert_eq!(
+            fields[1].value(&json!("in progress")),
+            Ok(json!({"singleSelectOptionId": "O_wip"}))
+        );
+        assert_eq!(
+            fields[2].value(&json!("Sprint 11")),
+            Ok(json!({"iterationId": "IT_11"}))
+        );
+        assert_eq!(fields[3].value(&json!("5")), Ok(json!({"number": 5.0})));
+        assert!(fields[1].value(&json!("Blocked")).is_err());
+        assert!(fields[0].value(&json!("x")).is_err());
+    }
+}
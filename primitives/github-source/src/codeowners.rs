@@ -0,0 +1,180 @@
+//! CODEOWNERS parsing and matching.
+//!
+//! Follows GitHub's dialect of gitignore patterns: a leading `/` or a `/`
+//! inside the pattern anchors it to the repository root, otherwise it
+//! matches at any depth; a trailing `/` matches only directories (and so
+//! everything below them); `*` and `?` stay within one path segment and
+//! `**` spans any number. A pattern names a directory's whole subtree,
+//! except that a final `*` segment (`docs/*`) stops at its direct children.
+//! Negation (`!`) and character ranges are not supported by GitHub and
+//! such lines are skipped. The last matching rule wins, and a rule with no
+//! owners leaves its paths unowned.
+
+/// GitHub's CODEOWNERS locations, in the order it looks for them.
+pub const LOCATIONS: &[&str] = &[".github/CODEOWNERS", "CODEOWNERS", "docs/CODEOWNERS"];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Rule {
+    segments: Vec<String>,
+    /// Trailing `/`: the pattern names a directory, never a file.
+    directory: bool,
+    owners: Vec<String>,
+}
+
+impl Rule {
+    fn parse(line: &str) -> Option<Self> {
+        let mut words = line.split_whitespace();
+        let pattern = words.next()?;
+        let pattern = pattern.strip_prefix('\\').unwrap_or(pattern);
+        if pattern.starts_with('!') || pattern.contains('[') {
+            return None;
+        }
+        let directory = pattern.ends_with('/');
+        let trimmed = pattern.trim_end_matches('/');
+        let anchored = trimmed.starts_with('/') || trimmed.contains('/');
+        let mut segments: Vec<String> = trimmed
+            .trim_start_matches('/')
+            .split('/')
+            .filter(|s| !s.is_empty())
+            .map(str::to_string)
+            .collect();
+        if segments.is_empty() {
+            // `/` or `*` alone: the whole repository
+            segments.push("**".to_string());
+        } else if !anchored {
+            segments.insert(0, "**".to_string());
+        }
+        let owners = words
+            .take_while(|w| !w.starts_with('#'))
+            .map(str::to_string)
+            .collect();
+        Some(Self {
+            segments,
+            directory,
+            owners,
+        })
+    }
+
+    fn matches(&self, path: &[&str]) -> bool {
+        let subtree = self.segments.last().is_none_or(|s| s != "*");
+        walk(&self.segments, path, subtree, !self.directory)
+    }
+}
+
+/// Whether `pattern` matches all of `path` (when `whole` is allowed) or a
+/// leading directory of it (when `subtree` is allowed).
+fn walk(pattern: &[String], path: &[&str], subtree: bool, whole: bool) -> bool {
+    match pattern.split_first() {
+        None if path.is_empty() => whole,
+        None => subtree,
+        Some((any, rest)) if any == "**" => {
+            (0..=path.len()).any(|skip| walk(rest, &path[skip..], subtree, whole))
+        }
+        Some((segment, rest)) => match path.split_first() {
+            Some((name, remaining)) => {
+                glob(segment.as_bytes(), name.as_bytes()) && walk(rest, remaining, subtree, whole)
+            }
+            None => false,
+        },
+    }
+}
+
+/// Match one path segment against a pattern segment with `*` and `?`.
+fn glob(pattern: &[u8], name: &[u8]) -> bool {
+    match pattern.split_first() {
+        None => name.is_empty(),
+        Some((b'*', rest)) => (0..=name.len()).any(|skip| glob(rest, &name[skip..])),
+        Some((b'?', rest)) => !name.is_empty() && glob(rest, &name[1..]),
+        Some((c, rest)) => name.first() == Some(c) && glob(rest, &name[1..]),
+    }
+}
+
+/// A parsed CODEOWNERS file.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CodeOwners {
+    rules: Vec<Rule>,
+}
+
+impl CodeOwners {
+    pub fn parse(text: &str) -> Self {
+        let rules = text
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .filter_map(Rule::parse)
+            .collect();
+        Self { rules }
+    }
+
+    /// Owners of `path` (relative to the repository root), by the last
+    /// matching rule; empty when no rule matches or the rule names no owners.
+    pub fn owners(&self, path: &str) -> &[String] {
+        let segments: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+        self.rules
+            .iter()
+            .rev()
+            .find(|rule| rule.matches(&segments))
+            .map(|rule| rule.owners.as_slice())
+            .unwrap_or_default()
+    }
+}
+
+/// What kind of reviewer an owner entry names.
+pub fn owner_kind(owner: &str) -> &'static str {
+    match owner.strip_prefix('@') {
+        Some(name) if name.contains('/') => "team",
+        Some(_) => "user",
+        None => "email",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FILE: &str = r"
+# Default owners
+*                   @acme/core
+*.js                @js-owner # trailing comment
+/build/logs/        @doctocat
+docs/*              docs@example.com
+apps/               @octocat
+/scripts/**/deploy  @acme/ops
+/vendor/
+!ignored            @nobody
+";
+
+    fn owners(path: &str) -> Vec<String> {
+        CodeOwners::parse(FILE).owners(path).to_vec()
+    }
+
+    #[test]
+    fn last_matching_rule_wins() {
+        assert_eq!(owners("src/main.rs"), ["@acme/core"]);
+        assert_eq!(owners("web/app.js"), ["@js-owner"]);
+        assert_eq!(owners("build/logs/today/out.txt"), ["@doctocat"]);
+        assert_eq!(owners("docs/intro.md"), ["docs@example.com"]);
+        assert_eq!(owners("docs/guides/intro.md"), ["@acme/core"]);
+        assert_eq!(owners("nested/apps/web/index.html"), ["@octocat"]);
+        assert_eq!(owners("scripts/deploy"), ["@acme/ops"]);
+        assert_eq!(owners("scripts/prod/eu/deploy"), ["@acme/ops"]);
+        assert!(owners("vendor/lib/code.c").is_empty());
+    }
+
+    #[test]
+    fn directory_patterns_do_not_match_files() {
+        let rules = CodeOwners::parse("apps/ @octocat\n/logs @ops");
+        assert!(rules.owners("apps").is_empty());
+        assert_eq!(rules.owners("apps/x"), ["@octocat"]);
+        assert_eq!(rules.owners("logs"), ["@ops"]);
+        assert_eq!(rules.owners("logs/x/y"), ["@ops"]);
+        assert!(rules.owners("src/logs").is_empty());
+    }
+
+    #[test]
+    fn owner_kinds() {
+        assert_eq!(owner_kind("@acme/core"), "team");
+        assert_eq!(owner_kind("@octocat"), "user");
+        assert_eq!(owner_kind("docs@example.com"), "email");
+    }
+}
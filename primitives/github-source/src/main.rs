@@ -0,0 +1,6 @@
+//! `github-source` binary; the primitive itself lives in the library.
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    github_source::run(std::env::args_os()).await
+}
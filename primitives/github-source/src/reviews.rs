@@ -0,0 +1,360 @@
+//! Reviewers a pull request needs.
+//!
+//! When a ready (non-draft) pull request is opened, pushed to, reopened or
+//! marked ready for review, github-source reads the CODEOWNERS file of the
+//! base branch and the pull request's changed files, and combines the owners
+//! of those files with the reviewers and teams already requested on the pull
+//! request. Each reviewer is published once as `github.pr.review_needed`:
+//!
+//! ```json
+//! {
+//!   "reviewer": "@acme/payments",
+//!   "kind": "team",
+//!   "reasons": ["codeowners", "requested"],
+//!   "paths": ["billing/invoice.rs"],
+//!   "repository": "acme/api",
+//!   "number": 42,
+//!   "title": "Round invoice totals",
+//!   "url": "https://github.com/acme/api/pull/42",
+//!   "author": "octocat",
+//!   "action": "opened",
+//!   "head_sha": "6dcb09b5..."
+//! }
+//! ```
+//!
+//! `kind` is `user`, `team` or `email` (CODEOWNERS may name owners by
+//! email); `paths` lists the changed files the reviewer owns. The author is
+//! never asked to review their own pull request. If CODEOWNERS or the file
+//! list cannot be fetched, the requested reviewers are still published.
+
+use crate::codeowners::{self, CodeOwners};
+use clap::Args;
+use reqwest::{Client, StatusCode};
+use serde_json::{Value, json};
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+/// Message type of the per-reviewer events.
+pub const REVIEW_NEEDED_EVENT_TYPE: &str = "github.pr.review_needed";
+
+/// `pull_request` actions after which reviewers are resolved.
+pub const TRIGGER_ACTIONS: &[&str] = &["opened", "synchronize", "reopened", "ready_for_review"];
+
+/// Files are listed 100 per page; GitHub stops at 3000.
+const FILES_PER_PAGE: usize = 100;
+const MAX_FILE_PAGES: usize = 30;
+
+/// GitHub API access for reviewer resolution.
+#[derive(Args, Debug, Clone)]
+pub struct ReviewArgs {
+    /// API token for reading CODEOWNERS and changed files (optional for public repositories).
+    #[arg(long, env = "GITHUB_TOKEN", hide_env_values = true)]
+    pub token: Option<String>,
+
+    /// API root; `https://<host>/api/v3` for GitHub Enterprise Server.
+    #[arg(long, env = "GITHUB_API_URL", default_value = "https://api.github.com")]
+    pub api_url: String,
+
+    /// Per-request API timeout in milliseconds.
+    #[arg(long, env = "GITHUB_SOURCE_API_TIMEOUT", default_value = "10000")]
+    pub api_timeout: u64,
+
+    /// Publish only the webhook events, without resolving reviewers.
+    #[arg(long, env = "GITHUB_SOURCE_NO_REVIEWERS")]
+    pub no_reviewers: bool,
+}
+
+/// Whether a webhook delivery should have its reviewers resolved.
+pub fn needs_review(event: &str, body: &Value) -> bool {
+    event == "pull_request"
+        && body["action"]
+            .as_str()
+            .is_some_and(|a| TRIGGER_ACTIONS.contains(&a))
+        && !body["pull_request"]["draft"].as_bool().unwrap_or(false)
+}
+
+/// One reviewer and why they are needed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Reviewer {
+    pub reviewer: String,
+    pub reasons: Vec<&'static str>,
+    pub paths: Vec<String>,
+}
+
+/// Combine CODEOWNERS owners of `files` with the reviewers requested in a
+/// `pull_request` webhook body, ordered by name.
+pub fn reviewers(body: &Value, rules: Option<&CodeOwners>, files: &[String]) -> Vec<Reviewer> {
+    let pr = &body["pull_request"];
+    let author = pr["user"]["login"]
+        .as_str()
+        .map(|login| format!("@{login}").to_lowercase());
+    let mut found: BTreeMap<String, Reviewer> = BTreeMap::new();
+    let mut add = |name: String, reason: &'static str, path: Option<&str>| {
+        let key = name.to_lowercase();
+        if author.as_ref() == Some(&key) {
+            return;
+        }
+        let entry = found.entry(key).or_insert_with(|| Reviewer {
+            reviewer: name,
+            reasons: Vec::new(),
+            paths: Vec::new(),
+        });
+        if !entry.reasons.contains(&reason) {
+            entry.reasons.push(reason);
+        }
+        if let Some(path) = path
+            && !entry.paths.iter().any(|p| p == path)
+        {
+            entry.paths.push(path.to_string());
+        }
+    };
+
+    if let Some(rules) = rules {
+        for file in files {
+            for owner in rules.owners(file) {
+                add(owner.clone(), "codeowners", Some(file));
+            }
+        }
+    }
+    let users = pr["requested_reviewers"].as_array().into_iter().flatten();
+    for login in users.filter_map(|u| u["login"].as_str()) {
+        add(format!("@{login}"), "requested", None);
+    }
+    let org = body["repository"]["owner"]["login"]
+        .as_str()
+        .unwrap_or_default();
+    let teams = pr["requested_teams"].as_array().into_iter().flatten();
+    for slug in teams.filter_map(|t| t["slug"].as_str()) {
+        add(format!("@{org}/{slug}"), "requested", None);
+    }
+    found.into_values().collect()
+}
+
+/// The `github.pr.review_needed` payload for one reviewer.
+pub fn event(body: &Value, reviewer: &Reviewer) -> Value {
+    let pr = &body["pull_request"];
+    json!({
+        "reviewer": reviewer.reviewer,
+        "kind": codeowners::owner_kind(&reviewer.reviewer),
+        "reasons": reviewer.reasons,
+        "paths": reviewer.paths,
+        "repository": body["repository"]["full_name"],
+        "number": pr["number"],
+        "title": pr["title"],
+        "url": pr["html_url"],
+        "author": pr["user"]["login"],
+        "action": body["action"],
+        "head_sha": pr["head"]["sha"],
+    })
+}
+
+/// Reads CODEOWNERS and changed files from the GitHub API.
+pub struct Resolver {
+    client: Client,
+    api_url: String,
+    token: Option<String>,
+    timeout: Duration,
+}
+
+impl Resolver {
+    pub fn new(args: &ReviewArgs) -> Self {
+        Self {
+            client: Client::new(),
+            api_url: args.api_url.trim_end_matches('/').to_string(),
+            token: args.token.clone(),
+            timeout: Duration::from_millis(args.api_timeout),
+        }
+    }
+
+    /// `github.pr.review_needed` payloads for a `pull_request` webhook body.
+    pub async fn resolve(&self, body: &Value) -> Vec<Value> {
+        let repo = body["repository"]["full_name"].as_str().unwrap_or_default();
+        let pr = &body["pull_request"];
+        let base = pr["base"]["ref"].as_str().unwrap_or_default();
+        let lookup = async {
+            let number = pr["number"]
+                .as_u64()
+                .ok_or_else(|| "pull request has no number".to_string())?;
+            let Some(rules) = self.codeowners(repo, base).await? else {
+                return Ok(None);
+            };
+            let files = self.files(repo, number).await?;
+            Ok::<_, String>(Some((rules, files)))
+        };
+        let (rules, files) = match lookup.await {
+            Ok(Some((rules, files))) => (Some(rules), files),
+            Ok(None) => (None, Vec::new()),
+            Err(e) => {
+                eprintln!("Could not resolve code owners for {repo}: {e}");
+                (None, Vec::new())
+            }
+        };
+        reviewers(body, rules.as_ref(), &files)
+            .iter()
+            .map(|reviewer| event(body, reviewer))
+            .collect()
+    }
+
+    /// The first CODEOWNERS file found on `base`, if any.
+    async fn codeowners(&self, repo: &str, base: &str) -> Result<Option<CodeOwners>, String> {
+        for location in codeowners::LOCATIONS {
+            let path = format!("/repos/{repo}/contents/{location}");
+            let response = self
+                .get(&path)
+                .query(&[("ref", base)])
+                .header("Accept", "application/vnd.github.raw")
+                .send()
+                .await
+                .map_err(|e| format!("GET {path}: {e}"))?;
+            if response.status() == StatusCode::NOT_FOUND {
+                continue;
+            }
+            let response = response
+                .error_for_status()
+                .map_err(|e| format!("GET {path}: {e}"))?;
+            let text = response
+                .text()
+                .await
+                .map_err(|e| format!("GET {path}: {e}"))?;
+            return Ok(Some(CodeOwners::parse(&text)));
+        }
+        Ok(None)
+    }
+
+    /// Paths the pull request touches, including the old names of renamed files.
+    async fn files(&self, repo: &str, number: u64) -> Result<Vec<String>, String> {
+        let path = format!("/repos/{repo}/pulls/{number}/files");
+        let mut files = Vec::new();
+        for page in 1..=MAX_FILE_PAGES {
+            let listed: Vec<Value> = self
+                .get(&path)
+                .query(&[("per_page", FILES_PER_PAGE), ("page", page)])
+                .header("Accept", "application/vnd.github+json")
+                .send()
+                .await
+                .and_then(reqwest::Response::error_for_status)
+                .map_err(|e| format!("GET {path}: {e}"))?
+                .json()
+                .await
+                .map_err(|e| format!("GET {path}: {e}"))?;
+            for file in &listed {
+                for key in ["filename", "previous_filename"] {
+                    if let Some(name) = file[key].as_str() {
+                        files.push(name.to_string());
+                    }
+                }
+            }
+            if listed.len() < FILES_PER_PAGE {
+                break;
+            }
+        }
+        Ok(files)
+    }
+
+    fn get(&self, path: &str) -> reqwest::RequestBuilder {
+        let request = self
+            .client
+            .get(format!("{}{path}", self.api_url))
+            .timeout(self.timeout)
+            .header("User-Agent", "emergent-github-source");
+        match &self.token {
+            Some(token) => request.bearer_auth(token),
+            None => request,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{Router, extract::Request, http::StatusCode, routing::any};
+
+    fn opened() -> Value {
+        json!({
+            "action": "opened",
+            "repository": {"full_name": "acme/api", "owner": {"login": "acme"}},
+            "pull_request": {
+                "number": 42,
+                "title": "Round invoice totals",
+                "draft": false,
+                "user": {"login": "Octocat"},
+                "base": {"ref": "main"},
+                "head": {"sha": "6dcb09b5"},
+                "requested_reviewers": [{"login": "hubot"}],
+                "requested_teams": [{"slug": "payments"}]
+            }
+        })
+    }
+
+    #[test]
+    fn only_ready_pull_request_updates_need_review() {
+        let mut body = opened();
+        assert!(needs_review("pull_request", &body));
+        assert!(!needs_review("issues", &body));
+        body["action"] = json!("closed");
+        assert!(!needs_review("pull_request", &body));
+        body["action"] = json!("synchronize");
+        body["pull_request"]["draft"] = json!(true);
+        assert!(!needs_review("pull_request", &body));
+    }
+
+    #[test]
+    fn owners_and_requested_reviewers_are_merged() {
+        let rules = CodeOwners::parse("* @acme/core\n/billing/ @acme/payments @octocat\n");
+        let files = vec!["billing/invoice.rs".to_string(), "README.md".to_string()];
+        let found = reviewers(&opened(), Some(&rules), &files);
+        let summary: Vec<(&str, &[&str], usize)> = found
+            .iter()
+            .map(|r| (r.reviewer.as_str(), r.reasons.as_slice(), r.paths.len()))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("@acme/core", &["codeowners"][..], 1),
+                ("@acme/payments", &["codeowners", "requested"][..], 1),
+                ("@hubot", &["requested"][..], 0),
+            ]
+        );
+        assert_eq!(event(&opened(), &found[1])["kind"], "team");
+        assert_eq!(
+            event(&opened(), &found[1])["paths"],
+            json!(["billing/invoice.rs"])
+        );
+    }
+
+    #[tokio::test]
+    async fn codeowners_are_read_from_the_base_branch() {
+        let app = Router::new().fallback(any(|request: Request| async move {
+            let uri = request.uri().to_string();
+            match uri.as_str() {
+                "/repos/acme/api/contents/CODEOWNERS?ref=main" => {
+                    (StatusCode::OK, "*.rs @rustaceans\n".to_string())
+                }
+                "/repos/acme/api/pulls/42/files?per_page=100&page=1" => (
+                    StatusCode::OK,
+                    json!([{"filename": "src/lib.rs"}, {"filename": "docs/a.md"}]).to_string(),
+                ),
+                _ => (StatusCode::NOT_FOUND, String::new()),
+            }
+        }));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap_or_else(|e| panic!("bind: {e}"));
+        let addr = listener
+            .local_addr()
+            .unwrap_or_else(|e| panic!("local_addr: {e}"));
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let resolver = Resolver::new(&ReviewArgs {
+            token: None,
+            api_url: format!("http://{addr}"),
+            api_timeout: 5000,
+            no_reviewers: false,
+        });
+        let events = resolver.resolve(&opened()).await;
+        let names: Vec<&Value> = events.iter().map(|e| &e["reviewer"]).collect();
+        assert_eq!(names, ["@acme/payments", "@hubot", "@rustaceans"]);
+        assert_eq!(events[2]["paths"], json!(["src/lib.rs"]));
+        assert_eq!(events[2]["number"], 42);
+    }
+}
@@ -0,0 +1,152 @@
+//! Provider-agnostic `scm.*` events.
+//!
+//! Alongside each raw `github.<event>`, pushes, pull request lifecycle
+//! changes and workflow runs are also published in the shared schemas
+//! (`scm.push`, `scm.pull_request`, `scm.pipeline`) that the other SCM
+//! sources emit, so automations need not know which provider a repository
+//! lives on.
+//!
+//! | GitHub event | `scm` event |
+//! |--------------|-------------|
+//! | `push` | `scm.push` |
+//! | `pull_request` (`opened`, `synchronize`, `closed`, `reopened`) | `scm.pull_request` (`opened`, `updated`, `merged`/`closed`, `reopened`) |
+//! | `workflow_run` | `scm.pipeline` |
+
+use event_schemas::{EventPayload, ScmPipeline, ScmPullRequest, ScmPush};
+use serde_json::Value;
+
+const PROVIDER: &str = "github";
+
+/// The `scm.*` events for a delivery of `event`, as (message type, payload)
+/// pairs; none for events without an `scm` equivalent.
+pub fn events(event: &str, payload: &Value) -> Vec<(&'static str, Value)> {
+    let normalized = match event {
+        "push" => push(payload).map(|e| (ScmPush::MESSAGE_TYPE, e.to_payload())),
+        "pull_request" => {
+            pull_request(payload).map(|e| (ScmPullRequest::MESSAGE_TYPE, e.to_payload()))
+        }
+        "workflow_run" => pipeline(payload).map(|e| (ScmPipeline::MESSAGE_TYPE, e.to_payload())),
+        _ => None,
+    };
+    normalized.into_iter().collect()
+}
+
+fn string(value: &Value) -> Option<String> {
+    value.as_str().map(str::to_string)
+}
+
+/// A commit id, with the all-zero id GitHub uses for a missing side as `None`.
+fn commit(value: &Value) -> Option<String> {
+    string(value).filter(|sha| sha.bytes().any(|b| b != b'0'))
+}
+
+fn push(payload: &Value) -> Option<ScmPush> {
+    let reference = payload["ref"].as_str()?;
+    Some(ScmPush {
+        provider: PROVIDER.to_string(),
+        repository: string(&payload["repository"]["full_name"])?,
+        r#ref: reference.to_string(),
+        branch: reference.strip_prefix("refs/heads/").map(str::to_string),
+        tag: reference.strip_prefix("refs/tags/").map(str::to_string),
+        before: commit(&payload["before"]),
+        after: commit(&payload["after"]),
+        commits: payload["commits"].as_array().map_or(0, |c| c.len() as u64),
+        sender: string(&payload["sender"]["login"]),
+        url: string(&payload["compare"]),
+    })
+}
+
+fn pull_request(payload: &Value) -> Option<ScmPullRequest> {
+    let pr = &payload["pull_request"];
+    let action = match payload["action"].as_str()? {
+        "opened" => "opened",
+        "synchronize" => "updated",
+        "closed" if pr["merged"].as_bool() == Some(true) => "merged",
+        "closed" => "closed",
+        "reopened" => "reopened",
+        _ => return None,
+    };
+    Some(ScmPullRequest {
+        provider: PROVIDER.to_string(),
+        repository: string(&payload["repository"]["full_name"])?,
+        number: pr["number"].as_u64()?,
+        action: action.to_string(),
+        title: string(&pr["title"]).unwrap_or_default(),
+        source_branch: string(&pr["head"]["ref"])?,
+        target_branch: string(&pr["base"]["ref"])?,
+        head_sha: string(&pr["head"]["sha"]),
+        sender: string(&payload["sender"]["login"]),
+        url: string(&pr["html_url"]),
+    })
+}
+
+fn pipeline(payload: &Value) -> Option<ScmPipeline> {
+    let run = &payload["workflow_run"];
+    let status = match (run["status"].as_str()?, run["conclusion"].as_str()) {
+        ("completed", Some("success")) => "success",
+        ("completed", Some("cancelled" | "stale")) => "canceled",
+        ("completed", Some("skipped" | "neutral")) => "skipped",
+        ("completed", _) => "failed",
+        ("in_progress", _) => "running",
+        _ => "queued",
+    };
+    Some(ScmPipeline {
+        provider: PROVIDER.to_string(),
+        repository: string(&payload["repository"]["full_name"])?,
+        id: run["id"].as_u64()?.to_string(),
+        name: string(&run["name"]),
+        branch: string(&run["head_branch"]),
+        sha: string(&run["head_sha"]),
+        status: status.to_string(),
+        url: string(&run["html_url"]),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn deliveries_normalize_to_scm_events() {
+        let push = json!({
+            "ref": "refs/heads/main",
+            "before": "0000000000000000000000000000000000000000",
+            "after": "6113728f27ae82c7b1a177c8d03f9e96e0adf246",
+            "commits": [{}, {}],
+            "compare": "https://github.com/acme/api/compare/main",
+            "repository": {"full_name": "acme/api"},
+            "sender": {"login": "octocat"},
+        });
+        let events = events("push", &push);
+        assert_eq!(events.len(), 1);
+        let (message_type, payload) = &events[0];
+        assert_eq!(*message_type, "scm.push");
+        assert_eq!(payload["branch"], "main");
+        assert!(payload["tag"].is_null());
+        assert!(payload["before"].is_null());
+        assert_eq!(payload["commits"], 2);
+
+        let merged = json!({
+            "action": "closed",
+            "pull_request": {"number": 7, "title": "Fix", "merged": true,
+                             "head": {"ref": "fix", "sha": "abc"}, "base": {"ref": "main"}},
+            "repository": {"full_name": "acme/api"},
+        });
+        let events = super::events("pull_request", &merged);
+        assert_eq!(events[0].1["action"], "merged");
+        assert_eq!(events[0].1["number"], 7);
+        let labeled = json!({"action": "labeled", "pull_request": {}, "repository": {}});
+        assert!(super::events("pull_request", &labeled).is_empty());
+
+        let run = json!({
+            "workflow_run": {"id": 30433642, "name": "CI", "status": "completed",
+                             "conclusion": "failure", "head_branch": "main"},
+            "repository": {"full_name": "acme/api"},
+        });
+        let events = super::events("workflow_run", &run);
+        assert_eq!(events[0].0, "scm.pipeline");
+        assert_eq!(events[0].1["status"], "failed");
+        assert_eq!(events[0].1["id"], "30433642");
+    }
+}
@@ -0,0 +1,211 @@
+//! GitLab REST calls, with rate limiting.
+//!
+//! A `429 Too Many Requests` is waited out (for `Retry-After`, or until
+//! `RateLimit-Reset`) and the call repeated, provided the wait is no longer
+//! than `--max-rate-limit-wait`; otherwise the call fails as `request` so
+//! the harness retries it later. When a response reports the limit used
+//! up (`RateLimit-Remaining: 0`), later calls hold off until it resets
+//! rather than spend a request on a certain 429.
+//!
+//! Other failures map onto handler error categories: transport errors are
+//! `request`, timeouts `timeout`, other non-2xx responses `rejected`.
+
+use primitive_common::errors::{ErrorCategory, HandlerError};
+use reqwest::header::{HeaderMap, RETRY_AFTER};
+use reqwest::{Client, Method, StatusCode};
+use serde_json::Value;
+use std::sync::{Mutex, PoisonError};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Times a rate-limited call is repeated.
+const RATE_LIMIT_RETRIES: u32 = 3;
+
+/// Authenticated access to one GitLab API.
+pub struct GitLab {
+    client: Client,
+    api_url: String,
+    token: String,
+    timeout: Duration,
+    max_wait: Duration,
+    /// When the exhausted rate limit resets.
+    paused_until: Mutex<Option<Instant>>,
+}
+
+/// `project` (a `group/project` path or an id) as one path segment.
+pub fn project_path(project: &str) -> String {
+    format!("/projects/{}", project.replace('/', "%2F"))
+}
+
+impl GitLab {
+    pub fn new(
+        client: Client,
+        api_url: &str,
+        token: &str,
+        timeout: Duration,
+        max_wait: Duration,
+    ) -> Self {
+        Self {
+            client,
+            api_url: api_url.trim_end_matches('/').to_string(),
+            token: token.to_string(),
+            timeout,
+            max_wait,
+            paused_until: Mutex::new(None),
+        }
+    }
+
+    /// Call a REST endpoint, e.g. `rest(Method::POST, "/projects/7/issues", Some(body))`.
+    pub async fn rest(
+        &self,
+        method: Method,
+        path: &str,
+        body: Option<&Value>,
+    ) -> Result<Value, HandlerError> {
+        let target = format!("{method} {path}");
+        let mut attempt = 0;
+        loop {
+            self.hold_off(&target).await?;
+            let mut request = self
+                .client
+                .request(method.clone(), format!("{}{path}", self.api_url))
+                .timeout(self.timeout)
+                .header("PRIVATE-TOKEN", &self.token);
+            if let Some(body) = body {
+                request = request.json(body);
+            }
+            let response = request.send().await.map_err(|e| transport(&target, &e))?;
+            let status = response.status();
+            let headers = response.headers().clone();
+            let text = response.text().await.map_err(|e| transport(&target, &e))?;
+
+            if headers
+                .get("ratelimit-remaining")
+                .and_then(|v| v.to_str().ok())
+                == Some("0")
+            {
+                self.pause(reset_delay(&headers).unwrap_or(Duration::from_secs(1)));
+            }
+            if status == StatusCode::TOO_MANY_REQUESTS {
+                let wait = retry_delay(&headers).unwrap_or(Duration::from_secs(1));
+                if attempt >= RATE_LIMIT_RETRIES || wait > self.max_wait {
+                    return Err(HandlerError::new(
+                        ErrorCategory::Request,
+                        format!("{target}: rate limited for {}s", wait.as_secs()),
+                    ));
+                }
+                attempt += 1;
+                eprintln!("{target}: rate limited; retrying in {}ms", wait.as_millis());
+                self.pause(wait);
+                continue;
+            }
+            if !status.is_success() {
+                return Err(HandlerError::new(
+                    ErrorCategory::Rejected,
+                    format!("{target}: HTTP {status}: {}", api_message(&text)),
+                ));
+            }
+            if text.trim().is_empty() {
+                return Ok(Value::Null);
+            }
+            return serde_json::from_str(&text)
+                .map_err(|e| HandlerError::new(ErrorCategory::Parse, format!("{target}: {e}")));
+        }
+    }
+
+    fn pause(&self, wait: Duration) {
+        let until = Instant::now() + wait;
+        let mut paused = self
+            .paused_until
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        if paused.is_none_or(|current| current < until) {
+            *paused = Some(until);
+        }
+    }
+
+    /// Wait out an exhausted rate limit, or fail if that would take too long.
+    async fn hold_off(&self, target: &str) -> Result<(), HandlerError> {
+        let until = *self
+            .paused_until
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        let Some(wait) = until.and_then(|until| until.checked_duration_since(Instant::now()))
+        else {
+            return Ok(());
+        };
+        if wait > self.max_wait {
+            return Err(HandlerError::new(
+                ErrorCategory::Request,
+                format!("{target}: rate limited for {}s", wait.as_secs()),
+            ));
+        }
+        tokio::time::sleep(wait).await;
+        Ok(())
+    }
+}
+
+/// How long `Retry-After` (seconds) or `RateLimit-Reset` asks to wait.
+fn retry_delay(headers: &HeaderMap) -> Option<Duration> {
+    headers
+        .get(RETRY_AFTER)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.trim().parse().ok())
+        .map(Duration::from_secs)
+        .or_else(|| reset_delay(headers))
+}
+
+/// Time until `RateLimit-Reset` (a Unix timestamp).
+fn reset_delay(headers: &HeaderMap) -> Option<Duration> {
+    let reset: u64 = headers
+        .get("ratelimit-reset")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.trim().parse().ok())?;
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).ok()?.as_secs();
+    Some(Duration::from_secs(reset.saturating_sub(now)))
+}
+
+fn transport(target: &str, e: &reqwest::Error) -> HandlerError {
+    if e.is_timeout() {
+        HandlerError::new(ErrorCategory::Timeout, format!("{target}: timed out"))
+    } else {
+        HandlerError::new(ErrorCategory::Request, format!("{target}: {e}"))
+    }
+}
+
+/// GitLab's `message` (or `error`) from an error body, or the body itself.
+fn api_message(body: &str) -> String {
+    serde_json::from_str::<Value>(body)
+        .ok()
+        .and_then(|v| {
+            let message = v.get("message").or_else(|| v.get("error"))?;
+            Some(match message {
+                Value::String(s) => s.clone(),
+                other => other.to_string(),
+            })
+        })
+        .unwrap_or_else(|| body.trim().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::HeaderValue;
+
+    #[test]
+    fn retry_after_wins_over_the_reset_time() {
+        let mut headers = HeaderMap::new();
+        let reset = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs())
+            + 30;
+        headers.insert(
+            "ratelimit-reset",
+            HeaderValue::from_str(&reset.to_string()).unwrap_or_else(|e| panic!("{e}")),
+        );
+        let wait = retry_delay(&headers).unwrap_or_default();
+        assert!((29..=30).contains(&wait.as_secs()), "{wait:?}");
+        headers.insert(RETRY_AFTER, HeaderValue::from_static("5"));
+        assert_eq!(retry_delay(&headers), Some(Duration::from_secs(5)));
+        assert_eq!(project_path("platform/app"), "/projects/platform%2Fapp");
+    }
+}
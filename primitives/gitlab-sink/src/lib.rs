@@ -0,0 +1,276 @@
+//! GitLab Sink - Issues, Merge Requests and Pipelines from Events
+//!
+//! A Sink that turns each payload into one GitLab REST call, named in its
+//! `op` field (see [`ops`]): creating, updating and commenting on issues
+//! and merge requests, and triggering pipelines. Rate limits are waited
+//! out where GitLab says how long (see [`api`]); other failed calls fail
+//! the message, so the harness retries and dead-letters it.
+//!
+//! # Examples
+//!
+//! ```bash
+//! # Apply whatever operations upstream transforms emit
+//! gitlab-sink -s gitlab.op --token $GITLAB_TOKEN
+//!
+//! # Self-managed GitLab
+//! gitlab-sink -s 'incident.*' \
+//!   --api-url https://gitlab.example.com/api/v4 --token $GITLAB_TOKEN
+//! ```
+//!
+//! ```json
+//! {"op": "pipeline.trigger", "project": "platform/app", "ref": "main", "variables": {"DEPLOY": "1"}}
+//! ```
+
+pub mod api;
+pub mod ops;
+
+use api::GitLab;
+use clap::Parser;
+use emergent_client::EmergentMessage;
+use ops::Operation;
+use primitive_common::capabilities::VERSION;
+use primitive_common::errors::{ErrorCategory, HandlerError};
+use primitive_common::{SinkArgs, SinkConfig, SinkContext, SinkHandler, run_sink};
+use reqwest::Client;
+use serde_json::json;
+use std::time::Duration;
+
+/// GitLab Sink — issues, merge requests and pipelines from events.
+#[derive(Parser, Debug)]
+#[command(name = "gitlab_sink", version = VERSION)]
+#[command(about = "Manage GitLab issues, merge requests and pipelines from events")]
+struct Args {
+    /// Message types to subscribe to.
+    #[arg(short, long = "subscribe", required = true)]
+    subscribe: Vec<String>,
+
+    /// Personal, project or group access token with `api` scope.
+    #[arg(long, env = "GITLAB_TOKEN", hide_env_values = true)]
+    token: String,
+
+    /// API root; `https://<host>/api/v4` for self-managed GitLab.
+    #[arg(
+        long,
+        env = "GITLAB_API_URL",
+        default_value = "https://gitlab.com/api/v4"
+    )]
+    api_url: String,
+
+    /// Per-request timeout in milliseconds.
+    #[arg(short, long, env = "GITLAB_SINK_TIMEOUT", default_value = "30000")]
+    timeout: u64,
+
+    /// Longest rate-limit wait in milliseconds; a longer one fails the
+    /// message instead.
+    #[arg(long, env = "GITLAB_SINK_MAX_RATE_LIMIT_WAIT", default_value = "60000")]
+    max_rate_limit_wait: u64,
+
+    #[command(flatten)]
+    sink: SinkArgs,
+}
+
+/// Applies the operation each payload carries.
+struct GitLabSink {
+    gitlab: GitLab,
+}
+
+impl SinkHandler for GitLabSink {
+    async fn handle(
+        &self,
+        _msg: &EmergentMessage,
+        ctx: &SinkContext<'_>,
+    ) -> Result<(), HandlerError> {
+        let op = Operation::parse(ctx.payload())
+            .map_err(|e| HandlerError::new(ErrorCategory::Parse, e))?;
+
+        if ctx.is_dry_run() {
+            let detail = serde_json::to_value(&op).unwrap_or_else(|_| json!({}));
+            ctx.would_have("gitlab", detail).await;
+            return Ok(());
+        }
+
+        let (method, path, body) = op.request();
+        self.gitlab
+            .rest(method, &path, Some(&body))
+            .await
+            .map(|_| ())
+            .map_err(|e| HandlerError::new(e.category, format!("{}: {}", op.name(), e.message)))
+    }
+}
+
+/// Run the primitive with `args` as its command line (the first item is
+/// the program name, as with `std::env::args_os`).
+pub async fn run<I, T>(args: I) -> Result<(), Box<dyn std::error::Error>>
+where
+    I: IntoIterator<Item = T>,
+    T: Into<std::ffi::OsString> + Clone,
+{
+    let args = Args::parse_from(args);
+
+    let config = SinkConfig {
+        name: "gitlab_sink",
+        subscribe: &args.subscribe,
+        would_have_as: "gitlab.would_have",
+        dead_letter_as: "gitlab.dead_letter",
+        settings: &args,
+    };
+    let handler = GitLabSink {
+        gitlab: GitLab::new(
+            Client::new(),
+            &args.api_url,
+            &args.token,
+            Duration::from_millis(args.timeout),
+            Duration::from_millis(args.max_rate_limit_wait),
+        ),
+    };
+
+    run_sink(config, &args.sink, handler).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{
+        Json, Router,
+        extract::Request,
+        http::{HeaderMap, StatusCode},
+        response::IntoResponse,
+        routing::any,
+    };
+    use emergent_testkit::{SinkFixture, fixtures, spawn_sink};
+    use serde_json::Value;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tokio::sync::mpsc;
+
+    /// Serve a fake GitLab API on a random port that rate-limits the first
+    /// request, forwarding each request's method, path, token and JSON body.
+    async fn server() -> (
+        String,
+        mpsc::UnboundedReceiver<(String, String, String, Value)>,
+    ) {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let calls = Arc::new(AtomicUsize::new(0));
+        let app = Router::new().fallback(any(move |request: Request| {
+            let tx = tx.clone();
+            let calls = Arc::clone(&calls);
+            async move {
+                let method = request.method().to_string();
+                let path = request.uri().path().to_string();
+                let token = request
+                    .headers()
+                    .get("private-token")
+                    .and_then(|v| v.to_str().ok())
+                    .unwrap_or_default()
+                    .to_string();
+                let body = axum::body::to_bytes(request.into_body(), usize::MAX)
+                    .await
+                    .unwrap_or_default();
+                let body: Value = serde_json::from_slice(&body).unwrap_or(Value::Null);
+                let _ = tx.send((method, path, token, body));
+                if calls.fetch_add(1, Ordering::SeqCst) == 0 {
+                    let mut headers = HeaderMap::new();
+                    headers.insert("retry-after", "0".parse().unwrap_or_else(|e| panic!("{e}")));
+                    return (StatusCode::TOO_MANY_REQUESTS, headers, Json(json!({})))
+                        .into_response();
+                }
+                (StatusCode::CREATED, Json(json!({"iid": 43}))).into_response()
+            }
+        }));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap_or_else(|e| panic!("bind: {e}"));
+        let addr = listener
+            .local_addr()
+            .unwrap_or_else(|e| panic!("local_addr: {e}"));
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        (format!("http://{addr}"), rx)
+    }
+
+    fn gitlab_sink(api_url: &str) -> GitLabSink {
+        GitLabSink {
+            gitlab: GitLab::new(
+                Client::new(),
+                api_url,
+                "t0ken",
+                Duration::from_secs(5),
+                Duration::from_secs(5),
+            ),
+        }
+    }
+
+    #[tokio::test]
+    async fn rate_limited_calls_are_retried() {
+        let (base, mut received) = server().await;
+        let (mut engine, run) = spawn_sink(
+            SinkFixture::new("gitlab_sink", "gitlab"),
+            SinkArgs::default(),
+            gitlab_sink(&base),
+        );
+        let mut next = async || {
+            received
+                .recv()
+                .await
+                .unwrap_or_else(|| panic!("no request"))
+        };
+
+        engine
+            .inject_message(fixtures::message(
+                "gitlab.op",
+                json!({"op": "pipeline.trigger", "project": "platform/app", "ref": "main",
+                       "variables": {"DEPLOY": "1"}}),
+            ))
+            .await;
+        for _ in 0..2 {
+            let (method, path, token, body) = next().await;
+            assert_eq!(
+                (method.as_str(), path.as_str(), token.as_str()),
+                ("POST", "/projects/platform%2Fapp/pipeline", "t0ken")
+            );
+            assert_eq!(
+                body,
+                json!({"ref": "main", "variables": [{"key": "DEPLOY", "value": "1"}]})
+            );
+        }
+
+        engine
+            .inject_message(fixtures::message(
+                "gitlab.op",
+                json!({"op": "mr.comment", "project": "7", "mr": 17, "body": "Deployed"}),
+            ))
+            .await;
+        let (_, path, _, body) = next().await;
+        assert_eq!(path, "/projects/7/merge_requests/17/notes");
+        assert_eq!(body, json!({"body": "Deployed"}));
+
+        engine.close();
+        assert_eq!(run.await.ok(), Some(Ok(())));
+    }
+
+    #[tokio::test]
+    async fn dry_run_reports_the_operation() {
+        let args = SinkArgs {
+            dry_run: true,
+            ..Default::default()
+        };
+        let (mut engine, run) = spawn_sink(
+            SinkFixture::new("gitlab_sink", "gitlab"),
+            args,
+            gitlab_sink("http://127.0.0.1:9"),
+        );
+
+        engine
+            .inject_message(fixtures::message(
+                "gitlab.op",
+                json!({"op": "issue.create", "project": "platform/app", "title": "Disk full"}),
+            ))
+            .await;
+        let report = engine.expect_published("gitlab.would_have").await;
+        assert_eq!(report.payload()["action"], "gitlab");
+        assert_eq!(report.payload()["detail"]["op"], "issue.create");
+        assert_eq!(report.payload()["detail"]["title"], "Disk full");
+
+        engine.close();
+        assert_eq!(run.await.ok(), Some(Ok(())));
+    }
+}
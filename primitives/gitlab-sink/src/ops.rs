@@ -0,0 +1,336 @@
+//! Operations carried in payloads.
+//!
+//! Every payload names its operation in `op`:
+//!
+//! ```json
+//! {"op": "issue.create", "project": "platform/app", "title": "Disk full on web1", "labels": ["ops"]}
+//! {"op": "issue.update", "project": "platform/app", "issue": 42, "state": "close", "add_labels": ["done"]}
+//! {"op": "issue.comment", "project": "platform/app", "issue": 42, "body": "Fixed by !17"}
+//! {"op": "mr.create", "project": "platform/app", "source_branch": "fix", "target_branch": "main", "title": "Fix"}
+//! {"op": "mr.update", "project": "platform/app", "mr": 17, "add_labels": ["ready"]}
+//! {"op": "mr.comment", "project": "platform/app", "mr": 17, "body": "Deployed to staging"}
+//! {"op": "pipeline.trigger", "project": "platform/app", "ref": "main", "variables": {"DEPLOY": "1"}}
+//! ```
+//!
+//! `project` is a `group/project` path or a numeric id; issues and merge
+//! requests are given by their project-scoped number (`iid`).
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value, json};
+use std::collections::BTreeMap;
+
+/// A state change for an issue or merge request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StateEvent {
+    Close,
+    Reopen,
+}
+
+/// One operation against GitLab.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "op", deny_unknown_fields)]
+pub enum Operation {
+    #[serde(rename = "issue.create")]
+    IssueCreate {
+        project: String,
+        title: String,
+        #[serde(default)]
+        description: Option<String>,
+        #[serde(default)]
+        labels: Vec<String>,
+        #[serde(default)]
+        confidential: bool,
+    },
+    /// Edit, label, close or reopen an issue.
+    #[serde(rename = "issue.update")]
+    IssueUpdate {
+        project: String,
+        issue: u64,
+        #[serde(default)]
+        title: Option<String>,
+        #[serde(default)]
+        description: Option<String>,
+        #[serde(default)]
+        add_labels: Vec<String>,
+        #[serde(default)]
+        remove_labels: Vec<String>,
+        #[serde(default)]
+        state: Option<StateEvent>,
+    },
+    #[serde(rename = "issue.comment")]
+    IssueComment {
+        project: String,
+        issue: u64,
+        body: String,
+    },
+    #[serde(rename = "mr.create")]
+    MrCreate {
+        project: String,
+        source_branch: String,
+        target_branch: String,
+        title: String,
+        #[serde(default)]
+        description: Option<String>,
+        #[serde(default)]
+        labels: Vec<String>,
+        #[serde(default)]
+        remove_source_branch: bool,
+    },
+    /// Edit, label, close or reopen a merge request.
+    #[serde(rename = "mr.update")]
+    MrUpdate {
+        project: String,
+        mr: u64,
+        #[serde(default)]
+        title: Option<String>,
+        #[serde(default)]
+        description: Option<String>,
+        #[serde(default)]
+        add_labels: Vec<String>,
+        #[serde(default)]
+        remove_labels: Vec<String>,
+        #[serde(default)]
+        state: Option<StateEvent>,
+    },
+    #[serde(rename = "mr.comment")]
+    MrComment {
+        project: String,
+        mr: u64,
+        body: String,
+    },
+    /// Run a pipeline on a branch or tag.
+    #[serde(rename = "pipeline.trigger")]
+    PipelineTrigger {
+        project: String,
+        #[serde(rename = "ref")]
+        reference: String,
+        #[serde(default)]
+        variables: BTreeMap<String, String>,
+    },
+}
+
+/// Add `labels` to `body` as GitLab's comma-separated `key`, if any.
+fn labels(body: &mut Map<String, Value>, key: &str, labels: &[String]) {
+    if !labels.is_empty() {
+        body.insert(key.to_string(), json!(labels.join(",")));
+    }
+}
+
+/// The fields an `*.update` sets.
+fn update_body(
+    title: &Option<String>,
+    description: &Option<String>,
+    add_labels: &[String],
+    remove_labels: &[String],
+    state: Option<StateEvent>,
+) -> Value {
+    let mut body = Map::new();
+    if let Some(title) = title {
+        body.insert("title".to_string(), json!(title));
+    }
+    if let Some(description) = description {
+        body.insert("description".to_string(), json!(description));
+    }
+    labels(&mut body, "add_labels", add_labels);
+    labels(&mut body, "remove_labels", remove_labels);
+    if let Some(state) = state {
+        body.insert("state_event".to_string(), json!(state));
+    }
+    Value::Object(body)
+}
+
+impl Operation {
+    /// Read the operation from a payload.
+    pub fn parse(payload: &Value) -> Result<Self, String> {
+        let op = Self::deserialize(payload).map_err(|e| format!("invalid operation: {e}"))?;
+        op.validate()?;
+        Ok(op)
+    }
+
+    /// The `op` name, for logs and errors.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::IssueCreate { .. } => "issue.create",
+            Self::IssueUpdate { .. } => "issue.update",
+            Self::IssueComment { .. } => "issue.comment",
+            Self::MrCreate { .. } => "mr.create",
+            Self::MrUpdate { .. } => "mr.update",
+            Self::MrComment { .. } => "mr.comment",
+            Self::PipelineTrigger { .. } => "pipeline.trigger",
+        }
+    }
+
+    fn project(&self) -> &str {
+        match self {
+            Self::IssueCreate { project, .. }
+            | Self::IssueUpdate { project, .. }
+            | Self::IssueComment { project, .. }
+            | Self::MrCreate { project, .. }
+            | Self::MrUpdate { project, .. }
+            | Self::MrComment { project, .. }
+            | Self::PipelineTrigger { project, .. } => project,
+        }
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if self.project().trim().is_empty() {
+            return Err(format!("{} needs a project", self.name()));
+        }
+        let empty = match self {
+            Self::IssueCreate { title, .. } | Self::MrCreate { title, .. } => {
+                title.trim().is_empty().then_some("title")
+            }
+            Self::IssueComment { body, .. } | Self::MrComment { body, .. } => {
+                body.trim().is_empty().then_some("body")
+            }
+            Self::PipelineTrigger { reference, .. } => reference.trim().is_empty().then_some("ref"),
+            Self::IssueUpdate { .. } | Self::MrUpdate { .. } => None,
+        };
+        match empty {
+            Some(field) => Err(format!("{} needs a {field}", self.name())),
+            None => Ok(()),
+        }
+    }
+
+    /// The REST call carrying out the operation: method, path (under
+    /// `--api-url`) and body.
+    pub fn request(&self) -> (reqwest::Method, String, Value) {
+        use reqwest::Method;
+        let project = crate::api::project_path(self.project());
+        match self {
+            Self::IssueCreate {
+                title,
+                description,
+                labels: issue_labels,
+                confidential,
+                ..
+            } => {
+                let mut body = Map::new();
+                body.insert("title".to_string(), json!(title));
+                if let Some(description) = description {
+                    body.insert("description".to_string(), json!(description));
+                }
+                labels(&mut body, "labels", issue_labels);
+                if *confidential {
+                    body.insert("confidential".to_string(), json!(true));
+                }
+                (
+                    Method::POST,
+                    format!("{project}/issues"),
+                    Value::Object(body),
+                )
+            }
+            Self::IssueUpdate {
+                issue,
+                title,
+                description,
+                add_labels,
+                remove_labels,
+                state,
+                ..
+            } => (
+                Method::PUT,
+                format!("{project}/issues/{issue}"),
+                update_body(title, description, add_labels, remove_labels, *state),
+            ),
+            Self::IssueComment { issue, body, .. } => (
+                Method::POST,
+                format!("{project}/issues/{issue}/notes"),
+                json!({"body": body}),
+            ),
+            Self::MrCreate {
+                source_branch,
+                target_branch,
+                title,
+                description,
+                labels: mr_labels,
+                remove_source_branch,
+                ..
+            } => {
+                let mut body = Map::new();
+                body.insert("source_branch".to_string(), json!(source_branch));
+                body.insert("target_branch".to_string(), json!(target_branch));
+                body.insert("title".to_string(), json!(title));
+                if let Some(description) = description {
+                    body.insert("description".to_string(), json!(description));
+                }
+                labels(&mut body, "labels", mr_labels);
+                if *remove_source_branch {
+                    body.insert("remove_source_branch".to_string(), json!(true));
+                }
+                (
+                    Method::POST,
+                    format!("{project}/merge_requests"),
+                    Value::Object(body),
+                )
+            }
+            Self::MrUpdate {
+                mr,
+                title,
+                description,
+                add_labels,
+                remove_labels,
+                state,
+                ..
+            } => (
+                Method::PUT,
+                format!("{project}/merge_requests/{mr}"),
+                update_body(title, description, add_labels, remove_labels, *state),
+            ),
+            Self::MrComment { mr, body, .. } => (
+                Method::POST,
+                format!("{project}/merge_requests/{mr}/notes"),
+                json!({"body": body}),
+            ),
+            Self::PipelineTrigger {
+                reference,
+                variables,
+                ..
+            } => {
+                let variables: Vec<Value> = variables
+                    .iter()
+                    .map(|(key, value)| json!({"key": key, "value": value}))
+                    .collect();
+                (
+                    Method::POST,
+                    format!("{project}/pipeline"),
+                    json!({"ref": reference, "variables": variables}),
+                )
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn operations_become_rest_calls() {
+        let close = Operation::parse(&json!({
+            "op": "issue.update", "project": "platform/app", "issue": 42,
+            "state": "close", "add_labels": ["done", "ops"]
+        }))
+        .unwrap_or_else(|e| panic!("parse: {e}"));
+        let (method, path, body) = close.request();
+        assert_eq!(method, reqwest::Method::PUT);
+        assert_eq!(path, "/projects/platform%2Fapp/issues/42");
+        assert_eq!(
+            body,
+            json!({"add_labels": "done,ops", "state_event": "close"})
+        );
+
+        assert!(
+            Operation::parse(
+                &json!({"op": "issue.comment", "project": "7", "issue": 1, "body": " "})
+            )
+            .is_err()
+        );
+        assert!(Operation::parse(&json!({"op": "mr.merge", "project": "7", "mr": 1})).is_err());
+        assert!(
+            Operation::parse(&json!({"op": "pipeline.trigger", "project": "", "ref": "main"}))
+                .is_err()
+        );
+    }
+}
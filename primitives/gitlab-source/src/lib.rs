@@ -0,0 +1,408 @@
+//! GitLab Source - GitLab Webhook Receiver
+//!
+//! A Source that receives GitLab webhook deliveries and emits each as
+//! `gitlab.<kind>` (e.g. `gitlab.push`, `gitlab.merge_request`,
+//! `gitlab.pipeline`), with the delivery's JSON body as payload. The kind
+//! is the body's `object_kind` (`event_type` for system hooks), falling
+//! back to the `X-Gitlab-Event` header. With `--secret`, deliveries must
+//! carry it as `X-Gitlab-Token`.
+//!
+//! Pushes, merge request changes and pipelines are also published as
+//! provider-agnostic `scm.push`, `scm.pull_request` and `scm.pipeline`
+//! events (see [`scm`]).
+//!
+//! `--emit-type-template` can also use `{kind}`. A delivery that cannot be
+//! published or spooled is answered with 503 so GitLab retries it.
+//!
+//! Sources are SILENT - they only produce domain messages.
+//! All lifecycle events are published by the engine.
+//!
+//! # Usage
+//!
+//! ```bash
+//! # Receive deliveries on :8080/
+//! gitlab-source --secret $WEBHOOK_SECRET
+//!
+//! gitlab-source --port 9000 --path /gitlab --secret $WEBHOOK_SECRET
+//! ```
+//!
+//! On SIGTERM the listener closes immediately and deliveries already being
+//! handled get `--drain-timeout` milliseconds to finish publishing.
+
+pub mod scm;
+
+use axum::{
+    Router,
+    body::Bytes,
+    extract::State,
+    http::{HeaderMap, StatusCode},
+    response::IntoResponse,
+    routing::post,
+};
+use clap::Parser;
+use emergent_client::EmergentSource;
+use primitive_common::capabilities::{self, CapabilityArgs, Role, VERSION, capabilities_message};
+use primitive_common::doctor::Report;
+use primitive_common::source::{Outlet, SourceArgs};
+use serde_json::Value;
+use std::{future::IntoFuture, net::SocketAddr, sync::Arc};
+use subtle::ConstantTimeEq;
+use tokio::{
+    signal::unix::{SignalKind, signal},
+    sync::Notify,
+};
+
+/// GitLab webhook receiver that emits gitlab.* events.
+#[derive(Parser, Debug, Clone)]
+#[command(name = "gitlab-source", version = VERSION)]
+#[command(about = "Receives GitLab webhooks and emits events")]
+struct Args {
+    /// Port to listen on.
+    #[arg(short, long, env = "GITLAB_SOURCE_PORT", default_value = "8080")]
+    port: u16,
+
+    /// Host to bind to.
+    #[arg(long, env = "GITLAB_SOURCE_HOST", default_value = "0.0.0.0")]
+    host: String,
+
+    /// Path to accept deliveries on.
+    #[arg(long, env = "GITLAB_SOURCE_PATH", default_value = "/")]
+    path: String,
+
+    /// Webhook secret token; deliveries must then carry it as X-Gitlab-Token.
+    #[arg(long, env = "GITLAB_WEBHOOK_SECRET", hide_env_values = true)]
+    secret: Option<String>,
+
+    /// Verify external dependencies and exit without connecting to the engine.
+    #[arg(long)]
+    self_test: bool,
+
+    #[command(flatten)]
+    source: SourceArgs,
+
+    #[command(flatten)]
+    capabilities: CapabilityArgs,
+}
+
+/// Variables available to `--emit-type-template`.
+const TEMPLATE_VARIABLES: &[&str] = &["source", "kind"];
+
+/// Shared application state.
+struct AppState {
+    outlet: Arc<Outlet>,
+    secret: Option<String>,
+}
+
+/// The kind of event a delivery carries: `object_kind` or, for system
+/// hooks, `event_type`; otherwise the header (`Merge Request Hook` becomes
+/// `merge_request`).
+fn event_kind(payload: &Value, header: Option<&str>) -> Option<String> {
+    let kind = payload["object_kind"]
+        .as_str()
+        .or_else(|| payload["event_type"].as_str())
+        .map(str::to_string)
+        .or_else(|| header.map(|h| h.trim_end_matches(" Hook").to_lowercase().replace(' ', "_")))?;
+    (!kind.is_empty()).then_some(kind)
+}
+
+/// Runs `--self-test` checks and exits.
+fn self_test(args: &Args, name: &str) -> ! {
+    let mut report = Report::new(name);
+    let addr = format!("{}:{}", args.host, args.port);
+    let bind = addr
+        .parse::<SocketAddr>()
+        .map_err(|e| format!("{addr}: {e}"))
+        .and_then(|a| {
+            std::net::TcpListener::bind(a)
+                .map(|_| addr.clone())
+                .map_err(|e| format!("{addr}: {e}"))
+        });
+    report.check("bind", bind);
+    report.check("path", check_path(&args.path));
+    args.source.self_test(&mut report, TEMPLATE_VARIABLES);
+    report.finish()
+}
+
+/// Check that the webhook path is absolute.
+fn check_path(path: &str) -> Result<String, String> {
+    if path.starts_with('/') {
+        Ok(path.to_string())
+    } else {
+        Err(format!("{path} must start with '/'"))
+    }
+}
+
+/// Handles incoming webhook deliveries.
+async fn handle_delivery(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> impl IntoResponse {
+    let header = |name: &str| headers.get(name).and_then(|h| h.to_str().ok());
+
+    if let Some(secret) = &state.secret {
+        match header("x-gitlab-token") {
+            Some(token) if bool::from(token.as_bytes().ct_eq(secret.as_bytes())) => {}
+            Some(_) => return (StatusCode::UNAUTHORIZED, "Invalid token"),
+            None => return (StatusCode::UNAUTHORIZED, "Missing token"),
+        }
+    }
+
+    let Ok(payload) = serde_json::from_slice::<Value>(&body) else {
+        return (StatusCode::BAD_REQUEST, "Body is not JSON");
+    };
+    let Some(kind) = event_kind(&payload, header("x-gitlab-event")) else {
+        return (
+            StatusCode::BAD_REQUEST,
+            "Missing object_kind and X-Gitlab-Event",
+        );
+    };
+
+    let vars = [("kind", kind.as_str())];
+    // The outlet logs and reports what it cannot deliver
+    if state
+        .outlet
+        .publish(&format!("gitlab.{kind}"), &vars, payload.clone())
+        .await
+        .is_err()
+    {
+        return (StatusCode::SERVICE_UNAVAILABLE, "Failed to publish event");
+    }
+    for (message_type, event) in scm::events(&kind, &payload) {
+        let _ = state.outlet.publish(message_type, &vars, event).await;
+    }
+
+    (StatusCode::ACCEPTED, "")
+}
+
+/// Run the primitive with `args` as its command line (the first item is
+/// the program name, as with `std::env::args_os`).
+pub async fn run<I, T>(args: I) -> Result<(), Box<dyn std::error::Error>>
+where
+    I: IntoIterator<Item = T>,
+    T: Into<std::ffi::OsString> + Clone,
+{
+    let args = Args::parse_from(args);
+
+    // Get the source name from environment (set by engine) or use default
+    let name = std::env::var("EMERGENT_NAME").unwrap_or_else(|_| "gitlab-source".to_string());
+
+    if args.self_test {
+        self_test(&args, &name);
+    }
+    if let Err(e) = check_path(&args.path).and_then(|_| args.source.validate(TEMPLATE_VARIABLES)) {
+        eprintln!("Error: {e}");
+        std::process::exit(1);
+    }
+
+    // Event kinds are only known per delivery, so the namespace is listed
+    let produces =
+        args.source
+            .produces(&["gitlab.*", "scm.push", "scm.pull_request", "scm.pipeline"]);
+    let produces: Vec<&str> = produces.iter().map(String::as_str).collect();
+    let descriptor = args
+        .capabilities
+        .describe(&name, Role::Source, &[], &produces, &args);
+    if args.capabilities.capabilities {
+        capabilities::print(&descriptor);
+    }
+
+    // Connect to the Emergent engine (silently - lifecycle events come from engine)
+    let source = match EmergentSource::connect(&name).await {
+        Ok(s) => s,
+        Err(e) => {
+            eprintln!("Failed to connect to Emergent engine: {e}");
+            std::process::exit(1);
+        }
+    };
+    let outlet = Arc::new(Outlet::new(source, &name, &args.source)?);
+    if args.capabilities.announce {
+        outlet.announce(capabilities_message(&descriptor)).await;
+    }
+    tokio::spawn(Arc::clone(&outlet).drain_every(args.source.spool.retry_interval()));
+
+    let state = Arc::new(AppState {
+        outlet: Arc::clone(&outlet),
+        secret: args.secret.clone(),
+    });
+    let app = Router::new()
+        .route(&args.path, post(handle_delivery))
+        .with_state(state);
+
+    let addr: SocketAddr = format!("{}:{}", args.host, args.port).parse()?;
+
+    // Set up SIGTERM handler for graceful shutdown
+    let mut sigterm = signal(SignalKind::terminate())?;
+
+    let shutdown = Arc::new(Notify::new());
+    let server = axum::serve(
+        tokio::net::TcpListener::bind(&addr).await?,
+        app.into_make_service(),
+    )
+    .with_graceful_shutdown({
+        let shutdown = Arc::clone(&shutdown);
+        async move { shutdown.notified().await }
+    })
+    .into_future();
+    tokio::pin!(server);
+
+    tokio::select! {
+        result = &mut server => {
+            result?;
+        }
+        _ = sigterm.recv() => {
+            // Stop accepting connections and let in-flight deliveries finish
+            shutdown.notify_one();
+            args.source.drain.drain("in-flight deliveries", &mut server).await;
+            outlet.disconnect().await;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use emergent_testkit::{MockEngine, fixtures};
+    use serde_json::json;
+    use std::{path::Path, time::Duration};
+
+    /// Connect to the engine on `socket` as the source would.
+    async fn connect(socket: &Path, args: &Args) -> Arc<AppState> {
+        let source = EmergentSource::connect_to("gitlab-source", socket)
+            .await
+            .unwrap_or_else(|e| panic!("connect: {e}"));
+        let outlet = Outlet::new(source, "gitlab-source", &args.source)
+            .unwrap_or_else(|e| panic!("open spool: {e}"));
+        Arc::new(AppState {
+            outlet: Arc::new(outlet),
+            secret: args.secret.clone(),
+        })
+    }
+
+    async fn deliver(
+        state: &Arc<AppState>,
+        headers: &[(&'static str, &str)],
+        body: &Value,
+    ) -> StatusCode {
+        let mut map = HeaderMap::new();
+        for (name, value) in headers {
+            map.insert(*name, value.parse().unwrap_or_else(|e| panic!("{e}")));
+        }
+        handle_delivery(State(Arc::clone(state)), map, Bytes::from(body.to_string()))
+            .await
+            .into_response()
+            .status()
+    }
+
+    fn push() -> Value {
+        json!({
+            "object_kind": "push",
+            "ref": "refs/heads/main",
+            "after": "abc123",
+            "project": {"path_with_namespace": "acme/api"},
+        })
+    }
+
+    #[tokio::test]
+    async fn bad_tokens_are_rejected_without_publishing() {
+        let dir = fixtures::TempDir::new("gitlab-source-token");
+        let socket = dir.path().join("engine.sock");
+        let args = Args::parse_from(["gitlab-source", "--secret", "s3cret"]);
+        let mut engine = MockEngine::serve(&socket);
+        let state = connect(&socket, &args).await;
+
+        let wrong = deliver(&state, &[("x-gitlab-token", "guess")], &push()).await;
+        assert_eq!(wrong, StatusCode::UNAUTHORIZED);
+        assert_eq!(
+            deliver(&state, &[], &push()).await,
+            StatusCode::UNAUTHORIZED
+        );
+        engine.expect_quiet(Duration::from_millis(100)).await;
+
+        let right = deliver(&state, &[("x-gitlab-token", "s3cret")], &push()).await;
+        assert_eq!(right, StatusCode::ACCEPTED);
+        engine.expect_published("gitlab.push").await;
+        engine.shut_down().await;
+    }
+
+    #[tokio::test]
+    async fn unknown_events_are_published_raw_only() {
+        let dir = fixtures::TempDir::new("gitlab-source-unknown");
+        let socket = dir.path().join("engine.sock");
+        let args = Args::parse_from(["gitlab-source"]);
+        let mut engine = MockEngine::serve(&socket);
+        let state = connect(&socket, &args).await;
+
+        let wiki = json!({"object_kind": "wiki_page", "object_attributes": {"slug": "home"}});
+        assert_eq!(deliver(&state, &[], &wiki).await, StatusCode::ACCEPTED);
+        let published = engine.expect_published("gitlab.wiki_page").await;
+        assert_eq!(published.payload()["object_attributes"]["slug"], "home");
+        engine.expect_quiet(Duration::from_millis(100)).await;
+
+        // Without a kind there is nothing to name the event after
+        let status = deliver(&state, &[], &json!({"ref": "refs/heads/main"})).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        engine.expect_quiet(Duration::from_millis(100)).await;
+        engine.shut_down().await;
+    }
+
+    #[tokio::test]
+    async fn deliveries_survive_the_engine_being_down() {
+        let dir = fixtures::TempDir::new("gitlab-source-spool");
+        let socket = dir.path().join("engine.sock");
+        let spool = dir.path().join("spool");
+        let args = Args::parse_from([
+            "gitlab-source",
+            "--spool-dir",
+            spool.to_str().unwrap_or_default(),
+        ]);
+
+        let mut engine = MockEngine::serve(&socket);
+        let state = connect(&socket, &args).await;
+        engine.shut_down().await;
+        assert_eq!(deliver(&state, &[], &push()).await, StatusCode::ACCEPTED);
+        drop(state);
+
+        let mut engine = MockEngine::serve(&socket);
+        let state = connect(&socket, &args).await;
+        state.outlet.drain_spool().await;
+        let published = engine.expect_published("gitlab.push").await;
+        assert_eq!(published.payload()["after"], "abc123");
+        engine.expect_published("scm.push").await;
+        engine.shut_down().await;
+    }
+
+    #[tokio::test]
+    async fn undeliverable_events_are_refused_for_a_retry() {
+        let dir = fixtures::TempDir::new("gitlab-source-down");
+        let socket = dir.path().join("engine.sock");
+        let args = Args::parse_from(["gitlab-source"]);
+
+        let mut engine = MockEngine::serve(&socket);
+        let state = connect(&socket, &args).await;
+        engine.shut_down().await;
+        let status = deliver(&state, &[], &push()).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    }
+
+    #[test]
+    fn event_kinds_come_from_the_body_or_header() {
+        let push = json!({"object_kind": "push", "ref": "refs/heads/main"});
+        assert_eq!(
+            event_kind(&push, Some("Push Hook")),
+            Some("push".to_string())
+        );
+        let system = json!({"event_type": "merge_request"});
+        assert_eq!(
+            event_kind(&system, Some("System Hook")),
+            Some("merge_request".to_string())
+        );
+        assert_eq!(
+            event_kind(&json!({}), Some("Merge Request Hook")),
+            Some("merge_request".to_string())
+        );
+        assert_eq!(event_kind(&json!({}), None), None);
+    }
+}
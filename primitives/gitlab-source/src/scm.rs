@@ -0,0 +1,153 @@
+//! Provider-agnostic `scm.*` events.
+//!
+//! Alongside each raw `gitlab.<kind>`, pushes, merge request changes and
+//! pipelines are also published in the shared schemas (`scm.push`,
+//! `scm.pull_request`, `scm.pipeline`) that the other SCM sources emit.
+//!
+//! | GitLab event | `scm` event |
+//! |--------------|-------------|
+//! | `push`, `tag_push` | `scm.push` |
+//! | `merge_request` (`open`, `update`, `merge`, `close`, `reopen`) | `scm.pull_request` (`opened`, `updated`, `merged`, `closed`, `reopened`) |
+//! | `pipeline` | `scm.pipeline` |
+
+use event_schemas::{EventPayload, ScmPipeline, ScmPullRequest, ScmPush};
+use serde_json::Value;
+
+const PROVIDER: &str = "gitlab";
+
+/// The `scm.*` events for a delivery of `kind`, as (message type, payload)
+/// pairs; none for events without an `scm` equivalent.
+pub fn events(kind: &str, payload: &Value) -> Vec<(&'static str, Value)> {
+    let normalized = match kind {
+        "push" | "tag_push" => push(payload).map(|e| (ScmPush::MESSAGE_TYPE, e.to_payload())),
+        "merge_request" => {
+            merge_request(payload).map(|e| (ScmPullRequest::MESSAGE_TYPE, e.to_payload()))
+        }
+        "pipeline" => pipeline(payload).map(|e| (ScmPipeline::MESSAGE_TYPE, e.to_payload())),
+        _ => None,
+    };
+    normalized.into_iter().collect()
+}
+
+fn string(value: &Value) -> Option<String> {
+    value.as_str().map(str::to_string)
+}
+
+/// A commit id, with the all-zero id GitLab uses for a missing side as `None`.
+fn commit(value: &Value) -> Option<String> {
+    string(value).filter(|sha| sha.bytes().any(|b| b != b'0'))
+}
+
+fn push(payload: &Value) -> Option<ScmPush> {
+    let reference = payload["ref"].as_str()?;
+    Some(ScmPush {
+        provider: PROVIDER.to_string(),
+        repository: string(&payload["project"]["path_with_namespace"])?,
+        r#ref: reference.to_string(),
+        branch: reference.strip_prefix("refs/heads/").map(str::to_string),
+        tag: reference.strip_prefix("refs/tags/").map(str::to_string),
+        before: commit(&payload["before"]),
+        after: commit(&payload["after"]),
+        commits: payload["total_commits_count"].as_u64().unwrap_or(0),
+        sender: string(&payload["user_username"]),
+        url: string(&payload["project"]["web_url"]),
+    })
+}
+
+fn merge_request(payload: &Value) -> Option<ScmPullRequest> {
+    let mr = &payload["object_attributes"];
+    let action = match mr["action"].as_str()? {
+        "open" => "opened",
+        "update" => "updated",
+        "merge" => "merged",
+        "close" => "closed",
+        "reopen" => "reopened",
+        _ => return None,
+    };
+    Some(ScmPullRequest {
+        provider: PROVIDER.to_string(),
+        repository: string(&payload["project"]["path_with_namespace"])?,
+        number: mr["iid"].as_u64()?,
+        action: action.to_string(),
+        title: string(&mr["title"]).unwrap_or_default(),
+        source_branch: string(&mr["source_branch"])?,
+        target_branch: string(&mr["target_branch"])?,
+        head_sha: string(&mr["last_commit"]["id"]),
+        sender: string(&payload["user"]["username"]),
+        url: string(&mr["url"]),
+    })
+}
+
+fn pipeline(payload: &Value) -> Option<ScmPipeline> {
+    let attributes = &payload["object_attributes"];
+    let status = match attributes["status"].as_str()? {
+        "running" => "running",
+        "success" => "success",
+        "failed" => "failed",
+        "canceled" | "canceling" => "canceled",
+        "skipped" => "skipped",
+        _ => "queued",
+    };
+    let id = attributes["id"].as_u64()?;
+    let url = string(&attributes["url"]).or_else(|| {
+        payload["project"]["web_url"]
+            .as_str()
+            .map(|project| format!("{project}/-/pipelines/{id}"))
+    });
+    Some(ScmPipeline {
+        provider: PROVIDER.to_string(),
+        repository: string(&payload["project"]["path_with_namespace"])?,
+        id: id.to_string(),
+        name: string(&attributes["name"]),
+        branch: string(&attributes["ref"]),
+        sha: string(&attributes["sha"]),
+        status: status.to_string(),
+        url,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn deliveries_normalize_to_scm_events() {
+        let push = json!({
+            "object_kind": "tag_push",
+            "ref": "refs/tags/v1.2.0",
+            "before": "0000000000000000000000000000000000000000",
+            "after": "82b3d5ae55f7080f1e6022629cdb57bfae7cccc7",
+            "user_username": "jsmith",
+            "total_commits_count": 0,
+            "project": {"path_with_namespace": "platform/app", "web_url": "https://gitlab.com/platform/app"},
+        });
+        let events = events("tag_push", &push);
+        assert_eq!(events[0].0, "scm.push");
+        assert_eq!(events[0].1["tag"], "v1.2.0");
+        assert!(events[0].1["branch"].is_null());
+        assert!(events[0].1["before"].is_null());
+
+        let mr = json!({
+            "object_attributes": {"iid": 17, "action": "merge", "title": "Fix",
+                                  "source_branch": "fix", "target_branch": "main"},
+            "project": {"path_with_namespace": "platform/app"},
+            "user": {"username": "jsmith"},
+        });
+        let events = super::events("merge_request", &mr);
+        assert_eq!(events[0].1["action"], "merged");
+        assert_eq!(events[0].1["sender"], "jsmith");
+
+        let pipeline = json!({
+            "object_attributes": {"id": 31, "ref": "main", "status": "pending"},
+            "project": {"path_with_namespace": "platform/app", "web_url": "https://gitlab.com/platform/app"},
+        });
+        let events = super::events("pipeline", &pipeline);
+        assert_eq!(events[0].1["status"], "queued");
+        assert_eq!(
+            events[0].1["url"],
+            "https://gitlab.com/platform/app/-/pipelines/31"
+        );
+        assert!(super::events("note", &pipeline).is_empty());
+    }
+}
@@ -0,0 +1,181 @@
+//! Request body encodings.
+//!
+//! The body — the payload, or the rendered body template — is sent as the
+//! endpoint's content type:
+//!
+//! - `application/json` (the default), or any `+json` type: as JSON
+//! - `application/x-www-form-urlencoded`: an object's fields as form
+//!   fields; arrays repeat the field, nested objects are sent as JSON text
+//! - `text/*`: a string as-is, anything else as JSON text
+//! - `multipart/form-data`: an object's fields as parts. A field holding
+//!   `{"base64": "...", "filename": "report.pdf", "content_type":
+//!   "application/pdf"}` becomes a file part of the decoded bytes (the name
+//!   and type are optional); arrays repeat the part, and other objects are
+//!   sent as JSON parts.
+//!
+//! Null fields are left out of forms.
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use reqwest::RequestBuilder;
+use reqwest::header::CONTENT_TYPE;
+use reqwest::multipart::{Form, Part};
+use serde_json::{Map, Value};
+
+/// How a content type is encoded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    Json,
+    Form,
+    Text,
+    Multipart,
+}
+
+impl Encoding {
+    /// The encoding for `content_type`, ignoring parameters such as
+    /// `charset`.
+    pub fn of(content_type: &str) -> Result<Self, String> {
+        let essence = content_type
+            .split(';')
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .to_ascii_lowercase();
+        match essence.as_str() {
+            "application/json" => Ok(Self::Json),
+            "application/x-www-form-urlencoded" => Ok(Self::Form),
+            "multipart/form-data" => Ok(Self::Multipart),
+            _ if essence.starts_with("application/") && essence.ends_with("+json") => {
+                Ok(Self::Json)
+            }
+            _ if essence.starts_with("text/") => Ok(Self::Text),
+            _ => Err(format!(
+                "unsupported content type '{content_type}' (expected JSON, form, multipart or text)"
+            )),
+        }
+    }
+}
+
+/// `value` as the text of a form field or part.
+fn text(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+/// The object a form is built from.
+fn fields<'a>(body: &'a Value, kind: &str) -> Result<&'a Map<String, Value>, String> {
+    body.as_object()
+        .ok_or_else(|| format!("a {kind} body must be a JSON object"))
+}
+
+/// The `name=value` pairs of a URL-encoded form.
+fn form_pairs(body: &Value) -> Result<Vec<(String, String)>, String> {
+    let mut pairs = Vec::new();
+    for (name, value) in fields(body, "form")? {
+        match value {
+            Value::Null => {}
+            Value::Array(items) => pairs.extend(
+                items
+                    .iter()
+                    .filter(|item| !item.is_null())
+                    .map(|item| (name.clone(), text(item))),
+            ),
+            other => pairs.push((name.clone(), text(other))),
+        }
+    }
+    Ok(pairs)
+}
+
+/// One multipart part for `value`.
+fn part(name: &str, value: &Value) -> Result<Part, String> {
+    let Value::Object(object) = value else {
+        return Ok(Part::text(text(value)));
+    };
+    let Some(data) = object.get("base64") else {
+        return Part::text(value.to_string())
+            .mime_str("application/json")
+            .map_err(|e| format!("{name}: {e}"));
+    };
+    let bytes = STANDARD
+        .decode(data.as_str().unwrap_or_default().trim())
+        .map_err(|e| format!("{name}: invalid base64: {e}"))?;
+    let mut part = Part::bytes(bytes);
+    if let Some(filename) = object.get("filename").and_then(Value::as_str) {
+        part = part.file_name(filename.to_string());
+    }
+    let content_type = object
+        .get("content_type")
+        .and_then(Value::as_str)
+        .unwrap_or("application/octet-stream");
+    part.mime_str(content_type)
+        .map_err(|e| format!("{name}: invalid content_type '{content_type}': {e}"))
+}
+
+/// The parts of a multipart form.
+fn multipart(body: &Value) -> Result<Form, String> {
+    let mut form = Form::new();
+    for (name, value) in fields(body, "multipart")? {
+        let items = match value {
+            Value::Array(items) => items.as_slice(),
+            other => std::slice::from_ref(other),
+        };
+        for item in items.iter().filter(|item| !item.is_null()) {
+            form = form.part(name.clone(), part(name, item)?);
+        }
+    }
+    Ok(form)
+}
+
+/// Attach `body` to `request`, encoded as `content_type`.
+pub fn attach(
+    request: RequestBuilder,
+    content_type: &str,
+    body: &Value,
+) -> Result<RequestBuilder, String> {
+    Ok(match Encoding::of(content_type)? {
+        Encoding::Json => request.json(body).header(CONTENT_TYPE, content_type),
+        Encoding::Form => request.form(&form_pairs(body)?),
+        Encoding::Text => request.header(CONTENT_TYPE, content_type).body(text(body)),
+        // The form sets its own content type, with the boundary
+        Encoding::Multipart => request.multipart(multipart(body)?),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn content_types_pick_an_encoding() {
+        assert_eq!(Encoding::of("application/json"), Ok(Encoding::Json));
+        assert_eq!(Encoding::of("application/vnd.api+json"), Ok(Encoding::Json));
+        assert_eq!(
+            Encoding::of("Application/X-WWW-Form-Urlencoded; charset=utf-8"),
+            Ok(Encoding::Form)
+        );
+        assert_eq!(Encoding::of("text/csv"), Ok(Encoding::Text));
+        assert_eq!(Encoding::of("multipart/form-data"), Ok(Encoding::Multipart));
+        assert!(Encoding::of("application/xml").is_err());
+
+        assert_eq!(
+            form_pairs(
+                &json!({"to": "+15550100", "tags": ["a", "b"], "count": 2, "skip": null, "meta": {"x": 1}})
+            ),
+            Ok(vec![
+                ("count".to_string(), "2".to_string()),
+                ("meta".to_string(), r#"{"x":1}"#.to_string()),
+                ("tags".to_string(), "a".to_string()),
+                ("tags".to_string(), "b".to_string()),
+                ("to".to_string(), "+15550100".to_string()),
+            ])
+        );
+        assert!(form_pairs(&json!("text")).is_err());
+        assert!(part("file", &json!({"base64": "not base64!"})).is_err());
+        assert!(
+            multipart(&json!({"file": {"base64": "aGk=", "content_type": "bad type"}})).is_err()
+        );
+    }
+}
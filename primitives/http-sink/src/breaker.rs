@@ -0,0 +1,121 @@
+//! Per-host circuit breakers.
+//!
+//! After `--breaker-threshold` consecutive failures against a host —
+//! connection errors, timeouts, 5xx and 429 answers — its breaker opens,
+//! and requests to it fail at once, without being sent, for
+//! `--breaker-cooldown`. Other answers, 4xx included, show the host is up
+//! and close it again. Once the cooldown ends requests go through again:
+//! the first success closes the breaker, a failure reopens it straight away.
+
+use std::collections::HashMap;
+use std::sync::{Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+/// A host's recent record.
+#[derive(Debug, Default)]
+struct Host {
+    failures: u32,
+    open_until: Option<Instant>,
+}
+
+/// The breakers of every host requests have gone to.
+#[derive(Debug)]
+pub struct Breakers {
+    threshold: u32,
+    cooldown: Duration,
+    hosts: Mutex<HashMap<String, Host>>,
+}
+
+impl Breakers {
+    /// Breakers opening after `threshold` consecutive failures, for
+    /// `cooldown`; `None` for a threshold of 0.
+    pub fn new(threshold: u32, cooldown: Duration) -> Option<Self> {
+        (threshold > 0).then(|| Self {
+            threshold,
+            cooldown,
+            hosts: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// The host of `url`, with its port, as breakers are keyed.
+    pub fn host(url: &str) -> String {
+        match reqwest::Url::parse(url) {
+            Ok(url) => format!(
+                "{}:{}",
+                url.host_str().unwrap_or_default(),
+                url.port_or_known_default().unwrap_or_default()
+            ),
+            Err(_) => url.to_string(),
+        }
+    }
+
+    /// How long `host`'s breaker stays open, or `None` if requests may go.
+    pub fn open_for(&self, host: &str, now: Instant) -> Option<Duration> {
+        let hosts = self.hosts.lock().unwrap_or_else(PoisonError::into_inner);
+        let until = hosts.get(host)?.open_until?;
+        (now < until).then(|| until - now)
+    }
+
+    /// Record the outcome of a request to `host`.
+    pub fn record(&self, host: &str, failed: bool, now: Instant) {
+        let mut hosts = self.hosts.lock().unwrap_or_else(PoisonError::into_inner);
+        if !failed {
+            if let Some(state) = hosts.remove(host)
+                && state.open_until.is_some()
+            {
+                eprintln!("http_sink: circuit to {host} closed");
+            }
+            return;
+        }
+        let state = hosts.entry(host.to_string()).or_default();
+        state.failures = state.failures.saturating_add(1);
+        if state.failures >= self.threshold {
+            state.open_until = Some(now + self.cooldown);
+            eprintln!(
+                "http_sink: circuit to {host} open for {}ms after {} consecutive failures",
+                self.cooldown.as_millis(),
+                state.failures
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn breakers_open_per_host_and_close_on_success() {
+        assert!(Breakers::new(0, Duration::from_secs(1)).is_none());
+        let breakers =
+            Breakers::new(2, Duration::from_secs(10)).unwrap_or_else(|| panic!("enabled"));
+        let host = Breakers::host("https://api.example.com/v1/events");
+        assert_eq!(host, "api.example.com:443");
+        let start = Instant::now();
+
+        breakers.record(&host, true, start);
+        assert_eq!(breakers.open_for(&host, start), None);
+        breakers.record(&host, true, start);
+        assert_eq!(
+            breakers.open_for(&host, start),
+            Some(Duration::from_secs(10))
+        );
+        let later = start + Duration::from_secs(4);
+        assert_eq!(
+            breakers.open_for(&host, later),
+            Some(Duration::from_secs(6))
+        );
+        // Other hosts are unaffected
+        assert_eq!(breakers.open_for("other.example.com:443", later), None);
+
+        // After the cooldown one failure reopens it, one success closes it
+        let after = start + Duration::from_secs(10);
+        assert_eq!(breakers.open_for(&host, after), None);
+        breakers.record(&host, true, after);
+        assert!(breakers.open_for(&host, after).is_some());
+        let after = after + Duration::from_secs(10);
+        breakers.record(&host, false, after);
+        breakers.record(&host, true, after);
+        assert_eq!(breakers.open_for(&host, after), None);
+    }
+}
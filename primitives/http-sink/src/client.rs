@@ -0,0 +1,206 @@
+//! HTTP client tuning and TLS identity.
+//!
+//! The client is built once at startup, so these flags are not
+//! hot-swappable through `--config`. `--client-cert` presents a client
+//! certificate for mutual TLS, and `--ca-cert` trusts a private CA
+//! alongside the public roots, for services inside a zero-trust mesh.
+
+use clap::Args;
+use reqwest::{Certificate, Client, Identity};
+use std::path::{Path, PathBuf};
+use std::{net::SocketAddr, time::Duration};
+
+/// CLI flags controlling connections.
+#[derive(Args, Debug, Clone, Default)]
+pub struct ClientArgs {
+    /// Speak HTTP/2 without negotiation (h2c for `http://`, skips ALPN fallback for `https://`).
+    #[arg(long, env = "HTTP_SINK_HTTP2_PRIOR_KNOWLEDGE")]
+    pub http2_prior_knowledge: bool,
+
+    /// Idle connections kept open per host.
+    #[arg(long, env = "HTTP_SINK_POOL_MAX_IDLE_PER_HOST")]
+    pub pool_max_idle_per_host: Option<usize>,
+
+    /// Milliseconds an idle pooled connection is kept before closing.
+    #[arg(long, env = "HTTP_SINK_POOL_IDLE_TIMEOUT")]
+    pub pool_idle_timeout: Option<u64>,
+
+    /// TCP keepalive interval in milliseconds.
+    #[arg(long, env = "HTTP_SINK_TCP_KEEPALIVE")]
+    pub tcp_keepalive: Option<u64>,
+
+    /// Milliseconds allowed to establish a connection, separate from the request timeout.
+    #[arg(long, env = "HTTP_SINK_CONNECT_TIMEOUT")]
+    pub connect_timeout: Option<u64>,
+
+    /// Pin a host to an address as `host:port:addr` (like `curl --resolve`); repeatable.
+    #[arg(long = "resolve", env = "HTTP_SINK_RESOLVE", value_delimiter = ',', value_parser = parse_resolve)]
+    pub resolve: Vec<(String, SocketAddr)>,
+
+    /// PEM client certificate (chain) presented for mutual TLS; may also hold the key.
+    #[arg(long, env = "HTTP_SINK_CLIENT_CERT")]
+    pub client_cert: Option<PathBuf>,
+
+    /// PEM private key for `--client-cert` (RSA, SEC1 or PKCS#8).
+    #[arg(long, env = "HTTP_SINK_CLIENT_KEY", requires = "client_cert")]
+    pub client_key: Option<PathBuf>,
+
+    /// PEM CA certificate(s) to trust in addition to the public roots.
+    #[arg(long, env = "HTTP_SINK_CA_CERT")]
+    pub ca_cert: Option<PathBuf>,
+}
+
+fn read(path: &Path) -> Result<Vec<u8>, String> {
+    std::fs::read(path).map_err(|e| format!("{}: {e}", path.display()))
+}
+
+impl ClientArgs {
+    /// Build the client these flags describe.
+    pub fn build(&self) -> Result<Client, String> {
+        let mut builder = Client::builder();
+        if self.http2_prior_knowledge {
+            builder = builder.http2_prior_knowledge();
+        }
+        if let Some(max) = self.pool_max_idle_per_host {
+            builder = builder.pool_max_idle_per_host(max);
+        }
+        if let Some(ms) = self.pool_idle_timeout {
+            builder = builder.pool_idle_timeout(Duration::from_millis(ms));
+        }
+        if let Some(ms) = self.tcp_keepalive {
+            builder = builder.tcp_keepalive(Duration::from_millis(ms));
+        }
+        if let Some(ms) = self.connect_timeout {
+            builder = builder.connect_timeout(Duration::from_millis(ms));
+        }
+        for (host, addr) in &self.resolve {
+            builder = builder.resolve(host, *addr);
+        }
+        if let Some(cert) = &self.client_cert {
+            let mut pem = read(cert)?;
+            if let Some(key) = &self.client_key {
+                pem.push(b'\n');
+                pem.extend(read(key)?);
+            }
+            let identity = Identity::from_pem(&pem)
+                .map_err(|e| format!("{}: invalid client certificate: {e}", cert.display()))?;
+            builder = builder.identity(identity);
+        }
+        if let Some(ca) = &self.ca_cert {
+            let certs = Certificate::from_pem_bundle(&read(ca)?)
+                .map_err(|e| format!("{}: invalid CA certificate: {e}", ca.display()))?;
+            if certs.is_empty() {
+                return Err(format!("{}: no certificates", ca.display()));
+            }
+            for cert in certs {
+                builder = builder.add_root_certificate(cert);
+            }
+        }
+        builder
+            .build()
+            .map_err(|e| format!("failed to build HTTP client: {e}"))
+    }
+}
+
+/// Parse a `--resolve` value: `host:port:addr`, where `addr` may be an
+/// IPv6 address in brackets.
+pub fn parse_resolve(s: &str) -> Result<(String, SocketAddr), String> {
+    let invalid = || format!("expected HOST:PORT:ADDR, got '{s}'");
+    let (host, rest) = s.split_once(':').ok_or_else(invalid)?;
+    let (port, addr) = rest.split_once(':').ok_or_else(invalid)?;
+    let port: u16 = port.parse().map_err(|_| invalid())?;
+    let addr = addr.trim_start_matches('[').trim_end_matches(']');
+    let ip = addr.parse().map_err(|_| invalid())?;
+    if host.is_empty() {
+        return Err(invalid());
+    }
+    Ok((host.to_string(), SocketAddr::new(ip, port)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+    #[test]
+    fn resolve_overrides_parse() {
+        assert_eq!(
+            parse_resolve("api.example.com:443:10.0.0.7"),
+            Ok((
+                "api.example.com".to_string(),
+                SocketAddr::new(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 7)), 443)
+            ))
+        );
+        assert_eq!(
+            parse_resolve("api.example.com:8443:[::1]").map(|(_, addr)| addr),
+            Ok(SocketAddr::new(IpAddr::V6(Ipv6Addr::LOCALHOST), 8443))
+        );
+        assert!(parse_resolve("api.example.com:10.0.0.7").is_err());
+        assert!(parse_resolve("api.example.com:https:10.0.0.7").is_err());
+        assert!(parse_resolve(":443:10.0.0.7").is_err());
+    }
+
+    #[test]
+    fn tuned_client_builds() {
+        let args = ClientArgs {
+            http2_prior_knowledge: true,
+            pool_max_idle_per_host: Some(4),
+            pool_idle_timeout: Some(30_000),
+            tcp_keepalive: Some(15_000),
+            connect_timeout: Some(2_000),
+            resolve: vec![(
+                "api.example.com".to_string(),
+                SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 443),
+            )],
+            ..ClientArgs::default()
+        };
+        assert!(args.build().is_ok());
+    }
+
+    #[test]
+    fn client_certificates_and_private_cas_load_from_pem() {
+        let dir = std::env::temp_dir().join(format!("http-sink-tls-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap_or_else(|e| panic!("{e}"));
+        let write = |name: &str, pem: &str| {
+            let path = dir.join(name);
+            std::fs::write(&path, pem).unwrap_or_else(|e| panic!("{e}"));
+            path
+        };
+        let key = rcgen::KeyPair::generate().unwrap_or_else(|e| panic!("{e}"));
+        let cert = rcgen::CertificateParams::new(vec!["sink.mesh.internal".to_string()])
+            .and_then(|params| params.self_signed(&key))
+            .unwrap_or_else(|e| panic!("{e}"));
+        let cert_path = write("client.crt", &cert.pem());
+        let key_path = write("client.key", &key.serialize_pem());
+        let combined = write(
+            "client.pem",
+            &format!("{}{}", cert.pem(), key.serialize_pem()),
+        );
+
+        let mtls = |client_cert: &Path, client_key: Option<&Path>, ca_cert: &Path| ClientArgs {
+            client_cert: Some(client_cert.to_path_buf()),
+            client_key: client_key.map(Path::to_path_buf),
+            ca_cert: Some(ca_cert.to_path_buf()),
+            ..ClientArgs::default()
+        };
+        assert!(
+            mtls(&cert_path, Some(&key_path), &cert_path)
+                .build()
+                .is_ok()
+        );
+        assert!(mtls(&combined, None, &cert_path).build().is_ok());
+        // A certificate without its key, or a key where the CA should be
+        assert!(mtls(&cert_path, None, &cert_path).build().is_err());
+        assert!(
+            mtls(&cert_path, Some(&key_path), &key_path)
+                .build()
+                .is_err()
+        );
+        assert!(
+            mtls(&cert_path, Some(&dir.join("missing.key")), &cert_path)
+                .build()
+                .is_err()
+        );
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
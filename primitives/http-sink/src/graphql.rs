@@ -0,0 +1,108 @@
+//! GraphQL requests.
+//!
+//! With `--graphql`, the payload (or the rendered `--body-template`) names
+//! the operation: a `query` string, plus optional `variables` and
+//! `operationName` (or `operation_name`). They are POSTed as a standard
+//! GraphQL JSON request. GraphQL servers report failures in an `errors`
+//! array while answering `200 OK`, so a response carrying errors fails the
+//! message like an HTTP error, even alongside partial `data`.
+
+use serde_json::{Map, Value};
+
+/// The GraphQL request for `body`.
+pub fn request(body: &Value) -> Result<Value, String> {
+    let Some(query) = body.get("query").and_then(Value::as_str) else {
+        return Err("GraphQL payload has no 'query' string".to_string());
+    };
+    let mut request = Map::new();
+    request.insert("query".to_string(), Value::from(query));
+    match body.get("variables") {
+        None | Some(Value::Null) => {}
+        Some(variables @ Value::Object(_)) => {
+            request.insert("variables".to_string(), variables.clone());
+        }
+        Some(other) => return Err(format!("GraphQL 'variables' is not an object: {other}")),
+    }
+    let operation = body
+        .get("operationName")
+        .or_else(|| body.get("operation_name"));
+    match operation {
+        None | Some(Value::Null) => {}
+        Some(Value::String(name)) => {
+            request.insert("operationName".to_string(), Value::from(name.as_str()));
+        }
+        Some(other) => return Err(format!("GraphQL operation name is not a string: {other}")),
+    }
+    Ok(Value::Object(request))
+}
+
+/// Judge a 2xx GraphQL response body: any `errors` fail it.
+pub fn check(body: &[u8]) -> Result<(), String> {
+    let body: Value =
+        serde_json::from_slice(body).map_err(|e| format!("response is not JSON: {e}"))?;
+    let errors = match body.get("errors") {
+        None | Some(Value::Null) => return Ok(()),
+        Some(Value::Array(errors)) if errors.is_empty() => return Ok(()),
+        Some(Value::Array(errors)) => errors,
+        Some(other) => return Err(format!("GraphQL errors: {other}")),
+    };
+    let messages: Vec<String> = errors
+        .iter()
+        .map(|error| match error.get("message").and_then(Value::as_str) {
+            Some(message) => message.to_string(),
+            None => error.to_string(),
+        })
+        .collect();
+    Err(format!("GraphQL errors: {}", messages.join("; ")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn payloads_become_graphql_requests() {
+        let payload = json!({
+            "query": "mutation Close($id: ID!) { closeIssue(id: $id) { id } }",
+            "variables": {"id": "I_42"},
+            "operation_name": "Close",
+            "source": "tracker",
+        });
+        assert_eq!(
+            request(&payload),
+            Ok(json!({
+                "query": "mutation Close($id: ID!) { closeIssue(id: $id) { id } }",
+                "variables": {"id": "I_42"},
+                "operationName": "Close",
+            }))
+        );
+        assert_eq!(
+            request(&json!({"query": "{ viewer { login } }", "variables": null})),
+            Ok(json!({"query": "{ viewer { login } }"}))
+        );
+        assert!(request(&json!({"variables": {}})).is_err());
+        assert!(request(&json!({"query": "{ a }", "variables": [1]})).is_err());
+    }
+
+    #[test]
+    fn errors_fail_even_with_data() {
+        assert_eq!(
+            check(br#"{"data": {"closeIssue": {"id": "I_42"}}}"#),
+            Ok(())
+        );
+        assert_eq!(check(br#"{"data": {}, "errors": []}"#), Ok(()));
+        assert_eq!(
+            check(
+                br#"{"data": {"closeIssue": null}, "errors": [
+                    {"message": "Issue not found", "path": ["closeIssue"]},
+                    {"extensions": {"code": "FORBIDDEN"}}]}"#
+            ),
+            Err(
+                r#"GraphQL errors: Issue not found; {"extensions":{"code":"FORBIDDEN"}}"#
+                    .to_string()
+            )
+        );
+        assert!(check(b"<html>").is_err());
+    }
+}
@@ -0,0 +1,429 @@
+//! The jq subset `--success-expr` is written in (see [`crate::success`]).
+//!
+//! - Paths: `.` (the whole body), `.status`, `.data[0].code`,
+//!   `.["odd key"]`, `.items[-1]` (counting from the end); a missing
+//!   field is `null`
+//! - Literals: JSON strings, numbers, `true`, `false`, `null`
+//! - Comparisons: `==` `!=` `<` `<=` `>` `>=`; numbers order against
+//!   numbers and strings against strings, anything else is not ordered
+//! - `and` and `or`, with `and` binding tighter, and parentheses
+//!
+//! As in jq, `false` and `null` are falsy and every other value truthy.
+//!
+//! That is the whole language. The rest of jq (pipes, `,`, functions such
+//! as `length` or `not`, `if`, arithmetic, variables, `..`, `.[]`, slices,
+//! `?`, and array or object construction) is rejected by [`parse`] with an
+//! error naming it, so a config using it fails at startup or reload rather
+//! than judging bodies differently from jq.
+
+use serde_json::Value;
+use std::cmp::Ordering;
+
+/// One step down a path.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Step {
+    Key(String),
+    Index(i64),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Cmp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Path(Vec<Step>),
+    Literal(Value),
+    Cmp(Cmp),
+    And,
+    Or,
+    Open,
+    Close,
+}
+
+/// A parsed expression.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Path(Vec<Step>),
+    Literal(Value),
+    Compare(Cmp, Box<Expr>, Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+}
+
+/// The error for a jq feature outside the subset.
+fn unsupported(what: &str) -> String {
+    format!(
+        "{what} is not supported; only paths, literals, comparisons, `and`, `or` and parentheses are"
+    )
+}
+
+fn is_ident(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+/// The JSON string starting at `chars[at]`, and where it ends.
+fn string(chars: &[char], at: usize) -> Result<(String, usize), String> {
+    let mut end = at + 1;
+    while end < chars.len() && chars[end] != '"' {
+        end += if chars[end] == '\\' { 2 } else { 1 };
+    }
+    if end >= chars.len() {
+        return Err("unterminated string".to_string());
+    }
+    let text: String = chars[at..=end].iter().collect();
+    let value = serde_json::from_str(&text).map_err(|e| format!("invalid string {text}: {e}"))?;
+    Ok((value, end + 1))
+}
+
+/// The path starting at the `.` at `chars[at]`, and where it ends.
+fn path(chars: &[char], mut at: usize) -> Result<(Vec<Step>, usize), String> {
+    let mut steps = Vec::new();
+    while at < chars.len() && matches!(chars[at], '.' | '[') {
+        if chars[at] == '.' {
+            at += 1;
+            if chars.get(at) == Some(&'.') {
+                return Err(unsupported("recursive descent (`..`)"));
+            }
+            let start = at;
+            while at < chars.len() && is_ident(chars[at]) {
+                at += 1;
+            }
+            if at > start {
+                steps.push(Step::Key(chars[start..at].iter().collect()));
+            }
+            continue;
+        }
+        at += 1;
+        if chars.get(at) == Some(&']') {
+            return Err(unsupported("iteration (`[]`)"));
+        }
+        if chars.get(at) == Some(&'"') {
+            let (key, end) = string(chars, at)?;
+            steps.push(Step::Key(key));
+            at = end;
+        } else {
+            let start = at;
+            while at < chars.len() && (chars[at] == '-' || chars[at].is_ascii_digit()) {
+                at += 1;
+            }
+            let index: String = chars[start..at].iter().collect();
+            let index = index
+                .parse()
+                .map_err(|_| format!("invalid index '{index}'"))?;
+            steps.push(Step::Index(index));
+        }
+        if chars.get(at) == Some(&':') {
+            return Err(unsupported("slicing (`[m:n]`)"));
+        }
+        if chars.get(at) == Some(&'?') {
+            return Err(unsupported("`?`"));
+        }
+        if chars.get(at) != Some(&']') {
+            return Err("expected ']'".to_string());
+        }
+        at += 1;
+    }
+    Ok((steps, at))
+}
+
+fn tokenize(text: &str) -> Result<Vec<Token>, String> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut tokens = Vec::new();
+    let mut at = 0;
+    while at < chars.len() {
+        let c = chars[at];
+        let next = chars.get(at + 1).copied();
+        match c {
+            _ if c.is_whitespace() => at += 1,
+            '.' => {
+                let (steps, end) = path(&chars, at)?;
+                tokens.push(Token::Path(steps));
+                at = end;
+            }
+            '"' => {
+                let (value, end) = string(&chars, at)?;
+                tokens.push(Token::Literal(Value::String(value)));
+                at = end;
+            }
+            '(' | ')' => {
+                tokens.push(if c == '(' { Token::Open } else { Token::Close });
+                at += 1;
+            }
+            '=' | '!' | '<' | '>' => {
+                let (cmp, len) = match (c, next) {
+                    ('=', Some('=')) => (Cmp::Eq, 2),
+                    ('!', Some('=')) => (Cmp::Ne, 2),
+                    ('<', Some('=')) => (Cmp::Le, 2),
+                    ('>', Some('=')) => (Cmp::Ge, 2),
+                    ('<', _) => (Cmp::Lt, 1),
+                    ('>', _) => (Cmp::Gt, 1),
+                    _ => return Err(format!("unexpected '{c}'")),
+                };
+                tokens.push(Token::Cmp(cmp));
+                at += len;
+            }
+            _ if c.is_ascii_digit() || (c == '-' && next.is_some_and(|n| n.is_ascii_digit())) => {
+                let start = at;
+                at += 1;
+                while at < chars.len()
+                    && (chars[at].is_ascii_digit() || matches!(chars[at], '.' | 'e' | 'E' | '+'))
+                {
+                    at += 1;
+                }
+                let text: String = chars[start..at].iter().collect();
+                let number: serde_json::Number = text
+                    .parse()
+                    .map_err(|_| format!("invalid number '{text}'"))?;
+                tokens.push(Token::Literal(Value::Number(number)));
+            }
+            _ if is_ident(c) => {
+                let start = at;
+                while at < chars.len() && is_ident(chars[at]) {
+                    at += 1;
+                }
+                let word: String = chars[start..at].iter().collect();
+                tokens.push(match word.as_str() {
+                    "and" => Token::And,
+                    "or" => Token::Or,
+                    "true" => Token::Literal(Value::Bool(true)),
+                    "false" => Token::Literal(Value::Bool(false)),
+                    "null" => Token::Literal(Value::Null),
+                    "if" | "then" | "elif" | "else" | "end" | "as" | "def" | "reduce"
+                    | "foreach" | "try" | "catch" | "label" | "import" | "include" => {
+                        return Err(unsupported(&format!("`{word}`")));
+                    }
+                    _ => return Err(unsupported(&format!("the function `{word}`"))),
+                });
+            }
+            '|' => return Err(unsupported("the pipe (`|`)")),
+            ',' => return Err(unsupported("`,`")),
+            '+' | '-' | '*' | '/' | '%' => return Err(unsupported("arithmetic")),
+            '$' => return Err(unsupported("variables")),
+            '?' => return Err(unsupported("`?`")),
+            '[' => return Err(unsupported("array construction")),
+            '{' => return Err(unsupported("object construction")),
+            _ => return Err(format!("unexpected '{c}'")),
+        }
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    at: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.at)
+    }
+
+    fn or(&mut self) -> Result<Expr, String> {
+        let mut left = self.and()?;
+        while self.peek() == Some(&Token::Or) {
+            self.at += 1;
+            left = Expr::Or(Box::new(left), Box::new(self.and()?));
+        }
+        Ok(left)
+    }
+
+    fn and(&mut self) -> Result<Expr, String> {
+        let mut left = self.compare()?;
+        while self.peek() == Some(&Token::And) {
+            self.at += 1;
+            left = Expr::And(Box::new(left), Box::new(self.compare()?));
+        }
+        Ok(left)
+    }
+
+    fn compare(&mut self) -> Result<Expr, String> {
+        let left = self.atom()?;
+        let Some(&Token::Cmp(cmp)) = self.peek() else {
+            return Ok(left);
+        };
+        self.at += 1;
+        Ok(Expr::Compare(cmp, Box::new(left), Box::new(self.atom()?)))
+    }
+
+    fn atom(&mut self) -> Result<Expr, String> {
+        let token = self.peek().cloned();
+        self.at += 1;
+        match token {
+            Some(Token::Path(steps)) => Ok(Expr::Path(steps)),
+            Some(Token::Literal(value)) => Ok(Expr::Literal(value)),
+            Some(Token::Open) => {
+                let inner = self.or()?;
+                if self.peek() != Some(&Token::Close) {
+                    return Err("expected ')'".to_string());
+                }
+                self.at += 1;
+                Ok(inner)
+            }
+            Some(_) => Err("expected a path or a value".to_string()),
+            None => Err("unexpected end of expression".to_string()),
+        }
+    }
+}
+
+/// Parse `text`.
+pub fn parse(text: &str) -> Result<Expr, String> {
+    let mut parser = Parser {
+        tokens: tokenize(text)?,
+        at: 0,
+    };
+    let expr = parser.or()?;
+    if parser.at < parser.tokens.len() {
+        return Err("unexpected input after the expression".to_string());
+    }
+    Ok(expr)
+}
+
+/// Whether jq counts `value` as true.
+pub fn truthy(value: &Value) -> bool {
+    !matches!(value, Value::Null | Value::Bool(false))
+}
+
+fn order(left: &Value, right: &Value) -> Option<Ordering> {
+    match (left, right) {
+        (Value::Number(l), Value::Number(r)) => l.as_f64()?.partial_cmp(&r.as_f64()?),
+        (Value::String(l), Value::String(r)) => Some(l.cmp(r)),
+        _ => None,
+    }
+}
+
+fn equal(left: &Value, right: &Value) -> bool {
+    match (left, right) {
+        (Value::Number(l), Value::Number(r)) => l.as_f64() == r.as_f64(),
+        _ => left == right,
+    }
+}
+
+impl Expr {
+    /// The value of the expression against `input`.
+    pub fn eval(&self, input: &Value) -> Value {
+        match self {
+            Expr::Path(steps) => steps
+                .iter()
+                .try_fold(input, |value, step| match (step, value) {
+                    (Step::Key(key), Value::Object(map)) => map.get(key),
+                    (Step::Index(index), Value::Array(items)) => {
+                        let index = if *index < 0 {
+                            items.len().checked_sub(index.unsigned_abs() as usize)?
+                        } else {
+                            *index as usize
+                        };
+                        items.get(index)
+                    }
+                    _ => None,
+                })
+                .cloned()
+                .unwrap_or(Value::Null),
+            Expr::Literal(value) => value.clone(),
+            Expr::Compare(cmp, left, right) => {
+                let (left, right) = (left.eval(input), right.eval(input));
+                let holds = match cmp {
+                    Cmp::Eq => equal(&left, &right),
+                    Cmp::Ne => !equal(&left, &right),
+                    Cmp::Lt => order(&left, &right) == Some(Ordering::Less),
+                    Cmp::Le => {
+                        matches!(order(&left, &right), Some(Ordering::Less | Ordering::Equal))
+                    }
+                    Cmp::Gt => order(&left, &right) == Some(Ordering::Greater),
+                    Cmp::Ge => matches!(
+                        order(&left, &right),
+                        Some(Ordering::Greater | Ordering::Equal)
+                    ),
+                };
+                Value::Bool(holds)
+            }
+            Expr::And(left, right) => {
+                Value::Bool(truthy(&left.eval(input)) && truthy(&right.eval(input)))
+            }
+            Expr::Or(left, right) => {
+                Value::Bool(truthy(&left.eval(input)) || truthy(&right.eval(input)))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn eval(text: &str, input: &Value) -> Value {
+        parse(text)
+            .unwrap_or_else(|e| panic!("{text}: {e}"))
+            .eval(input)
+    }
+
+    #[test]
+    fn paths_walk_objects_and_arrays() {
+        let body = json!({"status": "ok", "data": [{"code": 0}, {"code": 7}], "odd key": 1});
+        assert_eq!(eval(".status", &body), json!("ok"));
+        assert_eq!(eval(".data[1].code", &body), json!(7));
+        assert_eq!(eval(".data[-1].code", &body), json!(7));
+        assert_eq!(eval(".[\"odd key\"]", &body), json!(1));
+        assert_eq!(eval(".", &json!(3)), json!(3));
+        assert_eq!(eval(".missing.deeper", &body), Value::Null);
+        assert_eq!(eval(".data[9]", &body), Value::Null);
+    }
+
+    #[test]
+    fn comparisons_and_logic() {
+        let body = json!({"status": "ok", "count": 3, "errors": []});
+        assert_eq!(eval(".status == \"ok\"", &body), json!(true));
+        assert_eq!(eval(".count >= 3.0 and .count < 10", &body), json!(true));
+        assert_eq!(eval(".status != \"ok\" or .errors[0]", &body), json!(false));
+        assert_eq!(eval("(.missing or .count) and .status", &body), json!(true));
+        assert_eq!(eval(".status > 1", &body), json!(false));
+        assert_eq!(eval(".missing == null", &body), json!(true));
+    }
+
+    #[test]
+    fn malformed_expressions_are_rejected() {
+        for text in [
+            "",
+            ".status =",
+            ".status = \"ok\"",
+            ".data[x]",
+            "(.a == 1",
+            ".a == 1 .b",
+            "status",
+            "\"open",
+        ] {
+            assert!(parse(text).is_err(), "{text} parsed");
+        }
+    }
+
+    #[test]
+    fn jq_beyond_the_subset_is_named_in_the_error() {
+        for (text, named) in [
+            (".items | length > 0", "the pipe"),
+            ("length", "the function `length`"),
+            (".ok | not", "the pipe"),
+            ("not", "the function `not`"),
+            ("if .ok then true else false end", "`if`"),
+            (".count + 1 > 2", "arithmetic"),
+            ("$ENV.TOKEN", "variables"),
+            ("..", "recursive descent"),
+            (".items[]", "iteration"),
+            (".items[1:2]", "slicing"),
+            (".a?", "`?`"),
+            (".a, .b", "`,`"),
+            ("[.a]", "array construction"),
+            ("{a: 1}", "object construction"),
+        ] {
+            let error = parse(text).err().unwrap_or_else(|| panic!("{text} parsed"));
+            assert!(error.contains(named), "{text}: {error}");
+            assert!(error.contains("not supported"), "{text}: {error}");
+        }
+    }
+}
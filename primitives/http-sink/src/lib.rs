@@ -0,0 +1,1888 @@
+//! HTTP Sink - Deliver Event Payloads to HTTP Endpoints
+//!
+//! A Sink that sends each payload as a request body: JSON by default, or a
+//! form, multipart with file parts, or text (see [`body`]). A routing table
+//! lets one instance serve several endpoints, each with its own method,
+//! timeout, auth, headers and content type (see [`route`]). Non-2xx
+//! responses, timeouts and connection errors fail the message, so the harness
+//! retries and dead-letters it, waiting at least as long as a `Retry-After`
+//! header on a 429 or 503 response asks; a 4xx other than 408 and 429 is
+//! not retried but dead-lettered at once. `--success-jsonpath` or
+//! `--success-expr` can also fail 2xx responses by their body (see
+//! [`success`]). URLs and bodies can be rendered from the message with
+//! `{{field}}` placeholders (see [`template`]), bearer tokens obtained with
+//! OAuth2 client credentials (see [`oauth`]), and requests signed with AWS
+//! SigV4 or HMAC (see [`sign`]). `--graphql` wraps payloads into GraphQL
+//! requests and fails responses carrying `errors` (see [`graphql`]). With
+//! `--publish-responses`, every response is also published as an
+//! `http.response` event, making the sink a request/response bridge.
+//! Messages that exhaust their attempts are published as `http.deadletter`
+//! events unless `--no-dead-letter` is given.
+//!
+//! With `--batch-size` above 1, bodies are instead gathered per endpoint and
+//! sent as one JSON array: when `--batch-size` are waiting, every
+//! `--batch-interval`, and at shutdown. A message is only delivered (and its
+//! inbox entry acknowledged) once its batch is sent. When a batch fails,
+//! each message in it fails with the batch's error and goes through the
+//! harness's retries and dead letters on its own, honouring `Retry-After`
+//! and the breakers. Each waiting message holds a worker, so `--workers` is
+//! raised to at least `--batch-size`.
+//!
+//! Per-host circuit breakers (see [`breaker`]) stop a dead endpoint from
+//! taking every retry of every message, and `--rate-limit` keeps a burst
+//! of events under an API's request quota (see [`rate`]).
+//!
+//! # Examples
+//!
+//! ```bash
+//! # Everything to one endpoint
+//! http-sink -s alert.fired --url https://hooks.example.com/alerts
+//!
+//! # Orders and users to different APIs, the rest to a catch-all
+//! http-sink -s 'order.*' -s 'user.*' -s audit.entry \
+//!   --route 'order.*=https://orders.internal/api/events' \
+//!   --route 'user.*=https://identity.internal/api/events' \
+//!   --url https://audit.internal/ingest --auth bearer:$AUDIT_TOKEN
+//!
+//! # Call a REST API shaped by the event rather than forwarding it
+//! http-sink -s user.deactivated -m PATCH \
+//!   --url-template 'https://api.example.com/users/{{payload.user_id}}' \
+//!   --body-template '{"active": false, "reason": "{{reason}}"}'
+//!
+//! # Look users up and hand the answers on as http.response events
+//! http-sink -s user.lookup -m GET --publish-responses \
+//!   --url-template 'https://api.example.com/users/{{user_id}}'
+//!
+//! # A form post, and a multipart upload of a base64-encoded attachment
+//! http-sink -s sms.send --url https://api.sms.example/messages \
+//!   --content-type application/x-www-form-urlencoded
+//! http-sink -s report.ready --url https://files.example.com/upload \
+//!   --content-type multipart/form-data \
+//!   --body-template '{"file": {"base64": "{{pdf}}", "filename": "{{name}}.pdf"}}'
+//!
+//! # GraphQL mutations carried by the events themselves
+//! http-sink -s issue.close --url https://api.github.com/graphql --graphql \
+//!   --auth bearer:$GITHUB_TOKEN
+//!
+//! # Treat {"status": "error"} bodies as failures
+//! http-sink -s alert.fired --url https://api.example.com/events \
+//!   --success-jsonpath '$.status' --success-values ok,accepted
+//!
+//! # ... or by a jq expression over the body
+//! http-sink -s alert.fired --url https://api.example.com/events \
+//!   --success-expr '.status == "ok" and .errors[0] == null'
+//!
+//! # Blue/green: send the production hostname to the green stack
+//! http-sink -s 'order.*' --url https://orders.example.com/events \
+//!   --resolve orders.example.com:443:10.0.2.15 --connect-timeout 2000
+//!
+//! # One instance fanning topics out to several APIs, from a routes file
+//! http-sink -s 'order.*' -s 'user.*' --routes /etc/emergent/routes.yaml
+//!
+//! # Bearer tokens from an OAuth2 token endpoint, refreshed as they expire
+//! http-sink -s 'order.*' --url https://orders.internal/api/events \
+//!   --oauth-token-url https://login.example.com/oauth2/token \
+//!   --oauth-client-id emergent --oauth-client-secret $CLIENT_SECRET
+//!
+//! # Mutual TLS to a service inside a mesh with a private CA
+//! http-sink -s 'order.*' --url https://orders.mesh.internal/api/events \
+//!   --client-cert sink.crt --client-key sink.key --ca-cert mesh-ca.pem
+//!
+//! # Analytics events a hundred to a request
+//! http-sink -s 'analytics.*' --url https://ingest.example.com/v1/batch \
+//!   --batch-size 100 --batch-interval 5000
+//!
+//! # Stop calling a failing API for a minute after five failures in a row
+//! http-sink -s 'order.*' --url https://orders.internal/api/events \
+//!   --breaker-threshold 5 --breaker-cooldown 60000
+//!
+//! # At most 10 requests a second to each host, in bursts of up to 20
+//! http-sink -s 'crm.*' --routes /etc/emergent/crm-routes.yaml \
+//!   --rate-limit 10 --rate-limit-burst 20 --rate-limit-per-host
+//!
+//! # Straight to an API Gateway endpoint, signed with the caller's AWS credentials
+//! http-sink -s 'order.*' --url https://abc123.execute-api.eu-west-1.amazonaws.com/prod/orders \
+//!   --sign-aws-sigv4 --aws-region eu-west-1 --aws-service execute-api
+//!
+//! # A webhook that checks an HMAC of the body
+//! http-sink -s alert.fired --url https://hooks.example.com/alerts \
+//!   --sign-hmac $WEBHOOK_SECRET --sign-hmac-header X-Hub-Signature-256
+//!
+//! # Per-route overrides from a config file (re-read on SIGHUP)
+//! http-sink -s 'order.*' --config /etc/emergent/http-sink.json
+//! ```
+//!
+//! `--config` may override `url`, `method`, `timeout`, `auth`, `headers`,
+//! `content_type`, `body_template`, `routes`, `success_jsonpath`, `success_values` and
+//! `success_expr`:
+//!
+//! ```json
+//! {
+//!   "routes": [
+//!     {"match": "order.*", "url": "https://orders.internal/api/events",
+//!      "method": "PUT", "timeout": 5000, "auth": "bearer:...",
+//!      "headers": {"X-Team": "orders"},
+//!      "body_template": {"order": "{{order_id}}", "event": "{{type}}"}},
+//!     {"match": "user.*", "url": "https://identity.internal/api/events"}
+//!   ]
+//! }
+//! ```
+//!
+//! Every request carries `X-Emergent-Message-Type` and
+//! `X-Emergent-Message-Id` headers, and every batch an
+//! `X-Emergent-Batch-Size` header.
+
+pub mod body;
+pub mod breaker;
+pub mod client;
+pub mod graphql;
+pub mod jq;
+pub mod oauth;
+pub mod rate;
+pub mod route;
+pub mod sign;
+pub mod success;
+pub mod template;
+
+use body::Encoding;
+use breaker::Breakers;
+use clap::{Parser, ValueEnum};
+use client::ClientArgs;
+use emergent_client::EmergentMessage;
+use oauth::{OAuthArgs, TokenSource};
+use primitive_common::capabilities::VERSION;
+use primitive_common::doctor::Report;
+use primitive_common::errors::{ErrorCategory, HandlerError};
+use primitive_common::reload::HotConfig;
+use primitive_common::{Publisher, SinkArgs, SinkConfig, SinkContext, SinkHandler, run_sink};
+use rate::RateLimit;
+use reqwest::header::{HeaderMap, RETRY_AFTER};
+use reqwest::{Client, Method, StatusCode};
+use route::{Auth, Route, Table, Target, load_routes, parse_header, parse_route};
+use serde_json::{Value, json};
+use sign::{SignArgs, Signer};
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, Instant, SystemTime};
+use success::SuccessRule;
+use template::Vars;
+use tokio::sync::{Mutex, oneshot};
+
+/// Message type of the responses published with `--publish-responses`.
+pub const RESPONSE_EVENT_TYPE: &str = "http.response";
+
+/// Message type of messages that exhausted their attempts.
+pub const DEAD_LETTER_EVENT_TYPE: &str = "http.deadletter";
+
+/// HTTP Sink — deliver event payloads to HTTP endpoints.
+#[derive(Parser, Debug)]
+#[command(name = "http_sink", version = VERSION)]
+#[command(about = "Deliver event payloads to HTTP endpoints")]
+struct Args {
+    /// Message types to subscribe to.
+    #[arg(short, long = "subscribe", required = true)]
+    subscribe: Vec<String>,
+
+    /// Endpoint for message types no `--route` matches.
+    #[arg(short, long, env = "HTTP_SINK_URL")]
+    url: Option<String>,
+
+    /// `--url` rendered per message, e.g. `https://api.example.com/users/{{payload.user_id}}`.
+    #[arg(long, env = "HTTP_SINK_URL_TEMPLATE", conflicts_with = "url")]
+    url_template: Option<String>,
+
+    /// Route message types to an endpoint as `pattern=url` (`order.*` matches a namespace); first match wins, repeatable.
+    #[arg(long = "route", env = "HTTP_SINK_ROUTES", value_delimiter = ',', value_parser = parse_route)]
+    routes: Vec<Route>,
+
+    /// YAML or JSON file mapping patterns to endpoints (url, method, headers, body template, ...); tried after `--route`.
+    #[arg(long = "routes", env = "HTTP_SINK_ROUTES_FILE")]
+    routes_file: Option<PathBuf>,
+
+    /// HTTP method.
+    #[arg(short, long, env = "HTTP_SINK_METHOD", default_value = "POST")]
+    method: String,
+
+    /// Per-request timeout in milliseconds.
+    #[arg(short, long, env = "HTTP_SINK_TIMEOUT", default_value = "30000")]
+    timeout: u64,
+
+    /// Credentials as `bearer:<token>` or `basic:<user>[:<password>]`.
+    #[arg(long, env = "HTTP_SINK_AUTH")]
+    auth: Option<String>,
+
+    /// Extra request header as `Name: value`; repeatable.
+    #[arg(short = 'H', long = "header", value_parser = parse_header)]
+    headers: Vec<(String, String)>,
+
+    /// How bodies are encoded: JSON, `application/x-www-form-urlencoded`, `text/*` or `multipart/form-data`.
+    #[arg(
+        long,
+        env = "HTTP_SINK_CONTENT_TYPE",
+        default_value = "application/json",
+        visible_alias = "default-content-type"
+    )]
+    content_type: String,
+
+    /// JSON body rendered per message in place of the payload; strings may hold `{{field}}` placeholders.
+    #[arg(long, env = "HTTP_SINK_BODY_TEMPLATE", value_parser = parse_body_template)]
+    body_template: Option<Value>,
+
+    /// JSONPath into a 2xx response body (e.g. `$.status`) that must match for delivery to count.
+    #[arg(long, env = "HTTP_SINK_SUCCESS_JSONPATH")]
+    success_jsonpath: Option<String>,
+
+    /// Accepted values at `--success-jsonpath`; comma-separated (default: anything but null/false).
+    #[arg(
+        long,
+        env = "HTTP_SINK_SUCCESS_VALUES",
+        value_delimiter = ',',
+        requires = "success_jsonpath"
+    )]
+    success_values: Vec<String>,
+
+    /// jq expression over a 2xx response body (e.g. `.status == "ok"`) that must be truthy for delivery to count.
+    #[arg(
+        long,
+        env = "HTTP_SINK_SUCCESS_EXPR",
+        conflicts_with = "success_jsonpath"
+    )]
+    success_expr: Option<String>,
+
+    /// POST the payload's `query`, `variables` and `operationName` as a GraphQL request, failing responses with `errors`.
+    #[arg(long, env = "HTTP_SINK_GRAPHQL")]
+    graphql: bool,
+
+    /// Don't publish an `http.deadletter` event for each message that exhausts its attempts.
+    #[arg(long, env = "HTTP_SINK_NO_DEAD_LETTER", conflicts_with = "dead_letter")]
+    no_dead_letter: bool,
+
+    /// Publish every response (status, headers, body, latency) as an `http.response` event.
+    #[arg(long, env = "HTTP_SINK_PUBLISH_RESPONSES")]
+    publish_responses: bool,
+
+    /// Send bodies this many at a time, as one JSON array (1 sends each message on its own).
+    #[arg(
+        long,
+        env = "HTTP_SINK_BATCH_SIZE",
+        default_value = "1",
+        conflicts_with_all = ["publish_responses", "graphql"]
+    )]
+    batch_size: usize,
+
+    /// Send waiting batches this often, in milliseconds.
+    #[arg(long, env = "HTTP_SINK_BATCH_INTERVAL", default_value = "1000", value_parser = clap::value_parser!(u64).range(1..))]
+    batch_interval: u64,
+
+    /// Consecutive failures against a host that open its circuit breaker (0 disables breakers).
+    #[arg(long, env = "HTTP_SINK_BREAKER_THRESHOLD", default_value = "0")]
+    breaker_threshold: u32,
+
+    /// Milliseconds an open breaker short-circuits requests to its host.
+    #[arg(long, env = "HTTP_SINK_BREAKER_COOLDOWN", default_value = "30000")]
+    breaker_cooldown: u64,
+
+    /// What happens to messages for a host whose breaker is open.
+    #[arg(
+        long,
+        env = "HTTP_SINK_BREAKER_OPEN",
+        value_enum,
+        default_value = "requeue"
+    )]
+    breaker_open: BreakerOpen,
+
+    /// Requests per second, fractions allowed; requests beyond it wait their turn (0 disables the limit).
+    #[arg(long, env = "HTTP_SINK_RATE_LIMIT", default_value = "0")]
+    rate_limit: f64,
+
+    /// Requests that may go at once after a quiet spell (default: one second's worth).
+    #[arg(long, env = "HTTP_SINK_RATE_LIMIT_BURST", requires = "rate_limit")]
+    rate_limit_burst: Option<u32>,
+
+    /// Limit each host separately rather than all requests together.
+    #[arg(long, env = "HTTP_SINK_RATE_LIMIT_PER_HOST", requires = "rate_limit")]
+    rate_limit_per_host: bool,
+
+    #[command(flatten)]
+    client: ClientArgs,
+
+    #[command(flatten)]
+    oauth: OAuthArgs,
+
+    #[command(flatten)]
+    sign: SignArgs,
+
+    #[command(flatten)]
+    sink: SinkArgs,
+}
+
+/// What happens to messages for a host whose circuit breaker is open.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum BreakerOpen {
+    /// Retry them once the cooldown ends.
+    Requeue,
+    /// Dead-letter them without further attempts.
+    DeadLetter,
+}
+
+/// Parse a `--body-template` value.
+fn parse_body_template(s: &str) -> Result<Value, String> {
+    serde_json::from_str(s).map_err(|e| format!("not JSON: {e}"))
+}
+
+/// How long a `Retry-After` header asks to wait: seconds, or an HTTP date.
+fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let value = headers.get(RETRY_AFTER)?.to_str().ok()?.trim();
+    if let Ok(secs) = value.parse() {
+        return Some(Duration::from_secs(secs));
+    }
+    let at = httpdate::parse_http_date(value).ok()?;
+    Some(at.duration_since(SystemTime::now()).unwrap_or_default())
+}
+
+/// The `http.response` payload for a response to `msg`, delivered as
+/// `message_id`.
+fn response_payload(
+    msg: &EmergentMessage,
+    message_id: &str,
+    target: (&Method, &str),
+    status: StatusCode,
+    headers: &HeaderMap,
+    body: &[u8],
+    latency: Duration,
+) -> Value {
+    let mut names = serde_json::Map::new();
+    for name in headers.keys() {
+        let values: Vec<String> = headers
+            .get_all(name)
+            .iter()
+            .map(|value| String::from_utf8_lossy(value.as_bytes()).into_owned())
+            .collect();
+        names.insert(name.to_string(), Value::from(values.join(", ")));
+    }
+    // JSON bodies are passed on as JSON, anything else as text
+    let body = serde_json::from_slice(body)
+        .unwrap_or_else(|_| Value::from(String::from_utf8_lossy(body).into_owned()));
+    json!({
+        "message_id": message_id,
+        "message_type": msg.message_type.as_str(),
+        "method": target.0.as_str(),
+        "url": target.1,
+        "status": status.as_u16(),
+        "ok": status.is_success(),
+        "headers": names,
+        "body": body,
+        "latency_ms": latency.as_millis() as u64,
+    })
+}
+
+/// A response, with its body read when it was needed.
+struct Answer {
+    method: Method,
+    status: StatusCode,
+    headers: HeaderMap,
+    body: Vec<u8>,
+    latency: Duration,
+}
+
+/// The client, its OAuth tokens, request signer and the hot-swappable
+/// settings, shared by the handler and the batches it fills.
+struct Delivery {
+    client: Client,
+    tokens: Option<TokenSource>,
+    signer: Signer,
+    settings: HotConfig<Table>,
+    /// Set with `--breaker-threshold` above 0.
+    breakers: Option<Breakers>,
+    breaker_open: BreakerOpen,
+    /// Set with `--rate-limit` above 0.
+    rate: Option<RateLimit>,
+    graphql: bool,
+}
+
+impl Delivery {
+    /// Send `body` to `target` with the extra `headers`, reading the
+    /// response body if `read_body`.
+    async fn send(
+        &self,
+        target: &Target,
+        headers: &[(&str, &str)],
+        body: &Value,
+        read_body: bool,
+    ) -> Result<Answer, HandlerError> {
+        let method = Method::from_bytes(target.method.as_bytes()).map_err(|_| {
+            HandlerError::new(
+                ErrorCategory::Internal,
+                format!("invalid method '{}'", target.method),
+            )
+        })?;
+        let name = format!("{method} {}", target.url);
+        let host = Breakers::host(&target.url);
+        if let Some(breakers) = &self.breakers
+            && let Some(wait) = breakers.open_for(&host, Instant::now())
+        {
+            let error = HandlerError::new(
+                ErrorCategory::Request,
+                format!("{name}: circuit open for {}ms", wait.as_millis()),
+            );
+            // Nothing was sent, so a requeued message keeps its attempts
+            return Err(match self.breaker_open {
+                BreakerOpen::Requeue => error.requeued(wait),
+                BreakerOpen::DeadLetter => error.permanent(),
+            });
+        }
+        if let Some(rate) = &self.rate {
+            rate.acquire(&host).await;
+        }
+        let request = self
+            .client
+            .request(method.clone(), &target.url)
+            .timeout(Duration::from_millis(target.timeout));
+        let mut request = body::attach(request, &target.content_type, body)
+            .map_err(|e| HandlerError::new(ErrorCategory::Parse, e))?;
+        for (name, value) in headers {
+            request = request.header(*name, *value);
+        }
+        for (name, value) in &target.headers {
+            request = request.header(name, value);
+        }
+        // An explicit `auth` wins over the OAuth token
+        let mut oauth_token = None;
+        if let Some(auth) = &target.auth {
+            request = match Auth::parse(auth)
+                .map_err(|e| HandlerError::new(ErrorCategory::Internal, e))?
+            {
+                Auth::Bearer(token) => request.bearer_auth(token),
+                Auth::Basic { user, password } => request.basic_auth(user, password),
+            };
+        } else if let Some(tokens) = &self.tokens {
+            let token = tokens
+                .token(&self.client)
+                .await
+                .map_err(|e| HandlerError::new(ErrorCategory::Request, e))?;
+            request = request.bearer_auth(&token);
+            oauth_token = Some(token);
+        }
+
+        let mut request = request
+            .build()
+            .map_err(|e| HandlerError::new(ErrorCategory::Request, format!("{name}: {e}")))?;
+        if self.signer.is_enabled() {
+            self.signer
+                .sign(&mut request, SystemTime::now())
+                .await
+                .map_err(|e| HandlerError::new(ErrorCategory::Internal, format!("{name}: {e}")))?;
+        }
+
+        let started = Instant::now();
+        let response = self.client.execute(request).await.map_err(|e| {
+            if e.is_timeout() {
+                HandlerError::new(ErrorCategory::Timeout, format!("{name}: timed out"))
+            } else {
+                HandlerError::new(ErrorCategory::Request, format!("{name}: {e}"))
+            }
+        });
+        if let Some(breakers) = &self.breakers {
+            // Answers short of 5xx and 429 show the host is up
+            let failed = response.as_ref().map_or(true, |response| {
+                let status = response.status();
+                status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS
+            });
+            breakers.record(&host, failed, Instant::now());
+        }
+        let response = response?;
+        let status = response.status();
+        let headers = response.headers().clone();
+        let body = if read_body {
+            response
+                .bytes()
+                .await
+                .map_err(|e| HandlerError::new(ErrorCategory::Request, format!("{name}: {e}")))?
+                .to_vec()
+        } else {
+            Vec::new()
+        };
+        if status == StatusCode::UNAUTHORIZED
+            && let (Some(tokens), Some(token)) = (&self.tokens, &oauth_token)
+        {
+            // Revoked or rotated early: the retry fetches a new token
+            tokens.invalidate(token).await;
+        }
+        Ok(Answer {
+            method,
+            status,
+            headers,
+            body,
+            latency: started.elapsed(),
+        })
+    }
+
+    /// Fail `answer` if it is not 2xx, honouring `Retry-After` on 429 and
+    /// 503 and failing for good on other client errors, or if its body
+    /// fails the success rule or carries GraphQL errors.
+    fn check(&self, target: &Target, answer: &Answer) -> Result<(), HandlerError> {
+        let name = format!("{} {}", answer.method, target.url);
+        let status = answer.status;
+        if !status.is_success() {
+            let error =
+                HandlerError::new(ErrorCategory::Rejected, format!("{name}: HTTP {status}"))
+                    .with_status(status.as_u16());
+            let throttled = matches!(
+                status,
+                StatusCode::TOO_MANY_REQUESTS | StatusCode::SERVICE_UNAVAILABLE
+            );
+            // `send` dropped a refused OAuth token, so a retry can succeed
+            let stale_token = status == StatusCode::UNAUTHORIZED && self.tokens.is_some();
+            return Err(match retry_after(&answer.headers) {
+                Some(delay) if throttled => error.with_retry_after(delay),
+                _ if rejects_for_good(status.as_u16()) && !stale_token => error.permanent(),
+                _ => error,
+            });
+        }
+        let settings = self.settings.current();
+        if settings.success.is_enabled() {
+            settings.success.check(&answer.body).map_err(|e| {
+                HandlerError::new(ErrorCategory::Rejected, format!("{name}: {e}"))
+                    .with_status(status.as_u16())
+            })?;
+        }
+        if self.graphql {
+            graphql::check(&answer.body).map_err(|e| {
+                HandlerError::new(ErrorCategory::Rejected, format!("{name}: {e}"))
+                    .with_status(status.as_u16())
+            })?;
+        }
+        Ok(())
+    }
+}
+
+/// A body waiting in a batch, and the message waiting on it.
+struct Waiting {
+    body: Value,
+    done: oneshot::Sender<Result<(), HandlerError>>,
+}
+
+/// Whether an answer with `status` should be dead-lettered rather than
+/// retried: client errors, except timeouts and throttling.
+fn rejects_for_good(status: u16) -> bool {
+    (400..500).contains(&status) && !matches!(status, 408 | 429)
+}
+
+/// Bodies waiting to be sent together, per target.
+struct Batches {
+    delivery: Arc<Delivery>,
+    size: usize,
+    /// Held while sending, so batches go out one at a time and in order.
+    pending: Mutex<BTreeMap<Target, Vec<Waiting>>>,
+}
+
+impl Batches {
+    /// Send every waiting batch, telling each message how its batch went.
+    /// Returns the first failure, once every batch has been tried.
+    async fn flush(&self) -> Result<(), HandlerError> {
+        let mut pending = self.pending.lock().await;
+        let read_body = self.delivery.settings.current().success.is_enabled();
+        let mut failure = None;
+        for (target, waiting) in std::mem::take(&mut *pending) {
+            let (bodies, done): (Vec<Value>, Vec<_>) =
+                waiting.into_iter().map(|w| (w.body, w.done)).unzip();
+            let result = self.send(&target, bodies, read_body).await;
+            if let Err(e) = &result {
+                failure.get_or_insert_with(|| e.clone());
+            }
+            for done in done {
+                let _ = done.send(result.clone());
+            }
+        }
+        failure.map_or(Ok(()), Err)
+    }
+
+    /// Send one batch to `target`.
+    async fn send(
+        &self,
+        target: &Target,
+        bodies: Vec<Value>,
+        read_body: bool,
+    ) -> Result<(), HandlerError> {
+        let size = bodies.len().to_string();
+        let answer = self
+            .delivery
+            .send(
+                target,
+                &[("X-Emergent-Batch-Size", &size)],
+                &Value::Array(bodies),
+                read_body,
+            )
+            .await?;
+        self.delivery.check(target, &answer)
+    }
+
+    /// Add `body` to `target`'s batch and wait until the batch is sent,
+    /// sending every waiting batch at once if this one is now full.
+    async fn add(&self, target: Target, body: Value) -> Result<(), HandlerError> {
+        let (done, sent) = oneshot::channel();
+        let full = {
+            let mut pending = self.pending.lock().await;
+            let batch = pending.entry(target).or_default();
+            batch.push(Waiting { body, done });
+            batch.len() >= self.size
+        };
+        if full {
+            // Each message hears its own batch's outcome below
+            let _ = self.flush().await;
+        }
+        sent.await.unwrap_or_else(|_| {
+            Err(HandlerError::new(
+                ErrorCategory::Internal,
+                "batch dropped before it was sent",
+            ))
+        })
+    }
+}
+
+/// Send waiting batches every `interval`. Failures reach the messages in
+/// the batch, which report them.
+async fn flush_every(batches: Arc<Batches>, interval: Duration) {
+    let mut ticks = tokio::time::interval_at(tokio::time::Instant::now() + interval, interval);
+    ticks.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    loop {
+        ticks.tick().await;
+        let _ = batches.flush().await;
+    }
+}
+
+/// Sends each payload to the endpoint its message type routes to.
+struct HttpSink {
+    delivery: Arc<Delivery>,
+    /// Set with `--batch-size` above 1.
+    batches: Option<Arc<Batches>>,
+    publish_responses: bool,
+    publisher: OnceLock<Publisher>,
+}
+
+impl SinkHandler for HttpSink {
+    async fn handle(
+        &self,
+        msg: &EmergentMessage,
+        ctx: &SinkContext<'_>,
+    ) -> Result<(), HandlerError> {
+        let settings = self.delivery.settings.current();
+        let message_type = msg.message_type.as_str();
+        let Some(endpoint) = settings.endpoint(message_type) else {
+            return Err(HandlerError::new(
+                ErrorCategory::Internal,
+                format!("no route for {message_type} and no --url"),
+            ));
+        };
+        Method::from_bytes(endpoint.method.as_bytes()).map_err(|_| {
+            HandlerError::new(
+                ErrorCategory::Internal,
+                format!("invalid method '{}'", endpoint.method),
+            )
+        })?;
+
+        let message_id = ctx.message_id().to_string();
+        let vars = Vars {
+            message_type,
+            message_id: &message_id,
+            payload: ctx.payload(),
+        };
+        let url = template::url(endpoint.url, &vars)
+            .map_err(|e| HandlerError::new(ErrorCategory::Parse, e))?;
+        let body = match endpoint.body_template {
+            Some(body) => Cow::Owned(
+                template::body(body, &vars)
+                    .map_err(|e| HandlerError::new(ErrorCategory::Parse, e))?,
+            ),
+            None => Cow::Borrowed(ctx.payload()),
+        };
+        let mut target = endpoint.target(url);
+        let body = if self.delivery.graphql {
+            // Always a JSON POST, whatever the route says
+            target.method = Method::POST.to_string();
+            target.content_type = "application/json".to_string();
+            Cow::Owned(
+                graphql::request(&body).map_err(|e| HandlerError::new(ErrorCategory::Parse, e))?,
+            )
+        } else {
+            body
+        };
+
+        if ctx.is_dry_run() {
+            let detail = json!({
+                "method": target.method,
+                "url": target.url,
+                "content_type": target.content_type,
+                "body": body,
+            });
+            ctx.would_have("http", detail).await;
+            return Ok(());
+        }
+
+        if let Some(batches) = &self.batches {
+            if Encoding::of(endpoint.content_type) != Ok(Encoding::Json) {
+                return Err(HandlerError::new(
+                    ErrorCategory::Internal,
+                    format!(
+                        "batches are sent as JSON arrays, not {}",
+                        endpoint.content_type
+                    ),
+                ));
+            }
+            return batches.add(target, body.into_owned()).await;
+        }
+
+        let headers = [
+            ("X-Emergent-Message-Type", message_type),
+            ("X-Emergent-Message-Id", message_id.as_str()),
+        ];
+        let read_body =
+            self.publish_responses || settings.success.is_enabled() || self.delivery.graphql;
+        let answer = self
+            .delivery
+            .send(&target, &headers, &body, read_body)
+            .await?;
+        if self.publish_responses
+            && let Some(publisher) = self.publisher.get()
+        {
+            // Failed attempts are published too, so a bridge sees each answer
+            let payload = response_payload(
+                msg,
+                ctx.message_id(),
+                (&answer.method, &target.url),
+                answer.status,
+                &answer.headers,
+                &answer.body,
+                answer.latency,
+            );
+            let message = EmergentMessage::new(RESPONSE_EVENT_TYPE)
+                .with_causation_id(msg.id())
+                .with_payload(payload);
+            if let Err(e) = publisher.publish(message) {
+                eprintln!("Failed to publish {RESPONSE_EVENT_TYPE}: {e}");
+            }
+        }
+        self.delivery.check(&target, &answer)
+    }
+
+    fn self_test(&self, report: &mut Report) {
+        let settings = self.delivery.settings.current();
+        report.check(
+            "routes",
+            settings.validate().map(|()| {
+                format!(
+                    "{} route(s){}",
+                    settings.routes.len(),
+                    settings
+                        .url
+                        .as_deref()
+                        .map(|url| format!(", default {url}"))
+                        .unwrap_or_default()
+                )
+            }),
+        );
+        if let Some(tokens) = &self.delivery.tokens {
+            report.check("oauth", Ok(format!("tokens from {}", tokens.url())));
+        }
+        if self.delivery.signer.is_enabled() {
+            report.check("signing", Ok(self.delivery.signer.describe()));
+        }
+    }
+
+    fn reload(&self) -> Result<Vec<String>, String> {
+        self.delivery.settings.reload()
+    }
+
+    async fn flush(&self) {
+        let Some(batches) = &self.batches else {
+            return;
+        };
+        // Only messages abandoned at the drain deadline can still be waiting
+        if let Err(e) = batches.flush().await {
+            eprintln!("Error: a batch was not sent at shutdown: {e}");
+        }
+    }
+
+    fn publishes(&self) -> &'static [&'static str] {
+        if self.publish_responses {
+            &[RESPONSE_EVENT_TYPE]
+        } else {
+            &[]
+        }
+    }
+
+    fn attach(&self, publisher: Publisher) {
+        let _ = self.publisher.set(publisher);
+    }
+}
+
+impl Args {
+    /// The delivery settings the flags describe, before any config file.
+    fn table(&self, routes: Vec<Route>) -> Table {
+        Table {
+            url: self.url.clone().or_else(|| self.url_template.clone()),
+            method: self.method.clone(),
+            timeout: self.timeout,
+            auth: self.auth.clone(),
+            headers: self.headers.iter().cloned().collect(),
+            content_type: self.content_type.clone(),
+            body_template: self.body_template.clone(),
+            routes,
+            success: SuccessRule {
+                success_jsonpath: self.success_jsonpath.clone(),
+                success_values: self.success_values.clone(),
+                success_expr: self.success_expr.clone(),
+            },
+        }
+    }
+}
+
+/// Run the primitive with `args` as its command line (the first item is
+/// the program name, as with `std::env::args_os`).
+pub async fn run<I, T>(args: I) -> Result<(), Box<dyn std::error::Error>>
+where
+    I: IntoIterator<Item = T>,
+    T: Into<std::ffi::OsString> + Clone,
+{
+    let mut args = Args::parse_from(args);
+    // Dead letters are published unless turned off
+    args.sink.dead_letter = !args.no_dead_letter;
+    // A message waits on its worker until its batch is sent
+    args.sink.workers = args.sink.workers.max(args.batch_size);
+
+    let config = SinkConfig {
+        name: "http_sink",
+        subscribe: &args.subscribe,
+        would_have_as: "http.would_have",
+        dead_letter_as: DEAD_LETTER_EVENT_TYPE,
+        settings: &args,
+    };
+    let mut routes = args.routes.clone();
+    if let Some(path) = &args.routes_file {
+        match load_routes(path) {
+            Ok(file_routes) => routes.extend(file_routes),
+            Err(e) => {
+                eprintln!("Error: invalid routes file {e}");
+                std::process::exit(1);
+            }
+        }
+    }
+    let settings = match HotConfig::load(&args.sink.reload, args.table(routes)) {
+        Ok(settings) => settings,
+        Err(e) => {
+            eprintln!("Error: invalid config: {e}");
+            std::process::exit(1);
+        }
+    };
+    if let Err(e) = settings.current().validate() {
+        eprintln!("Error: {e}");
+        std::process::exit(1);
+    }
+    let client = match args.client.build() {
+        Ok(client) => client,
+        Err(e) => {
+            eprintln!("Error: {e}");
+            std::process::exit(1);
+        }
+    };
+    let signer = match Signer::new(&args.sign, &client) {
+        Ok(signer) => signer,
+        Err(e) => {
+            eprintln!("Error: {e}");
+            std::process::exit(1);
+        }
+    };
+    let delivery = Arc::new(Delivery {
+        client,
+        tokens: TokenSource::new(&args.oauth),
+        signer,
+        settings,
+        breakers: Breakers::new(
+            args.breaker_threshold,
+            Duration::from_millis(args.breaker_cooldown),
+        ),
+        breaker_open: args.breaker_open,
+        rate: RateLimit::new(
+            args.rate_limit,
+            args.rate_limit_burst,
+            args.rate_limit_per_host,
+        ),
+        graphql: args.graphql,
+    });
+    let batches = (args.batch_size > 1).then(|| {
+        Arc::new(Batches {
+            delivery: Arc::clone(&delivery),
+            size: args.batch_size,
+            pending: Mutex::new(BTreeMap::new()),
+        })
+    });
+    if let Some(batches) = &batches
+        && !args.sink.dry_run
+        && !args.sink.self_test
+    {
+        tokio::spawn(flush_every(
+            Arc::clone(batches),
+            Duration::from_millis(args.batch_interval),
+        ));
+    }
+    let handler = HttpSink {
+        delivery,
+        batches,
+        publish_responses: args.publish_responses,
+        publisher: OnceLock::new(),
+    };
+
+    run_sink(config, &args.sink, handler).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::response::IntoResponse;
+    use axum::{Router, extract::Request, routing::any};
+    use emergent_testkit::{SinkFixture, fixtures, spawn_sink};
+    use primitive_common::key;
+    use primitive_common::reload::ReloadArgs;
+    use std::collections::BTreeMap;
+    use tokio::sync::mpsc;
+
+    /// A request as seen by the test server.
+    #[derive(Debug)]
+    struct Received {
+        method: String,
+        path: String,
+        authorization: Option<String>,
+        content_type: Option<String>,
+        /// JSON bodies parsed, anything else as text.
+        body: Value,
+    }
+
+    /// Serve on a random port; `/fail` answers 503, `/reject` 400,
+    /// `/invalid` 422, `/busy` 429 with `Retry-After: 1`, `/soft-fail` 200 with an error body, `/slow` 200
+    /// after half a second, `/token` an OAuth2 token, `/graphql` 200 with
+    /// errors for queries mentioning `missing`, everything else 200 with
+    /// `{"status": "ok"}`.
+    async fn server() -> (String, mpsc::UnboundedReceiver<Received>) {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let app = Router::new().fallback(any(move |request: Request| {
+            let tx = tx.clone();
+            async move {
+                let path = request.uri().path().to_string();
+                let method = request.method().to_string();
+                let authorization = request
+                    .headers()
+                    .get("authorization")
+                    .and_then(|v| v.to_str().ok())
+                    .map(str::to_string);
+                let content_type = request
+                    .headers()
+                    .get("content-type")
+                    .and_then(|v| v.to_str().ok())
+                    .map(str::to_string);
+                let body = axum::body::to_bytes(request.into_body(), usize::MAX)
+                    .await
+                    .unwrap_or_default();
+                let missing = String::from_utf8_lossy(&body).contains("missing");
+                let _ = tx.send(Received {
+                    method,
+                    path: path.clone(),
+                    authorization,
+                    content_type,
+                    body: serde_json::from_slice(&body).unwrap_or_else(|_| {
+                        Value::from(String::from_utf8_lossy(&body).into_owned())
+                    }),
+                });
+                match path.as_str() {
+                    "/fail" => (StatusCode::SERVICE_UNAVAILABLE, "").into_response(),
+                    "/reject" => (StatusCode::BAD_REQUEST, "").into_response(),
+                    "/invalid" => (StatusCode::UNPROCESSABLE_ENTITY, "").into_response(),
+                    "/busy" => (
+                        StatusCode::TOO_MANY_REQUESTS,
+                        [("retry-after", "1")],
+                        "slow down",
+                    )
+                        .into_response(),
+                    "/soft-fail" => (StatusCode::OK, r#"{"status": "error"}"#).into_response(),
+                    "/token" => (
+                        StatusCode::OK,
+                        r#"{"access_token": "oauth-token", "expires_in": 3600}"#,
+                    )
+                        .into_response(),
+                    "/graphql" if missing => (
+                        StatusCode::OK,
+                        r#"{"data": null, "errors": [{"message": "Field 'missing' doesn't exist"}]}"#,
+                    )
+                        .into_response(),
+                    "/graphql" => (StatusCode::OK, r#"{"data": {"ok": true}}"#).into_response(),
+                    "/slow" => {
+                        tokio::time::sleep(Duration::from_millis(500)).await;
+                        (StatusCode::OK, r#"{"status": "ok"}"#).into_response()
+                    }
+                    _ => (StatusCode::OK, r#"{"status": "ok"}"#).into_response(),
+                }
+            }
+        }));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap_or_else(|e| panic!("bind: {e}"));
+        let addr = listener
+            .local_addr()
+            .unwrap_or_else(|e| panic!("local_addr: {e}"));
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        (format!("http://{addr}"), rx)
+    }
+
+    /// The table `--url url` gives, with a 5 second timeout.
+    fn table(url: String) -> Table {
+        let args = Args::try_parse_from(["http_sink", "-s", "*", "--timeout", "5000"])
+            .unwrap_or_else(|e| panic!("args: {e}"));
+        Table {
+            url: Some(url),
+            ..args.table(Vec::new())
+        }
+    }
+
+    fn delivery(table: Table) -> Delivery {
+        let settings = HotConfig::load(&ReloadArgs::default(), table)
+            .unwrap_or_else(|e| panic!("load settings: {e}"));
+        Delivery {
+            client: Client::new(),
+            tokens: None,
+            signer: Signer::default(),
+            settings,
+            breakers: None,
+            breaker_open: BreakerOpen::Requeue,
+            rate: None,
+            graphql: false,
+        }
+    }
+
+    fn sink_for(delivery: Delivery) -> HttpSink {
+        HttpSink {
+            delivery: Arc::new(delivery),
+            batches: None,
+            publish_responses: false,
+            publisher: OnceLock::new(),
+        }
+    }
+
+    fn http_sink(table: Table) -> HttpSink {
+        sink_for(delivery(table))
+    }
+
+    fn fixture() -> SinkFixture {
+        SinkFixture {
+            dead_letter_as: DEAD_LETTER_EVENT_TYPE.to_string(),
+            ..SinkFixture::new("http_sink", "http")
+        }
+    }
+
+    #[test]
+    fn dead_letters_are_published_unless_turned_off() {
+        let parse = |extra: &[&str]| {
+            let mut argv = vec!["http_sink", "-s", "*"];
+            argv.extend_from_slice(extra);
+            Args::try_parse_from(argv)
+        };
+        let args = parse(&[]).unwrap_or_else(|e| panic!("{e}"));
+        assert!(!args.no_dead_letter);
+        let args = parse(&["--no-dead-letter"]).unwrap_or_else(|e| panic!("{e}"));
+        assert!(args.no_dead_letter);
+        assert!(parse(&["--no-dead-letter", "--dead-letter"]).is_err());
+    }
+
+    #[tokio::test]
+    async fn messages_are_routed_by_type_with_route_overrides() {
+        let (base, mut received) = server().await;
+        let mut orders =
+            parse_route(&format!("order.*={base}/orders")).unwrap_or_else(|e| panic!("route: {e}"));
+        orders.method = Some("PUT".to_string());
+        orders.auth = Some("bearer:orders-token".to_string());
+        let table = Table {
+            routes: vec![orders],
+            ..table(format!("{base}/default"))
+        };
+        let (mut engine, run) = spawn_sink(fixture(), SinkArgs::default(), http_sink(table));
+
+        engine
+            .inject_message(fixtures::message("order.created", json!({"id": 1})))
+            .await;
+        let order = received
+            .recv()
+            .await
+            .unwrap_or_else(|| panic!("no request"));
+        assert_eq!(order.method, "PUT");
+        assert_eq!(order.path, "/orders");
+        assert_eq!(order.authorization.as_deref(), Some("Bearer orders-token"));
+
+        engine
+            .inject_message(fixtures::message("user.created", json!({"id": 2})))
+            .await;
+        let user = received
+            .recv()
+            .await
+            .unwrap_or_else(|| panic!("no request"));
+        assert_eq!(user.method, "POST");
+        assert_eq!(user.path, "/default");
+        assert_eq!(user.authorization, None);
+
+        engine.close();
+        assert_eq!(run.await.ok(), Some(Ok(())));
+    }
+
+    #[tokio::test]
+    async fn signed_requests_carry_a_sigv4_authorization() {
+        let (base, mut received) = server().await;
+        let table = table(format!("{base}/prod/orders"));
+        let args = Args::try_parse_from([
+            "http_sink",
+            "-s",
+            "order.*",
+            "--sign-aws-sigv4",
+            "--aws-region",
+            "eu-west-1",
+            "--aws-service",
+            "execute-api",
+            "--aws-access-key-id",
+            "AKIDEXAMPLE",
+            "--aws-secret-access-key",
+            "secret",
+        ])
+        .unwrap_or_else(|e| panic!("{e}"));
+        let mut delivery = delivery(table);
+        delivery.signer = Signer::new(&args.sign, &Client::new()).unwrap_or_else(|e| panic!("{e}"));
+        let (mut engine, run) = spawn_sink(fixture(), SinkArgs::default(), sink_for(delivery));
+
+        engine
+            .inject_message(fixtures::message("order.created", json!({"id": 1})))
+            .await;
+        let order = received
+            .recv()
+            .await
+            .unwrap_or_else(|| panic!("no request"));
+        let authorization = order.authorization.unwrap_or_default();
+        assert!(
+            authorization.starts_with("AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/"),
+            "{authorization}"
+        );
+        assert!(authorization.contains(
+            "/eu-west-1/execute-api/aws4_request, SignedHeaders=content-type;host;x-amz-date, Signature="
+        ));
+        assert_eq!(order.body, json!({"id": 1}));
+        assert!(Args::try_parse_from(["http_sink", "-s", "x", "--sign-aws-sigv4"]).is_err());
+
+        engine.close();
+        assert_eq!(run.await.ok(), Some(Ok(())));
+    }
+
+    #[tokio::test]
+    async fn oauth_tokens_authorize_requests_without_their_own_auth() {
+        let (base, mut received) = server().await;
+        let mut orders =
+            parse_route(&format!("order.*={base}/orders")).unwrap_or_else(|e| panic!("route: {e}"));
+        orders.auth = Some("bearer:orders-token".to_string());
+        let table = Table {
+            routes: vec![orders],
+            ..table(format!("{base}/default"))
+        };
+        let mut delivery = delivery(table);
+        delivery.tokens = TokenSource::new(&OAuthArgs {
+            oauth_token_url: Some(format!("{base}/token")),
+            oauth_client_id: Some("emergent".to_string()),
+            oauth_client_secret: Some("secret".to_string()),
+            oauth_scope: None,
+        });
+        let (mut engine, run) = spawn_sink(fixture(), SinkArgs::default(), sink_for(delivery));
+
+        let mut paths = Vec::new();
+        for (message_type, id) in [
+            ("user.created", 1),
+            ("user.updated", 2),
+            ("order.created", 3),
+        ] {
+            engine
+                .inject_message(fixtures::message(message_type, json!({"id": id})))
+                .await;
+            loop {
+                let request = received
+                    .recv()
+                    .await
+                    .unwrap_or_else(|| panic!("no request"));
+                paths.push(request.path.clone());
+                if request.path != "/token" {
+                    let expected = if id == 3 {
+                        "orders-token"
+                    } else {
+                        "oauth-token"
+                    };
+                    assert_eq!(request.authorization, Some(format!("Bearer {expected}")));
+                    break;
+                }
+            }
+        }
+        // One token serves both user messages; the order route keeps its own auth
+        assert_eq!(paths, ["/token", "/default", "/default", "/orders"]);
+
+        engine.close();
+        assert_eq!(run.await.ok(), Some(Ok(())));
+    }
+
+    #[tokio::test]
+    async fn bodies_are_sent_as_forms_multipart_or_text() {
+        let (base, mut received) = server().await;
+        let route = |pattern: &str, content_type: &str| {
+            let mut route = parse_route(&format!("{pattern}={base}/{pattern}"))
+                .unwrap_or_else(|e| panic!("route: {e}"));
+            route.content_type = Some(content_type.to_string());
+            route
+        };
+        let mut upload = route("upload", "multipart/form-data");
+        upload.body_template = Some(json!({
+            "note": "{{note}}",
+            "file": {"base64": "{{data}}", "filename": "{{name}}", "content_type": "text/plain"},
+        }));
+        let table = Table {
+            routes: vec![
+                route("sms", "application/x-www-form-urlencoded"),
+                route("line", "text/plain; charset=utf-8"),
+                upload,
+            ],
+            ..table(format!("{base}/default"))
+        };
+        let (mut engine, run) = spawn_sink(fixture(), SinkArgs::default(), http_sink(table));
+        let mut send = async |message_type: &str, payload: Value| {
+            engine
+                .inject_message(fixtures::message(message_type, payload))
+                .await;
+            received
+                .recv()
+                .await
+                .unwrap_or_else(|| panic!("no request"))
+        };
+
+        let sms = send("sms", json!({"to": "+15550100", "text": "On its way"})).await;
+        assert_eq!(
+            sms.content_type.as_deref(),
+            Some("application/x-www-form-urlencoded")
+        );
+        assert_eq!(sms.body, json!("text=On+its+way&to=%2B15550100"));
+
+        let line = send("line", json!("disk 91% full")).await;
+        assert_eq!(
+            line.content_type.as_deref(),
+            Some("text/plain; charset=utf-8")
+        );
+        assert_eq!(line.body, json!("disk 91% full"));
+
+        let upload = send(
+            "upload",
+            json!({"note": "Q3", "name": "q3.txt", "data": "cmV2ZW51ZSB1cA=="}),
+        )
+        .await;
+        assert!(
+            upload
+                .content_type
+                .is_some_and(|t| t.starts_with("multipart/form-data; boundary="))
+        );
+        let body = upload.body.as_str().unwrap_or_default();
+        assert!(body.contains("name=\"note\"\r\n\r\nQ3\r\n"), "{body}");
+        assert!(
+            body.contains("name=\"file\"; filename=\"q3.txt\"\r\nContent-Type: text/plain\r\n\r\nrevenue up\r\n"),
+            "{body}"
+        );
+
+        let json = send("other", json!({"id": 1})).await;
+        assert_eq!(json.content_type.as_deref(), Some("application/json"));
+        assert_eq!(json.body, json!({"id": 1}));
+
+        engine.close();
+        assert_eq!(run.await.ok(), Some(Ok(())));
+    }
+
+    #[tokio::test]
+    async fn urls_and_bodies_are_rendered_from_the_message() {
+        let (base, mut received) = server().await;
+        let table = Table {
+            method: "PATCH".to_string(),
+            body_template: Some(
+                json!({"active": false, "reason": "{{reason}}", "via": "{{type}}"}),
+            ),
+            ..table(format!("{base}/users/{{{{payload.user_id}}}}"))
+        };
+        let args = SinkArgs {
+            dead_letter: true,
+            ..Default::default()
+        };
+        let (mut engine, run) = spawn_sink(fixture(), args, http_sink(table));
+
+        engine
+            .inject_message(fixtures::message(
+                "user.deactivated",
+                json!({"user_id": "u 42", "reason": "churned"}),
+            ))
+            .await;
+        let request = received
+            .recv()
+            .await
+            .unwrap_or_else(|| panic!("no request"));
+        assert_eq!(request.method, "PATCH");
+        assert_eq!(request.path, "/users/u%2042");
+        assert_eq!(
+            request.body,
+            json!({"active": false, "reason": "churned", "via": "user.deactivated"})
+        );
+
+        // Without the field there is no request to make
+        engine
+            .inject_message(fixtures::message(
+                "user.deactivated",
+                json!({"reason": "x"}),
+            ))
+            .await;
+        let dead = engine.expect_published(DEAD_LETTER_EVENT_TYPE).await;
+        assert_eq!(dead.payload()["error"], "payload has no 'payload.user_id'");
+
+        engine.close();
+        assert_eq!(run.await.ok(), Some(Ok(())));
+    }
+
+    #[tokio::test]
+    async fn error_responses_are_dead_lettered() {
+        let (base, _received) = server().await;
+        let table = table(format!("{base}/fail"));
+        let args = SinkArgs {
+            max_attempts: 2,
+            retry_delay: 0,
+            dead_letter: true,
+            ..Default::default()
+        };
+        let (mut engine, run) = spawn_sink(fixture(), args, http_sink(table));
+
+        engine
+            .inject_message(fixtures::message("alert.fired", json!({"level": "high"})))
+            .await;
+        let dead = engine.expect_published(DEAD_LETTER_EVENT_TYPE).await;
+        let dead = dead.payload();
+        assert_eq!(
+            dead["error"],
+            format!("POST {base}/fail: HTTP 503 Service Unavailable")
+        );
+        assert_eq!(dead["status"], 503);
+        assert_eq!(dead["attempts"], 2);
+        assert_eq!(dead["message_type"], "alert.fired");
+        assert_eq!(dead["payload"], json!({"level": "high"}));
+
+        engine.close();
+        assert_eq!(run.await.ok(), Some(Ok(())));
+    }
+
+    #[tokio::test]
+    async fn client_errors_are_dead_lettered_without_retries() {
+        let (base, mut received) = server().await;
+        let table = table(format!("{base}/invalid"));
+        let args = SinkArgs {
+            max_attempts: 3,
+            retry_delay: 0,
+            dead_letter: true,
+            ..Default::default()
+        };
+        let (mut engine, run) = spawn_sink(fixture(), args, http_sink(table));
+
+        engine
+            .inject_message(fixtures::message("order.created", json!({"n": 1})))
+            .await;
+        let dead = engine.expect_published(DEAD_LETTER_EVENT_TYPE).await;
+        let dead = dead.payload();
+        assert_eq!(dead["status"], 422);
+        assert_eq!(dead["attempts"], 1);
+        let mut sent = 0;
+        while received.try_recv().is_ok() {
+            sent += 1;
+        }
+        assert_eq!(sent, 1);
+
+        engine.close();
+        assert_eq!(run.await.ok(), Some(Ok(())));
+    }
+
+    /// Batch `handler`'s deliveries `size` at a time, flushing every 50ms.
+    fn batching(handler: &mut HttpSink, size: usize) {
+        let batches = Arc::new(Batches {
+            delivery: Arc::clone(&handler.delivery),
+            size,
+            pending: Mutex::new(BTreeMap::new()),
+        });
+        tokio::spawn(flush_every(Arc::clone(&batches), Duration::from_millis(50)));
+        handler.batches = Some(batches);
+    }
+
+    #[tokio::test]
+    async fn batches_are_sent_per_endpoint_as_arrays() {
+        let (base, mut received) = server().await;
+        let table = Table {
+            routes: vec![
+                parse_route(&format!("order.*={base}/orders"))
+                    .unwrap_or_else(|e| panic!("route: {e}")),
+            ],
+            ..table(format!("{base}/events"))
+        };
+        let mut handler = http_sink(table);
+        batching(&mut handler, 2);
+        let args = SinkArgs {
+            workers: 4,
+            ..Default::default()
+        };
+        let (mut engine, run) = spawn_sink(fixture(), args, handler);
+
+        for (message_type, n) in [("alert.fired", 1), ("order.created", 2), ("alert.fired", 3)] {
+            engine
+                .inject_message(fixtures::message(message_type, json!({"n": n})))
+                .await;
+        }
+        // The full batch sends everything waiting, one request per endpoint
+        let mut next = async || {
+            received
+                .recv()
+                .await
+                .unwrap_or_else(|| panic!("no request"))
+        };
+        let events = next().await;
+        assert_eq!(events.path, "/events");
+        assert_eq!(events.body, json!([{"n": 1}, {"n": 3}]));
+        let orders = next().await;
+        assert_eq!(orders.path, "/orders");
+        assert_eq!(orders.body, json!([{"n": 2}]));
+
+        // Below the batch size, so it waits for the interval
+        engine
+            .inject_message(fixtures::message("alert.fired", json!({"n": 4})))
+            .await;
+        let last = next().await;
+        assert_eq!(last.body, json!([{"n": 4}]));
+        engine.close();
+        assert_eq!(run.await.ok(), Some(Ok(())));
+
+        let conflict = Args::try_parse_from([
+            "http-sink",
+            "-s",
+            "a",
+            "--batch-size",
+            "10",
+            "--publish-responses",
+        ]);
+        assert!(conflict.is_err());
+    }
+
+    #[tokio::test]
+    async fn failed_batches_are_retried_and_dead_lettered_per_message() {
+        let (base, mut received) = server().await;
+        let table = Table {
+            routes: vec![
+                parse_route(&format!("order.*={base}/reject"))
+                    .unwrap_or_else(|e| panic!("route: {e}")),
+            ],
+            ..table(format!("{base}/fail"))
+        };
+        let mut handler = http_sink(table);
+        batching(&mut handler, 2);
+        let args = SinkArgs {
+            workers: 4,
+            max_attempts: 2,
+            retry_delay: 0,
+            dead_letter: true,
+            ..Default::default()
+        };
+        let (mut engine, run) = spawn_sink(fixture(), args, handler);
+
+        // A 400 is not worth retrying: both messages are dead at once
+        for n in [1, 2] {
+            engine
+                .inject_message(fixtures::message("order.created", json!({"n": n})))
+                .await;
+        }
+        let mut dead_payloads = Vec::new();
+        for _ in [1, 2] {
+            let dead = engine.expect_published(DEAD_LETTER_EVENT_TYPE).await;
+            let dead = dead.payload();
+            assert_eq!(dead["status"], 400);
+            assert_eq!(dead["attempts"], 1);
+            dead_payloads.push(dead["payload"]["n"].as_u64());
+        }
+        dead_payloads.sort();
+        assert_eq!(dead_payloads, [Some(1), Some(2)]);
+        let rejected = received
+            .recv()
+            .await
+            .unwrap_or_else(|| panic!("no request"));
+        assert_eq!(rejected.body, json!([{"n": 1}, {"n": 2}]));
+
+        // A 503 is retried until each message runs out of attempts
+        engine
+            .inject_message(fixtures::message("alert.fired", json!({"n": 3})))
+            .await;
+        let dead = engine.expect_published(DEAD_LETTER_EVENT_TYPE).await;
+        let dead = dead.payload();
+        assert_eq!(dead["status"], 503);
+        assert_eq!(dead["attempts"], 2);
+        assert_eq!(dead["payload"], json!({"n": 3}));
+        let mut sent = Vec::new();
+        while let Ok(request) = received.try_recv() {
+            sent.push(request.body);
+        }
+        assert_eq!(sent, [json!([{"n": 3}]), json!([{"n": 3}])]);
+
+        engine.close();
+        assert_eq!(run.await.ok(), Some(Ok(())));
+    }
+
+    #[tokio::test]
+    async fn open_breakers_short_circuit_requests() {
+        let (base, mut received) = server().await;
+        let table = table(format!("{base}/fail"));
+        let mut delivery = delivery(table);
+        delivery.breakers = Breakers::new(2, Duration::from_secs(60));
+        delivery.breaker_open = BreakerOpen::DeadLetter;
+        let args = SinkArgs {
+            max_attempts: 5,
+            retry_delay: 0,
+            dead_letter: true,
+            ..Default::default()
+        };
+        let (mut engine, run) = spawn_sink(fixture(), args, sink_for(delivery));
+
+        engine
+            .inject_message(fixtures::message("alert.fired", json!({"n": 1})))
+            .await;
+        let dead = engine.expect_published(DEAD_LETTER_EVENT_TYPE).await;
+        let dead = dead.payload();
+        // Two failures open the breaker, and the third attempt gives up
+        assert_eq!(dead["attempts"], 3);
+        let error = dead["error"].as_str().unwrap_or_default();
+        assert!(error.contains("circuit open"), "{error}");
+
+        engine
+            .inject_message(fixtures::message("alert.fired", json!({"n": 2})))
+            .await;
+        let dead = engine.expect_published(DEAD_LETTER_EVENT_TYPE).await;
+        assert_eq!(dead.payload()["attempts"], 1);
+
+        engine.close();
+        assert_eq!(run.await.ok(), Some(Ok(())));
+        let mut requests = 0;
+        while received.try_recv().is_ok() {
+            requests += 1;
+        }
+        assert_eq!(requests, 2);
+    }
+
+    #[tokio::test]
+    async fn requeued_messages_outlast_an_open_breaker() {
+        let (base, mut received) = server().await;
+        let table = Table {
+            routes: vec![
+                parse_route(&format!("order.*={base}/fail"))
+                    .unwrap_or_else(|e| panic!("route: {e}")),
+            ],
+            ..table(format!("{base}/ok"))
+        };
+        let mut delivery = delivery(table);
+        delivery.breakers = Breakers::new(1, Duration::from_millis(600));
+        let args = SinkArgs {
+            max_attempts: 2,
+            retry_delay: 10,
+            dead_letter: true,
+            ..Default::default()
+        };
+        let (mut engine, run) = spawn_sink(fixture(), args, sink_for(delivery));
+
+        // One failure opens the host's breaker...
+        engine
+            .inject_message(fixtures::message("order.created", json!({"n": 1})))
+            .await;
+        let dead = engine.expect_published(DEAD_LETTER_EVENT_TYPE).await;
+        assert_eq!(dead.payload()["message_type"], "order.created");
+        let opened = tokio::time::Instant::now();
+
+        // ...for far longer than two attempts' backoff, yet the next
+        // message is sent once it closes rather than dead-lettered
+        engine
+            .inject_message(fixtures::message("alert.fired", json!({"n": 2})))
+            .await;
+        let mut paths = Vec::new();
+        while paths.last().map(String::as_str) != Some("/ok") {
+            let request = received
+                .recv()
+                .await
+                .unwrap_or_else(|| panic!("no request"));
+            paths.push(request.path);
+        }
+        assert_eq!(paths, ["/fail", "/fail", "/ok"]);
+        assert!(opened.elapsed() >= Duration::from_millis(400));
+        engine.expect_quiet(Duration::from_millis(100)).await;
+
+        engine.close();
+        assert_eq!(run.await.ok(), Some(Ok(())));
+    }
+
+    #[tokio::test]
+    async fn retry_after_delays_the_next_attempt() {
+        let (base, mut received) = server().await;
+        let table = table(format!("{base}/busy"));
+        let args = SinkArgs {
+            max_attempts: 2,
+            retry_delay: 0,
+            dead_letter: true,
+            ..Default::default()
+        };
+        let (mut engine, run) = spawn_sink(fixture(), args, http_sink(table));
+
+        engine
+            .inject_message(fixtures::message("alert.fired", json!({"level": "high"})))
+            .await;
+        let first = received.recv().await.map(|_| std::time::Instant::now());
+        let second = received.recv().await.map(|_| std::time::Instant::now());
+        let waited = first
+            .zip(second)
+            .map(|(first, second)| second - first)
+            .unwrap_or_else(|| panic!("no retry"));
+        assert!(
+            waited >= Duration::from_millis(900),
+            "retried after {waited:?}"
+        );
+        let dead = engine.expect_published(DEAD_LETTER_EVENT_TYPE).await;
+        assert_eq!(
+            dead.payload()["error"],
+            format!("POST {base}/busy: HTTP 429 Too Many Requests")
+        );
+
+        let headers = |value: &'static str| {
+            HeaderMap::from_iter([(
+                RETRY_AFTER,
+                reqwest::header::HeaderValue::from_static(value),
+            )])
+        };
+        assert_eq!(retry_after(&headers("120")), Some(Duration::from_secs(120)));
+        assert_eq!(
+            retry_after(&headers("Wed, 21 Oct 2015 07:28:00 GMT")),
+            Some(Duration::ZERO)
+        );
+        assert_eq!(retry_after(&headers("soon")), None);
+
+        engine.close();
+        assert_eq!(run.await.ok(), Some(Ok(())));
+    }
+
+    #[tokio::test]
+    async fn responses_are_published_when_asked() {
+        let (base, _received) = server().await;
+        let table = Table {
+            routes: vec![
+                parse_route(&format!("order.*={base}/fail"))
+                    .unwrap_or_else(|e| panic!("route: {e}")),
+            ],
+            ..table(format!("{base}/ok"))
+        };
+        let mut handler = http_sink(table);
+        handler.publish_responses = true;
+        assert_eq!(handler.publishes(), [RESPONSE_EVENT_TYPE]);
+        let (mut engine, run) = spawn_sink(fixture(), SinkArgs::default(), handler);
+
+        let msg = fixtures::message("user.lookup", json!({"id": 1}));
+        let id = msg.id().to_string();
+        engine.inject_message(msg).await;
+        let response = engine.expect_published(RESPONSE_EVENT_TYPE).await;
+        let payload = response.payload();
+        assert_eq!(payload["message_id"], id);
+        assert_eq!(payload["message_type"], "user.lookup");
+        assert_eq!(payload["url"], format!("{base}/ok"));
+        assert_eq!(payload["status"], 200);
+        assert_eq!(payload["ok"], true);
+        assert_eq!(payload["body"], json!({"status": "ok"}));
+        assert_eq!(payload["headers"]["content-length"], "16");
+        assert!(payload["latency_ms"].is_u64());
+
+        engine
+            .inject_message(fixtures::message("order.created", json!({"id": 2})))
+            .await;
+        let response = engine.expect_published(RESPONSE_EVENT_TYPE).await;
+        assert_eq!(response.payload()["status"], 503);
+        assert_eq!(response.payload()["ok"], false);
+        assert_eq!(response.payload()["body"], "");
+
+        engine.close();
+        assert_eq!(run.await.ok(), Some(Ok(())));
+    }
+
+    #[tokio::test]
+    async fn error_bodies_fail_the_success_jsonpath() {
+        let (base, _received) = server().await;
+        let table = Table {
+            routes: vec![
+                parse_route(&format!("order.*={base}/soft-fail"))
+                    .unwrap_or_else(|e| panic!("route: {e}")),
+            ],
+            success: SuccessRule {
+                success_jsonpath: Some("$.status".to_string()),
+                success_values: vec!["ok".to_string(), "accepted".to_string()],
+                success_expr: None,
+            },
+            ..table(format!("{base}/ok"))
+        };
+        let args = SinkArgs {
+            dead_letter: true,
+            ..Default::default()
+        };
+        let (mut engine, run) = spawn_sink(fixture(), args, http_sink(table));
+
+        engine
+            .inject_message(fixtures::message("user.created", json!({"id": 1})))
+            .await;
+        engine
+            .inject_message(fixtures::message("order.created", json!({"id": 2})))
+            .await;
+        let dead = engine.expect_published(DEAD_LETTER_EVENT_TYPE).await;
+        assert_eq!(dead.payload()["message_type"], "order.created");
+        assert_eq!(
+            dead.payload()["error"],
+            format!(r#"POST {base}/soft-fail: $.status is "error""#)
+        );
+
+        engine.close();
+        assert_eq!(run.await.ok(), Some(Ok(())));
+    }
+
+    #[tokio::test]
+    async fn graphql_errors_fail_ok_responses() {
+        let (base, mut received) = server().await;
+        let table = Table {
+            method: "PUT".to_string(),
+            content_type: "text/plain".to_string(),
+            ..table(format!("{base}/graphql"))
+        };
+        let mut delivery = delivery(table);
+        delivery.graphql = true;
+        let args = SinkArgs {
+            dead_letter: true,
+            ..Default::default()
+        };
+        let (mut engine, run) = spawn_sink(fixture(), args, sink_for(delivery));
+
+        engine
+            .inject_message(fixtures::message(
+                "issue.close",
+                json!({"query": "mutation { close(id: $id) }", "variables": {"id": 7}, "team": "ops"}),
+            ))
+            .await;
+        let request = received
+            .recv()
+            .await
+            .unwrap_or_else(|| panic!("no request"));
+        assert_eq!(request.method, "POST");
+        assert_eq!(request.content_type.as_deref(), Some("application/json"));
+        assert_eq!(
+            request.body,
+            json!({"query": "mutation { close(id: $id) }", "variables": {"id": 7}})
+        );
+
+        engine
+            .inject_message(fixtures::message(
+                "issue.close",
+                json!({"query": "{ missing }"}),
+            ))
+            .await;
+        let dead = engine.expect_published(DEAD_LETTER_EVENT_TYPE).await;
+        assert_eq!(
+            dead.payload()["error"],
+            format!("POST {base}/graphql: GraphQL errors: Field 'missing' doesn't exist")
+        );
+        assert_eq!(dead.payload()["status"], 200);
+
+        engine.close();
+        assert_eq!(run.await.ok(), Some(Ok(())));
+    }
+
+    #[tokio::test]
+    async fn a_slow_endpoint_is_called_concurrently_in_order_per_key() {
+        let (base, mut received) = server().await;
+        let table = table(format!("{base}/slow"));
+        let args = Args::try_parse_from([
+            "http-sink",
+            "-s",
+            "order.created",
+            "--concurrency",
+            "4",
+            "--correlation-key",
+            "payload.tenant",
+        ])
+        .unwrap_or_else(|e| panic!("{e}"));
+        assert_eq!(args.sink.workers, 4);
+        // Two tenants that land on different workers
+        let other = ["globex", "initech", "umbrella", "hooli"]
+            .into_iter()
+            .find(|tenant| key::bucket(tenant, 4) != key::bucket("acme", 4))
+            .unwrap_or_else(|| panic!("no tenant on another worker"));
+        let (mut engine, run) = spawn_sink(fixture(), args.sink, http_sink(table));
+
+        let started = tokio::time::Instant::now();
+        for (id, tenant) in [(1, "acme"), (2, "acme"), (3, other)] {
+            engine
+                .inject_message(fixtures::message(
+                    "order.created",
+                    json!({"id": id, "tenant": tenant}),
+                ))
+                .await;
+        }
+        let mut arrivals = Vec::new();
+        for _ in 0..3 {
+            let request = received
+                .recv()
+                .await
+                .unwrap_or_else(|| panic!("no request"));
+            arrivals.push((request.body["id"].clone(), started.elapsed()));
+        }
+        // The other tenant's order doesn't wait behind acme's...
+        let mut first: Vec<Value> = arrivals[..2].iter().map(|(id, _)| id.clone()).collect();
+        first.sort_by_key(|id| id.as_i64());
+        assert_eq!(first, [json!(1), json!(3)]);
+        assert!(arrivals[1].1 < Duration::from_millis(400), "{arrivals:?}");
+        // ...but acme's second order waits for its first
+        assert_eq!(arrivals[2].0, 2);
+        assert!(arrivals[2].1 >= Duration::from_millis(500), "{arrivals:?}");
+
+        engine.close();
+        assert_eq!(run.await.ok(), Some(Ok(())));
+    }
+
+    #[tokio::test]
+    async fn resolve_pins_hostnames_to_addresses() {
+        let (base, mut received) = server().await;
+        let addr = base
+            .trim_start_matches("http://")
+            .parse()
+            .unwrap_or_else(|e| panic!("addr: {e}"));
+        let client = ClientArgs {
+            resolve: vec![("green.test".to_string(), addr)],
+            connect_timeout: Some(1000),
+            ..Default::default()
+        };
+        let table = table(format!("http://green.test:{}/events", addr.port()));
+        let mut delivery = delivery(table);
+        delivery.client = client.build().unwrap_or_else(|e| panic!("client: {e}"));
+        let handler = sink_for(delivery);
+        let (mut engine, run) = spawn_sink(fixture(), SinkArgs::default(), handler);
+
+        engine
+            .inject_message(fixtures::message("order.created", json!({"id": 1})))
+            .await;
+        let request = received
+            .recv()
+            .await
+            .unwrap_or_else(|| panic!("no request"));
+        assert_eq!(request.path, "/events");
+
+        engine.close();
+        assert_eq!(run.await.ok(), Some(Ok(())));
+    }
+}
@@ -0,0 +1,192 @@
+//! OAuth2 client-credentials auth.
+//!
+//! With `--oauth-token-url`, requests that no `auth` setting covers carry a
+//! bearer token obtained from the token endpoint with the client's id and
+//! secret. The token is cached until shortly before it expires, and
+//! dropped early if an endpoint answers 401 so the retry fetches a new
+//! one. Like the client flags, these are not hot-swappable.
+
+use clap::Args;
+use reqwest::Client;
+use serde_json::Value;
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+
+/// Tokens are renewed this long before they expire.
+const TOKEN_MARGIN: Duration = Duration::from_secs(60);
+
+/// Lifetime assumed when the token endpoint doesn't give `expires_in`.
+const DEFAULT_LIFETIME: Duration = Duration::from_secs(3600);
+
+/// CLI flags for the client-credentials grant.
+#[derive(Args, Debug, Clone, Default)]
+pub struct OAuthArgs {
+    /// Token endpoint to obtain bearer tokens from with the client-credentials grant.
+    #[arg(
+        long,
+        env = "HTTP_SINK_OAUTH_TOKEN_URL",
+        requires_all = ["oauth_client_id", "oauth_client_secret"]
+    )]
+    pub oauth_token_url: Option<String>,
+
+    /// Client id presented to `--oauth-token-url`.
+    #[arg(long, env = "HTTP_SINK_OAUTH_CLIENT_ID", requires = "oauth_token_url")]
+    pub oauth_client_id: Option<String>,
+
+    /// Client secret presented to `--oauth-token-url`.
+    #[arg(
+        long,
+        env = "HTTP_SINK_OAUTH_CLIENT_SECRET",
+        requires = "oauth_token_url"
+    )]
+    pub oauth_client_secret: Option<String>,
+
+    /// Space-separated scopes to request.
+    #[arg(long, env = "HTTP_SINK_OAUTH_SCOPE", requires = "oauth_token_url")]
+    pub oauth_scope: Option<String>,
+}
+
+/// Fetches and caches bearer tokens for one client.
+pub struct TokenSource {
+    url: String,
+    client_id: String,
+    client_secret: String,
+    scope: Option<String>,
+    token: Mutex<Option<(String, Instant)>>,
+}
+
+impl TokenSource {
+    /// The token source `args` describe, if any.
+    pub fn new(args: &OAuthArgs) -> Option<Self> {
+        Some(Self {
+            url: args.oauth_token_url.clone()?,
+            client_id: args.oauth_client_id.clone()?,
+            client_secret: args.oauth_client_secret.clone()?,
+            scope: args.oauth_scope.clone(),
+            token: Mutex::new(None),
+        })
+    }
+
+    /// The token endpoint.
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// A current access token, fetching a new one if needed.
+    pub async fn token(&self, client: &Client) -> Result<String, String> {
+        // Held across the fetch, so concurrent workers wait for one token
+        let mut cached = self.token.lock().await;
+        if let Some((token, expires)) = cached.as_ref()
+            && Instant::now() + TOKEN_MARGIN < *expires
+        {
+            return Ok(token.clone());
+        }
+        let mut form = vec![
+            ("grant_type", "client_credentials"),
+            ("client_id", self.client_id.as_str()),
+            ("client_secret", self.client_secret.as_str()),
+        ];
+        if let Some(scope) = &self.scope {
+            form.push(("scope", scope.as_str()));
+        }
+        let response: Value = client
+            .post(&self.url)
+            .form(&form)
+            .send()
+            .await
+            .and_then(reqwest::Response::error_for_status)
+            .map_err(|e| format!("{}: {e}", self.url))?
+            .json()
+            .await
+            .map_err(|e| format!("{}: {e}", self.url))?;
+        let token = response["access_token"]
+            .as_str()
+            .ok_or_else(|| format!("{}: no access_token in response", self.url))?
+            .to_string();
+        let lifetime = response["expires_in"]
+            .as_u64()
+            .map_or(DEFAULT_LIFETIME, Duration::from_secs);
+        *cached = Some((token.clone(), Instant::now() + lifetime));
+        Ok(token)
+    }
+
+    /// Drop the cached token if it is still `token`, after an endpoint
+    /// refused it.
+    pub async fn invalidate(&self, token: &str) {
+        let mut cached = self.token.lock().await;
+        if cached.as_ref().is_some_and(|(current, _)| current == token) {
+            *cached = None;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{Form, Json, Router, routing::post};
+    use std::collections::HashMap;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    #[tokio::test]
+    async fn tokens_are_cached_until_expiry_or_refusal() {
+        let issued = Arc::new(AtomicU64::new(0));
+        let app = {
+            let issued = Arc::clone(&issued);
+            Router::new().route(
+                "/token",
+                post(move |Form(form): Form<HashMap<String, String>>| {
+                    let issued = Arc::clone(&issued);
+                    async move {
+                        assert_eq!(form["grant_type"], "client_credentials");
+                        assert_eq!(form["client_id"], "sink");
+                        assert_eq!(form["client_secret"], "s3cret");
+                        assert_eq!(form["scope"], "events:write");
+                        let n = issued.fetch_add(1, Ordering::SeqCst) + 1;
+                        // The second token is about to expire
+                        let expires_in = if n == 2 { 30 } else { 3600 };
+                        Json(serde_json::json!({
+                            "access_token": format!("token-{n}"),
+                            "token_type": "Bearer",
+                            "expires_in": expires_in,
+                        }))
+                    }
+                }),
+            )
+        };
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap_or_else(|e| panic!("bind: {e}"));
+        let addr = listener
+            .local_addr()
+            .unwrap_or_else(|e| panic!("local_addr: {e}"));
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let source = TokenSource::new(&OAuthArgs {
+            oauth_token_url: Some(format!("http://{addr}/token")),
+            oauth_client_id: Some("sink".to_string()),
+            oauth_client_secret: Some("s3cret".to_string()),
+            oauth_scope: Some("events:write".to_string()),
+        })
+        .unwrap_or_else(|| panic!("token source"));
+        let client = Client::new();
+        let token = || async {
+            source
+                .token(&client)
+                .await
+                .unwrap_or_else(|e| panic!("{e}"))
+        };
+
+        assert_eq!(token().await, "token-1");
+        assert_eq!(token().await, "token-1");
+        // A stale token is only dropped if it is still the cached one
+        source.invalidate("token-0").await;
+        assert_eq!(token().await, "token-1");
+        source.invalidate("token-1").await;
+        assert_eq!(token().await, "token-2");
+        // Within the margin of expiry, a new token is fetched
+        assert_eq!(token().await, "token-3");
+        assert_eq!(issued.load(Ordering::SeqCst), 3);
+        assert!(TokenSource::new(&OAuthArgs::default()).is_none());
+    }
+}
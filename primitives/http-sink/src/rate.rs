@@ -0,0 +1,107 @@
+//! Token-bucket rate limiting.
+//!
+//! With `--rate-limit`, each request takes a token from a bucket refilled
+//! at that many per second and holding at most `--rate-limit-burst`. A
+//! request finding the bucket empty reserves the next token and waits for
+//! it, so waiting requests go out in turn at the limit. The worker waiting
+//! holds its message meanwhile; once every worker waits, the harness queue
+//! fills up to `--queue-depth` and the sink stops reading from the engine,
+//! so a burst of events backs up instead of reaching the API. With
+//! `--rate-limit-per-host` every host gets a bucket of its own.
+
+use std::collections::HashMap;
+use std::sync::{Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+/// A bucket's tokens as of `updated`; negative while requests wait.
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// The buckets of every host, or the one shared by all.
+#[derive(Debug)]
+pub struct RateLimit {
+    per_second: f64,
+    burst: f64,
+    per_host: bool,
+    buckets: Mutex<HashMap<String, Bucket>>,
+}
+
+impl RateLimit {
+    /// A limit of `per_second` requests, in bursts of up to `burst`
+    /// (default: one second's worth); `None` for a limit of 0.
+    pub fn new(per_second: f64, burst: Option<u32>, per_host: bool) -> Option<Self> {
+        (per_second > 0.0).then(|| Self {
+            per_second,
+            burst: burst.map_or(per_second.ceil(), f64::from).max(1.0),
+            per_host,
+            buckets: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Take a token for a request to `host` at `now`: how long the
+    /// request must wait for it.
+    pub fn reserve(&self, host: &str, now: Instant) -> Duration {
+        let key = if self.per_host { host } else { "" };
+        let mut buckets = self.buckets.lock().unwrap_or_else(PoisonError::into_inner);
+        let bucket = buckets.entry(key.to_string()).or_insert(Bucket {
+            tokens: self.burst,
+            updated: now,
+        });
+        let refill = now.saturating_duration_since(bucket.updated).as_secs_f64() * self.per_second;
+        bucket.tokens = (bucket.tokens + refill).min(self.burst) - 1.0;
+        bucket.updated = bucket.updated.max(now);
+        if bucket.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-bucket.tokens / self.per_second)
+        }
+    }
+
+    /// Wait until a request to `host` may go.
+    pub async fn acquire(&self, host: &str) {
+        let wait = self.reserve(host, Instant::now());
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bursts_pass_then_requests_wait_their_turn() {
+        assert!(RateLimit::new(0.0, None, false).is_none());
+        let limit = RateLimit::new(2.0, Some(3), false).unwrap_or_else(|| panic!("enabled"));
+        let start = Instant::now();
+        let ms = |ms| Duration::from_millis(ms);
+
+        for _ in 0..3 {
+            assert_eq!(limit.reserve("a:443", start), Duration::ZERO);
+        }
+        // Queued behind each other, half a second apart
+        assert_eq!(limit.reserve("a:443", start), ms(500));
+        assert_eq!(limit.reserve("b:443", start), ms(1000));
+        // The first waiter's token has come in
+        assert_eq!(limit.reserve("a:443", start + ms(1000)), ms(500));
+        // A quiet spell refills the bucket, but no further than the burst
+        let later = start + ms(60_000);
+        for _ in 0..3 {
+            assert_eq!(limit.reserve("a:443", later), Duration::ZERO);
+        }
+        assert_eq!(limit.reserve("a:443", later), ms(500));
+    }
+
+    #[test]
+    fn hosts_get_their_own_buckets() {
+        let limit = RateLimit::new(1.0, None, true).unwrap_or_else(|| panic!("enabled"));
+        let start = Instant::now();
+        assert_eq!(limit.reserve("a:443", start), Duration::ZERO);
+        assert_eq!(limit.reserve("b:443", start), Duration::ZERO);
+        assert_eq!(limit.reserve("a:443", start), Duration::from_secs(1));
+    }
+}
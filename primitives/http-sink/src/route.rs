@@ -0,0 +1,468 @@
+//! Routing message types to endpoints.
+//!
+//! Routes are tried in order and the first whose pattern matches the
+//! message type wins; `order.*` matches a namespace and `*` matches
+//! everything. Types no route matches go to `--url`. A route may override
+//! the method, timeout, auth, headers, content type and body template;
+//! anything it leaves
+//! out falls back to the top-level setting. Route URLs may hold the same
+//! `{{field}}` placeholders as `--url-template` (see [`crate::template`]).
+//!
+//! Routes can also be kept in a YAML (or JSON) file given with `--routes`,
+//! mapping each pattern to its endpoint; entries keep the file's order:
+//!
+//! ```yaml
+//! order.refund:
+//!   url: https://payments.internal/refunds/{{payload.order_id}}
+//!   method: PUT
+//!   headers: {X-Team: payments}
+//! order.*:
+//!   url: https://orders.internal/api/events
+//!   body_template: {order: "{{order_id}}", event: "{{type}}"}
+//! ```
+
+use crate::body::Encoding;
+use crate::success::SuccessRule;
+use crate::template;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// One entry of the routing table.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Route {
+    /// Message type pattern.
+    #[serde(rename = "match")]
+    pub pattern: String,
+
+    /// Endpoint for matching messages.
+    pub url: String,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub method: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout: Option<u64>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub auth: Option<String>,
+
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub headers: BTreeMap<String, String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content_type: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub body_template: Option<Value>,
+}
+
+impl Route {
+    /// Whether this route handles `message_type`.
+    pub fn matches(&self, message_type: &str) -> bool {
+        match self.pattern.strip_suffix('*') {
+            Some(prefix) => message_type.starts_with(prefix),
+            None => self.pattern == message_type,
+        }
+    }
+}
+
+/// Parse a `--route` value: `pattern=url`.
+pub fn parse_route(s: &str) -> Result<Route, String> {
+    let (pattern, url) = s
+        .split_once('=')
+        .ok_or_else(|| format!("expected PATTERN=URL, got '{s}'"))?;
+    let (pattern, url) = (pattern.trim(), url.trim());
+    if pattern.is_empty() || url.is_empty() {
+        return Err(format!("expected PATTERN=URL, got '{s}'"));
+    }
+    Ok(Route {
+        pattern: pattern.to_string(),
+        url: url.to_string(),
+        method: None,
+        timeout: None,
+        auth: None,
+        headers: BTreeMap::new(),
+        content_type: None,
+        body_template: None,
+    })
+}
+
+/// Parse a `--routes` file: a mapping from pattern to endpoint, in order.
+pub fn parse_routes(text: &str) -> Result<Vec<Route>, String> {
+    let mapping: serde_yaml_ng::Mapping =
+        serde_yaml_ng::from_str(text).map_err(|e| e.to_string())?;
+    mapping
+        .into_iter()
+        .map(|(pattern, endpoint)| {
+            let pattern = match pattern {
+                serde_yaml_ng::Value::String(pattern) if !pattern.trim().is_empty() => pattern,
+                other => return Err(format!("route pattern must be a string, got {other:?}")),
+            };
+            let serde_yaml_ng::Value::Mapping(mut endpoint) = endpoint else {
+                return Err(format!("{pattern}: expected a mapping with at least a url"));
+            };
+            endpoint.insert("match".into(), pattern.clone().into());
+            serde_yaml_ng::from_value(serde_yaml_ng::Value::Mapping(endpoint))
+                .map_err(|e| format!("{pattern}: {e}"))
+        })
+        .collect()
+}
+
+/// Read a `--routes` file.
+pub fn load_routes(path: &Path) -> Result<Vec<Route>, String> {
+    let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {e}", path.display()))?;
+    parse_routes(&text).map_err(|e| format!("{}: {e}", path.display()))
+}
+
+/// Parse a `--header` value: `Name: value`.
+pub fn parse_header(s: &str) -> Result<(String, String), String> {
+    let (name, value) = s
+        .split_once(':')
+        .ok_or_else(|| format!("expected 'Name: value', got '{s}'"))?;
+    let name = name.trim();
+    if name.is_empty() {
+        return Err(format!("expected 'Name: value', got '{s}'"));
+    }
+    Ok((name.to_string(), value.trim().to_string()))
+}
+
+/// How requests authenticate.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Auth {
+    /// `Authorization: Bearer <token>`.
+    Bearer(String),
+    /// HTTP basic auth.
+    Basic {
+        user: String,
+        password: Option<String>,
+    },
+}
+
+impl Auth {
+    /// Parse `bearer:<token>` or `basic:<user>[:<password>]`.
+    pub fn parse(s: &str) -> Result<Self, String> {
+        match s.split_once(':') {
+            Some(("bearer", token)) if !token.is_empty() => Ok(Self::Bearer(token.to_string())),
+            Some(("basic", credentials)) if !credentials.is_empty() => {
+                let (user, password) = match credentials.split_once(':') {
+                    Some((user, password)) => (user, Some(password.to_string())),
+                    None => (credentials, None),
+                };
+                Ok(Self::Basic {
+                    user: user.to_string(),
+                    password,
+                })
+            }
+            _ => Err("auth must be bearer:<token> or basic:<user>[:<password>]".to_string()),
+        }
+    }
+}
+
+/// Where and how to deliver one message, after applying route overrides.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Endpoint<'a> {
+    pub url: &'a str,
+    pub method: &'a str,
+    pub timeout: u64,
+    pub auth: Option<&'a str>,
+    pub headers: BTreeMap<&'a str, &'a str>,
+    /// How the body is encoded (see [`crate::body`]).
+    pub content_type: &'a str,
+    /// Body to render instead of sending the payload as-is.
+    pub body_template: Option<&'a Value>,
+}
+
+impl Endpoint<'_> {
+    /// This endpoint with its URL rendered as `url`, owned so it can
+    /// outlive the settings it came from.
+    pub fn target(&self, url: String) -> Target {
+        Target {
+            method: self.method.to_string(),
+            url,
+            timeout: self.timeout,
+            auth: self.auth.map(str::to_string),
+            headers: self
+                .headers
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+            content_type: self.content_type.to_string(),
+        }
+    }
+}
+
+/// Everything that shapes a request but its body; batches are gathered per
+/// target.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Target {
+    pub method: String,
+    pub url: String,
+    pub timeout: u64,
+    pub auth: Option<String>,
+    pub headers: BTreeMap<String, String>,
+    pub content_type: String,
+}
+
+/// Top-level delivery settings plus the routing table.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Table {
+    /// Endpoint for message types no route matches; may hold placeholders.
+    pub url: Option<String>,
+    pub method: String,
+    pub timeout: u64,
+    pub auth: Option<String>,
+    pub headers: BTreeMap<String, String>,
+    pub content_type: String,
+    pub body_template: Option<Value>,
+    pub routes: Vec<Route>,
+    #[serde(flatten)]
+    pub success: SuccessRule,
+}
+
+impl Table {
+    /// The endpoint for `message_type`, or `None` if nothing matches and
+    /// there is no default `--url`.
+    pub fn endpoint(&self, message_type: &str) -> Option<Endpoint<'_>> {
+        let mut headers: BTreeMap<&str, &str> = self
+            .headers
+            .iter()
+            .map(|(k, v)| (k.as_str(), v.as_str()))
+            .collect();
+        let Some(route) = self.routes.iter().find(|r| r.matches(message_type)) else {
+            return self.url.as_deref().map(|url| Endpoint {
+                url,
+                method: &self.method,
+                timeout: self.timeout,
+                auth: self.auth.as_deref(),
+                headers,
+                content_type: &self.content_type,
+                body_template: self.body_template.as_ref(),
+            });
+        };
+        headers.extend(route.headers.iter().map(|(k, v)| (k.as_str(), v.as_str())));
+        Some(Endpoint {
+            url: &route.url,
+            method: route.method.as_deref().unwrap_or(&self.method),
+            timeout: route.timeout.unwrap_or(self.timeout),
+            auth: route.auth.as_deref().or(self.auth.as_deref()),
+            headers,
+            content_type: route.content_type.as_deref().unwrap_or(&self.content_type),
+            body_template: route.body_template.as_ref().or(self.body_template.as_ref()),
+        })
+    }
+
+    /// Check every URL, method, auth value, content type, body template and the success JSONPath, so
+    /// mistakes surface at startup rather than on the first matching message.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.url.is_none() && self.routes.is_empty() {
+            return Err("no --url, --url-template, --route or --routes given".to_string());
+        }
+        self.success.validate()?;
+        let defaults = self.url.iter().map(|url| {
+            (
+                "default",
+                url,
+                None,
+                None,
+                None,
+                self.body_template.as_ref(),
+            )
+        });
+        let routes = self.routes.iter().map(|r| {
+            (
+                r.pattern.as_str(),
+                &r.url,
+                r.method.as_ref(),
+                r.auth.as_ref(),
+                r.content_type.as_ref(),
+                r.body_template.as_ref(),
+            )
+        });
+        for (name, url, method, auth, content_type, body_template) in defaults.chain(routes) {
+            let sample = template::sample_url(url).map_err(|e| format!("{name}: {e}"))?;
+            reqwest::Url::parse(&sample)
+                .map_err(|e| format!("{name}: invalid url '{url}': {e}"))?;
+            if let Some(body_template) = body_template.or(self.body_template.as_ref()) {
+                template::check_body(body_template).map_err(|e| format!("{name}: {e}"))?;
+            }
+            let method = method.unwrap_or(&self.method);
+            reqwest::Method::from_bytes(method.as_bytes())
+                .map_err(|_| format!("{name}: invalid method '{method}'"))?;
+            if let Some(auth) = auth.or(self.auth.as_ref()) {
+                Auth::parse(auth).map_err(|e| format!("{name}: {e}"))?;
+            }
+            Encoding::of(content_type.unwrap_or(&self.content_type))
+                .map_err(|e| format!("{name}: {e}"))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn table(routes: &[&str]) -> Table {
+        Table {
+            url: Some("https://fallback/events".to_string()),
+            method: "POST".to_string(),
+            timeout: 30_000,
+            auth: Some("bearer:global".to_string()),
+            headers: BTreeMap::from([("X-Env".to_string(), "prod".to_string())]),
+            content_type: "application/json".to_string(),
+            body_template: None,
+            routes: routes
+                .iter()
+                .map(|r| parse_route(r).unwrap_or_else(|e| panic!("route: {e}")))
+                .collect(),
+            success: SuccessRule::default(),
+        }
+    }
+
+    #[test]
+    fn first_matching_route_wins() {
+        let table = table(&[
+            "order.refund=https://refunds/api",
+            "order.*=https://orders/api/events",
+            "user.*=https://identity/api/events",
+        ]);
+        let url = |t: &str| table.endpoint(t).map(|e| e.url);
+        assert_eq!(url("order.refund"), Some("https://refunds/api"));
+        assert_eq!(url("order.created"), Some("https://orders/api/events"));
+        assert_eq!(url("user.created"), Some("https://identity/api/events"));
+        assert_eq!(url("alert.fired"), Some("https://fallback/events"));
+    }
+
+    #[test]
+    fn route_overrides_fall_back_to_top_level_settings() {
+        let mut table = table(&["order.*=https://orders/api"]);
+        table.routes[0].method = Some("PUT".to_string());
+        table.routes[0].auth = Some("basic:svc:secret".to_string());
+        table.routes[0]
+            .headers
+            .insert("X-Team".to_string(), "orders".to_string());
+
+        let order = table.endpoint("order.created");
+        assert_eq!(
+            order,
+            Some(Endpoint {
+                url: "https://orders/api",
+                method: "PUT",
+                timeout: 30_000,
+                auth: Some("basic:svc:secret"),
+                headers: BTreeMap::from([("X-Env", "prod"), ("X-Team", "orders")]),
+                content_type: "application/json",
+                body_template: None,
+            })
+        );
+        let other = table.endpoint("user.created");
+        assert_eq!(
+            other.map(|e| (e.method, e.auth)),
+            Some(("POST", Some("bearer:global")))
+        );
+
+        table.url = None;
+        assert_eq!(table.endpoint("user.created"), None);
+    }
+
+    #[test]
+    fn invalid_settings_fail_validation() {
+        assert!(parse_route("order.*").is_err());
+        assert!(table(&["order.*=not a url"]).validate().is_err());
+
+        let mut bad_method = table(&["order.*=https://orders/api"]);
+        bad_method.routes[0].method = Some("GE T".to_string());
+        assert!(bad_method.validate().is_err());
+
+        let mut bad_auth = table(&[]);
+        bad_auth.auth = Some("token".to_string());
+        assert!(bad_auth.validate().is_err());
+
+        assert!(
+            table(&["user.*=https://users/{{user_id"])
+                .validate()
+                .is_err()
+        );
+        let mut bad_content_type = table(&["order.*=https://orders/api"]);
+        bad_content_type.routes[0].content_type = Some("application/xml".to_string());
+        assert!(bad_content_type.validate().is_err());
+
+        let mut bad_body = table(&[]);
+        bad_body.body_template = Some(serde_json::json!({"id": "{{}}"}));
+        assert!(bad_body.validate().is_err());
+
+        let mut nowhere = table(&[]);
+        nowhere.url = None;
+        assert!(nowhere.validate().is_err());
+
+        assert_eq!(table(&["*=https://all/api"]).validate(), Ok(()));
+        assert_eq!(
+            table(&["user.*=https://users/api/{{payload.user_id}}"]).validate(),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn route_files_map_patterns_to_endpoints_in_order() {
+        let routes = parse_routes(
+            r#"
+order.refund:
+  url: https://payments.internal/refunds/{{payload.order_id}}
+  method: PUT
+  timeout: 5000
+  headers: {X-Team: payments}
+order.*:
+  url: https://orders.internal/api/events
+  auth: bearer:orders
+  body_template: {order: "{{order_id}}", event: "{{type}}"}
+"#,
+        )
+        .unwrap_or_else(|e| panic!("{e}"));
+        assert_eq!(routes.len(), 2);
+        assert_eq!(routes[0].pattern, "order.refund");
+        assert_eq!(routes[0].method.as_deref(), Some("PUT"));
+        assert_eq!(routes[0].timeout, Some(5000));
+        assert_eq!(routes[0].headers["X-Team"], "payments");
+        assert_eq!(routes[1].pattern, "order.*");
+        assert_eq!(
+            routes[1].body_template,
+            Some(serde_json::json!({"order": "{{order_id}}", "event": "{{type}}"}))
+        );
+
+        let mut table = table(&[]);
+        table.routes = routes;
+        assert_eq!(table.validate(), Ok(()));
+        assert_eq!(
+            table.endpoint("order.refund").map(|e| e.method),
+            Some("PUT")
+        );
+
+        // JSON is YAML too
+        assert_eq!(
+            parse_routes(r#"{"user.*": {"url": "https://identity/api"}}"#).map(|r| r.len()),
+            Ok(1)
+        );
+        assert!(parse_routes("order.*: https://orders/api").is_err());
+        assert!(parse_routes("order.*: {url: https://orders/api, retries: 3}").is_err());
+        assert!(parse_routes("order.*: {method: PUT}").is_err());
+    }
+
+    #[test]
+    fn auth_values_parse() {
+        assert_eq!(
+            Auth::parse("bearer:abc"),
+            Ok(Auth::Bearer("abc".to_string()))
+        );
+        assert_eq!(
+            Auth::parse("basic:svc:p:w"),
+            Ok(Auth::Basic {
+                user: "svc".to_string(),
+                password: Some("p:w".to_string())
+            })
+        );
+        assert!(Auth::parse("bearer:").is_err());
+    }
+}
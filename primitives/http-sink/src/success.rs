@@ -0,0 +1,169 @@
+//! Judging delivery success from the response body.
+//!
+//! Some APIs answer `200 OK` with `{"status": "error"}`. With
+//! `--success-jsonpath '$.status'`, a 2xx response only counts as delivered
+//! if the JSONPath matches the body and the first match is one of
+//! `--success-values` (compared as strings, so `true` and `1` work too).
+//! Without `--success-values` any match other than `null` or `false` counts.
+//! `--success-expr '.status == "ok"'` instead judges the body by a jq
+//! expression (see [`crate::jq`]), which must come out truthy. Responses
+//! failing the check are retried and dead-lettered like HTTP errors.
+
+use crate::jq::{self, Expr};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use serde_json_path::JsonPath;
+
+/// Body assertion settings.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SuccessRule {
+    pub success_jsonpath: Option<String>,
+    pub success_values: Vec<String>,
+    pub success_expr: Option<String>,
+}
+
+impl SuccessRule {
+    /// Check that the JSONPath or expression parses.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.success_jsonpath.is_some() && self.success_expr.is_some() {
+            return Err("success_jsonpath and success_expr cannot both be set".to_string());
+        }
+        self.path()?;
+        self.expr().map(|_| ())
+    }
+
+    /// Whether the response body has to be read at all.
+    pub fn is_enabled(&self) -> bool {
+        self.success_jsonpath.is_some() || self.success_expr.is_some()
+    }
+
+    /// Judge a 2xx response body.
+    pub fn check(&self, body: &[u8]) -> Result<(), String> {
+        if let (Some(text), Some(expr)) = (&self.success_expr, self.expr()?) {
+            let body: Value =
+                serde_json::from_slice(body).map_err(|e| format!("response is not JSON: {e}"))?;
+            let result = expr.eval(&body);
+            return if jq::truthy(&result) {
+                Ok(())
+            } else {
+                Err(format!("{text} is {result}"))
+            };
+        }
+        let (Some(expr), Some(path)) = (&self.success_jsonpath, self.path()?) else {
+            return Ok(());
+        };
+        let body: Value =
+            serde_json::from_slice(body).map_err(|e| format!("response is not JSON: {e}"))?;
+        let Some(found) = path.query(&body).first() else {
+            return Err(format!("{expr} not found in response"));
+        };
+
+        let accepted = if self.success_values.is_empty() {
+            !matches!(found, Value::Null | Value::Bool(false))
+        } else {
+            let found = match found {
+                Value::String(s) => s.clone(),
+                other => other.to_string(),
+            };
+            self.success_values.contains(&found)
+        };
+        if accepted {
+            Ok(())
+        } else {
+            Err(format!("{expr} is {found}"))
+        }
+    }
+
+    fn path(&self) -> Result<Option<JsonPath>, String> {
+        self.success_jsonpath
+            .as_deref()
+            .map(|expr| {
+                JsonPath::parse(expr).map_err(|e| format!("invalid --success-jsonpath: {e}"))
+            })
+            .transpose()
+    }
+
+    fn expr(&self) -> Result<Option<Expr>, String> {
+        self.success_expr
+            .as_deref()
+            .map(|text| jq::parse(text).map_err(|e| format!("invalid --success-expr: {e}")))
+            .transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(path: &str, values: &[&str]) -> SuccessRule {
+        SuccessRule {
+            success_jsonpath: Some(path.to_string()),
+            success_values: values.iter().map(|v| v.to_string()).collect(),
+            success_expr: None,
+        }
+    }
+
+    fn expr(text: &str) -> SuccessRule {
+        SuccessRule {
+            success_expr: Some(text.to_string()),
+            ..SuccessRule::default()
+        }
+    }
+
+    #[test]
+    fn body_values_decide_success() {
+        let rule = rule("$.status", &["ok", "accepted"]);
+        assert_eq!(rule.check(br#"{"status": "accepted"}"#), Ok(()));
+        assert_eq!(
+            rule.check(br#"{"status": "error"}"#),
+            Err(r#"$.status is "error""#.to_string())
+        );
+        assert!(rule.check(br#"{"result": "ok"}"#).is_err());
+        assert!(rule.check(b"<html>").is_err());
+    }
+
+    #[test]
+    fn non_string_values_compare_by_their_json_text() {
+        assert_eq!(rule("$.ok", &["true"]).check(br#"{"ok": true}"#), Ok(()));
+        assert_eq!(
+            rule("$.data[0].code", &["0"]).check(br#"{"data": [{"code": 0}]}"#),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn without_values_any_truthy_match_succeeds() {
+        let rule = rule("$.id", &[]);
+        assert_eq!(rule.check(br#"{"id": "evt_1"}"#), Ok(()));
+        assert!(rule.check(br#"{"id": null}"#).is_err());
+        assert!(rule.check(br#"{}"#).is_err());
+    }
+
+    #[test]
+    fn disabled_and_invalid_rules() {
+        assert_eq!(SuccessRule::default().check(b"not json"), Ok(()));
+        assert!(rule("status[", &[]).validate().is_err());
+        assert!(expr(".status = 1").validate().is_err());
+        let both = SuccessRule {
+            success_expr: Some(".ok".to_string()),
+            ..rule("$.ok", &[])
+        };
+        assert!(both.validate().is_err());
+    }
+
+    #[test]
+    fn expressions_must_come_out_truthy() {
+        let rule = expr(r#".status == "ok" and .errors[0] == null"#);
+        assert_eq!(rule.validate(), Ok(()));
+        assert_eq!(rule.check(br#"{"status": "ok", "errors": []}"#), Ok(()));
+        assert_eq!(
+            rule.check(br#"{"status": "ok", "errors": ["quota"]}"#),
+            Err(r#".status == "ok" and .errors[0] == null is false"#.to_string())
+        );
+        assert_eq!(
+            expr(".id").check(br#"{"id": null}"#),
+            Err(".id is null".to_string())
+        );
+        assert!(rule.check(b"<html>").is_err());
+    }
+}
@@ -0,0 +1,207 @@
+//! Rendering request URLs and bodies from the message.
+//!
+//! URLs may contain `{{field}}` placeholders, filled from the payload
+//! field at that dotted path (a leading `payload.` is optional), so
+//! generic domain events can drive REST calls:
+//!
+//! ```text
+//! --url-template 'https://api.example.com/users/{{payload.user_id}}/events'
+//! ```
+//!
+//! `{{type}}` and `{{id}}` are the message type and ID; a payload field
+//! with one of those names is reached as `{{payload.type}}`. Values are
+//! percent-encoded in URLs, so they cannot add path segments or query
+//! parameters.
+//!
+//! A body template is JSON whose strings may hold placeholders. A string
+//! that is nothing but one placeholder is replaced by the field's value,
+//! keeping its type; placeholders within other text are interpolated:
+//!
+//! ```json
+//! {"user": "{{user_id}}", "tags": "{{payload.tags}}", "note": "{{type}} at {{at}}"}
+//! ```
+//!
+//! A placeholder the message lacks fails it, as the request it describes
+//! cannot be made.
+
+use primitive_common::key;
+use serde_json::{Map, Value};
+use std::borrow::Cow;
+
+/// What placeholders are filled from.
+pub struct Vars<'a> {
+    pub message_type: &'a str,
+    pub message_id: &'a str,
+    pub payload: &'a Value,
+}
+
+impl Vars<'_> {
+    fn get(&self, path: &str) -> Result<Cow<'_, Value>, String> {
+        match path {
+            "type" => Ok(Cow::Owned(Value::from(self.message_type))),
+            "id" => Ok(Cow::Owned(Value::from(self.message_id))),
+            path => key::lookup(self.payload, path)
+                .map(Cow::Borrowed)
+                .ok_or_else(|| format!("payload has no '{path}'")),
+        }
+    }
+}
+
+/// A value as text: strings verbatim, anything else as JSON.
+fn text(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+/// Replace each `{{path}}` in `template` with `fill(path)`.
+fn expand(
+    template: &str,
+    mut fill: impl FnMut(&str) -> Result<String, String>,
+) -> Result<String, String> {
+    let mut out = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        out.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        let end = after
+            .find("}}")
+            .ok_or_else(|| format!("unclosed '{{{{' in template '{template}'"))?;
+        let path = after[..end].trim();
+        if path.is_empty() {
+            return Err(format!("empty placeholder in template '{template}'"));
+        }
+        out.push_str(&fill(path)?);
+        rest = &after[end + 2..];
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+/// `value` percent-encoded for any part of a URL.
+fn encode(value: &str) -> String {
+    let mut encoded = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                encoded.push(char::from(byte));
+            }
+            _ => encoded.push_str(&format!("%{byte:02X}")),
+        }
+    }
+    encoded
+}
+
+/// Render a URL template.
+pub fn url(template: &str, vars: &Vars<'_>) -> Result<String, String> {
+    expand(template, |path| Ok(encode(&text(&*vars.get(path)?))))
+}
+
+/// The single placeholder a string consists of, if that is all it is.
+fn whole(s: &str) -> Option<&str> {
+    let path = s.strip_prefix("{{")?.strip_suffix("}}")?;
+    (!path.contains("{{") && !path.contains("}}")).then(|| path.trim())
+}
+
+/// Render a body template.
+pub fn body(template: &Value, vars: &Vars<'_>) -> Result<Value, String> {
+    Ok(match template {
+        Value::String(s) => match whole(s) {
+            Some(path) if !path.is_empty() => vars.get(path)?.into_owned(),
+            _ => Value::String(expand(s, |path| Ok(text(&*vars.get(path)?)))?),
+        },
+        Value::Array(items) => Value::Array(
+            items
+                .iter()
+                .map(|item| body(item, vars))
+                .collect::<Result<_, _>>()?,
+        ),
+        Value::Object(fields) => Value::Object(
+            fields
+                .iter()
+                .map(|(name, value)| Ok((name.clone(), body(value, vars)?)))
+                .collect::<Result<Map<_, _>, String>>()?,
+        ),
+        other => other.clone(),
+    })
+}
+
+/// A URL template with every placeholder filled, so it can be checked
+/// as a URL before any message arrives.
+pub fn sample_url(template: &str) -> Result<String, String> {
+    expand(template, |_| Ok("x".to_string()))
+}
+
+/// Check the placeholders of a body template.
+pub fn check_body(template: &Value) -> Result<(), String> {
+    match template {
+        Value::String(s) => expand(s, |_| Ok(String::new())).map(|_| ()),
+        Value::Array(items) => items.iter().try_for_each(check_body),
+        Value::Object(fields) => fields.values().try_for_each(check_body),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn urls_and_bodies_are_filled_from_the_message() {
+        let payload = json!({"user_id": "a/b c", "count": 3, "tags": ["x", "y"], "type": "vip"});
+        let vars = Vars {
+            message_type: "user.updated",
+            message_id: "msg-1",
+            payload: &payload,
+        };
+        assert_eq!(
+            url(
+                "https://api.example.com/users/{{payload.user_id}}?n={{ count }}&t={{type}}",
+                &vars
+            ),
+            Ok("https://api.example.com/users/a%2Fb%20c?n=3&t=user.updated".to_string())
+        );
+
+        let template = json!({
+            "user": "{{user_id}}",
+            "tags": "{{payload.tags}}",
+            "kind": "{{payload.type}}",
+            "note": "{{type}} #{{id}}: {{count}} items",
+            "fixed": [1, "{{count}}", {"nested": "{{payload.tags.1}}"}],
+        });
+        assert_eq!(
+            body(&template, &vars),
+            Ok(json!({
+                "user": "a/b c",
+                "tags": ["x", "y"],
+                "kind": "vip",
+                "note": "user.updated #msg-1: 3 items",
+                "fixed": [1, 3, {"nested": "y"}],
+            }))
+        );
+    }
+
+    #[test]
+    fn missing_fields_and_bad_templates_are_errors() {
+        let payload = json!({"user_id": 7});
+        let vars = Vars {
+            message_type: "user.updated",
+            message_id: "msg-1",
+            payload: &payload,
+        };
+        assert_eq!(
+            url("https://api/{{account}}", &vars),
+            Err("payload has no 'account'".to_string())
+        );
+        assert!(body(&json!({"a": "x {{b}}"}), &vars).is_err());
+        assert!(sample_url("https://api/{{user_id").is_err());
+        assert_eq!(
+            sample_url("https://api/{{user_id}}/x"),
+            Ok("https://api/x/x".to_string())
+        );
+        assert!(check_body(&json!({"a": ["{{}}"]})).is_err());
+        assert_eq!(check_body(&json!({"a": ["{{b}}", 1]})), Ok(()));
+    }
+}
@@ -0,0 +1,256 @@
+//! Request body decompression.
+//!
+//! Bodies sent with `Content-Encoding: gzip`, `deflate` or `br` (or a
+//! comma-separated chain of them) are decompressed before they are
+//! published, so consumers always see the plain body. A body beyond
+//! `--max-decompressed-bytes`, on the wire or once decompressed, is refused
+//! with `413`, which defuses compression bombs; unknown encodings get `415`
+//! and corrupt data `400`.
+//!
+//! Providers differ in what they sign: `--signature-target raw` (the
+//! default) checks `X-Signature` against the bytes on the wire before
+//! anything is decompressed, `decompressed` against the decoded body as it
+//! streams out of the decoder, before it is used for anything else.
+
+use axum::http::StatusCode;
+use clap::{Args, ValueEnum};
+use flate2::read::{DeflateDecoder, GzDecoder, ZlibDecoder};
+use std::io::{self, BufRead, BufReader, Read};
+
+/// Default cap on a decompressed body (10 MiB).
+const DEFAULT_MAX_DECOMPRESSED: &str = "10485760";
+
+/// Which bytes the HMAC signature covers.
+#[derive(ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SignatureTarget {
+    /// The body as received.
+    #[default]
+    Raw,
+    /// The body after decompression.
+    Decompressed,
+}
+
+/// CLI flags controlling decompression.
+#[derive(Args, Debug, Clone)]
+pub struct DecodeArgs {
+    /// Largest body accepted, in bytes, as received or decompressed; larger ones get 413.
+    #[arg(long, env = "HTTP_SOURCE_MAX_DECOMPRESSED_BYTES", default_value = DEFAULT_MAX_DECOMPRESSED)]
+    pub max_decompressed_bytes: usize,
+
+    /// Whether X-Signature covers the raw (possibly compressed) body or the decompressed one.
+    #[arg(
+        long,
+        env = "HTTP_SOURCE_SIGNATURE_TARGET",
+        value_enum,
+        default_value_t = SignatureTarget::Raw
+    )]
+    pub signature_target: SignatureTarget,
+}
+
+/// Why a body could not be decoded.
+#[derive(Debug, PartialEq, Eq)]
+pub enum DecodeError {
+    Unsupported(String),
+    TooLarge(usize),
+    Corrupt(String),
+}
+
+impl DecodeError {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            Self::Unsupported(_) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            Self::TooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            Self::Corrupt(_) => StatusCode::BAD_REQUEST,
+        }
+    }
+
+    pub fn message(&self) -> String {
+        match self {
+            Self::Unsupported(encoding) => format!("Unsupported Content-Encoding '{encoding}'"),
+            Self::TooLarge(limit) => format!("Body exceeds {limit} bytes"),
+            Self::Corrupt(e) => format!("Invalid compressed body: {e}"),
+        }
+    }
+}
+
+/// Decode `body` according to a `Content-Encoding` header value. Returns
+/// `None` when there is nothing to undo.
+pub fn decode(
+    content_encoding: Option<&str>,
+    body: &[u8],
+    limit: usize,
+) -> Result<Option<Vec<u8>>, DecodeError> {
+    decode_with(content_encoding, body, limit, &mut |_| {})
+}
+
+/// [`decode`], passing each decoded chunk to `inspect` as it comes out of
+/// the decoders, which run as one stream with no intermediate buffers.
+pub fn decode_with(
+    content_encoding: Option<&str>,
+    body: &[u8],
+    limit: usize,
+    inspect: &mut dyn FnMut(&[u8]),
+) -> Result<Option<Vec<u8>>, DecodeError> {
+    let encodings: Vec<String> = content_encoding
+        .unwrap_or("")
+        .split(',')
+        .map(|e| e.trim().to_ascii_lowercase())
+        .filter(|e| !e.is_empty() && e != "identity")
+        .collect();
+    if encodings.is_empty() {
+        return Ok(None);
+    }
+    if let Some(other) = encodings
+        .iter()
+        .find(|e| !matches!(e.as_str(), "gzip" | "x-gzip" | "deflate" | "br"))
+    {
+        return Err(DecodeError::Unsupported(other.clone()));
+    }
+
+    // Encodings are listed in the order they were applied
+    let mut reader: Box<dyn Read + '_> = Box::new(body);
+    for encoding in encodings.iter().rev() {
+        reader = match encoding.as_str() {
+            "deflate" => inflate(reader)?,
+            "br" => Box::new(brotli_decompressor::Decompressor::new(reader, 4096)),
+            _ => Box::new(GzDecoder::new(reader)),
+        };
+    }
+    read_limited(reader, limit, inspect).map(Some)
+}
+
+/// `deflate` is meant to be zlib-wrapped, but some clients send raw
+/// deflate; a zlib header tells the two apart.
+fn inflate<'a>(reader: Box<dyn Read + 'a>) -> Result<Box<dyn Read + 'a>, DecodeError> {
+    let mut reader = BufReader::new(reader);
+    let head = reader.fill_buf().map_err(corrupt)?;
+    let zlib =
+        head.len() >= 2 && head[0] & 0x0f == 8 && u16::from_be_bytes([head[0], head[1]]) % 31 == 0;
+    Ok(if zlib {
+        Box::new(ZlibDecoder::new(reader))
+    } else {
+        Box::new(DeflateDecoder::new(reader))
+    })
+}
+
+fn corrupt(e: io::Error) -> DecodeError {
+    DecodeError::Corrupt(e.to_string())
+}
+
+/// Read all of `reader`, handing each chunk to `inspect` and failing once
+/// more than `limit` bytes come out.
+fn read_limited(
+    reader: impl Read,
+    limit: usize,
+    inspect: &mut dyn FnMut(&[u8]),
+) -> Result<Vec<u8>, DecodeError> {
+    let mut reader = reader.take(limit as u64 + 1);
+    let mut out = Vec::new();
+    let mut chunk = [0; 8192];
+    loop {
+        let n = match reader.read(&mut chunk) {
+            Ok(0) => return Ok(out),
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(corrupt(e)),
+        };
+        if out.len() + n > limit {
+            return Err(DecodeError::TooLarge(limit));
+        }
+        inspect(&chunk[..n]);
+        out.extend_from_slice(&chunk[..n]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::{Compression, write::DeflateEncoder, write::GzEncoder, write::ZlibEncoder};
+    use std::io::Write;
+
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder
+            .write_all(data)
+            .unwrap_or_else(|e| panic!("gzip: {e}"));
+        encoder.finish().unwrap_or_else(|e| panic!("gzip: {e}"))
+    }
+
+    fn zlib(data: &[u8]) -> Vec<u8> {
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder
+            .write_all(data)
+            .unwrap_or_else(|e| panic!("zlib: {e}"));
+        encoder.finish().unwrap_or_else(|e| panic!("zlib: {e}"))
+    }
+
+    fn raw_deflate(data: &[u8]) -> Vec<u8> {
+        let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+        encoder
+            .write_all(data)
+            .unwrap_or_else(|e| panic!("deflate: {e}"));
+        encoder.finish().unwrap_or_else(|e| panic!("deflate: {e}"))
+    }
+
+    #[test]
+    fn compressed_bodies_are_decoded() {
+        let body = br#"{"event": "push"}"#;
+        assert_eq!(
+            decode(Some("gzip"), &gzip(body), 1024),
+            Ok(Some(body.to_vec()))
+        );
+        assert_eq!(
+            decode(Some("deflate"), &zlib(body), 1024),
+            Ok(Some(body.to_vec()))
+        );
+        assert_eq!(
+            decode(Some("deflate"), &raw_deflate(body), 1024),
+            Ok(Some(body.to_vec()))
+        );
+        // Applied gzip first, then deflate
+        assert_eq!(
+            decode(Some("gzip, deflate"), &zlib(&gzip(body)), 1024),
+            Ok(Some(body.to_vec()))
+        );
+        assert_eq!(
+            decode(Some("deflate, gzip"), &gzip(&raw_deflate(body)), 1024),
+            Ok(Some(body.to_vec()))
+        );
+        assert_eq!(decode(None, body, 1024), Ok(None));
+        assert_eq!(decode(Some("identity"), body, 1024), Ok(None));
+    }
+
+    #[test]
+    fn brotli_bodies_are_decoded() {
+        // "hello" compressed with brotli
+        let compressed = [0x0b, 0x02, 0x80, 0x68, 0x65, 0x6c, 0x6c, 0x6f, 0x03];
+        assert_eq!(
+            decode(Some("br"), &compressed, 1024),
+            Ok(Some(b"hello".to_vec()))
+        );
+    }
+
+    #[test]
+    fn bombs_unknown_encodings_and_garbage_are_refused() {
+        let bomb = gzip(&vec![0u8; 100_000]);
+        let mut inspected = 0;
+        let too_large = decode_with(Some("gzip"), &bomb, 1000, &mut |chunk| {
+            inspected += chunk.len()
+        });
+        assert_eq!(too_large, Err(DecodeError::TooLarge(1000)));
+        assert!(inspected <= 1000);
+        assert_eq!(
+            too_large.map_err(|e| e.status()),
+            Err(StatusCode::PAYLOAD_TOO_LARGE)
+        );
+
+        assert_eq!(
+            decode(Some("compress"), b"x", 1024).map_err(|e| e.status()),
+            Err(StatusCode::UNSUPPORTED_MEDIA_TYPE)
+        );
+        assert_eq!(
+            decode(Some("gzip"), b"not gzip", 1024).map_err(|e| e.status()),
+            Err(StatusCode::BAD_REQUEST)
+        );
+    }
+}
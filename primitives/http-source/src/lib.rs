@@ -0,0 +1,783 @@
+//! HTTP Source - Webhook Receiver
+//!
+//! A Source that receives HTTP POST requests and emits `http.request` events.
+//! Supports optional HMAC signature validation for webhook security.
+//!
+//! Sources are SILENT - they only produce domain messages.
+//! All lifecycle events are published by the engine.
+//!
+//! # Usage
+//!
+//! ```bash
+//! # Start with default settings (port 8080, path /)
+//! http-source
+//!
+//! # Custom port and path
+//! http-source --port 3000 --path /webhook
+//!
+//! # With HMAC signature validation
+//! http-source --secret my-secret-key
+//! ```
+//!
+//! Emitted types can be renamed with `--emit-type-map` or built per request
+//! with `--emit-type-template`, which may use `{source}`, `{method}`,
+//! `{path}`, and `{path_segment}` (the last non-empty path segment).
+//! A spooled request is still answered 202; one that can be neither
+//! published nor spooled gets 503, so the sender retries it.
+//!
+//! `--require-timestamp-header`, `--nonce-header` and
+//! `--reject-replayed-signatures` stop captured requests from being replayed
+//! (see [`replay`]).
+//!
+//! Bodies sent with `Content-Encoding: gzip`, `deflate` or `br` are
+//! decompressed, up to `--max-decompressed-bytes`, before they are published
+//! (see [`decode`]). With `--secret`, nothing is decompressed for a request
+//! whose signature has not been checked.
+//!
+//! `--responses` configures what each route answers, for providers that
+//! expect a particular status, content type or body, such as an echoed
+//! verification challenge (see [`responses`]).
+//!
+//! With `--expose-pull-path`, http-source also works in the other direction:
+//! it buffers the bus topics given by `--pull-topics` and lets authenticated
+//! clients long-poll them in batches, acknowledging by cursor (see [`pull`]).
+//!
+//! On SIGTERM the listener closes immediately and requests already being
+//! handled get `--drain-timeout` milliseconds to finish publishing.
+
+pub mod decode;
+pub mod pull;
+pub mod replay;
+pub mod responses;
+
+use axum::{
+    Router,
+    body::Bytes,
+    extract::{Query, State},
+    http::{HeaderMap, Method, StatusCode, Uri, header},
+    response::IntoResponse,
+    routing::any,
+};
+use clap::Parser;
+use decode::{DecodeArgs, DecodeError, SignatureTarget};
+use emergent_client::{EmergentHandler, EmergentSource};
+use event_schemas::{EventPayload, HttpRequest};
+use hmac::{Hmac, Mac};
+use primitive_common::capabilities::{self, CapabilityArgs, Role, VERSION, capabilities_message};
+use primitive_common::doctor::Report;
+use primitive_common::source::{Connection, Outlet, SourceArgs};
+use pull::{PullArgs, PullBuffer};
+use replay::{REPLAY_EVENT_TYPE, ReplayArgs, ReplayGuard};
+use responses::{RequestView, ResponseArgs, RouteResponse};
+use sha2::Sha256;
+use std::{collections::HashMap, future::IntoFuture, net::SocketAddr, sync::Arc};
+use tokio::{
+    signal::unix::{SignalKind, signal},
+    sync::Notify,
+};
+
+/// HTTP webhook receiver that emits http.request events.
+#[derive(Parser, Debug, Clone)]
+#[command(name = "http-source", version = VERSION)]
+#[command(about = "Receives HTTP webhooks and emits events")]
+struct Args {
+    /// Port to listen on.
+    #[arg(short, long, env = "HTTP_SOURCE_PORT", default_value = "8080")]
+    port: u16,
+
+    /// Host to bind to.
+    #[arg(long, env = "HTTP_SOURCE_HOST", default_value = "0.0.0.0")]
+    host: String,
+
+    /// Path to accept requests on.
+    #[arg(long, env = "HTTP_SOURCE_PATH", default_value = "/")]
+    path: String,
+
+    /// Optional HMAC secret for signature validation.
+    /// If provided, requests must include X-Signature header with HMAC-SHA256.
+    #[arg(long, env = "HTTP_SOURCE_SECRET")]
+    secret: Option<String>,
+
+    #[command(flatten)]
+    replay: ReplayArgs,
+
+    #[command(flatten)]
+    decode: DecodeArgs,
+
+    #[command(flatten)]
+    responses: ResponseArgs,
+
+    #[command(flatten)]
+    pull: PullArgs,
+
+    /// Verify external dependencies and exit without connecting to the engine.
+    #[arg(long)]
+    self_test: bool,
+
+    #[command(flatten)]
+    source: SourceArgs,
+
+    #[command(flatten)]
+    capabilities: CapabilityArgs,
+}
+
+/// Variables available to `--emit-type-template`.
+const TEMPLATE_VARIABLES: &[&str] = &["source", "method", "path", "path_segment"];
+
+/// Shared application state.
+struct AppState {
+    outlet: Arc<Outlet>,
+    secret: Option<String>,
+    /// Built-in type of published requests, before `--emit-type-map` / `--emit-type-template`.
+    publish_type: String,
+    replay: ReplayGuard,
+    decode: DecodeArgs,
+    responses: Vec<RouteResponse>,
+}
+
+impl AppState {
+    fn new(
+        outlet: Arc<Outlet>,
+        args: &Args,
+        publish_type: String,
+        responses: Vec<RouteResponse>,
+    ) -> Self {
+        Self {
+            outlet,
+            secret: args.secret.clone(),
+            publish_type,
+            replay: ReplayGuard::new(&args.replay),
+            decode: args.decode.clone(),
+            responses,
+        }
+    }
+
+    /// Answer a request failing replay protection, publishing the updated
+    /// rejection counts when they are due.
+    async fn reject_replay(&self, rejection: replay::Rejection) -> axum::response::Response {
+        eprintln!("Rejected request: {}", rejection.message());
+        if let Some(report) = self.replay.report(self.outlet.name()) {
+            self.outlet.announce(report).await;
+        }
+        (rejection.status(), rejection.message().to_string()).into_response()
+    }
+}
+
+/// An HMAC-SHA256 to feed the signed body to.
+///
+/// With replay protection's timestamp header, the signed content is
+/// `<timestamp>.<body>`.
+fn signer(secret: &str, timestamp: Option<&str>) -> Option<Hmac<Sha256>> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).ok()?;
+    if let Some(timestamp) = timestamp {
+        mac.update(timestamp.as_bytes());
+        mac.update(b".");
+    }
+    Some(mac)
+}
+
+/// Whether `mac`, fed the signed content, matches an `X-Signature` value.
+fn verify(mac: Hmac<Sha256>, signature: &str) -> bool {
+    match hex::decode(signature.trim_start_matches("sha256=")) {
+        Ok(expected) => mac.verify_slice(&expected).is_ok(),
+        Err(_) => false,
+    }
+}
+
+/// Decompresses a body as [`decode::decode_with`] does.
+type Decoder =
+    fn(Option<&str>, &[u8], usize, &mut dyn FnMut(&[u8])) -> Result<Option<Vec<u8>>, DecodeError>;
+
+/// Authenticate and decompress a request body with `decode`, returning it
+/// decoded (`None` if it was not encoded) or the status and message
+/// refusing it.
+///
+/// A sender without the secret gets nothing inflated: an oversized body is
+/// refused first, and a raw signature is checked before decompression. A
+/// decompressed signature is checked on the capped stream coming out of
+/// the decoder, before the body is used for anything else.
+fn open_body(
+    secret: Option<&str>,
+    args: &DecodeArgs,
+    headers: &HeaderMap,
+    timestamp: Option<&str>,
+    body: &[u8],
+    decode: Decoder,
+) -> Result<Option<Vec<u8>>, (StatusCode, String)> {
+    let limit = args.max_decompressed_bytes;
+    let refuse = |e: DecodeError| (e.status(), e.message());
+    if body.len() > limit {
+        return Err(refuse(DecodeError::TooLarge(limit)));
+    }
+    let content_encoding = headers
+        .get(header::CONTENT_ENCODING)
+        .and_then(|h| h.to_str().ok());
+
+    let Some(secret) = secret else {
+        return decode(content_encoding, body, limit, &mut |_| {}).map_err(refuse);
+    };
+    let Some(signature) = headers.get("x-signature").and_then(|h| h.to_str().ok()) else {
+        return Err((StatusCode::UNAUTHORIZED, "Missing signature".to_string()));
+    };
+    let invalid = || (StatusCode::UNAUTHORIZED, "Invalid signature".to_string());
+    let Some(mut mac) = signer(secret, timestamp) else {
+        return Err(invalid());
+    };
+    match args.signature_target {
+        SignatureTarget::Raw => {
+            mac.update(body);
+            if !verify(mac, signature) {
+                return Err(invalid());
+            }
+            decode(content_encoding, body, limit, &mut |_| {}).map_err(refuse)
+        }
+        SignatureTarget::Decompressed => {
+            let decoded = decode(content_encoding, body, limit, &mut |chunk| {
+                mac.update(chunk)
+            })
+            .map_err(refuse)?;
+            // A body sent without encoding is signed as is
+            if decoded.is_none() {
+                mac.update(body);
+            }
+            if !verify(mac, signature) {
+                return Err(invalid());
+            }
+            Ok(decoded)
+        }
+    }
+}
+
+/// Runs `--self-test` checks and exits.
+fn self_test(args: &Args, name: &str) -> ! {
+    let mut report = Report::new(name);
+    let addr = format!("{}:{}", args.host, args.port);
+    let bind = addr
+        .parse::<SocketAddr>()
+        .map_err(|e| format!("{addr}: {e}"))
+        .and_then(|a| {
+            std::net::TcpListener::bind(a)
+                .map(|_| addr.clone())
+                .map_err(|e| format!("{addr}: {e}"))
+        });
+    report.check("bind", bind);
+    report.check("path", check_path(&args.path));
+    if let Some(file) = &args.responses.responses {
+        report.check(
+            "responses",
+            args.responses
+                .load()
+                .and_then(|routes| check_routes(args, &routes))
+                .map(|()| file.display().to_string()),
+        );
+    }
+    if let Some(pull_path) = &args.pull.expose_pull_path {
+        report.check(
+            "expose-pull-path",
+            check_path(pull_path).and_then(|p| check_pull_path(args, &p).map(|()| p)),
+        );
+    }
+    args.source.self_test(&mut report, TEMPLATE_VARIABLES);
+    report.finish()
+}
+
+/// Check that a route path is absolute.
+fn check_path(path: &str) -> Result<String, String> {
+    if path.starts_with('/') {
+        Ok(path.to_string())
+    } else {
+        Err(format!("{path} must start with '/'"))
+    }
+}
+
+/// Check that the pull API does not shadow the webhook path.
+fn check_pull_path(args: &Args, pull_path: &str) -> Result<(), String> {
+    if pull_path == args.path {
+        return Err(format!(
+            "--expose-pull-path {pull_path} is also the webhook --path"
+        ));
+    }
+    Ok(())
+}
+
+/// Check that no `--responses` route takes the pull API's path.
+fn check_routes(args: &Args, routes: &[RouteResponse]) -> Result<(), String> {
+    match &args.pull.expose_pull_path {
+        Some(pull_path) if responses::paths(routes).contains(&pull_path.as_str()) => Err(format!(
+            "--expose-pull-path {pull_path} is also a --responses route"
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Handles incoming HTTP requests.
+async fn handle_request(
+    State(state): State<Arc<AppState>>,
+    method: Method,
+    uri: Uri,
+    Query(query): Query<HashMap<String, String>>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> impl IntoResponse {
+    let route = responses::find(&state.responses, uri.path(), method.as_str());
+
+    // Handshakes publish nothing, so they are answered without authentication
+    if let Some(route) = route.filter(|r| !r.publish) {
+        return route.respond(&RequestView {
+            method: method.as_str(),
+            path: uri.path(),
+            query: &query,
+            headers: &headers,
+            body: &body,
+        });
+    }
+
+    // Reject stale requests before spending time on them
+    let timestamp = match state.replay.timestamp(&headers) {
+        Ok(timestamp) => timestamp,
+        Err(rejection) => return state.reject_replay(rejection).await,
+    };
+
+    // Check the signature (if a secret is configured) before inflating anything
+    let decoded = match open_body(
+        state.secret.as_deref(),
+        &state.decode,
+        &headers,
+        timestamp,
+        &body,
+        decode::decode_with,
+    ) {
+        Ok(decoded) => decoded,
+        Err(refusal) => return refusal.into_response(),
+    };
+    let plain = decoded.as_deref().unwrap_or(&body);
+
+    // Only authenticated requests are remembered, so forgeries cannot fill the cache
+    if let Err(rejection) = state.replay.remember(&headers) {
+        return state.reject_replay(rejection).await;
+    }
+
+    // Convert headers to HashMap; the published body is no longer encoded
+    let headers_map: HashMap<String, String> = headers
+        .iter()
+        .filter(|(k, _)| decoded.is_none() || *k != header::CONTENT_ENCODING)
+        .filter_map(|(k, v)| {
+            v.to_str()
+                .ok()
+                .map(|val| (k.as_str().to_string(), val.to_string()))
+        })
+        .collect();
+
+    // Parse body: try JSON first, fall back to string value
+    let body_value = serde_json::from_slice(plain)
+        .unwrap_or_else(|_| serde_json::Value::String(String::from_utf8_lossy(plain).to_string()));
+
+    // The answer once the request is on its way
+    let request_method = method.to_string();
+    let accepted = || match route {
+        Some(route) => route.respond(&RequestView {
+            method: &request_method,
+            path: uri.path(),
+            query: &query,
+            headers: &headers,
+            body: plain,
+        }),
+        None => (StatusCode::ACCEPTED, "").into_response(),
+    };
+
+    // Create payload
+    let payload = HttpRequest {
+        method: method.to_string(),
+        path: uri.path().to_string(),
+        headers: headers_map,
+        body: body_value,
+        remote_addr: None,
+    };
+
+    // The outlet logs and reports a request it can neither publish nor spool
+    let method = method.as_str().to_ascii_lowercase();
+    let path_segment = uri.path().rsplit('/').find(|s| !s.is_empty()).unwrap_or("");
+    let vars = [
+        ("method", method.as_str()),
+        ("path", uri.path()),
+        ("path_segment", path_segment),
+    ];
+    if state
+        .outlet
+        .publish(&state.publish_type, &vars, payload.to_payload())
+        .await
+        .is_err()
+    {
+        return (StatusCode::SERVICE_UNAVAILABLE, "Failed to publish event").into_response();
+    }
+    accepted()
+}
+
+/// Connect as a handler subscribed to the pull topics, feeding what
+/// arrives into `buffer`.
+async fn connect_pulling(
+    name: &str,
+    topics: &[&str],
+    buffer: Arc<PullBuffer>,
+) -> Result<EmergentHandler, String> {
+    let mut handler = EmergentHandler::connect(name)
+        .await
+        .map_err(|e| e.to_string())?;
+    let mut stream = handler
+        .subscribe(topics)
+        .await
+        .map_err(|e| format!("failed to subscribe to pull topics: {e}"))?;
+    tokio::spawn(async move {
+        while let Some(msg) = stream.next().await {
+            buffer.push(
+                msg.message_type.as_str(),
+                &msg.id().to_string(),
+                msg.payload().clone(),
+            );
+        }
+        eprintln!("Pull subscription closed; no further events will be buffered");
+    });
+    Ok(handler)
+}
+
+/// Run the primitive with `args` as its command line (the first item is
+/// the program name, as with `std::env::args_os`).
+pub async fn run<I, T>(args: I) -> Result<(), Box<dyn std::error::Error>>
+where
+    I: IntoIterator<Item = T>,
+    T: Into<std::ffi::OsString> + Clone,
+{
+    let args = Args::parse_from(args);
+
+    // Get the source name from environment (set by engine) or use default
+    let name = std::env::var("EMERGENT_NAME").unwrap_or_else(|_| "http-source".to_string());
+
+    if args.self_test {
+        self_test(&args, &name);
+    }
+
+    if let Err(e) =
+        args.source
+            .validate(TEMPLATE_VARIABLES)
+            .and_then(|()| match &args.pull.expose_pull_path {
+                Some(pull_path) => {
+                    check_path(pull_path).and_then(|_| check_pull_path(&args, pull_path))
+                }
+                None => Ok(()),
+            })
+    {
+        eprintln!("Error: {e}");
+        std::process::exit(1);
+    }
+    let responses = match args
+        .responses
+        .load()
+        .and_then(|routes| check_routes(&args, &routes).map(|()| routes))
+    {
+        Ok(routes) => routes,
+        Err(e) => {
+            eprintln!("Error: {e}");
+            std::process::exit(1);
+        }
+    };
+
+    // Resolve publish type from EMERGENT_PUBLISHES env var or use default
+    let publish_type = std::env::var("EMERGENT_PUBLISHES")
+        .ok()
+        .and_then(|s| s.split(',').next().map(str::to_string))
+        .filter(|s| !s.is_empty())
+        .unwrap_or_else(|| "http.request".to_string());
+
+    let mut produces = args.source.produces(&[&publish_type]);
+    if args.replay.is_enabled() {
+        produces.push(REPLAY_EVENT_TYPE.to_string());
+    }
+    let produces: Vec<&str> = produces.iter().map(String::as_str).collect();
+    let pulling = args.pull.expose_pull_path.is_some();
+    let consumes: Vec<&str> = args.pull.pull_topics.iter().map(String::as_str).collect();
+    let role = if pulling { Role::Handler } else { Role::Source };
+    let descriptor = args
+        .capabilities
+        .describe(&name, role, &consumes, &produces, &args);
+    if args.capabilities.capabilities {
+        capabilities::print(&descriptor);
+    }
+
+    // Connect to the Emergent engine (silently - lifecycle events come from engine)
+    let buffer = Arc::new(PullBuffer::new(args.pull.pull_buffer));
+    let connected = if pulling {
+        connect_pulling(&name, &consumes, Arc::clone(&buffer))
+            .await
+            .map(Connection::from)
+    } else {
+        EmergentSource::connect(&name)
+            .await
+            .map(Connection::from)
+            .map_err(|e| e.to_string())
+    };
+    let source = match connected {
+        Ok(s) => s,
+        Err(e) => {
+            eprintln!("Failed to connect to Emergent engine: {e}");
+            std::process::exit(1);
+        }
+    };
+    let outlet = Arc::new(Outlet::new(source, &name, &args.source)?);
+    if args.capabilities.announce {
+        outlet.announce(capabilities_message(&descriptor)).await;
+    }
+    tokio::spawn(Arc::clone(&outlet).drain_every(args.source.spool.retry_interval()));
+
+    // Create shared state
+    let state = Arc::new(AppState::new(
+        Arc::clone(&outlet),
+        &args,
+        publish_type,
+        responses,
+    ));
+
+    // Create router
+    let mut routes = Router::new().route(&args.path, any(handle_request));
+    for path in responses::paths(&state.responses) {
+        if path != args.path {
+            routes = routes.route(path, any(handle_request));
+        }
+    }
+    let mut app = routes.with_state(state.clone());
+    if let Some(pull) = args.pull.router(buffer) {
+        app = app.merge(pull);
+    }
+
+    // Parse socket address
+    let addr: SocketAddr = format!("{}:{}", args.host, args.port).parse()?;
+
+    // Set up SIGTERM handler for graceful shutdown
+    let mut sigterm = signal(SignalKind::terminate())?;
+
+    // Create server with graceful shutdown
+    let shutdown = Arc::new(Notify::new());
+    let server = axum::serve(
+        tokio::net::TcpListener::bind(&addr).await?,
+        app.into_make_service(),
+    )
+    .with_graceful_shutdown({
+        let shutdown = Arc::clone(&shutdown);
+        async move { shutdown.notified().await }
+    })
+    .into_future();
+    tokio::pin!(server);
+
+    // Run server with shutdown signal
+    tokio::select! {
+        result = &mut server => {
+            result?;
+        }
+        _ = sigterm.recv() => {
+            // Stop accepting connections and let in-flight requests finish
+            shutdown.notify_one();
+            args.source.drain.drain("in-flight requests", &mut server).await;
+            outlet.disconnect().await;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use emergent_testkit::{MockEngine, fixtures};
+    use flate2::{Compression, write::GzEncoder};
+    use std::io::Write;
+    use std::path::Path;
+
+    /// Connect to the engine on `socket` as the source would.
+    async fn connect(socket: &Path, args: &Args) -> Arc<AppState> {
+        let source = EmergentSource::connect_to("http-source", socket)
+            .await
+            .unwrap_or_else(|e| panic!("connect: {e}"));
+        let outlet = Outlet::new(source, "http-source", &args.source)
+            .unwrap_or_else(|e| panic!("open spool: {e}"));
+        Arc::new(AppState::new(
+            Arc::new(outlet),
+            args,
+            "http.request".to_string(),
+            Vec::new(),
+        ))
+    }
+
+    async fn post(state: &Arc<AppState>, headers: HeaderMap, body: Vec<u8>) -> StatusCode {
+        handle_request(
+            State(Arc::clone(state)),
+            Method::POST,
+            Uri::from_static("/hooks/billing"),
+            Query(HashMap::new()),
+            headers,
+            Bytes::from(body),
+        )
+        .await
+        .into_response()
+        .status()
+    }
+
+    #[tokio::test]
+    async fn requests_survive_the_engine_being_down() {
+        let dir = fixtures::TempDir::new("http-source-spool");
+        let socket = dir.path().join("engine.sock");
+        let spool = dir.path().join("spool");
+        let args = Args::parse_from([
+            "http-source",
+            "--emit-type-template",
+            "{path_segment}.webhook",
+            "--spool-dir",
+            spool.to_str().unwrap_or_default(),
+        ]);
+
+        let mut engine = MockEngine::serve(&socket);
+        let state = connect(&socket, &args).await;
+        let status = post(&state, HeaderMap::new(), br#"{"n": 1}"#.to_vec()).await;
+        assert_eq!(status, StatusCode::ACCEPTED);
+        let published = engine.expect_published("billing.webhook").await;
+        assert_eq!(published.payload()["body"]["n"], 1);
+
+        // Accepted while the engine is down, and delivered once it is back
+        engine.shut_down().await;
+        let status = post(&state, HeaderMap::new(), br#"{"n": 2}"#.to_vec()).await;
+        assert_eq!(status, StatusCode::ACCEPTED);
+        drop(state);
+        let mut engine = MockEngine::serve(&socket);
+        let state = connect(&socket, &args).await;
+        state.outlet.drain_spool().await;
+        let published = engine.expect_published("billing.webhook").await;
+        assert_eq!(published.payload()["body"]["n"], 2);
+        engine.shut_down().await;
+    }
+
+    #[tokio::test]
+    async fn undeliverable_requests_are_refused_for_redelivery() {
+        let dir = fixtures::TempDir::new("http-source-down");
+        let socket = dir.path().join("engine.sock");
+        let args = Args::parse_from(["http-source"]);
+
+        let mut engine = MockEngine::serve(&socket);
+        let state = connect(&socket, &args).await;
+        engine.shut_down().await;
+        let status = post(&state, HeaderMap::new(), b"{}".to_vec()).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    }
+
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder
+            .write_all(data)
+            .unwrap_or_else(|e| panic!("gzip: {e}"));
+        encoder.finish().unwrap_or_else(|e| panic!("gzip: {e}"))
+    }
+
+    fn sign(secret: &str, body: &[u8]) -> String {
+        let mut mac = signer(secret, None).unwrap_or_else(|| panic!("signer"));
+        mac.update(body);
+        format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+    }
+
+    fn headers(pairs: &[(&'static str, &str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.insert(*name, value.parse().unwrap_or_else(|e| panic!("{e}")));
+        }
+        headers
+    }
+
+    fn never_decode(
+        _: Option<&str>,
+        _: &[u8],
+        _: usize,
+        _: &mut dyn FnMut(&[u8]),
+    ) -> Result<Option<Vec<u8>>, DecodeError> {
+        panic!("decoded a body whose signature was not checked")
+    }
+
+    #[test]
+    fn forged_bombs_are_refused_without_being_inflated() {
+        let args = Args::parse_from(["http-source", "--secret", "s3cret"]);
+        let bomb = gzip(&vec![0; 11 << 20]);
+        let open = |headers: &HeaderMap, body: &[u8], decode: Decoder| {
+            open_body(
+                args.secret.as_deref(),
+                &args.decode,
+                headers,
+                None,
+                body,
+                decode,
+            )
+            .map_err(|(status, _)| status)
+        };
+
+        let forged = headers(&[("content-encoding", "gzip"), ("x-signature", "sha256=00")]);
+        assert_eq!(
+            open(&forged, &bomb, never_decode),
+            Err(StatusCode::UNAUTHORIZED)
+        );
+        let unsigned = headers(&[("content-encoding", "gzip")]);
+        assert_eq!(
+            open(&unsigned, &bomb, never_decode),
+            Err(StatusCode::UNAUTHORIZED)
+        );
+
+        // Signed, it is inflated, but only up to the cap
+        let signed = headers(&[
+            ("content-encoding", "gzip"),
+            ("x-signature", &sign("s3cret", &bomb)),
+        ]);
+        assert_eq!(
+            open(&signed, &bomb, decode::decode_with),
+            Err(StatusCode::PAYLOAD_TOO_LARGE)
+        );
+        let oversized = vec![b'x'; args.decode.max_decompressed_bytes + 1];
+        assert_eq!(
+            open(&HeaderMap::new(), &oversized, never_decode),
+            Err(StatusCode::PAYLOAD_TOO_LARGE)
+        );
+    }
+
+    #[test]
+    fn decompressed_signatures_cover_the_decoded_stream() {
+        let args = Args::parse_from([
+            "http-source",
+            "--secret",
+            "s3cret",
+            "--signature-target",
+            "decompressed",
+        ]);
+        let body = br#"{"event": "push"}"#;
+        let open = |headers: &HeaderMap, body: &[u8]| {
+            open_body(
+                args.secret.as_deref(),
+                &args.decode,
+                headers,
+                None,
+                body,
+                decode::decode_with,
+            )
+            .map_err(|(status, _)| status)
+        };
+
+        let signed = headers(&[
+            ("content-encoding", "gzip"),
+            ("x-signature", &sign("s3cret", body)),
+        ]);
+        assert_eq!(open(&signed, &gzip(body)), Ok(Some(body.to_vec())));
+        let plain = headers(&[("x-signature", &sign("s3cret", body))]);
+        assert_eq!(open(&plain, body), Ok(None));
+
+        let compressed = gzip(body);
+        let wire_signed = headers(&[
+            ("content-encoding", "gzip"),
+            ("x-signature", &sign("s3cret", &compressed)),
+        ]);
+        assert_eq!(
+            open(&wire_signed, &compressed),
+            Err(StatusCode::UNAUTHORIZED)
+        );
+    }
+}
@@ -0,0 +1,326 @@
+//! Pull API: external clients fetching bus events over HTTP.
+//!
+//! With `--expose-pull-path /pull`, http-source also subscribes to
+//! `--pull-topics` and buffers what arrives (up to `--pull-buffer` events;
+//! past that the oldest are dropped). Clients holding `--pull-token` fetch
+//! batches:
+//!
+//! ```text
+//! GET /pull?cursor=41&max=100&wait=20000
+//! Authorization: Bearer <token>
+//!
+//! {"cursor": 43, "dropped": 0, "events": [
+//!   {"cursor": 42, "type": "alert.fired", "id": "...", "payload": {...}},
+//!   {"cursor": 43, "type": "alert.fired", "id": "...", "payload": {...}}
+//! ]}
+//! ```
+//!
+//! Sending `cursor` acknowledges every event up to and including it, which
+//! removes them from the buffer; the response carries the next `max` events
+//! and the cursor that acknowledges them. Events stay buffered until
+//! acknowledged, so a client that fails mid-batch gets them again by
+//! repeating its last cursor. When nothing is buffered the request is held
+//! for up to `wait` milliseconds (capped by `--pull-max-wait`) until an
+//! event arrives. `dropped` counts events evicted unacknowledged.
+//!
+//! Cursors restart at 1 when http-source restarts; a cursor ahead of
+//! everything buffered is treated as a restart and reads from the oldest
+//! event.
+
+use axum::{
+    Json, Router,
+    extract::{Query, State},
+    http::{HeaderMap, StatusCode, header},
+    response::{IntoResponse, Response},
+    routing::get,
+};
+use clap::Args;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex, PoisonError},
+    time::Duration,
+};
+use subtle::ConstantTimeEq;
+use tokio::{sync::Notify, time::Instant};
+
+/// Events per batch when the client does not say.
+const DEFAULT_BATCH: usize = 100;
+
+/// Upper bound on `max`.
+const MAX_BATCH: usize = 1000;
+
+/// CLI flags for the pull API.
+#[derive(Args, Debug, Clone)]
+pub struct PullArgs {
+    /// Serve buffered bus events to external pollers on this path (e.g. /pull).
+    #[arg(long, env = "HTTP_SOURCE_PULL_PATH", requires_all = ["pull_topics", "pull_token"])]
+    pub expose_pull_path: Option<String>,
+
+    /// Message types to buffer for pull clients (comma-separated).
+    #[arg(
+        long,
+        env = "HTTP_SOURCE_PULL_TOPICS",
+        value_delimiter = ',',
+        requires = "expose_pull_path"
+    )]
+    pub pull_topics: Vec<String>,
+
+    /// Bearer token pull clients must present.
+    #[arg(long, env = "HTTP_SOURCE_PULL_TOKEN", requires = "expose_pull_path")]
+    pub pull_token: Option<String>,
+
+    /// Events kept for pull clients; the oldest are dropped beyond this.
+    #[arg(long, env = "HTTP_SOURCE_PULL_BUFFER", default_value = "10000")]
+    pub pull_buffer: usize,
+
+    /// Longest a pull request may wait for events, in milliseconds.
+    #[arg(long, env = "HTTP_SOURCE_PULL_MAX_WAIT", default_value = "30000")]
+    pub pull_max_wait: u64,
+}
+
+impl PullArgs {
+    /// Routes serving `buffer` on the pull path, if one is configured.
+    pub fn router(&self, buffer: Arc<PullBuffer>) -> Option<Router> {
+        let path = self.expose_pull_path.as_deref()?;
+        let state = Arc::new(PullState {
+            buffer,
+            token: self.pull_token.clone().unwrap_or_default(),
+            max_wait: Duration::from_millis(self.pull_max_wait),
+        });
+        Some(Router::new().route(path, get(handle)).with_state(state))
+    }
+}
+
+/// One buffered event as served to clients.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PulledEvent {
+    pub cursor: u64,
+    #[serde(rename = "type")]
+    pub message_type: String,
+    pub id: String,
+    pub payload: Value,
+}
+
+/// Response to a pull request.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct Batch {
+    /// Cursor acknowledging this batch.
+    pub cursor: u64,
+    /// Events evicted before anyone acknowledged them.
+    pub dropped: u64,
+    pub events: Vec<PulledEvent>,
+}
+
+struct Buffered {
+    events: VecDeque<PulledEvent>,
+    /// Cursor the next event gets.
+    next: u64,
+    dropped: u64,
+}
+
+/// Bounded buffer of bus events awaiting pull clients.
+pub struct PullBuffer {
+    buffered: Mutex<Buffered>,
+    arrived: Notify,
+    capacity: usize,
+}
+
+impl PullBuffer {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            buffered: Mutex::new(Buffered {
+                events: VecDeque::new(),
+                next: 1,
+                dropped: 0,
+            }),
+            arrived: Notify::new(),
+            capacity: capacity.max(1),
+        }
+    }
+
+    /// Buffer an event and wake waiting clients.
+    pub fn push(&self, message_type: &str, id: &str, payload: Value) {
+        {
+            let mut buffered = self.buffered.lock().unwrap_or_else(PoisonError::into_inner);
+            if buffered.events.len() >= self.capacity {
+                buffered.events.pop_front();
+                buffered.dropped += 1;
+            }
+            let cursor = buffered.next;
+            buffered.next += 1;
+            buffered.events.push_back(PulledEvent {
+                cursor,
+                message_type: message_type.to_string(),
+                id: id.to_string(),
+                payload,
+            });
+        }
+        self.arrived.notify_waiters();
+    }
+
+    /// Acknowledge everything up to `cursor` and return up to `max` later
+    /// events, waiting up to `wait` for one if none are buffered.
+    pub async fn fetch(&self, cursor: u64, max: usize, wait: Duration) -> Batch {
+        let deadline = Instant::now() + wait;
+        loop {
+            // Register interest before looking, so a push in between is not missed
+            let arrived = self.arrived.notified();
+            tokio::pin!(arrived);
+            arrived.as_mut().enable();
+
+            let batch = self.take(cursor, max);
+            if !batch.events.is_empty() || tokio::time::timeout_at(deadline, arrived).await.is_err()
+            {
+                return batch;
+            }
+        }
+    }
+
+    fn take(&self, cursor: u64, max: usize) -> Batch {
+        let mut buffered = self.buffered.lock().unwrap_or_else(PoisonError::into_inner);
+        // A cursor from before a restart is ahead of everything buffered
+        let after = if cursor < buffered.next { cursor } else { 0 };
+        while buffered.events.front().is_some_and(|e| e.cursor <= after) {
+            buffered.events.pop_front();
+        }
+        let events: Vec<PulledEvent> = buffered.events.iter().take(max).cloned().collect();
+        Batch {
+            cursor: events.last().map_or(after, |e| e.cursor),
+            dropped: buffered.dropped,
+            events,
+        }
+    }
+}
+
+struct PullState {
+    buffer: Arc<PullBuffer>,
+    token: String,
+    max_wait: Duration,
+}
+
+#[derive(Deserialize)]
+struct PullQuery {
+    #[serde(default)]
+    cursor: u64,
+    max: Option<usize>,
+    wait: Option<u64>,
+}
+
+/// Serves one pull request.
+async fn handle(
+    State(state): State<Arc<PullState>>,
+    headers: HeaderMap,
+    Query(query): Query<PullQuery>,
+) -> Response {
+    let token = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| h.strip_prefix("Bearer "));
+    if !token.is_some_and(|t| bool::from(t.as_bytes().ct_eq(state.token.as_bytes()))) {
+        return (StatusCode::UNAUTHORIZED, "Invalid or missing token").into_response();
+    }
+
+    let max = query.max.unwrap_or(DEFAULT_BATCH).clamp(1, MAX_BATCH);
+    let wait = Duration::from_millis(query.wait.unwrap_or(0)).min(state.max_wait);
+    Json(state.buffer.fetch(query.cursor, max, wait).await).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn buffer(capacity: usize, events: u64) -> PullBuffer {
+        let buffer = PullBuffer::new(capacity);
+        for n in 1..=events {
+            buffer.push("alert.fired", &format!("msg-{n}"), json!({"n": n}));
+        }
+        buffer
+    }
+
+    fn cursors(batch: &Batch) -> Vec<u64> {
+        batch.events.iter().map(|e| e.cursor).collect()
+    }
+
+    #[tokio::test]
+    async fn cursors_acknowledge_and_page_through_events() {
+        let buffer = buffer(10, 5);
+        let first = buffer.fetch(0, 2, Duration::ZERO).await;
+        assert_eq!((cursors(&first), first.cursor), (vec![1, 2], 2));
+
+        // Repeating a cursor redelivers; advancing it acknowledges
+        let again = buffer.fetch(0, 2, Duration::ZERO).await;
+        assert_eq!(cursors(&again), vec![1, 2]);
+        let next = buffer.fetch(2, 10, Duration::ZERO).await;
+        assert_eq!((cursors(&next), next.cursor), (vec![3, 4, 5], 5));
+        assert_eq!(next.events[0].id, "msg-3");
+
+        let done = buffer.fetch(5, 10, Duration::ZERO).await;
+        assert_eq!((cursors(&done), done.cursor), (vec![], 5));
+    }
+
+    #[tokio::test]
+    async fn full_buffers_drop_the_oldest_events() {
+        let buffer = buffer(3, 5);
+        let batch = buffer.fetch(0, 10, Duration::ZERO).await;
+        assert_eq!(cursors(&batch), vec![3, 4, 5]);
+        assert_eq!(batch.dropped, 2);
+    }
+
+    #[tokio::test]
+    async fn cursors_from_before_a_restart_read_from_the_start() {
+        let buffer = buffer(10, 2);
+        let batch = buffer.fetch(900, 10, Duration::ZERO).await;
+        assert_eq!(cursors(&batch), vec![1, 2]);
+    }
+
+    #[tokio::test]
+    async fn long_polls_wait_for_the_next_event() {
+        let buffer = Arc::new(buffer(10, 1));
+        let poll = tokio::spawn({
+            let buffer = Arc::clone(&buffer);
+            async move { buffer.fetch(1, 10, Duration::from_secs(5)).await }
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        buffer.push("alert.fired", "msg-2", json!({}));
+
+        let batch = poll.await.unwrap_or_else(|e| panic!("poll: {e}"));
+        assert_eq!(cursors(&batch), vec![2]);
+
+        let empty = buffer.fetch(2, 10, Duration::from_millis(20)).await;
+        assert!(empty.events.is_empty());
+    }
+
+    #[tokio::test]
+    async fn tokens_compare_exactly() {
+        let state = Arc::new(PullState {
+            buffer: Arc::new(buffer(10, 1)),
+            token: "s3cret".to_string(),
+            max_wait: Duration::ZERO,
+        });
+        for (authorization, status) in [
+            (Some("Bearer s3cret"), StatusCode::OK),
+            (Some("Bearer s3creT"), StatusCode::UNAUTHORIZED),
+            (Some("Bearer s3cret!"), StatusCode::UNAUTHORIZED),
+            (Some("s3cret"), StatusCode::UNAUTHORIZED),
+            (None, StatusCode::UNAUTHORIZED),
+        ] {
+            let mut headers = HeaderMap::new();
+            if let Some(value) = authorization {
+                headers.insert(
+                    header::AUTHORIZATION,
+                    value.parse().unwrap_or_else(|e| panic!("{e}")),
+                );
+            }
+            let query = PullQuery {
+                cursor: 0,
+                max: None,
+                wait: None,
+            };
+            let response = handle(State(Arc::clone(&state)), headers, Query(query)).await;
+            assert_eq!(response.status(), status, "{authorization:?}");
+        }
+    }
+}
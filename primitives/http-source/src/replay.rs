@@ -0,0 +1,338 @@
+//! Replay protection.
+//!
+//! An HMAC signature proves who sent a request but not when, so a captured
+//! request verifies just as well the second time. Two defences, usable
+//! together:
+//!
+//! - `--require-timestamp-header X-Timestamp`: requests must carry a Unix
+//!   timestamp (seconds) within `--timestamp-tolerance` seconds of the local
+//!   clock, or get `401`. With `--secret` the signature then covers
+//!   `<timestamp>.<body>`, so the timestamp cannot be swapped.
+//! - `--nonce-header X-Nonce` and/or `--reject-replayed-signatures`: a nonce
+//!   (or signature) seen within the last `--timestamp-tolerance` seconds is
+//!   rejected with `409 Conflict`. At most `--replay-cache-size` are
+//!   remembered.
+//!
+//! Rejections are counted and published as `primitive.replay` events
+//! carrying running totals, at most once every ten seconds.
+
+use axum::http::{HeaderMap, StatusCode};
+use clap::Args;
+use emergent_client::EmergentMessage;
+use event_schemas::{EventPayload, PrimitiveReplay};
+use std::{
+    collections::{HashSet, VecDeque},
+    sync::{
+        Mutex, PoisonError,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+/// Message type of replay rejection counts.
+pub const REPLAY_EVENT_TYPE: &str = PrimitiveReplay::MESSAGE_TYPE;
+
+/// Minimum gap between `primitive.replay` events.
+const REPORT_EVERY: Duration = Duration::from_secs(10);
+
+/// CLI flags for replay protection.
+#[derive(Args, Debug, Clone)]
+pub struct ReplayArgs {
+    /// Header carrying the request's Unix timestamp in seconds; requests without a fresh one are rejected.
+    #[arg(long, env = "HTTP_SOURCE_TIMESTAMP_HEADER")]
+    pub require_timestamp_header: Option<String>,
+
+    /// Seconds a timestamp may differ from the local clock; also how long nonces and signatures are remembered.
+    #[arg(long, env = "HTTP_SOURCE_TIMESTAMP_TOLERANCE", default_value = "300")]
+    pub timestamp_tolerance: u64,
+
+    /// Header carrying a unique nonce per request; repeats are rejected with 409.
+    #[arg(long, env = "HTTP_SOURCE_NONCE_HEADER")]
+    pub nonce_header: Option<String>,
+
+    /// Reject requests repeating an already seen X-Signature with 409.
+    #[arg(
+        long,
+        env = "HTTP_SOURCE_REJECT_REPLAYED_SIGNATURES",
+        requires = "secret"
+    )]
+    pub reject_replayed_signatures: bool,
+
+    /// Most nonces and signatures remembered; the oldest are forgotten beyond this.
+    #[arg(long, env = "HTTP_SOURCE_REPLAY_CACHE_SIZE", default_value = "100000")]
+    pub replay_cache_size: usize,
+}
+
+impl ReplayArgs {
+    /// Whether any replay check is configured.
+    pub fn is_enabled(&self) -> bool {
+        self.require_timestamp_header.is_some()
+            || self.nonce_header.is_some()
+            || self.reject_replayed_signatures
+    }
+}
+
+/// Why a request was turned away.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Rejection {
+    /// Missing or stale timestamp, or missing nonce.
+    Unverifiable(String),
+    /// Nonce or signature already seen.
+    Replayed(String),
+}
+
+impl Rejection {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            Self::Unverifiable(_) => StatusCode::UNAUTHORIZED,
+            Self::Replayed(_) => StatusCode::CONFLICT,
+        }
+    }
+
+    pub fn message(&self) -> &str {
+        match self {
+            Self::Unverifiable(m) | Self::Replayed(m) => m,
+        }
+    }
+}
+
+/// Recently seen nonces and signatures, oldest first.
+#[derive(Default)]
+struct Seen {
+    keys: HashSet<String>,
+    order: VecDeque<(Instant, String)>,
+}
+
+impl Seen {
+    /// Drop the oldest entries for as long as `expired` holds for them.
+    fn forget(&mut self, expired: impl Fn(Instant) -> bool) {
+        while let Some((at, _)) = self.order.front()
+            && expired(*at)
+        {
+            if let Some((_, key)) = self.order.pop_front() {
+                self.keys.remove(&key);
+            }
+        }
+    }
+}
+
+/// Replay checks plus rejection counters.
+pub struct ReplayGuard {
+    args: ReplayArgs,
+    seen: Mutex<Seen>,
+    stale: AtomicU64,
+    replayed: AtomicU64,
+    last_report: Mutex<Option<Instant>>,
+}
+
+impl ReplayGuard {
+    pub fn new(args: &ReplayArgs) -> Self {
+        Self {
+            args: args.clone(),
+            seen: Mutex::new(Seen::default()),
+            stale: AtomicU64::new(0),
+            replayed: AtomicU64::new(0),
+            last_report: Mutex::new(None),
+        }
+    }
+
+    /// Check the timestamp header against the clock. Returns the header
+    /// value, which the signature covers, or `None` if timestamps are not
+    /// required.
+    pub fn timestamp<'h>(&self, headers: &'h HeaderMap) -> Result<Option<&'h str>, Rejection> {
+        let Some(name) = &self.args.require_timestamp_header else {
+            return Ok(None);
+        };
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        let value = header(headers, name);
+        value
+            .ok_or_else(|| format!("Missing {name} header"))
+            .and_then(|v| check_timestamp(v, now, self.args.timestamp_tolerance))
+            .map(|()| value)
+            .map_err(|e| self.reject(Rejection::Unverifiable(e)))
+    }
+
+    /// Remember the request's nonce and/or signature, rejecting it if
+    /// either was seen within the tolerance window. Call only once the
+    /// request is authenticated, so forgeries cannot fill the cache.
+    pub fn remember(&self, headers: &HeaderMap) -> Result<(), Rejection> {
+        let mut keys = Vec::new();
+        if let Some(name) = &self.args.nonce_header {
+            let Some(nonce) = header(headers, name).filter(|n| !n.is_empty()) else {
+                return Err(self.reject(Rejection::Unverifiable(format!("Missing {name} header"))));
+            };
+            keys.push(format!("nonce:{nonce}"));
+        }
+        if self.args.reject_replayed_signatures
+            && let Some(signature) = header(headers, "x-signature")
+        {
+            keys.push(format!("signature:{signature}"));
+        }
+        if keys.is_empty() {
+            return Ok(());
+        }
+
+        let now = Instant::now();
+        let window = Duration::from_secs(self.args.timestamp_tolerance);
+        let mut seen = self.seen.lock().unwrap_or_else(PoisonError::into_inner);
+        seen.forget(|at| now.duration_since(at) > window);
+        if keys.iter().any(|k| seen.keys.contains(k)) {
+            drop(seen);
+            return Err(self.reject(Rejection::Replayed("Replayed request".to_string())));
+        }
+        let capacity = self.args.replay_cache_size.max(keys.len());
+        while seen.order.len() + keys.len() > capacity
+            && let Some((_, oldest)) = seen.order.pop_front()
+        {
+            seen.keys.remove(&oldest);
+        }
+        for key in keys {
+            seen.keys.insert(key.clone());
+            seen.order.push_back((now, key));
+        }
+        Ok(())
+    }
+
+    /// Count a rejection.
+    fn reject(&self, rejection: Rejection) -> Rejection {
+        let counter = match rejection {
+            Rejection::Unverifiable(_) => &self.stale,
+            Rejection::Replayed(_) => &self.replayed,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        rejection
+    }
+
+    /// A `primitive.replay` event with the running totals, unless one was
+    /// built less than ten seconds ago.
+    pub fn report(&self, primitive: &str) -> Option<EmergentMessage> {
+        let mut last = self
+            .last_report
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        if last.is_some_and(|at| at.elapsed() < REPORT_EVERY) {
+            return None;
+        }
+        *last = Some(Instant::now());
+        let payload = PrimitiveReplay {
+            primitive: primitive.to_string(),
+            stale: self.stale.load(Ordering::Relaxed),
+            replayed: self.replayed.load(Ordering::Relaxed),
+        };
+        Some(EmergentMessage::new(REPLAY_EVENT_TYPE).with_payload(payload.to_payload()))
+    }
+}
+
+fn header<'h>(headers: &'h HeaderMap, name: &str) -> Option<&'h str> {
+    headers.get(name).and_then(|v| v.to_str().ok())
+}
+
+/// Check that `value` is a Unix timestamp within `tolerance` seconds of `now`.
+fn check_timestamp(value: &str, now: u64, tolerance: u64) -> Result<(), String> {
+    let timestamp: u64 = value
+        .trim()
+        .parse()
+        .map_err(|_| format!("Invalid timestamp '{value}'"))?;
+    if timestamp.abs_diff(now) > tolerance {
+        return Err("Timestamp outside tolerance".to_string());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+
+    fn args() -> ReplayArgs {
+        ReplayArgs {
+            require_timestamp_header: Some("X-Timestamp".to_string()),
+            timestamp_tolerance: 300,
+            nonce_header: Some("X-Nonce".to_string()),
+            reject_replayed_signatures: true,
+            replay_cache_size: 100,
+        }
+    }
+
+    fn headers(pairs: &[(&'static str, &str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            let value = HeaderValue::from_str(value).unwrap_or_else(|e| panic!("header: {e}"));
+            headers.insert(*name, value);
+        }
+        headers
+    }
+
+    #[test]
+    fn timestamps_must_be_within_tolerance() {
+        assert_eq!(check_timestamp("1000", 1200, 300), Ok(()));
+        assert_eq!(check_timestamp("1500", 1200, 300), Ok(()));
+        assert!(check_timestamp("800", 1200, 300).is_err());
+        assert!(check_timestamp("soon", 1200, 300).is_err());
+
+        let guard = ReplayGuard::new(&args());
+        let unstamped = headers(&[]);
+        let rejection = guard.timestamp(&unstamped);
+        assert_eq!(
+            rejection.map_err(|r| r.status()),
+            Err(StatusCode::UNAUTHORIZED)
+        );
+    }
+
+    #[test]
+    fn repeated_nonces_and_signatures_conflict() {
+        let guard = ReplayGuard::new(&args());
+        let first = headers(&[("x-nonce", "n-1"), ("x-signature", "sha256=aa")]);
+        assert_eq!(guard.remember(&first), Ok(()));
+        assert_eq!(
+            guard.remember(&first).map_err(|r| r.status()),
+            Err(StatusCode::CONFLICT)
+        );
+
+        // A fresh nonce with a reused signature is still a replay
+        let resigned = headers(&[("x-nonce", "n-2"), ("x-signature", "sha256=aa")]);
+        assert!(guard.remember(&resigned).is_err());
+        let fresh = headers(&[("x-nonce", "n-3"), ("x-signature", "sha256=bb")]);
+        assert_eq!(guard.remember(&fresh), Ok(()));
+
+        let missing = headers(&[("x-signature", "sha256=cc")]);
+        assert_eq!(
+            guard.remember(&missing).map_err(|r| r.status()),
+            Err(StatusCode::UNAUTHORIZED)
+        );
+    }
+
+    #[test]
+    fn the_cache_forgets_its_oldest_entries_when_full() {
+        let guard = ReplayGuard::new(&ReplayArgs {
+            replay_cache_size: 2,
+            reject_replayed_signatures: false,
+            ..args()
+        });
+        for nonce in ["a", "b", "c"] {
+            assert_eq!(guard.remember(&headers(&[("x-nonce", nonce)])), Ok(()));
+        }
+        assert_eq!(guard.remember(&headers(&[("x-nonce", "a")])), Ok(()));
+        assert!(guard.remember(&headers(&[("x-nonce", "c")])).is_err());
+    }
+
+    #[test]
+    fn rejections_are_reported_with_running_totals() {
+        let guard = ReplayGuard::new(&args());
+        let _ = guard.timestamp(&headers(&[("x-timestamp", "1")]));
+        let nonce = headers(&[("x-nonce", "n-1")]);
+        let _ = guard.remember(&nonce);
+        let _ = guard.remember(&nonce);
+
+        let report = guard
+            .report("ingest")
+            .unwrap_or_else(|| panic!("no report"));
+        let counts = PrimitiveReplay::from_payload(report.payload())
+            .unwrap_or_else(|e| panic!("payload: {e}"));
+        assert_eq!((counts.stale, counts.replayed), (1, 1));
+        assert!(guard.report("ingest").is_none());
+    }
+}
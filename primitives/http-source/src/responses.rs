@@ -0,0 +1,338 @@
+//! Per-route responses (`--responses`).
+//!
+//! Some providers insist on a particular reply: Twilio wants an XML
+//! `<Response/>`, Slack and Meta want a verification challenge echoed back.
+//! The file lists routes and what to answer on them:
+//!
+//! ```json
+//! {"routes": [
+//!   {"path": "/twilio", "status": 200, "content_type": "application/xml", "body": "<Response/>"},
+//!   {"path": "/slack", "status": 200, "content_type": "text/plain", "template": "{body.challenge}"},
+//!   {"path": "/meta", "method": "GET", "status": 200, "template": "{query.hub.challenge}", "publish": false}
+//! ]}
+//! ```
+//!
+//! Every listed path is served alongside `--path`. A route either has a
+//! static `body` or a `template` rendered from the request, using
+//! `{method}`, `{path}`, `{body}` (the raw body), `{body.<field>...}` (a
+//! field of a JSON body), `{query.<name>}` and `{header.<name>}`; missing
+//! values render empty. `status` defaults to 202. A route without `method`
+//! matches any method; one with it wins for that method.
+//!
+//! Routes with `"publish": false` are handshakes: they are answered without
+//! publishing anything, and so without signature or replay checks.
+
+use axum::{
+    http::{HeaderMap, HeaderValue, StatusCode, header},
+    response::{IntoResponse, Response},
+};
+use clap::Args;
+use primitive_common::template;
+use serde::Deserialize;
+use serde_json::Value;
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// CLI flags for per-route responses.
+#[derive(Args, Debug, Clone)]
+pub struct ResponseArgs {
+    /// JSON file of routes and the status, content type and body to answer them with.
+    #[arg(long, env = "HTTP_SOURCE_RESPONSES")]
+    pub responses: Option<PathBuf>,
+}
+
+impl ResponseArgs {
+    /// Load the configured routes; empty when `--responses` is not set.
+    pub fn load(&self) -> Result<Vec<RouteResponse>, String> {
+        match &self.responses {
+            Some(path) => load(path),
+            None => Ok(Vec::new()),
+        }
+    }
+}
+
+fn default_status() -> u16 {
+    StatusCode::ACCEPTED.as_u16()
+}
+
+fn default_publish() -> bool {
+    true
+}
+
+/// How to answer requests on one route.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RouteResponse {
+    pub path: String,
+    /// Only answer this method (case-insensitive); any method when unset.
+    #[serde(default)]
+    pub method: Option<String>,
+    #[serde(default = "default_status")]
+    pub status: u16,
+    #[serde(default)]
+    pub content_type: Option<String>,
+    /// Static response body.
+    #[serde(default)]
+    pub body: Option<String>,
+    /// Response body rendered from the request.
+    #[serde(default)]
+    pub template: Option<String>,
+    /// Whether requests on this route are published.
+    #[serde(default = "default_publish")]
+    pub publish: bool,
+}
+
+/// The parts of a request a template can refer to.
+pub struct RequestView<'a> {
+    pub method: &'a str,
+    pub path: &'a str,
+    pub query: &'a HashMap<String, String>,
+    pub headers: &'a HeaderMap,
+    pub body: &'a [u8],
+}
+
+impl RouteResponse {
+    /// Build the response to `request`.
+    pub fn respond(&self, request: &RequestView<'_>) -> Response {
+        let status = StatusCode::from_u16(self.status).unwrap_or(StatusCode::ACCEPTED);
+        let body = match (&self.body, &self.template) {
+            (Some(body), _) => body.clone(),
+            (None, Some(template)) => render(template, request),
+            (None, None) => String::new(),
+        };
+        let mut response = (status, body).into_response();
+        if let Some(content_type) = self
+            .content_type
+            .as_deref()
+            .and_then(|c| HeaderValue::from_str(c).ok())
+        {
+            response
+                .headers_mut()
+                .insert(header::CONTENT_TYPE, content_type);
+        }
+        response
+    }
+
+    fn matches(&self, path: &str, method: &str) -> bool {
+        self.path == path
+            && self
+                .method
+                .as_deref()
+                .is_none_or(|m| m.eq_ignore_ascii_case(method))
+    }
+}
+
+/// The route answering `method` on `path`: a method-specific one first,
+/// then one for any method.
+pub fn find<'a>(
+    routes: &'a [RouteResponse],
+    path: &str,
+    method: &str,
+) -> Option<&'a RouteResponse> {
+    routes
+        .iter()
+        .find(|r| r.method.is_some() && r.matches(path, method))
+        .or_else(|| {
+            routes
+                .iter()
+                .find(|r| r.method.is_none() && r.matches(path, method))
+        })
+}
+
+/// Distinct paths the routes are served on.
+pub fn paths(routes: &[RouteResponse]) -> Vec<&str> {
+    let mut seen = HashSet::new();
+    routes
+        .iter()
+        .map(|r| r.path.as_str())
+        .filter(|p| seen.insert(*p))
+        .collect()
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ResponsesFile {
+    routes: Vec<RouteResponse>,
+}
+
+/// Read and validate a `--responses` file.
+pub fn load(path: &Path) -> Result<Vec<RouteResponse>, String> {
+    let text =
+        fs::read_to_string(path).map_err(|e| format!("cannot read {}: {e}", path.display()))?;
+    parse(&text).map_err(|e| format!("{}: {e}", path.display()))
+}
+
+/// Parse and validate the contents of a `--responses` file.
+pub fn parse(text: &str) -> Result<Vec<RouteResponse>, String> {
+    let file: ResponsesFile = serde_json::from_str(text).map_err(|e| e.to_string())?;
+    let mut seen = HashSet::new();
+    for route in &file.routes {
+        if !route.path.starts_with('/') {
+            return Err(format!("route path {} must start with '/'", route.path));
+        }
+        let method = route.method.as_deref().map(str::to_ascii_uppercase);
+        if !seen.insert((route.path.as_str(), method)) {
+            return Err(format!("duplicate route for {}", route.path));
+        }
+        if !(100..=599).contains(&route.status) {
+            return Err(format!(
+                "route {}: status {} is not a valid HTTP status",
+                route.path, route.status
+            ));
+        }
+        if route.body.is_some() && route.template.is_some() {
+            return Err(format!(
+                "route {}: set either body or template, not both",
+                route.path
+            ));
+        }
+        if let Some(content_type) = &route.content_type
+            && HeaderValue::from_str(content_type).is_err()
+        {
+            return Err(format!(
+                "route {}: invalid content_type '{content_type}'",
+                route.path
+            ));
+        }
+        if let Some(template) = &route.template {
+            validate(template).map_err(|e| format!("route {}: {e}", route.path))?;
+        }
+    }
+    Ok(file.routes)
+}
+
+/// Check that every placeholder in `template` is one `render` knows.
+fn validate(template: &str) -> Result<(), String> {
+    for name in template::placeholders(template)? {
+        let known = matches!(name, "method" | "path" | "body")
+            || ["body.", "query.", "header."]
+                .iter()
+                .any(|prefix| name.strip_prefix(prefix).is_some_and(|n| !n.is_empty()));
+        if !known {
+            return Err(format!(
+                "unknown placeholder '{{{name}}}' (available: method, path, body, body.<field>, query.<name>, header.<name>)"
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Fill in a validated template from `request`.
+fn render(template: &str, request: &RequestView<'_>) -> String {
+    template::substitute(template, |name| lookup(name, request))
+}
+
+fn lookup(name: &str, request: &RequestView<'_>) -> String {
+    match name {
+        "method" => return request.method.to_string(),
+        "path" => return request.path.to_string(),
+        "body" => return String::from_utf8_lossy(request.body).into_owned(),
+        _ => {}
+    }
+    if let Some(query) = name.strip_prefix("query.") {
+        return request.query.get(query).cloned().unwrap_or_default();
+    }
+    if let Some(header) = name.strip_prefix("header.") {
+        return request
+            .headers
+            .get(header)
+            .and_then(|h| h.to_str().ok())
+            .unwrap_or_default()
+            .to_string();
+    }
+    let Some(field) = name.strip_prefix("body.") else {
+        return String::new();
+    };
+    let Ok(body) = serde_json::from_slice::<Value>(request.body) else {
+        return String::new();
+    };
+    match field
+        .split('.')
+        .try_fold(&body, |value, key| value.get(key))
+    {
+        Some(Value::String(s)) => s.clone(),
+        Some(Value::Null) | None => String::new(),
+        Some(other) => other.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn routes() -> Vec<RouteResponse> {
+        parse(
+            r#"{"routes": [
+                {"path": "/twilio", "status": 200, "content_type": "application/xml", "body": "<Response/>"},
+                {"path": "/meta", "method": "get", "status": 200, "template": "{query.hub.challenge}", "publish": false},
+                {"path": "/meta"}
+            ]}"#,
+        )
+        .unwrap_or_else(|e| panic!("parse: {e}"))
+    }
+
+    fn view<'a>(
+        query: &'a HashMap<String, String>,
+        headers: &'a HeaderMap,
+        body: &'a [u8],
+    ) -> RequestView<'a> {
+        RequestView {
+            method: "POST",
+            path: "/hook",
+            query,
+            headers,
+            body,
+        }
+    }
+
+    #[test]
+    fn routes_match_by_path_and_method() {
+        let routes = routes();
+        let get = find(&routes, "/meta", "GET").map(|r| r.publish);
+        assert_eq!(get, Some(false));
+        let post = find(&routes, "/meta", "POST").map(|r| (r.publish, r.status));
+        assert_eq!(post, Some((true, 202)));
+        assert!(find(&routes, "/other", "POST").is_none());
+        assert_eq!(paths(&routes), ["/twilio", "/meta"]);
+
+        let response = routes[0].respond(&view(&HashMap::new(), &HeaderMap::new(), b""));
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers().get(header::CONTENT_TYPE),
+            Some(&HeaderValue::from_static("application/xml"))
+        );
+    }
+
+    #[test]
+    fn templates_render_from_the_request() {
+        let query = HashMap::from([("hub.challenge".to_string(), "1158201444".to_string())]);
+        let mut headers = HeaderMap::new();
+        headers.insert("x-request-id", HeaderValue::from_static("abc"));
+        let body = br#"{"challenge": "3eZbrw1a", "event": {"n": 7}}"#;
+        let request = view(&query, &headers, body);
+
+        assert_eq!(render("{query.hub.challenge}", &request), "1158201444");
+        assert_eq!(render("{body.challenge}", &request), "3eZbrw1a");
+        assert_eq!(
+            render(
+                "{method} {path} {header.x-request-id} n={body.event.n}",
+                &request
+            ),
+            "POST /hook abc n=7"
+        );
+        assert_eq!(render("[{body.missing}{query.none}]", &request), "[]");
+    }
+
+    #[test]
+    fn invalid_responses_files_are_rejected() {
+        assert!(parse(r#"{"routes": [{"path": "hook"}]}"#).is_err());
+        assert!(parse(r#"{"routes": [{"path": "/a"}, {"path": "/a"}]}"#).is_err());
+        assert!(parse(r#"{"routes": [{"path": "/a", "status": 42}]}"#).is_err());
+        assert!(parse(r#"{"routes": [{"path": "/a", "body": "x", "template": "y"}]}"#).is_err());
+        assert!(parse(r#"{"routes": [{"path": "/a", "template": "{cookie}"}]}"#).is_err());
+        assert!(parse(r#"{"routes": [{"path": "/a", "delay": 5}]}"#).is_err());
+    }
+}
@@ -0,0 +1,376 @@
+//! Shared IRC connection for `irc-source` and `irc-sink`.
+//!
+//! [`connect`] opens a TCP or TLS connection, registers (server password,
+//! SASL PLAIN, and `nick_`, `nick__`, ... while the nick is taken), joins
+//! the configured channels and returns a [`Connection`] that answers the
+//! server's keepalives by itself. Both primitives reconnect after a lost
+//! connection, waiting [`Config::reconnect_delay`] between attempts.
+
+pub mod message;
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use message::Message;
+use primitive_common::doctor::Report;
+use std::net::ToSocketAddrs;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{
+    AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader, ReadHalf, WriteHalf,
+};
+use tokio::net::TcpStream;
+use tokio::sync::Mutex;
+use tokio::time::timeout;
+use tokio_rustls::TlsConnector;
+use tokio_rustls::rustls::pki_types::ServerName;
+use tokio_rustls::rustls::{ClientConfig, RootCertStore, crypto};
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(30);
+const REGISTER_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Alternative nicks tried while the chosen one is taken.
+const NICK_ATTEMPTS: usize = 5;
+
+/// Where to connect and as whom.
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub server: String,
+    pub port: u16,
+    pub tls: bool,
+    pub nick: String,
+    pub username: String,
+    pub realname: String,
+    /// Server password (`PASS`).
+    pub password: Option<String>,
+    /// SASL PLAIN account and password.
+    pub sasl: Option<(String, String)>,
+    pub channels: Vec<String>,
+    /// Milliseconds before the first reconnect; doubles on each failure.
+    pub reconnect_delay: u64,
+    /// Upper bound for the reconnect delay in milliseconds.
+    pub max_reconnect_delay: u64,
+}
+
+impl Config {
+    fn address(&self) -> String {
+        format!("{}:{}", self.server, self.port)
+    }
+
+    /// The delay before reconnect number `attempt` (0-based).
+    pub fn reconnect_delay(&self, attempt: u32) -> Duration {
+        let factor = 1u64.checked_shl(attempt).unwrap_or(u64::MAX);
+        Duration::from_millis(
+            self.reconnect_delay
+                .saturating_mul(factor)
+                .min(self.max_reconnect_delay),
+        )
+    }
+
+    /// Add a `--self-test` check that the server accepts connections.
+    pub fn self_test(&self, report: &mut Report) {
+        let address = self.address();
+        let reachable = address
+            .to_socket_addrs()
+            .map_err(|e| format!("{address}: {e}"))
+            .and_then(|mut addrs| addrs.next().ok_or_else(|| format!("{address}: no address")))
+            .and_then(|addr| {
+                std::net::TcpStream::connect_timeout(&addr, CONNECT_TIMEOUT)
+                    .map(|_| format!("{address} accepts connections"))
+                    .map_err(|e| format!("{address}: {e}"))
+            });
+        report.check("server", reachable);
+    }
+}
+
+trait Stream: AsyncRead + AsyncWrite + Unpin + Send + Sync {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send + Sync> Stream for T {}
+
+/// Writes to a connection; clones share it.
+#[derive(Clone)]
+pub struct Sender {
+    writer: Arc<Mutex<WriteHalf<Box<dyn Stream>>>>,
+}
+
+impl Sender {
+    pub async fn send(&self, message: &Message) -> Result<(), String> {
+        let line = format!("{message}\r\n");
+        let mut writer = self.writer.lock().await;
+        writer
+            .write_all(line.as_bytes())
+            .await
+            .map_err(|e| format!("write: {e}"))?;
+        writer.flush().await.map_err(|e| format!("write: {e}"))
+    }
+}
+
+/// A registered connection.
+pub struct Connection {
+    reader: BufReader<ReadHalf<Box<dyn Stream>>>,
+    sender: Sender,
+    nick: String,
+}
+
+impl Connection {
+    /// Our current nick.
+    pub fn nick(&self) -> &str {
+        &self.nick
+    }
+
+    /// Whether `nick` is ours.
+    pub fn is_me(&self, nick: &str) -> bool {
+        nick.eq_ignore_ascii_case(&self.nick)
+    }
+
+    pub fn sender(&self) -> Sender {
+        self.sender.clone()
+    }
+
+    pub async fn send(&self, message: &Message) -> Result<(), String> {
+        self.sender.send(message).await
+    }
+
+    /// The next message from the server. `PING`s are answered rather than
+    /// returned; a closed connection is an `Err`.
+    pub async fn recv(&mut self) -> Result<Message, String> {
+        let mut line = Vec::new();
+        loop {
+            line.clear();
+            let read = self
+                .reader
+                .read_until(b'\n', &mut line)
+                .await
+                .map_err(|e| format!("read: {e}"))?;
+            if read == 0 {
+                return Err("connection closed".to_string());
+            }
+            // Not every client sends UTF-8
+            let Some(message) = Message::parse(&String::from_utf8_lossy(&line)) else {
+                continue;
+            };
+            match message.command.as_str() {
+                "PING" => {
+                    self.send(&Message::new("PONG", &[message.param(0)]))
+                        .await?;
+                }
+                "ERROR" => {
+                    return Err(format!(
+                        "server closed the connection: {}",
+                        message.param(0)
+                    ));
+                }
+                "NICK" => {
+                    if message.nick().is_some_and(|nick| self.is_me(nick)) {
+                        self.nick = message.param(0).to_string();
+                    }
+                    return Ok(message);
+                }
+                _ => return Ok(message),
+            }
+        }
+    }
+}
+
+async fn tls(server: &str, tcp: TcpStream) -> Result<Box<dyn Stream>, String> {
+    let roots = RootCertStore {
+        roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+    };
+    let config = ClientConfig::builder_with_provider(Arc::new(crypto::ring::default_provider()))
+        .with_safe_default_protocol_versions()
+        .map_err(|e| e.to_string())?
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    let name = ServerName::try_from(server.to_string()).map_err(|e| format!("{server}: {e}"))?;
+    let stream = TlsConnector::from(Arc::new(config))
+        .connect(name, tcp)
+        .await
+        .map_err(|e| format!("TLS handshake with {server}: {e}"))?;
+    Ok(Box::new(stream))
+}
+
+async fn register(connection: &mut Connection, config: &Config) -> Result<(), String> {
+    if config.sasl.is_some() {
+        connection
+            .send(&Message::new("CAP", &["REQ", "sasl"]))
+            .await?;
+    }
+    if let Some(password) = &config.password {
+        connection.send(&Message::new("PASS", &[password])).await?;
+    }
+    connection
+        .send(&Message::new("NICK", &[&config.nick]))
+        .await?;
+    connection
+        .send(&Message::new(
+            "USER",
+            &[&config.username, "0", "*", &config.realname],
+        ))
+        .await?;
+
+    let mut attempts = 0;
+    loop {
+        let message = connection.recv().await?;
+        let reason = message.params.last().map_or("", String::as_str);
+        match message.command.as_str() {
+            // RPL_WELCOME names the nick we got
+            "001" => {
+                connection.nick = message.param(0).to_string();
+                return Ok(());
+            }
+            // ERR_NICKNAMEINUSE, ERR_UNAVAILRESOURCE
+            "433" | "437" => {
+                attempts += 1;
+                if attempts > NICK_ATTEMPTS {
+                    return Err(format!(
+                        "nick {} and its alternatives are taken",
+                        config.nick
+                    ));
+                }
+                connection.nick = format!("{}{}", config.nick, "_".repeat(attempts));
+                let nick = connection.nick.clone();
+                connection.send(&Message::new("NICK", &[&nick])).await?;
+            }
+            "432" => return Err(format!("nick {} rejected: {reason}", config.nick)),
+            "464" => return Err(format!("server password rejected: {reason}")),
+            "465" => return Err(format!("banned: {reason}")),
+            "CAP" => match message.param(1) {
+                "ACK" => {
+                    connection
+                        .send(&Message::new("AUTHENTICATE", &["PLAIN"]))
+                        .await?;
+                }
+                "NAK" => return Err("server does not offer SASL".to_string()),
+                _ => {}
+            },
+            "AUTHENTICATE" if message.param(0) == "+" => {
+                if let Some((account, password)) = &config.sasl {
+                    let token = BASE64.encode(format!("{account}\0{account}\0{password}"));
+                    connection
+                        .send(&Message::new("AUTHENTICATE", &[&token]))
+                        .await?;
+                }
+            }
+            // RPL_SASLSUCCESS
+            "903" => connection.send(&Message::new("CAP", &["END"])).await?,
+            "902" | "904" | "905" | "906" | "908" => {
+                return Err(format!("SASL authentication failed: {reason}"));
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Connect, register and join `config.channels`.
+pub async fn connect(config: &Config) -> Result<Connection, String> {
+    let address = config.address();
+    let tcp = timeout(CONNECT_TIMEOUT, TcpStream::connect(&address))
+        .await
+        .map_err(|_| format!("{address}: timed out"))?
+        .map_err(|e| format!("{address}: {e}"))?;
+    let stream: Box<dyn Stream> = if config.tls {
+        tls(&config.server, tcp).await?
+    } else {
+        Box::new(tcp)
+    };
+    let (reader, writer) = tokio::io::split(stream);
+    let mut connection = Connection {
+        reader: BufReader::new(reader),
+        sender: Sender {
+            writer: Arc::new(Mutex::new(writer)),
+        },
+        nick: config.nick.clone(),
+    };
+    timeout(REGISTER_TIMEOUT, register(&mut connection, config))
+        .await
+        .map_err(|_| format!("{address}: registration timed out"))?
+        .map_err(|e| format!("{address}: {e}"))?;
+    if !config.channels.is_empty() {
+        connection
+            .send(&Message::new("JOIN", &[&config.channels.join(",")]))
+            .await?;
+    }
+    Ok(connection)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::AsyncBufReadExt;
+    use tokio::net::TcpListener;
+
+    #[tokio::test]
+    async fn registration_authenticates_and_falls_back_to_a_free_nick() {
+        let listener = TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap_or_else(|e| panic!("bind: {e}"));
+        let port = listener
+            .local_addr()
+            .unwrap_or_else(|e| panic!("local_addr: {e}"))
+            .port();
+        let server = tokio::spawn(async move {
+            let (socket, _) = listener
+                .accept()
+                .await
+                .unwrap_or_else(|e| panic!("accept: {e}"));
+            let (reader, mut writer) = socket.into_split();
+            let mut lines = BufReader::new(reader).lines();
+            let mut received = Vec::new();
+            while let Ok(Some(line)) = lines.next_line().await {
+                let reply = match line.as_str() {
+                    "CAP REQ sasl" => "CAP * ACK :sasl",
+                    "AUTHENTICATE PLAIN" => "AUTHENTICATE +",
+                    "NICK bot" => ":irc.test 433 * bot :Nickname is already in use",
+                    "CAP END" => ":irc.test 001 bot_ :Welcome\r\nPING :irc.test",
+                    line if line.starts_with("AUTHENTICATE ") => ":irc.test 903 * :SASL successful",
+                    _ => "",
+                };
+                received.push(line);
+                if !reply.is_empty() {
+                    let reply = format!("{reply}\r\n");
+                    let _ = writer.write_all(reply.as_bytes()).await;
+                }
+                if received.last().is_some_and(|line| line.starts_with("JOIN")) {
+                    break;
+                }
+            }
+            received
+        });
+
+        let config = Config {
+            server: "127.0.0.1".to_string(),
+            port,
+            tls: false,
+            nick: "bot".to_string(),
+            username: "emergent".to_string(),
+            realname: "Emergent".to_string(),
+            password: None,
+            sasl: Some(("bot".to_string(), "hunter2".to_string())),
+            channels: vec!["#ops".to_string(), "#dev".to_string()],
+            reconnect_delay: 1000,
+            max_reconnect_delay: 60_000,
+        };
+        let connection = connect(&config)
+            .await
+            .unwrap_or_else(|e| panic!("connect: {e}"));
+        assert_eq!(connection.nick(), "bot_");
+        assert!(connection.is_me("BOT_"));
+
+        let received = server.await.unwrap_or_else(|e| panic!("server: {e}"));
+        assert_eq!(
+            received,
+            [
+                "CAP REQ sasl",
+                "NICK bot",
+                "USER emergent 0 * Emergent",
+                "AUTHENTICATE PLAIN",
+                "NICK bot_",
+                // base64("bot\0bot\0hunter2")
+                "AUTHENTICATE Ym90AGJvdABodW50ZXIy",
+                "CAP END",
+                "JOIN #ops,#dev",
+            ]
+        );
+        assert_eq!(config.reconnect_delay(3), Duration::from_secs(8));
+        assert_eq!(config.reconnect_delay(20), Duration::from_secs(60));
+    }
+}
@@ -0,0 +1,130 @@
+//! IRC protocol lines.
+//!
+//! `[@tags] [:prefix] COMMAND [params...] [:trailing]`, as in RFC 1459
+//! with IRCv3 message tags, which are skipped.
+
+use std::fmt;
+
+/// One protocol line, without the CR LF.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Message {
+    /// `nick!user@host` or a server name.
+    pub prefix: Option<String>,
+    pub command: String,
+    pub params: Vec<String>,
+}
+
+impl Message {
+    /// A message from us; the server adds the prefix.
+    pub fn new(command: &str, params: &[&str]) -> Self {
+        Self {
+            prefix: None,
+            command: command.to_string(),
+            params: params.iter().map(|param| (*param).to_string()).collect(),
+        }
+    }
+
+    /// Parse a line; `None` for an empty one.
+    pub fn parse(line: &str) -> Option<Self> {
+        let mut rest = line.trim_end_matches(['\r', '\n']);
+        if rest.starts_with('@') {
+            rest = rest.split_once(' ').map_or("", |(_, rest)| rest);
+        }
+        rest = rest.trim_start_matches(' ');
+        let prefix = match rest.strip_prefix(':') {
+            Some(prefixed) => {
+                let (prefix, after) = prefixed.split_once(' ').unwrap_or((prefixed, ""));
+                rest = after;
+                Some(prefix.to_string())
+            }
+            None => None,
+        };
+        let (head, trailing) = match rest.split_once(" :") {
+            Some((head, trailing)) => (head, Some(trailing)),
+            None => (rest, None),
+        };
+        let mut words = head.split(' ').filter(|word| !word.is_empty());
+        let command = words.next()?.to_ascii_uppercase();
+        let mut params: Vec<String> = words.map(str::to_string).collect();
+        params.extend(trailing.map(str::to_string));
+        Some(Self {
+            prefix,
+            command,
+            params,
+        })
+    }
+
+    /// The sender's nick, from a `nick!user@host` prefix.
+    pub fn nick(&self) -> Option<&str> {
+        let prefix = self.prefix.as_deref()?;
+        Some(prefix.split_once('!').map_or(prefix, |(nick, _)| nick))
+    }
+
+    /// The sender's user and host, from a `nick!user@host` prefix.
+    pub fn user_host(&self) -> (Option<&str>, Option<&str>) {
+        let Some((_, user_host)) = self.prefix.as_deref().and_then(|p| p.split_once('!')) else {
+            return (None, None);
+        };
+        match user_host.split_once('@') {
+            Some((user, host)) => (Some(user), Some(host)),
+            None => (Some(user_host), None),
+        }
+    }
+
+    /// Parameter `index`, or `""`.
+    pub fn param(&self, index: usize) -> &str {
+        self.params.get(index).map_or("", String::as_str)
+    }
+}
+
+impl fmt::Display for Message {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(prefix) = &self.prefix {
+            write!(f, ":{prefix} ")?;
+        }
+        f.write_str(&self.command)?;
+        if let Some((last, init)) = self.params.split_last() {
+            for param in init {
+                write!(f, " {param}")?;
+            }
+            if last.is_empty() || last.contains(' ') || last.starts_with(':') {
+                write!(f, " :{last}")?;
+            } else {
+                write!(f, " {last}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Whether `target` names a channel rather than a nick.
+pub fn is_channel(target: &str) -> bool {
+    target.starts_with(['#', '&', '+', '!'])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lines_round_trip_with_prefix_and_trailing_parameter() {
+        let line =
+            "@time=2024-01-01T00:00:00Z :alice!ali@example.org PRIVMSG #ops :deploy web1 now";
+        let message = Message::parse(line).unwrap_or_else(|| panic!("no message"));
+        assert_eq!(message.nick(), Some("alice"));
+        assert_eq!(message.user_host(), (Some("ali"), Some("example.org")));
+        assert_eq!(message.command, "PRIVMSG");
+        assert_eq!(message.params, ["#ops", "deploy web1 now"]);
+        assert_eq!(
+            message.to_string(),
+            ":alice!ali@example.org PRIVMSG #ops :deploy web1 now"
+        );
+
+        let ping = Message::parse("PING irc.example.org\r\n").unwrap_or_else(|| panic!("no ping"));
+        assert_eq!(ping.prefix, None);
+        assert_eq!(ping.param(0), "irc.example.org");
+        assert_eq!(Message::new("JOIN", &["#ops"]).to_string(), "JOIN #ops");
+        assert_eq!(Message::parse(""), None);
+        assert!(is_channel("#ops") && !is_channel("alice"));
+    }
+}
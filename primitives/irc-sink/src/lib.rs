@@ -0,0 +1,499 @@
+//! IRC Sink - Send Events to IRC Channels and Nicks
+//!
+//! A Sink that stays connected to an IRC server and sends each payload as
+//! a message, notice or `/me` action to a channel or nick:
+//!
+//! ```json
+//! {"target": "#ops", "text": "deploy of web1 finished", "kind": "notice"}
+//! ```
+//!
+//! `target` (or `channel`) defaults to `--target`, `text` (or `message`,
+//! `body`) may span several lines (see [`text`]), and `kind` is `privmsg`,
+//! `notice` or `action`, defaulting to `--kind`. Channels not joined at
+//! connect time with `--channel` are joined on first use. Lines are
+//! throttled so the server doesn't disconnect the sink for flooding (see
+//! [`throttle`]).
+//!
+//! The connection uses TLS unless `--no-tls` is given and can log in with
+//! SASL PLAIN. When it drops, the sink reconnects with a doubling delay;
+//! messages arriving meanwhile fail, so the harness retries them.
+//!
+//! # Examples
+//!
+//! ```bash
+//! irc-sink -s deploy.completed --server irc.libera.chat --nick emergent-bot \
+//!   --sasl-password "$IRC_SASL_PASSWORD" --channel '#deploys' --target '#deploys'
+//! ```
+
+pub mod text;
+pub mod throttle;
+
+use clap::{Parser, ValueEnum};
+use emergent_client::EmergentMessage;
+use irc_common::message::{Message, is_channel};
+use irc_common::{Config, Sender};
+use primitive_common::capabilities::VERSION;
+use primitive_common::doctor::Report;
+use primitive_common::errors::{ErrorCategory, HandlerError};
+use primitive_common::{SinkArgs, SinkConfig, SinkContext, SinkHandler, run_sink};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::HashSet;
+use std::time::Duration;
+use throttle::Throttle;
+use tokio::sync::{mpsc, oneshot};
+
+/// IRC Sink — send events to IRC channels and nicks.
+#[derive(Parser, Debug)]
+#[command(name = "irc_sink", version = VERSION)]
+#[command(about = "Send events as IRC messages to channels or nicks")]
+struct Args {
+    /// Message types to subscribe to.
+    #[arg(short, long = "subscribe", required = true)]
+    subscribe: Vec<String>,
+
+    /// Server host name.
+    #[arg(long, env = "IRC_SINK_SERVER")]
+    server: String,
+
+    /// Server port (default: 6697, or 6667 with `--no-tls`).
+    #[arg(short, long, env = "IRC_SINK_PORT")]
+    port: Option<u16>,
+
+    /// Connect without TLS.
+    #[arg(long, env = "IRC_SINK_NO_TLS")]
+    no_tls: bool,
+
+    /// Nick to use; `_` is appended while it is taken.
+    #[arg(short, long, env = "IRC_SINK_NICK", default_value = "emergent")]
+    nick: String,
+
+    /// User name (default: the nick).
+    #[arg(long, env = "IRC_SINK_USERNAME")]
+    username: Option<String>,
+
+    /// Real name shown in WHOIS.
+    #[arg(long, env = "IRC_SINK_REALNAME", default_value = "Emergent")]
+    realname: String,
+
+    /// Server password.
+    #[arg(long, env = "IRC_SINK_SERVER_PASSWORD", hide_env_values = true)]
+    server_password: Option<String>,
+
+    /// SASL account (default: the nick).
+    #[arg(long, env = "IRC_SINK_SASL_USER")]
+    sasl_user: Option<String>,
+
+    /// SASL password; enables SASL PLAIN.
+    #[arg(long, env = "IRC_SINK_SASL_PASSWORD", hide_env_values = true)]
+    sasl_password: Option<String>,
+
+    /// Channels to join on connect; repeatable.
+    #[arg(
+        short,
+        long = "channel",
+        env = "IRC_SINK_CHANNELS",
+        value_delimiter = ','
+    )]
+    channels: Vec<String>,
+
+    /// Channel or nick for payloads that name none.
+    #[arg(short, long, env = "IRC_SINK_TARGET")]
+    target: Option<String>,
+
+    /// Kind of message for payloads that name none.
+    #[arg(
+        short,
+        long,
+        env = "IRC_SINK_KIND",
+        value_enum,
+        default_value = "privmsg"
+    )]
+    kind: Kind,
+
+    /// Lines sent back to back before throttling starts.
+    #[arg(long, env = "IRC_SINK_BURST", default_value = "5")]
+    burst: u32,
+
+    /// Milliseconds between lines once throttled.
+    #[arg(long, env = "IRC_SINK_LINE_DELAY", default_value = "1000")]
+    line_delay: u64,
+
+    /// Longest line of text in bytes; longer lines are split.
+    #[arg(long, env = "IRC_SINK_MAX_LINE_BYTES", default_value = "400")]
+    max_line_bytes: usize,
+
+    /// Milliseconds before the first reconnect; doubles on each failure.
+    #[arg(long, env = "IRC_SINK_RECONNECT_DELAY", default_value = "1000")]
+    reconnect_delay: u64,
+
+    /// Upper bound for the reconnect delay in milliseconds.
+    #[arg(long, env = "IRC_SINK_MAX_RECONNECT_DELAY", default_value = "300000")]
+    max_reconnect_delay: u64,
+
+    #[command(flatten)]
+    sink: SinkArgs,
+}
+
+impl Args {
+    fn config(&self) -> Config {
+        Config {
+            server: self.server.clone(),
+            port: self.port.unwrap_or(if self.no_tls { 6667 } else { 6697 }),
+            tls: !self.no_tls,
+            nick: self.nick.clone(),
+            username: self.username.clone().unwrap_or_else(|| self.nick.clone()),
+            realname: self.realname.clone(),
+            password: self.server_password.clone(),
+            sasl: self.sasl_password.clone().map(|password| {
+                let user = self.sasl_user.clone().unwrap_or_else(|| self.nick.clone());
+                (user, password)
+            }),
+            channels: self.channels.clone(),
+            reconnect_delay: self.reconnect_delay,
+            max_reconnect_delay: self.max_reconnect_delay,
+        }
+    }
+}
+
+/// How a line is sent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Kind {
+    /// An ordinary message.
+    Privmsg,
+    /// A notice, which bots must not answer.
+    Notice,
+    /// A `/me` action.
+    Action,
+}
+
+impl Kind {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Privmsg => "privmsg",
+            Self::Notice => "notice",
+            Self::Action => "action",
+        }
+    }
+
+    fn message(self, target: &str, line: &str) -> Message {
+        match self {
+            Self::Privmsg => Message::new("PRIVMSG", &[target, line]),
+            Self::Notice => Message::new("NOTICE", &[target, line]),
+            Self::Action => Message::new("PRIVMSG", &[target, &format!("\u{1}ACTION {line}\u{1}")]),
+        }
+    }
+}
+
+/// A message payload.
+#[derive(Debug, Deserialize)]
+struct Outgoing {
+    #[serde(alias = "channel")]
+    target: Option<String>,
+    #[serde(alias = "message", alias = "body")]
+    text: String,
+    kind: Option<Kind>,
+}
+
+/// Lines for the connection task to send, and where to report the outcome.
+struct Request {
+    target: String,
+    lines: Vec<Message>,
+    done: oneshot::Sender<Result<(), String>>,
+}
+
+/// Send `request`, joining its channel first if need be.
+async fn send(
+    sender: &Sender,
+    throttle: &mut Throttle,
+    joined: &mut HashSet<String>,
+    request: &Request,
+) -> Result<(), String> {
+    let channel = request.target.to_lowercase();
+    if is_channel(&channel) && !joined.contains(&channel) {
+        throttle.wait().await;
+        sender
+            .send(&Message::new("JOIN", &[&request.target]))
+            .await?;
+        joined.insert(channel);
+    }
+    for line in &request.lines {
+        throttle.wait().await;
+        sender.send(line).await?;
+    }
+    Ok(())
+}
+
+/// Keep a connection up and send what arrives on `requests` over it.
+async fn connection(config: Config, mut throttle: Throttle, mut requests: mpsc::Receiver<Request>) {
+    let mut attempt = 0;
+    loop {
+        match irc_common::connect(&config).await {
+            Ok(connection) => {
+                attempt = 0;
+                eprintln!("Connected to {} as {}", config.server, connection.nick());
+                let sender = connection.sender();
+                let mut joined: HashSet<String> = config
+                    .channels
+                    .iter()
+                    .map(|channel| channel.to_lowercase())
+                    .collect();
+                // Reading answers the server's PINGs and notices a dropped connection
+                let mut reader = tokio::spawn(async move {
+                    let mut connection = connection;
+                    loop {
+                        if let Err(e) = connection.recv().await {
+                            return e;
+                        }
+                    }
+                });
+                loop {
+                    tokio::select! {
+                        lost = &mut reader => {
+                            let e = lost.unwrap_or_else(|e| e.to_string());
+                            eprintln!("Lost connection to {}: {e}", config.server);
+                            break;
+                        }
+                        request = requests.recv() => {
+                            let Some(request) = request else {
+                                let _ = sender.send(&Message::new("QUIT", &["Shutting down"])).await;
+                                reader.abort();
+                                return;
+                            };
+                            let sent = send(&sender, &mut throttle, &mut joined, &request).await;
+                            let failed = sent.is_err();
+                            let _ = request.done.send(sent);
+                            if failed {
+                                reader.abort();
+                                break;
+                            }
+                        }
+                    }
+                }
+            }
+            Err(e) => eprintln!("Failed to connect to {}: {e}", config.server),
+        }
+
+        // Fail what arrives while disconnected, so the harness retries it
+        let delay = tokio::time::sleep(config.reconnect_delay(attempt));
+        tokio::pin!(delay);
+        attempt = attempt.saturating_add(1);
+        loop {
+            tokio::select! {
+                _ = &mut delay => break,
+                request = requests.recv() => match request {
+                    Some(request) => {
+                        let _ = request.done.send(Err(format!("not connected to {}", config.server)));
+                    }
+                    None => return,
+                },
+            }
+        }
+    }
+}
+
+/// Hands each payload to the connection task.
+struct IrcSink {
+    config: Config,
+    target: Option<String>,
+    kind: Kind,
+    max_line_bytes: usize,
+    requests: mpsc::Sender<Request>,
+}
+
+impl SinkHandler for IrcSink {
+    async fn handle(
+        &self,
+        _msg: &EmergentMessage,
+        ctx: &SinkContext<'_>,
+    ) -> Result<(), HandlerError> {
+        let outgoing: Outgoing = ctx.decode()?;
+        let target = outgoing
+            .target
+            .or_else(|| self.target.clone())
+            .ok_or_else(|| {
+                HandlerError::new(
+                    ErrorCategory::Parse,
+                    "payload names no target and no --target",
+                )
+            })?;
+        if target.is_empty() || target.contains([' ', ',', '\r', '\n']) {
+            return Err(HandlerError::new(
+                ErrorCategory::Parse,
+                format!("invalid target '{target}'"),
+            ));
+        }
+        let kind = outgoing.kind.unwrap_or(self.kind);
+        // Room for the CTCP framing of an action
+        let max = match kind {
+            Kind::Action => self.max_line_bytes.saturating_sub(9),
+            _ => self.max_line_bytes,
+        };
+        let lines = text::lines(&outgoing.text, max);
+        if lines.is_empty() {
+            return Err(HandlerError::new(
+                ErrorCategory::Parse,
+                "payload has no text",
+            ));
+        }
+
+        if ctx.is_dry_run() {
+            let detail = json!({
+                "target": target,
+                "kind": kind.as_str(),
+                "lines": lines,
+            });
+            ctx.would_have("send", detail).await;
+            return Ok(());
+        }
+
+        let (done, outcome) = oneshot::channel();
+        let request = Request {
+            lines: lines
+                .iter()
+                .map(|line| kind.message(&target, line))
+                .collect(),
+            target,
+            done,
+        };
+        let stopped = || HandlerError::new(ErrorCategory::Internal, "IRC connection task stopped");
+        self.requests.send(request).await.map_err(|_| stopped())?;
+        outcome
+            .await
+            .map_err(|_| stopped())?
+            .map_err(|e| HandlerError::new(ErrorCategory::Request, e))
+    }
+
+    fn self_test(&self, report: &mut Report) {
+        self.config.self_test(report);
+    }
+}
+
+/// Run the primitive with `args` as its command line (the first item is
+/// the program name, as with `std::env::args_os`).
+pub async fn run<I, T>(args: I) -> Result<(), Box<dyn std::error::Error>>
+where
+    I: IntoIterator<Item = T>,
+    T: Into<std::ffi::OsString> + Clone,
+{
+    let args = Args::parse_from(args);
+
+    let config = SinkConfig {
+        name: "irc_sink",
+        subscribe: &args.subscribe,
+        would_have_as: "irc.would_have",
+        dead_letter_as: "irc.dead_letter",
+        settings: &args,
+    };
+    let (requests, pending) = mpsc::channel(1);
+    if !args.sink.dry_run && !args.sink.self_test {
+        let throttle = Throttle::new(args.burst, Duration::from_millis(args.line_delay));
+        tokio::spawn(connection(args.config(), throttle, pending));
+    }
+    let handler = IrcSink {
+        config: args.config(),
+        target: args.target.clone(),
+        kind: args.kind,
+        max_line_bytes: args.max_line_bytes,
+        requests,
+    };
+
+    run_sink(config, &args.sink, handler).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use emergent_testkit::{SinkFixture, fixtures, spawn_sink};
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::TcpListener;
+
+    #[tokio::test]
+    async fn payloads_are_sent_after_joining_their_channel() {
+        let listener = TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap_or_else(|e| panic!("bind: {e}"));
+        let port = listener
+            .local_addr()
+            .unwrap_or_else(|e| panic!("local_addr: {e}"))
+            .port();
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            let (socket, _) = listener
+                .accept()
+                .await
+                .unwrap_or_else(|e| panic!("accept: {e}"));
+            let (reader, mut writer) = socket.into_split();
+            let mut lines = BufReader::new(reader).lines();
+            while let Ok(Some(line)) = lines.next_line().await {
+                if line.starts_with("USER ") {
+                    let _ = writer.write_all(b":irc.test 001 bot :Welcome\r\n").await;
+                }
+                let _ = tx.send(line);
+            }
+        });
+
+        let config = Config {
+            server: "127.0.0.1".to_string(),
+            port,
+            tls: false,
+            nick: "bot".to_string(),
+            username: "bot".to_string(),
+            realname: "Emergent".to_string(),
+            password: None,
+            sasl: None,
+            channels: vec!["#ops".to_string()],
+            reconnect_delay: 1000,
+            max_reconnect_delay: 60_000,
+        };
+        let (requests, pending) = mpsc::channel(1);
+        let throttle = Throttle::new(10, Duration::from_millis(10));
+        tokio::spawn(connection(config.clone(), throttle, pending));
+        let handler = IrcSink {
+            config,
+            target: Some("#ops".to_string()),
+            kind: Kind::Privmsg,
+            max_line_bytes: 400,
+            requests,
+        };
+        let (mut engine, run) = spawn_sink(
+            SinkFixture::new("irc_sink", "irc"),
+            SinkArgs::default(),
+            handler,
+        );
+
+        engine
+            .inject_message(fixtures::message(
+                "deploy.completed",
+                json!({"text": "web1 deployed\nall green"}),
+            ))
+            .await;
+        engine
+            .inject_message(fixtures::message(
+                "deploy.completed",
+                json!({"channel": "#Deploys", "message": "restarts web1", "kind": "action"}),
+            ))
+            .await;
+
+        let mut sent = Vec::new();
+        while sent.len() < 7 {
+            sent.push(rx.recv().await.unwrap_or_else(|| panic!("server closed")));
+        }
+        assert_eq!(
+            sent,
+            [
+                "NICK bot",
+                "USER bot 0 * Emergent",
+                "JOIN #ops",
+                "PRIVMSG #ops :web1 deployed",
+                "PRIVMSG #ops :all green",
+                "JOIN #Deploys",
+                "PRIVMSG #Deploys :\u{1}ACTION restarts web1\u{1}",
+            ]
+        );
+
+        engine.close();
+        assert_eq!(run.await.ok(), Some(Ok(())));
+    }
+}
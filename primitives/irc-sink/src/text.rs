@@ -0,0 +1,51 @@
+//! Fitting text into IRC lines.
+//!
+//! A line can't hold a line break and the server truncates lines past 512
+//! bytes, including the `:nick!user@host PRIVMSG #channel :` prefix it adds
+//! when relaying. Text is sent one line per line of text, with long lines
+//! split at the last space before `max` bytes; blank lines are dropped.
+
+/// `text` as lines of at most `max` bytes.
+pub fn lines(text: &str, max: usize) -> Vec<String> {
+    let max = max.max(1);
+    let mut lines = Vec::new();
+    for line in text.lines() {
+        let mut rest = line.trim_end();
+        while rest.len() > max {
+            let mut cut = max;
+            while !rest.is_char_boundary(cut) {
+                cut -= 1;
+            }
+            if cut == 0 {
+                // A single character longer than `max`
+                cut = rest.chars().next().map_or(rest.len(), char::len_utf8);
+            }
+            let cut = match rest[..cut].rfind(' ') {
+                Some(space) if space > 0 => space,
+                _ => cut,
+            };
+            lines.push(rest[..cut].to_string());
+            rest = rest[cut..].trim_start();
+        }
+        if !rest.is_empty() {
+            lines.push(rest.to_string());
+        }
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn long_lines_are_split_at_spaces_and_blank_lines_dropped() {
+        assert_eq!(
+            lines("deploy web1 finished\n\nall green  ", 12),
+            ["deploy web1", "finished", "all green"]
+        );
+        assert_eq!(lines("abcdefgh", 3), ["abc", "def", "gh"]);
+        // Never inside a character
+        assert_eq!(lines("ééé", 3), ["é", "é", "é"]);
+    }
+}
@@ -0,0 +1,60 @@
+//! Flood protection.
+//!
+//! Servers disconnect clients that send too fast, so lines go out in a
+//! burst of up to `burst` and then one every `interval`, the classic
+//! IRC client penalty clock: each line moves a clock `interval` ahead, and
+//! sending waits while the clock is more than `burst` lines ahead of now.
+
+use std::time::{Duration, Instant};
+
+pub struct Throttle {
+    burst: u32,
+    interval: Duration,
+    clock: Instant,
+}
+
+impl Throttle {
+    pub fn new(burst: u32, interval: Duration) -> Self {
+        Self {
+            burst: burst.max(1),
+            interval,
+            clock: Instant::now(),
+        }
+    }
+
+    /// How long a line sent at `now` has to wait, counting it as sent.
+    fn delay(&mut self, now: Instant) -> Duration {
+        self.clock = self.clock.max(now);
+        let allowance = self.interval * (self.burst - 1);
+        let delay = self.clock.saturating_duration_since(now + allowance);
+        self.clock += self.interval;
+        delay
+    }
+
+    /// Wait until another line may be sent.
+    pub async fn wait(&mut self) {
+        let delay = self.delay(Instant::now());
+        if !delay.is_zero() {
+            tokio::time::sleep(delay).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lines_burst_then_follow_the_interval() {
+        let start = Instant::now();
+        let mut throttle = Throttle::new(3, Duration::from_secs(2));
+        // Five lines at once: three go out, then one every two seconds
+        let delays: Vec<u64> = (0..5).map(|_| throttle.delay(start).as_secs()).collect();
+        assert_eq!(delays, [0, 0, 0, 2, 4]);
+
+        // An idle spell refills the burst
+        let later = start + Duration::from_secs(60);
+        assert_eq!(throttle.delay(later), Duration::ZERO);
+        assert_eq!(throttle.delay(later), Duration::ZERO);
+    }
+}
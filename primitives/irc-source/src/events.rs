@@ -0,0 +1,134 @@
+//! Payloads of the events the source emits.
+//!
+//! `irc.message`, for `PRIVMSG`, `NOTICE` and `/me` actions:
+//!
+//! ```json
+//! {"server": "irc.libera.chat", "channel": "#ops", "nick": "alice", "user": "ali",
+//!  "host": "example.org", "kind": "privmsg", "text": "deploy web1", "private": false}
+//! ```
+//!
+//! `kind` is `privmsg`, `notice` or `action`; a message sent to the
+//! source's nick is `private`, with `channel` null. `irc.join` and
+//! `irc.part` carry `server`, `channel`, `nick`, `user` and `host`, and a
+//! part its `reason`; a kick is a part with `kicked_by`.
+
+use irc_common::message::{Message, is_channel};
+use serde_json::{Value, json};
+
+pub const MESSAGE_EVENT_TYPE: &str = "irc.message";
+pub const JOIN_EVENT_TYPE: &str = "irc.join";
+pub const PART_EVENT_TYPE: &str = "irc.part";
+pub const EVENT_TYPES: [&str; 3] = [MESSAGE_EVENT_TYPE, JOIN_EVENT_TYPE, PART_EVENT_TYPE];
+
+/// `text` without CTCP framing, and whether it is a `/me` action. Other
+/// CTCP requests (`VERSION`, `PING`, ...) are `None`.
+fn ctcp(text: &str) -> Option<(&str, bool)> {
+    let Some(inner) = text.strip_prefix('\u{1}') else {
+        return Some((text, false));
+    };
+    let inner = inner.strip_suffix('\u{1}').unwrap_or(inner);
+    inner.strip_prefix("ACTION ").map(|action| (action, true))
+}
+
+/// The event for `message`, if it is one the source emits.
+pub fn event(message: &Message, server: &str) -> Option<(&'static str, Value)> {
+    // Only users have a `nick!user@host` prefix; server notices are skipped
+    let (Some(user), host) = message.user_host() else {
+        return None;
+    };
+    let nick = message.nick()?;
+    let mut payload = json!({
+        "server": server,
+        "nick": nick,
+        "user": user,
+        "host": host,
+    });
+    let event_type = match message.command.as_str() {
+        command @ ("PRIVMSG" | "NOTICE") => {
+            let target = message.param(0);
+            let (text, action) = ctcp(message.param(1))?;
+            let kind = match (command, action) {
+                (_, true) => "action",
+                ("NOTICE", false) => "notice",
+                _ => "privmsg",
+            };
+            let channel = is_channel(target).then_some(target);
+            payload["channel"] = json!(channel);
+            payload["kind"] = json!(kind);
+            payload["text"] = json!(text);
+            payload["private"] = json!(channel.is_none());
+            MESSAGE_EVENT_TYPE
+        }
+        "JOIN" => {
+            payload["channel"] = json!(message.param(0));
+            JOIN_EVENT_TYPE
+        }
+        "PART" => {
+            payload["channel"] = json!(message.param(0));
+            payload["reason"] = json!(message.params.get(1));
+            PART_EVENT_TYPE
+        }
+        "KICK" => {
+            payload["channel"] = json!(message.param(0));
+            payload["nick"] = json!(message.param(1));
+            payload["user"] = Value::Null;
+            payload["host"] = Value::Null;
+            payload["reason"] = json!(message.params.get(2));
+            payload["kicked_by"] = json!(nick);
+            PART_EVENT_TYPE
+        }
+        _ => return None,
+    };
+    Some((event_type, payload))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(line: &str) -> Message {
+        Message::parse(line).unwrap_or_else(|| panic!("unparsable: {line}"))
+    }
+
+    #[test]
+    fn messages_actions_and_kicks_become_events() {
+        let (event_type, payload) = event(
+            &parse(":alice!ali@example.org PRIVMSG #ops :\u{1}ACTION restarts web1\u{1}"),
+            "irc.example.org",
+        )
+        .unwrap_or_else(|| panic!("no event"));
+        assert_eq!(event_type, MESSAGE_EVENT_TYPE);
+        assert_eq!(
+            payload,
+            json!({
+                "server": "irc.example.org",
+                "channel": "#ops",
+                "nick": "alice",
+                "user": "ali",
+                "host": "example.org",
+                "kind": "action",
+                "text": "restarts web1",
+                "private": false,
+            })
+        );
+
+        let (_, private) = event(&parse(":bob!b@h NOTICE emergent :hi"), "irc.example.org")
+            .unwrap_or_else(|| panic!("no event"));
+        assert_eq!(private["channel"], Value::Null);
+        assert_eq!(private["kind"], "notice");
+        assert_eq!(private["private"], true);
+
+        let (event_type, kick) = event(&parse(":op!o@h KICK #ops bob :spam"), "irc.example.org")
+            .unwrap_or_else(|| panic!("no event"));
+        assert_eq!(event_type, PART_EVENT_TYPE);
+        assert_eq!(kick["nick"], "bob");
+        assert_eq!(kick["kicked_by"], "op");
+        assert_eq!(kick["reason"], "spam");
+
+        assert_eq!(
+            event(&parse(":bob!b@h PRIVMSG emergent :\u{1}VERSION\u{1}"), "x"),
+            None
+        );
+        assert_eq!(event(&parse(":irc.example.org NOTICE * :motd"), "x"), None);
+    }
+}
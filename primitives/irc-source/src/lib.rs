@@ -0,0 +1,432 @@
+//! IRC Source - IRC Channel Events
+//!
+//! A Source that connects to an IRC server, joins a list of channels and
+//! emits what happens in them: messages, notices and actions as
+//! `irc.message`, and members coming and going as `irc.join` and
+//! `irc.part` (see [`events`] for the payloads). Private messages to the
+//! source's nick are emitted too.
+//!
+//! The connection uses TLS unless `--no-tls` is given, and can log in to
+//! services with SASL PLAIN. When it drops, the source reconnects and
+//! rejoins, waiting `--reconnect-delay` (doubling up to
+//! `--max-reconnect-delay`) between attempts; messages sent while it was
+//! away are lost, as IRC keeps no history.
+//!
+//! Sources are SILENT - they only produce domain messages.
+//! All lifecycle events are published by the engine.
+//!
+//! # Usage
+//!
+//! ```bash
+//! irc-source --server irc.libera.chat --nick emergent-ops \
+//!   --sasl-password "$IRC_SASL_PASSWORD" --channel '#ops' --channel '#deploys'
+//! ```
+//!
+//! On SIGTERM the source quits, giving the server `--drain-timeout`
+//! milliseconds to take the `QUIT`.
+
+pub mod events;
+
+use clap::Parser;
+use emergent_client::EmergentSource;
+use events::EVENT_TYPES;
+use irc_common::message::Message;
+use irc_common::{Config, Connection};
+use primitive_common::capabilities::{self, CapabilityArgs, Role, VERSION, capabilities_message};
+use primitive_common::doctor::Report;
+use primitive_common::source::{Outlet, SourceArgs};
+use std::sync::Arc;
+use tokio::signal::unix::{SignalKind, signal};
+
+/// IRC client that emits irc.* events.
+#[derive(Parser, Debug, Clone)]
+#[command(name = "irc-source", version = VERSION)]
+#[command(about = "Joins IRC channels and emits message, join and part events")]
+struct Args {
+    /// Server host name.
+    #[arg(long, env = "IRC_SOURCE_SERVER")]
+    server: String,
+
+    /// Server port (default: 6697, or 6667 with `--no-tls`).
+    #[arg(short, long, env = "IRC_SOURCE_PORT")]
+    port: Option<u16>,
+
+    /// Connect without TLS.
+    #[arg(long, env = "IRC_SOURCE_NO_TLS")]
+    no_tls: bool,
+
+    /// Nick to use; `_` is appended while it is taken.
+    #[arg(short, long, env = "IRC_SOURCE_NICK", default_value = "emergent")]
+    nick: String,
+
+    /// User name (default: the nick).
+    #[arg(long, env = "IRC_SOURCE_USERNAME")]
+    username: Option<String>,
+
+    /// Real name shown in WHOIS.
+    #[arg(long, env = "IRC_SOURCE_REALNAME", default_value = "Emergent")]
+    realname: String,
+
+    /// Server password.
+    #[arg(long, env = "IRC_SOURCE_SERVER_PASSWORD", hide_env_values = true)]
+    server_password: Option<String>,
+
+    /// SASL account (default: the nick).
+    #[arg(long, env = "IRC_SOURCE_SASL_USER")]
+    sasl_user: Option<String>,
+
+    /// SASL password; enables SASL PLAIN.
+    #[arg(long, env = "IRC_SOURCE_SASL_PASSWORD", hide_env_values = true)]
+    sasl_password: Option<String>,
+
+    /// Channels to join; repeatable.
+    #[arg(
+        short,
+        long = "channel",
+        env = "IRC_SOURCE_CHANNELS",
+        value_delimiter = ','
+    )]
+    channels: Vec<String>,
+
+    /// Milliseconds before the first reconnect; doubles on each failure.
+    #[arg(long, env = "IRC_SOURCE_RECONNECT_DELAY", default_value = "1000")]
+    reconnect_delay: u64,
+
+    /// Upper bound for the reconnect delay in milliseconds.
+    #[arg(long, env = "IRC_SOURCE_MAX_RECONNECT_DELAY", default_value = "300000")]
+    max_reconnect_delay: u64,
+
+    /// Verify external dependencies and exit without connecting to the engine.
+    #[arg(long)]
+    self_test: bool,
+
+    #[command(flatten)]
+    source: SourceArgs,
+
+    #[command(flatten)]
+    capabilities: CapabilityArgs,
+}
+
+/// Variables available to `--emit-type-template`.
+const TEMPLATE_VARIABLES: &[&str] = &["source"];
+
+impl Args {
+    fn config(&self) -> Config {
+        Config {
+            server: self.server.clone(),
+            port: self.port.unwrap_or(if self.no_tls { 6667 } else { 6697 }),
+            tls: !self.no_tls,
+            nick: self.nick.clone(),
+            username: self.username.clone().unwrap_or_else(|| self.nick.clone()),
+            realname: self.realname.clone(),
+            password: self.server_password.clone(),
+            sasl: self.sasl_password.clone().map(|password| {
+                let user = self.sasl_user.clone().unwrap_or_else(|| self.nick.clone());
+                (user, password)
+            }),
+            channels: self.channels.clone(),
+            reconnect_delay: self.reconnect_delay,
+            max_reconnect_delay: self.max_reconnect_delay,
+        }
+    }
+}
+
+/// Runs `--self-test` checks and exits.
+fn self_test(args: &Args, name: &str) -> ! {
+    let mut report = Report::new(name);
+    args.config().self_test(&mut report);
+    args.source.self_test(&mut report, TEMPLATE_VARIABLES);
+    report.finish()
+}
+
+/// The next message from the server, skipping the echoes of our own;
+/// `Err` once the connection is lost.
+async fn next(connection: &mut Connection) -> Result<Message, String> {
+    loop {
+        let message = connection.recv().await?;
+        if !message.nick().is_some_and(|nick| connection.is_me(nick)) {
+            return Ok(message);
+        }
+    }
+}
+
+/// Publish the event for `message`, if it is one the source emits.
+async fn forward(outlet: &Outlet, message: &Message, server: &str) {
+    if let Some((event_type, payload)) = events::event(message, server) {
+        // The outlet logs and reports what it cannot deliver
+        let _ = outlet.publish(event_type, &[], payload).await;
+    }
+}
+
+/// Run the primitive with `args` as its command line (the first item is
+/// the program name, as with `std::env::args_os`).
+pub async fn run<I, T>(args: I) -> Result<(), Box<dyn std::error::Error>>
+where
+    I: IntoIterator<Item = T>,
+    T: Into<std::ffi::OsString> + Clone,
+{
+    let args = Args::parse_from(args);
+
+    // Get the source name from environment (set by engine) or use default
+    let name = std::env::var("EMERGENT_NAME").unwrap_or_else(|_| "irc-source".to_string());
+
+    if args.self_test {
+        self_test(&args, &name);
+    }
+    if let Err(e) = args.source.validate(TEMPLATE_VARIABLES) {
+        eprintln!("Error: {e}");
+        std::process::exit(1);
+    }
+
+    let produces = args.source.produces(&EVENT_TYPES);
+    let produces: Vec<&str> = produces.iter().map(String::as_str).collect();
+    let descriptor = args
+        .capabilities
+        .describe(&name, Role::Source, &[], &produces, &args);
+    if args.capabilities.capabilities {
+        capabilities::print(&descriptor);
+    }
+
+    // Connect to the Emergent engine (silently - lifecycle events come from engine)
+    let source = match EmergentSource::connect(&name).await {
+        Ok(s) => s,
+        Err(e) => {
+            eprintln!("Failed to connect to Emergent engine: {e}");
+            std::process::exit(1);
+        }
+    };
+    let outlet = Arc::new(Outlet::new(source, &name, &args.source)?);
+    if args.capabilities.announce {
+        outlet.announce(capabilities_message(&descriptor)).await;
+    }
+    tokio::spawn(Arc::clone(&outlet).drain_every(args.source.spool.retry_interval()));
+
+    let config = args.config();
+    let mut sigterm = signal(SignalKind::terminate())?;
+    let mut attempt = 0;
+    'connect: loop {
+        let connected = tokio::select! {
+            _ = sigterm.recv() => break,
+            connected = irc_common::connect(&config) => connected,
+        };
+        match connected {
+            Ok(mut connection) => {
+                attempt = 0;
+                eprintln!("Connected to {} as {}", config.server, connection.nick());
+                loop {
+                    let message = tokio::select! {
+                        _ = sigterm.recv() => {
+                            let quit = Message::new("QUIT", &["Shutting down"]);
+                            args.source.drain.drain("QUIT", connection.send(&quit)).await;
+                            break 'connect;
+                        }
+                        message = next(&mut connection) => message,
+                    };
+                    let message = match message {
+                        Ok(message) => message,
+                        Err(e) => {
+                            eprintln!("Lost connection to {}: {e}", config.server);
+                            break;
+                        }
+                    };
+                    forward(&outlet, &message, &config.server).await;
+                }
+            }
+            Err(e) => eprintln!("Failed to connect to {}: {e}", config.server),
+        }
+
+        let delay = config.reconnect_delay(attempt);
+        attempt = attempt.saturating_add(1);
+        tokio::select! {
+            _ = sigterm.recv() => break,
+            _ = tokio::time::sleep(delay) => {}
+        }
+    }
+    outlet.disconnect().await;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use emergent_testkit::{MockEngine, fixtures};
+    use std::path::Path;
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::TcpListener;
+    use tokio::task::JoinHandle;
+
+    async fn outlet(socket: &Path, args: &SourceArgs) -> Outlet {
+        let source = EmergentSource::connect_to("irc-source", socket)
+            .await
+            .unwrap_or_else(|e| panic!("connect: {e}"));
+        Outlet::new(source, "irc-source", args).unwrap_or_else(|e| panic!("open spool: {e}"))
+    }
+
+    fn parse(line: &str) -> Message {
+        Message::parse(line).unwrap_or_else(|| panic!("unparsable: {line}"))
+    }
+
+    /// An IRC server for one client, answering each line it sends with the
+    /// bytes of the first reply whose prefix it starts with. Gives back the
+    /// lines received once the client hangs up.
+    async fn irc_server(
+        replies: &'static [(&'static str, &'static [u8])],
+    ) -> (u16, JoinHandle<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap_or_else(|e| panic!("bind: {e}"));
+        let port = listener
+            .local_addr()
+            .unwrap_or_else(|e| panic!("local_addr: {e}"))
+            .port();
+        let server = tokio::spawn(async move {
+            let (socket, _) = listener
+                .accept()
+                .await
+                .unwrap_or_else(|e| panic!("accept: {e}"));
+            let (reader, mut writer) = socket.into_split();
+            let mut lines = BufReader::new(reader).lines();
+            let mut received = Vec::new();
+            while let Ok(Some(line)) = lines.next_line().await {
+                if let Some((_, reply)) =
+                    replies.iter().find(|(prefix, _)| line.starts_with(prefix))
+                {
+                    let _ = writer.write_all(reply).await;
+                }
+                received.push(line);
+            }
+            received
+        });
+        (port, server)
+    }
+
+    fn cli(port: u16, extra: &[&str]) -> Args {
+        let port = port.to_string();
+        let mut argv = vec![
+            "irc-source",
+            "--server",
+            "127.0.0.1",
+            "--no-tls",
+            "--port",
+            &port,
+        ];
+        argv.extend_from_slice(extra);
+        Args::parse_from(argv)
+    }
+
+    #[tokio::test]
+    async fn rejected_logins_fail_the_connection_and_back_off() {
+        let (port, server) = irc_server(&[
+            ("CAP REQ sasl", b"CAP * ACK :sasl\r\n"),
+            ("AUTHENTICATE PLAIN", b"AUTHENTICATE +\r\n"),
+            (
+                "AUTHENTICATE ",
+                b":irc.test 904 emergent :SASL authentication failed\r\n",
+            ),
+        ])
+        .await;
+        let args = cli(
+            port,
+            &[
+                "--sasl-password",
+                "wrong",
+                "--reconnect-delay",
+                "500",
+                "--max-reconnect-delay",
+                "3000",
+            ],
+        );
+        let error = irc_common::connect(&args.config())
+            .await
+            .err()
+            .unwrap_or_else(|| panic!("connected with a wrong password"));
+        assert!(error.contains("SASL authentication failed"), "{error}");
+        let received = server.await.unwrap_or_else(|e| panic!("server: {e}"));
+        assert!(!received.iter().any(|line| line.starts_with("JOIN")));
+
+        let (port, _server) =
+            irc_server(&[("USER", b":irc.test 464 emergent :Password incorrect\r\n")]).await;
+        let error = irc_common::connect(&cli(port, &["--server-password", "wrong"]).config())
+            .await
+            .err()
+            .unwrap_or_else(|| panic!("connected with a wrong password"));
+        assert!(error.contains("server password rejected"), "{error}");
+
+        // Reconnects back off up to the maximum
+        let config = args.config();
+        let delays: Vec<u128> = (0..5)
+            .map(|attempt| config.reconnect_delay(attempt).as_millis())
+            .collect();
+        assert_eq!(delays, [500, 1000, 2000, 3000, 3000]);
+    }
+
+    #[tokio::test]
+    async fn malformed_lines_and_echoes_are_skipped_until_the_link_closes() {
+        let (port, server) = irc_server(&[
+            ("USER", b":irc.test 001 emergent :Welcome\r\n"),
+            (
+                "JOIN",
+                b"\r\n:\r\nPING :irc.test\r\n:irc.test NOTICE * :motd\r\n\
+                  :emergent!e@h PRIVMSG #ops :echo\r\n:alice!a@h PRIVMSG #ops :caf\xe9 ready\r\n\
+                  ERROR :Closing link (Ping timeout)\r\n",
+            ),
+        ])
+        .await;
+        let dir = fixtures::TempDir::new("irc-source-link");
+        let socket = dir.path().join("engine.sock");
+        let mut engine = MockEngine::serve(&socket);
+        let outlet = outlet(&socket, &SourceArgs::default()).await;
+
+        let mut connection = irc_common::connect(&cli(port, &["--channel", "#ops"]).config())
+            .await
+            .unwrap_or_else(|e| panic!("connect: {e}"));
+        let error = loop {
+            match next(&mut connection).await {
+                Ok(message) => forward(&outlet, &message, "irc.test").await,
+                Err(e) => break e,
+            }
+        };
+        assert!(error.contains("Closing link"), "{error}");
+        drop(connection);
+
+        let published = engine.expect_published(events::MESSAGE_EVENT_TYPE).await;
+        assert_eq!(published.payload()["nick"], "alice");
+        assert_eq!(published.payload()["text"], "caf\u{fffd} ready");
+        engine
+            .expect_quiet(std::time::Duration::from_millis(200))
+            .await;
+        let received = server.await.unwrap_or_else(|e| panic!("server: {e}"));
+        assert!(
+            received.contains(&"PONG irc.test".to_string()),
+            "{received:?}"
+        );
+        engine.shut_down().await;
+    }
+
+    #[tokio::test]
+    async fn channel_events_survive_the_engine_being_down() {
+        let dir = fixtures::TempDir::new("irc-source-spool");
+        let socket = dir.path().join("engine.sock");
+        let args = SourceArgs {
+            spool: primitive_common::spool::SpoolArgs {
+                spool_dir: Some(dir.path().join("spool")),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        let mut engine = MockEngine::serve(&socket);
+        let down = outlet(&socket, &args).await;
+        engine.shut_down().await;
+        let line = ":alice!ali@example.org PRIVMSG #ops :deploy web1";
+        forward(&down, &parse(line), "irc.example.org").await;
+        drop(down);
+
+        let mut engine = MockEngine::serve(&socket);
+        outlet(&socket, &args).await.drain_spool().await;
+        let published = engine.expect_published(events::MESSAGE_EVENT_TYPE).await;
+        assert_eq!(published.payload()["text"], "deploy web1");
+        engine.shut_down().await;
+    }
+}
@@ -0,0 +1,301 @@
+//! Builds, and which of their events have been published.
+//!
+//! A build is published twice at most: `ci.build.started` when it is first
+//! seen running and `ci.build.completed` when it is first seen with a
+//! result. [`Seen`] remembers, per job, the build numbers each event went
+//! out for, so notifications repeated by Jenkins (`COMPLETED` and then
+//! `FINALIZED`), polls seeing the same build again, and restarts with
+//! `--state-file` do not publish twice. Builds finishing out of order are
+//! still reported, since numbers are tracked individually rather than as a
+//! high-water mark.
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use std::collections::{BTreeMap, BTreeSet};
+
+pub const STARTED_EVENT_TYPE: &str = "ci.build.started";
+pub const COMPLETED_EVENT_TYPE: &str = "ci.build.completed";
+pub const EVENT_TYPES: [&str; 2] = [STARTED_EVENT_TYPE, COMPLETED_EVENT_TYPE];
+
+/// Build numbers remembered per job and event; older ones are forgotten.
+const KEEP: usize = 200;
+
+/// One commit in a build's changeset.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Change {
+    pub commit: Option<String>,
+    pub message: Option<String>,
+    pub author: Option<String>,
+}
+
+/// A build as the JSON API or a notification describes it.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Build {
+    pub job: String,
+    pub number: u64,
+    pub url: Option<String>,
+    pub building: bool,
+    /// `SUCCESS`, `UNSTABLE`, `FAILURE`, `ABORTED` or `NOT_BUILT`, once done.
+    pub result: Option<String>,
+    pub duration_ms: Option<u64>,
+    /// Start time, in milliseconds since the Unix epoch.
+    pub started_at: Option<u64>,
+    pub changeset: Vec<Change>,
+    pub artifacts: Vec<String>,
+}
+
+fn string(value: &Value) -> Option<String> {
+    value.as_str().map(str::to_string)
+}
+
+impl Build {
+    /// Read a build from the JSON API (`/job/<job>/<number>/api/json`).
+    pub fn from_api(job: &str, build: &Value) -> Option<Self> {
+        let url = string(&build["url"]);
+        let changeset = build["changeSets"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .chain(std::iter::once(&build["changeSet"]))
+            .filter_map(|set| set["items"].as_array())
+            .flatten()
+            .map(|item| Change {
+                commit: string(&item["commitId"]),
+                message: string(&item["msg"]),
+                author: string(&item["author"]["fullName"]),
+            })
+            .collect();
+        let artifacts = build["artifacts"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|artifact| {
+                let path = artifact["relativePath"].as_str()?;
+                Some(match &url {
+                    Some(url) => format!("{}/artifact/{path}", url.trim_end_matches('/')),
+                    None => path.to_string(),
+                })
+            })
+            .collect();
+        Some(Self {
+            job: job.to_string(),
+            number: build["number"].as_u64()?,
+            url,
+            building: build["building"].as_bool().unwrap_or(false),
+            result: string(&build["result"]),
+            duration_ms: build["duration"].as_u64().filter(|d| *d > 0),
+            started_at: build["timestamp"].as_u64(),
+            changeset,
+            artifacts,
+        })
+    }
+
+    /// Read a Notification plugin delivery: `STARTED` is a running build,
+    /// `COMPLETED` and `FINALIZED` a finished one; other phases are `None`.
+    pub fn from_notification(notification: &Value) -> Option<Self> {
+        let build = &notification["build"];
+        let building = match build["phase"].as_str()? {
+            "STARTED" => true,
+            "COMPLETED" | "FINALIZED" => false,
+            _ => return None,
+        };
+        let artifacts = build["artifacts"]
+            .as_object()
+            .into_iter()
+            .flatten()
+            .filter_map(|(_, artifact)| string(&artifact["archive"]))
+            .collect();
+        let changeset = string(&build["scm"]["commit"])
+            .map(|commit| Change {
+                commit: Some(commit),
+                message: None,
+                author: None,
+            })
+            .into_iter()
+            .collect();
+        Some(Self {
+            job: string(&notification["name"])?,
+            number: build["number"].as_u64()?,
+            url: string(&build["full_url"]),
+            building,
+            result: if building {
+                None
+            } else {
+                string(&build["status"])
+            },
+            duration_ms: build["duration"].as_u64().filter(|d| *d > 0),
+            started_at: build["timestamp"].as_u64(),
+            changeset,
+            artifacts,
+        })
+    }
+
+    /// Whether the build has finished.
+    pub fn completed(&self) -> bool {
+        !self.building && self.result.is_some()
+    }
+
+    /// The event payload.
+    pub fn payload(&self) -> Value {
+        json!({
+            "job": self.job,
+            "number": self.number,
+            "url": self.url,
+            "result": self.result,
+            "duration_ms": self.duration_ms,
+            "started_at": self.started_at,
+            "changeset": self.changeset,
+            "artifacts": self.artifacts,
+        })
+    }
+}
+
+/// Build numbers already published for one job.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct JobSeen {
+    #[serde(default)]
+    started: BTreeSet<u64>,
+    #[serde(default)]
+    completed: BTreeSet<u64>,
+}
+
+/// Remember `number` in `set`, forgetting the oldest beyond [`KEEP`].
+/// Returns whether it is new; numbers older than everything remembered
+/// count as seen.
+fn remember(set: &mut BTreeSet<u64>, number: u64) -> bool {
+    if set.len() >= KEEP && set.first().is_some_and(|oldest| number < *oldest) {
+        return false;
+    }
+    let new = set.insert(number);
+    while set.len() > KEEP {
+        set.pop_first();
+    }
+    new
+}
+
+/// Published build numbers for every job.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Seen {
+    jobs: BTreeMap<String, JobSeen>,
+}
+
+impl Seen {
+    /// Whether `job` has been seen before; the first poll of a job records
+    /// its builds without publishing them.
+    pub fn knows(&self, job: &str) -> bool {
+        self.jobs.contains_key(job)
+    }
+
+    /// Record every build as published, without publishing anything.
+    pub fn baseline(&mut self, job: &str, builds: &[Build]) {
+        let seen = self.jobs.entry(job.to_string()).or_default();
+        for build in builds {
+            remember(&mut seen.started, build.number);
+            if build.completed() {
+                remember(&mut seen.completed, build.number);
+            }
+        }
+    }
+
+    /// The event `build` calls for, if it has not been published yet.
+    pub fn event(&mut self, build: &Build) -> Option<&'static str> {
+        let seen = self.jobs.entry(build.job.clone()).or_default();
+        if build.completed() {
+            // A build first seen finished counts as started too
+            remember(&mut seen.started, build.number);
+            remember(&mut seen.completed, build.number).then_some(COMPLETED_EVENT_TYPE)
+        } else if build.building {
+            remember(&mut seen.started, build.number).then_some(STARTED_EVENT_TYPE)
+        } else {
+            None
+        }
+    }
+
+    /// Undo [`event`](Self::event) for a build whose event could not be
+    /// published, so it is tried again.
+    pub fn forget(&mut self, build: &Build, event_type: &str) {
+        if let Some(seen) = self.jobs.get_mut(&build.job) {
+            let set = if event_type == COMPLETED_EVENT_TYPE {
+                &mut seen.completed
+            } else {
+                &mut seen.started
+            };
+            set.remove(&build.number);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn build(number: u64, result: Option<&str>) -> Build {
+        Build {
+            job: "deploy".to_string(),
+            number,
+            building: result.is_none(),
+            result: result.map(str::to_string),
+            ..Build::default()
+        }
+    }
+
+    #[test]
+    fn each_event_is_published_once_per_build() {
+        let mut seen = Seen::default();
+        assert_eq!(seen.event(&build(12, None)), Some(STARTED_EVENT_TYPE));
+        assert_eq!(seen.event(&build(12, None)), None);
+        assert_eq!(
+            seen.event(&build(13, Some("SUCCESS"))),
+            Some(COMPLETED_EVENT_TYPE)
+        );
+        assert_eq!(
+            seen.event(&build(12, Some("FAILURE"))),
+            Some(COMPLETED_EVENT_TYPE)
+        );
+        assert_eq!(seen.event(&build(12, Some("FAILURE"))), None);
+        assert_eq!(seen.event(&build(13, None)), None);
+
+        seen.forget(&build(13, Some("SUCCESS")), COMPLETED_EVENT_TYPE);
+        assert_eq!(
+            seen.event(&build(13, Some("SUCCESS"))),
+            Some(COMPLETED_EVENT_TYPE)
+        );
+    }
+
+    #[test]
+    fn notifications_and_api_builds_are_read() {
+        let notification = serde_json::json!({
+            "name": "asgard",
+            "build": {
+                "full_url": "http://jenkins/job/asgard/18/",
+                "number": 18,
+                "phase": "FINALIZED",
+                "status": "UNSTABLE",
+                "scm": {"commit": "c6d86dc7"},
+                "artifacts": {"asgard.war": {"archive": "http://jenkins/job/asgard/18/artifact/asgard.war"}},
+            },
+        });
+        let build = Build::from_notification(&notification).unwrap_or_else(|| panic!("no build"));
+        assert!(build.completed());
+        assert_eq!(build.result.as_deref(), Some("UNSTABLE"));
+        assert_eq!(build.changeset[0].commit.as_deref(), Some("c6d86dc7"));
+        assert_eq!(build.artifacts.len(), 1);
+        let queued =
+            serde_json::json!({"name": "asgard", "build": {"number": 19, "phase": "QUEUED"}});
+        assert_eq!(Build::from_notification(&queued), None);
+
+        let api = serde_json::json!({
+            "number": 7, "building": false, "result": "SUCCESS", "duration": 5120,
+            "url": "http://jenkins/job/app/7/",
+            "changeSets": [{"items": [{"commitId": "a1b2", "msg": "Fix", "author": {"fullName": "Ada"}}]}],
+            "artifacts": [{"relativePath": "target/app.jar"}],
+        });
+        let build = Build::from_api("app", &api).unwrap_or_else(|| panic!("no build"));
+        assert_eq!(build.duration_ms, Some(5120));
+        assert_eq!(build.changeset[0].author.as_deref(), Some("Ada"));
+        assert_eq!(
+            build.artifacts,
+            ["http://jenkins/job/app/7/artifact/target/app.jar"]
+        );
+    }
+}
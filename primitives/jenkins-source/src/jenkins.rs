@@ -0,0 +1,111 @@
+//! Jenkins JSON API.
+//!
+//! Requests authenticate with `--user` and `--api-token` when given; an API
+//! token needs no CSRF crumb for reads.
+
+use crate::builds::Build;
+use reqwest::Client;
+use serde_json::Value;
+use std::time::Duration;
+
+/// The build fields read, for a job's recent builds or a single build.
+const BUILD_TREE: &str = "number,url,building,result,duration,timestamp,\
+    changeSet[items[commitId,msg,author[fullName]]],\
+    changeSets[items[commitId,msg,author[fullName]]],artifacts[relativePath]";
+
+/// Recent builds read per job and poll.
+const RECENT_BUILDS: usize = 20;
+
+/// Access to one Jenkins server.
+pub struct Jenkins {
+    client: Client,
+    url: Option<String>,
+    user: Option<String>,
+    token: Option<String>,
+    timeout: Duration,
+}
+
+impl Jenkins {
+    pub fn new(
+        client: Client,
+        url: Option<&str>,
+        user: Option<&str>,
+        token: Option<&str>,
+        timeout: Duration,
+    ) -> Self {
+        Self {
+            client,
+            url: url.map(|u| u.trim_end_matches('/').to_string()),
+            user: user.map(str::to_string),
+            token: token.map(str::to_string),
+            timeout,
+        }
+    }
+
+    /// The job URL, with folders as nested `job/` segments.
+    fn job_url(&self, job: &str) -> Result<String, String> {
+        let base = self.url.as_deref().ok_or("--jenkins-url is not set")?;
+        let path: String = job
+            .split('/')
+            .filter(|segment| !segment.is_empty())
+            .map(|segment| format!("/job/{segment}"))
+            .collect();
+        Ok(format!("{base}{path}"))
+    }
+
+    async fn get(&self, url: &str, tree: &str) -> Result<Value, String> {
+        let mut request = self
+            .client
+            .get(format!("{}/api/json", url.trim_end_matches('/')))
+            .query(&[("tree", tree)])
+            .timeout(self.timeout);
+        if let Some(user) = &self.user {
+            request = request.basic_auth(user, self.token.as_ref());
+        }
+        let response = request.send().await.map_err(|e| format!("{url}: {e}"))?;
+        let status = response.status();
+        if !status.is_success() {
+            return Err(format!("{url}: HTTP {status}"));
+        }
+        response.json().await.map_err(|e| format!("{url}: {e}"))
+    }
+
+    /// The job's most recent builds, newest first.
+    pub async fn builds(&self, job: &str) -> Result<Vec<Build>, String> {
+        let url = self.job_url(job)?;
+        let tree = format!("builds[{BUILD_TREE}]{{0,{RECENT_BUILDS}}}");
+        let body = self.get(&url, &tree).await?;
+        Ok(body["builds"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|build| Build::from_api(job, build))
+            .collect())
+    }
+
+    /// The build at `url`.
+    pub async fn build(&self, job: &str, url: &str) -> Result<Build, String> {
+        let body = self.get(url, BUILD_TREE).await?;
+        Build::from_api(job, &body).ok_or_else(|| format!("{url}: not a build"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn folders_become_nested_job_segments() {
+        let jenkins = Jenkins::new(
+            Client::new(),
+            Some("https://jenkins.example.com/"),
+            None,
+            None,
+            Duration::from_secs(1),
+        );
+        assert_eq!(
+            jenkins.job_url("deploy/prod"),
+            Ok("https://jenkins.example.com/job/deploy/job/prod".to_string())
+        );
+    }
+}
@@ -0,0 +1,658 @@
+//! Jenkins Source - Build Events
+//!
+//! A Source that reports Jenkins builds as `ci.build.started` and
+//! `ci.build.completed` events, carrying the job, build number, URL,
+//! result, duration, changeset and artifact URLs (see [`builds`]). Builds
+//! are learned of in one of two ways, chosen with `--mode`:
+//!
+//! - `webhook` (default): receive the Notification plugin's JSON
+//!   deliveries. `STARTED` becomes `ci.build.started`; `COMPLETED` and
+//!   `FINALIZED` become `ci.build.completed`, with duration, changeset and
+//!   artifacts read from the build's JSON API, since notifications carry
+//!   little of them.
+//! - `poll`: read the recent builds of each `--job` from the JSON API
+//!   every `--interval` milliseconds. The first poll of a job records its
+//!   builds without publishing them.
+//!
+//! Either way each build number is published at most once per event;
+//! with `--state-file` that holds across restarts.
+//!
+//! A build that can be neither published nor spooled is forgotten, so the
+//! next poll reports it again; a delivery is answered with 503.
+//!
+//! Sources are SILENT - they only produce domain messages.
+//! All lifecycle events are published by the engine.
+//!
+//! # Usage
+//!
+//! ```bash
+//! # Notification plugin endpoint: http://<host>:8080/jenkins?token=$SECRET
+//! jenkins-source --path /jenkins --secret $SECRET \
+//!   --user emergent --api-token $JENKINS_API_TOKEN
+//!
+//! # Poll two jobs every minute
+//! jenkins-source --mode poll --jenkins-url https://jenkins.example.com \
+//!   --user emergent --api-token $JENKINS_API_TOKEN \
+//!   --job deploy/prod --job app --interval 60000 --state-file /var/lib/emergent/jenkins.json
+//! ```
+//!
+//! On SIGTERM the listener closes immediately and deliveries already being
+//! handled get `--drain-timeout` milliseconds to finish publishing, as
+//! does a poll in progress.
+
+pub mod builds;
+pub mod jenkins;
+
+use axum::{
+    Router,
+    body::Bytes,
+    extract::{Query, State},
+    http::StatusCode,
+    response::IntoResponse,
+    routing::post,
+};
+use builds::{Build, EVENT_TYPES, Seen};
+use clap::{Parser, ValueEnum};
+use emergent_client::EmergentSource;
+use jenkins::Jenkins;
+use primitive_common::capabilities::{self, CapabilityArgs, Role, VERSION, capabilities_message};
+use primitive_common::doctor::{Report, check_writable_dir};
+use primitive_common::source::{Outlet, SourceArgs};
+use reqwest::Client;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::{future::IntoFuture, net::SocketAddr, time::Duration};
+use subtle::ConstantTimeEq;
+use tokio::{
+    signal::unix::{SignalKind, signal},
+    sync::Notify,
+};
+
+/// How builds are learned of.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Mode {
+    /// Receive Notification plugin deliveries.
+    Webhook,
+    /// Poll the JSON API for `--job`s.
+    Poll,
+}
+
+/// Jenkins build watcher that emits ci.build.* events.
+#[derive(Parser, Debug, Clone)]
+#[command(name = "jenkins-source", version = VERSION)]
+#[command(about = "Receives or polls Jenkins builds and emits build events")]
+struct Args {
+    /// Receive Notification plugin webhooks, or poll the JSON API.
+    #[arg(
+        long,
+        env = "JENKINS_SOURCE_MODE",
+        value_enum,
+        default_value = "webhook"
+    )]
+    mode: Mode,
+
+    /// Port to listen on (webhook mode).
+    #[arg(short, long, env = "JENKINS_SOURCE_PORT", default_value = "8080")]
+    port: u16,
+
+    /// Host to bind to (webhook mode).
+    #[arg(long, env = "JENKINS_SOURCE_HOST", default_value = "0.0.0.0")]
+    host: String,
+
+    /// Path to accept deliveries on (webhook mode).
+    #[arg(long, env = "JENKINS_SOURCE_PATH", default_value = "/")]
+    path: String,
+
+    /// Secret deliveries must carry as the `token` query parameter.
+    #[arg(long, env = "JENKINS_WEBHOOK_SECRET", hide_env_values = true)]
+    secret: Option<String>,
+
+    /// Jenkins root URL (required for polling).
+    #[arg(long, env = "JENKINS_URL")]
+    jenkins_url: Option<String>,
+
+    /// User for the JSON API.
+    #[arg(long, env = "JENKINS_USER", requires = "api_token")]
+    user: Option<String>,
+
+    /// API token of `--user`.
+    #[arg(long, env = "JENKINS_API_TOKEN", hide_env_values = true)]
+    api_token: Option<String>,
+
+    /// Job to poll, with folders as `folder/job` (repeatable or comma-separated).
+    #[arg(long = "job", env = "JENKINS_SOURCE_JOBS", value_delimiter = ',')]
+    jobs: Vec<String>,
+
+    /// Milliseconds between polls.
+    #[arg(short, long, env = "JENKINS_SOURCE_INTERVAL", default_value = "30000")]
+    interval: u64,
+
+    /// Per-request timeout in milliseconds.
+    #[arg(short, long, env = "JENKINS_SOURCE_TIMEOUT", default_value = "10000")]
+    timeout: u64,
+
+    /// File to keep published build numbers in across restarts.
+    #[arg(long, env = "JENKINS_SOURCE_STATE_FILE")]
+    state_file: Option<PathBuf>,
+
+    /// Verify external dependencies and exit without connecting to the engine.
+    #[arg(long)]
+    self_test: bool,
+
+    #[command(flatten)]
+    source: SourceArgs,
+
+    #[command(flatten)]
+    capabilities: CapabilityArgs,
+}
+
+/// Variables available to `--emit-type-template`.
+const TEMPLATE_VARIABLES: &[&str] = &["source"];
+
+/// State shared by the webhook handler and the poller.
+struct Shared {
+    outlet: Arc<Outlet>,
+    jenkins: Jenkins,
+    seen: Mutex<Seen>,
+    state_file: Option<PathBuf>,
+    secret: Option<String>,
+}
+
+impl Shared {
+    /// Publish `event_type` for `build`; if it is lost it is forgotten, so
+    /// a later delivery or poll tries again. Returns whether it was kept.
+    async fn publish(&self, event_type: &'static str, build: &Build) -> bool {
+        // The outlet logs and reports what it cannot deliver
+        let kept = self
+            .outlet
+            .publish(event_type, &[], build.payload())
+            .await
+            .is_ok();
+        if !kept {
+            self.seen().forget(build, event_type);
+        }
+        kept
+    }
+
+    fn seen(&self) -> std::sync::MutexGuard<'_, Seen> {
+        self.seen.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn save(&self) {
+        if let Some(path) = &self.state_file {
+            let seen = self.seen().clone();
+            if let Err(e) = save_state(path, &seen) {
+                eprintln!("Failed to save state: {e}");
+            }
+        }
+    }
+}
+
+/// The state saved in `path`, if any.
+fn load_state(path: &Path) -> Result<Option<Seen>, String> {
+    match std::fs::read(path) {
+        Ok(bytes) => serde_json::from_slice(&bytes)
+            .map(Some)
+            .map_err(|e| format!("{}: {e}", path.display())),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(format!("{}: {e}", path.display())),
+    }
+}
+
+/// Save `seen` to `path`, replacing it atomically.
+fn save_state(path: &Path, seen: &Seen) -> Result<(), String> {
+    let temp = path.with_extension("tmp");
+    let bytes = serde_json::to_vec(seen).map_err(|e| e.to_string())?;
+    std::fs::write(&temp, bytes)
+        .and_then(|()| std::fs::rename(&temp, path))
+        .map_err(|e| format!("{}: {e}", path.display()))
+}
+
+/// Check that the webhook path is absolute.
+fn check_path(path: &str) -> Result<String, String> {
+    if path.starts_with('/') {
+        Ok(path.to_string())
+    } else {
+        Err(format!("{path} must start with '/'"))
+    }
+}
+
+/// Check that polling has something to poll.
+fn check_poll(args: &Args) -> Result<(), String> {
+    if args.mode != Mode::Poll {
+        return Ok(());
+    }
+    if args.jenkins_url.is_none() {
+        return Err("--mode poll needs --jenkins-url".to_string());
+    }
+    if args.jobs.is_empty() {
+        return Err("--mode poll needs at least one --job".to_string());
+    }
+    Ok(())
+}
+
+fn jenkins(args: &Args) -> Jenkins {
+    Jenkins::new(
+        Client::new(),
+        args.jenkins_url.as_deref(),
+        args.user.as_deref(),
+        args.api_token.as_deref(),
+        Duration::from_millis(args.timeout),
+    )
+}
+
+/// Runs `--self-test` checks and exits.
+async fn self_test(args: &Args, name: &str) -> ! {
+    let mut report = Report::new(name);
+    match args.mode {
+        Mode::Webhook => {
+            let addr = format!("{}:{}", args.host, args.port);
+            let bind = addr
+                .parse::<SocketAddr>()
+                .map_err(|e| format!("{addr}: {e}"))
+                .and_then(|a| {
+                    std::net::TcpListener::bind(a)
+                        .map(|_| addr.clone())
+                        .map_err(|e| format!("{addr}: {e}"))
+                });
+            report.check("bind", bind);
+            report.check("path", check_path(&args.path));
+        }
+        Mode::Poll => {
+            report.check("poll", check_poll(args).map(|()| "configured".to_string()));
+            let jenkins = jenkins(args);
+            for job in &args.jobs {
+                let builds = jenkins
+                    .builds(job)
+                    .await
+                    .map(|builds| format!("{} recent builds", builds.len()));
+                report.check(&format!("job {job}"), builds);
+            }
+        }
+    }
+    if let Some(path) = &args.state_file {
+        let dir = path.parent().filter(|p| !p.as_os_str().is_empty());
+        report.check(
+            "state_file",
+            check_writable_dir(dir.unwrap_or(Path::new("."))),
+        );
+    }
+    args.source.self_test(&mut report, TEMPLATE_VARIABLES);
+    report.finish()
+}
+
+/// Handles incoming Notification plugin deliveries.
+async fn handle_notification(
+    State(shared): State<Arc<Shared>>,
+    Query(query): Query<HashMap<String, String>>,
+    body: Bytes,
+) -> impl IntoResponse {
+    if let Some(secret) = &shared.secret {
+        match query.get("token") {
+            Some(token) if bool::from(token.as_bytes().ct_eq(secret.as_bytes())) => {}
+            Some(_) => return (StatusCode::UNAUTHORIZED, "Invalid token"),
+            None => return (StatusCode::UNAUTHORIZED, "Missing token"),
+        }
+    }
+
+    let Ok(notification) = serde_json::from_slice::<Value>(&body) else {
+        return (StatusCode::BAD_REQUEST, "Body is not JSON");
+    };
+    // Queued builds and unknown phases are accepted and ignored
+    let Some(mut build) = Build::from_notification(&notification) else {
+        return (StatusCode::ACCEPTED, "");
+    };
+    let Some(event_type) = shared.seen().event(&build) else {
+        return (StatusCode::ACCEPTED, "");
+    };
+
+    if build.completed()
+        && let Some(url) = build.url.clone()
+    {
+        match shared.jenkins.build(&build.job, &url).await {
+            Ok(details) => {
+                build.duration_ms = details.duration_ms.or(build.duration_ms);
+                build.started_at = details.started_at.or(build.started_at);
+                if !details.changeset.is_empty() {
+                    build.changeset = details.changeset;
+                }
+                if !details.artifacts.is_empty() {
+                    build.artifacts = details.artifacts;
+                }
+            }
+            Err(e) => eprintln!(
+                "Publishing {} #{} without details: {e}",
+                build.job, build.number
+            ),
+        }
+    }
+
+    let kept = shared.publish(event_type, &build).await;
+    shared.save();
+    if !kept {
+        return (StatusCode::SERVICE_UNAVAILABLE, "Failed to publish event");
+    }
+    (StatusCode::ACCEPTED, "")
+}
+
+/// Poll every job once, marking what is new as published and returning it
+/// oldest first. Jobs seen for the first time are only recorded.
+async fn poll_jobs(
+    jenkins: &Jenkins,
+    jobs: &[String],
+    seen: &Mutex<Seen>,
+) -> Vec<(&'static str, Build)> {
+    let mut new = Vec::new();
+    for job in jobs {
+        let builds = match jenkins.builds(job).await {
+            Ok(builds) => builds,
+            Err(e) => {
+                eprintln!("Polling {job} failed: {e}");
+                continue;
+            }
+        };
+        let mut seen = seen.lock().unwrap_or_else(PoisonError::into_inner);
+        if !seen.knows(job) {
+            eprintln!("Baseline recorded for {job}: {} builds", builds.len());
+            seen.baseline(job, &builds);
+            continue;
+        }
+        for build in builds.into_iter().rev() {
+            if let Some(event_type) = seen.event(&build) {
+                new.push((event_type, build));
+            }
+        }
+    }
+    new
+}
+
+/// Poll until SIGTERM.
+async fn run_poller(args: &Args, shared: &Shared) -> Result<(), Box<dyn std::error::Error>> {
+    let mut sigterm = signal(SignalKind::terminate())?;
+    let mut interval = tokio::time::interval(Duration::from_millis(args.interval));
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+    loop {
+        tokio::select! {
+            _ = sigterm.recv() => break,
+
+            _ = interval.tick() => {
+                let poll = async {
+                    let polled = poll_jobs(&shared.jenkins, &args.jobs, &shared.seen).await;
+                    for (event_type, build) in &polled {
+                        shared.publish(event_type, build).await;
+                    }
+                    shared.save();
+                };
+                tokio::pin!(poll);
+                tokio::select! {
+                    () = &mut poll => {}
+                    _ = sigterm.recv() => {
+                        args.source.drain.drain("poll in progress", &mut poll).await;
+                        break;
+                    }
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Serve Notification plugin deliveries until SIGTERM.
+async fn run_listener(args: &Args, shared: Arc<Shared>) -> Result<(), Box<dyn std::error::Error>> {
+    let app = Router::new()
+        .route(&args.path, post(handle_notification))
+        .with_state(shared);
+
+    let addr: SocketAddr = format!("{}:{}", args.host, args.port).parse()?;
+
+    // Set up SIGTERM handler for graceful shutdown
+    let mut sigterm = signal(SignalKind::terminate())?;
+
+    let shutdown = Arc::new(Notify::new());
+    let server = axum::serve(
+        tokio::net::TcpListener::bind(&addr).await?,
+        app.into_make_service(),
+    )
+    .with_graceful_shutdown({
+        let shutdown = Arc::clone(&shutdown);
+        async move { shutdown.notified().await }
+    })
+    .into_future();
+    tokio::pin!(server);
+
+    tokio::select! {
+        result = &mut server => {
+            result?;
+        }
+        _ = sigterm.recv() => {
+            // Stop accepting connections and let in-flight deliveries finish
+            shutdown.notify_one();
+            args.source.drain.drain("in-flight deliveries", &mut server).await;
+        }
+    }
+    Ok(())
+}
+
+/// Run the primitive with `args` as its command line (the first item is
+/// the program name, as with `std::env::args_os`).
+pub async fn run<I, T>(args: I) -> Result<(), Box<dyn std::error::Error>>
+where
+    I: IntoIterator<Item = T>,
+    T: Into<std::ffi::OsString> + Clone,
+{
+    let args = Args::parse_from(args);
+
+    // Get the source name from environment (set by engine) or use default
+    let name = std::env::var("EMERGENT_NAME").unwrap_or_else(|_| "jenkins-source".to_string());
+
+    if args.self_test {
+        self_test(&args, &name).await;
+    }
+    if let Err(e) = check_path(&args.path)
+        .map(|_| ())
+        .and_then(|()| check_poll(&args))
+        .and_then(|()| args.source.validate(TEMPLATE_VARIABLES))
+    {
+        eprintln!("Error: {e}");
+        std::process::exit(1);
+    }
+    let seen = match args.state_file.as_deref().map(load_state).transpose() {
+        Ok(state) => state.flatten().unwrap_or_default(),
+        Err(e) => {
+            eprintln!("Error: cannot read state file {e}");
+            std::process::exit(1);
+        }
+    };
+
+    let produces = args.source.produces(&EVENT_TYPES);
+    let produces: Vec<&str> = produces.iter().map(String::as_str).collect();
+    let descriptor = args
+        .capabilities
+        .describe(&name, Role::Source, &[], &produces, &args);
+    if args.capabilities.capabilities {
+        capabilities::print(&descriptor);
+    }
+
+    // Connect to the Emergent engine (silently - lifecycle events come from engine)
+    let source = match EmergentSource::connect(&name).await {
+        Ok(s) => s,
+        Err(e) => {
+            eprintln!("Failed to connect to Emergent engine: {e}");
+            std::process::exit(1);
+        }
+    };
+    let outlet = Arc::new(Outlet::new(source, &name, &args.source)?);
+    if args.capabilities.announce {
+        outlet.announce(capabilities_message(&descriptor)).await;
+    }
+    tokio::spawn(Arc::clone(&outlet).drain_every(args.source.spool.retry_interval()));
+
+    let shared = Arc::new(Shared {
+        outlet: Arc::clone(&outlet),
+        jenkins: jenkins(&args),
+        seen: Mutex::new(seen),
+        state_file: args.state_file.clone(),
+        secret: args.secret.clone(),
+    });
+    match args.mode {
+        Mode::Webhook => run_listener(&args, Arc::clone(&shared)).await?,
+        Mode::Poll => run_poller(&args, &shared).await?,
+    }
+    outlet.disconnect().await;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{Json, routing::get};
+    use emergent_testkit::{MockEngine, fixtures};
+    use serde_json::json;
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    /// Serve a fake Jenkins job with build 41 finished and, once `started`
+    /// is set, build 42 running.
+    async fn server(started: Arc<AtomicBool>) -> String {
+        let app = Router::new().route(
+            "/job/deploy/job/prod/api/json",
+            get(move || {
+                let started = Arc::clone(&started);
+                async move {
+                    let mut builds = vec![json!({
+                        "number": 41, "building": false, "result": "SUCCESS",
+                        "url": "http://jenkins/job/deploy/job/prod/41/"
+                    })];
+                    if started.load(Ordering::SeqCst) {
+                        builds.insert(0, json!({"number": 42, "building": true, "result": null}));
+                    }
+                    Json(json!({"builds": builds}))
+                }
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap_or_else(|e| panic!("bind: {e}"));
+        let addr = listener
+            .local_addr()
+            .unwrap_or_else(|e| panic!("local_addr: {e}"));
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        format!("http://{addr}")
+    }
+
+    #[tokio::test]
+    async fn polls_publish_only_builds_newer_than_the_baseline() {
+        let started = Arc::new(AtomicBool::new(false));
+        let base = server(Arc::clone(&started)).await;
+        let jenkins = Jenkins::new(
+            Client::new(),
+            Some(&base),
+            None,
+            None,
+            Duration::from_secs(5),
+        );
+        let jobs = vec!["deploy/prod".to_string()];
+        let seen = Mutex::new(Seen::default());
+
+        assert!(poll_jobs(&jenkins, &jobs, &seen).await.is_empty());
+        started.store(true, Ordering::SeqCst);
+        let polled = poll_jobs(&jenkins, &jobs, &seen).await;
+        assert_eq!(polled.len(), 1);
+        assert_eq!(polled[0].0, "ci.build.started");
+        assert_eq!(polled[0].1.number, 42);
+        assert_eq!(polled[0].1.job, "deploy/prod");
+        assert!(poll_jobs(&jenkins, &jobs, &seen).await.is_empty());
+    }
+
+    #[test]
+    fn state_survives_a_round_trip() {
+        let dir = std::env::temp_dir().join(format!("jenkins-source-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap_or_else(|e| panic!("mkdir: {e}"));
+        let path = dir.join("state.json");
+        assert_eq!(load_state(&path), Ok(None));
+
+        let mut seen = Seen::default();
+        seen.baseline("app", &[]);
+        save_state(&path, &seen).unwrap_or_else(|e| panic!("save: {e}"));
+        assert_eq!(load_state(&path), Ok(Some(seen)));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    /// Connect to the engine on `socket` as the source would.
+    async fn connect(socket: &Path, args: &Args) -> Arc<Shared> {
+        let source = EmergentSource::connect_to("jenkins-source", socket)
+            .await
+            .unwrap_or_else(|e| panic!("connect: {e}"));
+        let outlet = Outlet::new(source, "jenkins-source", &args.source)
+            .unwrap_or_else(|e| panic!("open spool: {e}"));
+        Arc::new(Shared {
+            outlet: Arc::new(outlet),
+            jenkins: jenkins(args),
+            seen: Mutex::new(Seen::default()),
+            state_file: None,
+            secret: None,
+        })
+    }
+
+    fn started() -> Value {
+        json!({"name": "deploy/prod", "build": {"phase": "STARTED", "number": 42}})
+    }
+
+    async fn notify(shared: &Arc<Shared>, notification: &Value) -> StatusCode {
+        handle_notification(
+            State(Arc::clone(shared)),
+            Query(HashMap::new()),
+            Bytes::from(notification.to_string()),
+        )
+        .await
+        .into_response()
+        .status()
+    }
+
+    #[tokio::test]
+    async fn builds_survive_the_engine_being_down() {
+        let dir = fixtures::TempDir::new("jenkins-source-spool");
+        let socket = dir.path().join("engine.sock");
+        let spool = dir.path().join("spool");
+        let args = Args::parse_from([
+            "jenkins-source",
+            "--spool-dir",
+            spool.to_str().unwrap_or_default(),
+        ]);
+
+        let mut engine = MockEngine::serve(&socket);
+        let shared = connect(&socket, &args).await;
+        engine.shut_down().await;
+        assert_eq!(notify(&shared, &started()).await, StatusCode::ACCEPTED);
+        drop(shared);
+
+        let mut engine = MockEngine::serve(&socket);
+        let shared = connect(&socket, &args).await;
+        shared.outlet.drain_spool().await;
+        let published = engine.expect_published("ci.build.started").await;
+        assert_eq!(published.payload()["number"], 42);
+        engine.shut_down().await;
+    }
+
+    #[tokio::test]
+    async fn lost_builds_are_refused_and_reported_again() {
+        let dir = fixtures::TempDir::new("jenkins-source-down");
+        let socket = dir.path().join("engine.sock");
+        let args = Args::parse_from(["jenkins-source"]);
+
+        let mut engine = MockEngine::serve(&socket);
+        let shared = connect(&socket, &args).await;
+        engine.shut_down().await;
+        assert_eq!(
+            notify(&shared, &started()).await,
+            StatusCode::SERVICE_UNAVAILABLE
+        );
+        // Forgotten, so Jenkins' retry is published rather than skipped
+        let build = Build::from_notification(&started())
+            .unwrap_or_else(|| panic!("notification not parsed"));
+        assert_eq!(shared.seen().event(&build), Some("ci.build.started"));
+    }
+}
@@ -0,0 +1,234 @@
+//! Kafka consumer group lag.
+//!
+//! `kafka://BROKER?group=GROUP` is sampled by running Kafka's own
+//! `kafka-consumer-groups.sh --describe`, so any broker version and
+//! security setup it handles (through `--kafka-config`) works here too.
+//! One broker is enough to bootstrap from; the tool finds the rest.
+//! Its table has a row per partition:
+//!
+//! ```text
+//! GROUP    TOPIC   PARTITION  CURRENT-OFFSET  LOG-END-OFFSET  LAG  CONSUMER-ID  HOST  CLIENT-ID
+//! billing  orders  0          1200            1250            50   consumer-1   /10.0.0.7  consumer-1
+//! ```
+//!
+//! The lag is summed per topic; partitions the group has never committed
+//! show `-` and are counted as `uncommitted` instead.
+
+use serde_json::{Map, Value, json};
+use std::collections::BTreeMap;
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::process::Command;
+
+/// A consumer group on a cluster.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Group {
+    pub broker: String,
+    pub group: String,
+}
+
+impl Group {
+    /// Parse a `kafka://` URI.
+    pub fn parse(uri: &str) -> Result<Self, String> {
+        let rest = uri
+            .strip_prefix("kafka://")
+            .ok_or_else(|| format!("'{uri}': expected kafka://"))?;
+        let (broker, query) = rest.split_once('?').unwrap_or((rest, ""));
+        let group = query
+            .split('&')
+            .find_map(|pair| pair.strip_prefix("group="))
+            .filter(|group| !group.is_empty())
+            .ok_or_else(|| format!("'{uri}': no group="))?;
+        let broker = broker.trim_end_matches('/');
+        if broker.is_empty() {
+            return Err(format!("'{uri}': no broker"));
+        }
+        Ok(Self {
+            broker: broker.to_string(),
+            group: group.to_string(),
+        })
+    }
+
+    /// The command line describing the group.
+    pub fn argv(&self, command: &str, config: Option<&str>) -> Vec<String> {
+        let mut argv: Vec<String> = [
+            command,
+            "--bootstrap-server",
+            &self.broker,
+            "--describe",
+            "--group",
+            &self.group,
+        ]
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+        if let Some(config) = config {
+            argv.extend(["--command-config".to_string(), config.to_string()]);
+        }
+        argv
+    }
+
+    /// The group's total lag, and its lag per topic.
+    pub async fn sample(
+        &self,
+        command: &str,
+        config: Option<&str>,
+        timeout: Duration,
+    ) -> Result<(u64, Map<String, Value>), String> {
+        let argv = self.argv(command, config);
+        let child = Command::new(&argv[0])
+            .args(&argv[1..])
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(|e| format!("{command}: {e}"))?;
+        let output = tokio::time::timeout(timeout, child.wait_with_output())
+            .await
+            .map_err(|_| format!("{command}: timed out"))?
+            .map_err(|e| format!("{command}: {e}"))?;
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            return Err(format!("{command}: {} {}", output.status, stderr.trim()));
+        }
+        describe(&stdout).map_err(|e| format!("{}: {e}", self.group))
+    }
+}
+
+/// Sum the lag in `describe` output.
+pub fn describe(output: &str) -> Result<(u64, Map<String, Value>), String> {
+    let mut lines = output.lines();
+    let header: Vec<&str> = lines
+        .by_ref()
+        .map(|line| line.split_whitespace().collect::<Vec<_>>())
+        .find(|columns| columns.contains(&"TOPIC") && columns.contains(&"LAG"))
+        // The tool explains a missing group on stdout, and exits 0
+        .ok_or_else(|| match output.trim() {
+            "" => "no partitions".to_string(),
+            text => text.lines().last().unwrap_or(text).to_string(),
+        })?;
+    let column = |name| header.iter().position(|column| *column == name);
+    let (Some(topic), Some(lag)) = (column("TOPIC"), column("LAG")) else {
+        return Err("no TOPIC or LAG column".to_string());
+    };
+    let mut topics: BTreeMap<String, u64> = BTreeMap::new();
+    let (mut partitions, mut uncommitted) = (0, 0);
+    for line in lines {
+        let columns: Vec<&str> = line.split_whitespace().collect();
+        let (Some(name), Some(value)) = (columns.get(topic), columns.get(lag)) else {
+            continue;
+        };
+        // A later group's header, when several are described
+        if *name == "TOPIC" {
+            continue;
+        }
+        partitions += 1;
+        let total = topics.entry(name.to_string()).or_default();
+        match value.parse::<u64>() {
+            Ok(value) => *total += value,
+            Err(_) => uncommitted += 1,
+        }
+    }
+    let lag = topics.values().sum();
+    let fields = json!({
+        "topics": topics,
+        "partitions": partitions,
+        "uncommitted": uncommitted,
+    });
+    Ok((lag, fields.as_object().cloned().unwrap_or_default()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DESCRIBE: &str = "
+Consumer group 'billing' has no active members.
+
+GROUP           TOPIC           PARTITION  CURRENT-OFFSET  LOG-END-OFFSET  LAG             CONSUMER-ID     HOST            CLIENT-ID
+billing         orders          0          1200            1250            50              -               -               -
+billing         orders          1          980             1000            20              -               -               -
+billing         refunds         0          -               7               -               -               -               -
+billing         invoices        0          310             312             2               -               -               -
+";
+
+    #[test]
+    fn describe_output_is_summed_per_topic() {
+        let (lag, fields) = describe(DESCRIBE).unwrap_or_else(|e| panic!("{e}"));
+        assert_eq!(lag, 72);
+        assert_eq!(
+            Value::Object(fields),
+            json!({
+                "topics": {"invoices": 2, "orders": 70, "refunds": 0},
+                "partitions": 4,
+                "uncommitted": 1,
+            })
+        );
+        assert_eq!(
+            describe("\nError: Consumer group 'nobody' does not exist.\n").err(),
+            Some("Error: Consumer group 'nobody' does not exist.".to_string())
+        );
+
+        let group =
+            Group::parse("kafka://k1:9092/?group=billing").unwrap_or_else(|e| panic!("{e}"));
+        assert_eq!(
+            group.argv(
+                "kafka-consumer-groups.sh",
+                Some("/etc/kafka/client.properties")
+            ),
+            [
+                "kafka-consumer-groups.sh",
+                "--bootstrap-server",
+                "k1:9092",
+                "--describe",
+                "--group",
+                "billing",
+                "--command-config",
+                "/etc/kafka/client.properties",
+            ]
+        );
+        assert!(Group::parse("kafka://k1:9092").is_err());
+    }
+
+    #[tokio::test]
+    async fn the_describe_command_is_run() {
+        let dir = std::env::temp_dir().join(format!("lag-source-kafka-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap_or_else(|e| panic!("{e}"));
+        let script = dir.join("kafka-consumer-groups.sh");
+        let output = dir.join("describe.txt");
+        std::fs::write(&output, DESCRIBE).unwrap_or_else(|e| panic!("{e}"));
+        std::fs::write(
+            &script,
+            format!(
+                "#!/bin/sh\n[ \"$2\" = k1:9092 ] && [ \"$5\" = billing ] || exit 2\ncat {}\n",
+                output.display()
+            ),
+        )
+        .unwrap_or_else(|e| panic!("{e}"));
+        let status = std::process::Command::new("chmod")
+            .arg("+x")
+            .arg(&script)
+            .status()
+            .unwrap_or_else(|e| panic!("chmod: {e}"));
+        assert!(status.success());
+
+        let group = Group::parse("kafka://k1:9092?group=billing").unwrap_or_else(|e| panic!("{e}"));
+        let command = script.to_string_lossy();
+        let (lag, _) = group
+            .sample(&command, None, Duration::from_secs(5))
+            .await
+            .unwrap_or_else(|e| panic!("{e}"));
+        assert_eq!(lag, 72);
+
+        let other = Group::parse("kafka://k1:9092?group=other").unwrap_or_else(|e| panic!("{e}"));
+        let error = other
+            .sample(&command, None, Duration::from_secs(5))
+            .await
+            .err()
+            .unwrap_or_default();
+        assert!(error.contains("exit status: 2"), "{error}");
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,642 @@
+//! Lag Source - External Queue Backlogs
+//!
+//! A Source that samples the queues a pipeline depends on every
+//! `--interval` milliseconds and publishes `queue.lag` with how far behind
+//! their consumers are. Queues are given as `--queue NAME=URI`, where the
+//! URI names the system:
+//!
+//! - `kafka://BROKER?group=GROUP` — a Kafka consumer group's lag, summed
+//!   over its partitions (see [`kafka`])
+//! - `sqs://sqs.REGION.amazonaws.com/ACCOUNT/QUEUE` — an SQS queue's
+//!   visible messages (see [`sqs`])
+//! - `redis://HOST?stream=KEY&group=GROUP` — a Redis stream consumer
+//!   group's pending and undelivered entries
+//! - `sidekiq://HOST?queue=NAME` and `celery://HOST?queue=NAME` — the
+//!   depth of a Sidekiq or Celery queue on Redis (see [`redis`])
+//!
+//! With `--threshold [QUEUE=]LAG`, `queue.backlog_alert` is published when
+//! a queue's lag reaches the threshold, and again when it falls back.
+//!
+//! `--emit-type-template` can also use `{kind}`, the system sampled.
+//!
+//! Sources are SILENT - they only produce domain messages.
+//! All lifecycle events are published by the engine.
+//!
+//! # Usage
+//!
+//! ```bash
+//! lag-source --queue billing=kafka://kafka:9092?group=billing \
+//!   --queue mail=sidekiq://redis:6379?queue=mailers \
+//!   --threshold 10000 --threshold mail=500
+//! ```
+//!
+//! On SIGTERM sampling stops and samples already taken get
+//! `--drain-timeout` milliseconds to be published.
+
+pub mod kafka;
+pub mod redis;
+pub mod sqs;
+
+use clap::Parser;
+use emergent_client::EmergentSource;
+use primitive_common::aws::{self, CredentialChain, Credentials};
+use primitive_common::capabilities::{self, CapabilityArgs, Role, VERSION, capabilities_message};
+use primitive_common::doctor::Report;
+use primitive_common::source::{Outlet, SourceArgs};
+use primitive_common::time;
+use reqwest::Client;
+use serde_json::{Map, Value, json};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::signal::unix::{SignalKind, signal};
+use tokio::sync::mpsc;
+
+/// The event type published with each sample.
+pub const LAG_EVENT_TYPE: &str = "queue.lag";
+
+/// The event type published when a queue's lag crosses its threshold.
+pub const ALERT_EVENT_TYPE: &str = "queue.backlog_alert";
+
+/// Variables available to `--emit-type-template`.
+const TEMPLATE_VARIABLES: &[&str] = &["source", "kind"];
+
+/// External queue sampler that emits queue.lag events.
+#[derive(Parser, Debug, Clone)]
+#[command(name = "lag-source", version = VERSION)]
+#[command(about = "Samples Kafka, SQS, Redis, Sidekiq and Celery backlogs and emits queue lag")]
+struct Args {
+    /// Queue to sample as NAME=URI, where URI is kafka://BROKER?group=G,
+    /// sqs://HOST/ACCOUNT/QUEUE, redis://HOST?stream=S&group=G,
+    /// sidekiq://HOST?queue=Q or celery://HOST?queue=Q (repeatable or
+    /// comma-separated).
+    #[arg(
+        long = "queue",
+        env = "LAG_SOURCE_QUEUES",
+        value_delimiter = ',',
+        required = true
+    )]
+    queues: Vec<String>,
+
+    /// Milliseconds between samples.
+    #[arg(long, env = "LAG_SOURCE_INTERVAL", default_value = "30000")]
+    interval: u64,
+
+    /// Alert when a queue's lag reaches LAG, as [QUEUE=]LAG; without a
+    /// queue it applies to all the others (repeatable or comma-separated).
+    #[arg(
+        long = "threshold",
+        env = "LAG_SOURCE_THRESHOLDS",
+        value_delimiter = ','
+    )]
+    thresholds: Vec<String>,
+
+    /// Kafka's consumer group tool.
+    #[arg(
+        long,
+        env = "LAG_SOURCE_KAFKA_COMMAND",
+        default_value = "kafka-consumer-groups.sh"
+    )]
+    kafka_command: String,
+
+    /// Client properties file for secured Kafka clusters, passed as
+    /// `--command-config`.
+    #[arg(long, env = "LAG_SOURCE_KAFKA_CONFIG")]
+    kafka_config: Option<String>,
+
+    /// Region of SQS queues whose host does not name one (default:
+    /// `AWS_DEFAULT_REGION`, or us-east-1).
+    #[arg(long, env = "AWS_REGION")]
+    aws_region: Option<String>,
+
+    /// AWS access key ID, for SQS queues (default: the AWS credential
+    /// chain).
+    #[arg(long, env = "AWS_ACCESS_KEY_ID")]
+    aws_access_key_id: Option<String>,
+
+    /// AWS secret access key.
+    #[arg(long, env = "AWS_SECRET_ACCESS_KEY", hide_env_values = true)]
+    aws_secret_access_key: Option<String>,
+
+    /// Session token of temporary AWS credentials.
+    #[arg(long, env = "AWS_SESSION_TOKEN", hide_env_values = true)]
+    aws_session_token: Option<String>,
+
+    /// Timeout for each sample in milliseconds.
+    #[arg(long, env = "LAG_SOURCE_TIMEOUT", default_value = "10000")]
+    timeout: u64,
+
+    /// Verify external dependencies and exit without connecting to the engine.
+    #[arg(long)]
+    self_test: bool,
+
+    #[command(flatten)]
+    source: SourceArgs,
+
+    #[command(flatten)]
+    capabilities: CapabilityArgs,
+}
+
+/// Where a queue lives.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Target {
+    Kafka(kafka::Group),
+    Sqs(sqs::Queue),
+    Redis(redis::Queue),
+}
+
+impl Target {
+    fn kind(&self) -> &'static str {
+        match self {
+            Target::Kafka(_) => "kafka",
+            Target::Sqs(_) => "sqs",
+            Target::Redis(queue) => queue.kind(),
+        }
+    }
+}
+
+/// Split a `NAME=URI` queue spec.
+fn parse_queue(spec: &str) -> Result<(String, Target), String> {
+    let (name, uri) = spec
+        .split_once('=')
+        .map(|(name, uri)| (name.trim(), uri.trim()))
+        .filter(|(name, uri)| !name.is_empty() && !uri.is_empty())
+        .ok_or_else(|| format!("expected NAME=URI, got '{spec}'"))?;
+    let scheme = uri.split_once("://").map_or("", |(scheme, _)| scheme);
+    let target = match scheme {
+        "kafka" => Target::Kafka(kafka::Group::parse(uri)?),
+        "sqs" | "sqs+http" => Target::Sqs(sqs::Queue::parse(uri)?),
+        "redis" | "sidekiq" | "celery" => Target::Redis(redis::Queue::parse(uri)?),
+        _ => {
+            return Err(format!(
+                "'{uri}': expected kafka://, sqs://, redis://, sidekiq:// or celery://"
+            ));
+        }
+    };
+    Ok((name.to_string(), target))
+}
+
+/// A `--threshold`: a lag, for one queue or all of them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Threshold {
+    queue: Option<String>,
+    lag: u64,
+}
+
+impl Threshold {
+    /// Parse `[QUEUE=]LAG`.
+    fn parse(spec: &str) -> Result<Self, String> {
+        let (queue, lag) = match spec.split_once('=') {
+            Some((queue, lag)) => (Some(queue.trim().to_string()), lag),
+            None => (None, spec),
+        };
+        let lag = lag
+            .trim()
+            .parse()
+            .map_err(|_| format!("'{spec}': '{}' is not a lag", lag.trim()))?;
+        Ok(Self { queue, lag })
+    }
+}
+
+/// What is needed to sample any queue.
+struct Samplers {
+    client: Client,
+    kafka_command: String,
+    kafka_config: Option<String>,
+    aws_region: String,
+    credentials: CredentialChain,
+    timeout: Duration,
+}
+
+impl Samplers {
+    /// The lag of `target`, and what it is made of.
+    async fn sample(&self, target: &Target) -> Result<(u64, Map<String, Value>), String> {
+        match target {
+            Target::Kafka(group) => {
+                group
+                    .sample(
+                        &self.kafka_command,
+                        self.kafka_config.as_deref(),
+                        self.timeout,
+                    )
+                    .await
+            }
+            Target::Sqs(queue) => {
+                let credentials = self.credentials.credentials().await?;
+                queue
+                    .sample(&self.client, &self.aws_region, &credentials, self.timeout)
+                    .await
+            }
+            Target::Redis(queue) => queue.sample(self.timeout, time::now() as f64).await,
+        }
+    }
+}
+
+/// A sample of one queue, for the main loop to publish.
+struct Sample {
+    queue: String,
+    kind: &'static str,
+    lag: u64,
+    fields: Map<String, Value>,
+    sampled_at: i64,
+}
+
+/// Sample a queue every `interval` until the task is aborted. Failures
+/// are logged once until the queue can be sampled again.
+async fn poll(
+    queue: String,
+    target: Target,
+    samplers: Arc<Samplers>,
+    interval: Duration,
+    tx: mpsc::UnboundedSender<Sample>,
+) {
+    let mut ticks = tokio::time::interval(interval);
+    ticks.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    let mut failing = false;
+    loop {
+        ticks.tick().await;
+        match samplers.sample(&target).await {
+            Ok((lag, fields)) => {
+                if failing {
+                    eprintln!("{queue}: sampling again");
+                    failing = false;
+                }
+                let sample = Sample {
+                    queue: queue.clone(),
+                    kind: target.kind(),
+                    lag,
+                    fields,
+                    sampled_at: time::now(),
+                };
+                if tx.send(sample).is_err() {
+                    return;
+                }
+            }
+            Err(e) => {
+                if !failing {
+                    eprintln!("{queue}: {e}");
+                    failing = true;
+                }
+            }
+        }
+    }
+}
+
+/// The last lag of each queue, and which are over their threshold.
+#[derive(Default)]
+struct Backlogs {
+    last: HashMap<String, u64>,
+    backlogged: HashMap<String, bool>,
+}
+
+impl Backlogs {
+    /// The `queue.lag` payload of `sample`, and the `queue.backlog_alert`
+    /// payload when it crossed its threshold. A queue already over its
+    /// threshold when first sampled alerts straight away.
+    fn record(&mut self, sample: &Sample, thresholds: &[Threshold]) -> (Value, Option<Value>) {
+        let previous = self.last.insert(sample.queue.clone(), sample.lag);
+        let mut payload = json!({
+            "queue": sample.queue,
+            "kind": sample.kind,
+            "lag": sample.lag,
+            "change": previous.map(|previous| sample.lag as i64 - previous as i64),
+            "sampled_at": time::format(sample.sampled_at),
+        });
+        if let Some(payload) = payload.as_object_mut() {
+            payload.extend(sample.fields.clone());
+        }
+
+        let threshold = thresholds
+            .iter()
+            .find(|threshold| threshold.queue.as_ref() == Some(&sample.queue))
+            .or_else(|| {
+                thresholds
+                    .iter()
+                    .find(|threshold| threshold.queue.is_none())
+            });
+        let Some(threshold) = threshold else {
+            return (payload, None);
+        };
+        let over = sample.lag >= threshold.lag;
+        let was = self.backlogged.insert(sample.queue.clone(), over);
+        let alert = (was.unwrap_or(false) != over).then(|| {
+            json!({
+                "queue": sample.queue,
+                "kind": sample.kind,
+                "lag": sample.lag,
+                "threshold": threshold.lag,
+                "state": if over { "backlogged" } else { "recovered" },
+                "sampled_at": time::format(sample.sampled_at),
+            })
+        });
+        (payload, alert)
+    }
+}
+
+/// Publish a sample's lag and the alert it raises, if any; the outlet logs
+/// and reports what is lost.
+async fn publish(
+    outlet: &Outlet,
+    backlogs: &mut Backlogs,
+    thresholds: &[Threshold],
+    sample: Sample,
+) {
+    let (payload, alert) = backlogs.record(&sample, thresholds);
+    let vars = [("kind", sample.kind)];
+    let _ = outlet.publish(LAG_EVENT_TYPE, &vars, payload).await;
+    if let Some(alert) = alert {
+        let _ = outlet.publish(ALERT_EVENT_TYPE, &vars, alert).await;
+    }
+}
+
+/// Runs `--self-test` checks and exits.
+async fn self_test(
+    queues: &[(String, Target)],
+    samplers: &Samplers,
+    source: &SourceArgs,
+    name: &str,
+) -> ! {
+    let mut report = Report::new(name);
+    for (queue, target) in queues {
+        let result = samplers
+            .sample(target)
+            .await
+            .map(|(lag, _)| format!("{} lag {lag}", target.kind()));
+        report.check(queue, result);
+    }
+    source.self_test(&mut report, TEMPLATE_VARIABLES);
+    report.finish()
+}
+
+/// Run the primitive with `args` as its command line (the first item is
+/// the program name, as with `std::env::args_os`).
+pub async fn run<I, T>(args: I) -> Result<(), Box<dyn std::error::Error>>
+where
+    I: IntoIterator<Item = T>,
+    T: Into<std::ffi::OsString> + Clone,
+{
+    let args = Args::parse_from(args);
+
+    // Get the source name from environment (set by engine) or use default
+    let name = std::env::var("EMERGENT_NAME").unwrap_or_else(|_| "lag-source".to_string());
+
+    let exit = |e: String| -> ! {
+        eprintln!("Error: {e}");
+        std::process::exit(1);
+    };
+    if args.interval == 0 {
+        exit("--interval must be positive".to_string());
+    }
+    if let Err(e) = args.source.validate(TEMPLATE_VARIABLES) {
+        exit(e);
+    }
+    let mut queues: Vec<(String, Target)> = Vec::new();
+    for spec in &args.queues {
+        let (queue, target) = parse_queue(spec).unwrap_or_else(|e| exit(format!("--queue: {e}")));
+        if queues.iter().any(|(known, _)| *known == queue) {
+            exit(format!("--queue: '{queue}' given twice"));
+        }
+        queues.push((queue, target));
+    }
+    let thresholds = args
+        .thresholds
+        .iter()
+        .map(|spec| Threshold::parse(spec))
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap_or_else(|e| exit(format!("--threshold: {e}")));
+    if let Some(queue) = thresholds
+        .iter()
+        .filter_map(|threshold| threshold.queue.as_ref())
+        .find(|queue| !queues.iter().any(|(known, _)| known == *queue))
+    {
+        exit(format!("--threshold: no --queue named '{queue}'"));
+    }
+    let keys = match (&args.aws_access_key_id, &args.aws_secret_access_key) {
+        (Some(access_key_id), Some(secret_access_key)) => Some(Credentials {
+            access_key_id: access_key_id.clone(),
+            secret_access_key: secret_access_key.clone(),
+            session_token: args.aws_session_token.clone(),
+        }),
+        _ => None,
+    };
+    let client = Client::new();
+    let samplers = Arc::new(Samplers {
+        credentials: CredentialChain::new(client.clone(), keys, None),
+        client,
+        kafka_command: args.kafka_command.clone(),
+        kafka_config: args.kafka_config.clone(),
+        aws_region: args.aws_region.clone().unwrap_or_else(aws::default_region),
+        timeout: Duration::from_millis(args.timeout),
+    });
+    let interval = Duration::from_millis(args.interval);
+
+    if args.self_test {
+        self_test(&queues, &samplers, &args.source, &name).await;
+    }
+
+    let produces = args.source.produces(&[LAG_EVENT_TYPE, ALERT_EVENT_TYPE]);
+    let produces: Vec<&str> = produces.iter().map(String::as_str).collect();
+    let descriptor = args
+        .capabilities
+        .describe(&name, Role::Source, &[], &produces, &args);
+    if args.capabilities.capabilities {
+        capabilities::print(&descriptor);
+    }
+
+    // Connect to the Emergent engine (silently - lifecycle events come from engine)
+    let source = match EmergentSource::connect(&name).await {
+        Ok(s) => s,
+        Err(e) => {
+            eprintln!("Failed to connect to Emergent engine: {e}");
+            std::process::exit(1);
+        }
+    };
+    let outlet = Arc::new(Outlet::new(source, &name, &args.source)?);
+    if args.capabilities.announce {
+        outlet.announce(capabilities_message(&descriptor)).await;
+    }
+    tokio::spawn(Arc::clone(&outlet).drain_every(args.source.spool.retry_interval()));
+
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let pollers: Vec<_> = queues
+        .into_iter()
+        .map(|(queue, target)| {
+            tokio::spawn(poll(queue, target, samplers.clone(), interval, tx.clone()))
+        })
+        .collect();
+
+    let mut backlogs = Backlogs::default();
+    let mut sigterm = signal(SignalKind::terminate())?;
+    loop {
+        tokio::select! {
+            _ = sigterm.recv() => break,
+
+            Some(sample) = rx.recv() => {
+                publish(&outlet, &mut backlogs, &thresholds, sample).await;
+            }
+        }
+    }
+    // Stop sampling, then publish what was already sampled
+    for poller in pollers {
+        poller.abort();
+    }
+    rx.close();
+    let queued = async {
+        while let Some(sample) = rx.recv().await {
+            publish(&outlet, &mut backlogs, &thresholds, sample).await;
+        }
+    };
+    args.source.drain.drain("queued samples", queued).await;
+    outlet.disconnect().await;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(queue: &str, lag: u64) -> Sample {
+        Sample {
+            queue: queue.to_string(),
+            kind: "sidekiq",
+            lag,
+            fields: json!({"depth": lag})
+                .as_object()
+                .cloned()
+                .unwrap_or_default(),
+            sampled_at: 1_716_221_758,
+        }
+    }
+
+    #[test]
+    fn queue_uris_name_the_system() {
+        let kind = |spec| {
+            parse_queue(spec)
+                .map(|(_, target)| target.kind())
+                .unwrap_or_else(|e| panic!("{e}"))
+        };
+        assert_eq!(kind("billing=kafka://kafka:9092?group=billing"), "kafka");
+        assert_eq!(
+            kind("orders = sqs://sqs.eu-west-1.amazonaws.com/123456789012/orders"),
+            "sqs"
+        );
+        assert_eq!(
+            kind("events=redis://redis?stream=events&group=etl"),
+            "redis-stream"
+        );
+        assert_eq!(kind("mail=sidekiq://redis?queue=mailers"), "sidekiq");
+        assert_eq!(kind("tasks=celery://redis/1"), "celery");
+        assert!(parse_queue("jobs=amqp://rabbit/jobs").is_err());
+        assert!(parse_queue("kafka://kafka:9092?group=billing").is_err());
+
+        assert_eq!(
+            Threshold::parse("mail=500"),
+            Ok(Threshold {
+                queue: Some("mail".to_string()),
+                lag: 500
+            })
+        );
+        assert!(Threshold::parse("mail=lots").is_err());
+    }
+
+    #[test]
+    fn backlogs_alert_when_crossing_the_threshold() {
+        let thresholds = [
+            Threshold::parse("10000").unwrap_or_else(|e| panic!("{e}")),
+            Threshold::parse("mail=500").unwrap_or_else(|e| panic!("{e}")),
+        ];
+        let mut backlogs = Backlogs::default();
+
+        let (payload, alert) = backlogs.record(&sample("mail", 120), &thresholds);
+        assert_eq!(
+            payload,
+            json!({
+                "queue": "mail",
+                "kind": "sidekiq",
+                "lag": 120,
+                "change": null,
+                "sampled_at": "2024-05-20T16:15:58Z",
+                "depth": 120,
+            })
+        );
+        assert_eq!(alert, None);
+
+        let (payload, alert) = backlogs.record(&sample("mail", 640), &thresholds);
+        assert_eq!(payload["change"], 520);
+        assert_eq!(
+            alert,
+            Some(json!({
+                "queue": "mail",
+                "kind": "sidekiq",
+                "lag": 640,
+                "threshold": 500,
+                "state": "backlogged",
+                "sampled_at": "2024-05-20T16:15:58Z",
+            }))
+        );
+        assert_eq!(backlogs.record(&sample("mail", 900), &thresholds).1, None);
+        let (payload, alert) = backlogs.record(&sample("mail", 20), &thresholds);
+        assert_eq!(payload["change"], -880);
+        assert_eq!(
+            alert.map(|alert| alert["state"].clone()),
+            Some(json!("recovered"))
+        );
+
+        // Other queues use the default threshold, and alert on a first sample
+        let (_, alert) = backlogs.record(&sample("billing", 12000), &thresholds);
+        assert_eq!(
+            alert.map(|alert| alert["threshold"].clone()),
+            Some(json!(10000))
+        );
+        assert_eq!(backlogs.record(&sample("other", 640), &[]).1, None);
+    }
+
+    /// Connect to the engine on `socket` as the source would.
+    async fn connect(socket: &std::path::Path, args: &[&str]) -> Outlet {
+        let mut argv = vec![
+            "lag-source",
+            "--queue",
+            "mail=sidekiq://redis?queue=mailers",
+        ];
+        argv.extend_from_slice(args);
+        let args = Args::parse_from(argv);
+        let source = EmergentSource::connect_to("lag-source", socket)
+            .await
+            .unwrap_or_else(|e| panic!("connect: {e}"));
+        Outlet::new(source, "lag-source", &args.source)
+            .unwrap_or_else(|e| panic!("open spool: {e}"))
+    }
+
+    #[tokio::test]
+    async fn samples_survive_the_engine_being_down() {
+        use emergent_testkit::MockEngine;
+        use emergent_testkit::fixtures::TempDir;
+
+        let dir = TempDir::new("lag-source-spool");
+        let socket = dir.path().join("engine.sock");
+        let spool = dir.path().join("spool");
+        let args = [
+            "--spool-dir",
+            spool.to_str().unwrap_or_default(),
+            "--emit-type-template",
+            "{kind}.{type}",
+        ];
+        let thresholds = [Threshold::parse("mail=500").unwrap_or_else(|e| panic!("{e}"))];
+
+        let mut engine = MockEngine::serve(&socket);
+        let outlet = connect(&socket, &args).await;
+        engine.shut_down().await;
+        let mut backlogs = Backlogs::default();
+        publish(&outlet, &mut backlogs, &thresholds, sample("mail", 640)).await;
+        drop(outlet);
+
+        let mut engine = MockEngine::serve(&socket);
+        connect(&socket, &args).await.drain_spool().await;
+        let lag = engine.expect_published("sidekiq.queue.lag").await;
+        assert_eq!(lag.payload()["lag"], 640);
+        let alert = engine.expect_published("sidekiq.queue.backlog_alert").await;
+        assert_eq!(alert.payload()["state"], "backlogged");
+        engine.shut_down().await;
+    }
+}
@@ -0,0 +1,6 @@
+//! `lag-source` binary; the primitive itself lives in the library.
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    lag_source::run(std::env::args_os()).await
+}
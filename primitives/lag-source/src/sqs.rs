@@ -0,0 +1,233 @@
+//! Amazon SQS queue depth.
+//!
+//! `sqs://sqs.REGION.amazonaws.com/ACCOUNT/QUEUE` is the queue's URL with
+//! its scheme swapped; `sqs+http://HOST:PORT/ACCOUNT/QUEUE` reaches a
+//! local stand-in such as ElasticMQ or LocalStack over plain HTTP. Queues
+//! are read with a `GetQueueAttributes` call in SQS's JSON protocol,
+//! signed with AWS Signature Version 4.
+
+use primitive_common::aws::{Credentials, SigV4};
+use reqwest::Client;
+use serde_json::{Map, Value, json};
+use std::time::{Duration, SystemTime};
+
+/// The attributes read, and the fields they are published as.
+const ATTRIBUTES: [(&str, &str); 3] = [
+    ("ApproximateNumberOfMessages", "visible"),
+    ("ApproximateNumberOfMessagesNotVisible", "in_flight"),
+    ("ApproximateNumberOfMessagesDelayed", "delayed"),
+];
+
+/// A queue.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Queue {
+    /// The service endpoint requests are posted to.
+    pub endpoint: String,
+    /// The queue's URL, as SQS names it.
+    pub url: String,
+    /// The region from the endpoint's host, for AWS endpoints.
+    pub region: Option<String>,
+}
+
+impl Queue {
+    /// Parse an `sqs://` or `sqs+http://` URI.
+    pub fn parse(uri: &str) -> Result<Self, String> {
+        let (scheme, rest) = if let Some(rest) = uri.strip_prefix("sqs://") {
+            ("https", rest)
+        } else if let Some(rest) = uri.strip_prefix("sqs+http://") {
+            ("http", rest)
+        } else {
+            return Err(format!("'{uri}': expected sqs:// or sqs+http://"));
+        };
+        let (host, path) = rest
+            .split_once('/')
+            .filter(|(host, path)| !host.is_empty() && path.trim_matches('/').contains('/'))
+            .ok_or_else(|| format!("'{uri}': expected HOST/ACCOUNT/QUEUE"))?;
+        let region = host
+            .strip_prefix("sqs.")
+            .and_then(|rest| rest.strip_suffix(".amazonaws.com"))
+            .map(str::to_string);
+        let endpoint = format!("{scheme}://{host}");
+        Ok(Self {
+            url: format!("{endpoint}/{}", path.trim_matches('/')),
+            endpoint,
+            region,
+        })
+    }
+
+    /// The queue's visible messages, with those in flight and delayed.
+    pub async fn sample(
+        &self,
+        client: &Client,
+        region: &str,
+        credentials: &Credentials,
+        timeout: Duration,
+    ) -> Result<(u64, Map<String, Value>), String> {
+        let region = self.region.as_deref().unwrap_or(region);
+        let body = json!({
+            "QueueUrl": self.url,
+            "AttributeNames": ATTRIBUTES.map(|(attribute, _)| attribute),
+        })
+        .to_string();
+        let mut request = client
+            .post(format!("{}/", self.endpoint))
+            .timeout(timeout)
+            .header("content-type", "application/x-amz-json-1.0")
+            .header("x-amz-target", "AmazonSQS.GetQueueAttributes")
+            .body(body)
+            .build()
+            .map_err(|e| format!("{}: {}", self.url, e.without_url()))?;
+        SigV4::new(region, "sqs").sign(&mut request, credentials, SystemTime::now())?;
+        let response = client
+            .execute(request)
+            .await
+            .map_err(|e| format!("{}: {}", self.url, e.without_url()))?;
+        let status = response.status();
+        let reply: Value = response.json().await.unwrap_or_default();
+        if !status.is_success() {
+            // Errors come as {"__type": "...#QueueDoesNotExist", "message": "..."}
+            let kind = reply["__type"].as_str().unwrap_or_default();
+            let kind = kind.rsplit('#').next().unwrap_or(kind);
+            let message = reply["message"].as_str().unwrap_or_default();
+            return Err(format!("{}: {status} {kind} {message}", self.url)
+                .trim_end()
+                .to_string());
+        }
+        let mut fields = Map::new();
+        for (attribute, field) in ATTRIBUTES {
+            let count = reply["Attributes"][attribute]
+                .as_str()
+                .and_then(|count| count.parse::<u64>().ok())
+                .ok_or_else(|| format!("{}: no {attribute} in the response", self.url))?;
+            fields.insert(field.to_string(), Value::from(count));
+        }
+        let lag = fields["visible"].as_u64().unwrap_or(0);
+        Ok((lag, fields))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::Router;
+    use axum::http::{HeaderMap, StatusCode};
+    use axum::routing::post;
+    use tokio::sync::mpsc;
+
+    #[test]
+    fn queue_uris_give_the_endpoint_and_region() {
+        assert_eq!(
+            Queue::parse("sqs://sqs.eu-west-1.amazonaws.com/123456789012/orders"),
+            Ok(Queue {
+                endpoint: "https://sqs.eu-west-1.amazonaws.com".to_string(),
+                url: "https://sqs.eu-west-1.amazonaws.com/123456789012/orders".to_string(),
+                region: Some("eu-west-1".to_string()),
+            })
+        );
+        let local = Queue::parse("sqs+http://localhost:9324/000000000000/orders/")
+            .unwrap_or_else(|e| panic!("{e}"));
+        assert_eq!(local.url, "http://localhost:9324/000000000000/orders");
+        assert_eq!(local.region, None);
+        assert!(Queue::parse("sqs://sqs.eu-west-1.amazonaws.com/orders").is_err());
+    }
+
+    #[tokio::test]
+    async fn attributes_are_read_with_signed_requests() {
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let app = Router::new().route(
+            "/",
+            post(move |headers: HeaderMap, body: String| {
+                let tx = tx.clone();
+                async move {
+                    let header = |name: &str| {
+                        headers
+                            .get(name)
+                            .and_then(|v| v.to_str().ok())
+                            .unwrap_or_default()
+                            .to_string()
+                    };
+                    let _ = tx.send((
+                        header("authorization"),
+                        header("x-amz-target"),
+                        body.clone(),
+                    ));
+                    if body.contains("/missing") {
+                        return (
+                            StatusCode::BAD_REQUEST,
+                            axum::Json(json!({
+                                "__type": "com.amazonaws.sqs#QueueDoesNotExist",
+                                "message": "The specified queue does not exist.",
+                            })),
+                        );
+                    }
+                    (
+                        StatusCode::OK,
+                        axum::Json(json!({"Attributes": {
+                            "ApproximateNumberOfMessages": "1250",
+                            "ApproximateNumberOfMessagesNotVisible": "40",
+                            "ApproximateNumberOfMessagesDelayed": "0",
+                        }})),
+                    )
+                }
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap_or_else(|e| panic!("bind: {e}"));
+        let addr = listener
+            .local_addr()
+            .unwrap_or_else(|e| panic!("local_addr: {e}"));
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let credentials = Credentials {
+            access_key_id: "AKIDEXAMPLE".to_string(),
+            secret_access_key: "secret".to_string(),
+            session_token: None,
+        };
+        let queue = Queue::parse(&format!("sqs+http://{addr}/000000000000/orders"))
+            .unwrap_or_else(|e| panic!("{e}"));
+        let (lag, fields) = queue
+            .sample(
+                &Client::new(),
+                "us-east-1",
+                &credentials,
+                Duration::from_secs(5),
+            )
+            .await
+            .unwrap_or_else(|e| panic!("{e}"));
+        assert_eq!(lag, 1250);
+        assert_eq!(
+            Value::Object(fields),
+            json!({"visible": 1250, "in_flight": 40, "delayed": 0})
+        );
+        let (authorization, target, body) = rx.recv().await.unwrap_or_else(|| panic!("no request"));
+        assert!(authorization.starts_with("AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/"));
+        assert!(authorization.contains(
+            "/us-east-1/sqs/aws4_request, SignedHeaders=content-type;host;x-amz-date;x-amz-target, Signature="
+        ));
+        assert_eq!(target, "AmazonSQS.GetQueueAttributes");
+        let body: Value = serde_json::from_str(&body).unwrap_or_default();
+        assert_eq!(
+            body["QueueUrl"],
+            format!("http://{addr}/000000000000/orders")
+        );
+
+        let missing = Queue::parse(&format!("sqs+http://{addr}/000000000000/missing"))
+            .unwrap_or_else(|e| panic!("{e}"));
+        let error = missing
+            .sample(
+                &Client::new(),
+                "us-east-1",
+                &credentials,
+                Duration::from_secs(5),
+            )
+            .await
+            .err();
+        assert_eq!(
+            error,
+            Some(format!(
+                "http://{addr}/000000000000/missing: 400 Bad Request QueueDoesNotExist The specified queue does not exist."
+            ))
+        );
+    }
+}
@@ -0,0 +1,225 @@
+//! Reading users and groups from the directory.
+//!
+//! Each poll opens a connection, binds, reads every entry matching the user
+//! and group filters with paged searches, and unbinds. Binary attributes
+//! (photos, SIDs) are not tracked; `objectGUID` is kept, hex-encoded, as
+//! the user's id.
+
+use crate::snapshot::{Entry, Snapshot};
+use clap::Args;
+use ldap3::adapters::{Adapter, EntriesOnly, PagedResults};
+use ldap3::{Ldap, LdapConnAsync, LdapConnSettings, Scope, SearchEntry};
+use std::collections::{BTreeMap, BTreeSet};
+use std::time::Duration;
+
+/// Operational attributes that must be asked for by name.
+const OPERATIONAL: [&str; 4] = [
+    "entryUUID",
+    "objectGUID",
+    "nsAccountLock",
+    "pwdAccountLockedTime",
+];
+
+/// Attributes holding group members.
+const MEMBER_ATTRIBUTES: [&str; 2] = ["member", "uniqueMember"];
+
+/// Directory connection and search settings.
+#[derive(Args, Debug, Clone)]
+pub struct DirectoryArgs {
+    /// Server URL (`ldap://`, `ldaps://` or `ldapi://`).
+    #[arg(short, long, env = "LDAP_URL")]
+    pub url: String,
+
+    /// DN to bind as; binds anonymously when absent.
+    #[arg(long, env = "LDAP_BIND_DN")]
+    pub bind_dn: Option<String>,
+
+    /// Password for `--bind-dn`.
+    #[arg(long, env = "LDAP_BIND_PASSWORD", hide_env_values = true)]
+    pub bind_password: Option<String>,
+
+    /// Where to search for users and groups.
+    #[arg(short, long, env = "LDAP_BASE_DN")]
+    pub base_dn: String,
+
+    /// Filter selecting user entries.
+    #[arg(
+        long,
+        env = "LDAP_SOURCE_USER_FILTER",
+        default_value = "(&(objectClass=person)(!(objectClass=computer)))"
+    )]
+    pub user_filter: String,
+
+    /// Filter selecting group entries.
+    #[arg(
+        long,
+        env = "LDAP_SOURCE_GROUP_FILTER",
+        default_value = "(|(objectClass=group)(objectClass=groupOfNames)(objectClass=groupOfUniqueNames))"
+    )]
+    pub group_filter: String,
+
+    /// Do not track group membership.
+    #[arg(long, env = "LDAP_SOURCE_NO_GROUPS")]
+    pub no_groups: bool,
+
+    /// User attributes to track (comma-separated); all user attributes when absent.
+    #[arg(long, env = "LDAP_SOURCE_ATTRIBUTES", value_delimiter = ',')]
+    pub attributes: Vec<String>,
+
+    /// Attributes never reported as changes (comma-separated).
+    #[arg(
+        long,
+        env = "LDAP_SOURCE_IGNORE_ATTRIBUTES",
+        value_delimiter = ',',
+        default_value = "lastLogon,lastLogonTimestamp,lastLogoff,logonCount,badPwdCount,badPasswordTime,\
+                         whenChanged,uSNChanged,dSCorePropagationData,modifyTimestamp,entryCSN,\
+                         modifiersName,authTimestamp,pwdFailureTime,memberOf"
+    )]
+    pub ignore_attributes: Vec<String>,
+
+    /// Entries per page of search results.
+    #[arg(long, env = "LDAP_SOURCE_PAGE_SIZE", default_value = "500")]
+    pub page_size: i32,
+
+    /// Upgrade `ldap://` connections with StartTLS.
+    #[arg(long, env = "LDAP_STARTTLS")]
+    pub starttls: bool,
+
+    /// Accept any server certificate (testing only).
+    #[arg(long, env = "LDAP_NO_TLS_VERIFY")]
+    pub no_tls_verify: bool,
+
+    /// Connection and per-operation timeout in milliseconds.
+    #[arg(short, long, env = "LDAP_SOURCE_TIMEOUT", default_value = "30000")]
+    pub timeout: u64,
+}
+
+impl DirectoryArgs {
+    /// A bound connection.
+    pub async fn connect(&self) -> Result<Ldap, String> {
+        let timeout = Duration::from_millis(self.timeout);
+        let settings = LdapConnSettings::new()
+            .set_conn_timeout(timeout)
+            .set_starttls(self.starttls)
+            .set_no_tls_verify(self.no_tls_verify);
+        let (conn, mut ldap) = LdapConnAsync::with_settings(settings, &self.url)
+            .await
+            .map_err(|e| format!("{}: {e}", self.url))?;
+        ldap3::drive!(conn);
+        if let Some(bind_dn) = &self.bind_dn {
+            let password = self.bind_password.as_deref().unwrap_or_default();
+            ldap.with_timeout(timeout)
+                .simple_bind(bind_dn, password)
+                .await
+                .and_then(ldap3::LdapResult::success)
+                .map_err(|e| format!("bind as {bind_dn}: {e}"))?;
+        }
+        Ok(ldap)
+    }
+
+    /// Read the users and groups under the base DN.
+    pub async fn snapshot(&self) -> Result<Snapshot, String> {
+        let mut ldap = self.connect().await?;
+
+        let mut attributes: Vec<String> = if self.attributes.is_empty() {
+            vec!["*".to_string()]
+        } else {
+            self.attributes.clone()
+        };
+        attributes.push("userAccountControl".to_string());
+        attributes.extend(OPERATIONAL.map(str::to_string));
+        let mut users = BTreeMap::new();
+        for found in self
+            .search(&mut ldap, &self.user_filter, attributes)
+            .await?
+        {
+            let (id, entry) = self.user(found);
+            users.insert(id, entry);
+        }
+
+        let mut groups = BTreeMap::new();
+        if !self.no_groups {
+            let attributes = MEMBER_ATTRIBUTES.map(str::to_string).to_vec();
+            for found in self
+                .search(&mut ldap, &self.group_filter, attributes)
+                .await?
+            {
+                let members: BTreeSet<String> = found
+                    .attrs
+                    .into_iter()
+                    .filter(|(name, _)| {
+                        MEMBER_ATTRIBUTES
+                            .iter()
+                            .any(|m| m.eq_ignore_ascii_case(name))
+                    })
+                    .flat_map(|(_, values)| values)
+                    .collect();
+                groups.insert(found.dn, members);
+            }
+        }
+
+        let _ = ldap.unbind().await;
+        Ok(Snapshot { users, groups })
+    }
+
+    /// All entries under the base DN matching `filter`.
+    async fn search(
+        &self,
+        ldap: &mut Ldap,
+        filter: &str,
+        attributes: Vec<String>,
+    ) -> Result<Vec<SearchEntry>, String> {
+        let adapters: Vec<Box<dyn Adapter<_, _>>> = vec![
+            Box::new(EntriesOnly::new()),
+            Box::new(PagedResults::new(self.page_size)),
+        ];
+        let failed = |e: ldap3::LdapError| format!("search {filter}: {e}");
+        let mut stream = ldap
+            .with_timeout(Duration::from_millis(self.timeout))
+            .streaming_search_with(adapters, &self.base_dn, Scope::Subtree, filter, attributes)
+            .await
+            .map_err(failed)?;
+        let mut entries = Vec::new();
+        while let Some(entry) = stream.next().await.map_err(failed)? {
+            entries.push(SearchEntry::construct(entry));
+        }
+        stream.finish().await.success().map_err(failed)?;
+        Ok(entries)
+    }
+
+    /// A user entry keyed by its id, without ignored attributes.
+    fn user(&self, found: SearchEntry) -> (String, Entry) {
+        let guid = found
+            .bin_attrs
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case("objectGUID"))
+            .and_then(|(_, values)| values.first())
+            .map(hex::encode);
+        let mut id = guid;
+        let mut attributes = BTreeMap::new();
+        for (name, mut values) in found.attrs {
+            if name.eq_ignore_ascii_case("entryUUID") {
+                id = id.or_else(|| values.first().cloned());
+                continue;
+            }
+            if self
+                .ignore_attributes
+                .iter()
+                .any(|i| i.eq_ignore_ascii_case(&name))
+            {
+                continue;
+            }
+            // Value order is not significant in LDAP
+            values.sort();
+            attributes.insert(name, values);
+        }
+        let id = id.unwrap_or_else(|| found.dn.clone());
+        (
+            id,
+            Entry {
+                dn: found.dn,
+                attributes,
+            },
+        )
+    }
+}
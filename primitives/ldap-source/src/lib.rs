@@ -0,0 +1,345 @@
+//! LDAP Source - Directory Change Events
+//!
+//! A Source that polls an LDAP or Active Directory server and emits an event
+//! for each change to its users and groups: accounts added, modified,
+//! disabled, re-enabled or removed, with attribute diffs, and members added
+//! to or removed from groups (see [`snapshot`]). It lets identity changes
+//! drive automation such as provisioning, offboarding or access reviews.
+//!
+//! The first poll records a baseline and publishes nothing. With
+//! `--state-file`, the baseline survives restarts, so changes made while
+//! the source was down are reported on its first poll.
+//!
+//! Sources are SILENT - they only produce domain messages.
+//! All lifecycle events are published by the engine.
+//!
+//! # Usage
+//!
+//! ```bash
+//! # OpenLDAP, every minute
+//! ldap-source --url ldap://ldap.example.com --starttls \
+//!   --bind-dn cn=reader,dc=example,dc=com --bind-password $LDAP_BIND_PASSWORD \
+//!   --base-dn dc=example,dc=com
+//!
+//! # Active Directory, tracking a few attributes, every five minutes
+//! ldap-source --url ldaps://dc1.corp.example.com --base-dn DC=corp,DC=example,DC=com \
+//!   --bind-dn svc-emergent@corp.example.com --bind-password $LDAP_BIND_PASSWORD \
+//!   --attributes sAMAccountName,mail,department,title,manager \
+//!   --interval 300000 --state-file /var/lib/emergent/ldap.json
+//! ```
+//!
+//! On SIGTERM a poll in progress gets `--drain-timeout` milliseconds to
+//! finish. One cut off has not saved its snapshot, so the next start
+//! reports its changes again.
+
+pub mod directory;
+pub mod snapshot;
+
+use clap::Parser;
+use directory::DirectoryArgs;
+use emergent_client::EmergentSource;
+use primitive_common::capabilities::{self, CapabilityArgs, Role, VERSION, capabilities_message};
+use primitive_common::doctor::{Report, check_writable_dir};
+use primitive_common::source::{Outlet, SourceArgs};
+use snapshot::{EVENT_TYPES, Snapshot};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::signal::unix::{SignalKind, signal};
+
+/// Directory poller that emits directory.* events.
+#[derive(Parser, Debug, Clone)]
+#[command(name = "ldap-source", version = VERSION)]
+#[command(about = "Polls an LDAP or Active Directory server and emits change events")]
+struct Args {
+    #[command(flatten)]
+    directory: DirectoryArgs,
+
+    /// Milliseconds between polls.
+    #[arg(short, long, env = "LDAP_SOURCE_INTERVAL", default_value = "60000")]
+    interval: u64,
+
+    /// File to keep the last snapshot in across restarts.
+    #[arg(long, env = "LDAP_SOURCE_STATE_FILE")]
+    state_file: Option<PathBuf>,
+
+    /// Verify external dependencies and exit without connecting to the engine.
+    #[arg(long)]
+    self_test: bool,
+
+    #[command(flatten)]
+    source: SourceArgs,
+
+    #[command(flatten)]
+    capabilities: CapabilityArgs,
+}
+
+/// Variables available to `--emit-type-template`.
+const TEMPLATE_VARIABLES: &[&str] = &["source"];
+
+/// Runs `--self-test` checks and exits.
+async fn self_test(args: &Args, name: &str) -> ! {
+    let mut report = Report::new(name);
+    let directory = &args.directory;
+    let bind = directory.connect().await.map(|mut ldap| {
+        tokio::spawn(async move { ldap.unbind().await });
+        match &directory.bind_dn {
+            Some(dn) => format!("{} as {dn}", directory.url),
+            None => format!("{} (anonymous)", directory.url),
+        }
+    });
+    let bound = bind.is_ok();
+    report.check("bind", bind);
+    if bound {
+        let users = directory
+            .snapshot()
+            .await
+            .map(|s| format!("{} users, {} groups", s.users.len(), s.groups.len()));
+        report.check("search", users);
+    }
+    if let Some(path) = &args.state_file {
+        let dir = path.parent().filter(|p| !p.as_os_str().is_empty());
+        report.check(
+            "state_file",
+            check_writable_dir(dir.unwrap_or(Path::new("."))),
+        );
+    }
+    args.source.self_test(&mut report, TEMPLATE_VARIABLES);
+    report.finish()
+}
+
+/// The snapshot saved in `path`, if any.
+fn load_state(path: &Path) -> Result<Option<Snapshot>, String> {
+    match std::fs::read(path) {
+        Ok(bytes) => serde_json::from_slice(&bytes)
+            .map(Some)
+            .map_err(|e| format!("{}: {e}", path.display())),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(format!("{}: {e}", path.display())),
+    }
+}
+
+/// Save `snapshot` to `path`, replacing it atomically.
+fn save_state(path: &Path, snapshot: &Snapshot) -> Result<(), String> {
+    let temp = path.with_extension("tmp");
+    let bytes = serde_json::to_vec(snapshot).map_err(|e| e.to_string())?;
+    std::fs::write(&temp, bytes)
+        .and_then(|()| std::fs::rename(&temp, path))
+        .map_err(|e| format!("{}: {e}", path.display()))
+}
+
+/// Poll once, publishing the changes since `previous`. Returns the new
+/// snapshot, or `None` if the poll or a publish failed so that the changes
+/// are reported again next time.
+async fn poll(args: &Args, outlet: &Outlet, previous: Option<&Snapshot>) -> Option<Snapshot> {
+    match args.directory.snapshot().await {
+        Ok(current) => publish_changes(outlet, previous, current).await,
+        Err(e) => {
+            eprintln!("Directory poll failed: {e}");
+            None
+        }
+    }
+}
+
+/// Publish the changes from `previous` to `current`, returning `current`
+/// once all of them are published or spooled.
+async fn publish_changes(
+    outlet: &Outlet,
+    previous: Option<&Snapshot>,
+    current: Snapshot,
+) -> Option<Snapshot> {
+    let Some(previous) = previous else {
+        eprintln!(
+            "Baseline recorded: {} users, {} groups",
+            current.users.len(),
+            current.groups.len()
+        );
+        return Some(current);
+    };
+    for change in snapshot::diff(previous, &current) {
+        // The outlet logs and reports what it cannot deliver
+        outlet
+            .publish(change.event_type, &[], change.payload)
+            .await
+            .ok()?;
+    }
+    Some(current)
+}
+
+/// Run the primitive with `args` as its command line (the first item is
+/// the program name, as with `std::env::args_os`).
+pub async fn run<I, T>(args: I) -> Result<(), Box<dyn std::error::Error>>
+where
+    I: IntoIterator<Item = T>,
+    T: Into<std::ffi::OsString> + Clone,
+{
+    let args = Args::parse_from(args);
+
+    // Get the source name from environment (set by engine) or use default
+    let name = std::env::var("EMERGENT_NAME").unwrap_or_else(|_| "ldap-source".to_string());
+
+    if args.self_test {
+        self_test(&args, &name).await;
+    }
+    if let Err(e) = args.source.validate(TEMPLATE_VARIABLES) {
+        eprintln!("Error: {e}");
+        std::process::exit(1);
+    }
+    let mut previous = match args.state_file.as_deref().map(load_state).transpose() {
+        Ok(state) => state.flatten(),
+        Err(e) => {
+            eprintln!("Error: cannot read state file {e}");
+            std::process::exit(1);
+        }
+    };
+
+    let produces = args.source.produces(&EVENT_TYPES);
+    let produces: Vec<&str> = produces.iter().map(String::as_str).collect();
+    let descriptor = args
+        .capabilities
+        .describe(&name, Role::Source, &[], &produces, &args);
+    if args.capabilities.capabilities {
+        capabilities::print(&descriptor);
+    }
+
+    // Connect to the Emergent engine (silently - lifecycle events come from engine)
+    let source = match EmergentSource::connect(&name).await {
+        Ok(s) => s,
+        Err(e) => {
+            eprintln!("Failed to connect to Emergent engine: {e}");
+            std::process::exit(1);
+        }
+    };
+    let outlet = Arc::new(Outlet::new(source, &name, &args.source)?);
+    if args.capabilities.announce {
+        outlet.announce(capabilities_message(&descriptor)).await;
+    }
+    tokio::spawn(Arc::clone(&outlet).drain_every(args.source.spool.retry_interval()));
+
+    // Set up SIGTERM handler for graceful shutdown
+    let mut sigterm = signal(SignalKind::terminate())?;
+    let mut interval = tokio::time::interval(Duration::from_millis(args.interval));
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+    loop {
+        tokio::select! {
+            _ = sigterm.recv() => break,
+
+            _ = interval.tick() => {
+                let (polled, stopping) = {
+                    let poll = poll(&args, &outlet, previous.as_ref());
+                    tokio::pin!(poll);
+                    tokio::select! {
+                        polled = &mut poll => (polled, false),
+                        _ = sigterm.recv() => {
+                            // Let the poll finish publishing, so its snapshot can be saved
+                            let drained = args.source.drain.drain("poll in progress", &mut poll).await;
+                            (drained.flatten(), true)
+                        }
+                    }
+                };
+                if let Some(current) = polled {
+                    if let Some(path) = &args.state_file
+                        && let Err(e) = save_state(path, &current)
+                    {
+                        eprintln!("Failed to save state: {e}");
+                    }
+                    previous = Some(current);
+                }
+                if stopping {
+                    break;
+                }
+            }
+        }
+    }
+    outlet.disconnect().await;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use emergent_testkit::{MockEngine, fixtures};
+    use snapshot::Entry;
+
+    fn user(dn: &str, disabled: bool) -> Entry {
+        let flags = if disabled { "514" } else { "512" };
+        Entry {
+            dn: dn.to_string(),
+            attributes: [("userAccountControl".to_string(), vec![flags.to_string()])].into(),
+        }
+    }
+
+    async fn outlet(socket: &Path, args: &SourceArgs) -> Outlet {
+        let source = EmergentSource::connect_to("ldap-source", socket)
+            .await
+            .unwrap_or_else(|e| panic!("connect: {e}"));
+        Outlet::new(source, "ldap-source", args).unwrap_or_else(|e| panic!("open spool: {e}"))
+    }
+
+    #[tokio::test]
+    async fn changes_survive_the_engine_being_down() {
+        let dir = fixtures::TempDir::new("ldap-source-spool");
+        let socket = dir.path().join("engine.sock");
+        let args = SourceArgs {
+            spool: primitive_common::spool::SpoolArgs {
+                spool_dir: Some(dir.path().join("spool")),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let mut before = Snapshot::default();
+        before
+            .users
+            .insert("ada".to_string(), user("uid=ada", false));
+        let mut after = Snapshot::default();
+        after.users.insert("ada".to_string(), user("uid=ada", true));
+
+        let mut engine = MockEngine::serve(&socket);
+        let down = outlet(&socket, &args).await;
+        engine.shut_down().await;
+        // Spooled changes count as published, so the snapshot moves on
+        let saved = publish_changes(&down, Some(&before), after.clone()).await;
+        assert_eq!(saved, Some(after));
+        drop(down);
+
+        let mut engine = MockEngine::serve(&socket);
+        outlet(&socket, &args).await.drain_spool().await;
+        let published = engine.expect_published(snapshot::USER_DISABLED).await;
+        assert_eq!(published.payload()["id"], "ada");
+        engine.shut_down().await;
+    }
+
+    #[tokio::test]
+    async fn unpublished_changes_are_reported_again() {
+        let dir = fixtures::TempDir::new("ldap-source-down");
+        let socket = dir.path().join("engine.sock");
+        let mut after = Snapshot::default();
+        after
+            .users
+            .insert("ada".to_string(), user("uid=ada", false));
+
+        let mut engine = MockEngine::serve(&socket);
+        let down = outlet(&socket, &SourceArgs::default()).await;
+        engine.shut_down().await;
+        let saved = publish_changes(&down, Some(&Snapshot::default()), after).await;
+        assert_eq!(saved, None);
+    }
+
+    #[test]
+    fn state_survives_a_round_trip() {
+        let dir = std::env::temp_dir().join(format!("ldap-source-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap_or_else(|e| panic!("mkdir: {e}"));
+        let path = dir.join("state.json");
+        assert_eq!(load_state(&path), Ok(None));
+
+        let mut state = Snapshot::default();
+        state
+            .groups
+            .insert("cn=admins".to_string(), ["uid=ada".to_string()].into());
+        save_state(&path, &state).unwrap_or_else(|e| panic!("save: {e}"));
+        assert_eq!(load_state(&path), Ok(Some(state)));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,6 @@
+//! `ldap-source` binary; the primitive itself lives in the library.
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    ldap_source::run(std::env::args_os()).await
+}
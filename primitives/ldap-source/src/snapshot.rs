@@ -0,0 +1,310 @@
+//! Directory snapshots and the events between two of them.
+//!
+//! Each poll reads every user and group under the base DN into a
+//! [`Snapshot`]; comparing it with the previous one yields the events:
+//!
+//! - `directory.user.added` / `directory.user.removed` - with the entry's
+//!   attributes
+//! - `directory.user.modified` - with the changed attributes, old and new
+//! - `directory.user.disabled` / `directory.user.enabled` - instead of
+//!   `modified` when the change disables or re-enables the account
+//! - `directory.group.member_added` / `directory.group.member_removed` - one
+//!   per membership change
+//!
+//! Users are keyed by `entryUUID` or `objectGUID` where the server has one,
+//! so a renamed or moved entry is a modification with a `previous_dn`.
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use std::collections::{BTreeMap, BTreeSet};
+
+pub const USER_ADDED: &str = "directory.user.added";
+pub const USER_MODIFIED: &str = "directory.user.modified";
+pub const USER_DISABLED: &str = "directory.user.disabled";
+pub const USER_ENABLED: &str = "directory.user.enabled";
+pub const USER_REMOVED: &str = "directory.user.removed";
+pub const MEMBER_ADDED: &str = "directory.group.member_added";
+pub const MEMBER_REMOVED: &str = "directory.group.member_removed";
+
+/// Every type the source publishes.
+pub const EVENT_TYPES: [&str; 7] = [
+    USER_ADDED,
+    USER_MODIFIED,
+    USER_DISABLED,
+    USER_ENABLED,
+    USER_REMOVED,
+    MEMBER_ADDED,
+    MEMBER_REMOVED,
+];
+
+/// `userAccountControl` flag of a disabled Active Directory account.
+const ACCOUNTDISABLE: u64 = 0x2;
+
+/// One directory entry.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Entry {
+    pub dn: String,
+    pub attributes: BTreeMap<String, Vec<String>>,
+}
+
+impl Entry {
+    /// The values of `name`, matched case-insensitively as LDAP does.
+    fn values(&self, name: &str) -> Option<&Vec<String>> {
+        self.attributes
+            .iter()
+            .find(|(attribute, _)| attribute.eq_ignore_ascii_case(name))
+            .map(|(_, values)| values)
+    }
+
+    /// Whether the account is disabled: Active Directory's ACCOUNTDISABLE
+    /// flag, 389 Directory Server's `nsAccountLock`, or an OpenLDAP
+    /// password-policy lock.
+    pub fn is_disabled(&self) -> bool {
+        let first = |name| self.values(name).and_then(|v| v.first());
+        first("userAccountControl")
+            .and_then(|flags| flags.parse::<u64>().ok())
+            .is_some_and(|flags| flags & ACCOUNTDISABLE != 0)
+            || first("nsAccountLock").is_some_and(|lock| lock.eq_ignore_ascii_case("true"))
+            || first("pwdAccountLockedTime").is_some()
+    }
+}
+
+/// Users by id and group members by group DN.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub users: BTreeMap<String, Entry>,
+    pub groups: BTreeMap<String, BTreeSet<String>>,
+}
+
+/// An event to publish.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Change {
+    pub event_type: &'static str,
+    pub payload: Value,
+}
+
+/// The attributes that differ between two entries, as `{old, new}` pairs
+/// (`null` for an attribute one side lacks).
+fn attribute_changes(old: &Entry, new: &Entry) -> BTreeMap<String, Value> {
+    let names: BTreeSet<&String> = old.attributes.keys().chain(new.attributes.keys()).collect();
+    names
+        .into_iter()
+        .filter_map(|name| {
+            let (before, after) = (old.attributes.get(name), new.attributes.get(name));
+            (before != after).then(|| (name.clone(), json!({ "old": before, "new": after })))
+        })
+        .collect()
+}
+
+/// The events that turn `old` into `new`.
+pub fn diff(old: &Snapshot, new: &Snapshot) -> Vec<Change> {
+    let mut changes = Vec::new();
+
+    for (id, entry) in &new.users {
+        let Some(previous) = old.users.get(id) else {
+            changes.push(Change {
+                event_type: USER_ADDED,
+                payload: json!({
+                    "id": id,
+                    "dn": entry.dn,
+                    "disabled": entry.is_disabled(),
+                    "attributes": entry.attributes,
+                }),
+            });
+            continue;
+        };
+        let attributes = attribute_changes(previous, entry);
+        if attributes.is_empty() && previous.dn == entry.dn {
+            continue;
+        }
+        let event_type = match (previous.is_disabled(), entry.is_disabled()) {
+            (false, true) => USER_DISABLED,
+            (true, false) => USER_ENABLED,
+            _ => USER_MODIFIED,
+        };
+        let mut payload = json!({
+            "id": id,
+            "dn": entry.dn,
+            "changes": attributes,
+            "attributes": entry.attributes,
+        });
+        if previous.dn != entry.dn {
+            payload["previous_dn"] = json!(previous.dn);
+        }
+        changes.push(Change {
+            event_type,
+            payload,
+        });
+    }
+    for (id, entry) in &old.users {
+        if !new.users.contains_key(id) {
+            changes.push(Change {
+                event_type: USER_REMOVED,
+                payload: json!({ "id": id, "dn": entry.dn, "attributes": entry.attributes }),
+            });
+        }
+    }
+
+    // A group that appears or disappears adds or removes all its members
+    let none = BTreeSet::new();
+    let groups: BTreeSet<&String> = old.groups.keys().chain(new.groups.keys()).collect();
+    for group in groups {
+        let before = old.groups.get(group).unwrap_or(&none);
+        let after = new.groups.get(group).unwrap_or(&none);
+        for (event_type, members) in [
+            (MEMBER_ADDED, after.difference(before)),
+            (MEMBER_REMOVED, before.difference(after)),
+        ] {
+            changes.extend(members.map(|member| Change {
+                event_type,
+                payload: json!({ "group": group, "member": member }),
+            }));
+        }
+    }
+
+    changes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(dn: &str, attributes: &[(&str, &str)]) -> Entry {
+        let mut map: BTreeMap<String, Vec<String>> = BTreeMap::new();
+        for (name, value) in attributes {
+            map.entry(name.to_string())
+                .or_default()
+                .push(value.to_string());
+        }
+        Entry {
+            dn: dn.to_string(),
+            attributes: map,
+        }
+    }
+
+    fn snapshot(users: &[(&str, Entry)], groups: &[(&str, &[&str])]) -> Snapshot {
+        Snapshot {
+            users: users
+                .iter()
+                .map(|(id, e)| (id.to_string(), e.clone()))
+                .collect(),
+            groups: groups
+                .iter()
+                .map(|(dn, members)| {
+                    (
+                        dn.to_string(),
+                        members.iter().map(|m| m.to_string()).collect(),
+                    )
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn accounts_are_disabled_by_any_directory_flavour() {
+        assert!(entry("cn=a", &[("userAccountControl", "514")]).is_disabled());
+        assert!(!entry("cn=a", &[("userAccountControl", "512")]).is_disabled());
+        assert!(entry("cn=a", &[("nsaccountlock", "TRUE")]).is_disabled());
+        assert!(entry("cn=a", &[("pwdAccountLockedTime", "000001010000Z")]).is_disabled());
+        assert!(!entry("cn=a", &[("mail", "a@example.com")]).is_disabled());
+    }
+
+    #[test]
+    fn users_are_added_modified_disabled_and_removed() {
+        let ada = entry(
+            "uid=ada,ou=people",
+            &[("mail", "ada@example.com"), ("title", "Engineer")],
+        );
+        let old = snapshot(
+            &[("1", ada.clone()), ("2", entry("uid=bob,ou=people", &[]))],
+            &[],
+        );
+
+        let promoted = entry(
+            "uid=ada,ou=staff",
+            &[("mail", "ada@example.com"), ("title", "Principal")],
+        );
+        let new = snapshot(
+            &[
+                ("1", promoted),
+                (
+                    "3",
+                    entry("uid=cy,ou=people", &[("mail", "cy@example.com")]),
+                ),
+            ],
+            &[],
+        );
+        let changes = diff(&old, &new);
+        let types: Vec<&str> = changes.iter().map(|c| c.event_type).collect();
+        assert_eq!(types, [USER_MODIFIED, USER_ADDED, USER_REMOVED]);
+        let modified = &changes[0].payload;
+        assert_eq!(modified["previous_dn"], "uid=ada,ou=people");
+        assert_eq!(
+            modified["changes"],
+            json!({"title": {"old": ["Engineer"], "new": ["Principal"]}})
+        );
+        assert_eq!(changes[1].payload["disabled"], false);
+        assert_eq!(changes[2].payload["dn"], "uid=bob,ou=people");
+
+        let locked = entry(
+            "uid=ada,ou=people",
+            &[
+                ("mail", "ada@example.com"),
+                ("title", "Engineer"),
+                ("nsAccountLock", "true"),
+            ],
+        );
+        let changes = diff(
+            &old,
+            &snapshot(
+                &[("1", locked), ("2", entry("uid=bob,ou=people", &[]))],
+                &[],
+            ),
+        );
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].event_type, USER_DISABLED);
+        assert_eq!(
+            changes[0].payload["changes"],
+            json!({"nsAccountLock": {"old": null, "new": ["true"]}})
+        );
+        assert!(diff(&old, &old).is_empty());
+    }
+
+    #[test]
+    fn membership_changes_are_reported_per_member() {
+        let old = snapshot(
+            &[],
+            &[
+                ("cn=admins", &["uid=ada", "uid=bob"]),
+                ("cn=old", &["uid=ada"]),
+            ],
+        );
+        let new = snapshot(
+            &[],
+            &[
+                ("cn=admins", &["uid=ada", "uid=cy"]),
+                ("cn=new", &["uid=bob"]),
+            ],
+        );
+        let changes: Vec<(&str, String, String)> = diff(&old, &new)
+            .into_iter()
+            .map(|c| {
+                (
+                    c.event_type,
+                    c.payload["group"].as_str().unwrap_or_default().to_string(),
+                    c.payload["member"].as_str().unwrap_or_default().to_string(),
+                )
+            })
+            .collect();
+        let change = |t, g: &str, m: &str| (t, g.to_string(), m.to_string());
+        assert_eq!(
+            changes,
+            [
+                change(MEMBER_ADDED, "cn=admins", "uid=cy"),
+                change(MEMBER_REMOVED, "cn=admins", "uid=bob"),
+                change(MEMBER_ADDED, "cn=new", "uid=bob"),
+                change(MEMBER_REMOVED, "cn=old", "uid=ada"),
+            ]
+        );
+    }
+}
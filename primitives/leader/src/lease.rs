@@ -0,0 +1,219 @@
+//! The lease protocol spoken over the bus.
+//!
+//! Every instance of a group hears the others' `leader.lease` events. The
+//! leader renews its lease every tick, a quarter of `--lease-timeout`. A
+//! standby that hears nothing for a whole timeout claims the lease, and
+//! leads if a tick passes without a competing claim from a lower instance
+//! id. Whenever two instances claim or hold the lease at once the lower id
+//! wins and the other stands down, so a healed partition settles on one
+//! leader. A leader that stops releases its lease, and standbys claim at
+//! once instead of waiting the timeout out.
+
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant};
+
+/// A `leader.lease` payload.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Lease {
+    pub group: String,
+    pub holder: String,
+    /// Claimed but not yet leading.
+    #[serde(default)]
+    pub claiming: bool,
+    /// Given up by its holder.
+    #[serde(default)]
+    pub released: bool,
+}
+
+/// Where an instance stands.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Role {
+    Standby,
+    Candidate { since: Instant },
+    Leader,
+}
+
+/// What the instance must do after an election step.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Step {
+    /// Publish a claiming lease.
+    Claim,
+    /// Publish a lease.
+    Renew,
+    /// Start leading: publish a lease and start the command.
+    Lead,
+    /// Another instance won: stop the command.
+    StandDown,
+}
+
+/// One instance's view of its group's election.
+#[derive(Debug)]
+pub struct Election {
+    group: String,
+    instance: String,
+    timeout: Duration,
+    role: Role,
+    /// The last holder or claimant heard from, and when.
+    holder: Option<String>,
+    heard: Instant,
+}
+
+impl Election {
+    /// A standby for `group`, which waits a full `timeout` from `now` to
+    /// hear a current leader before claiming.
+    pub fn new(group: &str, instance: &str, timeout: Duration, now: Instant) -> Self {
+        Self {
+            group: group.to_string(),
+            instance: instance.to_string(),
+            timeout,
+            role: Role::Standby,
+            holder: None,
+            heard: now,
+        }
+    }
+
+    /// How often to call [`Election::tick`].
+    pub fn tick_interval(&self) -> Duration {
+        (self.timeout / 4).max(Duration::from_millis(1))
+    }
+
+    pub fn is_leader(&self) -> bool {
+        self.role == Role::Leader
+    }
+
+    /// The other instance last heard holding or claiming the lease.
+    pub fn holder(&self) -> Option<&str> {
+        self.holder.as_deref()
+    }
+
+    /// This instance's lease, as published.
+    pub fn lease(&self, released: bool) -> Lease {
+        Lease {
+            group: self.group.clone(),
+            holder: self.instance.clone(),
+            claiming: matches!(self.role, Role::Candidate { .. }),
+            released,
+        }
+    }
+
+    /// Take in another instance's `lease`, heard at `now`.
+    pub fn hear(&mut self, lease: &Lease, now: Instant) -> Option<Step> {
+        if lease.group != self.group || lease.holder == self.instance {
+            return None;
+        }
+        if lease.released {
+            if self.holder.as_deref() == Some(lease.holder.as_str()) {
+                self.holder = None;
+            }
+            if self.role == Role::Standby && self.holder.is_none() {
+                return Some(self.claim(now));
+            }
+            return None;
+        }
+        if self.role != Role::Standby && lease.holder > self.instance {
+            // It stands down once it hears us; a leader tells it straight away
+            return (self.role == Role::Leader).then_some(Step::Renew);
+        }
+        let was_leader = self.is_leader();
+        self.role = Role::Standby;
+        self.holder = Some(lease.holder.clone());
+        self.heard = now;
+        was_leader.then_some(Step::StandDown)
+    }
+
+    /// Move the election on at `now`.
+    pub fn tick(&mut self, now: Instant) -> Option<Step> {
+        match self.role {
+            Role::Standby if now.duration_since(self.heard) >= self.timeout => {
+                Some(self.claim(now))
+            }
+            Role::Standby => None,
+            Role::Candidate { since } if now.duration_since(since) >= self.tick_interval() => {
+                self.role = Role::Leader;
+                Some(Step::Lead)
+            }
+            Role::Candidate { .. } => None,
+            Role::Leader => Some(Step::Renew),
+        }
+    }
+
+    fn claim(&mut self, now: Instant) -> Step {
+        self.role = Role::Candidate { since: now };
+        Step::Claim
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TIMEOUT: Duration = Duration::from_millis(1000);
+
+    fn lease(holder: &str) -> Lease {
+        Lease {
+            group: "ingest".to_string(),
+            holder: holder.to_string(),
+            claiming: false,
+            released: false,
+        }
+    }
+
+    #[test]
+    fn standby_takes_over_when_the_lease_lapses() {
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        let mut election = Election::new("ingest", "b", TIMEOUT, start);
+        assert_eq!(election.tick_interval(), Duration::from_millis(250));
+
+        // A live leader keeps it standing by
+        assert_eq!(election.hear(&lease("c"), at(900)), None);
+        assert_eq!(election.tick(at(1500)), None);
+        assert_eq!(election.holder(), Some("c"));
+        // Other groups and its own echoes are not heard
+        let mut other = lease("a");
+        other.group = "billing".to_string();
+        assert_eq!(election.hear(&other, at(1600)), None);
+        assert_eq!(election.hear(&lease("b"), at(1600)), None);
+
+        // Silence for a whole timeout: claim, then lead a tick later
+        assert_eq!(election.tick(at(1900)), Some(Step::Claim));
+        assert!(election.lease(false).claiming);
+        assert_eq!(election.tick(at(2000)), None);
+        assert_eq!(election.tick(at(2150)), Some(Step::Lead));
+        assert!(election.is_leader());
+        assert!(!election.lease(false).claiming);
+        assert_eq!(election.tick(at(2400)), Some(Step::Renew));
+
+        // The old leader comes back: higher ids are told, lower ids win
+        assert_eq!(election.hear(&lease("c"), at(2500)), Some(Step::Renew));
+        assert!(election.is_leader());
+        assert_eq!(election.hear(&lease("a"), at(2600)), Some(Step::StandDown));
+        assert!(!election.is_leader());
+        assert_eq!(election.tick(at(3000)), None);
+    }
+
+    #[test]
+    fn contested_claims_go_to_the_lowest_id_and_releases_are_claimed_at_once() {
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        let mut election = Election::new("ingest", "b", TIMEOUT, start);
+        assert_eq!(election.tick(at(1000)), Some(Step::Claim));
+
+        // A higher claimant does not stop us; a lower one does
+        let mut claim = lease("c");
+        claim.claiming = true;
+        assert_eq!(election.hear(&claim, at(1100)), None);
+        let mut claim = lease("a");
+        claim.claiming = true;
+        assert_eq!(election.hear(&claim, at(1100)), None);
+        assert_eq!(election.tick(at(1500)), None);
+        assert_eq!(election.holder(), Some("a"));
+
+        // Its leader lets go: claim without waiting for the timeout
+        let mut release = lease("a");
+        release.released = true;
+        assert_eq!(election.hear(&release, at(1600)), Some(Step::Claim));
+        assert_eq!(election.holder(), None);
+        assert_eq!(election.tick(at(1850)), Some(Step::Lead));
+    }
+}
@@ -0,0 +1,490 @@
+//! Leader
+//!
+//! A Handler that lets redundant instances of a primitive take turns:
+//! several `leader` processes of one group each wrap the same command, and
+//! only the elected one runs it while the others stand by, ready to start
+//! it the moment the leader goes away.
+//!
+//! # Election
+//!
+//! By default the instances hold a lease over the bus: the leader renews
+//! it with `leader.lease` events, and a standby takes over once none has
+//! arrived for `--lease-timeout` milliseconds (see [`lease`]). With
+//! `--lock-file` they instead race for an advisory lock on a shared file,
+//! which the operating system frees the moment its holder dies (see
+//! [`lock`]).
+//!
+//! A newly elected leader publishes `leader.changed` and starts the
+//! command, which inherits the environment and output of `leader`. On
+//! SIGTERM, or on losing the lease, the command gets SIGTERM and
+//! `--drain-timeout` milliseconds to stop before it is killed. Should the
+//! command exit on its own, the leader gives up leadership and exits with
+//! its status, for the engine to restart.
+//!
+//! # Messages Published
+//!
+//! - `leader.lease`: the group, the holder's instance id, and whether it is
+//!   still `claiming` or has `released` the lease
+//! - `leader.changed`: the group, the new leader, the previous one if
+//!   known, and the election `backend`
+//!
+//! # Usage
+//!
+//! ```bash
+//! # Two or more of these, on different hosts: one polls at a time
+//! leader --group webhook-poller --lease-timeout 5000 -- \
+//!   http-source --url https://api.example.com/events --interval 10000
+//!
+//! # Instances sharing a disk, electing through a lock on it
+//! leader --group backup --lock-file /var/lib/emergent/backup.lock -- \
+//!   timer-source --interval 3600000
+//! ```
+
+pub mod lease;
+pub mod lock;
+
+use clap::Parser;
+use emergent_client::{EmergentHandler, EmergentMessage};
+use lease::{Election, Lease, Step};
+use lock::LockFile;
+use nix::{
+    sys::signal::{Signal, kill},
+    unistd::Pid,
+};
+use primitive_common::capabilities::{self, CapabilityArgs, Role, VERSION, capabilities_message};
+use primitive_common::crypto::{KeyArgs, Keyring};
+use primitive_common::doctor::{Report, check_executable, check_writable_dir};
+use primitive_common::payload::{self, PayloadArgs};
+use primitive_common::shutdown::DrainArgs;
+use serde::Deserialize;
+use serde_json::{Value, json};
+use std::path::{Path, PathBuf};
+use std::process::{ExitStatus, Stdio};
+use std::time::{Duration, Instant};
+use tokio::process::{Child, Command};
+use tokio::signal::unix::{SignalKind, signal};
+use tokio::time::MissedTickBehavior;
+
+/// Message type of lease claims, renewals and releases.
+pub const LEASE_EVENT_TYPE: &str = "leader.lease";
+
+/// Message type published by a newly elected leader.
+pub const CHANGED_EVENT_TYPE: &str = "leader.changed";
+
+/// Leader — run a command on one instance of a group at a time.
+#[derive(Parser, Debug)]
+#[command(name = "leader", version = VERSION)]
+#[command(
+    about = "Elect one instance of a redundant group to run a command while the others stand by"
+)]
+struct Args {
+    /// Name shared by the instances taking turns.
+    #[arg(long, env = "LEADER_GROUP")]
+    group: String,
+
+    /// This instance's id, unique in the group; the lowest id wins a contested election. Defaults to `<hostname>-<pid>`.
+    #[arg(long, env = "LEADER_INSTANCE")]
+    instance: Option<String>,
+
+    /// Elect through an advisory lock on this file instead of a lease over the bus.
+    #[arg(long, env = "LEADER_LOCK_FILE")]
+    lock_file: Option<PathBuf>,
+
+    /// Milliseconds without a lease renewal before a standby takes over; the lock file is polled every quarter of it.
+    #[arg(long, env = "LEADER_LEASE_TIMEOUT", default_value = "10000", value_parser = clap::value_parser!(u64).range(4..))]
+    lease_timeout: u64,
+
+    #[command(flatten)]
+    drain: DrainArgs,
+
+    #[command(flatten)]
+    payload: PayloadArgs,
+
+    /// Verify the configuration and exit without connecting to the engine.
+    #[arg(long)]
+    self_test: bool,
+
+    #[command(flatten)]
+    capabilities: CapabilityArgs,
+
+    #[command(flatten)]
+    keys: KeyArgs,
+
+    /// The command to run while leading, and its arguments.
+    #[arg(last = true, required = true, value_name = "COMMAND")]
+    command: Vec<String>,
+}
+
+/// `<hostname>-<pid>`, this instance's default id.
+fn default_instance() -> String {
+    let host = std::fs::read_to_string("/proc/sys/kernel/hostname")
+        .ok()
+        .or_else(|| std::env::var("HOSTNAME").ok())
+        .map(|host| host.trim().to_string())
+        .filter(|host| !host.is_empty())
+        .unwrap_or_else(|| "localhost".to_string());
+    format!("{host}-{}", std::process::id())
+}
+
+/// Run the primitive with `args` as its command line (the first item is
+/// the program name, as with `std::env::args_os`).
+pub async fn run<I, T>(args: I) -> Result<(), Box<dyn std::error::Error>>
+where
+    I: IntoIterator<Item = T>,
+    T: Into<std::ffi::OsString> + Clone,
+{
+    let args = Args::parse_from(args);
+
+    // Get the handler name from environment (set by engine) or use default
+    let name = std::env::var("EMERGENT_NAME").unwrap_or_else(|_| "leader".to_string());
+    let instance = args.instance.clone().unwrap_or_else(default_instance);
+
+    if args.self_test {
+        let mut report = Report::new(&name);
+        report.check("command", check_executable(&args.command[0]));
+        match &args.lock_file {
+            Some(path) => {
+                let dir = path
+                    .parent()
+                    .filter(|p| !p.as_os_str().is_empty())
+                    .unwrap_or(Path::new("."));
+                report.check("lock-file", check_writable_dir(dir));
+            }
+            None => report.check(
+                "lease",
+                Ok(format!(
+                    "{instance} in {}, renewing every {}ms",
+                    args.group,
+                    args.lease_timeout / 4
+                )),
+            ),
+        }
+        args.payload.self_test(&mut report);
+        args.keys.self_test(&mut report);
+        report.finish();
+    }
+
+    if let Err(e) = args.payload.encryption.validate() {
+        eprintln!("Error: {e}");
+        std::process::exit(1);
+    }
+    let keys = match args.keys.load() {
+        Ok(keys) => keys,
+        Err(e) => {
+            eprintln!("Error: failed to load identity file: {e}");
+            std::process::exit(1);
+        }
+    };
+
+    let topics_refs: Vec<&str> = match args.lock_file {
+        Some(_) => Vec::new(),
+        None => vec![LEASE_EVENT_TYPE],
+    };
+    let produces = match args.lock_file {
+        Some(_) => vec![CHANGED_EVENT_TYPE],
+        None => vec![LEASE_EVENT_TYPE, CHANGED_EVENT_TYPE],
+    };
+    let descriptor =
+        args.capabilities
+            .describe(&name, Role::Handler, &topics_refs, &produces, &args);
+    if args.capabilities.capabilities {
+        capabilities::print(&descriptor);
+    }
+
+    // Connect to the Emergent engine
+    let mut handler = match EmergentHandler::connect(&name).await {
+        Ok(h) => h,
+        Err(e) => {
+            eprintln!("Failed to connect to Emergent engine: {e}");
+            std::process::exit(1);
+        }
+    };
+    if args.capabilities.announce {
+        let _ = handler.publish(capabilities_message(&descriptor)).await;
+    }
+
+    let mut stream = if topics_refs.is_empty() {
+        None
+    } else {
+        match handler.subscribe(&topics_refs).await {
+            Ok(s) => Some(s),
+            Err(e) => {
+                eprintln!("Failed to subscribe: {e}");
+                std::process::exit(1);
+            }
+        }
+    };
+
+    // Set up SIGTERM handler for graceful shutdown
+    let mut sigterm = signal(SignalKind::terminate())?;
+
+    let timeout = Duration::from_millis(args.lease_timeout);
+    let mut election = Election::new(&args.group, &instance, timeout, Instant::now());
+    let mut lock = args.lock_file.as_deref().map(LockFile::new);
+    let mut node = Node {
+        args: &args,
+        handler: &handler,
+        instance: &instance,
+        child: None,
+    };
+    let mut ticks = tokio::time::interval(election.tick_interval());
+    ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+    let status = loop {
+        let child = node.child.as_mut();
+        let exited = async {
+            match child {
+                Some(child) => child.wait().await,
+                None => std::future::pending().await,
+            }
+        };
+        let next = async {
+            match stream.as_mut() {
+                Some(stream) => stream.next().await,
+                None => std::future::pending().await,
+            }
+        };
+
+        tokio::select! {
+            _ = sigterm.recv() => break None,
+
+            status = exited => break Some(status),
+
+            _ = ticks.tick() => {
+                let step = match lock.as_mut() {
+                    Some(lock) => lock_step(lock, &instance),
+                    None => election.tick(Instant::now()),
+                };
+                if let Err(e) = node.step(step, &election, lock.as_ref()).await {
+                    eprintln!("leader: {e}");
+                    break Some(Err(e));
+                }
+            }
+
+            msg = next => {
+                match msg {
+                    Some(msg) => {
+                        let step = match heard(&msg, keys.as_ref()).await {
+                            Ok(lease) => election.hear(&lease, Instant::now()),
+                            Err(e) => {
+                                eprintln!("leader: ignoring {LEASE_EVENT_TYPE}: {e}");
+                                None
+                            }
+                        };
+                        if let Err(e) = node.step(step, &election, lock.as_ref()).await {
+                            eprintln!("leader: {e}");
+                            break Some(Err(e));
+                        }
+                    }
+                    None => {
+                        // Stream ended (graceful shutdown)
+                        break None;
+                    }
+                }
+            }
+        }
+    };
+
+    node.stop().await;
+    if let Some(lock) = lock.as_mut() {
+        lock.release();
+    } else if election.is_leader() {
+        node.publish_lease(&election, true).await;
+    }
+    let _ = handler.disconnect().await;
+
+    match status {
+        None => Ok(()),
+        Some(Ok(status)) => {
+            eprintln!(
+                "leader: {} {}; giving up leadership",
+                args.command[0],
+                describe(status)
+            );
+            std::process::exit(status.code().unwrap_or(1));
+        }
+        Some(Err(_)) => std::process::exit(1),
+    }
+}
+
+/// Poll the lock file: lead once it is taken.
+fn lock_step(lock: &mut LockFile, instance: &str) -> Option<Step> {
+    if lock.is_held() {
+        return None;
+    }
+    match lock.try_acquire(instance) {
+        Ok(true) => Some(Step::Lead),
+        Ok(false) => None,
+        Err(e) => {
+            eprintln!("leader: failed to lock {e}");
+            None
+        }
+    }
+}
+
+/// The lease in a `leader.lease` message.
+async fn heard(msg: &EmergentMessage, keys: Option<&Keyring>) -> Result<Lease, String> {
+    let payload = payload::decode(msg.payload(), keys)
+        .await
+        .map_err(|e| e.to_string())?;
+    Lease::deserialize(payload.as_ref()).map_err(|e| e.to_string())
+}
+
+/// This instance and the command it runs while leading.
+struct Node<'a> {
+    args: &'a Args,
+    handler: &'a EmergentHandler,
+    instance: &'a str,
+    child: Option<Child>,
+}
+
+impl Node<'_> {
+    /// Carry out an election step.
+    async fn step(
+        &mut self,
+        step: Option<Step>,
+        election: &Election,
+        lock: Option<&LockFile>,
+    ) -> Result<(), std::io::Error> {
+        match step {
+            None => {}
+            Some(Step::Claim | Step::Renew) => self.publish_lease(election, false).await,
+            Some(Step::Lead) => {
+                let (previous, backend) = match lock {
+                    Some(lock) => (lock.previous().map(str::to_string), "lock"),
+                    None => {
+                        self.publish_lease(election, false).await;
+                        (election.holder().map(str::to_string), "lease")
+                    }
+                };
+                eprintln!(
+                    "leader: {} now leads {}; starting {}",
+                    self.instance, self.args.group, self.args.command[0]
+                );
+                self.publish(
+                    CHANGED_EVENT_TYPE,
+                    json!({
+                        "group": self.args.group,
+                        "leader": self.instance,
+                        "previous": previous,
+                        "backend": backend,
+                    }),
+                )
+                .await;
+                self.child = Some(self.spawn()?);
+            }
+            Some(Step::StandDown) => {
+                eprintln!(
+                    "leader: {} lost {} to {}",
+                    self.instance,
+                    self.args.group,
+                    election.holder().unwrap_or_default()
+                );
+                self.stop().await;
+            }
+        }
+        Ok(())
+    }
+
+    fn spawn(&self) -> std::io::Result<Child> {
+        Command::new(&self.args.command[0])
+            .args(&self.args.command[1..])
+            .stdin(Stdio::null())
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(|e| {
+                std::io::Error::new(
+                    e.kind(),
+                    format!("failed to start {}: {e}", self.args.command[0]),
+                )
+            })
+    }
+
+    /// SIGTERM the command, then kill it if it outlives `--drain-timeout`.
+    async fn stop(&mut self) {
+        let Some(mut child) = self.child.take() else {
+            return;
+        };
+        if let Some(pid) = child.id().and_then(|id| i32::try_from(id).ok()) {
+            let _ = kill(Pid::from_raw(pid), Signal::SIGTERM);
+        }
+        let grace = self.args.drain.deadline();
+        let command = &self.args.command[0];
+        match tokio::time::timeout(grace, child.wait()).await {
+            Ok(Ok(status)) => eprintln!("leader: {command} {}", describe(status)),
+            Ok(Err(e)) => eprintln!("leader: {command}: {e}"),
+            Err(_) => {
+                eprintln!(
+                    "leader: {command} still running {}ms after SIGTERM; killing",
+                    grace.as_millis()
+                );
+                let _ = child.kill().await;
+            }
+        }
+    }
+
+    async fn publish_lease(&self, election: &Election, released: bool) {
+        let lease = election.lease(released);
+        match serde_json::to_value(&lease) {
+            Ok(payload) => self.publish(LEASE_EVENT_TYPE, payload).await,
+            Err(e) => eprintln!("leader: failed to encode {LEASE_EVENT_TYPE}: {e}"),
+        }
+    }
+
+    async fn publish(&self, message_type: &str, payload: Value) {
+        let payload = match payload::encode(message_type, payload, &self.args.payload).await {
+            Ok(p) => p,
+            Err(e) => {
+                eprintln!("leader: failed to encode {message_type}: {e}");
+                return;
+            }
+        };
+        let _ = self
+            .handler
+            .publish(EmergentMessage::new(message_type).with_payload(payload))
+            .await;
+    }
+}
+
+fn describe(status: ExitStatus) -> String {
+    format!("exited ({status})")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_command_follows_the_double_dash() {
+        let args = Args::try_parse_from([
+            "leader",
+            "--group",
+            "poller",
+            "--lease-timeout",
+            "2000",
+            "--",
+            "http-source",
+            "--interval",
+            "10000",
+        ])
+        .unwrap_or_else(|e| panic!("{e}"));
+        assert_eq!(args.command, ["http-source", "--interval", "10000"]);
+        assert_eq!(args.lease_timeout, 2000);
+        assert!(args.lock_file.is_none());
+
+        assert!(Args::try_parse_from(["leader", "--group", "poller"]).is_err());
+        assert!(
+            Args::try_parse_from([
+                "leader",
+                "--group",
+                "poller",
+                "--lease-timeout",
+                "3",
+                "--",
+                "x"
+            ])
+            .is_err()
+        );
+        assert!(default_instance().ends_with(&format!("-{}", std::process::id())));
+    }
+}
@@ -0,0 +1,100 @@
+//! Leadership through an advisory lock on a shared file.
+//!
+//! Whoever holds the exclusive lock on `--lock-file` leads, and writes its
+//! instance id into the file for the others to see. The operating system
+//! drops the lock when its holder exits, however it dies, so a standby
+//! polling for it takes over within one poll. Every instance must see the
+//! same file with working locks: a local disk, or NFSv4.
+
+use std::fs::{File, OpenOptions, TryLockError};
+use std::io::{Read, Seek, Write};
+use std::path::{Path, PathBuf};
+
+/// The lock file, held or not.
+#[derive(Debug)]
+pub struct LockFile {
+    path: PathBuf,
+    held: Option<File>,
+    previous: Option<String>,
+}
+
+impl LockFile {
+    pub fn new(path: &Path) -> Self {
+        Self {
+            path: path.to_path_buf(),
+            held: None,
+            previous: None,
+        }
+    }
+
+    pub fn is_held(&self) -> bool {
+        self.held.is_some()
+    }
+
+    /// The instance that held the lock before this one took it.
+    pub fn previous(&self) -> Option<&str> {
+        self.previous.as_deref()
+    }
+
+    /// Take the lock for `instance` if it is free: `true` once held.
+    pub fn try_acquire(&mut self, instance: &str) -> Result<bool, String> {
+        if self.held.is_some() {
+            return Ok(true);
+        }
+        let error = |e: std::io::Error| format!("{}: {e}", self.path.display());
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&self.path)
+            .map_err(error)?;
+        match file.try_lock() {
+            Ok(()) => {}
+            Err(TryLockError::WouldBlock) => return Ok(false),
+            Err(TryLockError::Error(e)) => return Err(error(e)),
+        }
+        let mut previous = String::new();
+        file.read_to_string(&mut previous).map_err(error)?;
+        let previous = previous.trim();
+        self.previous = (!previous.is_empty()).then(|| previous.to_string());
+        file.set_len(0)
+            .and_then(|()| file.rewind())
+            .and_then(|()| writeln!(file, "{instance}"))
+            .map_err(error)?;
+        self.held = Some(file);
+        Ok(true)
+    }
+
+    /// Let the lock go.
+    pub fn release(&mut self) {
+        self.held = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use emergent_testkit::fixtures::TempDir;
+
+    #[test]
+    fn one_holder_at_a_time() {
+        let dir = TempDir::new("leader");
+        let path = dir.path().join("ingest.lock");
+        let mut a = LockFile::new(&path);
+        let mut b = LockFile::new(&path);
+
+        assert_eq!(a.try_acquire("a"), Ok(true));
+        assert_eq!(b.try_acquire("b"), Ok(false));
+        assert!(a.is_held() && !b.is_held());
+        assert_eq!(a.previous(), None);
+
+        a.release();
+        assert_eq!(b.try_acquire("b"), Ok(true));
+        assert_eq!(b.previous(), Some("a"));
+        assert_eq!(a.try_acquire("a"), Ok(false));
+
+        let mut missing = LockFile::new(&dir.path().join("no/such/dir.lock"));
+        assert!(missing.try_acquire("a").is_err());
+    }
+}
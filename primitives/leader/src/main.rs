@@ -0,0 +1,6 @@
+//! `leader` binary; the primitive itself lives in the library.
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    leader::run(std::env::args_os()).await
+}
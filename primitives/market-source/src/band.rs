@@ -0,0 +1,112 @@
+//! Price thresholds with hysteresis.
+//!
+//! A threshold, `SYMBOL=PRICE`, has a band around it, `--hysteresis`
+//! percent of the price on either side. A price only crosses above once it
+//! clears the top of the band, and back below once it clears the bottom,
+//! so a price hovering around the threshold does not alert on every tick.
+//! The first price of a symbol only tells which side it starts on.
+
+use serde_json::{Value, json};
+use std::collections::HashMap;
+
+/// A threshold on a symbol's price.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Threshold {
+    pub symbol: String,
+    pub price: f64,
+    /// Half the band's width, in price.
+    pub band: f64,
+}
+
+impl Threshold {
+    /// Parse `SYMBOL=PRICE`, with a band of `hysteresis` percent.
+    pub fn parse(spec: &str, hysteresis: f64) -> Result<Self, String> {
+        let (symbol, price) = spec
+            .split_once('=')
+            .map(|(symbol, price)| (symbol.trim(), price.trim()))
+            .filter(|(symbol, _)| !symbol.is_empty())
+            .ok_or_else(|| format!("expected SYMBOL=PRICE, got '{spec}'"))?;
+        let price: f64 = price
+            .parse()
+            .ok()
+            .filter(|price: &f64| price.is_finite() && *price > 0.0)
+            .ok_or_else(|| format!("'{spec}': '{price}' is not a price"))?;
+        Ok(Self {
+            symbol: symbol.to_string(),
+            price,
+            band: price * hysteresis / 100.0,
+        })
+    }
+}
+
+/// Which side of their thresholds symbols were last on.
+#[derive(Debug, Default)]
+pub struct Bands {
+    above: HashMap<usize, bool>,
+}
+
+impl Bands {
+    /// The crossings `price` of `symbol` makes, as payload fields.
+    pub fn check(&mut self, symbol: &str, price: f64, thresholds: &[Threshold]) -> Vec<Value> {
+        let mut crossings = Vec::new();
+        for (index, threshold) in thresholds.iter().enumerate() {
+            if threshold.symbol != symbol {
+                continue;
+            }
+            let above = match self.above.get(&index) {
+                None => price > threshold.price,
+                Some(false) => price >= threshold.price + threshold.band,
+                Some(true) => price > threshold.price - threshold.band,
+            };
+            if self
+                .above
+                .insert(index, above)
+                .is_some_and(|was| was != above)
+            {
+                crossings.push(json!({
+                    "threshold": threshold.price,
+                    "band": [threshold.price - threshold.band, threshold.price + threshold.band],
+                    "price": price,
+                    "direction": if above { "above" } else { "below" },
+                }));
+            }
+        }
+        crossings
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn crossings_must_clear_the_band() {
+        let thresholds = [
+            Threshold::parse("BTCUSDT=70000", 1.0).unwrap_or_else(|e| panic!("{e}")),
+            Threshold::parse("AAPL = 200", 1.0).unwrap_or_else(|e| panic!("{e}")),
+        ];
+        assert_eq!(thresholds[0].band, 700.0);
+        assert!(Threshold::parse("BTCUSDT=moon", 1.0).is_err());
+        assert!(Threshold::parse("BTCUSDT=-5", 1.0).is_err());
+
+        let mut bands = Bands::default();
+        let mut directions = |price| -> Vec<String> {
+            bands
+                .check("BTCUSDT", price, &thresholds)
+                .iter()
+                .map(|crossing| crossing["direction"].to_string())
+                .collect()
+        };
+        // Starts below; hovering inside the band is not a crossing
+        assert!(directions(69_000.0).is_empty());
+        assert!(directions(70_100.0).is_empty());
+        assert!(directions(69_900.0).is_empty());
+        assert_eq!(directions(70_700.0), ["\"above\""]);
+        assert!(directions(69_500.0).is_empty());
+        assert!(directions(70_200.0).is_empty());
+        assert_eq!(directions(69_300.0), ["\"below\""]);
+
+        let crossing = &Bands::default().check("AAPL", 150.0, &thresholds);
+        assert!(crossing.is_empty(), "the first price only sets the side");
+    }
+}
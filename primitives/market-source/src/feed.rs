@@ -0,0 +1,180 @@
+//! Where prices come from.
+//!
+//! Symbols are given as `FEED:SYMBOL`, where the feed is an exchange with
+//! a public WebSocket API, streamed, or `poll`, read from `--poll-url`:
+//!
+//! - `binance:BTCUSDT` — Binance trades, from
+//!   `/stream?streams=btcusdt@trade`
+//! - `coinbase:BTC-USD` — Coinbase Exchange's `ticker` channel
+//! - `poll:AAPL` (or just `AAPL`) — any JSON price API, such as a stock
+//!   quote service
+//!
+//! Both exchanges also have a REST ticker, polled while their stream is
+//! down.
+
+use serde_json::{Value, json};
+
+/// A price feed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Feed {
+    Binance,
+    Coinbase,
+    Poll,
+}
+
+impl Feed {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Feed::Binance => "binance",
+            Feed::Coinbase => "coinbase",
+            Feed::Poll => "poll",
+        }
+    }
+}
+
+/// A symbol and the feed it is read from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Symbol {
+    pub feed: Feed,
+    pub symbol: String,
+}
+
+impl Symbol {
+    /// Parse `[FEED:]SYMBOL`.
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let spec = spec.trim();
+        let (feed, symbol) = match spec.split_once(':') {
+            Some(("binance", symbol)) => (Feed::Binance, symbol.to_ascii_uppercase()),
+            Some(("coinbase", symbol)) => (Feed::Coinbase, symbol.to_ascii_uppercase()),
+            Some(("poll", symbol)) => (Feed::Poll, symbol.to_string()),
+            Some((feed, _)) => {
+                return Err(format!(
+                    "'{spec}': unknown feed '{feed}', expected binance, coinbase or poll"
+                ));
+            }
+            None => (Feed::Poll, spec.to_string()),
+        };
+        if symbol.is_empty() || symbol.contains(['/', '?', '&', '#', ' ']) {
+            return Err(format!("'{spec}': '{symbol}' is not a symbol"));
+        }
+        Ok(Self { feed, symbol })
+    }
+}
+
+/// A price seen on a feed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Quote {
+    pub symbol: String,
+    pub feed: Feed,
+    pub price: f64,
+    /// `stream` or `poll`.
+    pub via: &'static str,
+}
+
+/// A price, which exchanges send as a string to keep its precision.
+pub fn price(value: &Value) -> Option<f64> {
+    match value {
+        Value::String(text) => text.parse().ok(),
+        value => value.as_f64(),
+    }
+    .filter(|price: &f64| price.is_finite())
+}
+
+/// The stream URL for Binance trades of `symbols`.
+pub fn binance_stream(base: &str, symbols: &[&str]) -> String {
+    let streams: Vec<String> = symbols
+        .iter()
+        .map(|symbol| format!("{}@trade", symbol.to_ascii_lowercase()))
+        .collect();
+    format!(
+        "{}/stream?streams={}",
+        base.trim_end_matches('/'),
+        streams.join("/")
+    )
+}
+
+/// The quote in a message from Binance's combined stream.
+pub fn binance_quote(message: &Value) -> Option<Quote> {
+    let data = &message["data"];
+    (data["e"] == "trade").then_some(())?;
+    Some(Quote {
+        symbol: data["s"].as_str()?.to_string(),
+        feed: Feed::Binance,
+        price: price(&data["p"])?,
+        via: "stream",
+    })
+}
+
+/// The subscription Coinbase expects after connecting.
+pub fn coinbase_subscribe(symbols: &[&str]) -> Value {
+    json!({"type": "subscribe", "product_ids": symbols, "channels": ["ticker"]})
+}
+
+/// The quote in a message from Coinbase's feed, or the error it reports.
+pub fn coinbase_quote(message: &Value) -> Result<Option<Quote>, String> {
+    match message["type"].as_str() {
+        Some("ticker") => Ok(message["product_id"]
+            .as_str()
+            .zip(price(&message["price"]))
+            .map(|(symbol, price)| Quote {
+                symbol: symbol.to_string(),
+                feed: Feed::Coinbase,
+                price,
+                via: "stream",
+            })),
+        Some("error") => Err(format!(
+            "{}: {}",
+            message["message"].as_str().unwrap_or("error"),
+            message["reason"].as_str().unwrap_or_default()
+        )),
+        _ => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exchange_messages_give_quotes() {
+        let trade = json!({"stream": "btcusdt@trade", "data": {
+            "e": "trade", "E": 1716221758123u64, "s": "BTCUSDT", "t": 12345,
+            "p": "67012.50000000", "q": "0.00100000", "T": 1716221758122u64
+        }});
+        assert_eq!(
+            binance_quote(&trade),
+            Some(Quote {
+                symbol: "BTCUSDT".to_string(),
+                feed: Feed::Binance,
+                price: 67012.5,
+                via: "stream",
+            })
+        );
+        assert_eq!(binance_quote(&json!({"result": null, "id": 1})), None);
+
+        let ticker = json!({"type": "ticker", "product_id": "ETH-USD", "price": "3105.17"});
+        assert_eq!(
+            coinbase_quote(&ticker).map(|quote| quote.map(|quote| quote.price)),
+            Ok(Some(3105.17))
+        );
+        assert_eq!(coinbase_quote(&json!({"type": "subscriptions"})), Ok(None));
+        assert!(
+            coinbase_quote(&json!({"type": "error", "message": "Failed to subscribe"})).is_err()
+        );
+
+        assert_eq!(
+            binance_stream("wss://stream.binance.com:9443/", &["BTCUSDT", "ETHUSDT"]),
+            "wss://stream.binance.com:9443/stream?streams=btcusdt@trade/ethusdt@trade"
+        );
+        assert_eq!(
+            Symbol::parse("coinbase:btc-usd"),
+            Ok(Symbol {
+                feed: Feed::Coinbase,
+                symbol: "BTC-USD".to_string()
+            })
+        );
+        assert_eq!(Symbol::parse("AAPL").map(|s| s.feed), Ok(Feed::Poll));
+        assert!(Symbol::parse("kraken:XBTUSD").is_err());
+        assert!(Symbol::parse("poll:").is_err());
+    }
+}
@@ -0,0 +1,481 @@
+//! Market Source - Crypto and Stock Prices
+//!
+//! A Source that follows prices of `--symbol`s and publishes `market.tick`
+//! as they move, at most once per `--tick-interval` per symbol (see
+//! [`ticker`]). Exchange symbols are streamed over the exchange's
+//! WebSocket API, falling back to its REST ticker while the stream is down
+//! (see [`stream`]); others are polled from any JSON quote API (see
+//! [`poll`]). See [`feed`] for how symbols are given.
+//!
+//! With `--threshold SYMBOL=PRICE`, `market.threshold_crossed` is published
+//! when a price crosses the threshold, with a `--hysteresis` band so a
+//! price hovering around it does not alert repeatedly (see [`band`]).
+//!
+//! `--emit-type-template` can also use `{feed}`, `binance`, `coinbase` or
+//! `poll`.
+//!
+//! Sources are SILENT - they only produce domain messages.
+//! All lifecycle events are published by the engine.
+//!
+//! # Usage
+//!
+//! ```bash
+//! market-source --symbol binance:BTCUSDT --symbol coinbase:ETH-USD --symbol AAPL \
+//!   --poll-url 'https://finnhub.io/api/v1/quote?symbol={symbol}&token=...' --price-path '$.c' \
+//!   --threshold BTCUSDT=70000 --threshold AAPL=200
+//! ```
+//!
+//! On SIGTERM the streams are closed and prices already received get
+//! `--drain-timeout` milliseconds to be published.
+
+pub mod band;
+pub mod feed;
+pub mod poll;
+pub mod stream;
+pub mod ticker;
+
+use band::{Bands, Threshold};
+use clap::Parser;
+use emergent_client::EmergentSource;
+use feed::{Feed, Quote, Symbol};
+use poll::Poller;
+use primitive_common::capabilities::{self, CapabilityArgs, Role, VERSION, capabilities_message};
+use primitive_common::doctor::Report;
+use primitive_common::source::{Outlet, SourceArgs};
+use primitive_common::time;
+use reqwest::Client;
+use serde_json::Value;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use ticker::Ticker;
+use tokio::signal::unix::{SignalKind, signal};
+use tokio::sync::mpsc;
+
+/// The event type published when a price moves.
+pub const TICK_EVENT_TYPE: &str = "market.tick";
+
+/// The event type published when a price crosses a threshold.
+pub const THRESHOLD_EVENT_TYPE: &str = "market.threshold_crossed";
+
+/// Variables available to `--emit-type-template`.
+const TEMPLATE_VARIABLES: &[&str] = &["source", "feed"];
+
+/// Price follower that emits market.tick events.
+#[derive(Parser, Debug, Clone)]
+#[command(name = "market-source", version = VERSION)]
+#[command(about = "Streams or polls crypto and stock prices and emits ticks and threshold alerts")]
+struct Args {
+    /// Symbol to follow as [FEED:]SYMBOL, where FEED is binance, coinbase
+    /// or poll (the default) (repeatable or comma-separated).
+    #[arg(
+        long = "symbol",
+        env = "MARKET_SOURCE_SYMBOLS",
+        value_delimiter = ',',
+        required = true
+    )]
+    symbols: Vec<String>,
+
+    /// Alert when a price crosses a value, as SYMBOL=PRICE (repeatable or
+    /// comma-separated).
+    #[arg(
+        long = "threshold",
+        env = "MARKET_SOURCE_THRESHOLDS",
+        value_delimiter = ','
+    )]
+    thresholds: Vec<String>,
+
+    /// Width of the band around each threshold a price must clear, as a
+    /// percentage of the threshold on either side.
+    #[arg(long, env = "MARKET_SOURCE_HYSTERESIS", default_value = "0.5")]
+    hysteresis: f64,
+
+    /// Least milliseconds between ticks of a symbol.
+    #[arg(long, env = "MARKET_SOURCE_TICK_INTERVAL", default_value = "1000")]
+    tick_interval: u64,
+
+    /// URL polled for `poll` symbols, with {symbol} in it.
+    #[arg(long, env = "MARKET_SOURCE_POLL_URL")]
+    poll_url: Option<String>,
+
+    /// JSONPath to the price in --poll-url responses.
+    #[arg(long, env = "MARKET_SOURCE_PRICE_PATH", default_value = "$.price")]
+    price_path: String,
+
+    /// Milliseconds between polls, of `poll` symbols and of exchange
+    /// symbols while their stream is down.
+    #[arg(long, env = "MARKET_SOURCE_POLL_INTERVAL", default_value = "60000")]
+    poll_interval: u64,
+
+    /// Binance WebSocket stream base.
+    #[arg(
+        long,
+        env = "MARKET_SOURCE_BINANCE_STREAM_URL",
+        default_value = "wss://stream.binance.com:9443"
+    )]
+    binance_stream_url: String,
+
+    /// Binance REST API base.
+    #[arg(
+        long,
+        env = "MARKET_SOURCE_BINANCE_API_URL",
+        default_value = "https://api.binance.com"
+    )]
+    binance_api_url: String,
+
+    /// Coinbase Exchange WebSocket feed.
+    #[arg(
+        long,
+        env = "MARKET_SOURCE_COINBASE_STREAM_URL",
+        default_value = "wss://ws-feed.exchange.coinbase.com"
+    )]
+    coinbase_stream_url: String,
+
+    /// Coinbase Exchange REST API base.
+    #[arg(
+        long,
+        env = "MARKET_SOURCE_COINBASE_API_URL",
+        default_value = "https://api.exchange.coinbase.com"
+    )]
+    coinbase_api_url: String,
+
+    /// Timeout for connecting and polling in milliseconds.
+    #[arg(long, env = "MARKET_SOURCE_TIMEOUT", default_value = "10000")]
+    timeout: u64,
+
+    /// Verify external dependencies and exit without connecting to the engine.
+    #[arg(long)]
+    self_test: bool,
+
+    #[command(flatten)]
+    source: SourceArgs,
+
+    #[command(flatten)]
+    capabilities: CapabilityArgs,
+}
+
+/// The symbols of `feed`.
+fn of(symbols: &[Symbol], feed: Feed) -> Vec<String> {
+    symbols
+        .iter()
+        .filter(|symbol| symbol.feed == feed)
+        .map(|symbol| symbol.symbol.clone())
+        .collect()
+}
+
+/// The streams to open: each exchange with symbols, its stream URL and
+/// its symbols.
+fn streams(symbols: &[Symbol], args: &Args) -> Vec<(Feed, String, Vec<String>)> {
+    [
+        (Feed::Binance, &args.binance_stream_url),
+        (Feed::Coinbase, &args.coinbase_stream_url),
+    ]
+    .into_iter()
+    .filter_map(|(feed, url)| {
+        let streamed = of(symbols, feed);
+        if streamed.is_empty() {
+            return None;
+        }
+        let url = match feed {
+            Feed::Binance => {
+                let names: Vec<&str> = streamed.iter().map(String::as_str).collect();
+                feed::binance_stream(url, &names)
+            }
+            _ => url.clone(),
+        };
+        Some((feed, url, streamed))
+    })
+    .collect()
+}
+
+/// Poll `symbol` every `interval` until the task is aborted.
+async fn poll_symbol(
+    symbol: String,
+    poller: Poller,
+    interval: Duration,
+    tx: mpsc::UnboundedSender<Quote>,
+) {
+    let mut ticks = tokio::time::interval(interval);
+    ticks.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    loop {
+        ticks.tick().await;
+        match poller.quote(Feed::Poll, &symbol).await {
+            Ok(quote) => {
+                if tx.send(quote).is_err() {
+                    return;
+                }
+            }
+            Err(e) => eprintln!("{e}"),
+        }
+    }
+}
+
+/// Publish `payload` as `event_type`, stamped with the time; the outlet
+/// logs and reports it if it is lost.
+async fn publish(outlet: &Outlet, event_type: &str, mut payload: Value) {
+    payload["at"] = Value::from(time::format(time::now()));
+    let feed = payload["feed"].as_str().unwrap_or_default().to_string();
+    let _ = outlet
+        .publish(event_type, &[("feed", &feed)], payload)
+        .await;
+}
+
+/// Publish the thresholds `quote` crosses, and its tick unless the symbol
+/// ticked within the interval.
+async fn offer(
+    outlet: &Outlet,
+    ticker: &mut Ticker,
+    bands: &mut Bands,
+    thresholds: &[Threshold],
+    quote: Quote,
+) {
+    // Thresholds see every price, not just the ticks published
+    for mut crossing in bands.check(&quote.symbol, quote.price, thresholds) {
+        crossing["symbol"] = Value::from(quote.symbol.as_str());
+        crossing["feed"] = Value::from(quote.feed.as_str());
+        publish(outlet, THRESHOLD_EVENT_TYPE, crossing).await;
+    }
+    if let Some(tick) = ticker.offer(quote, Instant::now()) {
+        publish(outlet, TICK_EVENT_TYPE, tick).await;
+    }
+}
+
+/// Runs `--self-test` checks and exits.
+async fn self_test(symbols: &[Symbol], poller: &Poller, args: &Args, name: &str) -> ! {
+    let limit = Duration::from_millis(args.timeout);
+    let mut report = Report::new(name);
+    for (feed, url, _) in streams(symbols, args) {
+        let opened = stream::connect(&url, limit)
+            .await
+            .map(|_| "stream opened".to_string());
+        report.check(&format!("{} stream", feed.as_str()), opened);
+    }
+    for symbol in symbols {
+        let quote = poller
+            .quote(symbol.feed, &symbol.symbol)
+            .await
+            .map(|quote| format!("{}", quote.price));
+        report.check(&symbol.symbol, quote);
+    }
+    args.source.self_test(&mut report, TEMPLATE_VARIABLES);
+    report.finish()
+}
+
+/// Run the primitive with `args` as its command line (the first item is
+/// the program name, as with `std::env::args_os`).
+pub async fn run<I, T>(args: I) -> Result<(), Box<dyn std::error::Error>>
+where
+    I: IntoIterator<Item = T>,
+    T: Into<std::ffi::OsString> + Clone,
+{
+    let args = Args::parse_from(args);
+
+    // Get the source name from environment (set by engine) or use default
+    let name = std::env::var("EMERGENT_NAME").unwrap_or_else(|_| "market-source".to_string());
+
+    let exit = |e: String| -> ! {
+        eprintln!("Error: {e}");
+        std::process::exit(1);
+    };
+    if args.tick_interval == 0 || args.poll_interval == 0 {
+        exit("--tick-interval and --poll-interval must be positive".to_string());
+    }
+    if !(0.0..50.0).contains(&args.hysteresis) {
+        exit("--hysteresis must be a percentage from 0 to 50".to_string());
+    }
+    if let Err(e) = args.source.validate(TEMPLATE_VARIABLES) {
+        exit(e);
+    }
+    let mut symbols: Vec<Symbol> = Vec::new();
+    for spec in &args.symbols {
+        let symbol = Symbol::parse(spec).unwrap_or_else(|e| exit(format!("--symbol: {e}")));
+        if symbols.iter().any(|known| known.symbol == symbol.symbol) {
+            exit(format!("--symbol: '{}' given twice", symbol.symbol));
+        }
+        symbols.push(symbol);
+    }
+    if args.poll_url.is_none() && symbols.iter().any(|symbol| symbol.feed == Feed::Poll) {
+        exit("--poll-url is required for polled symbols".to_string());
+    }
+    let thresholds = args
+        .thresholds
+        .iter()
+        .map(|spec| Threshold::parse(spec, args.hysteresis))
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap_or_else(|e| exit(format!("--threshold: {e}")));
+    if let Some(threshold) = thresholds
+        .iter()
+        .find(|threshold| !symbols.iter().any(|known| known.symbol == threshold.symbol))
+    {
+        exit(format!("--threshold: no --symbol '{}'", threshold.symbol));
+    }
+    let timeout = Duration::from_millis(args.timeout);
+    let poll_interval = Duration::from_millis(args.poll_interval);
+    let poller = Poller::new(
+        Client::new(),
+        timeout,
+        args.poll_url.clone(),
+        &args.price_path,
+        args.binance_api_url.clone(),
+        args.coinbase_api_url.clone(),
+    )
+    .unwrap_or_else(|e| exit(e));
+
+    if args.self_test {
+        self_test(&symbols, &poller, &args, &name).await;
+    }
+
+    let produces = args
+        .source
+        .produces(&[TICK_EVENT_TYPE, THRESHOLD_EVENT_TYPE]);
+    let produces: Vec<&str> = produces.iter().map(String::as_str).collect();
+    let descriptor = args
+        .capabilities
+        .describe(&name, Role::Source, &[], &produces, &args);
+    if args.capabilities.capabilities {
+        capabilities::print(&descriptor);
+    }
+
+    // Connect to the Emergent engine (silently - lifecycle events come from engine)
+    let source = match EmergentSource::connect(&name).await {
+        Ok(s) => s,
+        Err(e) => {
+            eprintln!("Failed to connect to Emergent engine: {e}");
+            std::process::exit(1);
+        }
+    };
+    let outlet = Arc::new(Outlet::new(source, &name, &args.source)?);
+    if args.capabilities.announce {
+        outlet.announce(capabilities_message(&descriptor)).await;
+    }
+    tokio::spawn(Arc::clone(&outlet).drain_every(args.source.spool.retry_interval()));
+
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let mut tasks = Vec::new();
+    for (feed, url, streamed) in streams(&symbols, &args) {
+        tasks.push(tokio::spawn(stream::stream(
+            feed,
+            url,
+            streamed,
+            poller.clone(),
+            poll_interval,
+            timeout,
+            tx.clone(),
+        )));
+    }
+    for symbol in of(&symbols, Feed::Poll) {
+        tasks.push(tokio::spawn(poll_symbol(
+            symbol,
+            poller.clone(),
+            poll_interval,
+            tx.clone(),
+        )));
+    }
+
+    let mut ticker = Ticker::new(Duration::from_millis(args.tick_interval));
+    let mut bands = Bands::default();
+    let mut flush = tokio::time::interval(Duration::from_millis(args.tick_interval));
+    let mut sigterm = signal(SignalKind::terminate())?;
+    loop {
+        tokio::select! {
+            _ = sigterm.recv() => break,
+
+            _ = flush.tick() => {
+                for tick in ticker.flush(Instant::now()) {
+                    publish(&outlet, TICK_EVENT_TYPE, tick).await;
+                }
+            }
+
+            Some(quote) = rx.recv() => {
+                offer(&outlet, &mut ticker, &mut bands, &thresholds, quote).await;
+            }
+        }
+    }
+    // Close the streams, then publish what was already received
+    for task in tasks {
+        task.abort();
+    }
+    rx.close();
+    let queued = async {
+        while let Some(quote) = rx.recv().await {
+            offer(&outlet, &mut ticker, &mut bands, &thresholds, quote).await;
+        }
+    };
+    args.source.drain.drain("queued prices", queued).await;
+    outlet.disconnect().await;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use emergent_testkit::MockEngine;
+    use emergent_testkit::fixtures::TempDir;
+    use std::path::Path;
+
+    /// Connect to the engine on `socket` as the source would.
+    async fn connect(socket: &Path, args: &[&str]) -> Outlet {
+        let mut argv = vec!["market-source", "--symbol", "binance:BTCUSDT"];
+        argv.extend_from_slice(args);
+        let args = Args::parse_from(argv);
+        let source = EmergentSource::connect_to("market-source", socket)
+            .await
+            .unwrap_or_else(|e| panic!("connect: {e}"));
+        Outlet::new(source, "market-source", &args.source)
+            .unwrap_or_else(|e| panic!("open spool: {e}"))
+    }
+
+    #[tokio::test]
+    async fn prices_survive_the_engine_being_down() {
+        let dir = TempDir::new("market-source-spool");
+        let socket = dir.path().join("engine.sock");
+        let spool = dir.path().join("spool");
+        let args = [
+            "--spool-dir",
+            spool.to_str().unwrap_or_default(),
+            "--emit-type-template",
+            "{feed}.{type}",
+        ];
+        let thresholds = [Threshold::parse("BTCUSDT=70000", 0.0).unwrap_or_else(|e| panic!("{e}"))];
+        let mut ticker = Ticker::new(Duration::from_secs(60));
+        let mut bands = Bands::default();
+        let quote = |price| Quote {
+            symbol: "BTCUSDT".to_string(),
+            feed: Feed::Binance,
+            price,
+            via: "stream",
+        };
+
+        let mut engine = MockEngine::serve(&socket);
+        let outlet = connect(&socket, &args).await;
+        engine.shut_down().await;
+        offer(
+            &outlet,
+            &mut ticker,
+            &mut bands,
+            &thresholds,
+            quote(69_000.0),
+        )
+        .await;
+        offer(
+            &outlet,
+            &mut ticker,
+            &mut bands,
+            &thresholds,
+            quote(71_000.0),
+        )
+        .await;
+        drop(outlet);
+
+        let mut engine = MockEngine::serve(&socket);
+        connect(&socket, &args).await.drain_spool().await;
+        let tick = engine.expect_published("binance.market.tick").await;
+        assert_eq!(tick.payload()["price"], 69_000.0);
+        let crossed = engine
+            .expect_published("binance.market.threshold_crossed")
+            .await;
+        assert_eq!(crossed.payload()["symbol"], "BTCUSDT");
+        engine.expect_quiet(Duration::from_millis(200)).await;
+        engine.shut_down().await;
+    }
+}
@@ -0,0 +1,6 @@
+//! `market-source` binary; the primitive itself lives in the library.
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    market_source::run(std::env::args_os()).await
+}
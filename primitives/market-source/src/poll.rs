@@ -0,0 +1,155 @@
+//! Polled prices.
+//!
+//! `--poll-url` is a URL with `{symbol}` in it, and `--price-path` a
+//! JSONPath to the price in its response, so any quote API will do:
+//!
+//! ```text
+//! --poll-url 'https://finnhub.io/api/v1/quote?symbol={symbol}&token=...' --price-path '$.c'
+//! ```
+//!
+//! Exchange symbols are polled from their REST tickers instead, while
+//! their stream is down.
+
+use crate::feed::{Feed, Quote, price};
+use reqwest::Client;
+use serde_json::Value;
+use serde_json_path::JsonPath;
+use std::time::Duration;
+
+/// Reads prices over HTTP.
+#[derive(Clone)]
+pub struct Poller {
+    client: Client,
+    timeout: Duration,
+    /// `--poll-url` and `--price-path`, for `poll` symbols.
+    url: Option<String>,
+    path: JsonPath,
+    /// REST bases of the exchanges.
+    binance: String,
+    coinbase: String,
+}
+
+impl Poller {
+    pub fn new(
+        client: Client,
+        timeout: Duration,
+        url: Option<String>,
+        path: &str,
+        binance: String,
+        coinbase: String,
+    ) -> Result<Self, String> {
+        let path = JsonPath::parse(path).map_err(|e| format!("invalid --price-path: {e}"))?;
+        Ok(Self {
+            client,
+            timeout,
+            url,
+            path,
+            binance,
+            coinbase,
+        })
+    }
+
+    fn url(&self, feed: Feed, symbol: &str) -> Result<String, String> {
+        match feed {
+            Feed::Binance => Ok(format!(
+                "{}/api/v3/ticker/price?symbol={symbol}",
+                self.binance.trim_end_matches('/')
+            )),
+            Feed::Coinbase => Ok(format!(
+                "{}/products/{symbol}/ticker",
+                self.coinbase.trim_end_matches('/')
+            )),
+            Feed::Poll => self
+                .url
+                .as_ref()
+                .map(|url| url.replace("{symbol}", symbol))
+                .ok_or_else(|| "--poll-url is required for polled symbols".to_string()),
+        }
+    }
+
+    /// The current price of `symbol`.
+    pub async fn quote(&self, feed: Feed, symbol: &str) -> Result<Quote, String> {
+        let url = self.url(feed, symbol)?;
+        let response = self
+            .client
+            .get(&url)
+            .timeout(self.timeout)
+            .send()
+            .await
+            .map_err(|e| format!("{symbol}: {}", e.without_url()))?;
+        if !response.status().is_success() {
+            return Err(format!("{symbol}: {}", response.status()));
+        }
+        let body: Value = response
+            .json()
+            .await
+            .map_err(|e| format!("{symbol}: {}", e.without_url()))?;
+        // The exchanges' tickers both have the price at `price`
+        let found = match feed {
+            Feed::Poll => self.path.query(&body).first().and_then(price),
+            Feed::Binance | Feed::Coinbase => price(&body["price"]),
+        };
+        let price = found.ok_or_else(|| format!("{symbol}: no price in the response"))?;
+        Ok(Quote {
+            symbol: symbol.to_string(),
+            feed,
+            price,
+            via: "poll",
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{Json, Router, extract::Path, routing::get};
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn prices_are_found_with_the_price_path() {
+        let app = Router::new()
+            .route(
+                "/quote/{symbol}",
+                get(|Path(symbol): Path<String>| async move {
+                    Json(json!({"quote": {"symbol": symbol, "last": 189.84}}))
+                }),
+            )
+            .route(
+                "/products/{symbol}/ticker",
+                get(|| async { Json(json!({"trade_id": 1, "price": "3105.17"})) }),
+            );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap_or_else(|e| panic!("bind: {e}"));
+        let addr = listener
+            .local_addr()
+            .unwrap_or_else(|e| panic!("local_addr: {e}"));
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let base = format!("http://{addr}");
+        let poller = Poller::new(
+            Client::new(),
+            Duration::from_secs(5),
+            Some(format!("{base}/quote/{{symbol}}")),
+            "$.quote.last",
+            base.clone(),
+            base.clone(),
+        )
+        .unwrap_or_else(|e| panic!("{e}"));
+        let quote = poller
+            .quote(Feed::Poll, "AAPL")
+            .await
+            .unwrap_or_else(|e| panic!("{e}"));
+        assert_eq!(quote.price, 189.84);
+        assert_eq!(quote.via, "poll");
+
+        let quote = poller
+            .quote(Feed::Coinbase, "ETH-USD")
+            .await
+            .unwrap_or_else(|e| panic!("{e}"));
+        assert_eq!(quote.price, 3105.17);
+
+        let error = poller.quote(Feed::Binance, "BTCUSDT").await.err();
+        assert_eq!(error.as_deref(), Some("BTCUSDT: 404 Not Found"));
+    }
+}
@@ -0,0 +1,241 @@
+//! Exchange WebSocket streams.
+//!
+//! One connection is kept per exchange, carrying all of its symbols. A
+//! stream that drops, or goes quiet for a minute, is reopened every few
+//! seconds; meanwhile its symbols are polled from the exchange's REST
+//! ticker every `--poll-interval`, so prices keep coming.
+
+use crate::feed::{self, Feed, Quote};
+use crate::poll::Poller;
+use futures::{SinkExt, StreamExt};
+use reqwest::Url;
+use serde_json::Value;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+use tokio::sync::mpsc;
+use tokio::time::timeout;
+use tokio_rustls::TlsConnector;
+use tokio_rustls::rustls::pki_types::ServerName;
+use tokio_rustls::rustls::{ClientConfig, RootCertStore, crypto};
+use tokio_tungstenite::WebSocketStream;
+use tokio_tungstenite::tungstenite::Message;
+
+/// Delay before a dropped stream is reopened.
+const RETRY: Duration = Duration::from_secs(5);
+
+/// How long a stream may go without a message before it is reopened.
+const IDLE: Duration = Duration::from_secs(60);
+
+/// A plain or TLS connection.
+pub trait Io: AsyncRead + AsyncWrite + Unpin + Send {}
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Io for T {}
+
+async fn tls(host: &str, stream: TcpStream) -> Result<Box<dyn Io>, String> {
+    let roots = RootCertStore {
+        roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+    };
+    let config = ClientConfig::builder_with_provider(Arc::new(crypto::ring::default_provider()))
+        .with_safe_default_protocol_versions()
+        .map_err(|e| e.to_string())?
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    let name = ServerName::try_from(host.to_string()).map_err(|e| format!("{host}: {e}"))?;
+    let stream = TlsConnector::from(Arc::new(config))
+        .connect(name, stream)
+        .await
+        .map_err(|e| format!("TLS handshake with {host}: {e}"))?;
+    Ok(Box::new(stream))
+}
+
+/// Open a `ws://` or `wss://` URL.
+pub async fn connect(url: &str, limit: Duration) -> Result<WebSocketStream<Box<dyn Io>>, String> {
+    let parsed = Url::parse(url).map_err(|e| format!("{url}: {e}"))?;
+    let host = parsed
+        .host_str()
+        .ok_or_else(|| format!("{url}: no host"))?
+        .to_string();
+    let port = parsed
+        .port_or_known_default()
+        .ok_or_else(|| format!("{url}: no port"))?;
+    let open = async {
+        let tcp = TcpStream::connect((host.as_str(), port))
+            .await
+            .map_err(|e| format!("{host}:{port}: {e}"))?;
+        let stream: Box<dyn Io> = match parsed.scheme() {
+            "wss" => tls(&host, tcp).await?,
+            "ws" => Box::new(tcp),
+            scheme => return Err(format!("{url}: expected ws:// or wss://, not {scheme}://")),
+        };
+        let (socket, _) = tokio_tungstenite::client_async(url, stream)
+            .await
+            .map_err(|e| format!("{host}: {e}"))?;
+        Ok(socket)
+    };
+    timeout(limit, open)
+        .await
+        .map_err(|_| format!("{host}:{port}: timed out"))?
+}
+
+/// Follow one connection to `feed` until it fails.
+async fn follow(
+    feed: Feed,
+    url: &str,
+    symbols: &[&str],
+    limit: Duration,
+    tx: &mpsc::UnboundedSender<Quote>,
+) -> Result<(), String> {
+    let mut socket = connect(url, limit).await?;
+    if feed == Feed::Coinbase {
+        let subscribe = feed::coinbase_subscribe(symbols).to_string();
+        socket
+            .send(Message::text(subscribe))
+            .await
+            .map_err(|e| e.to_string())?;
+    }
+    loop {
+        let message = match timeout(IDLE, socket.next()).await {
+            Err(_) => return Err(format!("no message in {}s", IDLE.as_secs())),
+            Ok(None) => return Err("closed".to_string()),
+            Ok(Some(message)) => message.map_err(|e| e.to_string())?,
+        };
+        let Message::Text(text) = message else {
+            continue;
+        };
+        let Ok(message) = serde_json::from_str::<Value>(text.as_str()) else {
+            continue;
+        };
+        let quote = match feed {
+            Feed::Coinbase => feed::coinbase_quote(&message)?,
+            _ => feed::binance_quote(&message),
+        };
+        if let Some(quote) = quote
+            && tx.send(quote).is_err()
+        {
+            return Ok(());
+        }
+    }
+}
+
+/// Stream `symbols` from `feed` at `url` until the task is aborted,
+/// polling them while the stream is down.
+pub async fn stream(
+    feed: Feed,
+    url: String,
+    symbols: Vec<String>,
+    poller: Poller,
+    poll_interval: Duration,
+    limit: Duration,
+    tx: mpsc::UnboundedSender<Quote>,
+) {
+    let symbols: Vec<&str> = symbols.iter().map(String::as_str).collect();
+    let mut polled: Option<Instant> = None;
+    loop {
+        match follow(feed, &url, &symbols, limit, &tx).await {
+            Ok(()) => return,
+            Err(e) => eprintln!("{}: {e}", feed.as_str()),
+        }
+        if polled.is_none_or(|at| at.elapsed() >= poll_interval) {
+            polled = Some(Instant::now());
+            for symbol in &symbols {
+                match poller.quote(feed, symbol).await {
+                    Ok(quote) => {
+                        let _ = tx.send(quote);
+                    }
+                    Err(e) => eprintln!("{}: {e}", feed.as_str()),
+                }
+            }
+        }
+        tokio::time::sleep(RETRY).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{Json, Router, routing::get};
+    use serde_json::json;
+
+    async fn next(rx: &mut mpsc::UnboundedReceiver<Quote>) -> Quote {
+        tokio::time::timeout(Duration::from_secs(5), rx.recv())
+            .await
+            .ok()
+            .flatten()
+            .unwrap_or_else(|| panic!("no quote"))
+    }
+
+    #[tokio::test]
+    async fn streams_send_quotes_and_fall_back_to_polling() {
+        // An exchange that sends one trade per connection
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap_or_else(|e| panic!("bind: {e}"));
+        let addr = listener
+            .local_addr()
+            .unwrap_or_else(|e| panic!("local_addr: {e}"));
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let Ok(mut socket) = tokio_tungstenite::accept_async(stream).await else {
+                    continue;
+                };
+                let trade = json!({"stream": "btcusdt@trade",
+                    "data": {"e": "trade", "s": "BTCUSDT", "p": "67012.50"}});
+                let _ = socket.send(Message::text(trade.to_string())).await;
+                tokio::time::sleep(Duration::from_secs(10)).await;
+            }
+        });
+
+        // The REST ticker, for a stream that cannot be opened
+        let app = Router::new().route(
+            "/api/v3/ticker/price",
+            get(|| async { Json(json!({"symbol": "ETHUSDT", "price": "3105.17"})) }),
+        );
+        let rest = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap_or_else(|e| panic!("bind: {e}"));
+        let rest_addr = rest
+            .local_addr()
+            .unwrap_or_else(|e| panic!("local_addr: {e}"));
+        tokio::spawn(async move { axum::serve(rest, app).await });
+        let poller = Poller::new(
+            reqwest::Client::new(),
+            Duration::from_secs(5),
+            None,
+            "$.price",
+            format!("http://{rest_addr}"),
+            format!("http://{rest_addr}"),
+        )
+        .unwrap_or_else(|e| panic!("{e}"));
+
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let streaming = tokio::spawn(stream(
+            Feed::Binance,
+            feed::binance_stream(&format!("ws://{addr}"), &["BTCUSDT"]),
+            vec!["BTCUSDT".to_string()],
+            poller.clone(),
+            Duration::from_secs(60),
+            Duration::from_secs(5),
+            tx.clone(),
+        ));
+        let quote = next(&mut rx).await;
+        assert_eq!((quote.symbol.as_str(), quote.price), ("BTCUSDT", 67012.5));
+        assert_eq!(quote.via, "stream");
+        streaming.abort();
+
+        // Nothing listens on the REST server's address for WebSockets
+        let polling = tokio::spawn(stream(
+            Feed::Binance,
+            format!("ws://{rest_addr}/stream?streams=ethusdt@trade"),
+            vec!["ETHUSDT".to_string()],
+            poller,
+            Duration::from_secs(60),
+            Duration::from_secs(5),
+            tx,
+        ));
+        let quote = next(&mut rx).await;
+        assert_eq!((quote.symbol.as_str(), quote.price), ("ETHUSDT", 3105.17));
+        assert_eq!(quote.via, "poll");
+        polling.abort();
+    }
+}
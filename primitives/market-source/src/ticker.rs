@@ -0,0 +1,151 @@
+//! Throttling ticks.
+//!
+//! An exchange stream can carry dozens of trades a second. A symbol's
+//! price is published as `market.tick` at most once per `--tick-interval`:
+//! a price arriving sooner is held, and published when the interval is
+//! up unless a newer one replaces it first. A price equal to the last one
+//! published is not a tick.
+
+use crate::feed::Quote;
+use serde_json::{Value, json};
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// What was last published of a symbol, and what is waiting.
+#[derive(Debug)]
+struct Last {
+    at: Instant,
+    price: f64,
+    pending: Option<Quote>,
+}
+
+/// Ticks to publish, at most one per symbol per interval.
+#[derive(Debug)]
+pub struct Ticker {
+    interval: Duration,
+    symbols: HashMap<String, Last>,
+}
+
+/// The `market.tick` payload of `quote`, given the last price published.
+fn tick(quote: &Quote, previous: Option<f64>) -> Value {
+    let change_pct = previous
+        .filter(|previous| *previous != 0.0)
+        .map(|previous| ((quote.price - previous) / previous * 1e6).round() / 1e4);
+    json!({
+        "symbol": quote.symbol,
+        "feed": quote.feed.as_str(),
+        "price": quote.price,
+        "previous": previous,
+        "change_pct": change_pct,
+        "via": quote.via,
+    })
+}
+
+impl Ticker {
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            symbols: HashMap::new(),
+        }
+    }
+
+    /// The tick to publish for `quote` now, if any.
+    pub fn offer(&mut self, quote: Quote, now: Instant) -> Option<Value> {
+        match self.symbols.get_mut(&quote.symbol) {
+            None => {
+                let payload = tick(&quote, None);
+                self.symbols.insert(
+                    quote.symbol,
+                    Last {
+                        at: now,
+                        price: quote.price,
+                        pending: None,
+                    },
+                );
+                Some(payload)
+            }
+            Some(last) if quote.price == last.price => {
+                last.pending = None;
+                None
+            }
+            Some(last) if now.duration_since(last.at) >= self.interval => {
+                let payload = tick(&quote, Some(last.price));
+                *last = Last {
+                    at: now,
+                    price: quote.price,
+                    pending: None,
+                };
+                Some(payload)
+            }
+            Some(last) => {
+                last.pending = Some(quote);
+                None
+            }
+        }
+    }
+
+    /// Held prices whose interval is up.
+    pub fn flush(&mut self, now: Instant) -> Vec<Value> {
+        let mut ticks = Vec::new();
+        for last in self.symbols.values_mut() {
+            if now.duration_since(last.at) < self.interval {
+                continue;
+            }
+            if let Some(quote) = last.pending.take() {
+                ticks.push(tick(&quote, Some(last.price)));
+                last.at = now;
+                last.price = quote.price;
+            }
+        }
+        ticks
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::feed::Feed;
+
+    fn quote(price: f64) -> Quote {
+        Quote {
+            symbol: "BTCUSDT".to_string(),
+            feed: Feed::Binance,
+            price,
+            via: "stream",
+        }
+    }
+
+    #[test]
+    fn ticks_are_throttled_to_the_latest_price() {
+        let mut ticker = Ticker::new(Duration::from_secs(1));
+        let start = Instant::now();
+        let at = |millis| start + Duration::from_millis(millis);
+
+        let first = ticker.offer(quote(100.0), at(0));
+        assert_eq!(
+            first,
+            Some(
+                json!({"symbol": "BTCUSDT", "feed": "binance", "price": 100.0,
+                        "previous": null, "change_pct": null, "via": "stream"})
+            )
+        );
+        assert_eq!(ticker.offer(quote(101.0), at(200)), None);
+        assert_eq!(ticker.offer(quote(102.0), at(400)), None);
+        assert!(ticker.flush(at(900)).is_empty());
+        let held = ticker.flush(at(1000));
+        assert_eq!(held.len(), 1);
+        assert_eq!(held[0]["price"], 102.0);
+        assert_eq!(held[0]["change_pct"], 2.0);
+
+        // Back to the published price before the interval is up: no tick
+        assert_eq!(ticker.offer(quote(103.0), at(1500)), None);
+        assert_eq!(ticker.offer(quote(102.0), at(1600)), None);
+        assert!(ticker.flush(at(2500)).is_empty());
+
+        let tick = ticker.offer(quote(99.96), at(2600));
+        assert_eq!(
+            tick.map(|tick| tick["change_pct"].clone()),
+            Some(json!(-2.0))
+        );
+    }
+}
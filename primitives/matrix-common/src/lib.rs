@@ -0,0 +1,267 @@
+//! Shared Matrix login for `matrix-source` and `matrix-sink`.
+//!
+//! Each primitive keeps a state directory holding matrix-sdk's SQLite state
+//! and crypto stores and `session.json`, the access token and device ID
+//! from the first password login. Later starts restore that session rather
+//! than logging in again, so the device, and the end-to-end encryption
+//! keys it holds, stay the same across restarts. Encrypted rooms work as
+//! long as the directory survives; two primitives must not share one.
+
+use matrix_sdk::Client;
+use matrix_sdk::authentication::matrix::MatrixSession;
+use primitive_common::doctor::{Report, check_writable_dir};
+use std::fs;
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+
+/// Where and as whom to log in.
+#[derive(Debug, Clone)]
+pub struct Login {
+    /// Homeserver URL, or a server name to discover it from.
+    pub homeserver: String,
+    /// User ID (`@bot:example.org`) or localpart.
+    pub user: String,
+    /// Needed only until a session is saved.
+    pub password: Option<String>,
+    /// State directory.
+    pub store: PathBuf,
+    /// Encrypts the SQLite stores.
+    pub passphrase: Option<String>,
+    /// Display name given to a new device.
+    pub device_name: String,
+}
+
+impl Login {
+    fn session_path(&self) -> PathBuf {
+        self.store.join("session.json")
+    }
+
+    /// Add `--self-test` checks for the state directory and credentials.
+    pub fn self_test(&self, report: &mut Report) {
+        report.check("store", check_writable_dir(&self.store));
+        let session = match (load_session(&self.session_path()), &self.password) {
+            (Ok(Some(session)), _) => Ok(format!(
+                "saved for {} on device {}",
+                session.meta.user_id, session.meta.device_id
+            )),
+            (Ok(None), Some(_)) => Ok("none saved, will log in with the password".to_string()),
+            (Ok(None), None) => Err("none saved and no password given".to_string()),
+            (Err(e), _) => Err(e),
+        };
+        report.check("session", session);
+    }
+}
+
+fn load_session(path: &Path) -> Result<Option<MatrixSession>, String> {
+    match fs::read(path) {
+        Ok(bytes) => serde_json::from_slice(&bytes)
+            .map(Some)
+            .map_err(|e| format!("{}: {e}", path.display())),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(format!("{}: {e}", path.display())),
+    }
+}
+
+/// Write the session readable by the owner only; it holds the access token.
+fn save_session(path: &Path, session: &MatrixSession) -> Result<(), String> {
+    let json = serde_json::to_vec_pretty(session).map_err(|e| e.to_string())?;
+    let tmp = path.with_extension("tmp");
+    fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(&tmp)
+        .and_then(|mut file| file.write_all(&json))
+        .and_then(|()| fs::rename(&tmp, path))
+        .map_err(|e| format!("{}: {e}", path.display()))
+}
+
+/// A logged-in client, and whether its session was restored rather than
+/// freshly created.
+pub async fn connect(login: &Login) -> Result<(Client, bool), String> {
+    fs::create_dir_all(&login.store).map_err(|e| format!("{}: {e}", login.store.display()))?;
+    let client = Client::builder()
+        .server_name_or_homeserver_url(&login.homeserver)
+        .sqlite_store(&login.store, login.passphrase.as_deref())
+        .build()
+        .await
+        .map_err(|e| format!("{}: {e}", login.homeserver))?;
+
+    let path = login.session_path();
+    if let Some(session) = load_session(&path)? {
+        client
+            .restore_session(session)
+            .await
+            .map_err(|e| format!("restoring the session in {}: {e}", path.display()))?;
+        return Ok((client, true));
+    }
+
+    let Some(password) = &login.password else {
+        return Err(format!(
+            "no session in {} and no password to log in with",
+            path.display()
+        ));
+    };
+    let auth = client.matrix_auth();
+    auth.login_username(&login.user, password)
+        .initial_device_display_name(&login.device_name)
+        .send()
+        .await
+        .map_err(|e| format!("login as {}: {e}", login.user))?;
+    let session = auth
+        .session()
+        .ok_or_else(|| format!("login as {} returned no session", login.user))?;
+    save_session(&path, &session)?;
+    eprintln!(
+        "Logged in as {} on new device {}",
+        session.meta.user_id, session.meta.device_id
+    );
+    Ok((client, false))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::StatusCode;
+    use axum::{Json, Router, extract::Request};
+    use matrix_sdk::{SessionMeta, SessionTokens};
+    use serde_json::{Value, json};
+    use std::os::unix::fs::PermissionsExt;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// A fake homeserver accepting `@bot:example.org` with the password
+    /// `s3cret`, counting the logins it is asked for.
+    async fn homeserver(logins: Arc<AtomicUsize>) -> String {
+        let app = Router::new().fallback(move |request: Request| {
+            let logins = Arc::clone(&logins);
+            async move {
+                let path = request.uri().path().to_string();
+                let body = axum::body::to_bytes(request.into_body(), usize::MAX)
+                    .await
+                    .unwrap_or_default();
+                let body: Value = serde_json::from_slice(&body).unwrap_or(Value::Null);
+                match path.as_str() {
+                    "/_matrix/client/versions" => {
+                        (StatusCode::OK, Json(json!({"versions": ["v1.1", "v1.11"]})))
+                    }
+                    "/_matrix/client/v3/login" => {
+                        logins.fetch_add(1, Ordering::SeqCst);
+                        if body["password"] == "s3cret" {
+                            (
+                                StatusCode::OK,
+                                Json(json!({
+                                    "user_id": "@bot:example.org",
+                                    "access_token": "syt_token",
+                                    "device_id": "EMERGENT1",
+                                })),
+                            )
+                        } else {
+                            (
+                                StatusCode::FORBIDDEN,
+                                Json(
+                                    json!({"errcode": "M_FORBIDDEN", "error": "Invalid password"}),
+                                ),
+                            )
+                        }
+                    }
+                    _ => (
+                        StatusCode::NOT_FOUND,
+                        Json(json!({"errcode": "M_UNRECOGNIZED"})),
+                    ),
+                }
+            }
+        });
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap_or_else(|e| panic!("bind: {e}"));
+        let addr = listener
+            .local_addr()
+            .unwrap_or_else(|e| panic!("local_addr: {e}"));
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        format!("http://{addr}")
+    }
+
+    fn login(homeserver: &str, password: Option<&str>, label: &str) -> Login {
+        let store =
+            std::env::temp_dir().join(format!("matrix-common-{label}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&store);
+        Login {
+            homeserver: homeserver.to_string(),
+            user: "@bot:example.org".to_string(),
+            password: password.map(str::to_string),
+            store,
+            passphrase: None,
+            device_name: "emergent test".to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn saved_sessions_are_restored_instead_of_logging_in_again() {
+        let logins = Arc::new(AtomicUsize::new(0));
+        let url = homeserver(Arc::clone(&logins)).await;
+        let mut login = login(&url, Some("s3cret"), "restore");
+
+        let (client, restored) = connect(&login).await.unwrap_or_else(|e| panic!("{e}"));
+        assert!(!restored);
+        assert_eq!(client.device_id().map(|d| d.as_str()), Some("EMERGENT1"));
+        drop(client);
+
+        // The password is no longer needed once a session is saved
+        login.password = None;
+        let (client, restored) = connect(&login).await.unwrap_or_else(|e| panic!("{e}"));
+        assert!(restored);
+        assert_eq!(client.device_id().map(|d| d.as_str()), Some("EMERGENT1"));
+        assert_eq!(logins.load(Ordering::SeqCst), 1);
+        let _ = fs::remove_dir_all(&login.store);
+    }
+
+    #[tokio::test]
+    async fn refused_logins_save_no_session() {
+        let logins = Arc::new(AtomicUsize::new(0));
+        let url = homeserver(Arc::clone(&logins)).await;
+
+        let refused = login(&url, Some("wrong"), "refused");
+        let error = connect(&refused).await.err().unwrap_or_default();
+        assert!(error.starts_with("login as @bot:example.org: "), "{error}");
+        assert_eq!(load_session(&refused.session_path()), Ok(None));
+
+        let passwordless = login(&url, None, "passwordless");
+        let error = connect(&passwordless).await.err().unwrap_or_default();
+        assert!(error.contains("no password to log in with"), "{error}");
+        assert_eq!(logins.load(Ordering::SeqCst), 1);
+        let _ = fs::remove_dir_all(&refused.store);
+        let _ = fs::remove_dir_all(&passwordless.store);
+    }
+
+    #[test]
+    fn sessions_are_saved_privately() {
+        let dir = std::env::temp_dir().join(format!("matrix-common-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap_or_else(|e| panic!("mkdir: {e}"));
+        let path = dir.join("session.json");
+        assert_eq!(load_session(&path), Ok(None));
+
+        let session: MatrixSession = MatrixSession {
+            meta: SessionMeta {
+                user_id: "@bot:example.org"
+                    .try_into()
+                    .unwrap_or_else(|e| panic!("{e}")),
+                device_id: "EMERGENT1".into(),
+            },
+            tokens: SessionTokens {
+                access_token: "syt_secret".to_string(),
+                refresh_token: None,
+            },
+        };
+        save_session(&path, &session).unwrap_or_else(|e| panic!("save: {e}"));
+        assert_eq!(load_session(&path), Ok(Some(session)));
+        let mode = fs::metadata(&path)
+            .unwrap_or_else(|e| panic!("stat: {e}"))
+            .permissions()
+            .mode();
+        assert_eq!(mode & 0o777, 0o600);
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,127 @@
+//! Turning payloads into Matrix messages.
+//!
+//! ```json
+//! {"room": "#ops:example.org", "text": "**web1** is back up", "msgtype": "notice",
+//!  "reply_to": "$xyz", "thread_root": "$root"}
+//! ```
+//!
+//! `text` (or `body`) is Markdown and is sent with an HTML rendering when
+//! it has any formatting; give `html` to send HTML of your own instead.
+//! `room` defaults to `--room` and `msgtype` to `--msgtype`. `reply_to`
+//! makes the message a reply and `thread_root` posts it into a thread
+//! (as a reply to `reply_to` within it, if both are given).
+
+use clap::ValueEnum;
+use matrix_sdk::ruma::OwnedEventId;
+use matrix_sdk::ruma::events::relation::{InReplyTo, Thread};
+use matrix_sdk::ruma::events::room::message::{Relation, RoomMessageEventContent};
+use serde::Deserialize;
+
+/// The kind of message sent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MsgType {
+    /// `m.text`, an ordinary message.
+    Text,
+    /// `m.notice`, what bots send; other bots should not answer it.
+    Notice,
+    /// `m.emote`, shown as an action ("* bot restarts web1").
+    Emote,
+}
+
+/// A message payload.
+#[derive(Debug, Deserialize)]
+pub struct Outgoing {
+    pub room: Option<String>,
+    #[serde(alias = "body")]
+    pub text: String,
+    pub html: Option<String>,
+    pub msgtype: Option<MsgType>,
+    pub reply_to: Option<String>,
+    pub thread_root: Option<String>,
+}
+
+fn event_id(field: &str, id: &str) -> Result<OwnedEventId, String> {
+    OwnedEventId::try_from(id).map_err(|e| format!("{field} '{id}': {e}"))
+}
+
+impl Outgoing {
+    /// The event content, with `msgtype` for payloads that name none.
+    pub fn content(&self, msgtype: MsgType) -> Result<RoomMessageEventContent, String> {
+        let text = self.text.as_str();
+        let mut content = match (self.msgtype.unwrap_or(msgtype), &self.html) {
+            (MsgType::Text, None) => RoomMessageEventContent::text_markdown(text),
+            (MsgType::Text, Some(html)) => RoomMessageEventContent::text_html(text, html),
+            (MsgType::Notice, None) => RoomMessageEventContent::notice_markdown(text),
+            (MsgType::Notice, Some(html)) => RoomMessageEventContent::notice_html(text, html),
+            (MsgType::Emote, None) => RoomMessageEventContent::emote_markdown(text),
+            (MsgType::Emote, Some(html)) => RoomMessageEventContent::emote_html(text, html),
+        };
+        let reply_to = self
+            .reply_to
+            .as_deref()
+            .map(|id| event_id("reply_to", id))
+            .transpose()?;
+        content.relates_to = match (&self.thread_root, reply_to) {
+            (Some(root), Some(reply_to)) => Some(Relation::Thread(Thread::reply(
+                event_id("thread_root", root)?,
+                reply_to,
+            ))),
+            (Some(root), None) => {
+                let root = event_id("thread_root", root)?;
+                Some(Relation::Thread(Thread::plain(root.clone(), root)))
+            }
+            (None, Some(reply_to)) => Some(Relation::Reply {
+                in_reply_to: InReplyTo::new(reply_to),
+            }),
+            (None, None) => None,
+        };
+        Ok(content)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{Value, json};
+
+    fn render(payload: Value) -> Value {
+        let outgoing: Outgoing =
+            serde_json::from_value(payload).unwrap_or_else(|e| panic!("payload: {e}"));
+        let content = outgoing
+            .content(MsgType::Notice)
+            .unwrap_or_else(|e| panic!("content: {e}"));
+        serde_json::to_value(content).unwrap_or_else(|e| panic!("serialize: {e}"))
+    }
+
+    #[test]
+    fn markdown_is_rendered_and_relations_are_kept() {
+        assert_eq!(
+            render(json!({"text": "**web1** is back up", "reply_to": "$xyz"})),
+            json!({
+                "msgtype": "m.notice",
+                "body": "**web1** is back up",
+                "format": "org.matrix.custom.html",
+                "formatted_body": "<strong>web1</strong> is back up",
+                "m.relates_to": {"m.in_reply_to": {"event_id": "$xyz"}},
+            })
+        );
+        assert_eq!(
+            render(json!({"body": "plain words", "msgtype": "text", "thread_root": "$root"})),
+            json!({
+                "msgtype": "m.text",
+                "body": "plain words",
+                "m.relates_to": {
+                    "rel_type": "m.thread",
+                    "event_id": "$root",
+                    "is_falling_back": true,
+                    "m.in_reply_to": {"event_id": "$root"},
+                },
+            })
+        );
+
+        let bad: Outgoing = serde_json::from_value(json!({"text": "hi", "reply_to": "xyz"}))
+            .unwrap_or_else(|e| panic!("payload: {e}"));
+        assert!(bad.content(MsgType::Text).is_err());
+    }
+}
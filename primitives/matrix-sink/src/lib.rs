@@ -0,0 +1,503 @@
+//! Matrix Sink - Send Events to Matrix Rooms
+//!
+//! A Sink that sends each payload as a message to a Matrix room, rendering
+//! Markdown to HTML (see [`content`] for the payload). Rooms are named by
+//! ID or alias and joined on demand, so inviting the sink's account, or
+//! pointing it at a public room, is all the setup a room needs. Messages
+//! to end-to-end encrypted rooms are encrypted with keys kept in the
+//! `--store` directory (see `matrix_common`), and every message sent is
+//! announced as `matrix.sent` with its event ID for later replies.
+//!
+//! The sink logs in on the first message rather than at startup, so a
+//! homeserver outage fails messages (which the harness retries) instead of
+//! the process.
+//!
+//! # Examples
+//!
+//! ```bash
+//! matrix-sink -s alert.fired --homeserver https://matrix.example.org \
+//!   --user @ops-bot:example.org --password "$MATRIX_PASSWORD" \
+//!   --store /var/lib/emergent/matrix-sink --room '#ops:example.org'
+//! ```
+
+// matrix-sdk futures nest deeper than the default limit allows
+#![recursion_limit = "256"]
+
+pub mod content;
+
+use clap::Parser;
+use content::{MsgType, Outgoing};
+use emergent_client::EmergentMessage;
+use matrix_common::Login;
+use matrix_sdk::config::SyncSettings;
+use matrix_sdk::ruma::{OwnedRoomId, RoomOrAliasId};
+use matrix_sdk::{Client, Room, RoomState};
+use primitive_common::capabilities::VERSION;
+use primitive_common::doctor::Report;
+use primitive_common::errors::{ErrorCategory, HandlerError};
+use primitive_common::{Publisher, SinkArgs, SinkConfig, SinkContext, SinkHandler, run_sink};
+use serde_json::{Value, json};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Mutex, OnceLock, PoisonError};
+use std::time::Duration;
+use tokio::sync::OnceCell;
+
+pub const SENT_EVENT_TYPE: &str = "matrix.sent";
+
+/// Delay before a failed background sync is retried.
+const SYNC_RETRY: Duration = Duration::from_secs(5);
+
+/// Matrix Sink — send events to Matrix rooms.
+#[derive(Parser, Debug)]
+#[command(name = "matrix_sink", version = VERSION)]
+#[command(about = "Send events as messages to Matrix rooms")]
+struct Args {
+    /// Message types to subscribe to.
+    #[arg(short, long = "subscribe", required = true)]
+    subscribe: Vec<String>,
+
+    /// Homeserver URL, or a server name to discover it from.
+    #[arg(long, env = "MATRIX_SINK_HOMESERVER")]
+    homeserver: String,
+
+    /// User to log in as.
+    #[arg(short, long, env = "MATRIX_SINK_USER")]
+    user: String,
+
+    /// Password, needed until a session is saved in `--store`.
+    #[arg(long, env = "MATRIX_SINK_PASSWORD", hide_env_values = true)]
+    password: Option<String>,
+
+    /// State directory for the session, sync state and encryption keys.
+    #[arg(long, env = "MATRIX_SINK_STORE")]
+    store: PathBuf,
+
+    /// Passphrase encrypting the stores.
+    #[arg(long, env = "MATRIX_SINK_STORE_PASSPHRASE", hide_env_values = true)]
+    store_passphrase: Option<String>,
+
+    /// Room (ID or alias) for payloads that name none.
+    #[arg(short, long, env = "MATRIX_SINK_ROOM")]
+    room: Option<String>,
+
+    /// Message type for payloads that name none.
+    #[arg(
+        long,
+        env = "MATRIX_SINK_MSGTYPE",
+        value_enum,
+        default_value = "notice"
+    )]
+    msgtype: MsgType,
+
+    #[command(flatten)]
+    sink: SinkArgs,
+}
+
+/// Sends each payload to its room.
+struct MatrixSink {
+    login: Login,
+    /// Logged in on first use, so a homeserver outage fails messages, not startup.
+    client: OnceCell<Client>,
+    room: Option<String>,
+    msgtype: MsgType,
+    /// Room IDs of aliases already joined.
+    aliases: Mutex<HashMap<String, OwnedRoomId>>,
+    publisher: OnceLock<Publisher>,
+}
+
+/// Classify a homeserver error: refusals are final, anything else is retried.
+fn failure(what: &str, e: &matrix_sdk::Error) -> HandlerError {
+    let error = HandlerError::new(ErrorCategory::Request, format!("{what}: {e}"));
+    match e.as_client_api_error() {
+        Some(api) if api.status_code.is_client_error() && api.status_code.as_u16() != 429 => {
+            HandlerError {
+                category: ErrorCategory::Rejected,
+                ..error
+            }
+            .permanent()
+        }
+        _ => error,
+    }
+}
+
+impl MatrixSink {
+    fn publish(&self, message_type: &str, payload: Value) {
+        if let Some(publisher) = self.publisher.get() {
+            let message = EmergentMessage::new(message_type).with_payload(payload);
+            if let Err(e) = publisher.publish(message) {
+                eprintln!("Failed to publish {message_type}: {e}");
+            }
+        }
+    }
+
+    /// The logged-in client. The first call logs in, catches up with the
+    /// joined rooms and starts syncing in the background, which keeps the
+    /// room members' devices, and so the encryption keys, up to date.
+    async fn client(&self) -> Result<&Client, HandlerError> {
+        self.client
+            .get_or_try_init(|| async {
+                let (client, _) = matrix_common::connect(&self.login).await?;
+                client
+                    .sync_once(SyncSettings::default())
+                    .await
+                    .map_err(|e| format!("initial sync: {e}"))?;
+                tokio::spawn({
+                    let client = client.clone();
+                    async move {
+                        loop {
+                            if let Err(e) = client.sync(SyncSettings::default()).await {
+                                eprintln!("Sync failed: {e}");
+                                tokio::time::sleep(SYNC_RETRY).await;
+                            }
+                        }
+                    }
+                });
+                Ok(client)
+            })
+            .await
+            .map_err(|e: String| HandlerError::new(ErrorCategory::Request, e))
+    }
+
+    /// The joined room `target` names, joining it first if need be.
+    async fn room(&self, client: &Client, id: &RoomOrAliasId) -> Result<Room, HandlerError> {
+        let target = id.as_str();
+        let known = if id.is_room_id() {
+            OwnedRoomId::try_from(target).ok()
+        } else {
+            let aliases = self.aliases.lock().unwrap_or_else(PoisonError::into_inner);
+            aliases.get(target).cloned()
+        };
+        if let Some(room) = known
+            .and_then(|room_id| client.get_room(&room_id))
+            .filter(|room| room.state() == RoomState::Joined)
+        {
+            return Ok(room);
+        }
+
+        let room = client
+            .join_room_by_id_or_alias(id, &[])
+            .await
+            .map_err(|e| failure(&format!("joining {target}"), &e))?;
+        eprintln!("Joined {target}");
+        if id.is_room_alias_id() {
+            let mut aliases = self.aliases.lock().unwrap_or_else(PoisonError::into_inner);
+            aliases.insert(target.to_string(), room.room_id().to_owned());
+        }
+        Ok(room)
+    }
+}
+
+impl SinkHandler for MatrixSink {
+    async fn handle(
+        &self,
+        msg: &EmergentMessage,
+        ctx: &SinkContext<'_>,
+    ) -> Result<(), HandlerError> {
+        let outgoing: Outgoing = ctx.decode()?;
+        let target = outgoing
+            .room
+            .as_deref()
+            .or(self.room.as_deref())
+            .ok_or_else(|| {
+                HandlerError::new(ErrorCategory::Parse, "payload names no room and no --room")
+            })?;
+        let id = <&RoomOrAliasId>::try_from(target).map_err(|e| {
+            HandlerError::new(ErrorCategory::Parse, format!("room '{target}': {e}"))
+        })?;
+        let content = outgoing
+            .content(self.msgtype)
+            .map_err(|e| HandlerError::new(ErrorCategory::Parse, e))?;
+
+        if ctx.is_dry_run() {
+            let detail = json!({
+                "room": target,
+                "msgtype": content.msgtype(),
+                "body": content.body(),
+            });
+            ctx.would_have("send", detail).await;
+            return Ok(());
+        }
+
+        let client = self.client().await?;
+        let room = self.room(client, id).await?;
+        let response = room
+            .send(content)
+            .await
+            .map_err(|e| failure(&format!("sending to {target}"), &e))?;
+        self.publish(
+            SENT_EVENT_TYPE,
+            json!({
+                "room": target,
+                "room_id": room.room_id().as_str(),
+                "event_id": response.event_id.as_str(),
+                "message_id": ctx.message_id(),
+                "message_type": msg.message_type.as_str(),
+            }),
+        );
+        Ok(())
+    }
+
+    fn self_test(&self, report: &mut Report) {
+        self.login.self_test(report);
+    }
+
+    fn publishes(&self) -> &'static [&'static str] {
+        &[SENT_EVENT_TYPE]
+    }
+
+    fn attach(&self, publisher: Publisher) {
+        let _ = self.publisher.set(publisher);
+    }
+}
+
+/// Run the primitive with `args` as its command line (the first item is
+/// the program name, as with `std::env::args_os`).
+pub async fn run<I, T>(args: I) -> Result<(), Box<dyn std::error::Error>>
+where
+    I: IntoIterator<Item = T>,
+    T: Into<std::ffi::OsString> + Clone,
+{
+    let args = Args::parse_from(args);
+
+    let config = SinkConfig {
+        name: "matrix_sink",
+        subscribe: &args.subscribe,
+        would_have_as: "matrix.would_have",
+        dead_letter_as: "matrix.dead_letter",
+        settings: &args,
+    };
+    let handler = MatrixSink {
+        login: Login {
+            homeserver: args.homeserver.clone(),
+            user: args.user.clone(),
+            password: args.password.clone(),
+            store: args.store.clone(),
+            passphrase: args.store_passphrase.clone(),
+            device_name: "emergent matrix-sink".to_string(),
+        },
+        client: OnceCell::new(),
+        room: args.room.clone(),
+        msgtype: args.msgtype,
+        aliases: Mutex::new(HashMap::new()),
+        publisher: OnceLock::new(),
+    };
+
+    run_sink(config, &args.sink, handler).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::StatusCode;
+    use axum::{Json, Router, extract::Request};
+    use emergent_testkit::{SinkFixture, fixtures, spawn_sink};
+    use primitive_common::errors::ErrorArgs;
+
+    /// A fake homeserver that accepts `@bot:example.org` with the password
+    /// `s3cret`, syncs an empty account and answers room joins with `join`.
+    async fn homeserver(join: (StatusCode, Value)) -> String {
+        let app = Router::new().fallback(move |request: Request| {
+            let join = join.clone();
+            async move {
+                let path = request.uri().path().to_string();
+                let body = axum::body::to_bytes(request.into_body(), usize::MAX)
+                    .await
+                    .unwrap_or_default();
+                let body: Value = serde_json::from_slice(&body).unwrap_or(Value::Null);
+                match path.as_str() {
+                    "/_matrix/client/versions" => {
+                        (StatusCode::OK, Json(json!({"versions": ["v1.1", "v1.11"]})))
+                    }
+                    "/_matrix/client/v3/login" if body["password"] == "s3cret" => (
+                        StatusCode::OK,
+                        Json(json!({
+                            "user_id": "@bot:example.org",
+                            "access_token": "token",
+                            "device_id": "SINKDEVICE",
+                        })),
+                    ),
+                    "/_matrix/client/v3/login" => (
+                        StatusCode::FORBIDDEN,
+                        Json(json!({"errcode": "M_FORBIDDEN", "error": "Invalid password"})),
+                    ),
+                    "/_matrix/client/v3/sync" => {
+                        (StatusCode::OK, Json(json!({"next_batch": "s1"})))
+                    }
+                    _ if path.starts_with("/_matrix/client/v3/join/") => (join.0, Json(join.1)),
+                    "/_matrix/client/v3/keys/upload" => (
+                        StatusCode::OK,
+                        Json(json!({"one_time_key_counts": {"signed_curve25519": 50}})),
+                    ),
+                    _ => (StatusCode::OK, Json(json!({}))),
+                }
+            }
+        });
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap_or_else(|e| panic!("bind: {e}"));
+        let addr = listener
+            .local_addr()
+            .unwrap_or_else(|e| panic!("local_addr: {e}"));
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        format!("http://{addr}")
+    }
+
+    fn matrix_sink(homeserver: &str, password: &str, store: &fixtures::TempDir) -> MatrixSink {
+        MatrixSink {
+            login: Login {
+                homeserver: homeserver.to_string(),
+                user: "@bot:example.org".to_string(),
+                password: Some(password.to_string()),
+                store: store.path().to_path_buf(),
+                passphrase: None,
+                device_name: "emergent matrix-sink".to_string(),
+            },
+            client: OnceCell::new(),
+            room: None,
+            msgtype: MsgType::Notice,
+            aliases: Mutex::new(HashMap::new()),
+            publisher: OnceLock::new(),
+        }
+    }
+
+    fn failing() -> SinkArgs {
+        SinkArgs {
+            max_attempts: 2,
+            retry_delay: 0,
+            dead_letter: true,
+            errors: ErrorArgs { emit_errors: true },
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn unaddressable_messages_are_dead_lettered_as_parse_errors() {
+        let store = fixtures::TempDir::new("matrix-sink-parse");
+        let args = SinkArgs {
+            max_attempts: 1,
+            ..failing()
+        };
+        let handler = matrix_sink("http://127.0.0.1:9", "s3cret", &store);
+        let (mut engine, run) =
+            spawn_sink(SinkFixture::new("matrix_sink", "matrix"), args, handler);
+
+        for (payload, expected) in [
+            (json!({"text": "hi"}), "payload names no room and no --room"),
+            (json!({"text": "hi", "room": "ops"}), "room 'ops': "),
+            (
+                json!({"text": "hi", "room": "#ops:example.org", "reply_to": "xyz"}),
+                "reply_to",
+            ),
+        ] {
+            engine
+                .inject_message(fixtures::message("alert.fired", payload))
+                .await;
+            let error = engine.expect_published("primitive.error").await;
+            assert_eq!(error.payload()["category"], "parse");
+            let dead = engine.expect_published("matrix.dead_letter").await;
+            let message = dead.payload()["error"].as_str().unwrap_or_default();
+            assert!(message.contains(expected), "{message}");
+        }
+
+        engine.close();
+        assert_eq!(run.await.ok(), Some(Ok(())));
+    }
+
+    #[tokio::test]
+    async fn dry_run_reports_the_message_without_logging_in() {
+        let store = fixtures::TempDir::new("matrix-sink-dry-run");
+        let args = SinkArgs {
+            dry_run: true,
+            ..Default::default()
+        };
+        let mut handler = matrix_sink("http://127.0.0.1:9", "s3cret", &store);
+        handler.room = Some("#ops:example.org".to_string());
+        let (mut engine, run) =
+            spawn_sink(SinkFixture::new("matrix_sink", "matrix"), args, handler);
+
+        engine
+            .inject_message(fixtures::message(
+                "alert.fired",
+                json!({"text": "**web1** is down"}),
+            ))
+            .await;
+        let report = engine.expect_published("matrix.would_have").await;
+        assert_eq!(
+            report.payload()["detail"],
+            json!({"room": "#ops:example.org", "msgtype": "m.notice", "body": "**web1** is down"})
+        );
+        assert!(!store.path().join("session.json").exists());
+
+        engine.close();
+        assert_eq!(run.await.ok(), Some(Ok(())));
+    }
+
+    #[tokio::test]
+    async fn refused_logins_are_retried_and_save_no_session() {
+        let store = fixtures::TempDir::new("matrix-sink-login");
+        let url = homeserver((StatusCode::OK, json!({}))).await;
+        let handler = matrix_sink(&url, "wrong", &store);
+        let (mut engine, run) = spawn_sink(
+            SinkFixture::new("matrix_sink", "matrix"),
+            failing(),
+            handler,
+        );
+
+        engine
+            .inject_message(fixtures::message(
+                "alert.fired",
+                json!({"text": "hi", "room": "#ops:example.org"}),
+            ))
+            .await;
+        for disposition in ["retrying", "dead_lettered"] {
+            let error = engine.expect_published("primitive.error").await;
+            assert_eq!(error.payload()["category"], "request");
+            assert_eq!(error.payload()["disposition"], disposition);
+        }
+        let dead = engine.expect_published("matrix.dead_letter").await;
+        let message = dead.payload()["error"].as_str().unwrap_or_default();
+        assert!(
+            message.starts_with("login as @bot:example.org"),
+            "{message}"
+        );
+        assert!(!store.path().join("session.json").exists());
+
+        engine.close();
+        assert_eq!(run.await.ok(), Some(Ok(())));
+    }
+
+    #[tokio::test]
+    async fn refused_joins_are_dead_lettered_without_retrying() {
+        let store = fixtures::TempDir::new("matrix-sink-join");
+        let url = homeserver((
+            StatusCode::FORBIDDEN,
+            json!({"errcode": "M_FORBIDDEN", "error": "You are not invited"}),
+        ))
+        .await;
+        let handler = matrix_sink(&url, "s3cret", &store);
+        let (mut engine, run) = spawn_sink(
+            SinkFixture::new("matrix_sink", "matrix"),
+            failing(),
+            handler,
+        );
+
+        engine
+            .inject_message(fixtures::message(
+                "alert.fired",
+                json!({"text": "hi", "room": "#ops:example.org"}),
+            ))
+            .await;
+        let error = engine.expect_published("primitive.error").await;
+        assert_eq!(error.payload()["category"], "rejected");
+        assert_eq!(error.payload()["disposition"], "dead_lettered");
+        let dead = engine.expect_published("matrix.dead_letter").await;
+        assert_eq!(dead.payload()["attempts"], 1);
+        let message = dead.payload()["error"].as_str().unwrap_or_default();
+        assert!(message.starts_with("joining #ops:example.org"), "{message}");
+        assert!(store.path().join("session.json").exists());
+
+        engine.close();
+        assert_eq!(run.await.ok(), Some(Ok(())));
+    }
+}
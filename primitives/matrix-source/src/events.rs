@@ -0,0 +1,209 @@
+//! Payloads of the events the source emits.
+//!
+//! `matrix.message`:
+//!
+//! ```json
+//! {"room_id": "!abc:example.org", "room_name": "Ops", "event_id": "$xyz",
+//!  "sender": "@alice:example.org", "msgtype": "m.text", "body": "deploy web1",
+//!  "formatted_body": "deploy <code>web1</code>", "timestamp": 1700000000000,
+//!  "encrypted": true, "in_reply_to": "$parent", "thread_root": "$root"}
+//! ```
+//!
+//! `formatted_body` is the HTML of formatted text, `url` the `mxc://` URI
+//! of unencrypted media, `in_reply_to` and `thread_root` are present for
+//! replies and threads, and an edit carries the new text with `replaces`
+//! naming the original event.
+//!
+//! `matrix.reaction`:
+//!
+//! ```json
+//! {"room_id": "!abc:example.org", "event_id": "$r1", "sender": "@alice:example.org",
+//!  "key": "👍", "relates_to": "$xyz", "timestamp": 1700000000000}
+//! ```
+
+use matrix_sdk::ruma::RoomId;
+use matrix_sdk::ruma::events::reaction::OriginalSyncReactionEvent;
+use matrix_sdk::ruma::events::room::MediaSource;
+use matrix_sdk::ruma::events::room::message::{
+    MessageType, OriginalSyncRoomMessageEvent, Relation,
+};
+use serde_json::{Value, json};
+
+pub const MESSAGE_EVENT_TYPE: &str = "matrix.message";
+pub const REACTION_EVENT_TYPE: &str = "matrix.reaction";
+pub const EVENT_TYPES: [&str; 2] = [MESSAGE_EVENT_TYPE, REACTION_EVENT_TYPE];
+
+/// Where a room event came from.
+pub struct Origin<'a> {
+    pub room_id: &'a RoomId,
+    pub room_name: Option<String>,
+    pub encrypted: bool,
+}
+
+fn formatted_body(msgtype: &MessageType) -> Option<&str> {
+    let formatted = match msgtype {
+        MessageType::Text(content) => content.formatted.as_ref(),
+        MessageType::Notice(content) => content.formatted.as_ref(),
+        MessageType::Emote(content) => content.formatted.as_ref(),
+        _ => None,
+    }?;
+    Some(&formatted.body)
+}
+
+fn media_url(msgtype: &MessageType) -> Option<String> {
+    let source = match msgtype {
+        MessageType::Image(content) => &content.source,
+        MessageType::File(content) => &content.source,
+        MessageType::Audio(content) => &content.source,
+        MessageType::Video(content) => &content.source,
+        _ => return None,
+    };
+    match source {
+        MediaSource::Plain(uri) => Some(uri.to_string()),
+        MediaSource::Encrypted(_) => None,
+    }
+}
+
+/// The `matrix.message` payload for `event`.
+pub fn message(event: &OriginalSyncRoomMessageEvent, origin: &Origin<'_>) -> Value {
+    let mut msgtype = &event.content.msgtype;
+    let mut payload = json!({
+        "room_id": origin.room_id.as_str(),
+        "room_name": origin.room_name,
+        "event_id": event.event_id.as_str(),
+        "sender": event.sender.as_str(),
+        "timestamp": u64::from(event.origin_server_ts.0),
+        "encrypted": origin.encrypted,
+    });
+    match &event.content.relates_to {
+        Some(Relation::Reply { in_reply_to }) => {
+            payload["in_reply_to"] = json!(in_reply_to.event_id.as_str());
+        }
+        Some(Relation::Thread(thread)) => {
+            payload["thread_root"] = json!(thread.event_id.as_str());
+            if let Some(in_reply_to) = thread
+                .in_reply_to
+                .as_ref()
+                .filter(|_| !thread.is_falling_back)
+            {
+                payload["in_reply_to"] = json!(in_reply_to.event_id.as_str());
+            }
+        }
+        Some(Relation::Replacement(replacement)) => {
+            payload["replaces"] = json!(replacement.event_id.as_str());
+            msgtype = &replacement.new_content.msgtype;
+        }
+        _ => {}
+    }
+    payload["msgtype"] = json!(msgtype.msgtype());
+    payload["body"] = json!(msgtype.body());
+    if let Some(html) = formatted_body(msgtype) {
+        payload["formatted_body"] = json!(html);
+    }
+    if let Some(url) = media_url(msgtype) {
+        payload["url"] = json!(url);
+    }
+    payload
+}
+
+/// The `matrix.reaction` payload for `event`.
+pub fn reaction(event: &OriginalSyncReactionEvent, room_id: &RoomId) -> Value {
+    json!({
+        "room_id": room_id.as_str(),
+        "event_id": event.event_id.as_str(),
+        "sender": event.sender.as_str(),
+        "key": event.content.relates_to.key,
+        "relates_to": event.content.relates_to.event_id.as_str(),
+        "timestamp": u64::from(event.origin_server_ts.0),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse<T: serde::de::DeserializeOwned>(event: Value) -> T {
+        serde_json::from_value(event).unwrap_or_else(|e| panic!("event: {e}"))
+    }
+
+    #[test]
+    fn threaded_replies_and_reactions_keep_their_relations() {
+        let room_id = <&RoomId>::try_from("!abc:example.org").unwrap_or_else(|e| panic!("{e}"));
+        let event: OriginalSyncRoomMessageEvent = parse(json!({
+            "type": "m.room.message",
+            "event_id": "$reply",
+            "sender": "@alice:example.org",
+            "origin_server_ts": 1700000000000_u64,
+            "content": {
+                "msgtype": "m.text",
+                "body": "deploy web1",
+                "format": "org.matrix.custom.html",
+                "formatted_body": "deploy <code>web1</code>",
+                "m.relates_to": {
+                    "rel_type": "m.thread",
+                    "event_id": "$root",
+                    "is_falling_back": false,
+                    "m.in_reply_to": {"event_id": "$parent"},
+                },
+            },
+        }));
+        let origin = Origin {
+            room_id,
+            room_name: Some("Ops".to_string()),
+            encrypted: true,
+        };
+        assert_eq!(
+            message(&event, &origin),
+            json!({
+                "room_id": "!abc:example.org",
+                "room_name": "Ops",
+                "event_id": "$reply",
+                "sender": "@alice:example.org",
+                "timestamp": 1700000000000_u64,
+                "encrypted": true,
+                "thread_root": "$root",
+                "in_reply_to": "$parent",
+                "msgtype": "m.text",
+                "body": "deploy web1",
+                "formatted_body": "deploy <code>web1</code>",
+            })
+        );
+
+        let edit: OriginalSyncRoomMessageEvent = parse(json!({
+            "type": "m.room.message",
+            "event_id": "$edit",
+            "sender": "@alice:example.org",
+            "origin_server_ts": 1700000001000_u64,
+            "content": {
+                "msgtype": "m.text",
+                "body": "* deploy web2",
+                "m.new_content": {"msgtype": "m.text", "body": "deploy web2"},
+                "m.relates_to": {"rel_type": "m.replace", "event_id": "$reply"},
+            },
+        }));
+        let payload = message(&edit, &origin);
+        assert_eq!(payload["replaces"], "$reply");
+        assert_eq!(payload["body"], "deploy web2");
+
+        let thumbs: OriginalSyncReactionEvent = parse(json!({
+            "type": "m.reaction",
+            "event_id": "$r1",
+            "sender": "@bob:example.org",
+            "origin_server_ts": 1700000002000_u64,
+            "content": {
+                "m.relates_to": {"rel_type": "m.annotation", "event_id": "$reply", "key": "👍"},
+            },
+        }));
+        assert_eq!(
+            reaction(&thumbs, room_id),
+            json!({
+                "room_id": "!abc:example.org",
+                "event_id": "$r1",
+                "sender": "@bob:example.org",
+                "key": "👍",
+                "relates_to": "$reply",
+                "timestamp": 1700000002000_u64,
+            })
+        );
+    }
+}
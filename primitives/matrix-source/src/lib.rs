@@ -0,0 +1,457 @@
+//! Matrix Source - Matrix Room Events
+//!
+//! A Source that logs in to a Matrix homeserver, follows the client-server
+//! `/sync` stream and emits room messages as `matrix.message` and reactions
+//! as `matrix.reaction` (see [`events`] for the payloads). Messages in
+//! end-to-end encrypted rooms are decrypted with the keys kept in the
+//! `--store` directory (see `matrix_common`); `encrypted` tells them apart.
+//!
+//! On its first start the source logs in with `--password` and begins with
+//! events arriving from then on; after a restart it resumes where it
+//! stopped. Its own messages are skipped unless `--include-own` is given,
+//! so a bot replying through `matrix-sink` with the same account does not
+//! hear itself.
+//!
+//! Sources are SILENT - they only produce domain messages.
+//! All lifecycle events are published by the engine.
+//!
+//! # Usage
+//!
+//! ```bash
+//! matrix-source --homeserver https://matrix.example.org --user @ops-bot:example.org \
+//!   --password "$MATRIX_PASSWORD" --store /var/lib/emergent/matrix-source \
+//!   --room '#ops:example.org' --autojoin
+//! ```
+//!
+//! On SIGTERM syncing stops and events already received get
+//! `--drain-timeout` milliseconds to be published.
+
+// matrix-sdk futures nest deeper than the default limit allows
+#![recursion_limit = "256"]
+
+pub mod events;
+
+use clap::Parser;
+use emergent_client::EmergentSource;
+use events::{EVENT_TYPES, MESSAGE_EVENT_TYPE, Origin, REACTION_EVENT_TYPE};
+use matrix_common::Login;
+use matrix_sdk::config::SyncSettings;
+use matrix_sdk::deserialized_responses::EncryptionInfo;
+use matrix_sdk::ruma::events::reaction::OriginalSyncReactionEvent;
+use matrix_sdk::ruma::events::room::member::{MembershipState, StrippedRoomMemberEvent};
+use matrix_sdk::ruma::events::room::message::OriginalSyncRoomMessageEvent;
+use matrix_sdk::ruma::{OwnedRoomId, RoomAliasId, RoomId, UserId};
+use matrix_sdk::{Client, Room};
+use primitive_common::capabilities::{self, CapabilityArgs, Role, VERSION, capabilities_message};
+use primitive_common::doctor::Report;
+use primitive_common::source::{Outlet, SourceArgs};
+use serde_json::Value;
+use std::collections::HashSet;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::signal::unix::{SignalKind, signal};
+use tokio::sync::mpsc;
+
+/// Delay before a failed sync is retried.
+const SYNC_RETRY: Duration = Duration::from_secs(5);
+
+#[derive(Parser, Debug, Clone)]
+#[command(name = "matrix-source", version = VERSION)]
+#[command(about = "Syncs Matrix rooms and emits message and reaction events")]
+struct Args {
+    /// Homeserver URL, or a server name to discover it from.
+    #[arg(long, env = "MATRIX_SOURCE_HOMESERVER")]
+    homeserver: String,
+
+    /// User to log in as.
+    #[arg(short, long, env = "MATRIX_SOURCE_USER")]
+    user: String,
+
+    /// Password, needed until a session is saved in `--store`.
+    #[arg(long, env = "MATRIX_SOURCE_PASSWORD", hide_env_values = true)]
+    password: Option<String>,
+
+    /// State directory for the session, sync state and encryption keys.
+    #[arg(long, env = "MATRIX_SOURCE_STORE")]
+    store: PathBuf,
+
+    /// Passphrase encrypting the stores.
+    #[arg(long, env = "MATRIX_SOURCE_STORE_PASSPHRASE", hide_env_values = true)]
+    store_passphrase: Option<String>,
+
+    /// Rooms to emit events from, by ID or alias (default: every joined room); repeatable.
+    #[arg(long = "room", env = "MATRIX_SOURCE_ROOMS", value_delimiter = ',')]
+    rooms: Vec<String>,
+
+    /// Accept invites (only to `--room` rooms, if any are given).
+    #[arg(long, env = "MATRIX_SOURCE_AUTOJOIN")]
+    autojoin: bool,
+
+    /// Also emit messages sent by the source's own account.
+    #[arg(long, env = "MATRIX_SOURCE_INCLUDE_OWN")]
+    include_own: bool,
+
+    /// Verify external dependencies and exit without connecting to the engine.
+    #[arg(long)]
+    self_test: bool,
+
+    #[command(flatten)]
+    source: SourceArgs,
+
+    #[command(flatten)]
+    capabilities: CapabilityArgs,
+}
+
+/// Variables available to `--emit-type-template`.
+const TEMPLATE_VARIABLES: &[&str] = &["source"];
+
+impl Args {
+    fn login(&self) -> Login {
+        Login {
+            homeserver: self.homeserver.clone(),
+            user: self.user.clone(),
+            password: self.password.clone(),
+            store: self.store.clone(),
+            passphrase: self.store_passphrase.clone(),
+            device_name: "emergent matrix-source".to_string(),
+        }
+    }
+}
+
+/// Which events are emitted.
+struct Filter {
+    rooms: HashSet<OwnedRoomId>,
+    include_own: bool,
+}
+
+impl Filter {
+    fn room(&self, room_id: &RoomId) -> bool {
+        self.rooms.is_empty() || self.rooms.contains(room_id)
+    }
+
+    fn wants(&self, room: &Room, sender: &UserId) -> bool {
+        self.room(room.room_id()) && (self.include_own || sender != room.own_user_id())
+    }
+}
+
+/// Runs `--self-test` checks and exits.
+fn self_test(args: &Args, name: &str) -> ! {
+    let mut report = Report::new(name);
+    args.login().self_test(&mut report);
+    args.source.self_test(&mut report, TEMPLATE_VARIABLES);
+    report.finish()
+}
+
+/// Resolve `--room` aliases to room IDs.
+async fn resolve_rooms(client: &Client, rooms: &[String]) -> Result<HashSet<OwnedRoomId>, String> {
+    let mut ids = HashSet::new();
+    for room in rooms {
+        let id = if room.starts_with('#') {
+            let alias =
+                <&RoomAliasId>::try_from(room.as_str()).map_err(|e| format!("{room}: {e}"))?;
+            client
+                .resolve_room_alias(alias)
+                .await
+                .map_err(|e| format!("{room}: {e}"))?
+                .room_id
+        } else {
+            OwnedRoomId::try_from(room.as_str()).map_err(|e| format!("{room}: {e}"))?
+        };
+        ids.insert(id);
+    }
+    Ok(ids)
+}
+
+/// Forward room events to `tx` and, with `--autojoin`, accept invites.
+fn add_handlers(
+    client: &Client,
+    filter: &Arc<Filter>,
+    autojoin: bool,
+    tx: &mpsc::UnboundedSender<(&'static str, Value)>,
+) {
+    if autojoin {
+        let filter = Arc::clone(filter);
+        client.add_event_handler(move |event: StrippedRoomMemberEvent, room: Room| {
+            let filter = Arc::clone(&filter);
+            async move {
+                if event.state_key != room.own_user_id()
+                    || event.content.membership != MembershipState::Invite
+                {
+                    return;
+                }
+                if !filter.room(room.room_id()) {
+                    eprintln!(
+                        "Ignoring invite to {} from {}",
+                        room.room_id(),
+                        event.sender
+                    );
+                    return;
+                }
+                match room.join().await {
+                    Ok(()) => {
+                        eprintln!("Joined {} on invite from {}", room.room_id(), event.sender)
+                    }
+                    Err(e) => eprintln!("Failed to join {}: {e}", room.room_id()),
+                }
+            }
+        });
+    }
+
+    let (messages, message_filter) = (tx.clone(), Arc::clone(filter));
+    client.add_event_handler(
+        move |event: OriginalSyncRoomMessageEvent,
+              room: Room,
+              encryption: Option<EncryptionInfo>| {
+            let (tx, filter) = (messages.clone(), Arc::clone(&message_filter));
+            async move {
+                if !filter.wants(&room, &event.sender) {
+                    return;
+                }
+                let origin = Origin {
+                    room_id: room.room_id(),
+                    room_name: room.name(),
+                    encrypted: encryption.is_some(),
+                };
+                let _ = tx.send((MESSAGE_EVENT_TYPE, events::message(&event, &origin)));
+            }
+        },
+    );
+
+    let (reactions, reaction_filter) = (tx.clone(), Arc::clone(filter));
+    client.add_event_handler(move |event: OriginalSyncReactionEvent, room: Room| {
+        let (tx, filter) = (reactions.clone(), Arc::clone(&reaction_filter));
+        async move {
+            if filter.wants(&room, &event.sender) {
+                let _ = tx.send((
+                    REACTION_EVENT_TYPE,
+                    events::reaction(&event, room.room_id()),
+                ));
+            }
+        }
+    });
+}
+
+/// Run the primitive with `args` as its command line (the first item is
+/// the program name, as with `std::env::args_os`).
+pub async fn run<I, T>(args: I) -> Result<(), Box<dyn std::error::Error>>
+where
+    I: IntoIterator<Item = T>,
+    T: Into<std::ffi::OsString> + Clone,
+{
+    let args = Args::parse_from(args);
+
+    let name = std::env::var("EMERGENT_NAME").unwrap_or_else(|_| "matrix-source".to_string());
+
+    if args.self_test {
+        self_test(&args, &name);
+    }
+    if let Err(e) = args.source.validate(TEMPLATE_VARIABLES) {
+        eprintln!("Error: {e}");
+        std::process::exit(1);
+    }
+
+    let produces = args.source.produces(&EVENT_TYPES);
+    let produces: Vec<&str> = produces.iter().map(String::as_str).collect();
+    let descriptor = args
+        .capabilities
+        .describe(&name, Role::Source, &[], &produces, &args);
+    if args.capabilities.capabilities {
+        capabilities::print(&descriptor);
+    }
+
+    let exit = |e: String| -> ! {
+        eprintln!("Error: {e}");
+        std::process::exit(1);
+    };
+    let (client, restored) = matrix_common::connect(&args.login())
+        .await
+        .unwrap_or_else(|e| exit(e));
+    let filter = Arc::new(Filter {
+        rooms: resolve_rooms(&client, &args.rooms)
+            .await
+            .unwrap_or_else(|e| exit(e)),
+        include_own: args.include_own,
+    });
+
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    if restored {
+        // Resume from the stored sync token, emitting what arrived while down
+        add_handlers(&client, &filter, args.autojoin, &tx);
+    } else {
+        // Skip the room history a first sync returns
+        client
+            .sync_once(SyncSettings::default())
+            .await
+            .unwrap_or_else(|e| exit(format!("initial sync: {e}")));
+        add_handlers(&client, &filter, args.autojoin, &tx);
+    }
+
+    let source = match EmergentSource::connect(&name).await {
+        Ok(s) => s,
+        Err(e) => {
+            eprintln!("Failed to connect to Emergent engine: {e}");
+            std::process::exit(1);
+        }
+    };
+    let outlet = Arc::new(Outlet::new(source, &name, &args.source)?);
+    if args.capabilities.announce {
+        outlet.announce(capabilities_message(&descriptor)).await;
+    }
+    tokio::spawn(Arc::clone(&outlet).drain_every(args.source.spool.retry_interval()));
+
+    let sync = tokio::spawn({
+        let client = client.clone();
+        async move {
+            loop {
+                if let Err(e) = client.sync(SyncSettings::default()).await {
+                    eprintln!("Sync failed: {e}");
+                    tokio::time::sleep(SYNC_RETRY).await;
+                }
+            }
+        }
+    });
+
+    let mut sigterm = signal(SignalKind::terminate())?;
+    loop {
+        tokio::select! {
+            _ = sigterm.recv() => break,
+
+            Some((event_type, payload)) = rx.recv() => {
+                // The outlet logs and reports what it cannot deliver
+                let _ = outlet.publish(event_type, &[], payload).await;
+            }
+        }
+    }
+    // Stop syncing, then publish what was already received
+    sync.abort();
+    rx.close();
+    let queued = async {
+        while let Some((event_type, payload)) = rx.recv().await {
+            let _ = outlet.publish(event_type, &[], payload).await;
+        }
+    };
+    args.source.drain.drain("queued events", queued).await;
+    outlet.disconnect().await;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::StatusCode;
+    use axum::{Json, Router, extract::Request};
+    use emergent_testkit::{MockEngine, fixtures};
+    use serde_json::json;
+    use std::path::Path;
+
+    async fn outlet(socket: &Path, args: &SourceArgs) -> Outlet {
+        let source = EmergentSource::connect_to("matrix-source", socket)
+            .await
+            .unwrap_or_else(|e| panic!("connect: {e}"));
+        Outlet::new(source, "matrix-source", args).unwrap_or_else(|e| panic!("open spool: {e}"))
+    }
+
+    fn message() -> Value {
+        json!({"room_id": "!abc:example.org", "event_id": "$xyz", "body": "deploy"})
+    }
+
+    #[tokio::test]
+    async fn events_survive_the_engine_being_down() {
+        let dir = fixtures::TempDir::new("matrix-source-spool");
+        let socket = dir.path().join("engine.sock");
+        let args = SourceArgs {
+            topics: primitive_common::topics::TopicArgs {
+                emit_type_map: vec![("default".to_string(), "chat.event".to_string())],
+                ..Default::default()
+            },
+            spool: primitive_common::spool::SpoolArgs {
+                spool_dir: Some(dir.path().join("spool")),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        let mut engine = MockEngine::serve(&socket);
+        let down = outlet(&socket, &args).await;
+        engine.shut_down().await;
+        down.publish(MESSAGE_EVENT_TYPE, &[], message())
+            .await
+            .unwrap_or_else(|e| panic!("spool: {e}"));
+        drop(down);
+
+        let mut engine = MockEngine::serve(&socket);
+        outlet(&socket, &args).await.drain_spool().await;
+        let published = engine.expect_published("chat.event").await;
+        assert_eq!(published.payload()["event_id"], "$xyz");
+        engine.shut_down().await;
+    }
+
+    #[tokio::test]
+    async fn events_are_lost_without_a_spool() {
+        let dir = fixtures::TempDir::new("matrix-source-down");
+        let socket = dir.path().join("engine.sock");
+
+        let mut engine = MockEngine::serve(&socket);
+        let down = outlet(&socket, &SourceArgs::default()).await;
+        engine.shut_down().await;
+        let lost = down.publish(MESSAGE_EVENT_TYPE, &[], message()).await;
+        assert!(lost.is_err_and(|e| e.contains(MESSAGE_EVENT_TYPE)));
+    }
+
+    /// A client of a fake homeserver whose directory knows `#ops:example.org`.
+    async fn directory() -> Client {
+        let app = Router::new().fallback(|request: Request| async move {
+            let path = request.uri().path();
+            if path == "/_matrix/client/versions" {
+                (StatusCode::OK, Json(json!({"versions": ["v1.1", "v1.11"]})))
+            } else if path == "/_matrix/client/v3/directory/room/%23ops:example.org" {
+                let room = json!({"room_id": "!ops:example.org", "servers": ["example.org"]});
+                (StatusCode::OK, Json(room))
+            } else {
+                let missing = json!({"errcode": "M_NOT_FOUND", "error": "Room alias not found"});
+                (StatusCode::NOT_FOUND, Json(missing))
+            }
+        });
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap_or_else(|e| panic!("bind: {e}"));
+        let addr = listener
+            .local_addr()
+            .unwrap_or_else(|e| panic!("local_addr: {e}"));
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        Client::builder()
+            .homeserver_url(format!("http://{addr}"))
+            .build()
+            .await
+            .unwrap_or_else(|e| panic!("client: {e}"))
+    }
+
+    #[tokio::test]
+    async fn rooms_are_checked_and_aliases_resolved() {
+        let client = directory().await;
+        let rooms = |names: &[&str]| names.iter().map(|n| n.to_string()).collect::<Vec<_>>();
+
+        let ids = resolve_rooms(&client, &rooms(&["#ops:example.org", "!abc:example.org"]))
+            .await
+            .unwrap_or_else(|e| panic!("{e}"));
+        let expected: HashSet<OwnedRoomId> = ["!ops:example.org", "!abc:example.org"]
+            .into_iter()
+            .map(|id| OwnedRoomId::try_from(id).unwrap_or_else(|e| panic!("{e}")))
+            .collect();
+        assert_eq!(ids, expected);
+
+        for (names, prefix) in [
+            (["#gone:example.org"], "#gone:example.org: "),
+            (["#ops"], "#ops: "),
+            (["ops"], "ops: "),
+        ] {
+            let error = resolve_rooms(&client, &rooms(&names))
+                .await
+                .err()
+                .unwrap_or_default();
+            assert!(error.starts_with(prefix), "{error}");
+        }
+    }
+}
@@ -0,0 +1,161 @@
+//! Exporting closed periods: as CSV files, or pushed to a billing API.
+//!
+//! Each target keeps its own record of what it received, so a failing API
+//! doesn't hold back the files, and a period missed while the API was down
+//! is pushed on the next pass.
+
+use crate::store::{Period, Store, Usage};
+use primitive_common::time;
+use reqwest::Client;
+use serde_json::{Value, json};
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// `field` quoted for CSV if it needs to be.
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/// The rollups of a period as CSV, with a header row.
+pub fn csv(period: Period, start: i64, usage: &[Usage]) -> String {
+    let mut out = String::from("period,start,end,key,metric,quantity,events\n");
+    let (from, to) = (time::format(start), time::format(start + period.seconds()));
+    for row in usage {
+        out.push_str(&format!(
+            "{},{from},{to},{},{},{},{}\n",
+            period.as_str(),
+            csv_field(&row.key),
+            csv_field(&row.metric),
+            row.quantity,
+            row.events
+        ));
+    }
+    out
+}
+
+/// The CSV file name of a period: `usage-day-2024-05-20.csv`,
+/// `usage-hour-2024-05-20T16.csv`.
+pub fn file_name(period: Period, start: i64) -> String {
+    let stamp = time::format(start);
+    let stamp = match period {
+        Period::Hour => &stamp[..13],
+        Period::Day => &stamp[..10],
+    };
+    format!("usage-{}-{stamp}.csv", period.as_str())
+}
+
+/// The JSON document pushed to the billing API for a period.
+pub fn document(period: Period, start: i64, usage: &[Usage]) -> Value {
+    json!({
+        "period": period.as_str(),
+        "start": time::format(start),
+        "end": time::format(start + period.seconds()),
+        "usage": usage
+            .iter()
+            .map(|row| json!({
+                "key": row.key,
+                "metric": row.metric,
+                "quantity": row.quantity,
+                "events": row.events,
+            }))
+            .collect::<Vec<_>>(),
+    })
+}
+
+/// Where closed periods go.
+pub struct Exporter {
+    pub period: Period,
+    pub dir: Option<PathBuf>,
+    pub push_url: Option<String>,
+    pub token: Option<String>,
+    pub client: Client,
+    pub timeout: Duration,
+}
+
+impl Exporter {
+    /// Export every period closed by `now` that a target hasn't received,
+    /// returning how many exports were made.
+    pub async fn export(&self, store: &Store, now: i64) -> Result<usize, String> {
+        let mut exported = 0;
+        if let Some(dir) = &self.dir {
+            let target = dir.display().to_string();
+            for start in store.unexported(self.period, now, &target)? {
+                let usage = store.usage(self.period, start)?;
+                std::fs::create_dir_all(dir).map_err(|e| format!("{target}: {e}"))?;
+                let path = dir.join(file_name(self.period, start));
+                std::fs::write(&path, csv(self.period, start, &usage))
+                    .map_err(|e| format!("{}: {e}", path.display()))?;
+                store.exported(self.period, start, &target)?;
+                exported += 1;
+            }
+        }
+        if let Some(url) = &self.push_url {
+            for start in store.unexported(self.period, now, url)? {
+                let usage = store.usage(self.period, start)?;
+                let mut request = self.client.post(url).timeout(self.timeout).json(&document(
+                    self.period,
+                    start,
+                    &usage,
+                ));
+                if let Some(token) = &self.token {
+                    request = request.bearer_auth(token);
+                }
+                let response = request
+                    .send()
+                    .await
+                    .map_err(|e| format!("{url}: {}", e.without_url()))?;
+                if !response.status().is_success() {
+                    return Err(format!("{url}: HTTP {}", response.status()));
+                }
+                store.exported(self.period, start, url)?;
+                exported += 1;
+            }
+        }
+        Ok(exported)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn periods_export_as_csv_and_json() {
+        let usage = [
+            Usage {
+                key: "acme, inc".to_string(),
+                metric: "api.call".to_string(),
+                quantity: 5.0,
+                events: 2,
+            },
+            Usage {
+                key: "globex".to_string(),
+                metric: "storage.write".to_string(),
+                quantity: 1.5,
+                events: 1,
+            },
+        ];
+        let day = 1_716_163_200;
+        assert_eq!(
+            csv(Period::Day, day, &usage),
+            "period,start,end,key,metric,quantity,events\n\
+             day,2024-05-20T00:00:00Z,2024-05-21T00:00:00Z,\"acme, inc\",api.call,5,2\n\
+             day,2024-05-20T00:00:00Z,2024-05-21T00:00:00Z,globex,storage.write,1.5,1\n"
+        );
+        assert_eq!(file_name(Period::Day, day), "usage-day-2024-05-20.csv");
+        assert_eq!(
+            file_name(Period::Hour, day + 16 * 3600),
+            "usage-hour-2024-05-20T16.csv"
+        );
+        let document = document(Period::Hour, day, &usage[1..]);
+        assert_eq!(document["end"], "2024-05-20T01:00:00Z");
+        assert_eq!(
+            document["usage"],
+            json!([{"key": "globex", "metric": "storage.write", "quantity": 1.5, "events": 1}])
+        );
+    }
+}
@@ -0,0 +1,520 @@
+//! Metering Sink - Aggregate Usage for Billing
+//!
+//! Every event adds to its tenant's usage: the key is read from the
+//! payload with `--key` (a JSONPath such as `$.tenant_id`), the quantity
+//! with `--quantity` (1 per event without it), and the metric is the
+//! message type. Usage is rolled up per hour and per day in a local SQLite
+//! database (see [`store`]).
+//!
+//! With `--quota`, usage is held to a limit per key and `--quota-period`;
+//! as it passes each `--warn-at` level of the limit, `usage.threshold` is
+//! published, once per level, key and period.
+//!
+//! With `--export-dir` or `--push-url`, every `--export-interval` seconds
+//! the `--export` periods that have closed are written as CSV files or
+//! POSTed as JSON to a billing API (see [`export`]).
+//!
+//! # Examples
+//!
+//! ```bash
+//! # Count API calls and storage writes per tenant, warn at 80% and 100%
+//! # of the daily quota, and write daily CSVs
+//! metering-sink -s api.call -s storage.write --key '$.tenant_id' \
+//!   --quantity '$.units' --quota api.call=10000 --warn-at 0.8,1 \
+//!   --export-dir /var/lib/metering/exports
+//!
+//! # Push hourly rollups to a billing API
+//! metering-sink -s 'api.*' --key '$.account' --export hour \
+//!   --push-url https://billing.example.com/usage --push-token "$BILLING_TOKEN"
+//! ```
+
+pub mod export;
+pub mod quota;
+pub mod store;
+
+use clap::Parser;
+use emergent_client::EmergentMessage;
+use emergent_client::types::CausationId;
+use export::Exporter;
+use primitive_common::capabilities::VERSION;
+use primitive_common::doctor::Report;
+use primitive_common::errors::{ErrorCategory, HandlerError};
+use primitive_common::time;
+use primitive_common::{Publisher, SinkArgs, SinkConfig, SinkContext, SinkHandler, run_sink};
+use quota::{Quota, crossed, parse_level, parse_quota};
+use reqwest::Client;
+use serde_json::{Value, json};
+use serde_json_path::JsonPath;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
+use store::{Period, Store};
+
+pub const THRESHOLD_EVENT_TYPE: &str = "usage.threshold";
+
+/// Metering Sink — aggregate usage per tenant.
+#[derive(Parser, Debug)]
+#[command(name = "metering_sink", version = VERSION)]
+#[command(about = "Aggregate usage per tenant, export rollups and alert on quotas")]
+struct Args {
+    /// Message types to meter; each is a metric.
+    #[arg(short, long = "subscribe", required = true)]
+    subscribe: Vec<String>,
+
+    /// JSONPath to the tenant or key usage is counted for (e.g. `$.tenant_id`).
+    #[arg(long, env = "METERING_SINK_KEY")]
+    key: String,
+
+    /// JSONPath to the quantity an event uses (default: 1 per event).
+    #[arg(long, env = "METERING_SINK_QUANTITY")]
+    quantity: Option<String>,
+
+    /// SQLite database holding the rollups.
+    #[arg(long, env = "METERING_SINK_DB", default_value = "metering.db")]
+    db: PathBuf,
+
+    /// Usage limit per key, as `[METRIC=]LIMIT`; without a metric, the limit
+    /// is on all metrics together (comma-separated or repeated).
+    #[arg(long = "quota", env = "METERING_SINK_QUOTAS", value_delimiter = ',', value_parser = parse_quota)]
+    quotas: Vec<Quota>,
+
+    /// Period quotas apply to.
+    #[arg(long, env = "METERING_SINK_QUOTA_PERIOD", value_enum, default_value_t = Period::Day)]
+    quota_period: Period,
+
+    /// Fractions of a quota at which `usage.threshold` is published.
+    #[arg(
+        long,
+        env = "METERING_SINK_WARN_AT",
+        value_delimiter = ',',
+        default_value = "0.8,1",
+        value_parser = parse_level
+    )]
+    warn_at: Vec<f64>,
+
+    /// Directory closed periods are written to as CSV.
+    #[arg(long, env = "METERING_SINK_EXPORT_DIR")]
+    export_dir: Option<PathBuf>,
+
+    /// Billing API closed periods are POSTed to as JSON.
+    #[arg(long, env = "METERING_SINK_PUSH_URL")]
+    push_url: Option<String>,
+
+    /// Bearer token for `--push-url`.
+    #[arg(long, env = "METERING_SINK_PUSH_TOKEN", hide_env_values = true)]
+    push_token: Option<String>,
+
+    /// Rollup period that is exported.
+    #[arg(long, env = "METERING_SINK_EXPORT", value_enum, default_value_t = Period::Day)]
+    export: Period,
+
+    /// Seconds between checks for closed periods to export.
+    #[arg(long, env = "METERING_SINK_EXPORT_INTERVAL", default_value = "300")]
+    export_interval: u64,
+
+    /// Timeout for `--push-url` requests, in milliseconds.
+    #[arg(long, env = "METERING_SINK_TIMEOUT", default_value = "30000")]
+    timeout: u64,
+
+    #[command(flatten)]
+    sink: SinkArgs,
+}
+
+/// Records usage and announces quota levels.
+struct Meter {
+    store: Store,
+    key: JsonPath,
+    quantity: Option<JsonPath>,
+    quotas: Vec<Quota>,
+    quota_period: Period,
+    warn_at: Vec<f64>,
+    /// Held while usage is read and recorded, so concurrent workers can't
+    /// both see a level as not yet passed.
+    recording: Mutex<()>,
+    publisher: OnceLock<Publisher>,
+}
+
+/// The text of a key: strings as they are, numbers and booleans as JSON.
+fn key_text(value: &Value) -> Option<String> {
+    match value {
+        Value::String(s) if !s.is_empty() => Some(s.clone()),
+        Value::Number(_) | Value::Bool(_) => Some(value.to_string()),
+        _ => None,
+    }
+}
+
+/// The `usage.threshold` payload.
+fn threshold(
+    quota: &Quota,
+    period: Period,
+    start: i64,
+    key: &str,
+    level: f64,
+    usage: f64,
+) -> Value {
+    json!({
+        "key": key,
+        "metric": quota.metric,
+        "period": period.as_str(),
+        "period_start": time::format(start),
+        "usage": usage,
+        "limit": quota.limit,
+        "level": level,
+        "exceeded": usage >= quota.limit,
+    })
+}
+
+impl Meter {
+    /// The key and quantity `payload` is metered under.
+    fn measure(&self, payload: &Value) -> Result<(String, f64), HandlerError> {
+        let key = self
+            .key
+            .query(payload)
+            .first()
+            .and_then(key_text)
+            .ok_or_else(|| HandlerError::new(ErrorCategory::Parse, "no key in payload"))?;
+        let quantity = match &self.quantity {
+            None => 1.0,
+            Some(path) => path
+                .query(payload)
+                .first()
+                .and_then(Value::as_f64)
+                .ok_or_else(|| {
+                    HandlerError::new(ErrorCategory::Parse, "no numeric quantity in payload")
+                })?,
+        };
+        Ok((key, quantity))
+    }
+
+    /// Record `quantity` of `metric` used by `key` at `at`, returning the
+    /// `usage.threshold` payloads due.
+    fn record(
+        &self,
+        at: i64,
+        key: &str,
+        metric: &str,
+        quantity: f64,
+    ) -> Result<Vec<Value>, String> {
+        let _recording = self
+            .recording
+            .lock()
+            .map_err(|_| "recording lock poisoned".to_string())?;
+        let start = self.quota_period.start(at);
+        let quotas: Vec<&Quota> = self
+            .quotas
+            .iter()
+            .filter(|quota| quota.metric.as_deref().is_none_or(|m| m == metric))
+            .collect();
+        let mut before = Vec::with_capacity(quotas.len());
+        for quota in &quotas {
+            before.push(self.store.total(
+                self.quota_period,
+                start,
+                key,
+                quota.metric.as_deref(),
+            )?);
+        }
+        self.store.record(at, key, metric, quantity)?;
+
+        let mut alerts = Vec::new();
+        for (quota, before) in quotas.into_iter().zip(before) {
+            let after = before + quantity;
+            for level in crossed(&self.warn_at, quota.limit, before, after) {
+                // Announced once per period, across restarts
+                if self
+                    .store
+                    .alert(self.quota_period, start, key, quota.label(), level)?
+                {
+                    alerts.push(threshold(
+                        quota,
+                        self.quota_period,
+                        start,
+                        key,
+                        level,
+                        after,
+                    ));
+                }
+            }
+        }
+        Ok(alerts)
+    }
+
+    fn publish(&self, mut payload: Value, msg: &EmergentMessage, message_id: &str) {
+        let Some(publisher) = self.publisher.get() else {
+            return;
+        };
+        payload["message_id"] = json!(message_id);
+        let message = EmergentMessage::new(THRESHOLD_EVENT_TYPE)
+            .with_causation_id(CausationId::from(msg.id()))
+            .with_payload(payload);
+        if let Err(e) = publisher.publish(message) {
+            eprintln!("Failed to publish {THRESHOLD_EVENT_TYPE}: {e}");
+        }
+    }
+}
+
+/// Meters events.
+struct MeteringSink {
+    meter: Arc<Meter>,
+    exporter: Option<Exporter>,
+}
+
+impl SinkHandler for MeteringSink {
+    async fn handle(
+        &self,
+        msg: &EmergentMessage,
+        ctx: &SinkContext<'_>,
+    ) -> Result<(), HandlerError> {
+        let (key, quantity) = self.meter.measure(ctx.payload())?;
+        let metric = msg.message_type.as_str();
+        if ctx.is_dry_run() {
+            ctx.would_have(
+                "record",
+                json!({ "key": key, "metric": metric, "quantity": quantity }),
+            )
+            .await;
+            return Ok(());
+        }
+
+        let alerts = self
+            .meter
+            .record(time::now(), &key, metric, quantity)
+            .map_err(|e| HandlerError::new(ErrorCategory::Internal, e))?;
+        for alert in alerts {
+            eprintln!(
+                "{key} reached {}% of its {} quota",
+                alert["level"].as_f64().unwrap_or_default() * 100.0,
+                alert["metric"].as_str().unwrap_or("total")
+            );
+            self.meter.publish(alert, msg, ctx.message_id());
+        }
+        Ok(())
+    }
+
+    fn self_test(&self, report: &mut Report) {
+        report.check(
+            "database",
+            self.meter
+                .store
+                .total(Period::Day, 0, "", None)
+                .map(|_| "readable".to_string()),
+        );
+        if let Some(dir) = self.exporter.as_ref().and_then(|e| e.dir.as_ref()) {
+            let writable = std::fs::create_dir_all(dir)
+                .map(|()| dir.display().to_string())
+                .map_err(|e| format!("{}: {e}", dir.display()));
+            report.check("export dir", writable);
+        }
+    }
+
+    fn publishes(&self) -> &'static [&'static str] {
+        &[THRESHOLD_EVENT_TYPE]
+    }
+
+    fn attach(&self, publisher: Publisher) {
+        let _ = self.meter.publisher.set(publisher);
+    }
+}
+
+/// Export closed periods every `interval`, starting now.
+async fn schedule(meter: Arc<Meter>, exporter: Exporter, interval: Duration) {
+    let mut ticks = tokio::time::interval(interval);
+    ticks.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    loop {
+        ticks.tick().await;
+        match exporter.export(&meter.store, time::now()).await {
+            Ok(0) => {}
+            Ok(n) => eprintln!("Exported {n} {} rollups", exporter.period.as_str()),
+            // What wasn't exported is tried again on the next tick
+            Err(e) => eprintln!("Export failed: {e}"),
+        }
+    }
+}
+
+/// Run the primitive with `args` as its command line (the first item is
+/// the program name, as with `std::env::args_os`).
+pub async fn run<I, T>(args: I) -> Result<(), Box<dyn std::error::Error>>
+where
+    I: IntoIterator<Item = T>,
+    T: Into<std::ffi::OsString> + Clone,
+{
+    let args = Args::parse_from(args);
+
+    let parse = |flag: &str, path: &str| {
+        JsonPath::parse(path).unwrap_or_else(|e| {
+            eprintln!("Error: invalid {flag}: {e}");
+            std::process::exit(1);
+        })
+    };
+    let key = parse("--key", &args.key);
+    let quantity = args
+        .quantity
+        .as_deref()
+        .map(|path| parse("--quantity", path));
+    let store = match Store::open(&args.db) {
+        Ok(store) => store,
+        Err(e) => {
+            eprintln!("Error: failed to open database {e}");
+            std::process::exit(1);
+        }
+    };
+
+    let config = SinkConfig {
+        name: "metering_sink",
+        subscribe: &args.subscribe,
+        would_have_as: "usage.would_have",
+        dead_letter_as: "usage.dead_letter",
+        settings: &args,
+    };
+    let meter = Arc::new(Meter {
+        store,
+        key,
+        quantity,
+        quotas: args.quotas.clone(),
+        quota_period: args.quota_period,
+        warn_at: args.warn_at.clone(),
+        recording: Mutex::new(()),
+        publisher: OnceLock::new(),
+    });
+    let exporter = || {
+        (args.export_dir.is_some() || args.push_url.is_some()).then(|| Exporter {
+            period: args.export,
+            dir: args.export_dir.clone(),
+            push_url: args.push_url.clone(),
+            token: args.push_token.clone(),
+            client: Client::new(),
+            timeout: Duration::from_millis(args.timeout),
+        })
+    };
+
+    if let Some(scheduled) = exporter()
+        && !args.sink.self_test
+    {
+        if args.sink.dry_run {
+            eprintln!("Dry run: exports are skipped");
+        } else {
+            tokio::spawn(schedule(
+                Arc::clone(&meter),
+                scheduled,
+                Duration::from_secs(args.export_interval.max(1)),
+            ));
+        }
+    }
+
+    run_sink(
+        config,
+        &args.sink,
+        MeteringSink {
+            meter,
+            exporter: exporter(),
+        },
+    )
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use emergent_testkit::{SinkFixture, fixtures, spawn_sink};
+
+    fn meter(quotas: &[&str]) -> Meter {
+        Meter {
+            store: Store::in_memory().unwrap_or_else(|e| panic!("{e}")),
+            key: JsonPath::parse("$.tenant").unwrap_or_else(|e| panic!("{e}")),
+            quantity: Some(JsonPath::parse("$.units").unwrap_or_else(|e| panic!("{e}"))),
+            quotas: quotas
+                .iter()
+                .map(|quota| parse_quota(quota).unwrap_or_else(|e| panic!("{e}")))
+                .collect(),
+            quota_period: Period::Day,
+            warn_at: vec![0.8, 1.0],
+            recording: Mutex::new(()),
+            publisher: OnceLock::new(),
+        }
+    }
+
+    #[test]
+    fn quota_levels_are_announced_once_per_period() {
+        let meter = meter(&["api.call=100", "150"]);
+        let at = 1_716_221_758;
+        let levels = |alerts: Vec<Value>| -> Vec<(Value, f64)> {
+            alerts
+                .iter()
+                .map(|alert| {
+                    (
+                        alert["metric"].clone(),
+                        alert["level"].as_f64().unwrap_or_default(),
+                    )
+                })
+                .collect()
+        };
+        let record = |at, metric, quantity| {
+            meter
+                .record(at, "acme", metric, quantity)
+                .unwrap_or_else(|e| panic!("{e}"))
+        };
+
+        assert_eq!(levels(record(at, "api.call", 70.0)), []);
+        let alerts = record(at, "api.call", 15.0);
+        assert_eq!(alerts[0]["period_start"], "2024-05-20T00:00:00Z");
+        assert_eq!(alerts[0]["usage"], 85.0);
+        assert_eq!(alerts[0]["exceeded"], false);
+        assert_eq!(levels(alerts), [(json!("api.call"), 0.8)]);
+        // The total quota counts every metric
+        assert_eq!(
+            levels(record(at, "storage.write", 40.0)),
+            [(Value::Null, 0.8)]
+        );
+        let alerts = record(at, "api.call", 30.0);
+        assert_eq!(alerts[0]["exceeded"], true);
+        assert_eq!(
+            levels(alerts),
+            [(json!("api.call"), 1.0), (Value::Null, 1.0)]
+        );
+        assert_eq!(levels(record(at, "api.call", 30.0)), []);
+        // A new day starts over
+        assert_eq!(
+            levels(record(at + 86_400, "api.call", 90.0)),
+            [(json!("api.call"), 0.8)]
+        );
+
+        assert_eq!(
+            meter.measure(&json!({"tenant": 42, "units": 2.5})).ok(),
+            Some(("42".to_string(), 2.5))
+        );
+        assert!(meter.measure(&json!({"units": 1})).is_err());
+        assert!(meter.measure(&json!({"tenant": "acme"})).is_err());
+    }
+
+    #[tokio::test]
+    async fn usage_past_a_quota_is_published() {
+        let sink = MeteringSink {
+            meter: Arc::new(meter(&["api.call=10"])),
+            exporter: None,
+        };
+        let (mut engine, run) = spawn_sink(
+            SinkFixture::new("metering_sink", "usage"),
+            SinkArgs::default(),
+            sink,
+        );
+
+        for units in [5, 4] {
+            engine
+                .inject_message(fixtures::message(
+                    "api.call",
+                    json!({"tenant": "acme", "units": units}),
+                ))
+                .await;
+        }
+        let alert = engine.expect_published(THRESHOLD_EVENT_TYPE).await;
+        assert_eq!(alert.payload()["key"], "acme");
+        assert_eq!(alert.payload()["metric"], "api.call");
+        assert_eq!(alert.payload()["level"], 0.8);
+        assert_eq!(alert.payload()["usage"], 9.0);
+        assert_eq!(alert.payload()["limit"], 10.0);
+        assert!(alert.payload()["message_id"].is_string());
+
+        engine.close();
+        assert_eq!(run.await.ok(), Some(Ok(())));
+    }
+}
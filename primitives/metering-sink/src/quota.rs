@@ -0,0 +1,82 @@
+//! Quotas and the levels at which they are announced.
+
+/// A usage limit per key and quota period: of one metric, or of all of
+/// them together.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Quota {
+    pub metric: Option<String>,
+    pub limit: f64,
+}
+
+impl Quota {
+    /// The metric alerts are filed under; `*` for all of them.
+    pub fn label(&self) -> &str {
+        self.metric.as_deref().unwrap_or("*")
+    }
+}
+
+/// Parse `[METRIC=]LIMIT`.
+pub fn parse_quota(s: &str) -> Result<Quota, String> {
+    let (metric, limit) = match s.rsplit_once('=') {
+        Some((metric, limit)) if !metric.is_empty() => (Some(metric.to_string()), limit),
+        Some(_) => return Err(format!("invalid quota '{s}': expected [METRIC=]LIMIT")),
+        None => (None, s),
+    };
+    let limit: f64 = limit
+        .trim()
+        .parse()
+        .map_err(|_| format!("invalid quota '{s}': '{limit}' is not a number"))?;
+    if limit <= 0.0 || !limit.is_finite() {
+        return Err(format!("invalid quota '{s}': the limit must be positive"));
+    }
+    Ok(Quota { metric, limit })
+}
+
+/// Parse a `--warn-at` level: a fraction of the limit, such as `0.8`.
+pub fn parse_level(s: &str) -> Result<f64, String> {
+    match s.trim().parse::<f64>() {
+        Ok(level) if level > 0.0 && level.is_finite() => Ok(level),
+        _ => Err(format!(
+            "invalid level '{s}': expected a positive fraction such as 0.8"
+        )),
+    }
+}
+
+/// The `levels` usage passed going from `before` to `after` of `limit`.
+pub fn crossed(levels: &[f64], limit: f64, before: f64, after: f64) -> Vec<f64> {
+    levels
+        .iter()
+        .copied()
+        .filter(|level| before < level * limit && after >= level * limit)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quotas_parse_and_levels_cross_once() {
+        assert_eq!(
+            parse_quota("api.call=1000"),
+            Ok(Quota {
+                metric: Some("api.call".to_string()),
+                limit: 1000.0,
+            })
+        );
+        assert_eq!(
+            parse_quota("50.5").map(|quota| quota.label().to_string()),
+            Ok("*".to_string())
+        );
+        assert!(parse_quota("=10").is_err());
+        assert!(parse_quota("api.call=lots").is_err());
+        assert!(parse_quota("0").is_err());
+        assert!(parse_level("-1").is_err());
+
+        let levels = [0.8, 1.0];
+        assert_eq!(crossed(&levels, 100.0, 10.0, 50.0), Vec::<f64>::new());
+        assert_eq!(crossed(&levels, 100.0, 50.0, 80.0), [0.8]);
+        assert_eq!(crossed(&levels, 100.0, 79.0, 120.0), [0.8, 1.0]);
+        assert_eq!(crossed(&levels, 100.0, 100.0, 120.0), Vec::<f64>::new());
+    }
+}
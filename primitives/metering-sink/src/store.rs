@@ -0,0 +1,293 @@
+//! Usage rollups in a local SQLite database.
+//!
+//! Every recorded event adds its quantity to two rows of the `usage`
+//! table, one for the hour and one for the day it fell in, keyed by tenant
+//! and metric. `alerts` remembers which quota levels have been announced
+//! for a period, so a restart doesn't announce them again, and `exports`
+//! which closed periods each export target has received.
+
+use clap::ValueEnum;
+use rusqlite::{Connection, params};
+use std::path::Path;
+use std::sync::Mutex;
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS usage (
+    period   TEXT NOT NULL,
+    start    INTEGER NOT NULL,
+    key      TEXT NOT NULL,
+    metric   TEXT NOT NULL,
+    quantity REAL NOT NULL,
+    events   INTEGER NOT NULL,
+    PRIMARY KEY (period, start, key, metric)
+);
+CREATE TABLE IF NOT EXISTS alerts (
+    period TEXT NOT NULL,
+    start  INTEGER NOT NULL,
+    key    TEXT NOT NULL,
+    metric TEXT NOT NULL,
+    level  REAL NOT NULL,
+    PRIMARY KEY (period, start, key, metric, level)
+);
+CREATE TABLE IF NOT EXISTS exports (
+    period TEXT NOT NULL,
+    start  INTEGER NOT NULL,
+    target TEXT NOT NULL,
+    PRIMARY KEY (period, start, target)
+);
+";
+
+/// The length of a rollup period.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Period {
+    Hour,
+    Day,
+}
+
+impl Period {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Hour => "hour",
+            Self::Day => "day",
+        }
+    }
+
+    pub fn seconds(self) -> i64 {
+        match self {
+            Self::Hour => 3600,
+            Self::Day => 86_400,
+        }
+    }
+
+    /// The start of the period `at` falls in.
+    pub fn start(self, at: i64) -> i64 {
+        at - at.rem_euclid(self.seconds())
+    }
+}
+
+/// One rollup row.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Usage {
+    pub key: String,
+    pub metric: String,
+    pub quantity: f64,
+    pub events: i64,
+}
+
+/// The rollup database.
+pub struct Store {
+    db: Mutex<Connection>,
+}
+
+impl Store {
+    /// Open (creating if need be) the database at `path`.
+    pub fn open(path: &Path) -> Result<Self, String> {
+        let db = Connection::open(path).map_err(|e| format!("{}: {e}", path.display()))?;
+        Self::init(db).map_err(|e| format!("{}: {e}", path.display()))
+    }
+
+    /// An in-memory database, for tests.
+    pub fn in_memory() -> Result<Self, String> {
+        let db = Connection::open_in_memory().map_err(|e| e.to_string())?;
+        Self::init(db)
+    }
+
+    fn init(db: Connection) -> Result<Self, String> {
+        db.execute_batch(SCHEMA).map_err(|e| e.to_string())?;
+        Ok(Self { db: Mutex::new(db) })
+    }
+
+    fn with<T>(&self, f: impl FnOnce(&Connection) -> rusqlite::Result<T>) -> Result<T, String> {
+        let db = self
+            .db
+            .lock()
+            .map_err(|_| "usage lock poisoned".to_string())?;
+        f(&db).map_err(|e| format!("usage: {e}"))
+    }
+
+    /// Add `quantity` of `metric` used by `key` at `at` to its hourly and
+    /// daily rollups.
+    pub fn record(&self, at: i64, key: &str, metric: &str, quantity: f64) -> Result<(), String> {
+        self.with(|db| {
+            for period in [Period::Hour, Period::Day] {
+                db.execute(
+                    "INSERT INTO usage (period, start, key, metric, quantity, events)
+                     VALUES (?1, ?2, ?3, ?4, ?5, 1)
+                     ON CONFLICT (period, start, key, metric)
+                     DO UPDATE SET quantity = quantity + excluded.quantity, events = events + 1",
+                    params![period.as_str(), period.start(at), key, metric, quantity],
+                )?;
+            }
+            Ok(())
+        })
+    }
+
+    /// What `key` has used in the period starting at `start`: of `metric`,
+    /// or of every metric together.
+    pub fn total(
+        &self,
+        period: Period,
+        start: i64,
+        key: &str,
+        metric: Option<&str>,
+    ) -> Result<f64, String> {
+        self.with(|db| {
+            db.query_row(
+                "SELECT COALESCE(SUM(quantity), 0) FROM usage
+                 WHERE period = ?1 AND start = ?2 AND key = ?3 AND (?4 IS NULL OR metric = ?4)",
+                params![period.as_str(), start, key, metric],
+                |row| row.get(0),
+            )
+        })
+    }
+
+    /// Note that `level` was announced for `key`'s `metric` in a period,
+    /// returning whether it hadn't been already.
+    pub fn alert(
+        &self,
+        period: Period,
+        start: i64,
+        key: &str,
+        metric: &str,
+        level: f64,
+    ) -> Result<bool, String> {
+        self.with(|db| {
+            db.execute(
+                "INSERT OR IGNORE INTO alerts (period, start, key, metric, level)
+                 VALUES (?1, ?2, ?3, ?4, ?5)",
+                params![period.as_str(), start, key, metric, level],
+            )
+            .map(|inserted| inserted > 0)
+        })
+    }
+
+    /// The rollups of the period starting at `start`.
+    pub fn usage(&self, period: Period, start: i64) -> Result<Vec<Usage>, String> {
+        self.with(|db| {
+            let mut statement = db.prepare(
+                "SELECT key, metric, quantity, events FROM usage
+                 WHERE period = ?1 AND start = ?2 ORDER BY key, metric",
+            )?;
+            statement
+                .query_map(params![period.as_str(), start], |row| {
+                    Ok(Usage {
+                        key: row.get(0)?,
+                        metric: row.get(1)?,
+                        quantity: row.get(2)?,
+                        events: row.get(3)?,
+                    })
+                })?
+                .collect()
+        })
+    }
+
+    /// Starts of the periods that closed by `now` with usage that `target`
+    /// hasn't received, oldest first.
+    pub fn unexported(&self, period: Period, now: i64, target: &str) -> Result<Vec<i64>, String> {
+        self.with(|db| {
+            let mut statement = db.prepare(
+                "SELECT DISTINCT start FROM usage
+                 WHERE period = ?1 AND start + ?2 <= ?3
+                   AND start NOT IN (SELECT start FROM exports WHERE period = ?1 AND target = ?4)
+                 ORDER BY start",
+            )?;
+            statement
+                .query_map(
+                    params![period.as_str(), period.seconds(), now, target],
+                    |row| row.get(0),
+                )?
+                .collect()
+        })
+    }
+
+    /// Note that `target` received the period starting at `start`.
+    pub fn exported(&self, period: Period, start: i64, target: &str) -> Result<(), String> {
+        self.with(|db| {
+            db.execute(
+                "INSERT OR IGNORE INTO exports (period, start, target) VALUES (?1, ?2, ?3)",
+                params![period.as_str(), start, target],
+            )
+            .map(|_| ())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn usage_rolls_up_by_hour_and_day() {
+        let store = Store::in_memory().unwrap_or_else(|e| panic!("{e}"));
+        // 2024-05-20T16:15:58Z, the same hour, the next hour
+        let at = 1_716_221_758;
+        store
+            .record(at, "acme", "api.call", 2.0)
+            .unwrap_or_else(|e| panic!("{e}"));
+        store
+            .record(at + 60, "acme", "api.call", 3.0)
+            .unwrap_or_else(|e| panic!("{e}"));
+        store
+            .record(at + 3600, "acme", "storage.write", 1.5)
+            .unwrap_or_else(|e| panic!("{e}"));
+        store
+            .record(at, "globex", "api.call", 1.0)
+            .unwrap_or_else(|e| panic!("{e}"));
+
+        let hour = Period::Hour.start(at);
+        let day = Period::Day.start(at);
+        assert_eq!(hour, 1_716_220_800);
+        assert_eq!(day, 1_716_163_200);
+        let total = |period, start, metric| {
+            store
+                .total(period, start, "acme", metric)
+                .unwrap_or_else(|e| panic!("{e}"))
+        };
+        assert_eq!(total(Period::Hour, hour, Some("api.call")), 5.0);
+        assert_eq!(total(Period::Hour, hour, Some("storage.write")), 0.0);
+        assert_eq!(total(Period::Day, day, Some("api.call")), 5.0);
+        assert_eq!(total(Period::Day, day, None), 6.5);
+
+        let usage = store
+            .usage(Period::Hour, hour)
+            .unwrap_or_else(|e| panic!("{e}"));
+        assert_eq!(
+            usage,
+            [
+                Usage {
+                    key: "acme".to_string(),
+                    metric: "api.call".to_string(),
+                    quantity: 5.0,
+                    events: 2,
+                },
+                Usage {
+                    key: "globex".to_string(),
+                    metric: "api.call".to_string(),
+                    quantity: 1.0,
+                    events: 1,
+                },
+            ]
+        );
+
+        // Only closed periods are exported, once per target
+        let unexported = |now| {
+            store
+                .unexported(Period::Hour, now, "dir")
+                .unwrap_or_else(|e| panic!("{e}"))
+        };
+        assert_eq!(unexported(at), Vec::<i64>::new());
+        assert_eq!(unexported(at + 7200), [hour, hour + 3600]);
+        store
+            .exported(Period::Hour, hour, "dir")
+            .unwrap_or_else(|e| panic!("{e}"));
+        assert_eq!(unexported(at + 7200), [hour + 3600]);
+
+        let alert = || {
+            store
+                .alert(Period::Day, day, "acme", "api.call", 0.8)
+                .unwrap_or_else(|e| panic!("{e}"))
+        };
+        assert!(alert());
+        assert!(!alert());
+    }
+}
@@ -0,0 +1,163 @@
+//! Check results built from payload fields.
+//!
+//! | Field | Use |
+//! |-------|-----|
+//! | `--host-field` (`host`) | Monitored host (default: `--host`) |
+//! | `--service-field` (`service`) | Nagios service, or Zabbix item key (default: `--service`); an empty service is a Nagios host check |
+//! | `--state-field` (`state`) | `0`-`3`, or `ok`/`up`, `warning`, `critical`/`down`, `unknown` (default: `--state`) |
+//! | `--output-field` (`output`) | Plugin output (default: the payload as JSON) |
+//! | `--value-field` (`value`) | Zabbix item value (default: the output) |
+//!
+//! Field names are dotted paths, so `--host-field labels.instance` works.
+
+use clap::ValueEnum;
+use primitive_common::key;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// A Nagios plugin state.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum State {
+    Ok,
+    Warning,
+    Critical,
+    Unknown,
+}
+
+impl State {
+    /// The plugin return code.
+    pub fn code(self) -> i16 {
+        match self {
+            Self::Ok => 0,
+            Self::Warning => 1,
+            Self::Critical => 2,
+            Self::Unknown => 3,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Ok => "ok",
+            Self::Warning => "warning",
+            Self::Critical => "critical",
+            Self::Unknown => "unknown",
+        }
+    }
+
+    /// State for a return code or state name.
+    pub fn parse(value: &Value) -> Option<Self> {
+        let name = match value {
+            Value::Number(n) => n.to_string(),
+            Value::String(s) => s.to_ascii_lowercase(),
+            _ => return None,
+        };
+        Some(match name.as_str() {
+            "0" | "ok" | "up" | "recovery" | "resolved" => Self::Ok,
+            "1" | "warning" | "warn" => Self::Warning,
+            "2" | "critical" | "crit" | "down" | "error" | "unreachable" => Self::Critical,
+            "3" | "unknown" => Self::Unknown,
+            _ => return None,
+        })
+    }
+}
+
+/// Which payload fields carry the parts of a check, and the fallbacks for
+/// payloads without them.
+#[derive(Debug, Clone)]
+pub struct Fields {
+    pub host: String,
+    pub service: String,
+    pub state: String,
+    pub output: String,
+    pub value: String,
+    pub default_host: Option<String>,
+    pub default_service: Option<String>,
+    pub default_state: State,
+}
+
+/// One passive check result, backend-neutral.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Check {
+    pub host: String,
+    /// Empty for a Nagios host check.
+    pub service: String,
+    pub state: State,
+    pub output: String,
+    /// The Zabbix item value.
+    pub value: String,
+}
+
+impl Check {
+    /// Build the check for `payload`.
+    pub fn build(payload: &Value, fields: &Fields) -> Result<Self, String> {
+        let host = key::extract(payload, &fields.host)
+            .or_else(|| fields.default_host.clone())
+            .filter(|host| !host.is_empty())
+            .ok_or_else(|| format!("payload has no '{}' and there is no --host", fields.host))?;
+        let service = key::extract(payload, &fields.service)
+            .or_else(|| fields.default_service.clone())
+            .unwrap_or_default();
+        let state = match key::lookup(payload, &fields.state) {
+            Some(value) => State::parse(value)
+                .ok_or_else(|| format!("unknown state {value} in '{}'", fields.state))?,
+            None => fields.default_state,
+        };
+        let output = key::extract(payload, &fields.output).unwrap_or_else(|| payload.to_string());
+        let value = key::extract(payload, &fields.value).unwrap_or_else(|| output.clone());
+        Ok(Self {
+            host,
+            service,
+            state,
+            output,
+            value,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn fields() -> Fields {
+        Fields {
+            host: "host".to_string(),
+            service: "service".to_string(),
+            state: "state".to_string(),
+            output: "output".to_string(),
+            value: "value".to_string(),
+            default_host: None,
+            default_service: Some("emergent".to_string()),
+            default_state: State::Unknown,
+        }
+    }
+
+    #[test]
+    fn checks_take_payload_fields_and_fall_back_to_defaults() {
+        let check = Check::build(
+            &json!({"host": "web1", "service": "disk", "state": "CRITICAL", "output": "/var at 98%"}),
+            &fields(),
+        )
+        .unwrap_or_else(|e| panic!("{e}"));
+        assert_eq!(
+            check,
+            Check {
+                host: "web1".to_string(),
+                service: "disk".to_string(),
+                state: State::Critical,
+                output: "/var at 98%".to_string(),
+                value: "/var at 98%".to_string(),
+            }
+        );
+
+        let check = Check::build(&json!({"host": "db1", "state": 1, "value": 42}), &fields())
+            .unwrap_or_else(|e| panic!("{e}"));
+        assert_eq!(check.service, "emergent");
+        assert_eq!(check.state, State::Warning);
+        assert_eq!(check.value, "42");
+
+        assert!(Check::build(&json!({"state": "ok"}), &fields()).is_err());
+        assert!(Check::build(&json!({"host": "web1", "state": "sideways"}), &fields()).is_err());
+    }
+}
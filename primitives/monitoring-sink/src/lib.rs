@@ -0,0 +1,387 @@
+//! Monitoring Sink - Passive Checks for Zabbix and Nagios
+//!
+//! A Sink that feeds events into existing monitoring as passive check
+//! results, without an agent on the monitored hosts: as values of Zabbix
+//! trapper items (see [`zabbix`]), or as Nagios, Icinga or Naemon service
+//! and host check results through NSCA (see [`nsca`]).
+//!
+//! ```json
+//! {"host": "web1", "service": "disk", "state": "critical", "output": "/var at 98%"}
+//! ```
+//!
+//! Host, service (for Zabbix, the item key), state and output are taken
+//! from payload fields, with fallbacks for payloads that lack them (see
+//! [`check`]). Each event is one check result on its own connection.
+//!
+//! # Examples
+//!
+//! ```bash
+//! monitoring-sink -s backup.completed -s backup.failed --backend zabbix \
+//!   --server zabbix.example.com --service backup.status --value-field status
+//! monitoring-sink -s alert.fired --backend nsca --server nagios.example.com \
+//!   --encryption xor --password "$NSCA_PASSWORD"
+//! ```
+
+pub mod check;
+pub mod nsca;
+pub mod zabbix;
+
+use check::{Check, Fields, State};
+use clap::{Parser, ValueEnum};
+use emergent_client::EmergentMessage;
+use nsca::Encryption;
+use primitive_common::capabilities::VERSION;
+use primitive_common::doctor::Report;
+use primitive_common::errors::{ErrorCategory, HandlerError};
+use primitive_common::{SinkArgs, SinkConfig, SinkContext, SinkHandler, run_sink};
+use serde_json::json;
+use std::net::ToSocketAddrs;
+use std::time::Duration;
+use tokio::net::TcpStream;
+
+/// Monitoring Sink — passive checks for Zabbix and Nagios.
+#[derive(Parser, Debug)]
+#[command(name = "monitoring_sink", version = VERSION)]
+#[command(about = "Send events as Zabbix trapper values or NSCA passive check results")]
+struct Args {
+    /// Message types to subscribe to.
+    #[arg(short, long = "subscribe", required = true)]
+    subscribe: Vec<String>,
+
+    /// Monitoring system to feed.
+    #[arg(short, long, env = "MONITORING_SINK_BACKEND", value_enum)]
+    backend: Backend,
+
+    /// Server (or Zabbix proxy) as `host[:port]`; the port defaults to
+    /// 10051 for Zabbix and 5667 for NSCA.
+    #[arg(long, env = "MONITORING_SINK_SERVER")]
+    server: String,
+
+    /// Payload field holding the host.
+    #[arg(long, env = "MONITORING_SINK_HOST_FIELD", default_value = "host")]
+    host_field: String,
+
+    /// Payload field holding the service, or Zabbix item key.
+    #[arg(long, env = "MONITORING_SINK_SERVICE_FIELD", default_value = "service")]
+    service_field: String,
+
+    /// Payload field holding the state.
+    #[arg(long, env = "MONITORING_SINK_STATE_FIELD", default_value = "state")]
+    state_field: String,
+
+    /// Payload field holding the plugin output.
+    #[arg(long, env = "MONITORING_SINK_OUTPUT_FIELD", default_value = "output")]
+    output_field: String,
+
+    /// Payload field holding the Zabbix item value.
+    #[arg(long, env = "MONITORING_SINK_VALUE_FIELD", default_value = "value")]
+    value_field: String,
+
+    /// Host for payloads that name none.
+    #[arg(long, env = "MONITORING_SINK_HOST")]
+    host: Option<String>,
+
+    /// Service or item key for payloads that name none.
+    #[arg(long, env = "MONITORING_SINK_SERVICE")]
+    service: Option<String>,
+
+    /// State for payloads that give none.
+    #[arg(
+        long,
+        env = "MONITORING_SINK_STATE",
+        value_enum,
+        default_value = "unknown"
+    )]
+    state: State,
+
+    /// NSCA encryption method.
+    #[arg(
+        long,
+        env = "MONITORING_SINK_ENCRYPTION",
+        value_enum,
+        default_value = "none"
+    )]
+    encryption: Encryption,
+
+    /// NSCA password.
+    #[arg(long, env = "MONITORING_SINK_PASSWORD", hide_env_values = true)]
+    password: Option<String>,
+
+    /// Per-check timeout in milliseconds.
+    #[arg(short, long, env = "MONITORING_SINK_TIMEOUT", default_value = "10000")]
+    timeout: u64,
+
+    #[command(flatten)]
+    sink: SinkArgs,
+}
+
+/// The monitoring system fed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Backend {
+    /// Zabbix trapper items.
+    Zabbix,
+    /// Nagios-compatible passive checks through NSCA.
+    Nsca,
+}
+
+impl Backend {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Zabbix => "zabbix",
+            Self::Nsca => "nsca",
+        }
+    }
+
+    /// `server` with the backend's port added if it has none.
+    pub fn address(self, server: &str) -> String {
+        if server.contains(':') {
+            return server.to_string();
+        }
+        let port = match self {
+            Self::Zabbix => 10051,
+            Self::Nsca => 5667,
+        };
+        format!("{server}:{port}")
+    }
+}
+
+/// Submits one check result per event.
+struct MonitoringSink {
+    backend: Backend,
+    address: String,
+    fields: Fields,
+    encryption: Encryption,
+    password: String,
+    timeout: Duration,
+}
+
+impl MonitoringSink {
+    async fn submit(&self, check: &Check) -> Result<(), HandlerError> {
+        let address = &self.address;
+        let request =
+            |e: String| HandlerError::new(ErrorCategory::Request, format!("{address}: {e}"));
+        let exchange = async {
+            let mut stream = TcpStream::connect(address)
+                .await
+                .map_err(|e| request(e.to_string()))?;
+            match self.backend {
+                Backend::Zabbix => {
+                    let response = zabbix::send(&mut stream, std::slice::from_ref(check))
+                        .await
+                        .map_err(request)?;
+                    if response.failed > 0 || response.processed == 0 {
+                        return Err(HandlerError::new(
+                            ErrorCategory::Rejected,
+                            format!(
+                                "{address}: value for {}:{} not accepted ({}); is it a trapper item?",
+                                check.host, check.service, response.info
+                            ),
+                        ));
+                    }
+                    Ok(())
+                }
+                Backend::Nsca => nsca::send(&mut stream, check, self.encryption, &self.password)
+                    .await
+                    .map_err(request),
+            }
+        };
+        tokio::time::timeout(self.timeout, exchange)
+            .await
+            .map_err(|_| {
+                HandlerError::new(ErrorCategory::Timeout, format!("{address}: timed out"))
+            })?
+    }
+}
+
+impl SinkHandler for MonitoringSink {
+    async fn handle(
+        &self,
+        _msg: &EmergentMessage,
+        ctx: &SinkContext<'_>,
+    ) -> Result<(), HandlerError> {
+        let check = Check::build(ctx.payload(), &self.fields)
+            .map_err(|e| HandlerError::new(ErrorCategory::Parse, e))?;
+        if self.backend == Backend::Zabbix && check.service.is_empty() {
+            return Err(HandlerError::new(
+                ErrorCategory::Parse,
+                format!(
+                    "payload has no '{}' and there is no --service",
+                    self.fields.service
+                ),
+            ));
+        }
+
+        if ctx.is_dry_run() {
+            let detail = match self.backend {
+                Backend::Zabbix => json!({
+                    "backend": "zabbix",
+                    "host": check.host,
+                    "key": check.service,
+                    "value": check.value,
+                }),
+                Backend::Nsca => json!({
+                    "backend": "nsca",
+                    "host": check.host,
+                    "service": check.service,
+                    "state": check.state.as_str(),
+                    "output": check.output,
+                }),
+            };
+            ctx.would_have("submit", detail).await;
+            return Ok(());
+        }
+
+        self.submit(&check).await
+    }
+
+    fn self_test(&self, report: &mut Report) {
+        let address = &self.address;
+        let reachable = address
+            .to_socket_addrs()
+            .map_err(|e| format!("{address}: {e}"))
+            .and_then(|mut addrs| addrs.next().ok_or_else(|| format!("{address}: no address")))
+            .and_then(|addr| {
+                std::net::TcpStream::connect_timeout(&addr, self.timeout)
+                    .map(|_| format!("{address} accepts connections"))
+                    .map_err(|e| format!("{address}: {e}"))
+            });
+        report.check(self.backend.as_str(), reachable);
+    }
+}
+
+/// Run the primitive with `args` as its command line (the first item is
+/// the program name, as with `std::env::args_os`).
+pub async fn run<I, T>(args: I) -> Result<(), Box<dyn std::error::Error>>
+where
+    I: IntoIterator<Item = T>,
+    T: Into<std::ffi::OsString> + Clone,
+{
+    let args = Args::parse_from(args);
+
+    let config = SinkConfig {
+        name: "monitoring_sink",
+        subscribe: &args.subscribe,
+        would_have_as: "monitoring.would_have",
+        dead_letter_as: "monitoring.dead_letter",
+        settings: &args,
+    };
+    let handler = MonitoringSink {
+        backend: args.backend,
+        address: args.backend.address(&args.server),
+        fields: Fields {
+            host: args.host_field.clone(),
+            service: args.service_field.clone(),
+            state: args.state_field.clone(),
+            output: args.output_field.clone(),
+            value: args.value_field.clone(),
+            default_host: args.host.clone(),
+            default_service: args.service.clone(),
+            default_state: args.state,
+        },
+        encryption: args.encryption,
+        password: args.password.clone().unwrap_or_default(),
+        timeout: Duration::from_millis(args.timeout),
+    };
+
+    run_sink(config, &args.sink, handler).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use emergent_testkit::{SinkFixture, fixtures, spawn_sink};
+    use serde_json::Value;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+    use tokio::sync::mpsc;
+
+    #[tokio::test]
+    async fn values_are_sent_to_zabbix_trapper_items() {
+        let listener = TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap_or_else(|e| panic!("bind: {e}"));
+        let addr = listener
+            .local_addr()
+            .unwrap_or_else(|e| panic!("local_addr: {e}"));
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            loop {
+                let Ok((mut socket, _)) = listener.accept().await else {
+                    return;
+                };
+                let mut header = [0u8; 13];
+                if socket.read_exact(&mut header).await.is_err() {
+                    continue;
+                }
+                let mut body = vec![0u8; usize::from(header[5])];
+                let _ = socket.read_exact(&mut body).await;
+                let request: Value = serde_json::from_slice(&body).unwrap_or_default();
+                let failed = u8::from(request["data"][0]["key"] == "unknown.key");
+                let _ = tx.send(request);
+                let reply = format!(
+                    "{{\"response\":\"success\",\"info\":\"processed: {}; failed: {failed}; total: 1\"}}",
+                    1 - failed
+                );
+                let _ = socket.write_all(&zabbix::frame(reply.as_bytes())).await;
+            }
+        });
+
+        let handler = MonitoringSink {
+            backend: Backend::Zabbix,
+            address: addr.to_string(),
+            fields: Fields {
+                host: "host".to_string(),
+                service: "key".to_string(),
+                state: "state".to_string(),
+                output: "output".to_string(),
+                value: "status".to_string(),
+                default_host: Some("backup01".to_string()),
+                default_service: None,
+                default_state: State::Unknown,
+            },
+            encryption: Encryption::None,
+            password: String::new(),
+            timeout: Duration::from_secs(5),
+        };
+        let (mut engine, run) = spawn_sink(
+            SinkFixture::new("monitoring_sink", "monitoring"),
+            SinkArgs {
+                dead_letter: true,
+                ..Default::default()
+            },
+            handler,
+        );
+
+        engine
+            .inject_message(fixtures::message(
+                "backup.completed",
+                json!({"key": "backup.status", "status": 0}),
+            ))
+            .await;
+        let request = rx.recv().await.unwrap_or_else(|| panic!("no request"));
+        assert_eq!(
+            request,
+            json!({
+                "request": "sender data",
+                "data": [{"host": "backup01", "key": "backup.status", "value": "0"}],
+            })
+        );
+
+        engine
+            .inject_message(fixtures::message(
+                "backup.completed",
+                json!({"key": "unknown.key", "status": 1}),
+            ))
+            .await;
+        assert!(rx.recv().await.is_some());
+        let dead = engine.expect_published("monitoring.dead_letter").await;
+        let error = dead.payload()["error"]
+            .as_str()
+            .unwrap_or_default()
+            .to_string();
+        assert!(error.contains("is it a trapper item?"), "{error}");
+
+        engine.close();
+        assert_eq!(run.await.ok(), Some(Ok(())));
+    }
+}
@@ -0,0 +1,159 @@
+//! The NSCA protocol, for Nagios, Icinga and Naemon passive checks.
+//!
+//! On connect the NSCA daemon sends 128 random bytes and a timestamp; the
+//! client answers with one fixed-size check result packet, checksummed
+//! and encrypted with the random bytes and the shared password. Only the
+//! `none` (0) and `xor` (1) encryption methods are supported, as the
+//! others need libmcrypt ciphers. The daemon doesn't reply, so a result
+//! for a host or service Nagios doesn't know is dropped silently.
+
+use crate::check::Check;
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+const IV_SIZE: usize = 128;
+const PACKET_VERSION: i16 = 3;
+const HOST_SIZE: usize = 64;
+const SERVICE_SIZE: usize = 128;
+const OUTPUT_SIZE: usize = 512;
+/// Version, padding, CRC, timestamp and return code, then the three
+/// strings, padded to a multiple of four like the C struct.
+pub const PACKET_SIZE: usize = 720;
+
+/// How packets are encrypted (`decryption_method` in nsca.cfg).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Encryption {
+    None,
+    Xor,
+}
+
+/// CRC-32 (IEEE) as NSCA computes it.
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for &byte in data {
+        crc ^= u32::from(byte);
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+/// Copy `s` into `field`, cut at a character boundary so a terminating
+/// NUL still fits.
+fn put(field: &mut [u8], s: &str) {
+    let mut end = s.len().min(field.len() - 1);
+    while !s.is_char_boundary(end) {
+        end -= 1;
+    }
+    field[..end].copy_from_slice(&s.as_bytes()[..end]);
+}
+
+/// The encrypted packet carrying `check`, for a connection whose
+/// initialisation packet held `iv` and `timestamp`.
+pub fn packet(
+    check: &Check,
+    iv: &[u8],
+    timestamp: u32,
+    encryption: Encryption,
+    password: &str,
+) -> Vec<u8> {
+    let mut packet = vec![0u8; PACKET_SIZE];
+    packet[0..2].copy_from_slice(&PACKET_VERSION.to_be_bytes());
+    packet[8..12].copy_from_slice(&timestamp.to_be_bytes());
+    packet[12..14].copy_from_slice(&check.state.code().to_be_bytes());
+    let service = 14 + HOST_SIZE;
+    let output = service + SERVICE_SIZE;
+    put(&mut packet[14..service], &check.host);
+    put(&mut packet[service..output], &check.service);
+    put(&mut packet[output..output + OUTPUT_SIZE], &check.output);
+    let crc = crc32(&packet);
+    packet[4..8].copy_from_slice(&crc.to_be_bytes());
+
+    if encryption == Encryption::Xor {
+        for (byte, key) in packet.iter_mut().zip(iv.iter().cycle()) {
+            *byte ^= key;
+        }
+        if !password.is_empty() {
+            for (byte, key) in packet.iter_mut().zip(password.as_bytes().iter().cycle()) {
+                *byte ^= key;
+            }
+        }
+    }
+    packet
+}
+
+/// Send `check` over `stream`.
+pub async fn send<S>(
+    stream: &mut S,
+    check: &Check,
+    encryption: Encryption,
+    password: &str,
+) -> Result<(), String>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut init = [0u8; IV_SIZE + 4];
+    stream
+        .read_exact(&mut init)
+        .await
+        .map_err(|e| format!("read: {e}"))?;
+    let mut timestamp = [0u8; 4];
+    timestamp.copy_from_slice(&init[IV_SIZE..]);
+    let packet = packet(
+        check,
+        &init[..IV_SIZE],
+        u32::from_be_bytes(timestamp),
+        encryption,
+        password,
+    );
+    stream
+        .write_all(&packet)
+        .await
+        .map_err(|e| format!("write: {e}"))?;
+    stream.flush().await.map_err(|e| format!("write: {e}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::check::State;
+
+    #[test]
+    fn packets_are_checksummed_then_encrypted() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+
+        let check = Check {
+            host: "web1".to_string(),
+            service: "disk".to_string(),
+            state: State::Critical,
+            output: "/var at 98%".to_string(),
+            value: String::new(),
+        };
+        let iv = [0x5Au8; IV_SIZE];
+        let plain = packet(&check, &iv, 1_700_000_000, Encryption::None, "");
+        assert_eq!(plain.len(), PACKET_SIZE);
+        assert_eq!(&plain[0..2], &[0, 3]);
+        assert_eq!(&plain[12..14], &[0, 2]);
+        assert_eq!(&plain[14..19], b"web1\0");
+        assert_eq!(&plain[78..83], b"disk\0");
+        assert_eq!(&plain[206..218], b"/var at 98%\0");
+        let mut zeroed = plain.clone();
+        zeroed[4..8].fill(0);
+        assert_eq!(&plain[4..8], &crc32(&zeroed).to_be_bytes());
+
+        let encrypted = packet(&check, &iv, 1_700_000_000, Encryption::Xor, "pw");
+        let decrypted: Vec<u8> = encrypted
+            .iter()
+            .zip(b"pw".iter().cycle())
+            .map(|(byte, key)| byte ^ key ^ 0x5A)
+            .collect();
+        assert_eq!(decrypted, plain);
+    }
+}
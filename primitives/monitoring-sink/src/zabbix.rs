@@ -0,0 +1,121 @@
+//! The Zabbix sender protocol.
+//!
+//! A trapper item is fed by connecting to the server (or proxy) on port
+//! 10051 and sending a `sender data` request framed with a `ZBXD` header;
+//! the server answers in the same framing with how many values it
+//! processed. A value for a host or key the server doesn't know, or for
+//! an item that isn't a trapper item, counts as failed.
+
+use crate::check::Check;
+use serde_json::{Value, json};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// Protocol flag: the standard (uncompressed, 4-byte length) framing.
+const FLAG_PROTOCOL: u8 = 0x01;
+
+/// Responses larger than this are not something a sender should get.
+const MAX_RESPONSE: usize = 1024 * 1024;
+
+/// The server's verdict on a request.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Response {
+    pub processed: u64,
+    pub failed: u64,
+    pub info: String,
+}
+
+/// `payload` framed with the `ZBXD` header.
+pub fn frame(payload: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(payload.len() + 13);
+    frame.extend_from_slice(b"ZBXD");
+    frame.push(FLAG_PROTOCOL);
+    frame.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    frame.extend_from_slice(&0u32.to_le_bytes());
+    frame.extend_from_slice(payload);
+    frame
+}
+
+/// The `sender data` request for `checks`.
+pub fn request(checks: &[Check]) -> Value {
+    let data: Vec<Value> = checks
+        .iter()
+        .map(|check| json!({"host": check.host, "key": check.service, "value": check.value}))
+        .collect();
+    json!({"request": "sender data", "data": data})
+}
+
+/// Parse the server's reply body.
+pub fn response(body: &Value) -> Result<Response, String> {
+    let info = body["info"].as_str().unwrap_or_default().to_string();
+    if body["response"] != "success" {
+        return Err(format!("server answered {body}"));
+    }
+    // "processed: 1; failed: 0; total: 1; seconds spent: 0.000055"
+    let count = |name: &str| {
+        info.split(';')
+            .filter_map(|part| part.split_once(':'))
+            .find(|(key, _)| key.trim() == name)
+            .and_then(|(_, n)| n.trim().parse().ok())
+            .unwrap_or(0)
+    };
+    Ok(Response {
+        processed: count("processed"),
+        failed: count("failed"),
+        info,
+    })
+}
+
+/// Send `checks` over `stream` and read the server's verdict.
+pub async fn send<S>(stream: &mut S, checks: &[Check]) -> Result<Response, String>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let body = serde_json::to_vec(&request(checks)).map_err(|e| e.to_string())?;
+    stream
+        .write_all(&frame(&body))
+        .await
+        .map_err(|e| format!("write: {e}"))?;
+
+    let mut header = [0u8; 13];
+    stream
+        .read_exact(&mut header)
+        .await
+        .map_err(|e| format!("read: {e}"))?;
+    if &header[..4] != b"ZBXD" {
+        return Err("not a Zabbix server (bad response header)".to_string());
+    }
+    let mut length = [0u8; 4];
+    length.copy_from_slice(&header[5..9]);
+    let length = u32::from_le_bytes(length) as usize;
+    if length > MAX_RESPONSE {
+        return Err(format!("response of {length} bytes is too large"));
+    }
+    let mut body = vec![0u8; length];
+    stream
+        .read_exact(&mut body)
+        .await
+        .map_err(|e| format!("read: {e}"))?;
+    let body: Value = serde_json::from_slice(&body).map_err(|e| format!("bad response: {e}"))?;
+    response(&body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn responses_report_processed_and_failed_values() {
+        let framed = frame(b"{}");
+        assert_eq!(&framed[..5], b"ZBXD\x01");
+        assert_eq!(&framed[5..13], &[2, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(&framed[13..], b"{}");
+
+        let verdict = response(&json!({
+            "response": "success",
+            "info": "processed: 0; failed: 1; total: 1; seconds spent: 0.000055",
+        }))
+        .unwrap_or_else(|e| panic!("{e}"));
+        assert_eq!((verdict.processed, verdict.failed), (0, 1));
+        assert!(response(&json!({"response": "failed", "info": "bad request"})).is_err());
+    }
+}
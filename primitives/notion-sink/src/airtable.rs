@@ -0,0 +1,89 @@
+//! Creating and updating records in an Airtable table.
+//!
+//! Values are sent with `typecast`, so Airtable converts them to each
+//! field's type (and adds missing select options). With a key, records are
+//! written with Airtable's own upsert (`performUpsert`), merging on the key
+//! field. The description goes, as Markdown, to `--description-field`,
+//! which should be a long text field with rich text formatting.
+
+use crate::api::{Api, encode};
+use crate::record::{Action, Record, Written};
+use primitive_common::errors::{ErrorCategory, HandlerError};
+use reqwest::Method;
+use serde_json::{Map, Value, json};
+
+/// One Airtable table.
+pub struct Airtable {
+    api: Api,
+    base: String,
+    table: String,
+    description_field: Option<String>,
+}
+
+impl Airtable {
+    /// `database` is `BASE_ID/TABLE`, where the table is a name or id.
+    pub fn new(
+        api: Api,
+        database: &str,
+        description_field: Option<String>,
+    ) -> Result<Self, String> {
+        match database.split_once('/') {
+            Some((base, table)) if !base.is_empty() && !table.is_empty() => Ok(Self {
+                api,
+                base: base.to_string(),
+                table: table.to_string(),
+                description_field,
+            }),
+            _ => Err(format!("expected BASE_ID/TABLE, got '{database}'")),
+        }
+    }
+
+    /// The request body writing `record`.
+    pub fn body(&self, record: &Record) -> Value {
+        let mut fields: Map<String, Value> = record.fields.iter().cloned().collect();
+        if let (Some(field), Some(description)) = (&self.description_field, &record.description) {
+            fields.insert(field.clone(), Value::from(description.as_str()));
+        }
+        let mut body = json!({"records": [{"fields": fields}], "typecast": true});
+        if let Some((key, _)) = &record.key {
+            body["performUpsert"] = json!({"fieldsToMergeOn": [key]});
+        }
+        body
+    }
+
+    /// Create the record, or update the one with its key.
+    pub async fn upsert(&self, record: &Record) -> Result<Written, HandlerError> {
+        let path = format!("/{}/{}", encode(&self.base), encode(&self.table));
+        // Upserts go through PATCH, plain creates through POST
+        let method = if record.key.is_some() {
+            Method::PATCH
+        } else {
+            Method::POST
+        };
+        let response = self
+            .api
+            .call(method, &path, Some(&self.body(record)))
+            .await?;
+        let id = response["records"][0]["id"]
+            .as_str()
+            .ok_or_else(|| {
+                HandlerError::new(
+                    ErrorCategory::Parse,
+                    format!("{path}: no record in response"),
+                )
+            })?
+            .to_string();
+        let updated = response["updatedRecords"]
+            .as_array()
+            .is_some_and(|ids| ids.iter().any(|updated| updated == id.as_str()));
+        Ok(Written {
+            action: if updated {
+                Action::Updated
+            } else {
+                Action::Created
+            },
+            id,
+            url: None,
+        })
+    }
+}
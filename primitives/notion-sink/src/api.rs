@@ -0,0 +1,240 @@
+//! JSON calls to the Notion or Airtable API, within their rate limits.
+//!
+//! Both services allow a few requests per second (Notion about 3, Airtable
+//! 5 per base), so calls are spaced at least `1 / --rate-limit` seconds
+//! apart. A `429 Too Many Requests` is still waited out (for
+//! `Retry-After`, or a second) and the call repeated, provided the wait is
+//! no longer than `--max-rate-limit-wait`; otherwise the call fails as
+//! `request` so the harness retries it later.
+//!
+//! Other failures map onto handler error categories: transport errors and
+//! `5xx` responses are `request`, timeouts `timeout`, other non-2xx
+//! responses `rejected`.
+
+use primitive_common::errors::{ErrorCategory, HandlerError};
+use reqwest::header::{HeaderMap, RETRY_AFTER};
+use reqwest::{Client, Method, StatusCode};
+use serde_json::Value;
+use std::time::Duration;
+use tokio::sync::Mutex;
+use tokio::time::Instant;
+
+/// Times a rate-limited call is repeated.
+const RATE_LIMIT_RETRIES: u32 = 3;
+
+/// Authenticated, paced access to one API.
+pub struct Api {
+    client: Client,
+    url: String,
+    /// Sent with every request (authorization, API version).
+    headers: HeaderMap,
+    timeout: Duration,
+    max_wait: Duration,
+    /// The least time between two requests.
+    spacing: Duration,
+    /// When the next request may start.
+    next: Mutex<Instant>,
+}
+
+impl Api {
+    pub fn new(
+        client: Client,
+        url: &str,
+        headers: HeaderMap,
+        timeout: Duration,
+        rate_limit: f64,
+        max_wait: Duration,
+    ) -> Self {
+        let spacing = if rate_limit > 0.0 {
+            Duration::from_secs_f64(1.0 / rate_limit)
+        } else {
+            Duration::ZERO
+        };
+        Self {
+            client,
+            url: url.trim_end_matches('/').to_string(),
+            headers,
+            timeout,
+            max_wait,
+            spacing,
+            next: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// Wait for this request's turn; later callers queue behind it.
+    async fn turn(&self, delay: Duration) {
+        let start = {
+            let mut next = self.next.lock().await;
+            let start = (*next).max(Instant::now() + delay);
+            *next = start + self.spacing;
+            start
+        };
+        tokio::time::sleep_until(start).await;
+    }
+
+    /// Call an endpoint, e.g. `call(Method::POST, "/pages", Some(&body))`.
+    pub async fn call(
+        &self,
+        method: Method,
+        path: &str,
+        body: Option<&Value>,
+    ) -> Result<Value, HandlerError> {
+        let target = format!("{method} {path}");
+        let mut attempt = 0;
+        let mut delay = Duration::ZERO;
+        loop {
+            self.turn(delay).await;
+            let mut request = self
+                .client
+                .request(method.clone(), format!("{}{path}", self.url))
+                .timeout(self.timeout)
+                .headers(self.headers.clone());
+            if let Some(body) = body {
+                request = request.json(body);
+            }
+            let response = request.send().await.map_err(|e| transport(&target, &e))?;
+            let status = response.status();
+            let headers = response.headers().clone();
+            let text = response.text().await.map_err(|e| transport(&target, &e))?;
+
+            if status == StatusCode::TOO_MANY_REQUESTS {
+                let wait = retry_delay(&headers).unwrap_or(Duration::from_secs(1));
+                if attempt >= RATE_LIMIT_RETRIES || wait > self.max_wait {
+                    return Err(HandlerError::new(
+                        ErrorCategory::Request,
+                        format!("{target}: rate limited for {}s", wait.as_secs()),
+                    ));
+                }
+                attempt += 1;
+                eprintln!("{target}: rate limited; retrying in {}ms", wait.as_millis());
+                delay = wait;
+                continue;
+            }
+            if !status.is_success() {
+                let category = if status.is_server_error() || status == StatusCode::CONFLICT {
+                    ErrorCategory::Request
+                } else {
+                    ErrorCategory::Rejected
+                };
+                return Err(HandlerError::new(
+                    category,
+                    format!("{target}: HTTP {status}: {}", api_message(&text)),
+                ));
+            }
+            if text.trim().is_empty() {
+                return Ok(Value::Null);
+            }
+            return serde_json::from_str(&text)
+                .map_err(|e| HandlerError::new(ErrorCategory::Parse, format!("{target}: {e}")));
+        }
+    }
+}
+
+/// How long `Retry-After` (seconds) asks to wait.
+fn retry_delay(headers: &HeaderMap) -> Option<Duration> {
+    headers
+        .get(RETRY_AFTER)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.trim().parse::<f64>().ok())
+        .filter(|seconds| seconds.is_finite() && *seconds >= 0.0)
+        .map(Duration::from_secs_f64)
+}
+
+fn transport(target: &str, e: &reqwest::Error) -> HandlerError {
+    if e.is_timeout() {
+        HandlerError::new(ErrorCategory::Timeout, format!("{target}: timed out"))
+    } else {
+        HandlerError::new(ErrorCategory::Request, format!("{target}: {e}"))
+    }
+}
+
+/// The error message from a Notion (`message`) or Airtable
+/// (`error.message`) error body, or the body itself.
+fn api_message(body: &str) -> String {
+    serde_json::from_str::<Value>(body)
+        .ok()
+        .and_then(|v| {
+            let message = v
+                .get("message")
+                .or_else(|| v.pointer("/error/message"))
+                .or_else(|| v.get("error"))?;
+            Some(match message {
+                Value::String(s) => s.clone(),
+                other => other.to_string(),
+            })
+        })
+        .unwrap_or_else(|| body.trim().to_string())
+}
+
+/// Percent-encode a path segment (an Airtable table name may contain
+/// spaces).
+pub fn encode(segment: &str) -> String {
+    let mut out = String::with_capacity(segment.len());
+    for byte in segment.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                out.push(byte as char);
+            }
+            byte => out.push_str(&format!("%{byte:02X}")),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{Router, http::StatusCode as Status, routing::get};
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[tokio::test]
+    async fn rate_limited_calls_are_spaced_and_retried() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&calls);
+        let app = Router::new().route(
+            "/v1/ping",
+            get(move || {
+                let counter = Arc::clone(&counter);
+                async move {
+                    // The first call is rate limited
+                    if counter.fetch_add(1, Ordering::SeqCst) == 0 {
+                        (Status::TOO_MANY_REQUESTS, [("retry-after", "0.05")], "{}")
+                    } else {
+                        (Status::OK, [("retry-after", "0")], r#"{"ok": true}"#)
+                    }
+                }
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap_or_else(|e| panic!("bind: {e}"));
+        let addr = listener
+            .local_addr()
+            .unwrap_or_else(|e| panic!("local_addr: {e}"));
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let api = Api::new(
+            Client::new(),
+            &format!("http://{addr}/v1/"),
+            HeaderMap::new(),
+            Duration::from_secs(5),
+            20.0,
+            Duration::from_secs(1),
+        );
+        let started = Instant::now();
+        let first = api.call(Method::GET, "/ping", None).await;
+        assert_eq!(first.ok(), Some(serde_json::json!({"ok": true})));
+        let second = api.call(Method::GET, "/ping", None).await;
+        assert!(second.is_ok());
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+        // 50ms for the retry, then 50ms between requests at 20 per second
+        assert!(started.elapsed() >= Duration::from_millis(100));
+
+        assert_eq!(encode("Deploy log"), "Deploy%20log");
+        assert_eq!(
+            api_message(r#"{"error": {"type": "INVALID", "message": "Unknown field"}}"#),
+            "Unknown field"
+        );
+    }
+}
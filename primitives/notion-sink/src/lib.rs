@@ -0,0 +1,574 @@
+//! Notion Sink - Database Records from Events
+//!
+//! A Sink that turns each event into a record (see [`record`]) and writes
+//! it to a Notion database (see [`notion`]) or, with `--backend airtable`,
+//! an Airtable table (see [`airtable`]): a tracker row per incident, a page
+//! per customer request, a CRM entry per sign-up. With `--key`, repeated
+//! events update the record they created rather than add duplicates.
+//!
+//! Requests stay within the service's rate limit (see [`api`]), and events
+//! are written one at a time so two events with the same key cannot both
+//! create a record. Each written record is announced as `notion.written`.
+//!
+//! # Examples
+//!
+//! ```bash
+//! notion-sink -s 'incident.*' \
+//!   --token "$NOTION_TOKEN" --database 8a6b35e6e67f4e4b8b8a2c5a1f0d3c9e \
+//!   --field 'Name=title' --field 'Incident=id' --field 'Status=status' \
+//!   --field 'Severity=severity' --key Incident --description summary
+//!
+//! notion-sink -s 'signup.*' --backend airtable \
+//!   --token "$AIRTABLE_TOKEN" --database appXXXXXXXXXXXXXX/Signups \
+//!   --field 'Email=email' --field 'Plan=plan' --key Email
+//! ```
+
+pub mod airtable;
+pub mod api;
+pub mod notion;
+pub mod record;
+pub mod richtext;
+
+use airtable::Airtable;
+use api::Api;
+use clap::{Parser, ValueEnum};
+use emergent_client::EmergentMessage;
+use notion::Notion;
+use primitive_common::capabilities::VERSION;
+use primitive_common::doctor::Report;
+use primitive_common::errors::{ErrorCategory, HandlerError};
+use primitive_common::{Publisher, SinkArgs, SinkConfig, SinkContext, SinkHandler, run_sink};
+use record::{Field, Mapping, Record, Written};
+use reqwest::Client;
+use reqwest::header::{AUTHORIZATION, HeaderMap, HeaderValue};
+use serde_json::{Map, Value, json};
+use std::sync::OnceLock;
+use std::time::Duration;
+use tokio::sync::Mutex;
+
+pub const WRITTEN_EVENT_TYPE: &str = "notion.written";
+
+/// The service records are written to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Backend {
+    Notion,
+    Airtable,
+}
+
+impl Backend {
+    fn api_url(self) -> &'static str {
+        match self {
+            Self::Notion => "https://api.notion.com/v1",
+            Self::Airtable => "https://api.airtable.com/v0",
+        }
+    }
+
+    /// Requests per second the service allows.
+    fn rate_limit(self) -> f64 {
+        match self {
+            Self::Notion => 3.0,
+            Self::Airtable => 5.0,
+        }
+    }
+}
+
+/// Notion Sink — create or update database records from events.
+#[derive(Parser, Debug)]
+#[command(name = "notion_sink", version = VERSION)]
+#[command(about = "Create or update Notion database pages or Airtable records from events")]
+struct Args {
+    /// Message types to subscribe to.
+    #[arg(short, long = "subscribe", required = true)]
+    subscribe: Vec<String>,
+
+    /// Service to write to.
+    #[arg(
+        long,
+        env = "NOTION_SINK_BACKEND",
+        value_enum,
+        default_value = "notion"
+    )]
+    backend: Backend,
+
+    /// Notion integration token or Airtable personal access token.
+    #[arg(long, env = "NOTION_SINK_TOKEN")]
+    token: String,
+
+    /// Notion database id, or `BASE_ID/TABLE` for Airtable.
+    #[arg(long, env = "NOTION_SINK_DATABASE")]
+    database: String,
+
+    /// Field as `NAME=PATH`, filled from the payload field at the dotted
+    /// path (repeatable).
+    #[arg(
+        long = "field",
+        env = "NOTION_SINK_FIELDS",
+        value_delimiter = ',',
+        required = true
+    )]
+    fields: Vec<String>,
+
+    /// Field whose value identifies a record; events with a value already
+    /// present update that record.
+    #[arg(long, env = "NOTION_SINK_KEY")]
+    key: Option<String>,
+
+    /// Payload field with Markdown rendered as rich text: the page content
+    /// in Notion, `--description-field` in Airtable.
+    #[arg(long, env = "NOTION_SINK_DESCRIPTION")]
+    description: Option<String>,
+
+    /// Airtable long text field the description goes to.
+    #[arg(long, env = "NOTION_SINK_DESCRIPTION_FIELD")]
+    description_field: Option<String>,
+
+    /// API base URL (defaults to the backend's).
+    #[arg(long, env = "NOTION_SINK_API_URL")]
+    api_url: Option<String>,
+
+    /// Requests per second (defaults to 3 for Notion, 5 for Airtable).
+    #[arg(long, env = "NOTION_SINK_RATE_LIMIT")]
+    rate_limit: Option<f64>,
+
+    /// Longest rate limit wait in seconds before the event is retried
+    /// later instead.
+    #[arg(long, env = "NOTION_SINK_MAX_RATE_LIMIT_WAIT", default_value = "60")]
+    max_rate_limit_wait: u64,
+
+    /// Timeout for each request in milliseconds.
+    #[arg(long, env = "NOTION_SINK_TIMEOUT", default_value = "30000")]
+    timeout: u64,
+
+    #[command(flatten)]
+    sink: SinkArgs,
+}
+
+/// Where records are written.
+enum Target {
+    Notion(Notion),
+    Airtable(Airtable),
+}
+
+impl Target {
+    async fn upsert(&self, record: &Record) -> Result<Written, HandlerError> {
+        match self {
+            Self::Notion(notion) => notion.upsert(record).await,
+            Self::Airtable(airtable) => airtable.upsert(record).await,
+        }
+    }
+}
+
+/// Writes a record per event.
+struct NotionSink {
+    target: Target,
+    mapping: Mapping,
+    /// One event at a time, so the same key is never created twice.
+    writing: Mutex<()>,
+    publisher: OnceLock<Publisher>,
+}
+
+impl SinkHandler for NotionSink {
+    async fn handle(
+        &self,
+        msg: &EmergentMessage,
+        ctx: &SinkContext<'_>,
+    ) -> Result<(), HandlerError> {
+        let record = self
+            .mapping
+            .record(ctx.payload())
+            .map_err(|e| HandlerError::new(ErrorCategory::Rejected, e))?;
+
+        if ctx.is_dry_run() {
+            let fields: Map<String, Value> = record.fields.iter().cloned().collect();
+            let detail = json!({
+                "fields": fields,
+                "key": record.key.as_ref().map(|(name, value)| json!({name: value})),
+                "description": record.description,
+            });
+            ctx.would_have("upsert", detail).await;
+            return Ok(());
+        }
+
+        let written = {
+            let _writing = self.writing.lock().await;
+            self.target.upsert(&record).await?
+        };
+
+        if let Some(publisher) = self.publisher.get() {
+            let event = EmergentMessage::new(WRITTEN_EVENT_TYPE)
+                .with_causation_id(msg.id())
+                .with_payload(json!({
+                    "action": written.action.as_str(),
+                    "id": written.id,
+                    "url": written.url,
+                    "message_id": ctx.message_id(),
+                    "message_type": msg.message_type.as_str(),
+                }));
+            if let Err(e) = publisher.publish(event) {
+                eprintln!("Failed to publish {WRITTEN_EVENT_TYPE}: {e}");
+            }
+        }
+        Ok(())
+    }
+
+    fn self_test(&self, report: &mut Report) {
+        let backend = match self.target {
+            Target::Notion(_) => "notion",
+            Target::Airtable(_) => "airtable",
+        };
+        report.check("backend", Ok(backend.to_string()));
+    }
+
+    fn publishes(&self) -> &'static [&'static str] {
+        &[WRITTEN_EVENT_TYPE]
+    }
+
+    fn attach(&self, publisher: Publisher) {
+        let _ = self.publisher.set(publisher);
+    }
+}
+
+/// Run the primitive with `args` as its command line (the first item is
+/// the program name, as with `std::env::args_os`).
+pub async fn run<I, T>(args: I) -> Result<(), Box<dyn std::error::Error>>
+where
+    I: IntoIterator<Item = T>,
+    T: Into<std::ffi::OsString> + Clone,
+{
+    let args = Args::parse_from(args);
+
+    let exit = |e: String| -> ! {
+        eprintln!("Error: {e}");
+        std::process::exit(1);
+    };
+    let fields = args
+        .fields
+        .iter()
+        .map(|spec| Field::parse(spec).map_err(|e| format!("--field: {e}")))
+        .collect::<Result<Vec<_>, String>>()
+        .unwrap_or_else(|e| exit(e));
+    let mapping = Mapping {
+        fields,
+        key: args.key.clone(),
+        description: args.description.clone(),
+    };
+    mapping.validate().unwrap_or_else(|e| exit(e));
+    if args.backend == Backend::Airtable
+        && args.description.is_some()
+        && args.description_field.is_none()
+    {
+        exit("--description with Airtable needs --description-field".to_string());
+    }
+
+    let mut headers = HeaderMap::new();
+    let bearer = HeaderValue::from_str(&format!("Bearer {}", args.token))
+        .unwrap_or_else(|e| exit(format!("--token: {e}")));
+    headers.insert(AUTHORIZATION, bearer);
+    if args.backend == Backend::Notion {
+        headers.insert("Notion-Version", HeaderValue::from_static(notion::VERSION));
+    }
+    let api = Api::new(
+        Client::new(),
+        args.api_url.as_deref().unwrap_or(args.backend.api_url()),
+        headers,
+        Duration::from_millis(args.timeout),
+        args.rate_limit.unwrap_or(args.backend.rate_limit()),
+        Duration::from_secs(args.max_rate_limit_wait),
+    );
+    let target = match args.backend {
+        Backend::Notion => Target::Notion(Notion::new(api, &args.database)),
+        Backend::Airtable => Target::Airtable(
+            Airtable::new(api, &args.database, args.description_field.clone())
+                .unwrap_or_else(|e| exit(format!("--database: {e}"))),
+        ),
+    };
+
+    let config = SinkConfig {
+        name: "notion_sink",
+        subscribe: &args.subscribe,
+        would_have_as: "notion.would_have",
+        dead_letter_as: "notion.dead_letter",
+        settings: &args,
+    };
+    let handler = NotionSink {
+        target,
+        mapping,
+        writing: Mutex::new(()),
+        publisher: OnceLock::new(),
+    };
+
+    run_sink(config, &args.sink, handler).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{Json, Router, extract::Request, routing::any};
+    use emergent_testkit::{SinkFixture, fixtures, spawn_sink};
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use tokio::sync::mpsc;
+
+    type Requests = mpsc::UnboundedReceiver<(String, String, Value)>;
+
+    /// Serve a fake Notion or Airtable API on a random port, forwarding
+    /// each request's method, path and JSON body. The database starts
+    /// empty; once a record is created, queries find it.
+    async fn server() -> (String, Requests) {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let created = Arc::new(AtomicBool::new(false));
+        let app = Router::new().fallback(any(move |request: Request| {
+            let tx = tx.clone();
+            let created = Arc::clone(&created);
+            async move {
+                let method = request.method().to_string();
+                let path = request.uri().to_string();
+                let body = axum::body::to_bytes(request.into_body(), usize::MAX)
+                    .await
+                    .unwrap_or_default();
+                let body: Value = serde_json::from_slice(&body).unwrap_or(Value::Null);
+                let exists = created.load(Ordering::SeqCst);
+                let reply = match (method.as_str(), path.as_str()) {
+                    ("GET", "/databases/db1") => json!({"properties": {
+                        "Name": {"type": "title"},
+                        "Incident": {"type": "rich_text"},
+                        "Severity": {"type": "select"},
+                    }}),
+                    ("POST", "/databases/db1/query") if exists => {
+                        json!({"results": [{"id": "page-1"}], "has_more": false})
+                    }
+                    ("POST", "/databases/db1/query") => json!({"results": [], "has_more": false}),
+                    ("POST", "/pages") => {
+                        created.store(true, Ordering::SeqCst);
+                        json!({"id": "page-1", "url": "https://www.notion.so/page-1"})
+                    }
+                    ("PATCH", "/pages/page-1") => {
+                        json!({"id": "page-1", "url": "https://www.notion.so/page-1"})
+                    }
+                    ("GET", "/blocks/page-1/children?page_size=100") => {
+                        json!({"results": [{"id": "block-1"}], "has_more": false})
+                    }
+                    ("POST" | "PATCH", "/appBase/Signups%20list") => {
+                        let updated = if exists { vec!["rec1"] } else { vec![] };
+                        created.store(true, Ordering::SeqCst);
+                        json!({"records": [{"id": "rec1"}], "updatedRecords": updated})
+                    }
+                    _ => json!({}),
+                };
+                let _ = tx.send((method, path, body));
+                Json(reply)
+            }
+        }));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap_or_else(|e| panic!("bind: {e}"));
+        let addr = listener
+            .local_addr()
+            .unwrap_or_else(|e| panic!("local_addr: {e}"));
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        (format!("http://{addr}"), rx)
+    }
+
+    fn api(url: &str) -> Api {
+        Api::new(
+            Client::new(),
+            url,
+            HeaderMap::new(),
+            Duration::from_secs(5),
+            0.0,
+            Duration::from_secs(1),
+        )
+    }
+
+    fn handler(target: Target, fields: &[&str], key: &str, description: bool) -> NotionSink {
+        NotionSink {
+            target,
+            mapping: Mapping {
+                fields: fields
+                    .iter()
+                    .map(|spec| Field::parse(spec).unwrap_or_else(|e| panic!("{e}")))
+                    .collect(),
+                key: Some(key.to_string()),
+                description: description.then(|| "summary".to_string()),
+            },
+            writing: Mutex::new(()),
+            publisher: OnceLock::new(),
+        }
+    }
+
+    async fn next(requests: &mut Requests) -> (String, String, Value) {
+        requests
+            .recv()
+            .await
+            .unwrap_or_else(|| panic!("no request"))
+    }
+
+    #[tokio::test]
+    async fn repeated_keys_update_the_notion_page() {
+        let (url, mut requests) = server().await;
+        let (mut engine, run) = spawn_sink(
+            SinkFixture::new("notion_sink", "notion"),
+            SinkArgs::default(),
+            handler(
+                Target::Notion(Notion::new(api(&url), "db1")),
+                &["Name=title", "Incident=id", "Severity=severity"],
+                "Incident",
+                true,
+            ),
+        );
+
+        let incident = |severity: &str, summary: &str| {
+            fixtures::message(
+                "incident.opened",
+                json!({"id": "INC-7", "title": "Checkout down", "severity": severity,
+                       "summary": summary}),
+            )
+        };
+        engine
+            .inject_message(incident("sev2", "**Investigating**"))
+            .await;
+        let written = engine.expect_published(WRITTEN_EVENT_TYPE).await;
+        assert_eq!(written.payload()["action"], "created");
+        assert_eq!(written.payload()["url"], "https://www.notion.so/page-1");
+
+        let (_, path, _) = next(&mut requests).await;
+        assert_eq!(path, "/databases/db1");
+        let (_, path, query) = next(&mut requests).await;
+        assert_eq!(path, "/databases/db1/query");
+        assert_eq!(
+            query["filter"],
+            json!({"property": "Incident", "rich_text": {"equals": "INC-7"}})
+        );
+        let (method, path, page) = next(&mut requests).await;
+        assert_eq!((method.as_str(), path.as_str()), ("POST", "/pages"));
+        assert_eq!(page["parent"], json!({"database_id": "db1"}));
+        assert_eq!(
+            page["properties"]["Severity"],
+            json!({"select": {"name": "sev2"}})
+        );
+        assert_eq!(
+            page["properties"]["Name"]["title"][0]["text"]["content"],
+            "Checkout down"
+        );
+        assert_eq!(
+            page["children"][0]["paragraph"]["rich_text"][0]["annotations"],
+            json!({"bold": true})
+        );
+
+        engine.inject_message(incident("sev1", "Resolved")).await;
+        let written = engine.expect_published(WRITTEN_EVENT_TYPE).await;
+        assert_eq!(written.payload()["action"], "updated");
+        let (_, path, _) = next(&mut requests).await;
+        assert_eq!(path, "/databases/db1/query");
+        let (method, path, update) = next(&mut requests).await;
+        assert_eq!((method.as_str(), path.as_str()), ("PATCH", "/pages/page-1"));
+        assert_eq!(
+            update["properties"]["Severity"],
+            json!({"select": {"name": "sev1"}})
+        );
+        assert_eq!(next(&mut requests).await.0, "GET");
+        let (method, path, _) = next(&mut requests).await;
+        assert_eq!(
+            (method.as_str(), path.as_str()),
+            ("DELETE", "/blocks/block-1")
+        );
+        let (method, path, content) = next(&mut requests).await;
+        assert_eq!(
+            (method.as_str(), path.as_str()),
+            ("PATCH", "/blocks/page-1/children")
+        );
+        assert_eq!(
+            content["children"][0]["paragraph"]["rich_text"][0]["text"]["content"],
+            "Resolved"
+        );
+
+        engine.close();
+        assert_eq!(run.await.ok(), Some(Ok(())));
+    }
+
+    #[tokio::test]
+    async fn airtable_records_are_upserted_on_the_key() {
+        let (url, mut requests) = server().await;
+        let airtable = Airtable::new(api(&url), "appBase/Signups list", Some("Notes".to_string()))
+            .unwrap_or_else(|e| panic!("{e}"));
+        let (mut engine, run) = spawn_sink(
+            SinkFixture::new("notion_sink", "notion"),
+            SinkArgs::default(),
+            handler(
+                Target::Airtable(airtable),
+                &["Email=email", "Plan=plan"],
+                "Email",
+                true,
+            ),
+        );
+
+        let signup = |plan: &str| {
+            fixtures::message(
+                "signup.completed",
+                json!({"email": "ana@example.com", "plan": plan, "summary": "*Trial*"}),
+            )
+        };
+        engine.inject_message(signup("free")).await;
+        let written = engine.expect_published(WRITTEN_EVENT_TYPE).await;
+        assert_eq!(written.payload()["action"], "created");
+        assert_eq!(written.payload()["id"], "rec1");
+        let (method, path, body) = next(&mut requests).await;
+        assert_eq!(
+            (method.as_str(), path.as_str()),
+            ("PATCH", "/appBase/Signups%20list")
+        );
+        assert_eq!(
+            body,
+            json!({
+                "records": [{"fields": {
+                    "Email": "ana@example.com", "Plan": "free", "Notes": "*Trial*"
+                }}],
+                "performUpsert": {"fieldsToMergeOn": ["Email"]},
+                "typecast": true,
+            })
+        );
+
+        engine.inject_message(signup("pro")).await;
+        let written = engine.expect_published(WRITTEN_EVENT_TYPE).await;
+        assert_eq!(written.payload()["action"], "updated");
+
+        engine.close();
+        assert_eq!(run.await.ok(), Some(Ok(())));
+    }
+
+    #[tokio::test]
+    async fn dry_run_reports_the_record() {
+        let args = SinkArgs {
+            dry_run: true,
+            ..Default::default()
+        };
+        let (mut engine, run) = spawn_sink(
+            SinkFixture::new("notion_sink", "notion"),
+            args,
+            handler(
+                Target::Notion(Notion::new(api("http://127.0.0.1:9"), "db1")),
+                &["Name=title", "Incident=id"],
+                "Incident",
+                false,
+            ),
+        );
+        engine
+            .inject_message(fixtures::message(
+                "incident.opened",
+                json!({"id": "INC-8", "title": "Search slow"}),
+            ))
+            .await;
+        let report = engine.expect_published("notion.would_have").await;
+        assert_eq!(
+            report.payload()["detail"]["fields"],
+            json!({"Name": "Search slow", "Incident": "INC-8"})
+        );
+        assert_eq!(
+            report.payload()["detail"]["key"],
+            json!({"Incident": "INC-8"})
+        );
+
+        engine.close();
+        assert_eq!(run.await.ok(), Some(Ok(())));
+    }
+}
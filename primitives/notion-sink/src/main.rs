@@ -0,0 +1,6 @@
+//! `notion-sink` binary; the primitive itself lives in the library.
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    notion_sink::run(std::env::args_os()).await
+}
@@ -0,0 +1,282 @@
+//! Creating and updating pages in a Notion database.
+//!
+//! Payload values are converted to the type of the property they fill, as
+//! the database's schema (read once, on first use) declares it: text for
+//! `title` and `rich_text`, names for `select`, `status` and
+//! `multi_select` (from an array or a comma-separated string), `start`
+//! for `date`, and so on. The description becomes the page's content;
+//! updating a page replaces its content.
+//!
+//! With a key, the database is queried for a page whose key property
+//! equals the event's value, which must be a `title`, `rich_text`,
+//! `number`, `select`, `status`, `url`, `email` or `phone_number`
+//! property.
+
+use crate::api::Api;
+use crate::record::{Action, Record, Written, text};
+use crate::richtext;
+use primitive_common::errors::{ErrorCategory, HandlerError};
+use reqwest::Method;
+use serde_json::{Map, Value, json};
+use std::collections::HashMap;
+use tokio::sync::OnceCell;
+
+/// The API version the requests are written for.
+pub const VERSION: &str = "2022-06-28";
+
+/// One Notion database.
+pub struct Notion {
+    api: Api,
+    database_id: String,
+    /// Property names and their types.
+    schema: OnceCell<HashMap<String, String>>,
+}
+
+fn rejected(message: String) -> HandlerError {
+    HandlerError::new(ErrorCategory::Rejected, message)
+}
+
+fn number(value: &Value) -> Option<Value> {
+    match value {
+        Value::Number(_) => Some(value.clone()),
+        Value::String(s) => s.trim().parse::<f64>().ok().map(Value::from),
+        _ => None,
+    }
+}
+
+/// `value` as a property of type `kind`.
+pub fn property(kind: &str, value: &Value) -> Result<Value, String> {
+    let names = |value: &Value| -> Vec<Value> {
+        match value {
+            Value::Array(items) => items
+                .iter()
+                .map(|item| json!({"name": text(item)}))
+                .collect(),
+            other => text(other)
+                .split(',')
+                .map(str::trim)
+                .filter(|name| !name.is_empty())
+                .map(|name| json!({"name": name}))
+                .collect(),
+        }
+    };
+    Ok(match kind {
+        "title" | "rich_text" => json!({ kind: richtext::plain(&text(value)) }),
+        "number" => json!({
+            "number": number(value).ok_or_else(|| format!("'{value}' is not a number"))?
+        }),
+        "checkbox" => json!({
+            "checkbox": match value {
+                Value::Bool(b) => *b,
+                other => matches!(text(other).as_str(), "true" | "yes" | "1"),
+            }
+        }),
+        "select" | "status" => json!({ kind: {"name": text(value)} }),
+        "multi_select" => json!({ "multi_select": names(value) }),
+        "date" => json!({ "date": {"start": text(value)} }),
+        "url" | "email" | "phone_number" => json!({ kind: text(value) }),
+        "people" | "relation" => {
+            let ids: Vec<Value> = match value {
+                Value::Array(items) => items.iter().map(|id| json!({"id": text(id)})).collect(),
+                other => vec![json!({"id": text(other)})],
+            };
+            json!({ kind: ids })
+        }
+        other => return Err(format!("{other} properties cannot be set")),
+    })
+}
+
+/// A query filter matching pages whose `name` property equals `value`.
+pub fn filter(name: &str, kind: &str, value: &Value) -> Result<Value, String> {
+    let equals = match kind {
+        "title" | "rich_text" | "url" | "email" | "phone_number" | "select" | "status" => {
+            Value::from(text(value))
+        }
+        "number" => number(value).ok_or_else(|| format!("'{value}' is not a number"))?,
+        other => return Err(format!("a {other} property cannot be the key")),
+    };
+    Ok(json!({"property": name, kind: {"equals": equals}}))
+}
+
+impl Notion {
+    pub fn new(api: Api, database_id: &str) -> Self {
+        Self {
+            api,
+            database_id: database_id.to_string(),
+            schema: OnceCell::new(),
+        }
+    }
+
+    async fn schema(&self) -> Result<&HashMap<String, String>, HandlerError> {
+        self.schema
+            .get_or_try_init(|| async {
+                let database = self
+                    .api
+                    .call(
+                        Method::GET,
+                        &format!("/databases/{}", self.database_id),
+                        None,
+                    )
+                    .await?;
+                let properties = database["properties"].as_object().ok_or_else(|| {
+                    HandlerError::new(
+                        ErrorCategory::Parse,
+                        format!("database {} has no properties", self.database_id),
+                    )
+                })?;
+                Ok(properties
+                    .iter()
+                    .map(|(name, property)| {
+                        let kind = property["type"].as_str().unwrap_or_default();
+                        (name.clone(), kind.to_string())
+                    })
+                    .collect())
+            })
+            .await
+    }
+
+    /// The type of property `name`.
+    async fn kind(&self, name: &str) -> Result<&str, HandlerError> {
+        self.schema()
+            .await?
+            .get(name)
+            .map(String::as_str)
+            .ok_or_else(|| {
+                rejected(format!(
+                    "database {} has no property '{name}'",
+                    self.database_id
+                ))
+            })
+    }
+
+    /// The page whose key property holds the record's key value.
+    async fn find(&self, record: &Record) -> Result<Option<String>, HandlerError> {
+        let Some((name, value)) = &record.key else {
+            return Ok(None);
+        };
+        let filter = filter(name, self.kind(name).await?, value).map_err(rejected)?;
+        let found = self
+            .api
+            .call(
+                Method::POST,
+                &format!("/databases/{}/query", self.database_id),
+                Some(&json!({"filter": filter, "page_size": 1})),
+            )
+            .await?;
+        Ok(found["results"][0]["id"].as_str().map(str::to_string))
+    }
+
+    /// Replace the content of page `id` with `blocks`.
+    async fn replace_content(&self, id: &str, blocks: Vec<Value>) -> Result<(), HandlerError> {
+        let children = format!("/blocks/{id}/children");
+        loop {
+            let current = self
+                .api
+                .call(Method::GET, &format!("{children}?page_size=100"), None)
+                .await?;
+            let ids: Vec<&str> = current["results"]
+                .as_array()
+                .map(|blocks| blocks.iter().filter_map(|b| b["id"].as_str()).collect())
+                .unwrap_or_default();
+            for block in &ids {
+                self.api
+                    .call(Method::DELETE, &format!("/blocks/{block}"), None)
+                    .await?;
+            }
+            if ids.is_empty() || current["has_more"] != true {
+                break;
+            }
+        }
+        if !blocks.is_empty() {
+            self.api
+                .call(
+                    Method::PATCH,
+                    &children,
+                    Some(&json!({ "children": blocks })),
+                )
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Create the record's page, or update the one with its key.
+    pub async fn upsert(&self, record: &Record) -> Result<Written, HandlerError> {
+        let mut properties = Map::new();
+        for (name, value) in &record.fields {
+            let kind = self.kind(name).await?;
+            let property = property(kind, value).map_err(|e| rejected(format!("{name}: {e}")))?;
+            properties.insert(name.clone(), property);
+        }
+        let blocks = record.description.as_deref().map(richtext::blocks);
+
+        let (action, page) = match self.find(record).await? {
+            Some(id) => {
+                let page = self
+                    .api
+                    .call(
+                        Method::PATCH,
+                        &format!("/pages/{id}"),
+                        Some(&json!({ "properties": properties })),
+                    )
+                    .await?;
+                if let Some(blocks) = blocks {
+                    self.replace_content(&id, blocks).await?;
+                }
+                (Action::Updated, page)
+            }
+            None => {
+                let mut body = json!({
+                    "parent": {"database_id": self.database_id},
+                    "properties": properties,
+                });
+                if let Some(blocks) = blocks {
+                    body["children"] = Value::Array(blocks);
+                }
+                let page = self.api.call(Method::POST, "/pages", Some(&body)).await?;
+                (Action::Created, page)
+            }
+        };
+        Ok(Written {
+            action,
+            id: page["id"].as_str().unwrap_or_default().to_string(),
+            url: page["url"].as_str().map(str::to_string),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn values_take_the_property_type() {
+        assert_eq!(
+            property("title", &json!("Login fails")),
+            Ok(json!({"title": [{"type": "text", "text": {"content": "Login fails"}}]}))
+        );
+        assert_eq!(
+            property("number", &json!("12.5")),
+            Ok(json!({"number": 12.5}))
+        );
+        assert!(property("number", &json!("many")).is_err());
+        assert_eq!(
+            property("multi_select", &json!("bug, login")),
+            Ok(json!({"multi_select": [{"name": "bug"}, {"name": "login"}]}))
+        );
+        assert_eq!(
+            property("status", &json!("Done")),
+            Ok(json!({"status": {"name": "Done"}}))
+        );
+        assert_eq!(
+            property("checkbox", &json!("yes")),
+            Ok(json!({"checkbox": true}))
+        );
+        assert!(property("formula", &json!(1)).is_err());
+
+        assert_eq!(
+            filter("Ticket", "number", &json!("42")),
+            Ok(json!({"property": "Ticket", "number": {"equals": 42.0}}))
+        );
+        assert!(filter("Due", "date", &json!("2024-05-20")).is_err());
+    }
+}
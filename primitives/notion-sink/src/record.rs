@@ -0,0 +1,175 @@
+//! Which payload values go in which field, and the record they make.
+//!
+//! `--field NAME=PATH` fills the property (Notion) or field (Airtable)
+//! `NAME` with the payload value at the dotted `PATH`, such as
+//! `ticket.title`; fields missing from the payload are left as they are.
+//! `--key NAME` makes one of them the upsert key: a record whose `NAME`
+//! already holds the event's value is updated instead of a new one
+//! created. `--description PATH` is a Markdown payload field rendered as
+//! rich text.
+
+use primitive_common::key;
+use serde_json::Value;
+
+/// One `--field` mapping.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Field {
+    pub name: String,
+    path: String,
+}
+
+impl Field {
+    /// Parse `NAME=PATH`.
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        match spec.split_once('=') {
+            Some((name, path)) if !name.trim().is_empty() && !path.trim().is_empty() => Ok(Self {
+                name: name.trim().to_string(),
+                path: path.trim().to_string(),
+            }),
+            _ => Err(format!("expected NAME=PATH, got '{spec}'")),
+        }
+    }
+}
+
+/// How events become records.
+#[derive(Debug, Clone)]
+pub struct Mapping {
+    pub fields: Vec<Field>,
+    /// Name of the field that identifies a record.
+    pub key: Option<String>,
+    /// Payload path of the Markdown description.
+    pub description: Option<String>,
+}
+
+/// What one event writes.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Record {
+    /// Field names and values, in `--field` order.
+    pub fields: Vec<(String, Value)>,
+    /// The key field and its value.
+    pub key: Option<(String, Value)>,
+    /// Markdown.
+    pub description: Option<String>,
+}
+
+/// Whether a record was new.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Created,
+    Updated,
+}
+
+impl Action {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Created => "created",
+            Self::Updated => "updated",
+        }
+    }
+}
+
+/// A record as the service stored it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Written {
+    pub action: Action,
+    pub id: String,
+    pub url: Option<String>,
+}
+
+impl Mapping {
+    /// Check that the key names a field.
+    pub fn validate(&self) -> Result<(), String> {
+        match &self.key {
+            Some(key) if !self.fields.iter().any(|field| &field.name == key) => {
+                Err(format!("--key '{key}' is not one of the --field names"))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// The record for `payload`; fails if the key's value is missing.
+    pub fn record(&self, payload: &Value) -> Result<Record, String> {
+        let fields: Vec<(String, Value)> = self
+            .fields
+            .iter()
+            .filter_map(|field| {
+                key::lookup(payload, &field.path)
+                    .filter(|value| !value.is_null())
+                    .map(|value| (field.name.clone(), value.clone()))
+            })
+            .collect();
+        let key = match &self.key {
+            Some(name) => {
+                let value = fields
+                    .iter()
+                    .find(|(field, _)| field == name)
+                    .map(|(_, value)| value.clone())
+                    .ok_or_else(|| format!("payload has no value for the key field '{name}'"))?;
+                Some((name.clone(), value))
+            }
+            None => None,
+        };
+        let description = self
+            .description
+            .as_deref()
+            .and_then(|path| key::lookup(payload, path))
+            .and_then(|value| match value {
+                Value::String(text) => Some(text.clone()),
+                Value::Null => None,
+                other => Some(other.to_string()),
+            });
+        Ok(Record {
+            fields,
+            key,
+            description,
+        })
+    }
+}
+
+/// A value as text.
+pub fn text(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn payload_values_fill_fields() {
+        let mapping = Mapping {
+            fields: ["Ticket=ticket.id", "Title=ticket.title", "Owner=owner"]
+                .iter()
+                .map(|spec| Field::parse(spec).unwrap_or_else(|e| panic!("{e}")))
+                .collect(),
+            key: Some("Ticket".to_string()),
+            description: Some("ticket.body".to_string()),
+        };
+        assert_eq!(mapping.validate(), Ok(()));
+
+        let payload = json!({"ticket": {"id": 42, "title": "Login fails", "body": "**Steps**"}});
+        assert_eq!(
+            mapping.record(&payload),
+            Ok(Record {
+                fields: vec![
+                    ("Ticket".to_string(), json!(42)),
+                    ("Title".to_string(), json!("Login fails")),
+                ],
+                key: Some(("Ticket".to_string(), json!(42))),
+                description: Some("**Steps**".to_string()),
+            })
+        );
+        assert!(mapping.record(&json!({"owner": "ana"})).is_err());
+
+        let unknown_key = Mapping {
+            key: Some("Id".to_string()),
+            ..mapping
+        };
+        assert!(unknown_key.validate().is_err());
+        assert!(Field::parse("Title").is_err());
+    }
+}
@@ -0,0 +1,265 @@
+//! Rendering the description field as Notion blocks.
+//!
+//! The description is Markdown, of which the sink understands the common
+//! part: `#` to `###` headings, `-` and `1.` list items, `>` quotes, fenced
+//! code blocks and paragraphs, with `**bold**`, `*italic*`, `` `code` ``
+//! and `[links](https://example.com)` inside them. Anything else is kept
+//! as plain text. Notion takes at most 100 blocks per request and 2000
+//! characters per text run, so longer descriptions are cut short and
+//! longer runs split.
+
+use serde_json::{Map, Value, json};
+
+/// Blocks Notion accepts in one request.
+pub const MAX_BLOCKS: usize = 100;
+
+/// Characters Notion accepts in one text run.
+const MAX_TEXT: usize = 2000;
+
+/// Code block languages passed through; others become `plain text`.
+const LANGUAGES: &[&str] = &[
+    "bash",
+    "c",
+    "c++",
+    "css",
+    "diff",
+    "go",
+    "html",
+    "java",
+    "javascript",
+    "json",
+    "markdown",
+    "python",
+    "ruby",
+    "rust",
+    "shell",
+    "sql",
+    "typescript",
+    "yaml",
+];
+
+#[derive(Debug, Default, Clone, Copy)]
+struct Style {
+    bold: bool,
+    italic: bool,
+    code: bool,
+}
+
+/// Text runs for `content`, split to Notion's length limit.
+fn runs(content: &str, style: Style, link: Option<&str>) -> Vec<Value> {
+    let chars: Vec<char> = content.chars().collect();
+    chars
+        .chunks(MAX_TEXT)
+        .map(|chunk| {
+            let mut text = json!({"content": chunk.iter().collect::<String>()});
+            if let Some(url) = link {
+                text["link"] = json!({"url": url});
+            }
+            let mut run = json!({"type": "text", "text": text});
+            let mut annotations = Map::new();
+            for (name, set) in [
+                ("bold", style.bold),
+                ("italic", style.italic),
+                ("code", style.code),
+            ] {
+                if set {
+                    annotations.insert(name.to_string(), Value::Bool(true));
+                }
+            }
+            if !annotations.is_empty() {
+                run["annotations"] = Value::Object(annotations);
+            }
+            run
+        })
+        .collect()
+}
+
+/// Unformatted rich text, as title and text properties take it.
+pub fn plain(text: &str) -> Vec<Value> {
+    runs(text, Style::default(), None)
+}
+
+/// Rich text for one line of Markdown.
+pub fn rich_text(line: &str) -> Vec<Value> {
+    let mut out = Vec::new();
+    let mut style = Style::default();
+    let mut buffer = String::new();
+    let mut rest = line;
+    let flush = |buffer: &mut String, style: Style, out: &mut Vec<Value>| {
+        if !buffer.is_empty() {
+            out.extend(runs(buffer, style, None));
+            buffer.clear();
+        }
+    };
+    while let Some(c) = rest.chars().next() {
+        if let Some(after) = rest.strip_prefix("**")
+            && (style.bold || after.contains("**"))
+        {
+            flush(&mut buffer, style, &mut out);
+            style.bold = !style.bold;
+            rest = after;
+        } else if let Some(after) = rest.strip_prefix('*')
+            && (style.italic || after.contains('*'))
+        {
+            flush(&mut buffer, style, &mut out);
+            style.italic = !style.italic;
+            rest = after;
+        } else if let Some(after) = rest.strip_prefix('`')
+            && let Some(end) = after.find('`')
+        {
+            flush(&mut buffer, style, &mut out);
+            let code = Style {
+                code: true,
+                ..style
+            };
+            out.extend(runs(&after[..end], code, None));
+            rest = &after[end + 1..];
+        } else if let Some(after) = rest.strip_prefix('[')
+            && let Some(middle) = after.find("](")
+            && let Some(end) = after[middle + 2..].find(')')
+        {
+            flush(&mut buffer, style, &mut out);
+            let url = &after[middle + 2..middle + 2 + end];
+            out.extend(runs(&after[..middle], style, Some(url)));
+            rest = &after[middle + 2 + end + 1..];
+        } else {
+            buffer.push(c);
+            rest = &rest[c.len_utf8()..];
+        }
+    }
+    flush(&mut buffer, style, &mut out);
+    out
+}
+
+fn block(kind: &str, rich_text: Vec<Value>) -> Value {
+    json!({"object": "block", "type": kind, kind: {"rich_text": rich_text}})
+}
+
+/// A numbered list item's text, if `line` is one (`1. text`).
+fn numbered(line: &str) -> Option<&str> {
+    let digits = line.find(|c: char| !c.is_ascii_digit())?;
+    (digits > 0).then_some(())?;
+    line[digits..].strip_prefix(". ")
+}
+
+/// Notion blocks for a Markdown document.
+pub fn blocks(markdown: &str) -> Vec<Value> {
+    let mut out = Vec::new();
+    let mut paragraph: Vec<&str> = Vec::new();
+    let end_paragraph = |paragraph: &mut Vec<&str>, out: &mut Vec<Value>| {
+        if !paragraph.is_empty() {
+            out.push(block("paragraph", rich_text(&paragraph.join("\n"))));
+            paragraph.clear();
+        }
+    };
+    let mut lines = markdown.lines();
+    while let Some(line) = lines.next() {
+        let trimmed = line.trim_start();
+        if let Some(language) = trimmed.strip_prefix("```") {
+            end_paragraph(&mut paragraph, &mut out);
+            let code: Vec<&str> = lines
+                .by_ref()
+                .take_while(|line| !line.trim_start().starts_with("```"))
+                .collect();
+            let language = language.trim().to_lowercase();
+            let language = if LANGUAGES.contains(&language.as_str()) {
+                language
+            } else {
+                "plain text".to_string()
+            };
+            out.push(json!({
+                "object": "block",
+                "type": "code",
+                "code": {"rich_text": plain(&code.join("\n")), "language": language},
+            }));
+            continue;
+        }
+        let (kind, text) = if trimmed.is_empty() {
+            end_paragraph(&mut paragraph, &mut out);
+            continue;
+        } else if let Some(text) = trimmed.strip_prefix("### ") {
+            ("heading_3", text)
+        } else if let Some(text) = trimmed.strip_prefix("## ") {
+            ("heading_2", text)
+        } else if let Some(text) = trimmed.strip_prefix("# ") {
+            ("heading_1", text)
+        } else if let Some(text) = trimmed
+            .strip_prefix("- ")
+            .or_else(|| trimmed.strip_prefix("* "))
+        {
+            ("bulleted_list_item", text)
+        } else if let Some(text) = numbered(trimmed) {
+            ("numbered_list_item", text)
+        } else if let Some(text) = trimmed.strip_prefix("> ") {
+            ("quote", text)
+        } else {
+            paragraph.push(line);
+            continue;
+        };
+        end_paragraph(&mut paragraph, &mut out);
+        out.push(block(kind, rich_text(text)));
+    }
+    end_paragraph(&mut paragraph, &mut out);
+    out.truncate(MAX_BLOCKS);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn markdown_becomes_blocks() {
+        let markdown = "## Impact\n\
+                        Checkout was **down** for *12 minutes*,\n\
+                        see [the graph](https://grafana.example.com/d/1).\n\
+                        \n\
+                        - Rolled back `v2.1.0`\n\
+                        1. Add an alert\n\
+                        > Not again\n\
+                        ```Rust\n\
+                        let x = 2 * 3;\n\
+                        ```\n\
+                        2 * 3 = 6";
+        let blocks = blocks(markdown);
+        let kinds: Vec<&str> = blocks.iter().filter_map(|b| b["type"].as_str()).collect();
+        assert_eq!(
+            kinds,
+            [
+                "heading_2",
+                "paragraph",
+                "bulleted_list_item",
+                "numbered_list_item",
+                "quote",
+                "code",
+                "paragraph",
+            ]
+        );
+
+        let paragraph = &blocks[1]["paragraph"]["rich_text"];
+        assert_eq!(paragraph[0]["text"]["content"], "Checkout was ");
+        assert_eq!(paragraph[1]["text"]["content"], "down");
+        assert_eq!(paragraph[1]["annotations"], json!({"bold": true}));
+        assert_eq!(paragraph[3]["text"]["content"], "12 minutes");
+        assert_eq!(paragraph[3]["annotations"], json!({"italic": true}));
+        assert_eq!(paragraph[4]["text"]["content"], ",\nsee ");
+        assert_eq!(
+            paragraph[5]["text"],
+            json!({"content": "the graph", "link": {"url": "https://grafana.example.com/d/1"}})
+        );
+        let item = &blocks[2]["bulleted_list_item"]["rich_text"];
+        assert_eq!(item[1]["annotations"], json!({"code": true}));
+        assert_eq!(blocks[5]["code"]["language"], "rust");
+        assert_eq!(
+            blocks[5]["code"]["rich_text"][0]["text"]["content"],
+            "let x = 2 * 3;"
+        );
+        // A lone asterisk is not emphasis
+        assert_eq!(
+            blocks[6]["paragraph"]["rich_text"],
+            json!([{"type": "text", "text": {"content": "2 * 3 = 6"}}])
+        );
+
+        assert_eq!(plain(&"x".repeat(4500)).len(), 3);
+    }
+}
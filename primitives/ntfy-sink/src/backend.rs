@@ -0,0 +1,144 @@
+//! Request bodies for the two servers.
+//!
+//! - **ntfy** takes JSON published to the server root, with the topic in
+//!   the body, tags rendered as emoji where they name one, and `view`
+//!   action buttons. `--token` is an access token.
+//! - **Gotify** takes JSON at `/message` for the application whose token
+//!   is sent, so routes name application tokens instead of topics.
+//!   Priorities are scaled to Gotify's 0-10, and buttons become Markdown
+//!   links since Gotify clients have none.
+
+use crate::message::Notification;
+use clap::ValueEnum;
+use serde_json::{Value, json};
+
+/// The notification server.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Backend {
+    Ntfy,
+    Gotify,
+}
+
+impl Backend {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Ntfy => "ntfy",
+            Self::Gotify => "gotify",
+        }
+    }
+
+    /// Where notifications are posted on the server at `base`.
+    pub fn endpoint(self, base: &str) -> String {
+        let base = base.trim_end_matches('/');
+        match self {
+            Self::Ntfy => base.to_string(),
+            Self::Gotify => format!("{base}/message"),
+        }
+    }
+
+    /// The request body publishing `notification` to `topic`.
+    pub fn body(self, topic: &str, notification: &Notification) -> Value {
+        match self {
+            Self::Ntfy => ntfy(topic, notification),
+            Self::Gotify => gotify(notification),
+        }
+    }
+}
+
+fn ntfy(topic: &str, notification: &Notification) -> Value {
+    let mut body = json!({
+        "topic": topic,
+        "title": notification.title,
+        "message": notification.message,
+        "priority": notification.priority,
+    });
+    if !notification.tags.is_empty() {
+        body["tags"] = json!(notification.tags);
+    }
+    if let Some(click) = &notification.click {
+        body["click"] = json!(click);
+    }
+    if !notification.actions.is_empty() {
+        let actions: Vec<Value> = notification
+            .actions
+            .iter()
+            .map(|action| json!({"action": "view", "label": action.label, "url": action.url}))
+            .collect();
+        body["actions"] = json!(actions);
+    }
+    body
+}
+
+/// ntfy's 1-5 on Gotify's 0-10 scale.
+fn gotify_priority(priority: u8) -> u8 {
+    match priority {
+        0 | 1 => 0,
+        2 => 2,
+        3 => 5,
+        4 => 8,
+        _ => 10,
+    }
+}
+
+fn gotify(notification: &Notification) -> Value {
+    let mut message = notification.message.clone();
+    let mut extras = json!({});
+    if !notification.actions.is_empty() {
+        let links: Vec<String> = notification
+            .actions
+            .iter()
+            .map(|action| format!("[{}]({})", action.label, action.url))
+            .collect();
+        message = format!("{message}\n\n{}", links.join(" · "));
+        extras["client::display"] = json!({"contentType": "text/markdown"});
+    }
+    if let Some(click) = &notification.click {
+        extras["client::notification"] = json!({"click": {"url": click}});
+    }
+    let mut body = json!({
+        "title": notification.title,
+        "message": message,
+        "priority": gotify_priority(notification.priority),
+    });
+    if extras.as_object().is_some_and(|extras| !extras.is_empty()) {
+        body["extras"] = extras;
+    }
+    body
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::Action;
+
+    #[test]
+    fn gotify_scales_priority_and_links_actions() {
+        let notification = Notification {
+            title: "Disk full".to_string(),
+            message: "/var at 98%".to_string(),
+            priority: 4,
+            tags: vec!["warning".to_string()],
+            click: Some("https://grafana.example.com".to_string()),
+            actions: vec![Action {
+                label: "Runbook".to_string(),
+                url: "https://wiki.example.com/disk".to_string(),
+            }],
+        };
+        assert_eq!(
+            Backend::Gotify.body("ignored", &notification),
+            json!({
+                "title": "Disk full",
+                "message": "/var at 98%\n\n[Runbook](https://wiki.example.com/disk)",
+                "priority": 8,
+                "extras": {
+                    "client::display": {"contentType": "text/markdown"},
+                    "client::notification": {"click": {"url": "https://grafana.example.com"}},
+                },
+            })
+        );
+        assert_eq!(
+            Backend::Gotify.endpoint("https://gotify.example.com/"),
+            "https://gotify.example.com/message"
+        );
+    }
+}
@@ -0,0 +1,347 @@
+//! Ntfy Sink - Phone Notifications through ntfy or Gotify
+//!
+//! Publishes each event to an [ntfy] topic (ntfy.sh or self-hosted) or a
+//! [Gotify] application, so it shows up on a phone without any app of your
+//! own. Title, text, priority, emoji tags, click URL and action buttons
+//! come from payload fields (see [`message`]); message types are routed to
+//! topics by pattern (see [`route`]).
+//!
+//! ```json
+//! {"title": "Disk full", "message": "/var on web1 is at 98%", "priority": "high",
+//!  "tags": ["web1"], "url": "https://grafana.example.com/d/disk",
+//!  "actions": [{"label": "Runbook", "url": "https://wiki.example.com/disk"}]}
+//! ```
+//!
+//! The topic, routes and emoji map can be changed through `--config` on
+//! SIGHUP.
+//!
+//! # Examples
+//!
+//! ```bash
+//! ntfy-sink -s alert.raised -s backup.failed --topic homelab \
+//!   --route "alert.*=homelab-alerts" --emoji critical=rotating_light
+//! ntfy-sink -s deploy.completed --backend gotify \
+//!   --url https://gotify.example.com --topic AbCdEf123
+//! ```
+//!
+//! [ntfy]: https://ntfy.sh
+//! [Gotify]: https://gotify.net
+
+pub mod backend;
+pub mod message;
+pub mod route;
+
+use backend::Backend;
+use clap::Parser;
+use emergent_client::EmergentMessage;
+use message::{Fields, Notification};
+use primitive_common::capabilities::VERSION;
+use primitive_common::errors::{ErrorCategory, HandlerError};
+use primitive_common::reload::HotConfig;
+use primitive_common::{SinkArgs, SinkConfig, SinkContext, SinkHandler, run_sink};
+use reqwest::Client;
+use route::{Route, parse_emoji, parse_route};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+/// Ntfy Sink — phone notifications through ntfy or Gotify.
+#[derive(Parser, Debug)]
+#[command(name = "ntfy_sink", version = VERSION)]
+#[command(about = "Publish events as ntfy or Gotify notifications")]
+struct Args {
+    /// Message types to subscribe to.
+    #[arg(short, long = "subscribe", required = true)]
+    subscribe: Vec<String>,
+
+    /// Notification server.
+    #[arg(
+        short,
+        long,
+        env = "NTFY_SINK_BACKEND",
+        value_enum,
+        default_value = "ntfy"
+    )]
+    backend: Backend,
+
+    /// Server base URL.
+    #[arg(short, long, env = "NTFY_SINK_URL", default_value = "https://ntfy.sh")]
+    url: String,
+
+    /// Topic (Gotify: application token) for message types no `--route` matches.
+    #[arg(long, env = "NTFY_SINK_TOPIC")]
+    topic: Option<String>,
+
+    /// Route message types to a topic as `pattern=topic` (`alert.*` matches a namespace); first match wins, repeatable.
+    #[arg(long = "route", env = "NTFY_SINK_ROUTES", value_delimiter = ',', value_parser = parse_route)]
+    routes: Vec<Route>,
+
+    /// ntfy access token.
+    #[arg(long, env = "NTFY_SINK_TOKEN")]
+    token: Option<String>,
+
+    /// Payload field holding the priority.
+    #[arg(long, env = "NTFY_SINK_PRIORITY_FIELD", default_value = "priority")]
+    priority_field: String,
+
+    /// Payload field holding the URL opened on tap.
+    #[arg(long, env = "NTFY_SINK_CLICK_FIELD", default_value = "url")]
+    click_field: String,
+
+    /// Emoji tag for a priority field value as `value=shortcode`; repeatable.
+    #[arg(long = "emoji", env = "NTFY_SINK_EMOJI", value_delimiter = ',', value_parser = parse_emoji)]
+    emoji: Vec<(String, String)>,
+
+    /// Per-request timeout in milliseconds.
+    #[arg(short, long, env = "NTFY_SINK_TIMEOUT", default_value = "10000")]
+    timeout: u64,
+
+    #[command(flatten)]
+    sink: SinkArgs,
+}
+
+/// Settings that can be swapped on SIGHUP.
+#[derive(Debug, Serialize, Deserialize)]
+struct Settings {
+    topic: Option<String>,
+    routes: Vec<Route>,
+    emoji: BTreeMap<String, String>,
+}
+
+impl Settings {
+    fn route(&self, message_type: &str) -> Option<(&str, Option<&Route>)> {
+        match self.routes.iter().find(|route| route.matches(message_type)) {
+            Some(route) => Some((&route.topic, Some(route))),
+            None => self.topic.as_deref().map(|topic| (topic, None)),
+        }
+    }
+}
+
+/// Posts each event to the topic its message type routes to.
+struct NtfySink {
+    settings: HotConfig<Settings>,
+    backend: Backend,
+    endpoint: String,
+    token: Option<String>,
+    priority_field: String,
+    click_field: String,
+    client: Client,
+}
+
+impl SinkHandler for NtfySink {
+    async fn handle(
+        &self,
+        msg: &EmergentMessage,
+        ctx: &SinkContext<'_>,
+    ) -> Result<(), HandlerError> {
+        let settings = self.settings.current();
+        let message_type = msg.message_type.as_str();
+        let Some((topic, route)) = settings.route(message_type) else {
+            return Err(HandlerError::new(
+                ErrorCategory::Internal,
+                format!("no route for {message_type} and no --topic"),
+            ));
+        };
+        let fields = Fields {
+            priority: &self.priority_field,
+            click: &self.click_field,
+            emoji: &settings.emoji,
+        };
+        let mut notification = Notification::build(message_type, ctx.payload(), &fields);
+        if let Some(route) = route {
+            if let Some(priority) = route.priority {
+                notification.priority = priority.clamp(1, 5);
+            }
+            notification.tags.extend(route.tags.iter().cloned());
+        }
+
+        if ctx.is_dry_run() {
+            // Gotify application tokens are credentials
+            let topic = (self.backend == Backend::Ntfy).then_some(topic);
+            let detail = json!({
+                "backend": self.backend.as_str(),
+                "topic": topic,
+                "title": notification.title,
+                "priority": notification.priority,
+                "tags": notification.tags,
+            });
+            ctx.would_have("notify", detail).await;
+            return Ok(());
+        }
+
+        let mut request = self
+            .client
+            .post(&self.endpoint)
+            .json(&self.backend.body(topic, &notification));
+        request = match self.backend {
+            Backend::Ntfy => match &self.token {
+                Some(token) => request.bearer_auth(token),
+                None => request,
+            },
+            Backend::Gotify => request.header("X-Gotify-Key", topic),
+        };
+        let target = self.backend.as_str();
+        let response = request.send().await.map_err(|e| {
+            if e.is_timeout() {
+                HandlerError::new(ErrorCategory::Timeout, format!("{target}: timed out"))
+            } else {
+                HandlerError::new(ErrorCategory::Request, format!("{target}: {e}"))
+            }
+        })?;
+        let status = response.status();
+        if status.is_server_error() || status.as_u16() == 429 {
+            return Err(HandlerError::new(
+                ErrorCategory::Request,
+                format!("{target}: HTTP {status}"),
+            ));
+        }
+        if !status.is_success() {
+            let detail = response.text().await.unwrap_or_default();
+            return Err(HandlerError::new(
+                ErrorCategory::Rejected,
+                format!("{target}: HTTP {status} {}", detail.trim()),
+            ));
+        }
+        Ok(())
+    }
+
+    fn reload(&self) -> Result<Vec<String>, String> {
+        self.settings.reload()
+    }
+}
+
+/// Run the primitive with `args` as its command line (the first item is
+/// the program name, as with `std::env::args_os`).
+pub async fn run<I, T>(args: I) -> Result<(), Box<dyn std::error::Error>>
+where
+    I: IntoIterator<Item = T>,
+    T: Into<std::ffi::OsString> + Clone,
+{
+    let args = Args::parse_from(args);
+
+    let config = SinkConfig {
+        name: "ntfy_sink",
+        subscribe: &args.subscribe,
+        would_have_as: "ntfy.would_have",
+        dead_letter_as: "ntfy.dead_letter",
+        settings: &args,
+    };
+    let defaults = Settings {
+        topic: args.topic.clone(),
+        routes: args.routes.clone(),
+        emoji: args.emoji.iter().cloned().collect(),
+    };
+    let settings = match HotConfig::load(&args.sink.reload, defaults) {
+        Ok(settings) => settings,
+        Err(e) => {
+            eprintln!("Error: invalid config: {e}");
+            std::process::exit(1);
+        }
+    };
+    let handler = NtfySink {
+        settings,
+        backend: args.backend,
+        endpoint: args.backend.endpoint(&args.url),
+        token: args.token.clone(),
+        priority_field: args.priority_field.clone(),
+        click_field: args.click_field.clone(),
+        client: Client::builder()
+            .timeout(Duration::from_millis(args.timeout))
+            .build()?,
+    };
+
+    run_sink(config, &args.sink, handler).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderMap;
+    use axum::{Json, Router, routing::post};
+    use emergent_testkit::{SinkFixture, fixtures, spawn_sink};
+    use primitive_common::reload::ReloadArgs;
+    use serde_json::Value;
+    use tokio::sync::mpsc;
+
+    #[tokio::test]
+    async fn events_are_published_to_their_routed_topic() {
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let app = Router::new().route(
+            "/",
+            post(
+                move |headers: HeaderMap, Json(body): Json<Value>| async move {
+                    let auth = headers
+                        .get("authorization")
+                        .and_then(|value| value.to_str().ok())
+                        .map(str::to_string);
+                    let _ = tx.send((auth, body));
+                    Json(json!({"id": "abc"}))
+                },
+            ),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap_or_else(|e| panic!("bind: {e}"));
+        let addr = listener
+            .local_addr()
+            .unwrap_or_else(|e| panic!("local_addr: {e}"));
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let mut alerts = parse_route("alert.*=homelab-alerts").unwrap_or_else(|e| panic!("{e}"));
+        alerts.tags = vec!["alert".to_string()];
+        let defaults = Settings {
+            topic: Some("homelab".to_string()),
+            routes: vec![alerts],
+            emoji: BTreeMap::from([("high".to_string(), "warning".to_string())]),
+        };
+        let handler = NtfySink {
+            settings: HotConfig::load(&ReloadArgs::default(), defaults)
+                .unwrap_or_else(|e| panic!("settings: {e}")),
+            backend: Backend::Ntfy,
+            endpoint: Backend::Ntfy.endpoint(&format!("http://{addr}/")),
+            token: Some("tk_secret".to_string()),
+            priority_field: "priority".to_string(),
+            click_field: "url".to_string(),
+            client: Client::new(),
+        };
+        let (mut engine, run) = spawn_sink(
+            SinkFixture::new("ntfy_sink", "ntfy"),
+            SinkArgs::default(),
+            handler,
+        );
+
+        engine
+            .inject_message(fixtures::message(
+                "alert.raised",
+                json!({"title": "Disk full", "message": "/var at 98%", "priority": "high"}),
+            ))
+            .await;
+        let (auth, body) = rx.recv().await.unwrap_or_else(|| panic!("no request"));
+        assert_eq!(auth.as_deref(), Some("Bearer tk_secret"));
+        assert_eq!(
+            body,
+            json!({
+                "topic": "homelab-alerts",
+                "title": "Disk full",
+                "message": "/var at 98%",
+                "priority": 4,
+                "tags": ["warning", "alert"],
+            })
+        );
+
+        engine
+            .inject_message(fixtures::message(
+                "backup.completed",
+                json!({"message": "nightly backup done"}),
+            ))
+            .await;
+        let (_, body) = rx.recv().await.unwrap_or_else(|| panic!("no request"));
+        assert_eq!(body["topic"], "homelab");
+        assert_eq!(body["title"], "backup.completed");
+        assert_eq!(body["priority"], 3);
+
+        engine.close();
+        assert_eq!(run.await.ok(), Some(Ok(())));
+    }
+}
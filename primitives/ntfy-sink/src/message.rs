@@ -0,0 +1,177 @@
+//! Notifications built from payload fields.
+//!
+//! | Field | Use |
+//! |-------|-----|
+//! | `title` | Title (default: the message type) |
+//! | `message`, `body` | Text (default: the payload as JSON) |
+//! | `--priority-field` (`priority`) | 1-5, or a name: `min`/`debug`, `low`/`info`, `default`/`warning`, `high`/`error`, `max`/`urgent`/`critical` |
+//! | `tags` | Tags or emoji shortcodes, a list or comma-separated |
+//! | `--click-field` (`url`) | Opened when the notification is tapped |
+//! | `actions` | Buttons: `[{"label": "Open run", "url": "https://..."}]` |
+//!
+//! `--emoji` adds a tag for the priority field's value, e.g.
+//! `critical=rotating_light`.
+
+use serde_json::Value;
+use std::collections::BTreeMap;
+
+/// The ntfy priority used when a payload gives none.
+pub const DEFAULT_PRIORITY: u8 = 3;
+
+/// A button opening a URL.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Action {
+    pub label: String,
+    pub url: String,
+}
+
+/// One notification, backend-neutral.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Notification {
+    pub title: String,
+    pub message: String,
+    /// ntfy's 1 (min) to 5 (max).
+    pub priority: u8,
+    pub tags: Vec<String>,
+    pub click: Option<String>,
+    pub actions: Vec<Action>,
+}
+
+/// Which payload fields carry the priority and click URL.
+#[derive(Debug, Clone)]
+pub struct Fields<'a> {
+    pub priority: &'a str,
+    pub click: &'a str,
+    /// Priority field value to emoji shortcode.
+    pub emoji: &'a BTreeMap<String, String>,
+}
+
+/// Priority for a number or level name.
+pub fn priority(value: &Value) -> Option<u8> {
+    if let Some(n) = value.as_u64() {
+        return Some(n.clamp(1, 5) as u8);
+    }
+    let level = value.as_str()?.to_ascii_lowercase();
+    if let Ok(n) = level.parse::<u8>() {
+        return Some(n.clamp(1, 5));
+    }
+    Some(match level.as_str() {
+        "min" | "debug" | "trace" => 1,
+        "low" | "info" => 2,
+        "default" | "normal" | "notice" | "warning" | "warn" => 3,
+        "high" | "error" => 4,
+        "max" | "urgent" | "critical" | "emergency" | "fatal" => 5,
+        _ => return None,
+    })
+}
+
+fn strings(value: &Value) -> Vec<String> {
+    match value {
+        Value::String(s) => s
+            .split(',')
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+            .map(str::to_string)
+            .collect(),
+        Value::Array(items) => items
+            .iter()
+            .filter_map(Value::as_str)
+            .map(str::to_string)
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+impl Notification {
+    /// Build the notification for a message of `message_type`.
+    pub fn build(message_type: &str, payload: &Value, fields: &Fields<'_>) -> Self {
+        let message = payload["message"]
+            .as_str()
+            .or_else(|| payload["body"].as_str())
+            .map(str::to_string)
+            .unwrap_or_else(|| payload.to_string());
+        let level = &payload[fields.priority];
+        let mut tags = Vec::new();
+        let level_name = match level {
+            Value::String(s) => Some(s.clone()),
+            Value::Number(n) => Some(n.to_string()),
+            _ => None,
+        };
+        if let Some(emoji) = level_name.and_then(|name| fields.emoji.get(&name)) {
+            tags.push(emoji.clone());
+        }
+        tags.extend(strings(&payload["tags"]));
+        let actions = payload["actions"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|action| {
+                Some(Action {
+                    label: action["label"].as_str()?.to_string(),
+                    url: action["url"].as_str()?.to_string(),
+                })
+            })
+            .collect();
+        Self {
+            title: payload["title"]
+                .as_str()
+                .unwrap_or(message_type)
+                .to_string(),
+            message,
+            priority: priority(level).unwrap_or(DEFAULT_PRIORITY),
+            tags,
+            click: payload[fields.click].as_str().map(str::to_string),
+            actions,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn payload_fields_map_to_priority_tags_and_buttons() {
+        let emoji = BTreeMap::from([("critical".to_string(), "rotating_light".to_string())]);
+        let fields = Fields {
+            priority: "severity",
+            click: "url",
+            emoji: &emoji,
+        };
+        let payload = json!({
+            "title": "Disk full",
+            "body": "/var on web1 is at 98%",
+            "severity": "critical",
+            "tags": "web1,disk",
+            "url": "https://grafana.example.com/d/disk",
+            "actions": [{"label": "Runbook", "url": "https://wiki.example.com/disk"}, {"label": "no url"}],
+        });
+        assert_eq!(
+            Notification::build("alert.raised", &payload, &fields),
+            Notification {
+                title: "Disk full".to_string(),
+                message: "/var on web1 is at 98%".to_string(),
+                priority: 5,
+                tags: vec![
+                    "rotating_light".to_string(),
+                    "web1".to_string(),
+                    "disk".to_string()
+                ],
+                click: Some("https://grafana.example.com/d/disk".to_string()),
+                actions: vec![Action {
+                    label: "Runbook".to_string(),
+                    url: "https://wiki.example.com/disk".to_string(),
+                }],
+            }
+        );
+
+        let bare = Notification::build("backup.completed", &json!({"files": 3}), &fields);
+        assert_eq!(bare.title, "backup.completed");
+        assert_eq!(bare.message, r#"{"files":3}"#);
+        assert_eq!(bare.priority, DEFAULT_PRIORITY);
+        assert_eq!(priority(&json!(9)), Some(5));
+        assert_eq!(priority(&json!("Info")), Some(2));
+        assert_eq!(priority(&json!("sometimes")), None);
+    }
+}
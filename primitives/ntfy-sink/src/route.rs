@@ -0,0 +1,83 @@
+//! Routing message types to topics.
+//!
+//! Routes are tried in order and the first whose pattern matches the
+//! message type wins; `alert.*` matches a namespace and `*` matches
+//! everything. Types no route matches go to `--topic`. A route may also
+//! fix the priority and add tags for everything it carries.
+
+use serde::{Deserialize, Serialize};
+
+/// One entry of the routing table.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Route {
+    /// Message type pattern.
+    #[serde(rename = "match")]
+    pub pattern: String,
+
+    /// ntfy topic, or Gotify application token.
+    pub topic: String,
+
+    /// Priority (1-5) overriding the payload's.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub priority: Option<u8>,
+
+    /// Tags added to every notification.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+}
+
+impl Route {
+    /// Whether this route handles `message_type`.
+    pub fn matches(&self, message_type: &str) -> bool {
+        match self.pattern.strip_suffix('*') {
+            Some(prefix) => message_type.starts_with(prefix),
+            None => self.pattern == message_type,
+        }
+    }
+}
+
+/// Parse a `--route` value: `pattern=topic`.
+pub fn parse_route(s: &str) -> Result<Route, String> {
+    let (pattern, topic) = s
+        .split_once('=')
+        .ok_or_else(|| format!("expected PATTERN=TOPIC, got '{s}'"))?;
+    let (pattern, topic) = (pattern.trim(), topic.trim());
+    if pattern.is_empty() || topic.is_empty() {
+        return Err(format!("expected PATTERN=TOPIC, got '{s}'"));
+    }
+    Ok(Route {
+        pattern: pattern.to_string(),
+        topic: topic.to_string(),
+        priority: None,
+        tags: Vec::new(),
+    })
+}
+
+/// Parse an `--emoji` value: `value=shortcode`.
+pub fn parse_emoji(s: &str) -> Result<(String, String), String> {
+    match s.split_once('=') {
+        Some((value, emoji)) if !value.trim().is_empty() && !emoji.trim().is_empty() => {
+            Ok((value.trim().to_string(), emoji.trim().to_string()))
+        }
+        _ => Err(format!("expected VALUE=SHORTCODE, got '{s}'")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn namespace_patterns_match_by_prefix() {
+        let route = parse_route("alert.*=ops-alerts").unwrap_or_else(|e| panic!("{e}"));
+        assert_eq!(route.topic, "ops-alerts");
+        assert!(route.matches("alert.raised"));
+        assert!(!route.matches("backup.completed"));
+        assert!(parse_route("alert.*").is_err());
+        assert_eq!(
+            parse_emoji("critical = rotating_light"),
+            Ok(("critical".to_string(), "rotating_light".to_string()))
+        );
+    }
+}
@@ -0,0 +1,444 @@
+//! Package Watch Source - Registry Release Events
+//!
+//! A Source that polls package registries for configured packages and
+//! publishes `package.released` when a new version appears and
+//! `package.yanked` when a known version is yanked, carrying the registry,
+//! package, version, yanked status, publish time, changelog URL and
+//! registry page. Packages are given as `registry:name`, with crates.io
+//! (`crates`), npm, PyPI (`pypi`) and Docker Hub tags (`docker`) supported
+//! (see [`registry`]).
+//!
+//! The first poll of a package records its versions without publishing
+//! them; with `--state-file` known versions survive restarts, so releases
+//! made while the source was down are still reported. A package whose
+//! events could not all be published is not recorded, so they are tried
+//! again on the next poll.
+//!
+//! `--emit-type-template` can also use `{registry}`.
+//!
+//! Sources are SILENT - they only produce domain messages.
+//! All lifecycle events are published by the engine.
+//!
+//! # Usage
+//!
+//! ```bash
+//! package-watch-source --package crates:tokio --package npm:@types/node \
+//!   --package pypi:requests --package docker:nginx \
+//!   --interval 900000 --state-file /var/lib/emergent/packages.json
+//! ```
+//!
+//! On SIGTERM a poll in progress gets `--drain-timeout` milliseconds to
+//! finish.
+
+pub mod registry;
+pub mod watch;
+
+use clap::Parser;
+use emergent_client::EmergentSource;
+use primitive_common::capabilities::{self, CapabilityArgs, Role, VERSION, capabilities_message};
+use primitive_common::doctor::{Report, check_writable_dir};
+use primitive_common::source::{Outlet, SourceArgs};
+use registry::{Package, Registries, RegistryArgs};
+use reqwest::Client;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::signal::unix::{SignalKind, signal};
+use watch::{Change, EVENT_TYPES, Known};
+
+/// Package registry watcher that emits package.* events.
+#[derive(Parser, Debug, Clone)]
+#[command(name = "package-watch-source", version = VERSION)]
+#[command(about = "Polls package registries and emits release events")]
+struct Args {
+    /// Package to watch as registry:name, where registry is crates, npm,
+    /// pypi or docker (repeatable or comma-separated).
+    #[arg(
+        long = "package",
+        env = "PACKAGE_WATCH_PACKAGES",
+        value_delimiter = ',',
+        value_parser = Package::parse,
+        required = true
+    )]
+    packages: Vec<Package>,
+
+    /// Milliseconds between polls.
+    #[arg(short, long, env = "PACKAGE_WATCH_INTERVAL", default_value = "900000")]
+    interval: u64,
+
+    /// File to keep known versions in across restarts.
+    #[arg(long, env = "PACKAGE_WATCH_STATE_FILE")]
+    state_file: Option<PathBuf>,
+
+    #[command(flatten)]
+    registries: RegistryArgs,
+
+    /// Verify external dependencies and exit without connecting to the engine.
+    #[arg(long)]
+    self_test: bool,
+
+    #[command(flatten)]
+    source: SourceArgs,
+
+    #[command(flatten)]
+    capabilities: CapabilityArgs,
+}
+
+/// Variables available to `--emit-type-template`.
+const TEMPLATE_VARIABLES: &[&str] = &["source", "registry"];
+
+/// The state saved in `path`, if any.
+fn load_state(path: &Path) -> Result<Option<Known>, String> {
+    match std::fs::read(path) {
+        Ok(bytes) => serde_json::from_slice(&bytes)
+            .map(Some)
+            .map_err(|e| format!("{}: {e}", path.display())),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(format!("{}: {e}", path.display())),
+    }
+}
+
+/// Save `known` to `path`, replacing it atomically.
+fn save_state(path: &Path, known: &Known) -> Result<(), String> {
+    let temp = path.with_extension("tmp");
+    let bytes = serde_json::to_vec(known).map_err(|e| e.to_string())?;
+    std::fs::write(&temp, bytes)
+        .and_then(|()| std::fs::rename(&temp, path))
+        .map_err(|e| format!("{}: {e}", path.display()))
+}
+
+/// Runs `--self-test` checks and exits.
+async fn self_test(args: &Args, name: &str) -> ! {
+    let mut report = Report::new(name);
+    let registries = Registries::new(Client::new(), &args.registries);
+    for package in &args.packages {
+        let releases = registries
+            .releases(package)
+            .await
+            .map(|releases| format!("{} versions", releases.len()));
+        report.check(&package.key(), releases);
+    }
+    if let Some(path) = &args.state_file {
+        let dir = path.parent().filter(|p| !p.as_os_str().is_empty());
+        report.check(
+            "state_file",
+            check_writable_dir(dir.unwrap_or(Path::new("."))),
+        );
+    }
+    args.source.self_test(&mut report, TEMPLATE_VARIABLES);
+    report.finish()
+}
+
+/// List one package and publish what changed since it was last listed,
+/// recording the listing if everything was published. Returns whether
+/// `known` changed.
+async fn poll_package(
+    outlet: &Outlet,
+    registries: &Registries,
+    package: &Package,
+    known: &mut Known,
+) -> bool {
+    let releases = match registries.releases(package).await {
+        Ok(releases) => releases,
+        Err(e) => {
+            eprintln!("Polling {} failed: {e}", package.key());
+            return false;
+        }
+    };
+    if !known.knows(package) {
+        eprintln!(
+            "Baseline recorded for {}: {} versions",
+            package.key(),
+            releases.len()
+        );
+        known.record(package, &releases);
+        return true;
+    }
+    let changes = known.changes(package, &releases);
+    if changes.is_empty() {
+        return false;
+    }
+    let vars = [("registry", package.registry.as_str())];
+    for Change {
+        event_type,
+        payload,
+    } in changes
+    {
+        // The outlet logs and reports what it cannot deliver
+        if outlet.publish(event_type, &vars, payload).await.is_err() {
+            return false;
+        }
+    }
+    known.record(package, &releases);
+    true
+}
+
+/// Poll until SIGTERM.
+async fn run_poller(
+    args: &Args,
+    outlet: &Outlet,
+    mut known: Known,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let registries = Registries::new(Client::new(), &args.registries);
+    let mut sigterm = signal(SignalKind::terminate())?;
+    let mut interval = tokio::time::interval(Duration::from_millis(args.interval));
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+    loop {
+        tokio::select! {
+            _ = sigterm.recv() => break,
+
+            _ = interval.tick() => {
+                let poll = async {
+                    for package in &args.packages {
+                        let changed = poll_package(outlet, &registries, package, &mut known).await;
+                        if changed && let Some(path) = &args.state_file
+                            && let Err(e) = save_state(path, &known)
+                        {
+                            eprintln!("Failed to save state: {e}");
+                        }
+                    }
+                };
+                tokio::pin!(poll);
+                tokio::select! {
+                    () = &mut poll => {}
+                    _ = sigterm.recv() => {
+                        args.source.drain.drain("poll in progress", &mut poll).await;
+                        break;
+                    }
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Run the primitive with `args` as its command line (the first item is
+/// the program name, as with `std::env::args_os`).
+pub async fn run<I, T>(args: I) -> Result<(), Box<dyn std::error::Error>>
+where
+    I: IntoIterator<Item = T>,
+    T: Into<std::ffi::OsString> + Clone,
+{
+    let args = Args::parse_from(args);
+
+    // Get the source name from environment (set by engine) or use default
+    let name =
+        std::env::var("EMERGENT_NAME").unwrap_or_else(|_| "package-watch-source".to_string());
+
+    if args.self_test {
+        self_test(&args, &name).await;
+    }
+    if let Err(e) = args.source.validate(TEMPLATE_VARIABLES) {
+        eprintln!("Error: {e}");
+        std::process::exit(1);
+    }
+    let known = match args.state_file.as_deref().map(load_state).transpose() {
+        Ok(state) => state.flatten().unwrap_or_default(),
+        Err(e) => {
+            eprintln!("Error: cannot read state file {e}");
+            std::process::exit(1);
+        }
+    };
+
+    let produces = args.source.produces(&EVENT_TYPES);
+    let produces: Vec<&str> = produces.iter().map(String::as_str).collect();
+    let descriptor = args
+        .capabilities
+        .describe(&name, Role::Source, &[], &produces, &args);
+    if args.capabilities.capabilities {
+        capabilities::print(&descriptor);
+    }
+
+    // Connect to the Emergent engine (silently - lifecycle events come from engine)
+    let source = match EmergentSource::connect(&name).await {
+        Ok(s) => s,
+        Err(e) => {
+            eprintln!("Failed to connect to Emergent engine: {e}");
+            std::process::exit(1);
+        }
+    };
+    let outlet = Arc::new(Outlet::new(source, &name, &args.source)?);
+    if args.capabilities.announce {
+        outlet.announce(capabilities_message(&descriptor)).await;
+    }
+    tokio::spawn(Arc::clone(&outlet).drain_every(args.source.spool.retry_interval()));
+
+    run_poller(&args, &outlet, known).await?;
+    outlet.disconnect().await;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{Json, Router, routing::get};
+    use emergent_testkit::{MockEngine, fixtures};
+    use serde_json::json;
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    /// Serve a fake crates.io listing `serde` 1.0.0 and, once `released`
+    /// is set, 1.0.1.
+    async fn server(released: Arc<AtomicBool>) -> String {
+        let app = Router::new().route(
+            "/api/v1/crates/serde",
+            get(move || {
+                let released = Arc::clone(&released);
+                async move {
+                    let mut versions = vec![json!({
+                        "num": "1.0.0", "yanked": false, "created_at": "2024-01-01T00:00:00Z"
+                    })];
+                    if released.load(Ordering::SeqCst) {
+                        versions.insert(0, json!({
+                            "num": "1.0.1", "yanked": false, "created_at": "2024-02-01T00:00:00Z"
+                        }));
+                    }
+                    Json(json!({
+                        "crate": {"repository": "https://github.com/serde-rs/serde"},
+                        "versions": versions,
+                    }))
+                }
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap_or_else(|e| panic!("bind: {e}"));
+        let addr = listener
+            .local_addr()
+            .unwrap_or_else(|e| panic!("local_addr: {e}"));
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        format!("http://{addr}")
+    }
+
+    #[tokio::test]
+    async fn only_versions_after_the_baseline_are_changes() {
+        let released = Arc::new(AtomicBool::new(false));
+        let base = server(Arc::clone(&released)).await;
+        let args = Args::parse_from([
+            "package-watch-source",
+            "--package",
+            "crates:serde",
+            "--crates-url",
+            &base,
+        ]);
+        let registries = Registries::new(Client::new(), &args.registries);
+        let serde = &args.packages[0];
+        let mut known = Known::default();
+
+        let releases = registries
+            .releases(serde)
+            .await
+            .unwrap_or_else(|e| panic!("{e}"));
+        known.record(serde, &releases);
+        released.store(true, Ordering::SeqCst);
+        let releases = registries
+            .releases(serde)
+            .await
+            .unwrap_or_else(|e| panic!("{e}"));
+        let changes = known.changes(serde, &releases);
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].event_type, "package.released");
+        assert_eq!(changes[0].payload["version"], "1.0.1");
+        assert_eq!(
+            changes[0].payload["changelog_url"],
+            "https://github.com/serde-rs/serde/releases"
+        );
+    }
+
+    /// Connect to the engine on `socket` as the source would.
+    async fn connect(socket: &Path, args: &Args) -> Outlet {
+        let source = EmergentSource::connect_to("package-watch-source", socket)
+            .await
+            .unwrap_or_else(|e| panic!("connect: {e}"));
+        Outlet::new(source, "package-watch-source", &args.source)
+            .unwrap_or_else(|e| panic!("open spool: {e}"))
+    }
+
+    /// Poll the first package for a baseline, shut the engine on `socket`
+    /// down, set `released` and poll again; returns whether that changed
+    /// `known`.
+    async fn poll_release_while_down(
+        socket: &Path,
+        args: &Args,
+        released: &AtomicBool,
+        known: &mut Known,
+    ) -> bool {
+        let registries = Registries::new(Client::new(), &args.registries);
+        let mut engine = MockEngine::serve(socket);
+        let outlet = connect(socket, args).await;
+        assert!(poll_package(&outlet, &registries, &args.packages[0], known).await);
+        engine.shut_down().await;
+        released.store(true, Ordering::SeqCst);
+        poll_package(&outlet, &registries, &args.packages[0], known).await
+    }
+
+    #[tokio::test]
+    async fn releases_survive_the_engine_being_down() {
+        let dir = fixtures::TempDir::new("package-watch-source-spool");
+        let socket = dir.path().join("engine.sock");
+        let spool = dir.path().join("spool");
+        let released = Arc::new(AtomicBool::new(false));
+        let base = server(Arc::clone(&released)).await;
+        let args = Args::parse_from([
+            "package-watch-source",
+            "--package",
+            "crates:serde",
+            "--crates-url",
+            &base,
+            "--spool-dir",
+            spool.to_str().unwrap_or_default(),
+            "--emit-type-template",
+            "{registry}.{type}",
+        ]);
+
+        let mut known = Known::default();
+        assert!(poll_release_while_down(&socket, &args, &released, &mut known).await);
+
+        let mut engine = MockEngine::serve(&socket);
+        connect(&socket, &args).await.drain_spool().await;
+        let published = engine.expect_published("crates.package.released").await;
+        assert_eq!(published.payload()["version"], "1.0.1");
+        engine.shut_down().await;
+    }
+
+    #[tokio::test]
+    async fn unpublished_releases_are_reported_again() {
+        let dir = fixtures::TempDir::new("package-watch-source-down");
+        let socket = dir.path().join("engine.sock");
+        let released = Arc::new(AtomicBool::new(false));
+        let base = server(Arc::clone(&released)).await;
+        let args = Args::parse_from([
+            "package-watch-source",
+            "--package",
+            "crates:serde",
+            "--crates-url",
+            &base,
+        ]);
+
+        let mut known = Known::default();
+        assert!(!poll_release_while_down(&socket, &args, &released, &mut known).await);
+        let releases = Registries::new(Client::new(), &args.registries)
+            .releases(&args.packages[0])
+            .await
+            .unwrap_or_else(|e| panic!("{e}"));
+        let changes = known.changes(&args.packages[0], &releases);
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].payload["version"], "1.0.1");
+    }
+
+    #[test]
+    fn state_survives_a_round_trip() {
+        let dir = std::env::temp_dir().join(format!("package-watch-source-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap_or_else(|e| panic!("mkdir: {e}"));
+        let path = dir.join("state.json");
+        assert_eq!(load_state(&path), Ok(None));
+
+        let mut known = Known::default();
+        let package = Package::parse("pypi:requests").unwrap_or_else(|e| panic!("{e}"));
+        known.record(&package, &[]);
+        save_state(&path, &known).unwrap_or_else(|e| panic!("save: {e}"));
+        assert_eq!(load_state(&path), Ok(Some(known)));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,376 @@
+//! Package registries and the releases they list.
+//!
+//! | Registry | Endpoint | Yanked | Changelog |
+//! |----------|----------|--------|-----------|
+//! | `crates` | `/api/v1/crates/<name>` | `yanked` | repository's releases page |
+//! | `npm` | `/<name>` | `deprecated` | repository's releases page |
+//! | `pypi` | `/pypi/<name>/json` | every file yanked | `project_urls` changelog entry, else repository's releases page |
+//! | `docker` | `/v2/repositories/<namespace>/<name>/tags` | never | none |
+//!
+//! npm has no yanking, so a deprecated version counts as yanked. Docker
+//! Hub tags are versions; the 100 most recently pushed are read, and
+//! images without a namespace are `library/` ones. A "releases page" is
+//! only known for repositories on GitHub, GitLab or Codeberg.
+
+use clap::{Args, ValueEnum};
+use reqwest::Client;
+use serde_json::Value;
+use std::time::Duration;
+
+/// Registry API roots, overridable for mirrors.
+#[derive(Args, Debug, Clone)]
+pub struct RegistryArgs {
+    /// crates.io API root.
+    #[arg(
+        long,
+        env = "PACKAGE_WATCH_CRATES_URL",
+        default_value = "https://crates.io"
+    )]
+    pub crates_url: String,
+
+    /// npm registry root.
+    #[arg(
+        long,
+        env = "PACKAGE_WATCH_NPM_URL",
+        default_value = "https://registry.npmjs.org"
+    )]
+    pub npm_url: String,
+
+    /// PyPI root (JSON API).
+    #[arg(
+        long,
+        env = "PACKAGE_WATCH_PYPI_URL",
+        default_value = "https://pypi.org"
+    )]
+    pub pypi_url: String,
+
+    /// Docker Hub API root.
+    #[arg(
+        long,
+        env = "PACKAGE_WATCH_DOCKER_URL",
+        default_value = "https://hub.docker.com"
+    )]
+    pub docker_url: String,
+
+    /// Per-request timeout in milliseconds.
+    #[arg(short, long, env = "PACKAGE_WATCH_TIMEOUT", default_value = "30000")]
+    pub timeout: u64,
+}
+
+/// A package registry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+pub enum Registry {
+    Crates,
+    Npm,
+    Pypi,
+    Docker,
+}
+
+impl Registry {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Crates => "crates",
+            Self::Npm => "npm",
+            Self::Pypi => "pypi",
+            Self::Docker => "docker",
+        }
+    }
+}
+
+/// A watched package, written `registry:name` (e.g. `crates:serde`,
+/// `npm:@types/node`, `docker:grafana/grafana`).
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Package {
+    pub registry: Registry,
+    pub name: String,
+}
+
+impl Package {
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let (registry, name) = spec
+            .split_once(':')
+            .ok_or_else(|| format!("{spec}: expected registry:name"))?;
+        let registry = <Registry as ValueEnum>::from_str(registry, true).map_err(|_| {
+            format!("{spec}: unknown registry {registry} (crates, npm, pypi or docker)")
+        })?;
+        let name = name.trim();
+        if name.is_empty() {
+            return Err(format!("{spec}: missing package name"));
+        }
+        let name = match registry {
+            Registry::Docker if !name.contains('/') => format!("library/{name}"),
+            _ => name.to_string(),
+        };
+        Ok(Self { registry, name })
+    }
+
+    /// The `registry:name` key state is kept under.
+    pub fn key(&self) -> String {
+        format!("{}:{}", self.registry.as_str(), self.name)
+    }
+}
+
+/// One version of a package.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Release {
+    pub version: String,
+    pub yanked: bool,
+    /// When it was published, as the registry gives it (RFC 3339).
+    pub published_at: Option<String>,
+    pub changelog_url: Option<String>,
+    /// The version's page on the registry's website.
+    pub url: Option<String>,
+}
+
+fn string(value: &Value) -> Option<String> {
+    value.as_str().map(str::to_string)
+}
+
+/// The releases page of a repository on a known forge.
+fn releases_page(repository: &str) -> Option<String> {
+    let repository = repository
+        .trim()
+        .trim_start_matches("git+")
+        .replace("git://", "https://")
+        .replace("ssh://git@", "https://");
+    let repository = repository.trim_end_matches('/').trim_end_matches(".git");
+    let forge = [
+        "https://github.com/",
+        "https://gitlab.com/",
+        "https://codeberg.org/",
+    ]
+    .iter()
+    .any(|forge| repository.starts_with(forge));
+    if !forge {
+        return None;
+    }
+    Some(if repository.starts_with("https://gitlab.com/") {
+        format!("{repository}/-/releases")
+    } else {
+        format!("{repository}/releases")
+    })
+}
+
+/// Reads releases from the registries.
+pub struct Registries {
+    client: Client,
+    args: RegistryArgs,
+}
+
+impl Registries {
+    pub fn new(client: Client, args: &RegistryArgs) -> Self {
+        Self {
+            client,
+            args: args.clone(),
+        }
+    }
+
+    async fn get(&self, url: &str) -> Result<Value, String> {
+        let response = self
+            .client
+            .get(url)
+            .header(
+                "User-Agent",
+                concat!("emergent-package-watch-source/", env!("CARGO_PKG_VERSION")),
+            )
+            .timeout(Duration::from_millis(self.args.timeout))
+            .send()
+            .await
+            .map_err(|e| format!("{url}: {e}"))?;
+        let status = response.status();
+        if !status.is_success() {
+            return Err(format!("{url}: HTTP {status}"));
+        }
+        response.json().await.map_err(|e| format!("{url}: {e}"))
+    }
+
+    /// Every listed release of `package`.
+    pub async fn releases(&self, package: &Package) -> Result<Vec<Release>, String> {
+        let name = &package.name;
+        match package.registry {
+            Registry::Crates => {
+                let base = self.args.crates_url.trim_end_matches('/');
+                let body = self.get(&format!("{base}/api/v1/crates/{name}")).await?;
+                Ok(crates(name, &body))
+            }
+            Registry::Npm => {
+                let base = self.args.npm_url.trim_end_matches('/');
+                let body = self
+                    .get(&format!("{base}/{}", name.replace('/', "%2F")))
+                    .await?;
+                Ok(npm(name, &body))
+            }
+            Registry::Pypi => {
+                let base = self.args.pypi_url.trim_end_matches('/');
+                let body = self.get(&format!("{base}/pypi/{name}/json")).await?;
+                Ok(pypi(name, &body))
+            }
+            Registry::Docker => {
+                let base = self.args.docker_url.trim_end_matches('/');
+                let url = format!(
+                    "{base}/v2/repositories/{name}/tags?page_size=100&ordering=last_updated"
+                );
+                Ok(docker(name, &self.get(&url).await?))
+            }
+        }
+    }
+}
+
+fn crates(name: &str, body: &Value) -> Vec<Release> {
+    let changelog = body["crate"]["repository"].as_str().and_then(releases_page);
+    body["versions"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|version| {
+            let num = version["num"].as_str()?;
+            Some(Release {
+                version: num.to_string(),
+                yanked: version["yanked"].as_bool().unwrap_or(false),
+                published_at: string(&version["created_at"]),
+                changelog_url: changelog.clone(),
+                url: Some(format!("https://crates.io/crates/{name}/{num}")),
+            })
+        })
+        .collect()
+}
+
+fn npm(name: &str, body: &Value) -> Vec<Release> {
+    let repository = match &body["repository"] {
+        Value::String(url) => Some(url.as_str()),
+        repository => repository["url"].as_str(),
+    };
+    let changelog = repository.and_then(releases_page);
+    body["versions"]
+        .as_object()
+        .into_iter()
+        .flatten()
+        .map(|(version, manifest)| Release {
+            version: version.clone(),
+            yanked: manifest.get("deprecated").is_some_and(|d| !d.is_null()),
+            published_at: string(&body["time"][version]),
+            changelog_url: changelog.clone(),
+            url: Some(format!("https://www.npmjs.com/package/{name}/v/{version}")),
+        })
+        .collect()
+}
+
+fn pypi(name: &str, body: &Value) -> Vec<Release> {
+    let urls = body["info"]["project_urls"].as_object();
+    let changelog = urls
+        .and_then(|urls| {
+            urls.iter().find_map(|(label, url)| {
+                let label = label.to_lowercase();
+                [
+                    "changelog",
+                    "change log",
+                    "changes",
+                    "release notes",
+                    "history",
+                ]
+                .iter()
+                .any(|l| label.contains(l))
+                .then(|| string(url))
+                .flatten()
+            })
+        })
+        .or_else(|| {
+            urls.into_iter()
+                .flatten()
+                .find_map(|(_, url)| url.as_str().and_then(releases_page))
+        });
+    body["releases"]
+        .as_object()
+        .into_iter()
+        .flatten()
+        .map(|(version, files)| {
+            let files = files.as_array().map(Vec::as_slice).unwrap_or_default();
+            Release {
+                version: version.clone(),
+                yanked: !files.is_empty()
+                    && files.iter().all(|f| f["yanked"].as_bool() == Some(true)),
+                published_at: files
+                    .iter()
+                    .filter_map(|f| f["upload_time_iso_8601"].as_str())
+                    .min()
+                    .map(str::to_string),
+                changelog_url: changelog.clone(),
+                url: Some(format!("https://pypi.org/project/{name}/{version}/")),
+            }
+        })
+        .collect()
+}
+
+fn docker(name: &str, body: &Value) -> Vec<Release> {
+    let page = match name.strip_prefix("library/") {
+        Some(official) => format!("https://hub.docker.com/_/{official}/tags"),
+        None => format!("https://hub.docker.com/r/{name}/tags"),
+    };
+    body["results"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|tag| {
+            Some(Release {
+                version: string(&tag["name"])?,
+                yanked: false,
+                published_at: string(&tag["tag_last_pushed"])
+                    .or_else(|| string(&tag["last_updated"])),
+                changelog_url: None,
+                url: Some(page.clone()),
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn package_specs_name_a_registry() {
+        let nginx = Package::parse("docker:nginx").unwrap_or_else(|e| panic!("{e}"));
+        assert_eq!(nginx.key(), "docker:library/nginx");
+        let types = Package::parse("npm:@types/node").unwrap_or_else(|e| panic!("{e}"));
+        assert_eq!(types.name, "@types/node");
+        assert!(Package::parse("serde").is_err());
+        assert!(Package::parse("maven:junit").is_err());
+        assert!(Package::parse("crates: ").is_err());
+    }
+
+    #[test]
+    fn registry_listings_become_releases() {
+        let body = json!({
+            "info": {"project_urls": {"Source": "https://github.com/psf/requests",
+                                      "Changelog": "https://requests.readthedocs.io/en/latest/community/updates/"}},
+            "releases": {
+                "2.32.0": [{"yanked": true, "upload_time_iso_8601": "2024-05-20T16:15:58Z"}],
+                "2.32.3": [{"yanked": false, "upload_time_iso_8601": "2024-05-29T15:37:47Z"},
+                           {"yanked": true, "upload_time_iso_8601": "2024-05-29T15:37:49Z"}],
+            },
+        });
+        let releases = pypi("requests", &body);
+        assert_eq!(releases.len(), 2);
+        assert!(releases[0].yanked);
+        assert!(!releases[1].yanked);
+        assert_eq!(
+            releases[1].changelog_url.as_deref(),
+            Some("https://requests.readthedocs.io/en/latest/community/updates/")
+        );
+
+        let body = json!({
+            "repository": {"type": "git", "url": "git+https://github.com/facebook/react.git"},
+            "versions": {"19.0.0": {}, "0.14.0": {"deprecated": "use a newer version"}},
+            "time": {"19.0.0": "2024-12-05T18:10:24.000Z"},
+        });
+        let releases = npm("react", &body);
+        let old = releases.iter().find(|r| r.version == "0.14.0");
+        assert_eq!(old.map(|r| r.yanked), Some(true));
+        assert_eq!(
+            releases[0].changelog_url.as_deref(),
+            Some("https://github.com/facebook/react/releases")
+        );
+        assert_eq!(releases_page("https://example.com/repo"), None);
+    }
+}
@@ -0,0 +1,132 @@
+//! Which releases are new.
+//!
+//! [`Known`] remembers every version seen per package and whether it was
+//! yanked. A version not seen before is published as `package.released`,
+//! and a known version that has since been yanked as `package.yanked`.
+//! The first listing of a package only records what is there.
+
+use crate::registry::{Package, Release};
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use std::collections::BTreeMap;
+
+pub const RELEASED_EVENT_TYPE: &str = "package.released";
+pub const YANKED_EVENT_TYPE: &str = "package.yanked";
+pub const EVENT_TYPES: [&str; 2] = [RELEASED_EVENT_TYPE, YANKED_EVENT_TYPE];
+
+/// Versions seen per package (`registry:name`), with their yanked status.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Known {
+    packages: BTreeMap<String, BTreeMap<String, bool>>,
+}
+
+/// An event to publish.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Change {
+    pub event_type: &'static str,
+    pub payload: Value,
+}
+
+fn payload(package: &Package, release: &Release) -> Value {
+    json!({
+        "registry": package.registry.as_str(),
+        "package": package.name,
+        "version": release.version,
+        "yanked": release.yanked,
+        "published_at": release.published_at,
+        "changelog_url": release.changelog_url,
+        "url": release.url,
+    })
+}
+
+impl Known {
+    /// Compare `releases` with what is known of `package`, returning the
+    /// events due, oldest release first. Does not record them; call
+    /// [`record`](Self::record) once they are published.
+    pub fn changes(&self, package: &Package, releases: &[Release]) -> Vec<Change> {
+        let Some(known) = self.packages.get(&package.key()) else {
+            return Vec::new();
+        };
+        let mut changes: Vec<(&Release, &'static str)> = releases
+            .iter()
+            .filter_map(|release| match known.get(&release.version) {
+                None => Some((release, RELEASED_EVENT_TYPE)),
+                Some(false) if release.yanked => Some((release, YANKED_EVENT_TYPE)),
+                Some(_) => None,
+            })
+            .collect();
+        changes.sort_by(|(a, _), (b, _)| a.published_at.cmp(&b.published_at));
+        changes
+            .into_iter()
+            .map(|(release, event_type)| Change {
+                event_type,
+                payload: payload(package, release),
+            })
+            .collect()
+    }
+
+    /// Whether `package` has been listed before.
+    pub fn knows(&self, package: &Package) -> bool {
+        self.packages.contains_key(&package.key())
+    }
+
+    /// Record `releases` as the current listing of `package`. Versions no
+    /// longer listed (unpublished, or past the page Docker Hub returns)
+    /// are kept, so they are not reported again if they reappear.
+    pub fn record(&mut self, package: &Package, releases: &[Release]) {
+        let known = self.packages.entry(package.key()).or_default();
+        for release in releases {
+            known.insert(release.version.clone(), release.yanked);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::registry::Registry;
+
+    fn release(version: &str, yanked: bool, published_at: &str) -> Release {
+        Release {
+            version: version.to_string(),
+            yanked,
+            published_at: Some(published_at.to_string()),
+            changelog_url: None,
+            url: None,
+        }
+    }
+
+    #[test]
+    fn new_and_yanked_versions_are_reported_once() {
+        let serde = Package {
+            registry: Registry::Crates,
+            name: "serde".to_string(),
+        };
+        let mut known = Known::default();
+        let first = [release("1.0.0", false, "2024-01-01")];
+        assert!(known.changes(&serde, &first).is_empty());
+        known.record(&serde, &first);
+
+        let second = [
+            release("1.0.2", false, "2024-03-01"),
+            release("1.0.1", false, "2024-02-01"),
+            release("1.0.0", true, "2024-01-01"),
+        ];
+        let changes = known.changes(&serde, &second);
+        let events: Vec<(&str, &Value)> = changes
+            .iter()
+            .map(|c| (c.event_type, &c.payload["version"]))
+            .collect();
+        assert_eq!(
+            events,
+            [
+                (YANKED_EVENT_TYPE, &json!("1.0.0")),
+                (RELEASED_EVENT_TYPE, &json!("1.0.1")),
+                (RELEASED_EVENT_TYPE, &json!("1.0.2")),
+            ]
+        );
+        assert_eq!(changes[0].payload["registry"], "crates");
+        known.record(&serde, &second);
+        assert!(known.changes(&serde, &second).is_empty());
+    }
+}
@@ -0,0 +1,451 @@
+//! PII Detect
+//!
+//! A Handler that scans payloads for personal data and secrets — email
+//! addresses, phone numbers, national ID numbers, Luhn-valid card numbers
+//! and API keys, plus patterns of your own (see [`detect`]) — and reports
+//! what it finds, so leaks are caught before events reach logs, tickets or
+//! third parties.
+//!
+//! # Data Flow
+//!
+//! 1. Receive an event matching configured subscriptions
+//! 2. Scan every string in its payload, or only the `--field` paths
+//! 3. With `--action annotate` (the default), republish the payload with
+//!    a `pii` summary of the findings
+//! 4. With `--action quarantine`, publish messages with findings to the
+//!    quarantine topic instead, and republish clean ones unchanged
+//!
+//! Findings give the detector, the dotted path and a masked preview; the
+//! values themselves are never copied into them.
+//!
+//! # Messages Published
+//!
+//! - The incoming type, renamed by `--publish-as` (default:
+//!   `scanned.{type}`): annotated messages, or clean ones when quarantining
+//! - `pii.quarantined` (configurable with `--quarantine-as`): messages with
+//!   findings, when quarantining
+//!
+//! # Usage
+//!
+//! ```bash
+//! # Annotate every support ticket with what it contains
+//! pii-detect -s 'ticket.*'
+//!
+//! # Hold back webhook payloads carrying card numbers or keys
+//! pii-detect -s 'webhook.*' --detect card,api-key --action quarantine
+//!
+//! # Add an in-house identifier, scanning only the free-text fields
+//! pii-detect -s 'order.*' --field notes --field customer.comment \
+//!   --pattern 'employee_id=EMP-\d{6}'
+//! ```
+
+pub mod detect;
+
+use clap::{Parser, ValueEnum};
+use detect::{Finding, Kind, Scanner};
+use emergent_client::EmergentMessage;
+use primitive_common::capabilities::{self, CapabilityArgs, Role, VERSION};
+use primitive_common::crypto::{KeyArgs, Keyring};
+use primitive_common::doctor::Report;
+use primitive_common::errors::{ERROR_EVENT_TYPE, ErrorArgs, ErrorCategory};
+use primitive_common::handler::{Bus, HandlerConfig, MessageHandler, run_handler};
+use primitive_common::key;
+use primitive_common::payload::{self, PayloadArgs};
+use serde_json::{Value, json};
+use std::collections::BTreeSet;
+
+/// What happens to a message with findings.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Action {
+    /// Republish it with the findings added.
+    Annotate,
+    /// Publish it to the quarantine topic instead.
+    Quarantine,
+}
+
+/// PII Detect — find personal data and secrets in payloads.
+#[derive(Parser, Debug)]
+#[command(name = "pii-detect", version = VERSION)]
+#[command(
+    about = "Scan payloads for personal data and secrets, annotating or quarantining offenders"
+)]
+struct Args {
+    /// Message types to subscribe to.
+    #[arg(short, long = "subscribe", required = true)]
+    subscribe: Vec<String>,
+
+    /// Kinds of data to look for.
+    #[arg(
+        long,
+        env = "PII_DETECT_KINDS",
+        value_enum,
+        value_delimiter = ',',
+        default_value = "email,phone,national-id,card,api-key"
+    )]
+    detect: Vec<Kind>,
+
+    /// Extra detector as `name=regex` (e.g. `employee_id=EMP-\d{6}`); repeatable.
+    #[arg(long = "pattern", env = "PII_DETECT_PATTERNS", value_delimiter = ';')]
+    patterns: Vec<String>,
+
+    /// Payload field to scan, as a dotted path; repeatable (default: the whole payload).
+    #[arg(long = "field", env = "PII_DETECT_FIELDS", value_delimiter = ',')]
+    fields: Vec<String>,
+
+    /// What to do with messages that have findings.
+    #[arg(
+        long,
+        env = "PII_DETECT_ACTION",
+        value_enum,
+        default_value = "annotate"
+    )]
+    action: Action,
+
+    /// Payload field the findings are added under, when annotating.
+    #[arg(long, env = "PII_DETECT_FINDINGS_FIELD", default_value = "pii")]
+    findings_field: String,
+
+    /// Message type to republish as; `{type}` stands for the incoming type.
+    #[arg(
+        long,
+        env = "PII_DETECT_PUBLISH_AS",
+        default_value = "scanned.{type}",
+        value_parser = parse_publish_as
+    )]
+    publish_as: String,
+
+    /// Message type messages with findings are quarantined as.
+    #[arg(
+        long,
+        env = "PII_DETECT_QUARANTINE_AS",
+        default_value = "pii.quarantined"
+    )]
+    quarantine_as: String,
+
+    #[command(flatten)]
+    payload: PayloadArgs,
+
+    /// Verify the configuration and exit without connecting to the engine.
+    #[arg(long)]
+    self_test: bool,
+
+    #[command(flatten)]
+    errors: ErrorArgs,
+
+    #[command(flatten)]
+    capabilities: CapabilityArgs,
+
+    #[command(flatten)]
+    keys: KeyArgs,
+}
+
+fn parse_publish_as(value: &str) -> Result<String, String> {
+    match value {
+        "" => Err("must not be empty".to_string()),
+        // Republishing under the same type would feed straight back in
+        "{type}" => Err("must differ from the incoming type".to_string()),
+        _ => Ok(value.to_string()),
+    }
+}
+
+/// The message type `message_type` is republished as.
+fn publish_type(publish_as: &str, message_type: &str) -> String {
+    publish_as.replace("{type}", message_type)
+}
+
+/// Whether `message_type` is one this handler published.
+fn is_own(args: &Args, message_type: &str) -> bool {
+    if message_type == args.quarantine_as {
+        return true;
+    }
+    match args.publish_as.split_once("{type}") {
+        Some((prefix, suffix)) => {
+            message_type.len() > prefix.len() + suffix.len()
+                && message_type.starts_with(prefix)
+                && message_type.ends_with(suffix)
+        }
+        None => message_type == args.publish_as,
+    }
+}
+
+/// The findings in `input`: in the `fields` given, or anywhere.
+fn scan(scanner: &Scanner, input: &Value, fields: &[String]) -> Vec<Finding> {
+    let mut findings = Vec::new();
+    if fields.is_empty() {
+        scanner.scan("", input, &mut findings);
+    }
+    for field in fields {
+        if let Some(value) = key::lookup(input, field) {
+            let path = field.strip_prefix("payload.").unwrap_or(field);
+            scanner.scan(path, value, &mut findings);
+        }
+    }
+    findings
+}
+
+/// The summary of `findings` added to annotated payloads and quarantined
+/// messages.
+fn summary(findings: &[Finding]) -> Value {
+    let kinds: BTreeSet<&str> = findings.iter().map(|f| f.kind.as_str()).collect();
+    json!({
+        "found": !findings.is_empty(),
+        "kinds": kinds,
+        "findings": findings,
+    })
+}
+
+/// The message published for `input`, per `--action`: its type and payload.
+fn outcome(
+    args: &Args,
+    message_type: &str,
+    message_id: &str,
+    mut input: Value,
+    findings: &[Finding],
+) -> (String, Value) {
+    match args.action {
+        Action::Quarantine if !findings.is_empty() => {
+            let mut quarantined = summary(findings);
+            quarantined["message_id"] = Value::from(message_id);
+            quarantined["message_type"] = Value::from(message_type);
+            quarantined["payload"] = input;
+            (args.quarantine_as.clone(), quarantined)
+        }
+        Action::Quarantine => (publish_type(&args.publish_as, message_type), input),
+        Action::Annotate => {
+            let annotation = summary(findings);
+            match &mut input {
+                Value::Object(map) => {
+                    map.insert(args.findings_field.clone(), annotation);
+                }
+                // Nowhere to annotate a bare value, so wrap it
+                other => {
+                    let value = other.take();
+                    input = json!({"value": value, args.findings_field.as_str(): annotation});
+                }
+            }
+            (publish_type(&args.publish_as, message_type), input)
+        }
+    }
+}
+
+/// Run the primitive with `args` as its command line (the first item is
+/// the program name, as with `std::env::args_os`).
+pub async fn run<I, T>(args: I) -> Result<(), Box<dyn std::error::Error>>
+where
+    I: IntoIterator<Item = T>,
+    T: Into<std::ffi::OsString> + Clone,
+{
+    let args = Args::parse_from(args);
+
+    // Get the handler name from environment (set by engine) or use default
+    let name = std::env::var("EMERGENT_NAME").unwrap_or_else(|_| "pii-detect".to_string());
+
+    let scanner = Scanner::new(&args.detect, &args.patterns);
+    if args.self_test {
+        let mut report = Report::new(&name);
+        report.check(
+            "detectors",
+            scanner
+                .as_ref()
+                .map(|scanner| scanner.names().join(", "))
+                .map_err(Clone::clone),
+        );
+        args.payload.self_test(&mut report);
+        args.keys.self_test(&mut report);
+        report.finish();
+    }
+    let scanner = match scanner {
+        Ok(scanner) => scanner,
+        Err(e) => {
+            eprintln!("Error: {e}");
+            std::process::exit(1);
+        }
+    };
+
+    if let Err(e) = args.payload.encryption.validate() {
+        eprintln!("Error: {e}");
+        std::process::exit(1);
+    }
+    let keys = match args.keys.load() {
+        Ok(keys) => keys,
+        Err(e) => {
+            eprintln!("Error: failed to load identity file: {e}");
+            std::process::exit(1);
+        }
+    };
+
+    let topics_refs: Vec<&str> = args.subscribe.iter().map(String::as_str).collect();
+    let mut produces = vec![args.publish_as.as_str()];
+    if args.action == Action::Quarantine {
+        produces.push(args.quarantine_as.as_str());
+    }
+    if args.errors.emit_errors {
+        produces.push(ERROR_EVENT_TYPE);
+    }
+    let descriptor =
+        args.capabilities
+            .describe(&name, Role::Handler, &topics_refs, &produces, &args);
+    if args.capabilities.capabilities {
+        capabilities::print(&descriptor);
+    }
+
+    let config = HandlerConfig {
+        name: &name,
+        subscribe: &args.subscribe,
+        announce: args.capabilities.announce.then_some(&descriptor),
+        emit_errors: args.errors.emit_errors,
+    };
+    let mut detect = PiiDetect {
+        args: &args,
+        scanner,
+        keys,
+    };
+    run_handler(config, &mut detect).await
+}
+
+/// The detectors and what they need to read payloads.
+struct PiiDetect<'a> {
+    args: &'a Args,
+    scanner: Scanner,
+    keys: Option<Keyring>,
+}
+
+impl MessageHandler for PiiDetect<'_> {
+    type Wake = ();
+
+    /// Scan one message and publish it per `--action`.
+    async fn handle(&mut self, msg: &EmergentMessage, bus: &Bus) {
+        let args = self.args;
+        let message_type = msg.message_type.as_str();
+        if is_own(args, message_type) {
+            return;
+        }
+        let input = match payload::decode(msg.payload(), self.keys.as_ref()).await {
+            Ok(p) => p.into_owned(),
+            Err(e) => {
+                let error = format!("failed to decode payload: {e}");
+                eprintln!("pii-detect: {error}");
+                bus.report_error(msg, ErrorCategory::Parse, &error).await;
+                return;
+            }
+        };
+
+        let findings = scan(&self.scanner, &input, &args.fields);
+        if !findings.is_empty() {
+            let kinds: BTreeSet<&str> = findings.iter().map(|f| f.kind.as_str()).collect();
+            eprintln!(
+                "pii-detect: {} finding(s) in {message_type} {}: {}",
+                findings.len(),
+                msg.id(),
+                kinds.into_iter().collect::<Vec<_>>().join(", ")
+            );
+        }
+        let message_id = msg.id().to_string();
+        let (publish_as, output) = outcome(args, message_type, &message_id, input, &findings);
+        let payload = match payload::encode(&publish_as, output, &args.payload).await {
+            Ok(p) => p,
+            Err(e) => {
+                eprintln!("pii-detect: failed to encode output: {e}");
+                return;
+            }
+        };
+        let message = EmergentMessage::new(&publish_as)
+            .with_causation_id(msg.id())
+            .with_payload(payload);
+        bus.publish(message).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(extra: &[&str]) -> Args {
+        let mut argv = vec!["pii-detect", "-s", "ticket.*"];
+        argv.extend_from_slice(extra);
+        Args::try_parse_from(argv).unwrap_or_else(|e| panic!("{e}"))
+    }
+
+    #[test]
+    fn offenders_are_annotated_or_quarantined() {
+        let input = json!({
+            "subject": "Refund",
+            "notes": "Card 4111-1111-1111-1111, mail me at jane@example.com",
+            "customer": {"comment": "thanks"},
+        });
+
+        let annotate = args(&[]);
+        let scanner =
+            Scanner::new(&annotate.detect, &annotate.patterns).unwrap_or_else(|e| panic!("{e}"));
+        let findings = scan(&scanner, &input, &annotate.fields);
+        let (publish_as, output) = outcome(
+            &annotate,
+            "ticket.created",
+            "msg_1",
+            input.clone(),
+            &findings,
+        );
+        assert_eq!(publish_as, "scanned.ticket.created");
+        assert_eq!(output["subject"], "Refund");
+        assert_eq!(
+            output["pii"],
+            json!({
+                "found": true,
+                "kinds": ["card", "email"],
+                "findings": [
+                    {"kind": "card", "path": "notes", "preview": "***************1111"},
+                    {"kind": "email", "path": "notes", "preview": "j***@example.com"},
+                ],
+            })
+        );
+
+        let quarantine = args(&["--action", "quarantine", "--field", "customer.comment"]);
+        let findings = scan(&scanner, &input, &quarantine.fields);
+        let (publish_as, output) = outcome(
+            &quarantine,
+            "ticket.created",
+            "msg_1",
+            input.clone(),
+            &findings,
+        );
+        // Only the comment was scanned, and it is clean
+        assert_eq!(publish_as, "scanned.ticket.created");
+        assert_eq!(output, input);
+
+        let quarantine = args(&["--action", "quarantine"]);
+        let findings = scan(&scanner, &input, &quarantine.fields);
+        let (publish_as, output) = outcome(
+            &quarantine,
+            "ticket.created",
+            "msg_1",
+            input.clone(),
+            &findings,
+        );
+        assert_eq!(publish_as, "pii.quarantined");
+        assert_eq!(output["message_id"], "msg_1");
+        assert_eq!(output["message_type"], "ticket.created");
+        assert_eq!(output["kinds"], json!(["card", "email"]));
+        assert_eq!(output["payload"], input);
+
+        assert!(is_own(&quarantine, "pii.quarantined"));
+        assert!(is_own(&quarantine, "scanned.ticket.created"));
+        assert!(!is_own(&quarantine, "ticket.created"));
+    }
+
+    #[test]
+    fn bare_values_are_wrapped_to_be_annotated() {
+        let annotate = args(&["--detect", "email", "--findings-field", "privacy"]);
+        let scanner =
+            Scanner::new(&annotate.detect, &annotate.patterns).unwrap_or_else(|e| panic!("{e}"));
+        let input = json!("no personal data here");
+        let findings = scan(&scanner, &input, &annotate.fields);
+        let (_, output) = outcome(&annotate, "ticket.note", "msg_2", input, &findings);
+        assert_eq!(
+            output,
+            json!({
+                "value": "no personal data here",
+                "privacy": {"found": false, "kinds": [], "findings": []},
+            })
+        );
+    }
+}
@@ -0,0 +1,6 @@
+//! `pii-detect` binary; the primitive itself lives in the library.
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    pii_detect::run(std::env::args_os()).await
+}
@@ -0,0 +1,212 @@
+//! Power commands for baseboard management controllers.
+//!
+//! - IPMI through `ipmitool chassis power`, the password passed in
+//!   `IPMI_PASSWORD` (`-E`) rather than on the command line.
+//! - Redfish through the system's `ComputerSystem.Reset` action, with
+//!   `PowerState` read back for `status`.
+
+use crate::registry::{Ipmi, Op, Redfish};
+use reqwest::Client;
+use serde_json::{Value, json};
+use std::time::Duration;
+use tokio::process::Command;
+
+/// The ipmitool command line for `op`.
+pub fn ipmi_argv(binary: &str, ipmi: &Ipmi, op: Op) -> Vec<String> {
+    let action = match op {
+        Op::Wake | Op::On => "on",
+        Op::Off => "off",
+        Op::Cycle => "cycle",
+        Op::Reset => "reset",
+        Op::Shutdown => "soft",
+        Op::Status => "status",
+    };
+    [
+        binary,
+        "-I",
+        &ipmi.interface,
+        "-H",
+        &ipmi.host,
+        "-U",
+        &ipmi.username,
+        "-E",
+        "chassis",
+        "power",
+        action,
+    ]
+    .iter()
+    .map(|s| s.to_string())
+    .collect()
+}
+
+/// Run `op` with ipmitool, returning the power state for `status`.
+pub async fn ipmi(
+    binary: &str,
+    ipmi: &Ipmi,
+    op: Op,
+    timeout: Duration,
+) -> Result<Option<String>, String> {
+    let argv = ipmi_argv(binary, ipmi, op);
+    let child = Command::new(&argv[0])
+        .args(&argv[1..])
+        .env("IPMI_PASSWORD", &ipmi.password)
+        .stdin(std::process::Stdio::null())
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(|e| format!("{binary}: {e}"))?;
+    let output = tokio::time::timeout(timeout, child.wait_with_output())
+        .await
+        .map_err(|_| format!("{binary}: timed out"))?
+        .map_err(|e| format!("{binary}: {e}"))?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(format!("{binary}: {} {}", output.status, stderr.trim()));
+    }
+    // "Chassis Power is on"
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    Ok(match op {
+        Op::Status => stdout
+            .trim()
+            .rsplit(' ')
+            .next()
+            .map(str::to_ascii_lowercase),
+        _ => None,
+    })
+}
+
+/// Redfish `ResetType` for `op`.
+fn reset_type(op: Op) -> Option<&'static str> {
+    match op {
+        Op::Wake | Op::On => Some("On"),
+        Op::Off => Some("ForceOff"),
+        Op::Cycle => Some("PowerCycle"),
+        Op::Reset => Some("ForceRestart"),
+        Op::Shutdown => Some("GracefulShutdown"),
+        Op::Status => None,
+    }
+}
+
+async fn get(client: &Client, redfish: &Redfish, path: &str) -> Result<Value, String> {
+    let url = format!("{}{path}", redfish.url.trim_end_matches('/'));
+    client
+        .get(&url)
+        .basic_auth(&redfish.username, Some(&redfish.password))
+        .send()
+        .await
+        .and_then(reqwest::Response::error_for_status)
+        .map_err(|e| format!("{url}: {e}"))?
+        .json()
+        .await
+        .map_err(|e| format!("{url}: {e}"))
+}
+
+/// Run `op` through Redfish, returning the power state for `status`.
+pub async fn redfish(client: &Client, redfish: &Redfish, op: Op) -> Result<Option<String>, String> {
+    let system = match &redfish.system {
+        Some(system) => system.clone(),
+        None => {
+            let systems = get(client, redfish, "/redfish/v1/Systems").await?;
+            systems["Members"][0]["@odata.id"]
+                .as_str()
+                .map(str::to_string)
+                .ok_or("no systems in /redfish/v1/Systems")?
+        }
+    };
+    let Some(reset_type) = reset_type(op) else {
+        let state = get(client, redfish, &system).await?["PowerState"]
+            .as_str()
+            .map(str::to_ascii_lowercase);
+        return Ok(state);
+    };
+
+    let url = format!(
+        "{}{system}/Actions/ComputerSystem.Reset",
+        redfish.url.trim_end_matches('/')
+    );
+    client
+        .post(&url)
+        .basic_auth(&redfish.username, Some(&redfish.password))
+        .json(&json!({ "ResetType": reset_type }))
+        .send()
+        .await
+        .and_then(reqwest::Response::error_for_status)
+        .map_err(|e| format!("{url}: {e}"))?;
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{
+        Json, Router,
+        routing::{get as get_route, post},
+    };
+    use std::sync::{Arc, Mutex, PoisonError};
+
+    #[test]
+    fn ipmitool_gets_the_password_from_the_environment() {
+        let ipmi = Ipmi {
+            host: "10.0.0.5".to_string(),
+            username: "admin".to_string(),
+            password: "s3cret".to_string(),
+            interface: "lanplus".to_string(),
+        };
+        assert_eq!(
+            ipmi_argv("ipmitool", &ipmi, Op::Shutdown).join(" "),
+            "ipmitool -I lanplus -H 10.0.0.5 -U admin -E chassis power soft"
+        );
+    }
+
+    #[tokio::test]
+    async fn redfish_resets_the_first_system() {
+        let resets = Arc::new(Mutex::new(Vec::new()));
+        let seen = Arc::clone(&resets);
+        let app = Router::new()
+            .route(
+                "/redfish/v1/Systems",
+                get_route(|| async {
+                    Json(json!({"Members": [{"@odata.id": "/redfish/v1/Systems/1"}]}))
+                }),
+            )
+            .route(
+                "/redfish/v1/Systems/1",
+                get_route(|| async { Json(json!({"Id": "1", "PowerState": "On"})) }),
+            )
+            .route(
+                "/redfish/v1/Systems/1/Actions/ComputerSystem.Reset",
+                post(move |Json(body): Json<Value>| async move {
+                    seen.lock()
+                        .unwrap_or_else(PoisonError::into_inner)
+                        .push(body);
+                    Json(json!({}))
+                }),
+            );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap_or_else(|e| panic!("bind: {e}"));
+        let addr = listener
+            .local_addr()
+            .unwrap_or_else(|e| panic!("local_addr: {e}"));
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let bmc = Redfish {
+            url: format!("http://{addr}"),
+            username: "root".to_string(),
+            password: "calvin".to_string(),
+            system: None,
+            insecure: false,
+        };
+        let client = Client::new();
+        assert_eq!(redfish(&client, &bmc, Op::Cycle).await, Ok(None));
+        assert_eq!(
+            redfish(&client, &bmc, Op::Status).await,
+            Ok(Some("on".to_string()))
+        );
+        assert_eq!(
+            *resets.lock().unwrap_or_else(PoisonError::into_inner),
+            [json!({"ResetType": "PowerCycle"})]
+        );
+    }
+}
@@ -0,0 +1,360 @@
+//! Power Sink - Wake, Switch and Reset Machines
+//!
+//! Each event names a power operation and a host from the registry in
+//! `--config` (see [`registry`]):
+//!
+//! ```json
+//! {"op": "wake", "host": "nas"}
+//! {"op": "cycle", "host": "web1", "via": "ipmi"}
+//! ```
+//!
+//! `op` is one of `wake`, `on`, `off`, `cycle`, `reset`, `shutdown` and
+//! `status`. `wake` sends a Wake-on-LAN magic packet (see [`wol`]); the
+//! others go to the host's BMC over Redfish or IPMI (see [`bmc`]) or to
+//! its smart plug (see [`plug`]), in that order of preference unless `via`
+//! names one. A `wake` payload may give a `mac` instead of a host.
+//!
+//! Every operation carried out is confirmed with a `power.result` event,
+//! `ok` or with the `error`, and `state` (`on`/`off`) where the device
+//! reports one.
+//!
+//! # Examples
+//!
+//! ```bash
+//! power-sink -s power.request --config /etc/emergent/power.json
+//! ```
+
+pub mod bmc;
+pub mod plug;
+pub mod registry;
+pub mod wol;
+
+use clap::Parser;
+use emergent_client::EmergentMessage;
+use primitive_common::capabilities::VERSION;
+use primitive_common::doctor::{Report, check_executable};
+use primitive_common::errors::{ErrorCategory, HandlerError};
+use primitive_common::reload::HotConfig;
+use primitive_common::{Publisher, SinkArgs, SinkConfig, SinkContext, SinkHandler, run_sink};
+use registry::{Host, Method, Op};
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::sync::OnceLock;
+use std::time::Duration;
+
+pub const RESULT_EVENT_TYPE: &str = "power.result";
+
+/// Power Sink — wake, switch and reset machines.
+#[derive(Parser, Debug)]
+#[command(name = "power_sink", version = VERSION)]
+#[command(about = "Send Wake-on-LAN packets and IPMI, Redfish or smart plug power commands")]
+struct Args {
+    /// Message types to subscribe to.
+    #[arg(short, long = "subscribe", required = true)]
+    subscribe: Vec<String>,
+
+    /// Per-operation timeout in milliseconds.
+    #[arg(short, long, env = "POWER_SINK_TIMEOUT", default_value = "10000")]
+    timeout: u64,
+
+    /// Where magic packets go unless a host names its own address.
+    #[arg(
+        long,
+        env = "POWER_SINK_BROADCAST",
+        default_value = "255.255.255.255:9"
+    )]
+    broadcast: SocketAddr,
+
+    /// Path or name of ipmitool.
+    #[arg(long, env = "POWER_SINK_IPMITOOL", default_value = "ipmitool")]
+    ipmitool: String,
+
+    #[command(flatten)]
+    sink: SinkArgs,
+}
+
+/// Settings that can be swapped on SIGHUP.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Settings {
+    hosts: BTreeMap<String, Host>,
+}
+
+/// Carries out power operations.
+struct PowerSink {
+    settings: HotConfig<Settings>,
+    client: Client,
+    /// For BMCs with self-signed certificates.
+    insecure_client: Client,
+    broadcast: SocketAddr,
+    ipmitool: String,
+    timeout: Duration,
+    publisher: OnceLock<Publisher>,
+}
+
+/// The device an operation is sent to, for results and dry runs.
+fn target(host: &Host, method: Method) -> Value {
+    match method {
+        Method::Wol => json!(host.mac),
+        Method::Ipmi => json!(host.ipmi.as_ref().map(|ipmi| &ipmi.host)),
+        Method::Redfish => json!(host.redfish.as_ref().map(|redfish| &redfish.url)),
+        Method::Plug => json!(host.plug.as_ref().map(|plug| &plug.url)),
+    }
+}
+
+impl PowerSink {
+    /// Carry out `op`, returning the power state where known.
+    async fn execute(&self, host: &Host, method: Method, op: Op) -> Result<Option<String>, String> {
+        match method {
+            Method::Wol => {
+                let mac = wol::parse_mac(host.mac.as_deref().unwrap_or_default())?;
+                let target = match &host.broadcast {
+                    Some(address) => address
+                        .parse()
+                        .map_err(|_| format!("'{address}' is not an address:port"))?,
+                    None => self.broadcast,
+                };
+                wol::wake(mac, target).await?;
+                Ok(None)
+            }
+            Method::Ipmi => {
+                let ipmi = host.ipmi.as_ref().ok_or("no ipmi configured")?;
+                bmc::ipmi(&self.ipmitool, ipmi, op, self.timeout).await
+            }
+            Method::Redfish => {
+                let redfish = host.redfish.as_ref().ok_or("no redfish configured")?;
+                let client = if redfish.insecure {
+                    &self.insecure_client
+                } else {
+                    &self.client
+                };
+                bmc::redfish(client, redfish, op).await
+            }
+            Method::Plug => {
+                let plug = host.plug.as_ref().ok_or("no plug configured")?;
+                plug::switch(&self.client, plug, op).await
+            }
+        }
+    }
+}
+
+impl SinkHandler for PowerSink {
+    async fn handle(
+        &self,
+        msg: &EmergentMessage,
+        ctx: &SinkContext<'_>,
+    ) -> Result<(), HandlerError> {
+        let payload = ctx.payload();
+        let op = payload["op"].as_str().unwrap_or_default();
+        let Some(op) = Op::parse(op) else {
+            return Err(HandlerError::new(
+                ErrorCategory::Parse,
+                format!("unknown op '{op}'"),
+            ));
+        };
+        let via = match payload["via"].as_str() {
+            Some(via) => Some(Method::parse(via).ok_or_else(|| {
+                HandlerError::new(ErrorCategory::Parse, format!("unknown method '{via}'"))
+            })?),
+            None => None,
+        };
+
+        let settings = self.settings.current();
+        let (name, host) = match (payload["host"].as_str(), payload["mac"].as_str()) {
+            (Some(name), _) => match settings.hosts.get(name) {
+                Some(host) => (Value::from(name), host.clone()),
+                None => {
+                    return Err(HandlerError::new(
+                        ErrorCategory::Rejected,
+                        format!("unknown host '{name}'"),
+                    ));
+                }
+            },
+            (None, Some(mac)) if op == Op::Wake => (
+                Value::Null,
+                Host {
+                    mac: Some(mac.to_string()),
+                    ..Default::default()
+                },
+            ),
+            _ => {
+                return Err(HandlerError::new(
+                    ErrorCategory::Parse,
+                    "payload names no 'host'",
+                ));
+            }
+        };
+        let method = host
+            .method(op, via)
+            .map_err(|e| HandlerError::new(ErrorCategory::Rejected, e))?;
+
+        if ctx.is_dry_run() {
+            let detail = json!({
+                "host": name,
+                "method": method.as_str(),
+                "target": target(&host, method),
+            });
+            ctx.would_have(op.as_str(), detail).await;
+            return Ok(());
+        }
+
+        let result = self.execute(&host, method, op).await;
+        let (state, error) = match &result {
+            Ok(state) => (state.clone(), None),
+            Err(e) => (None, Some(e.clone())),
+        };
+        match &error {
+            None => eprintln!("{} {name} via {}: ok", op.as_str(), method.as_str()),
+            Some(e) => eprintln!("{} {name} via {}: {e}", op.as_str(), method.as_str()),
+        }
+        if let Some(publisher) = self.publisher.get() {
+            let payload = json!({
+                "op": op.as_str(),
+                "host": name,
+                "method": method.as_str(),
+                "target": target(&host, method),
+                "ok": error.is_none(),
+                "state": state,
+                "error": error,
+                "message_id": ctx.message_id(),
+                "message_type": msg.message_type.as_str(),
+            });
+            let message = EmergentMessage::new(RESULT_EVENT_TYPE).with_payload(payload);
+            if let Err(e) = publisher.publish(message) {
+                eprintln!("Failed to publish {RESULT_EVENT_TYPE}: {e}");
+            }
+        }
+        result
+            .map(|_| ())
+            .map_err(|e| HandlerError::new(ErrorCategory::Request, e))
+    }
+
+    fn self_test(&self, report: &mut Report) {
+        let settings = self.settings.current();
+        report.check("hosts", Ok(format!("{} configured", settings.hosts.len())));
+        if settings.hosts.values().any(|host| host.ipmi.is_some()) {
+            report.check("ipmitool", check_executable(&self.ipmitool));
+        }
+    }
+
+    fn reload(&self) -> Result<Vec<String>, String> {
+        self.settings.reload()
+    }
+
+    fn publishes(&self) -> &'static [&'static str] {
+        &[RESULT_EVENT_TYPE]
+    }
+
+    fn attach(&self, publisher: Publisher) {
+        let _ = self.publisher.set(publisher);
+    }
+}
+
+/// Run the primitive with `args` as its command line (the first item is
+/// the program name, as with `std::env::args_os`).
+pub async fn run<I, T>(args: I) -> Result<(), Box<dyn std::error::Error>>
+where
+    I: IntoIterator<Item = T>,
+    T: Into<std::ffi::OsString> + Clone,
+{
+    let args = Args::parse_from(args);
+
+    let config = SinkConfig {
+        name: "power_sink",
+        subscribe: &args.subscribe,
+        would_have_as: "power.would_have",
+        dead_letter_as: "power.dead_letter",
+        settings: &args,
+    };
+    let settings = match HotConfig::load(&args.sink.reload, Settings::default()) {
+        Ok(settings) => settings,
+        Err(e) => {
+            eprintln!("Error: invalid config: {e}");
+            std::process::exit(1);
+        }
+    };
+    let timeout = Duration::from_millis(args.timeout);
+    let handler = PowerSink {
+        settings,
+        client: Client::builder().timeout(timeout).build()?,
+        insecure_client: Client::builder()
+            .timeout(timeout)
+            .danger_accept_invalid_certs(true)
+            .build()?,
+        broadcast: args.broadcast,
+        ipmitool: args.ipmitool.clone(),
+        timeout,
+        publisher: OnceLock::new(),
+    };
+
+    run_sink(config, &args.sink, handler).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use emergent_testkit::{SinkFixture, fixtures, spawn_sink};
+    use primitive_common::reload::ReloadArgs;
+    use tokio::net::UdpSocket;
+
+    #[tokio::test]
+    async fn wake_sends_a_magic_packet_and_confirms() {
+        let receiver = UdpSocket::bind("127.0.0.1:0")
+            .await
+            .unwrap_or_else(|e| panic!("bind: {e}"));
+        let address = receiver
+            .local_addr()
+            .unwrap_or_else(|e| panic!("local_addr: {e}"));
+        let hosts = BTreeMap::from([(
+            "nas".to_string(),
+            Host {
+                mac: Some("00:11:22:33:44:55".to_string()),
+                broadcast: Some(address.to_string()),
+                ..Default::default()
+            },
+        )]);
+        let settings = HotConfig::load(&ReloadArgs::default(), Settings { hosts })
+            .unwrap_or_else(|e| panic!("load settings: {e}"));
+        let handler = PowerSink {
+            settings,
+            client: Client::new(),
+            insecure_client: Client::new(),
+            broadcast: address,
+            ipmitool: "ipmitool".to_string(),
+            timeout: Duration::from_secs(5),
+            publisher: OnceLock::new(),
+        };
+        let (mut engine, run) = spawn_sink(
+            SinkFixture::new("power_sink", "power"),
+            SinkArgs::default(),
+            handler,
+        );
+
+        engine
+            .inject_message(fixtures::message(
+                "power.request",
+                json!({"op": "wake", "host": "nas"}),
+            ))
+            .await;
+        let mut packet = [0; 256];
+        let (len, _) = receiver
+            .recv_from(&mut packet)
+            .await
+            .unwrap_or_else(|e| panic!("recv: {e}"));
+        assert_eq!(
+            packet[..len],
+            wol::magic_packet([0x00, 0x11, 0x22, 0x33, 0x44, 0x55])
+        );
+
+        let result = engine.expect_published(RESULT_EVENT_TYPE).await;
+        assert_eq!(result.payload()["op"], "wake");
+        assert_eq!(result.payload()["host"], "nas");
+        assert_eq!(result.payload()["method"], "wol");
+        assert_eq!(result.payload()["ok"], true);
+
+        engine.close();
+        assert_eq!(run.await.ok(), Some(Ok(())));
+    }
+}
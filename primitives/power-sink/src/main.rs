@@ -0,0 +1,6 @@
+//! `power-sink` binary; the primitive itself lives in the library.
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    power_sink::run(std::env::args_os()).await
+}
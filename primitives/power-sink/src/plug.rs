@@ -0,0 +1,104 @@
+//! Smart plug HTTP APIs.
+//!
+//! - `shelly` - Gen1 devices: `/relay/<channel>?turn=on|off`, state in `ison`.
+//! - `shelly-rpc` - Gen2 and later: `/rpc/Switch.Set?id=<channel>&on=true|false`
+//!   and `/rpc/Switch.GetStatus`, state in `output`.
+//! - `tasmota` - `/cm?cmnd=Power<n> On|Off`, state in `POWER<n>` (or `POWER`).
+
+use crate::registry::{Op, Plug, PlugKind};
+use reqwest::Client;
+use serde_json::Value;
+
+/// The URL carrying out `op` (`on`, `off` or `status`).
+pub fn url(plug: &Plug, op: Op) -> Result<String, String> {
+    let base = plug.url.trim_end_matches('/');
+    let channel = plug.channel;
+    let url = match (plug.kind, op) {
+        (PlugKind::Shelly, Op::On) => format!("{base}/relay/{channel}?turn=on"),
+        (PlugKind::Shelly, Op::Off) => format!("{base}/relay/{channel}?turn=off"),
+        (PlugKind::Shelly, Op::Status) => format!("{base}/relay/{channel}"),
+        (PlugKind::ShellyRpc, Op::On) => format!("{base}/rpc/Switch.Set?id={channel}&on=true"),
+        (PlugKind::ShellyRpc, Op::Off) => format!("{base}/rpc/Switch.Set?id={channel}&on=false"),
+        (PlugKind::ShellyRpc, Op::Status) => format!("{base}/rpc/Switch.GetStatus?id={channel}"),
+        (PlugKind::Tasmota, Op::On) => format!("{base}/cm?cmnd=Power{}%20On", channel + 1),
+        (PlugKind::Tasmota, Op::Off) => format!("{base}/cm?cmnd=Power{}%20Off", channel + 1),
+        (PlugKind::Tasmota, Op::Status) => format!("{base}/cm?cmnd=Power{}", channel + 1),
+        (_, op) => return Err(format!("plugs cannot {}", op.as_str())),
+    };
+    Ok(url)
+}
+
+/// The relay state a response reports, `on` or `off`.
+pub fn state(kind: PlugKind, response: &Value) -> Option<String> {
+    let on = match kind {
+        PlugKind::Shelly => response["ison"].as_bool()?,
+        PlugKind::ShellyRpc => response["output"].as_bool()?,
+        PlugKind::Tasmota => {
+            let object = response.as_object()?;
+            let (_, power) = object.iter().find(|(key, _)| key.starts_with("POWER"))?;
+            power.as_str()? == "ON"
+        }
+    };
+    Some(if on { "on" } else { "off" }.to_string())
+}
+
+/// Switch or query the plug, returning the state it reports.
+pub async fn switch(client: &Client, plug: &Plug, op: Op) -> Result<Option<String>, String> {
+    let url = url(plug, op)?;
+    let response: Value = client
+        .get(&url)
+        .send()
+        .await
+        .and_then(reqwest::Response::error_for_status)
+        .map_err(|e| format!("{url}: {e}"))?
+        .json()
+        .await
+        .map_err(|e| format!("{url}: {e}"))?;
+    // Shelly Gen2 answers a switch with the previous state only
+    Ok(match (plug.kind, op) {
+        (PlugKind::ShellyRpc, Op::On) => Some("on".to_string()),
+        (PlugKind::ShellyRpc, Op::Off) => Some("off".to_string()),
+        (kind, _) => state(kind, &response),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn each_firmware_has_its_own_urls_and_replies() {
+        let plug = |kind| Plug {
+            kind,
+            url: "http://10.0.0.7/".to_string(),
+            channel: 1,
+        };
+        assert_eq!(
+            url(&plug(PlugKind::Shelly), Op::On).as_deref(),
+            Ok("http://10.0.0.7/relay/1?turn=on")
+        );
+        assert_eq!(
+            url(&plug(PlugKind::ShellyRpc), Op::Status).as_deref(),
+            Ok("http://10.0.0.7/rpc/Switch.GetStatus?id=1")
+        );
+        assert_eq!(
+            url(&plug(PlugKind::Tasmota), Op::Off).as_deref(),
+            Ok("http://10.0.0.7/cm?cmnd=Power2%20Off")
+        );
+        assert!(url(&plug(PlugKind::Tasmota), Op::Cycle).is_err());
+
+        assert_eq!(
+            state(PlugKind::Shelly, &json!({"ison": true})).as_deref(),
+            Some("on")
+        );
+        assert_eq!(
+            state(PlugKind::ShellyRpc, &json!({"id": 1, "output": false})).as_deref(),
+            Some("off")
+        );
+        assert_eq!(
+            state(PlugKind::Tasmota, &json!({"POWER2": "ON"})).as_deref(),
+            Some("on")
+        );
+    }
+}
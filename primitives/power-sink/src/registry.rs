@@ -0,0 +1,239 @@
+//! The hosts the sink can power, as listed in `--config`.
+//!
+//! ```json
+//! {"hosts": {
+//!   "nas":   {"mac": "00:11:22:33:44:55", "broadcast": "192.168.1.255:9",
+//!             "ipmi": {"host": "10.0.0.5", "username": "admin", "password": "..."}},
+//!   "web1":  {"redfish": {"url": "https://10.0.0.6", "username": "root", "password": "...", "insecure": true}},
+//!   "lamp":  {"plug": {"kind": "shelly", "url": "http://10.0.0.7"}}
+//! }}
+//! ```
+//!
+//! Each entry may combine a MAC address (for `wake`), a BMC reachable over
+//! IPMI or Redfish, and a smart plug. The file is re-read on SIGHUP.
+
+use serde::{Deserialize, Serialize};
+
+/// What a payload asks for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Op {
+    Wake,
+    On,
+    Off,
+    Cycle,
+    Reset,
+    Shutdown,
+    Status,
+}
+
+impl Op {
+    pub fn parse(op: &str) -> Option<Self> {
+        Some(match op {
+            "wake" => Self::Wake,
+            "on" => Self::On,
+            "off" => Self::Off,
+            "cycle" => Self::Cycle,
+            "reset" => Self::Reset,
+            "shutdown" => Self::Shutdown,
+            "status" => Self::Status,
+            _ => return None,
+        })
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Wake => "wake",
+            Self::On => "on",
+            Self::Off => "off",
+            Self::Cycle => "cycle",
+            Self::Reset => "reset",
+            Self::Shutdown => "shutdown",
+            Self::Status => "status",
+        }
+    }
+}
+
+/// A BMC reached with ipmitool over the network.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Ipmi {
+    pub host: String,
+    pub username: String,
+    pub password: String,
+    /// ipmitool interface.
+    #[serde(default = "default_interface")]
+    pub interface: String,
+}
+
+fn default_interface() -> String {
+    "lanplus".to_string()
+}
+
+/// A BMC with a Redfish API.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Redfish {
+    /// Base URL, e.g. `https://10.0.0.6`.
+    pub url: String,
+    pub username: String,
+    pub password: String,
+    /// System resource, e.g. `/redfish/v1/Systems/1` (default: the first system).
+    #[serde(default)]
+    pub system: Option<String>,
+    /// Accept the BMC's self-signed certificate.
+    #[serde(default)]
+    pub insecure: bool,
+}
+
+/// Smart plug firmware.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum PlugKind {
+    /// Shelly Gen1 HTTP API.
+    Shelly,
+    /// Shelly Gen2+ RPC API.
+    ShellyRpc,
+    Tasmota,
+}
+
+/// A smart plug switching the host's power.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Plug {
+    pub kind: PlugKind,
+    /// Base URL, e.g. `http://10.0.0.7`.
+    pub url: String,
+    /// Relay on multi-channel devices, from 0.
+    #[serde(default)]
+    pub channel: u32,
+}
+
+/// One powered host.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Host {
+    #[serde(default)]
+    pub mac: Option<String>,
+    /// Where its magic packets go (default: `--broadcast`).
+    #[serde(default)]
+    pub broadcast: Option<String>,
+    #[serde(default)]
+    pub ipmi: Option<Ipmi>,
+    #[serde(default)]
+    pub redfish: Option<Redfish>,
+    #[serde(default)]
+    pub plug: Option<Plug>,
+}
+
+/// How an operation reaches the host.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Method {
+    Wol,
+    Ipmi,
+    Redfish,
+    Plug,
+}
+
+impl Method {
+    pub fn parse(method: &str) -> Option<Self> {
+        Some(match method {
+            "wol" => Self::Wol,
+            "ipmi" => Self::Ipmi,
+            "redfish" => Self::Redfish,
+            "plug" => Self::Plug,
+            _ => return None,
+        })
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Wol => "wol",
+            Self::Ipmi => "ipmi",
+            Self::Redfish => "redfish",
+            Self::Plug => "plug",
+        }
+    }
+}
+
+impl Host {
+    fn has(&self, method: Method) -> bool {
+        match method {
+            Method::Wol => self.mac.is_some(),
+            Method::Ipmi => self.ipmi.is_some(),
+            Method::Redfish => self.redfish.is_some(),
+            Method::Plug => self.plug.is_some(),
+        }
+    }
+
+    /// The method carrying out `op`: `via` if given, otherwise Redfish,
+    /// IPMI, then the plug. `wake` is always Wake-on-LAN, and `on` falls
+    /// back to it.
+    pub fn method(&self, op: Op, via: Option<Method>) -> Result<Method, String> {
+        let candidates: &[Method] = match (op, via) {
+            (_, Some(via)) => &[via],
+            (Op::Wake, None) => &[Method::Wol],
+            (Op::On, None) => &[Method::Redfish, Method::Ipmi, Method::Plug, Method::Wol],
+            (_, None) => &[Method::Redfish, Method::Ipmi, Method::Plug],
+        };
+        let Some(method) = candidates.iter().copied().find(|m| self.has(*m)) else {
+            let wanted: Vec<&str> = candidates.iter().map(|m| m.as_str()).collect();
+            return Err(format!("no {} configured", wanted.join(" or ")));
+        };
+        let supported = match method {
+            Method::Wol => matches!(op, Op::Wake | Op::On),
+            Method::Plug => matches!(op, Op::On | Op::Off | Op::Status),
+            Method::Ipmi | Method::Redfish => op != Op::Wake,
+        };
+        if supported {
+            Ok(method)
+        } else {
+            Err(format!("{} cannot {}", method.as_str(), op.as_str()))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn operations_prefer_the_bmc() {
+        let plug = Plug {
+            kind: PlugKind::Tasmota,
+            url: "http://10.0.0.7".to_string(),
+            channel: 0,
+        };
+        let host = Host {
+            mac: Some("00:11:22:33:44:55".to_string()),
+            ipmi: Some(Ipmi {
+                host: "10.0.0.5".to_string(),
+                username: "admin".to_string(),
+                password: "s3cret".to_string(),
+                interface: default_interface(),
+            }),
+            plug: Some(plug.clone()),
+            ..Default::default()
+        };
+        assert_eq!(host.method(Op::Wake, None), Ok(Method::Wol));
+        assert_eq!(host.method(Op::On, None), Ok(Method::Ipmi));
+        assert_eq!(host.method(Op::Off, Some(Method::Plug)), Ok(Method::Plug));
+        assert!(host.method(Op::Reset, Some(Method::Plug)).is_err());
+        assert!(host.method(Op::Off, Some(Method::Redfish)).is_err());
+
+        let lamp = Host {
+            plug: Some(plug),
+            ..Default::default()
+        };
+        assert_eq!(lamp.method(Op::On, None), Ok(Method::Plug));
+        assert_eq!(
+            lamp.method(Op::Wake, None),
+            Err("no wol configured".to_string())
+        );
+        let sleeper = Host {
+            mac: Some("00:11:22:33:44:55".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(sleeper.method(Op::On, None), Ok(Method::Wol));
+        assert!(sleeper.method(Op::Off, None).is_err());
+    }
+}
@@ -0,0 +1,70 @@
+//! Wake-on-LAN magic packets.
+//!
+//! A magic packet is six `0xFF` bytes followed by the target's MAC address
+//! sixteen times, sent as a UDP broadcast (port 9 by convention) that the
+//! sleeping machine's network card listens for.
+
+use std::net::SocketAddr;
+use tokio::net::UdpSocket;
+
+/// Parse a MAC address written with `:`, `-` or no separators.
+pub fn parse_mac(mac: &str) -> Result<[u8; 6], String> {
+    let digits: String = mac.chars().filter(|c| !matches!(c, ':' | '-')).collect();
+    if digits.len() != 12 || !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(format!("'{mac}' is not a MAC address"));
+    }
+    let mut bytes = [0; 6];
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&digits[i * 2..i * 2 + 2], 16)
+            .map_err(|_| format!("'{mac}' is not a MAC address"))?;
+    }
+    Ok(bytes)
+}
+
+pub fn magic_packet(mac: [u8; 6]) -> Vec<u8> {
+    let mut packet = vec![0xFF; 6];
+    for _ in 0..16 {
+        packet.extend_from_slice(&mac);
+    }
+    packet
+}
+
+/// Send the magic packet for `mac` to `target`.
+pub async fn wake(mac: [u8; 6], target: SocketAddr) -> Result<(), String> {
+    let bind = if target.is_ipv4() {
+        "0.0.0.0:0"
+    } else {
+        "[::]:0"
+    };
+    let socket = UdpSocket::bind(bind)
+        .await
+        .map_err(|e| format!("bind: {e}"))?;
+    socket
+        .set_broadcast(true)
+        .map_err(|e| format!("broadcast: {e}"))?;
+    socket
+        .send_to(&magic_packet(mac), target)
+        .await
+        .map(|_| ())
+        .map_err(|e| format!("{target}: {e}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn packets_repeat_the_mac_after_a_sync_stream() {
+        let mac = parse_mac("00:11:22:aa:BB:cc").unwrap_or_else(|e| panic!("{e}"));
+        assert_eq!(mac, [0x00, 0x11, 0x22, 0xAA, 0xBB, 0xCC]);
+        assert_eq!(parse_mac("00-11-22-aa-bb-cc"), Ok(mac));
+        assert_eq!(parse_mac("001122aabbcc"), Ok(mac));
+        assert!(parse_mac("00:11:22:aa:bb").is_err());
+        assert!(parse_mac("00:11:22:aa:bb:zz").is_err());
+
+        let packet = magic_packet(mac);
+        assert_eq!(packet.len(), 102);
+        assert_eq!(packet[..6], [0xFF; 6]);
+        assert!(packet[6..].chunks(6).all(|chunk| chunk == mac));
+    }
+}
@@ -0,0 +1,41 @@
+//! Records the build's git commit for `--version` and capability descriptors.
+//!
+//! `EMERGENT_GIT_SHA` overrides the lookup (for builds from a source tarball
+//! or a CI checkout without `.git`). Without either, the SHA is left unset.
+
+use std::{env, path::Path, process::Command};
+
+fn main() {
+    let repo = Path::new(env!("CARGO_MANIFEST_DIR")).join("../..");
+    println!("cargo:rerun-if-env-changed=EMERGENT_GIT_SHA");
+    println!(
+        "cargo:rerun-if-changed={}",
+        repo.join(".git/HEAD").display()
+    );
+    println!(
+        "cargo:rerun-if-changed={}",
+        repo.join(".git/refs/heads").display()
+    );
+
+    let sha = env::var("EMERGENT_GIT_SHA").ok().or_else(|| {
+        let output = Command::new("git")
+            .args(["rev-parse", "--short=12", "HEAD"])
+            .current_dir(&repo)
+            .output()
+            .ok()?;
+        output
+            .status
+            .success()
+            .then(|| String::from_utf8_lossy(&output.stdout).trim().to_string())
+    });
+    let sha = sha.filter(|s| !s.is_empty());
+
+    let version = env!("CARGO_PKG_VERSION");
+    match &sha {
+        Some(sha) => {
+            println!("cargo:rustc-env=EMERGENT_BUILD_SHA={sha}");
+            println!("cargo:rustc-env=EMERGENT_BUILD_VERSION={version} ({sha})");
+        }
+        None => println!("cargo:rustc-env=EMERGENT_BUILD_VERSION={version}"),
+    }
+}
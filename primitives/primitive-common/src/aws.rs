@@ -0,0 +1,900 @@
+//! AWS Signature Version 4, for the primitives that call AWS APIs (or
+//! S3-compatible stores) over plain HTTP.
+//!
+//! [`SigV4::sign`] signs a built [`reqwest::Request`] in place: it adds
+//! `x-amz-date` (and `x-amz-security-token` for temporary credentials) and
+//! sets `Authorization`. The signature covers `host`, `content-type` and
+//! every `x-amz-*` header already on the request. For `s3` the payload hash
+//! is also sent as `x-amz-content-sha256`; bodies that are streamed rather
+//! than buffered are sent as `UNSIGNED-PAYLOAD`, which S3 accepts.
+//!
+//! [`CredentialChain`] finds the credentials to sign with the way AWS SDKs
+//! do, from keys, a profile, a web identity, the container endpoint or
+//! instance metadata, and fetches temporary ones again before they expire.
+//! [`default_region`] falls back from `AWS_REGION` to `AWS_DEFAULT_REGION`.
+
+use crate::time;
+use hmac::{Hmac, Mac};
+use reqwest::header::{AUTHORIZATION, HeaderName, HeaderValue};
+use reqwest::{Client, Request, Response};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::Mutex;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Payload hash of bodies that are streamed rather than signed.
+pub const UNSIGNED_PAYLOAD: &str = "UNSIGNED-PAYLOAD";
+
+/// Access key and secret, with the session token of temporary credentials.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Credentials {
+    pub access_key_id: String,
+    pub secret_access_key: String,
+    pub session_token: Option<String>,
+}
+
+impl Credentials {
+    /// The credentials of `profile` in an AWS shared credentials file.
+    pub fn from_profile(text: &str, profile: &str) -> Option<Self> {
+        let mut in_profile = false;
+        let (mut key_id, mut secret, mut token) = (None, None, None);
+        for line in text.lines().map(str::trim) {
+            if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+                in_profile = name.trim() == profile;
+                continue;
+            }
+            let Some((name, value)) = line.split_once('=') else {
+                continue;
+            };
+            if !in_profile {
+                continue;
+            }
+            let value = Some(value.trim().to_string());
+            match name.trim() {
+                "aws_access_key_id" => key_id = value,
+                "aws_secret_access_key" => secret = value,
+                "aws_session_token" => token = value,
+                _ => {}
+            }
+        }
+        Some(Self {
+            access_key_id: key_id?,
+            secret_access_key: secret?,
+            session_token: token,
+        })
+    }
+
+    /// The credentials of `profile` in the shared credentials file
+    /// (`~/.aws/credentials`, or `AWS_SHARED_CREDENTIALS_FILE`).
+    pub fn from_shared_file(profile: &str) -> Result<Self, String> {
+        let path = std::env::var_os("AWS_SHARED_CREDENTIALS_FILE")
+            .map(PathBuf::from)
+            .or_else(|| {
+                std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".aws/credentials"))
+            })
+            .ok_or("no AWS credentials: set AWS_ACCESS_KEY_ID and AWS_SECRET_ACCESS_KEY")?;
+        let text = std::fs::read_to_string(&path).map_err(|e| {
+            format!(
+                "no AWS credentials: set AWS_ACCESS_KEY_ID and AWS_SECRET_ACCESS_KEY, or {}: {e}",
+                path.display()
+            )
+        })?;
+        Self::from_profile(&text, profile)
+            .ok_or_else(|| format!("{}: no credentials for profile '{profile}'", path.display()))
+    }
+}
+
+/// HMAC-SHA256 of `data` under `key`.
+pub fn hmac(key: &[u8], data: &[u8]) -> Vec<u8> {
+    // HMAC takes keys of any length
+    let Ok(mut mac) = HmacSha256::new_from_slice(key) else {
+        return Vec::new();
+    };
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// The SigV4 key for requests to `service` in `region` on `date`
+/// (`YYYYMMDD`).
+pub fn signing_key(secret: &str, date: &str, region: &str, service: &str) -> Vec<u8> {
+    let key = hmac(format!("AWS4{secret}").as_bytes(), date.as_bytes());
+    let key = hmac(&key, region.as_bytes());
+    let key = hmac(&key, service.as_bytes());
+    hmac(&key, b"aws4_request")
+}
+
+/// `bytes` percent-encoded as SigV4 requires: everything but unreserved
+/// characters.
+pub fn uri_encode(bytes: &[u8]) -> String {
+    let mut encoded = String::new();
+    for &byte in bytes {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                encoded.push(char::from(byte));
+            }
+            _ => encoded.push_str(&format!("%{byte:02X}")),
+        }
+    }
+    encoded
+}
+
+/// `path` percent-encoded segment by segment, keeping its `/`s, as S3
+/// object keys are.
+pub fn uri_encode_path(path: &str) -> String {
+    path.split('/')
+        .map(|segment| uri_encode(segment.as_bytes()))
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// `text` with `%XX` escapes decoded.
+fn percent_decode(text: &str) -> Vec<u8> {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut at = 0;
+    while at < bytes.len() {
+        let escaped = (bytes[at] == b'%')
+            .then(|| text.get(at + 1..at + 3))
+            .flatten()
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                at += 3;
+            }
+            None => {
+                decoded.push(bytes[at]);
+                at += 1;
+            }
+        }
+    }
+    decoded
+}
+
+/// `now` as `20240520T161558Z`.
+pub fn amz_date(now: SystemTime) -> String {
+    time::compact(
+        now.duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs() as i64)
+            .unwrap_or(0),
+    )
+}
+
+fn header_value(value: &str) -> Result<HeaderValue, String> {
+    HeaderValue::from_str(value).map_err(|e| format!("invalid header value: {e}"))
+}
+
+/// Where requests are signed for.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SigV4 {
+    pub region: String,
+    pub service: String,
+}
+
+impl SigV4 {
+    pub fn new(region: &str, service: &str) -> Self {
+        Self {
+            region: region.to_string(),
+            service: service.to_string(),
+        }
+    }
+
+    /// Sign `request` with `credentials` as of `now`.
+    pub fn sign(
+        &self,
+        request: &mut Request,
+        credentials: &Credentials,
+        now: SystemTime,
+    ) -> Result<(), String> {
+        let payload_hash = match request.body() {
+            Some(body) => body.as_bytes().map_or_else(
+                || UNSIGNED_PAYLOAD.to_string(),
+                |body| hex::encode(Sha256::digest(body)),
+            ),
+            None => hex::encode(Sha256::digest(b"")),
+        };
+        let url = request.url();
+        let host = match (url.host_str(), url.port()) {
+            (Some(host), Some(port)) => format!("{host}:{port}"),
+            (Some(host), None) => host.to_string(),
+            (None, _) => return Err(format!("{url}: no host to sign for")),
+        };
+        // S3 encodes paths once, every other service twice
+        let s3 = self.service == "s3";
+        let path = url
+            .path()
+            .split('/')
+            .map(|segment| {
+                let once = uri_encode(&percent_decode(segment));
+                if s3 {
+                    once
+                } else {
+                    uri_encode(once.as_bytes())
+                }
+            })
+            .collect::<Vec<_>>()
+            .join("/");
+        let mut query: Vec<String> = url
+            .query()
+            .unwrap_or_default()
+            .split('&')
+            .filter(|pair| !pair.is_empty())
+            .map(|pair| {
+                let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+                format!(
+                    "{}={}",
+                    uri_encode(&percent_decode(name)),
+                    uri_encode(&percent_decode(value))
+                )
+            })
+            .collect();
+        query.sort();
+        let query = query.join("&");
+
+        let timestamp = amz_date(now);
+        let date = &timestamp[..8];
+        let mut headers = vec![
+            ("host".to_string(), host),
+            ("x-amz-date".to_string(), timestamp.clone()),
+        ];
+        if s3 {
+            headers.push(("x-amz-content-sha256".to_string(), payload_hash.clone()));
+        }
+        if let Some(token) = &credentials.session_token {
+            headers.push(("x-amz-security-token".to_string(), token.clone()));
+        }
+        for (name, value) in request.headers() {
+            let name = name.as_str();
+            let signed = name == "content-type" || name.starts_with("x-amz-");
+            if signed && !headers.iter().any(|(known, _)| known == name) {
+                let value = value.to_str().map_err(|e| format!("{name}: {e}"))?;
+                headers.push((name.to_string(), value.to_string()));
+            }
+        }
+        headers.sort();
+        let canonical_headers: String = headers
+            .iter()
+            .map(|(name, value)| format!("{name}:{}\n", value.trim()))
+            .collect();
+        let signed_headers = headers
+            .iter()
+            .map(|(name, _)| name.as_str())
+            .collect::<Vec<_>>()
+            .join(";");
+        let canonical_request = format!(
+            "{}\n{path}\n{query}\n{canonical_headers}\n{signed_headers}\n{payload_hash}",
+            request.method()
+        );
+        let scope = format!("{date}/{}/{}/aws4_request", self.region, self.service);
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{timestamp}\n{scope}\n{}",
+            hex::encode(Sha256::digest(canonical_request.as_bytes()))
+        );
+        let key = signing_key(
+            &credentials.secret_access_key,
+            date,
+            &self.region,
+            &self.service,
+        );
+        let signature = hex::encode(hmac(&key, string_to_sign.as_bytes()));
+        let authorization = format!(
+            "AWS4-HMAC-SHA256 Credential={}/{scope}, SignedHeaders={signed_headers}, Signature={signature}",
+            credentials.access_key_id
+        );
+
+        let target = request.headers_mut();
+        target.insert(AUTHORIZATION, header_value(&authorization)?);
+        for (name, value) in headers {
+            if name == "host" || target.contains_key(name.as_str()) {
+                continue;
+            }
+            let name = HeaderName::from_bytes(name.as_bytes()).map_err(|e| e.to_string())?;
+            target.insert(name, header_value(&value)?);
+        }
+        Ok(())
+    }
+}
+
+/// How long before temporary credentials expire they are fetched again.
+const REFRESH_BEFORE_SECS: i64 = 300;
+
+/// The region AWS tools default to: `AWS_REGION`, then
+/// `AWS_DEFAULT_REGION`, then `us-east-1`.
+pub fn default_region() -> String {
+    ["AWS_REGION", "AWS_DEFAULT_REGION"]
+        .into_iter()
+        .find_map(env)
+        .unwrap_or_else(|| "us-east-1".to_string())
+}
+
+fn env(name: &str) -> Option<String> {
+    std::env::var(name).ok().filter(|value| !value.is_empty())
+}
+
+/// A role assumed with a web identity token, as EKS service accounts and
+/// CI OIDC providers hand out.
+#[derive(Debug, Clone, PartialEq)]
+struct WebIdentity {
+    token_file: PathBuf,
+    role_arn: String,
+    session_name: String,
+}
+
+/// A container credentials endpoint (ECS tasks, EKS Pod Identity).
+#[derive(Debug, Clone, PartialEq)]
+struct Container {
+    url: String,
+    token: Option<String>,
+    token_file: Option<PathBuf>,
+}
+
+/// Where [`CredentialChain`] looks, as the environment configures it.
+#[derive(Debug, Clone, Default)]
+struct Sources {
+    fixed: Option<Credentials>,
+    profile: String,
+    web_identity: Option<WebIdentity>,
+    /// STS endpoint web identity tokens are exchanged at.
+    sts: String,
+    container: Option<Container>,
+    /// Instance metadata endpoint, unless `AWS_EC2_METADATA_DISABLED`.
+    imds: Option<String>,
+}
+
+impl Sources {
+    fn from_env(fixed: Option<Credentials>, profile: &str) -> Self {
+        let fixed = fixed.or_else(|| {
+            Some(Credentials {
+                access_key_id: env("AWS_ACCESS_KEY_ID")?,
+                secret_access_key: env("AWS_SECRET_ACCESS_KEY")?,
+                session_token: env("AWS_SESSION_TOKEN"),
+            })
+        });
+        let web_identity = match (env("AWS_WEB_IDENTITY_TOKEN_FILE"), env("AWS_ROLE_ARN")) {
+            (Some(token_file), Some(role_arn)) => Some(WebIdentity {
+                token_file: PathBuf::from(token_file),
+                role_arn,
+                session_name: env("AWS_ROLE_SESSION_NAME")
+                    .unwrap_or_else(|| format!("emergent-{}", time::now())),
+            }),
+            _ => None,
+        };
+        let container = env("AWS_CONTAINER_CREDENTIALS_RELATIVE_URI")
+            .map(|path| format!("http://169.254.170.2{path}"))
+            .or_else(|| env("AWS_CONTAINER_CREDENTIALS_FULL_URI"))
+            .map(|url| Container {
+                url,
+                token: env("AWS_CONTAINER_AUTHORIZATION_TOKEN"),
+                token_file: env("AWS_CONTAINER_AUTHORIZATION_TOKEN_FILE").map(PathBuf::from),
+            });
+        let imds = match env("AWS_EC2_METADATA_DISABLED") {
+            Some(disabled) if disabled.eq_ignore_ascii_case("true") => None,
+            _ => Some(
+                env("AWS_EC2_METADATA_SERVICE_ENDPOINT")
+                    .unwrap_or_else(|| "http://169.254.169.254".to_string()),
+            ),
+        };
+        Self {
+            fixed,
+            profile: profile.to_string(),
+            web_identity,
+            sts: env("AWS_ENDPOINT_URL_STS")
+                .unwrap_or_else(|| format!("https://sts.{}.amazonaws.com", default_region())),
+            container,
+            imds,
+        }
+    }
+}
+
+/// Credentials and when they expire (Unix seconds), if they do.
+#[derive(Debug, Clone)]
+struct Fetched {
+    credentials: Credentials,
+    expires_at: Option<i64>,
+}
+
+impl Fetched {
+    fn lasting(credentials: Credentials) -> Self {
+        Self {
+            credentials,
+            expires_at: None,
+        }
+    }
+
+    /// Whether these are due to be fetched again at `now`.
+    fn stale(&self, now: i64) -> bool {
+        self.expires_at
+            .is_some_and(|at| now >= at - REFRESH_BEFORE_SECS)
+    }
+
+    /// Credentials in the JSON the container and instance metadata
+    /// endpoints answer with.
+    fn from_json(value: &Value) -> Option<Self> {
+        let field = |name: &str| value[name].as_str().map(str::to_string);
+        Some(Self {
+            credentials: Credentials {
+                access_key_id: field("AccessKeyId")?,
+                secret_access_key: field("SecretAccessKey")?,
+                session_token: field("Token"),
+            },
+            expires_at: value["Expiration"].as_str().and_then(time::parse),
+        })
+    }
+}
+
+/// The text of the first `<tag>` element in `xml`.
+fn element(xml: &str, tag: &str) -> Option<String> {
+    let open = format!("<{tag}>");
+    let start = xml.find(&open)? + open.len();
+    let end = start + xml[start..].find(&format!("</{tag}>"))?;
+    Some(xml[start..end].trim().to_string())
+}
+
+/// AWS credentials, found where AWS SDKs look for them:
+///
+/// 1. keys given outright, or `AWS_ACCESS_KEY_ID`, `AWS_SECRET_ACCESS_KEY`
+///    and `AWS_SESSION_TOKEN`
+/// 2. the profile in the shared credentials file
+/// 3. a web identity token (`AWS_WEB_IDENTITY_TOKEN_FILE` and
+///    `AWS_ROLE_ARN`) exchanged with STS
+/// 4. the container credentials endpoint
+///    (`AWS_CONTAINER_CREDENTIALS_RELATIVE_URI` or `_FULL_URI`)
+/// 5. EC2 instance metadata (IMDSv2)
+///
+/// Temporary credentials are fetched again shortly before they expire;
+/// the rest are kept for good. Clones share one cache.
+#[derive(Debug, Clone)]
+pub struct CredentialChain {
+    client: Client,
+    sources: Arc<Sources>,
+    cached: Arc<Mutex<Option<Fetched>>>,
+}
+
+impl CredentialChain {
+    /// The chain the environment configures, starting with `fixed` keys
+    /// when given and reading `profile` (default: `AWS_PROFILE`, or
+    /// `default`) from the shared credentials file.
+    pub fn new(client: Client, fixed: Option<Credentials>, profile: Option<&str>) -> Self {
+        let profile = profile
+            .map(str::to_string)
+            .or_else(|| env("AWS_PROFILE"))
+            .unwrap_or_else(|| "default".to_string());
+        Self::with_sources(client, Sources::from_env(fixed, &profile))
+    }
+
+    fn with_sources(client: Client, sources: Sources) -> Self {
+        Self {
+            client,
+            sources: Arc::new(sources),
+            cached: Arc::default(),
+        }
+    }
+
+    /// Credentials to sign with now.
+    pub async fn credentials(&self) -> Result<Credentials, String> {
+        let mut cached = self.cached.lock().await;
+        let now = time::now();
+        if let Some(fetched) = cached.as_ref().filter(|fetched| !fetched.stale(now)) {
+            return Ok(fetched.credentials.clone());
+        }
+        match self.fetch().await {
+            Ok(fetched) => {
+                let credentials = fetched.credentials.clone();
+                *cached = Some(fetched);
+                Ok(credentials)
+            }
+            // Keep signing with the old ones while they last
+            Err(e) => match cached.as_ref() {
+                Some(fetched) if fetched.expires_at.is_some_and(|at| now < at) => {
+                    eprintln!("Failed to refresh AWS credentials: {e}");
+                    Ok(fetched.credentials.clone())
+                }
+                _ => Err(e),
+            },
+        }
+    }
+
+    async fn fetch(&self) -> Result<Fetched, String> {
+        let sources = &self.sources;
+        if let Some(credentials) = &sources.fixed {
+            return Ok(Fetched::lasting(credentials.clone()));
+        }
+        if let Ok(credentials) = Credentials::from_shared_file(&sources.profile) {
+            return Ok(Fetched::lasting(credentials));
+        }
+        if let Some(identity) = &sources.web_identity {
+            return self.web_identity(identity).await;
+        }
+        if let Some(container) = &sources.container {
+            return self.container(container).await;
+        }
+        match &sources.imds {
+            Some(endpoint) => self.instance(endpoint).await.map_err(|e| {
+                format!(
+                    "no AWS credentials: set AWS_ACCESS_KEY_ID and AWS_SECRET_ACCESS_KEY, \
+                     a profile in ~/.aws/credentials, or a role (instance metadata: {e})"
+                )
+            }),
+            None => Err(
+                "no AWS credentials: set AWS_ACCESS_KEY_ID and AWS_SECRET_ACCESS_KEY, \
+                 a profile in ~/.aws/credentials, or a role"
+                    .to_string(),
+            ),
+        }
+    }
+
+    async fn web_identity(&self, identity: &WebIdentity) -> Result<Fetched, String> {
+        let token = std::fs::read_to_string(&identity.token_file)
+            .map_err(|e| format!("{}: {e}", identity.token_file.display()))?;
+        let response = self
+            .client
+            .get(&self.sources.sts)
+            .query(&[
+                ("Action", "AssumeRoleWithWebIdentity"),
+                ("Version", "2011-06-15"),
+                ("RoleArn", identity.role_arn.as_str()),
+                ("RoleSessionName", identity.session_name.as_str()),
+                ("WebIdentityToken", token.trim()),
+            ])
+            .send()
+            .await
+            .map_err(|e| format!("STS: {e}"))?;
+        let status = response.status();
+        let xml = response.text().await.unwrap_or_default();
+        if !status.is_success() {
+            let message = element(&xml, "Message").unwrap_or_default();
+            return Err(format!("STS: {status} {message}").trim_end().to_string());
+        }
+        let credentials = Credentials {
+            access_key_id: element(&xml, "AccessKeyId").ok_or("STS: no AccessKeyId")?,
+            secret_access_key: element(&xml, "SecretAccessKey").ok_or("STS: no SecretAccessKey")?,
+            session_token: element(&xml, "SessionToken"),
+        };
+        Ok(Fetched {
+            credentials,
+            expires_at: element(&xml, "Expiration").as_deref().and_then(time::parse),
+        })
+    }
+
+    async fn container(&self, container: &Container) -> Result<Fetched, String> {
+        let token = match &container.token_file {
+            Some(path) => Some(
+                std::fs::read_to_string(path)
+                    .map_err(|e| format!("{}: {e}", path.display()))?
+                    .trim()
+                    .to_string(),
+            ),
+            None => container.token.clone(),
+        };
+        let mut request = self
+            .client
+            .get(&container.url)
+            .timeout(Duration::from_secs(5));
+        if let Some(token) = token {
+            request = request.header(AUTHORIZATION, token);
+        }
+        let response = request
+            .send()
+            .await
+            .and_then(Response::error_for_status)
+            .map_err(|e| format!("container credentials: {e}"))?;
+        let value: Value = response
+            .json()
+            .await
+            .map_err(|e| format!("container credentials: {e}"))?;
+        Fetched::from_json(&value).ok_or_else(|| "container credentials: no keys".to_string())
+    }
+
+    async fn instance(&self, endpoint: &str) -> Result<Fetched, String> {
+        let timeout = Duration::from_secs(1);
+        let token = self
+            .client
+            .put(format!("{endpoint}/latest/api/token"))
+            .header("x-aws-ec2-metadata-token-ttl-seconds", "21600")
+            .timeout(timeout)
+            .send()
+            .await
+            .and_then(Response::error_for_status)
+            .map_err(|e| e.to_string())?
+            .text()
+            .await
+            .map_err(|e| e.to_string())?;
+        let get = |path: String| {
+            self.client
+                .get(format!(
+                    "{endpoint}/latest/meta-data/iam/security-credentials/{path}"
+                ))
+                .header("x-aws-ec2-metadata-token", &token)
+                .timeout(timeout)
+                .send()
+        };
+        let role = get(String::new())
+            .await
+            .and_then(Response::error_for_status)
+            .map_err(|e| e.to_string())?
+            .text()
+            .await
+            .map_err(|e| e.to_string())?;
+        let role = role.lines().next().unwrap_or_default().trim().to_string();
+        if role.is_empty() {
+            return Err("no role attached to the instance".to_string());
+        }
+        let value: Value = get(role)
+            .await
+            .and_then(Response::error_for_status)
+            .map_err(|e| e.to_string())?
+            .json()
+            .await
+            .map_err(|e| e.to_string())?;
+        Fetched::from_json(&value)
+            .ok_or_else(|| "no keys in the instance's credentials".to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::Router;
+    use axum::http::HeaderMap;
+    use axum::routing::{get, put};
+    use reqwest::Method;
+    use serde_json::json;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    // 2015-08-30T12:36:00Z, the date of AWS's SigV4 test suite
+    fn now() -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(1_440_938_160)
+    }
+
+    fn example() -> Credentials {
+        Credentials {
+            access_key_id: "AKIDEXAMPLE".to_string(),
+            secret_access_key: "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY".to_string(),
+            session_token: None,
+        }
+    }
+
+    fn header(request: &Request, name: &str) -> String {
+        request
+            .headers()
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or_default()
+            .to_string()
+    }
+
+    #[test]
+    fn signatures_match_the_aws_test_suite() {
+        let mut request = Client::new()
+            .request(Method::GET, "https://example.amazonaws.com/")
+            .build()
+            .unwrap_or_else(|e| panic!("{e}"));
+        SigV4::new("us-east-1", "service")
+            .sign(&mut request, &example(), now())
+            .unwrap_or_else(|e| panic!("{e}"));
+        assert_eq!(header(&request, "x-amz-date"), "20150830T123600Z");
+        assert_eq!(
+            header(&request, "authorization"),
+            "AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/20150830/us-east-1/service/aws4_request, \
+             SignedHeaders=host;x-amz-date, \
+             Signature=5fa00fa31553b73ebf1942676e86291e8372ff2a2260956d9b8aae1d763fbf31"
+        );
+        assert_eq!(
+            hex::encode(signing_key(
+                "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY",
+                "20120215",
+                "us-east-1",
+                "iam"
+            )),
+            "f4780e2d9f65fa895f9c67b32ce1baf0b0d8a43505a000a1a9e090d414db404d"
+        );
+    }
+
+    #[test]
+    fn s3_requests_carry_the_payload_hash_and_session_token() {
+        let credentials = Credentials {
+            session_token: Some("session".to_string()),
+            ..example()
+        };
+        let mut request = Client::new()
+            .request(
+                Method::PUT,
+                "https://bucket.s3.amazonaws.com/a%20b.json?x-id=PutObject",
+            )
+            .header("content-type", "application/json")
+            .header("x-amz-acl", "public-read")
+            .body(r#"{"ok":true}"#)
+            .build()
+            .unwrap_or_else(|e| panic!("{e}"));
+        SigV4::new("eu-west-1", "s3")
+            .sign(&mut request, &credentials, now())
+            .unwrap_or_else(|e| panic!("{e}"));
+        assert_eq!(
+            header(&request, "x-amz-content-sha256"),
+            hex::encode(Sha256::digest(br#"{"ok":true}"#))
+        );
+        assert_eq!(header(&request, "x-amz-security-token"), "session");
+        assert!(header(&request, "authorization").contains(
+            "/eu-west-1/s3/aws4_request, SignedHeaders=content-type;host;x-amz-acl;x-amz-content-sha256;x-amz-date;x-amz-security-token, Signature="
+        ));
+        assert_eq!(percent_decode("a%20b%2"), b"a b%2");
+        assert_eq!(uri_encode(b"status page/x"), "status%20page%2Fx");
+        assert_eq!(uri_encode_path("status page/x"), "status%20page/x");
+    }
+
+    #[test]
+    fn profiles_are_read_from_the_shared_credentials_file() {
+        let text = "[default]\naws_access_key_id = AKIDDEFAULT\naws_secret_access_key = one\n\n\
+                    [ci]\naws_access_key_id=AKIDCI\naws_secret_access_key=two\naws_session_token=t\n";
+        assert_eq!(
+            Credentials::from_profile(text, "ci"),
+            Some(Credentials {
+                access_key_id: "AKIDCI".to_string(),
+                secret_access_key: "two".to_string(),
+                session_token: Some("t".to_string()),
+            })
+        );
+        assert_eq!(
+            Credentials::from_profile(text, "default").map(|c| c.session_token),
+            Some(None)
+        );
+        assert_eq!(Credentials::from_profile(text, "prod"), None);
+    }
+
+    async fn serve(app: Router) -> String {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap_or_else(|e| panic!("bind: {e}"));
+        let addr = listener
+            .local_addr()
+            .unwrap_or_else(|e| panic!("local_addr: {e}"));
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        format!("http://{addr}")
+    }
+
+    /// Container credentials lasting `lifetime` seconds, counting fetches.
+    async fn container(lifetime: i64, fetches: Arc<AtomicUsize>) -> Container {
+        let app = Router::new().route(
+            "/creds",
+            get(move |headers: HeaderMap| {
+                let fetch = fetches.fetch_add(1, Ordering::SeqCst) + 1;
+                async move {
+                    let authorized = headers.get("authorization").is_some_and(|v| v == "pod");
+                    axum::Json(json!({
+                        "AccessKeyId": if authorized { format!("ASIA{fetch}") } else { String::new() },
+                        "SecretAccessKey": "secret",
+                        "Token": "session",
+                        "Expiration": time::format(time::now() + lifetime),
+                    }))
+                }
+            }),
+        );
+        Container {
+            url: format!("{}/creds", serve(app).await),
+            token: Some("pod".to_string()),
+            token_file: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn temporary_credentials_are_fetched_again_before_they_expire() {
+        let fetches = Arc::new(AtomicUsize::new(0));
+        let chain = CredentialChain::with_sources(
+            Client::new(),
+            Sources {
+                profile: "missing-profile".to_string(),
+                container: Some(container(60, Arc::clone(&fetches)).await),
+                ..Sources::default()
+            },
+        );
+        let first = chain.credentials().await.unwrap_or_else(|e| panic!("{e}"));
+        assert_eq!(first.access_key_id, "ASIA1");
+        assert_eq!(first.session_token.as_deref(), Some("session"));
+        // A minute left is inside the refresh window
+        let second = chain.credentials().await.unwrap_or_else(|e| panic!("{e}"));
+        assert_eq!(second.access_key_id, "ASIA2");
+
+        let fetches = Arc::new(AtomicUsize::new(0));
+        let chain = CredentialChain::with_sources(
+            Client::new(),
+            Sources {
+                profile: "missing-profile".to_string(),
+                container: Some(container(3600, Arc::clone(&fetches)).await),
+                ..Sources::default()
+            },
+        );
+        for _ in 0..3 {
+            chain.credentials().await.unwrap_or_else(|e| panic!("{e}"));
+        }
+        assert_eq!(fetches.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn roles_come_from_web_identity_tokens_and_instance_metadata() {
+        let sts = Router::new().route(
+            "/",
+            get(
+                |axum::extract::Query(query): axum::extract::Query<Vec<(String, String)>>| async move {
+                    let token = query
+                        .iter()
+                        .find(|(name, _)| name == "WebIdentityToken")
+                        .map(|(_, value)| value.clone())
+                        .unwrap_or_default();
+                    format!(
+                        "<AssumeRoleWithWebIdentityResponse><AssumeRoleWithWebIdentityResult>\
+                         <Credentials><AccessKeyId>ASIA-{token}</AccessKeyId>\
+                         <SecretAccessKey>secret</SecretAccessKey><SessionToken>session</SessionToken>\
+                         <Expiration>2099-01-01T00:00:00Z</Expiration></Credentials>\
+                         </AssumeRoleWithWebIdentityResult></AssumeRoleWithWebIdentityResponse>"
+                    )
+                },
+            ),
+        );
+        let token_file =
+            std::env::temp_dir().join(format!("aws-web-identity-{}", std::process::id()));
+        std::fs::write(&token_file, "oidc\n").unwrap_or_else(|e| panic!("{e}"));
+        let chain = CredentialChain::with_sources(
+            Client::new(),
+            Sources {
+                profile: "missing-profile".to_string(),
+                web_identity: Some(WebIdentity {
+                    token_file: token_file.clone(),
+                    role_arn: "arn:aws:iam::123456789012:role/sink".to_string(),
+                    session_name: "test".to_string(),
+                }),
+                sts: format!("{}/", serve(sts).await),
+                ..Sources::default()
+            },
+        );
+        let credentials = chain.credentials().await.unwrap_or_else(|e| panic!("{e}"));
+        let _ = std::fs::remove_file(&token_file);
+        assert_eq!(credentials.access_key_id, "ASIA-oidc");
+
+        let imds = Router::new()
+            .route("/latest/api/token", put(|| async { "imds-token" }))
+            .route(
+                "/latest/meta-data/iam/security-credentials/",
+                get(|| async { "sink-role\n" }),
+            )
+            .route(
+                "/latest/meta-data/iam/security-credentials/sink-role",
+                get(|headers: HeaderMap| async move {
+                    let token = headers
+                        .get("x-aws-ec2-metadata-token")
+                        .and_then(|v| v.to_str().ok())
+                        .unwrap_or_default()
+                        .to_string();
+                    axum::Json(json!({
+                        "Code": "Success",
+                        "AccessKeyId": format!("ASIA-{token}"),
+                        "SecretAccessKey": "secret",
+                        "Token": "session",
+                        "Expiration": "2099-01-01T00:00:00Z",
+                    }))
+                }),
+            );
+        let chain = CredentialChain::with_sources(
+            Client::new(),
+            Sources {
+                profile: "missing-profile".to_string(),
+                imds: Some(serve(imds).await),
+                ..Sources::default()
+            },
+        );
+        let credentials = chain.credentials().await.unwrap_or_else(|e| panic!("{e}"));
+        assert_eq!(credentials.access_key_id, "ASIA-imds-token");
+
+        let none = CredentialChain::with_sources(
+            Client::new(),
+            Sources {
+                profile: "missing-profile".to_string(),
+                ..Sources::default()
+            },
+        );
+        assert!(none.credentials().await.is_err());
+    }
+}
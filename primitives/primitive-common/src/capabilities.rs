@@ -0,0 +1,186 @@
+//! Capability handshake.
+//!
+//! Every primitive can describe itself as a `primitive.capabilities` event:
+//! its build (version and git SHA), its role, the message types it consumes
+//! and produces, and a hash of its startup configuration. With `--announce`
+//! the descriptor is published right after connecting, so management tooling
+//! can inventory a fleet and spot replicas running different builds or
+//! settings. `--capabilities` prints the same descriptor as JSON and exits
+//! without connecting.
+//!
+//! ```json
+//! {"primitive": "pager", "role": "sink", "version": "0.8.2", "git_sha": "4abb090c1d2e",
+//!  "consumes": ["alert.fired"], "produces": ["exec.dead_letter"], "config_hash": "9f2c41d07be3a6e1"}
+//! ```
+//!
+//! [`VERSION`] is also what every primitive reports for `--version`.
+
+use clap::Args;
+use emergent_client::EmergentMessage;
+use event_schemas::{EventPayload, PrimitiveCapabilities};
+use sha2::{Digest, Sha256};
+use std::fmt;
+
+/// Message type of capability descriptors.
+pub const CAPABILITIES_EVENT_TYPE: &str = PrimitiveCapabilities::MESSAGE_TYPE;
+
+/// Package version, followed by the git SHA in parentheses when known.
+pub const VERSION: &str = env!("EMERGENT_BUILD_VERSION");
+
+/// Git commit the build came from, when known.
+pub const GIT_SHA: Option<&str> = option_env!("EMERGENT_BUILD_SHA");
+
+/// Hex digits of the SHA-256 kept for `config_hash`.
+const CONFIG_HASH_LEN: usize = 16;
+
+/// CLI flags for the capability descriptor.
+///
+/// Its `Debug` output is constant so that these flags do not change the
+/// config hash of the arguments they are part of.
+#[derive(Args, Clone, Default)]
+pub struct CapabilityArgs {
+    /// Publish a `primitive.capabilities` descriptor after connecting.
+    #[arg(long, env = "EMERGENT_ANNOUNCE")]
+    pub announce: bool,
+
+    /// Print the `primitive.capabilities` descriptor as JSON and exit without connecting.
+    #[arg(long)]
+    pub capabilities: bool,
+}
+
+impl CapabilityArgs {
+    /// Build the descriptor for `primitive`.
+    ///
+    /// `config` is normally the primitive's parsed arguments; only its hash
+    /// is included, so credentials never leave the process. Duplicate types
+    /// are listed once, and `primitive.capabilities` is added to `produces`
+    /// when announcing.
+    pub fn describe(
+        &self,
+        primitive: &str,
+        role: Role,
+        consumes: &[&str],
+        produces: &[&str],
+        config: &dyn fmt::Debug,
+    ) -> PrimitiveCapabilities {
+        let mut produces = unique(produces);
+        if self.announce && !produces.iter().any(|t| t == CAPABILITIES_EVENT_TYPE) {
+            produces.push(CAPABILITIES_EVENT_TYPE.to_string());
+        }
+        PrimitiveCapabilities {
+            primitive: primitive.to_string(),
+            role: role.as_str().to_string(),
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            git_sha: GIT_SHA.map(str::to_string),
+            consumes: unique(consumes),
+            produces,
+            config_hash: config_hash(config),
+        }
+    }
+}
+
+impl fmt::Debug for CapabilityArgs {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("CapabilityArgs")
+    }
+}
+
+/// How a primitive attaches to the engine.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    Source,
+    Handler,
+    Sink,
+}
+
+impl Role {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Source => "source",
+            Self::Handler => "handler",
+            Self::Sink => "sink",
+        }
+    }
+}
+
+/// Short SHA-256 of `config`'s `Debug` output.
+pub fn config_hash(config: &dyn fmt::Debug) -> String {
+    let digest = Sha256::digest(format!("{config:?}").as_bytes());
+    let mut hash = hex::encode(digest);
+    hash.truncate(CONFIG_HASH_LEN);
+    hash
+}
+
+/// The descriptor as a `primitive.capabilities` event.
+pub fn capabilities_message(capabilities: &PrimitiveCapabilities) -> EmergentMessage {
+    EmergentMessage::new(CAPABILITIES_EVENT_TYPE).with_payload(capabilities.to_payload())
+}
+
+/// Print the descriptor for `--capabilities` and exit.
+pub fn print(capabilities: &PrimitiveCapabilities) -> ! {
+    println!("{}", capabilities.to_payload());
+    std::process::exit(0);
+}
+
+/// `types` in their original order without repeats.
+fn unique(types: &[&str]) -> Vec<String> {
+    let mut out: Vec<String> = Vec::with_capacity(types.len());
+    for t in types {
+        if !out.iter().any(|seen| seen == t) {
+            out.push((*t).to_string());
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn descriptor_lists_types_once() {
+        let caps = CapabilityArgs::default().describe(
+            "pager",
+            Role::Handler,
+            &["alert.fired"],
+            &["exec.output", "exec.error", "exec.output"],
+            &"settings",
+        );
+        assert_eq!(caps.role, "handler");
+        assert_eq!(caps.consumes, vec!["alert.fired"]);
+        assert_eq!(caps.produces, vec!["exec.output", "exec.error"]);
+        assert_eq!(caps.version, env!("CARGO_PKG_VERSION"));
+        assert!(VERSION.starts_with(&caps.version));
+    }
+
+    #[test]
+    fn config_hash_tracks_settings_but_not_capability_flags() {
+        // Stand-in for a primitive's Args: (timeout, flags)
+        let base = (30000, CapabilityArgs::default());
+        let announced = (
+            30000,
+            CapabilityArgs {
+                announce: true,
+                capabilities: true,
+            },
+        );
+        let changed = (60000, CapabilityArgs::default());
+        assert_eq!(config_hash(&base), config_hash(&announced));
+        assert_ne!(config_hash(&base), config_hash(&changed));
+        assert_eq!(config_hash(&base).len(), CONFIG_HASH_LEN);
+    }
+
+    #[test]
+    fn message_carries_the_descriptor() {
+        let args = CapabilityArgs {
+            announce: true,
+            capabilities: false,
+        };
+        let caps = args.describe("ingest", Role::Source, &[], &["http.request"], &());
+        assert_eq!(caps.produces, vec!["http.request", CAPABILITIES_EVENT_TYPE]);
+        let msg = capabilities_message(&caps);
+        assert_eq!(msg.message_type.as_str(), CAPABILITIES_EVENT_TYPE);
+        assert_eq!(msg.payload()["produces"][0], "http.request");
+        assert_eq!(msg.payload()["config_hash"], caps.config_hash);
+    }
+}
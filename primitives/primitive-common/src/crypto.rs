@@ -0,0 +1,278 @@
+//! End-to-end payload encryption for sensitive topics.
+//!
+//! Publishers given `--encrypt-topic` and `--encrypt-to` seal the payloads
+//! of matching message types with [age] to one or more X25519 recipients
+//! (`age1...`). The sealed payload travels as a payload envelope (see
+//! [`crate::payload`]):
+//!
+//! ```json
+//! {"$emergent": "age", "data": "<base64 age ciphertext>"}
+//! ```
+//!
+//! Consumers holding a matching private key (`--identity-file`, one
+//! `AGE-SECRET-KEY-1...` per line as written by `age-keygen`) decrypt
+//! transparently in [`crate::payload::decode`]. Everyone else, including the
+//! engine, sees only the envelope, so sensitive data can cross a shared bus.
+//! The message type and metadata stay in the clear.
+//!
+//! [age]: https://age-encryption.org
+
+use crate::doctor::Report;
+use age::x25519::{Identity, Recipient};
+use base64::{Engine, engine::general_purpose::STANDARD};
+use clap::Args;
+use serde_json::{Value, json};
+use std::{
+    fs,
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+};
+
+/// Envelope kind for sealed payloads.
+pub(crate) const ENVELOPE: &str = "age";
+
+/// CLI flags controlling which payloads a publisher encrypts.
+#[derive(Args, Debug, Clone, Default)]
+pub struct EncryptArgs {
+    /// Encrypt payloads of these message types (`prefix.*` matches a namespace); repeatable or comma-separated.
+    #[arg(
+        long,
+        env = "EMERGENT_ENCRYPT_TOPICS",
+        value_delimiter = ',',
+        requires = "encrypt_to"
+    )]
+    pub encrypt_topic: Vec<String>,
+
+    /// age X25519 recipient (`age1...`) allowed to decrypt; repeatable or comma-separated.
+    #[arg(long, env = "EMERGENT_ENCRYPT_TO", value_delimiter = ',')]
+    pub encrypt_to: Vec<String>,
+}
+
+impl EncryptArgs {
+    /// Check that every recipient parses, so typos fail at startup rather
+    /// than on the first sensitive message.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.encrypt_topic.is_empty() {
+            return Ok(());
+        }
+        self.recipients().map(|_| ())
+    }
+
+    /// Add the `--self-test` check for the configured recipients.
+    pub fn self_test(&self, report: &mut Report) {
+        if self.encrypt_topic.is_empty() {
+            return;
+        }
+        report.check(
+            "encrypt-to",
+            self.recipients().map(|r| {
+                format!(
+                    "{} recipient(s) for {}",
+                    r.len(),
+                    self.encrypt_topic.join(", ")
+                )
+            }),
+        );
+    }
+
+    /// Whether payloads of `message_type` must be sealed.
+    pub fn applies_to(&self, message_type: &str) -> bool {
+        self.encrypt_topic
+            .iter()
+            .any(|pattern| match pattern.strip_suffix('*') {
+                Some(prefix) => message_type.starts_with(prefix),
+                None => pattern == message_type,
+            })
+    }
+
+    fn recipients(&self) -> Result<Vec<Recipient>, String> {
+        if self.encrypt_to.is_empty() {
+            return Err("no --encrypt-to recipient".to_string());
+        }
+        self.encrypt_to
+            .iter()
+            .map(|r| {
+                r.trim()
+                    .parse::<Recipient>()
+                    .map_err(|e| format!("invalid recipient '{r}': {e}"))
+            })
+            .collect()
+    }
+}
+
+/// Seal `payload` if `message_type` is configured for encryption; other
+/// payloads pass through unchanged.
+pub fn seal(message_type: &str, payload: Value, args: &EncryptArgs) -> io::Result<Value> {
+    if !args.applies_to(message_type) {
+        return Ok(payload);
+    }
+    let recipients = args.recipients().map_err(io::Error::other)?;
+    let plaintext = serde_json::to_vec(&payload).map_err(io::Error::other)?;
+
+    let encryptor =
+        age::Encryptor::with_recipients(recipients.iter().map(|r| r as &dyn age::Recipient))
+            .map_err(io::Error::other)?;
+    let mut ciphertext = Vec::with_capacity(plaintext.len());
+    let mut writer = encryptor.wrap_output(&mut ciphertext)?;
+    writer.write_all(&plaintext)?;
+    writer.finish()?;
+
+    Ok(json!({
+        crate::payload::MARKER: ENVELOPE,
+        "data": STANDARD.encode(ciphertext),
+    }))
+}
+
+/// CLI flag naming a consumer's private keys.
+#[derive(Args, Debug, Clone, Default)]
+pub struct KeyArgs {
+    /// age identity file (`AGE-SECRET-KEY-1...` lines) used to decrypt sealed payloads.
+    #[arg(long, env = "EMERGENT_IDENTITY_FILE")]
+    pub identity_file: Option<PathBuf>,
+}
+
+impl KeyArgs {
+    /// Load the identities, if an identity file was given.
+    pub fn load(&self) -> io::Result<Option<Keyring>> {
+        let Some(path) = &self.identity_file else {
+            return Ok(None);
+        };
+        Keyring::load(path)
+            .map(Some)
+            .map_err(|e| io::Error::new(e.kind(), format!("{}: {e}", path.display())))
+    }
+
+    /// Add the `--self-test` check for the identity file.
+    pub fn self_test(&self, report: &mut Report) {
+        if let Some(path) = &self.identity_file {
+            report.check(
+                "identity-file",
+                Keyring::load(path)
+                    .map(|k| format!("{} ({} key(s))", path.display(), k.identities.len()))
+                    .map_err(|e| format!("{}: {e}", path.display())),
+            );
+        }
+    }
+}
+
+/// Private keys able to open sealed payloads.
+pub struct Keyring {
+    identities: Vec<Identity>,
+}
+
+impl Keyring {
+    /// Read an identity file: one key per line; blank lines and `#`
+    /// comments are ignored.
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let text = fs::read_to_string(path)?;
+        let identities = text
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(|line| line.parse::<Identity>().map_err(invalid))
+            .collect::<io::Result<Vec<_>>>()?;
+        if identities.is_empty() {
+            return Err(invalid("no identities in file"));
+        }
+        Ok(Self { identities })
+    }
+
+    /// Decrypt the `data` of a sealed envelope back into its payload.
+    pub(crate) fn open(&self, envelope: &Value) -> io::Result<Value> {
+        let data = envelope
+            .get("data")
+            .and_then(Value::as_str)
+            .ok_or_else(|| invalid("age envelope has no data"))?;
+        let ciphertext = STANDARD.decode(data).map_err(invalid)?;
+
+        let decryptor = age::Decryptor::new_buffered(ciphertext.as_slice()).map_err(invalid)?;
+        let mut reader = decryptor
+            .decrypt(self.identities.iter().map(|i| i as &dyn age::Identity))
+            .map_err(invalid)?;
+        let mut plaintext = Vec::new();
+        reader.read_to_end(&mut plaintext)?;
+        serde_json::from_slice(&plaintext).map_err(invalid)
+    }
+
+    #[cfg(test)]
+    pub(crate) fn generate() -> (Self, String) {
+        let identity = Identity::generate();
+        let recipient = identity.to_public().to_string();
+        (
+            Self {
+                identities: vec![identity],
+            },
+            recipient,
+        )
+    }
+}
+
+fn invalid(e: impl ToString) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(topics: &[&str], recipients: &[String]) -> EncryptArgs {
+        EncryptArgs {
+            encrypt_topic: topics.iter().map(|t| t.to_string()).collect(),
+            encrypt_to: recipients.to_vec(),
+        }
+    }
+
+    #[test]
+    fn sealed_payload_opens_with_the_matching_key() {
+        let (keyring, recipient) = Keyring::generate();
+        let payload = json!({"ssn": "078-05-1120"});
+        let sealed = seal(
+            "patient.admitted",
+            payload.clone(),
+            &args(&["patient.*"], &[recipient]),
+        )
+        .unwrap_or_else(|e| panic!("seal: {e}"));
+
+        assert_eq!(sealed[crate::payload::MARKER], ENVELOPE);
+        assert!(!sealed.to_string().contains("078-05-1120"));
+        let opened = keyring
+            .open(&sealed)
+            .unwrap_or_else(|e| panic!("open: {e}"));
+        assert_eq!(opened, payload);
+    }
+
+    #[test]
+    fn other_keys_cannot_open() {
+        let (_, recipient) = Keyring::generate();
+        let (stranger, _) = Keyring::generate();
+        let sealed = seal(
+            "billing.charge",
+            json!({"card": "4242"}),
+            &args(&["billing.charge"], &[recipient]),
+        )
+        .unwrap_or_else(|e| panic!("seal: {e}"));
+        assert!(stranger.open(&sealed).is_err());
+    }
+
+    #[test]
+    fn unmatched_topics_pass_through() {
+        let (_, recipient) = Keyring::generate();
+        let args = args(&["billing.*"], &[recipient]);
+        assert!(!args.applies_to("billing"));
+        assert!(!args.applies_to("exec.output"));
+        let payload = json!({"ok": true});
+        let sealed =
+            seal("exec.output", payload.clone(), &args).unwrap_or_else(|e| panic!("seal: {e}"));
+        assert_eq!(sealed, payload);
+    }
+
+    #[test]
+    fn invalid_recipients_fail_validation() {
+        assert!(
+            args(&["a.b"], &["age1nope".to_string()])
+                .validate()
+                .is_err()
+        );
+        assert!(args(&["a.b"], &[]).validate().is_err());
+    }
+}
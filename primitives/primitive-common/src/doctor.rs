@@ -0,0 +1,154 @@
+//! Self-test (`--self-test`) reporting.
+//!
+//! Each primitive verifies its external dependencies — executables on
+//! `PATH`, writable directories, bindable ports — without connecting to the
+//! engine, prints one line per check, and exits non-zero if any failed:
+//!
+//! ```text
+//! exec-sink self-test
+//!   [PASS] command: /usr/bin/jq
+//!   [FAIL] inbox-dir: /var/lib/emergent/inbox: Permission denied (os error 13)
+//! 2 checks, 1 failed
+//! ```
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// Outcome of a single check.
+struct Check {
+    name: String,
+    outcome: Result<String, String>,
+}
+
+/// Collected self-test results for one primitive.
+pub struct Report {
+    primitive: String,
+    checks: Vec<Check>,
+}
+
+impl Report {
+    /// Start an empty report for `primitive`.
+    pub fn new(primitive: &str) -> Self {
+        Self {
+            primitive: primitive.to_string(),
+            checks: Vec::new(),
+        }
+    }
+
+    /// Record a check: `Ok(detail)` passes, `Err(reason)` fails.
+    pub fn check(&mut self, name: &str, outcome: Result<String, String>) {
+        self.checks.push(Check {
+            name: name.to_string(),
+            outcome,
+        });
+    }
+
+    /// Number of failed checks.
+    pub fn failures(&self) -> usize {
+        self.checks.iter().filter(|c| c.outcome.is_err()).count()
+    }
+
+    /// Render the report as printed by [`Report::finish`].
+    pub fn render(&self) -> String {
+        let mut out = format!("{} self-test\n", self.primitive);
+        for check in &self.checks {
+            let (status, detail) = match &check.outcome {
+                Ok(detail) => ("PASS", detail),
+                Err(reason) => ("FAIL", reason),
+            };
+            out.push_str(&format!("  [{status}] {}: {detail}\n", check.name));
+        }
+        out.push_str(&format!(
+            "{} checks, {} failed",
+            self.checks.len(),
+            self.failures()
+        ));
+        out
+    }
+
+    /// Print the report and exit: 0 if every check passed, 1 otherwise.
+    pub fn finish(self) -> ! {
+        println!("{}", self.render());
+        std::process::exit(if self.failures() == 0 { 0 } else { 1 });
+    }
+}
+
+/// Resolve `program` the way `Command::new` would: paths are checked
+/// directly, bare names are searched on `PATH`.
+pub fn check_executable(program: &str) -> Result<String, String> {
+    if program.contains('/') {
+        let path = Path::new(program);
+        return if is_executable(path) {
+            Ok(program.to_string())
+        } else {
+            Err(format!("{program} is not an executable file"))
+        };
+    }
+
+    std::env::var_os("PATH")
+        .iter()
+        .flat_map(std::env::split_paths)
+        .map(|dir| dir.join(program))
+        .find(|candidate| is_executable(candidate))
+        .map(|found| found.display().to_string())
+        .ok_or_else(|| format!("{program} not found on PATH"))
+}
+
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    fs::metadata(path).is_ok_and(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+}
+
+/// Check that `dir` exists (creating it if needed) and accepts writes.
+pub fn check_writable_dir(dir: &Path) -> Result<String, String> {
+    let probe: PathBuf = dir.join(format!(".self-test-{}", std::process::id()));
+    fs::create_dir_all(dir)
+        .and_then(|()| fs::write(&probe, b"ok"))
+        .and_then(|()| fs::remove_file(&probe))
+        .map(|()| dir.display().to_string())
+        .map_err(|e| format!("{}: {e}", dir.display()))
+}
+
+/// Check that `dir` exists and is a directory.
+pub fn check_dir_exists(dir: &Path) -> Result<String, String> {
+    if dir.is_dir() {
+        Ok(dir.display().to_string())
+    } else {
+        Err(format!("{} is not a directory", dir.display()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn report_counts_failures() {
+        let mut report = Report::new("demo");
+        report.check("a", Ok("fine".to_string()));
+        report.check("b", Err("broken".to_string()));
+
+        assert_eq!(report.failures(), 1);
+        let rendered = report.render();
+        assert!(rendered.contains("[PASS] a: fine"));
+        assert!(rendered.contains("[FAIL] b: broken"));
+        assert!(rendered.ends_with("2 checks, 1 failed"));
+    }
+
+    #[test]
+    fn executable_lookup_searches_path() {
+        assert!(check_executable("sh").is_ok());
+        assert!(check_executable("nonexistent_command_that_should_not_exist").is_err());
+        assert!(check_executable("/definitely/not/here").is_err());
+    }
+
+    #[test]
+    fn writable_dir_is_created_and_probed() {
+        let dir = std::env::temp_dir().join(format!("doctor-test-{}", std::process::id()));
+        assert!(check_writable_dir(&dir).is_ok());
+        assert!(check_dir_exists(&dir).is_ok());
+        let _ = fs::remove_dir_all(dir);
+    }
+}
@@ -0,0 +1,231 @@
+//! Standardized `primitive.error` events.
+//!
+//! Failures used to surface only on stderr. With `--emit-errors`, primitives
+//! also publish a `primitive.error` event so operators can alert on failures
+//! bus-wide:
+//!
+//! ```json
+//! {
+//!   "primitive": "exec_sink",
+//!   "message_id": "msg_01h...",
+//!   "message_type": "alert.fired",
+//!   "category": "rejected",
+//!   "disposition": "retrying",
+//!   "attempt": 1,
+//!   "error": "./page-oncall.sh: exit code 1"
+//! }
+//! ```
+
+use clap::Args;
+use emergent_client::EmergentMessage;
+use event_schemas::{EventPayload, PrimitiveError};
+use serde_json::Value;
+use std::fmt;
+use std::time::Duration;
+
+/// Message type of standardized error events.
+pub const ERROR_EVENT_TYPE: &str = PrimitiveError::MESSAGE_TYPE;
+
+/// CLI flag enabling `primitive.error` events.
+#[derive(Args, Debug, Clone, Default)]
+pub struct ErrorArgs {
+    /// Publish a `primitive.error` event for every failure.
+    #[arg(long, env = "EMERGENT_EMIT_ERRORS")]
+    pub emit_errors: bool,
+}
+
+/// Broad class of a failure, for routing alerts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorCategory {
+    /// The outbound call could not be made (spawn, connect, DNS).
+    Request,
+    /// The outbound call did not finish in time.
+    Timeout,
+    /// The remote side (API, command) refused or failed the request.
+    Rejected,
+    /// Input could not be parsed or decoded.
+    Parse,
+    /// A failure inside the primitive itself (I/O, encoding).
+    Internal,
+}
+
+impl ErrorCategory {
+    /// Wire name used in event payloads.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Request => "request",
+            Self::Timeout => "timeout",
+            Self::Rejected => "rejected",
+            Self::Parse => "parse",
+            Self::Internal => "internal",
+        }
+    }
+}
+
+/// What happens to the message after the failure.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Disposition {
+    /// Another attempt will be made.
+    Retrying,
+    /// Attempts are exhausted; the message was dead-lettered.
+    DeadLettered,
+    /// The message is gone.
+    Dropped,
+}
+
+impl Disposition {
+    /// Wire name used in event payloads.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Retrying => "retrying",
+            Self::DeadLettered => "dead_lettered",
+            Self::Dropped => "dropped",
+        }
+    }
+}
+
+/// A categorized handler failure.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HandlerError {
+    pub category: ErrorCategory,
+    pub message: String,
+    /// How long the remote side asked to be left alone (e.g. `Retry-After`).
+    pub retry_after: Option<Duration>,
+    /// The status the remote side answered with (e.g. an HTTP status).
+    pub status: Option<u16>,
+    /// Dead-letter the message now rather than retrying it.
+    pub permanent: bool,
+    /// The attempt was never made, so it does not count against `--max-attempts`.
+    pub requeue: bool,
+}
+
+impl HandlerError {
+    pub fn new(category: ErrorCategory, message: impl Into<String>) -> Self {
+        Self {
+            category,
+            message: message.into(),
+            retry_after: None,
+            status: None,
+            permanent: false,
+            requeue: false,
+        }
+    }
+
+    /// Ask the harness to wait at least `delay` before the next attempt.
+    pub fn with_retry_after(mut self, delay: Duration) -> Self {
+        self.retry_after = Some(delay);
+        self
+    }
+
+    /// Skip the remaining attempts: the message is dead-lettered at once.
+    pub fn permanent(mut self) -> Self {
+        self.permanent = true;
+        self
+    }
+
+    /// The attempt was not made (for example a circuit breaker refused it):
+    /// try again after `delay` without counting it against `--max-attempts`.
+    pub fn requeued(mut self, delay: Duration) -> Self {
+        self.retry_after = Some(delay);
+        self.requeue = true;
+        self
+    }
+
+    /// Record the status the remote side answered with, for dead letters.
+    pub fn with_status(mut self, status: u16) -> Self {
+        self.status = Some(status);
+        self
+    }
+}
+
+impl fmt::Display for HandlerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl From<String> for HandlerError {
+    fn from(message: String) -> Self {
+        Self::new(ErrorCategory::Internal, message)
+    }
+}
+
+/// Everything a `primitive.error` event reports.
+pub struct ErrorEvent<'a> {
+    pub primitive: &'a str,
+    pub message_id: Option<&'a str>,
+    pub message_type: Option<&'a str>,
+    pub category: ErrorCategory,
+    pub disposition: Disposition,
+    pub attempt: Option<u32>,
+    pub error: &'a str,
+}
+
+impl ErrorEvent<'_> {
+    /// Build the event payload.
+    pub fn to_payload(&self) -> Value {
+        PrimitiveError {
+            primitive: self.primitive.to_string(),
+            message_id: self.message_id.map(str::to_string),
+            message_type: self.message_type.map(str::to_string),
+            category: self.category.as_str().to_string(),
+            disposition: self.disposition.as_str().to_string(),
+            attempt: self.attempt,
+            error: self.error.to_string(),
+        }
+        .to_payload()
+    }
+
+    /// Build the `primitive.error` message (without causation).
+    pub fn to_message(&self) -> EmergentMessage {
+        EmergentMessage::new(ERROR_EVENT_TYPE).with_payload(self.to_payload())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn payload_uses_wire_names() {
+        let event = ErrorEvent {
+            primitive: "exec_sink",
+            message_id: Some("msg_1"),
+            message_type: Some("alert.fired"),
+            category: ErrorCategory::Timeout,
+            disposition: Disposition::DeadLettered,
+            attempt: Some(3),
+            error: "timed out",
+        };
+        let payload = event.to_payload();
+
+        assert_eq!(payload["primitive"], "exec_sink");
+        assert_eq!(payload["category"], "timeout");
+        assert_eq!(payload["disposition"], "dead_lettered");
+        assert_eq!(payload["attempt"], 3);
+    }
+
+    #[test]
+    fn source_errors_have_null_message_fields() {
+        let event = ErrorEvent {
+            primitive: "exec-source",
+            message_id: None,
+            message_type: None,
+            category: ErrorCategory::Request,
+            disposition: Disposition::Dropped,
+            attempt: None,
+            error: "spawn failed",
+        };
+        let payload = event.to_payload();
+
+        assert!(payload["message_id"].is_null());
+        assert!(payload["attempt"].is_null());
+    }
+
+    #[test]
+    fn plain_strings_are_internal_errors() {
+        let err = HandlerError::from("boom".to_string());
+        assert_eq!(err.category, ErrorCategory::Internal);
+        assert_eq!(err.to_string(), "boom");
+    }
+}
@@ -0,0 +1,41 @@
+//! Message type and name patterns, where `*` is any run of characters
+//! (`order.*`, `*.failed`, `a*b*c`).
+
+/// Whether `name` matches the glob `pattern`, where `*` is any run of
+/// characters.
+pub fn matches(pattern: &str, name: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = name.strip_prefix(first) else {
+        return false;
+    };
+    let parts: Vec<&str> = parts.collect();
+    let Some((last, middle)) = parts.split_last() else {
+        return rest.is_empty();
+    };
+    for part in middle {
+        match rest.find(part) {
+            Some(at) => rest = &rest[at + part.len()..],
+            None => return false,
+        }
+    }
+    rest.len() >= last.len() && rest.ends_with(last)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stars_match_any_run_of_characters() {
+        assert!(matches("order.*", "order.placed"));
+        assert!(!matches("order.*", "orders.placed"));
+        assert!(matches("*.failed", "deploy.failed"));
+        assert!(matches("a*b*c", "axxbyyc"));
+        assert!(!matches("a*b*c", "axxbyy"));
+        assert!(!matches("aba*aba", "aba"));
+        assert!(matches("*", ""));
+        assert!(matches("exact", "exact"));
+        assert!(!matches("exact", "exactly"));
+    }
+}
@@ -0,0 +1,196 @@
+//! Handler harness.
+//!
+//! Handlers follow the same shape as sinks: connect under `EMERGENT_NAME`,
+//! announce their capabilities with `--announce`, subscribe, and hand each
+//! message to the primitive until SIGTERM or the engine closes the
+//! subscription. [`run_handler`] owns that loop; a handler implements
+//! [`MessageHandler`] and publishes through the [`Bus`] it is given.
+//!
+//! Handlers that also act on their own, such as a timer firing or a call
+//! from an HTTP API, return that work from [`MessageHandler::wake`]; it is
+//! raced against the subscription and handed to [`MessageHandler::woken`].
+//!
+//! Nothing is retried: a message the handler cannot use is dropped, and
+//! with `--emit-errors` reported as a `primitive.error` event (see
+//! [`Bus::report_error`]). A publish the engine refuses is logged.
+
+use crate::capabilities::capabilities_message;
+use crate::errors::{Disposition, ErrorCategory, ErrorEvent};
+use emergent_client::types::CausationId;
+use emergent_client::{EmergentHandler, EmergentMessage};
+use event_schemas::PrimitiveCapabilities;
+use std::future::Future;
+use tokio::signal::unix::{SignalKind, signal};
+
+/// What a handler's run needs besides the handler itself.
+pub struct HandlerConfig<'a> {
+    /// Client name, already resolved from `EMERGENT_NAME`.
+    pub name: &'a str,
+    /// Message types to subscribe to.
+    pub subscribe: &'a [String],
+    /// Descriptor published once connected, when `--announce` is set.
+    pub announce: Option<&'a PrimitiveCapabilities>,
+    /// Whether `--emit-errors` is set.
+    pub emit_errors: bool,
+}
+
+/// Whether the run goes on after [`MessageHandler::woken`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Flow {
+    Continue,
+    Stop,
+}
+
+/// A handler's behaviour, driven by [`run_handler`].
+pub trait MessageHandler: Send {
+    /// What [`wake`](Self::wake) resolves with.
+    type Wake: Send;
+
+    /// Handle one message from the subscription.
+    fn handle(&mut self, msg: &EmergentMessage, bus: &Bus) -> impl Future<Output = ()> + Send;
+
+    /// Resolves when the handler has work of its own; never, by default.
+    /// Dropped unfinished whenever a message arrives first.
+    fn wake(&mut self) -> impl Future<Output = Self::Wake> + Send {
+        std::future::pending()
+    }
+
+    /// Do the work [`wake`](Self::wake) resolved with.
+    fn woken(&mut self, wake: Self::Wake, bus: &Bus) -> impl Future<Output = Flow> + Send {
+        let _ = (wake, bus);
+        async { Flow::Continue }
+    }
+
+    /// Called once the loop ends, before disconnecting.
+    fn finish(&mut self, bus: &Bus) -> impl Future<Output = ()> + Send {
+        let _ = bus;
+        async {}
+    }
+}
+
+/// A handler's connection to the engine, with what it needs to publish
+/// and report errors under its name.
+pub struct Bus {
+    handler: EmergentHandler,
+    name: String,
+    emit_errors: bool,
+}
+
+impl Bus {
+    pub fn new(handler: EmergentHandler, name: &str, emit_errors: bool) -> Self {
+        Self {
+            handler,
+            name: name.to_string(),
+            emit_errors,
+        }
+    }
+
+    /// The handler's client name.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Publish `message`, logging a refusal.
+    pub async fn publish(&self, message: EmergentMessage) {
+        let message_type = message.message_type.to_string();
+        if let Err(e) = self.handler.publish(message).await {
+            eprintln!("Failed to publish {message_type}: {e}");
+        }
+    }
+
+    /// Report `msg` as dropped for `error`, when `--emit-errors` is set.
+    pub async fn report_error(&self, msg: &EmergentMessage, category: ErrorCategory, error: &str) {
+        let message_id = msg.id().to_string();
+        let cause = CausationId::from(msg.id());
+        self.report_dropped(
+            &message_id,
+            msg.message_type.as_str(),
+            cause,
+            category,
+            error,
+        )
+        .await;
+    }
+
+    /// [`report_error`](Self::report_error) for a message no longer at
+    /// hand, such as one held in a batch.
+    pub async fn report_dropped(
+        &self,
+        message_id: &str,
+        message_type: &str,
+        cause: CausationId,
+        category: ErrorCategory,
+        error: &str,
+    ) {
+        if !self.emit_errors {
+            return;
+        }
+        let event = ErrorEvent {
+            primitive: &self.name,
+            message_id: Some(message_id),
+            message_type: Some(message_type),
+            category,
+            disposition: Disposition::Dropped,
+            attempt: None,
+            error,
+        };
+        self.publish(event.to_message().with_causation_id(cause))
+            .await;
+    }
+}
+
+/// Connect, subscribe, and hand each message to `handler` until SIGTERM
+/// or the end of the subscription. Connection and subscription failures
+/// exit the process.
+pub async fn run_handler<H: MessageHandler>(
+    config: HandlerConfig<'_>,
+    handler: &mut H,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let client = match EmergentHandler::connect(config.name).await {
+        Ok(h) => h,
+        Err(e) => {
+            eprintln!("Failed to connect to Emergent engine: {e}");
+            std::process::exit(1);
+        }
+    };
+    let mut bus = Bus::new(client, config.name, config.emit_errors);
+    if let Some(descriptor) = config.announce {
+        bus.publish(capabilities_message(descriptor)).await;
+    }
+
+    let topics_refs: Vec<&str> = config.subscribe.iter().map(String::as_str).collect();
+    let mut stream = match bus.handler.subscribe(&topics_refs).await {
+        Ok(s) => s,
+        Err(e) => {
+            eprintln!("Failed to subscribe: {e}");
+            std::process::exit(1);
+        }
+    };
+
+    // Set up SIGTERM handler for graceful shutdown
+    let mut sigterm = signal(SignalKind::terminate())?;
+
+    loop {
+        tokio::select! {
+            _ = sigterm.recv() => break,
+
+            wake = handler.wake() => {
+                if handler.woken(wake, &bus).await == Flow::Stop {
+                    break;
+                }
+            }
+
+            msg = stream.next() => {
+                match msg {
+                    Some(msg) => handler.handle(&msg, &bus).await,
+                    // Stream ended (graceful shutdown)
+                    None => break,
+                }
+            }
+        }
+    }
+
+    handler.finish(&bus).await;
+    let _ = bus.handler.disconnect().await;
+    Ok(())
+}
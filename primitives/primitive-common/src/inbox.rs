@@ -0,0 +1,404 @@
+//! Persistent local inbox for at-least-once sink delivery.
+//!
+//! The engine does not redeliver messages, so a sink that crashes mid-handler
+//! would lose them. With `--inbox-dir`, the harness writes every received
+//! message to disk before handling it and removes the file only once the
+//! handler succeeds. Entries still present at startup are replayed in order.
+//!
+//! Each entry records its attempt count and, after a failure, when the next
+//! attempt is due, so a sink restarted mid-backoff resumes the retry
+//! schedule instead of starting over.
+//!
+//! An entry file that cannot be read back (for example one written by an
+//! incompatible version, or damaged on disk) is moved to `corrupt/` and
+//! logged, so it cannot stop the sink from starting.
+//!
+//! # Layout
+//!
+//! ```text
+//! <inbox-dir>/
+//!   00000000000000000001.json   pending entry (seq-ordered)
+//!   dead/
+//!     00000000000000000000.json entry that exhausted its attempts
+//!   corrupt/
+//!     00000000000000000002.json entry that could not be parsed
+//! ```
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{
+    fs::{self, File},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicU64, AtomicUsize, Ordering},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// A message persisted in the inbox.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct InboxEntry {
+    /// Monotonic sequence number; determines replay order.
+    pub seq: u64,
+    /// Original message type.
+    pub message_type: String,
+    /// Original message id (kept for correlation after replay).
+    pub message_id: String,
+    /// Original payload.
+    pub payload: Value,
+    /// Handler attempts made so far.
+    pub attempts: u32,
+    /// Last handler error, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_error: Option<String>,
+    /// Status of the last failed attempt, when the remote side answered.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_status: Option<u16>,
+    /// When the message was received (Unix milliseconds).
+    #[serde(default = "now_ms")]
+    pub received_at: u64,
+    /// When the next attempt is due (Unix milliseconds), after a failure.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub next_due: Option<u64>,
+}
+
+impl InboxEntry {
+    /// An unpersisted entry for a message received just now.
+    pub fn new(seq: u64, message_type: &str, message_id: &str, payload: &Value) -> Self {
+        Self {
+            seq,
+            message_type: message_type.to_string(),
+            message_id: message_id.to_string(),
+            payload: payload.clone(),
+            attempts: 0,
+            last_error: None,
+            last_status: None,
+            received_at: now_ms(),
+            next_due: None,
+        }
+    }
+
+    /// Count a failed attempt and schedule the next one `retry_after` from now.
+    pub fn fail(&mut self, error: &str, retry_after: Duration) {
+        self.attempts += 1;
+        self.last_error = Some(error.to_string());
+        let delay = u64::try_from(retry_after.as_millis()).unwrap_or(u64::MAX);
+        self.next_due = Some(now_ms().saturating_add(delay));
+    }
+
+    /// Put the next attempt off by `delay` without counting one.
+    pub fn defer(&mut self, delay: Duration) {
+        let delay = u64::try_from(delay.as_millis()).unwrap_or(u64::MAX);
+        self.next_due = Some(now_ms().saturating_add(delay));
+    }
+
+    /// How long until the next attempt is due (zero if it already is).
+    pub fn wait(&self) -> Duration {
+        let due = self.next_due.unwrap_or_default();
+        Duration::from_millis(due.saturating_sub(now_ms()))
+    }
+
+    /// How long ago the message was received.
+    pub fn age(&self) -> Duration {
+        Duration::from_millis(now_ms().saturating_sub(self.received_at))
+    }
+}
+
+/// The current time in Unix milliseconds.
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| u64::try_from(d.as_millis()).unwrap_or(u64::MAX))
+}
+
+/// Directory-backed queue of unacknowledged messages.
+///
+/// All operations take `&self`, so one inbox can be shared by the harness's
+/// dispatcher and its workers.
+pub struct Inbox {
+    dir: PathBuf,
+    next_seq: AtomicU64,
+    pending: AtomicUsize,
+}
+
+impl Inbox {
+    /// Open (creating if needed) an inbox rooted at `dir`.
+    pub fn open(dir: impl AsRef<Path>) -> io::Result<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(dir.join("dead"))?;
+        fs::create_dir_all(dir.join("corrupt"))?;
+
+        let pending = entry_seqs(&dir)?;
+        let next_seq = pending
+            .iter()
+            .copied()
+            .chain(entry_seqs(&dir.join("dead"))?)
+            .chain(entry_seqs(&dir.join("corrupt"))?)
+            .max()
+            .map_or(0, |s| s + 1);
+
+        Ok(Self {
+            dir,
+            next_seq: AtomicU64::new(next_seq),
+            pending: AtomicUsize::new(pending.len()),
+        })
+    }
+
+    /// Number of entries awaiting acknowledgement.
+    pub fn len(&self) -> usize {
+        self.pending.load(Ordering::Relaxed)
+    }
+
+    /// Whether no entries are awaiting acknowledgement.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Entries awaiting acknowledgement, oldest first. Entries that cannot
+    /// be parsed are moved to `corrupt/` and left out.
+    pub fn pending(&self) -> io::Result<Vec<InboxEntry>> {
+        let mut seqs = entry_seqs(&self.dir)?;
+        seqs.sort_unstable();
+        let mut entries = Vec::with_capacity(seqs.len());
+        for seq in seqs {
+            let path = self.entry_path(seq);
+            match read_entry(&path) {
+                Ok(entry) => entries.push(entry),
+                Err(e) if e.kind() == io::ErrorKind::InvalidData => self.quarantine(seq, &e)?,
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(entries)
+    }
+
+    /// Move an unreadable entry out of the way, keeping it for inspection.
+    fn quarantine(&self, seq: u64, error: &io::Error) -> io::Result<()> {
+        let corrupt = self.dir.join("corrupt");
+        let file = entry_file_name(seq);
+        eprintln!(
+            "Moving unreadable inbox entry {} to {}: {error}",
+            file,
+            corrupt.display()
+        );
+        fs::rename(self.dir.join(&file), corrupt.join(file))?;
+        self.pending.fetch_sub(1, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Persist a newly received message.
+    pub fn append(
+        &self,
+        message_type: &str,
+        message_id: &str,
+        payload: &Value,
+    ) -> io::Result<InboxEntry> {
+        let seq = self.next_seq.fetch_add(1, Ordering::Relaxed);
+        let entry = InboxEntry::new(seq, message_type, message_id, payload);
+        self.write(&entry)?;
+        self.pending.fetch_add(1, Ordering::Relaxed);
+        Ok(entry)
+    }
+
+    /// Record a failed attempt and when to retry, so both survive a restart.
+    pub fn record_failure(
+        &self,
+        entry: &mut InboxEntry,
+        error: &str,
+        retry_after: Duration,
+    ) -> io::Result<()> {
+        entry.fail(error, retry_after);
+        self.write(entry)
+    }
+
+    /// Record when a requeued entry is next due, without counting an attempt.
+    pub fn record_deferral(&self, entry: &mut InboxEntry, delay: Duration) -> io::Result<()> {
+        entry.defer(delay);
+        self.write(entry)
+    }
+
+    /// Acknowledge an entry: the handler succeeded, so drop it.
+    pub fn ack(&self, entry: &InboxEntry) -> io::Result<()> {
+        fs::remove_file(self.entry_path(entry.seq))?;
+        self.pending.fetch_sub(1, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Move an entry that exhausted its attempts (or expired) into `dead/`,
+    /// keeping its final state.
+    pub fn dead_letter(&self, entry: &InboxEntry) -> io::Result<()> {
+        self.write(entry)?;
+        let file = entry_file_name(entry.seq);
+        fs::rename(self.dir.join(&file), self.dir.join("dead").join(file))?;
+        self.pending.fetch_sub(1, Ordering::Relaxed);
+        Ok(())
+    }
+
+    fn entry_path(&self, seq: u64) -> PathBuf {
+        self.dir.join(entry_file_name(seq))
+    }
+
+    /// Write atomically (synced temp file + rename, then a synced
+    /// directory) so a crash never leaves a torn entry.
+    fn write(&self, entry: &InboxEntry) -> io::Result<()> {
+        let path = self.entry_path(entry.seq);
+        let tmp = path.with_extension("tmp");
+        let bytes = serde_json::to_vec(entry).map_err(io::Error::other)?;
+        let mut file = File::create(&tmp)?;
+        file.write_all(&bytes)?;
+        file.sync_all()?;
+        fs::rename(tmp, path)?;
+        File::open(&self.dir)?.sync_all()
+    }
+}
+
+fn entry_file_name(seq: u64) -> String {
+    format!("{seq:020}.json")
+}
+
+fn read_entry(path: &Path) -> io::Result<InboxEntry> {
+    let bytes = fs::read(path)?;
+    serde_json::from_slice(&bytes).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+/// Sequence numbers of all `*.json` entries directly inside `dir`.
+fn entry_seqs(dir: &Path) -> io::Result<Vec<u64>> {
+    let mut seqs = Vec::new();
+    for item in fs::read_dir(dir)? {
+        let path = item?.path();
+        if path.extension().is_some_and(|e| e == "json")
+            && let Some(seq) = path
+                .file_stem()
+                .and_then(|s| s.to_str())
+                .and_then(|s| s.parse().ok())
+        {
+            seqs.push(seq);
+        }
+    }
+    Ok(seqs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn temp_inbox_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("inbox-test-{}-{name}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn appended_entries_are_pending_in_order() {
+        let dir = temp_inbox_dir("order");
+        let inbox = Inbox::open(&dir).unwrap_or_else(|e| panic!("open: {e}"));
+
+        for i in 0..3 {
+            inbox
+                .append("t", &format!("m{i}"), &json!({"i": i}))
+                .unwrap_or_else(|e| panic!("append: {e}"));
+        }
+
+        let pending = inbox.pending().unwrap_or_else(|e| panic!("pending: {e}"));
+        let ids: Vec<&str> = pending.iter().map(|e| e.message_id.as_str()).collect();
+        assert_eq!(ids, ["m0", "m1", "m2"]);
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn ack_removes_entry() {
+        let dir = temp_inbox_dir("ack");
+        let inbox = Inbox::open(&dir).unwrap_or_else(|e| panic!("open: {e}"));
+        let entry = inbox
+            .append("t", "m", &json!({}))
+            .unwrap_or_else(|e| panic!("append: {e}"));
+
+        inbox.ack(&entry).unwrap_or_else(|e| panic!("ack: {e}"));
+
+        assert!(inbox.pending().unwrap_or_default().is_empty());
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn failures_and_sequence_survive_reopen() {
+        let dir = temp_inbox_dir("reopen");
+        let inbox = Inbox::open(&dir).unwrap_or_else(|e| panic!("open: {e}"));
+        let mut entry = inbox
+            .append("t", "m", &json!({}))
+            .unwrap_or_else(|e| panic!("append: {e}"));
+        inbox
+            .record_failure(&mut entry, "boom", Duration::from_secs(60))
+            .unwrap_or_else(|e| panic!("record: {e}"));
+
+        let reopened = Inbox::open(&dir).unwrap_or_else(|e| panic!("reopen: {e}"));
+        assert_eq!(reopened.len(), 1);
+        let pending = reopened.pending().unwrap_or_default();
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].attempts, 1);
+        assert_eq!(pending[0].last_error.as_deref(), Some("boom"));
+        assert_eq!(pending[0].received_at, entry.received_at);
+        assert!(pending[0].wait() > Duration::from_secs(50));
+
+        let next = reopened
+            .append("t", "n", &json!({}))
+            .unwrap_or_else(|e| panic!("append: {e}"));
+        assert_eq!(next.seq, entry.seq + 1);
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn dead_letter_moves_entry_out_of_pending() {
+        let dir = temp_inbox_dir("dead");
+        let inbox = Inbox::open(&dir).unwrap_or_else(|e| panic!("open: {e}"));
+        let entry = inbox
+            .append("t", "m", &json!({}))
+            .unwrap_or_else(|e| panic!("append: {e}"));
+
+        inbox
+            .dead_letter(&entry)
+            .unwrap_or_else(|e| panic!("dead_letter: {e}"));
+
+        assert!(inbox.pending().unwrap_or_default().is_empty());
+        assert!(inbox.is_empty());
+        assert!(dir.join("dead").join(entry_file_name(entry.seq)).exists());
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn unreadable_entries_are_quarantined() {
+        let dir = temp_inbox_dir("corrupt");
+        let inbox = Inbox::open(&dir).unwrap_or_else(|e| panic!("open: {e}"));
+        for id in ["m0", "m1"] {
+            inbox
+                .append("t", id, &json!({}))
+                .unwrap_or_else(|e| panic!("append: {e}"));
+        }
+        fs::write(dir.join(entry_file_name(1)), b"{\"seq\": 0, \"mess")
+            .unwrap_or_else(|e| panic!("write: {e}"));
+
+        let reopened = Inbox::open(&dir).unwrap_or_else(|e| panic!("reopen: {e}"));
+        let pending = reopened
+            .pending()
+            .unwrap_or_else(|e| panic!("pending: {e}"));
+        let ids: Vec<&str> = pending.iter().map(|e| e.message_id.as_str()).collect();
+        assert_eq!(ids, ["m0"]);
+        assert_eq!(reopened.len(), 1);
+        assert!(dir.join("corrupt").join(entry_file_name(1)).exists());
+
+        // The quarantined sequence number is not reused
+        let next = reopened
+            .append("t", "m2", &json!({}))
+            .unwrap_or_else(|e| panic!("append: {e}"));
+        assert_eq!(next.seq, 2);
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn entries_without_timestamps_are_treated_as_new() {
+        let entry: InboxEntry = serde_json::from_value(json!({
+            "seq": 1, "message_type": "t", "message_id": "m", "payload": {}, "attempts": 2
+        }))
+        .unwrap_or_else(|e| panic!("parse: {e}"));
+        assert!(entry.age() < Duration::from_secs(5));
+        assert_eq!(entry.wait(), Duration::ZERO);
+    }
+}
@@ -0,0 +1,94 @@
+//! Message key extraction.
+//!
+//! Keys pick out one payload field (e.g. `payload.user_id`) so related
+//! messages can be routed together — to the same worker for ordered
+//! processing, or to the same replica for sharding.
+
+use serde_json::Value;
+
+/// Resolve a dotted path such as `payload.user.id` (or `user.id`) against a
+/// payload. A leading `payload.` segment is optional.
+pub fn lookup<'a>(payload: &'a Value, path: &str) -> Option<&'a Value> {
+    let path = path.strip_prefix("payload").unwrap_or(path);
+    path.split('.')
+        .filter(|s| !s.is_empty())
+        .try_fold(payload, |value, segment| match value {
+            Value::Object(map) => map.get(segment),
+            Value::Array(items) => segment.parse::<usize>().ok().and_then(|i| items.get(i)),
+            _ => None,
+        })
+}
+
+/// The key string for `payload` at `path`, or `None` if the field is absent.
+///
+/// Strings are used verbatim; other values use their JSON encoding so `1`
+/// and `"1"` stay distinct.
+pub fn extract(payload: &Value, path: &str) -> Option<String> {
+    lookup(payload, path).map(|v| match v {
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    })
+}
+
+/// Stable 64-bit FNV-1a hash.
+///
+/// Unlike `DefaultHasher`, the result is identical across processes and Rust
+/// versions, which matters when several replicas must agree on a bucket.
+pub fn stable_hash(key: &str) -> u64 {
+    const OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+    const PRIME: u64 = 0x0000_0100_0000_01b3;
+    key.bytes().fold(OFFSET, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(PRIME)
+    })
+}
+
+/// Map `key` onto one of `buckets` buckets.
+pub fn bucket(key: &str, buckets: usize) -> usize {
+    if buckets <= 1 {
+        return 0;
+    }
+    (stable_hash(key) % buckets as u64) as usize
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn lookup_accepts_optional_payload_prefix() {
+        let payload = json!({"user": {"id": 42}});
+        assert_eq!(lookup(&payload, "payload.user.id"), Some(&json!(42)));
+        assert_eq!(lookup(&payload, "user.id"), Some(&json!(42)));
+    }
+
+    #[test]
+    fn lookup_indexes_arrays() {
+        let payload = json!({"items": [{"sku": "a"}, {"sku": "b"}]});
+        assert_eq!(lookup(&payload, "items.1.sku"), Some(&json!("b")));
+    }
+
+    #[test]
+    fn extract_keeps_strings_verbatim() {
+        let payload = json!({"id": "abc", "n": 1});
+        assert_eq!(extract(&payload, "id").as_deref(), Some("abc"));
+        assert_eq!(extract(&payload, "n").as_deref(), Some("1"));
+        assert_eq!(extract(&payload, "missing"), None);
+    }
+
+    #[test]
+    fn stable_hash_matches_reference_vectors() {
+        assert_eq!(stable_hash(""), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(stable_hash("a"), 0xaf63_dc4c_8601_ec8c);
+    }
+
+    #[test]
+    fn bucket_is_deterministic_and_in_range() {
+        for key in ["alpha", "beta", "gamma"] {
+            let b = bucket(key, 3);
+            assert!(b < 3);
+            assert_eq!(b, bucket(key, 3));
+        }
+        assert_eq!(bucket("anything", 1), 0);
+    }
+}
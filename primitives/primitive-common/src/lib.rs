@@ -0,0 +1,49 @@
+//! Shared runtime harness for Emergent primitives.
+//!
+//! Provides:
+//! - `sink::run_sink` — connect, subscribe, and drive a sink's message loop
+//! - `sink::SinkArgs` — CLI flags every sink inherits (`--dry-run`, ...)
+//! - `sink::SinkHandler` — the per-message trait a sink implements
+//! - `handler::run_handler` — the same for handlers, publishing through a `handler::Bus`
+//! - `aws::SigV4` — AWS Signature Version 4 request signing
+//! - `capabilities` — `--version` build info and `primitive.capabilities` descriptors
+//! - `crypto` — age encryption of sensitive topics' payloads
+//! - `doctor::Report` — `--self-test` checks and pass/fail reporting
+//! - `errors` — categorized handler errors and `primitive.error` events
+//! - `glob` — `*` patterns over message types and names
+//! - `inbox::Inbox` — on-disk queue backing at-least-once delivery
+//! - `key` — payload key extraction and stable hashing for ordering/sharding
+//! - `payload` — zstd compression and blob offloading of large payloads
+//! - `reload::HotConfig` — settings re-read from `--config` on SIGHUP
+//! - `source` — `SourceArgs` and the `Outlet` sources publish events through
+//! - `template` — `{name}` placeholders filled from a lookup or a payload
+//! - `spool::Spool` — on-disk queue for source events while the engine is down
+//! - `shard::ShardArgs` — partition a subscription across sink replicas
+//! - `time` — UTC timestamps and calendar arithmetic
+//! - `topics::TopicArgs` — `--emit-type-map` / `--emit-type-template` for sources
+//! - `shutdown::DrainArgs` — `--drain-timeout` for graceful shutdown
+
+pub mod aws;
+pub mod capabilities;
+pub mod crypto;
+pub mod doctor;
+pub mod errors;
+pub mod glob;
+pub mod handler;
+pub mod inbox;
+pub mod key;
+pub mod payload;
+pub mod reload;
+pub mod shard;
+pub mod shutdown;
+pub mod sink;
+pub mod source;
+pub mod spool;
+pub mod template;
+pub mod time;
+pub mod topics;
+
+pub use sink::{
+    Loopback, Publisher, SinkArgs, SinkConfig, SinkContext, SinkHandler, run_sink,
+    run_sink_loopback,
+};
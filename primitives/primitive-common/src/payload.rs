@@ -0,0 +1,502 @@
+//! Payload compression and large-message offloading.
+//!
+//! Webhook bodies and command output can be far larger than the bus is
+//! comfortable carrying. Publishers run payloads through [`encode`], which
+//! leaves small payloads untouched and replaces large ones with an envelope:
+//!
+//! ```json
+//! {"$emergent": "zstd", "size": 182044, "data": "<base64 zstd frame>"}
+//! {"$emergent": "blob", "size": 9120331, "sha256": "…", "path": "/var/lib/emergent/blobs/…"}
+//! {"$emergent": "blob", "size": 9120331, "sha256": "…", "url": "https://…/….json", "region": "eu-west-1"}
+//! ```
+//!
+//! Blobs go to `--offload-dir`, a directory consumers can read, or to
+//! `--offload-url`, an S3 (or S3-compatible) bucket URL. Bucket requests are
+//! signed with [`crate::aws::SigV4`] using credentials from
+//! [`crate::aws::CredentialChain`], on both the publishing and the consuming
+//! side, for the region in `--offload-region` (recorded in the envelope).
+//!
+//! Consumers call [`decode`], which reverses either envelope and passes any
+//! other payload through unchanged, so encoded and plain producers can share
+//! a topic. Blobs are content-addressed by SHA-256 and never deleted by the
+//! primitives; prune the directory or bucket out of band (for example with a
+//! lifecycle rule).
+//!
+//! Payloads of topics configured for encryption are sealed first (see
+//! [`crate::crypto`]), so compression and offloading only ever see
+//! ciphertext. [`decode`] opens sealed payloads when given a [`Keyring`] and
+//! otherwise returns the `age` envelope as-is.
+
+use crate::aws::{self, CredentialChain, SigV4};
+use crate::crypto::{self, ENVELOPE, EncryptArgs, Keyring};
+use crate::doctor::{Report, check_writable_dir};
+use base64::{Engine, engine::general_purpose::STANDARD};
+use clap::Args;
+use reqwest::{Client, Method, Response, header::CONTENT_TYPE};
+use serde_json::{Value, json};
+use sha2::{Digest, Sha256};
+use std::{
+    borrow::Cow,
+    fs, io,
+    path::PathBuf,
+    sync::OnceLock,
+    time::{Duration, SystemTime},
+};
+
+/// Envelope marker key.
+pub(crate) const MARKER: &str = "$emergent";
+
+/// zstd compression level: favour speed, payloads are compressed inline.
+const ZSTD_LEVEL: i32 = 3;
+
+/// Deepest envelope nesting [`decode`] unwraps (a blob holding a sealed payload).
+const MAX_ENVELOPES: usize = 3;
+
+/// CLI flags controlling payload encoding on publish.
+#[derive(Args, Debug, Clone, Default)]
+pub struct PayloadArgs {
+    /// Compress payloads whose JSON encoding exceeds this many bytes (zstd).
+    #[arg(long, env = "EMERGENT_COMPRESS_ABOVE")]
+    pub compress_above: Option<usize>,
+
+    /// Offload payloads exceeding this many bytes to `--offload-dir` or `--offload-url` and publish a reference.
+    #[arg(long, env = "EMERGENT_OFFLOAD_ABOVE", requires = "offload_target")]
+    pub offload_above: Option<usize>,
+
+    /// Directory for offloaded payload blobs (must be readable by consumers).
+    #[arg(long, env = "EMERGENT_OFFLOAD_DIR", group = "offload_target")]
+    pub offload_dir: Option<PathBuf>,
+
+    /// S3 or S3-compatible bucket URL (with optional key prefix) for offloaded payload blobs.
+    #[arg(long, env = "EMERGENT_OFFLOAD_URL", group = "offload_target")]
+    pub offload_url: Option<String>,
+
+    /// Region bucket requests are signed for [default: AWS_REGION, AWS_DEFAULT_REGION, or us-east-1].
+    #[arg(long, env = "EMERGENT_OFFLOAD_REGION")]
+    pub offload_region: Option<String>,
+
+    #[command(flatten)]
+    pub encryption: EncryptArgs,
+}
+
+impl PayloadArgs {
+    /// Add `--self-test` checks for the configured offload target.
+    pub fn self_test(&self, report: &mut Report) {
+        if let Some(dir) = &self.offload_dir {
+            report.check("offload-dir", check_writable_dir(dir));
+        }
+        if let Some(url) = &self.offload_url {
+            report.check(
+                "offload-url",
+                check_bucket_url(url).map(|()| format!("{url} ({})", self.region())),
+            );
+        }
+        self.encryption.self_test(report);
+    }
+
+    /// The region bucket requests are signed for.
+    fn region(&self) -> String {
+        self.offload_region
+            .clone()
+            .unwrap_or_else(aws::default_region)
+    }
+}
+
+/// Check that `--offload-url` is an absolute HTTP(S) URL.
+fn check_bucket_url(url: &str) -> Result<(), String> {
+    match reqwest::Url::parse(url) {
+        Ok(parsed) if matches!(parsed.scheme(), "http" | "https") => Ok(()),
+        Ok(_) => Err(format!("{url}: not an http(s) URL")),
+        Err(e) => Err(format!("{url}: {e}")),
+    }
+}
+
+/// The HTTP client and credential cache shared by every bucket request in
+/// the process, so temporary credentials are fetched once.
+struct Bucket {
+    client: Client,
+    chain: CredentialChain,
+}
+
+static BUCKET: OnceLock<Bucket> = OnceLock::new();
+
+/// Bucket requests give up after this long.
+const BUCKET_TIMEOUT: Duration = Duration::from_secs(30);
+
+impl Bucket {
+    fn shared() -> &'static Self {
+        BUCKET.get_or_init(|| {
+            let client = Client::new();
+            Self {
+                chain: CredentialChain::new(client.clone(), None, None),
+                client,
+            }
+        })
+    }
+
+    /// Send a signed request for the object at `url`.
+    async fn send(
+        &self,
+        method: Method,
+        url: &str,
+        region: &str,
+        body: Option<Vec<u8>>,
+    ) -> io::Result<Response> {
+        let mut request = self.client.request(method, url).timeout(BUCKET_TIMEOUT);
+        if let Some(body) = body {
+            request = request.header(CONTENT_TYPE, "application/json").body(body);
+        }
+        let mut request = request.build().map_err(io::Error::other)?;
+        let credentials = self.chain.credentials().await.map_err(io::Error::other)?;
+        SigV4::new(region, "s3")
+            .sign(&mut request, &credentials, SystemTime::now())
+            .map_err(io::Error::other)?;
+        self.client
+            .execute(request)
+            .await
+            .and_then(Response::error_for_status)
+            .map_err(|e| io::Error::other(format!("{url}: {e}")))
+    }
+
+    async fn put(&self, url: &str, region: &str, bytes: Vec<u8>) -> io::Result<()> {
+        self.send(Method::PUT, url, region, Some(bytes)).await?;
+        Ok(())
+    }
+
+    async fn get(&self, url: &str, region: &str) -> io::Result<Vec<u8>> {
+        let response = self.send(Method::GET, url, region, None).await?;
+        let bytes = response
+            .bytes()
+            .await
+            .map_err(|e| io::Error::other(format!("{url}: {e}")))?;
+        Ok(bytes.to_vec())
+    }
+}
+
+/// Encode a payload of `message_type` for publishing according to `args`.
+///
+/// Encryption applies first; offloading takes precedence over compression
+/// when both thresholds apply.
+pub async fn encode(message_type: &str, payload: Value, args: &PayloadArgs) -> io::Result<Value> {
+    let payload = crypto::seal(message_type, payload, &args.encryption)?;
+    if args.compress_above.is_none() && args.offload_above.is_none() {
+        return Ok(payload);
+    }
+
+    let bytes = serde_json::to_vec(&payload).map_err(io::Error::other)?;
+
+    if let (Some(limit), Some(url)) = (args.offload_above, &args.offload_url)
+        && bytes.len() > limit
+    {
+        let digest = hex::encode(Sha256::digest(&bytes));
+        let url = format!("{}/{digest}.json", url.trim_end_matches('/'));
+        let region = args.region();
+        let size = bytes.len();
+        Bucket::shared().put(&url, &region, bytes).await?;
+        return Ok(json!({
+            MARKER: "blob",
+            "size": size,
+            "sha256": digest,
+            "url": url,
+            "region": region,
+        }));
+    }
+
+    if let (Some(limit), Some(dir)) = (args.offload_above, &args.offload_dir)
+        && bytes.len() > limit
+    {
+        let digest = hex::encode(Sha256::digest(&bytes));
+        fs::create_dir_all(dir)?;
+        let path = dir.join(format!("{digest}.json"));
+        if !path.exists() {
+            let tmp = path.with_extension("tmp");
+            fs::write(&tmp, &bytes)?;
+            fs::rename(&tmp, &path)?;
+        }
+        return Ok(json!({
+            MARKER: "blob",
+            "size": bytes.len(),
+            "sha256": digest,
+            "path": path.to_string_lossy(),
+        }));
+    }
+
+    if let Some(limit) = args.compress_above
+        && bytes.len() > limit
+    {
+        let compressed = zstd::encode_all(bytes.as_slice(), ZSTD_LEVEL)?;
+        return Ok(json!({
+            MARKER: "zstd",
+            "size": bytes.len(),
+            "data": STANDARD.encode(compressed),
+        }));
+    }
+
+    Ok(payload)
+}
+
+/// Decode a payload produced by [`encode`]; plain payloads are borrowed as-is.
+///
+/// Sealed payloads are decrypted with `keys`; without keys their envelope is
+/// returned unchanged.
+pub async fn decode<'a>(payload: &'a Value, keys: Option<&Keyring>) -> io::Result<Cow<'a, Value>> {
+    let mut current = Cow::Borrowed(payload);
+    for _ in 0..MAX_ENVELOPES {
+        match unwrap_envelope(&current, keys).await? {
+            Some(inner) => current = Cow::Owned(inner),
+            None => return Ok(current),
+        }
+    }
+    Err(invalid("too many nested payload envelopes"))
+}
+
+/// Reverse one envelope, or `None` if `payload` is plain (or sealed and no
+/// keys were given).
+async fn unwrap_envelope(payload: &Value, keys: Option<&Keyring>) -> io::Result<Option<Value>> {
+    let Some(kind) = payload.get(MARKER).and_then(Value::as_str) else {
+        return Ok(None);
+    };
+
+    let bytes = match kind {
+        ENVELOPE => return keys.map(|k| k.open(payload)).transpose(),
+        "zstd" => {
+            let data = payload
+                .get("data")
+                .and_then(Value::as_str)
+                .ok_or_else(|| invalid("zstd envelope has no data"))?;
+            let compressed = STANDARD.decode(data).map_err(invalid)?;
+            zstd::decode_all(compressed.as_slice())?
+        }
+        "blob" => {
+            let (location, bytes) = match payload.get("url").and_then(Value::as_str) {
+                Some(url) => {
+                    let region = payload
+                        .get("region")
+                        .and_then(Value::as_str)
+                        .map_or_else(aws::default_region, str::to_string);
+                    (url, Bucket::shared().get(url, &region).await?)
+                }
+                None => {
+                    let path = payload
+                        .get("path")
+                        .and_then(Value::as_str)
+                        .ok_or_else(|| invalid("blob envelope has no path or url"))?;
+                    (path, fs::read(path)?)
+                }
+            };
+            if let Some(expected) = payload.get("sha256").and_then(Value::as_str)
+                && hex::encode(Sha256::digest(&bytes)) != expected
+            {
+                return Err(invalid(format!(
+                    "blob {location} failed checksum verification"
+                )));
+            }
+            bytes
+        }
+        other => return Err(invalid(format!("unknown payload envelope '{other}'"))),
+    };
+
+    serde_json::from_slice(&bytes).map(Some).map_err(invalid)
+}
+
+fn invalid(e: impl Into<Box<dyn std::error::Error + Send + Sync>>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn large_payload() -> Value {
+        json!({"output": "x".repeat(4096)})
+    }
+
+    #[tokio::test]
+    async fn small_payloads_pass_through() {
+        let args = PayloadArgs {
+            compress_above: Some(1024),
+            ..Default::default()
+        };
+        let payload = json!({"ok": true});
+        let encoded = encode("test.event", payload.clone(), &args)
+            .await
+            .unwrap_or_else(|e| panic!("encode: {e}"));
+        assert_eq!(encoded, payload);
+    }
+
+    #[tokio::test]
+    async fn compressed_payload_round_trips() {
+        let args = PayloadArgs {
+            compress_above: Some(1024),
+            ..Default::default()
+        };
+        let encoded = encode("test.event", large_payload(), &args)
+            .await
+            .unwrap_or_else(|e| panic!("encode: {e}"));
+        assert_eq!(encoded[MARKER], "zstd");
+
+        let decoded = decode(&encoded, None)
+            .await
+            .unwrap_or_else(|e| panic!("decode: {e}"));
+        assert_eq!(decoded.into_owned(), large_payload());
+    }
+
+    #[tokio::test]
+    async fn offloaded_payload_round_trips() {
+        let dir = std::env::temp_dir().join(format!("blob-test-{}", std::process::id()));
+        let args = PayloadArgs {
+            compress_above: Some(16),
+            offload_above: Some(1024),
+            offload_dir: Some(dir.clone()),
+            ..Default::default()
+        };
+        let encoded = encode("test.event", large_payload(), &args)
+            .await
+            .unwrap_or_else(|e| panic!("encode: {e}"));
+        assert_eq!(encoded[MARKER], "blob");
+
+        let decoded = decode(&encoded, None)
+            .await
+            .unwrap_or_else(|e| panic!("decode: {e}"));
+        assert_eq!(decoded.into_owned(), large_payload());
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[tokio::test]
+    async fn unknown_envelope_is_an_error() {
+        let payload = json!({MARKER: "lz4", "data": ""});
+        assert!(decode(&payload, None).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn sealed_payload_is_offloaded_as_ciphertext_and_opened_with_keys() {
+        let dir = std::env::temp_dir().join(format!("sealed-blob-test-{}", std::process::id()));
+        let (keys, recipient) = Keyring::generate();
+        let args = PayloadArgs {
+            offload_above: Some(1024),
+            offload_dir: Some(dir.clone()),
+            encryption: EncryptArgs {
+                encrypt_topic: vec!["test.*".to_string()],
+                encrypt_to: vec![recipient],
+            },
+            ..Default::default()
+        };
+        let encoded = encode("test.event", large_payload(), &args)
+            .await
+            .unwrap_or_else(|e| panic!("encode: {e}"));
+        assert_eq!(encoded[MARKER], "blob");
+
+        // Without keys the blob unwraps to the sealed envelope
+        let sealed = decode(&encoded, None)
+            .await
+            .unwrap_or_else(|e| panic!("decode: {e}"));
+        assert_eq!(sealed[MARKER], ENVELOPE);
+
+        let decoded = decode(&encoded, Some(&keys))
+            .await
+            .unwrap_or_else(|e| panic!("decode: {e}"));
+        assert_eq!(decoded.into_owned(), large_payload());
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[tokio::test]
+    async fn bucket_offload_round_trips_with_signed_requests() {
+        use crate::aws::Credentials;
+        use axum::{
+            Router,
+            body::Bytes,
+            extract::{Path, State},
+            http::{HeaderMap, StatusCode},
+            routing::get,
+        };
+        use std::{
+            collections::HashMap,
+            sync::{Arc, Mutex},
+        };
+
+        type Objects = Arc<Mutex<HashMap<String, Vec<u8>>>>;
+        fn signed(headers: &HeaderMap) -> bool {
+            headers
+                .get("authorization")
+                .and_then(|v| v.to_str().ok())
+                .is_some_and(|v| v.contains("/eu-west-1/s3/aws4_request"))
+        }
+        let objects = Objects::default();
+        let app = Router::new()
+            .route(
+                "/bucket/prefix/{key}",
+                get(
+                    |State(objects): State<Objects>,
+                     Path(key): Path<String>,
+                     headers: HeaderMap| async move {
+                        if !signed(&headers) {
+                            return Err(StatusCode::FORBIDDEN);
+                        }
+                        let objects = objects.lock().unwrap_or_else(|e| e.into_inner());
+                        objects.get(&key).cloned().ok_or(StatusCode::NOT_FOUND)
+                    },
+                )
+                .put(
+                    |State(objects): State<Objects>,
+                     Path(key): Path<String>,
+                     headers: HeaderMap,
+                     body: Bytes| async move {
+                        if !signed(&headers) {
+                            return StatusCode::FORBIDDEN;
+                        }
+                        let mut objects = objects.lock().unwrap_or_else(|e| e.into_inner());
+                        objects.insert(key, body.to_vec());
+                        StatusCode::OK
+                    },
+                ),
+            )
+            .with_state(Arc::clone(&objects));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap_or_else(|e| panic!("bind: {e}"));
+        let addr = listener
+            .local_addr()
+            .unwrap_or_else(|e| panic!("local_addr: {e}"));
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        // Fixed keys instead of the environment's
+        let client = Client::new();
+        let credentials = Credentials {
+            access_key_id: "AKIDEXAMPLE".to_string(),
+            secret_access_key: "secret".to_string(),
+            session_token: None,
+        };
+        let _ = BUCKET.set(Bucket {
+            chain: CredentialChain::new(client.clone(), Some(credentials), None),
+            client,
+        });
+
+        let args = PayloadArgs {
+            offload_above: Some(1024),
+            offload_url: Some(format!("http://{addr}/bucket/prefix/")),
+            offload_region: Some("eu-west-1".to_string()),
+            ..Default::default()
+        };
+        let encoded = encode("test.event", large_payload(), &args)
+            .await
+            .unwrap_or_else(|e| panic!("encode: {e}"));
+        assert_eq!(encoded[MARKER], "blob");
+        assert_eq!(encoded["region"], "eu-west-1");
+        let digest = encoded["sha256"].as_str().unwrap_or_default();
+        assert_eq!(
+            encoded["url"],
+            format!("http://{addr}/bucket/prefix/{digest}.json")
+        );
+        assert_eq!(objects.lock().map(|o| o.len()).unwrap_or_default(), 1);
+
+        let decoded = decode(&encoded, None)
+            .await
+            .unwrap_or_else(|e| panic!("decode: {e}"));
+        assert_eq!(decoded.into_owned(), large_payload());
+
+        // A missing object is an error, not a plain payload
+        let mut missing = encoded.clone();
+        missing["url"] = json!(format!("http://{addr}/bucket/prefix/gone.json"));
+        assert!(decode(&missing, None).await.is_err());
+    }
+}
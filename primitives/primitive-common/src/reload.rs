@@ -0,0 +1,235 @@
+//! Hot configuration reload (SIGHUP).
+//!
+//! Settings that are safe to swap at runtime — commands, timeouts,
+//! templates, credentials — can be overridden from a JSON file given with
+//! `--config`. The file holds a subset of the primitive's settings; keys it
+//! omits keep the value from the command line:
+//!
+//! ```json
+//! {"timeout": 60000, "command": ["./page-oncall.sh", "--v2"]}
+//! ```
+//!
+//! On SIGHUP the file is re-read and applied without dropping the engine
+//! connection. A file that fails to parse is reported and the previous
+//! settings stay in effect. Successful reloads are announced as
+//! `primitive.reloaded` events listing the names (never the values) of the
+//! settings that changed.
+
+use clap::Args;
+use emergent_client::EmergentMessage;
+use event_schemas::{EventPayload, PrimitiveReloaded};
+use serde::{Serialize, de::DeserializeOwned};
+use serde_json::Value;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::{Arc, PoisonError, RwLock},
+};
+
+/// Message type of reload announcements.
+pub const RELOADED_EVENT_TYPE: &str = PrimitiveReloaded::MESSAGE_TYPE;
+
+/// CLI flag naming the hot-reloadable config file.
+#[derive(Args, Debug, Clone, Default)]
+pub struct ReloadArgs {
+    /// JSON file of hot-swappable settings, re-read on SIGHUP.
+    #[arg(long, env = "EMERGENT_CONFIG")]
+    pub config: Option<PathBuf>,
+}
+
+/// The settings currently in effect, with their JSON form for diffing.
+struct Snapshot<T> {
+    value: Value,
+    settings: Arc<T>,
+}
+
+/// Settings of type `T` built from command-line defaults overlaid with an
+/// optional config file, swappable at runtime via [`HotConfig::reload`].
+pub struct HotConfig<T> {
+    path: Option<PathBuf>,
+    defaults: Value,
+    current: RwLock<Snapshot<T>>,
+}
+
+impl<T: Serialize + DeserializeOwned> HotConfig<T> {
+    /// Resolve the initial settings: `defaults` overlaid with `--config`.
+    pub fn load(args: &ReloadArgs, defaults: T) -> Result<Self, String> {
+        let defaults = serde_json::to_value(&defaults).map_err(|e| e.to_string())?;
+        let (value, settings) = resolve(&defaults, args.config.as_deref())?;
+        Ok(Self {
+            path: args.config.clone(),
+            defaults,
+            current: RwLock::new(Snapshot {
+                value,
+                settings: Arc::new(settings),
+            }),
+        })
+    }
+
+    /// The settings currently in effect.
+    pub fn current(&self) -> Arc<T> {
+        let snapshot = self.current.read().unwrap_or_else(PoisonError::into_inner);
+        Arc::clone(&snapshot.settings)
+    }
+
+    /// Re-read the config file and swap in the result.
+    ///
+    /// Returns the names of the settings that changed (empty without
+    /// `--config`). On error the current settings are left untouched.
+    pub fn reload(&self) -> Result<Vec<String>, String> {
+        let Some(path) = &self.path else {
+            return Ok(Vec::new());
+        };
+        let (value, settings) = resolve(&self.defaults, Some(path))?;
+        let mut snapshot = self.current.write().unwrap_or_else(PoisonError::into_inner);
+        let changed = diff(&snapshot.value, &value);
+        *snapshot = Snapshot {
+            value,
+            settings: Arc::new(settings),
+        };
+        Ok(changed)
+    }
+}
+
+/// Overlay the file at `path` (if any) on `defaults` and deserialize.
+fn resolve<T: DeserializeOwned>(
+    defaults: &Value,
+    path: Option<&Path>,
+) -> Result<(Value, T), String> {
+    let mut value = defaults.clone();
+    if let Some(path) = path {
+        let overrides = read_config(path)?;
+        let Value::Object(target) = &mut value else {
+            return Err("settings must serialize to a JSON object".to_string());
+        };
+        for (key, setting) in overrides {
+            if !target.contains_key(&key) {
+                return Err(format!("{}: unknown setting '{key}'", path.display()));
+            }
+            target.insert(key, setting);
+        }
+    }
+    let settings = serde_json::from_value(value.clone()).map_err(|e| match path {
+        Some(path) => format!("{}: {e}", path.display()),
+        None => e.to_string(),
+    })?;
+    Ok((value, settings))
+}
+
+/// Read a config file, which must contain a JSON object.
+fn read_config(path: &Path) -> Result<serde_json::Map<String, Value>, String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("{}: {e}", path.display()))?;
+    match serde_json::from_str(&text) {
+        Ok(Value::Object(map)) => Ok(map),
+        Ok(_) => Err(format!("{}: expected a JSON object", path.display())),
+        Err(e) => Err(format!("{}: {e}", path.display())),
+    }
+}
+
+/// Check that a config file can be read and parsed (for `--self-test`).
+pub fn check_config(path: &Path) -> Result<String, String> {
+    read_config(path).map(|map| format!("{} ({} settings)", path.display(), map.len()))
+}
+
+/// Top-level keys whose values differ between `old` and `new`, sorted.
+fn diff(old: &Value, new: &Value) -> Vec<String> {
+    let (Value::Object(old), Value::Object(new)) = (old, new) else {
+        return Vec::new();
+    };
+    let mut changed: Vec<String> = old
+        .keys()
+        .chain(new.keys())
+        .filter(|key| old.get(*key) != new.get(*key))
+        .cloned()
+        .collect();
+    changed.sort();
+    changed.dedup();
+    changed
+}
+
+/// Build the `primitive.reloaded` announcement.
+pub fn reloaded_message(primitive: &str, config: &Path, changed: &[String]) -> EmergentMessage {
+    let payload = PrimitiveReloaded {
+        primitive: primitive.to_string(),
+        config: config.to_string_lossy().into_owned(),
+        changed: changed.to_vec(),
+    };
+    EmergentMessage::new(RELOADED_EVENT_TYPE).with_payload(payload.to_payload())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+
+    #[derive(Debug, Serialize, Deserialize, PartialEq)]
+    struct Settings {
+        command: Vec<String>,
+        timeout: u64,
+    }
+
+    fn defaults() -> Settings {
+        Settings {
+            command: vec!["jq".to_string(), ".".to_string()],
+            timeout: 30_000,
+        }
+    }
+
+    fn config_file(name: &str, contents: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("reload-{name}-{}.json", std::process::id()));
+        fs::write(&path, contents).unwrap_or_else(|e| panic!("write config: {e}"));
+        path
+    }
+
+    #[test]
+    fn file_overrides_defaults() {
+        let path = config_file("override", r#"{"timeout": 5000}"#);
+        let args = ReloadArgs {
+            config: Some(path.clone()),
+        };
+        let config = HotConfig::load(&args, defaults()).unwrap_or_else(|e| panic!("load: {e}"));
+
+        assert_eq!(config.current().timeout, 5000);
+        assert_eq!(config.current().command, defaults().command);
+        let _ = fs::remove_file(path);
+    }
+
+    #[test]
+    fn reload_reports_changed_settings() {
+        let path = config_file("changed", r#"{"timeout": 5000}"#);
+        let args = ReloadArgs {
+            config: Some(path.clone()),
+        };
+        let config = HotConfig::load(&args, defaults()).unwrap_or_else(|e| panic!("load: {e}"));
+
+        fs::write(&path, r#"{"timeout": 5000, "command": ["cat"]}"#)
+            .unwrap_or_else(|e| panic!("rewrite config: {e}"));
+        let changed = config.reload().unwrap_or_else(|e| panic!("reload: {e}"));
+
+        assert_eq!(changed, vec!["command".to_string()]);
+        assert_eq!(config.current().command, vec!["cat".to_string()]);
+        let _ = fs::remove_file(path);
+    }
+
+    #[test]
+    fn bad_reload_keeps_previous_settings() {
+        let path = config_file("bad", r#"{"timeout": 5000}"#);
+        let args = ReloadArgs {
+            config: Some(path.clone()),
+        };
+        let config = HotConfig::load(&args, defaults()).unwrap_or_else(|e| panic!("load: {e}"));
+
+        fs::write(&path, r#"{"retries": 3}"#).unwrap_or_else(|e| panic!("rewrite config: {e}"));
+        assert!(config.reload().is_err());
+        assert_eq!(config.current().timeout, 5000);
+        let _ = fs::remove_file(path);
+    }
+
+    #[test]
+    fn without_a_file_reload_is_a_no_op() {
+        let config = HotConfig::load(&ReloadArgs::default(), defaults())
+            .unwrap_or_else(|e| panic!("load: {e}"));
+        assert_eq!(config.reload(), Ok(Vec::new()));
+        assert_eq!(*config.current(), defaults());
+    }
+}
@@ -0,0 +1,246 @@
+//! Sharding messages across sink replicas.
+//!
+//! Every replica of a sink receives every message. With `--shard-key`, each
+//! replica keeps only the messages whose key lands on it and silently skips
+//! the rest, so replicas partition the work without duplication. Two ways
+//! to assign keys:
+//!
+//! - Static: `--shard-index 1 --shard-count 3`. Each key maps to one index
+//!   via [`key::bucket`]; every replica must use the same count.
+//! - Rendezvous: `--shard-peers a,b,c` lists the replicas' names (the
+//!   orchestrator can pass it as `EMERGENT_SHARD_PEERS`). Each key goes to
+//!   the peer with the highest `hash(peer, key)`, so adding or removing a
+//!   peer only moves the keys that peer gains or loses.
+//!
+//! Messages without the key field are all treated as the empty key and so
+//! land on a single replica.
+
+use crate::doctor::Report;
+use crate::key;
+use clap::Args;
+use serde_json::Value;
+
+/// CLI flags for partitioning messages across replicas.
+#[derive(Args, Debug, Clone)]
+pub struct ShardArgs {
+    /// Payload field (e.g. `payload.user_id`) that decides which replica handles a message.
+    #[arg(long, env = "EMERGENT_SHARD_KEY")]
+    pub shard_key: Option<String>,
+
+    /// This replica's index, from 0 to `--shard-count` - 1.
+    #[arg(long, env = "EMERGENT_SHARD_INDEX", default_value = "0")]
+    pub shard_index: usize,
+
+    /// Number of replicas sharing the subscription.
+    #[arg(long, env = "EMERGENT_SHARD_COUNT", default_value = "1")]
+    pub shard_count: usize,
+
+    /// Names of all replicas for rendezvous hashing (overrides index/count); comma-separated.
+    #[arg(long, env = "EMERGENT_SHARD_PEERS", value_delimiter = ',')]
+    pub shard_peers: Vec<String>,
+}
+
+impl Default for ShardArgs {
+    fn default() -> Self {
+        Self {
+            shard_key: None,
+            shard_index: 0,
+            shard_count: 1,
+            shard_peers: Vec::new(),
+        }
+    }
+}
+
+impl ShardArgs {
+    /// Resolve the shard this replica (named `name`) owns, or `None` when
+    /// sharding is off.
+    pub fn resolve(&self, name: &str) -> Result<Option<Shard>, String> {
+        let Some(path) = &self.shard_key else {
+            if self.shard_count > 1 || !self.shard_peers.is_empty() {
+                return Err("sharding requires --shard-key".to_string());
+            }
+            return Ok(None);
+        };
+
+        let assignment = if self.shard_peers.is_empty() {
+            if self.shard_count == 0 || self.shard_index >= self.shard_count {
+                return Err(format!(
+                    "--shard-index {} is out of range for --shard-count {}",
+                    self.shard_index, self.shard_count
+                ));
+            }
+            Assignment::Static {
+                index: self.shard_index,
+                count: self.shard_count,
+            }
+        } else {
+            let peers: Vec<String> = self
+                .shard_peers
+                .iter()
+                .map(|p| p.trim().to_string())
+                .filter(|p| !p.is_empty())
+                .collect();
+            if !peers.iter().any(|p| p == name) {
+                return Err(format!(
+                    "'{name}' is not among --shard-peers ({})",
+                    peers.join(", ")
+                ));
+            }
+            Assignment::Rendezvous {
+                me: name.to_string(),
+                peers,
+            }
+        };
+        Ok(Some(Shard {
+            path: path.clone(),
+            assignment,
+        }))
+    }
+
+    /// Add the `--self-test` check describing this replica's shard.
+    pub fn self_test(&self, report: &mut Report, name: &str) {
+        if self.shard_key.is_none() && self.shard_count <= 1 && self.shard_peers.is_empty() {
+            return;
+        }
+        report.check(
+            "shard",
+            self.resolve(name)
+                .map(|shard| shard.map(|s| s.describe()).unwrap_or_default()),
+        );
+    }
+}
+
+/// How keys are assigned to replicas.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Assignment {
+    Static { index: usize, count: usize },
+    Rendezvous { me: String, peers: Vec<String> },
+}
+
+/// The slice of the key space owned by this replica.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Shard {
+    path: String,
+    assignment: Assignment,
+}
+
+impl Shard {
+    /// Whether this replica should handle `payload`.
+    pub fn owns(&self, payload: &Value) -> bool {
+        let key = key::extract(payload, &self.path).unwrap_or_default();
+        match &self.assignment {
+            Assignment::Static { index, count } => key::bucket(&key, *count) == *index,
+            Assignment::Rendezvous { me, peers } => {
+                rendezvous(peers, &key).is_some_and(|owner| owner == me)
+            }
+        }
+    }
+
+    /// Human-readable summary for logs and `--self-test`.
+    pub fn describe(&self) -> String {
+        match &self.assignment {
+            Assignment::Static { index, count } => {
+                format!("{} shard {index} of {count}", self.path)
+            }
+            Assignment::Rendezvous { me, peers } => {
+                format!("{} as {me} among {} peers", self.path, peers.len())
+            }
+        }
+    }
+}
+
+/// The peer with the highest weight for `key` (ties go to the smaller name).
+fn rendezvous<'a>(peers: &'a [String], key: &str) -> Option<&'a str> {
+    peers
+        .iter()
+        .map(|peer| (key::stable_hash(&format!("{peer}\0{key}")), peer.as_str()))
+        .max_by(|a, b| a.0.cmp(&b.0).then_with(|| b.1.cmp(a.1)))
+        .map(|(_, peer)| peer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn shard(args: ShardArgs, name: &str) -> Shard {
+        match args.resolve(name) {
+            Ok(Some(shard)) => shard,
+            other => panic!("expected a shard, got {other:?}"),
+        }
+    }
+
+    fn users() -> Vec<Value> {
+        (0..200).map(|i| json!({"user_id": i})).collect()
+    }
+
+    #[test]
+    fn static_shards_partition_every_message_exactly_once() {
+        let shards: Vec<Shard> = (0..3)
+            .map(|i| {
+                let args = ShardArgs {
+                    shard_key: Some("payload.user_id".to_string()),
+                    shard_index: i,
+                    shard_count: 3,
+                    ..Default::default()
+                };
+                shard(args, "sink")
+            })
+            .collect();
+        for payload in users() {
+            assert_eq!(shards.iter().filter(|s| s.owns(&payload)).count(), 1);
+        }
+    }
+
+    #[test]
+    fn rendezvous_only_moves_keys_of_the_removed_peer() {
+        let peers =
+            |names: &[&str]| -> Vec<String> { names.iter().map(|n| n.to_string()).collect() };
+        let three = peers(&["a", "b", "c"]);
+        let two = peers(&["a", "b"]);
+        for payload in users() {
+            let key = key::extract(&payload, "user_id").unwrap_or_default();
+            let before = rendezvous(&three, &key);
+            let after = rendezvous(&two, &key);
+            if before != Some("c") {
+                assert_eq!(before, after);
+            }
+        }
+
+        let args = ShardArgs {
+            shard_key: Some("user_id".to_string()),
+            shard_peers: three.clone(),
+            ..Default::default()
+        };
+        let shards: Vec<Shard> = three.iter().map(|n| shard(args.clone(), n)).collect();
+        for payload in users() {
+            assert_eq!(shards.iter().filter(|s| s.owns(&payload)).count(), 1);
+        }
+    }
+
+    #[test]
+    fn invalid_configurations_are_rejected() {
+        let no_key = ShardArgs {
+            shard_count: 2,
+            ..Default::default()
+        };
+        assert!(no_key.resolve("sink").is_err());
+
+        let out_of_range = ShardArgs {
+            shard_key: Some("id".to_string()),
+            shard_index: 3,
+            shard_count: 3,
+            ..Default::default()
+        };
+        assert!(out_of_range.resolve("sink").is_err());
+
+        let not_a_peer = ShardArgs {
+            shard_key: Some("id".to_string()),
+            shard_peers: vec!["a".to_string(), "b".to_string()],
+            ..Default::default()
+        };
+        assert!(not_a_peer.resolve("c").is_err());
+
+        assert_eq!(ShardArgs::default().resolve("sink"), Ok(None));
+    }
+}
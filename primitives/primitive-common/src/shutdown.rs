@@ -0,0 +1,71 @@
+//! Graceful shutdown.
+//!
+//! On SIGTERM a primitive stops taking new work, gives in-flight work (handler
+//! retries, running commands, open HTTP requests) up to `--drain-timeout` to
+//! finish, and only then disconnects. Work still running at the deadline is
+//! aborted; with the sink inbox enabled it is replayed on the next start.
+
+use clap::Args;
+use std::{future::Future, time::Duration};
+
+/// Default drain deadline in milliseconds.
+const DEFAULT_DRAIN_TIMEOUT_MS: u64 = 10_000;
+
+/// CLI flag controlling the shutdown drain deadline.
+#[derive(Args, Debug, Clone)]
+pub struct DrainArgs {
+    /// Milliseconds in-flight work may take to finish after SIGTERM before it is aborted.
+    #[arg(long, env = "EMERGENT_DRAIN_TIMEOUT", default_value_t = DEFAULT_DRAIN_TIMEOUT_MS)]
+    pub drain_timeout: u64,
+}
+
+impl Default for DrainArgs {
+    fn default() -> Self {
+        Self {
+            drain_timeout: DEFAULT_DRAIN_TIMEOUT_MS,
+        }
+    }
+}
+
+impl DrainArgs {
+    /// The drain deadline as a `Duration`.
+    pub fn deadline(&self) -> Duration {
+        Duration::from_millis(self.drain_timeout)
+    }
+
+    /// Await `work` for at most the drain deadline.
+    ///
+    /// Returns `Some(output)` if it finished in time; otherwise logs that
+    /// `what` was abandoned and returns `None` (dropping — and thereby
+    /// aborting — the future).
+    pub async fn drain<F: Future>(&self, what: &str, work: F) -> Option<F::Output> {
+        match tokio::time::timeout(self.deadline(), work).await {
+            Ok(output) => Some(output),
+            Err(_) => {
+                eprintln!(
+                    "Drain deadline of {}ms exceeded; aborting {what}",
+                    self.drain_timeout
+                );
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn work_finishing_in_time_is_returned() {
+        let args = DrainArgs { drain_timeout: 500 };
+        assert_eq!(args.drain("test", async { 7 }).await, Some(7));
+    }
+
+    #[tokio::test]
+    async fn work_past_the_deadline_is_abandoned() {
+        let args = DrainArgs { drain_timeout: 10 };
+        let slow = tokio::time::sleep(Duration::from_secs(5));
+        assert_eq!(args.drain("test", slow).await, None);
+    }
+}
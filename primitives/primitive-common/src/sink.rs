@@ -0,0 +1,1139 @@
+//! Sink harness.
+//!
+//! Every sink follows the same shape: connect under `EMERGENT_NAME`, subscribe
+//! to its topics, hand each message to a handler, and drain and disconnect on
+//! SIGTERM.
+//! `run_sink` owns that loop so individual sinks only implement
+//! [`SinkHandler`] and inherit the shared flags in [`SinkArgs`].
+//!
+//! # Dry Run
+//!
+//! With `--dry-run`, handlers must not perform their outbound side effect.
+//! Instead they call [`SinkContext::would_have`], which logs the intended
+//! action to stderr and publishes it as a `*.would_have` event (e.g.
+//! `exec.would_have`) so pipelines can be validated against live traffic.
+//!
+//! # Delivery Guarantees
+//!
+//! A failed handler is retried up to `--max-attempts` times after a
+//! `--retry-delay` that grows linearly or, with `--retry-backoff
+//! exponential`, doubles per attempt, capped at `--max-retry-delay` and
+//! spread out by `--retry-jitter`. A handler can ask for a longer wait (see
+//! [`HandlerError::with_retry_after`]), as HTTP sinks do for `Retry-After`,
+//! or fail for good (see [`HandlerError::permanent`]). An attempt the
+//! handler never made, such as one refused by an open circuit breaker, can
+//! be requeued without counting it (see [`HandlerError::requeued`]); it is
+//! then limited only by `--inbox-max-age`. Messages that exhaust
+//! their attempts are dead-lettered: reported on stderr and, with
+//! `--dead-letter`, published as a `*.dead_letter` event carrying the
+//! original message, the attempt count, the last error and, when the remote
+//! side answered, its status. With `--inbox-dir`, messages are persisted
+//! before handling and only removed once acknowledged (see [`crate::inbox`]),
+//! giving at-least-once processing across restarts. The inbox doubles as a
+//! persistent retry queue: attempt counts and the next due time survive a
+//! restart, `--max-inbox-size` bounds it (new messages are dropped while it
+//! is full), and `--inbox-max-age` dead-letters entries that are still
+//! undelivered after that long.
+//!
+//! # Concurrency
+//!
+//! Messages are handled by `--workers` tasks fed from a queue bounded by
+//! `--queue-depth`. When the queue is full the harness stops reading from
+//! the subscription until a worker frees a slot, so a slow handler applies
+//! backpressure instead of growing memory. With `--order-key`, each worker
+//! gets its own queue and messages sharing a key always land on the same
+//! worker, preserving their relative order.
+//!
+//! # Sharding
+//!
+//! Replicas of a sink all receive every message. With `--shard-key` (plus
+//! `--shard-index`/`--shard-count` or `--shard-peers`), each replica skips
+//! messages whose key belongs to another replica before they reach the
+//! inbox or a worker (see [`crate::shard`]).
+//!
+//! # Payload Encoding
+//!
+//! Compressed or offloaded payloads (see [`crate::payload`]) are decoded
+//! before reaching the handler; use [`SinkContext::payload`] rather than
+//! `msg.payload()` to read them.
+//!
+//! # Encryption
+//!
+//! With `--identity-file`, payloads sealed for the sink's key (see
+//! [`crate::crypto`]) are decrypted before reaching the handler; sealed
+//! payloads the sink cannot open fail to decode and are dropped. Note that
+//! `--inbox-dir` persists the decrypted payload.
+//!
+//! # Reload
+//!
+//! With `--config`, SIGHUP asks the handler to re-read its hot-swappable
+//! settings (see [`crate::reload`]) while the engine connection stays up.
+//! Each successful reload is announced as a `primitive.reloaded` event.
+//!
+//! # Capabilities
+//!
+//! With `--announce`, the harness publishes a `primitive.capabilities`
+//! descriptor (see [`crate::capabilities`]) once connected, listing the
+//! subscribed types and the report types the enabled flags can produce.
+//! `--capabilities` prints it and exits.
+//!
+//! # Shutdown
+//!
+//! On SIGTERM the harness stops reading from the subscription, lets queued
+//! and in-flight messages finish (including pending retries), calls
+//! [`SinkHandler::flush`], sends what was queued on the [`Publisher`], and
+//! then disconnects. All of this is bounded by
+//! `--drain-timeout`; workers still running at the deadline are aborted; with
+//! `--inbox-dir` their messages are replayed on the next start.
+
+use crate::capabilities::{CAPABILITIES_EVENT_TYPE, CapabilityArgs, Role, capabilities_message};
+use crate::crypto::{KeyArgs, Keyring};
+use crate::doctor::{Report, check_writable_dir};
+use crate::errors::{
+    Disposition, ERROR_EVENT_TYPE, ErrorArgs, ErrorCategory, ErrorEvent, HandlerError,
+};
+use crate::inbox::{Inbox, InboxEntry};
+use crate::key;
+use crate::payload;
+use crate::reload::{RELOADED_EVENT_TYPE, ReloadArgs, check_config, reloaded_message};
+use crate::shard::{Shard, ShardArgs};
+use crate::shutdown::DrainArgs;
+use clap::{Args, ValueEnum};
+use emergent_client::{EmergentHandler, EmergentMessage, EmergentSink};
+use event_schemas::PrimitiveCapabilities;
+use serde::de::DeserializeOwned;
+use serde_json::{Value, json};
+use std::{fmt, future::Future, path::PathBuf, pin::Pin, sync::Arc, time::Duration};
+use tokio::{
+    signal::unix::{SignalKind, signal},
+    sync::{Mutex, mpsc, oneshot},
+    task::JoinSet,
+};
+
+/// CLI flags shared by every sink built on [`run_sink`].
+#[derive(Args, Debug, Clone, Default)]
+pub struct SinkArgs {
+    /// Log outbound side effects as `*.would_have` events instead of executing them.
+    #[arg(long, env = "EMERGENT_DRY_RUN")]
+    pub dry_run: bool,
+
+    /// Persist messages here until the handler succeeds (at-least-once delivery).
+    #[arg(long, env = "EMERGENT_INBOX_DIR", visible_alias = "queue-dir")]
+    pub inbox_dir: Option<PathBuf>,
+
+    /// Pending inbox entries at which new messages are dropped (0 = unbounded).
+    #[arg(
+        long,
+        env = "EMERGENT_MAX_INBOX_SIZE",
+        visible_alias = "max-queue-size",
+        default_value = "0"
+    )]
+    pub max_inbox_size: usize,
+
+    /// Milliseconds after which an undelivered inbox entry is dead-lettered (0 = never).
+    #[arg(long, env = "EMERGENT_INBOX_MAX_AGE", default_value = "0")]
+    pub inbox_max_age: u64,
+
+    /// Handler attempts per message before it is dead-lettered.
+    #[arg(long, env = "EMERGENT_MAX_ATTEMPTS", default_value = "1")]
+    pub max_attempts: u32,
+
+    /// Delay before the first retry in milliseconds; `--retry-backoff` sets how it grows.
+    #[arg(long, env = "EMERGENT_RETRY_DELAY", default_value = "1000")]
+    pub retry_delay: u64,
+
+    /// How the delay grows: `linear` (times the attempt number) or `exponential` (doubling).
+    #[arg(
+        long,
+        env = "EMERGENT_RETRY_BACKOFF",
+        value_enum,
+        default_value = "linear"
+    )]
+    pub retry_backoff: Backoff,
+
+    /// Longest delay between attempts in milliseconds (0 = no cap).
+    #[arg(long, env = "EMERGENT_MAX_RETRY_DELAY", default_value = "0")]
+    pub max_retry_delay: u64,
+
+    /// Fraction of each delay (0-1) taken off at random, so retries spread out.
+    #[arg(long, env = "EMERGENT_RETRY_JITTER", default_value = "0", value_parser = parse_jitter)]
+    pub retry_jitter: f64,
+
+    /// Publish messages that exhaust their attempts as `*.dead_letter` events.
+    #[arg(long, env = "EMERGENT_DEAD_LETTER")]
+    pub dead_letter: bool,
+
+    /// Number of messages handled concurrently.
+    #[arg(
+        long,
+        env = "EMERGENT_WORKERS",
+        default_value = "1",
+        visible_alias = "concurrency"
+    )]
+    pub workers: usize,
+
+    /// Messages buffered ahead of the workers before the subscription is paused.
+    #[arg(long, env = "EMERGENT_QUEUE_DEPTH", default_value = "64")]
+    pub queue_depth: usize,
+
+    /// Payload field (e.g. `payload.user_id`) whose messages are processed in order.
+    #[arg(long, env = "EMERGENT_ORDER_KEY", visible_alias = "correlation-key")]
+    pub order_key: Option<String>,
+
+    /// Verify external dependencies and exit without connecting to the engine.
+    #[arg(long)]
+    pub self_test: bool,
+
+    #[command(flatten)]
+    pub errors: ErrorArgs,
+
+    #[command(flatten)]
+    pub drain: DrainArgs,
+
+    #[command(flatten)]
+    pub reload: ReloadArgs,
+
+    #[command(flatten)]
+    pub capabilities: CapabilityArgs,
+
+    #[command(flatten)]
+    pub keys: KeyArgs,
+
+    #[command(flatten)]
+    pub shard: ShardArgs,
+}
+
+/// How the delay between attempts grows.
+#[derive(ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Backoff {
+    /// `--retry-delay` times the attempt number.
+    #[default]
+    Linear,
+    /// `--retry-delay` doubled for every attempt after the first.
+    Exponential,
+}
+
+/// Parse a `--retry-jitter` fraction.
+fn parse_jitter(s: &str) -> Result<f64, String> {
+    let jitter: f64 = s.parse().map_err(|_| format!("'{s}' is not a number"))?;
+    if !(0.0..=1.0).contains(&jitter) {
+        return Err(format!("{jitter} is not between 0 and 1"));
+    }
+    Ok(jitter)
+}
+
+/// A number in `[0, 1)` for jitter. Every `RandomState` is freshly keyed,
+/// which is random enough to keep retries from lining up.
+fn random_unit() -> f64 {
+    use std::hash::{BuildHasher, Hasher};
+    let bits = std::collections::hash_map::RandomState::new()
+        .build_hasher()
+        .finish();
+    (bits >> 11) as f64 / (1u64 << 53) as f64
+}
+
+/// The delay before attempt `attempt + 1`, with `random` in `[0, 1)`
+/// choosing how much jitter takes off. A delay the handler asked for
+/// (`requested`) wins when longer, cap or not: retrying sooner would only
+/// be refused again.
+fn backoff(args: &SinkArgs, attempt: u32, requested: Option<Duration>, random: f64) -> Duration {
+    let factor = match args.retry_backoff {
+        Backoff::Linear => u64::from(attempt),
+        Backoff::Exponential => 2u64.saturating_pow(attempt.saturating_sub(1)),
+    };
+    let mut delay = args.retry_delay.saturating_mul(factor);
+    if args.max_retry_delay > 0 {
+        delay = delay.min(args.max_retry_delay);
+    }
+    let delay = Duration::from_millis(delay).mul_f64(1.0 - args.retry_jitter * random);
+    requested.map_or(delay, |requested| requested.max(delay))
+}
+
+/// Static description of a sink, supplied by the primitive.
+pub struct SinkConfig<'a> {
+    /// Default client name, used when `EMERGENT_NAME` is unset.
+    pub name: &'a str,
+    /// Message types to subscribe to.
+    pub subscribe: &'a [String],
+    /// Message type for dry-run reports (e.g. `exec.would_have`).
+    pub would_have_as: &'a str,
+    /// Message type for dead letters (e.g. `exec.dead_letter`).
+    pub dead_letter_as: &'a str,
+    /// The sink's parsed arguments, hashed into its capability descriptor.
+    pub settings: &'a (dyn fmt::Debug + Sync),
+}
+
+impl SinkConfig<'_> {
+    /// The `primitive.capabilities` descriptor for a sink named `name`.
+    /// `published` lists the types the handler publishes on its own (see
+    /// [`SinkHandler::publishes`]).
+    fn describe(&self, args: &SinkArgs, name: &str, published: &[&str]) -> PrimitiveCapabilities {
+        let consumes: Vec<&str> = self.subscribe.iter().map(String::as_str).collect();
+        let mut produces = published.to_vec();
+        if args.dry_run {
+            produces.push(self.would_have_as);
+        }
+        if args.dead_letter {
+            produces.push(self.dead_letter_as);
+        }
+        if args.errors.emit_errors {
+            produces.push(ERROR_EVENT_TYPE);
+        }
+        if args.reload.config.is_some() {
+            produces.push(RELOADED_EVENT_TYPE);
+        }
+        args.capabilities
+            .describe(name, Role::Sink, &consumes, &produces, self.settings)
+    }
+}
+
+/// A sink's per-message behaviour.
+///
+/// Handlers are shared by all workers, so they must be `Send + Sync`.
+pub trait SinkHandler: Send + Sync + 'static {
+    /// Handle one message. An `Err` is reported (stderr, and `primitive.error`
+    /// with `--emit-errors`) and retried.
+    fn handle(
+        &self,
+        msg: &EmergentMessage,
+        ctx: &SinkContext<'_>,
+    ) -> impl Future<Output = Result<(), HandlerError>> + Send;
+
+    /// Add sink-specific `--self-test` checks (executables, credentials, ...).
+    fn self_test(&self, _report: &mut Report) {}
+
+    /// Re-read hot-swappable settings on SIGHUP, typically through a
+    /// [`HotConfig`](crate::reload::HotConfig). Returns the names of the
+    /// settings that changed.
+    fn reload(&self) -> Result<Vec<String>, String> {
+        Ok(Vec::new())
+    }
+
+    /// Flush buffered output (batches, open files) during shutdown, after
+    /// every in-flight message has finished.
+    fn flush(&self) -> impl Future<Output = ()> + Send {
+        async {}
+    }
+
+    /// Message types the sink publishes through a [`Publisher`], beyond the
+    /// harness's own reports. A sink declaring any connects able to publish
+    /// and lists them in its capabilities.
+    fn publishes(&self) -> &'static [&'static str] {
+        &[]
+    }
+
+    /// Receive the [`Publisher`] for events produced outside
+    /// [`handle`](Self::handle); called once, before the first message.
+    fn attach(&self, _publisher: Publisher) {}
+}
+
+/// Publishes events a sink produces outside [`SinkHandler::handle`], such as
+/// replies arriving on a listener of its own.
+#[derive(Clone)]
+pub struct Publisher {
+    tx: mpsc::UnboundedSender<EmergentMessage>,
+}
+
+impl Publisher {
+    /// Queue `msg` for publishing; fails once the sink has shut down.
+    pub fn publish(&self, msg: EmergentMessage) -> Result<(), String> {
+        self.tx
+            .send(msg)
+            .map_err(|_| "sink has shut down".to_string())
+    }
+}
+
+/// Per-message context handed to [`SinkHandler::handle`].
+pub struct SinkContext<'a> {
+    connection: &'a Connection,
+    message: &'a EmergentMessage,
+    message_id: &'a str,
+    payload: &'a Value,
+    dry_run: bool,
+    would_have_as: &'a str,
+}
+
+impl SinkContext<'_> {
+    /// The id the engine delivered the message under. Use it rather than
+    /// `msg.id()` in payloads, keys and logs: a message replayed from the
+    /// inbox after a restart is rebuilt by the client under a new id, and
+    /// only this one matches what the engine saw.
+    pub fn message_id(&self) -> &str {
+        self.message_id
+    }
+
+    /// The message payload, decoded if it was compressed or offloaded.
+    pub fn payload(&self) -> &Value {
+        self.payload
+    }
+
+    /// Deserialize the payload into a typed struct (see `event_schemas`).
+    ///
+    /// A payload that does not match is a [`ErrorCategory::Parse`] error.
+    pub fn decode<T: DeserializeOwned>(&self) -> Result<T, HandlerError> {
+        T::deserialize(self.payload).map_err(|e| {
+            HandlerError::new(
+                ErrorCategory::Parse,
+                format!("payload of {}: {e}", self.message.message_type.as_str()),
+            )
+        })
+    }
+
+    /// Whether outbound side effects must be suppressed.
+    pub fn is_dry_run(&self) -> bool {
+        self.dry_run
+    }
+
+    /// Report a side effect that was skipped because of `--dry-run`.
+    ///
+    /// `action` names the effect (e.g. `"exec"`, `"http.post"`); `detail`
+    /// carries whatever a reviewer needs to judge it (command, URL, body).
+    pub async fn would_have(&self, action: &str, detail: Value) {
+        let payload = would_have_payload(self.message, self.message_id, action, detail);
+        eprintln!("[dry-run] {payload}");
+
+        let report = EmergentMessage::new(self.would_have_as)
+            .with_causation_id(self.message.id())
+            .with_payload(payload);
+        if let Err(e) = self.connection.publish(report).await {
+            eprintln!("Failed to publish {}: {e}", self.would_have_as);
+        }
+    }
+}
+
+/// Build the payload of a `*.would_have` event.
+fn would_have_payload(
+    message: &EmergentMessage,
+    message_id: &str,
+    action: &str,
+    detail: Value,
+) -> Value {
+    json!({
+        "action": action,
+        "detail": detail,
+        "message_id": message_id,
+        "message_type": message.message_type.as_str(),
+    })
+}
+
+/// Build the payload of a `*.dead_letter` event.
+fn dead_letter_payload(entry: &InboxEntry) -> Value {
+    let mut payload = json!({
+        "message_id": entry.message_id,
+        "message_type": entry.message_type,
+        "payload": entry.payload,
+        "attempts": entry.attempts,
+        "error": entry.last_error,
+    });
+    if let Some(status) = entry.last_status {
+        payload["status"] = Value::from(status);
+    }
+    payload
+}
+
+/// Engine connection used by the harness.
+///
+/// Plain sinks cannot publish, so the harness connects as a handler only
+/// when it has something to report (dry-run, dead-letter, or error events,
+/// or events of the handler's own).
+enum Connection {
+    Sink(EmergentSink),
+    Handler(EmergentHandler),
+    /// In-process stand-in; see [`Loopback`].
+    Loopback(mpsc::UnboundedSender<EmergentMessage>),
+}
+
+impl Connection {
+    async fn connect(name: &str, publishes: bool) -> Result<Self, String> {
+        if publishes {
+            EmergentHandler::connect(name)
+                .await
+                .map(Self::Handler)
+                .map_err(|e| e.to_string())
+        } else {
+            EmergentSink::connect(name)
+                .await
+                .map(Self::Sink)
+                .map_err(|e| e.to_string())
+        }
+    }
+
+    async fn publish(&self, msg: EmergentMessage) -> Result<(), String> {
+        match self {
+            Self::Sink(_) => Err("sink connection cannot publish".to_string()),
+            Self::Handler(h) => h.publish(msg).await.map_err(|e| e.to_string()),
+            Self::Loopback(tx) => tx.send(msg).map_err(|_| "loopback closed".to_string()),
+        }
+    }
+
+    async fn disconnect(&self) {
+        let _ = match self {
+            Self::Sink(s) => s.disconnect().await,
+            Self::Handler(h) => h.disconnect().await,
+            Self::Loopback(_) => return,
+        };
+    }
+}
+
+/// A message queued for a worker, with its (possibly persisted) inbox entry.
+struct Job {
+    msg: EmergentMessage,
+    entry: InboxEntry,
+}
+
+/// Per-run state resolved before connecting.
+struct Setup {
+    name: String,
+    inbox: Option<Inbox>,
+    keys: Option<Keyring>,
+    shard: Option<Shard>,
+}
+
+/// State shared by the dispatcher and every worker.
+struct Harness<H> {
+    handler: H,
+    connection: Connection,
+    inbox: Option<Inbox>,
+    keys: Option<Keyring>,
+    shard: Option<Shard>,
+    args: SinkArgs,
+    name: String,
+    would_have_as: String,
+    dead_letter_as: String,
+    capabilities: PrimitiveCapabilities,
+}
+
+impl<H: SinkHandler> Harness<H> {
+    fn new(
+        config: &SinkConfig<'_>,
+        args: &SinkArgs,
+        handler: H,
+        connection: Connection,
+        setup: Setup,
+    ) -> Self {
+        Self {
+            capabilities: config.describe(args, &setup.name, handler.publishes()),
+            handler,
+            connection,
+            inbox: setup.inbox,
+            keys: setup.keys,
+            shard: setup.shard,
+            args: args.clone(),
+            name: setup.name,
+            would_have_as: config.would_have_as.to_string(),
+            dead_letter_as: config.dead_letter_as.to_string(),
+        }
+    }
+
+    /// Wrap a freshly received message in a job, decoding its payload and
+    /// persisting it first when the inbox is enabled. Returns `None` if the
+    /// message belongs to another shard or cannot be decoded or persisted.
+    async fn admit(&self, msg: EmergentMessage) -> Option<Job> {
+        let message_id = msg.id().to_string();
+        let message_type = msg.message_type.as_str().to_string();
+        let payload = match payload::decode(msg.payload(), self.keys.as_ref()).await {
+            Ok(p) => p.into_owned(),
+            Err(e) => {
+                eprintln!(
+                    "{}: failed to decode payload of {message_id}: {e}",
+                    self.name
+                );
+                return None;
+            }
+        };
+        if let Some(shard) = &self.shard
+            && !shard.owns(&payload)
+        {
+            return None;
+        }
+        let entry = match &self.inbox {
+            Some(inbox)
+                if self.args.max_inbox_size > 0 && inbox.len() >= self.args.max_inbox_size =>
+            {
+                eprintln!(
+                    "{}: inbox full ({} entries); dropping {message_id}",
+                    self.name,
+                    inbox.len()
+                );
+                return None;
+            }
+            Some(inbox) => match inbox.append(&message_type, &message_id, &payload) {
+                Ok(entry) => entry,
+                Err(e) => {
+                    eprintln!("{}: failed to persist message to inbox: {e}", self.name);
+                    return None;
+                }
+            },
+            None => InboxEntry::new(0, &message_type, &message_id, &payload),
+        };
+        Some(Job { msg, entry })
+    }
+
+    /// Run the handler until it succeeds or attempts are exhausted, then
+    /// acknowledge or dead-letter the entry.
+    async fn deliver(&self, job: Job) {
+        let Job { msg, mut entry } = job;
+        let max_attempts = self.args.max_attempts.max(1);
+
+        while entry.attempts < max_attempts {
+            // Honour the backoff, including one persisted before a restart
+            tokio::time::sleep(entry.wait()).await;
+            if let Some(age) = self.expired(&entry) {
+                entry.last_error = Some(format!("expired after {}ms undelivered", age.as_millis()));
+                break;
+            }
+
+            let ctx = SinkContext {
+                connection: &self.connection,
+                message: &msg,
+                message_id: &entry.message_id,
+                payload: &entry.payload,
+                dry_run: self.args.dry_run,
+                would_have_as: &self.would_have_as,
+            };
+            match self.handler.handle(&msg, &ctx).await {
+                Ok(()) => {
+                    if let Some(inbox) = &self.inbox
+                        && let Err(e) = inbox.ack(&entry)
+                    {
+                        eprintln!("{}: failed to acknowledge inbox entry: {e}", self.name);
+                    }
+                    return;
+                }
+                Err(e) if e.requeue => {
+                    eprintln!("{}: {e}; requeued", self.name);
+                    let delay = e
+                        .retry_after
+                        .unwrap_or(Duration::from_millis(self.args.retry_delay))
+                        .max(Duration::from_millis(1));
+                    match &self.inbox {
+                        Some(inbox) => {
+                            if let Err(io) = inbox.record_deferral(&mut entry, delay) {
+                                eprintln!("{}: failed to update inbox entry: {io}", self.name);
+                            }
+                        }
+                        None => entry.defer(delay),
+                    }
+                    self.report_error(&msg, &entry, &e, Disposition::Retrying)
+                        .await;
+                }
+                Err(e) => {
+                    eprintln!("{}: {e}", self.name);
+                    entry.last_status = e.status;
+                    let retry_after =
+                        backoff(&self.args, entry.attempts + 1, e.retry_after, random_unit());
+                    match &self.inbox {
+                        Some(inbox) => {
+                            if let Err(io) =
+                                inbox.record_failure(&mut entry, &e.message, retry_after)
+                            {
+                                eprintln!("{}: failed to update inbox entry: {io}", self.name);
+                            }
+                        }
+                        None => entry.fail(&e.message, retry_after),
+                    }
+                    let give_up = e.permanent || entry.attempts >= max_attempts;
+                    let disposition = if !give_up {
+                        Disposition::Retrying
+                    } else if self.args.dead_letter || self.inbox.is_some() {
+                        Disposition::DeadLettered
+                    } else {
+                        Disposition::Dropped
+                    };
+                    self.report_error(&msg, &entry, &e, disposition).await;
+                    if give_up {
+                        break;
+                    }
+                }
+            }
+        }
+
+        self.dead_letter(&msg, &entry).await;
+    }
+
+    /// The entry's age, if it is older than `--inbox-max-age`.
+    fn expired(&self, entry: &InboxEntry) -> Option<Duration> {
+        let max_age = Duration::from_millis(self.args.inbox_max_age);
+        let age = entry.age();
+        (self.args.inbox_max_age > 0 && age > max_age).then_some(age)
+    }
+
+    /// Publish a `primitive.error` event when `--emit-errors` is set.
+    async fn report_error(
+        &self,
+        msg: &EmergentMessage,
+        entry: &InboxEntry,
+        err: &HandlerError,
+        disposition: Disposition,
+    ) {
+        if !self.args.errors.emit_errors {
+            return;
+        }
+        let event = ErrorEvent {
+            primitive: &self.name,
+            message_id: Some(&entry.message_id),
+            message_type: Some(&entry.message_type),
+            category: err.category,
+            disposition,
+            attempt: Some(entry.attempts),
+            error: &err.message,
+        };
+        let report = event.to_message().with_causation_id(msg.id());
+        if let Err(e) = self.connection.publish(report).await {
+            eprintln!("Failed to publish {ERROR_EVENT_TYPE}: {e}");
+        }
+    }
+
+    /// Publish the capability descriptor when `--announce` is set.
+    async fn announce(&self) {
+        if !self.args.capabilities.announce {
+            return;
+        }
+        let report = capabilities_message(&self.capabilities);
+        if let Err(e) = self.connection.publish(report).await {
+            eprintln!("Failed to publish {CAPABILITIES_EVENT_TYPE}: {e}");
+        }
+    }
+
+    /// Apply a SIGHUP: reload the handler's settings and announce the result.
+    async fn reload(&self) {
+        let Some(path) = &self.args.reload.config else {
+            return;
+        };
+        match self.handler.reload() {
+            Ok(changed) => {
+                eprintln!(
+                    "{}: reloaded {} (changed: {changed:?})",
+                    self.name,
+                    path.display()
+                );
+                let report = reloaded_message(&self.name, path, &changed);
+                if let Err(e) = self.connection.publish(report).await {
+                    eprintln!("Failed to publish {RELOADED_EVENT_TYPE}: {e}");
+                }
+            }
+            Err(e) => eprintln!(
+                "{}: reload failed, keeping previous settings: {e}",
+                self.name
+            ),
+        }
+    }
+
+    async fn dead_letter(&self, msg: &EmergentMessage, entry: &InboxEntry) {
+        let payload = dead_letter_payload(entry);
+        eprintln!("{}: dead-lettered {payload}", self.name);
+
+        if let Some(inbox) = &self.inbox
+            && let Err(e) = inbox.dead_letter(entry)
+        {
+            eprintln!("{}: failed to move inbox entry to dead/: {e}", self.name);
+        }
+
+        if self.args.dead_letter {
+            let report = EmergentMessage::new(&self.dead_letter_as)
+                .with_causation_id(msg.id())
+                .with_payload(payload);
+            if let Err(e) = self.connection.publish(report).await {
+                eprintln!("Failed to publish {}: {e}", self.dead_letter_as);
+            }
+        }
+    }
+}
+
+/// Bounded pool of workers draining jobs into [`Harness::deliver`].
+struct WorkerPool {
+    senders: Vec<mpsc::Sender<Job>>,
+    order_key: Option<String>,
+    tasks: JoinSet<()>,
+}
+
+impl WorkerPool {
+    fn spawn<H: SinkHandler>(harness: &Arc<Harness<H>>) -> Self {
+        let workers = harness.args.workers.max(1);
+        let depth = harness.args.queue_depth.max(1);
+        let order_key = harness.args.order_key.clone();
+        let mut tasks = JoinSet::new();
+        let mut senders = Vec::new();
+
+        if order_key.is_some() {
+            // One queue per worker: a key always maps to the same worker.
+            for _ in 0..workers {
+                let (tx, mut rx) = mpsc::channel::<Job>(depth);
+                let harness = Arc::clone(harness);
+                tasks.spawn(async move {
+                    while let Some(job) = rx.recv().await {
+                        harness.deliver(job).await;
+                    }
+                });
+                senders.push(tx);
+            }
+        } else {
+            // One shared queue: whichever worker is free takes the next job.
+            let (tx, rx) = mpsc::channel::<Job>(depth);
+            let rx = Arc::new(Mutex::new(rx));
+            for _ in 0..workers {
+                let harness = Arc::clone(harness);
+                let rx = Arc::clone(&rx);
+                tasks.spawn(async move {
+                    loop {
+                        let job = rx.lock().await.recv().await;
+                        match job {
+                            Some(job) => harness.deliver(job).await,
+                            None => break,
+                        }
+                    }
+                });
+            }
+            senders.push(tx);
+        }
+
+        Self {
+            senders,
+            order_key,
+            tasks,
+        }
+    }
+
+    /// Queue a job, waiting for space when the queue is full (backpressure).
+    async fn submit(&self, job: Job) {
+        let index = match &self.order_key {
+            Some(path) => {
+                let key = key::extract(&job.entry.payload, path).unwrap_or_default();
+                key::bucket(&key, self.senders.len())
+            }
+            None => 0,
+        };
+        // Workers only exit once every sender is dropped, so send cannot fail here.
+        let _ = self.senders[index].send(job).await;
+    }
+
+    /// Stop accepting jobs and wait for the queued ones to finish.
+    ///
+    /// Takes the task set by reference so a caller that gives up waiting
+    /// can still abort the remaining workers.
+    async fn close(senders: Vec<mpsc::Sender<Job>>, tasks: &mut JoinSet<()>) {
+        drop(senders);
+        while tasks.join_next().await.is_some() {}
+    }
+
+    /// Close the pool, giving workers until the drain deadline to finish.
+    /// Returns the number of workers aborted at the deadline.
+    async fn drain(self, drain: &DrainArgs) -> usize {
+        let Self {
+            senders, mut tasks, ..
+        } = self;
+        if drain
+            .drain("in-flight messages", Self::close(senders, &mut tasks))
+            .await
+            .is_some()
+        {
+            return 0;
+        }
+        let abandoned = tasks.len();
+        tasks.shutdown().await;
+        abandoned
+    }
+}
+
+/// Connect to the engine and feed every subscribed message to `handler`
+/// until SIGTERM or the stream ends, then drain within `--drain-timeout`.
+///
+/// Connection and subscription failures are fatal (exit code 1), matching
+/// the behaviour of the standalone primitives. With `--inbox-dir`, entries
+/// left over from a previous run are replayed before new messages.
+pub async fn run_sink<H: SinkHandler>(
+    config: SinkConfig<'_>,
+    args: &SinkArgs,
+    handler: H,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let setup = prepare(&config, args, &handler)?;
+    let name = &setup.name;
+
+    let publishes = args.dry_run
+        || args.dead_letter
+        || args.errors.emit_errors
+        || args.reload.config.is_some()
+        || args.capabilities.announce
+        || !handler.publishes().is_empty();
+    let mut connection = match Connection::connect(name, publishes).await {
+        Ok(c) => c,
+        Err(e) => {
+            eprintln!("Failed to connect to Emergent engine: {e}");
+            std::process::exit(1);
+        }
+    };
+
+    let topics_refs: Vec<&str> = config.subscribe.iter().map(String::as_str).collect();
+    let subscribed = match &mut connection {
+        Connection::Sink(s) => s.subscribe(&topics_refs).await.map_err(|e| e.to_string()),
+        Connection::Handler(h) => h.subscribe(&topics_refs).await.map_err(|e| e.to_string()),
+        Connection::Loopback(_) => unreachable!("run_sink only connects to the engine"),
+    };
+    let stream = match subscribed {
+        Ok(s) => s,
+        Err(e) => {
+            eprintln!("Failed to subscribe: {e}");
+            std::process::exit(1);
+        }
+    };
+
+    let harness = Harness::new(&config, args, handler, connection, setup);
+    serve(harness, stream, |s| Box::pin(s.next())).await
+}
+
+/// In-process stand-in for the engine, used to test sinks end to end
+/// (see the `emergent-testkit` crate).
+///
+/// Messages sent on `inbound` are delivered as if subscribed; everything the
+/// harness publishes (dry-run reports, dead letters, error events) arrives
+/// on `outbound`. Closing `inbound` ends the run like a closed subscription.
+pub struct Loopback {
+    pub inbound: mpsc::Receiver<EmergentMessage>,
+    pub outbound: mpsc::UnboundedSender<EmergentMessage>,
+}
+
+/// Run a sink against a [`Loopback`] instead of the engine.
+///
+/// Behaves like [`run_sink`] (inbox replay, workers, retries, drain) except
+/// that no connection is made and subscriptions are not filtered.
+pub async fn run_sink_loopback<H: SinkHandler>(
+    config: SinkConfig<'_>,
+    args: &SinkArgs,
+    handler: H,
+    loopback: Loopback,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let setup = prepare(&config, args, &handler)?;
+    let connection = Connection::Loopback(loopback.outbound);
+    let harness = Harness::new(&config, args, handler, connection, setup);
+    serve(harness, loopback.inbound, |rx| Box::pin(rx.recv())).await
+}
+
+/// Resolve the sink name, run `--self-test` if requested, open the inbox,
+/// and load decryption keys.
+fn prepare<H: SinkHandler>(
+    config: &SinkConfig<'_>,
+    args: &SinkArgs,
+    handler: &H,
+) -> Result<Setup, Box<dyn std::error::Error>> {
+    // Get the sink name from environment (set by engine) or use default
+    let name = std::env::var("EMERGENT_NAME").unwrap_or_else(|_| config.name.to_string());
+
+    if args.self_test {
+        let mut report = Report::new(&name);
+        if let Some(dir) = &args.inbox_dir {
+            report.check("inbox-dir", check_writable_dir(dir));
+        }
+        if let Some(path) = &args.reload.config {
+            report.check("config", check_config(path));
+        }
+        args.keys.self_test(&mut report);
+        args.shard.self_test(&mut report, &name);
+        handler.self_test(&mut report);
+        report.finish();
+    }
+
+    if args.capabilities.capabilities {
+        crate::capabilities::print(&config.describe(args, &name, handler.publishes()));
+    }
+
+    let inbox = match &args.inbox_dir {
+        Some(dir) => Some(Inbox::open(dir)?),
+        None => None,
+    };
+    let keys = args.keys.load()?;
+    let shard = args.shard.resolve(&name)?;
+    if let Some(shard) = &shard {
+        eprintln!("{name}: handling {}", shard.describe());
+    }
+    Ok(Setup {
+        name,
+        inbox,
+        keys,
+        shard,
+    })
+}
+
+/// Boxed future yielding the next inbound message.
+type NextMessage<'a> = Pin<Box<dyn Future<Output = Option<EmergentMessage>> + Send + 'a>>;
+
+/// Drive the message loop: replay the inbox, dispatch `stream` to the
+/// workers until SIGTERM or end of stream, then drain and disconnect.
+async fn serve<H, S>(
+    harness: Harness<H>,
+    mut stream: S,
+    next: impl for<'a> Fn(&'a mut S) -> NextMessage<'a>,
+) -> Result<(), Box<dyn std::error::Error>>
+where
+    H: SinkHandler,
+{
+    let harness = Arc::new(harness);
+    let pool = WorkerPool::spawn(&harness);
+    harness.announce().await;
+
+    // Forward what the handler publishes on its own
+    let (tx, mut published) = mpsc::unbounded_channel::<EmergentMessage>();
+    harness.handler.attach(Publisher { tx });
+    let (stop_forwarding, mut stop) = oneshot::channel::<()>();
+    let mut forwarder = {
+        let harness = Arc::clone(&harness);
+        tokio::spawn(async move {
+            let mut stopping = false;
+            loop {
+                tokio::select! {
+                    msg = published.recv() => {
+                        // Closed and emptied once stopping
+                        let Some(msg) = msg else { break };
+                        let message_type = msg.message_type.as_str().to_string();
+                        if let Err(e) = harness.connection.publish(msg).await {
+                            eprintln!("Failed to publish {message_type}: {e}");
+                        }
+                    }
+                    _ = &mut stop, if !stopping => {
+                        published.close();
+                        stopping = true;
+                    }
+                }
+            }
+        })
+    };
+
+    // Replay messages that were received but never acknowledged. The
+    // client cannot rebuild a message under a given id, so handlers see the
+    // original one through `SinkContext::message_id`.
+    if let Some(inbox) = &harness.inbox {
+        for entry in inbox.pending()? {
+            let msg = EmergentMessage::new(&entry.message_type).with_payload(entry.payload.clone());
+            pool.submit(Job { msg, entry }).await;
+        }
+    }
+
+    let mut sigterm = signal(SignalKind::terminate())?;
+    let mut sighup = signal(SignalKind::hangup())?;
+
+    loop {
+        tokio::select! {
+            _ = sigterm.recv() => break,
+
+            _ = sighup.recv() => harness.reload().await,
+
+            msg = next(&mut stream) => {
+                let Some(msg) = msg else { break };
+                if let Some(job) = harness.admit(msg).await {
+                    // Stay responsive to SIGTERM while blocked on a full queue;
+                    // a job dropped here is still in the inbox if enabled.
+                    tokio::select! {
+                        _ = pool.submit(job) => {}
+                        _ = sigterm.recv() => break,
+                    }
+                }
+            }
+        }
+    }
+
+    // Stop taking messages, let in-flight ones finish, flush, then disconnect
+    let drain = &harness.args.drain;
+    let started = tokio::time::Instant::now();
+    let abandoned = pool.drain(drain).await;
+    if abandoned > 0 {
+        eprintln!(
+            "{}: aborted {abandoned} worker(s) still running at the drain deadline",
+            harness.name
+        );
+    }
+    let remaining = drain.deadline().saturating_sub(started.elapsed());
+    if tokio::time::timeout(remaining, harness.handler.flush())
+        .await
+        .is_err()
+    {
+        eprintln!(
+            "{}: flush did not finish before the drain deadline",
+            harness.name
+        );
+    }
+    // Refuse further publishes, and send the ones already queued
+    let _ = stop_forwarding.send(());
+    let remaining = drain.deadline().saturating_sub(started.elapsed());
+    if tokio::time::timeout(remaining, &mut forwarder)
+        .await
+        .is_err()
+    {
+        eprintln!(
+            "{}: dropped publishes still queued at the drain deadline",
+            harness.name
+        );
+        forwarder.abort();
+    }
+    harness.connection.disconnect().await;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn would_have_payload_references_original_message() {
+        let msg = EmergentMessage::new("alert.fired").with_payload(json!({"level": "high"}));
+        let payload = would_have_payload(&msg, "msg_1", "exec", json!({"command": "curl"}));
+
+        assert_eq!(payload["action"], "exec");
+        assert_eq!(payload["detail"]["command"], "curl");
+        assert_eq!(payload["message_type"], "alert.fired");
+        assert_eq!(payload["message_id"], "msg_1");
+    }
+
+    #[test]
+    fn dead_letter_payload_carries_attempts_and_error() {
+        let mut entry = InboxEntry::new(7, "alert.fired", "msg_1", &json!({"level": "high"}));
+        entry.attempts = 3;
+        entry.last_error = Some("exit code 1".to_string());
+        let payload = dead_letter_payload(&entry);
+
+        assert_eq!(payload["message_id"], "msg_1");
+        assert_eq!(payload["payload"]["level"], "high");
+        assert_eq!(payload["attempts"], 3);
+        assert_eq!(payload["error"], "exit code 1");
+        assert!(payload.get("status").is_none());
+
+        entry.last_status = Some(503);
+        assert_eq!(dead_letter_payload(&entry)["status"], 503);
+    }
+
+    #[test]
+    fn backoff_grows_caps_jitters_and_yields_to_requests() {
+        let ms = Duration::from_millis;
+        let mut args = SinkArgs {
+            retry_delay: 1000,
+            ..Default::default()
+        };
+        let delays = |args: &SinkArgs| -> Vec<Duration> {
+            (1..=5)
+                .map(|attempt| backoff(args, attempt, None, 0.5))
+                .collect()
+        };
+        assert_eq!(
+            delays(&args),
+            [ms(1000), ms(2000), ms(3000), ms(4000), ms(5000)]
+        );
+
+        args.retry_backoff = Backoff::Exponential;
+        args.max_retry_delay = 10_000;
+        assert_eq!(
+            delays(&args),
+            [ms(1000), ms(2000), ms(4000), ms(8000), ms(10_000)]
+        );
+        assert_eq!(backoff(&args, 80, None, 0.0), ms(10_000));
+
+        args.retry_jitter = 0.5;
+        assert_eq!(backoff(&args, 3, None, 0.0), ms(4000));
+        assert_eq!(backoff(&args, 3, None, 0.5), ms(3000));
+
+        // Retry-After outranks a shorter backoff, and the cap
+        assert_eq!(backoff(&args, 3, Some(ms(30_000)), 0.5), ms(30_000));
+        assert_eq!(backoff(&args, 3, Some(ms(500)), 0.5), ms(3000));
+
+        assert!(parse_jitter("1.5").is_err());
+        assert!((0.0..1.0).contains(&random_unit()));
+    }
+}
@@ -0,0 +1,348 @@
+//! The publishing path sources share.
+//!
+//! Sources have no harness like [`crate::sink`]: each one receives or polls
+//! for events its own way. What they share is how an event reaches the bus,
+//! which is [`Outlet`]'s job.
+//!
+//! [`SourceArgs`] flattens those flags together with `--drain-timeout`,
+//! which sources honour on SIGTERM by letting in-flight work finish before
+//! disconnecting.
+
+use crate::doctor::Report;
+use crate::errors::{Disposition, ERROR_EVENT_TYPE, ErrorArgs, ErrorCategory, ErrorEvent};
+use crate::payload::{self, PayloadArgs};
+use crate::shutdown::DrainArgs;
+use crate::spool::{Delivery, SPOOL_EVENT_TYPE, Spool, SpoolArgs};
+use crate::topics::TopicArgs;
+use clap::Args;
+use emergent_client::{ClientError, EmergentHandler, EmergentMessage, EmergentSource};
+use serde_json::Value;
+use std::{io, sync::Arc, time::Duration};
+
+/// CLI flags every source inherits for publishing and shutdown.
+#[derive(Args, Debug, Clone, Default)]
+pub struct SourceArgs {
+    #[command(flatten)]
+    pub topics: TopicArgs,
+
+    #[command(flatten)]
+    pub payload: PayloadArgs,
+
+    #[command(flatten)]
+    pub spool: SpoolArgs,
+
+    #[command(flatten)]
+    pub errors: ErrorArgs,
+
+    #[command(flatten)]
+    pub drain: DrainArgs,
+}
+
+impl SourceArgs {
+    /// Check the flags before connecting. `variables` are the source's
+    /// `--emit-type-template` variables besides `{type}`, `source` among them.
+    pub fn validate(&self, variables: &[&str]) -> Result<(), String> {
+        self.topics
+            .validate(variables)
+            .and_then(|()| self.payload.encryption.validate())
+    }
+
+    /// Add `--self-test` checks for the template, payload encoding and spool.
+    pub fn self_test(&self, report: &mut Report, variables: &[&str]) {
+        if let Some(template) = &self.topics.emit_type_template {
+            report.check(
+                "emit-type-template",
+                self.topics.validate(variables).map(|()| template.clone()),
+            );
+        }
+        self.payload.self_test(report);
+        self.spool.self_test(report);
+    }
+
+    /// The types a source emitting the built-in `types` lists as produced:
+    /// each as renamed (or the template itself, since templated types vary
+    /// per event), plus `primitive.error` and `primitive.spool` when enabled.
+    pub fn produces(&self, types: &[&str]) -> Vec<String> {
+        let mut produces: Vec<String> = match &self.topics.emit_type_template {
+            Some(template) => vec![template.clone()],
+            None => types
+                .iter()
+                .map(|t| self.topics.emit_type(t, &[]))
+                .collect(),
+        };
+        if self.errors.emit_errors {
+            produces.push(ERROR_EVENT_TYPE.to_string());
+        }
+        if self.spool.spool_dir.is_some() {
+            produces.push(SPOOL_EVENT_TYPE.to_string());
+        }
+        produces
+    }
+}
+
+/// The engine connection an [`Outlet`] publishes over. Sources cannot
+/// subscribe, so one that also consumes bus events (such as `http-source`
+/// with its pull API) connects as a handler instead.
+pub enum Connection {
+    Source(EmergentSource),
+    Handler(EmergentHandler),
+}
+
+impl Connection {
+    async fn publish(&self, message: EmergentMessage) -> Result<(), ClientError> {
+        match self {
+            Self::Source(s) => s.publish(message).await,
+            Self::Handler(h) => h.publish(message).await,
+        }
+    }
+
+    async fn publish_ack(&self, message: EmergentMessage) -> Result<(), ClientError> {
+        match self {
+            Self::Source(s) => s.publish_ack(message).await,
+            Self::Handler(h) => h.publish_ack(message).await,
+        }
+    }
+
+    async fn disconnect(&self) -> Result<(), ClientError> {
+        match self {
+            Self::Source(s) => s.disconnect().await,
+            Self::Handler(h) => h.disconnect().await,
+        }
+    }
+}
+
+impl From<EmergentSource> for Connection {
+    fn from(source: EmergentSource) -> Self {
+        Self::Source(source)
+    }
+}
+
+impl From<EmergentHandler> for Connection {
+    fn from(handler: EmergentHandler) -> Self {
+        Self::Handler(handler)
+    }
+}
+
+/// A source's connection to the bus, with its spool.
+///
+/// Every event a source emits goes through [`Outlet::publish`]:
+///
+/// 1. the built-in type is renamed by `--emit-type-map` or built from
+///    `--emit-type-template` ([`TopicArgs`]), which can use `{type}`,
+///    `{source}` and whatever variables the source adds;
+/// 2. the payload is compressed, offloaded or encrypted ([`payload`]);
+/// 3. the message is published and acknowledged by the engine, or with
+///    `--spool-dir` spooled to disk while the engine is unreachable and
+///    delivered in order by [`Outlet::drain_every`] once it is back;
+/// 4. an event that is lost anyway is logged and, with `--emit-errors`,
+///    reported as a `primitive.error` event.
+pub struct Outlet {
+    source: Connection,
+    name: String,
+    topics: TopicArgs,
+    payload: PayloadArgs,
+    emit_errors: bool,
+    spool: Option<Spool>,
+}
+
+impl Outlet {
+    /// Publish through `source` as `name`, opening the spool `args`
+    /// configures.
+    pub fn new(source: impl Into<Connection>, name: &str, args: &SourceArgs) -> io::Result<Self> {
+        Ok(Self {
+            source: source.into(),
+            name: name.to_string(),
+            topics: args.topics.clone(),
+            payload: args.payload.clone(),
+            emit_errors: args.errors.emit_errors,
+            spool: Spool::open(&args.spool)?,
+        })
+    }
+
+    /// The source's name.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Publish `payload` as the built-in type `builtin`, with `vars` for
+    /// `--emit-type-template` besides `{source}`. Returns the error if the
+    /// event was lost: neither published nor spooled.
+    pub async fn publish(
+        &self,
+        builtin: &str,
+        vars: &[(&str, &str)],
+        payload: Value,
+    ) -> Result<(), String> {
+        let mut all = vec![("source", self.name.as_str())];
+        all.extend_from_slice(vars);
+        let message_type = self.topics.emit_type(builtin, &all);
+
+        let payload = match payload::encode(&message_type, payload, &self.payload).await {
+            Ok(p) => p,
+            Err(e) => {
+                return Err(self
+                    .lost(
+                        ErrorCategory::Internal,
+                        &format!("failed to encode payload: {e}"),
+                    )
+                    .await);
+            }
+        };
+
+        let Some(spool) = &self.spool else {
+            let message = EmergentMessage::new(&message_type).with_payload(payload);
+            return match self.source.publish_ack(message).await {
+                Ok(()) => Ok(()),
+                Err(e) => Err(self
+                    .lost(
+                        ErrorCategory::Request,
+                        &format!("failed to publish {message_type}: {e}"),
+                    )
+                    .await),
+            };
+        };
+        match spool
+            .send(&message_type, payload, |m| self.source.publish_ack(m))
+            .await
+        {
+            Ok(Delivery::Published) => Ok(()),
+            Ok(Delivery::Spooled { depth }) => {
+                eprintln!("Engine unavailable; spooled {message_type} (depth {depth})");
+                Ok(())
+            }
+            Err(e) => Err(self
+                .lost(
+                    ErrorCategory::Internal,
+                    &format!("failed to spool {message_type}: {e}"),
+                )
+                .await),
+        }
+    }
+
+    /// Publish `message` as is, unacknowledged and without the spool, for
+    /// announcements and reports that are not worth keeping. A failure is
+    /// only logged.
+    pub async fn announce(&self, message: EmergentMessage) {
+        let message_type = message.message_type.as_str().to_string();
+        if let Err(e) = self.source.publish(message).await {
+            eprintln!("Failed to publish {message_type}: {e}");
+        }
+    }
+
+    /// Log an error and publish a `primitive.error` event when
+    /// `--emit-errors` is set.
+    pub async fn report_error(&self, category: ErrorCategory, error: &str) {
+        eprintln!("{error}");
+        if !self.emit_errors {
+            return;
+        }
+        let event = ErrorEvent {
+            primitive: &self.name,
+            message_id: None,
+            message_type: None,
+            category,
+            disposition: Disposition::Dropped,
+            attempt: None,
+            error,
+        };
+        self.announce(event.to_message()).await;
+    }
+
+    /// Report an event as lost, returning the error.
+    async fn lost(&self, category: ErrorCategory, error: &str) -> String {
+        self.report_error(category, error).await;
+        error.to_string()
+    }
+
+    /// Deliver what the spool holds, announcing progress as a
+    /// `primitive.spool` event.
+    pub async fn drain_spool(&self) {
+        let Some(spool) = &self.spool else {
+            return;
+        };
+        match spool.drain(|m| self.source.publish_ack(m)).await {
+            Ok(report) if !report.is_empty() => {
+                eprintln!(
+                    "Drained spool: {} delivered, {} expired, {} remaining",
+                    report.published, report.expired, report.remaining
+                );
+                self.announce(report.to_message(&self.name)).await;
+            }
+            Ok(_) => {}
+            Err(e) => eprintln!("Failed to drain spool: {e}"),
+        }
+    }
+
+    /// Drain the spool every `interval`, forever; returns at once without
+    /// `--spool-dir`.
+    pub async fn drain_every(self: Arc<Self>, interval: Duration) {
+        if self.spool.is_none() {
+            return;
+        }
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            ticker.tick().await;
+            self.drain_spool().await;
+        }
+    }
+
+    /// Disconnect from the engine.
+    pub async fn disconnect(&self) {
+        let _ = self.source.disconnect().await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    #[derive(Parser)]
+    struct Cli {
+        #[command(flatten)]
+        source: SourceArgs,
+    }
+
+    fn args(argv: &[&str]) -> SourceArgs {
+        let mut all = vec!["source"];
+        all.extend_from_slice(argv);
+        Cli::try_parse_from(all)
+            .unwrap_or_else(|e| panic!("{e}"))
+            .source
+    }
+
+    #[test]
+    fn produced_types_follow_renames_errors_and_the_spool() {
+        let plain = args(&[]);
+        assert_eq!(plain.produces(&["a.b", "a.c"]), ["a.b", "a.c"]);
+
+        let renamed = args(&[
+            "--emit-type-map",
+            "a.b=x.y",
+            "--emit-errors",
+            "--spool-dir",
+            "/tmp/spool",
+        ]);
+        assert_eq!(
+            renamed.produces(&["a.b", "a.c"]),
+            ["x.y", "a.c", ERROR_EVENT_TYPE, SPOOL_EVENT_TYPE]
+        );
+
+        let templated = args(&["--emit-type-template", "{source}.{kind}"]);
+        assert_eq!(templated.produces(&["a.b"]), ["{source}.{kind}"]);
+    }
+
+    #[test]
+    fn templates_are_checked_against_the_source_variables() {
+        assert!(
+            args(&["--emit-type-template", "{source}.{kind}"])
+                .validate(&["source", "kind"])
+                .is_ok()
+        );
+        assert!(
+            args(&["--emit-type-template", "{type}.{path}"])
+                .validate(&["source", "kind"])
+                .is_err()
+        );
+    }
+}
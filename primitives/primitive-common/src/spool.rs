@@ -0,0 +1,774 @@
+//! Write-ahead spool for sources while the engine is unreachable.
+//!
+//! Without a spool, a source that fails to publish drops the event (and
+//! `http-source` answers 500). With `--spool-dir`, failed publishes are
+//! appended to an on-disk queue instead, and once the engine accepts
+//! publishes again the queue is drained oldest first. While anything is
+//! spooled, new events queue behind it so order is preserved.
+//!
+//! # Layout
+//!
+//! ```text
+//! <spool-dir>/
+//!   spool.jsonl   append-only records, one JSON object per line
+//!   offset        byte offset of the first undelivered record
+//!   corrupt.jsonl records that could not be read, kept for inspection
+//!   spool.jsonl.tmp       a compaction being copied
+//!   spool.jsonl.compacted a copied compaction, waiting to replace the log
+//! ```
+//!
+//! The file is truncated once fully drained, and rewritten without its
+//! delivered prefix once that passes 1 MiB and outweighs the rest. The
+//! copy is only renamed to `spool.jsonl.compacted` once it is synced, so a
+//! crash mid-copy leaves a `.tmp` file that is discarded on open. A last
+//! line a crash left without its newline is moved to `corrupt.jsonl` on
+//! open, as is any line that fails to parse while draining; both are
+//! logged and skipped. `--spool-max-bytes` caps the
+//! undelivered backlog (events beyond it are rejected) and records older
+//! than `--spool-max-age` are discarded during draining. Delivery is
+//! at-least-once: a crash between publish and offset update replays one
+//! record.
+//!
+//! After each drain pass that delivered or discarded anything, sources
+//! publish a `primitive.spool` event with the counts and remaining depth.
+
+use crate::doctor::{Report, check_writable_dir};
+use clap::Args;
+use emergent_client::EmergentMessage;
+use event_schemas::{EventPayload, PrimitiveSpool};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{
+    fmt::Display,
+    fs::{self, File, OpenOptions},
+    future::Future,
+    io::{self, BufRead, BufReader, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tokio::sync::Mutex;
+
+/// Message type of drain progress events.
+pub const SPOOL_EVENT_TYPE: &str = PrimitiveSpool::MESSAGE_TYPE;
+
+/// CLI flags controlling the source spool.
+#[derive(Args, Debug, Clone)]
+pub struct SpoolArgs {
+    /// Spool events here when publishing fails, and deliver them once the engine is back.
+    #[arg(long, env = "EMERGENT_SPOOL_DIR")]
+    pub spool_dir: Option<PathBuf>,
+
+    /// Maximum undelivered spool size in bytes; events beyond it are rejected.
+    #[arg(long, env = "EMERGENT_SPOOL_MAX_BYTES", default_value_t = 64 * 1024 * 1024)]
+    pub spool_max_bytes: u64,
+
+    /// Discard spooled events older than this many seconds instead of delivering them.
+    #[arg(long, env = "EMERGENT_SPOOL_MAX_AGE", default_value_t = 24 * 60 * 60)]
+    pub spool_max_age: u64,
+
+    /// Milliseconds between attempts to drain the spool.
+    #[arg(long, env = "EMERGENT_SPOOL_RETRY", default_value_t = 1000)]
+    pub spool_retry: u64,
+}
+
+impl Default for SpoolArgs {
+    fn default() -> Self {
+        Self {
+            spool_dir: None,
+            spool_max_bytes: 64 * 1024 * 1024,
+            spool_max_age: 24 * 60 * 60,
+            spool_retry: 1000,
+        }
+    }
+}
+
+impl SpoolArgs {
+    /// Interval between drain attempts.
+    pub fn retry_interval(&self) -> Duration {
+        Duration::from_millis(self.spool_retry.max(1))
+    }
+
+    /// Add `--self-test` checks for the spool directory.
+    pub fn self_test(&self, report: &mut Report) {
+        let Some(dir) = &self.spool_dir else {
+            return;
+        };
+        let outcome = check_writable_dir(dir).and_then(|detail| {
+            SpoolFile::open(dir)
+                .map(|file| format!("{detail} ({} spooled)", file.depth))
+                .map_err(|e| format!("{}: {e}", dir.display()))
+        });
+        report.check("spool-dir", outcome);
+    }
+}
+
+/// How [`Spool::send`] disposed of an event.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Delivery {
+    /// Published to the engine.
+    Published,
+    /// Written to the spool; `depth` events are now waiting.
+    Spooled { depth: u64 },
+}
+
+/// Outcome of one [`Spool::drain`] pass.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DrainReport {
+    /// Spooled events delivered.
+    pub published: u64,
+    /// Spooled events discarded for exceeding `--spool-max-age`.
+    pub expired: u64,
+    /// Events still waiting (non-zero if the engine failed again).
+    pub remaining: u64,
+}
+
+impl DrainReport {
+    /// Whether the pass changed anything worth reporting.
+    pub fn is_empty(&self) -> bool {
+        self.published == 0 && self.expired == 0
+    }
+
+    /// Build the `primitive.spool` metrics event for this pass.
+    pub fn to_message(&self, primitive: &str) -> EmergentMessage {
+        let payload = PrimitiveSpool {
+            primitive: primitive.to_string(),
+            published: self.published,
+            expired: self.expired,
+            remaining: self.remaining,
+        };
+        EmergentMessage::new(SPOOL_EVENT_TYPE).with_payload(payload.to_payload())
+    }
+}
+
+/// One spooled event.
+#[derive(Debug, Serialize, Deserialize)]
+struct Record {
+    /// Unix time in milliseconds when the event was spooled.
+    at: u64,
+    message_type: String,
+    payload: Value,
+}
+
+/// Shared, async-safe spool.
+pub struct Spool {
+    file: Mutex<SpoolFile>,
+    /// Mirror of the undelivered count, readable without the lock.
+    depth: AtomicU64,
+    max_bytes: u64,
+    max_age: Duration,
+}
+
+impl Spool {
+    /// Open the spool configured by `args`, or `None` without `--spool-dir`.
+    pub fn open(args: &SpoolArgs) -> io::Result<Option<Self>> {
+        let Some(dir) = &args.spool_dir else {
+            return Ok(None);
+        };
+        let file = SpoolFile::open(dir)?;
+        Ok(Some(Self {
+            depth: AtomicU64::new(file.depth),
+            file: Mutex::new(file),
+            max_bytes: args.spool_max_bytes,
+            max_age: Duration::from_secs(args.spool_max_age),
+        }))
+    }
+
+    /// Number of undelivered events.
+    pub fn depth(&self) -> u64 {
+        self.depth.load(Ordering::Relaxed)
+    }
+
+    /// Publish an event, spooling it if the spool is non-empty (to keep
+    /// order) or `publish` fails.
+    ///
+    /// Returns an error only if the event could not be spooled either
+    /// (backlog full or I/O failure); the event is then lost.
+    pub async fn send<F, Fut, E>(
+        &self,
+        message_type: &str,
+        payload: Value,
+        mut publish: F,
+    ) -> io::Result<Delivery>
+    where
+        F: FnMut(EmergentMessage) -> Fut,
+        Fut: Future<Output = Result<(), E>>,
+        E: Display,
+    {
+        // Fast path: nothing queued, so publishing directly keeps order
+        if self.depth() == 0 {
+            let msg = EmergentMessage::new(message_type).with_payload(payload.clone());
+            if publish(msg).await.is_ok() {
+                return Ok(Delivery::Published);
+            }
+        }
+
+        let mut file = self.file.lock().await;
+        let record = Record {
+            at: now_millis(),
+            message_type: message_type.to_string(),
+            payload,
+        };
+        file.append(&record, self.max_bytes)?;
+        self.depth.store(file.depth, Ordering::Relaxed);
+        Ok(Delivery::Spooled { depth: file.depth })
+    }
+
+    /// Deliver spooled events oldest first until the spool is empty or
+    /// `publish` fails.
+    pub async fn drain<F, Fut, E>(&self, mut publish: F) -> io::Result<DrainReport>
+    where
+        F: FnMut(EmergentMessage) -> Fut,
+        Fut: Future<Output = Result<(), E>>,
+        E: Display,
+    {
+        let mut report = DrainReport::default();
+        if self.depth() == 0 {
+            return Ok(report);
+        }
+
+        let mut file = self.file.lock().await;
+        let cutoff = now_millis().saturating_sub(self.max_age.as_millis() as u64);
+        while let Some((record, len)) = file.peek()? {
+            if record.at < cutoff {
+                report.expired += 1;
+            } else {
+                let msg = EmergentMessage::new(&record.message_type).with_payload(record.payload);
+                if publish(msg).await.is_err() {
+                    break;
+                }
+                report.published += 1;
+            }
+            file.advance(len)?;
+            self.depth.store(file.depth, Ordering::Relaxed);
+        }
+        report.remaining = file.depth;
+        Ok(report)
+    }
+}
+
+/// Delivered bytes past which the log is compacted, once they also
+/// outweigh the undelivered rest.
+const COMPACT_BYTES: u64 = 1024 * 1024;
+
+/// The on-disk queue; callers serialize access.
+struct SpoolFile {
+    log: PathBuf,
+    /// The log being rewritten without its delivered prefix.
+    copying: PathBuf,
+    /// A finished copy; its presence commits the compaction.
+    compacted: PathBuf,
+    offset_path: PathBuf,
+    corrupt: PathBuf,
+    /// Byte offset of the first undelivered record.
+    offset: u64,
+    /// Total length of the log in bytes.
+    len: u64,
+    /// Undelivered record count.
+    depth: u64,
+    /// Delivered prefix in bytes at which the log is compacted.
+    compact_after: u64,
+}
+
+impl SpoolFile {
+    fn open(dir: &Path) -> io::Result<Self> {
+        fs::create_dir_all(dir)?;
+        let log = dir.join("spool.jsonl");
+        let copying = dir.join("spool.jsonl.tmp");
+        let compacted = dir.join("spool.jsonl.compacted");
+        let offset_path = dir.join("offset");
+        let saved = match fs::read_to_string(&offset_path) {
+            Ok(text) => Some(text.trim().parse::<u64>().map_err(io::Error::other)?),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+            Err(e) => return Err(e),
+        };
+        // A copy a crash cut short is dropped; a finished one is committed
+        if copying.exists() {
+            fs::remove_file(&copying)?;
+        }
+        let committed = compacted.exists();
+        if committed {
+            fs::rename(&compacted, &log)?;
+            sync_dir(dir)?;
+        }
+        let len = match fs::metadata(&log) {
+            Ok(meta) => meta.len(),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => 0,
+            Err(e) => return Err(e),
+        };
+
+        let mut file = Self {
+            log,
+            copying,
+            compacted,
+            offset_path,
+            corrupt: dir.join("corrupt.jsonl"),
+            offset: if committed {
+                0
+            } else {
+                saved.unwrap_or(0).min(len)
+            },
+            len,
+            depth: 0,
+            compact_after: COMPACT_BYTES,
+        };
+        file.scan()?;
+        if committed {
+            file.save_offset()?;
+        }
+        Ok(file)
+    }
+
+    /// Count the undelivered records, cutting off a last line a crash
+    /// left without its newline so the next append starts a fresh one.
+    fn scan(&mut self) -> io::Result<()> {
+        self.depth = 0;
+        if self.offset >= self.len {
+            return Ok(());
+        }
+        let mut reader = BufReader::new(File::open(&self.log)?);
+        reader.seek(SeekFrom::Start(self.offset))?;
+        let mut at = self.offset;
+        let mut line = Vec::new();
+        loop {
+            line.clear();
+            let read = reader.read_until(b'\n', &mut line)? as u64;
+            if read == 0 {
+                return Ok(());
+            }
+            if line.last() != Some(&b'\n') {
+                eprintln!(
+                    "Moving a torn spool record at byte {at} to {}",
+                    self.corrupt.display()
+                );
+                self.quarantine(&line)?;
+                OpenOptions::new()
+                    .write(true)
+                    .open(&self.log)?
+                    .set_len(at)?;
+                self.len = at;
+                return Ok(());
+            }
+            self.depth += 1;
+            at += read;
+        }
+    }
+
+    /// Keep an unreadable line for inspection.
+    fn quarantine(&self, line: &[u8]) -> io::Result<()> {
+        let mut corrupt = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.corrupt)?;
+        corrupt.write_all(line)?;
+        if line.last() != Some(&b'\n') {
+            corrupt.write_all(b"\n")?;
+        }
+        Ok(())
+    }
+
+    fn append(&mut self, record: &Record, max_bytes: u64) -> io::Result<()> {
+        let mut line = serde_json::to_vec(record).map_err(io::Error::other)?;
+        line.push(b'\n');
+        let backlog = self.len - self.offset;
+        if backlog + line.len() as u64 > max_bytes {
+            return Err(io::Error::other(format!(
+                "spool full ({backlog} bytes, limit {max_bytes})"
+            )));
+        }
+        let mut log = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.log)?;
+        if let Err(e) = log.write_all(&line).and_then(|()| log.sync_data()) {
+            // Drop whatever part of the record made it, so it cannot run
+            // into the next one
+            let _ = log.set_len(self.len);
+            return Err(e);
+        }
+        self.len += line.len() as u64;
+        self.depth += 1;
+        Ok(())
+    }
+
+    /// The oldest undelivered record and its length in bytes (with
+    /// newline). Lines that do not parse are quarantined and skipped.
+    fn peek(&mut self) -> io::Result<Option<(Record, u64)>> {
+        while self.depth > 0 {
+            let mut reader = BufReader::new(File::open(&self.log)?);
+            reader.seek(SeekFrom::Start(self.offset))?;
+            let mut line = Vec::new();
+            let len = reader.read_until(b'\n', &mut line)? as u64;
+            if len == 0 {
+                return Ok(None);
+            }
+            match serde_json::from_slice(&line) {
+                Ok(record) => return Ok(Some((record, len))),
+                Err(e) => {
+                    eprintln!(
+                        "Moving an unreadable spool record at byte {} to {}: {e}",
+                        self.offset,
+                        self.corrupt.display()
+                    );
+                    self.quarantine(&line)?;
+                    self.advance(len)?;
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    /// Mark `len` bytes as delivered; truncate the log once it is drained
+    /// and compact it once the delivered prefix outweighs the rest.
+    fn advance(&mut self, len: u64) -> io::Result<()> {
+        self.offset += len;
+        self.depth = self.depth.saturating_sub(1);
+        if self.depth == 0 {
+            File::create(&self.log)?;
+            self.offset = 0;
+            self.len = 0;
+        } else if self.offset >= self.compact_after && self.offset >= self.len - self.offset {
+            return self.compact();
+        }
+        self.save_offset()
+    }
+
+    /// Rewrite the log without its delivered prefix. The current offset
+    /// is saved first, so a crash mid-copy resumes where delivery stopped;
+    /// renaming the synced copy to `spool.jsonl.compacted` commits it, and
+    /// [`Self::open`] finishes a committed compaction a crash interrupted.
+    fn compact(&mut self) -> io::Result<()> {
+        self.save_offset()?;
+        let mut log = File::open(&self.log)?;
+        log.seek(SeekFrom::Start(self.offset))?;
+        let mut copy = File::create(&self.copying)?;
+        io::copy(&mut log, &mut copy)?;
+        copy.sync_all()?;
+        fs::rename(&self.copying, &self.compacted)?;
+        self.sync_dir()?;
+        self.len -= self.offset;
+        self.offset = 0;
+        self.save_offset()?;
+        fs::rename(&self.compacted, &self.log)?;
+        self.sync_dir()
+    }
+
+    fn save_offset(&self) -> io::Result<()> {
+        let tmp = self.offset_path.with_extension("tmp");
+        let mut file = File::create(&tmp)?;
+        file.write_all(self.offset.to_string().as_bytes())?;
+        file.sync_all()?;
+        fs::rename(&tmp, &self.offset_path)
+    }
+
+    fn sync_dir(&self) -> io::Result<()> {
+        match self.log.parent() {
+            Some(dir) => sync_dir(dir),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Make renames within `dir` durable.
+fn sync_dir(dir: &Path) -> io::Result<()> {
+    File::open(dir)?.sync_all()
+}
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis() as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::sync::atomic::AtomicBool;
+
+    fn spool_args(name: &str) -> SpoolArgs {
+        let dir = std::env::temp_dir().join(format!("spool-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        SpoolArgs {
+            spool_dir: Some(dir),
+            ..Default::default()
+        }
+    }
+
+    fn open(args: &SpoolArgs) -> Spool {
+        match Spool::open(args) {
+            Ok(Some(spool)) => spool,
+            Ok(None) => panic!("spool not configured"),
+            Err(e) => panic!("open spool: {e}"),
+        }
+    }
+
+    async fn failing(_msg: EmergentMessage) -> Result<(), String> {
+        Err("engine down".to_string())
+    }
+
+    #[tokio::test]
+    async fn failed_publishes_are_spooled_and_drained_in_order() {
+        let args = spool_args("order");
+        let spool = open(&args);
+
+        for n in 0..3 {
+            let delivery = spool
+                .send("tick", json!({"n": n}), failing)
+                .await
+                .unwrap_or_else(|e| panic!("send: {e}"));
+            assert_eq!(delivery, Delivery::Spooled { depth: n + 1 });
+        }
+
+        let mut seen = Vec::new();
+        let report = spool
+            .drain(|msg| {
+                seen.push(msg.payload()["n"].clone());
+                async { Ok::<(), String>(()) }
+            })
+            .await
+            .unwrap_or_else(|e| panic!("drain: {e}"));
+
+        assert_eq!(seen, vec![json!(0), json!(1), json!(2)]);
+        assert_eq!(report.published, 3);
+        assert_eq!(spool.depth(), 0);
+        let _ = fs::remove_dir_all(args.spool_dir.unwrap_or_default());
+    }
+
+    #[tokio::test]
+    async fn new_events_queue_behind_a_non_empty_spool() {
+        let args = spool_args("behind");
+        let spool = open(&args);
+        let _ = spool.send("tick", json!(1), failing).await;
+
+        let published = AtomicBool::new(false);
+        let delivery = spool
+            .send("tick", json!(2), |_msg| {
+                published.store(true, Ordering::Relaxed);
+                async { Ok::<(), String>(()) }
+            })
+            .await
+            .unwrap_or_else(|e| panic!("send: {e}"));
+
+        assert_eq!(delivery, Delivery::Spooled { depth: 2 });
+        assert!(!published.load(Ordering::Relaxed));
+        let _ = fs::remove_dir_all(args.spool_dir.unwrap_or_default());
+    }
+
+    #[tokio::test]
+    async fn spool_survives_restart_and_stops_on_failure() {
+        let args = spool_args("restart");
+        {
+            let spool = open(&args);
+            let _ = spool.send("tick", json!(1), failing).await;
+            let _ = spool.send("tick", json!(2), failing).await;
+        }
+
+        let spool = open(&args);
+        assert_eq!(spool.depth(), 2);
+
+        let mut calls = 0;
+        let report = spool
+            .drain(|_msg| {
+                calls += 1;
+                let result = if calls == 1 {
+                    Ok(())
+                } else {
+                    Err("down again")
+                };
+                async move { result }
+            })
+            .await
+            .unwrap_or_else(|e| panic!("drain: {e}"));
+        assert_eq!(report.published, 1);
+        assert_eq!(report.remaining, 1);
+        assert_eq!(open(&args).depth(), 1);
+        let _ = fs::remove_dir_all(args.spool_dir.unwrap_or_default());
+    }
+
+    #[tokio::test]
+    async fn backlog_cap_rejects_new_events() {
+        let mut args = spool_args("cap");
+        args.spool_max_bytes = 64;
+        let spool = open(&args);
+
+        assert!(spool.send("tick", json!(1), failing).await.is_ok());
+        let big = json!({"data": "x".repeat(100)});
+        assert!(spool.send("tick", big, failing).await.is_err());
+        assert_eq!(spool.depth(), 1);
+        let _ = fs::remove_dir_all(args.spool_dir.unwrap_or_default());
+    }
+
+    #[tokio::test]
+    async fn expired_events_are_discarded() {
+        let mut args = spool_args("expiry");
+        args.spool_max_age = 0;
+        let spool = open(&args);
+        let _ = spool.send("tick", json!(1), failing).await;
+        tokio::time::sleep(Duration::from_millis(5)).await;
+
+        let report = spool
+            .drain(|_msg| async { Ok::<(), String>(()) })
+            .await
+            .unwrap_or_else(|e| panic!("drain: {e}"));
+        assert_eq!(report.expired, 1);
+        assert_eq!(report.published, 0);
+        let _ = fs::remove_dir_all(args.spool_dir.unwrap_or_default());
+    }
+
+    fn payloads(seen: &[EmergentMessage]) -> Vec<Value> {
+        seen.iter().map(|msg| msg.payload().clone()).collect()
+    }
+
+    #[tokio::test]
+    async fn torn_and_unreadable_records_are_quarantined() {
+        let args = spool_args("torn");
+        let dir = args.spool_dir.clone().unwrap_or_default();
+        {
+            let spool = open(&args);
+            let _ = spool.send("tick", json!(1), failing).await;
+            let _ = spool.send("tick", json!(2), failing).await;
+        }
+        // A garbled record, then one a crash cut short
+        let mut log = OpenOptions::new()
+            .append(true)
+            .open(dir.join("spool.jsonl"))
+            .unwrap_or_else(|e| panic!("open log: {e}"));
+        log.write_all(b"not json\n{\"at\":1,\"mess")
+            .unwrap_or_else(|e| panic!("write log: {e}"));
+
+        let spool = open(&args);
+        assert_eq!(spool.depth(), 3);
+        let _ = spool.send("tick", json!(3), failing).await;
+
+        let mut seen = Vec::new();
+        let report = spool
+            .drain(|msg| {
+                seen.push(msg);
+                async { Ok::<(), String>(()) }
+            })
+            .await
+            .unwrap_or_else(|e| panic!("drain: {e}"));
+        assert_eq!(payloads(&seen), vec![json!(1), json!(2), json!(3)]);
+        assert_eq!(report.published, 3);
+        assert_eq!(spool.depth(), 0);
+        let corrupt = fs::read_to_string(dir.join("corrupt.jsonl")).unwrap_or_default();
+        assert_eq!(corrupt, "{\"at\":1,\"mess\nnot json\n");
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[tokio::test]
+    async fn delivered_prefix_is_compacted() {
+        let args = spool_args("compact");
+        let dir = args.spool_dir.clone().unwrap_or_default();
+        let spool = open(&args);
+        spool.file.lock().await.compact_after = 1;
+        for n in 0..5 {
+            let _ = spool.send("tick", json!(n), failing).await;
+        }
+        let record_len = fs::metadata(dir.join("spool.jsonl")).map_or(0, |m| m.len()) / 5;
+
+        let mut calls = 0;
+        let report = spool
+            .drain(|_msg| {
+                calls += 1;
+                let result = if calls <= 3 {
+                    Ok(())
+                } else {
+                    Err("down again")
+                };
+                async move { result }
+            })
+            .await
+            .unwrap_or_else(|e| panic!("drain: {e}"));
+        assert_eq!(report.remaining, 2);
+        // Three delivered records outweigh the two left, so they are gone
+        let log = fs::read_to_string(dir.join("spool.jsonl")).unwrap_or_default();
+        assert_eq!(log.len() as u64, 2 * record_len);
+        assert_eq!(
+            fs::read_to_string(dir.join("offset")).ok().as_deref(),
+            Some("0")
+        );
+
+        let mut seen = Vec::new();
+        let _ = open(&args)
+            .drain(|msg| {
+                seen.push(msg);
+                async { Ok::<(), String>(()) }
+            })
+            .await;
+        assert_eq!(payloads(&seen), vec![json!(3), json!(4)]);
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[tokio::test]
+    async fn interrupted_compactions_finish_only_once_committed() {
+        let args = spool_args("interrupted");
+        let dir = args.spool_dir.clone().unwrap_or_default();
+        {
+            let spool = open(&args);
+            let _ = spool.send("tick", json!(1), failing).await;
+            let _ = spool.send("tick", json!(2), failing).await;
+        }
+        let log = fs::read_to_string(dir.join("spool.jsonl")).unwrap_or_default();
+        let (first, rest) = log.split_at(log.find('\n').map_or(0, |at| at + 1));
+        let write = |name: &str, text: &str| {
+            fs::write(dir.join(name), text).unwrap_or_else(|e| panic!("write {name}: {e}"));
+        };
+
+        // Copied but not committed: the copy is dropped
+        write("offset", &first.len().to_string());
+        write("spool.jsonl.tmp", rest);
+        assert_eq!(open(&args).depth(), 1);
+        assert!(!dir.join("spool.jsonl.tmp").exists());
+        assert_eq!(
+            fs::read_to_string(dir.join("spool.jsonl")).ok().as_deref(),
+            Some(log.as_str())
+        );
+
+        // Committed but not renamed: the copy replaces the log, whatever
+        // offset was saved
+        write("spool.jsonl.compacted", rest);
+        assert_eq!(open(&args).depth(), 1);
+        assert!(!dir.join("spool.jsonl.compacted").exists());
+        assert_eq!(
+            fs::read_to_string(dir.join("spool.jsonl")).ok().as_deref(),
+            Some(rest)
+        );
+        assert_eq!(
+            fs::read_to_string(dir.join("offset")).ok().as_deref(),
+            Some("0")
+        );
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[tokio::test]
+    async fn crash_mid_compaction_keeps_undelivered_records() {
+        let args = spool_args("mid-compaction");
+        let dir = args.spool_dir.clone().unwrap_or_default();
+        {
+            let spool = open(&args);
+            for n in 0..3 {
+                let _ = spool.send("tick", json!(n), failing).await;
+            }
+        }
+        let log = fs::read_to_string(dir.join("spool.jsonl")).unwrap_or_default();
+
+        // Nothing delivered yet, and a crash left half a copy behind
+        fs::write(dir.join("offset"), "0").unwrap_or_else(|e| panic!("write offset: {e}"));
+        fs::write(dir.join("spool.jsonl.tmp"), &log[..log.len() / 2])
+            .unwrap_or_else(|e| panic!("write copy: {e}"));
+
+        let mut seen = Vec::new();
+        let spool = open(&args);
+        assert_eq!(spool.depth(), 3);
+        let _ = spool
+            .drain(|msg| {
+                seen.push(msg);
+                async { Ok::<(), String>(()) }
+            })
+            .await;
+        assert_eq!(payloads(&seen), vec![json!(0), json!(1), json!(2)]);
+        let _ = fs::remove_dir_all(dir);
+    }
+}
@@ -0,0 +1,121 @@
+//! `{name}` placeholders in paths, messages and documents.
+//!
+//! [`substitute`] fills them from any lookup; [`render`] and
+//! [`render_strict`] read each as a dotted path into a payload
+//! (`{build.branch}`), where non-string values use their JSON encoding.
+
+use crate::key;
+use serde_json::Value;
+use std::convert::Infallible;
+
+/// Replace each `{name}` in `template` with `value(name)`. An unclosed `{`
+/// and everything after it is kept as written.
+pub fn substitute(template: &str, value: impl Fn(&str) -> String) -> String {
+    let Ok(out) = try_substitute(template, |name| Ok::<_, Infallible>(value(name)));
+    out
+}
+
+/// Fill `{dotted.path}` placeholders in `template` from `payload`; a
+/// placeholder the payload lacks becomes an empty string.
+pub fn render(template: &str, payload: &Value) -> String {
+    substitute(template, |path| {
+        key::extract(payload, path).unwrap_or_default()
+    })
+}
+
+/// [`render`], except that a placeholder the payload lacks is an error.
+pub fn render_strict(template: &str, payload: &Value) -> Result<String, String> {
+    try_substitute(template, |path| {
+        key::extract(payload, path).ok_or_else(|| format!("payload has no '{path}'"))
+    })
+}
+
+/// [`substitute`] with a lookup that can fail, stopping at the first error.
+pub fn try_substitute<E>(
+    template: &str,
+    mut value: impl FnMut(&str) -> Result<String, E>,
+) -> Result<String, E> {
+    let mut out = String::with_capacity(template.len());
+    for piece in pieces(template) {
+        match piece {
+            Piece::Text(text) | Piece::Unclosed(text) => out.push_str(text),
+            Piece::Placeholder(name) => out.push_str(&value(name)?),
+        }
+    }
+    Ok(out)
+}
+
+/// Names of the `{placeholders}` in `template`, for templates where an
+/// unclosed `{` is a mistake to report rather than text to keep.
+pub fn placeholders(template: &str) -> Result<Vec<&str>, String> {
+    pieces(template)
+        .filter_map(|piece| match piece {
+            Piece::Text(_) => None,
+            Piece::Placeholder(name) => Some(Ok(name)),
+            Piece::Unclosed(_) => Some(Err(format!("unclosed '{{' in template '{template}'"))),
+        })
+        .collect()
+}
+
+/// A run of a template: plain text, a `{name}`, or an unclosed `{` with
+/// everything after it.
+enum Piece<'a> {
+    Text(&'a str),
+    Placeholder(&'a str),
+    Unclosed(&'a str),
+}
+
+fn pieces(template: &str) -> impl Iterator<Item = Piece<'_>> {
+    let mut rest = template;
+    std::iter::from_fn(move || {
+        if rest.is_empty() {
+            return None;
+        }
+        let (piece, next) = match rest.find('{') {
+            Some(0) => match rest.find('}') {
+                Some(end) => (Piece::Placeholder(&rest[1..end]), &rest[end + 1..]),
+                None => (Piece::Unclosed(rest), ""),
+            },
+            Some(start) => (Piece::Text(&rest[..start]), &rest[start..]),
+            None => (Piece::Text(rest), ""),
+        };
+        rest = next;
+        Some(piece)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn placeholders_are_replaced_and_unclosed_ones_kept() {
+        let upper = |name: &str| name.to_uppercase();
+        assert_eq!(substitute("{a}-{b}.{c}", upper), "A-B.C");
+        assert_eq!(substitute("no placeholders", upper), "no placeholders");
+        assert_eq!(substitute("{a} {unclosed", upper), "A {unclosed");
+        assert_eq!(substitute("{}{a}}", upper), "A}");
+        assert_eq!(substitute("{missing}", |_| String::new()), "");
+        assert_eq!(placeholders("{a}.{b}-c"), Ok(vec!["a", "b"]));
+        assert!(placeholders("{a}.{b").is_err());
+    }
+
+    #[test]
+    fn payload_paths_fill_placeholders() {
+        let payload = json!({"severity": "critical", "host": {"name": "web1"}, "n": 3});
+        assert_eq!(
+            render("{severity} on {host.name}{missing} x{n}", &payload),
+            "critical on web1 x3"
+        );
+        assert_eq!(render("unclosed {brace", &payload), "unclosed {brace");
+        assert_eq!(
+            render_strict("{host.name}:{n}", &payload),
+            Ok("web1:3".to_string())
+        );
+        assert_eq!(
+            render_strict("--limit {hostname} {host.name}", &payload),
+            Err("payload has no 'hostname'".to_string())
+        );
+    }
+}
@@ -0,0 +1,140 @@
+//! UTC timestamps and proleptic Gregorian calendar arithmetic, for the
+//! primitives that stamp, parse or sign with dates.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Seconds since the Unix epoch.
+pub fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0)
+}
+
+/// Milliseconds since the Unix epoch.
+pub fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+/// Days since 1970-01-01 of a proleptic Gregorian date.
+pub fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+/// The date `days` after 1970-01-01.
+pub fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+/// The day of `secs`, as `2024-05-20`.
+pub fn date(secs: i64) -> String {
+    let (year, month, day) = civil_from_days(secs.div_euclid(86_400));
+    format!("{year:04}-{month:02}-{day:02}")
+}
+
+/// `secs` as `2024-05-20T16:15:58Z`.
+pub fn format(secs: i64) -> String {
+    let time = secs.rem_euclid(86_400);
+    format!(
+        "{}T{:02}:{:02}:{:02}Z",
+        date(secs),
+        time / 3600,
+        time % 3600 / 60,
+        time % 60
+    )
+}
+
+/// `secs` as `20240520T161558Z`, for file names and request signatures.
+pub fn compact(secs: i64) -> String {
+    format(secs).replace(['-', ':'], "")
+}
+
+/// Seconds since the Unix epoch of an RFC 3339 timestamp, or of one
+/// without an offset such as NVD's `2024-05-20T16:15:58.123`, which is
+/// taken as UTC. Fractions of a second are dropped.
+pub fn parse(timestamp: &str) -> Option<i64> {
+    let number = |s: &str| s.parse::<i64>().ok();
+    let (date, time) = timestamp.split_once(['T', 't', ' '])?;
+    let mut date = date.splitn(3, '-');
+    let (year, month, day) = (
+        number(date.next()?)?,
+        number(date.next()?)?,
+        number(date.next()?)?,
+    );
+    let (clock, offset) = match time.find(['Z', 'z', '+', '-']) {
+        Some(at) => time.split_at(at),
+        None => (time, "Z"),
+    };
+    let offset_secs = match offset {
+        "Z" | "z" => 0,
+        _ => {
+            let sign = if offset.starts_with('-') { -1 } else { 1 };
+            let (hours, minutes) = offset[1..].split_once(':')?;
+            sign * (number(hours)? * 3600 + number(minutes)? * 60)
+        }
+    };
+    let clock = clock.split('.').next()?;
+    let mut clock = clock.splitn(3, ':');
+    let (hours, minutes, seconds) = (
+        number(clock.next()?)?,
+        number(clock.next()?)?,
+        number(clock.next().unwrap_or("0"))?,
+    );
+    Some(
+        days_from_civil(year, month, day) * 86_400 + hours * 3600 + minutes * 60 + seconds
+            - offset_secs,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dates_round_trip() {
+        assert_eq!(days_from_civil(1970, 1, 1), 0);
+        assert_eq!(days_from_civil(2000, 2, 29), 11_016);
+        assert_eq!(civil_from_days(11_016), (2000, 2, 29));
+        assert_eq!(civil_from_days(-1), (1969, 12, 31));
+        assert_eq!(
+            civil_from_days(days_from_civil(2023, 12, 31) + 1),
+            (2024, 1, 1)
+        );
+    }
+
+    #[test]
+    fn timestamps_format_and_parse() {
+        assert_eq!(format(1_716_221_758), "2024-05-20T16:15:58Z");
+        assert_eq!(format(951_782_400), "2000-02-29T00:00:00Z");
+        assert_eq!(date(1_716_221_758), "2024-05-20");
+        assert_eq!(compact(1_440_938_160), "20150830T123600Z");
+
+        let secs = parse("2024-05-20T16:15:58Z");
+        assert_eq!(secs, Some(1_716_221_758));
+        assert_eq!(parse("2024-05-20T18:15:58.412+02:00"), secs);
+        assert_eq!(parse("2024-05-20T16:15:58.412"), secs);
+        assert_eq!(parse("yesterday"), None);
+    }
+}
@@ -0,0 +1,163 @@
+//! Emitted message type mapping.
+//!
+//! Sources publish fixed types (`http.request`, `exec.output`, ...). These
+//! flags let a deployment rename them without code changes:
+//!
+//! - `--emit-type-map http.request=billing.webhook` renames one type;
+//!   `default=...` renames every type without its own entry.
+//! - `--emit-type-template "{source}.{path_segment}"` builds the type from
+//!   per-message variables. `{type}` is the (mapped) built-in type; the other
+//!   variables depend on the source. A message whose variables are empty
+//!   falls back to the mapped type.
+
+use crate::template;
+use clap::Args;
+
+/// Map key matching every type without an explicit entry.
+const DEFAULT_KEY: &str = "default";
+
+/// CLI flags controlling the message types a source emits.
+#[derive(Args, Debug, Clone, Default)]
+pub struct TopicArgs {
+    /// Rename emitted types as `from=to` (`from` may be `default`); repeatable or comma-separated.
+    #[arg(long, env = "EMERGENT_EMIT_TYPE_MAP", value_delimiter = ',', value_parser = parse_mapping)]
+    pub emit_type_map: Vec<(String, String)>,
+
+    /// Build emitted types from a template such as `{source}.{path_segment}`.
+    #[arg(long, env = "EMERGENT_EMIT_TYPE_TEMPLATE")]
+    pub emit_type_template: Option<String>,
+}
+
+impl TopicArgs {
+    /// The type to emit instead of `builtin`, given the message's template
+    /// variables.
+    pub fn emit_type(&self, builtin: &str, vars: &[(&str, &str)]) -> String {
+        let mapped = self
+            .lookup(builtin)
+            .or_else(|| self.lookup(DEFAULT_KEY))
+            .unwrap_or(builtin);
+        let Some(template) = &self.emit_type_template else {
+            return mapped.to_string();
+        };
+        let mut all = vec![("type", mapped)];
+        all.extend_from_slice(vars);
+        render(template, &all).unwrap_or_else(|_| mapped.to_string())
+    }
+
+    /// Check that the template only uses `{type}` and the source's
+    /// `variables`, so typos fail at startup rather than per message.
+    pub fn validate(&self, variables: &[&str]) -> Result<(), String> {
+        let Some(template) = &self.emit_type_template else {
+            return Ok(());
+        };
+        for name in template::placeholders(template)? {
+            if name != "type" && !variables.contains(&name) {
+                return Err(format!(
+                    "unknown placeholder '{{{name}}}' in --emit-type-template (available: type, {})",
+                    variables.join(", ")
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    fn lookup(&self, from: &str) -> Option<&str> {
+        self.emit_type_map
+            .iter()
+            .find(|(key, _)| key == from)
+            .map(|(_, to)| to.as_str())
+    }
+}
+
+/// Parse one `from=to` mapping.
+fn parse_mapping(s: &str) -> Result<(String, String), String> {
+    match s.split_once('=') {
+        Some((from, to)) if !from.trim().is_empty() && !to.trim().is_empty() => {
+            Ok((from.trim().to_string(), to.trim().to_string()))
+        }
+        _ => Err(format!("expected from=to, got '{s}'")),
+    }
+}
+
+/// Substitute `vars` into `template`. Fails if a placeholder is unknown or
+/// its value is empty.
+fn render(template: &str, vars: &[(&str, &str)]) -> Result<String, String> {
+    template::try_substitute(template, |name| {
+        vars.iter()
+            .find(|(key, _)| *key == name)
+            .map(|(_, value)| value.to_string())
+            .filter(|value| !value.is_empty())
+            .ok_or_else(|| format!("no value for '{{{name}}}'"))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mapping(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs
+            .iter()
+            .map(|(from, to)| (from.to_string(), to.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn explicit_entries_win_over_default() {
+        let args = TopicArgs {
+            emit_type_map: mapping(&[("default", "ops.event"), ("exec.exit", "job.finished")]),
+            emit_type_template: None,
+        };
+        assert_eq!(args.emit_type("exec.exit", &[]), "job.finished");
+        assert_eq!(args.emit_type("exec.output", &[]), "ops.event");
+    }
+
+    #[test]
+    fn unmapped_types_pass_through() {
+        let args = TopicArgs::default();
+        assert_eq!(args.emit_type("http.request", &[]), "http.request");
+    }
+
+    #[test]
+    fn template_uses_variables_and_mapped_type() {
+        let args = TopicArgs {
+            emit_type_map: mapping(&[("http.request", "webhook")]),
+            emit_type_template: Some("{source}.{path_segment}.{type}".to_string()),
+        };
+        let vars = [("source", "billing"), ("path_segment", "stripe")];
+        assert_eq!(
+            args.emit_type("http.request", &vars),
+            "billing.stripe.webhook"
+        );
+    }
+
+    #[test]
+    fn empty_variables_fall_back_to_mapped_type() {
+        let args = TopicArgs {
+            emit_type_map: Vec::new(),
+            emit_type_template: Some("{source}.{path_segment}".to_string()),
+        };
+        let vars = [("source", "billing"), ("path_segment", "")];
+        assert_eq!(args.emit_type("http.request", &vars), "http.request");
+    }
+
+    #[test]
+    fn validate_rejects_unknown_placeholders() {
+        let args = TopicArgs {
+            emit_type_map: Vec::new(),
+            emit_type_template: Some("{source}.{segment}".to_string()),
+        };
+        assert!(args.validate(&["source", "path_segment"]).is_err());
+        assert!(args.validate(&["source", "segment"]).is_ok());
+    }
+
+    #[test]
+    fn mappings_require_both_sides() {
+        assert_eq!(
+            parse_mapping("default = billing.webhook"),
+            Ok(("default".to_string(), "billing.webhook".to_string()))
+        );
+        assert!(parse_mapping("billing.webhook").is_err());
+        assert!(parse_mapping("=x").is_err());
+    }
+}
@@ -0,0 +1,454 @@
+//! Print Sink - Print Receipts and Labels from Events
+//!
+//! A Sink that prints, for each event, a document rendered from a
+//! template (see [`template`]): a kitchen ticket when an order is placed,
+//! a shipping label when a shipment is booked. Templates are plain text,
+//! ESC/POS for receipt printers or ZPL for label printers.
+//!
+//! Printers are named with `--printer NAME=URI` and reached through CUPS,
+//! a raw TCP socket or a device file (see [`printer`]). `--route
+//! PATTERN=PRINTER` picks the printer for each event type, and
+//! `--template PATTERN=FILE` its template; patterns may use `*`, and the
+//! first match wins. With a single printer, routes are optional.
+//!
+//! Every job is reported as `print.completed` or `print.failed`.
+//!
+//! # Examples
+//!
+//! ```bash
+//! print-sink -s order.placed -s shipment.booked \
+//!   --printer kitchen=cups:Kitchen_TM20 --printer labels=tcp://10.0.0.40:9100 \
+//!   --route 'order.*=kitchen' --route 'shipment.*=labels' \
+//!   --template 'order.*=templates/ticket.escpos' \
+//!   --template 'shipment.*=templates/label.zpl'
+//! ```
+
+pub mod printer;
+pub mod template;
+
+use clap::Parser;
+use emergent_client::EmergentMessage;
+use primitive_common::capabilities::VERSION;
+use primitive_common::doctor::Report;
+use primitive_common::errors::{ErrorCategory, HandlerError};
+use primitive_common::glob;
+use primitive_common::time;
+use primitive_common::{Publisher, SinkArgs, SinkConfig, SinkContext, SinkHandler, run_sink};
+use printer::Printer;
+use serde_json::{Value, json};
+use std::path::Path;
+use std::sync::OnceLock;
+use std::time::Duration;
+use template::{Template, Vars};
+
+pub const COMPLETED_EVENT_TYPE: &str = "print.completed";
+pub const FAILED_EVENT_TYPE: &str = "print.failed";
+
+/// Print Sink — print receipts and labels from events.
+#[derive(Parser, Debug)]
+#[command(name = "print_sink", version = VERSION)]
+#[command(
+    about = "Print templated text, ESC/POS receipts or ZPL labels to CUPS or network printers"
+)]
+struct Args {
+    /// Message types to subscribe to.
+    #[arg(short, long = "subscribe", required = true)]
+    subscribe: Vec<String>,
+
+    /// A printer, as NAME=URI (cups:QUEUE, tcp://HOST:PORT or file:PATH).
+    #[arg(
+        long = "printer",
+        env = "PRINT_SINK_PRINTERS",
+        value_delimiter = ',',
+        required = true
+    )]
+    printers: Vec<String>,
+
+    /// The printer for matching message types, as PATTERN=PRINTER.
+    #[arg(long = "route", env = "PRINT_SINK_ROUTES", value_delimiter = ',')]
+    routes: Vec<String>,
+
+    /// The template for matching message types, as PATTERN=FILE.
+    #[arg(long = "template", env = "PRINT_SINK_TEMPLATES", value_delimiter = ',')]
+    templates: Vec<String>,
+
+    /// Command submitting jobs to CUPS.
+    #[arg(long, env = "PRINT_SINK_LP_COMMAND", default_value = "lp")]
+    lp_command: String,
+
+    /// Command checking CUPS queues in the self-test.
+    #[arg(long, env = "PRINT_SINK_LPSTAT_COMMAND", default_value = "lpstat")]
+    lpstat_command: String,
+
+    /// Timeout for each job in milliseconds.
+    #[arg(long, env = "PRINT_SINK_TIMEOUT", default_value = "30000")]
+    timeout: u64,
+
+    #[command(flatten)]
+    sink: SinkArgs,
+}
+
+/// Split a `PATTERN=VALUE` rule.
+fn parse_rule(rule: &str) -> Result<(String, String), String> {
+    match rule.split_once('=') {
+        Some((pattern, value)) if !pattern.trim().is_empty() && !value.trim().is_empty() => {
+            Ok((pattern.trim().to_string(), value.trim().to_string()))
+        }
+        _ => Err(format!("expected PATTERN=VALUE, got '{rule}'")),
+    }
+}
+
+fn first<'a, T>(rules: &'a [(String, T)], message_type: &str) -> Option<&'a T> {
+    rules
+        .iter()
+        .find(|(pattern, _)| glob::matches(pattern, message_type))
+        .map(|(_, value)| value)
+}
+
+/// Prints a job per event.
+struct PrintSink {
+    printers: Vec<Printer>,
+    /// Patterns and the index of their printer.
+    routes: Vec<(String, usize)>,
+    templates: Vec<(String, Template)>,
+    fallback: Template,
+    lp: String,
+    lpstat: String,
+    timeout: Duration,
+    publisher: OnceLock<Publisher>,
+}
+
+impl PrintSink {
+    fn printer(&self, message_type: &str) -> Option<&Printer> {
+        match first(&self.routes, message_type) {
+            Some(&index) => self.printers.get(index),
+            None if self.routes.is_empty() && self.printers.len() == 1 => self.printers.first(),
+            None => None,
+        }
+    }
+
+    fn publish(
+        &self,
+        message_type: &str,
+        msg: &EmergentMessage,
+        message_id: &str,
+        mut payload: Value,
+    ) {
+        let Some(publisher) = self.publisher.get() else {
+            return;
+        };
+        payload["message_id"] = Value::from(message_id);
+        payload["message_type"] = Value::from(msg.message_type.as_str());
+        let event = EmergentMessage::new(message_type)
+            .with_causation_id(msg.id())
+            .with_payload(payload);
+        if let Err(e) = publisher.publish(event) {
+            eprintln!("Failed to publish {message_type}: {e}");
+        }
+    }
+}
+
+impl SinkHandler for PrintSink {
+    async fn handle(
+        &self,
+        msg: &EmergentMessage,
+        ctx: &SinkContext<'_>,
+    ) -> Result<(), HandlerError> {
+        let message_type = msg.message_type.as_str();
+        let printer = self.printer(message_type).ok_or_else(|| {
+            HandlerError::new(
+                ErrorCategory::Rejected,
+                format!("no printer routed for {message_type}"),
+            )
+        })?;
+        let template = first(&self.templates, message_type).unwrap_or(&self.fallback);
+        let id = ctx.message_id().to_string();
+        let vars = Vars {
+            message_type,
+            id: &id,
+            time: time::now(),
+            payload: ctx.payload(),
+        };
+        let data = template.render(&vars);
+
+        if ctx.is_dry_run() {
+            let detail = json!({
+                "printer": printer.name,
+                "format": template.format.as_str(),
+                "bytes": data.len(),
+            });
+            ctx.would_have("print", detail).await;
+            return Ok(());
+        }
+
+        let title = format!("{message_type} {id}");
+        match printer
+            .print(&data, template.format, &title, &self.lp, self.timeout)
+            .await
+        {
+            Ok(job) => {
+                self.publish(
+                    COMPLETED_EVENT_TYPE,
+                    msg,
+                    ctx.message_id(),
+                    json!({
+                        "printer": printer.name,
+                        "job": job.id,
+                        "format": template.format.as_str(),
+                        "bytes": job.bytes,
+                    }),
+                );
+                Ok(())
+            }
+            Err(error) => {
+                self.publish(
+                    FAILED_EVENT_TYPE,
+                    msg,
+                    ctx.message_id(),
+                    json!({"printer": printer.name, "error": error}),
+                );
+                Err(HandlerError::new(ErrorCategory::Request, error))
+            }
+        }
+    }
+
+    fn self_test(&self, report: &mut Report) {
+        for printer in &self.printers {
+            let check = printer.check(&self.lpstat, self.timeout);
+            report.check(&format!("printer {}", printer.name), check);
+        }
+    }
+
+    fn publishes(&self) -> &'static [&'static str] {
+        &[COMPLETED_EVENT_TYPE, FAILED_EVENT_TYPE]
+    }
+
+    fn attach(&self, publisher: Publisher) {
+        let _ = self.publisher.set(publisher);
+    }
+}
+
+/// Run the primitive with `args` as its command line (the first item is
+/// the program name, as with `std::env::args_os`).
+pub async fn run<I, T>(args: I) -> Result<(), Box<dyn std::error::Error>>
+where
+    I: IntoIterator<Item = T>,
+    T: Into<std::ffi::OsString> + Clone,
+{
+    let args = Args::parse_from(args);
+
+    let exit = |e: String| -> ! {
+        eprintln!("Error: {e}");
+        std::process::exit(1);
+    };
+    let printers: Vec<Printer> = args
+        .printers
+        .iter()
+        .map(|spec| Printer::parse(spec))
+        .collect::<Result<_, _>>()
+        .unwrap_or_else(|e| exit(format!("--printer: {e}")));
+    let routes = args
+        .routes
+        .iter()
+        .map(|rule| {
+            let (pattern, name) = parse_rule(rule)?;
+            let index = printers
+                .iter()
+                .position(|printer| printer.name == name)
+                .ok_or_else(|| format!("no printer named '{name}'"))?;
+            Ok((pattern, index))
+        })
+        .collect::<Result<_, String>>()
+        .unwrap_or_else(|e| exit(format!("--route: {e}")));
+    let templates = args
+        .templates
+        .iter()
+        .map(|rule| {
+            let (pattern, file) = parse_rule(rule)?;
+            Ok((pattern, Template::load(Path::new(&file))?))
+        })
+        .collect::<Result<_, String>>()
+        .unwrap_or_else(|e| exit(format!("--template: {e}")));
+
+    let config = SinkConfig {
+        name: "print_sink",
+        subscribe: &args.subscribe,
+        would_have_as: "print.would_have",
+        dead_letter_as: "print.dead_letter",
+        settings: &args,
+    };
+    let handler = PrintSink {
+        printers,
+        routes,
+        templates,
+        fallback: Template::default(),
+        lp: args.lp_command.clone(),
+        lpstat: args.lpstat_command.clone(),
+        timeout: Duration::from_millis(args.timeout),
+        publisher: OnceLock::new(),
+    };
+
+    run_sink(config, &args.sink, handler).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use emergent_testkit::fixtures::TempDir;
+    use emergent_testkit::{SinkFixture, fixtures, spawn_sink};
+    use template::Format;
+    use tokio::io::AsyncReadExt;
+
+    fn handler(printers: &[&str], routes: &[(&str, usize)], lp: &str) -> PrintSink {
+        PrintSink {
+            printers: printers
+                .iter()
+                .map(|spec| Printer::parse(spec).unwrap_or_else(|e| panic!("{e}")))
+                .collect(),
+            routes: routes
+                .iter()
+                .map(|(pattern, index)| (pattern.to_string(), *index))
+                .collect(),
+            templates: vec![(
+                "order.*".to_string(),
+                Template {
+                    text: "[bold]Table {table}[/bold]\n".to_string(),
+                    format: Format::EscPos,
+                },
+            )],
+            fallback: Template::default(),
+            lp: lp.to_string(),
+            lpstat: "lpstat".to_string(),
+            timeout: Duration::from_secs(5),
+            publisher: OnceLock::new(),
+        }
+    }
+
+    #[tokio::test]
+    async fn jobs_go_to_the_routed_printer() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap_or_else(|e| panic!("bind: {e}"));
+        let addr = listener
+            .local_addr()
+            .unwrap_or_else(|e| panic!("local_addr: {e}"));
+        let received = tokio::spawn(async move {
+            let (mut socket, _) = listener
+                .accept()
+                .await
+                .unwrap_or_else(|e| panic!("accept: {e}"));
+            let mut data = Vec::new();
+            socket
+                .read_to_end(&mut data)
+                .await
+                .unwrap_or_else(|e| panic!("read: {e}"));
+            data
+        });
+
+        let kitchen = format!("kitchen=tcp://{addr}");
+        let (mut engine, run) = spawn_sink(
+            SinkFixture::new("print_sink", "print"),
+            SinkArgs::default(),
+            handler(
+                &[&kitchen, "labels=file:/nonexistent/lp0"],
+                &[("order.*", 0), ("shipment.*", 1)],
+                "lp",
+            ),
+        );
+
+        engine
+            .inject_message(fixtures::message("order.placed", json!({"table": 4})))
+            .await;
+        let completed = engine.expect_published(COMPLETED_EVENT_TYPE).await;
+        assert_eq!(completed.payload()["printer"], "kitchen");
+        assert_eq!(completed.payload()["format"], "escpos");
+        assert_eq!(completed.payload()["message_type"], "order.placed");
+        let data = received.await.unwrap_or_else(|e| panic!("{e}"));
+        assert_eq!(completed.payload()["bytes"], data.len());
+        assert!(data.starts_with(b"\x1b@\x1bE\x01Table 4\x1bE\x00\n"));
+
+        engine
+            .inject_message(fixtures::message("shipment.booked", json!({})))
+            .await;
+        let failed = engine.expect_published(FAILED_EVENT_TYPE).await;
+        assert_eq!(failed.payload()["printer"], "labels");
+        assert!(
+            failed.payload()["error"]
+                .as_str()
+                .is_some_and(|error| error.starts_with("/nonexistent/lp0: "))
+        );
+
+        engine.close();
+        assert_eq!(run.await.ok(), Some(Ok(())));
+    }
+
+    #[tokio::test]
+    async fn cups_jobs_go_through_lp() {
+        let dir = TempDir::new("print-sink-lp");
+        let spool = dir.path().join("spool");
+        let lp = dir.path().join("lp");
+        std::fs::write(
+            &lp,
+            format!(
+                "#!/bin/sh\necho \"$@\" > {spool}.args\ncat > {spool}\necho 'request id is Kitchen-42 (1 file(s))'\n",
+                spool = spool.display()
+            ),
+        )
+        .unwrap_or_else(|e| panic!("{e}"));
+        std::process::Command::new("chmod")
+            .arg("+x")
+            .arg(&lp)
+            .status()
+            .unwrap_or_else(|e| panic!("chmod: {e}"));
+
+        let (mut engine, run) = spawn_sink(
+            SinkFixture::new("print_sink", "print"),
+            SinkArgs::default(),
+            handler(&["kitchen=cups:Kitchen"], &[], &lp.display().to_string()),
+        );
+        let order = fixtures::message("order.placed", json!({"table": 7}));
+        let id = order.id().to_string();
+        engine.inject_message(order).await;
+        let completed = engine.expect_published(COMPLETED_EVENT_TYPE).await;
+        assert_eq!(completed.payload()["job"], "Kitchen-42");
+
+        let args = std::fs::read_to_string(dir.path().join("spool.args"))
+            .unwrap_or_else(|e| panic!("{e}"));
+        assert_eq!(
+            args.trim(),
+            format!("-d Kitchen -t order.placed {id} -o raw")
+        );
+        let data = std::fs::read(&spool).unwrap_or_else(|e| panic!("{e}"));
+        assert!(data.starts_with(b"\x1b@\x1bE\x01Table 7"));
+
+        engine.close();
+        assert_eq!(run.await.ok(), Some(Ok(())));
+    }
+
+    #[tokio::test]
+    async fn dry_run_reports_the_job() {
+        let args = SinkArgs {
+            dry_run: true,
+            ..Default::default()
+        };
+        let (mut engine, run) = spawn_sink(
+            SinkFixture::new("print_sink", "print"),
+            args,
+            handler(
+                &["kitchen=cups:Kitchen", "labels=tcp://127.0.0.1:9"],
+                &[("shipment.*", 1)],
+                "lp",
+            ),
+        );
+        engine
+            .inject_message(fixtures::message("shipment.booked", json!({"id": 3})))
+            .await;
+        let report = engine.expect_published("print.would_have").await;
+        let detail = &report.payload()["detail"];
+        assert_eq!(detail["printer"], "labels");
+        assert_eq!(detail["format"], "text");
+
+        engine.close();
+        assert_eq!(run.await.ok(), Some(Ok(())));
+    }
+}
@@ -0,0 +1,260 @@
+//! Sending jobs to printers.
+//!
+//! A printer is `NAME=URI`, where the URI is one of:
+//!
+//! - `cups:QUEUE` — submitted with `lp -d QUEUE`; raw formats (ESC/POS,
+//!   ZPL) add `-o raw` so CUPS passes the bytes through untouched
+//! - `tcp://HOST:PORT` — written to a raw socket, as printers listening on
+//!   port 9100 (JetDirect/AppSocket) expect
+//! - `file:PATH` — written to a device or file, like `/dev/usb/lp0`
+//!
+//! Each printer prints one job at a time, so jobs for the same printer
+//! never interleave.
+
+use crate::template::Format;
+use std::net::ToSocketAddrs;
+use std::path::PathBuf;
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpStream;
+use tokio::process::Command;
+use tokio::sync::Mutex;
+
+/// Where a printer's jobs go.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Destination {
+    Cups(String),
+    Tcp(String),
+    File(PathBuf),
+}
+
+impl Destination {
+    pub fn parse(uri: &str) -> Result<Self, String> {
+        if let Some(queue) = uri.strip_prefix("cups:") {
+            let queue = queue.trim_start_matches('/');
+            if queue.is_empty() {
+                return Err(format!("'{uri}': no CUPS queue"));
+            }
+            Ok(Self::Cups(queue.to_string()))
+        } else if let Some(address) = uri.strip_prefix("tcp://") {
+            let address = address.trim_end_matches('/');
+            if address.is_empty() {
+                return Err(format!("'{uri}': no host"));
+            }
+            // The raw printing port, unless one is given
+            if address.rsplit_once(':').is_some_and(|(_, port)| {
+                !port.is_empty() && port.bytes().all(|b| b.is_ascii_digit())
+            }) {
+                Ok(Self::Tcp(address.to_string()))
+            } else {
+                Ok(Self::Tcp(format!("{address}:9100")))
+            }
+        } else if let Some(path) = uri.strip_prefix("file:") {
+            if path.is_empty() {
+                return Err(format!("'{uri}': no path"));
+            }
+            Ok(Self::File(PathBuf::from(path)))
+        } else {
+            Err(format!(
+                "'{uri}': expected cups:QUEUE, tcp://HOST:PORT or file:PATH"
+            ))
+        }
+    }
+}
+
+/// A named printer.
+pub struct Printer {
+    pub name: String,
+    pub destination: Destination,
+    /// Held while a job prints.
+    busy: Mutex<()>,
+}
+
+/// A printed job.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Job {
+    /// The CUPS job id, for CUPS printers.
+    pub id: Option<String>,
+    pub bytes: usize,
+}
+
+/// The CUPS job id in `lp`'s output, like `request id is kitchen-42 (1 file(s))`.
+pub fn job_id(output: &str) -> Option<String> {
+    let rest = output.split("request id is ").nth(1)?;
+    let id = rest.split_whitespace().next()?;
+    Some(id.to_string())
+}
+
+impl Printer {
+    /// Parse `NAME=URI`.
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let (name, uri) = spec
+            .split_once('=')
+            .filter(|(name, _)| !name.trim().is_empty())
+            .ok_or_else(|| format!("expected NAME=URI, got '{spec}'"))?;
+        Ok(Self {
+            name: name.trim().to_string(),
+            destination: Destination::parse(uri.trim())?,
+            busy: Mutex::new(()),
+        })
+    }
+
+    /// Send `data` to the printer, giving up after `timeout`.
+    pub async fn print(
+        &self,
+        data: &[u8],
+        format: Format,
+        title: &str,
+        lp: &str,
+        timeout: Duration,
+    ) -> Result<Job, String> {
+        let _busy = self.busy.lock().await;
+        let job = async {
+            match &self.destination {
+                Destination::Cups(queue) => submit(lp, queue, data, format, title).await,
+                Destination::Tcp(address) => {
+                    let mut stream = TcpStream::connect(address)
+                        .await
+                        .map_err(|e| format!("{address}: {e}"))?;
+                    stream
+                        .write_all(data)
+                        .await
+                        .map_err(|e| format!("{address}: {e}"))?;
+                    stream
+                        .shutdown()
+                        .await
+                        .map_err(|e| format!("{address}: {e}"))?;
+                    Ok(None)
+                }
+                Destination::File(path) => {
+                    let mut file = tokio::fs::OpenOptions::new()
+                        .append(true)
+                        .create(true)
+                        .open(path)
+                        .await
+                        .map_err(|e| format!("{}: {e}", path.display()))?;
+                    file.write_all(data)
+                        .await
+                        .map_err(|e| format!("{}: {e}", path.display()))?;
+                    file.flush()
+                        .await
+                        .map_err(|e| format!("{}: {e}", path.display()))?;
+                    Ok(None)
+                }
+            }
+        };
+        let id = tokio::time::timeout(timeout, job)
+            .await
+            .map_err(|_| format!("{}: timed out after {}ms", self.name, timeout.as_millis()))??;
+        Ok(Job {
+            id,
+            bytes: data.len(),
+        })
+    }
+
+    /// Whether the printer looks reachable, for the self-test.
+    pub fn check(&self, lpstat: &str, timeout: Duration) -> Result<String, String> {
+        match &self.destination {
+            Destination::Cups(queue) => {
+                let output = std::process::Command::new(lpstat)
+                    .args(["-p", queue])
+                    .stdin(Stdio::null())
+                    .output()
+                    .map_err(|e| format!("{lpstat}: {e}"))?;
+                if output.status.success() {
+                    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+                } else {
+                    Err(String::from_utf8_lossy(&output.stderr).trim().to_string())
+                }
+            }
+            Destination::Tcp(address) => address
+                .to_socket_addrs()
+                .map_err(|e| format!("{address}: {e}"))
+                .and_then(|mut addrs| addrs.next().ok_or_else(|| format!("{address}: no address")))
+                .and_then(|addr| {
+                    std::net::TcpStream::connect_timeout(&addr, timeout)
+                        .map(|_| format!("{address} accepts connections"))
+                        .map_err(|e| format!("{address}: {e}"))
+                }),
+            Destination::File(path) => std::fs::OpenOptions::new()
+                .append(true)
+                .open(path)
+                .map(|_| format!("{} is writable", path.display()))
+                .map_err(|e| format!("{}: {e}", path.display())),
+        }
+    }
+}
+
+/// Submit a job to a CUPS queue through `lp`.
+async fn submit(
+    lp: &str,
+    queue: &str,
+    data: &[u8],
+    format: Format,
+    title: &str,
+) -> Result<Option<String>, String> {
+    let mut command = Command::new(lp);
+    command.args(["-d", queue, "-t", title]);
+    if format.is_raw() {
+        command.args(["-o", "raw"]);
+    }
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(|e| format!("{lp}: {e}"))?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin
+            .write_all(data)
+            .await
+            .map_err(|e| format!("{lp}: {e}"))?;
+    }
+    let output = child
+        .wait_with_output()
+        .await
+        .map_err(|e| format!("{lp}: {e}"))?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(format!("{lp} -d {queue}: {}", stderr.trim()));
+    }
+    Ok(job_id(&String::from_utf8_lossy(&output.stdout)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn printers_parse() {
+        let kitchen =
+            Printer::parse("kitchen=cups:Kitchen_Epson").unwrap_or_else(|e| panic!("parse: {e}"));
+        assert_eq!(kitchen.name, "kitchen");
+        assert_eq!(
+            kitchen.destination,
+            Destination::Cups("Kitchen_Epson".to_string())
+        );
+        assert_eq!(
+            Destination::parse("tcp://10.0.0.7"),
+            Ok(Destination::Tcp("10.0.0.7:9100".to_string()))
+        );
+        assert_eq!(
+            Destination::parse("tcp://labels.local:6101/"),
+            Ok(Destination::Tcp("labels.local:6101".to_string()))
+        );
+        assert_eq!(
+            Destination::parse("file:/dev/usb/lp0"),
+            Ok(Destination::File(PathBuf::from("/dev/usb/lp0")))
+        );
+        assert!(Destination::parse("ipp://printer").is_err());
+        assert!(Printer::parse("cups:Kitchen").is_err());
+
+        assert_eq!(
+            job_id("request id is Kitchen_Epson-42 (1 file(s))\n"),
+            Some("Kitchen_Epson-42".to_string())
+        );
+        assert_eq!(job_id(""), None);
+    }
+}
@@ -0,0 +1,236 @@
+//! Print templates: plain text, ESC/POS receipts and ZPL labels.
+//!
+//! A template's format follows its extension: `.zpl` is ZPL for label
+//! printers, `.escpos` (or `.pos`) is ESC/POS for receipt printers, and
+//! anything else is plain text. In all of them `{field}` is replaced with
+//! the payload field at that dotted path, and `{type}`, `{id}`, `{date}`,
+//! `{time}` (UTC) and `{payload}` (indented JSON) are filled in by the
+//! sink. Missing fields become empty.
+//!
+//! ESC/POS templates are text with a few tags turned into printer
+//! commands: `[bold]`/`[/bold]`, `[underline]`/`[/underline]`,
+//! `[double]`/`[/double]` (double width and height), `[left]`,
+//! `[center]`, `[right]`, `[feed N]` and `[cut]`. The printer is reset
+//! first, and the paper fed and cut at the end unless the template cuts.
+//! Receipt printers rarely speak UTF-8, so characters outside ASCII print
+//! as `?`.
+//!
+//! Substituted values cannot add commands: control characters are
+//! dropped from them, tags are only read from the template itself, and
+//! in ZPL the `^` and `~` command prefixes are dropped.
+
+use primitive_common::key;
+use primitive_common::template::substitute;
+use primitive_common::time;
+use serde_json::Value;
+use std::path::Path;
+
+const ESC: u8 = 0x1b;
+const GS: u8 = 0x1d;
+
+/// What a template renders to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Text,
+    EscPos,
+    Zpl,
+}
+
+impl Format {
+    pub fn from_extension(ext: &str) -> Self {
+        match ext.to_ascii_lowercase().as_str() {
+            "zpl" => Self::Zpl,
+            "escpos" | "pos" => Self::EscPos,
+            _ => Self::Text,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Text => "text",
+            Self::EscPos => "escpos",
+            Self::Zpl => "zpl",
+        }
+    }
+
+    /// Whether the output is printer commands rather than a document, so
+    /// CUPS must pass it through untouched.
+    pub fn is_raw(self) -> bool {
+        self != Self::Text
+    }
+}
+
+/// A loaded template.
+#[derive(Debug, Clone)]
+pub struct Template {
+    pub text: String,
+    pub format: Format,
+}
+
+impl Default for Template {
+    /// The type and the payload as JSON, as plain text.
+    fn default() -> Self {
+        Self {
+            text: "{type}\n{time}\n\n{payload}\n".to_string(),
+            format: Format::Text,
+        }
+    }
+}
+
+/// The event a job is printed for.
+pub struct Vars<'a> {
+    pub message_type: &'a str,
+    pub id: &'a str,
+    /// Seconds since the Unix epoch.
+    pub time: i64,
+    pub payload: &'a Value,
+}
+
+impl Vars<'_> {
+    /// The value of `{name}` in a template.
+    pub fn get(&self, name: &str) -> String {
+        match name {
+            "type" => self.message_type.to_string(),
+            "id" => self.id.to_string(),
+            "date" => time::date(self.time),
+            "time" => time::format(self.time),
+            "payload" => serde_json::to_string_pretty(self.payload).unwrap_or_default(),
+            field => key::extract(self.payload, field).unwrap_or_default(),
+        }
+    }
+}
+
+/// `value` without control characters other than newlines and tabs.
+fn clean(value: &str) -> String {
+    value
+        .chars()
+        .filter(|c| !c.is_control() || *c == '\n' || *c == '\t')
+        .collect()
+}
+
+/// ASCII bytes of `text`, with `?` for anything else.
+fn ascii(text: &str, out: &mut Vec<u8>) {
+    out.extend(
+        text.chars()
+            .map(|c| if c.is_ascii() { c as u8 } else { b'?' }),
+    );
+}
+
+/// The bytes of an ESC/POS tag, if `tag` is one.
+fn escpos_command(tag: &str) -> Option<Vec<u8>> {
+    Some(match tag {
+        "bold" => vec![ESC, b'E', 1],
+        "/bold" => vec![ESC, b'E', 0],
+        "underline" => vec![ESC, b'-', 1],
+        "/underline" => vec![ESC, b'-', 0],
+        "double" => vec![GS, b'!', 0x11],
+        "/double" => vec![GS, b'!', 0],
+        "left" => vec![ESC, b'a', 0],
+        "center" => vec![ESC, b'a', 1],
+        "right" => vec![ESC, b'a', 2],
+        // Partial cut after feeding past the cutter
+        "cut" => vec![GS, b'V', 66, 3],
+        tag => {
+            let lines: u8 = tag.strip_prefix("feed ")?.trim().parse().ok()?;
+            vec![ESC, b'd', lines]
+        }
+    })
+}
+
+impl Template {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {e}", path.display()))?;
+        let ext = path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .unwrap_or_default();
+        Ok(Self {
+            text,
+            format: Format::from_extension(ext),
+        })
+    }
+
+    /// The bytes to send to the printer.
+    pub fn render(&self, vars: &Vars) -> Vec<u8> {
+        match self.format {
+            Format::Text => substitute(&self.text, |name| clean(&vars.get(name))).into_bytes(),
+            Format::Zpl => substitute(&self.text, |name| {
+                clean(&vars.get(name)).replace(['^', '~'], "")
+            })
+            .into_bytes(),
+            Format::EscPos => self.render_escpos(vars),
+        }
+    }
+
+    fn render_escpos(&self, vars: &Vars) -> Vec<u8> {
+        let mut out = vec![ESC, b'@'];
+        let mut rest = self.text.as_str();
+        let mut cut = false;
+        let text = |segment: &str, out: &mut Vec<u8>| {
+            ascii(&substitute(segment, |name| clean(&vars.get(name))), out);
+        };
+        while let Some(start) = rest.find('[') {
+            let tag = rest[start + 1..]
+                .find(']')
+                .map(|end| &rest[start + 1..start + 1 + end]);
+            match tag.and_then(|tag| Some((tag, escpos_command(tag)?))) {
+                Some((tag, command)) => {
+                    text(&rest[..start], &mut out);
+                    out.extend(command);
+                    cut |= tag == "cut";
+                    rest = &rest[start + tag.len() + 2..];
+                }
+                None => {
+                    text(&rest[..=start], &mut out);
+                    rest = &rest[start + 1..];
+                }
+            }
+        }
+        text(rest, &mut out);
+        if !cut {
+            out.extend(escpos_command("cut").unwrap_or_default());
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn templates_render_per_format() {
+        let payload = json!({"table": 12, "item": "Crème brûlée\u{1b}@", "sku": "A^FS~JA"});
+        let vars = Vars {
+            message_type: "order.placed",
+            id: "msg_1",
+            time: 1_716_221_758,
+            payload: &payload,
+        };
+
+        let receipt = Template {
+            text: "[center][bold]Table {table}[/bold]\n[left]{item} [x]\n[feed 2]".to_string(),
+            format: Format::EscPos,
+        };
+        let mut expected = vec![ESC, b'@', ESC, b'a', 1, ESC, b'E', 1];
+        expected.extend(b"Table 12");
+        expected.extend([ESC, b'E', 0, b'\n', ESC, b'a', 0]);
+        expected.extend(b"Cr?me br?l?e@ [x]\n");
+        expected.extend([ESC, b'd', 2, GS, b'V', 66, 3]);
+        assert_eq!(receipt.render(&vars), expected);
+
+        let label = Template {
+            text: "^XA^FO50,50^FD{sku}^FS^XZ".to_string(),
+            format: Format::Zpl,
+        };
+        assert_eq!(label.render(&vars), b"^XA^FO50,50^FDAFSJA^FS^XZ");
+
+        let text = Template::default().render(&vars);
+        assert!(text.starts_with(b"order.placed\n2024-05-20T16:15:58Z\n\n{\n"));
+
+        assert_eq!(Format::from_extension("ZPL"), Format::Zpl);
+        assert_eq!(Format::from_extension("pos"), Format::EscPos);
+        assert_eq!(Format::from_extension("txt"), Format::Text);
+    }
+}
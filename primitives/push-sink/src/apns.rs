@@ -0,0 +1,168 @@
+//! Apple Push Notification service, with token-based authentication.
+//!
+//! Requests carry a JWT signed with the `.p8` key from the Apple Developer
+//! portal (`--apns-key`, with its `--apns-key-id` and `--apns-team-id`).
+//! Apple rejects tokens older than an hour and refreshes more often than
+//! every twenty minutes, so one is reused for [`TOKEN_LIFETIME`].
+
+use crate::jwt;
+use crate::notification::{Failure, Notification};
+use reqwest::Client;
+use ring::rand::SystemRandom;
+use ring::signature::{ECDSA_P256_SHA256_FIXED_SIGNING, EcdsaKeyPair};
+use serde_json::{Value, json};
+use std::fs;
+use std::path::Path;
+use std::sync::{Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+pub const PRODUCTION_URL: &str = "https://api.push.apple.com";
+pub const SANDBOX_URL: &str = "https://api.sandbox.push.apple.com";
+
+/// How long one provider token is used.
+const TOKEN_LIFETIME: Duration = Duration::from_secs(50 * 60);
+
+/// An APNs provider the sink sends as.
+pub struct Apns {
+    key: EcdsaKeyPair,
+    rng: SystemRandom,
+    key_id: String,
+    team_id: String,
+    /// The app's bundle ID.
+    topic: String,
+    url: String,
+    token: Mutex<Option<(String, Instant)>>,
+}
+
+impl Apns {
+    /// Load the `.p8` signing key at `path`.
+    pub fn load(
+        path: &Path,
+        key_id: &str,
+        team_id: &str,
+        topic: &str,
+        url: &str,
+    ) -> Result<Self, String> {
+        let pem = fs::read_to_string(path).map_err(|e| format!("{}: {e}", path.display()))?;
+        Self::from_pem(&pem, key_id, team_id, topic, url)
+            .map_err(|e| format!("{}: {e}", path.display()))
+    }
+
+    pub fn from_pem(
+        pem: &str,
+        key_id: &str,
+        team_id: &str,
+        topic: &str,
+        url: &str,
+    ) -> Result<Self, String> {
+        let der = jwt::pem_to_der(pem)?;
+        let rng = SystemRandom::new();
+        let key = EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &der, &rng)
+            .map_err(|e| format!("not a PKCS#8 P-256 key: {e}"))?;
+        Ok(Self {
+            key,
+            rng,
+            key_id: key_id.to_string(),
+            team_id: team_id.to_string(),
+            topic: topic.to_string(),
+            url: url.trim_end_matches('/').to_string(),
+            token: Mutex::new(None),
+        })
+    }
+
+    /// A current provider token, signing a new one if needed.
+    fn provider_token(&self) -> Result<String, String> {
+        let mut cached = self.token.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some((token, issued)) = cached.as_ref()
+            && issued.elapsed() < TOKEN_LIFETIME
+        {
+            return Ok(token.clone());
+        }
+        let header = json!({"alg": "ES256", "kid": self.key_id});
+        let claims = json!({"iss": self.team_id, "iat": jwt::now()});
+        let token = jwt::encode(&header, &claims, |message| {
+            self.key
+                .sign(&self.rng, message)
+                .map(|signature| signature.as_ref().to_vec())
+                .map_err(|_| "signing failed".to_string())
+        })?;
+        *cached = Some((token.clone(), Instant::now()));
+        Ok(token)
+    }
+
+    /// Send `notification` to the device with `token`.
+    pub async fn send(
+        &self,
+        client: &Client,
+        token: &str,
+        notification: &Notification,
+    ) -> Result<(), Failure> {
+        let provider_token = self.provider_token().map_err(Failure::Other)?;
+        let url = format!("{}/3/device/{token}", self.url);
+        let mut request = client
+            .post(&url)
+            .header("authorization", format!("bearer {provider_token}"))
+            .header("apns-topic", &self.topic)
+            .header("apns-push-type", "alert")
+            .json(&body(notification));
+        if let Some(key) = &notification.collapse_key {
+            request = request.header("apns-collapse-id", key);
+        }
+        let response = request
+            .send()
+            .await
+            .map_err(|e| Failure::Other(format!("{}: {e}", self.url)))?;
+        let status = response.status();
+        if status.is_success() {
+            return Ok(());
+        }
+        let body: Value = response.json().await.unwrap_or_default();
+        Err(classify(status.as_u16(), &body))
+    }
+}
+
+/// The notification's JSON body: the alert under `aps`, custom data
+/// alongside it.
+pub fn body(notification: &Notification) -> Value {
+    let mut body = notification.data.clone();
+    body.insert(
+        "aps".to_string(),
+        json!({"alert": {"title": notification.title, "body": notification.body}}),
+    );
+    Value::Object(body)
+}
+
+/// Tell tokens APNs has dropped from other errors.
+pub fn classify(status: u16, body: &Value) -> Failure {
+    let reason = body["reason"].as_str().unwrap_or_default();
+    let message = format!("{status} {reason}");
+    if status == 410 || matches!(reason, "BadDeviceToken" | "DeviceTokenNotForTopic") {
+        Failure::InvalidToken(message)
+    } else {
+        Failure::Other(message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gone_and_malformed_tokens_are_invalid() {
+        assert_eq!(
+            classify(
+                410,
+                &json!({"reason": "Unregistered", "timestamp": 1700000000000_u64})
+            ),
+            Failure::InvalidToken("410 Unregistered".to_string())
+        );
+        assert_eq!(
+            classify(400, &json!({"reason": "BadDeviceToken"})),
+            Failure::InvalidToken("400 BadDeviceToken".to_string())
+        );
+        assert_eq!(
+            classify(429, &json!({"reason": "TooManyRequests"})),
+            Failure::Other("429 TooManyRequests".to_string())
+        );
+    }
+}
@@ -0,0 +1,240 @@
+//! Firebase Cloud Messaging over the HTTP v1 API.
+//!
+//! The sink authenticates as the service account in `--fcm-credentials`
+//! (the JSON key file downloaded from the Firebase console): a JWT signed
+//! with the account's RSA key is exchanged at its `token_uri` for an OAuth2
+//! access token, which is reused until shortly before it expires.
+
+use crate::jwt;
+use crate::notification::{Failure, Notification};
+use reqwest::Client;
+use ring::rand::SystemRandom;
+use ring::signature::{RSA_PKCS1_SHA256, RsaKeyPair};
+use serde::Deserialize;
+use serde_json::{Map, Value, json};
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+
+const SCOPE: &str = "https://www.googleapis.com/auth/firebase.messaging";
+
+/// Access tokens are renewed this long before they expire.
+const TOKEN_MARGIN: Duration = Duration::from_secs(60);
+
+/// The fields of a service account key file the sink needs.
+#[derive(Deserialize)]
+struct ServiceAccount {
+    project_id: String,
+    client_email: String,
+    private_key: String,
+    #[serde(default = "default_token_uri")]
+    token_uri: String,
+}
+
+fn default_token_uri() -> String {
+    "https://oauth2.googleapis.com/token".to_string()
+}
+
+/// An FCM project the sink sends through.
+pub struct Fcm {
+    project_id: String,
+    client_email: String,
+    token_uri: String,
+    key: RsaKeyPair,
+    rng: SystemRandom,
+    /// API base URL.
+    url: String,
+    token: Mutex<Option<(String, Instant)>>,
+}
+
+impl Fcm {
+    /// Load the service account key file at `path`.
+    pub fn load(path: &Path, url: &str) -> Result<Self, String> {
+        let json = fs::read_to_string(path).map_err(|e| format!("{}: {e}", path.display()))?;
+        Self::from_json(&json, url).map_err(|e| format!("{}: {e}", path.display()))
+    }
+
+    pub fn from_json(json: &str, url: &str) -> Result<Self, String> {
+        let account: ServiceAccount = serde_json::from_str(json).map_err(|e| e.to_string())?;
+        let der = jwt::pem_to_der(&account.private_key)?;
+        let key = RsaKeyPair::from_pkcs8(&der)
+            .map_err(|e| format!("private_key is not a PKCS#8 RSA key: {e}"))?;
+        Ok(Self {
+            project_id: account.project_id,
+            client_email: account.client_email,
+            token_uri: account.token_uri,
+            key,
+            rng: SystemRandom::new(),
+            url: url.trim_end_matches('/').to_string(),
+            token: Mutex::new(None),
+        })
+    }
+
+    fn assertion(&self) -> Result<String, String> {
+        let now = jwt::now();
+        let claims = json!({
+            "iss": self.client_email,
+            "scope": SCOPE,
+            "aud": self.token_uri,
+            "iat": now,
+            "exp": now + 3600,
+        });
+        jwt::encode(&json!({"alg": "RS256", "typ": "JWT"}), &claims, |message| {
+            let mut signature = vec![0; self.key.public().modulus_len()];
+            self.key
+                .sign(&RSA_PKCS1_SHA256, &self.rng, message, &mut signature)
+                .map_err(|_| "signing failed".to_string())?;
+            Ok(signature)
+        })
+    }
+
+    /// A current access token, fetching a new one if needed.
+    async fn access_token(&self, client: &Client) -> Result<String, String> {
+        let mut cached = self.token.lock().await;
+        if let Some((token, expires)) = cached.as_ref()
+            && Instant::now() + TOKEN_MARGIN < *expires
+        {
+            return Ok(token.clone());
+        }
+        let assertion = self.assertion()?;
+        let response: Value = client
+            .post(&self.token_uri)
+            .form(&[
+                ("grant_type", "urn:ietf:params:oauth:grant-type:jwt-bearer"),
+                ("assertion", &assertion),
+            ])
+            .send()
+            .await
+            .and_then(reqwest::Response::error_for_status)
+            .map_err(|e| format!("{}: {e}", self.token_uri))?
+            .json()
+            .await
+            .map_err(|e| format!("{}: {e}", self.token_uri))?;
+        let token = response["access_token"]
+            .as_str()
+            .ok_or_else(|| format!("{}: no access_token in response", self.token_uri))?
+            .to_string();
+        let lifetime = Duration::from_secs(response["expires_in"].as_u64().unwrap_or(3600));
+        *cached = Some((token.clone(), Instant::now() + lifetime));
+        Ok(token)
+    }
+
+    /// Send `notification` to the device with `token`.
+    pub async fn send(
+        &self,
+        client: &Client,
+        token: &str,
+        notification: &Notification,
+    ) -> Result<(), Failure> {
+        let access_token = self.access_token(client).await.map_err(Failure::Other)?;
+        let url = format!("{}/v1/projects/{}/messages:send", self.url, self.project_id);
+        let response = client
+            .post(&url)
+            .bearer_auth(access_token)
+            .json(&message(token, notification))
+            .send()
+            .await
+            .map_err(|e| Failure::Other(format!("{url}: {e}")))?;
+        let status = response.status();
+        if status.is_success() {
+            return Ok(());
+        }
+        let body: Value = response.json().await.unwrap_or_default();
+        Err(classify(status.as_u16(), &body))
+    }
+}
+
+/// The `messages:send` request body.
+pub fn message(token: &str, notification: &Notification) -> Value {
+    // FCM data values must be strings
+    let data: Map<String, Value> = notification
+        .data
+        .iter()
+        .map(|(key, value)| {
+            let value = match value {
+                Value::String(s) => s.clone(),
+                other => other.to_string(),
+            };
+            (key.clone(), Value::String(value))
+        })
+        .collect();
+    let mut message = json!({
+        "token": token,
+        "notification": {"title": notification.title, "body": notification.body},
+    });
+    if !data.is_empty() {
+        message["data"] = Value::Object(data);
+    }
+    if let Some(key) = &notification.collapse_key {
+        message["android"] = json!({"collapse_key": key});
+    }
+    json!({ "message": message })
+}
+
+/// Tell tokens FCM has dropped from other errors.
+pub fn classify(status: u16, body: &Value) -> Failure {
+    let error = &body["error"];
+    let code = error["details"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .find_map(|detail| detail["errorCode"].as_str())
+        .or_else(|| error["status"].as_str())
+        .unwrap_or_default();
+    let reason = match error["message"].as_str() {
+        Some(message) => format!("{status} {code}: {message}"),
+        None => format!("{status} {code}"),
+    };
+    if status == 404 || matches!(code, "UNREGISTERED" | "SENDER_ID_MISMATCH") {
+        Failure::InvalidToken(reason)
+    } else {
+        Failure::Other(reason)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unregistered_tokens_are_invalid() {
+        let unregistered = json!({"error": {
+            "code": 404,
+            "message": "Requested entity was not found.",
+            "status": "NOT_FOUND",
+            "details": [{
+                "@type": "type.googleapis.com/google.firebase.fcm.v1.FcmError",
+                "errorCode": "UNREGISTERED",
+            }],
+        }});
+        assert_eq!(
+            classify(404, &unregistered),
+            Failure::InvalidToken("404 UNREGISTERED: Requested entity was not found.".to_string())
+        );
+        let quota = json!({"error": {"code": 429, "status": "RESOURCE_EXHAUSTED"}});
+        assert_eq!(
+            classify(429, &quota),
+            Failure::Other("429 RESOURCE_EXHAUSTED".to_string())
+        );
+
+        let notification = Notification {
+            title: "Deploy".to_string(),
+            body: "done".to_string(),
+            data: json!({"build": 42})
+                .as_object()
+                .cloned()
+                .unwrap_or_default(),
+            collapse_key: Some("deploy".to_string()),
+        };
+        assert_eq!(
+            message("f00", &notification),
+            json!({"message": {
+                "token": "f00",
+                "notification": {"title": "Deploy", "body": "done"},
+                "data": {"build": "42"},
+                "android": {"collapse_key": "deploy"},
+            }})
+        );
+    }
+}
@@ -0,0 +1,569 @@
+//! Push Sink - Mobile Push Notifications
+//!
+//! Each event is sent as a push notification to the devices of the users
+//! it names, through Firebase Cloud Messaging (FCM HTTP v1, see [`fcm`])
+//! for Android and the Apple Push Notification service (token-based
+//! auth, see [`apns`]) for iOS:
+//!
+//! ```json
+//! {"to": ["alice", "bob"], "title": "Deploy failed", "body": "web1: exit 1", "data": {"run": 42}}
+//! ```
+//!
+//! `to` is a user or list of users from the device registry (see
+//! [`registry`]), which `push.register` messages keep current. Titles,
+//! bodies and collapse keys come from templates over the payload (see
+//! [`notification`]), re-read from `--config` on SIGHUP.
+//!
+//! Each event is summarised in a `push.sent` event. A token the service
+//! reports as unregistered or malformed is dropped from the registry and
+//! published as `push.invalid_token`, so apps and directories can clean up
+//! after it.
+//!
+//! # Examples
+//!
+//! ```bash
+//! push-sink -s alert.raised --tokens-file /var/lib/emergent/push-tokens.json \
+//!   --title "{severity}: {summary}" --body "{description}" \
+//!   --fcm-credentials /etc/emergent/firebase.json \
+//!   --apns-key /etc/emergent/AuthKey_ABC123.p8 --apns-key-id ABC123 \
+//!   --apns-team-id DEF456 --apns-topic com.example.ops
+//! ```
+
+pub mod apns;
+pub mod fcm;
+pub mod jwt;
+pub mod notification;
+pub mod registry;
+
+use apns::Apns;
+use clap::Parser;
+use emergent_client::EmergentMessage;
+use fcm::Fcm;
+use notification::{Failure, Notification};
+use primitive_common::capabilities::VERSION;
+use primitive_common::doctor::Report;
+use primitive_common::errors::{ErrorCategory, HandlerError};
+use primitive_common::reload::HotConfig;
+use primitive_common::{Publisher, SinkArgs, SinkConfig, SinkContext, SinkHandler, run_sink};
+use registry::{Device, Platform, Registry};
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use std::path::PathBuf;
+use std::sync::OnceLock;
+use std::time::Duration;
+
+pub const SENT_EVENT_TYPE: &str = "push.sent";
+pub const INVALID_TOKEN_EVENT_TYPE: &str = "push.invalid_token";
+
+/// Push Sink — send mobile push notifications through FCM and APNs.
+#[derive(Parser, Debug)]
+#[command(name = "push_sink", version = VERSION)]
+#[command(about = "Send push notifications to Android and iOS devices through FCM and APNs")]
+struct Args {
+    /// Message types to subscribe to.
+    #[arg(short, long = "subscribe", required = true)]
+    subscribe: Vec<String>,
+
+    /// Device registry file, updated as devices register and tokens expire.
+    #[arg(long, env = "PUSH_SINK_TOKENS_FILE")]
+    tokens_file: Option<PathBuf>,
+
+    /// Message type carrying device registrations (subscribed to automatically).
+    #[arg(
+        long,
+        env = "PUSH_SINK_REGISTER_TOPIC",
+        default_value = "push.register"
+    )]
+    register_topic: String,
+
+    /// Title template.
+    #[arg(long, env = "PUSH_SINK_TITLE", default_value = "{title}")]
+    title: String,
+
+    /// Body template.
+    #[arg(long, env = "PUSH_SINK_BODY", default_value = "{body}")]
+    body: String,
+
+    /// Collapse key template; notifications sharing a key replace each other.
+    #[arg(long, env = "PUSH_SINK_COLLAPSE_KEY")]
+    collapse_key: Option<String>,
+
+    /// Firebase service account key file (JSON).
+    #[arg(long, env = "PUSH_SINK_FCM_CREDENTIALS")]
+    fcm_credentials: Option<PathBuf>,
+
+    /// FCM API base URL.
+    #[arg(
+        long,
+        env = "PUSH_SINK_FCM_URL",
+        default_value = "https://fcm.googleapis.com"
+    )]
+    fcm_url: String,
+
+    /// APNs signing key (`AuthKey_<key id>.p8`).
+    #[arg(
+        long,
+        env = "PUSH_SINK_APNS_KEY",
+        requires_all = ["apns_key_id", "apns_team_id", "apns_topic"]
+    )]
+    apns_key: Option<PathBuf>,
+
+    /// ID of the APNs signing key.
+    #[arg(long, env = "PUSH_SINK_APNS_KEY_ID")]
+    apns_key_id: Option<String>,
+
+    /// Apple Developer team ID.
+    #[arg(long, env = "PUSH_SINK_APNS_TEAM_ID")]
+    apns_team_id: Option<String>,
+
+    /// The app's bundle ID.
+    #[arg(long, env = "PUSH_SINK_APNS_TOPIC")]
+    apns_topic: Option<String>,
+
+    /// Send to the APNs development environment.
+    #[arg(long, env = "PUSH_SINK_APNS_SANDBOX")]
+    apns_sandbox: bool,
+
+    /// APNs base URL, overriding `--apns-sandbox`.
+    #[arg(long, env = "PUSH_SINK_APNS_URL")]
+    apns_url: Option<String>,
+
+    /// Request timeout in milliseconds.
+    #[arg(short, long, env = "PUSH_SINK_TIMEOUT", default_value = "10000")]
+    timeout: u64,
+
+    #[command(flatten)]
+    sink: SinkArgs,
+}
+
+/// Settings that can be swapped on SIGHUP.
+#[derive(Debug, Serialize, Deserialize)]
+struct Settings {
+    title: String,
+    body: String,
+    collapse_key: Option<String>,
+}
+
+/// Sends notifications and keeps the device registry.
+struct PushSink {
+    settings: HotConfig<Settings>,
+    registry: Registry,
+    register_topic: String,
+    fcm: Option<Fcm>,
+    apns: Option<Apns>,
+    client: Client,
+    publisher: OnceLock<Publisher>,
+}
+
+/// `to` as a list of users.
+fn recipients(to: &Value) -> Vec<String> {
+    match to {
+        Value::String(user) => vec![user.clone()],
+        Value::Array(users) => users
+            .iter()
+            .filter_map(Value::as_str)
+            .map(str::to_string)
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+impl PushSink {
+    fn publish(&self, message_type: &str, payload: Value) {
+        if let Some(publisher) = self.publisher.get() {
+            let message = EmergentMessage::new(message_type).with_payload(payload);
+            if let Err(e) = publisher.publish(message) {
+                eprintln!("Failed to publish {message_type}: {e}");
+            }
+        }
+    }
+
+    async fn register(&self, ctx: &SinkContext<'_>) -> Result<(), HandlerError> {
+        let payload = ctx.payload();
+        let Some(token) = payload["token"].as_str().filter(|t| !t.is_empty()) else {
+            return Err(HandlerError::new(
+                ErrorCategory::Parse,
+                "registration has no 'token'",
+            ));
+        };
+        let unregister = payload["op"].as_str() == Some("unregister");
+        if ctx.is_dry_run() {
+            let action = if unregister { "unregister" } else { "register" };
+            ctx.would_have(action, payload.clone()).await;
+            return Ok(());
+        }
+        let changed = if unregister {
+            self.registry.unregister(token)
+        } else {
+            let Some(user) = payload["user"].as_str() else {
+                return Err(HandlerError::new(
+                    ErrorCategory::Parse,
+                    "registration has no 'user'",
+                ));
+            };
+            let platform = payload["platform"].as_str().unwrap_or_default();
+            let Some(platform) = Platform::parse(platform) else {
+                return Err(HandlerError::new(
+                    ErrorCategory::Parse,
+                    format!("unknown platform '{platform}' (expected fcm or apns)"),
+                ));
+            };
+            let device = Device {
+                platform,
+                token: token.to_string(),
+            };
+            self.registry.register(user, device)
+        };
+        changed
+            .map(|_| ())
+            .map_err(|e| HandlerError::new(ErrorCategory::Internal, e))
+    }
+
+    async fn deliver(&self, device: &Device, notification: &Notification) -> Result<(), Failure> {
+        match device.platform {
+            Platform::Fcm => match &self.fcm {
+                Some(fcm) => fcm.send(&self.client, &device.token, notification).await,
+                None => Err(Failure::Other("no --fcm-credentials".to_string())),
+            },
+            Platform::Apns => match &self.apns {
+                Some(apns) => apns.send(&self.client, &device.token, notification).await,
+                None => Err(Failure::Other("no --apns-key".to_string())),
+            },
+        }
+    }
+}
+
+impl SinkHandler for PushSink {
+    async fn handle(
+        &self,
+        msg: &EmergentMessage,
+        ctx: &SinkContext<'_>,
+    ) -> Result<(), HandlerError> {
+        if msg.message_type.as_str() == self.register_topic {
+            return self.register(ctx).await;
+        }
+
+        let payload = ctx.payload();
+        let users = recipients(&payload["to"]);
+        if users.is_empty() {
+            return Err(HandlerError::new(
+                ErrorCategory::Parse,
+                "payload names no users in 'to'",
+            ));
+        }
+        let devices = self.registry.devices(&users);
+        if devices.is_empty() {
+            return Err(HandlerError::new(
+                ErrorCategory::Rejected,
+                format!("no devices registered for {}", users.join(", ")),
+            ));
+        }
+        let settings = self.settings.current();
+        let notification = Notification::build(
+            payload,
+            &settings.title,
+            &settings.body,
+            settings.collapse_key.as_deref(),
+        );
+
+        if ctx.is_dry_run() {
+            let detail = json!({
+                "to": users,
+                "devices": devices.len(),
+                "title": notification.title,
+                "body": notification.body,
+                "collapse_key": notification.collapse_key,
+            });
+            ctx.would_have("send", detail).await;
+            return Ok(());
+        }
+
+        let mut sent = 0;
+        let mut invalid = 0;
+        let mut errors = Vec::new();
+        for (user, device) in &devices {
+            match self.deliver(device, &notification).await {
+                Ok(()) => sent += 1,
+                Err(Failure::InvalidToken(reason)) => {
+                    invalid += 1;
+                    eprintln!(
+                        "Dropping {} token of {user}: {reason}",
+                        device.platform.as_str()
+                    );
+                    if let Err(e) = self.registry.unregister(&device.token) {
+                        eprintln!("Failed to update the device registry: {e}");
+                    }
+                    self.publish(
+                        INVALID_TOKEN_EVENT_TYPE,
+                        json!({
+                            "user": user,
+                            "platform": device.platform.as_str(),
+                            "token": device.token,
+                            "reason": reason,
+                            "message_id": ctx.message_id(),
+                        }),
+                    );
+                }
+                Err(Failure::Other(reason)) => {
+                    eprintln!(
+                        "Push to a {} device of {user} failed: {reason}",
+                        device.platform.as_str()
+                    );
+                    errors.push(json!({
+                        "user": user,
+                        "platform": device.platform.as_str(),
+                        "error": reason,
+                    }));
+                }
+            }
+        }
+        self.publish(
+            SENT_EVENT_TYPE,
+            json!({
+                "to": users,
+                "sent": sent,
+                "invalid": invalid,
+                "failed": errors.len(),
+                "errors": errors,
+                "message_id": ctx.message_id(),
+                "message_type": msg.message_type.as_str(),
+            }),
+        );
+        // Only retry when nothing got through, or devices would be notified twice
+        if sent == 0 && !errors.is_empty() {
+            let reasons: Vec<&str> = errors
+                .iter()
+                .filter_map(|error| error["error"].as_str())
+                .collect();
+            return Err(HandlerError::new(
+                ErrorCategory::Request,
+                reasons.join("; "),
+            ));
+        }
+        Ok(())
+    }
+
+    fn self_test(&self, report: &mut Report) {
+        if let Some(path) = self.registry.path() {
+            report.check(
+                "tokens-file",
+                Registry::load(Some(path)).map(|_| format!("{} readable", path.display())),
+            );
+        }
+        if self.fcm.is_none() && self.apns.is_none() {
+            report.check(
+                "credentials",
+                Err("neither --fcm-credentials nor --apns-key given".to_string()),
+            );
+        }
+    }
+
+    fn reload(&self) -> Result<Vec<String>, String> {
+        self.settings.reload()
+    }
+
+    fn publishes(&self) -> &'static [&'static str] {
+        &[SENT_EVENT_TYPE, INVALID_TOKEN_EVENT_TYPE]
+    }
+
+    fn attach(&self, publisher: Publisher) {
+        let _ = self.publisher.set(publisher);
+    }
+}
+
+/// Run the primitive with `args` as its command line (the first item is
+/// the program name, as with `std::env::args_os`).
+pub async fn run<I, T>(args: I) -> Result<(), Box<dyn std::error::Error>>
+where
+    I: IntoIterator<Item = T>,
+    T: Into<std::ffi::OsString> + Clone,
+{
+    let mut args = Args::parse_from(args);
+    if !args.subscribe.contains(&args.register_topic) {
+        args.subscribe.push(args.register_topic.clone());
+    }
+
+    let exit = |e: String| -> ! {
+        eprintln!("Error: {e}");
+        std::process::exit(1);
+    };
+    let defaults = Settings {
+        title: args.title.clone(),
+        body: args.body.clone(),
+        collapse_key: args.collapse_key.clone(),
+    };
+    let settings = HotConfig::load(&args.sink.reload, defaults)
+        .unwrap_or_else(|e| exit(format!("invalid config: {e}")));
+    let registry = Registry::load(args.tokens_file.as_deref()).unwrap_or_else(|e| exit(e));
+    let fcm = args
+        .fcm_credentials
+        .as_deref()
+        .map(|path| Fcm::load(path, &args.fcm_url))
+        .transpose()
+        .unwrap_or_else(|e| exit(e));
+    let apns = match &args.apns_key {
+        Some(path) => {
+            let url = args.apns_url.as_deref().unwrap_or(if args.apns_sandbox {
+                apns::SANDBOX_URL
+            } else {
+                apns::PRODUCTION_URL
+            });
+            let apns = Apns::load(
+                path,
+                args.apns_key_id.as_deref().unwrap_or_default(),
+                args.apns_team_id.as_deref().unwrap_or_default(),
+                args.apns_topic.as_deref().unwrap_or_default(),
+                url,
+            );
+            Some(apns.unwrap_or_else(|e| exit(e)))
+        }
+        None => None,
+    };
+
+    let config = SinkConfig {
+        name: "push_sink",
+        subscribe: &args.subscribe,
+        would_have_as: "push.would_have",
+        dead_letter_as: "push.dead_letter",
+        settings: &args,
+    };
+    let handler = PushSink {
+        settings,
+        registry,
+        register_topic: args.register_topic.clone(),
+        fcm,
+        apns,
+        client: Client::builder()
+            .timeout(Duration::from_millis(args.timeout))
+            .build()?,
+        publisher: OnceLock::new(),
+    };
+
+    run_sink(config, &args.sink, handler).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::extract::Path as UrlPath;
+    use axum::http::{HeaderMap, StatusCode};
+    use axum::{Json, Router, routing::post};
+    use emergent_testkit::{SinkFixture, fixtures, spawn_sink};
+    use primitive_common::reload::ReloadArgs;
+    use std::sync::{Arc, Mutex, PoisonError};
+
+    #[tokio::test]
+    async fn expired_tokens_are_reported_and_forgotten() {
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let seen = Arc::clone(&received);
+        let app = Router::new().route(
+            "/3/device/{token}",
+            post(
+                move |UrlPath(token): UrlPath<String>,
+                      headers: HeaderMap,
+                      Json(body): Json<Value>| async move {
+                    let topic = headers.get("apns-topic").cloned();
+                    seen.lock().unwrap_or_else(PoisonError::into_inner).push((
+                        token.clone(),
+                        topic,
+                        body,
+                    ));
+                    if token == "gone" {
+                        (StatusCode::GONE, Json(json!({"reason": "Unregistered"})))
+                    } else {
+                        (StatusCode::OK, Json(json!({})))
+                    }
+                },
+            ),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap_or_else(|e| panic!("bind: {e}"));
+        let addr = listener
+            .local_addr()
+            .unwrap_or_else(|e| panic!("local_addr: {e}"));
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let key = rcgen::KeyPair::generate()
+            .unwrap_or_else(|e| panic!("generate key: {e}"))
+            .serialize_pem();
+        let apns = Apns::from_pem(
+            &key,
+            "ABC123",
+            "DEF456",
+            "com.example.ops",
+            &format!("http://{addr}"),
+        )
+        .unwrap_or_else(|e| panic!("apns: {e}"));
+        let defaults = Settings {
+            title: "{title}".to_string(),
+            body: "{body}".to_string(),
+            collapse_key: None,
+        };
+        let handler = PushSink {
+            settings: HotConfig::load(&ReloadArgs::default(), defaults)
+                .unwrap_or_else(|e| panic!("settings: {e}")),
+            registry: Registry::load(None).unwrap_or_else(|e| panic!("registry: {e}")),
+            register_topic: "push.register".to_string(),
+            fcm: None,
+            apns: Some(apns),
+            client: Client::new(),
+            publisher: OnceLock::new(),
+        };
+        let (mut engine, run) = spawn_sink(
+            SinkFixture::new("push_sink", "push"),
+            SinkArgs::default(),
+            handler,
+        );
+
+        for token in ["phone", "gone"] {
+            engine
+                .inject_message(fixtures::message(
+                    "push.register",
+                    json!({"user": "alice", "platform": "ios", "token": token}),
+                ))
+                .await;
+        }
+        let alert =
+            json!({"to": "alice", "title": "Deploy failed", "body": "web1", "data": {"run": 42}});
+        engine
+            .inject_message(fixtures::message("alert.raised", alert.clone()))
+            .await;
+
+        let invalid = engine.expect_published(INVALID_TOKEN_EVENT_TYPE).await;
+        assert_eq!(invalid.payload()["user"], "alice");
+        assert_eq!(invalid.payload()["token"], "gone");
+        assert_eq!(invalid.payload()["reason"], "410 Unregistered");
+        let sent = engine.expect_published(SENT_EVENT_TYPE).await;
+        assert_eq!(sent.payload()["sent"], 1);
+        assert_eq!(sent.payload()["invalid"], 1);
+
+        // The dropped token is not tried again
+        engine
+            .inject_message(fixtures::message("alert.raised", alert))
+            .await;
+        let sent = engine.expect_published(SENT_EVENT_TYPE).await;
+        assert_eq!(sent.payload()["sent"], 1);
+        assert_eq!(sent.payload()["invalid"], 0);
+
+        let received = received
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone();
+        let tokens: Vec<&str> = received.iter().map(|(token, ..)| token.as_str()).collect();
+        assert_eq!(tokens, ["phone", "gone", "phone"]);
+        let (_, topic, body) = &received[0];
+        assert_eq!(
+            topic.as_ref().and_then(|t| t.to_str().ok()),
+            Some("com.example.ops")
+        );
+        assert_eq!(
+            *body,
+            json!({"aps": {"alert": {"title": "Deploy failed", "body": "web1"}}, "run": 42})
+        );
+
+        engine.close();
+        assert_eq!(run.await.ok(), Some(Ok(())));
+    }
+}
@@ -0,0 +1,80 @@
+//! What is sent, built from the payload and the `--title`, `--body` and
+//! `--collapse-key` templates.
+//!
+//! Templates substitute `{field}` with the payload field at that dotted
+//! path (`{build.branch}`); missing fields become empty. Non-string values
+//! use their JSON encoding.
+
+use primitive_common::template::render;
+use serde_json::{Map, Value};
+
+/// One notification, ready for either service.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Notification {
+    pub title: String,
+    pub body: String,
+    /// Custom key-value data for the app.
+    pub data: Map<String, Value>,
+    /// Notifications sharing a collapse key replace each other.
+    pub collapse_key: Option<String>,
+}
+
+/// Why a delivery failed.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Failure {
+    /// The service no longer accepts the token; it should be forgotten.
+    InvalidToken(String),
+    /// Anything else, worth retrying.
+    Other(String),
+}
+
+impl Failure {
+    pub fn reason(&self) -> &str {
+        match self {
+            Self::InvalidToken(reason) | Self::Other(reason) => reason,
+        }
+    }
+}
+
+impl Notification {
+    /// Build the notification for `payload`. A payload's own
+    /// `collapse_key` wins over the template.
+    pub fn build(payload: &Value, title: &str, body: &str, collapse_key: Option<&str>) -> Self {
+        let collapse_key = payload["collapse_key"]
+            .as_str()
+            .map(str::to_string)
+            .or_else(|| collapse_key.map(|template| render(template, payload)))
+            .filter(|key| !key.is_empty());
+        Self {
+            title: render(title, payload),
+            body: render(body, payload),
+            data: payload["data"].as_object().cloned().unwrap_or_default(),
+            collapse_key,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn templates_read_nested_fields() {
+        let payload = json!({
+            "build": {"branch": "main", "number": 42},
+            "status": "failed",
+            "data": {"url": "https://ci.example.com/42"},
+        });
+        let notification = Notification::build(
+            &payload,
+            "Build {build.number} {status}",
+            "{build.branch}{missing}",
+            Some("build-{build.branch}"),
+        );
+        assert_eq!(notification.title, "Build 42 failed");
+        assert_eq!(notification.body, "main");
+        assert_eq!(notification.collapse_key.as_deref(), Some("build-main"));
+        assert_eq!(notification.data["url"], "https://ci.example.com/42");
+    }
+}
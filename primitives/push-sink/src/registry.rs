@@ -0,0 +1,196 @@
+//! Device tokens, grouped by the user (or any other name) they belong to.
+//!
+//! The registry starts from `--tokens-file` and is kept up to date by
+//! `push.register` messages:
+//!
+//! ```json
+//! {"user": "alice", "platform": "apns", "token": "a1b2..."}
+//! {"op": "unregister", "token": "a1b2..."}
+//! ```
+//!
+//! Tokens the push services report as invalid are dropped. Every change is
+//! written back to the file, which has the same shape as the registry:
+//!
+//! ```json
+//! {"alice": [{"platform": "apns", "token": "a1b2..."}, {"platform": "fcm", "token": "f00..."}]}
+//! ```
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, PoisonError};
+
+/// The service delivering to a device.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Platform {
+    /// Firebase Cloud Messaging, for Android (and web).
+    Fcm,
+    /// Apple Push Notification service.
+    Apns,
+}
+
+impl Platform {
+    pub fn parse(platform: &str) -> Option<Self> {
+        match platform {
+            "fcm" | "android" => Some(Self::Fcm),
+            "apns" | "ios" => Some(Self::Apns),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Fcm => "fcm",
+            Self::Apns => "apns",
+        }
+    }
+}
+
+/// One device.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Device {
+    pub platform: Platform,
+    pub token: String,
+}
+
+type Devices = BTreeMap<String, Vec<Device>>;
+
+/// Device tokens by user, persisted to `--tokens-file` if given.
+pub struct Registry {
+    path: Option<PathBuf>,
+    devices: Mutex<Devices>,
+}
+
+impl Registry {
+    /// Load the registry from `path`; a missing file is an empty registry.
+    pub fn load(path: Option<&Path>) -> Result<Self, String> {
+        let devices = match path {
+            None => Devices::new(),
+            Some(path) => match fs::read_to_string(path) {
+                Ok(text) => {
+                    serde_json::from_str(&text).map_err(|e| format!("{}: {e}", path.display()))?
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => Devices::new(),
+                Err(e) => return Err(format!("{}: {e}", path.display())),
+            },
+        };
+        Ok(Self {
+            path: path.map(Path::to_path_buf),
+            devices: Mutex::new(devices),
+        })
+    }
+
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Devices> {
+        self.devices.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Write the registry back to its file.
+    fn save(&self, devices: &Devices) -> Result<(), String> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let json = serde_json::to_vec_pretty(devices).map_err(|e| e.to_string())?;
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, json)
+            .and_then(|()| fs::rename(&tmp, path))
+            .map_err(|e| format!("{}: {e}", path.display()))
+    }
+
+    /// The devices of each of `users`, with the user they belong to.
+    pub fn devices(&self, users: &[String]) -> Vec<(String, Device)> {
+        let devices = self.lock();
+        users
+            .iter()
+            .flat_map(|user| {
+                devices
+                    .get(user)
+                    .into_iter()
+                    .flatten()
+                    .map(|device| (user.clone(), device.clone()))
+            })
+            .collect()
+    }
+
+    /// Add `device` for `user`, moving it from any other user. Returns
+    /// whether anything changed.
+    pub fn register(&self, user: &str, device: Device) -> Result<bool, String> {
+        let mut devices = self.lock();
+        if devices
+            .get(user)
+            .is_some_and(|known| known.contains(&device))
+        {
+            return Ok(false);
+        }
+        remove(&mut devices, &device.token);
+        devices.entry(user.to_string()).or_default().push(device);
+        self.save(&devices)?;
+        Ok(true)
+    }
+
+    /// Drop `token` wherever it is registered. Returns whether it was.
+    pub fn unregister(&self, token: &str) -> Result<bool, String> {
+        let mut devices = self.lock();
+        if !remove(&mut devices, token) {
+            return Ok(false);
+        }
+        self.save(&devices)?;
+        Ok(true)
+    }
+}
+
+fn remove(devices: &mut Devices, token: &str) -> bool {
+    let mut removed = false;
+    devices.retain(|_, list| {
+        let before = list.len();
+        list.retain(|device| device.token != token);
+        removed |= list.len() != before;
+        !list.is_empty()
+    });
+    removed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use emergent_testkit::fixtures::TempDir;
+
+    #[test]
+    fn registrations_survive_a_reload() {
+        let dir = TempDir::new("push-registry");
+        let path = dir.path().join("tokens.json");
+        let registry = Registry::load(Some(&path)).unwrap_or_else(|e| panic!("{e}"));
+        let phone = Device {
+            platform: Platform::Apns,
+            token: "a1b2".to_string(),
+        };
+        let tablet = Device {
+            platform: Platform::Fcm,
+            token: "f00".to_string(),
+        };
+        assert_eq!(registry.register("alice", phone.clone()), Ok(true));
+        assert_eq!(registry.register("alice", phone.clone()), Ok(false));
+        assert_eq!(registry.register("bob", tablet.clone()), Ok(true));
+        // A handed-down tablet changes owner
+        assert_eq!(registry.register("alice", tablet.clone()), Ok(true));
+
+        let reloaded = Registry::load(Some(&path)).unwrap_or_else(|e| panic!("{e}"));
+        let users = ["alice".to_string(), "bob".to_string()];
+        assert_eq!(
+            reloaded.devices(&users),
+            [
+                ("alice".to_string(), phone),
+                ("alice".to_string(), tablet.clone())
+            ]
+        );
+        assert_eq!(reloaded.unregister("a1b2"), Ok(true));
+        assert_eq!(reloaded.unregister("a1b2"), Ok(false));
+        assert_eq!(reloaded.devices(&users), [("alice".to_string(), tablet)]);
+    }
+}
@@ -0,0 +1,217 @@
+//! Run history in a local SQLite database.
+//!
+//! Every run is a row of the `runs` table, inserted as `running` when it
+//! starts and updated with its outcome, exit code and the tail of its
+//! output when it ends. Runs still `running` when the sink starts were cut
+//! short by a restart and are marked `interrupted`. Only the newest
+//! `keep` runs of each runbook are kept.
+
+use rusqlite::{Connection, OptionalExtension, params};
+use serde_json::Value;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS runs (
+    id           INTEGER PRIMARY KEY AUTOINCREMENT,
+    runbook      TEXT NOT NULL,
+    kind         TEXT NOT NULL,
+    message_id   TEXT NOT NULL,
+    message_type TEXT NOT NULL,
+    params       TEXT NOT NULL,
+    status       TEXT NOT NULL,
+    started_at   INTEGER NOT NULL,
+    finished_at  INTEGER,
+    exit_code    INTEGER,
+    error        TEXT,
+    output       TEXT
+);
+CREATE INDEX IF NOT EXISTS runs_by_runbook ON runs (runbook, id);
+";
+
+/// How a run ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    Succeeded,
+    Failed,
+    TimedOut,
+}
+
+impl Status {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Succeeded => "succeeded",
+            Self::Failed => "failed",
+            Self::TimedOut => "timed_out",
+        }
+    }
+}
+
+/// A run as recorded.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Run {
+    pub id: i64,
+    pub runbook: String,
+    pub status: String,
+    pub exit_code: Option<i32>,
+    pub error: Option<String>,
+    pub output: Option<String>,
+}
+
+/// The run history database.
+pub struct History {
+    db: Mutex<Connection>,
+    keep: usize,
+}
+
+fn now_ms() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| i64::try_from(d.as_millis()).unwrap_or(i64::MAX))
+}
+
+impl History {
+    /// Open (creating if need be) the database at `path`.
+    pub fn open(path: &Path, keep: usize) -> Result<Self, String> {
+        let db = Connection::open(path).map_err(|e| format!("{}: {e}", path.display()))?;
+        Self::init(db, keep).map_err(|e| format!("{}: {e}", path.display()))
+    }
+
+    /// An in-memory database, for tests.
+    pub fn in_memory(keep: usize) -> Result<Self, String> {
+        let db = Connection::open_in_memory().map_err(|e| e.to_string())?;
+        Self::init(db, keep)
+    }
+
+    fn init(db: Connection, keep: usize) -> Result<Self, String> {
+        db.execute_batch(SCHEMA).map_err(|e| e.to_string())?;
+        db.execute(
+            "UPDATE runs SET status = 'interrupted', finished_at = ?1 WHERE status = 'running'",
+            params![now_ms()],
+        )
+        .map_err(|e| e.to_string())?;
+        Ok(Self {
+            db: Mutex::new(db),
+            keep,
+        })
+    }
+
+    fn with<T>(&self, f: impl FnOnce(&Connection) -> rusqlite::Result<T>) -> Result<T, String> {
+        let db = self
+            .db
+            .lock()
+            .map_err(|_| "history lock poisoned".to_string())?;
+        f(&db).map_err(|e| format!("history: {e}"))
+    }
+
+    /// Record the start of a run, returning its id.
+    pub fn start(
+        &self,
+        runbook: &str,
+        kind: &str,
+        message_id: &str,
+        message_type: &str,
+        params: &Value,
+    ) -> Result<i64, String> {
+        self.with(|db| {
+            db.execute(
+                "INSERT INTO runs (runbook, kind, message_id, message_type, params, status, started_at)
+                 VALUES (?1, ?2, ?3, ?4, ?5, 'running', ?6)",
+                params![runbook, kind, message_id, message_type, params.to_string(), now_ms()],
+            )?;
+            Ok(db.last_insert_rowid())
+        })
+    }
+
+    /// Record how run `id` ended, and prune its runbook's oldest runs.
+    pub fn finish(
+        &self,
+        id: i64,
+        status: Status,
+        exit_code: Option<i32>,
+        error: Option<&str>,
+        output: &str,
+    ) -> Result<(), String> {
+        let keep = i64::try_from(self.keep).unwrap_or(i64::MAX);
+        self.with(|db| {
+            db.execute(
+                "UPDATE runs SET status = ?2, finished_at = ?3, exit_code = ?4, error = ?5, output = ?6
+                 WHERE id = ?1",
+                params![id, status.as_str(), now_ms(), exit_code, error, output],
+            )?;
+            db.execute(
+                "DELETE FROM runs WHERE runbook = (SELECT runbook FROM runs WHERE id = ?1)
+                 AND status != 'running'
+                 AND id NOT IN (SELECT id FROM runs WHERE runbook = (SELECT runbook FROM runs WHERE id = ?1)
+                                ORDER BY id DESC LIMIT ?2)",
+                params![id, keep],
+            )?;
+            Ok(())
+        })
+    }
+
+    /// Run `id`, if it is still recorded.
+    pub fn get(&self, id: i64) -> Result<Option<Run>, String> {
+        self.with(|db| {
+            db.query_row(
+                "SELECT id, runbook, status, exit_code, error, output FROM runs WHERE id = ?1",
+                params![id],
+                |row| {
+                    Ok(Run {
+                        id: row.get(0)?,
+                        runbook: row.get(1)?,
+                        status: row.get(2)?,
+                        exit_code: row.get(3)?,
+                        error: row.get(4)?,
+                        output: row.get(5)?,
+                    })
+                },
+            )
+            .optional()
+        })
+    }
+
+    /// The number of runs recorded, for `--self-test`.
+    pub fn count(&self) -> Result<i64, String> {
+        self.with(|db| db.query_row("SELECT COUNT(*) FROM runs", [], |row| row.get(0)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn runs_are_recorded_and_pruned() {
+        let history = History::in_memory(2).unwrap_or_else(|e| panic!("open: {e}"));
+        let ids: Vec<i64> = (0..3)
+            .map(|_| {
+                let id = history
+                    .start(
+                        "rotate",
+                        "script",
+                        "m1",
+                        "disk.full",
+                        &json!({"mount": "/var"}),
+                    )
+                    .unwrap_or_else(|e| panic!("start: {e}"));
+                history
+                    .finish(id, Status::Failed, Some(3), Some("exit code 3"), "no space")
+                    .unwrap_or_else(|e| panic!("finish: {e}"));
+                id
+            })
+            .collect();
+
+        assert_eq!(history.get(ids[0]), Ok(None));
+        let run = history
+            .get(ids[2])
+            .unwrap_or_else(|e| panic!("get: {e}"))
+            .unwrap_or_else(|| panic!("run {} missing", ids[2]));
+        assert_eq!(run.status, "failed");
+        assert_eq!(run.exit_code, Some(3));
+        assert_eq!(run.output.as_deref(), Some("no space"));
+        assert_eq!(history.count(), Ok(2));
+    }
+}
@@ -0,0 +1,715 @@
+//! Runbook Sink - Run Ansible Playbooks, Scripts and Jobs on Events
+//!
+//! A Sink that maps message types to the runbooks in `--config` (see
+//! [`registry`]): ansible playbooks, scripts from a bundle, or jobs
+//! triggered over HTTP, with parameters filled from the payload. Each
+//! runbook allows `concurrency` runs at once; further events wait for a
+//! slot or are rejected, as the runbook says.
+//!
+//! Runs report their progress as `runbook.progress` events, `stage` being
+//! `queued`, `started`, `output` (one per line the process writes, with
+//! the ansible `task` it belongs to) and `finished` (with the `status`,
+//! `exit_code` and `error`). Every run is recorded in a SQLite database
+//! (see [`history`]) whose row id is the events' `run_id`.
+//!
+//! # Examples
+//!
+//! ```bash
+//! runbook-sink -s service.down -s disk.full --config /etc/emergent/runbooks.json \
+//!   --history /var/lib/emergent/runbooks.db --workers 4
+//! ```
+//!
+//! `--workers` bounds the runs in progress across all runbooks.
+
+pub mod history;
+pub mod registry;
+pub mod runner;
+
+use clap::Parser;
+use emergent_client::EmergentMessage;
+use futures::future::join_all;
+use history::{History, Status};
+use primitive_common::capabilities::VERSION;
+use primitive_common::doctor::{Report, check_executable};
+use primitive_common::errors::{ErrorCategory, HandlerError};
+use primitive_common::reload::HotConfig;
+use primitive_common::template::render_strict;
+use primitive_common::{Publisher, SinkArgs, SinkConfig, SinkContext, SinkHandler, run_sink};
+use registry::{Action, Busy, Http, Runbook};
+use reqwest::Client;
+use runner::Process;
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant};
+use tokio::sync::Semaphore;
+
+pub const PROGRESS_EVENT_TYPE: &str = "runbook.progress";
+
+/// Runbook Sink — run ansible playbooks, scripts and jobs on events.
+#[derive(Parser, Debug)]
+#[command(name = "runbook_sink", version = VERSION)]
+#[command(
+    about = "Run ansible playbooks, scripts or HTTP jobs on events, with progress and history"
+)]
+struct Args {
+    /// Message types to subscribe to.
+    #[arg(short, long = "subscribe", required = true)]
+    subscribe: Vec<String>,
+
+    /// SQLite database recording the runs.
+    #[arg(
+        long,
+        env = "RUNBOOK_SINK_HISTORY",
+        default_value = "runbook-history.db"
+    )]
+    history: PathBuf,
+
+    /// Runs kept in the history per runbook.
+    #[arg(long, env = "RUNBOOK_SINK_KEEP", default_value = "1000")]
+    keep: usize,
+
+    /// Per-run timeout in milliseconds, unless the runbook sets its own.
+    #[arg(short, long, env = "RUNBOOK_SINK_TIMEOUT", default_value = "3600000")]
+    timeout: u64,
+
+    /// Output lines per run published as progress events; the rest are
+    /// only counted.
+    #[arg(long, env = "RUNBOOK_SINK_PROGRESS_LINES", default_value = "1000")]
+    progress_lines: usize,
+
+    /// Output lines per run kept in the history.
+    #[arg(long, env = "RUNBOOK_SINK_TAIL", default_value = "100")]
+    tail: usize,
+
+    /// Path or name of ansible-playbook.
+    #[arg(
+        long,
+        env = "RUNBOOK_SINK_ANSIBLE_PLAYBOOK",
+        default_value = "ansible-playbook"
+    )]
+    ansible_playbook: String,
+
+    #[command(flatten)]
+    sink: SinkArgs,
+}
+
+/// Settings that can be swapped on SIGHUP.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Settings {
+    runbooks: BTreeMap<String, Runbook>,
+}
+
+/// What a run does, with its templates filled in.
+enum Plan {
+    Process(Process),
+    Http { http: Http, body: Value },
+}
+
+impl Plan {
+    fn describe(&self) -> Value {
+        match self {
+            Self::Process(process) => json!({"command": process.command_line()}),
+            Self::Http { http, .. } => json!({"method": http.method, "url": http.url}),
+        }
+    }
+}
+
+/// One runbook started by one event.
+struct Job {
+    name: String,
+    runbook: Runbook,
+    kind: &'static str,
+    params: Value,
+    plan: Plan,
+}
+
+/// Fields every progress event of a run carries.
+struct Progress<'a> {
+    run_id: Option<i64>,
+    runbook: &'a str,
+    kind: &'a str,
+    message_id: String,
+    message_type: &'a str,
+}
+
+impl Progress<'_> {
+    fn event(&self, stage: &str, fields: Value) -> Value {
+        let mut payload = json!({
+            "run_id": self.run_id,
+            "runbook": self.runbook,
+            "kind": self.kind,
+            "stage": stage,
+            "message_id": self.message_id,
+            "message_type": self.message_type,
+        });
+        if let (Some(payload), Value::Object(fields)) = (payload.as_object_mut(), fields) {
+            payload.extend(fields);
+        }
+        payload
+    }
+}
+
+/// How a run ended.
+struct Outcome {
+    status: Status,
+    exit_code: Option<i32>,
+    error: Option<HandlerError>,
+    lines: usize,
+    output: String,
+}
+
+/// Runs runbooks.
+struct RunbookSink {
+    settings: HotConfig<Settings>,
+    history: History,
+    /// Each runbook's run slots, with the concurrency they were made for.
+    slots: Mutex<HashMap<String, (usize, Arc<Semaphore>)>>,
+    client: Client,
+    ansible_playbook: String,
+    timeout: Duration,
+    progress_lines: usize,
+    tail: usize,
+    publisher: OnceLock<Publisher>,
+}
+
+impl RunbookSink {
+    fn publish(&self, payload: Value) {
+        if let Some(publisher) = self.publisher.get() {
+            let message = EmergentMessage::new(PROGRESS_EVENT_TYPE).with_payload(payload);
+            if let Err(e) = publisher.publish(message) {
+                eprintln!("Failed to publish {PROGRESS_EVENT_TYPE}: {e}");
+            }
+        }
+    }
+
+    /// The run slots of runbook `name`, remade if its concurrency changed.
+    fn slots(&self, name: &str, concurrency: usize) -> Arc<Semaphore> {
+        let concurrency = concurrency.max(1);
+        let mut slots = self
+            .slots
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        match slots.get(name) {
+            Some((limit, semaphore)) if *limit == concurrency => semaphore.clone(),
+            _ => {
+                let semaphore = Arc::new(Semaphore::new(concurrency));
+                slots.insert(name.to_string(), (concurrency, semaphore.clone()));
+                semaphore
+            }
+        }
+    }
+
+    /// Fill in `runbook`'s templates from `payload`. A placeholder the
+    /// payload lacks is an error, so a missing host never widens a `--limit`.
+    fn plan(&self, name: &str, runbook: &Runbook, payload: &Value) -> Result<Job, HandlerError> {
+        let action = runbook.action().map_err(|e| {
+            HandlerError::new(ErrorCategory::Internal, format!("runbook '{name}' {e}"))
+        })?;
+        let fill = |template: &str| {
+            render_strict(template, payload).map_err(|e| {
+                HandlerError::new(ErrorCategory::Parse, format!("runbook '{name}': {e}"))
+            })
+        };
+        let mut params = BTreeMap::new();
+        for (param, template) in &runbook.params {
+            params.insert(param.clone(), fill(template)?);
+        }
+
+        let plan = match action {
+            Action::Ansible(ansible) => {
+                let mut args = Vec::new();
+                if let Some(inventory) = &ansible.inventory {
+                    args.extend(["--inventory".to_string(), inventory.clone()]);
+                }
+                if let Some(limit) = &ansible.limit {
+                    args.extend(["--limit".to_string(), fill(limit)?]);
+                }
+                if ansible.check {
+                    args.push("--check".to_string());
+                }
+                args.extend(["--extra-vars".to_string(), json!(params).to_string()]);
+                args.extend(ansible.args.iter().cloned());
+                args.push(ansible.playbook.clone());
+                Plan::Process(Process {
+                    program: self.ansible_playbook.clone(),
+                    args,
+                    env: BTreeMap::from([
+                        ("ANSIBLE_NOCOLOR".to_string(), "1".to_string()),
+                        ("PYTHONUNBUFFERED".to_string(), "1".to_string()),
+                    ]),
+                    ..Default::default()
+                })
+            }
+            Action::Script(script) => {
+                let mut command = script.command.iter();
+                let program = command.next().cloned().unwrap_or_default();
+                let args = command.map(|arg| fill(arg)).collect::<Result<_, _>>()?;
+                let env = params
+                    .iter()
+                    .map(|(param, value)| {
+                        let param = param.to_uppercase().replace(['-', '.'], "_");
+                        (format!("RUNBOOK_PARAM_{param}"), value.clone())
+                    })
+                    .chain([("RUNBOOK_NAME".to_string(), name.to_string())])
+                    .collect();
+                Plan::Process(Process {
+                    program,
+                    args,
+                    dir: script.dir.clone(),
+                    env,
+                    stdin: serde_json::to_vec(payload).unwrap_or_default(),
+                })
+            }
+            Action::Http(http) => Plan::Http {
+                http: http.clone(),
+                body: json!({"runbook": name, "params": params}),
+            },
+        };
+        Ok(Job {
+            name: name.to_string(),
+            runbook: runbook.clone(),
+            kind: action.kind(),
+            params: json!(params),
+            plan,
+        })
+    }
+
+    /// Carry out `plan`, reporting progress as it goes.
+    async fn execute(&self, plan: Plan, timeout: Duration, progress: &Progress<'_>) -> Outcome {
+        let mut lines = 0;
+        let mut tail = VecDeque::with_capacity(self.tail);
+        let keep = |line: &str, tail: &mut VecDeque<String>| {
+            if self.tail > 0 {
+                if tail.len() == self.tail {
+                    tail.pop_front();
+                }
+                tail.push_back(line.to_string());
+            }
+        };
+        let (status, exit_code, error) = match plan {
+            Plan::Process(mut process) => {
+                if let Some(run_id) = progress.run_id {
+                    process
+                        .env
+                        .insert("RUNBOOK_RUN_ID".to_string(), run_id.to_string());
+                }
+                let mut task: Option<String> = None;
+                let on_line = |stream: runner::Stream, line: &str| {
+                    if let Some(name) = line
+                        .strip_prefix("TASK [")
+                        .and_then(|rest| rest.split_once(']'))
+                    {
+                        task = Some(name.0.to_string());
+                    }
+                    keep(line, &mut tail);
+                    if lines < self.progress_lines {
+                        self.publish(progress.event(
+                            "output",
+                            json!({"seq": lines, "stream": stream.as_str(), "line": line, "task": task}),
+                        ));
+                    }
+                    lines += 1;
+                };
+                match tokio::time::timeout(timeout, runner::spawn(&process, on_line)).await {
+                    Err(_) => (
+                        Status::TimedOut,
+                        None,
+                        Some(HandlerError::new(
+                            ErrorCategory::Timeout,
+                            format!("{}: timed out", progress.runbook),
+                        )),
+                    ),
+                    Ok(Err(e)) => (
+                        Status::Failed,
+                        None,
+                        Some(HandlerError::new(ErrorCategory::Request, e)),
+                    ),
+                    Ok(Ok(0)) => (Status::Succeeded, Some(0), None),
+                    Ok(Ok(code)) => (
+                        Status::Failed,
+                        Some(code),
+                        Some(HandlerError::new(
+                            ErrorCategory::Rejected,
+                            format!("{}: exit code {code}", progress.runbook),
+                        )),
+                    ),
+                }
+            }
+            Plan::Http { http, mut body } => {
+                body["run_id"] = json!(progress.run_id);
+                let method = reqwest::Method::from_bytes(http.method.to_uppercase().as_bytes())
+                    .unwrap_or(reqwest::Method::POST);
+                let mut request = self
+                    .client
+                    .request(method, &http.url)
+                    .timeout(timeout)
+                    .json(&body);
+                for (name, value) in &http.headers {
+                    request = request.header(name, value);
+                }
+                match request.send().await {
+                    Err(e) if e.is_timeout() => (
+                        Status::TimedOut,
+                        None,
+                        Some(HandlerError::new(
+                            ErrorCategory::Timeout,
+                            format!("{}: timed out", http.url),
+                        )),
+                    ),
+                    Err(e) => (
+                        Status::Failed,
+                        None,
+                        Some(HandlerError::new(
+                            ErrorCategory::Request,
+                            format!("{}: {e}", http.url),
+                        )),
+                    ),
+                    Ok(response) => {
+                        let code = response.status();
+                        let text = response.text().await.unwrap_or_default();
+                        for line in text.lines() {
+                            keep(line, &mut tail);
+                            lines += 1;
+                        }
+                        if code.is_success() {
+                            (Status::Succeeded, None, None)
+                        } else {
+                            (
+                                Status::Failed,
+                                None,
+                                Some(HandlerError::new(
+                                    ErrorCategory::Request,
+                                    format!("{}: HTTP {code}", http.url),
+                                )),
+                            )
+                        }
+                    }
+                }
+            }
+        };
+        Outcome {
+            status,
+            exit_code,
+            error,
+            lines,
+            output: Vec::from(tail).join("\n"),
+        }
+    }
+
+    /// Run `job` once a slot is free, recording it in the history.
+    async fn run_job(
+        &self,
+        msg: &EmergentMessage,
+        message_id: &str,
+        job: Job,
+    ) -> Result<(), HandlerError> {
+        let mut progress = Progress {
+            run_id: None,
+            runbook: &job.name,
+            kind: job.kind,
+            message_id: message_id.to_string(),
+            message_type: msg.message_type.as_str(),
+        };
+        let slots = self.slots(&job.name, job.runbook.concurrency);
+        let _slot = match slots.clone().try_acquire_owned() {
+            Ok(slot) => slot,
+            Err(_) if job.runbook.busy == Busy::Reject => {
+                return Err(HandlerError::new(
+                    ErrorCategory::Rejected,
+                    format!("runbook '{}' is busy", job.name),
+                ));
+            }
+            Err(_) => {
+                self.publish(progress.event("queued", json!({})));
+                slots.acquire_owned().await.map_err(|e| {
+                    HandlerError::new(ErrorCategory::Internal, format!("{}: {e}", job.name))
+                })?
+            }
+        };
+
+        let run_id = self
+            .history
+            .start(
+                &job.name,
+                job.kind,
+                &progress.message_id,
+                progress.message_type,
+                &job.params,
+            )
+            .map_err(|e| HandlerError::new(ErrorCategory::Internal, e))?;
+        progress.run_id = Some(run_id);
+        let detail = job.plan.describe();
+        eprintln!("run {run_id} of {}: started", job.name);
+        self.publish(progress.event("started", json!({"params": job.params, "detail": detail})));
+
+        let started = Instant::now();
+        let timeout = job
+            .runbook
+            .timeout
+            .map_or(self.timeout, Duration::from_millis);
+        let outcome = self.execute(job.plan, timeout, &progress).await;
+        let error = outcome.error.as_ref().map(|e| e.message.clone());
+        if let Err(e) = self.history.finish(
+            run_id,
+            outcome.status,
+            outcome.exit_code,
+            error.as_deref(),
+            &outcome.output,
+        ) {
+            eprintln!("run {run_id} of {}: {e}", job.name);
+        }
+        eprintln!(
+            "run {run_id} of {}: {}",
+            job.name,
+            error.as_deref().unwrap_or(outcome.status.as_str())
+        );
+        self.publish(progress.event(
+            "finished",
+            json!({
+                "status": outcome.status.as_str(),
+                "exit_code": outcome.exit_code,
+                "error": error,
+                "lines": outcome.lines,
+                "duration_ms": u64::try_from(started.elapsed().as_millis()).unwrap_or(u64::MAX),
+            }),
+        ));
+        outcome.error.map_or(Ok(()), Err)
+    }
+}
+
+impl SinkHandler for RunbookSink {
+    async fn handle(
+        &self,
+        msg: &EmergentMessage,
+        ctx: &SinkContext<'_>,
+    ) -> Result<(), HandlerError> {
+        let message_type = msg.message_type.as_str();
+        let settings = self.settings.current();
+        let jobs = settings
+            .runbooks
+            .iter()
+            .filter(|(_, runbook)| runbook.matches(message_type))
+            .map(|(name, runbook)| self.plan(name, runbook, ctx.payload()))
+            .collect::<Result<Vec<_>, _>>()?;
+        if jobs.is_empty() {
+            return Err(HandlerError::new(
+                ErrorCategory::Rejected,
+                format!("no runbook for '{message_type}'"),
+            ));
+        }
+
+        if ctx.is_dry_run() {
+            for job in &jobs {
+                let mut detail = json!({
+                    "runbook": job.name,
+                    "kind": job.kind,
+                    "params": job.params,
+                });
+                if let (Some(detail), Value::Object(plan)) =
+                    (detail.as_object_mut(), job.plan.describe())
+                {
+                    detail.extend(plan);
+                }
+                ctx.would_have("run", detail).await;
+            }
+            return Ok(());
+        }
+
+        let results = join_all(
+            jobs.into_iter()
+                .map(|job| self.run_job(msg, ctx.message_id(), job)),
+        )
+        .await;
+        results.into_iter().collect()
+    }
+
+    fn self_test(&self, report: &mut Report) {
+        let settings = self.settings.current();
+        report.check(
+            "runbooks",
+            Ok(format!("{} configured", settings.runbooks.len())),
+        );
+        let mut ansible = false;
+        for (name, runbook) in &settings.runbooks {
+            match runbook.action() {
+                Ok(Action::Ansible(_)) => ansible = true,
+                Ok(Action::Script(script)) => {
+                    let program = script.command.first().map_or("", String::as_str);
+                    let program = match &script.dir {
+                        Some(dir) if program.starts_with("./") => format!("{dir}/{program}"),
+                        _ => program.to_string(),
+                    };
+                    report.check(name, check_executable(&program));
+                }
+                Ok(Action::Http(http)) => report.check(name, Ok(http.url.clone())),
+                Err(e) => report.check(name, Err(e)),
+            }
+        }
+        if ansible {
+            report.check("ansible-playbook", check_executable(&self.ansible_playbook));
+        }
+        report.check(
+            "history",
+            self.history
+                .count()
+                .map(|runs| format!("{runs} runs recorded")),
+        );
+    }
+
+    fn reload(&self) -> Result<Vec<String>, String> {
+        self.settings.reload()
+    }
+
+    fn publishes(&self) -> &'static [&'static str] {
+        &[PROGRESS_EVENT_TYPE]
+    }
+
+    fn attach(&self, publisher: Publisher) {
+        let _ = self.publisher.set(publisher);
+    }
+}
+
+/// Run the primitive with `args` as its command line (the first item is
+/// the program name, as with `std::env::args_os`).
+pub async fn run<I, T>(args: I) -> Result<(), Box<dyn std::error::Error>>
+where
+    I: IntoIterator<Item = T>,
+    T: Into<std::ffi::OsString> + Clone,
+{
+    let args = Args::parse_from(args);
+
+    let config = SinkConfig {
+        name: "runbook_sink",
+        subscribe: &args.subscribe,
+        would_have_as: "runbook.would_have",
+        dead_letter_as: "runbook.dead_letter",
+        settings: &args,
+    };
+    let settings = match HotConfig::load(&args.sink.reload, Settings::default()) {
+        Ok(settings) => settings,
+        Err(e) => {
+            eprintln!("Error: invalid config: {e}");
+            std::process::exit(1);
+        }
+    };
+    let history = match History::open(&args.history, args.keep) {
+        Ok(history) => history,
+        Err(e) => {
+            eprintln!("Error: cannot open history: {e}");
+            std::process::exit(1);
+        }
+    };
+    let handler = RunbookSink {
+        settings,
+        history,
+        slots: Mutex::new(HashMap::new()),
+        client: Client::new(),
+        ansible_playbook: args.ansible_playbook.clone(),
+        timeout: Duration::from_millis(args.timeout),
+        progress_lines: args.progress_lines,
+        tail: args.tail,
+        publisher: OnceLock::new(),
+    };
+
+    run_sink(config, &args.sink, handler).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use emergent_testkit::{SinkFixture, fixtures, spawn_sink};
+    use primitive_common::reload::ReloadArgs;
+    use registry::Script;
+
+    fn runbook_sink(runbooks: BTreeMap<String, Runbook>) -> RunbookSink {
+        let settings = HotConfig::load(&ReloadArgs::default(), Settings { runbooks })
+            .unwrap_or_else(|e| panic!("load settings: {e}"));
+        RunbookSink {
+            settings,
+            history: History::in_memory(10).unwrap_or_else(|e| panic!("history: {e}")),
+            slots: Mutex::new(HashMap::new()),
+            client: Client::new(),
+            ansible_playbook: "ansible-playbook".to_string(),
+            timeout: Duration::from_secs(5),
+            progress_lines: 10,
+            tail: 10,
+            publisher: OnceLock::new(),
+        }
+    }
+
+    #[tokio::test]
+    async fn scripts_stream_progress_and_are_recorded() {
+        let runbook = Runbook {
+            on: vec!["disk.*".to_string()],
+            ansible: None,
+            script: Some(Script {
+                command: vec![
+                    "sh".to_string(),
+                    "-c".to_string(),
+                    "echo \"rotating $1 on $RUNBOOK_PARAM_HOST\"; [ \"$1\" = /var ]".to_string(),
+                    "rotate".to_string(),
+                    "{mount}".to_string(),
+                ],
+                dir: None,
+            }),
+            http: None,
+            params: BTreeMap::from([("host".to_string(), "{host}".to_string())]),
+            concurrency: 1,
+            busy: Busy::Queue,
+            timeout: None,
+        };
+        let handler = runbook_sink(BTreeMap::from([("rotate-logs".to_string(), runbook)]));
+        let (mut engine, run) = spawn_sink(
+            SinkFixture::new("runbook_sink", "runbook"),
+            SinkArgs {
+                dead_letter: true,
+                ..Default::default()
+            },
+            handler,
+        );
+
+        engine
+            .inject_message(fixtures::message(
+                "disk.full",
+                json!({"host": "web1", "mount": "/var"}),
+            ))
+            .await;
+        let started = engine.expect_published(PROGRESS_EVENT_TYPE).await;
+        assert_eq!(started.payload()["stage"], "started");
+        assert_eq!(started.payload()["runbook"], "rotate-logs");
+        assert_eq!(started.payload()["params"], json!({"host": "web1"}));
+        let run_id = started.payload()["run_id"].clone();
+        let output = engine.expect_published(PROGRESS_EVENT_TYPE).await;
+        assert_eq!(output.payload()["stage"], "output");
+        assert_eq!(output.payload()["line"], "rotating /var on web1");
+        assert_eq!(output.payload()["run_id"], run_id);
+        let finished = engine.expect_published(PROGRESS_EVENT_TYPE).await;
+        assert_eq!(finished.payload()["stage"], "finished");
+        assert_eq!(finished.payload()["status"], "succeeded");
+        assert_eq!(finished.payload()["exit_code"], 0);
+
+        engine
+            .inject_message(fixtures::message(
+                "disk.full",
+                json!({"host": "web2", "mount": "/home"}),
+            ))
+            .await;
+        let dead = engine.expect_published("runbook.dead_letter").await;
+        assert_eq!(dead.payload()["error"], "rotate-logs: exit code 1");
+
+        engine
+            .inject_message(fixtures::message("disk.full", json!({"mount": "/var"})))
+            .await;
+        let dead = engine.expect_published("runbook.dead_letter").await;
+        assert_eq!(
+            dead.payload()["error"],
+            "runbook 'rotate-logs': payload has no 'host'"
+        );
+
+        engine.close();
+        assert_eq!(run.await.ok(), Some(Ok(())));
+    }
+}
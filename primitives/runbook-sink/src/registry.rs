@@ -0,0 +1,196 @@
+//! The runbooks the sink can run, as listed in `--config`.
+//!
+//! ```json
+//! {"runbooks": {
+//!   "restart-web": {"on": ["service.down"], "concurrency": 1,
+//!                   "ansible": {"playbook": "/etc/ansible/restart.yml", "inventory": "/etc/ansible/hosts",
+//!                               "limit": "{host}"},
+//!                   "params": {"service": "{service}"}},
+//!   "rotate-logs": {"on": ["disk.*"], "timeout": 300000,
+//!                   "script": {"command": ["./rotate.sh", "{mount}"], "dir": "/opt/runbooks/logs"}},
+//!   "rebuild":     {"on": ["deploy.failed"],
+//!                   "http": {"url": "https://ci.example.com/job/rebuild/buildWithParameters",
+//!                            "headers": {"Authorization": "Bearer ..."}},
+//!                   "params": {"ref": "{commit}"}}
+//! }}
+//! ```
+//!
+//! Each runbook names the message types that start it (`order.*` matches a
+//! namespace, `*` everything) and exactly one of an ansible playbook, a
+//! script or an HTTP job trigger. `params` are filled from the payload
+//! with `{dotted.path}` placeholders, as are the script's arguments and the
+//! playbook's `limit`; nothing else in the payload reaches the runbook.
+//! The file is re-read on SIGHUP.
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// An ansible playbook run with `ansible-playbook`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Ansible {
+    pub playbook: String,
+    #[serde(default)]
+    pub inventory: Option<String>,
+    /// Host pattern template for `--limit`.
+    #[serde(default)]
+    pub limit: Option<String>,
+    /// Run in check mode, changing nothing.
+    #[serde(default)]
+    pub check: bool,
+    /// Further `ansible-playbook` arguments, used verbatim.
+    #[serde(default)]
+    pub args: Vec<String>,
+}
+
+/// A script or other executable; the payload is piped to its stdin.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Script {
+    /// Program and argument templates.
+    pub command: Vec<String>,
+    /// Working directory, e.g. the bundle the script belongs to.
+    #[serde(default)]
+    pub dir: Option<String>,
+}
+
+/// A job triggered over HTTP, e.g. on a CI server.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Http {
+    pub url: String,
+    #[serde(default = "default_method")]
+    pub method: String,
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+}
+
+fn default_method() -> String {
+    "POST".to_string()
+}
+
+/// What to do when a runbook already has `concurrency` runs going.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Busy {
+    /// Wait for a run to finish.
+    #[default]
+    Queue,
+    /// Fail the event straight away.
+    Reject,
+}
+
+/// One runbook.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Runbook {
+    /// Message type patterns that start the runbook.
+    pub on: Vec<String>,
+    #[serde(default)]
+    pub ansible: Option<Ansible>,
+    #[serde(default)]
+    pub script: Option<Script>,
+    #[serde(default)]
+    pub http: Option<Http>,
+    /// Parameter templates.
+    #[serde(default)]
+    pub params: BTreeMap<String, String>,
+    /// Runs allowed at once.
+    #[serde(default = "default_concurrency")]
+    pub concurrency: usize,
+    #[serde(default)]
+    pub busy: Busy,
+    /// Per-run timeout in milliseconds (default: `--timeout`).
+    #[serde(default)]
+    pub timeout: Option<u64>,
+}
+
+fn default_concurrency() -> usize {
+    1
+}
+
+/// A runbook's action.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action<'a> {
+    Ansible(&'a Ansible),
+    Script(&'a Script),
+    Http(&'a Http),
+}
+
+impl Action<'_> {
+    pub fn kind(self) -> &'static str {
+        match self {
+            Self::Ansible(_) => "ansible",
+            Self::Script(_) => "script",
+            Self::Http(_) => "http",
+        }
+    }
+}
+
+impl Runbook {
+    /// Whether `message_type` starts this runbook.
+    pub fn matches(&self, message_type: &str) -> bool {
+        self.on
+            .iter()
+            .any(|pattern| match pattern.strip_suffix('*') {
+                Some(prefix) => message_type.starts_with(prefix),
+                None => pattern == message_type,
+            })
+    }
+
+    /// The runbook's action, provided it has exactly one.
+    pub fn action(&self) -> Result<Action<'_>, String> {
+        let actions: Vec<Action<'_>> = [
+            self.ansible.as_ref().map(Action::Ansible),
+            self.script.as_ref().map(Action::Script),
+            self.http.as_ref().map(Action::Http),
+        ]
+        .into_iter()
+        .flatten()
+        .collect();
+        match actions[..] {
+            [Action::Script(script)] if script.command.is_empty() => {
+                Err("script has an empty command".to_string())
+            }
+            [action] => Ok(action),
+            [] => Err("needs one of ansible, script or http".to_string()),
+            _ => Err("has more than one of ansible, script and http".to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn runbooks_need_exactly_one_action() {
+        let runbook: Runbook = serde_json::from_value(json!({
+            "on": ["disk.*", "backup.failed"],
+            "script": {"command": ["./rotate.sh"]},
+        }))
+        .unwrap_or_else(|e| panic!("parse: {e}"));
+        assert!(runbook.matches("disk.full"));
+        assert!(runbook.matches("backup.failed"));
+        assert!(!runbook.matches("backup.completed"));
+        assert_eq!(runbook.concurrency, 1);
+        assert_eq!(runbook.busy, Busy::Queue);
+        assert_eq!(runbook.action().map(Action::kind), Ok("script"));
+
+        let both = Runbook {
+            http: Some(Http {
+                url: "https://ci.example.com/job".to_string(),
+                method: default_method(),
+                headers: BTreeMap::new(),
+            }),
+            ..runbook.clone()
+        };
+        assert!(both.action().is_err());
+        let neither = Runbook {
+            script: None,
+            ..runbook
+        };
+        assert!(neither.action().is_err());
+    }
+}
@@ -0,0 +1,134 @@
+//! Running a runbook's process and following its output.
+//!
+//! [`spawn`] starts the program with its output piped and hands every line
+//! of stdout and stderr to a callback as it is written, so progress can be
+//! reported while the run goes on. The process is killed if the run is
+//! dropped, e.g. on timeout.
+
+use std::collections::BTreeMap;
+use std::process::Stdio;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::process::Command;
+
+/// Which output a line came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stream {
+    Stdout,
+    Stderr,
+}
+
+impl Stream {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Stdout => "stdout",
+            Self::Stderr => "stderr",
+        }
+    }
+}
+
+/// A process to run.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Process {
+    pub program: String,
+    pub args: Vec<String>,
+    pub dir: Option<String>,
+    pub env: BTreeMap<String, String>,
+    /// Written to stdin, which is then closed.
+    pub stdin: Vec<u8>,
+}
+
+impl Process {
+    /// The command line, for logs and dry runs.
+    pub fn command_line(&self) -> String {
+        std::iter::once(&self.program)
+            .chain(&self.args)
+            .map(String::as_str)
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+}
+
+/// Run `process` to completion, passing each line of output to `on_line`,
+/// and return its exit code (-1 if it was killed by a signal).
+pub async fn spawn(
+    process: &Process,
+    mut on_line: impl FnMut(Stream, &str),
+) -> Result<i32, String> {
+    let mut command = Command::new(&process.program);
+    command
+        .args(&process.args)
+        .envs(&process.env)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true);
+    if let Some(dir) = &process.dir {
+        command.current_dir(dir);
+    }
+    let mut child = command
+        .spawn()
+        .map_err(|e| format!("{}: {e}", process.program))?;
+
+    if let Some(mut stdin) = child.stdin.take() {
+        // Ignore broken pipes: the program need not read stdin
+        let _ = stdin.write_all(&process.stdin).await;
+    }
+    let (Some(stdout), Some(stderr)) = (child.stdout.take(), child.stderr.take()) else {
+        return Err(format!("{}: output not captured", process.program));
+    };
+    let mut stdout = BufReader::new(stdout).lines();
+    let mut stderr = BufReader::new(stderr).lines();
+    let (mut stdout_open, mut stderr_open) = (true, true);
+    while stdout_open || stderr_open {
+        tokio::select! {
+            line = stdout.next_line(), if stdout_open => match line {
+                Ok(Some(line)) => on_line(Stream::Stdout, &line),
+                _ => stdout_open = false,
+            },
+            line = stderr.next_line(), if stderr_open => match line {
+                Ok(Some(line)) => on_line(Stream::Stderr, &line),
+                _ => stderr_open = false,
+            },
+        }
+    }
+
+    let status = child
+        .wait()
+        .await
+        .map_err(|e| format!("{}: {e}", process.program))?;
+    Ok(status.code().unwrap_or(-1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn output_lines_are_streamed_as_written() {
+        let process = Process {
+            program: "sh".to_string(),
+            args: vec![
+                "-c".to_string(),
+                "read input; echo \"got $input\"; echo \"$STAGE\" >&2; exit 2".to_string(),
+            ],
+            env: BTreeMap::from([("STAGE".to_string(), "cleanup".to_string())]),
+            stdin: b"hello\n".to_vec(),
+            ..Default::default()
+        };
+        let mut lines = Vec::new();
+        let code = spawn(&process, |stream, line| {
+            lines.push((stream, line.to_string()))
+        })
+        .await
+        .unwrap_or_else(|e| panic!("spawn: {e}"));
+        assert_eq!(code, 2);
+        lines.sort_by_key(|(stream, _)| stream.as_str());
+        assert_eq!(
+            lines,
+            [
+                (Stream::Stderr, "cleanup".to_string()),
+                (Stream::Stdout, "got hello".to_string()),
+            ]
+        );
+    }
+}
@@ -0,0 +1,206 @@
+//! Which payload values go in which column.
+//!
+//! `--column NAME=SOURCE` adds a column headed `NAME`; columns appear in
+//! the order given. A source is a JSONPath into the payload (`$.service`,
+//! `$.items[0].sku`, or `$` for the whole payload) or one of:
+//!
+//! - `@type`: the message type
+//! - `@id`: the message id
+//! - `@time`: when the sink received it, as `2024-05-20T16:15:58Z`
+//!
+//! Strings, numbers and booleans keep their type in the sheet; objects and
+//! arrays are written as JSON, and missing values leave the cell empty.
+//! Without `--column`, rows are `time`, `type` and `payload`.
+
+use serde_json::Value;
+
+/// Where a column's value comes from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Source {
+    Type,
+    Id,
+    Time,
+    /// Payload path segments; empty is the whole payload.
+    Path(Vec<String>),
+}
+
+/// One column of the sheet.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Column {
+    pub name: String,
+    source: Source,
+}
+
+/// What a row is built from.
+pub struct Event<'a> {
+    pub message_type: &'a str,
+    pub id: &'a str,
+    /// Seconds since the Unix epoch.
+    pub time: i64,
+    pub payload: &'a Value,
+}
+
+/// Split `$.a.b[0]["c d"]` into `a`, `b`, `0`, `c d`.
+fn json_path(path: &str) -> Result<Vec<String>, String> {
+    let invalid = || format!("invalid JSONPath '{path}'");
+    let mut rest = path.strip_prefix('$').ok_or_else(invalid)?;
+    let mut segments = Vec::new();
+    while !rest.is_empty() {
+        if let Some(after) = rest.strip_prefix('.') {
+            let end = after.find(['.', '[']).unwrap_or(after.len());
+            if end == 0 {
+                return Err(invalid());
+            }
+            segments.push(after[..end].to_string());
+            rest = &after[end..];
+        } else if let Some(after) = rest.strip_prefix('[') {
+            let end = after.find(']').ok_or_else(invalid)?;
+            let inner = after[..end].trim();
+            let quoted = inner
+                .strip_prefix('"')
+                .and_then(|s| s.strip_suffix('"'))
+                .or_else(|| inner.strip_prefix('\'').and_then(|s| s.strip_suffix('\'')));
+            segments.push(quoted.unwrap_or(inner).to_string());
+            rest = &after[end + 1..];
+        } else {
+            return Err(invalid());
+        }
+    }
+    Ok(segments)
+}
+
+impl Column {
+    /// Parse `NAME=SOURCE`.
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let Some((name, source)) = spec.split_once('=') else {
+            return Err(format!("expected NAME=SOURCE, got '{spec}'"));
+        };
+        let source = match source.trim() {
+            "@type" => Source::Type,
+            "@id" => Source::Id,
+            "@time" => Source::Time,
+            path => Source::Path(json_path(path)?),
+        };
+        Ok(Self {
+            name: name.trim().to_string(),
+            source,
+        })
+    }
+
+    /// The columns used without `--column`.
+    pub fn defaults() -> Vec<Self> {
+        [
+            ("time", Source::Time),
+            ("type", Source::Type),
+            ("payload", Source::Path(Vec::new())),
+        ]
+        .into_iter()
+        .map(|(name, source)| Self {
+            name: name.to_string(),
+            source,
+        })
+        .collect()
+    }
+
+    /// This column's cell for `event`: a string, number, boolean or null.
+    pub fn cell(&self, event: &Event) -> Value {
+        let value = match &self.source {
+            Source::Type => return Value::from(event.message_type),
+            Source::Id => return Value::from(event.id),
+            Source::Time => return Value::from(primitive_common::time::format(event.time)),
+            Source::Path(segments) => {
+                segments
+                    .iter()
+                    .try_fold(event.payload, |value, segment| match value {
+                        Value::Object(map) => map.get(segment),
+                        Value::Array(items) => {
+                            segment.parse::<usize>().ok().and_then(|i| items.get(i))
+                        }
+                        _ => None,
+                    })
+            }
+        };
+        match value {
+            None => Value::Null,
+            Some(value @ (Value::Object(_) | Value::Array(_))) => Value::from(value.to_string()),
+            Some(value) => value.clone(),
+        }
+    }
+}
+
+/// The header row.
+pub fn header(columns: &[Column]) -> Vec<Value> {
+    columns
+        .iter()
+        .map(|c| Value::from(c.name.as_str()))
+        .collect()
+}
+
+/// The row for `event`.
+pub fn row(columns: &[Column], event: &Event) -> Vec<Value> {
+    columns.iter().map(|c| c.cell(event)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn columns_pick_payload_values() {
+        let payload = json!({
+            "service": "billing-api",
+            "duration": 12.5,
+            "ok": true,
+            "items": [{"sku": "A-1"}],
+            "tags": {"team": "payments"},
+            "odd key": 1
+        });
+        let event = Event {
+            message_type: "deploy.finished",
+            id: "msg_1",
+            time: 1_716_221_758,
+            payload: &payload,
+        };
+        let columns: Vec<Column> = [
+            "Service=$.service",
+            "Seconds = $.duration",
+            "OK=$.ok",
+            "SKU=$.items[0].sku",
+            "Tags=$.tags",
+            "Odd=$['odd key']",
+            "Missing=$.nope",
+            "When=@time",
+            "Type=@type",
+            "Id=@id",
+        ]
+        .iter()
+        .map(|spec| Column::parse(spec).unwrap_or_else(|e| panic!("{e}")))
+        .collect();
+
+        assert_eq!(header(&columns)[..2], [json!("Service"), json!("Seconds")]);
+        assert_eq!(
+            row(&columns, &event),
+            vec![
+                json!("billing-api"),
+                json!(12.5),
+                json!(true),
+                json!("A-1"),
+                json!(r#"{"team":"payments"}"#),
+                json!(1),
+                Value::Null,
+                json!("2024-05-20T16:15:58Z"),
+                json!("deploy.finished"),
+                json!("msg_1"),
+            ]
+        );
+        assert_eq!(
+            row(&Column::defaults(), &event)[2],
+            json!(payload.to_string())
+        );
+
+        assert!(Column::parse("Service").is_err());
+        assert!(Column::parse("Service=service").is_err());
+        assert!(Column::parse("Service=$..service").is_err());
+    }
+}
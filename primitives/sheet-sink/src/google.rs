@@ -0,0 +1,239 @@
+//! Appending rows to a Google Sheet through the Sheets API v4.
+//!
+//! The sink authenticates as the service account in
+//! `--google-credentials` (its JSON key file): a JWT signed with the
+//! account's RSA key is exchanged at its `token_uri` for an OAuth2 access
+//! token, which is reused until shortly before it expires. The spreadsheet
+//! must be shared with the account's `client_email`.
+//!
+//! Rows are sent with `valueInputOption=USER_ENTERED`, so numbers and dates
+//! are parsed the way the Sheets UI would parse typed input.
+
+use crate::jwt;
+use reqwest::Client;
+use ring::rand::SystemRandom;
+use ring::signature::{RSA_PKCS1_SHA256, RsaKeyPair};
+use serde::Deserialize;
+use serde_json::{Value, json};
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+
+const SCOPE: &str = "https://www.googleapis.com/auth/spreadsheets";
+
+/// Access tokens are renewed this long before they expire.
+const TOKEN_MARGIN: Duration = Duration::from_secs(60);
+
+/// The fields of a service account key file the sink needs.
+#[derive(Deserialize)]
+struct ServiceAccount {
+    client_email: String,
+    private_key: String,
+    #[serde(default = "default_token_uri")]
+    token_uri: String,
+}
+
+fn default_token_uri() -> String {
+    "https://oauth2.googleapis.com/token".to_string()
+}
+
+/// A service account that signs its own token requests.
+pub struct Credentials {
+    client_email: String,
+    token_uri: String,
+    key: RsaKeyPair,
+    rng: SystemRandom,
+    token: Mutex<Option<(String, Instant)>>,
+}
+
+impl Credentials {
+    /// Load the service account key file at `path`.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let json = fs::read_to_string(path).map_err(|e| format!("{}: {e}", path.display()))?;
+        Self::from_json(&json).map_err(|e| format!("{}: {e}", path.display()))
+    }
+
+    pub fn from_json(json: &str) -> Result<Self, String> {
+        let account: ServiceAccount = serde_json::from_str(json).map_err(|e| e.to_string())?;
+        let der = jwt::pem_to_der(&account.private_key)?;
+        let key = RsaKeyPair::from_pkcs8(&der)
+            .map_err(|e| format!("private_key is not a PKCS#8 RSA key: {e}"))?;
+        Ok(Self {
+            client_email: account.client_email,
+            token_uri: account.token_uri,
+            key,
+            rng: SystemRandom::new(),
+            token: Mutex::new(None),
+        })
+    }
+
+    fn assertion(&self) -> Result<String, String> {
+        let now = jwt::now();
+        let claims = json!({
+            "iss": self.client_email,
+            "scope": SCOPE,
+            "aud": self.token_uri,
+            "iat": now,
+            "exp": now + 3600,
+        });
+        jwt::encode(&json!({"alg": "RS256", "typ": "JWT"}), &claims, |message| {
+            let mut signature = vec![0; self.key.public().modulus_len()];
+            self.key
+                .sign(&RSA_PKCS1_SHA256, &self.rng, message, &mut signature)
+                .map_err(|_| "signing failed".to_string())?;
+            Ok(signature)
+        })
+    }
+
+    /// A current access token, fetching a new one if needed.
+    async fn access_token(&self, client: &Client) -> Result<String, String> {
+        let mut cached = self.token.lock().await;
+        if let Some((token, expires)) = cached.as_ref()
+            && Instant::now() + TOKEN_MARGIN < *expires
+        {
+            return Ok(token.clone());
+        }
+        let assertion = self.assertion()?;
+        let response: Value = client
+            .post(&self.token_uri)
+            .form(&[
+                ("grant_type", "urn:ietf:params:oauth:grant-type:jwt-bearer"),
+                ("assertion", &assertion),
+            ])
+            .send()
+            .await
+            .and_then(reqwest::Response::error_for_status)
+            .map_err(|e| format!("{}: {e}", self.token_uri))?
+            .json()
+            .await
+            .map_err(|e| format!("{}: {e}", self.token_uri))?;
+        let token = response["access_token"]
+            .as_str()
+            .ok_or_else(|| format!("{}: no access_token in response", self.token_uri))?
+            .to_string();
+        let lifetime = Duration::from_secs(response["expires_in"].as_u64().unwrap_or(3600));
+        *cached = Some((token.clone(), Instant::now() + lifetime));
+        Ok(token)
+    }
+}
+
+/// How requests to the Sheets API are authorized.
+pub enum Auth {
+    ServiceAccount(Box<Credentials>),
+    /// A token obtained elsewhere, such as from `gcloud auth
+    /// print-access-token`.
+    Token(String),
+}
+
+/// One tab of a spreadsheet.
+pub struct Sheets {
+    pub auth: Auth,
+    pub spreadsheet_id: String,
+    /// The tab's name.
+    pub sheet: String,
+    /// API base URL.
+    pub url: String,
+    pub client: Client,
+}
+
+/// Percent-encode a path segment (the sheet name may contain spaces).
+fn encode(segment: &str) -> String {
+    let mut out = String::with_capacity(segment.len());
+    for byte in segment.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'!' | b':' => {
+                out.push(byte as char);
+            }
+            byte => out.push_str(&format!("%{byte:02X}")),
+        }
+    }
+    out
+}
+
+impl Sheets {
+    async fn token(&self) -> Result<String, String> {
+        match &self.auth {
+            Auth::ServiceAccount(credentials) => credentials.access_token(&self.client).await,
+            Auth::Token(token) => Ok(token.clone()),
+        }
+    }
+
+    /// The URL of the values of `range` in the tab, plus `suffix`.
+    fn values_url(&self, range: &str, suffix: &str) -> String {
+        // Quoted, in case the name looks like a cell reference
+        let range = format!("'{}'!{range}", self.sheet.replace('\'', "''"));
+        format!(
+            "{}/spreadsheets/{}/values/{}{suffix}",
+            self.url.trim_end_matches('/'),
+            encode(&self.spreadsheet_id),
+            encode(&range)
+        )
+    }
+
+    async fn send(&self, request: reqwest::RequestBuilder, url: &str) -> Result<Value, String> {
+        let response = request
+            .bearer_auth(self.token().await?)
+            .send()
+            .await
+            .map_err(|e| format!("{url}: {e}"))?;
+        let status = response.status();
+        let body: Value = response.json().await.unwrap_or_default();
+        if status.is_success() {
+            Ok(body)
+        } else {
+            let message = body["error"]["message"].as_str().unwrap_or_default();
+            Err(format!("{url}: {status} {message}").trim_end().to_string())
+        }
+    }
+
+    /// Write `header` to the first row unless that row has something in it.
+    pub async fn ensure_header(&self, header: &[Value]) -> Result<(), String> {
+        let url = self.values_url("1:1", "");
+        let current = self.send(self.client.get(&url), &url).await?;
+        if current["values"]
+            .as_array()
+            .is_some_and(|rows| !rows.is_empty())
+        {
+            return Ok(());
+        }
+        let url = self.values_url("A1", "?valueInputOption=RAW");
+        self.send(
+            self.client.put(&url).json(&json!({"values": [header]})),
+            &url,
+        )
+        .await
+        .map(|_| ())
+    }
+
+    /// Append `rows` after the last row of the tab.
+    pub async fn append(&self, rows: &[Vec<Value>]) -> Result<(), String> {
+        let url = self.values_url(
+            "A1",
+            ":append?valueInputOption=USER_ENTERED&insertDataOption=INSERT_ROWS",
+        );
+        self.send(self.client.post(&url).json(&json!({"values": rows})), &url)
+            .await
+            .map(|_| ())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ranges_are_quoted_and_encoded() {
+        let sheets = Sheets {
+            auth: Auth::Token("t".to_string()),
+            spreadsheet_id: "1AbC".to_string(),
+            sheet: "Deploy log's".to_string(),
+            url: "https://sheets.googleapis.com/v4/".to_string(),
+            client: Client::new(),
+        };
+        assert_eq!(
+            sheets.values_url("A1", ":append"),
+            "https://sheets.googleapis.com/v4/spreadsheets/1AbC/values/%27Deploy%20log%27%27s%27!A1:append"
+        );
+    }
+}
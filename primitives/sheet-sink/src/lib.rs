@@ -0,0 +1,571 @@
+//! Sheet Sink - Append Events as Spreadsheet Rows
+//!
+//! A Sink that turns each event into a row (see [`columns`]) and appends
+//! it to a Google Sheet (see [`google`]) or a local `.xlsx` workbook (see
+//! [`xlsx`]), for the people who live in spreadsheets: deploy logs, sign-up
+//! lists, inventory counts.
+//!
+//! Rows are buffered and written in batches: when `--batch-size` rows are
+//! waiting, every `--flush-interval`, and at shutdown. A batch that cannot
+//! be written stays buffered for the next attempt; once `--max-pending`
+//! rows are waiting, new events fail (and go to the dead letter with
+//! `--dead-letter`) until a batch gets through. The header row is written
+//! first if the sheet has none. Each written batch is announced as
+//! `sheet.flushed`.
+//!
+//! # Examples
+//!
+//! ```bash
+//! sheet-sink -s deploy.finished \
+//!   --spreadsheet-id 1BxiMVs0XRA5nFMdKvBdBZjgmUUqptlbs74OgvE2upms \
+//!   --sheet Deploys --google-credentials ./service-account.json \
+//!   --column 'When=@time' --column 'Service=$.service' \
+//!   --column 'Version=$.version' --column 'Seconds=$.duration'
+//!
+//! sheet-sink -s 'signup.*' --xlsx ./signups.xlsx \
+//!   --column 'Email=$.email' --column 'Plan=$.plan'
+//! ```
+
+pub mod columns;
+pub mod google;
+pub mod jwt;
+pub mod xlsx;
+
+use clap::Parser;
+use columns::{Column, Event};
+use emergent_client::EmergentMessage;
+use google::{Auth, Credentials, Sheets};
+use primitive_common::capabilities::VERSION;
+use primitive_common::doctor::{Report, check_writable_dir};
+use primitive_common::errors::{ErrorCategory, HandlerError};
+use primitive_common::time;
+use primitive_common::{Publisher, SinkArgs, SinkConfig, SinkContext, SinkHandler, run_sink};
+use reqwest::Client;
+use serde_json::{Value, json};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+use tokio::sync::Mutex;
+use xlsx::Workbook;
+
+pub const FLUSHED_EVENT_TYPE: &str = "sheet.flushed";
+
+/// Sheet Sink — append events as spreadsheet rows.
+#[derive(Parser, Debug)]
+#[command(name = "sheet_sink", version = VERSION)]
+#[command(about = "Append events as rows to a Google Sheet or a local .xlsx workbook")]
+struct Args {
+    /// Message types to subscribe to.
+    #[arg(short, long = "subscribe", required = true)]
+    subscribe: Vec<String>,
+
+    /// Append to this .xlsx workbook, created if missing.
+    #[arg(
+        long,
+        env = "SHEET_SINK_XLSX",
+        required_unless_present = "spreadsheet_id",
+        conflicts_with = "spreadsheet_id"
+    )]
+    xlsx: Option<PathBuf>,
+
+    /// Append to the Google Sheet with this id (from its URL).
+    #[arg(long, env = "SHEET_SINK_SPREADSHEET_ID")]
+    spreadsheet_id: Option<String>,
+
+    /// Tab of the Google Sheet, or name of the worksheet in a new workbook.
+    #[arg(long, env = "SHEET_SINK_SHEET", default_value = "Sheet1")]
+    sheet: String,
+
+    /// Service account key file for the Google Sheet.
+    #[arg(long, env = "SHEET_SINK_GOOGLE_CREDENTIALS")]
+    google_credentials: Option<PathBuf>,
+
+    /// OAuth2 access token for the Google Sheet, instead of a service
+    /// account.
+    #[arg(
+        long,
+        env = "SHEET_SINK_GOOGLE_ACCESS_TOKEN",
+        conflicts_with = "google_credentials"
+    )]
+    google_access_token: Option<String>,
+
+    /// Sheets API base URL.
+    #[arg(
+        long,
+        env = "SHEET_SINK_API_URL",
+        default_value = "https://sheets.googleapis.com/v4"
+    )]
+    api_url: String,
+
+    /// Column as `NAME=SOURCE`, where the source is a payload JSONPath or
+    /// `@type`, `@id` or `@time` (repeatable, in order).
+    #[arg(long = "column", env = "SHEET_SINK_COLUMNS", value_delimiter = ',')]
+    columns: Vec<String>,
+
+    /// Write once this many rows are waiting.
+    #[arg(long, env = "SHEET_SINK_BATCH_SIZE", default_value = "100")]
+    batch_size: usize,
+
+    /// Write waiting rows this often, in milliseconds.
+    #[arg(long, env = "SHEET_SINK_FLUSH_INTERVAL", default_value = "5000")]
+    flush_interval: u64,
+
+    /// Refuse new events while this many rows are waiting to be written.
+    #[arg(long, env = "SHEET_SINK_MAX_PENDING", default_value = "10000")]
+    max_pending: usize,
+
+    /// Timeout for each Sheets API request in milliseconds.
+    #[arg(long, env = "SHEET_SINK_TIMEOUT", default_value = "30000")]
+    timeout: u64,
+
+    #[command(flatten)]
+    sink: SinkArgs,
+}
+
+/// Where rows are appended.
+enum Target {
+    Xlsx(Workbook),
+    Sheets(Box<Sheets>),
+}
+
+impl Target {
+    fn describe(&self) -> String {
+        match self {
+            Self::Xlsx(workbook) => workbook.path.display().to_string(),
+            Self::Sheets(sheets) => format!("{}/{}", sheets.spreadsheet_id, sheets.sheet),
+        }
+    }
+}
+
+/// Rows waiting to be written.
+#[derive(Default)]
+struct Pending {
+    rows: Vec<Vec<Value>>,
+    /// Whether the Google Sheet is known to have a header row.
+    header_checked: bool,
+}
+
+/// Buffers rows and writes them in batches.
+struct Rows {
+    target: Target,
+    columns: Vec<Column>,
+    batch_size: usize,
+    max_pending: usize,
+    /// Held while writing, so batches go out one at a time and in order.
+    pending: Mutex<Pending>,
+    publisher: OnceLock<Publisher>,
+}
+
+impl Rows {
+    /// Write every waiting row; returns how many were written.
+    async fn flush(&self) -> Result<usize, String> {
+        let mut pending = self.pending.lock().await;
+        if pending.rows.is_empty() {
+            return Ok(0);
+        }
+        let header = columns::header(&self.columns);
+        match &self.target {
+            Target::Xlsx(workbook) => {
+                let workbook = workbook.clone();
+                let rows = pending.rows.clone();
+                tokio::task::spawn_blocking(move || workbook.append(&header, &rows))
+                    .await
+                    .map_err(|e| e.to_string())??;
+            }
+            Target::Sheets(sheets) => {
+                if !pending.header_checked {
+                    sheets.ensure_header(&header).await?;
+                    pending.header_checked = true;
+                }
+                sheets.append(&pending.rows).await?;
+            }
+        }
+        let count = std::mem::take(&mut pending.rows).len();
+
+        if let Some(publisher) = self.publisher.get() {
+            let flushed = EmergentMessage::new(FLUSHED_EVENT_TYPE).with_payload(json!({
+                "target": self.target.describe(),
+                "rows": count,
+            }));
+            if let Err(e) = publisher.publish(flushed) {
+                eprintln!("Failed to publish {FLUSHED_EVENT_TYPE}: {e}");
+            }
+        }
+        Ok(count)
+    }
+}
+
+/// Appends a row per event.
+struct SheetSink {
+    rows: Arc<Rows>,
+}
+
+impl SinkHandler for SheetSink {
+    async fn handle(
+        &self,
+        msg: &EmergentMessage,
+        ctx: &SinkContext<'_>,
+    ) -> Result<(), HandlerError> {
+        let id = ctx.message_id().to_string();
+        let event = Event {
+            message_type: msg.message_type.as_str(),
+            id: &id,
+            time: time::now(),
+            payload: ctx.payload(),
+        };
+        let row = columns::row(&self.rows.columns, &event);
+
+        if ctx.is_dry_run() {
+            let detail = json!({
+                "target": self.rows.target.describe(),
+                "header": columns::header(&self.rows.columns),
+                "row": row,
+            });
+            ctx.would_have("append", detail).await;
+            return Ok(());
+        }
+
+        let full = self.rows.pending.lock().await.rows.len() >= self.rows.max_pending;
+        if full {
+            self.rows
+                .flush()
+                .await
+                .map_err(|e| HandlerError::new(ErrorCategory::Request, e))?;
+        }
+        let waiting = {
+            let mut pending = self.rows.pending.lock().await;
+            pending.rows.push(row);
+            pending.rows.len()
+        };
+        if waiting >= self.rows.batch_size
+            && let Err(e) = self.rows.flush().await
+        {
+            eprintln!("Failed to write {waiting} rows, keeping them for the next flush: {e}");
+        }
+        Ok(())
+    }
+
+    fn self_test(&self, report: &mut Report) {
+        match &self.rows.target {
+            Target::Xlsx(workbook) => {
+                let dir = workbook
+                    .path
+                    .parent()
+                    .filter(|dir| !dir.as_os_str().is_empty())
+                    .unwrap_or(Path::new("."));
+                report.check("workbook directory", check_writable_dir(dir));
+            }
+            Target::Sheets(sheets) => {
+                let auth = match sheets.auth {
+                    Auth::ServiceAccount(_) => "service account",
+                    Auth::Token(_) => "access token",
+                };
+                report.check("google credentials", Ok(auth.to_string()));
+            }
+        }
+    }
+
+    async fn flush(&self) {
+        match self.rows.flush().await {
+            Ok(_) => {}
+            Err(e) => {
+                let lost = self.rows.pending.lock().await.rows.len();
+                eprintln!("Error: {lost} rows were not written: {e}");
+            }
+        }
+    }
+
+    fn publishes(&self) -> &'static [&'static str] {
+        &[FLUSHED_EVENT_TYPE]
+    }
+
+    fn attach(&self, publisher: Publisher) {
+        let _ = self.rows.publisher.set(publisher);
+    }
+}
+
+/// Write waiting rows every `interval`.
+async fn flush_every(rows: Arc<Rows>, interval: Duration) {
+    let mut ticks = tokio::time::interval_at(tokio::time::Instant::now() + interval, interval);
+    ticks.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    loop {
+        ticks.tick().await;
+        if let Err(e) = rows.flush().await {
+            eprintln!("Failed to write rows, retrying at the next flush: {e}");
+        }
+    }
+}
+
+/// Run the primitive with `args` as its command line (the first item is
+/// the program name, as with `std::env::args_os`).
+pub async fn run<I, T>(args: I) -> Result<(), Box<dyn std::error::Error>>
+where
+    I: IntoIterator<Item = T>,
+    T: Into<std::ffi::OsString> + Clone,
+{
+    let args = Args::parse_from(args);
+
+    let exit = |e: String| -> ! {
+        eprintln!("Error: {e}");
+        std::process::exit(1);
+    };
+    let columns = if args.columns.is_empty() {
+        Column::defaults()
+    } else {
+        args.columns
+            .iter()
+            .map(|spec| Column::parse(spec).map_err(|e| format!("--column: {e}")))
+            .collect::<Result<Vec<_>, String>>()
+            .unwrap_or_else(|e| exit(e))
+    };
+    let target = match (&args.xlsx, &args.spreadsheet_id) {
+        (Some(path), _) => Target::Xlsx(Workbook {
+            path: path.clone(),
+            sheet_name: args.sheet.clone(),
+        }),
+        (None, Some(spreadsheet_id)) => {
+            let auth = match (&args.google_credentials, &args.google_access_token) {
+                (Some(path), _) => Auth::ServiceAccount(Box::new(
+                    Credentials::load(path).unwrap_or_else(|e| exit(e)),
+                )),
+                (None, Some(token)) => Auth::Token(token.clone()),
+                (None, None) => exit(
+                    "--spreadsheet-id needs --google-credentials or --google-access-token"
+                        .to_string(),
+                ),
+            };
+            let client = Client::builder()
+                .timeout(Duration::from_millis(args.timeout))
+                .build()
+                .unwrap_or_else(|e| exit(e.to_string()));
+            Target::Sheets(Box::new(Sheets {
+                auth,
+                spreadsheet_id: spreadsheet_id.clone(),
+                sheet: args.sheet.clone(),
+                url: args.api_url.clone(),
+                client,
+            }))
+        }
+        (None, None) => exit("--xlsx or --spreadsheet-id is required".to_string()),
+    };
+
+    let config = SinkConfig {
+        name: "sheet_sink",
+        subscribe: &args.subscribe,
+        would_have_as: "sheet.would_have",
+        dead_letter_as: "sheet.dead_letter",
+        settings: &args,
+    };
+    let rows = Arc::new(Rows {
+        target,
+        columns,
+        batch_size: args.batch_size.max(1),
+        max_pending: args.max_pending.max(1),
+        pending: Mutex::new(Pending::default()),
+        publisher: OnceLock::new(),
+    });
+
+    if args.flush_interval > 0 && !args.sink.dry_run && !args.sink.self_test {
+        tokio::spawn(flush_every(
+            Arc::clone(&rows),
+            Duration::from_millis(args.flush_interval),
+        ));
+    }
+
+    run_sink(config, &args.sink, SheetSink { rows }).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{Json, Router, extract::Request, routing::any};
+    use emergent_testkit::fixtures::TempDir;
+    use emergent_testkit::{SinkFixture, fixtures, spawn_sink};
+    use std::io::Read;
+    use tokio::sync::mpsc;
+
+    fn columns() -> Vec<Column> {
+        ["Service=$.service", "Seconds=$.duration"]
+            .iter()
+            .map(|spec| Column::parse(spec).unwrap_or_else(|e| panic!("{e}")))
+            .collect()
+    }
+
+    fn sheet_sink(target: Target, batch_size: usize) -> SheetSink {
+        SheetSink {
+            rows: Arc::new(Rows {
+                target,
+                columns: columns(),
+                batch_size,
+                max_pending: 100,
+                pending: Mutex::new(Pending::default()),
+                publisher: OnceLock::new(),
+            }),
+        }
+    }
+
+    fn deploy(service: &str, duration: f64) -> EmergentMessage {
+        fixtures::message(
+            "deploy.finished",
+            json!({"service": service, "duration": duration}),
+        )
+    }
+
+    #[tokio::test]
+    async fn rows_are_written_in_batches_and_at_shutdown() {
+        let dir = TempDir::new("sheet-sink");
+        let path = dir.path().join("deploys.xlsx");
+        let workbook = Workbook {
+            path: path.clone(),
+            sheet_name: "Deploys".to_string(),
+        };
+        let (mut engine, run) = spawn_sink(
+            SinkFixture::new("sheet_sink", "sheet"),
+            SinkArgs::default(),
+            sheet_sink(Target::Xlsx(workbook), 2),
+        );
+
+        engine.inject_message(deploy("billing-api", 12.5)).await;
+        engine.inject_message(deploy("search", 3.0)).await;
+        let flushed = engine.expect_published(FLUSHED_EVENT_TYPE).await;
+        assert_eq!(flushed.payload()["rows"], 2);
+        assert!(path.exists());
+
+        // Below the batch size, so it waits for shutdown
+        engine.inject_message(deploy("auth", 7.0)).await;
+        engine.close();
+        assert_eq!(run.await.ok(), Some(Ok(())));
+
+        let file = std::fs::File::open(&path).unwrap_or_else(|e| panic!("{e}"));
+        let mut archive = zip::ZipArchive::new(file).unwrap_or_else(|e| panic!("{e}"));
+        let mut sheet = String::new();
+        archive
+            .by_name("xl/worksheets/sheet1.xml")
+            .unwrap_or_else(|e| panic!("{e}"))
+            .read_to_string(&mut sheet)
+            .unwrap_or_else(|e| panic!("{e}"));
+        assert_eq!(sheet.matches("<row ").count(), 4);
+        assert!(sheet.contains(">Service</t>"));
+        assert!(sheet.contains(r#"<row r="4"><c r="A4" t="inlineStr"><is><t xml:space="preserve">auth</t></is></c><c r="B4"><v>7.0</v></c></row>"#));
+    }
+
+    /// Serve a fake Sheets API on a random port, forwarding each request's
+    /// method, path and JSON body; the sheet starts empty.
+    async fn server() -> (String, mpsc::UnboundedReceiver<(String, String, Value)>) {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let app = Router::new().fallback(any(move |request: Request| {
+            let tx = tx.clone();
+            async move {
+                let method = request.method().to_string();
+                let path = request.uri().to_string();
+                let authorized = request
+                    .headers()
+                    .get("authorization")
+                    .is_some_and(|value| value == "Bearer t0ken");
+                let body = axum::body::to_bytes(request.into_body(), usize::MAX)
+                    .await
+                    .unwrap_or_default();
+                let body: Value = serde_json::from_slice(&body).unwrap_or(Value::Null);
+                if authorized {
+                    let _ = tx.send((method, path, body));
+                }
+                Json(json!({"range": "Deploys!A1:Z1", "majorDimension": "ROWS"}))
+            }
+        }));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap_or_else(|e| panic!("bind: {e}"));
+        let addr = listener
+            .local_addr()
+            .unwrap_or_else(|e| panic!("local_addr: {e}"));
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        (format!("http://{addr}/v4"), rx)
+    }
+
+    #[tokio::test]
+    async fn a_header_is_created_before_the_first_append() {
+        let (url, mut received) = server().await;
+        let sheets = Sheets {
+            auth: Auth::Token("t0ken".to_string()),
+            spreadsheet_id: "1AbC".to_string(),
+            sheet: "Deploys".to_string(),
+            url,
+            client: Client::new(),
+        };
+        let (mut engine, run) = spawn_sink(
+            SinkFixture::new("sheet_sink", "sheet"),
+            SinkArgs::default(),
+            sheet_sink(Target::Sheets(Box::new(sheets)), 2),
+        );
+        engine.inject_message(deploy("billing-api", 12.5)).await;
+        engine.inject_message(deploy("search", 3.0)).await;
+        engine.expect_published(FLUSHED_EVENT_TYPE).await;
+
+        let mut next = async || {
+            received
+                .recv()
+                .await
+                .unwrap_or_else(|| panic!("no request"))
+        };
+        let (method, path, _) = next().await;
+        assert_eq!(method, "GET");
+        assert_eq!(path, "/v4/spreadsheets/1AbC/values/%27Deploys%27!1:1");
+        let (method, path, body) = next().await;
+        assert_eq!(method, "PUT");
+        assert_eq!(
+            path,
+            "/v4/spreadsheets/1AbC/values/%27Deploys%27!A1?valueInputOption=RAW"
+        );
+        assert_eq!(body, json!({"values": [["Service", "Seconds"]]}));
+        let (method, path, body) = next().await;
+        assert_eq!(method, "POST");
+        assert!(
+            path.ends_with("!A1:append?valueInputOption=USER_ENTERED&insertDataOption=INSERT_ROWS")
+        );
+        assert_eq!(
+            body,
+            json!({"values": [["billing-api", 12.5], ["search", 3.0]]})
+        );
+
+        // The header is only checked once
+        engine.inject_message(deploy("auth", 7.0)).await;
+        engine.close();
+        assert_eq!(run.await.ok(), Some(Ok(())));
+        let (method, _, body) = next().await;
+        assert_eq!(method, "POST");
+        assert_eq!(body, json!({"values": [["auth", 7.0]]}));
+    }
+
+    #[tokio::test]
+    async fn dry_run_reports_the_row() {
+        let dir = TempDir::new("sheet-sink-dry");
+        let path = dir.path().join("deploys.xlsx");
+        let workbook = Workbook {
+            path: path.clone(),
+            sheet_name: "Deploys".to_string(),
+        };
+        let args = SinkArgs {
+            dry_run: true,
+            ..Default::default()
+        };
+        let (mut engine, run) = spawn_sink(
+            SinkFixture::new("sheet_sink", "sheet"),
+            args,
+            sheet_sink(Target::Xlsx(workbook), 1),
+        );
+        engine.inject_message(deploy("billing-api", 12.5)).await;
+        let report = engine.expect_published("sheet.would_have").await;
+        assert_eq!(
+            report.payload()["detail"]["row"],
+            json!(["billing-api", 12.5])
+        );
+        assert_eq!(
+            report.payload()["detail"]["header"],
+            json!(["Service", "Seconds"])
+        );
+
+        engine.close();
+        assert_eq!(run.await.ok(), Some(Ok(())));
+        assert!(!path.exists());
+    }
+}
@@ -0,0 +1,274 @@
+//! Appending rows to a local `.xlsx` workbook.
+//!
+//! A missing file is created as a minimal workbook with one worksheet. An
+//! existing one gets the rows added at the end of its first worksheet
+//! (`xl/worksheets/sheet1.xml`) and everything else copied as it is, so a
+//! workbook saved by Excel or LibreOffice keeps its other sheets and its
+//! formatting. Each append writes a new file next to the old one and
+//! renames it over, so a crash never leaves half a workbook.
+//!
+//! Cells are written as inline strings, numbers and booleans; the file
+//! should not be open for writing elsewhere while the sink appends.
+
+use serde_json::Value;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::PathBuf;
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipArchive, ZipWriter};
+
+const SHEET: &str = "xl/worksheets/sheet1.xml";
+
+const CONTENT_TYPES: &str = r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<Types xmlns="http://schemas.openxmlformats.org/package/2006/content-types"><Default Extension="rels" ContentType="application/vnd.openxmlformats-package.relationships+xml"/><Default Extension="xml" ContentType="application/xml"/><Override PartName="/xl/workbook.xml" ContentType="application/vnd.openxmlformats-officedocument.spreadsheetml.sheet.main+xml"/><Override PartName="/xl/worksheets/sheet1.xml" ContentType="application/vnd.openxmlformats-officedocument.spreadsheetml.worksheet+xml"/></Types>"#;
+
+const RELS: &str = r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<Relationships xmlns="http://schemas.openxmlformats.org/package/2006/relationships"><Relationship Id="rId1" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/officeDocument" Target="xl/workbook.xml"/></Relationships>"#;
+
+const WORKBOOK_RELS: &str = r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<Relationships xmlns="http://schemas.openxmlformats.org/package/2006/relationships"><Relationship Id="rId1" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/worksheet" Target="worksheets/sheet1.xml"/></Relationships>"#;
+
+const EMPTY_SHEET: &str = r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<worksheet xmlns="http://schemas.openxmlformats.org/spreadsheetml/2006/main"><sheetData/></worksheet>"#;
+
+fn workbook(sheet_name: &str) -> String {
+    format!(
+        r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<workbook xmlns="http://schemas.openxmlformats.org/spreadsheetml/2006/main" xmlns:r="http://schemas.openxmlformats.org/officeDocument/2006/relationships"><sheets><sheet name="{}" sheetId="1" r:id="rId1"/></sheets></workbook>"#,
+        escape(sheet_name)
+    )
+}
+
+/// Text made safe for XML, dropping the control characters XML forbids.
+fn escape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\t' | '\n' | '\r' => out.push(c),
+            c if c < ' ' => {}
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// `A` for column 0, `Z` for 25, `AA` for 26.
+fn column_name(mut index: usize) -> String {
+    let mut name = Vec::new();
+    loop {
+        name.push(b'A' + (index % 26) as u8);
+        if index < 26 {
+            break;
+        }
+        index = index / 26 - 1;
+    }
+    name.reverse();
+    String::from_utf8_lossy(&name).into_owned()
+}
+
+/// `<row>` elements for `rows`, numbered from `first`.
+fn rows_xml(first: usize, rows: &[Vec<Value>]) -> String {
+    let mut xml = String::new();
+    for (offset, row) in rows.iter().enumerate() {
+        let number = first + offset;
+        xml.push_str(&format!(r#"<row r="{number}">"#));
+        for (index, value) in row.iter().enumerate() {
+            let cell = format!("{}{number}", column_name(index));
+            match value {
+                Value::Null => {}
+                Value::Bool(b) => {
+                    xml.push_str(&format!(
+                        r#"<c r="{cell}" t="b"><v>{}</v></c>"#,
+                        u8::from(*b)
+                    ));
+                }
+                Value::Number(n) => xml.push_str(&format!(r#"<c r="{cell}"><v>{n}</v></c>"#)),
+                Value::String(s) => xml.push_str(&format!(
+                    r#"<c r="{cell}" t="inlineStr"><is><t xml:space="preserve">{}</t></is></c>"#,
+                    escape(s)
+                )),
+                other => xml.push_str(&format!(
+                    r#"<c r="{cell}" t="inlineStr"><is><t xml:space="preserve">{}</t></is></c>"#,
+                    escape(&other.to_string())
+                )),
+            }
+        }
+        xml.push_str("</row>");
+    }
+    xml
+}
+
+/// The number of the last row in a worksheet, 0 if it has none.
+fn last_row(sheet: &str) -> usize {
+    let Some(start) = sheet.rfind("<row ").or_else(|| sheet.rfind("<row>")) else {
+        return 0;
+    };
+    let tag = &sheet[start..];
+    let tag = &tag[..tag.find('>').unwrap_or(tag.len())];
+    tag.find(" r=\"")
+        .and_then(|at| {
+            let value = &tag[at + 4..];
+            value[..value.find('"')?].parse().ok()
+        })
+        .unwrap_or_else(|| sheet.matches("<row").count())
+}
+
+/// `sheet` with `rows` added at the end of its data.
+fn append_rows(sheet: &str, rows: &str) -> Result<String, String> {
+    // The dimension would be stale; it is optional, so drop it
+    let mut sheet = sheet.to_string();
+    if let Some(start) = sheet.find("<dimension ")
+        && let Some(length) = sheet[start..].find("/>")
+    {
+        sheet.replace_range(start..start + length + 2, "");
+    }
+    if let Some(end) = sheet.rfind("</sheetData>") {
+        sheet.insert_str(end, rows);
+        Ok(sheet)
+    } else if sheet.contains("<sheetData/>") {
+        Ok(sheet.replacen("<sheetData/>", &format!("<sheetData>{rows}</sheetData>"), 1))
+    } else {
+        Err(format!("{SHEET} has no sheetData"))
+    }
+}
+
+/// A workbook rows are appended to.
+#[derive(Debug, Clone)]
+pub struct Workbook {
+    pub path: PathBuf,
+    /// Name of the worksheet in a workbook the sink creates.
+    pub sheet_name: String,
+}
+
+impl Workbook {
+    /// Append `rows`, after `header` if the worksheet has no rows yet.
+    pub fn append(&self, header: &[Value], rows: &[Vec<Value>]) -> Result<(), String> {
+        let at = |e: &dyn std::fmt::Display| format!("{}: {e}", self.path.display());
+        let mut archive = match File::open(&self.path) {
+            Ok(file) => Some(ZipArchive::new(file).map_err(|e| at(&e))?),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+            Err(e) => return Err(at(&e)),
+        };
+        let sheet = match &mut archive {
+            Some(archive) => {
+                let mut sheet = String::new();
+                archive
+                    .by_name(SHEET)
+                    .map_err(|e| at(&format!("{SHEET}: {e}")))?
+                    .read_to_string(&mut sheet)
+                    .map_err(|e| at(&e))?;
+                sheet
+            }
+            None => EMPTY_SHEET.to_string(),
+        };
+
+        let last = last_row(&sheet);
+        let mut new_rows = Vec::with_capacity(rows.len() + 1);
+        if last == 0 {
+            new_rows.push(header.to_vec());
+        }
+        new_rows.extend_from_slice(rows);
+        let sheet = append_rows(&sheet, &rows_xml(last + 1, &new_rows)).map_err(|e| at(&e))?;
+
+        let temporary = self.path.with_extension("xlsx.tmp");
+        let file = File::create(&temporary).map_err(|e| at(&e))?;
+        let mut zip = ZipWriter::new(file);
+        let options = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+        let mut write = |name: &str, content: &str| {
+            zip.start_file(name, options)
+                .map_err(|e| e.to_string())
+                .and_then(|()| zip.write_all(content.as_bytes()).map_err(|e| e.to_string()))
+        };
+        match &mut archive {
+            Some(archive) => {
+                for index in 0..archive.len() {
+                    let entry = archive.by_index_raw(index).map_err(|e| at(&e))?;
+                    if entry.name() != SHEET {
+                        zip.raw_copy_file(entry).map_err(|e| at(&e))?;
+                    }
+                }
+                zip.start_file(SHEET, options).map_err(|e| at(&e))?;
+                zip.write_all(sheet.as_bytes()).map_err(|e| at(&e))?;
+            }
+            None => {
+                write("[Content_Types].xml", CONTENT_TYPES).map_err(|e| at(&e))?;
+                write("_rels/.rels", RELS).map_err(|e| at(&e))?;
+                write("xl/workbook.xml", &workbook(&self.sheet_name)).map_err(|e| at(&e))?;
+                write("xl/_rels/workbook.xml.rels", WORKBOOK_RELS).map_err(|e| at(&e))?;
+                write(SHEET, &sheet).map_err(|e| at(&e))?;
+            }
+        }
+        zip.finish().map_err(|e| at(&e))?;
+        std::fs::rename(&temporary, &self.path).map_err(|e| at(&e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use emergent_testkit::fixtures::TempDir;
+    use serde_json::json;
+
+    fn read_sheet(path: &std::path::Path) -> String {
+        let file = File::open(path).unwrap_or_else(|e| panic!("{e}"));
+        let mut archive = ZipArchive::new(file).unwrap_or_else(|e| panic!("{e}"));
+        let mut sheet = String::new();
+        archive
+            .by_name(SHEET)
+            .unwrap_or_else(|e| panic!("{e}"))
+            .read_to_string(&mut sheet)
+            .unwrap_or_else(|e| panic!("{e}"));
+        sheet
+    }
+
+    #[test]
+    fn rows_are_appended_after_a_header() {
+        let dir = TempDir::new("sheet-sink-xlsx");
+        let workbook = Workbook {
+            path: dir.path().join("events.xlsx"),
+            sheet_name: "Deploys".to_string(),
+        };
+        let header = [json!("service"), json!("seconds"), json!("ok")];
+        workbook
+            .append(
+                &header,
+                &[vec![json!("billing <api>"), json!(12.5), json!(true)]],
+            )
+            .unwrap_or_else(|e| panic!("{e}"));
+        workbook
+            .append(&header, &[vec![json!("search"), Value::Null, json!(false)]])
+            .unwrap_or_else(|e| panic!("{e}"));
+
+        let sheet = read_sheet(&workbook.path);
+        assert_eq!(last_row(&sheet), 3);
+        assert!(sheet.contains(
+            r#"<row r="1"><c r="A1" t="inlineStr"><is><t xml:space="preserve">service</t>"#
+        ));
+        assert!(sheet.contains("billing &lt;api&gt;"));
+        assert!(sheet.contains(r#"<c r="B2"><v>12.5</v></c><c r="C2" t="b"><v>1</v></c></row>"#));
+        assert!(sheet.contains(r#"<row r="3"><c r="A3" t="inlineStr"><is><t xml:space="preserve">search</t></is></c><c r="C3" t="b"><v>0</v></c></row>"#));
+        assert!(!dir.path().join("events.xlsx.tmp").exists());
+    }
+
+    #[test]
+    fn existing_sheets_keep_their_rows() {
+        let sheet = r#"<worksheet><dimension ref="A1:B7"/><sheetData><row r="7" spans="1:2"><c r="A7"><v>1</v></c></row></sheetData></worksheet>"#;
+        assert_eq!(last_row(sheet), 7);
+        assert_eq!(
+            append_rows(sheet, &rows_xml(8, &[vec![json!(2)]])).as_deref(),
+            Ok(
+                r#"<worksheet><sheetData><row r="7" spans="1:2"><c r="A7"><v>1</v></c></row><row r="8"><c r="A8"><v>2</v></c></row></sheetData></worksheet>"#
+            )
+        );
+        assert_eq!(last_row(EMPTY_SHEET), 0);
+        assert_eq!(column_name(0), "A");
+        assert_eq!(column_name(25), "Z");
+        assert_eq!(column_name(26), "AA");
+        assert_eq!(column_name(27), "AB");
+        assert_eq!(escape("a\u{1}b\tc&"), "ab\tc&amp;");
+    }
+}
@@ -0,0 +1,81 @@
+//! Committing written pages with the `git` command line.
+//!
+//! Each page is staged and committed on its own, so the history reads as
+//! one commit per event. A page that did not change is not committed.
+
+use std::path::PathBuf;
+use std::time::Duration;
+use tokio::process::Command;
+
+/// The repository the content directory belongs to.
+#[derive(Debug, Clone)]
+pub struct Git {
+    pub dir: PathBuf,
+    /// Committer name and email, when git's own configuration has none.
+    pub name: Option<String>,
+    pub email: Option<String>,
+    /// Push after each commit.
+    pub push: bool,
+    pub timeout: Duration,
+}
+
+impl Git {
+    /// Run `git` in the content directory; returns its standard output.
+    async fn git(&self, args: &[&str]) -> Result<String, String> {
+        let mut command = Command::new("git");
+        command.arg("-C").arg(&self.dir);
+        if let Some(name) = &self.name {
+            command.arg("-c").arg(format!("user.name={name}"));
+        }
+        if let Some(email) = &self.email {
+            command.arg("-c").arg(format!("user.email={email}"));
+        }
+        let command_line = format!("git {}", args.join(" "));
+        let child = command
+            .args(args)
+            .stdin(std::process::Stdio::null())
+            .stdout(std::process::Stdio::piped())
+            .stderr(std::process::Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(|e| format!("{command_line}: {e}"))?;
+        let output = tokio::time::timeout(self.timeout, child.wait_with_output())
+            .await
+            .map_err(|_| {
+                format!(
+                    "{command_line}: timed out after {}s",
+                    self.timeout.as_secs()
+                )
+            })?
+            .map_err(|e| format!("{command_line}: {e}"))?;
+        if output.status.success() {
+            Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+        } else {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            Err(format!("{command_line}: {}", stderr.trim()))
+        }
+    }
+
+    /// Commit `path` (relative to the content directory) with `message`;
+    /// returns the new commit, or `None` if the page was unchanged.
+    pub async fn commit(&self, path: &str, message: &str) -> Result<Option<String>, String> {
+        self.git(&["add", "--", path]).await?;
+        // Exits 0 when nothing is staged for the path
+        let unchanged = self
+            .git(&["diff", "--cached", "--quiet", "--", path])
+            .await
+            .is_ok();
+        let commit = if unchanged {
+            None
+        } else {
+            self.git(&["commit", "--quiet", "-m", message, "--", path])
+                .await?;
+            Some(self.git(&["rev-parse", "HEAD"]).await?)
+        };
+        // Also when unchanged, in case a retry follows a failed push
+        if self.push {
+            self.git(&["push", "--quiet"]).await?;
+        }
+        Ok(commit)
+    }
+}
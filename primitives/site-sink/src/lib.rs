@@ -0,0 +1,380 @@
+//! Site Sink - Publish Events as Markdown and HTML Pages
+//!
+//! A Sink that renders each event through a template (see [`template`])
+//! into a file in a content directory, such as the content tree of a
+//! static site generator: changelog entries from releases, postmortems
+//! from resolved incidents, notes from chat commands. Which template and
+//! which file each type gets is set by rules (see [`site`]).
+//!
+//! With `--git`, each written page is committed on its own, and pushed
+//! with `--git-push` (see [`git`]), so publishing the site is whatever the
+//! repository already does on push. Each written page is announced as
+//! `site.written`.
+//!
+//! # Examples
+//!
+//! ```bash
+//! site-sink -s release.published -s incident.resolved \
+//!   --content-dir ./site/content \
+//!   --template 'release.*=templates/changelog.md' \
+//!   --output 'release.*=changelog/{date}-{tag}.{ext}' \
+//!   --template 'incident.*=templates/postmortem.html' \
+//!   --output 'incident.*=postmortems/{date}-{title}.{ext}' \
+//!   --git --git-push
+//! ```
+
+pub mod git;
+pub mod site;
+pub mod template;
+
+use clap::Parser;
+use emergent_client::EmergentMessage;
+use git::Git;
+use primitive_common::capabilities::VERSION;
+use primitive_common::doctor::{Report, check_executable, check_writable_dir};
+use primitive_common::errors::{ErrorCategory, HandlerError};
+use primitive_common::template::substitute;
+use primitive_common::time;
+use primitive_common::{Publisher, SinkArgs, SinkConfig, SinkContext, SinkHandler, run_sink};
+use serde_json::json;
+use site::{Site, parse_rule};
+use std::path::PathBuf;
+use std::sync::OnceLock;
+use std::time::Duration;
+use template::{Template, Vars};
+use tokio::sync::Mutex;
+
+pub const WRITTEN_EVENT_TYPE: &str = "site.written";
+
+/// Site Sink — publish events as Markdown and HTML pages.
+#[derive(Parser, Debug)]
+#[command(name = "site_sink", version = VERSION)]
+#[command(
+    about = "Render events through templates into Markdown and HTML files, optionally committed with git"
+)]
+struct Args {
+    /// Message types to subscribe to.
+    #[arg(short, long = "subscribe", required = true)]
+    subscribe: Vec<String>,
+
+    /// Directory the pages are written to.
+    #[arg(long, env = "SITE_SINK_CONTENT_DIR")]
+    content_dir: PathBuf,
+
+    /// Template for matching types, as `PATTERN=FILE` (repeatable; the
+    /// first match wins).
+    #[arg(long = "template", env = "SITE_SINK_TEMPLATES", value_delimiter = ',')]
+    templates: Vec<String>,
+
+    /// File for matching types, as `PATTERN=PATH` relative to the content
+    /// directory (repeatable; the first match wins).
+    #[arg(long = "output", env = "SITE_SINK_OUTPUTS", value_delimiter = ',')]
+    outputs: Vec<String>,
+
+    /// Commit each page to the git repository the content directory is in.
+    #[arg(long, env = "SITE_SINK_GIT")]
+    git: bool,
+
+    /// Push after each commit.
+    #[arg(long, env = "SITE_SINK_GIT_PUSH", requires = "git")]
+    git_push: bool,
+
+    /// Commit message template; `{path}` is the page's path.
+    #[arg(
+        long,
+        env = "SITE_SINK_COMMIT_MESSAGE",
+        default_value = "Publish {path}"
+    )]
+    commit_message: String,
+
+    /// Committer name, if git has none configured.
+    #[arg(long, env = "SITE_SINK_GIT_NAME")]
+    git_name: Option<String>,
+
+    /// Committer email, if git has none configured.
+    #[arg(long, env = "SITE_SINK_GIT_EMAIL")]
+    git_email: Option<String>,
+
+    /// Timeout for each git command in milliseconds.
+    #[arg(long, env = "SITE_SINK_TIMEOUT", default_value = "60000")]
+    timeout: u64,
+
+    #[command(flatten)]
+    sink: SinkArgs,
+}
+
+/// Writes a page per event.
+struct SiteSink {
+    site: Site,
+    git: Option<Git>,
+    commit_message: String,
+    /// One page at a time, so commits do not race for the git index.
+    writing: Mutex<()>,
+    publisher: OnceLock<Publisher>,
+}
+
+impl SinkHandler for SiteSink {
+    async fn handle(
+        &self,
+        msg: &EmergentMessage,
+        ctx: &SinkContext<'_>,
+    ) -> Result<(), HandlerError> {
+        let id = ctx.message_id().to_string();
+        let vars = Vars {
+            message_type: msg.message_type.as_str(),
+            id: &id,
+            time: time::now(),
+            payload: ctx.payload(),
+        };
+        let page = self
+            .site
+            .page(&vars)
+            .map_err(|e| HandlerError::new(ErrorCategory::Rejected, e))?;
+        let message = self.git.as_ref().map(|_| {
+            substitute(&self.commit_message, |name| match name {
+                "path" => page.path.clone(),
+                name => vars.get(name),
+            })
+        });
+
+        if ctx.is_dry_run() {
+            let detail = json!({
+                "path": page.path,
+                "content": page.content,
+                "commit_message": message,
+            });
+            ctx.would_have("write", detail).await;
+            return Ok(());
+        }
+
+        let _writing = self.writing.lock().await;
+        self.site
+            .write(&page)
+            .map_err(|e| HandlerError::new(ErrorCategory::Internal, e))?;
+        let commit = match (&self.git, &message) {
+            (Some(git), Some(message)) => git
+                .commit(&page.path, message)
+                .await
+                .map_err(|e| HandlerError::new(ErrorCategory::Request, e))?,
+            _ => None,
+        };
+
+        if let Some(publisher) = self.publisher.get() {
+            let written = EmergentMessage::new(WRITTEN_EVENT_TYPE)
+                .with_causation_id(msg.id())
+                .with_payload(json!({
+                    "path": page.path,
+                    "file": self.site.file(&page).display().to_string(),
+                    "commit": commit,
+                    "message_id": id,
+                    "message_type": msg.message_type.as_str(),
+                }));
+            if let Err(e) = publisher.publish(written) {
+                eprintln!("Failed to publish {WRITTEN_EVENT_TYPE}: {e}");
+            }
+        }
+        Ok(())
+    }
+
+    fn self_test(&self, report: &mut Report) {
+        report.check("content directory", check_writable_dir(&self.site.dir));
+        if self.git.is_some() {
+            report.check("git", check_executable("git"));
+        }
+    }
+
+    fn publishes(&self) -> &'static [&'static str] {
+        &[WRITTEN_EVENT_TYPE]
+    }
+
+    fn attach(&self, publisher: Publisher) {
+        let _ = self.publisher.set(publisher);
+    }
+}
+
+/// Run the primitive with `args` as its command line (the first item is
+/// the program name, as with `std::env::args_os`).
+pub async fn run<I, T>(args: I) -> Result<(), Box<dyn std::error::Error>>
+where
+    I: IntoIterator<Item = T>,
+    T: Into<std::ffi::OsString> + Clone,
+{
+    let args = Args::parse_from(args);
+
+    let exit = |e: String| -> ! {
+        eprintln!("Error: {e}");
+        std::process::exit(1);
+    };
+    let templates = args
+        .templates
+        .iter()
+        .map(|rule| {
+            let (pattern, file) = parse_rule(rule).map_err(|e| format!("--template: {e}"))?;
+            Ok((pattern, Template::load(&PathBuf::from(file))?))
+        })
+        .collect::<Result<Vec<_>, String>>()
+        .unwrap_or_else(|e| exit(e));
+    let outputs = args
+        .outputs
+        .iter()
+        .map(|rule| parse_rule(rule).map_err(|e| format!("--output: {e}")))
+        .collect::<Result<Vec<_>, String>>()
+        .unwrap_or_else(|e| exit(e));
+
+    let config = SinkConfig {
+        name: "site_sink",
+        subscribe: &args.subscribe,
+        would_have_as: "site.would_have",
+        dead_letter_as: "site.dead_letter",
+        settings: &args,
+    };
+    let handler = SiteSink {
+        site: Site {
+            dir: args.content_dir.clone(),
+            templates,
+            outputs,
+            fallback: Template::default(),
+        },
+        git: args.git.then(|| Git {
+            dir: args.content_dir.clone(),
+            name: args.git_name.clone(),
+            email: args.git_email.clone(),
+            push: args.git_push,
+            timeout: Duration::from_millis(args.timeout),
+        }),
+        commit_message: args.commit_message.clone(),
+        writing: Mutex::new(()),
+        publisher: OnceLock::new(),
+    };
+
+    run_sink(config, &args.sink, handler).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use emergent_testkit::fixtures::TempDir;
+    use emergent_testkit::{SinkFixture, fixtures, spawn_sink};
+    use template::Format;
+
+    fn handler(dir: &std::path::Path, git: bool) -> SiteSink {
+        let changelog = Template {
+            text: "## {tag}\n\n{notes}\n".to_string(),
+            format: Format::Markdown,
+            ext: "md".to_string(),
+        };
+        SiteSink {
+            site: Site {
+                dir: dir.to_path_buf(),
+                templates: vec![("release.*".to_string(), changelog)],
+                outputs: vec![("release.*".to_string(), "changelog/{tag}.{ext}".to_string())],
+                fallback: Template::default(),
+            },
+            git: git.then(|| Git {
+                dir: dir.to_path_buf(),
+                name: Some("Site Sink".to_string()),
+                email: Some("site@example.com".to_string()),
+                push: false,
+                timeout: Duration::from_secs(30),
+            }),
+            commit_message: "Add {tag} to {path}".to_string(),
+            writing: Mutex::new(()),
+            publisher: OnceLock::new(),
+        }
+    }
+
+    fn git(dir: &std::path::Path, args: &[&str]) -> String {
+        let output = std::process::Command::new("git")
+            .arg("-C")
+            .arg(dir)
+            .args(args)
+            .output()
+            .unwrap_or_else(|e| panic!("git: {e}"));
+        String::from_utf8_lossy(&output.stdout).trim().to_string()
+    }
+
+    #[tokio::test]
+    async fn pages_are_written_and_committed() {
+        let dir = TempDir::new("site-sink");
+        git(dir.path(), &["init", "--quiet"]);
+        let (mut engine, run) = spawn_sink(
+            SinkFixture::new("site_sink", "site"),
+            SinkArgs::default(),
+            handler(dir.path(), true),
+        );
+
+        engine
+            .inject_message(fixtures::message(
+                "release.published",
+                json!({"tag": "v1.2.0", "notes": "Faster builds."}),
+            ))
+            .await;
+        let written = engine.expect_published(WRITTEN_EVENT_TYPE).await;
+        assert_eq!(written.payload()["path"], "changelog/v1.2.0.md");
+        let content = std::fs::read_to_string(dir.path().join("changelog/v1.2.0.md"));
+        assert_eq!(
+            content.ok().as_deref(),
+            Some("## v1.2.0\n\nFaster builds.\n")
+        );
+        let head = git(dir.path(), &["rev-parse", "HEAD"]);
+        assert_eq!(written.payload()["commit"], head.as_str());
+        assert_eq!(
+            git(dir.path(), &["log", "-1", "--format=%s"]),
+            "Add v1.2.0 to changelog/v1.2.0.md"
+        );
+
+        // The same page again changes nothing, so nothing is committed
+        engine
+            .inject_message(fixtures::message(
+                "release.published",
+                json!({"tag": "v1.2.0", "notes": "Faster builds."}),
+            ))
+            .await;
+        let again = engine.expect_published(WRITTEN_EVENT_TYPE).await;
+        assert_eq!(again.payload()["commit"], serde_json::Value::Null);
+
+        engine
+            .inject_message(fixtures::message("til.noted", json!({"text": "hi"})))
+            .await;
+        let note = engine.expect_published(WRITTEN_EVENT_TYPE).await;
+        let path = note.payload()["path"]
+            .as_str()
+            .unwrap_or_default()
+            .to_string();
+        assert!(path.starts_with("til.noted/"), "{path}");
+        assert_eq!(git(dir.path(), &["rev-list", "--count", "HEAD"]), "2");
+
+        engine.close();
+        assert_eq!(run.await.ok(), Some(Ok(())));
+    }
+
+    #[tokio::test]
+    async fn dry_run_reports_the_page() {
+        let dir = TempDir::new("site-sink-dry");
+        let args = SinkArgs {
+            dry_run: true,
+            ..Default::default()
+        };
+        let (mut engine, run) = spawn_sink(
+            SinkFixture::new("site_sink", "site"),
+            args,
+            handler(dir.path(), false),
+        );
+        engine
+            .inject_message(fixtures::message(
+                "release.published",
+                json!({"tag": "v2.0.0", "notes": "Breaking."}),
+            ))
+            .await;
+        let report = engine.expect_published("site.would_have").await;
+        assert_eq!(report.payload()["detail"]["path"], "changelog/v2.0.0.md");
+        assert_eq!(
+            report.payload()["detail"]["content"],
+            "## v2.0.0\n\nBreaking.\n"
+        );
+        assert!(!dir.path().join("changelog").exists());
+
+        engine.close();
+        assert_eq!(run.await.ok(), Some(Ok(())));
+    }
+}
@@ -0,0 +1,133 @@
+//! Which template and path each message type gets, and writing the page.
+//!
+//! `--template` and `--output` take `PATTERN=VALUE` rules, where the
+//! pattern is a message type glob (`*` is any run of characters); the
+//! first rule that matches wins. Types no `--template` matches get a
+//! Markdown page with the payload as JSON, and types no `--output` matches
+//! go to `{type}/{date}-{id}.{ext}`.
+
+use crate::template::{Template, Vars};
+use primitive_common::glob;
+use std::path::{Path, PathBuf};
+
+/// Where pages go unless an `--output` rule says otherwise.
+pub const DEFAULT_OUTPUT: &str = "{type}/{date}-{id}.{ext}";
+
+/// Split a `PATTERN=VALUE` rule.
+pub fn parse_rule(rule: &str) -> Result<(String, String), String> {
+    match rule.split_once('=') {
+        Some((pattern, value)) if !pattern.trim().is_empty() && !value.trim().is_empty() => {
+            Ok((pattern.trim().to_string(), value.trim().to_string()))
+        }
+        _ => Err(format!("expected PATTERN=VALUE, got '{rule}'")),
+    }
+}
+
+fn first<'a, T>(rules: &'a [(String, T)], message_type: &str) -> Option<&'a T> {
+    rules
+        .iter()
+        .find(|(pattern, _)| glob::matches(pattern, message_type))
+        .map(|(_, value)| value)
+}
+
+/// A rendered page, not yet written.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Page {
+    /// Relative to the content directory, with `/` separators.
+    pub path: String,
+    pub content: String,
+}
+
+/// The content directory and the rules for filling it.
+#[derive(Debug)]
+pub struct Site {
+    pub dir: PathBuf,
+    pub templates: Vec<(String, Template)>,
+    pub outputs: Vec<(String, String)>,
+    pub fallback: Template,
+}
+
+impl Site {
+    /// Render the page for one message.
+    pub fn page(&self, vars: &Vars) -> Result<Page, String> {
+        let template = first(&self.templates, vars.message_type).unwrap_or(&self.fallback);
+        let output = first(&self.outputs, vars.message_type)
+            .map(String::as_str)
+            .unwrap_or(DEFAULT_OUTPUT);
+        Ok(Page {
+            path: vars.path(output, &template.ext)?,
+            content: template.render(vars),
+        })
+    }
+
+    pub fn file(&self, page: &Page) -> PathBuf {
+        self.dir.join(Path::new(&page.path))
+    }
+
+    /// Write `page`, replacing any earlier version.
+    pub fn write(&self, page: &Page) -> Result<(), String> {
+        let file = self.file(page);
+        if let Some(parent) = file.parent() {
+            std::fs::create_dir_all(parent).map_err(|e| format!("{}: {e}", parent.display()))?;
+        }
+        std::fs::write(&file, &page.content).map_err(|e| format!("{}: {e}", file.display()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::template::Format;
+    use serde_json::json;
+
+    #[test]
+    fn the_first_matching_rule_wins() {
+        let changelog = Template {
+            text: "## {tag}\n".to_string(),
+            format: Format::Markdown,
+            ext: "md".to_string(),
+        };
+        let site = Site {
+            dir: PathBuf::from("/srv/site/content"),
+            templates: vec![("release.*".to_string(), changelog)],
+            outputs: vec![
+                (
+                    "release.published".to_string(),
+                    "changelog/{tag}.{ext}".to_string(),
+                ),
+                ("*".to_string(), "misc/{id}.{ext}".to_string()),
+            ],
+            fallback: Template::default(),
+        };
+        let payload = json!({"tag": "v1.2.0"});
+        let vars = |message_type| Vars {
+            message_type,
+            id: "msg_1",
+            time: 0,
+            payload: &payload,
+        };
+
+        let release = site.page(&vars("release.published"));
+        assert_eq!(
+            release,
+            Ok(Page {
+                path: "changelog/v1.2.0.md".to_string(),
+                content: "## v1.2.0\n".to_string(),
+            })
+        );
+        let other = site
+            .page(&vars("til.noted"))
+            .unwrap_or_else(|e| panic!("{e}"));
+        assert_eq!(other.path, "misc/msg_1.md");
+        assert!(other.content.starts_with("# til.noted"));
+
+        assert_eq!(
+            parse_rule(" incident.* = templates/postmortem.md "),
+            Ok((
+                "incident.*".to_string(),
+                "templates/postmortem.md".to_string()
+            ))
+        );
+        assert!(parse_rule("templates/postmortem.md").is_err());
+    }
+}
@@ -0,0 +1,217 @@
+//! Templates for page contents and file paths.
+//!
+//! `{field}` is replaced with the payload field at that dotted path
+//! (`{release.tag}`); missing fields become empty, and non-string values
+//! use their JSON encoding. A few names are filled in by the sink instead:
+//!
+//! | Placeholder | Value |
+//! |---|---|
+//! | `{type}` | the message type |
+//! | `{id}` | the message id |
+//! | `{date}` | the UTC date, `2024-05-20` |
+//! | `{time}` | the UTC time, `2024-05-20T16:15:58Z` |
+//! | `{payload}` | the whole payload as indented JSON |
+//! | `{ext}` | the template's file extension (paths only) |
+//!
+//! HTML templates escape what they substitute; in paths, every value is
+//! reduced to a slug so it cannot climb out of the content directory.
+
+use primitive_common::key;
+use primitive_common::template::substitute;
+use serde_json::Value;
+use std::path::Path;
+
+/// How substituted values are written into a page.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Markdown,
+    Html,
+}
+
+/// A page template and what it renders to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Template {
+    pub text: String,
+    pub format: Format,
+    /// Extension for the files it renders, without the dot.
+    pub ext: String,
+}
+
+/// The template for types no `--template` matches.
+const DEFAULT: &str = "# {type}
+
+- id: `{id}`
+- time: {time}
+
+```json
+{payload}
+```
+";
+
+impl Default for Template {
+    fn default() -> Self {
+        Self {
+            text: DEFAULT.to_string(),
+            format: Format::Markdown,
+            ext: "md".to_string(),
+        }
+    }
+}
+
+impl Template {
+    /// Read a template; `.html` and `.htm` files are HTML, anything else
+    /// Markdown.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {e}", path.display()))?;
+        let ext = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .unwrap_or("md")
+            .to_ascii_lowercase();
+        let format = match ext.as_str() {
+            "html" | "htm" => Format::Html,
+            _ => Format::Markdown,
+        };
+        Ok(Self { text, format, ext })
+    }
+
+    pub fn render(&self, vars: &Vars) -> String {
+        match self.format {
+            Format::Markdown => substitute(&self.text, |name| vars.get(name)),
+            Format::Html => substitute(&self.text, |name| escape_html(&vars.get(name))),
+        }
+    }
+}
+
+/// What placeholders are filled from for one message.
+pub struct Vars<'a> {
+    pub message_type: &'a str,
+    pub id: &'a str,
+    /// Seconds since the Unix epoch.
+    pub time: i64,
+    pub payload: &'a Value,
+}
+
+impl Vars<'_> {
+    pub fn get(&self, name: &str) -> String {
+        match name {
+            "type" => self.message_type.to_string(),
+            "id" => self.id.to_string(),
+            "date" => primitive_common::time::date(self.time),
+            "time" => primitive_common::time::format(self.time),
+            "payload" => serde_json::to_string_pretty(self.payload).unwrap_or_default(),
+            field => key::extract(self.payload, field).unwrap_or_default(),
+        }
+    }
+
+    /// A file path from `template`, relative to the content directory.
+    pub fn path(&self, template: &str, ext: &str) -> Result<String, String> {
+        let path = substitute(template, |name| match name {
+            "ext" => slug(ext),
+            name => slug(&self.get(name)),
+        });
+        let inside = !path.starts_with('/')
+            && path
+                .split('/')
+                .all(|part| !part.is_empty() && part != "." && part != "..");
+        if inside {
+            Ok(path)
+        } else {
+            Err(format!(
+                "output path '{path}' is empty or leaves the content directory"
+            ))
+        }
+    }
+}
+
+/// `text` lowercased with every run of characters other than letters,
+/// digits, `.` and `_` turned into one `-`, and no leading dots or dashes.
+pub fn slug(text: &str) -> String {
+    let mut slug = String::with_capacity(text.len());
+    for c in text.chars().flat_map(char::to_lowercase) {
+        if c.is_alphanumeric() || c == '.' || c == '_' {
+            slug.push(c);
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    slug.trim_start_matches(['.', '-'])
+        .trim_end_matches('-')
+        .to_string()
+}
+
+fn escape_html(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn vars(payload: &Value) -> Vars<'_> {
+        Vars {
+            message_type: "release.published",
+            id: "msg_1",
+            time: 1_716_221_758,
+            payload,
+        }
+    }
+
+    #[test]
+    fn templates_substitute_fields_and_builtins() {
+        let payload = json!({"tag": "v1.2.0", "notes": "Fixes <b>bugs</b>", "n": 3});
+        let markdown = Template {
+            text: "## {tag} ({date})\n\n{notes} {n}{missing} {type}".to_string(),
+            format: Format::Markdown,
+            ext: "md".to_string(),
+        };
+        assert_eq!(
+            markdown.render(&vars(&payload)),
+            "## v1.2.0 (2024-05-20)\n\nFixes <b>bugs</b> 3 release.published"
+        );
+        let html = Template {
+            text: "<p>{notes}</p>".to_string(),
+            format: Format::Html,
+            ext: "html".to_string(),
+        };
+        assert_eq!(
+            html.render(&vars(&payload)),
+            "<p>Fixes &lt;b&gt;bugs&lt;/b&gt;</p>"
+        );
+        assert!(
+            Template::default()
+                .render(&vars(&payload))
+                .contains("\"tag\": \"v1.2.0\"")
+        );
+    }
+
+    #[test]
+    fn paths_are_slugged_and_stay_inside() {
+        let payload = json!({"title": "Outage: DB / Primary!", "up": "../../etc"});
+        let vars = vars(&payload);
+        assert_eq!(
+            vars.path("incidents/{date}-{title}.{ext}", "md").as_deref(),
+            Ok("incidents/2024-05-20-outage-db-primary.md")
+        );
+        assert_eq!(
+            vars.path("{up}/{type}.{ext}", "html").as_deref(),
+            Ok("etc/release.published.html")
+        );
+        assert!(vars.path("/abs/{id}.md", "md").is_err());
+        assert!(vars.path("../{id}.md", "md").is_err());
+        assert!(vars.path("{missing}/{id}.md", "md").is_err());
+        assert_eq!(slug("  Hello, World  "), "hello-world");
+    }
+}
@@ -0,0 +1,753 @@
+//! SLA
+//!
+//! A Handler that times how long work takes between two events: a start
+//! event begins a timer for its correlation key, and a matching end event
+//! must follow within the allowed time. Each timer ends in `sla.met`, with
+//! the measured latency, or `sla.breached` when time runs out first.
+//!
+//! # Data Flow
+//!
+//! 1. Receive a `--start` event and start a timer for its `--key` value,
+//!    allowing `--within` milliseconds, or the first `--override` whose
+//!    pattern matches the key
+//! 2. Receive an `--end` event with the same key value and publish
+//!    `sla.met`, or publish `sla.breached` once the deadline passes
+//!
+//! A type matching both `--start` and `--end` starts timers. A second
+//! start event for a pending key leaves the first timer running, and an
+//! end event with no pending timer (never started, or already
+//! breached) is ignored. With `--state-file`, pending timers survive
+//! restarts; those whose deadline passed meanwhile breach on startup.
+//!
+//! # Messages Published
+//!
+//! - `sla.met`: the key, the start and end types and ids, `latency_ms` and
+//!   `within_ms`
+//! - `sla.breached`: the key, the start type and id, `within_ms` and the
+//!   deadline
+//!
+//! Both carry the handler's name as `sla`, so several trackers can share
+//! an engine.
+//!
+//! # Usage
+//!
+//! ```bash
+//! # Orders must ship within a day
+//! sla --start order.placed --end order.shipped --key order_id --within 86400000
+//!
+//! # Tickets get a first response within 4 hours, 30 minutes for VIPs
+//! sla --start ticket.opened --end ticket.responded --key ticket.id \
+//!   --within 14400000 --override 'vip-*=1800000' \
+//!   --state-file /var/lib/emergent/sla-tickets.json
+//! ```
+
+pub mod timers;
+
+use clap::Parser;
+use emergent_client::EmergentMessage;
+use emergent_client::types::CausationId;
+use primitive_common::capabilities::{self, CapabilityArgs, Role, VERSION};
+use primitive_common::crypto::{KeyArgs, Keyring};
+use primitive_common::doctor::{Report, check_writable_dir};
+use primitive_common::errors::{ERROR_EVENT_TYPE, ErrorArgs, ErrorCategory};
+use primitive_common::glob;
+use primitive_common::handler::{Bus, Flow, HandlerConfig, MessageHandler, run_handler};
+use primitive_common::key;
+use primitive_common::payload::{self, PayloadArgs};
+use primitive_common::time;
+use serde_json::{Value, json};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use timers::{Timer, Timers};
+
+/// Message type published when an end event arrives in time.
+pub const MET_EVENT_TYPE: &str = "sla.met";
+
+/// Message type published when a timer runs out.
+pub const BREACHED_EVENT_TYPE: &str = "sla.breached";
+
+/// SLA — time the work between start and end events.
+#[derive(Parser, Debug)]
+#[command(name = "sla", version = VERSION)]
+#[command(
+    about = "Time start-to-end event pairs by correlation key, publishing sla.met or sla.breached"
+)]
+struct Args {
+    /// Message types that start a timer (`*` matches any run of characters); repeatable.
+    #[arg(long, env = "SLA_START", value_delimiter = ',', required = true)]
+    start: Vec<String>,
+
+    /// Message types that stop it; repeatable.
+    #[arg(long, env = "SLA_END", value_delimiter = ',', required = true)]
+    end: Vec<String>,
+
+    /// Payload field (dotted path) correlating start and end events.
+    #[arg(long, env = "SLA_KEY", default_value = "correlation_id")]
+    key: String,
+
+    /// Payload field holding the key in end events, when it differs from `--key`.
+    #[arg(long, env = "SLA_END_KEY")]
+    end_key: Option<String>,
+
+    /// Milliseconds allowed between start and end.
+    #[arg(long, env = "SLA_WITHIN")]
+    within: u64,
+
+    /// Allowance for keys matching a pattern, as `pattern=ms` (e.g. `vip-*=60000`); first match wins, repeatable.
+    #[arg(long = "override", env = "SLA_OVERRIDES", value_delimiter = ',', value_parser = parse_override)]
+    overrides: Vec<(String, u64)>,
+
+    /// File keeping pending timers across restarts.
+    #[arg(long, env = "SLA_STATE_FILE")]
+    state_file: Option<PathBuf>,
+
+    /// Most timers pending at once; start events beyond it are dropped.
+    #[arg(long, env = "SLA_MAX_PENDING", default_value = "100000")]
+    max_pending: usize,
+
+    #[command(flatten)]
+    payload: PayloadArgs,
+
+    /// Verify the configuration and exit without connecting to the engine.
+    #[arg(long)]
+    self_test: bool,
+
+    #[command(flatten)]
+    errors: ErrorArgs,
+
+    #[command(flatten)]
+    capabilities: CapabilityArgs,
+
+    #[command(flatten)]
+    keys: KeyArgs,
+}
+
+/// Parse an `--override` value: `pattern=ms`.
+fn parse_override(value: &str) -> Result<(String, u64), String> {
+    let (pattern, within) = value
+        .split_once('=')
+        .ok_or_else(|| format!("'{value}': expected pattern=ms"))?;
+    let within = within
+        .trim()
+        .parse()
+        .map_err(|_| format!("'{value}': '{within}' is not a number of milliseconds"))?;
+    Ok((pattern.trim().to_string(), within))
+}
+
+/// The milliseconds allowed for `key`.
+fn allowance(args: &Args, key: &str) -> u64 {
+    args.overrides
+        .iter()
+        .find(|(pattern, _)| glob::matches(pattern, key))
+        .map_or(args.within, |(_, within)| *within)
+}
+
+/// The correlation key in `input`, read from `field`.
+fn correlation_key(input: &Value, field: &str) -> Option<String> {
+    match key::lookup(input, field)? {
+        Value::Null => None,
+        Value::String(s) if s.is_empty() => None,
+        Value::String(s) => Some(s.clone()),
+        other => Some(other.to_string()),
+    }
+}
+
+/// A received event, as the tracker sees it.
+struct Event<'a> {
+    message_type: &'a str,
+    message_id: String,
+    cause: Option<CausationId>,
+    payload: &'a Value,
+}
+
+/// What an event did to the timers.
+#[derive(Debug, PartialEq)]
+enum Outcome {
+    /// It stopped a timer in time: the `sla.met` payload.
+    Met(Value),
+    Started,
+    /// A start event for a key already being timed.
+    AlreadyStarted,
+    /// An end event without a pending timer.
+    Unmatched,
+    /// Neither a start nor an end event (or one of ours).
+    Ignored,
+}
+
+/// Apply `event` at `now` (milliseconds since the Unix epoch).
+fn observe(
+    args: &Args,
+    name: &str,
+    timers: &mut Timers,
+    event: &Event<'_>,
+    now: u64,
+) -> Result<Outcome, String> {
+    let message_type = event.message_type;
+    if matches!(message_type, MET_EVENT_TYPE | BREACHED_EVENT_TYPE) {
+        return Ok(Outcome::Ignored);
+    }
+    let is_start = args
+        .start
+        .iter()
+        .any(|pattern| glob::matches(pattern, message_type));
+    if !is_start
+        && args
+            .end
+            .iter()
+            .any(|pattern| glob::matches(pattern, message_type))
+    {
+        let field = args.end_key.as_deref().unwrap_or(&args.key);
+        let key = correlation_key(event.payload, field)
+            .ok_or_else(|| format!("{message_type} has no '{field}' to correlate by"))?;
+        if let Some(timer) = timers.stop(&key) {
+            let latency = now.saturating_sub(timer.started_at);
+            // The deadline may have passed since the last check
+            if latency <= timer.within {
+                return Ok(Outcome::Met(json!({
+                    "sla": name,
+                    "key": key,
+                    "start_type": timer.start_type,
+                    "start_id": timer.start_id,
+                    "end_type": message_type,
+                    "end_id": event.message_id,
+                    "started_at": time::format((timer.started_at / 1000) as i64),
+                    "latency_ms": latency,
+                    "within_ms": timer.within,
+                })));
+            }
+            timers.start(key, timer);
+        }
+        return Ok(Outcome::Unmatched);
+    }
+    if !is_start {
+        return Ok(Outcome::Ignored);
+    }
+    let key = correlation_key(event.payload, &args.key)
+        .ok_or_else(|| format!("{message_type} has no '{}' to correlate by", args.key))?;
+    if timers.contains(&key) {
+        return Ok(Outcome::AlreadyStarted);
+    }
+    if timers.len() >= args.max_pending {
+        return Err(format!(
+            "{} timers pending (--max-pending); not timing {key}",
+            timers.len()
+        ));
+    }
+    let within = allowance(args, &key);
+    timers.start(
+        key,
+        Timer {
+            start_type: message_type.to_string(),
+            start_id: event.message_id.clone(),
+            started_at: now,
+            within,
+            cause: event.cause.clone(),
+        },
+    );
+    Ok(Outcome::Started)
+}
+
+/// The `sla.breached` payloads of timers past their deadline at `now`,
+/// with their start messages.
+fn breaches(name: &str, timers: &mut Timers, now: u64) -> Vec<(Value, Option<CausationId>)> {
+    timers
+        .expired(now)
+        .into_iter()
+        .map(|(key, timer)| {
+            let payload = json!({
+                "sla": name,
+                "key": key,
+                "start_type": timer.start_type,
+                "start_id": timer.start_id,
+                "started_at": time::format((timer.started_at / 1000) as i64),
+                "deadline": time::format((timer.deadline() / 1000) as i64),
+                "within_ms": timer.within,
+            });
+            (payload, timer.cause)
+        })
+        .collect()
+}
+
+/// Run the primitive with `args` as its command line (the first item is
+/// the program name, as with `std::env::args_os`).
+pub async fn run<I, T>(args: I) -> Result<(), Box<dyn std::error::Error>>
+where
+    I: IntoIterator<Item = T>,
+    T: Into<std::ffi::OsString> + Clone,
+{
+    let args = Args::parse_from(args);
+
+    // Get the handler name from environment (set by engine) or use default
+    let name = std::env::var("EMERGENT_NAME").unwrap_or_else(|_| "sla".to_string());
+
+    let timers = Timers::load(args.state_file.as_deref());
+    if args.self_test {
+        let mut report = Report::new(&name);
+        if let Some(path) = &args.state_file {
+            let dir = path
+                .parent()
+                .filter(|p| !p.as_os_str().is_empty())
+                .unwrap_or(Path::new("."));
+            report.check("state-file", check_writable_dir(dir));
+        }
+        report.check(
+            "timers",
+            timers
+                .as_ref()
+                .map(|timers| format!("{} pending", timers.len()))
+                .map_err(Clone::clone),
+        );
+        args.payload.self_test(&mut report);
+        args.keys.self_test(&mut report);
+        report.finish();
+    }
+    let timers = match timers {
+        Ok(timers) => timers,
+        Err(e) => {
+            eprintln!("Error: invalid state file {e}");
+            std::process::exit(1);
+        }
+    };
+
+    if let Err(e) = args.payload.encryption.validate() {
+        eprintln!("Error: {e}");
+        std::process::exit(1);
+    }
+    let keys = match args.keys.load() {
+        Ok(keys) => keys,
+        Err(e) => {
+            eprintln!("Error: failed to load identity file: {e}");
+            std::process::exit(1);
+        }
+    };
+
+    let mut topics: Vec<String> = Vec::new();
+    for topic in args.start.iter().chain(&args.end) {
+        if !topics.contains(topic) {
+            topics.push(topic.clone());
+        }
+    }
+    let topics_refs: Vec<&str> = topics.iter().map(String::as_str).collect();
+    let mut produces = vec![MET_EVENT_TYPE, BREACHED_EVENT_TYPE];
+    if args.errors.emit_errors {
+        produces.push(ERROR_EVENT_TYPE);
+    }
+    let descriptor =
+        args.capabilities
+            .describe(&name, Role::Handler, &topics_refs, &produces, &args);
+    if args.capabilities.capabilities {
+        capabilities::print(&descriptor);
+    }
+
+    let config = HandlerConfig {
+        name: &name,
+        subscribe: &topics,
+        announce: args.capabilities.announce.then_some(&descriptor),
+        emit_errors: args.errors.emit_errors,
+    };
+    let mut sla = Sla {
+        args: &args,
+        timers,
+        keys,
+    };
+    run_handler(config, &mut sla).await
+}
+
+/// Write the timers to the state file, reporting failures.
+fn save(timers: &Timers) {
+    if let Err(e) = timers.save() {
+        eprintln!("sla: failed to save timers: {e}");
+    }
+}
+
+/// The timers and what they need to read payloads.
+struct Sla<'a> {
+    args: &'a Args,
+    timers: Timers,
+    keys: Option<Keyring>,
+}
+
+impl MessageHandler for Sla<'_> {
+    type Wake = ();
+
+    /// Start or stop a timer for one message.
+    async fn handle(&mut self, msg: &EmergentMessage, bus: &Bus) {
+        let args = self.args;
+        let input = match payload::decode(msg.payload(), self.keys.as_ref()).await {
+            Ok(p) => p,
+            Err(e) => {
+                let error = format!("failed to decode payload: {e}");
+                eprintln!("sla: {error}");
+                bus.report_error(msg, ErrorCategory::Parse, &error).await;
+                return;
+            }
+        };
+        let event = Event {
+            message_type: msg.message_type.as_str(),
+            message_id: msg.id().to_string(),
+            cause: Some(CausationId::from(msg.id())),
+            payload: &input,
+        };
+        match observe(args, bus.name(), &mut self.timers, &event, time::now_ms()) {
+            Ok(Outcome::Met(payload)) => {
+                save(&self.timers);
+                publish(bus, args, MET_EVENT_TYPE, payload, event.cause).await;
+            }
+            Ok(Outcome::Started) => save(&self.timers),
+            Ok(Outcome::AlreadyStarted | Outcome::Unmatched | Outcome::Ignored) => {}
+            Err(error) => {
+                eprintln!("sla: {error}");
+                bus.report_error(msg, ErrorCategory::Parse, &error).await;
+            }
+        }
+    }
+
+    /// Sleep until the earliest deadline.
+    async fn wake(&mut self) {
+        match self.timers.next_deadline() {
+            Some(at) => {
+                let wait = at.saturating_sub(time::now_ms());
+                tokio::time::sleep(Duration::from_millis(wait)).await;
+            }
+            None => std::future::pending().await,
+        }
+    }
+
+    async fn woken(&mut self, (): (), bus: &Bus) -> Flow {
+        for (payload, cause) in breaches(bus.name(), &mut self.timers, time::now_ms()) {
+            publish(bus, self.args, BREACHED_EVENT_TYPE, payload, cause).await;
+        }
+        save(&self.timers);
+        Flow::Continue
+    }
+}
+
+/// Publish an `sla.*` event.
+async fn publish(
+    bus: &Bus,
+    args: &Args,
+    message_type: &str,
+    payload: Value,
+    cause: Option<CausationId>,
+) {
+    let payload = match payload::encode(message_type, payload, &args.payload).await {
+        Ok(p) => p,
+        Err(e) => {
+            eprintln!("sla: failed to encode {message_type}: {e}");
+            return;
+        }
+    };
+    let mut message = EmergentMessage::new(message_type).with_payload(payload);
+    if let Some(cause) = cause {
+        message = message.with_causation_id(cause);
+    }
+    bus.publish(message).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use emergent_client::EmergentHandler;
+    use emergent_testkit::{MockEngine, fixtures};
+
+    fn args(extra: &[&str]) -> Args {
+        let mut argv = vec!["sla", "--start", "ticket.opened", "--end", "ticket.*"];
+        argv.extend_from_slice(extra);
+        Args::try_parse_from(argv).unwrap_or_else(|e| panic!("{e}"))
+    }
+
+    fn event<'a>(message_type: &'a str, id: &str, payload: &'a Value) -> Event<'a> {
+        Event {
+            message_type,
+            message_id: id.to_string(),
+            cause: None,
+            payload,
+        }
+    }
+
+    #[test]
+    fn end_events_in_time_meet_the_sla_and_late_ones_breach() {
+        let args = args(&[
+            "--key",
+            "ticket.id",
+            "--within",
+            "1000",
+            "--override",
+            "vip-*=100",
+        ]);
+        let mut timers = Timers::default();
+        let opened = |id: &str| json!({"ticket": {"id": id}});
+
+        let payload = opened("t1");
+        let start = event("ticket.opened", "msg_1", &payload);
+        assert_eq!(
+            observe(&args, "sla", &mut timers, &start, 10_000),
+            Ok(Outcome::Started)
+        );
+        assert_eq!(
+            observe(&args, "sla", &mut timers, &start, 10_100),
+            Ok(Outcome::AlreadyStarted)
+        );
+        let end = event("ticket.responded", "msg_2", &payload);
+        let Ok(Outcome::Met(met)) = observe(&args, "sla", &mut timers, &end, 10_250) else {
+            panic!("expected sla.met");
+        };
+        assert_eq!(met["key"], "t1");
+        assert_eq!(met["latency_ms"], 250);
+        assert_eq!(met["within_ms"], 1000);
+        assert_eq!(met["start_id"], "msg_1");
+        assert_eq!(met["end_id"], "msg_2");
+        assert_eq!(met["started_at"], "1970-01-01T00:00:10Z");
+        // Already stopped
+        assert_eq!(
+            observe(&args, "sla", &mut timers, &end, 10_300),
+            Ok(Outcome::Unmatched)
+        );
+
+        let payload = opened("vip-7");
+        let vip = event("ticket.opened", "msg_3", &payload);
+        assert_eq!(
+            observe(&args, "sla", &mut timers, &vip, 20_000),
+            Ok(Outcome::Started)
+        );
+        assert!(breaches("sla", &mut timers, 20_099).is_empty());
+        let breached = breaches("sla", &mut timers, 20_100);
+        assert_eq!(breached.len(), 1);
+        assert_eq!(breached[0].0["key"], "vip-7");
+        assert_eq!(breached[0].0["within_ms"], 100);
+        assert_eq!(breached[0].0["deadline"], "1970-01-01T00:00:20Z");
+        assert!(timers.is_empty());
+
+        // An end that arrives after the deadline but before the check is late
+        let payload = opened("t2");
+        let start = event("ticket.opened", "msg_4", &payload);
+        let end = event("ticket.closed", "msg_5", &payload);
+        assert_eq!(
+            observe(&args, "sla", &mut timers, &start, 30_000),
+            Ok(Outcome::Started)
+        );
+        assert_eq!(
+            observe(&args, "sla", &mut timers, &end, 31_001),
+            Ok(Outcome::Unmatched)
+        );
+        assert_eq!(breaches("sla", &mut timers, 31_001).len(), 1);
+
+        let nameless = json!({"subject": "no id"});
+        assert!(
+            observe(
+                &args,
+                "sla",
+                &mut timers,
+                &event("ticket.opened", "msg_6", &nameless),
+                0
+            )
+            .is_err()
+        );
+        let other = event("user.created", "msg_7", &payload);
+        assert_eq!(
+            observe(&args, "sla", &mut timers, &other, 0),
+            Ok(Outcome::Ignored)
+        );
+        assert!(
+            Args::try_parse_from([
+                "sla",
+                "--start",
+                "a",
+                "--end",
+                "b",
+                "--within",
+                "1",
+                "--override",
+                "x"
+            ])
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn keys_are_read_and_pending_timers_capped() {
+        let args = args(&[
+            "--start",
+            "sla.*",
+            "--key",
+            "ticket",
+            "--end-key",
+            "id",
+            "--within",
+            "1000",
+            "--max-pending",
+            "1",
+        ]);
+        let mut timers = Timers::default();
+        let numbered = json!({"ticket": 42});
+        let start = event("ticket.opened", "msg_1", &numbered);
+        assert_eq!(
+            observe(&args, "sla", &mut timers, &start, 0),
+            Ok(Outcome::Started)
+        );
+        assert!(timers.contains("42"));
+
+        // Beyond --max-pending a start is refused until a timer stops
+        let second = json!({"ticket": "t2"});
+        let refused = observe(
+            &args,
+            "sla",
+            &mut timers,
+            &event("ticket.opened", "msg_2", &second),
+            10,
+        );
+        assert_eq!(
+            refused,
+            Err("1 timers pending (--max-pending); not timing t2".to_string())
+        );
+        // End events carry the key under --end-key
+        let answered = json!({"id": 42});
+        let end = event("ticket.responded", "msg_3", &answered);
+        assert!(matches!(
+            observe(&args, "sla", &mut timers, &end, 20),
+            Ok(Outcome::Met(_))
+        ));
+        assert_eq!(
+            observe(
+                &args,
+                "sla",
+                &mut timers,
+                &event("ticket.opened", "msg_4", &second),
+                30
+            ),
+            Ok(Outcome::Started)
+        );
+
+        for missing in [json!({}), json!({"id": null}), json!({"id": ""})] {
+            let end = event("ticket.closed", "msg_5", &missing);
+            assert_eq!(
+                observe(&args, "sla", &mut timers, &end, 40),
+                Err("ticket.closed has no 'id' to correlate by".to_string())
+            );
+        }
+        // Our own events never start timers, even when a pattern matches
+        let met = json!({"ticket": "t3"});
+        assert_eq!(
+            observe(
+                &args,
+                "sla",
+                &mut timers,
+                &event(MET_EVENT_TYPE, "msg_6", &met),
+                50
+            ),
+            Ok(Outcome::Ignored)
+        );
+        assert_eq!(timers.len(), 1);
+
+        let error = Args::try_parse_from([
+            "sla",
+            "--start",
+            "a",
+            "--end",
+            "b",
+            "--within",
+            "1",
+            "--override",
+            "x=soon",
+        ])
+        .err()
+        .map(|e| e.to_string())
+        .unwrap_or_default();
+        assert!(
+            error.contains("'x=soon': 'soon' is not a number of milliseconds"),
+            "{error}"
+        );
+    }
+
+    #[test]
+    fn timers_restored_past_their_deadline_breach_at_once() {
+        let dir = fixtures::TempDir::new("sla-restart");
+        let path = dir.path().join("timers.json");
+        let args = args(&["--key", "ticket.id", "--within", "1000"]);
+        let payload = json!({"ticket": {"id": "t1"}});
+
+        let mut timers = Timers::load(Some(&path)).unwrap_or_else(|e| panic!("{e}"));
+        let opened = fixtures::message("ticket.opened", payload.clone());
+        let start = Event {
+            message_type: "ticket.opened",
+            message_id: opened.id().to_string(),
+            cause: Some(CausationId::from(opened.id())),
+            payload: &payload,
+        };
+        assert_eq!(
+            observe(&args, "sla", &mut timers, &start, 5_000),
+            Ok(Outcome::Started)
+        );
+        timers.save().unwrap_or_else(|e| panic!("{e}"));
+        drop(timers);
+
+        // Down past the deadline: the restored timer breaches on the first check
+        let mut restored = Timers::load(Some(&path)).unwrap_or_else(|e| panic!("{e}"));
+        assert_eq!(restored.next_deadline(), Some(6_000));
+        let breached = breaches("sla", &mut restored, 60_000);
+        assert_eq!(breached.len(), 1);
+        let (breach, cause) = &breached[0];
+        assert_eq!(breach["key"], "t1");
+        assert_eq!(breach["start_id"], opened.id().to_string());
+        assert_eq!(breach["deadline"], "1970-01-01T00:00:06Z");
+        // The start message is not kept, so the breach has no cause
+        assert_eq!(cause, &None);
+        // A late end event finds nothing to stop
+        let end = event("ticket.closed", "msg_2", &payload);
+        assert_eq!(
+            observe(&args, "sla", &mut restored, &end, 60_001),
+            Ok(Outcome::Unmatched)
+        );
+    }
+
+    #[tokio::test]
+    async fn undecodable_and_unkeyed_events_are_reported() {
+        let dir = fixtures::TempDir::new("sla-errors");
+        let socket = dir.path().join("engine.sock");
+        let mut engine = MockEngine::serve(&socket);
+        let handler = EmergentHandler::connect_to("sla", &socket)
+            .await
+            .unwrap_or_else(|e| panic!("connect: {e}"));
+        let args = args(&["--key", "ticket.id", "--within", "60000", "--emit-errors"]);
+        let bus = Bus::new(handler, "sla", args.errors.emit_errors);
+        let mut sla = Sla {
+            args: &args,
+            timers: Timers::default(),
+            keys: None,
+        };
+
+        let garbled = fixtures::message(
+            "ticket.opened",
+            json!({"$emergent": "zstd", "data": "not base64!"}),
+        );
+        sla.handle(&garbled, &bus).await;
+        let error = engine.expect_published(ERROR_EVENT_TYPE).await;
+        assert_eq!(error.payload()["category"], "parse");
+        assert_eq!(error.payload()["disposition"], "dropped");
+        assert_eq!(error.payload()["message_id"], garbled.id().to_string());
+        let text = error.payload()["error"].as_str().unwrap_or_default();
+        assert!(text.starts_with("failed to decode payload: "), "{text}");
+
+        let unkeyed = fixtures::message("ticket.opened", json!({"subject": "help"}));
+        sla.handle(&unkeyed, &bus).await;
+        let error = engine.expect_published(ERROR_EVENT_TYPE).await;
+        assert_eq!(
+            error.payload()["error"],
+            "ticket.opened has no 'ticket.id' to correlate by"
+        );
+        assert!(sla.timers.is_empty());
+
+        // A good pair still goes through, caused by the end event
+        let opened = fixtures::message("ticket.opened", json!({"ticket": {"id": "t1"}}));
+        sla.handle(&opened, &bus).await;
+        let closed = fixtures::message("ticket.closed", json!({"ticket": {"id": "t1"}}));
+        sla.handle(&closed, &bus).await;
+        let met = engine.expect_published(MET_EVENT_TYPE).await;
+        assert_eq!(met.payload()["start_id"], opened.id().to_string());
+        assert_eq!(
+            met.causation_id.as_ref().map(ToString::to_string),
+            Some(closed.id().to_string())
+        );
+        engine.expect_quiet(Duration::from_millis(100)).await;
+    }
+}
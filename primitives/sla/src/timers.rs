@@ -0,0 +1,178 @@
+//! Pending timers, one per correlation key, optionally kept in a state
+//! file so they survive restarts.
+//!
+//! The file is rewritten after every change, through a temporary file so a
+//! crash never leaves half of it. Timers restored past their deadline
+//! breach as soon as the handler starts.
+
+use emergent_client::types::CausationId;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// A started clock waiting for its end event.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Timer {
+    /// Type and id of the message that started it.
+    pub start_type: String,
+    pub start_id: String,
+    /// Milliseconds since the Unix epoch.
+    pub started_at: u64,
+    /// Milliseconds allowed.
+    pub within: u64,
+    /// The start message, for causation; not kept across restarts.
+    #[serde(skip)]
+    pub cause: Option<CausationId>,
+}
+
+impl Timer {
+    /// When the timer breaches, in milliseconds since the Unix epoch.
+    pub fn deadline(&self) -> u64 {
+        self.started_at.saturating_add(self.within)
+    }
+}
+
+/// The pending timers.
+#[derive(Debug, Default)]
+pub struct Timers {
+    pending: BTreeMap<String, Timer>,
+    path: Option<PathBuf>,
+}
+
+impl Timers {
+    /// The timers in `path`, or none without one; a missing file has none.
+    pub fn load(path: Option<&Path>) -> Result<Self, String> {
+        let Some(path) = path else {
+            return Ok(Self::default());
+        };
+        let pending = match fs::read_to_string(path) {
+            Ok(text) => {
+                serde_json::from_str(&text).map_err(|e| format!("{}: {e}", path.display()))?
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => return Err(format!("{}: {e}", path.display())),
+        };
+        Ok(Self {
+            pending,
+            path: Some(path.to_path_buf()),
+        })
+    }
+
+    /// How many timers are pending.
+    pub fn len(&self) -> usize {
+        self.pending.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+
+    /// Whether a timer is pending for `key`.
+    pub fn contains(&self, key: &str) -> bool {
+        self.pending.contains_key(key)
+    }
+
+    /// Start `timer` for `key`.
+    pub fn start(&mut self, key: String, timer: Timer) {
+        self.pending.insert(key, timer);
+    }
+
+    /// Stop and return the timer for `key`.
+    pub fn stop(&mut self, key: &str) -> Option<Timer> {
+        self.pending.remove(key)
+    }
+
+    /// The earliest deadline, if any timer is pending.
+    pub fn next_deadline(&self) -> Option<u64> {
+        self.pending.values().map(Timer::deadline).min()
+    }
+
+    /// Remove and return the timers whose deadline is at or before `now`,
+    /// earliest first.
+    pub fn expired(&mut self, now: u64) -> Vec<(String, Timer)> {
+        let keys: Vec<String> = self
+            .pending
+            .iter()
+            .filter(|(_, timer)| timer.deadline() <= now)
+            .map(|(key, _)| key.clone())
+            .collect();
+        let mut expired: Vec<(String, Timer)> = keys
+            .into_iter()
+            .filter_map(|key| self.pending.remove(&key).map(|timer| (key, timer)))
+            .collect();
+        expired.sort_by_key(|(_, timer)| timer.deadline());
+        expired
+    }
+
+    /// Write the pending timers to the state file, if there is one.
+    pub fn save(&self) -> Result<(), String> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let state = serde_json::to_vec_pretty(&self.pending).map_err(|e| e.to_string())?;
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, state)
+            .and_then(|()| fs::rename(&tmp, path))
+            .map_err(|e| format!("{}: {e}", path.display()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use emergent_testkit::fixtures::TempDir;
+
+    fn timer(started_at: u64, within: u64) -> Timer {
+        Timer {
+            start_type: "order.placed".to_string(),
+            start_id: format!("msg_{started_at}"),
+            started_at,
+            within,
+            cause: None,
+        }
+    }
+
+    #[test]
+    fn timers_expire_by_deadline_and_survive_restarts() {
+        let dir = TempDir::new("sla");
+        let path = dir.path().join("timers.json");
+        let mut timers = Timers::load(Some(&path)).unwrap_or_else(|e| panic!("{e}"));
+        assert!(timers.is_empty());
+        timers.start("a".to_string(), timer(1000, 5000));
+        timers.start("b".to_string(), timer(2000, 1000));
+        timers.start("c".to_string(), timer(3000, 60_000));
+        timers.save().unwrap_or_else(|e| panic!("{e}"));
+        assert_eq!(timers.next_deadline(), Some(3000));
+
+        let mut restored = Timers::load(Some(&path)).unwrap_or_else(|e| panic!("{e}"));
+        assert_eq!(restored.len(), 3);
+        let expired: Vec<String> = restored.expired(6000).into_iter().map(|(k, _)| k).collect();
+        assert_eq!(expired, ["b", "a"]);
+        assert_eq!(restored.stop("c").map(|t| t.within), Some(60_000));
+        assert!(!restored.contains("c"));
+        assert_eq!(restored.next_deadline(), None);
+
+        std::fs::write(&path, "not json").unwrap_or_else(|e| panic!("{e}"));
+        assert!(Timers::load(Some(&path)).is_err());
+    }
+
+    #[test]
+    fn unreadable_and_unwritable_state_files_are_reported() {
+        let dir = TempDir::new("sla-state");
+        // A directory where the file should be
+        let error = Timers::load(Some(dir.path())).err().unwrap_or_default();
+        assert!(
+            error.starts_with(&dir.path().display().to_string()),
+            "{error}"
+        );
+
+        let path = dir.path().join("gone/timers.json");
+        let mut timers = Timers::load(Some(&path)).unwrap_or_else(|e| panic!("{e}"));
+        timers.start("a".to_string(), timer(1000, 5000));
+        let error = timers.save().err().unwrap_or_default();
+        assert!(error.starts_with(&path.display().to_string()), "{error}");
+        // The timers themselves are kept for the next save
+        assert!(timers.contains("a"));
+    }
+}
@@ -0,0 +1,197 @@
+//! Approval requests and the Block Kit messages that carry them.
+//!
+//! A payload with `op: request_approval` becomes a message with Approve and
+//! Deny buttons:
+//!
+//! ```json
+//! {"op": "request_approval", "correlation_id": "deploy-4711", "channel": "#releases",
+//!  "title": "Deploy api 2.1 to production?", "text": "Canary error rate 0.02%",
+//!  "fields": {"Service": "api", "Version": "2.1.0"}, "approvers": ["U061F7AUR"]}
+//! ```
+//!
+//! The buttons carry the correlation id, the request's message id and the
+//! allowed approvers, so a decision can be turned into an event without
+//! any state kept between request and click (see [`crate::interactions`]).
+//! Once decided, the buttons are replaced by who decided and how.
+
+use serde::Deserialize;
+use serde_json::{Map, Value, json};
+
+/// `block_id` of the buttons, which identifies approval clicks.
+pub const ACTIONS_BLOCK: &str = "emergent_approval";
+/// `action_id`s of the two buttons.
+pub const APPROVE_ACTION: &str = "approve";
+pub const DENY_ACTION: &str = "deny";
+
+/// Slack shows at most 10 fields in a section.
+const MAX_FIELDS: usize = 10;
+
+fn approve_label() -> String {
+    "Approve".to_string()
+}
+
+fn deny_label() -> String {
+    "Deny".to_string()
+}
+
+/// A `request_approval` payload.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct ApprovalRequest {
+    /// Echoed in the decision event, to match it with the request.
+    pub correlation_id: String,
+    /// Channel id or name; `--channel` when absent.
+    #[serde(default)]
+    pub channel: Option<String>,
+    pub title: String,
+    #[serde(default)]
+    pub text: Option<String>,
+    /// Shown as labelled fields.
+    #[serde(default)]
+    pub fields: Map<String, Value>,
+    /// User ids allowed to decide; anyone in the channel when empty.
+    #[serde(default)]
+    pub approvers: Vec<String>,
+    #[serde(default = "approve_label")]
+    pub approve_label: String,
+    #[serde(default = "deny_label")]
+    pub deny_label: String,
+}
+
+/// What the buttons carry back when clicked.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct ButtonValue {
+    pub correlation_id: String,
+    pub request_id: String,
+    #[serde(default)]
+    pub approvers: Vec<String>,
+}
+
+impl ApprovalRequest {
+    /// The `chat.postMessage` body for this request, sent to `channel`.
+    /// `request_id` is the id of the message that asked for approval.
+    pub fn message(&self, channel: &str, request_id: &str) -> Value {
+        let value = json!({
+            "correlation_id": self.correlation_id,
+            "request_id": request_id,
+            "approvers": self.approvers,
+        })
+        .to_string();
+        let button = |action: &str, label: &str, style: &str| {
+            json!({
+                "type": "button",
+                "action_id": action,
+                "style": style,
+                "text": {"type": "plain_text", "text": label},
+                "value": value,
+            })
+        };
+
+        let mut heading = format!("*{}*", self.title);
+        if let Some(text) = &self.text {
+            heading.push('\n');
+            heading.push_str(text);
+        }
+        let mut blocks = vec![json!({
+            "type": "section",
+            "text": {"type": "mrkdwn", "text": heading},
+        })];
+        if !self.fields.is_empty() {
+            let fields: Vec<Value> = self
+                .fields
+                .iter()
+                .take(MAX_FIELDS)
+                .map(|(label, value)| {
+                    let value = match value {
+                        Value::String(s) => s.clone(),
+                        other => other.to_string(),
+                    };
+                    json!({"type": "mrkdwn", "text": format!("*{label}*\n{value}")})
+                })
+                .collect();
+            blocks.push(json!({"type": "section", "fields": fields}));
+        }
+        blocks.push(json!({
+            "type": "actions",
+            "block_id": ACTIONS_BLOCK,
+            "elements": [
+                button(APPROVE_ACTION, &self.approve_label, "primary"),
+                button(DENY_ACTION, &self.deny_label, "danger"),
+            ],
+        }));
+
+        json!({
+            "channel": channel,
+            "text": format!("Approval requested: {}", self.title),
+            "blocks": blocks,
+        })
+    }
+}
+
+/// The blocks of a decided request: the original ones with the buttons
+/// replaced by the decision.
+pub fn decided(blocks: &[Value], granted: bool, user_id: &str) -> Vec<Value> {
+    let outcome = if granted {
+        format!(":white_check_mark: Approved by <@{user_id}>")
+    } else {
+        format!(":no_entry: Denied by <@{user_id}>")
+    };
+    blocks
+        .iter()
+        .filter(|block| block["block_id"] != ACTIONS_BLOCK)
+        .cloned()
+        .chain([json!({
+            "type": "context",
+            "elements": [{"type": "mrkdwn", "text": outcome}],
+        })])
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request() -> ApprovalRequest {
+        ApprovalRequest::deserialize(json!({
+            "op": "request_approval",
+            "correlation_id": "deploy-4711",
+            "title": "Deploy api 2.1?",
+            "fields": {"Service": "api", "Replicas": 3},
+            "approvers": ["U1"]
+        }))
+        .unwrap_or_else(|e| panic!("parse: {e}"))
+    }
+
+    #[test]
+    fn requests_render_with_buttons_carrying_their_context() {
+        let message = request().message("C1", "msg_1");
+        assert_eq!(message["channel"], "C1");
+        let blocks = message["blocks"]
+            .as_array()
+            .unwrap_or_else(|| panic!("no blocks"));
+        assert_eq!(blocks.len(), 3);
+        assert_eq!(blocks[1]["fields"][0]["text"], "*Replicas*\n3");
+
+        let buttons = &blocks[2]["elements"];
+        assert_eq!(buttons[0]["text"]["text"], "Approve");
+        assert_eq!(buttons[1]["action_id"], DENY_ACTION);
+        let value = buttons[0]["value"].as_str().unwrap_or_default();
+        let value: ButtonValue =
+            serde_json::from_str(value).unwrap_or_else(|e| panic!("value: {e}"));
+        assert_eq!(value.correlation_id, "deploy-4711");
+        assert_eq!(value.request_id, "msg_1");
+        assert_eq!(value.approvers, ["U1"]);
+    }
+
+    #[test]
+    fn decisions_replace_the_buttons() {
+        let message = request().message("C1", "msg_1");
+        let blocks = message["blocks"].as_array().cloned().unwrap_or_default();
+        let after = decided(&blocks, false, "U2");
+        assert_eq!(after.len(), 3);
+        assert_eq!(after[1], blocks[1]);
+        assert_eq!(
+            after[2]["elements"][0]["text"],
+            ":no_entry: Denied by <@U2>"
+        );
+    }
+}
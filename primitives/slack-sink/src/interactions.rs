@@ -0,0 +1,358 @@
+//! Button clicks on approval requests.
+//!
+//! With `--interactivity-port`, slack-sink serves the Slack app's
+//! Interactivity Request URL. A click on Approve or Deny is published as
+//! `approval.granted` or `approval.denied`:
+//!
+//! ```json
+//! {
+//!   "correlation_id": "deploy-4711",
+//!   "decision": "granted",
+//!   "approver": {"id": "U061F7AUR", "username": "ada", "name": "Ada Lovelace"},
+//!   "team_id": "T0001",
+//!   "channel": "C0LAN2Q65",
+//!   "message_ts": "1700000000.000100",
+//!   "request_id": "msg_01h...",
+//!   "action_ts": "1700000042.123456"
+//! }
+//! ```
+//!
+//! `request_id` is the id of the message that requested approval. The
+//! request is then updated through the click's `response_url` so it shows
+//! the decision instead of the buttons, which also stops a second click.
+//! Clicks by users not among the request's `approvers` are answered with a
+//! message only they see and publish nothing.
+//!
+//! Requests must be signed with the app's `--signing-secret`
+//! (`X-Slack-Signature` over `v0:<timestamp>:<body>`, timestamps at most
+//! five minutes off).
+
+use crate::approval::{self, ButtonValue};
+use axum::{
+    Router,
+    body::Bytes,
+    extract::State,
+    http::{HeaderMap, StatusCode},
+    response::IntoResponse,
+    routing::post,
+};
+use clap::Args;
+use emergent_client::EmergentMessage;
+use hmac::{Hmac, Mac};
+use primitive_common::Publisher;
+use reqwest::Client;
+use serde::Deserialize;
+use serde_json::{Value, json};
+use sha2::Sha256;
+use std::sync::{Arc, OnceLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub const GRANTED_EVENT_TYPE: &str = "approval.granted";
+pub const DENIED_EVENT_TYPE: &str = "approval.denied";
+
+/// Oldest request timestamp accepted, in seconds, as Slack recommends.
+const TIMESTAMP_TOLERANCE: u64 = 300;
+
+/// Interactivity listener settings.
+#[derive(Args, Debug, Clone, Default)]
+pub struct InteractionArgs {
+    /// Serve the app's Interactivity Request URL on this port, publishing approval decisions.
+    #[arg(
+        long,
+        env = "SLACK_SINK_INTERACTIVITY_PORT",
+        requires = "signing_secret"
+    )]
+    pub interactivity_port: Option<u16>,
+
+    /// Host the interactivity listener binds to.
+    #[arg(long, env = "SLACK_SINK_INTERACTIVITY_HOST", default_value = "0.0.0.0")]
+    pub interactivity_host: String,
+
+    /// Path of the Interactivity Request URL.
+    #[arg(
+        long,
+        env = "SLACK_SINK_INTERACTIVITY_PATH",
+        default_value = "/slack/interactivity"
+    )]
+    pub interactivity_path: String,
+
+    /// App signing secret, required to accept interactions.
+    #[arg(long, env = "SLACK_SIGNING_SECRET", hide_env_values = true)]
+    pub signing_secret: Option<String>,
+}
+
+/// What to do about one interaction payload.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Outcome {
+    /// A decision: publish `event` as `event_type` and post `update` to the response URL.
+    Decided {
+        event_type: &'static str,
+        event: Value,
+        update: Value,
+    },
+    /// The user may not decide; tell them with `reply`.
+    Refused { reply: Value },
+    /// Not a click on an approval request.
+    Ignored,
+}
+
+/// Interpret a `block_actions` interaction payload.
+pub fn interpret(payload: &Value) -> Outcome {
+    if payload["type"] != "block_actions" {
+        return Outcome::Ignored;
+    }
+    let Some(action) = payload["actions"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .find(|a| a["block_id"] == approval::ACTIONS_BLOCK)
+    else {
+        return Outcome::Ignored;
+    };
+    let granted = match action["action_id"].as_str() {
+        Some(approval::APPROVE_ACTION) => true,
+        Some(approval::DENY_ACTION) => false,
+        _ => return Outcome::Ignored,
+    };
+    let Some(value) = action["value"]
+        .as_str()
+        .and_then(|v| serde_json::from_str::<ButtonValue>(v).ok())
+    else {
+        return Outcome::Ignored;
+    };
+
+    let user = &payload["user"];
+    let user_id = user["id"].as_str().unwrap_or_default();
+    if !value.approvers.is_empty() && !value.approvers.iter().any(|a| a == user_id) {
+        return Outcome::Refused {
+            reply: json!({
+                "response_type": "ephemeral",
+                "replace_original": false,
+                "text": "You are not one of the approvers for this request.",
+            }),
+        };
+    }
+
+    let event = json!({
+        "correlation_id": value.correlation_id,
+        "decision": if granted { "granted" } else { "denied" },
+        "approver": {
+            "id": user_id,
+            "username": user["username"],
+            "name": user["name"],
+        },
+        "team_id": payload["team"]["id"],
+        "channel": payload["channel"]["id"],
+        "message_ts": payload["container"]["message_ts"],
+        "request_id": value.request_id,
+        "action_ts": action["action_ts"],
+    });
+    let blocks = payload["message"]["blocks"]
+        .as_array()
+        .cloned()
+        .unwrap_or_default();
+    let update = json!({
+        "replace_original": true,
+        "text": payload["message"]["text"],
+        "blocks": approval::decided(&blocks, granted, user_id),
+    });
+    Outcome::Decided {
+        event_type: if granted {
+            GRANTED_EVENT_TYPE
+        } else {
+            DENIED_EVENT_TYPE
+        },
+        event,
+        update,
+    }
+}
+
+/// Validates a `X-Slack-Signature` (`v0=<hex>`) over `v0:<timestamp>:<body>`.
+pub fn validate_signature(secret: &str, timestamp: &str, body: &[u8], signature: &str) -> bool {
+    let Ok(mut mac) = Hmac::<Sha256>::new_from_slice(secret.as_bytes()) else {
+        return false;
+    };
+    mac.update(b"v0:");
+    mac.update(timestamp.as_bytes());
+    mac.update(b":");
+    mac.update(body);
+    let Some(Ok(expected)) = signature.strip_prefix("v0=").map(hex::decode) else {
+        return false;
+    };
+    mac.verify_slice(&expected).is_ok()
+}
+
+/// State shared by the sink and its interactivity listener.
+pub struct Interactions {
+    secret: String,
+    client: Client,
+    /// Set once the harness attaches the sink.
+    pub publisher: OnceLock<Publisher>,
+}
+
+impl Interactions {
+    pub fn new(secret: &str, client: Client) -> Self {
+        Self {
+            secret: secret.to_string(),
+            client,
+            publisher: OnceLock::new(),
+        }
+    }
+
+    /// The listener's routes, with `path` as the Interactivity Request URL.
+    pub fn router(self: &Arc<Self>, path: &str) -> Router {
+        Router::new()
+            .route(path, post(handle_interaction))
+            .with_state(Arc::clone(self))
+    }
+
+    /// Post `body` to a click's response URL.
+    async fn respond(&self, response_url: &str, body: &Value) {
+        let sent = self
+            .client
+            .post(response_url)
+            .json(body)
+            .send()
+            .await
+            .and_then(reqwest::Response::error_for_status);
+        if let Err(e) = sent {
+            eprintln!("Failed to update approval message: {e}");
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct Form {
+    payload: String,
+}
+
+/// Handles one interaction request from Slack.
+async fn handle_interaction(
+    State(state): State<Arc<Interactions>>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> impl IntoResponse {
+    let header = |name: &str| headers.get(name).and_then(|h| h.to_str().ok());
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default();
+    let fresh = |t: &&str| {
+        t.parse::<u64>()
+            .is_ok_and(|ts| ts.abs_diff(now) <= TIMESTAMP_TOLERANCE)
+    };
+    let Some(timestamp) = header("x-slack-request-timestamp").filter(fresh) else {
+        return (StatusCode::UNAUTHORIZED, "Stale or missing timestamp");
+    };
+    match header("x-slack-signature") {
+        Some(signature) if validate_signature(&state.secret, timestamp, &body, signature) => {}
+        Some(_) => return (StatusCode::UNAUTHORIZED, "Invalid signature"),
+        None => return (StatusCode::UNAUTHORIZED, "Missing signature"),
+    }
+
+    let Some(payload) = serde_urlencoded::from_bytes::<Form>(&body)
+        .ok()
+        .and_then(|form| serde_json::from_str::<Value>(&form.payload).ok())
+    else {
+        return (StatusCode::BAD_REQUEST, "Missing payload");
+    };
+    let response_url = payload["response_url"].as_str().map(str::to_string);
+
+    let reply = match interpret(&payload) {
+        Outcome::Ignored => return (StatusCode::OK, ""),
+        Outcome::Refused { reply } => reply,
+        Outcome::Decided {
+            event_type,
+            event,
+            update,
+        } => {
+            let Some(publisher) = state.publisher.get() else {
+                return (StatusCode::SERVICE_UNAVAILABLE, "Not ready");
+            };
+            let message = EmergentMessage::new(event_type).with_payload(event);
+            if let Err(e) = publisher.publish(message) {
+                eprintln!("Failed to publish {event_type}: {e}");
+                return (
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    "Failed to publish decision",
+                );
+            }
+            update
+        }
+    };
+
+    // Slack wants its answer within 3 seconds, so the message is updated afterwards
+    if let Some(response_url) = response_url {
+        tokio::spawn(async move { state.respond(&response_url, &reply).await });
+    }
+    (StatusCode::OK, "")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn click(action_id: &str, user: &str, approvers: &[&str]) -> Value {
+        let value =
+            json!({"correlation_id": "deploy-4711", "request_id": "msg_1", "approvers": approvers});
+        json!({
+            "type": "block_actions",
+            "user": {"id": user, "username": "ada", "name": "Ada Lovelace"},
+            "team": {"id": "T1"},
+            "channel": {"id": "C1"},
+            "container": {"message_ts": "1700000000.000100"},
+            "message": {"text": "Approval requested: Deploy", "blocks": [
+                {"type": "section", "text": {"type": "mrkdwn", "text": "*Deploy*"}},
+                {"type": "actions", "block_id": approval::ACTIONS_BLOCK, "elements": []}
+            ]},
+            "response_url": "https://hooks.slack.com/actions/T1/1/x",
+            "actions": [{"block_id": approval::ACTIONS_BLOCK, "action_id": action_id,
+                         "value": value.to_string(), "action_ts": "1700000042.1"}]
+        })
+    }
+
+    #[test]
+    fn clicks_become_decisions() {
+        let Outcome::Decided {
+            event_type,
+            event,
+            update,
+        } = interpret(&click("approve", "U1", &[]))
+        else {
+            panic!("not decided");
+        };
+        assert_eq!(event_type, GRANTED_EVENT_TYPE);
+        assert_eq!(event["correlation_id"], "deploy-4711");
+        assert_eq!(event["approver"]["username"], "ada");
+        assert_eq!(event["message_ts"], "1700000000.000100");
+        assert_eq!(update["blocks"].as_array().map(Vec::len), Some(2));
+
+        let denied = interpret(&click("deny", "U1", &["U1"]));
+        assert!(
+            matches!(denied, Outcome::Decided { event_type, .. } if event_type == DENIED_EVENT_TYPE)
+        );
+    }
+
+    #[test]
+    fn only_listed_approvers_may_decide() {
+        assert!(matches!(
+            interpret(&click("approve", "U2", &["U1"])),
+            Outcome::Refused { .. }
+        ));
+        assert_eq!(interpret(&click("other", "U1", &[])), Outcome::Ignored);
+        assert_eq!(
+            interpret(&json!({"type": "view_submission"})),
+            Outcome::Ignored
+        );
+    }
+
+    #[test]
+    fn forms_round_trip() {
+        let payload = click("approve", "U1", &[]);
+        let encoded = serde_urlencoded::to_string([("payload", payload.to_string())])
+            .unwrap_or_else(|e| panic!("encode: {e}"));
+        let form: Form =
+            serde_urlencoded::from_str(&encoded).unwrap_or_else(|e| panic!("decode: {e}"));
+        assert_eq!(form.payload, payload.to_string());
+    }
+}
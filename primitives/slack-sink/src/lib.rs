@@ -0,0 +1,384 @@
+//! Slack Sink - Post Events to Slack and Ask for Approvals
+//!
+//! A Sink that posts each payload with `chat.postMessage`. A plain payload
+//! is the message itself (`channel`, `text`, `blocks`, `thread_ts`, ...);
+//! one with `op: request_approval` is rendered as a message with Approve
+//! and Deny buttons (see [`approval`]). With `--interactivity-port`, the
+//! sink also receives the clicks and publishes `approval.granted` or
+//! `approval.denied` with the approver and the request's correlation id,
+//! putting a human in the loop of a pipeline (see [`interactions`]).
+//!
+//! Failed API calls fail the message, so the harness retries and
+//! dead-letters it.
+//!
+//! # Examples
+//!
+//! ```bash
+//! # Post alerts to #ops unless the payload names a channel
+//! slack-sink -s alert.fired --bot-token $SLACK_BOT_TOKEN --channel '#ops'
+//!
+//! # Ask for deploy approvals and publish the decisions
+//! slack-sink -s deploy.approval_requested --bot-token $SLACK_BOT_TOKEN \
+//!   --interactivity-port 3000 --signing-secret $SLACK_SIGNING_SECRET
+//! ```
+
+pub mod approval;
+pub mod interactions;
+
+use approval::ApprovalRequest;
+use clap::Parser;
+use emergent_client::EmergentMessage;
+use interactions::{DENIED_EVENT_TYPE, GRANTED_EVENT_TYPE, InteractionArgs, Interactions};
+use primitive_common::capabilities::VERSION;
+use primitive_common::errors::{ErrorCategory, HandlerError};
+use primitive_common::{Publisher, SinkArgs, SinkConfig, SinkContext, SinkHandler, run_sink};
+use reqwest::Client;
+use serde_json::{Value, json};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Slack Sink — post events to Slack and ask for approvals.
+#[derive(Parser, Debug)]
+#[command(name = "slack_sink", version = VERSION)]
+#[command(about = "Post events to Slack and ask for approvals")]
+struct Args {
+    /// Message types to subscribe to.
+    #[arg(short, long = "subscribe", required = true)]
+    subscribe: Vec<String>,
+
+    /// Bot token (`xoxb-...`) with `chat:write`.
+    #[arg(long, env = "SLACK_BOT_TOKEN", hide_env_values = true)]
+    bot_token: String,
+
+    /// Web API root.
+    #[arg(long, env = "SLACK_API_URL", default_value = "https://slack.com/api")]
+    api_url: String,
+
+    /// Channel for payloads that name none.
+    #[arg(short, long, env = "SLACK_SINK_CHANNEL")]
+    channel: Option<String>,
+
+    /// Per-request timeout in milliseconds.
+    #[arg(short, long, env = "SLACK_SINK_TIMEOUT", default_value = "30000")]
+    timeout: u64,
+
+    #[command(flatten)]
+    interactions: InteractionArgs,
+
+    #[command(flatten)]
+    sink: SinkArgs,
+}
+
+/// Posts each payload as a message.
+struct SlackSink {
+    client: Client,
+    api_url: String,
+    token: String,
+    channel: Option<String>,
+    timeout: Duration,
+    /// `None` without `--interactivity-port`.
+    interactions: Option<Arc<Interactions>>,
+}
+
+impl SlackSink {
+    /// The `chat.postMessage` body for a payload.
+    fn message(&self, ctx: &SinkContext<'_>) -> Result<Value, HandlerError> {
+        let payload = ctx.payload();
+        let channel = |named: Option<&str>| {
+            named
+                .or(self.channel.as_deref())
+                .map(str::to_string)
+                .ok_or_else(|| {
+                    HandlerError::new(
+                        ErrorCategory::Parse,
+                        "payload names no channel and no --channel",
+                    )
+                })
+        };
+        match payload["op"].as_str() {
+            None | Some("post") => {
+                let Some(mut body) = payload.as_object().cloned() else {
+                    return Err(HandlerError::new(
+                        ErrorCategory::Parse,
+                        "payload must be a message object",
+                    ));
+                };
+                body.remove("op");
+                let channel = channel(body.get("channel").and_then(Value::as_str))?;
+                body.insert("channel".to_string(), Value::String(channel));
+                Ok(Value::Object(body))
+            }
+            Some("request_approval") => {
+                let request: ApprovalRequest = ctx.decode()?;
+                let channel = channel(request.channel.as_deref())?;
+                Ok(request.message(&channel, ctx.message_id()))
+            }
+            Some(other) => Err(HandlerError::new(
+                ErrorCategory::Parse,
+                format!("unknown op '{other}'"),
+            )),
+        }
+    }
+
+    async fn post_message(&self, body: &Value) -> Result<(), HandlerError> {
+        let target = "chat.postMessage";
+        let response = self
+            .client
+            .post(format!("{}/{target}", self.api_url))
+            .timeout(self.timeout)
+            .bearer_auth(&self.token)
+            .json(body)
+            .send()
+            .await
+            .map_err(|e| {
+                if e.is_timeout() {
+                    HandlerError::new(ErrorCategory::Timeout, format!("{target}: timed out"))
+                } else {
+                    HandlerError::new(ErrorCategory::Request, format!("{target}: {e}"))
+                }
+            })?;
+        let status = response.status();
+        if !status.is_success() {
+            return Err(HandlerError::new(
+                ErrorCategory::Rejected,
+                format!("{target}: HTTP {status}"),
+            ));
+        }
+        let reply: Value = response
+            .json()
+            .await
+            .map_err(|e| HandlerError::new(ErrorCategory::Parse, format!("{target}: {e}")))?;
+        if reply["ok"].as_bool() != Some(true) {
+            let error = reply["error"].as_str().unwrap_or("unknown error");
+            return Err(HandlerError::new(
+                ErrorCategory::Rejected,
+                format!("{target}: {error}"),
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl SinkHandler for SlackSink {
+    async fn handle(
+        &self,
+        _msg: &EmergentMessage,
+        ctx: &SinkContext<'_>,
+    ) -> Result<(), HandlerError> {
+        let body = self.message(ctx)?;
+
+        if ctx.is_dry_run() {
+            let detail = json!({ "method": "chat.postMessage", "body": body });
+            ctx.would_have("slack", detail).await;
+            return Ok(());
+        }
+
+        self.post_message(&body).await
+    }
+
+    fn publishes(&self) -> &'static [&'static str] {
+        match self.interactions {
+            Some(_) => &[GRANTED_EVENT_TYPE, DENIED_EVENT_TYPE],
+            None => &[],
+        }
+    }
+
+    fn attach(&self, publisher: Publisher) {
+        if let Some(interactions) = &self.interactions {
+            let _ = interactions.publisher.set(publisher);
+        }
+    }
+}
+
+/// Run the primitive with `args` as its command line (the first item is
+/// the program name, as with `std::env::args_os`).
+pub async fn run<I, T>(args: I) -> Result<(), Box<dyn std::error::Error>>
+where
+    I: IntoIterator<Item = T>,
+    T: Into<std::ffi::OsString> + Clone,
+{
+    let args = Args::parse_from(args);
+
+    let config = SinkConfig {
+        name: "slack_sink",
+        subscribe: &args.subscribe,
+        would_have_as: "slack.would_have",
+        dead_letter_as: "slack.dead_letter",
+        settings: &args,
+    };
+    let client = Client::new();
+    let listener = &args.interactions;
+    let interactions = match (listener.interactivity_port, &listener.signing_secret) {
+        (Some(port), Some(secret)) => {
+            let interactions = Arc::new(Interactions::new(secret, client.clone()));
+            let addr = format!("{}:{port}", listener.interactivity_host);
+            let bound = match tokio::net::TcpListener::bind(&addr).await {
+                Ok(bound) => bound,
+                Err(e) => {
+                    eprintln!("Error: cannot listen on {addr}: {e}");
+                    std::process::exit(1);
+                }
+            };
+            let app = interactions.router(&listener.interactivity_path);
+            tokio::spawn(async move { axum::serve(bound, app).await });
+            Some(interactions)
+        }
+        _ => None,
+    };
+    let handler = SlackSink {
+        client,
+        api_url: args.api_url.trim_end_matches('/').to_string(),
+        token: args.bot_token.clone(),
+        channel: args.channel.clone(),
+        timeout: Duration::from_millis(args.timeout),
+        interactions,
+    };
+
+    run_sink(config, &args.sink, handler).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{Json, Router, extract::Request, routing::post};
+    use emergent_testkit::{SinkFixture, fixtures, spawn_sink};
+    use hmac::{Hmac, Mac};
+    use sha2::Sha256;
+    use tokio::sync::mpsc;
+
+    /// Serve `app` on a random port, returning its base URL.
+    async fn serve(app: Router) -> String {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap_or_else(|e| panic!("bind: {e}"));
+        let addr = listener
+            .local_addr()
+            .unwrap_or_else(|e| panic!("local_addr: {e}"));
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        format!("http://{addr}")
+    }
+
+    /// A fake Slack: `chat.postMessage` and response URLs forward their bodies.
+    async fn slack() -> (String, mpsc::UnboundedReceiver<(String, Value)>) {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let app = Router::new().fallback(post(move |request: Request| {
+            let tx = tx.clone();
+            async move {
+                let path = request.uri().path().to_string();
+                let body = axum::body::to_bytes(request.into_body(), usize::MAX)
+                    .await
+                    .unwrap_or_default();
+                let body = serde_json::from_slice(&body).unwrap_or(Value::Null);
+                let _ = tx.send((path, body));
+                Json(json!({"ok": true, "ts": "1700000000.000100"}))
+            }
+        }));
+        (serve(app).await, rx)
+    }
+
+    fn slack_sink(api_url: &str, interactions: Option<Arc<Interactions>>) -> SlackSink {
+        SlackSink {
+            client: Client::new(),
+            api_url: api_url.to_string(),
+            token: "xoxb-test".to_string(),
+            channel: Some("#ops".to_string()),
+            timeout: Duration::from_secs(5),
+            interactions,
+        }
+    }
+
+    #[tokio::test]
+    async fn approvals_are_requested_and_decisions_published() {
+        let (api, mut received) = slack().await;
+        let interactions = Arc::new(Interactions::new("s3cret", Client::new()));
+        let listener = serve(interactions.router("/slack/interactivity")).await;
+        let (mut engine, run) = spawn_sink(
+            SinkFixture::new("slack_sink", "slack"),
+            SinkArgs::default(),
+            slack_sink(&api, Some(interactions)),
+        );
+
+        engine
+            .inject_message(fixtures::message(
+                "deploy.approval_requested",
+                json!({"op": "request_approval", "correlation_id": "deploy-4711",
+                       "title": "Deploy api 2.1?", "approvers": ["U1"]}),
+            ))
+            .await;
+        let (path, posted) = received.recv().await.unwrap_or_else(|| panic!("no post"));
+        assert_eq!(path, "/chat.postMessage");
+        assert_eq!(posted["channel"], "#ops");
+
+        // Click Approve on the posted message
+        let mut action = posted["blocks"][1]["elements"][0].clone();
+        action["block_id"] = json!(approval::ACTIONS_BLOCK);
+        let click = json!({
+            "type": "block_actions",
+            "user": {"id": "U1", "username": "ada"},
+            "channel": {"id": "C1"},
+            "message": {"text": posted["text"], "blocks": posted["blocks"]},
+            "response_url": format!("{api}/actions/1"),
+            "actions": [action],
+        });
+        let body = serde_urlencoded::to_string([("payload", click.to_string())])
+            .unwrap_or_else(|e| panic!("encode: {e}"));
+        let timestamp = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_secs().to_string())
+            .unwrap_or_default();
+        let mut mac =
+            Hmac::<Sha256>::new_from_slice(b"s3cret").unwrap_or_else(|e| panic!("hmac: {e}"));
+        mac.update(format!("v0:{timestamp}:{body}").as_bytes());
+        let signature = format!("v0={}", hex::encode(mac.finalize().into_bytes()));
+        let status = Client::new()
+            .post(format!("{listener}/slack/interactivity"))
+            .header("X-Slack-Request-Timestamp", timestamp)
+            .header("X-Slack-Signature", signature)
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .body(body)
+            .send()
+            .await
+            .map(|r| r.status().as_u16())
+            .unwrap_or_else(|e| panic!("click: {e}"));
+        assert_eq!(status, 200);
+
+        let granted = engine.expect_published("approval.granted").await;
+        assert_eq!(granted.payload()["correlation_id"], "deploy-4711");
+        assert_eq!(granted.payload()["approver"]["id"], "U1");
+        let (path, update) = received.recv().await.unwrap_or_else(|| panic!("no update"));
+        assert_eq!(path, "/actions/1");
+        assert_eq!(update["replace_original"], true);
+
+        engine.close();
+        assert_eq!(run.await.ok(), Some(Ok(())));
+    }
+
+    #[tokio::test]
+    async fn dry_run_reports_the_message() {
+        let args = SinkArgs {
+            dry_run: true,
+            ..Default::default()
+        };
+        let (mut engine, run) = spawn_sink(
+            SinkFixture::new("slack_sink", "slack"),
+            args,
+            slack_sink("http://127.0.0.1:9", None),
+        );
+
+        engine
+            .inject_message(fixtures::message(
+                "alert.fired",
+                json!({"text": "Disk full", "thread_ts": "1.0"}),
+            ))
+            .await;
+        let report = engine.expect_published("slack.would_have").await;
+        assert_eq!(report.payload()["detail"]["method"], "chat.postMessage");
+        assert_eq!(
+            report.payload()["detail"]["body"],
+            json!({"channel": "#ops", "text": "Disk full", "thread_ts": "1.0"})
+        );
+
+        engine.close();
+        assert_eq!(run.await.ok(), Some(Ok(())));
+    }
+}
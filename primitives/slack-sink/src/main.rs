@@ -0,0 +1,6 @@
+//! `slack-sink` binary; the primitive itself lives in the library.
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    slack_sink::run(std::env::args_os()).await
+}
@@ -0,0 +1,341 @@
+//! Resolving the ids in Slack events.
+//!
+//! Events API payloads name users and channels by id only. With a bot
+//! token, slack-source looks them up (`users.info`, `conversations.info`)
+//! and adds what it finds under `context`; with `--thread-context`, replies
+//! in a thread also get the thread's parent message (`conversations.replies`):
+//!
+//! ```json
+//! "context": {
+//!   "user": {"id": "U061F7AUR", "name": "ada", "real_name": "Ada Lovelace"},
+//!   "channel": {"id": "C0LAN2Q65", "name": "deploys", "is_private": false, "is_im": false},
+//!   "parent": {"ts": "1700000000.000100", "user": "U0G9QF9C6", "user_name": "grace",
+//!              "text": "Shipping 2.1 today?"}
+//! }
+//! ```
+//!
+//! Lookups are cached for `--cache-ttl` seconds; failed lookups are not
+//! cached and simply leave their entry out. Enrichment is bounded by
+//! `--enrich-timeout` so that Slack, which retries deliveries not
+//! acknowledged within 3 seconds, always gets its answer in time.
+
+use clap::Args;
+use reqwest::Client;
+use serde_json::{Map, Value, json};
+use std::collections::HashMap;
+use std::sync::{Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+/// Slack Web API access and caching.
+#[derive(Args, Debug, Clone)]
+pub struct EnrichArgs {
+    /// Bot token (`xoxb-...`) with `users:read` and `channels:read` (plus history scopes for `--thread-context`).
+    #[arg(long, env = "SLACK_BOT_TOKEN", hide_env_values = true)]
+    pub bot_token: Option<String>,
+
+    /// Web API root.
+    #[arg(long, env = "SLACK_API_URL", default_value = "https://slack.com/api")]
+    pub api_url: String,
+
+    /// Seconds to cache looked-up users, channels and parent messages.
+    #[arg(long, env = "SLACK_SOURCE_CACHE_TTL", default_value = "3600")]
+    pub cache_ttl: u64,
+
+    /// Add the parent message to threaded replies.
+    #[arg(long, env = "SLACK_SOURCE_THREAD_CONTEXT", requires = "bot_token")]
+    pub thread_context: bool,
+
+    /// Longest enrichment may delay a delivery, in milliseconds.
+    #[arg(long, env = "SLACK_SOURCE_ENRICH_TIMEOUT", default_value = "2000")]
+    pub enrich_timeout: u64,
+}
+
+/// Values by key, each kept for a fixed time.
+pub struct TtlCache {
+    ttl: Duration,
+    entries: Mutex<HashMap<String, (Instant, Value)>>,
+}
+
+impl TtlCache {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// The value cached under `key`, if it has not expired.
+    pub fn get(&self, key: &str) -> Option<Value> {
+        let mut entries = self.entries.lock().unwrap_or_else(PoisonError::into_inner);
+        match entries.get(key) {
+            Some((stored, value)) if stored.elapsed() < self.ttl => Some(value.clone()),
+            Some(_) => {
+                entries.remove(key);
+                None
+            }
+            None => None,
+        }
+    }
+
+    pub fn insert(&self, key: &str, value: Value) {
+        let mut entries = self.entries.lock().unwrap_or_else(PoisonError::into_inner);
+        // Sweep expired entries now and then so the map cannot grow unbounded
+        if entries.len() >= 10_000 {
+            let ttl = self.ttl;
+            entries.retain(|_, (stored, _)| stored.elapsed() < ttl);
+        }
+        entries.insert(key.to_string(), (Instant::now(), value));
+    }
+}
+
+/// Adds user, channel and thread context to events.
+pub struct Enricher {
+    client: Client,
+    api_url: String,
+    token: String,
+    thread_context: bool,
+    timeout: Duration,
+    users: TtlCache,
+    channels: TtlCache,
+    parents: TtlCache,
+}
+
+impl Enricher {
+    /// `None` without a bot token.
+    pub fn new(args: &EnrichArgs) -> Option<Self> {
+        let ttl = Duration::from_secs(args.cache_ttl);
+        Some(Self {
+            client: Client::new(),
+            api_url: args.api_url.trim_end_matches('/').to_string(),
+            token: args.bot_token.clone()?,
+            thread_context: args.thread_context,
+            timeout: Duration::from_millis(args.enrich_timeout),
+            users: TtlCache::new(ttl),
+            channels: TtlCache::new(ttl),
+            parents: TtlCache::new(ttl),
+        })
+    }
+
+    /// `event` with a `context` object added, as far as lookups succeed
+    /// within the enrichment timeout.
+    pub async fn enrich(&self, mut event: Value) -> Value {
+        let context = tokio::time::timeout(self.timeout, self.context(&event))
+            .await
+            .unwrap_or_else(|_| {
+                eprintln!("Slack lookups timed out; publishing without context");
+                Map::new()
+            });
+        if !context.is_empty()
+            && let Some(object) = event.as_object_mut()
+        {
+            object.insert("context".to_string(), Value::Object(context));
+        }
+        event
+    }
+
+    async fn context(&self, event: &Value) -> Map<String, Value> {
+        let mut context = Map::new();
+        if let Some(user) = event["user"].as_str()
+            && let Some(found) = self.user(user).await
+        {
+            context.insert("user".to_string(), found);
+        }
+        let channel = event["channel"]
+            .as_str()
+            .or(event["item"]["channel"].as_str());
+        if let Some(channel) = channel
+            && let Some(found) = self.channel(channel).await
+        {
+            context.insert("channel".to_string(), found);
+        }
+        if self.thread_context
+            && let (Some(channel), Some(thread_ts)) = (channel, event["thread_ts"].as_str())
+            && event["ts"].as_str() != Some(thread_ts)
+            && let Some(parent) = self.parent(channel, thread_ts).await
+        {
+            context.insert("parent".to_string(), parent);
+        }
+        context
+    }
+
+    async fn user(&self, id: &str) -> Option<Value> {
+        if let Some(cached) = self.users.get(id) {
+            return Some(cached);
+        }
+        let user = self.call("users.info", &[("user", id)]).await?["user"].take();
+        let profile = &user["profile"];
+        let name = [
+            &profile["display_name"],
+            &profile["real_name"],
+            &user["name"],
+        ]
+        .into_iter()
+        .filter_map(Value::as_str)
+        .find(|n| !n.is_empty())
+        .unwrap_or(id);
+        let found = json!({
+            "id": id,
+            "name": name,
+            "real_name": user["real_name"].as_str().or(profile["real_name"].as_str()),
+        });
+        self.users.insert(id, found.clone());
+        Some(found)
+    }
+
+    async fn channel(&self, id: &str) -> Option<Value> {
+        if let Some(cached) = self.channels.get(id) {
+            return Some(cached);
+        }
+        let channel = self.call("conversations.info", &[("channel", id)]).await?["channel"].take();
+        let found = json!({
+            "id": id,
+            "name": channel["name"],
+            "is_private": channel["is_private"].as_bool().unwrap_or(false),
+            "is_im": channel["is_im"].as_bool().unwrap_or(false),
+        });
+        self.channels.insert(id, found.clone());
+        Some(found)
+    }
+
+    async fn parent(&self, channel: &str, ts: &str) -> Option<Value> {
+        let key = format!("{channel}:{ts}");
+        if let Some(cached) = self.parents.get(&key) {
+            return Some(cached);
+        }
+        let replies = self
+            .call(
+                "conversations.replies",
+                &[
+                    ("channel", channel),
+                    ("ts", ts),
+                    ("limit", "1"),
+                    ("inclusive", "true"),
+                ],
+            )
+            .await?;
+        let message = replies["messages"].get(0)?;
+        let user = message["user"].as_str();
+        let user_name = match user {
+            Some(user) => self.user(user).await.map(|u| u["name"].clone()),
+            None => None,
+        };
+        let found = json!({
+            "ts": ts,
+            "user": user,
+            "user_name": user_name,
+            "text": message["text"],
+        });
+        self.parents.insert(&key, found.clone());
+        Some(found)
+    }
+
+    /// Call a Web API method, returning its body when `ok`.
+    async fn call(&self, method: &str, query: &[(&str, &str)]) -> Option<Value> {
+        let response = self
+            .client
+            .get(format!("{}/{method}", self.api_url))
+            .query(query)
+            .bearer_auth(&self.token)
+            .send()
+            .await
+            .and_then(reqwest::Response::error_for_status);
+        let body: Value = match response {
+            Ok(response) => response.json().await.ok()?,
+            Err(e) => {
+                eprintln!("Slack {method} failed: {e}");
+                return None;
+            }
+        };
+        if body["ok"].as_bool() != Some(true) {
+            let error = body["error"].as_str().unwrap_or("unknown error");
+            eprintln!("Slack {method} failed: {error}");
+            return None;
+        }
+        Some(body)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{Json, Router, extract::Request, routing::get};
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[test]
+    fn expired_entries_are_dropped() {
+        let cache = TtlCache::new(Duration::from_secs(60));
+        cache.insert("U1", json!("ada"));
+        assert_eq!(cache.get("U1"), Some(json!("ada")));
+        assert_eq!(cache.get("U2"), None);
+
+        let expired = TtlCache::new(Duration::ZERO);
+        expired.insert("U1", json!("ada"));
+        assert_eq!(expired.get("U1"), None);
+    }
+
+    #[tokio::test]
+    async fn replies_get_user_channel_and_parent_context() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&calls);
+        let app = Router::new()
+            .route(
+                "/users.info",
+                get(move |request: Request| {
+                    counter.fetch_add(1, Ordering::SeqCst);
+                    let query = request.uri().query().unwrap_or_default().to_string();
+                    async move {
+                        Json(if query.contains("U_ADA") {
+                            json!({"ok": true, "user": {"name": "ada", "real_name": "Ada Lovelace",
+                                   "profile": {"display_name": "", "real_name": "Ada Lovelace"}}})
+                        } else {
+                            json!({"ok": true, "user": {"name": "grace",
+                                   "profile": {"display_name": "grace.h"}}})
+                        })
+                    }
+                }),
+            )
+            .route(
+                "/conversations.info",
+                get(|| async { Json(json!({"ok": false, "error": "channel_not_found"})) }),
+            )
+            .route(
+                "/conversations.replies",
+                get(|| async {
+                    Json(json!({"ok": true, "messages": [
+                        {"ts": "1.000", "user": "U_GRACE", "text": "Shipping today?"}
+                    ]}))
+                }),
+            );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap_or_else(|e| panic!("bind: {e}"));
+        let addr = listener
+            .local_addr()
+            .unwrap_or_else(|e| panic!("local_addr: {e}"));
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let enricher = Enricher::new(&EnrichArgs {
+            bot_token: Some("xoxb-test".to_string()),
+            api_url: format!("http://{addr}"),
+            cache_ttl: 60,
+            thread_context: true,
+            enrich_timeout: 5000,
+        })
+        .unwrap_or_else(|| panic!("no enricher"));
+        let reply = json!({"type": "message", "user": "U_ADA", "channel": "C1",
+                           "ts": "2.000", "thread_ts": "1.000", "text": "Yes"});
+
+        let enriched = enricher.enrich(reply.clone()).await;
+        let context = &enriched["context"];
+        assert_eq!(context["user"]["name"], "Ada Lovelace");
+        assert_eq!(context["channel"], Value::Null);
+        assert_eq!(context["parent"]["user_name"], "grace.h");
+        assert_eq!(context["parent"]["text"], "Shipping today?");
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+
+        // Both users are cached now
+        enricher.enrich(reply).await;
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+}
@@ -0,0 +1,435 @@
+//! Slack Source - Slack Events API Receiver
+//!
+//! A Source that receives Slack Events API deliveries and emits each inner
+//! event as `slack.<type>` (e.g. `slack.message`, `slack.app_mention`,
+//! `slack.reaction_added`). The payload is the event object, with
+//! `team_id`, `event_id` and `event_time` from its envelope.
+//!
+//! With `--signing-secret`, deliveries must carry a valid `X-Slack-Signature`
+//! for a `X-Slack-Request-Timestamp` less than five minutes old. The
+//! `url_verification` handshake sent when the Request URL is configured is
+//! answered with its challenge.
+//!
+//! With `--bot-token`, user and channel ids are resolved to names, and with
+//! `--thread-context` threaded replies carry their parent message (see
+//! [`enrich`]).
+//!
+//! `--emit-type-template` can also use `{event}`. A delivery that cannot be
+//! published or spooled is answered with 503 so Slack retries it.
+//!
+//! Sources are SILENT - they only produce domain messages.
+//! All lifecycle events are published by the engine.
+//!
+//! # Usage
+//!
+//! ```bash
+//! # Events with ids only
+//! slack-source --path /slack/events --signing-secret $SLACK_SIGNING_SECRET
+//!
+//! # With names and thread context
+//! slack-source --path /slack/events --signing-secret $SLACK_SIGNING_SECRET \
+//!   --bot-token $SLACK_BOT_TOKEN --thread-context
+//! ```
+//!
+//! On SIGTERM the listener closes immediately and deliveries already being
+//! handled get `--drain-timeout` milliseconds to finish publishing.
+
+pub mod enrich;
+
+use axum::{
+    Json, Router,
+    body::Bytes,
+    extract::State,
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    routing::post,
+};
+use clap::Parser;
+use emergent_client::EmergentSource;
+use enrich::{EnrichArgs, Enricher};
+use hmac::{Hmac, Mac};
+use primitive_common::capabilities::{self, CapabilityArgs, Role, VERSION, capabilities_message};
+use primitive_common::doctor::Report;
+use primitive_common::source::{Outlet, SourceArgs};
+use serde_json::{Value, json};
+use sha2::Sha256;
+use std::{
+    future::IntoFuture,
+    net::SocketAddr,
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+use tokio::{
+    signal::unix::{SignalKind, signal},
+    sync::Notify,
+};
+
+/// Oldest request timestamp accepted, in seconds, as Slack recommends.
+const TIMESTAMP_TOLERANCE: u64 = 300;
+
+/// Slack Events API receiver that emits slack.* events.
+#[derive(Parser, Debug, Clone)]
+#[command(name = "slack-source", version = VERSION)]
+#[command(about = "Receives Slack Events API deliveries and emits events")]
+struct Args {
+    /// Port to listen on.
+    #[arg(short, long, env = "SLACK_SOURCE_PORT", default_value = "8080")]
+    port: u16,
+
+    /// Host to bind to.
+    #[arg(long, env = "SLACK_SOURCE_HOST", default_value = "0.0.0.0")]
+    host: String,
+
+    /// Path to accept deliveries on (the app's Request URL).
+    #[arg(long, env = "SLACK_SOURCE_PATH", default_value = "/")]
+    path: String,
+
+    /// App signing secret; deliveries must then carry a valid X-Slack-Signature.
+    #[arg(long, env = "SLACK_SIGNING_SECRET", hide_env_values = true)]
+    signing_secret: Option<String>,
+
+    #[command(flatten)]
+    enrich: EnrichArgs,
+
+    /// Verify external dependencies and exit without connecting to the engine.
+    #[arg(long)]
+    self_test: bool,
+
+    #[command(flatten)]
+    source: SourceArgs,
+
+    #[command(flatten)]
+    capabilities: CapabilityArgs,
+}
+
+/// Variables available to `--emit-type-template`.
+const TEMPLATE_VARIABLES: &[&str] = &["source", "event"];
+
+/// Shared application state.
+struct AppState {
+    outlet: Arc<Outlet>,
+    signing_secret: Option<String>,
+    /// `None` without `--bot-token`.
+    enricher: Option<Enricher>,
+}
+
+impl AppState {
+    fn new(outlet: Arc<Outlet>, args: &Args) -> Self {
+        Self {
+            outlet,
+            signing_secret: args.signing_secret.clone(),
+            enricher: Enricher::new(&args.enrich),
+        }
+    }
+}
+
+/// Validates a `X-Slack-Signature` (`v0=<hex>`) over `v0:<timestamp>:<body>`.
+fn validate_signature(secret: &str, timestamp: &str, body: &[u8], signature: &str) -> bool {
+    let Ok(mut mac) = Hmac::<Sha256>::new_from_slice(secret.as_bytes()) else {
+        return false;
+    };
+    mac.update(b"v0:");
+    mac.update(timestamp.as_bytes());
+    mac.update(b":");
+    mac.update(body);
+    let Some(Ok(expected)) = signature.strip_prefix("v0=").map(hex::decode) else {
+        return false;
+    };
+    mac.verify_slice(&expected).is_ok()
+}
+
+/// Whether a `X-Slack-Request-Timestamp` is recent enough to accept.
+fn is_fresh(timestamp: &str, now: u64) -> bool {
+    timestamp
+        .parse::<u64>()
+        .is_ok_and(|ts| ts.abs_diff(now) <= TIMESTAMP_TOLERANCE)
+}
+
+/// The event to publish from an `event_callback` envelope, with its type.
+fn unwrap_event(envelope: &Value) -> Option<(String, Value)> {
+    let mut event = envelope["event"].clone();
+    let event_type = event["type"].as_str()?.to_string();
+    let object = event.as_object_mut()?;
+    for key in ["team_id", "event_id", "event_time"] {
+        if !envelope[key].is_null() {
+            object.insert(key.to_string(), envelope[key].clone());
+        }
+    }
+    Some((event_type, event))
+}
+
+/// Runs `--self-test` checks and exits.
+fn self_test(args: &Args, name: &str) -> ! {
+    let mut report = Report::new(name);
+    let addr = format!("{}:{}", args.host, args.port);
+    let bind = addr
+        .parse::<SocketAddr>()
+        .map_err(|e| format!("{addr}: {e}"))
+        .and_then(|a| {
+            std::net::TcpListener::bind(a)
+                .map(|_| addr.clone())
+                .map_err(|e| format!("{addr}: {e}"))
+        });
+    report.check("bind", bind);
+    report.check("path", check_path(&args.path));
+    args.source.self_test(&mut report, TEMPLATE_VARIABLES);
+    report.finish()
+}
+
+/// Check that the Request URL path is absolute.
+fn check_path(path: &str) -> Result<String, String> {
+    if path.starts_with('/') {
+        Ok(path.to_string())
+    } else {
+        Err(format!("{path} must start with '/'"))
+    }
+}
+
+/// Handles incoming Events API deliveries.
+async fn handle_delivery(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Response {
+    let header = |name: &str| headers.get(name).and_then(|h| h.to_str().ok());
+
+    if let Some(secret) = &state.signing_secret {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+        let Some(timestamp) = header("x-slack-request-timestamp").filter(|t| is_fresh(t, now))
+        else {
+            return (StatusCode::UNAUTHORIZED, "Stale or missing timestamp").into_response();
+        };
+        match header("x-slack-signature") {
+            Some(signature) if validate_signature(secret, timestamp, &body, signature) => {}
+            Some(_) => return (StatusCode::UNAUTHORIZED, "Invalid signature").into_response(),
+            None => return (StatusCode::UNAUTHORIZED, "Missing signature").into_response(),
+        }
+    }
+
+    let Ok(envelope) = serde_json::from_slice::<Value>(&body) else {
+        return (StatusCode::BAD_REQUEST, "Body is not JSON").into_response();
+    };
+    match envelope["type"].as_str() {
+        Some("url_verification") => {
+            return Json(json!({ "challenge": envelope["challenge"] })).into_response();
+        }
+        Some("event_callback") => {}
+        // Other envelopes (e.g. app_rate_limited) carry no event
+        _ => return StatusCode::OK.into_response(),
+    }
+    let Some((event_type, event)) = unwrap_event(&envelope) else {
+        return (StatusCode::BAD_REQUEST, "Event has no type").into_response();
+    };
+
+    let event = match &state.enricher {
+        Some(enricher) => enricher.enrich(event).await,
+        None => event,
+    };
+    // The outlet logs and reports what it cannot deliver
+    let vars = [("event", event_type.as_str())];
+    match state
+        .outlet
+        .publish(&format!("slack.{event_type}"), &vars, event)
+        .await
+    {
+        Ok(()) => StatusCode::OK.into_response(),
+        Err(_) => (StatusCode::SERVICE_UNAVAILABLE, "Failed to publish event").into_response(),
+    }
+}
+
+/// Run the primitive with `args` as its command line (the first item is
+/// the program name, as with `std::env::args_os`).
+pub async fn run<I, T>(args: I) -> Result<(), Box<dyn std::error::Error>>
+where
+    I: IntoIterator<Item = T>,
+    T: Into<std::ffi::OsString> + Clone,
+{
+    let args = Args::parse_from(args);
+
+    // Get the source name from environment (set by engine) or use default
+    let name = std::env::var("EMERGENT_NAME").unwrap_or_else(|_| "slack-source".to_string());
+
+    if args.self_test {
+        self_test(&args, &name);
+    }
+    if let Err(e) = check_path(&args.path).and_then(|_| args.source.validate(TEMPLATE_VARIABLES)) {
+        eprintln!("Error: {e}");
+        std::process::exit(1);
+    }
+
+    // Event types are only known per delivery, so the namespace is listed
+    let produces = args.source.produces(&["slack.*"]);
+    let produces: Vec<&str> = produces.iter().map(String::as_str).collect();
+    let descriptor = args
+        .capabilities
+        .describe(&name, Role::Source, &[], &produces, &args);
+    if args.capabilities.capabilities {
+        capabilities::print(&descriptor);
+    }
+
+    // Connect to the Emergent engine (silently - lifecycle events come from engine)
+    let source = match EmergentSource::connect(&name).await {
+        Ok(s) => s,
+        Err(e) => {
+            eprintln!("Failed to connect to Emergent engine: {e}");
+            std::process::exit(1);
+        }
+    };
+    let outlet = Arc::new(Outlet::new(source, &name, &args.source)?);
+    if args.capabilities.announce {
+        outlet.announce(capabilities_message(&descriptor)).await;
+    }
+    tokio::spawn(Arc::clone(&outlet).drain_every(args.source.spool.retry_interval()));
+
+    let state = Arc::new(AppState::new(Arc::clone(&outlet), &args));
+    let app = Router::new()
+        .route(&args.path, post(handle_delivery))
+        .with_state(state);
+
+    let addr: SocketAddr = format!("{}:{}", args.host, args.port).parse()?;
+
+    // Set up SIGTERM handler for graceful shutdown
+    let mut sigterm = signal(SignalKind::terminate())?;
+
+    let shutdown = Arc::new(Notify::new());
+    let server = axum::serve(
+        tokio::net::TcpListener::bind(&addr).await?,
+        app.into_make_service(),
+    )
+    .with_graceful_shutdown({
+        let shutdown = Arc::clone(&shutdown);
+        async move { shutdown.notified().await }
+    })
+    .into_future();
+    tokio::pin!(server);
+
+    tokio::select! {
+        result = &mut server => {
+            result?;
+        }
+        _ = sigterm.recv() => {
+            // Stop accepting connections and let in-flight deliveries finish
+            shutdown.notify_one();
+            args.source.drain.drain("in-flight deliveries", &mut server).await;
+            outlet.disconnect().await;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use emergent_testkit::{MockEngine, fixtures};
+    use std::path::Path;
+
+    /// Connect to the engine on `socket` as the source would.
+    async fn connect(socket: &Path, args: &Args) -> Arc<AppState> {
+        let source = EmergentSource::connect_to("slack-source", socket)
+            .await
+            .unwrap_or_else(|e| panic!("connect: {e}"));
+        let outlet = Outlet::new(source, "slack-source", &args.source)
+            .unwrap_or_else(|e| panic!("open spool: {e}"));
+        Arc::new(AppState::new(Arc::new(outlet), args))
+    }
+
+    async fn deliver(state: &Arc<AppState>, envelope: &Value) -> StatusCode {
+        handle_delivery(
+            State(Arc::clone(state)),
+            HeaderMap::new(),
+            Bytes::from(envelope.to_string()),
+        )
+        .await
+        .status()
+    }
+
+    fn mention() -> Value {
+        json!({
+            "type": "event_callback", "team_id": "T1", "event_id": "Ev1",
+            "event": {"type": "app_mention", "user": "U1", "text": "<@U0BOT> deploy"}
+        })
+    }
+
+    #[tokio::test]
+    async fn deliveries_survive_the_engine_being_down() {
+        let dir = fixtures::TempDir::new("slack-source-spool");
+        let socket = dir.path().join("engine.sock");
+        let spool = dir.path().join("spool");
+        let args = Args::parse_from([
+            "slack-source",
+            "--spool-dir",
+            spool.to_str().unwrap_or_default(),
+        ]);
+
+        let mut engine = MockEngine::serve(&socket);
+        let state = connect(&socket, &args).await;
+        engine.shut_down().await;
+        assert_eq!(deliver(&state, &mention()).await, StatusCode::OK);
+        drop(state);
+
+        let mut engine = MockEngine::serve(&socket);
+        let state = connect(&socket, &args).await;
+        state.outlet.drain_spool().await;
+        let published = engine.expect_published("slack.app_mention").await;
+        assert_eq!(published.payload()["event_id"], "Ev1");
+        engine.shut_down().await;
+    }
+
+    #[tokio::test]
+    async fn undeliverable_events_are_refused_for_a_retry() {
+        let dir = fixtures::TempDir::new("slack-source-down");
+        let socket = dir.path().join("engine.sock");
+        let args = Args::parse_from([
+            "slack-source",
+            "--emit-type-map",
+            "slack.app_mention=chat.mention",
+        ]);
+
+        let mut engine = MockEngine::serve(&socket);
+        let state = connect(&socket, &args).await;
+        assert_eq!(deliver(&state, &mention()).await, StatusCode::OK);
+        engine.expect_published("chat.mention").await;
+
+        engine.shut_down().await;
+        let status = deliver(&state, &mention()).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    }
+
+    #[test]
+    fn slack_signatures_are_checked() {
+        let body = b"token=xyz&team_id=T1";
+        let mut mac = Hmac::<Sha256>::new_from_slice(b"s3cret").unwrap_or_else(|e| panic!("{e}"));
+        mac.update(b"v0:1531420618:token=xyz&team_id=T1");
+        let signature = format!("v0={}", hex::encode(mac.finalize().into_bytes()));
+
+        assert!(validate_signature("s3cret", "1531420618", body, &signature));
+        assert!(!validate_signature(
+            "s3cret",
+            "1531420619",
+            body,
+            &signature
+        ));
+        assert!(!validate_signature("other", "1531420618", body, &signature));
+        assert!(is_fresh("1531420618", 1531420618 + 300));
+        assert!(!is_fresh("1531420618", 1531420618 + 301));
+        assert!(!is_fresh("soon", 1531420618));
+    }
+
+    #[test]
+    fn events_are_unwrapped_with_envelope_ids() {
+        let envelope = json!({
+            "type": "event_callback", "team_id": "T1", "event_id": "Ev1", "event_time": 1700000000,
+            "event": {"type": "app_mention", "user": "U1", "text": "<@U0BOT> deploy"}
+        });
+        let (event_type, event) = unwrap_event(&envelope).unwrap_or_else(|| panic!("no event"));
+        assert_eq!(event_type, "app_mention");
+        assert_eq!(event["team_id"], "T1");
+        assert_eq!(event["event_id"], "Ev1");
+        assert_eq!(event["text"], "<@U0BOT> deploy");
+        assert!(unwrap_event(&json!({"type": "event_callback"})).is_none());
+    }
+}
@@ -0,0 +1,252 @@
+//! The subset of BER that SNMP messages use.
+//!
+//! Everything is a tag, a length and contents: integers are minimal two's
+//! complement, object identifiers pack the first two arcs into one and
+//! the rest base-128, and sequences (including the PDUs, which are
+//! context-specific constructed tags) nest further encodings.
+
+pub const INTEGER: u8 = 0x02;
+pub const OCTET_STRING: u8 = 0x04;
+pub const NULL: u8 = 0x05;
+pub const OBJECT_IDENTIFIER: u8 = 0x06;
+pub const SEQUENCE: u8 = 0x30;
+
+/// Append a tag-length-value to `out`.
+pub fn tlv(out: &mut Vec<u8>, tag: u8, contents: &[u8]) {
+    out.push(tag);
+    let len = contents.len();
+    if len < 0x80 {
+        out.push(len as u8);
+    } else {
+        let bytes = len.to_be_bytes();
+        let skip = bytes.iter().take_while(|b| **b == 0).count();
+        out.push(0x80 | (bytes.len() - skip) as u8);
+        out.extend_from_slice(&bytes[skip..]);
+    }
+    out.extend_from_slice(contents);
+}
+
+/// A constructed value whose contents `build` writes.
+pub fn nested(out: &mut Vec<u8>, tag: u8, build: impl FnOnce(&mut Vec<u8>)) {
+    let mut contents = Vec::new();
+    build(&mut contents);
+    tlv(out, tag, &contents);
+}
+
+/// Minimal two's complement contents of `n`.
+pub fn signed(n: i64) -> Vec<u8> {
+    let bytes = n.to_be_bytes();
+    let mut start = 0;
+    while start < 7 {
+        let redundant = (bytes[start] == 0x00 && bytes[start + 1] & 0x80 == 0)
+            || (bytes[start] == 0xFF && bytes[start + 1] & 0x80 != 0);
+        if !redundant {
+            break;
+        }
+        start += 1;
+    }
+    bytes[start..].to_vec()
+}
+
+/// Contents of an unsigned `n` (with a leading zero where the top bit is
+/// set, so it doesn't read as negative).
+pub fn unsigned(n: u64) -> Vec<u8> {
+    let bytes = n.to_be_bytes();
+    let skip = bytes.iter().take(7).take_while(|b| **b == 0).count();
+    let mut contents = Vec::with_capacity(9);
+    if bytes[skip] & 0x80 != 0 {
+        contents.push(0);
+    }
+    contents.extend_from_slice(&bytes[skip..]);
+    contents
+}
+
+pub fn integer(out: &mut Vec<u8>, n: i64) {
+    tlv(out, INTEGER, &signed(n));
+}
+
+pub fn octets(out: &mut Vec<u8>, bytes: &[u8]) {
+    tlv(out, OCTET_STRING, bytes);
+}
+
+/// Parse dotted `1.3.6.1...` (a leading dot is allowed).
+pub fn parse_oid(s: &str) -> Result<Vec<u32>, String> {
+    let arcs: Vec<u32> = s
+        .trim_start_matches('.')
+        .split('.')
+        .map(|arc| arc.parse::<u32>())
+        .collect::<Result<_, _>>()
+        .map_err(|_| format!("'{s}' is not a numeric OID"))?;
+    match arcs.as_slice() {
+        [first, second, ..] if *first <= 2 && (*first == 2 || *second < 40) => Ok(arcs),
+        _ => Err(format!("'{s}' is not a numeric OID")),
+    }
+}
+
+pub fn format_oid(arcs: &[u32]) -> String {
+    arcs.iter()
+        .map(u32::to_string)
+        .collect::<Vec<_>>()
+        .join(".")
+}
+
+/// Contents of an OID from [`parse_oid`].
+pub fn oid_contents(arcs: &[u32]) -> Vec<u8> {
+    let mut contents = Vec::new();
+    let first = arcs.first().copied().unwrap_or(0) * 40 + arcs.get(1).copied().unwrap_or(0);
+    for arc in std::iter::once(first).chain(arcs.iter().skip(2).copied()) {
+        let mut groups = vec![(arc & 0x7F) as u8];
+        let mut rest = arc >> 7;
+        while rest > 0 {
+            groups.push((rest & 0x7F) as u8 | 0x80);
+            rest >>= 7;
+        }
+        contents.extend(groups.iter().rev());
+    }
+    contents
+}
+
+pub fn oid(out: &mut Vec<u8>, arcs: &[u32]) {
+    tlv(out, OBJECT_IDENTIFIER, &oid_contents(arcs));
+}
+
+/// Reads consecutive encodings from a buffer.
+pub struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self { data }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    /// The next tag and its contents.
+    pub fn read(&mut self) -> Result<(u8, &'a [u8]), String> {
+        let truncated = || "truncated BER encoding".to_string();
+        let (&tag, rest) = self.data.split_first().ok_or_else(truncated)?;
+        let (&first, mut rest) = rest.split_first().ok_or_else(truncated)?;
+        let len = if first < 0x80 {
+            usize::from(first)
+        } else {
+            let count = usize::from(first & 0x7F);
+            if count == 0 || count > 4 || rest.len() < count {
+                return Err("unsupported BER length".to_string());
+            }
+            let len = rest[..count]
+                .iter()
+                .fold(0usize, |len, b| (len << 8) | usize::from(*b));
+            rest = &rest[count..];
+            len
+        };
+        if rest.len() < len {
+            return Err(truncated());
+        }
+        let (contents, rest) = rest.split_at(len);
+        self.data = rest;
+        Ok((tag, contents))
+    }
+
+    /// The next encoding, which must have `tag`.
+    pub fn expect(&mut self, tag: u8) -> Result<&'a [u8], String> {
+        let (found, contents) = self.read()?;
+        if found != tag {
+            return Err(format!("expected tag {tag:#04x}, found {found:#04x}"));
+        }
+        Ok(contents)
+    }
+
+    pub fn sequence(&mut self, tag: u8) -> Result<Reader<'a>, String> {
+        self.expect(tag).map(Reader::new)
+    }
+
+    pub fn integer(&mut self) -> Result<i64, String> {
+        read_signed(self.expect(INTEGER)?)
+    }
+
+    pub fn octets(&mut self) -> Result<&'a [u8], String> {
+        self.expect(OCTET_STRING)
+    }
+}
+
+pub fn read_signed(contents: &[u8]) -> Result<i64, String> {
+    if contents.is_empty() || contents.len() > 8 {
+        return Err("bad INTEGER".to_string());
+    }
+    let negative = contents[0] & 0x80 != 0;
+    Ok(contents
+        .iter()
+        .fold(if negative { -1 } else { 0 }, |n: i64, b| {
+            (n << 8) | i64::from(*b)
+        }))
+}
+
+pub fn read_unsigned(contents: &[u8]) -> Result<u64, String> {
+    let contents = match contents {
+        [0, rest @ ..] if !rest.is_empty() => rest,
+        _ => contents,
+    };
+    if contents.is_empty() || contents.len() > 8 {
+        return Err("bad unsigned integer".to_string());
+    }
+    Ok(contents.iter().fold(0u64, |n, b| (n << 8) | u64::from(*b)))
+}
+
+pub fn read_oid(contents: &[u8]) -> Result<Vec<u32>, String> {
+    let mut arcs = Vec::new();
+    let mut arc = 0u32;
+    for b in contents {
+        arc = arc
+            .checked_mul(128)
+            .ok_or("OID arc too large")?
+            .wrapping_add(u32::from(b & 0x7F));
+        if b & 0x80 != 0 {
+            continue;
+        }
+        if arcs.is_empty() {
+            let first = (arc / 40).min(2);
+            arcs.push(first);
+            arcs.push(arc - first * 40);
+        } else {
+            arcs.push(arc);
+        }
+        arc = 0;
+    }
+    if arcs.is_empty() || contents.last().is_some_and(|b| b & 0x80 != 0) {
+        return Err("bad OBJECT IDENTIFIER".to_string());
+    }
+    Ok(arcs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn integers_and_oids_round_trip() {
+        for n in [0, 1, 127, 128, 255, 256, -1, -128, -129, i64::MAX, i64::MIN] {
+            assert_eq!(read_signed(&signed(n)), Ok(n), "{n}");
+        }
+        assert_eq!(signed(128), [0x00, 0x80]);
+        assert_eq!(signed(-129), [0xFF, 0x7F]);
+        assert_eq!(unsigned(0xFFFF_FFFF), [0x00, 0xFF, 0xFF, 0xFF, 0xFF]);
+        assert_eq!(read_unsigned(&unsigned(u64::MAX)), Ok(u64::MAX));
+
+        let arcs = parse_oid(".1.3.6.1.2.1.2.2.1.7.100000").unwrap_or_else(|e| panic!("{e}"));
+        let contents = oid_contents(&arcs);
+        assert_eq!(&contents[..1], &[0x2B]);
+        assert_eq!(read_oid(&contents), Ok(arcs.clone()));
+        assert_eq!(format_oid(&arcs), "1.3.6.1.2.1.2.2.1.7.100000");
+        assert!(parse_oid("ifAdminStatus.3").is_err());
+
+        let mut out = Vec::new();
+        octets(&mut out, &[0xAB; 300]);
+        assert_eq!(&out[..4], &[0x04, 0x82, 0x01, 0x2C]);
+        let mut reader = Reader::new(&out);
+        assert_eq!(reader.octets().map(<[u8]>::len), Ok(300));
+        assert!(reader.is_empty());
+    }
+}
@@ -0,0 +1,140 @@
+//! Request-response exchanges with an agent over UDP.
+//!
+//! Each request is sent up to `retries + 1` times, waiting `timeout` for
+//! the response with the matching request (and, for v3, message) ID.
+//! Over v3 the engine is discovered before every operation, so boots and
+//! time are always fresh and a rebooted agent needs no special handling.
+
+use crate::pdu::{self, Pdu, VarBind};
+use crate::registry::{Device, Version};
+use crate::usm::{self, Engine, Keys};
+use std::sync::atomic::{AtomicI32, AtomicU64, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::net::UdpSocket;
+
+/// Sends requests to devices.
+#[derive(Debug)]
+pub struct Client {
+    timeout: Duration,
+    retries: u32,
+    ids: AtomicI32,
+    salts: AtomicU64,
+}
+
+impl Client {
+    pub fn new(timeout: Duration, retries: u32) -> Self {
+        let seed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_nanos() as u64);
+        Self {
+            timeout,
+            retries,
+            ids: AtomicI32::new((seed as i32 & 0x3FFF_FFFF).max(1)),
+            salts: AtomicU64::new(seed),
+        }
+    }
+
+    fn next_id(&self) -> i32 {
+        self.ids.fetch_add(1, Ordering::Relaxed) & 0x7FFF_FFFF
+    }
+
+    /// Send `message` until a datagram `accept` takes arrives.
+    async fn exchange<T>(
+        &self,
+        socket: &UdpSocket,
+        message: &[u8],
+        mut accept: impl FnMut(&[u8]) -> Option<Result<T, String>>,
+    ) -> Result<T, String> {
+        let mut buf = vec![0u8; 65535];
+        for _ in 0..=self.retries {
+            socket
+                .send(message)
+                .await
+                .map_err(|e| format!("send: {e}"))?;
+            let deadline = tokio::time::Instant::now() + self.timeout;
+            loop {
+                let received = tokio::time::timeout_at(deadline, socket.recv(&mut buf)).await;
+                let Ok(received) = received else {
+                    break;
+                };
+                let len = received.map_err(|e| format!("receive: {e}"))?;
+                if let Some(outcome) = accept(&buf[..len]) {
+                    return outcome;
+                }
+            }
+        }
+        Err(format!("no response after {} attempts", self.retries + 1))
+    }
+
+    /// Send a request PDU of `tag` to `device` and return the response.
+    pub async fn request(
+        &self,
+        device: &Device,
+        tag: u8,
+        varbinds: Vec<VarBind>,
+    ) -> Result<Pdu, String> {
+        let address = device.address();
+        let bind = if address.starts_with('[') {
+            "[::]:0"
+        } else {
+            "0.0.0.0:0"
+        };
+        let socket = UdpSocket::bind(bind)
+            .await
+            .map_err(|e| format!("bind: {e}"))?;
+        socket
+            .connect(&address)
+            .await
+            .map_err(|e| format!("{address}: {e}"))?;
+
+        let request_id = self.next_id();
+        let request = Pdu::request(tag, request_id, varbinds);
+        match device.version {
+            Version::V2c => {
+                let community = device.community.as_deref().unwrap_or_default();
+                let message = pdu::encode_v2c(community, &request);
+                self.exchange(&socket, &message, |datagram| {
+                    let response = pdu::decode_v2c(datagram).ok()?;
+                    (response.request_id == request_id).then_some(Ok(response))
+                })
+                .await
+            }
+            Version::V3 => {
+                let msg_id = self.next_id();
+                let discovery = usm::discovery(msg_id, self.next_id());
+                let engine: Engine = self
+                    .exchange(&socket, &discovery, |datagram| {
+                        let incoming = usm::decode(datagram, &Keys::none()).ok()?;
+                        (incoming.msg_id == msg_id).then_some(Ok(incoming.engine))
+                    })
+                    .await?;
+                if engine.id.is_empty() {
+                    return Err("agent did not report its engine ID".to_string());
+                }
+                let keys = Keys::localize(
+                    device.user.as_deref().unwrap_or_default(),
+                    device.auth.zip(device.auth_password.as_deref()),
+                    device.privacy.zip(device.privacy_password.as_deref()),
+                    &engine,
+                )?;
+                let msg_id = self.next_id();
+                let salt = self.salts.fetch_add(1, Ordering::Relaxed);
+                let message = usm::encode(msg_id, &engine, &keys, &device.context, &request, salt);
+                let response = self
+                    .exchange(&socket, &message, |datagram| {
+                        let incoming = match usm::decode(datagram, &keys) {
+                            Ok(incoming) if incoming.msg_id == msg_id => incoming,
+                            Ok(_) => return None,
+                            Err(e) => return Some(Err(e)),
+                        };
+                        Some(Ok(incoming.pdu))
+                    })
+                    .await?;
+                if response.tag == pdu::REPORT {
+                    return Err(usm::report_error(&response));
+                }
+                Ok(response)
+            }
+        }
+    }
+}
@@ -0,0 +1,454 @@
+//! SNMP Sink - SET Operations on Network Devices
+//!
+//! Each event names a device from the registry in `--config` (see
+//! [`registry`]) and the variables to set on it:
+//!
+//! ```json
+//! {"device": "core-sw1", "oid": "1.3.6.1.2.1.2.2.1.7.3", "value": 2}
+//! {"device": "pdu1", "varbinds": [
+//!   {"oid": "1.3.6.1.4.1.318.1.1.4.4.2.1.3.5", "value": 3, "type": "integer"}]}
+//! ```
+//!
+//! A value's `type` is a name or net-snmp's letter (`integer`/`i`,
+//! `string`/`s`, `hex`/`x`, `oid`/`o`, `ip`/`a`, `counter32`/`c`,
+//! `gauge32`/`u`, `timeticks`/`t`, `counter64`/`C`); without one, numbers
+//! are integers, booleans TruthValues and strings octet strings (see
+//! [`pdu`]). Only OIDs under the device's `allow` subtrees are set, all
+//! in one request, so the agent applies all or none of them.
+//!
+//! Devices speak v2c with a community or v3 with authentication and
+//! privacy (see [`usm`]). After a successful SET the variables are read
+//! back, unless `--no-verify` is given, and a value that didn't stick
+//! fails the event. Every SET attempted is reported with an
+//! `snmp.set.result` event: `ok`, `verified`, each variable with its
+//! `read_back` value, and the `error` if any.
+//!
+//! # Examples
+//!
+//! ```bash
+//! snmp-sink -s port.disable -s pdu.outlet --config /etc/emergent/snmp.json
+//! ```
+
+pub mod ber;
+pub mod client;
+pub mod pdu;
+pub mod registry;
+pub mod usm;
+
+use clap::Parser;
+use client::Client;
+use emergent_client::EmergentMessage;
+use pdu::{Pdu, Value, VarBind};
+use primitive_common::capabilities::VERSION;
+use primitive_common::doctor::Report;
+use primitive_common::errors::{ErrorCategory, HandlerError};
+use primitive_common::reload::HotConfig;
+use primitive_common::{Publisher, SinkArgs, SinkConfig, SinkContext, SinkHandler, run_sink};
+use registry::Device;
+use serde::{Deserialize, Serialize};
+use serde_json::{Value as Json, json};
+use std::collections::BTreeMap;
+use std::sync::OnceLock;
+use std::time::Duration;
+
+pub const RESULT_EVENT_TYPE: &str = "snmp.set.result";
+
+/// SNMP Sink — SET operations on network devices.
+#[derive(Parser, Debug)]
+#[command(name = "snmp_sink", version = VERSION)]
+#[command(about = "Set SNMP variables on configured devices and verify them")]
+struct Args {
+    /// Message types to subscribe to.
+    #[arg(short, long = "subscribe", required = true)]
+    subscribe: Vec<String>,
+
+    /// Milliseconds to wait for each response.
+    #[arg(short, long, env = "SNMP_SINK_TIMEOUT", default_value = "5000")]
+    timeout: u64,
+
+    /// Times a request is resent when no response arrives.
+    #[arg(short, long, env = "SNMP_SINK_RETRIES", default_value = "1")]
+    retries: u32,
+
+    /// Don't read variables back after setting them.
+    #[arg(long, env = "SNMP_SINK_NO_VERIFY")]
+    no_verify: bool,
+
+    #[command(flatten)]
+    sink: SinkArgs,
+}
+
+/// Settings that can be swapped on SIGHUP.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Settings {
+    devices: BTreeMap<String, Device>,
+}
+
+/// One variable to set, as given in a payload.
+#[derive(Debug, Deserialize)]
+struct Assignment {
+    oid: String,
+    value: Json,
+    #[serde(rename = "type")]
+    kind: Option<String>,
+}
+
+/// The variables a payload sets: its `varbinds`, or its own `oid`,
+/// `value` and `type`.
+fn assignments(payload: &Json) -> Result<Vec<Assignment>, String> {
+    let assignments = match payload.get("varbinds") {
+        Some(varbinds) => Vec::<Assignment>::deserialize(varbinds),
+        None => Assignment::deserialize(payload).map(|assignment| vec![assignment]),
+    }
+    .map_err(|e| format!("invalid varbinds: {e}"))?;
+    if assignments.is_empty() {
+        return Err("payload sets no variables".to_string());
+    }
+    Ok(assignments)
+}
+
+/// Varbinds for the payload's variables, where `device` allows them.
+fn varbinds(payload: &Json, name: &str, device: &Device) -> Result<Vec<VarBind>, HandlerError> {
+    let parse = |e: String| HandlerError::new(ErrorCategory::Parse, e);
+    let mut varbinds = Vec::new();
+    for assignment in assignments(payload).map_err(parse)? {
+        let oid = ber::parse_oid(&assignment.oid).map_err(parse)?;
+        if !device.allows(&oid) {
+            return Err(HandlerError::new(
+                ErrorCategory::Rejected,
+                format!("{} is not in {name}'s allowlist", assignment.oid),
+            ));
+        }
+        let value = Value::from_json(assignment.kind.as_deref(), &assignment.value)
+            .map_err(|e| parse(format!("{}: {e}", assignment.oid)))?;
+        varbinds.push((oid, value));
+    }
+    Ok(varbinds)
+}
+
+fn describe(varbinds: &[VarBind], read_back: Option<&[VarBind]>) -> Json {
+    let described: Vec<Json> = varbinds
+        .iter()
+        .enumerate()
+        .map(|(i, (oid, value))| {
+            let mut varbind = json!({
+                "oid": ber::format_oid(oid),
+                "type": value.type_name(),
+                "value": value.to_json(),
+            });
+            if let Some((_, read)) = read_back.and_then(|read_back| read_back.get(i)) {
+                varbind["read_back"] = read.to_json();
+            }
+            varbind
+        })
+        .collect();
+    json!(described)
+}
+
+/// Sets variables and confirms them.
+struct SnmpSink {
+    settings: HotConfig<Settings>,
+    client: Client,
+    verify: bool,
+    publisher: OnceLock<Publisher>,
+}
+
+impl SnmpSink {
+    /// Set `varbinds` on `device`, reading them back if verifying.
+    async fn set(
+        &self,
+        device: &Device,
+        varbinds: &[VarBind],
+    ) -> Result<Option<Vec<VarBind>>, HandlerError> {
+        let request = |e: String| HandlerError::new(ErrorCategory::Request, e);
+        let response = self
+            .client
+            .request(device, pdu::SET_REQUEST, varbinds.to_vec())
+            .await
+            .map_err(request)?;
+        if let Some(error) = response.error() {
+            return Err(HandlerError::new(ErrorCategory::Rejected, error));
+        }
+        if !self.verify {
+            return Ok(None);
+        }
+
+        let query = varbinds
+            .iter()
+            .map(|(oid, _)| (oid.clone(), Value::Null))
+            .collect();
+        let response: Pdu = self
+            .client
+            .request(device, pdu::GET_REQUEST, query)
+            .await
+            .map_err(|e| request(format!("read-back: {e}")))?;
+        if let Some(error) = response.error() {
+            return Err(request(format!("read-back: {error}")));
+        }
+        Ok(Some(response.varbinds))
+    }
+}
+
+impl SinkHandler for SnmpSink {
+    async fn handle(
+        &self,
+        msg: &EmergentMessage,
+        ctx: &SinkContext<'_>,
+    ) -> Result<(), HandlerError> {
+        let payload = ctx.payload();
+        let Some(name) = payload["device"].as_str() else {
+            return Err(HandlerError::new(
+                ErrorCategory::Parse,
+                "payload names no 'device'",
+            ));
+        };
+        let settings = self.settings.current();
+        let Some(device) = settings.devices.get(name) else {
+            return Err(HandlerError::new(
+                ErrorCategory::Rejected,
+                format!("unknown device '{name}'"),
+            ));
+        };
+        device
+            .validate()
+            .map_err(|e| HandlerError::new(ErrorCategory::Rejected, format!("{name}: {e}")))?;
+        let varbinds = varbinds(payload, name, device)?;
+
+        if ctx.is_dry_run() {
+            let detail = json!({
+                "device": name,
+                "host": device.address(),
+                "varbinds": describe(&varbinds, None),
+            });
+            ctx.would_have("set", detail).await;
+            return Ok(());
+        }
+
+        let (read_back, result) = match self.set(device, &varbinds).await {
+            Ok(Some(read_back)) => {
+                let differs = varbinds.len() != read_back.len()
+                    || varbinds
+                        .iter()
+                        .zip(&read_back)
+                        .any(|(set, read)| set != read);
+                let result = if differs {
+                    Err(HandlerError::new(
+                        ErrorCategory::Rejected,
+                        format!("{name}: values read back differ from those set"),
+                    ))
+                } else {
+                    Ok(())
+                };
+                (Some(read_back), result)
+            }
+            Ok(None) => (None, Ok(())),
+            Err(e) => (None, Err(e)),
+        };
+        let error = result.as_ref().err().map(ToString::to_string);
+        match &error {
+            None => eprintln!("set {} variable(s) on {name}: ok", varbinds.len()),
+            Some(e) => eprintln!("set {} variable(s) on {name}: {e}", varbinds.len()),
+        }
+        if let Some(publisher) = self.publisher.get() {
+            let payload = json!({
+                "device": name,
+                "host": device.address(),
+                "ok": error.is_none(),
+                "verified": read_back.as_ref().map(|_| error.is_none()),
+                "varbinds": describe(&varbinds, read_back.as_deref()),
+                "error": error,
+                "message_id": ctx.message_id(),
+                "message_type": msg.message_type.as_str(),
+            });
+            let message = EmergentMessage::new(RESULT_EVENT_TYPE).with_payload(payload);
+            if let Err(e) = publisher.publish(message) {
+                eprintln!("Failed to publish {RESULT_EVENT_TYPE}: {e}");
+            }
+        }
+        result
+    }
+
+    fn self_test(&self, report: &mut Report) {
+        let settings = self.settings.current();
+        report.check(
+            "devices",
+            Ok(format!("{} configured", settings.devices.len())),
+        );
+        for (name, device) in &settings.devices {
+            report.check(
+                &format!("device {name}"),
+                device.validate().map(|()| device.address()),
+            );
+        }
+    }
+
+    fn reload(&self) -> Result<Vec<String>, String> {
+        self.settings.reload()
+    }
+
+    fn publishes(&self) -> &'static [&'static str] {
+        &[RESULT_EVENT_TYPE]
+    }
+
+    fn attach(&self, publisher: Publisher) {
+        let _ = self.publisher.set(publisher);
+    }
+}
+
+/// Run the primitive with `args` as its command line (the first item is
+/// the program name, as with `std::env::args_os`).
+pub async fn run<I, T>(args: I) -> Result<(), Box<dyn std::error::Error>>
+where
+    I: IntoIterator<Item = T>,
+    T: Into<std::ffi::OsString> + Clone,
+{
+    let args = Args::parse_from(args);
+
+    let config = SinkConfig {
+        name: "snmp_sink",
+        subscribe: &args.subscribe,
+        would_have_as: "snmp.would_have",
+        dead_letter_as: "snmp.dead_letter",
+        settings: &args,
+    };
+    let settings = match HotConfig::load(&args.sink.reload, Settings::default()) {
+        Ok(settings) => settings,
+        Err(e) => {
+            eprintln!("Error: invalid config: {e}");
+            std::process::exit(1);
+        }
+    };
+    let handler = SnmpSink {
+        settings,
+        client: Client::new(Duration::from_millis(args.timeout), args.retries),
+        verify: !args.no_verify,
+        publisher: OnceLock::new(),
+    };
+
+    run_sink(config, &args.sink, handler).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use emergent_testkit::{SinkFixture, fixtures, spawn_sink};
+    use primitive_common::reload::ReloadArgs;
+    use std::collections::HashMap;
+    use tokio::net::UdpSocket;
+
+    /// A v2c agent holding its variables in a map; `sysLocation` won't
+    /// take new values, but says it did.
+    async fn agent(socket: UdpSocket) {
+        let stuck = ber::parse_oid("1.3.6.1.2.1.1.6.0").unwrap_or_else(|e| panic!("{e}"));
+        let mut variables: HashMap<Vec<u32>, Value> = HashMap::new();
+        let mut buf = [0u8; 1500];
+        loop {
+            let Ok((len, peer)) = socket.recv_from(&mut buf).await else {
+                return;
+            };
+            let Ok(mut request) = pdu::decode_v2c(&buf[..len]) else {
+                continue;
+            };
+            for (oid, value) in &mut request.varbinds {
+                if request.tag == pdu::SET_REQUEST {
+                    if *oid != stuck {
+                        variables.insert(oid.clone(), value.clone());
+                    }
+                } else {
+                    *value = variables.get(oid).cloned().unwrap_or(Value::NoSuchInstance);
+                }
+            }
+            request.tag = pdu::RESPONSE;
+            let _ = socket
+                .send_to(&pdu::encode_v2c("private", &request), peer)
+                .await;
+        }
+    }
+
+    #[tokio::test]
+    async fn sets_are_applied_read_back_and_reported() {
+        let socket = UdpSocket::bind("127.0.0.1:0")
+            .await
+            .unwrap_or_else(|e| panic!("bind: {e}"));
+        let address = socket
+            .local_addr()
+            .unwrap_or_else(|e| panic!("local_addr: {e}"));
+        tokio::spawn(agent(socket));
+
+        let devices = BTreeMap::from([(
+            "core-sw1".to_string(),
+            Device {
+                host: address.to_string(),
+                community: Some("private".to_string()),
+                allow: vec![
+                    "1.3.6.1.2.1.2.2.1.7".to_string(),
+                    "1.3.6.1.2.1.1".to_string(),
+                ],
+                ..Default::default()
+            },
+        )]);
+        let settings = HotConfig::load(&ReloadArgs::default(), Settings { devices })
+            .unwrap_or_else(|e| panic!("load settings: {e}"));
+        let handler = SnmpSink {
+            settings,
+            client: Client::new(Duration::from_secs(2), 0),
+            verify: true,
+            publisher: OnceLock::new(),
+        };
+        let (mut engine, run) = spawn_sink(
+            SinkFixture::new("snmp_sink", "snmp"),
+            SinkArgs {
+                dead_letter: true,
+                ..Default::default()
+            },
+            handler,
+        );
+
+        engine
+            .inject_message(fixtures::message(
+                "port.disable",
+                json!({"device": "core-sw1", "varbinds": [
+                    {"oid": "1.3.6.1.2.1.2.2.1.7.3", "value": 2},
+                    {"oid": "1.3.6.1.2.1.1.5.0", "value": "core-sw1", "type": "s"},
+                ]}),
+            ))
+            .await;
+        let result = engine.expect_published(RESULT_EVENT_TYPE).await;
+        assert_eq!(result.payload()["ok"], true);
+        assert_eq!(result.payload()["verified"], true);
+        assert_eq!(
+            result.payload()["varbinds"],
+            json!([
+                {"oid": "1.3.6.1.2.1.2.2.1.7.3", "type": "integer", "value": 2, "read_back": 2},
+                {"oid": "1.3.6.1.2.1.1.5.0", "type": "string", "value": "core-sw1", "read_back": "core-sw1"},
+            ])
+        );
+
+        engine
+            .inject_message(fixtures::message(
+                "port.disable",
+                json!({"device": "core-sw1", "oid": "1.3.6.1.2.1.1.6.0", "value": "rack 4"}),
+            ))
+            .await;
+        let result = engine.expect_published(RESULT_EVENT_TYPE).await;
+        assert_eq!(result.payload()["ok"], false);
+        assert_eq!(result.payload()["verified"], false);
+        engine.expect_published("snmp.dead_letter").await;
+
+        engine
+            .inject_message(fixtures::message(
+                "port.disable",
+                json!({"device": "core-sw1", "oid": "1.3.6.1.4.1.9.9.1", "value": 1}),
+            ))
+            .await;
+        let dead = engine.expect_published("snmp.dead_letter").await;
+        let error = dead.payload()["error"]
+            .as_str()
+            .unwrap_or_default()
+            .to_string();
+        assert!(error.contains("allowlist"), "{error}");
+
+        engine.close();
+        assert_eq!(run.await.ok(), Some(Ok(())));
+    }
+}
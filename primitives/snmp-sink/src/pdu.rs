@@ -0,0 +1,351 @@
+//! Values, PDUs and the community-based (v2c) message wrapping.
+
+use crate::ber::{self, Reader};
+use serde_json::{Value as Json, json};
+
+pub const GET_REQUEST: u8 = 0xA0;
+pub const RESPONSE: u8 = 0xA2;
+pub const SET_REQUEST: u8 = 0xA3;
+pub const REPORT: u8 = 0xA8;
+
+const IP_ADDRESS: u8 = 0x40;
+const COUNTER32: u8 = 0x41;
+const GAUGE32: u8 = 0x42;
+const TIMETICKS: u8 = 0x43;
+const COUNTER64: u8 = 0x46;
+const NO_SUCH_OBJECT: u8 = 0x80;
+const NO_SUCH_INSTANCE: u8 = 0x81;
+const END_OF_MIB_VIEW: u8 = 0x82;
+
+/// A variable's value.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Value {
+    Integer(i64),
+    OctetString(Vec<u8>),
+    Null,
+    Oid(Vec<u32>),
+    IpAddress([u8; 4]),
+    Counter32(u32),
+    Gauge32(u32),
+    TimeTicks(u32),
+    Counter64(u64),
+    NoSuchObject,
+    NoSuchInstance,
+    EndOfMibView,
+}
+
+fn hex(s: &str) -> Result<Vec<u8>, String> {
+    let digits: String = s
+        .trim_start_matches("0x")
+        .chars()
+        .filter(|c| !matches!(c, ' ' | ':' | '-'))
+        .collect();
+    if !digits.len().is_multiple_of(2) {
+        return Err(format!("'{s}' is not hex"));
+    }
+    (0..digits.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&digits[i..i + 2], 16).map_err(|_| format!("'{s}' is not hex")))
+        .collect()
+}
+
+fn number<T: TryFrom<i64> + std::str::FromStr>(value: &Json, kind: &str) -> Result<T, String> {
+    let parsed = match value {
+        Json::Number(n) => n.as_i64().and_then(|n| T::try_from(n).ok()),
+        Json::String(s) => s.trim().parse().ok(),
+        _ => None,
+    };
+    parsed.ok_or_else(|| format!("{value} is not a valid {kind}"))
+}
+
+impl Value {
+    /// The value for JSON `value`, as `kind` (a type name or net-snmp's
+    /// letter) or, without one, inferred: numbers are integers, booleans
+    /// TruthValues and strings octet strings.
+    pub fn from_json(kind: Option<&str>, value: &Json) -> Result<Self, String> {
+        let text = || match value {
+            Json::String(s) => Ok(s.clone()),
+            Json::Number(n) => Ok(n.to_string()),
+            _ => Err(format!("{value} is not a string")),
+        };
+        let Some(kind) = kind else {
+            return match value {
+                Json::Number(_) => number(value, "integer").map(Self::Integer),
+                Json::Bool(b) => Ok(Self::Integer(if *b { 1 } else { 2 })),
+                Json::String(s) => Ok(Self::OctetString(s.clone().into_bytes())),
+                _ => Err(format!("{value} needs a 'type'")),
+            };
+        };
+        Ok(match kind {
+            "integer" | "i" => Self::Integer(number(value, "integer")?),
+            "string" | "s" => Self::OctetString(text()?.into_bytes()),
+            "hex" | "x" => Self::OctetString(hex(&text()?)?),
+            "oid" | "o" => Self::Oid(ber::parse_oid(&text()?)?),
+            "ip" | "a" => {
+                let ip: std::net::Ipv4Addr = text()?
+                    .parse()
+                    .map_err(|_| format!("{value} is not an IPv4 address"))?;
+                Self::IpAddress(ip.octets())
+            }
+            "counter32" | "c" => Self::Counter32(number(value, "counter32")?),
+            "gauge32" | "unsigned" | "u" => Self::Gauge32(number(value, "gauge32")?),
+            "timeticks" | "t" => Self::TimeTicks(number(value, "timeticks")?),
+            "counter64" | "C" => Self::Counter64(
+                text()?
+                    .parse()
+                    .map_err(|_| format!("{value} is not a valid counter64"))?,
+            ),
+            _ => return Err(format!("unknown type '{kind}'")),
+        })
+    }
+
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Self::Integer(_) => "integer",
+            Self::OctetString(_) => "string",
+            Self::Null => "null",
+            Self::Oid(_) => "oid",
+            Self::IpAddress(_) => "ip",
+            Self::Counter32(_) => "counter32",
+            Self::Gauge32(_) => "gauge32",
+            Self::TimeTicks(_) => "timeticks",
+            Self::Counter64(_) => "counter64",
+            Self::NoSuchObject => "noSuchObject",
+            Self::NoSuchInstance => "noSuchInstance",
+            Self::EndOfMibView => "endOfMibView",
+        }
+    }
+
+    /// The value for an event payload; octet strings that aren't UTF-8
+    /// are hex.
+    pub fn to_json(&self) -> Json {
+        match self {
+            Self::Integer(n) => json!(n),
+            Self::OctetString(bytes) => match std::str::from_utf8(bytes) {
+                Ok(s) => json!(s),
+                Err(_) => json!(bytes.iter().map(|b| format!("{b:02x}")).collect::<String>()),
+            },
+            Self::Oid(arcs) => json!(ber::format_oid(arcs)),
+            Self::IpAddress(ip) => json!(std::net::Ipv4Addr::from(*ip).to_string()),
+            Self::Counter32(n) | Self::Gauge32(n) | Self::TimeTicks(n) => json!(n),
+            Self::Counter64(n) => json!(n),
+            Self::Null | Self::NoSuchObject | Self::NoSuchInstance | Self::EndOfMibView => {
+                Json::Null
+            }
+        }
+    }
+
+    fn encode(&self, out: &mut Vec<u8>) {
+        match self {
+            Self::Integer(n) => ber::integer(out, *n),
+            Self::OctetString(bytes) => ber::octets(out, bytes),
+            Self::Null => ber::tlv(out, ber::NULL, &[]),
+            Self::Oid(arcs) => ber::oid(out, arcs),
+            Self::IpAddress(ip) => ber::tlv(out, IP_ADDRESS, ip),
+            Self::Counter32(n) => ber::tlv(out, COUNTER32, &ber::unsigned(u64::from(*n))),
+            Self::Gauge32(n) => ber::tlv(out, GAUGE32, &ber::unsigned(u64::from(*n))),
+            Self::TimeTicks(n) => ber::tlv(out, TIMETICKS, &ber::unsigned(u64::from(*n))),
+            Self::Counter64(n) => ber::tlv(out, COUNTER64, &ber::unsigned(*n)),
+            Self::NoSuchObject => ber::tlv(out, NO_SUCH_OBJECT, &[]),
+            Self::NoSuchInstance => ber::tlv(out, NO_SUCH_INSTANCE, &[]),
+            Self::EndOfMibView => ber::tlv(out, END_OF_MIB_VIEW, &[]),
+        }
+    }
+
+    fn decode(tag: u8, contents: &[u8]) -> Result<Self, String> {
+        let u32 = |contents: &[u8]| {
+            ber::read_unsigned(contents)
+                .and_then(|n| u32::try_from(n).map_err(|_| "32-bit value out of range".to_string()))
+        };
+        Ok(match tag {
+            ber::INTEGER => Self::Integer(ber::read_signed(contents)?),
+            ber::OCTET_STRING => Self::OctetString(contents.to_vec()),
+            ber::NULL => Self::Null,
+            ber::OBJECT_IDENTIFIER => Self::Oid(ber::read_oid(contents)?),
+            IP_ADDRESS => Self::IpAddress(
+                contents
+                    .try_into()
+                    .map_err(|_| "bad IpAddress".to_string())?,
+            ),
+            COUNTER32 => Self::Counter32(u32(contents)?),
+            GAUGE32 => Self::Gauge32(u32(contents)?),
+            TIMETICKS => Self::TimeTicks(u32(contents)?),
+            COUNTER64 => Self::Counter64(ber::read_unsigned(contents)?),
+            NO_SUCH_OBJECT => Self::NoSuchObject,
+            NO_SUCH_INSTANCE => Self::NoSuchInstance,
+            END_OF_MIB_VIEW => Self::EndOfMibView,
+            _ => return Err(format!("unsupported value type {tag:#04x}")),
+        })
+    }
+}
+
+/// An OID and its value.
+pub type VarBind = (Vec<u32>, Value);
+
+/// A protocol data unit.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pdu {
+    pub tag: u8,
+    pub request_id: i32,
+    pub error_status: i64,
+    pub error_index: i64,
+    pub varbinds: Vec<VarBind>,
+}
+
+impl Pdu {
+    pub fn request(tag: u8, request_id: i32, varbinds: Vec<VarBind>) -> Self {
+        Self {
+            tag,
+            request_id,
+            error_status: 0,
+            error_index: 0,
+            varbinds,
+        }
+    }
+
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        ber::nested(out, self.tag, |out| {
+            ber::integer(out, i64::from(self.request_id));
+            ber::integer(out, self.error_status);
+            ber::integer(out, self.error_index);
+            ber::nested(out, ber::SEQUENCE, |out| {
+                for (oid, value) in &self.varbinds {
+                    ber::nested(out, ber::SEQUENCE, |out| {
+                        ber::oid(out, oid);
+                        value.encode(out);
+                    });
+                }
+            });
+        });
+    }
+
+    pub fn decode(tag: u8, contents: &[u8]) -> Result<Self, String> {
+        let mut reader = Reader::new(contents);
+        let request_id = i32::try_from(reader.integer()?).map_err(|_| "bad request-id")?;
+        let error_status = reader.integer()?;
+        let error_index = reader.integer()?;
+        let mut list = reader.sequence(ber::SEQUENCE)?;
+        let mut varbinds = Vec::new();
+        while !list.is_empty() {
+            let mut varbind = list.sequence(ber::SEQUENCE)?;
+            let oid = ber::read_oid(varbind.expect(ber::OBJECT_IDENTIFIER)?)?;
+            let (tag, contents) = varbind.read()?;
+            varbinds.push((oid, Value::decode(tag, contents)?));
+        }
+        Ok(Self {
+            tag,
+            request_id,
+            error_status,
+            error_index,
+            varbinds,
+        })
+    }
+
+    /// The error the agent reported, naming the varbind it concerns.
+    pub fn error(&self) -> Option<String> {
+        if self.error_status == 0 {
+            return None;
+        }
+        let status = match self.error_status {
+            1 => "tooBig",
+            2 => "noSuchName",
+            3 => "badValue",
+            4 => "readOnly",
+            5 => "genErr",
+            6 => "noAccess",
+            7 => "wrongType",
+            8 => "wrongLength",
+            9 => "wrongEncoding",
+            10 => "wrongValue",
+            11 => "noCreation",
+            12 => "inconsistentValue",
+            13 => "resourceUnavailable",
+            14 => "commitFailed",
+            15 => "undoFailed",
+            16 => "authorizationError",
+            17 => "notWritable",
+            18 => "inconsistentName",
+            _ => "unknown error",
+        };
+        let index = usize::try_from(self.error_index).unwrap_or(0);
+        Some(
+            match index.checked_sub(1).and_then(|i| self.varbinds.get(i)) {
+                Some((oid, _)) => format!("{status} ({})", ber::format_oid(oid)),
+                None => status.to_string(),
+            },
+        )
+    }
+}
+
+/// A v2c message carrying `pdu` with `community`.
+pub fn encode_v2c(community: &str, pdu: &Pdu) -> Vec<u8> {
+    let mut out = Vec::new();
+    ber::nested(&mut out, ber::SEQUENCE, |out| {
+        ber::integer(out, 1);
+        ber::octets(out, community.as_bytes());
+        pdu.encode(out);
+    });
+    out
+}
+
+/// The PDU of a v2c message.
+pub fn decode_v2c(message: &[u8]) -> Result<Pdu, String> {
+    let mut message = Reader::new(message).sequence(ber::SEQUENCE)?;
+    if message.integer()? != 1 {
+        return Err("not an SNMPv2c message".to_string());
+    }
+    message.octets()?;
+    let (tag, contents) = message.read()?;
+    Pdu::decode(tag, contents)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn values_come_from_json_and_survive_the_wire() {
+        let ifadmin = ber::parse_oid("1.3.6.1.2.1.2.2.1.7.3").unwrap_or_else(|e| panic!("{e}"));
+        let cases = [
+            (None, json!(2), Value::Integer(2)),
+            (None, json!(true), Value::Integer(1)),
+            (
+                None,
+                json!("core-sw1"),
+                Value::OctetString(b"core-sw1".to_vec()),
+            ),
+            (
+                Some("x"),
+                json!("de:ad:be:ef"),
+                Value::OctetString(vec![0xDE, 0xAD, 0xBE, 0xEF]),
+            ),
+            (
+                Some("ip"),
+                json!("10.0.0.1"),
+                Value::IpAddress([10, 0, 0, 1]),
+            ),
+            (
+                Some("gauge32"),
+                json!("4000000000"),
+                Value::Gauge32(4_000_000_000),
+            ),
+            (Some("counter64"), json!(7), Value::Counter64(7)),
+            (Some("oid"), json!(".1.3.6.1"), Value::Oid(vec![1, 3, 6, 1])),
+        ];
+        for (kind, json, value) in cases {
+            assert_eq!(Value::from_json(kind, &json), Ok(value.clone()), "{json}");
+            let pdu = Pdu::request(SET_REQUEST, 42, vec![(ifadmin.clone(), value)]);
+            assert_eq!(decode_v2c(&encode_v2c("private", &pdu)), Ok(pdu));
+        }
+        assert!(Value::from_json(Some("integer"), &json!("up")).is_err());
+        assert!(Value::from_json(Some("gauge32"), &json!(-1)).is_err());
+
+        let mut failed = Pdu::request(RESPONSE, 42, vec![(ifadmin, Value::Integer(9))]);
+        failed.error_status = 10;
+        failed.error_index = 1;
+        assert_eq!(
+            failed.error().as_deref(),
+            Some("wrongValue (1.3.6.1.2.1.2.2.1.7.3)")
+        );
+    }
+}
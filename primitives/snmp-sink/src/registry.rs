@@ -0,0 +1,140 @@
+//! The devices the sink may write to, as listed in `--config`.
+//!
+//! ```json
+//! {"devices": {
+//!   "core-sw1": {"host": "10.0.0.2", "community": "private",
+//!                "allow": ["1.3.6.1.2.1.2.2.1.7", "1.3.6.1.2.1.1.5.0"]},
+//!   "pdu1":     {"host": "10.0.0.9", "version": "v3", "user": "emergent",
+//!                "auth": "sha256", "auth_password": "...",
+//!                "privacy": "aes", "privacy_password": "...",
+//!                "allow": ["1.3.6.1.4.1.318.1.1.4.4.2.1.3"]}
+//! }}
+//! ```
+//!
+//! `allow` lists the OID subtrees a device accepts SETs under; a device
+//! with none accepts nothing. `auth` is `md5`, `sha`, `sha224`, `sha256`,
+//! `sha384` or `sha512` and `privacy` is `des` or `aes` (AES-128). The
+//! file is re-read on SIGHUP.
+
+use crate::ber;
+use crate::usm::{Auth, Privacy};
+use serde::{Deserialize, Serialize};
+
+/// SNMP version.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Version {
+    #[default]
+    V2c,
+    V3,
+}
+
+/// One writable device.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Device {
+    /// `host[:port]`; the port defaults to 161.
+    pub host: String,
+    #[serde(default)]
+    pub version: Version,
+    /// v2c community.
+    #[serde(default)]
+    pub community: Option<String>,
+    /// v3 user.
+    #[serde(default)]
+    pub user: Option<String>,
+    #[serde(default)]
+    pub auth: Option<Auth>,
+    #[serde(default)]
+    pub auth_password: Option<String>,
+    #[serde(default)]
+    pub privacy: Option<Privacy>,
+    #[serde(default)]
+    pub privacy_password: Option<String>,
+    /// v3 context name.
+    #[serde(default)]
+    pub context: String,
+    /// OID subtrees SETs may target.
+    #[serde(default)]
+    pub allow: Vec<String>,
+}
+
+impl Device {
+    pub fn address(&self) -> String {
+        let host = &self.host;
+        match host.matches(':').count() {
+            0 => format!("{host}:161"),
+            1 => host.clone(),
+            // IPv6, bracketed or bare
+            _ if host.starts_with('[') && !host.ends_with(']') => host.clone(),
+            _ if host.starts_with('[') => format!("{host}:161"),
+            _ => format!("[{host}]:161"),
+        }
+    }
+
+    /// Whether `oid` lies under one of the `allow` subtrees.
+    pub fn allows(&self, oid: &[u32]) -> bool {
+        self.allow
+            .iter()
+            .filter_map(|subtree| ber::parse_oid(subtree).ok())
+            .any(|subtree| oid.starts_with(&subtree))
+    }
+
+    /// What's missing for the device's version.
+    pub fn validate(&self) -> Result<(), String> {
+        for subtree in &self.allow {
+            ber::parse_oid(subtree)?;
+        }
+        match self.version {
+            Version::V2c if self.community.is_none() => Err("v2c needs a community".to_string()),
+            Version::V3 if self.user.is_none() => Err("v3 needs a user".to_string()),
+            Version::V3 if self.auth.is_some() != self.auth_password.is_some() => {
+                Err("auth and auth_password go together".to_string())
+            }
+            Version::V3 if self.privacy.is_some() != self.privacy_password.is_some() => {
+                Err("privacy and privacy_password go together".to_string())
+            }
+            Version::V3 if self.privacy.is_some() && self.auth.is_none() => {
+                Err("privacy needs auth".to_string())
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sets_are_confined_to_allowed_subtrees() {
+        let device = Device {
+            host: "10.0.0.2".to_string(),
+            community: Some("private".to_string()),
+            allow: vec![
+                "1.3.6.1.2.1.2.2.1.7".to_string(),
+                ".1.3.6.1.2.1.1.5.0".to_string(),
+            ],
+            ..Default::default()
+        };
+        assert_eq!(device.address(), "10.0.0.2:161");
+        assert_eq!(device.validate(), Ok(()));
+        let oid = |s: &str| ber::parse_oid(s).unwrap_or_else(|e| panic!("{e}"));
+        assert!(device.allows(&oid("1.3.6.1.2.1.2.2.1.7.3")));
+        assert!(device.allows(&oid("1.3.6.1.2.1.1.5.0")));
+        assert!(!device.allows(&oid("1.3.6.1.2.1.2.2.1.70.3")));
+        assert!(!device.allows(&oid("1.3.6.1.2.1.1.6.0")));
+        assert!(!Device::default().allows(&oid("1.3.6.1.2.1.1.5.0")));
+
+        let v3 = Device {
+            host: "fe80::1".to_string(),
+            version: Version::V3,
+            user: Some("emergent".to_string()),
+            privacy: Some(Privacy::Aes),
+            privacy_password: Some("privpass1".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(v3.address(), "[fe80::1]:161");
+        assert_eq!(v3.validate(), Err("privacy needs auth".to_string()));
+    }
+}
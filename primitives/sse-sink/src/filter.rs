@@ -0,0 +1,44 @@
+//! Which events a stream carries.
+//!
+//! A filter is a list of message type patterns, where `*` is any run of
+//! characters (`deploy.*`, `*.failed`). An empty filter lets every event
+//! through.
+
+use primitive_common::glob;
+
+/// The message types a stream carries.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Filter(Vec<String>);
+
+impl Filter {
+    /// A filter from a comma-separated list of patterns.
+    pub fn parse(list: &str) -> Self {
+        Self(
+            list.split(',')
+                .map(str::trim)
+                .filter(|p| !p.is_empty())
+                .map(str::to_string)
+                .collect(),
+        )
+    }
+
+    pub fn matches(&self, message_type: &str) -> bool {
+        self.0.is_empty() || self.0.iter().any(|p| glob::matches(p, message_type))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn filters_match_globs() {
+        let filter = Filter::parse("deploy.*, *.failed,,");
+        assert!(filter.matches("deploy.started"));
+        assert!(filter.matches("backup.failed"));
+        assert!(!filter.matches("alert.fired"));
+        assert!(Filter::parse("alert.fired").matches("alert.fired"));
+        assert!(!Filter::parse("alert.fired").matches("alert.fired.twice"));
+        assert!(Filter::parse("").matches("anything"));
+    }
+}
@@ -0,0 +1,212 @@
+//! The live channel and the bounded history behind every stream.
+//!
+//! Each event gets an id `<epoch>-<seq>`: the time the sink started, in
+//! Unix milliseconds, and a counter. A client that reconnects with a
+//! `Last-Event-ID` from this run is sent what it missed, as far as the
+//! ring buffer reaches; one from an earlier run is sent everything the
+//! buffer holds, since its counter means nothing any more.
+
+use serde_json::{Value, json};
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::sync::broadcast;
+
+/// One event as streams send it.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Entry {
+    pub seq: u64,
+    pub message_type: String,
+    /// The `data:` line, `{"type", "id", "payload"}`.
+    pub data: String,
+}
+
+/// The most recent events, oldest first.
+#[derive(Debug)]
+pub struct Ring {
+    entries: VecDeque<Arc<Entry>>,
+    capacity: usize,
+    next: u64,
+}
+
+impl Ring {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            entries: VecDeque::new(),
+            capacity,
+            next: 1,
+        }
+    }
+
+    /// Keep an event, dropping the oldest beyond capacity.
+    pub fn push(&mut self, message_type: &str, data: String) -> Arc<Entry> {
+        let entry = Arc::new(Entry {
+            seq: self.next,
+            message_type: message_type.to_string(),
+            data,
+        });
+        self.next += 1;
+        if self.capacity > 0 {
+            if self.entries.len() == self.capacity {
+                self.entries.pop_front();
+            }
+            self.entries.push_back(Arc::clone(&entry));
+        }
+        entry
+    }
+
+    /// The sequence number of the newest event, 0 before the first.
+    pub fn head(&self) -> u64 {
+        self.next - 1
+    }
+
+    /// The kept events after `seq`.
+    pub fn after(&self, seq: u64) -> VecDeque<Arc<Entry>> {
+        let start = self.entries.partition_point(|e| e.seq <= seq);
+        self.entries.range(start..).cloned().collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+/// A stream just opened.
+pub struct Subscription {
+    /// The sequence number the stream picks up after.
+    pub after: u64,
+    /// Kept events it missed while away.
+    pub backlog: VecDeque<Arc<Entry>>,
+    pub receiver: broadcast::Receiver<Arc<Entry>>,
+}
+
+/// Where events reach the streams from.
+pub struct Hub {
+    epoch: u64,
+    ring: Mutex<Ring>,
+    sender: broadcast::Sender<Arc<Entry>>,
+}
+
+impl Hub {
+    /// A hub keeping `replay` events for reconnecting clients and letting
+    /// a stream fall `buffer` events behind before it catches up from them.
+    pub fn new(replay: usize, buffer: usize) -> Self {
+        let epoch = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or_default();
+        let (sender, _) = broadcast::channel(buffer.max(1));
+        Self {
+            epoch,
+            ring: Mutex::new(Ring::new(replay)),
+            sender,
+        }
+    }
+
+    fn ring(&self) -> std::sync::MutexGuard<'_, Ring> {
+        self.ring.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Keep an event and send it to the open streams.
+    pub fn publish(&self, message_type: &str, id: &str, payload: &Value) {
+        let data = json!({ "type": message_type, "id": id, "payload": payload }).to_string();
+        let mut ring = self.ring();
+        let entry = ring.push(message_type, data);
+        // Sending fails only when no stream is open
+        let _ = self.sender.send(entry);
+    }
+
+    /// Open a stream after `last_event_id`, or at the newest event if
+    /// none is given.
+    pub fn subscribe(&self, last_event_id: Option<&str>) -> Subscription {
+        // Both under the lock, so no event falls between them
+        let ring = self.ring();
+        let after = match last_event_id {
+            Some(id) => self.resume_after(id),
+            None => ring.head(),
+        };
+        Subscription {
+            after,
+            backlog: ring.after(after),
+            receiver: self.sender.subscribe(),
+        }
+    }
+
+    /// The kept events after `seq`, for a stream that fell behind.
+    pub fn after(&self, seq: u64) -> VecDeque<Arc<Entry>> {
+        self.ring().after(seq)
+    }
+
+    /// Events currently kept for replay.
+    pub fn kept(&self) -> usize {
+        self.ring().len()
+    }
+
+    /// The id a stream sends `entry` with.
+    pub fn event_id(&self, entry: &Entry) -> String {
+        format!("{}-{}", self.epoch, entry.seq)
+    }
+
+    /// The sequence number to replay after for a `Last-Event-ID`.
+    fn resume_after(&self, id: &str) -> u64 {
+        let parsed = id
+            .split_once('-')
+            .and_then(|(epoch, seq)| Some((epoch.parse::<u64>().ok()?, seq.parse().ok()?)));
+        match parsed {
+            Some((epoch, seq)) if epoch == self.epoch => seq,
+            // From an earlier run or not ours at all
+            _ => 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn seqs(entries: &VecDeque<Arc<Entry>>) -> Vec<u64> {
+        entries.iter().map(|e| e.seq).collect()
+    }
+
+    #[test]
+    fn the_ring_keeps_the_newest_events() {
+        let mut ring = Ring::new(3);
+        for n in 0..5 {
+            ring.push("tick", n.to_string());
+        }
+        assert_eq!(ring.len(), 3);
+        assert_eq!(seqs(&ring.after(0)), [3, 4, 5]);
+        assert_eq!(seqs(&ring.after(4)), [5]);
+        assert!(ring.after(5).is_empty());
+
+        let mut none = Ring::new(0);
+        assert_eq!(none.push("tick", String::new()).seq, 1);
+        assert!(none.is_empty());
+    }
+
+    #[test]
+    fn streams_resume_after_the_last_event_id() {
+        let hub = Hub::new(10, 10);
+        hub.publish("a", "m1", &json!(1));
+        hub.publish("b", "m2", &json!(2));
+        hub.publish("c", "m3", &json!(3));
+        let first = hub.event_id(&hub.after(0)[0]);
+
+        let resumed = hub.subscribe(Some(&first));
+        assert_eq!(resumed.after, 1);
+        assert_eq!(seqs(&resumed.backlog), [2, 3]);
+        assert_eq!(
+            resumed.backlog[0].data,
+            r#"{"id":"m2","payload":2,"type":"b"}"#
+        );
+        assert_eq!(seqs(&hub.subscribe(Some("1-2")).backlog), [1, 2, 3]);
+        assert_eq!(seqs(&hub.subscribe(Some("garbage")).backlog), [1, 2, 3]);
+        let fresh = hub.subscribe(None);
+        assert_eq!(fresh.after, 3);
+        assert!(fresh.backlog.is_empty());
+    }
+}
@@ -0,0 +1,320 @@
+//! SSE Sink - Server-Sent Events Streams of the Bus
+//!
+//! A Sink that serves the events it receives as Server-Sent Events, as a
+//! firehose filtered by type globs or as one stream per type (see
+//! [`server`]). Browsers follow them with a plain `EventSource`.
+//!
+//! The newest events are kept in a ring buffer (see [`hub`]), so a client
+//! that drops off for a moment reconnects with `Last-Event-ID` and is sent
+//! what it missed instead of losing it.
+//!
+//! # Examples
+//!
+//! ```bash
+//! sse-sink -s 'deploy.*' -s 'alert.*' --port 8792 --replay 5000 \
+//!   --allowed-origin https://dash.example.com
+//! ```
+//!
+//! ```js
+//! const events = new EventSource("https://bus.example.com/events?types=deploy.*");
+//! events.onmessage = (e) => console.log(JSON.parse(e.data));
+//! ```
+
+pub mod filter;
+pub mod hub;
+pub mod server;
+
+use clap::Parser;
+use emergent_client::EmergentMessage;
+use hub::Hub;
+use primitive_common::capabilities::VERSION;
+use primitive_common::doctor::Report;
+use primitive_common::errors::HandlerError;
+use primitive_common::{SinkArgs, SinkConfig, SinkContext, SinkHandler, run_sink};
+use serde_json::json;
+use server::{Server, Settings};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// SSE Sink — Server-Sent Events streams of the bus.
+#[derive(Parser, Debug)]
+#[command(name = "sse_sink", version = VERSION)]
+#[command(about = "Serve events as Server-Sent Events streams with Last-Event-ID replay")]
+struct Args {
+    /// Message types to subscribe to.
+    #[arg(short, long = "subscribe", required = true)]
+    subscribe: Vec<String>,
+
+    /// Port the streams are served on.
+    #[arg(short, long, env = "SSE_SINK_PORT", default_value = "8792")]
+    port: u16,
+
+    /// Host the streams are served on.
+    #[arg(long, env = "SSE_SINK_HOST", default_value = "0.0.0.0")]
+    host: String,
+
+    /// Path of the firehose; each type is served below it.
+    #[arg(long, env = "SSE_SINK_PATH", default_value = "/events")]
+    path: String,
+
+    /// Events kept for clients that reconnect.
+    #[arg(long, env = "SSE_SINK_REPLAY", default_value = "1000")]
+    replay: usize,
+
+    /// Events a stream may fall behind before it catches up from the
+    /// replay buffer.
+    #[arg(long, env = "SSE_SINK_BUFFER", default_value = "1024")]
+    buffer: usize,
+
+    /// Token clients must pass as `?token=` or a bearer token.
+    #[arg(long, env = "SSE_SINK_TOKEN", hide_env_values = true)]
+    token: Option<String>,
+
+    /// Origin allowed to read the streams from a browser (repeatable;
+    /// `*` for any).
+    #[arg(
+        long = "allowed-origin",
+        env = "SSE_SINK_ALLOWED_ORIGINS",
+        value_delimiter = ','
+    )]
+    allowed_origins: Vec<String>,
+
+    /// Milliseconds between keep-alive comments on idle streams.
+    #[arg(
+        long,
+        env = "SSE_SINK_KEEP_ALIVE",
+        default_value = "15000",
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    keep_alive: u64,
+
+    #[command(flatten)]
+    sink: SinkArgs,
+}
+
+/// Hands each event to the open streams.
+struct SseSink {
+    hub: Arc<Hub>,
+}
+
+impl SinkHandler for SseSink {
+    async fn handle(
+        &self,
+        msg: &EmergentMessage,
+        ctx: &SinkContext<'_>,
+    ) -> Result<(), HandlerError> {
+        let message_type = msg.message_type.as_str();
+        let id = ctx.message_id().to_string();
+
+        if ctx.is_dry_run() {
+            let detail = json!({ "type": message_type, "id": id });
+            ctx.would_have("stream", detail).await;
+            return Ok(());
+        }
+
+        self.hub.publish(message_type, &id, ctx.payload());
+        Ok(())
+    }
+
+    fn self_test(&self, report: &mut Report) {
+        report.check(
+            "replay buffer",
+            Ok(format!("{} events kept", self.hub.kept())),
+        );
+    }
+}
+
+/// Run the primitive with `args` as its command line (the first item is
+/// the program name, as with `std::env::args_os`).
+pub async fn run<I, T>(args: I) -> Result<(), Box<dyn std::error::Error>>
+where
+    I: IntoIterator<Item = T>,
+    T: Into<std::ffi::OsString> + Clone,
+{
+    let args = Args::parse_from(args);
+
+    let hub = Arc::new(Hub::new(args.replay, args.buffer));
+    let server = Arc::new(Server {
+        hub: Arc::clone(&hub),
+        settings: Settings {
+            token: args.token.clone(),
+            allowed_origins: args.allowed_origins.clone(),
+            keep_alive: Duration::from_millis(args.keep_alive),
+        },
+    });
+    let addr = format!("{}:{}", args.host, args.port);
+    let listener = match tokio::net::TcpListener::bind(&addr).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            eprintln!("Error: cannot listen on {addr}: {e}");
+            std::process::exit(1);
+        }
+    };
+    let app = server.router(&args.path);
+    tokio::spawn(async move { axum::serve(listener, app).await });
+    eprintln!("Event streams on http://{addr}{}", args.path);
+
+    let config = SinkConfig {
+        name: "sse_sink",
+        subscribe: &args.subscribe,
+        would_have_as: "sse.would_have",
+        dead_letter_as: "sse.dead_letter",
+        settings: &args,
+    };
+
+    run_sink(config, &args.sink, SseSink { hub }).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use emergent_testkit::{SinkFixture, fixtures, spawn_sink};
+    use serde_json::Value;
+
+    async fn serve(server: Server) -> String {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap_or_else(|e| panic!("bind: {e}"));
+        let addr = listener
+            .local_addr()
+            .unwrap_or_else(|e| panic!("local_addr: {e}"));
+        let app = Arc::new(server).router("/events");
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        format!("http://{addr}/events")
+    }
+
+    /// Read events off a stream as `(id, data)` until `count` have come.
+    async fn read(response: &mut reqwest::Response, count: usize) -> Vec<(String, Value)> {
+        let mut buffer = String::new();
+        let mut events = Vec::new();
+        while events.len() < count {
+            let chunk = tokio::time::timeout(Duration::from_secs(5), response.chunk()).await;
+            let chunk = match chunk {
+                Ok(Ok(Some(chunk))) => chunk,
+                other => panic!("stream ended early: {other:?}"),
+            };
+            buffer.push_str(&String::from_utf8_lossy(&chunk));
+            while let Some(end) = buffer.find("\n\n") {
+                let block: String = buffer.drain(..end + 2).collect();
+                let field = |name: &str| {
+                    block
+                        .lines()
+                        .find_map(|l| l.strip_prefix(name))
+                        .map(str::to_string)
+                };
+                if let (Some(id), Some(data)) = (field("id: "), field("data: ")) {
+                    let data = serde_json::from_str(&data).unwrap_or_else(|e| panic!("{e}"));
+                    events.push((id, data));
+                }
+            }
+        }
+        events
+    }
+
+    #[tokio::test]
+    async fn streams_replay_missed_events_and_follow_live_ones() {
+        let hub = Arc::new(Hub::new(100, 16));
+        let url = serve(Server {
+            hub: Arc::clone(&hub),
+            settings: Settings {
+                token: Some("t0k".to_string()),
+                allowed_origins: vec!["https://dash.example.com".to_string()],
+                keep_alive: Duration::from_secs(15),
+            },
+        })
+        .await;
+        let (mut engine, run) = spawn_sink(
+            SinkFixture::new("sse_sink", "sse"),
+            SinkArgs::default(),
+            SseSink {
+                hub: Arc::clone(&hub),
+            },
+        );
+
+        let client = reqwest::Client::new();
+        let denied = client.get(&url).send().await.map(|r| r.status().as_u16());
+        assert_eq!(denied.ok(), Some(401));
+
+        for (message_type, n) in [
+            ("deploy.started", 1),
+            ("alert.fired", 2),
+            ("deploy.finished", 3),
+        ] {
+            engine
+                .inject_message(fixtures::message(message_type, json!({ "n": n })))
+                .await;
+        }
+        for _ in 0..100 {
+            if hub.kept() == 3 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        // An id from an earlier run replays everything kept
+        let mut firehose = client
+            .get(format!("{url}?types=deploy.*&last_event_id=0-0"))
+            .bearer_auth("t0k")
+            .header("origin", "https://dash.example.com")
+            .send()
+            .await
+            .unwrap_or_else(|e| panic!("{e}"));
+        assert_eq!(
+            firehose.headers()["access-control-allow-origin"],
+            "https://dash.example.com"
+        );
+        let replayed = read(&mut firehose, 2).await;
+        assert_eq!(replayed[0].1["type"], "deploy.started");
+        assert_eq!(replayed[1].1["type"], "deploy.finished");
+        let mut resumed = client
+            .get(format!("{url}/deploy.*?token=t0k"))
+            .header("last-event-id", &replayed[0].0)
+            .send()
+            .await
+            .unwrap_or_else(|e| panic!("{e}"));
+        assert_eq!(read(&mut resumed, 1).await[0].1["payload"], json!({"n": 3}));
+        let mut alerts = client
+            .get(format!("{url}/alert.fired?token=t0k"))
+            .send()
+            .await
+            .unwrap_or_else(|e| panic!("{e}"));
+
+        engine
+            .inject_message(fixtures::message("deploy.failed", json!({"n": 4})))
+            .await;
+        engine
+            .inject_message(fixtures::message("alert.fired", json!({"n": 5})))
+            .await;
+        assert_eq!(read(&mut firehose, 1).await[0].1["type"], "deploy.failed");
+        assert_eq!(read(&mut resumed, 1).await[0].1["payload"], json!({"n": 4}));
+        let alert = read(&mut alerts, 1).await;
+        assert_eq!(alert[0].1["payload"], json!({"n": 5}));
+
+        engine.close();
+        assert_eq!(run.await.ok(), Some(Ok(())));
+    }
+
+    #[tokio::test]
+    async fn dry_run_reports_instead_of_streaming() {
+        let hub = Arc::new(Hub::new(100, 16));
+        let (mut engine, run) = spawn_sink(
+            SinkFixture::new("sse_sink", "sse"),
+            SinkArgs {
+                dry_run: true,
+                ..SinkArgs::default()
+            },
+            SseSink {
+                hub: Arc::clone(&hub),
+            },
+        );
+        engine
+            .inject_message(fixtures::message("deploy.started", json!({})))
+            .await;
+        let report = engine.expect_published("sse.would_have").await;
+        assert_eq!(report.payload()["detail"]["type"], "deploy.started");
+        assert_eq!(hub.kept(), 0);
+
+        engine.close();
+        assert_eq!(run.await.ok(), Some(Ok(())));
+    }
+}
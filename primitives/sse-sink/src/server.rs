@@ -0,0 +1,216 @@
+//! The Server-Sent Events endpoints.
+//!
+//! - `GET <path>?types=deploy.*,alert.*`: the firehose, every event or
+//!   those whose type matches one of the globs
+//! - `GET <path>/{type}`: the events of one type (or glob)
+//!
+//! Each event is sent with its id (see [`hub`](crate::hub)) and no event
+//! name, so `EventSource.onmessage` sees them all; its data is
+//! `{"type", "id", "payload"}`. A client resumes with the `Last-Event-ID`
+//! header, which browsers send when they reconnect, or `?last_event_id=`
+//! for a fresh page. A stream that falls behind the live channel catches
+//! up from the ring buffer.
+//!
+//! With `--token`, clients pass it as `?token=` (`EventSource` cannot set
+//! headers) or as `Authorization: Bearer <token>`.
+
+use crate::filter::Filter;
+use crate::hub::{Entry, Hub, Subscription};
+use axum::Router;
+use axum::extract::{Path, Query, State};
+use axum::http::{HeaderMap, HeaderValue, StatusCode};
+use axum::response::sse::{Event, KeepAlive, Sse};
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use futures::Stream;
+use serde::Deserialize;
+use std::collections::VecDeque;
+use std::convert::Infallible;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast::{self, error::RecvError};
+
+/// How the endpoints admit clients.
+#[derive(Debug, Clone)]
+pub struct Settings {
+    pub token: Option<String>,
+    /// Origins sent `Access-Control-Allow-Origin`; `*` is any.
+    pub allowed_origins: Vec<String>,
+    /// Comment sent on idle streams to keep proxies from closing them.
+    pub keep_alive: Duration,
+}
+
+/// What the endpoints serve from.
+pub struct Server {
+    pub hub: Arc<Hub>,
+    pub settings: Settings,
+}
+
+/// Query parameters of both endpoints.
+#[derive(Debug, Default, Deserialize)]
+struct Params {
+    token: Option<String>,
+    types: Option<String>,
+    last_event_id: Option<String>,
+}
+
+impl Server {
+    /// Serve the firehose at `path` and each type below it.
+    pub fn router(self: Arc<Self>, path: &str) -> Router {
+        let path = path.trim_end_matches('/');
+        let topic = format!("{path}/{{topic}}");
+        let firehose = if path.is_empty() { "/" } else { path };
+        Router::new()
+            .route(firehose, get(firehose_stream))
+            .route(&topic, get(topic_stream))
+            .with_state(self)
+    }
+
+    fn authorized(&self, headers: &HeaderMap, params: &Params) -> bool {
+        let Some(token) = &self.settings.token else {
+            return true;
+        };
+        let bearer = headers
+            .get("authorization")
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.strip_prefix("Bearer "));
+        params.token.as_deref() == Some(token.as_str()) || bearer == Some(token.as_str())
+    }
+
+    /// The `Access-Control-Allow-Origin` to answer a request with.
+    fn allow_origin(&self, headers: &HeaderMap) -> Option<HeaderValue> {
+        let origin = headers.get("origin")?;
+        self.settings
+            .allowed_origins
+            .iter()
+            .any(|a| a == "*" || origin.to_str().is_ok_and(|o| o == a))
+            .then(|| origin.clone())
+    }
+
+    fn stream(&self, headers: &HeaderMap, params: Params, filter: Filter) -> Response {
+        if !self.authorized(headers, &params) {
+            return (StatusCode::UNAUTHORIZED, "missing or wrong token").into_response();
+        }
+        let last_event_id = headers
+            .get("last-event-id")
+            .and_then(|v| v.to_str().ok())
+            .or(params.last_event_id.as_deref());
+        let Subscription {
+            after,
+            backlog,
+            receiver,
+        } = self.hub.subscribe(last_event_id);
+        let cursor = Cursor {
+            hub: Arc::clone(&self.hub),
+            filter,
+            pending: backlog,
+            receiver,
+            last: after,
+        };
+        let sse = Sse::new(events(cursor))
+            .keep_alive(KeepAlive::new().interval(self.settings.keep_alive));
+        let mut response = sse.into_response();
+        if let Some(origin) = self.allow_origin(headers) {
+            response
+                .headers_mut()
+                .insert("access-control-allow-origin", origin);
+        }
+        response
+    }
+}
+
+async fn firehose_stream(
+    State(server): State<Arc<Server>>,
+    headers: HeaderMap,
+    Query(params): Query<Params>,
+) -> Response {
+    let filter = params
+        .types
+        .as_deref()
+        .map(Filter::parse)
+        .unwrap_or_default();
+    server.stream(&headers, params, filter)
+}
+
+async fn topic_stream(
+    State(server): State<Arc<Server>>,
+    headers: HeaderMap,
+    Path(topic): Path<String>,
+    Query(params): Query<Params>,
+) -> Response {
+    server.stream(&headers, params, Filter::parse(&topic))
+}
+
+/// Where one stream is.
+struct Cursor {
+    hub: Arc<Hub>,
+    filter: Filter,
+    /// Events to send before waiting on the channel again.
+    pending: VecDeque<Arc<Entry>>,
+    receiver: broadcast::Receiver<Arc<Entry>>,
+    /// The newest event already sent or skipped.
+    last: u64,
+}
+
+impl Cursor {
+    async fn next(&mut self) -> Option<Event> {
+        loop {
+            while let Some(entry) = self.pending.pop_front() {
+                if entry.seq <= self.last {
+                    continue;
+                }
+                self.last = entry.seq;
+                if self.filter.matches(&entry.message_type) {
+                    return Some(
+                        Event::default()
+                            .id(self.hub.event_id(&entry))
+                            .data(&entry.data),
+                    );
+                }
+            }
+            match self.receiver.recv().await {
+                Ok(entry) => self.pending.push_back(entry),
+                Err(RecvError::Lagged(_)) => self.pending = self.hub.after(self.last),
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    }
+}
+
+fn events(cursor: Cursor) -> impl Stream<Item = Result<Event, Infallible>> {
+    futures::stream::unfold(cursor, |mut cursor| async move {
+        let event = cursor.next().await?;
+        Some((Ok(event), cursor))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn streams_catch_up_from_the_ring_when_they_lag() {
+        let hub = Arc::new(Hub::new(10, 1));
+        let Subscription {
+            after, receiver, ..
+        } = hub.subscribe(None);
+        let mut cursor = Cursor {
+            hub: Arc::clone(&hub),
+            filter: Filter::parse("deploy.*"),
+            pending: VecDeque::new(),
+            receiver,
+            last: after,
+        };
+        hub.publish("deploy.started", "m1", &json!({}));
+        hub.publish("alert.fired", "m2", &json!({}));
+        hub.publish("deploy.finished", "m3", &json!({}));
+
+        // The channel holds one event; the rest come from the ring
+        let first = cursor.next().await.map(|e| format!("{e:?}"));
+        assert!(first.is_some_and(|e| e.contains("deploy.started")));
+        let second = cursor.next().await.map(|e| format!("{e:?}"));
+        assert!(second.is_some_and(|e| e.contains("deploy.finished")));
+        assert_eq!(cursor.last, 3);
+    }
+}
@@ -0,0 +1,191 @@
+//! Health events read as check results, and checks mapped to components.
+//!
+//! ```json
+//! {"check": "api-eu", "status": "down", "message": "HTTP 503"}
+//! ```
+//!
+//! The check is named by the check field, or else `component`, `service`
+//! or `name`. The status field accepts the words health checks commonly
+//! use (`up`, `ok`, `passing`, `warn`, `fail`, `critical`, ...); a payload
+//! without one takes it from the last segment of the event type, as in
+//! `health.down`.
+
+use primitive_common::glob;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// Payload fields read, by name.
+#[derive(Debug, Clone)]
+pub struct Fields {
+    pub check: String,
+    pub status: String,
+    pub message: String,
+}
+
+/// How a component is doing, from best to worst.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Operational,
+    Degraded,
+    Down,
+}
+
+impl Status {
+    pub fn parse(status: &str) -> Option<Self> {
+        match status.trim().to_ascii_lowercase().as_str() {
+            "up" | "ok" | "pass" | "passing" | "passed" | "healthy" | "operational" | "success"
+            | "resolved" | "recovered" => Some(Self::Operational),
+            "degraded" | "warn" | "warning" | "partial" | "slow" => Some(Self::Degraded),
+            "down" | "fail" | "failing" | "failed" | "critical" | "crit" | "error"
+            | "unhealthy" | "outage" => Some(Self::Down),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Operational => "operational",
+            Self::Degraded => "degraded",
+            Self::Down => "down",
+        }
+    }
+}
+
+/// One check result.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Reading {
+    pub check: String,
+    pub status: Status,
+    pub message: Option<String>,
+}
+
+impl Reading {
+    pub fn build(payload: &Value, event_type: &str, fields: &Fields) -> Result<Self, String> {
+        let text = |field: &str| match &payload[field] {
+            Value::String(s) if !s.is_empty() => Some(s.clone()),
+            Value::Number(n) => Some(n.to_string()),
+            _ => None,
+        };
+        let check = [fields.check.as_str(), "component", "service", "name"]
+            .into_iter()
+            .find_map(text)
+            .ok_or_else(|| format!("payload has no '{}'", fields.check))?;
+        let status = match (text(&fields.status), &payload[&fields.status]) {
+            (Some(status), _) => Status::parse(&status)
+                .ok_or_else(|| format!("{check}: unknown status '{status}'"))?,
+            (None, Value::Bool(healthy)) => {
+                if *healthy {
+                    Status::Operational
+                } else {
+                    Status::Down
+                }
+            }
+            _ => event_type
+                .rsplit('.')
+                .next()
+                .and_then(Status::parse)
+                .ok_or_else(|| {
+                    format!(
+                        "{check}: payload has no '{}' and {event_type} names no status",
+                        fields.status
+                    )
+                })?,
+        };
+        Ok(Self {
+            check,
+            status,
+            message: text(&fields.message),
+        })
+    }
+}
+
+/// Checks grouped into the components shown, from `CHECK=COMPONENT`
+/// entries where the check may be a glob. The first matching entry wins;
+/// a check matching none is a component of its own.
+#[derive(Debug, Clone, Default)]
+pub struct Mapping(Vec<(String, String)>);
+
+impl Mapping {
+    pub fn parse(entries: &[String]) -> Result<Self, String> {
+        entries
+            .iter()
+            .map(|entry| match entry.split_once('=') {
+                Some((check, component)) if !check.is_empty() && !component.is_empty() => {
+                    Ok((check.trim().to_string(), component.trim().to_string()))
+                }
+                _ => Err(format!("{entry}: expected CHECK=COMPONENT")),
+            })
+            .collect::<Result<_, _>>()
+            .map(Self)
+    }
+
+    pub fn component(&self, check: &str) -> String {
+        self.0
+            .iter()
+            .find(|(pattern, _)| glob::matches(pattern, check))
+            .map_or(check, |(_, component)| component)
+            .to_string()
+    }
+
+    /// Components named by the mapping, in order and without repeats.
+    pub fn components(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = Vec::new();
+        for (_, component) in &self.0 {
+            if !names.contains(&component.as_str()) {
+                names.push(component);
+            }
+        }
+        names
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn readings_and_mapping() {
+        let fields = Fields {
+            check: "check".to_string(),
+            status: "status".to_string(),
+            message: "message".to_string(),
+        };
+        let read = |payload: Value, event_type: &str| Reading::build(&payload, event_type, &fields);
+        assert_eq!(
+            read(
+                json!({"check": "api-eu", "status": "CRITICAL", "message": "HTTP 503"}),
+                "health.check"
+            ),
+            Ok(Reading {
+                check: "api-eu".to_string(),
+                status: Status::Down,
+                message: Some("HTTP 503".to_string()),
+            })
+        );
+        assert_eq!(
+            read(json!({"service": "db"}), "health.degraded").map(|r| (r.check, r.status)),
+            Ok(("db".to_string(), Status::Degraded))
+        );
+        assert_eq!(
+            read(json!({"check": "db", "status": true}), "health.check").map(|r| r.status),
+            Ok(Status::Operational)
+        );
+        assert!(read(json!({"check": "db"}), "health.check").is_err());
+        assert!(read(json!({"check": "db", "status": "meh"}), "health.up").is_err());
+        assert!(read(json!({"status": "up"}), "health.up").is_err());
+
+        let mapping = Mapping::parse(&[
+            "api-*=API".to_string(),
+            "db=Database".to_string(),
+            "web=API".to_string(),
+        ])
+        .unwrap_or_else(|e| panic!("{e}"));
+        assert_eq!(mapping.component("api-eu"), "API");
+        assert_eq!(mapping.component("db"), "Database");
+        assert_eq!(mapping.component("queue"), "queue");
+        assert_eq!(mapping.components(), ["API", "Database"]);
+        assert!(Mapping::parse(&["api".to_string()]).is_err());
+    }
+}
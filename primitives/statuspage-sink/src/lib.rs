@@ -0,0 +1,523 @@
+//! Status Page Sink - Uptime and Incidents from Health Events
+//!
+//! A Sink that keeps a static status page from `health.*` events: each
+//! event is the result of one check (see [`health`]), checks are grouped
+//! into components, and the page shows every component's status, its
+//! uptime over rolling windows and the incident history (see [`page`]).
+//!
+//! ```json
+//! {"check": "api-eu", "status": "down", "message": "HTTP 503"}
+//! ```
+//!
+//! After every event the page is written as `index.html` and
+//! `status.json`, to a directory and/or an S3-compatible bucket (see
+//! [`s3`]). Changes of component status can also be mirrored to
+//! Atlassian Statuspage or Instatus (see [`provider`]). History and
+//! incidents survive restarts in `--state-file`.
+//!
+//! # Examples
+//!
+//! ```bash
+//! statuspage-sink -s health.check --output-dir /var/www/status \
+//!   --component 'api-*=API' --component db-primary=Database \
+//!   --state-file /var/lib/statuspage/state.json
+//! statuspage-sink -s health.check --s3-bucket status-example-com \
+//!   --provider statuspage --provider-page-id kctbh9vrtdwd \
+//!   --provider-component API=8kbf7d35c070
+//! ```
+
+pub mod health;
+pub mod page;
+pub mod provider;
+pub mod render;
+pub mod s3;
+
+use clap::Parser;
+use emergent_client::EmergentMessage;
+use health::{Fields, Mapping, Reading};
+use page::{Page, Window};
+use primitive_common::aws::{self, CredentialChain, Credentials};
+use primitive_common::capabilities::VERSION;
+use primitive_common::doctor::{Report, check_writable_dir};
+use primitive_common::errors::{ErrorCategory, HandlerError};
+use primitive_common::time;
+use primitive_common::{SinkArgs, SinkConfig, SinkContext, SinkHandler, run_sink};
+use provider::{Kind, Provider};
+use reqwest::Client;
+use s3::Bucket;
+use serde_json::{Value, json};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::sync::Mutex;
+
+/// Status Page Sink — uptime and incidents from health events.
+#[derive(Parser, Debug)]
+#[command(name = "statuspage_sink", version = VERSION)]
+#[command(about = "Keep a static status page, with uptime and incidents, from health events")]
+struct Args {
+    /// Message types to subscribe to.
+    #[arg(short, long = "subscribe", required = true)]
+    subscribe: Vec<String>,
+
+    /// Page title.
+    #[arg(long, env = "STATUSPAGE_SINK_TITLE", default_value = "Status")]
+    title: String,
+
+    /// Check shown as part of a component, as `CHECK=COMPONENT`; the check
+    /// may be a `*` glob. Checks matching none are components of their
+    /// own. Components are listed in the order given here.
+    #[arg(
+        long = "component",
+        env = "STATUSPAGE_SINK_COMPONENTS",
+        value_delimiter = ','
+    )]
+    components: Vec<String>,
+
+    /// Payload field naming the check.
+    #[arg(long, env = "STATUSPAGE_SINK_CHECK_FIELD", default_value = "check")]
+    check_field: String,
+
+    /// Payload field holding the status.
+    #[arg(long, env = "STATUSPAGE_SINK_STATUS_FIELD", default_value = "status")]
+    status_field: String,
+
+    /// Payload field holding a message shown with incidents.
+    #[arg(long, env = "STATUSPAGE_SINK_MESSAGE_FIELD", default_value = "message")]
+    message_field: String,
+
+    /// Rolling windows uptime is shown over (`s`, `m`, `h` or `d`).
+    #[arg(
+        long = "window",
+        env = "STATUSPAGE_SINK_WINDOWS",
+        value_delimiter = ',',
+        default_value = "24h,7d,30d,90d",
+        value_parser = Window::parse
+    )]
+    windows: Vec<Window>,
+
+    /// Days resolved incidents stay on the page.
+    #[arg(long, env = "STATUSPAGE_SINK_INCIDENT_DAYS", default_value = "90")]
+    incident_days: u32,
+
+    /// Directory to write `index.html` and `status.json` to.
+    #[arg(long, env = "STATUSPAGE_SINK_OUTPUT_DIR")]
+    output_dir: Option<PathBuf>,
+
+    /// File keeping status history and incidents across restarts.
+    #[arg(long, env = "STATUSPAGE_SINK_STATE_FILE")]
+    state_file: Option<PathBuf>,
+
+    /// S3 bucket to upload `index.html` and `status.json` to.
+    #[arg(long, env = "STATUSPAGE_SINK_S3_BUCKET")]
+    s3_bucket: Option<String>,
+
+    /// Key prefix within the bucket.
+    #[arg(long, env = "STATUSPAGE_SINK_S3_PREFIX", default_value = "")]
+    s3_prefix: String,
+
+    /// Bucket region (default: `AWS_DEFAULT_REGION`, or us-east-1).
+    #[arg(long, env = "AWS_REGION")]
+    s3_region: Option<String>,
+
+    /// Endpoint of an S3-compatible store; defaults to AWS's for the region.
+    #[arg(long, env = "STATUSPAGE_SINK_S3_ENDPOINT")]
+    s3_endpoint: Option<String>,
+
+    /// S3 access key ID (default: the AWS credential chain).
+    #[arg(long, env = "AWS_ACCESS_KEY_ID")]
+    s3_access_key_id: Option<String>,
+
+    /// S3 secret access key.
+    #[arg(long, env = "AWS_SECRET_ACCESS_KEY", hide_env_values = true)]
+    s3_secret_access_key: Option<String>,
+
+    /// Session token of temporary S3 credentials.
+    #[arg(long, env = "AWS_SESSION_TOKEN", hide_env_values = true)]
+    s3_session_token: Option<String>,
+
+    /// Hosted status page to mirror component status to.
+    #[arg(long, env = "STATUSPAGE_SINK_PROVIDER", value_enum)]
+    provider: Option<Kind>,
+
+    /// API base URL of the provider; defaults to the service's own.
+    #[arg(long, env = "STATUSPAGE_SINK_PROVIDER_URL")]
+    provider_url: Option<String>,
+
+    /// Page ID on the provider.
+    #[arg(long, env = "STATUSPAGE_SINK_PROVIDER_PAGE_ID")]
+    provider_page_id: Option<String>,
+
+    /// Provider API key.
+    #[arg(long, env = "STATUSPAGE_SINK_PROVIDER_API_KEY", hide_env_values = true)]
+    provider_api_key: Option<String>,
+
+    /// Component on the provider's page, as `NAME=ID`.
+    #[arg(
+        long = "provider-component",
+        env = "STATUSPAGE_SINK_PROVIDER_COMPONENTS",
+        value_delimiter = ','
+    )]
+    provider_components: Vec<String>,
+
+    /// Per-request timeout in milliseconds.
+    #[arg(short, long, env = "STATUSPAGE_SINK_TIMEOUT", default_value = "10000")]
+    timeout: u64,
+
+    #[command(flatten)]
+    sink: SinkArgs,
+}
+
+/// Keeps the page and publishes it after every event.
+struct StatuspageSink {
+    title: String,
+    fields: Fields,
+    mapping: Mapping,
+    windows: Vec<Window>,
+    incident_secs: i64,
+    output_dir: Option<PathBuf>,
+    state_file: Option<PathBuf>,
+    bucket: Option<Bucket>,
+    provider: Option<Provider>,
+    page: Mutex<Page>,
+}
+
+/// Read the page state; a missing file is an empty page.
+fn load_state(path: &Path) -> Result<Page, String> {
+    match fs::read_to_string(path) {
+        Ok(text) => serde_json::from_str(&text).map_err(|e| format!("{}: {e}", path.display())),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Page::default()),
+        Err(e) => Err(format!("{}: {e}", path.display())),
+    }
+}
+
+/// Write `contents` to `path` through a temporary file, so readers never
+/// see half a file.
+fn write_atomic(path: &Path, contents: &[u8]) -> Result<(), String> {
+    let tmp = path.with_extension("tmp");
+    fs::write(&tmp, contents)
+        .and_then(|()| fs::rename(&tmp, path))
+        .map_err(|e| format!("{}: {e}", path.display()))
+}
+
+impl StatuspageSink {
+    /// Write the page out to the directory and the bucket.
+    async fn publish(&self, summary: &Value) -> Result<(), HandlerError> {
+        let json = serde_json::to_vec_pretty(summary)
+            .map_err(|e| HandlerError::new(ErrorCategory::Internal, e.to_string()))?;
+        let html = render::html(summary, &self.windows).into_bytes();
+        if let Some(dir) = &self.output_dir {
+            write_atomic(&dir.join("status.json"), &json)
+                .and_then(|()| write_atomic(&dir.join("index.html"), &html))
+                .map_err(|e| HandlerError::new(ErrorCategory::Internal, e))?;
+        }
+        if let Some(bucket) = &self.bucket {
+            bucket
+                .put("status.json", json, "application/json")
+                .await
+                .map_err(|e| HandlerError::new(ErrorCategory::Request, e))?;
+            bucket
+                .put("index.html", html, "text/html; charset=utf-8")
+                .await
+                .map_err(|e| HandlerError::new(ErrorCategory::Request, e))?;
+        }
+        Ok(())
+    }
+
+    /// Seconds of history the longest window needs.
+    fn history_secs(&self) -> i64 {
+        self.windows.iter().map(|w| w.secs).max().unwrap_or(0)
+    }
+}
+
+impl SinkHandler for StatuspageSink {
+    async fn handle(
+        &self,
+        msg: &EmergentMessage,
+        ctx: &SinkContext<'_>,
+    ) -> Result<(), HandlerError> {
+        let reading = Reading::build(ctx.payload(), msg.message_type.as_str(), &self.fields)
+            .map_err(|e| HandlerError::new(ErrorCategory::Parse, e))?;
+        let component = self.mapping.component(&reading.check);
+
+        // Held until the page is published, so events apply one at a time
+        let mut page = self.page.lock().await;
+        let mut next = page.clone();
+        let now = time::now();
+        let change = next.apply(&component, &reading, now);
+        next.prune(self.history_secs(), self.incident_secs, now);
+        let summary = next.summary(&self.title, &self.windows, &self.mapping.components(), now);
+
+        if ctx.is_dry_run() {
+            let provider = change.as_ref().and_then(|change| {
+                let (method, url, body) = self.provider.as_ref()?.request(&component, change.to)?;
+                Some(json!({"method": method, "url": url, "body": body}))
+            });
+            let detail = json!({
+                "component": component,
+                "check": reading.check,
+                "status": summary["components"]
+                    .as_array()
+                    .and_then(|all| all.iter().find(|c| c["name"] == component.as_str()))
+                    .map(|c| c["status"].clone()),
+                "changed": change.is_some(),
+                "provider": provider,
+            });
+            ctx.would_have("update", detail).await;
+            return Ok(());
+        }
+
+        self.publish(&summary).await?;
+        if let (Some(provider), Some(change)) = (&self.provider, &change) {
+            provider
+                .update(&component, change.to)
+                .await
+                .map_err(|e| HandlerError::new(ErrorCategory::Rejected, e))?;
+        }
+        if let Some(path) = &self.state_file {
+            let state = serde_json::to_vec_pretty(&next)
+                .map_err(|e| HandlerError::new(ErrorCategory::Internal, e.to_string()))?;
+            write_atomic(path, &state)
+                .map_err(|e| HandlerError::new(ErrorCategory::Internal, e))?;
+        }
+        *page = next;
+        Ok(())
+    }
+
+    fn self_test(&self, report: &mut Report) {
+        if let Some(dir) = &self.output_dir {
+            report.check("output-dir", check_writable_dir(dir));
+        }
+        if let Some(path) = &self.state_file {
+            let dir = path
+                .parent()
+                .filter(|p| !p.as_os_str().is_empty())
+                .unwrap_or(Path::new("."));
+            report.check("state-file", check_writable_dir(dir));
+            report.check(
+                "state",
+                load_state(path).map(|page| format!("{} components", page.components.len())),
+            );
+        }
+        if let Some(bucket) = &self.bucket {
+            report.check("s3", Ok(bucket.describe()));
+        }
+        if let Some(provider) = &self.provider {
+            report.check(provider.kind.as_str(), Ok("configured".to_string()));
+        }
+    }
+}
+
+/// Run the primitive with `args` as its command line (the first item is
+/// the program name, as with `std::env::args_os`).
+pub async fn run<I, T>(args: I) -> Result<(), Box<dyn std::error::Error>>
+where
+    I: IntoIterator<Item = T>,
+    T: Into<std::ffi::OsString> + Clone,
+{
+    let args = Args::parse_from(args);
+
+    let exit = |e: String| -> ! {
+        eprintln!("Error: {e}");
+        std::process::exit(1);
+    };
+    if args.output_dir.is_none() && args.s3_bucket.is_none() && args.provider.is_none() {
+        exit("nothing to publish to: give --output-dir, --s3-bucket or --provider".to_string());
+    }
+    let mapping = Mapping::parse(&args.components).unwrap_or_else(|e| exit(e));
+    let page = match &args.state_file {
+        Some(path) => load_state(path).unwrap_or_else(|e| exit(e)),
+        None => Page::default(),
+    };
+    if let Some(dir) = &args.output_dir {
+        fs::create_dir_all(dir).unwrap_or_else(|e| exit(format!("{}: {e}", dir.display())));
+    }
+    let client = Client::builder()
+        .timeout(Duration::from_millis(args.timeout))
+        .build()?;
+    let bucket = args.s3_bucket.as_deref().map(|name| {
+        let keys = match (&args.s3_access_key_id, &args.s3_secret_access_key) {
+            (Some(access_key_id), Some(secret_access_key)) => Some(Credentials {
+                access_key_id: access_key_id.clone(),
+                secret_access_key: secret_access_key.clone(),
+                session_token: args.s3_session_token.clone(),
+            }),
+            _ => None,
+        };
+        Bucket::new(
+            client.clone(),
+            args.s3_endpoint.as_deref(),
+            name,
+            &args.s3_region.clone().unwrap_or_else(aws::default_region),
+            &args.s3_prefix,
+            CredentialChain::new(client.clone(), keys, None),
+        )
+    });
+    let provider = args.provider.map(|kind| {
+        let (Some(page_id), Some(api_key)) = (&args.provider_page_id, &args.provider_api_key)
+        else {
+            exit("--provider needs --provider-page-id and --provider-api-key".to_string());
+        };
+        Provider::new(
+            kind,
+            client.clone(),
+            args.provider_url.as_deref(),
+            page_id,
+            api_key,
+            &args.provider_components,
+        )
+        .unwrap_or_else(|e| exit(e))
+    });
+
+    let config = SinkConfig {
+        name: "statuspage_sink",
+        subscribe: &args.subscribe,
+        would_have_as: "statuspage.would_have",
+        dead_letter_as: "statuspage.dead_letter",
+        settings: &args,
+    };
+    let handler = StatuspageSink {
+        title: args.title.clone(),
+        fields: Fields {
+            check: args.check_field.clone(),
+            status: args.status_field.clone(),
+            message: args.message_field.clone(),
+        },
+        mapping,
+        windows: args.windows.clone(),
+        incident_secs: i64::from(args.incident_days) * 86_400,
+        output_dir: args.output_dir.clone(),
+        state_file: args.state_file.clone(),
+        bucket,
+        provider,
+        page: Mutex::new(page),
+    };
+
+    run_sink(config, &args.sink, handler).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::Router;
+    use axum::extract::Path as UrlPath;
+    use axum::http::{HeaderMap, StatusCode};
+    use axum::routing::patch;
+    use emergent_testkit::fixtures::TempDir;
+    use emergent_testkit::{SinkFixture, fixtures, spawn_sink};
+    use tokio::sync::mpsc;
+
+    fn handler(dir: &Path, provider: Option<Provider>) -> StatuspageSink {
+        StatuspageSink {
+            title: "Acme Status".to_string(),
+            fields: Fields {
+                check: "check".to_string(),
+                status: "status".to_string(),
+                message: "message".to_string(),
+            },
+            mapping: Mapping::parse(&["api-*=API".to_string()]).unwrap_or_else(|e| panic!("{e}")),
+            windows: vec![Window::parse("24h").unwrap_or_else(|e| panic!("{e}"))],
+            incident_secs: 86_400,
+            output_dir: Some(dir.to_path_buf()),
+            state_file: Some(dir.join("state.json")),
+            bucket: None,
+            provider,
+            page: Mutex::new(Page::default()),
+        }
+    }
+
+    #[tokio::test]
+    async fn health_events_update_the_page_and_the_provider() {
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let app = Router::new().route(
+            "/v1/pages/{page}/components/{id}",
+            patch(
+                move |UrlPath((page, id)): UrlPath<(String, String)>,
+                      headers: HeaderMap,
+                      body: axum::Json<Value>| {
+                    let tx = tx.clone();
+                    async move {
+                        let auth = headers
+                            .get("authorization")
+                            .and_then(|v| v.to_str().ok())
+                            .unwrap_or_default()
+                            .to_string();
+                        let _ = tx.send((page, id, auth, body.0));
+                        StatusCode::OK
+                    }
+                },
+            ),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap_or_else(|e| panic!("bind: {e}"));
+        let addr = listener
+            .local_addr()
+            .unwrap_or_else(|e| panic!("local_addr: {e}"));
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let dir = TempDir::new("statuspage-sink");
+        let provider = Provider::new(
+            Kind::Statuspage,
+            Client::new(),
+            Some(&format!("http://{addr}")),
+            "page1",
+            "key1",
+            &["API=cmp1".to_string()],
+        )
+        .unwrap_or_else(|e| panic!("{e}"));
+        let (mut engine, run) = spawn_sink(
+            SinkFixture::new("statuspage_sink", "statuspage"),
+            SinkArgs {
+                dead_letter: true,
+                ..Default::default()
+            },
+            handler(dir.path(), Some(provider)),
+        );
+
+        engine
+            .inject_message(fixtures::message(
+                "health.check",
+                json!({"check": "api-eu", "status": "critical", "message": "HTTP 503"}),
+            ))
+            .await;
+        let (page, id, auth, body) = rx.recv().await.unwrap_or_else(|| panic!("no update"));
+        assert_eq!(
+            (page.as_str(), id.as_str(), auth.as_str()),
+            ("page1", "cmp1", "OAuth key1")
+        );
+        assert_eq!(body, json!({"component": {"status": "major_outage"}}));
+
+        // Not on the provider's page; only written out
+        engine
+            .inject_message(fixtures::message("health.up", json!({"service": "db"})))
+            .await;
+        engine
+            .inject_message(fixtures::message("health.check", json!({"status": "up"})))
+            .await;
+        let dead = engine.expect_published("statuspage.dead_letter").await;
+        assert!(
+            dead.payload()["error"]
+                .as_str()
+                .is_some_and(|e| e.contains("payload has no 'check'"))
+        );
+
+        let status: Value = fs::read(dir.path().join("status.json"))
+            .ok()
+            .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+            .unwrap_or_else(|| panic!("no status.json"));
+        assert_eq!(status["title"], "Acme Status");
+        assert_eq!(status["status"], "down");
+        assert_eq!(status["components"][0]["name"], "API");
+        assert_eq!(status["components"][1]["name"], "db");
+        assert_eq!(status["components"][1]["uptime"]["24h"], 100.0);
+        assert_eq!(status["incidents"][0]["message"], "HTTP 503");
+        let html = fs::read_to_string(dir.path().join("index.html")).unwrap_or_default();
+        assert!(html.contains("<title>Acme Status</title>"), "{html}");
+        let state = load_state(&dir.path().join("state.json")).unwrap_or_else(|e| panic!("{e}"));
+        assert_eq!(state.components.len(), 2);
+
+        engine.close();
+        assert_eq!(run.await.ok(), Some(Ok(())));
+    }
+}
@@ -0,0 +1,363 @@
+//! The status page: components, their uptime, and incidents.
+//!
+//! A component's status is the worst of its checks' latest results. Every
+//! change of status is kept in the component's history, from which uptime
+//! over a rolling window is the share of the window (or of the time since
+//! the component was first seen, if shorter) it was not down; degraded
+//! counts as up. An incident opens when a component stops being
+//! operational and is resolved when it is operational again.
+
+use crate::health::{Reading, Status};
+use primitive_common::time;
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use std::collections::BTreeMap;
+
+/// A rolling window uptime is reported over, such as `24h` or `30d`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Window {
+    pub label: String,
+    pub secs: i64,
+}
+
+impl Window {
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let spec = spec.trim();
+        let unit = match spec.chars().last() {
+            Some('s') => 1,
+            Some('m') => 60,
+            Some('h') => 3600,
+            Some('d') => 86_400,
+            _ => return Err(format!("{spec}: expected a number of s, m, h or d")),
+        };
+        let count: i64 = spec[..spec.len() - 1]
+            .parse()
+            .ok()
+            .filter(|n| *n > 0)
+            .ok_or_else(|| format!("{spec}: expected a number of s, m, h or d"))?;
+        Ok(Self {
+            label: spec.to_string(),
+            secs: count * unit,
+        })
+    }
+}
+
+/// A component shown on the page.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Component {
+    pub status: Status,
+    /// When the status last changed.
+    pub since: i64,
+    pub first_seen: i64,
+    /// Latest result of each check.
+    checks: BTreeMap<String, Status>,
+    /// Status changes, oldest first.
+    history: Vec<(i64, Status)>,
+}
+
+impl Component {
+    /// Percentage of the `secs` before `now` the component was not down,
+    /// rounded down to three decimals.
+    pub fn uptime(&self, secs: i64, now: i64) -> f64 {
+        let start = (now - secs).max(self.first_seen);
+        let total = now - start;
+        if total <= 0 {
+            return if self.status == Status::Down {
+                0.0
+            } else {
+                100.0
+            };
+        }
+        let mut down = 0;
+        for (i, (at, status)) in self.history.iter().enumerate() {
+            let end = self.history.get(i + 1).map_or(now, |(next, _)| *next);
+            let from = (*at).max(start);
+            if *status == Status::Down && end > from {
+                down += end - from;
+            }
+        }
+        let uptime = 100.0 * (total - down) as f64 / total as f64;
+        (uptime * 1000.0).floor() / 1000.0
+    }
+}
+
+/// A period a component was not operational.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Incident {
+    pub id: String,
+    pub component: String,
+    /// The worst status reached.
+    pub status: Status,
+    pub started_at: i64,
+    pub resolved_at: Option<i64>,
+    /// The latest message of a check that was not operational.
+    pub message: Option<String>,
+}
+
+impl Incident {
+    fn payload(&self) -> Value {
+        json!({
+            "id": self.id,
+            "component": self.component,
+            "status": self.status.as_str(),
+            "started_at": time::format(self.started_at),
+            "resolved_at": self.resolved_at.map(time::format),
+            "message": self.message,
+        })
+    }
+}
+
+/// A component's status changing.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Change {
+    pub component: String,
+    /// `None` for a component seen for the first time.
+    pub from: Option<Status>,
+    pub to: Status,
+}
+
+/// Everything the page shows, as kept in the state file.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Page {
+    #[serde(default)]
+    pub components: BTreeMap<String, Component>,
+    /// Incidents, oldest first.
+    #[serde(default)]
+    pub incidents: Vec<Incident>,
+}
+
+impl Page {
+    /// Record `reading` for `component`, returning the change of status it
+    /// makes, if any.
+    pub fn apply(&mut self, component: &str, reading: &Reading, now: i64) -> Option<Change> {
+        let entry = self
+            .components
+            .entry(component.to_string())
+            .or_insert_with(|| Component {
+                status: reading.status,
+                since: now,
+                first_seen: now,
+                checks: BTreeMap::new(),
+                history: Vec::new(),
+            });
+        entry.checks.insert(reading.check.clone(), reading.status);
+        let status = entry
+            .checks
+            .values()
+            .copied()
+            .max()
+            .unwrap_or(reading.status);
+        let from = entry.history.last().map(|(_, status)| *status);
+        let change = (from != Some(status)).then(|| {
+            entry.status = status;
+            entry.since = now;
+            entry.history.push((now, status));
+            Change {
+                component: component.to_string(),
+                from,
+                to: status,
+            }
+        });
+
+        let open = self
+            .incidents
+            .iter_mut()
+            .find(|i| i.component == component && i.resolved_at.is_none());
+        match open {
+            Some(incident) if status == Status::Operational => incident.resolved_at = Some(now),
+            Some(incident) => {
+                incident.status = incident.status.max(status);
+                if reading.status != Status::Operational && reading.message.is_some() {
+                    incident.message.clone_from(&reading.message);
+                }
+            }
+            None if status != Status::Operational => self.incidents.push(Incident {
+                id: format!("{}-{now}", slug(component)),
+                component: component.to_string(),
+                status,
+                started_at: now,
+                resolved_at: None,
+                message: reading.message.clone(),
+            }),
+            None => {}
+        }
+        change
+    }
+
+    /// Drop history no window reaches back to, and incidents resolved more
+    /// than `incidents_secs` ago.
+    pub fn prune(&mut self, history_secs: i64, incidents_secs: i64, now: i64) {
+        for component in self.components.values_mut() {
+            // The last change before the cutoff still says what the
+            // status was at the cutoff
+            let before = component
+                .history
+                .iter()
+                .rposition(|(at, _)| *at <= now - history_secs);
+            if let Some(keep) = before {
+                component.history.drain(..keep);
+            }
+        }
+        self.incidents.retain(|incident| {
+            incident
+                .resolved_at
+                .is_none_or(|resolved| resolved >= now - incidents_secs)
+        });
+    }
+
+    /// The worst status of any component.
+    pub fn overall(&self) -> Status {
+        self.components
+            .values()
+            .map(|c| c.status)
+            .max()
+            .unwrap_or(Status::Operational)
+    }
+
+    /// The page as `status.json`: components in `order` first, then the
+    /// rest by name; incidents newest first.
+    pub fn summary(&self, title: &str, windows: &[Window], order: &[&str], now: i64) -> Value {
+        let mut names: Vec<&str> = order
+            .iter()
+            .copied()
+            .filter(|name| self.components.contains_key(*name))
+            .collect();
+        names.extend(
+            self.components
+                .keys()
+                .map(String::as_str)
+                .filter(|name| !order.contains(name)),
+        );
+        let components: Vec<Value> = names
+            .iter()
+            .filter_map(|name| Some((*name, self.components.get(*name)?)))
+            .map(|(name, component)| {
+                let uptime: serde_json::Map<String, Value> = windows
+                    .iter()
+                    .map(|w| (w.label.clone(), json!(component.uptime(w.secs, now))))
+                    .collect();
+                let checks: serde_json::Map<String, Value> = component
+                    .checks
+                    .iter()
+                    .map(|(check, status)| (check.clone(), json!(status.as_str())))
+                    .collect();
+                json!({
+                    "name": name,
+                    "status": component.status.as_str(),
+                    "since": time::format(component.since),
+                    "uptime": uptime,
+                    "checks": checks,
+                })
+            })
+            .collect();
+        let incidents: Vec<Value> = self.incidents.iter().rev().map(Incident::payload).collect();
+        json!({
+            "title": title,
+            "status": self.overall().as_str(),
+            "updated_at": time::format(now),
+            "components": components,
+            "incidents": incidents,
+        })
+    }
+}
+
+/// `name` lowercased, with runs of anything but letters and digits as `-`.
+fn slug(name: &str) -> String {
+    let mut slug = String::new();
+    for c in name.chars() {
+        if c.is_ascii_alphanumeric() {
+            slug.push(c.to_ascii_lowercase());
+        } else if !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    slug.trim_matches('-').to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reading(check: &str, status: Status) -> Reading {
+        Reading {
+            check: check.to_string(),
+            status,
+            message: (status != Status::Operational).then(|| format!("{check} failing")),
+        }
+    }
+
+    #[test]
+    fn components_track_status_uptime_and_incidents() {
+        let mut page = Page::default();
+        let t = 1_000_000;
+        let first = page.apply("API", &reading("api-eu", Status::Operational), t);
+        assert_eq!(
+            first,
+            Some(Change {
+                component: "API".to_string(),
+                from: None,
+                to: Status::Operational,
+            })
+        );
+        assert_eq!(
+            page.apply("API", &reading("api-us", Status::Operational), t),
+            None
+        );
+
+        // The worst check decides; an incident opens
+        let down = page.apply("API", &reading("api-us", Status::Down), t + 100);
+        assert_eq!(down.map(|c| c.to), Some(Status::Down));
+        assert_eq!(page.incidents.len(), 1);
+        assert_eq!(page.incidents[0].id, "api-1000100");
+        assert_eq!(page.incidents[0].message.as_deref(), Some("api-us failing"));
+        // Still down while any check is
+        assert_eq!(
+            page.apply("API", &reading("api-eu", Status::Degraded), t + 150),
+            None
+        );
+        let back = page.apply("API", &reading("api-us", Status::Operational), t + 200);
+        assert_eq!(back.map(|c| c.to), Some(Status::Degraded));
+        page.apply("API", &reading("api-eu", Status::Operational), t + 300);
+        assert_eq!(page.incidents[0].resolved_at, Some(t + 300));
+        assert_eq!(page.incidents[0].status, Status::Down);
+
+        // Down 100 of the 400 seconds seen
+        let api = &page.components["API"];
+        assert_eq!(api.uptime(86_400, t + 400), 75.0);
+        // Down none of the last 200, and 100 of the last 350
+        assert_eq!(api.uptime(200, t + 400), 100.0);
+        assert_eq!(api.uptime(350, t + 400), 71.428);
+
+        page.prune(250, 50, t + 400);
+        assert_eq!(page.components["API"].history.len(), 3);
+        assert_eq!(page.incidents.len(), 0);
+        assert_eq!(page.overall(), Status::Operational);
+
+        let windows = [Window::parse("1d").unwrap_or_else(|e| panic!("{e}"))];
+        page.apply("db", &reading("db", Status::Down), t + 400);
+        let summary = page.summary("Status", &windows, &["db"], t + 500);
+        assert_eq!(summary["status"], "down");
+        assert_eq!(summary["components"][0]["name"], "db");
+        assert_eq!(summary["components"][0]["uptime"]["1d"], 0.0);
+        assert_eq!(summary["components"][1]["checks"]["api-us"], "operational");
+        assert_eq!(
+            summary["incidents"][0]["started_at"],
+            "1970-01-12T13:53:20Z"
+        );
+    }
+
+    #[test]
+    fn windows_parse() {
+        assert_eq!(
+            Window::parse("7d"),
+            Ok(Window {
+                label: "7d".to_string(),
+                secs: 604_800
+            })
+        );
+        assert_eq!(Window::parse("90m").map(|w| w.secs), Ok(5400));
+        assert!(Window::parse("0h").is_err());
+        assert!(Window::parse("24").is_err());
+        assert_eq!(slug("Payments API (EU)"), "payments-api-eu");
+    }
+}
@@ -0,0 +1,197 @@
+//! Mirroring component status to a hosted status page.
+//!
+//! Components are matched to the provider's by `NAME=ID` entries; a
+//! component with no entry is only shown on the page this sink writes.
+//! Only changes of status are sent.
+
+use crate::health::Status;
+use clap::ValueEnum;
+use reqwest::Client;
+use serde_json::{Value, json};
+use std::collections::BTreeMap;
+
+/// A hosted status page service.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Kind {
+    /// Atlassian Statuspage (statuspage.io).
+    Statuspage,
+    /// Instatus.
+    Instatus,
+}
+
+impl Kind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Statuspage => "statuspage",
+            Self::Instatus => "instatus",
+        }
+    }
+
+    pub fn default_url(self) -> &'static str {
+        match self {
+            Self::Statuspage => "https://api.statuspage.io",
+            Self::Instatus => "https://api.instatus.com",
+        }
+    }
+
+    /// The provider's name for `status`.
+    fn status(self, status: Status) -> &'static str {
+        match (self, status) {
+            (Self::Statuspage, Status::Operational) => "operational",
+            (Self::Statuspage, Status::Degraded) => "degraded_performance",
+            (Self::Statuspage, Status::Down) => "major_outage",
+            (Self::Instatus, Status::Operational) => "OPERATIONAL",
+            (Self::Instatus, Status::Degraded) => "DEGRADEDPERFORMANCE",
+            (Self::Instatus, Status::Down) => "MAJOROUTAGE",
+        }
+    }
+}
+
+/// A page on a hosted service and the components on it.
+pub struct Provider {
+    pub kind: Kind,
+    client: Client,
+    url: String,
+    page_id: String,
+    api_key: String,
+    components: BTreeMap<String, String>,
+}
+
+impl Provider {
+    /// `components` holds `NAME=ID` entries.
+    pub fn new(
+        kind: Kind,
+        client: Client,
+        url: Option<&str>,
+        page_id: &str,
+        api_key: &str,
+        components: &[String],
+    ) -> Result<Self, String> {
+        let components = components
+            .iter()
+            .map(|entry| match entry.split_once('=') {
+                Some((name, id)) if !name.is_empty() && !id.is_empty() => {
+                    Ok((name.trim().to_string(), id.trim().to_string()))
+                }
+                _ => Err(format!("{entry}: expected NAME=ID")),
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Self {
+            kind,
+            client,
+            url: url
+                .unwrap_or(kind.default_url())
+                .trim_end_matches('/')
+                .to_string(),
+            page_id: page_id.to_string(),
+            api_key: api_key.to_string(),
+            components,
+        })
+    }
+
+    /// The request that sets `component` to `status`, as method, URL and
+    /// body; `None` for a component not on the provider's page.
+    pub fn request(
+        &self,
+        component: &str,
+        status: Status,
+    ) -> Option<(&'static str, String, Value)> {
+        let id = self.components.get(component)?;
+        let (page, status_name) = (&self.page_id, self.kind.status(status));
+        Some(match self.kind {
+            Kind::Statuspage => (
+                "PATCH",
+                format!("{}/v1/pages/{page}/components/{id}", self.url),
+                json!({"component": {"status": status_name}}),
+            ),
+            Kind::Instatus => (
+                "PUT",
+                format!("{}/v1/{page}/components/{id}", self.url),
+                json!({"status": status_name}),
+            ),
+        })
+    }
+
+    /// Set `component` to `status`; a component not on the provider's page
+    /// is skipped.
+    pub async fn update(&self, component: &str, status: Status) -> Result<(), String> {
+        let Some((method, url, body)) = self.request(component, status) else {
+            return Ok(());
+        };
+        let request = match self.kind {
+            Kind::Statuspage => self
+                .client
+                .patch(&url)
+                .header("authorization", format!("OAuth {}", self.api_key)),
+            Kind::Instatus => self.client.put(&url).bearer_auth(&self.api_key),
+        };
+        let response = request
+            .json(&body)
+            .send()
+            .await
+            .map_err(|e| format!("{method} {url}: {e}"))?;
+        let status = response.status();
+        if !status.is_success() {
+            let body = response.text().await.unwrap_or_default();
+            return Err(format!("{method} {url}: {status}: {}", body.trim()));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn requests_follow_each_provider() {
+        let components = ["API=cmp1".to_string()];
+        let statuspage = Provider::new(
+            Kind::Statuspage,
+            Client::new(),
+            None,
+            "page1",
+            "key",
+            &components,
+        )
+        .unwrap_or_else(|e| panic!("{e}"));
+        assert_eq!(
+            statuspage.request("API", Status::Degraded),
+            Some((
+                "PATCH",
+                "https://api.statuspage.io/v1/pages/page1/components/cmp1".to_string(),
+                json!({"component": {"status": "degraded_performance"}}),
+            ))
+        );
+        assert_eq!(statuspage.request("db", Status::Down), None);
+
+        let instatus = Provider::new(
+            Kind::Instatus,
+            Client::new(),
+            Some("http://localhost:9000/"),
+            "page1",
+            "key",
+            &components,
+        )
+        .unwrap_or_else(|e| panic!("{e}"));
+        assert_eq!(
+            instatus.request("API", Status::Down),
+            Some((
+                "PUT",
+                "http://localhost:9000/v1/page1/components/cmp1".to_string(),
+                json!({"status": "MAJOROUTAGE"}),
+            ))
+        );
+        assert!(
+            Provider::new(
+                Kind::Instatus,
+                Client::new(),
+                None,
+                "p",
+                "k",
+                &["API".to_string()]
+            )
+            .is_err()
+        );
+    }
+}
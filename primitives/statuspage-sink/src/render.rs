@@ -0,0 +1,138 @@
+//! The page as HTML, rendered from the `status.json` summary so both always
+//! agree. It is a single self-contained file with no scripts.
+
+use crate::page::Window;
+use serde_json::Value;
+use std::fmt::Write;
+
+/// `text` with the characters HTML gives meaning to escaped.
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn text(value: &Value) -> String {
+    escape(value.as_str().unwrap_or_default())
+}
+
+fn headline(status: &str) -> &'static str {
+    match status {
+        "operational" => "All systems operational",
+        "degraded" => "Degraded performance",
+        _ => "Service disruption",
+    }
+}
+
+const STYLE: &str = "body{font-family:system-ui,sans-serif;max-width:48rem;margin:2rem auto;padding:0 1rem;color:#222}\
+.banner{padding:1rem;border-radius:.5rem;color:#fff;font-weight:600}\
+.operational{background:#2e7d32}.degraded{background:#ed6c02}.down{background:#c62828}\
+table{width:100%;border-collapse:collapse;margin:1rem 0}td,th{padding:.5rem;border-bottom:1px solid #ddd;text-align:left}\
+.dot{display:inline-block;width:.75rem;height:.75rem;border-radius:50%;margin-right:.5rem}\
+.muted{color:#666;font-size:.875rem}";
+
+/// `summary` (see [`Page::summary`](crate::page::Page::summary)) as HTML,
+/// with a column for the uptime over each of `windows`.
+pub fn html(summary: &Value, windows: &[Window]) -> String {
+    let title = text(&summary["title"]);
+    let status = summary["status"].as_str().unwrap_or("operational");
+
+    let mut page = String::new();
+    let _ = write!(
+        page,
+        "<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">\n\
+         <meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">\n\
+         <title>{title}</title>\n<style>{STYLE}</style>\n</head>\n<body>\n\
+         <h1>{title}</h1>\n<div class=\"banner {status}\">{}</div>\n",
+        headline(status)
+    );
+
+    page.push_str("<table>\n<tr><th>Component</th><th>Status</th>");
+    for window in windows {
+        let _ = write!(page, "<th>Uptime {}</th>", escape(&window.label));
+    }
+    page.push_str("</tr>\n");
+    for component in summary["components"].as_array().into_iter().flatten() {
+        let status = component["status"].as_str().unwrap_or_default();
+        let _ = write!(
+            page,
+            "<tr><td>{}</td><td><span class=\"dot {status}\"></span>{status}</td>",
+            text(&component["name"])
+        );
+        for window in windows {
+            // Rounded down, so a page never shows 100% for an outage
+            let uptime = component["uptime"][&window.label].as_f64().unwrap_or(100.0);
+            let _ = write!(page, "<td>{:.2}%</td>", (uptime * 100.0).floor() / 100.0);
+        }
+        page.push_str("</tr>\n");
+    }
+    page.push_str("</table>\n<h2>Incidents</h2>\n");
+
+    let incidents = summary["incidents"]
+        .as_array()
+        .map_or(&[][..], Vec::as_slice);
+    if incidents.is_empty() {
+        page.push_str("<p class=\"muted\">No incidents reported.</p>\n");
+    }
+    for incident in incidents {
+        let resolved = match incident["resolved_at"].as_str() {
+            Some(at) => format!("resolved {}", escape(at)),
+            None => "ongoing".to_string(),
+        };
+        let _ = write!(
+            page,
+            "<h3>{} {}</h3>\n<p class=\"muted\">{}, {resolved}</p>\n",
+            text(&incident["component"]),
+            text(&incident["status"]),
+            text(&incident["started_at"])
+        );
+        if let Some(message) = incident["message"].as_str() {
+            let _ = writeln!(page, "<p>{}</p>", escape(message));
+        }
+    }
+    let _ = write!(
+        page,
+        "<p class=\"muted\">Updated {}</p>\n</body>\n</html>\n",
+        text(&summary["updated_at"])
+    );
+    page
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn page_shows_components_and_escaped_incidents() {
+        let summary = json!({
+            "title": "Acme <Status>",
+            "status": "down",
+            "updated_at": "2024-05-20T16:15:58Z",
+            "components": [
+                {"name": "API", "status": "down", "uptime": {"24h": 99.5, "30d": 99.987}},
+            ],
+            "incidents": [
+                {"component": "API", "status": "down", "started_at": "2024-05-20T16:00:00Z",
+                 "resolved_at": null, "message": "HTTP 503 <upstream>"},
+            ],
+        });
+        let windows = ["24h", "30d"].map(|w| Window::parse(w).unwrap_or_else(|e| panic!("{e}")));
+        let page = html(&summary, &windows);
+        assert!(page.contains("<title>Acme &lt;Status&gt;</title>"));
+        assert!(page.contains("<div class=\"banner down\">Service disruption</div>"));
+        assert!(page.contains("<th>Uptime 24h</th><th>Uptime 30d</th>"));
+        assert!(page.contains("<td>99.50%</td><td>99.98%</td>"));
+        assert!(page.contains("2024-05-20T16:00:00Z, ongoing"));
+        assert!(page.contains("<p>HTTP 503 &lt;upstream&gt;</p>"));
+    }
+}
@@ -0,0 +1,165 @@
+//! Uploading the page to S3, or any S3-compatible store (MinIO, R2, ...).
+//!
+//! Objects are written with a single `PutObject` request, addressed
+//! path-style (`<endpoint>/<bucket>/<key>`) so custom endpoints work
+//! without DNS for every bucket, and signed with AWS Signature Version 4.
+
+use primitive_common::aws::{CredentialChain, SigV4, uri_encode, uri_encode_path};
+use reqwest::Client;
+use std::time::SystemTime;
+
+/// A bucket objects are written to, under a key prefix.
+pub struct Bucket {
+    client: Client,
+    endpoint: String,
+    bucket: String,
+    prefix: String,
+    sigv4: SigV4,
+    credentials: CredentialChain,
+}
+
+impl Bucket {
+    /// `endpoint` defaults to AWS's for `region`.
+    pub fn new(
+        client: Client,
+        endpoint: Option<&str>,
+        bucket: &str,
+        region: &str,
+        prefix: &str,
+        credentials: CredentialChain,
+    ) -> Self {
+        let endpoint = endpoint.map_or_else(
+            || format!("https://s3.{region}.amazonaws.com"),
+            |e| e.trim_end_matches('/').to_string(),
+        );
+        Self {
+            client,
+            endpoint,
+            bucket: bucket.to_string(),
+            prefix: prefix.trim_matches('/').to_string(),
+            sigv4: SigV4::new(region, "s3"),
+            credentials,
+        }
+    }
+
+    /// `s3://bucket/prefix`, for messages.
+    pub fn describe(&self) -> String {
+        format!("s3://{}/{}", self.bucket, self.prefix)
+    }
+
+    /// Write `body` to `key` under the prefix.
+    pub async fn put(&self, key: &str, body: Vec<u8>, content_type: &str) -> Result<(), String> {
+        let key = if self.prefix.is_empty() {
+            key.to_string()
+        } else {
+            format!("{}/{key}", self.prefix)
+        };
+        let url = format!(
+            "{}/{}/{}",
+            self.endpoint,
+            uri_encode(self.bucket.as_bytes()),
+            uri_encode_path(&key)
+        );
+        let mut request = self
+            .client
+            .put(&url)
+            .header("content-type", content_type)
+            .body(body)
+            .build()
+            .map_err(|e| format!("{url}: {e}"))?;
+        let credentials = self.credentials.credentials().await?;
+        self.sigv4
+            .sign(&mut request, &credentials, SystemTime::now())?;
+        let response = self
+            .client
+            .execute(request)
+            .await
+            .map_err(|e| format!("{url}: {e}"))?;
+        let status = response.status();
+        if !status.is_success() {
+            let body = response.text().await.unwrap_or_default();
+            return Err(format!("{url}: {status}: {}", body.trim()));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::Router;
+    use axum::body::Bytes;
+    use axum::extract::Path;
+    use axum::http::{HeaderMap, StatusCode};
+    use axum::routing::put;
+    use primitive_common::aws::Credentials;
+    use tokio::sync::mpsc;
+
+    #[tokio::test]
+    async fn objects_are_put_signed_under_the_prefix() {
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let app = Router::new().route(
+            "/{*path}",
+            put(
+                move |Path(path): Path<String>, headers: HeaderMap, body: Bytes| {
+                    let tx = tx.clone();
+                    async move {
+                        let header = |name: &str| {
+                            headers
+                                .get(name)
+                                .and_then(|v| v.to_str().ok())
+                                .unwrap_or_default()
+                                .to_string()
+                        };
+                        let _ = tx.send((
+                            path,
+                            header("authorization"),
+                            header("x-amz-security-token"),
+                            body,
+                        ));
+                        StatusCode::OK
+                    }
+                },
+            ),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap_or_else(|e| panic!("bind: {e}"));
+        let addr = listener
+            .local_addr()
+            .unwrap_or_else(|e| panic!("local_addr: {e}"));
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let bucket = Bucket::new(
+            Client::new(),
+            Some(&format!("http://{addr}/")),
+            "status",
+            "eu-west-1",
+            "/public/",
+            CredentialChain::new(
+                Client::new(),
+                Some(Credentials {
+                    access_key_id: "AKIDEXAMPLE".to_string(),
+                    secret_access_key: "secret".to_string(),
+                    session_token: Some("token".to_string()),
+                }),
+                None,
+            ),
+        );
+        assert_eq!(bucket.describe(), "s3://status/public");
+        bucket
+            .put("status.json", b"{}".to_vec(), "application/json")
+            .await
+            .unwrap_or_else(|e| panic!("{e}"));
+        let (path, authorization, token, body) =
+            rx.recv().await.unwrap_or_else(|| panic!("no request"));
+        assert_eq!(path, "status/public/status.json");
+        assert!(
+            authorization.starts_with("AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/"),
+            "{authorization}"
+        );
+        assert!(authorization.contains("/eu-west-1/s3/aws4_request, SignedHeaders=content-type;host;x-amz-content-sha256;x-amz-date;x-amz-security-token, Signature="));
+        assert_eq!(token, "token");
+        assert_eq!(&body[..], b"{}");
+    }
+}
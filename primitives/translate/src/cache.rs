@@ -0,0 +1,92 @@
+//! Translations already made, so repeated texts — notification templates,
+//! status names — are only paid for once.
+
+use std::collections::{HashMap, VecDeque};
+
+/// Source language (empty when detected), target language and text.
+type Key = (String, String, String);
+
+/// A bounded cache of translations, evicting the oldest first.
+pub struct Cache {
+    capacity: usize,
+    entries: HashMap<Key, String>,
+    order: VecDeque<Key>,
+    hits: u64,
+    misses: u64,
+}
+
+impl Cache {
+    /// A cache of at most `capacity` translations (none when 0).
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            entries: HashMap::new(),
+            order: VecDeque::new(),
+            hits: 0,
+            misses: 0,
+        }
+    }
+
+    fn key(from: Option<&str>, to: &str, text: &str) -> Key {
+        (
+            from.unwrap_or_default().to_string(),
+            to.to_string(),
+            text.to_string(),
+        )
+    }
+
+    /// The translation of `text` into `to`, if cached.
+    pub fn get(&mut self, from: Option<&str>, to: &str, text: &str) -> Option<String> {
+        let found = self.entries.get(&Self::key(from, to, text)).cloned();
+        if found.is_some() {
+            self.hits += 1;
+        } else {
+            self.misses += 1;
+        }
+        found
+    }
+
+    pub fn insert(&mut self, from: Option<&str>, to: &str, text: &str, translation: String) {
+        if self.capacity == 0 {
+            return;
+        }
+        let key = Self::key(from, to, text);
+        if self.entries.insert(key.clone(), translation).is_none() {
+            self.order.push_back(key);
+        }
+        while self.entries.len() > self.capacity {
+            let Some(oldest) = self.order.pop_front() else {
+                break;
+            };
+            self.entries.remove(&oldest);
+        }
+    }
+
+    /// Lookups answered from the cache and not.
+    pub fn stats(&self) -> (u64, u64) {
+        (self.hits, self.misses)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_oldest_translations_are_evicted() {
+        let mut cache = Cache::new(2);
+        cache.insert(None, "de", "Hello", "Hallo".to_string());
+        cache.insert(None, "fr", "Hello", "Bonjour".to_string());
+        assert_eq!(cache.get(None, "de", "Hello").as_deref(), Some("Hallo"));
+        assert_eq!(cache.get(Some("en"), "de", "Hello"), None);
+
+        cache.insert(None, "es", "Hello", "Hola".to_string());
+        assert_eq!(cache.get(None, "de", "Hello"), None);
+        assert_eq!(cache.get(None, "fr", "Hello").as_deref(), Some("Bonjour"));
+        assert_eq!(cache.stats(), (2, 2));
+
+        let mut disabled = Cache::new(0);
+        disabled.insert(None, "de", "Hello", "Hallo".to_string());
+        assert_eq!(disabled.get(None, "de", "Hello"), None);
+    }
+}
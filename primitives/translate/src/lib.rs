@@ -0,0 +1,466 @@
+//! Translate
+//!
+//! A Handler that adds translations of payload text fields, so one event
+//! can drive notifications in each recipient's language. Text goes to
+//! DeepL, Google Cloud Translation or a LibreTranslate server — run
+//! locally, it translates without text leaving the host (see
+//! [`provider`]). Translations are cached (see [`cache`]).
+//!
+//! # Data Flow
+//!
+//! 1. Receive an event matching configured subscriptions
+//! 2. Collect the `--field` texts present in its payload
+//! 3. For each `--to` language, translate the texts not yet cached in one
+//!    request
+//! 4. Republish the payload with each translation beside its field, under
+//!    the language as a suffix (`title` → `title_de`, `pt-BR` → `title_pt_br`)
+//!
+//! A message whose translation fails is reported and dropped.
+//!
+//! # Messages Published
+//!
+//! - The incoming type, renamed by `--publish-as` (default:
+//!   `translated.{type}`), with the translated fields added
+//!
+//! # Usage
+//!
+//! ```bash
+//! # German and French copies of alert texts, via DeepL
+//! translate -s alert.fired --field title --field body --to de,fr \
+//!   --provider deepl --api-key $DEEPL_KEY
+//!
+//! # Translate locally with LibreTranslate
+//! translate -s 'notify.*' --field message --to es \
+//!   --provider libretranslate --api-url http://localhost:5000
+//! ```
+
+pub mod cache;
+pub mod provider;
+
+use cache::Cache;
+use clap::Parser;
+use emergent_client::EmergentMessage;
+use primitive_common::capabilities::{self, CapabilityArgs, Role, VERSION};
+use primitive_common::crypto::{KeyArgs, Keyring};
+use primitive_common::doctor::Report;
+use primitive_common::errors::{ERROR_EVENT_TYPE, ErrorArgs, ErrorCategory};
+use primitive_common::handler::{Bus, HandlerConfig, MessageHandler, run_handler};
+use primitive_common::key;
+use primitive_common::payload::{self, PayloadArgs};
+use provider::{Provider, Translator};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::time::Duration;
+
+/// Translate — add translated copies of payload text fields.
+#[derive(Parser, Debug)]
+#[command(name = "translate", version = VERSION)]
+#[command(about = "Add translations of payload text fields via DeepL, Google or LibreTranslate")]
+struct Args {
+    /// Message types to subscribe to.
+    #[arg(short, long = "subscribe", required = true)]
+    subscribe: Vec<String>,
+
+    /// Payload text field to translate, as a dotted path (e.g. `alert.title`); repeatable.
+    #[arg(
+        long = "field",
+        env = "TRANSLATE_FIELDS",
+        value_delimiter = ',',
+        required = true
+    )]
+    fields: Vec<String>,
+
+    /// Languages to translate into (e.g. `de,fr,pt-BR`).
+    #[arg(long, env = "TRANSLATE_TO", value_delimiter = ',', required = true)]
+    to: Vec<String>,
+
+    /// Language of the texts (default: detected by the provider).
+    #[arg(long, env = "TRANSLATE_FROM")]
+    from: Option<String>,
+
+    /// Translation API.
+    #[arg(long, env = "TRANSLATE_PROVIDER", value_enum, default_value = "deepl")]
+    provider: Provider,
+
+    /// API base URL (default: the provider's; required for LibreTranslate).
+    #[arg(long, env = "TRANSLATE_API_URL")]
+    api_url: Option<String>,
+
+    /// API key (optional for LibreTranslate).
+    #[arg(long, env = "TRANSLATE_API_KEY")]
+    api_key: Option<String>,
+
+    /// Message type to republish as; `{type}` stands for the incoming type.
+    #[arg(
+        long,
+        env = "TRANSLATE_PUBLISH_AS",
+        default_value = "translated.{type}",
+        value_parser = parse_publish_as
+    )]
+    publish_as: String,
+
+    /// Translations kept in memory (0 disables the cache).
+    #[arg(long, env = "TRANSLATE_CACHE_SIZE", default_value = "10000")]
+    cache_size: usize,
+
+    /// Per-request timeout in milliseconds.
+    #[arg(long, env = "TRANSLATE_TIMEOUT", default_value = "10000")]
+    timeout: u64,
+
+    #[command(flatten)]
+    payload: PayloadArgs,
+
+    /// Verify the configuration and exit without connecting to the engine.
+    #[arg(long)]
+    self_test: bool,
+
+    #[command(flatten)]
+    errors: ErrorArgs,
+
+    #[command(flatten)]
+    capabilities: CapabilityArgs,
+
+    #[command(flatten)]
+    keys: KeyArgs,
+}
+
+fn parse_publish_as(value: &str) -> Result<String, String> {
+    match value {
+        "" => Err("must not be empty".to_string()),
+        // Republishing under the same type would feed straight back in
+        "{type}" => Err("must differ from the incoming type".to_string()),
+        _ => Ok(value.to_string()),
+    }
+}
+
+/// The message type `message_type` is republished as.
+fn publish_type(publish_as: &str, message_type: &str) -> String {
+    publish_as.replace("{type}", message_type)
+}
+
+/// Whether `message_type` is one this handler published.
+fn is_own(publish_as: &str, message_type: &str) -> bool {
+    match publish_as.split_once("{type}") {
+        Some((prefix, suffix)) => {
+            message_type.len() > prefix.len() + suffix.len()
+                && message_type.starts_with(prefix)
+                && message_type.ends_with(suffix)
+        }
+        None => message_type == publish_as,
+    }
+}
+
+/// The field a translation of `field` into `language` goes in:
+/// `title` and `pt-BR` give `title_pt_br`.
+fn translated_field(field: &str, language: &str) -> String {
+    format!("{field}_{}", language.to_lowercase().replace('-', "_"))
+}
+
+/// The non-empty texts at `fields` in `input`, with their fields; fields
+/// that are absent or not strings are skipped.
+fn texts<'a>(input: &'a Value, fields: &'a [String]) -> Vec<(&'a str, &'a str)> {
+    fields
+        .iter()
+        .filter_map(|field| {
+            let text = key::lookup(input, field)?.as_str()?;
+            (!text.trim().is_empty()).then_some((field.as_str(), text))
+        })
+        .collect()
+}
+
+/// Set the sibling of the dotted `field` named by `name`.
+fn set_beside(input: &mut Value, field: &str, name: &str, value: Value) {
+    let path = field.strip_prefix("payload.").unwrap_or(field);
+    let segments: Vec<&str> = path.split('.').collect();
+    let Some((_, parents)) = segments.split_last() else {
+        return;
+    };
+    let parent = parents
+        .iter()
+        .try_fold(input, |value, segment| match value {
+            Value::Object(map) => map.get_mut(*segment),
+            Value::Array(items) => segment.parse::<usize>().ok().and_then(|i| items.get_mut(i)),
+            _ => None,
+        });
+    if let Some(Value::Object(map)) = parent {
+        map.insert(name.to_string(), value);
+    }
+}
+
+/// Add the translations of `input`'s fields, returning how many were made.
+async fn translate(
+    input: &mut Value,
+    args: &Args,
+    translator: &Translator,
+    cache: &mut Cache,
+) -> Result<usize, String> {
+    let found: Vec<(String, String)> = texts(input, &args.fields)
+        .into_iter()
+        .map(|(field, text)| (field.to_string(), text.to_string()))
+        .collect();
+    let from = args.from.as_deref();
+    let mut added = 0;
+    for language in &args.to {
+        let mut translations: HashMap<&str, String> = HashMap::new();
+        let mut missing: Vec<&str> = Vec::new();
+        for (_, text) in &found {
+            if translations.contains_key(text.as_str()) || missing.contains(&text.as_str()) {
+                continue;
+            }
+            match cache.get(from, language, text) {
+                Some(translation) => {
+                    translations.insert(text, translation);
+                }
+                None => missing.push(text),
+            }
+        }
+        if !missing.is_empty() {
+            let fetched = translator.translate(&missing, from, language).await?;
+            for (text, translation) in missing.into_iter().zip(fetched) {
+                cache.insert(from, language, text, translation.clone());
+                translations.insert(text, translation);
+            }
+        }
+        for (field, text) in &found {
+            if let Some(translation) = translations.get(text.as_str()) {
+                let name = translated_field(field.rsplit('.').next().unwrap_or(field), language);
+                set_beside(input, field, &name, Value::from(translation.as_str()));
+                added += 1;
+            }
+        }
+    }
+    Ok(added)
+}
+
+/// Run the primitive with `args` as its command line (the first item is
+/// the program name, as with `std::env::args_os`).
+pub async fn run<I, T>(args: I) -> Result<(), Box<dyn std::error::Error>>
+where
+    I: IntoIterator<Item = T>,
+    T: Into<std::ffi::OsString> + Clone,
+{
+    let args = Args::parse_from(args);
+
+    // Get the handler name from environment (set by engine) or use default
+    let name = std::env::var("EMERGENT_NAME").unwrap_or_else(|_| "translate".to_string());
+
+    let translator = Translator::new(
+        args.provider,
+        args.api_url.as_deref(),
+        args.api_key.as_deref(),
+        Duration::from_millis(args.timeout),
+    );
+    if args.self_test {
+        let mut report = Report::new(&name);
+        report.check(
+            "provider",
+            translator
+                .as_ref()
+                .map(|translator| format!("{} into {}", translator.endpoint(), args.to.join(", ")))
+                .map_err(Clone::clone),
+        );
+        args.payload.self_test(&mut report);
+        args.keys.self_test(&mut report);
+        report.finish();
+    }
+    let translator = match translator {
+        Ok(translator) => translator,
+        Err(e) => {
+            eprintln!("Error: {e}");
+            std::process::exit(1);
+        }
+    };
+
+    if let Err(e) = args.payload.encryption.validate() {
+        eprintln!("Error: {e}");
+        std::process::exit(1);
+    }
+    let keys = match args.keys.load() {
+        Ok(keys) => keys,
+        Err(e) => {
+            eprintln!("Error: failed to load identity file: {e}");
+            std::process::exit(1);
+        }
+    };
+
+    let topics_refs: Vec<&str> = args.subscribe.iter().map(String::as_str).collect();
+    let mut produces = vec![args.publish_as.as_str()];
+    if args.errors.emit_errors {
+        produces.push(ERROR_EVENT_TYPE);
+    }
+    let descriptor =
+        args.capabilities
+            .describe(&name, Role::Handler, &topics_refs, &produces, &args);
+    if args.capabilities.capabilities {
+        capabilities::print(&descriptor);
+    }
+
+    let config = HandlerConfig {
+        name: &name,
+        subscribe: &args.subscribe,
+        announce: args.capabilities.announce.then_some(&descriptor),
+        emit_errors: args.errors.emit_errors,
+    };
+    let mut translate = Translate {
+        args: &args,
+        translator,
+        cache: Cache::new(args.cache_size),
+        keys,
+    };
+    run_handler(config, &mut translate).await?;
+
+    let (hits, misses) = translate.cache.stats();
+    eprintln!("translate: {hits} cached, {misses} translated");
+    Ok(())
+}
+
+/// The provider and the translations already fetched.
+struct Translate<'a> {
+    args: &'a Args,
+    translator: Translator,
+    cache: Cache,
+    keys: Option<Keyring>,
+}
+
+impl MessageHandler for Translate<'_> {
+    type Wake = ();
+
+    /// Translate one message and republish it.
+    async fn handle(&mut self, msg: &EmergentMessage, bus: &Bus) {
+        let args = self.args;
+        let message_type = msg.message_type.as_str();
+        if is_own(&args.publish_as, message_type) {
+            return;
+        }
+        let mut input = match payload::decode(msg.payload(), self.keys.as_ref()).await {
+            Ok(p) => p.into_owned(),
+            Err(e) => {
+                let error = format!("failed to decode payload: {e}");
+                eprintln!("translate: {error}");
+                bus.report_error(msg, ErrorCategory::Parse, &error).await;
+                return;
+            }
+        };
+
+        if let Err(e) = translate(&mut input, args, &self.translator, &mut self.cache).await {
+            let error = format!("translation failed: {e}");
+            eprintln!("translate: {error}");
+            bus.report_error(msg, ErrorCategory::Request, &error).await;
+            return;
+        }
+
+        let publish_as = publish_type(&args.publish_as, message_type);
+        let payload = match payload::encode(&publish_as, input, &args.payload).await {
+            Ok(p) => p,
+            Err(e) => {
+                eprintln!("translate: failed to encode output: {e}");
+                return;
+            }
+        };
+        let message = EmergentMessage::new(&publish_as)
+            .with_causation_id(msg.id())
+            .with_payload(payload);
+        bus.publish(message).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{Json, Router, routing::post};
+    use serde_json::json;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[tokio::test]
+    async fn fields_gain_translations_beside_them_and_repeats_are_cached() {
+        let requests = Arc::new(AtomicUsize::new(0));
+        let app = {
+            let requests = Arc::clone(&requests);
+            Router::new().route(
+                "/translate",
+                post(move |Json(body): Json<Value>| {
+                    let requests = Arc::clone(&requests);
+                    async move {
+                        requests.fetch_add(1, Ordering::SeqCst);
+                        let target = body["target"].as_str().unwrap_or_default().to_string();
+                        let translated: Vec<String> = body["q"]
+                            .as_array()
+                            .map(|texts| {
+                                texts
+                                    .iter()
+                                    .map(|text| {
+                                        format!("{target}: {}", text.as_str().unwrap_or_default())
+                                    })
+                                    .collect()
+                            })
+                            .unwrap_or_default();
+                        Json(json!({"translatedText": translated}))
+                    }
+                }),
+            )
+        };
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap_or_else(|e| panic!("bind: {e}"));
+        let addr = listener
+            .local_addr()
+            .unwrap_or_else(|e| panic!("local_addr: {e}"));
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let args = Args::try_parse_from([
+            "translate",
+            "-s",
+            "alert.fired",
+            "--field",
+            "title,payload.detail.body,missing,count",
+            "--to",
+            "de,pt-BR",
+            "--provider",
+            "libretranslate",
+            "--api-url",
+            &format!("http://{addr}"),
+        ])
+        .unwrap_or_else(|e| panic!("{e}"));
+        let translator = Translator::new(
+            args.provider,
+            args.api_url.as_deref(),
+            None,
+            Duration::from_secs(5),
+        )
+        .unwrap_or_else(|e| panic!("{e}"));
+        let mut cache = Cache::new(100);
+
+        let mut input = json!({"title": "Disk full", "detail": {"body": "Disk full"}, "count": 3});
+        let added = translate(&mut input, &args, &translator, &mut cache).await;
+        assert_eq!(added, Ok(4));
+        assert_eq!(
+            input,
+            json!({
+                "title": "Disk full",
+                "title_de": "de: Disk full",
+                "title_pt_br": "pt-BR: Disk full",
+                "detail": {
+                    "body": "Disk full",
+                    "body_de": "de: Disk full",
+                    "body_pt_br": "pt-BR: Disk full",
+                },
+                "count": 3,
+            })
+        );
+        // The repeated text went once per language
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+
+        let mut again = json!({"title": "Disk full"});
+        let added = translate(&mut again, &args, &translator, &mut cache).await;
+        assert_eq!(added, Ok(2));
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+
+        assert_eq!(
+            publish_type(&args.publish_as, "alert.fired"),
+            "translated.alert.fired"
+        );
+        assert!(is_own(&args.publish_as, "translated.alert.fired"));
+        assert!(!is_own(&args.publish_as, "alert.fired"));
+    }
+}
@@ -0,0 +1,6 @@
+//! `translate` binary; the primitive itself lives in the library.
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    translate::run(std::env::args_os()).await
+}
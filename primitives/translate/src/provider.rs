@@ -0,0 +1,267 @@
+//! Translation APIs.
+//!
+//! Every provider takes a batch of texts and one target language per
+//! request, so a message's fields cost one call per language:
+//!
+//! - DeepL: `POST /v2/translate`, keyed with `Authorization: DeepL-Auth-Key`.
+//!   Free-plan keys (ending `:fx`) go to `api-free.deepl.com`.
+//! - Google Cloud Translation (v2): `POST /language/translate/v2?key=...`.
+//! - LibreTranslate: `POST /translate`, with an optional `api_key`. Run it
+//!   locally to translate with its bundled models and no text leaving the
+//!   host.
+
+use clap::ValueEnum;
+use reqwest::Client;
+use serde_json::{Value, json};
+use std::time::Duration;
+
+/// A translation API.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Provider {
+    Deepl,
+    Google,
+    Libretranslate,
+}
+
+impl Provider {
+    /// The API endpoint when no `--api-url` is given.
+    fn default_url(self, key: Option<&str>) -> Option<&'static str> {
+        match self {
+            Self::Deepl if key.is_some_and(|key| key.ends_with(":fx")) => {
+                Some("https://api-free.deepl.com")
+            }
+            Self::Deepl => Some("https://api.deepl.com"),
+            Self::Google => Some("https://translation.googleapis.com"),
+            // Usually self-hosted
+            Self::Libretranslate => None,
+        }
+    }
+
+    /// The request path under the API URL.
+    fn path(self) -> &'static str {
+        match self {
+            Self::Deepl => "/v2/translate",
+            Self::Google => "/language/translate/v2",
+            Self::Libretranslate => "/translate",
+        }
+    }
+
+    /// The request body translating `texts` from `from` (detected when
+    /// `None`) into `to`.
+    fn body(self, texts: &[&str], from: Option<&str>, to: &str, key: Option<&str>) -> Value {
+        match self {
+            Self::Deepl => {
+                let mut body = json!({"text": texts, "target_lang": to.to_uppercase()});
+                if let Some(from) = from {
+                    // DeepL takes the bare language as the source: EN, not EN-GB
+                    let base = from.split('-').next().unwrap_or(from);
+                    body["source_lang"] = Value::from(base.to_uppercase());
+                }
+                body
+            }
+            Self::Google => {
+                let mut body = json!({"q": texts, "target": to, "format": "text"});
+                if let Some(from) = from {
+                    body["source"] = Value::from(from);
+                }
+                body
+            }
+            Self::Libretranslate => {
+                let mut body = json!({
+                    "q": texts,
+                    "source": from.unwrap_or("auto"),
+                    "target": to,
+                    "format": "text",
+                });
+                if let Some(key) = key {
+                    body["api_key"] = Value::from(key);
+                }
+                body
+            }
+        }
+    }
+
+    /// The translations in a response, in the order of the texts sent.
+    fn translations(self, response: &Value) -> Option<Vec<String>> {
+        let strings = |items: &Value, field: Option<&str>| -> Option<Vec<String>> {
+            items
+                .as_array()?
+                .iter()
+                .map(|item| {
+                    let text = match field {
+                        Some(field) => &item[field],
+                        None => item,
+                    };
+                    text.as_str().map(str::to_string)
+                })
+                .collect()
+        };
+        match self {
+            Self::Deepl => strings(&response["translations"], Some("text")),
+            Self::Google => strings(&response["data"]["translations"], Some("translatedText")),
+            Self::Libretranslate => strings(&response["translatedText"], None),
+        }
+    }
+}
+
+/// A configured translation API.
+pub struct Translator {
+    client: Client,
+    provider: Provider,
+    url: String,
+    key: Option<String>,
+}
+
+impl Translator {
+    pub fn new(
+        provider: Provider,
+        url: Option<&str>,
+        key: Option<&str>,
+        timeout: Duration,
+    ) -> Result<Self, String> {
+        let url = url
+            .or_else(|| provider.default_url(key))
+            .ok_or("--api-url is required for libretranslate")?
+            .trim_end_matches('/')
+            .to_string();
+        if provider != Provider::Libretranslate && key.is_none() {
+            return Err("--api-key is required".to_string());
+        }
+        let client = Client::builder()
+            .timeout(timeout)
+            .build()
+            .map_err(|e| format!("failed to build HTTP client: {e}"))?;
+        Ok(Self {
+            client,
+            provider,
+            url,
+            key: key.map(str::to_string),
+        })
+    }
+
+    /// The API endpoint, for reporting.
+    pub fn endpoint(&self) -> String {
+        format!("{}{}", self.url, self.provider.path())
+    }
+
+    /// Translate `texts` from `from` (detected when `None`) into `to`.
+    pub async fn translate(
+        &self,
+        texts: &[&str],
+        from: Option<&str>,
+        to: &str,
+    ) -> Result<Vec<String>, String> {
+        let endpoint = self.endpoint();
+        let mut request = self.client.post(&endpoint).json(&self.provider.body(
+            texts,
+            from,
+            to,
+            self.key.as_deref(),
+        ));
+        match (self.provider, &self.key) {
+            (Provider::Deepl, Some(key)) => {
+                request = request.header("Authorization", format!("DeepL-Auth-Key {key}"));
+            }
+            (Provider::Google, Some(key)) => request = request.query(&[("key", key)]),
+            _ => {}
+        }
+        let response = request
+            .send()
+            .await
+            .map_err(|e| format!("{endpoint}: {e}"))?;
+        let status = response.status();
+        if !status.is_success() {
+            let body = response.text().await.unwrap_or_default();
+            return Err(format!("{endpoint}: HTTP {status}: {}", body.trim()));
+        }
+        let response: Value = response
+            .json()
+            .await
+            .map_err(|e| format!("{endpoint}: {e}"))?;
+        match self.provider.translations(&response) {
+            Some(translations) if translations.len() == texts.len() => Ok(translations),
+            _ => Err(format!("{endpoint}: unexpected response {response}")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{Json, Router, routing::post};
+
+    #[test]
+    fn requests_and_responses_follow_each_api() {
+        assert_eq!(
+            Provider::Deepl.body(&["Hello"], Some("en-GB"), "de", Some("k:fx")),
+            json!({"text": ["Hello"], "target_lang": "DE", "source_lang": "EN"})
+        );
+        assert_eq!(
+            Provider::Google.body(&["Hello"], None, "de", Some("k")),
+            json!({"q": ["Hello"], "target": "de", "format": "text"})
+        );
+        assert_eq!(
+            Provider::Deepl.translations(&json!({"translations": [
+                {"detected_source_language": "EN", "text": "Hallo"}
+            ]})),
+            Some(vec!["Hallo".to_string()])
+        );
+        assert_eq!(
+            Provider::Google.translations(&json!({"data": {"translations": [
+                {"translatedText": "Hallo"}, {"translatedText": "Welt"}
+            ]}})),
+            Some(vec!["Hallo".to_string(), "Welt".to_string()])
+        );
+        assert_eq!(
+            Provider::Deepl.default_url(Some("abc:fx")),
+            Some("https://api-free.deepl.com")
+        );
+        assert!(Translator::new(Provider::Libretranslate, None, None, Duration::ZERO).is_err());
+        assert!(Translator::new(Provider::Deepl, None, None, Duration::ZERO).is_err());
+    }
+
+    #[tokio::test]
+    async fn libretranslate_translates_a_batch() {
+        let app = Router::new().route(
+            "/translate",
+            post(|Json(body): Json<Value>| async move {
+                assert_eq!(body["source"], "auto");
+                assert_eq!(body["target"], "fr");
+                let translated: Vec<String> = body["q"]
+                    .as_array()
+                    .map(|texts| {
+                        texts
+                            .iter()
+                            .map(|text| format!("[fr] {}", text.as_str().unwrap_or_default()))
+                            .collect()
+                    })
+                    .unwrap_or_default();
+                Json(json!({"translatedText": translated}))
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap_or_else(|e| panic!("bind: {e}"));
+        let addr = listener
+            .local_addr()
+            .unwrap_or_else(|e| panic!("local_addr: {e}"));
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let translator = Translator::new(
+            Provider::Libretranslate,
+            Some(&format!("http://{addr}/")),
+            None,
+            Duration::from_secs(5),
+        )
+        .unwrap_or_else(|e| panic!("{e}"));
+        assert_eq!(
+            translator
+                .translate(&["Order shipped", "Thanks"], None, "fr")
+                .await,
+            Ok(vec![
+                "[fr] Order shipped".to_string(),
+                "[fr] Thanks".to_string()
+            ])
+        );
+    }
+}
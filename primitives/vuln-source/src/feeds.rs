@@ -0,0 +1,317 @@
+//! Vulnerability feeds and what is queried from them.
+//!
+//! - OSV (`/v1/query`) lists every vulnerability of a package; it has no
+//!   "modified since" filter, so each poll reads them all.
+//! - GitHub's global advisories (`/advisories`) are read per package, or
+//!   per ecosystem, modified since the last poll.
+//! - NVD's CVE API 2.0 is searched per keyword, modified since the last
+//!   poll. Its date windows are limited to 120 days, and without an API
+//!   key it allows 5 requests per 30 seconds, so requests are spaced.
+
+use crate::vuln::Vuln;
+use clap::{Args, ValueEnum};
+use reqwest::{Client, RequestBuilder};
+use serde_json::{Value, json};
+use std::time::Duration;
+
+/// Longest date window NVD accepts, in seconds.
+pub const NVD_MAX_WINDOW: i64 = 120 * 86_400;
+
+/// Pause between NVD requests without an API key.
+const NVD_PACING: Duration = Duration::from_secs(6);
+
+/// Pages read per query at most.
+const MAX_PAGES: usize = 20;
+
+/// A vulnerability feed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Feed {
+    /// OSV.dev, per `--package`.
+    Osv,
+    /// GitHub Security Advisories, per `--package` and `--ecosystem`.
+    Ghsa,
+    /// NVD, per `--nvd-keyword`.
+    Nvd,
+}
+
+/// Feed endpoints and credentials.
+#[derive(Args, Debug, Clone)]
+pub struct FeedArgs {
+    /// OSV API root.
+    #[arg(
+        long,
+        env = "VULN_SOURCE_OSV_URL",
+        default_value = "https://api.osv.dev"
+    )]
+    pub osv_url: String,
+
+    /// GitHub API root.
+    #[arg(long, env = "GITHUB_API_URL", default_value = "https://api.github.com")]
+    pub github_api_url: String,
+
+    /// GitHub token, for higher rate limits.
+    #[arg(long, env = "GITHUB_TOKEN", hide_env_values = true)]
+    pub github_token: Option<String>,
+
+    /// NVD CVE API root.
+    #[arg(
+        long,
+        env = "VULN_SOURCE_NVD_URL",
+        default_value = "https://services.nvd.nist.gov/rest/json/cves/2.0"
+    )]
+    pub nvd_url: String,
+
+    /// NVD API key, for higher rate limits.
+    #[arg(long, env = "NVD_API_KEY", hide_env_values = true)]
+    pub nvd_api_key: Option<String>,
+
+    /// Per-request timeout in milliseconds.
+    #[arg(short, long, env = "VULN_SOURCE_TIMEOUT", default_value = "30000")]
+    pub timeout: u64,
+}
+
+/// A package ecosystem, by its OSV and GitHub names.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Ecosystem {
+    pub osv: &'static str,
+    pub ghsa: &'static str,
+}
+
+/// Known ecosystems and other names they go by.
+const ECOSYSTEMS: [(&str, &str, &[&str]); 11] = [
+    ("crates.io", "rust", &["crates", "cargo"]),
+    ("npm", "npm", &[]),
+    ("PyPI", "pip", &[]),
+    ("Go", "go", &["golang"]),
+    ("Maven", "maven", &[]),
+    ("NuGet", "nuget", &[]),
+    ("RubyGems", "rubygems", &["gem"]),
+    ("Packagist", "composer", &[]),
+    ("Hex", "erlang", &[]),
+    ("Pub", "pub", &[]),
+    ("SwiftURL", "swift", &[]),
+];
+
+impl Ecosystem {
+    /// An ecosystem by its OSV or GitHub name or an alias, in any case.
+    pub fn parse(name: &str) -> Result<Self, String> {
+        let name = name.trim().to_lowercase();
+        ECOSYSTEMS
+            .iter()
+            .find(|(osv, ghsa, aliases)| {
+                osv.to_lowercase() == name || *ghsa == name || aliases.contains(&name.as_str())
+            })
+            .map(|(osv, ghsa, _)| Self { osv, ghsa })
+            .ok_or_else(|| {
+                let known: Vec<&str> = ECOSYSTEMS.iter().map(|(osv, ..)| *osv).collect();
+                format!("unknown ecosystem {name} ({})", known.join(", "))
+            })
+    }
+}
+
+/// A watched package, written `ecosystem:name` (e.g. `npm:lodash`,
+/// `crates.io:openssl`, `Maven:org.apache.logging.log4j:log4j-core`).
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Package {
+    pub ecosystem: Ecosystem,
+    pub name: String,
+}
+
+impl Package {
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let (ecosystem, name) = spec
+            .split_once(':')
+            .ok_or_else(|| format!("{spec}: expected ecosystem:name"))?;
+        let ecosystem = Ecosystem::parse(ecosystem).map_err(|e| format!("{spec}: {e}"))?;
+        let name = name.trim();
+        if name.is_empty() {
+            return Err(format!("{spec}: missing package name"));
+        }
+        Ok(Self {
+            ecosystem,
+            name: name.to_string(),
+        })
+    }
+}
+
+/// The `rel="next"` URL of a `Link` header.
+fn next_link(link: &str) -> Option<String> {
+    link.split(',').find_map(|part| {
+        let (url, params) = part.split_once(';')?;
+        params.contains("rel=\"next\"").then(|| {
+            url.trim()
+                .trim_start_matches('<')
+                .trim_end_matches('>')
+                .to_string()
+        })
+    })
+}
+
+/// Reads vulnerabilities from the feeds.
+pub struct Feeds {
+    client: Client,
+    args: FeedArgs,
+}
+
+impl Feeds {
+    pub fn new(client: Client, args: &FeedArgs) -> Self {
+        Self {
+            client,
+            args: args.clone(),
+        }
+    }
+
+    async fn send(&self, request: RequestBuilder) -> Result<reqwest::Response, String> {
+        let response = request
+            .header(
+                "User-Agent",
+                concat!("emergent-vuln-source/", env!("CARGO_PKG_VERSION")),
+            )
+            .timeout(Duration::from_millis(self.args.timeout))
+            .send()
+            .await
+            .map_err(|e| e.to_string())?;
+        let status = response.status();
+        if status.is_success() {
+            Ok(response)
+        } else {
+            let url = response.url().clone();
+            Err(format!("{url}: HTTP {status}"))
+        }
+    }
+
+    /// Every vulnerability OSV lists for `package`.
+    pub async fn osv(&self, package: &Package) -> Result<Vec<Vuln>, String> {
+        let url = format!("{}/v1/query", self.args.osv_url.trim_end_matches('/'));
+        let mut query = json!({
+            "package": {"name": package.name, "ecosystem": package.ecosystem.osv},
+        });
+        let mut vulns = Vec::new();
+        for _ in 0..MAX_PAGES {
+            let response = self.send(self.client.post(&url).json(&query)).await?;
+            let body: Value = response.json().await.map_err(|e| e.to_string())?;
+            vulns.extend(
+                body["vulns"]
+                    .as_array()
+                    .into_iter()
+                    .flatten()
+                    .filter_map(Vuln::from_osv),
+            );
+            match body["next_page_token"].as_str() {
+                Some(token) if !token.is_empty() => query["page_token"] = json!(token),
+                _ => break,
+            }
+        }
+        Ok(vulns)
+    }
+
+    /// Reviewed GitHub advisories for `ecosystem`, or only those affecting
+    /// `package` in it, modified at or after `since`.
+    pub async fn ghsa(
+        &self,
+        ecosystem: Ecosystem,
+        package: Option<&str>,
+        since: &str,
+    ) -> Result<Vec<Vuln>, String> {
+        let mut query = vec![
+            ("type", "reviewed".to_string()),
+            ("ecosystem", ecosystem.ghsa.to_string()),
+            ("modified", format!(">={since}")),
+            ("sort", "updated".to_string()),
+            ("direction", "asc".to_string()),
+            ("per_page", "100".to_string()),
+        ];
+        if let Some(package) = package {
+            query.push(("affects", package.to_string()));
+        }
+        let url = format!(
+            "{}/advisories",
+            self.args.github_api_url.trim_end_matches('/')
+        );
+        let mut request = self.client.get(&url).query(&query);
+        let mut vulns = Vec::new();
+        for _ in 0..MAX_PAGES {
+            request = request.header("Accept", "application/vnd.github+json");
+            if let Some(token) = &self.args.github_token {
+                request = request.bearer_auth(token);
+            }
+            let response = self.send(request).await?;
+            let next = response
+                .headers()
+                .get("link")
+                .and_then(|link| link.to_str().ok())
+                .and_then(next_link);
+            let body: Value = response.json().await.map_err(|e| e.to_string())?;
+            vulns.extend(
+                body.as_array()
+                    .into_iter()
+                    .flatten()
+                    .filter_map(Vuln::from_ghsa),
+            );
+            match next {
+                Some(next) => request = self.client.get(next),
+                None => break,
+            }
+        }
+        Ok(vulns)
+    }
+
+    /// CVEs matching `keyword` last modified between `since` and `until`
+    /// (Unix seconds, at most [`NVD_MAX_WINDOW`] apart).
+    pub async fn nvd(&self, keyword: &str, since: i64, until: i64) -> Result<Vec<Vuln>, String> {
+        let nvd_time = |secs| primitive_common::time::format(secs).replace('Z', ".000+00:00");
+        let mut vulns = Vec::new();
+        let mut start = 0;
+        for _ in 0..MAX_PAGES {
+            let mut request = self.client.get(&self.args.nvd_url).query(&[
+                ("keywordSearch", keyword.to_string()),
+                ("lastModStartDate", nvd_time(since)),
+                ("lastModEndDate", nvd_time(until)),
+                ("startIndex", start.to_string()),
+            ]);
+            match &self.args.nvd_api_key {
+                Some(key) => request = request.header("apiKey", key),
+                None => tokio::time::sleep(NVD_PACING).await,
+            }
+            let response = self.send(request).await?;
+            let body: Value = response.json().await.map_err(|e| e.to_string())?;
+            let page = body["vulnerabilities"].as_array().map_or(0, Vec::len);
+            vulns.extend(
+                body["vulnerabilities"]
+                    .as_array()
+                    .into_iter()
+                    .flatten()
+                    .filter_map(|v| Vuln::from_nvd(&v["cve"])),
+            );
+            start += page as u64;
+            if page == 0 || start >= body["totalResults"].as_u64().unwrap_or(0) {
+                break;
+            }
+        }
+        Ok(vulns)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn packages_name_an_ecosystem() {
+        let log4j = Package::parse("maven:org.apache.logging.log4j:log4j-core")
+            .unwrap_or_else(|e| panic!("{e}"));
+        assert_eq!(log4j.ecosystem.osv, "Maven");
+        assert_eq!(log4j.name, "org.apache.logging.log4j:log4j-core");
+        let serde = Package::parse("cargo:serde").unwrap_or_else(|e| panic!("{e}"));
+        assert_eq!(serde.ecosystem.ghsa, "rust");
+        assert!(Package::parse("cobol:payroll").is_err());
+        assert!(Package::parse("npm").is_err());
+
+        let link = "<https://api.github.com/advisories?after=Y3Vy>; rel=\"next\", \
+                    <https://api.github.com/advisories?before=Y3Vy>; rel=\"prev\"";
+        assert_eq!(
+            next_link(link).as_deref(),
+            Some("https://api.github.com/advisories?after=Y3Vy")
+        );
+    }
+}
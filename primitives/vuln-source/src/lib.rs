@@ -0,0 +1,593 @@
+//! Vuln Source - Vulnerability Feed Events
+//!
+//! A Source that polls vulnerability feeds and publishes `vuln.published`
+//! for vulnerabilities it has not seen and `vuln.updated` when one changes
+//! (including being withdrawn), carrying the id, aliases, summary,
+//! severity, CVSS score and vector, affected ranges and references (see
+//! [`vuln`]). What is polled (see [`feeds`]):
+//!
+//! - OSV.dev, for each `--package`.
+//! - GitHub Security Advisories, for each `--package` and `--ecosystem`.
+//! - NVD, for each `--nvd-keyword`.
+//!
+//! Each query keeps a cursor, the time it last succeeded, and GitHub and
+//! NVD are asked only for what was modified since. A query's first poll
+//! publishes nothing: OSV results are recorded as seen, and GitHub and NVD
+//! start from that moment. A vulnerability is published from the first
+//! feed that reports it and ignored by the others, matched by id and
+//! aliases (see [`watch`]). With `--state-file` cursors and seen
+//! vulnerabilities survive restarts.
+//!
+//! `--emit-type-template` can also use `{feed}`.
+//!
+//! Sources are SILENT - they only produce domain messages.
+//! All lifecycle events are published by the engine.
+//!
+//! # Usage
+//!
+//! ```bash
+//! vuln-source --package npm:lodash --package crates.io:openssl \
+//!   --ecosystem rust --nvd-keyword log4j --nvd-api-key $NVD_API_KEY \
+//!   --state-file /var/lib/emergent/vulns.json
+//! ```
+//!
+//! On SIGTERM a poll in progress gets `--drain-timeout` milliseconds to
+//! finish.
+
+pub mod feeds;
+pub mod vuln;
+pub mod watch;
+
+use clap::Parser;
+use emergent_client::EmergentSource;
+use feeds::{Ecosystem, Feed, FeedArgs, Feeds, NVD_MAX_WINDOW, Package};
+use primitive_common::capabilities::{self, CapabilityArgs, Role, VERSION, capabilities_message};
+use primitive_common::doctor::{Report, check_writable_dir};
+use primitive_common::source::{Outlet, SourceArgs};
+use primitive_common::time;
+use reqwest::Client;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::signal::unix::{SignalKind, signal};
+use vuln::Vuln;
+use watch::{EVENT_TYPES, State};
+
+/// Vulnerability feed watcher that emits vuln.* events.
+#[derive(Parser, Debug, Clone)]
+#[command(name = "vuln-source", version = VERSION)]
+#[command(about = "Polls OSV, GitHub advisories and NVD and emits vulnerability events")]
+struct Args {
+    /// Package to watch as ecosystem:name, e.g. npm:lodash (repeatable or
+    /// comma-separated).
+    #[arg(
+        long = "package",
+        env = "VULN_SOURCE_PACKAGES",
+        value_delimiter = ',',
+        value_parser = Package::parse
+    )]
+    packages: Vec<Package>,
+
+    /// Ecosystem to watch every GitHub advisory of, e.g. rust (repeatable
+    /// or comma-separated).
+    #[arg(
+        long = "ecosystem",
+        env = "VULN_SOURCE_ECOSYSTEMS",
+        value_delimiter = ',',
+        value_parser = Ecosystem::parse
+    )]
+    ecosystems: Vec<Ecosystem>,
+
+    /// Keyword to search NVD for (repeatable or comma-separated).
+    #[arg(
+        long = "nvd-keyword",
+        env = "VULN_SOURCE_NVD_KEYWORDS",
+        value_delimiter = ','
+    )]
+    nvd_keywords: Vec<String>,
+
+    /// Feeds to poll.
+    #[arg(
+        long = "feed",
+        env = "VULN_SOURCE_FEEDS",
+        value_enum,
+        value_delimiter = ',',
+        default_value = "osv,ghsa,nvd"
+    )]
+    feeds: Vec<Feed>,
+
+    /// Milliseconds between polls.
+    #[arg(short, long, env = "VULN_SOURCE_INTERVAL", default_value = "3600000")]
+    interval: u64,
+
+    /// File to keep cursors and seen vulnerabilities in across restarts.
+    #[arg(long, env = "VULN_SOURCE_STATE_FILE")]
+    state_file: Option<PathBuf>,
+
+    #[command(flatten)]
+    endpoints: FeedArgs,
+
+    /// Verify external dependencies and exit without connecting to the engine.
+    #[arg(long)]
+    self_test: bool,
+
+    #[command(flatten)]
+    source: SourceArgs,
+
+    #[command(flatten)]
+    capabilities: CapabilityArgs,
+}
+
+/// Variables available to `--emit-type-template`.
+const TEMPLATE_VARIABLES: &[&str] = &["source", "feed"];
+
+/// One query against one feed.
+#[derive(Debug, Clone, PartialEq)]
+enum Query {
+    Osv(Package),
+    Ghsa(Ecosystem, Option<String>),
+    Nvd(String),
+}
+
+impl Query {
+    /// The key its cursor is kept under.
+    fn key(&self) -> String {
+        match self {
+            Self::Osv(package) => format!("osv:{}:{}", package.ecosystem.osv, package.name),
+            Self::Ghsa(ecosystem, None) => format!("ghsa:{}", ecosystem.ghsa),
+            Self::Ghsa(ecosystem, Some(package)) => format!("ghsa:{}:{package}", ecosystem.ghsa),
+            Self::Nvd(keyword) => format!("nvd:{keyword}"),
+        }
+    }
+
+    /// The feed it runs against, as `--feed` names it.
+    fn feed(&self) -> &'static str {
+        match self {
+            Self::Osv(_) => "osv",
+            Self::Ghsa(..) => "ghsa",
+            Self::Nvd(_) => "nvd",
+        }
+    }
+}
+
+/// The queries `args` call for.
+fn queries(args: &Args) -> Vec<Query> {
+    let mut queries = Vec::new();
+    for feed in &args.feeds {
+        match feed {
+            Feed::Osv => queries.extend(args.packages.iter().cloned().map(Query::Osv)),
+            Feed::Ghsa => {
+                queries.extend(args.ecosystems.iter().map(|e| Query::Ghsa(*e, None)));
+                queries.extend(
+                    args.packages
+                        .iter()
+                        .filter(|p| !args.ecosystems.contains(&p.ecosystem))
+                        .map(|p| Query::Ghsa(p.ecosystem, Some(p.name.clone()))),
+                );
+            }
+            Feed::Nvd => queries.extend(args.nvd_keywords.iter().cloned().map(Query::Nvd)),
+        }
+    }
+    queries
+}
+
+/// The state saved in `path`, if any.
+fn load_state(path: &Path) -> Result<Option<State>, String> {
+    match std::fs::read(path) {
+        Ok(bytes) => serde_json::from_slice(&bytes)
+            .map(Some)
+            .map_err(|e| format!("{}: {e}", path.display())),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(format!("{}: {e}", path.display())),
+    }
+}
+
+/// Save `state` to `path`, replacing it atomically.
+fn save_state(path: &Path, state: &State) -> Result<(), String> {
+    let temp = path.with_extension("tmp");
+    let bytes = serde_json::to_vec(state).map_err(|e| e.to_string())?;
+    std::fs::write(&temp, bytes)
+        .and_then(|()| std::fs::rename(&temp, path))
+        .map_err(|e| format!("{}: {e}", path.display()))
+}
+
+/// Runs `--self-test` checks and exits.
+async fn self_test(args: &Args, name: &str) -> ! {
+    let mut report = Report::new(name);
+    let queries = queries(args);
+    report.check(
+        "queries",
+        if queries.is_empty() {
+            Err("nothing to watch".to_string())
+        } else {
+            Ok(format!("{} queries", queries.len()))
+        },
+    );
+    let feeds = Feeds::new(Client::new(), &args.endpoints);
+    let day_ago = time::format(time::now() - 86_400);
+    for query in &queries {
+        let result = match query {
+            Query::Osv(package) => feeds.osv(package).await,
+            Query::Ghsa(ecosystem, package) => {
+                feeds.ghsa(*ecosystem, package.as_deref(), &day_ago).await
+            }
+            Query::Nvd(keyword) => feeds.nvd(keyword, time::now() - 86_400, time::now()).await,
+        };
+        report.check(
+            &query.key(),
+            result.map(|vulns| format!("{} vulnerabilities", vulns.len())),
+        );
+    }
+    if let Some(path) = &args.state_file {
+        let dir = path.parent().filter(|p| !p.as_os_str().is_empty());
+        report.check(
+            "state_file",
+            check_writable_dir(dir.unwrap_or(Path::new("."))),
+        );
+    }
+    args.source.self_test(&mut report, TEMPLATE_VARIABLES);
+    report.finish()
+}
+
+/// Run `query` once. Its first run only records a starting point; later
+/// runs return the vulnerabilities it found and the time (Unix seconds)
+/// from which changes were asked for, if the feed filters by time.
+async fn fetch(
+    feeds: &Feeds,
+    query: &Query,
+    state: &mut State,
+    started: i64,
+) -> Result<Option<(Vec<Vuln>, Option<i64>)>, String> {
+    let key = query.key();
+    let since = state.cursor(&key).and_then(time::parse);
+    let (query, since) = match (query, since) {
+        (Query::Osv(package), None) => {
+            let vulns = feeds.osv(package).await?;
+            eprintln!(
+                "Baseline recorded for {key}: {} vulnerabilities",
+                vulns.len()
+            );
+            for vuln in &vulns {
+                state.record(vuln);
+            }
+            return Ok(None);
+        }
+        (_, None) => {
+            eprintln!("Watching {key} from now on");
+            return Ok(None);
+        }
+        (query, Some(since)) => (query, since),
+    };
+    Ok(Some(match query {
+        Query::Osv(package) => (feeds.osv(package).await?, None),
+        Query::Ghsa(ecosystem, package) => {
+            let vulns = feeds
+                .ghsa(*ecosystem, package.as_deref(), &time::format(since))
+                .await?;
+            (vulns, Some(since))
+        }
+        Query::Nvd(keyword) => {
+            let since = since.max(started - NVD_MAX_WINDOW);
+            (feeds.nvd(keyword, since, started).await?, Some(since))
+        }
+    }))
+}
+
+/// Run `query` once and publish what it found that is new or changed.
+/// Its cursor only advances if everything was published.
+async fn poll_query(outlet: &Outlet, feeds: &Feeds, query: &Query, state: &mut State) {
+    let key = query.key();
+    let started = time::now();
+    let found = match fetch(feeds, query, state, started).await {
+        Ok(found) => found,
+        Err(e) => {
+            eprintln!("Polling {key} failed: {e}");
+            return;
+        }
+    };
+    if let Some((vulns, since)) = found {
+        let vars = [("feed", query.feed())];
+        for vuln in &vulns {
+            let Some(event_type) = state.event(vuln, since) else {
+                continue;
+            };
+            // The outlet logs and reports what it cannot deliver
+            if outlet
+                .publish(event_type, &vars, vuln.payload())
+                .await
+                .is_err()
+            {
+                return;
+            }
+            state.record(vuln);
+        }
+    }
+    state.set_cursor(&key, time::format(started));
+}
+
+/// Poll until SIGTERM.
+async fn run_poller(
+    args: &Args,
+    outlet: &Outlet,
+    mut state: State,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let feeds = Feeds::new(Client::new(), &args.endpoints);
+    let queries = queries(args);
+    let mut sigterm = signal(SignalKind::terminate())?;
+    let mut interval = tokio::time::interval(Duration::from_millis(args.interval));
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+    loop {
+        tokio::select! {
+            _ = sigterm.recv() => break,
+
+            _ = interval.tick() => {
+                let poll = async {
+                    for query in &queries {
+                        poll_query(outlet, &feeds, query, &mut state).await;
+                        if let Some(path) = &args.state_file
+                            && let Err(e) = save_state(path, &state)
+                        {
+                            eprintln!("Failed to save state: {e}");
+                        }
+                    }
+                };
+                tokio::pin!(poll);
+                tokio::select! {
+                    () = &mut poll => {}
+                    _ = sigterm.recv() => {
+                        args.source.drain.drain("poll in progress", &mut poll).await;
+                        break;
+                    }
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Run the primitive with `args` as its command line (the first item is
+/// the program name, as with `std::env::args_os`).
+pub async fn run<I, T>(args: I) -> Result<(), Box<dyn std::error::Error>>
+where
+    I: IntoIterator<Item = T>,
+    T: Into<std::ffi::OsString> + Clone,
+{
+    let args = Args::parse_from(args);
+
+    // Get the source name from environment (set by engine) or use default
+    let name = std::env::var("EMERGENT_NAME").unwrap_or_else(|_| "vuln-source".to_string());
+
+    if args.self_test {
+        self_test(&args, &name).await;
+    }
+    if queries(&args).is_empty() {
+        eprintln!("Error: nothing to watch; give --package, --ecosystem or --nvd-keyword");
+        std::process::exit(1);
+    }
+    if let Err(e) = args.source.validate(TEMPLATE_VARIABLES) {
+        eprintln!("Error: {e}");
+        std::process::exit(1);
+    }
+    let state = match args.state_file.as_deref().map(load_state).transpose() {
+        Ok(state) => state.flatten().unwrap_or_default(),
+        Err(e) => {
+            eprintln!("Error: cannot read state file {e}");
+            std::process::exit(1);
+        }
+    };
+
+    let produces = args.source.produces(&EVENT_TYPES);
+    let produces: Vec<&str> = produces.iter().map(String::as_str).collect();
+    let descriptor = args
+        .capabilities
+        .describe(&name, Role::Source, &[], &produces, &args);
+    if args.capabilities.capabilities {
+        capabilities::print(&descriptor);
+    }
+
+    // Connect to the Emergent engine (silently - lifecycle events come from engine)
+    let source = match EmergentSource::connect(&name).await {
+        Ok(s) => s,
+        Err(e) => {
+            eprintln!("Failed to connect to Emergent engine: {e}");
+            std::process::exit(1);
+        }
+    };
+    let outlet = Arc::new(Outlet::new(source, &name, &args.source)?);
+    if args.capabilities.announce {
+        outlet.announce(capabilities_message(&descriptor)).await;
+    }
+    tokio::spawn(Arc::clone(&outlet).drain_every(args.source.spool.retry_interval()));
+
+    run_poller(&args, &outlet, state).await?;
+    outlet.disconnect().await;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{Json, Router, routing::post};
+    use emergent_testkit::{MockEngine, fixtures};
+    use serde_json::json;
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    /// Serve a fake OSV listing GHSA-1 and, once `disclosed` is set, GHSA-2.
+    async fn server(disclosed: Arc<AtomicBool>) -> String {
+        let app = Router::new().route(
+            "/v1/query",
+            post(move || {
+                let disclosed = Arc::clone(&disclosed);
+                async move {
+                    let mut vulns =
+                        vec![json!({"id": "GHSA-1", "modified": "2024-01-01T00:00:00Z"})];
+                    if disclosed.load(Ordering::SeqCst) {
+                        vulns.push(json!({
+                            "id": "GHSA-2", "aliases": ["CVE-2024-2"],
+                            "modified": "2024-02-01T00:00:00Z",
+                            "database_specific": {"severity": "HIGH"},
+                        }));
+                    }
+                    Json(json!({"vulns": vulns}))
+                }
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap_or_else(|e| panic!("bind: {e}"));
+        let addr = listener
+            .local_addr()
+            .unwrap_or_else(|e| panic!("local_addr: {e}"));
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        format!("http://{addr}")
+    }
+
+    #[tokio::test]
+    async fn only_vulnerabilities_after_the_baseline_are_found() {
+        let disclosed = Arc::new(AtomicBool::new(false));
+        let base = server(Arc::clone(&disclosed)).await;
+        let args = Args::parse_from([
+            "vuln-source",
+            "--package",
+            "npm:lodash",
+            "--feed",
+            "osv",
+            "--osv-url",
+            &base,
+        ]);
+        let feeds = Feeds::new(Client::new(), &args.endpoints);
+        let queries = queries(&args);
+        assert_eq!(queries.len(), 1);
+        let mut state = State::default();
+
+        let first = fetch(&feeds, &queries[0], &mut state, time::now()).await;
+        assert_eq!(first, Ok(None));
+        state.set_cursor(&queries[0].key(), time::format(time::now()));
+        disclosed.store(true, Ordering::SeqCst);
+        let (vulns, since) = fetch(&feeds, &queries[0], &mut state, time::now())
+            .await
+            .unwrap_or_else(|e| panic!("{e}"))
+            .unwrap_or_else(|| panic!("no results"));
+        let events: Vec<_> = vulns
+            .iter()
+            .filter_map(|v| Some((state.event(v, since)?, v.id.as_str())))
+            .collect();
+        assert_eq!(events, [("vuln.published", "GHSA-2")]);
+    }
+
+    /// Connect to the engine on `socket` as the source would.
+    async fn connect(socket: &Path, args: &Args) -> Outlet {
+        let source = EmergentSource::connect_to("vuln-source", socket)
+            .await
+            .unwrap_or_else(|e| panic!("connect: {e}"));
+        Outlet::new(source, "vuln-source", &args.source)
+            .unwrap_or_else(|e| panic!("open spool: {e}"))
+    }
+
+    /// Args watching lodash on the fake OSV at `base`, plus `extra`.
+    fn osv_args(base: &str, extra: &[&str]) -> Args {
+        let mut argv = vec![
+            "vuln-source",
+            "--package",
+            "npm:lodash",
+            "--feed",
+            "osv",
+            "--osv-url",
+            base,
+        ];
+        argv.extend_from_slice(extra);
+        Args::parse_from(argv)
+    }
+
+    /// Poll for a baseline, shut the engine on `socket` down, disclose
+    /// GHSA-2 and poll again.
+    async fn poll_disclosure_while_down(
+        socket: &Path,
+        args: &Args,
+        disclosed: &AtomicBool,
+    ) -> State {
+        let feeds = Feeds::new(Client::new(), &args.endpoints);
+        let query = &queries(args)[0];
+        let mut state = State::default();
+        let mut engine = MockEngine::serve(socket);
+        let outlet = connect(socket, args).await;
+        poll_query(&outlet, &feeds, query, &mut state).await;
+        engine.shut_down().await;
+        disclosed.store(true, Ordering::SeqCst);
+        poll_query(&outlet, &feeds, query, &mut state).await;
+        state
+    }
+
+    #[tokio::test]
+    async fn disclosures_survive_the_engine_being_down() {
+        let dir = fixtures::TempDir::new("vuln-source-spool");
+        let socket = dir.path().join("engine.sock");
+        let spool = dir.path().join("spool");
+        let disclosed = Arc::new(AtomicBool::new(false));
+        let base = server(Arc::clone(&disclosed)).await;
+        let args = osv_args(
+            &base,
+            &[
+                "--spool-dir",
+                spool.to_str().unwrap_or_default(),
+                "--emit-type-template",
+                "{feed}.{type}",
+            ],
+        );
+
+        poll_disclosure_while_down(&socket, &args, &disclosed).await;
+
+        let mut engine = MockEngine::serve(&socket);
+        connect(&socket, &args).await.drain_spool().await;
+        let published = engine.expect_published("osv.vuln.published").await;
+        assert_eq!(published.payload()["id"], "GHSA-2");
+        engine.shut_down().await;
+    }
+
+    #[tokio::test]
+    async fn unpublished_disclosures_are_found_again() {
+        let dir = fixtures::TempDir::new("vuln-source-down");
+        let socket = dir.path().join("engine.sock");
+        let disclosed = Arc::new(AtomicBool::new(false));
+        let base = server(Arc::clone(&disclosed)).await;
+        let args = osv_args(&base, &[]);
+
+        let mut state = poll_disclosure_while_down(&socket, &args, &disclosed).await;
+        let feeds = Feeds::new(Client::new(), &args.endpoints);
+        let (vulns, since) = fetch(&feeds, &queries(&args)[0], &mut state, time::now())
+            .await
+            .unwrap_or_else(|e| panic!("{e}"))
+            .unwrap_or_else(|| panic!("no results"));
+        let events: Vec<_> = vulns
+            .iter()
+            .filter_map(|v| Some((state.event(v, since)?, v.id.as_str())))
+            .collect();
+        assert_eq!(events, [("vuln.published", "GHSA-2")]);
+    }
+
+    #[test]
+    fn queries_cover_each_feed() {
+        let args = Args::parse_from([
+            "vuln-source",
+            "--package",
+            "npm:lodash,cargo:openssl",
+            "--ecosystem",
+            "rust",
+            "--nvd-keyword",
+            "log4j",
+        ]);
+        let keys: Vec<String> = queries(&args).iter().map(Query::key).collect();
+        assert_eq!(
+            keys,
+            [
+                "osv:npm:lodash",
+                "osv:crates.io:openssl",
+                "ghsa:rust",
+                "ghsa:npm:lodash",
+                "nvd:log4j",
+            ]
+        );
+    }
+}
@@ -0,0 +1,351 @@
+//! Vulnerability records, read from each feed into one shape.
+//!
+//! | Field | OSV | GitHub advisory | NVD |
+//! |-------|-----|-----------------|-----|
+//! | `severity` | `database_specific.severity` | `severity` | CVSS base severity |
+//! | `cvss_score` | — | `cvss.score` | CVSS base score |
+//! | `affected` | `affected[].ranges` | `vulnerabilities[]` | `configurations` CPE matches |
+//!
+//! CVSS v3.1 is preferred, then v4.0, v3.0 and v2. OSV only carries CVSS
+//! vectors, so its records have a vector but no score. NVD's affected
+//! "packages" are CPE products, with `cpe:<vendor>` as the ecosystem.
+
+use serde::Serialize;
+use serde_json::{Value, json};
+
+/// Normalized severity.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Critical,
+    High,
+    Medium,
+    Low,
+    Unknown,
+}
+
+impl Severity {
+    fn parse(severity: Option<&str>) -> Self {
+        match severity.map(str::to_lowercase).as_deref() {
+            Some("critical") => Self::Critical,
+            Some("high") => Self::High,
+            Some("medium" | "moderate") => Self::Medium,
+            Some("low") => Self::Low,
+            _ => Self::Unknown,
+        }
+    }
+}
+
+/// Affected versions of one package.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Affected {
+    pub ecosystem: String,
+    pub package: String,
+    /// Affected version ranges, e.g. `>= 2.0.0, < 2.0.5`.
+    pub ranges: Vec<String>,
+    pub fixed: Vec<String>,
+}
+
+/// A vulnerability as one feed describes it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Vuln {
+    pub id: String,
+    /// The feed: `osv`, `ghsa` or `nvd`.
+    pub source: &'static str,
+    pub aliases: Vec<String>,
+    pub summary: Option<String>,
+    pub severity: Severity,
+    pub cvss_score: Option<f64>,
+    pub cvss_vector: Option<String>,
+    pub published_at: Option<String>,
+    pub modified_at: Option<String>,
+    pub withdrawn_at: Option<String>,
+    pub affected: Vec<Affected>,
+    pub references: Vec<String>,
+    pub url: Option<String>,
+}
+
+fn string(value: &Value) -> Option<String> {
+    value.as_str().map(str::to_string)
+}
+
+fn strings(value: &Value) -> Vec<String> {
+    value
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|v| v.as_str())
+        .map(str::to_string)
+        .collect()
+}
+
+/// A range from its bounds, e.g. `>= 1.0, < 1.2`; `None` with no bounds.
+fn range(bounds: &[(&str, Option<&str>)]) -> Option<String> {
+    let range: Vec<String> = bounds
+        .iter()
+        .filter_map(|(op, version)| Some(format!("{op} {}", (*version)?)))
+        .collect();
+    (!range.is_empty()).then(|| range.join(", "))
+}
+
+impl Vuln {
+    /// Read an OSV record (`/v1/query` result or `/v1/vulns/<id>`).
+    pub fn from_osv(vuln: &Value) -> Option<Self> {
+        let affected = vuln["affected"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .map(|affected| {
+                let mut ranges = Vec::new();
+                let mut fixed = Vec::new();
+                let events = affected["ranges"]
+                    .as_array()
+                    .into_iter()
+                    .flatten()
+                    .flat_map(|r| r["events"].as_array().into_iter().flatten());
+                // Events alternate: introduced, then fixed or last_affected
+                let mut introduced = None;
+                for event in events {
+                    if let Some(version) = event["introduced"].as_str() {
+                        introduced = Some(version).filter(|v| *v != "0");
+                        continue;
+                    }
+                    let end = event["fixed"]
+                        .as_str()
+                        .map(|v| ("<", v))
+                        .or_else(|| event["last_affected"].as_str().map(|v| ("<=", v)));
+                    if let Some((op, version)) = end {
+                        if op == "<" {
+                            fixed.push(version.to_string());
+                        }
+                        ranges.extend(range(&[(">=", introduced.take()), (op, Some(version))]));
+                    }
+                }
+                if let Some(version) = introduced {
+                    ranges.push(format!(">= {version}"));
+                }
+                Affected {
+                    ecosystem: string(&affected["package"]["ecosystem"]).unwrap_or_default(),
+                    package: string(&affected["package"]["name"]).unwrap_or_default(),
+                    ranges,
+                    fixed,
+                }
+            })
+            .collect();
+        let id = string(&vuln["id"])?;
+        Some(Self {
+            url: Some(format!("https://osv.dev/vulnerability/{id}")),
+            id,
+            source: "osv",
+            aliases: strings(&vuln["aliases"]),
+            summary: string(&vuln["summary"]).or_else(|| string(&vuln["details"])),
+            severity: Severity::parse(vuln["database_specific"]["severity"].as_str()),
+            cvss_score: None,
+            cvss_vector: vuln["severity"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .filter_map(|s| s["score"].as_str())
+                .find(|s| s.starts_with("CVSS:3"))
+                .or_else(|| vuln["severity"][0]["score"].as_str())
+                .map(str::to_string),
+            published_at: string(&vuln["published"]),
+            modified_at: string(&vuln["modified"]),
+            withdrawn_at: string(&vuln["withdrawn"]),
+            affected,
+            references: vuln["references"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .filter_map(|r| string(&r["url"]))
+                .collect(),
+        })
+    }
+
+    /// Read a GitHub global security advisory (`/advisories`).
+    pub fn from_ghsa(advisory: &Value) -> Option<Self> {
+        let affected = advisory["vulnerabilities"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .map(|v| Affected {
+                ecosystem: string(&v["package"]["ecosystem"]).unwrap_or_default(),
+                package: string(&v["package"]["name"]).unwrap_or_default(),
+                ranges: string(&v["vulnerable_version_range"]).into_iter().collect(),
+                fixed: string(&v["first_patched_version"]).into_iter().collect(),
+            })
+            .collect();
+        let cvss = [
+            &advisory["cvss_severities"]["cvss_v3"],
+            &advisory["cvss_severities"]["cvss_v4"],
+            &advisory["cvss"],
+        ]
+        .into_iter()
+        .find(|cvss| cvss["vector_string"].is_string());
+        Some(Self {
+            id: string(&advisory["ghsa_id"])?,
+            source: "ghsa",
+            aliases: string(&advisory["cve_id"]).into_iter().collect(),
+            summary: string(&advisory["summary"]),
+            severity: Severity::parse(advisory["severity"].as_str()),
+            cvss_score: cvss.and_then(|cvss| cvss["score"].as_f64()),
+            cvss_vector: cvss.and_then(|cvss| string(&cvss["vector_string"])),
+            published_at: string(&advisory["published_at"]),
+            modified_at: string(&advisory["updated_at"]),
+            withdrawn_at: string(&advisory["withdrawn_at"]),
+            affected,
+            references: strings(&advisory["references"]),
+            url: string(&advisory["html_url"]),
+        })
+    }
+
+    /// Read an NVD CVE (`vulnerabilities[].cve` of the CVE API 2.0).
+    pub fn from_nvd(cve: &Value) -> Option<Self> {
+        let id = string(&cve["id"])?;
+        let metrics = &cve["metrics"];
+        let cvss = [
+            "cvssMetricV31",
+            "cvssMetricV40",
+            "cvssMetricV30",
+            "cvssMetricV2",
+        ]
+        .iter()
+        .find_map(|version| metrics[version].as_array()?.first());
+        let severity = cvss.and_then(|metric| {
+            metric["cvssData"]["baseSeverity"]
+                .as_str()
+                .or_else(|| metric["baseSeverity"].as_str())
+        });
+        let affected = cve["configurations"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .flat_map(|c| c["nodes"].as_array().into_iter().flatten())
+            .flat_map(|n| n["cpeMatch"].as_array().into_iter().flatten())
+            .filter(|m| m["vulnerable"].as_bool() == Some(true))
+            .filter_map(|m| {
+                // cpe:2.3:<part>:<vendor>:<product>:<version>:...
+                let cpe: Vec<&str> = m["criteria"].as_str()?.split(':').collect();
+                let bound = |key: &str| m[key].as_str();
+                let version = cpe.get(5).filter(|v| !["*", "-"].contains(v));
+                let ranges = match version {
+                    Some(version) => Some(format!("= {version}")),
+                    None => range(&[
+                        (">=", bound("versionStartIncluding")),
+                        (">", bound("versionStartExcluding")),
+                        ("<", bound("versionEndExcluding")),
+                        ("<=", bound("versionEndIncluding")),
+                    ]),
+                };
+                Some(Affected {
+                    ecosystem: format!("cpe:{}", cpe.get(3)?),
+                    package: cpe.get(4)?.to_string(),
+                    ranges: ranges.into_iter().collect(),
+                    fixed: bound("versionEndExcluding")
+                        .map(str::to_string)
+                        .into_iter()
+                        .collect(),
+                })
+            })
+            .collect();
+        Some(Self {
+            url: Some(format!("https://nvd.nist.gov/vuln/detail/{id}")),
+            id,
+            source: "nvd",
+            aliases: Vec::new(),
+            summary: cve["descriptions"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .find(|d| d["lang"] == "en")
+                .and_then(|d| string(&d["value"])),
+            severity: Severity::parse(severity),
+            cvss_score: cvss.and_then(|metric| metric["cvssData"]["baseScore"].as_f64()),
+            cvss_vector: cvss.and_then(|metric| string(&metric["cvssData"]["vectorString"])),
+            published_at: string(&cve["published"]),
+            modified_at: string(&cve["lastModified"]),
+            withdrawn_at: (cve["vulnStatus"] == "Rejected")
+                .then(|| string(&cve["lastModified"]))
+                .flatten(),
+            affected,
+            references: cve["references"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .filter_map(|r| string(&r["url"]))
+                .collect(),
+        })
+    }
+
+    /// The event payload.
+    pub fn payload(&self) -> Value {
+        json!({
+            "id": self.id,
+            "source": self.source,
+            "aliases": self.aliases,
+            "summary": self.summary,
+            "severity": self.severity,
+            "cvss_score": self.cvss_score,
+            "cvss_vector": self.cvss_vector,
+            "published_at": self.published_at,
+            "modified_at": self.modified_at,
+            "withdrawn_at": self.withdrawn_at,
+            "affected": self.affected,
+            "references": self.references,
+            "url": self.url,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn feeds_read_into_one_shape() {
+        let osv = json!({
+            "id": "GHSA-67hx-6x53-jw92", "aliases": ["CVE-2023-45133"],
+            "summary": "Arbitrary code execution", "modified": "2024-01-02T00:00:00Z",
+            "database_specific": {"severity": "CRITICAL"},
+            "severity": [{"type": "CVSS_V3", "score": "CVSS:3.1/AV:N/AC:L/PR:N/UI:N/S:U/C:H/I:H/A:H"}],
+            "affected": [{"package": {"ecosystem": "npm", "name": "@babel/traverse"},
+                          "ranges": [{"type": "SEMVER", "events": [
+                              {"introduced": "0"}, {"fixed": "7.23.2"},
+                              {"introduced": "8.0.0-alpha.0"}, {"fixed": "8.0.0-alpha.4"}]}]}],
+            "references": [{"type": "ADVISORY", "url": "https://nvd.nist.gov/vuln/detail/CVE-2023-45133"}],
+        });
+        let vuln = Vuln::from_osv(&osv).unwrap_or_else(|| panic!("no vuln"));
+        assert_eq!(vuln.severity, Severity::Critical);
+        assert_eq!(
+            vuln.affected[0].ranges,
+            ["< 7.23.2", ">= 8.0.0-alpha.0, < 8.0.0-alpha.4"]
+        );
+        assert_eq!(vuln.affected[0].fixed, ["7.23.2", "8.0.0-alpha.4"]);
+
+        let ghsa = json!({
+            "ghsa_id": "GHSA-67hx-6x53-jw92", "cve_id": "CVE-2023-45133", "severity": "moderate",
+            "cvss": {"score": 9.3, "vector_string": "CVSS:3.1/AV:N"},
+            "vulnerabilities": [{"package": {"ecosystem": "npm", "name": "@babel/traverse"},
+                                 "vulnerable_version_range": "< 7.23.2", "first_patched_version": "7.23.2"}],
+        });
+        let vuln = Vuln::from_ghsa(&ghsa).unwrap_or_else(|| panic!("no vuln"));
+        assert_eq!(vuln.severity, Severity::Medium);
+        assert_eq!(vuln.cvss_score, Some(9.3));
+        assert_eq!(vuln.aliases, ["CVE-2023-45133"]);
+
+        let nvd = json!({
+            "id": "CVE-2021-44228", "published": "2021-12-10T10:15:09.143",
+            "descriptions": [{"lang": "en", "value": "Log4Shell"}],
+            "metrics": {"cvssMetricV31": [{"cvssData": {"baseScore": 10.0, "baseSeverity": "CRITICAL",
+                                                         "vectorString": "CVSS:3.1/AV:N"}}]},
+            "configurations": [{"nodes": [{"cpeMatch": [{
+                "vulnerable": true, "criteria": "cpe:2.3:a:apache:log4j:*:*:*:*:*:*:*:*",
+                "versionStartIncluding": "2.0.1", "versionEndExcluding": "2.3.1"}]}]}],
+        });
+        let vuln = Vuln::from_nvd(&nvd).unwrap_or_else(|| panic!("no vuln"));
+        assert_eq!(vuln.payload()["severity"], "critical");
+        assert_eq!(vuln.affected[0].ecosystem, "cpe:apache");
+        assert_eq!(vuln.affected[0].ranges, [">= 2.0.1, < 2.3.1"]);
+        assert_eq!(vuln.summary.as_deref(), Some("Log4Shell"));
+    }
+}
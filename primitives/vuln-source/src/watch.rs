@@ -0,0 +1,137 @@
+//! Which vulnerabilities are new or changed, and the poll cursors.
+//!
+//! [`State`] remembers each vulnerability's `modified_at` and the feed it
+//! was first published from. A vulnerability not seen before is
+//! `vuln.published` (or `vuln.updated` if it was published before the
+//! query's window, so predates the watch); one whose `modified_at` changed
+//! is `vuln.updated`. Feeds overlap - OSV carries GitHub advisories, and
+//! both alias CVEs - so a vulnerability first seen from one feed, under its
+//! id or any alias, is ignored when the others report it.
+
+use crate::vuln::Vuln;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+pub const PUBLISHED_EVENT_TYPE: &str = "vuln.published";
+pub const UPDATED_EVENT_TYPE: &str = "vuln.updated";
+pub const EVENT_TYPES: [&str; 2] = [PUBLISHED_EVENT_TYPE, UPDATED_EVENT_TYPE];
+
+/// A vulnerability as last published.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct Known {
+    source: String,
+    modified_at: Option<String>,
+}
+
+/// Cursors and published vulnerabilities, saved with `--state-file`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct State {
+    /// When each query last succeeded (RFC 3339), by query key.
+    #[serde(default)]
+    cursors: BTreeMap<String, String>,
+    /// Published vulnerabilities by id.
+    #[serde(default)]
+    known: BTreeMap<String, Known>,
+    /// Aliases of published vulnerabilities, to their id.
+    #[serde(default)]
+    aliases: BTreeMap<String, String>,
+}
+
+impl State {
+    /// When the query `key` last succeeded; `None` before its first poll.
+    pub fn cursor(&self, key: &str) -> Option<&str> {
+        self.cursors.get(key).map(String::as_str)
+    }
+
+    pub fn set_cursor(&mut self, key: &str, at: String) {
+        self.cursors.insert(key.to_string(), at);
+    }
+
+    /// The event `vuln` calls for, given that the query covered changes
+    /// from `since` (Unix seconds) on, if it is known.
+    pub fn event(&self, vuln: &Vuln, since: Option<i64>) -> Option<&'static str> {
+        if let Some(known) = self.known.get(&vuln.id) {
+            return (known.source == vuln.source && known.modified_at != vuln.modified_at)
+                .then_some(UPDATED_EVENT_TYPE);
+        }
+        let elsewhere = vuln
+            .aliases
+            .iter()
+            .any(|alias| self.known.contains_key(alias) || self.aliases.contains_key(alias))
+            || self.aliases.contains_key(&vuln.id);
+        if elsewhere {
+            return None;
+        }
+        let published = vuln
+            .published_at
+            .as_deref()
+            .and_then(primitive_common::time::parse);
+        match (since, published) {
+            (Some(since), Some(published)) if published < since => Some(UPDATED_EVENT_TYPE),
+            _ => Some(PUBLISHED_EVENT_TYPE),
+        }
+    }
+
+    /// Record `vuln` as published.
+    pub fn record(&mut self, vuln: &Vuln) {
+        self.known.insert(
+            vuln.id.clone(),
+            Known {
+                source: vuln.source.to_string(),
+                modified_at: vuln.modified_at.clone(),
+            },
+        );
+        for alias in &vuln.aliases {
+            self.aliases
+                .entry(alias.clone())
+                .or_insert_with(|| vuln.id.clone());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vuln::Severity;
+
+    fn vuln(id: &str, source: &'static str, aliases: &[&str], modified: &str) -> Vuln {
+        Vuln {
+            id: id.to_string(),
+            source,
+            aliases: aliases.iter().map(|a| a.to_string()).collect(),
+            summary: None,
+            severity: Severity::Unknown,
+            cvss_score: None,
+            cvss_vector: None,
+            published_at: Some("2024-05-01T00:00:00Z".to_string()),
+            modified_at: Some(modified.to_string()),
+            withdrawn_at: None,
+            affected: Vec::new(),
+            references: Vec::new(),
+            url: None,
+        }
+    }
+
+    #[test]
+    fn vulnerabilities_are_published_once_across_feeds() {
+        let mut state = State::default();
+        let ghsa = vuln("GHSA-1", "osv", &["CVE-2024-1"], "2024-05-02T00:00:00Z");
+        assert_eq!(state.event(&ghsa, None), Some(PUBLISHED_EVENT_TYPE));
+        state.record(&ghsa);
+        assert_eq!(state.event(&ghsa, None), None);
+
+        // The same advisory from GitHub, and its CVE from NVD
+        let same = vuln("GHSA-1", "ghsa", &["CVE-2024-1"], "2024-05-02T00:00:01Z");
+        assert_eq!(state.event(&same, None), None);
+        let cve = vuln("CVE-2024-1", "nvd", &[], "2024-05-03T00:00:00Z");
+        assert_eq!(state.event(&cve, None), None);
+
+        let changed = vuln("GHSA-1", "osv", &["CVE-2024-1"], "2024-06-01T00:00:00Z");
+        assert_eq!(state.event(&changed, None), Some(UPDATED_EVENT_TYPE));
+
+        // Published before the watch began
+        let old = vuln("CVE-2019-9", "nvd", &[], "2024-06-01T00:00:00Z");
+        let since = primitive_common::time::parse("2024-05-15T00:00:00Z");
+        assert_eq!(state.event(&old, since), Some(UPDATED_EVENT_TYPE));
+    }
+}
@@ -0,0 +1,252 @@
+//! WebDAV requests: creating directories and uploading.
+//!
+//! The directories of an upload's path are created first (`MKCOL`, which
+//! answers `405` for a directory that exists), and remembered so the next
+//! upload to them skips the requests. A file is then sent with one `PUT`,
+//! replacing any file already at the path.
+//!
+//! With `--chunk-url` (Nextcloud's uploads collection, such as
+//! `https://cloud.example.com/remote.php/dav/uploads/alice`), files larger
+//! than `--chunk-size` use Nextcloud's chunked upload instead: the chunks
+//! are `PUT` into a temporary collection one at a time, so no more than a
+//! chunk is ever held in memory, and then assembled with a `MOVE` to the
+//! destination. A failed chunked upload removes what it sent.
+//!
+//! Failures map onto handler error categories: transport errors, `423
+//! Locked` and `5xx` responses are `request`, timeouts `timeout`, other
+//! non-2xx responses `rejected`.
+
+use crate::upload::{Body, Upload};
+use primitive_common::errors::{ErrorCategory, HandlerError};
+use reqwest::{Client, Method, RequestBuilder, Response, StatusCode};
+use std::collections::HashSet;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, PoisonError};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::io::AsyncReadExt;
+
+/// Percent-encode each segment of a `/`-separated path.
+pub fn encode_path(path: &str) -> String {
+    path.split('/')
+        .map(|segment| {
+            let mut out = String::with_capacity(segment.len());
+            for byte in segment.bytes() {
+                match byte {
+                    b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                        out.push(byte as char);
+                    }
+                    byte => out.push_str(&format!("%{byte:02X}")),
+                }
+            }
+            out
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+fn transport(target: &str, e: &reqwest::Error) -> HandlerError {
+    if e.is_timeout() {
+        HandlerError::new(ErrorCategory::Timeout, format!("{target}: timed out"))
+    } else {
+        HandlerError::new(ErrorCategory::Request, format!("{target}: {e}"))
+    }
+}
+
+/// A WebDAV method reqwest has no constant for.
+fn method(name: &'static str) -> Result<Method, HandlerError> {
+    Method::from_bytes(name.as_bytes())
+        .map_err(|e| HandlerError::new(ErrorCategory::Internal, format!("{name}: {e}")))
+}
+
+/// An error for an unexpected `status`.
+fn failed(target: &str, status: StatusCode) -> HandlerError {
+    let category = if status.is_server_error() || status == StatusCode::LOCKED {
+        ErrorCategory::Request
+    } else {
+        ErrorCategory::Rejected
+    };
+    HandlerError::new(category, format!("{target}: HTTP {status}"))
+}
+
+/// A WebDAV server, under a base URL.
+pub struct Dav {
+    pub client: Client,
+    /// Base URL uploads are relative to, without a trailing `/`.
+    pub url: String,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub timeout: Duration,
+    /// Nextcloud's uploads collection, for chunked uploads.
+    pub chunk_url: Option<String>,
+    pub chunk_size: u64,
+    /// Directories known to exist.
+    pub known: Mutex<HashSet<String>>,
+}
+
+impl Dav {
+    /// The URL of `path` under the base URL.
+    pub fn url(&self, path: &str) -> String {
+        format!("{}/{}", self.url, encode_path(path))
+    }
+
+    fn request(&self, method: Method, url: &str) -> RequestBuilder {
+        let request = self.client.request(method, url).timeout(self.timeout);
+        match &self.username {
+            Some(username) => request.basic_auth(username, self.password.as_deref()),
+            None => request,
+        }
+    }
+
+    async fn send(&self, request: RequestBuilder, target: &str) -> Result<Response, HandlerError> {
+        request.send().await.map_err(|e| transport(target, &e))
+    }
+
+    /// Create the directories `path` is in.
+    pub async fn create_dirs(&self, path: &str) -> Result<(), HandlerError> {
+        let segments: Vec<&str> = path.split('/').collect();
+        for depth in 1..segments.len() {
+            let dir = segments[..depth].join("/");
+            let known = self
+                .known
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .contains(&dir);
+            if known {
+                continue;
+            }
+            let target = format!("MKCOL {dir}/");
+            let url = format!("{}/", self.url(&dir));
+            let request = self.request(method("MKCOL")?, &url);
+            let status = self.send(request, &target).await?.status();
+            // 405: it already exists
+            if !status.is_success() && status != StatusCode::METHOD_NOT_ALLOWED {
+                return Err(failed(&target, status));
+            }
+            self.known
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .insert(dir);
+        }
+        Ok(())
+    }
+
+    /// Upload `upload`, creating its directories first.
+    pub async fn put(&self, upload: &Upload) -> Result<(), HandlerError> {
+        self.create_dirs(&upload.path).await?;
+        let target = format!("PUT {}", upload.path);
+        let bytes = match &upload.body {
+            Body::File(file) => {
+                if let Some(chunk_url) = &self.chunk_url
+                    && upload.size > self.chunk_size
+                {
+                    return self.put_chunked(chunk_url, file, upload).await;
+                }
+                tokio::fs::read(file).await.map_err(|e| {
+                    HandlerError::new(ErrorCategory::Internal, format!("{}: {e}", file.display()))
+                })?
+            }
+            Body::Rendered(bytes) => bytes.clone(),
+        };
+        let request = self
+            .request(Method::PUT, &self.url(&upload.path))
+            .header("Content-Type", upload.content_type)
+            .body(bytes);
+        let status = self.send(request, &target).await?.status();
+        if status.is_success() {
+            Ok(())
+        } else {
+            Err(failed(&target, status))
+        }
+    }
+
+    /// Upload `file` in chunks through the uploads collection.
+    async fn put_chunked(
+        &self,
+        chunk_url: &str,
+        file: &Path,
+        upload: &Upload,
+    ) -> Result<(), HandlerError> {
+        static TRANSFERS: AtomicU64 = AtomicU64::new(0);
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos())
+            .unwrap_or_default();
+        let transfer = format!(
+            "{}/emergent-{nanos}-{}",
+            chunk_url.trim_end_matches('/'),
+            TRANSFERS.fetch_add(1, Ordering::Relaxed)
+        );
+        let destination = self.url(&upload.path);
+        let total = upload.size.to_string();
+        let with_headers = |request: RequestBuilder| {
+            request
+                .header("Destination", &destination)
+                .header("OC-Total-Length", &total)
+        };
+
+        let result = async {
+            let target = format!("MKCOL {transfer}");
+            let request = with_headers(self.request(method("MKCOL")?, &transfer));
+            let status = self.send(request, &target).await?.status();
+            if !status.is_success() {
+                return Err(failed(&target, status));
+            }
+
+            let read_error = |e: std::io::Error| {
+                HandlerError::new(ErrorCategory::Internal, format!("{}: {e}", file.display()))
+            };
+            let mut reader = tokio::fs::File::open(file).await.map_err(read_error)?;
+            // Nextcloud numbers chunks from 1
+            for number in 1.. {
+                let mut chunk = Vec::new();
+                (&mut reader)
+                    .take(self.chunk_size)
+                    .read_to_end(&mut chunk)
+                    .await
+                    .map_err(read_error)?;
+                if chunk.is_empty() {
+                    break;
+                }
+                let url = format!("{transfer}/{number}");
+                let target = format!("PUT {url}");
+                let request = with_headers(self.request(Method::PUT, &url)).body(chunk);
+                let status = self.send(request, &target).await?.status();
+                if !status.is_success() {
+                    return Err(failed(&target, status));
+                }
+            }
+
+            let url = format!("{transfer}/.file");
+            let target = format!("MOVE {url}");
+            let request =
+                with_headers(self.request(method("MOVE")?, &url)).header("Overwrite", "T");
+            let status = self.send(request, &target).await?.status();
+            if status.is_success() {
+                Ok(())
+            } else {
+                Err(failed(&target, status))
+            }
+        }
+        .await;
+
+        if result.is_err() {
+            // Best effort; Nextcloud also expires abandoned uploads
+            let _ = self.request(Method::DELETE, &transfer).send().await;
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn paths_are_encoded_per_segment() {
+        assert_eq!(
+            encode_path("Reports/Q2 2024/über.pdf"),
+            "Reports/Q2%202024/%C3%BCber.pdf"
+        );
+    }
+}
@@ -0,0 +1,543 @@
+//! WebDAV Sink - Upload Files and Payloads to WebDAV and Nextcloud
+//!
+//! A Sink that uploads, for each event, the file its payload names or the
+//! payload itself (see [`upload`]) to a path on a WebDAV server such as
+//! Nextcloud, ownCloud or Apache `mod_dav`: generated reports, exported
+//! data, rendered summaries. Missing directories are created, and large
+//! files can go up in chunks (see [`dav`]). Each upload is announced as
+//! `file.uploaded`.
+//!
+//! With `--share`, a public link to each upload is created on Nextcloud
+//! (see [`share`]) and published as `file.shared`.
+//!
+//! # Examples
+//!
+//! ```bash
+//! webdav-sink -s report.ready \
+//!   --url https://cloud.example.com/remote.php/dav/files/reports/Reports \
+//!   --username reports --password "$NEXTCLOUD_APP_PASSWORD" \
+//!   --root /var/lib/reports --path '{customer}/{date}/{name}' \
+//!   --chunk-url https://cloud.example.com/remote.php/dav/uploads/reports \
+//!   --share --share-expire-days 7
+//! ```
+
+pub mod dav;
+pub mod share;
+pub mod upload;
+
+use clap::Parser;
+use dav::Dav;
+use emergent_client::EmergentMessage;
+use primitive_common::capabilities::VERSION;
+use primitive_common::doctor::Report;
+use primitive_common::errors::{ErrorCategory, HandlerError};
+use primitive_common::time;
+use primitive_common::{Publisher, SinkArgs, SinkConfig, SinkContext, SinkHandler, run_sink};
+use reqwest::Client;
+use serde_json::{Value, json};
+use share::Sharing;
+use std::collections::HashSet;
+use std::path::PathBuf;
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
+use upload::{Body, Template, Uploads, Vars};
+
+pub const UPLOADED_EVENT_TYPE: &str = "file.uploaded";
+pub const SHARED_EVENT_TYPE: &str = "file.shared";
+
+/// WebDAV Sink — upload files and payloads to WebDAV and Nextcloud.
+#[derive(Parser, Debug)]
+#[command(name = "webdav_sink", version = VERSION)]
+#[command(
+    about = "Upload payload-referenced files or rendered payloads to WebDAV or Nextcloud, with share links"
+)]
+struct Args {
+    /// Message types to subscribe to.
+    #[arg(short, long = "subscribe", required = true)]
+    subscribe: Vec<String>,
+
+    /// WebDAV URL uploads are relative to.
+    #[arg(long, env = "WEBDAV_SINK_URL")]
+    url: String,
+
+    /// Username for basic authentication.
+    #[arg(long, env = "WEBDAV_SINK_USERNAME")]
+    username: Option<String>,
+
+    /// Password (a Nextcloud app password) for basic authentication.
+    #[arg(long, env = "WEBDAV_SINK_PASSWORD")]
+    password: Option<String>,
+
+    /// Remote path template, relative to the URL.
+    #[arg(long, env = "WEBDAV_SINK_PATH", default_value = upload::DEFAULT_PATH)]
+    path: String,
+
+    /// Payload field naming a local file to upload.
+    #[arg(long, env = "WEBDAV_SINK_FILE_FIELD", default_value = "file")]
+    file_field: String,
+
+    /// Directory local files must be under; without it, payloads naming
+    /// files are rejected.
+    #[arg(long, env = "WEBDAV_SINK_ROOT")]
+    root: Option<PathBuf>,
+
+    /// Template payloads are rendered through (indented JSON without one).
+    #[arg(long, env = "WEBDAV_SINK_TEMPLATE")]
+    template: Option<PathBuf>,
+
+    /// Nextcloud uploads collection for chunked uploads.
+    #[arg(long, env = "WEBDAV_SINK_CHUNK_URL")]
+    chunk_url: Option<String>,
+
+    /// Upload files larger than this many bytes in chunks of this size.
+    #[arg(long, env = "WEBDAV_SINK_CHUNK_SIZE", default_value = "10485760")]
+    chunk_size: u64,
+
+    /// Create a public Nextcloud share link for each upload.
+    #[arg(long, env = "WEBDAV_SINK_SHARE")]
+    share: bool,
+
+    /// Days until share links expire.
+    #[arg(long, env = "WEBDAV_SINK_SHARE_EXPIRE_DAYS", requires = "share")]
+    share_expire_days: Option<u32>,
+
+    /// Password protecting share links.
+    #[arg(long, env = "WEBDAV_SINK_SHARE_PASSWORD", requires = "share")]
+    share_password: Option<String>,
+
+    /// Timeout for each request in milliseconds.
+    #[arg(long, env = "WEBDAV_SINK_TIMEOUT", default_value = "300000")]
+    timeout: u64,
+
+    #[command(flatten)]
+    sink: SinkArgs,
+}
+
+/// Uploads a file per event.
+struct WebDavSink {
+    uploads: Uploads,
+    dav: Dav,
+    sharing: Option<Sharing>,
+    publisher: OnceLock<Publisher>,
+}
+
+impl WebDavSink {
+    fn publish(
+        &self,
+        message_type: &str,
+        msg: &EmergentMessage,
+        message_id: &str,
+        mut payload: Value,
+    ) {
+        let Some(publisher) = self.publisher.get() else {
+            return;
+        };
+        payload["message_id"] = Value::from(message_id);
+        payload["message_type"] = Value::from(msg.message_type.as_str());
+        let event = EmergentMessage::new(message_type)
+            .with_causation_id(msg.id())
+            .with_payload(payload);
+        if let Err(e) = publisher.publish(event) {
+            eprintln!("Failed to publish {message_type}: {e}");
+        }
+    }
+}
+
+impl SinkHandler for WebDavSink {
+    async fn handle(
+        &self,
+        msg: &EmergentMessage,
+        ctx: &SinkContext<'_>,
+    ) -> Result<(), HandlerError> {
+        let id = ctx.message_id().to_string();
+        let vars = Vars {
+            message_type: msg.message_type.as_str(),
+            id: &id,
+            time: time::now(),
+            payload: ctx.payload(),
+        };
+        let upload = self
+            .uploads
+            .upload(&vars)
+            .map_err(|e| HandlerError::new(ErrorCategory::Rejected, e))?;
+
+        if ctx.is_dry_run() {
+            let source = match &upload.body {
+                Body::File(file) => Value::from(file.display().to_string()),
+                Body::Rendered(_) => Value::Null,
+            };
+            let chunked = self.dav.chunk_url.is_some()
+                && matches!(upload.body, Body::File(_))
+                && upload.size > self.dav.chunk_size;
+            let detail = json!({
+                "path": upload.path,
+                "url": self.dav.url(&upload.path),
+                "file": source,
+                "size": upload.size,
+                "chunked": chunked,
+                "share": self.sharing.is_some(),
+            });
+            ctx.would_have("upload", detail).await;
+            return Ok(());
+        }
+
+        self.dav.put(&upload).await?;
+        let url = self.dav.url(&upload.path);
+        self.publish(
+            UPLOADED_EVENT_TYPE,
+            msg,
+            ctx.message_id(),
+            json!({"path": upload.path, "url": url, "size": upload.size}),
+        );
+
+        if let Some(sharing) = &self.sharing {
+            let share = sharing.share(&upload.path).await?;
+            self.publish(
+                SHARED_EVENT_TYPE,
+                msg,
+                ctx.message_id(),
+                json!({
+                    "path": upload.path,
+                    "url": share.url,
+                    "share_id": share.id,
+                    "expires": share.expires,
+                }),
+            );
+        }
+        Ok(())
+    }
+
+    fn self_test(&self, report: &mut Report) {
+        if let Some(root) = &self.uploads.root {
+            let readable = std::fs::read_dir(root)
+                .map(|_| root.display().to_string())
+                .map_err(|e| format!("{}: {e}", root.display()));
+            report.check("root", readable);
+        }
+    }
+
+    fn publishes(&self) -> &'static [&'static str] {
+        &[UPLOADED_EVENT_TYPE, SHARED_EVENT_TYPE]
+    }
+
+    fn attach(&self, publisher: Publisher) {
+        let _ = self.publisher.set(publisher);
+    }
+}
+
+/// Run the primitive with `args` as its command line (the first item is
+/// the program name, as with `std::env::args_os`).
+pub async fn run<I, T>(args: I) -> Result<(), Box<dyn std::error::Error>>
+where
+    I: IntoIterator<Item = T>,
+    T: Into<std::ffi::OsString> + Clone,
+{
+    let args = Args::parse_from(args);
+
+    let exit = |e: String| -> ! {
+        eprintln!("Error: {e}");
+        std::process::exit(1);
+    };
+    let template = args
+        .template
+        .as_ref()
+        .map(|path| Template::load(path))
+        .transpose()
+        .unwrap_or_else(|e| exit(e));
+    if args.chunk_size == 0 {
+        exit("--chunk-size must be at least 1".to_string());
+    }
+
+    let client = Client::new();
+    let url = args.url.trim_end_matches('/').to_string();
+    let sharing = args.share.then(|| {
+        let (server, folder) = share::split_url(&url).unwrap_or_else(|| {
+            exit("--share needs a Nextcloud URL (.../remote.php/dav/files/USER/...)".to_string())
+        });
+        Sharing {
+            client: client.clone(),
+            server,
+            folder,
+            username: args.username.clone(),
+            password: args.password.clone(),
+            expire_days: args.share_expire_days,
+            share_password: args.share_password.clone(),
+            timeout: Duration::from_millis(args.timeout),
+        }
+    });
+
+    let config = SinkConfig {
+        name: "webdav_sink",
+        subscribe: &args.subscribe,
+        would_have_as: "webdav.would_have",
+        dead_letter_as: "webdav.dead_letter",
+        settings: &args,
+    };
+    let handler = WebDavSink {
+        uploads: Uploads {
+            root: args.root.clone(),
+            file_field: args.file_field.clone(),
+            template,
+            path: args.path.clone(),
+        },
+        dav: Dav {
+            client,
+            url,
+            username: args.username.clone(),
+            password: args.password.clone(),
+            timeout: Duration::from_millis(args.timeout),
+            chunk_url: args.chunk_url.clone(),
+            chunk_size: args.chunk_size,
+            known: Mutex::new(HashSet::new()),
+        },
+        sharing,
+        publisher: OnceLock::new(),
+    };
+
+    run_sink(config, &args.sink, handler).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{Json, Router, extract::Request, http::StatusCode, routing::any};
+    use emergent_testkit::fixtures::TempDir;
+    use emergent_testkit::{SinkFixture, fixtures, spawn_sink};
+    use tokio::sync::mpsc;
+
+    /// A request the fake server received: method, path, `Destination`
+    /// header and body.
+    type Received = (String, String, Option<String>, Vec<u8>);
+
+    /// Serve a fake Nextcloud on a random port, forwarding each request.
+    /// `MKCOL` of `existing/` answers that it exists.
+    async fn server() -> (String, mpsc::UnboundedReceiver<Received>) {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let app = Router::new().fallback(any(move |request: Request| {
+            let tx = tx.clone();
+            async move {
+                let method = request.method().to_string();
+                let path = request.uri().path().to_string();
+                let destination = request
+                    .headers()
+                    .get("destination")
+                    .and_then(|v| v.to_str().ok())
+                    .map(str::to_string);
+                let body = axum::body::to_bytes(request.into_body(), usize::MAX)
+                    .await
+                    .unwrap_or_default()
+                    .to_vec();
+                let status = match (method.as_str(), path.as_str()) {
+                    ("MKCOL", path) if path.ends_with("/existing/") => {
+                        StatusCode::METHOD_NOT_ALLOWED
+                    }
+                    ("MKCOL" | "PUT" | "MOVE", _) => StatusCode::CREATED,
+                    _ => StatusCode::OK,
+                };
+                let _ = tx.send((method, path, destination, body));
+                let share = json!({"ocs": {
+                    "meta": {"status": "ok", "statuscode": 200},
+                    "data": {"id": 17, "url": "https://cloud.example.com/s/AbC"},
+                }});
+                (status, Json(share))
+            }
+        }));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap_or_else(|e| panic!("bind: {e}"));
+        let addr = listener
+            .local_addr()
+            .unwrap_or_else(|e| panic!("local_addr: {e}"));
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        (format!("http://{addr}"), rx)
+    }
+
+    fn handler(server: &str, root: &std::path::Path, path: &str, share: bool) -> WebDavSink {
+        let client = Client::new();
+        let url = format!("{server}/remote.php/dav/files/alice/Reports");
+        WebDavSink {
+            uploads: Uploads {
+                root: Some(root.to_path_buf()),
+                file_field: "file".to_string(),
+                template: None,
+                path: path.to_string(),
+            },
+            sharing: share.then(|| {
+                let (server, folder) =
+                    share::split_url(&url).unwrap_or_else(|| panic!("not a Nextcloud URL"));
+                Sharing {
+                    client: client.clone(),
+                    server,
+                    folder,
+                    username: Some("alice".to_string()),
+                    password: Some("secret".to_string()),
+                    expire_days: Some(7),
+                    share_password: None,
+                    timeout: Duration::from_secs(5),
+                }
+            }),
+            dav: Dav {
+                client,
+                url,
+                username: Some("alice".to_string()),
+                password: Some("secret".to_string()),
+                timeout: Duration::from_secs(5),
+                chunk_url: Some(format!("{server}/remote.php/dav/uploads/alice")),
+                chunk_size: 4,
+                known: Mutex::new(HashSet::new()),
+            },
+            publisher: OnceLock::new(),
+        }
+    }
+
+    async fn next(received: &mut mpsc::UnboundedReceiver<Received>) -> Received {
+        received
+            .recv()
+            .await
+            .unwrap_or_else(|| panic!("no request"))
+    }
+
+    #[tokio::test]
+    async fn payloads_are_uploaded_and_shared() {
+        let (server, mut received) = server().await;
+        let dir = TempDir::new("webdav-sink");
+        let (mut engine, run) = spawn_sink(
+            SinkFixture::new("webdav_sink", "webdav"),
+            SinkArgs::default(),
+            handler(&server, dir.path(), "existing/{customer}/{name}", true),
+        );
+
+        let report = fixtures::message("report.ready", json!({"customer": "acme"}));
+        let id = report.id().to_string();
+        engine.inject_message(report).await;
+        let uploaded = engine.expect_published(UPLOADED_EVENT_TYPE).await;
+        assert_eq!(
+            uploaded.payload()["path"],
+            format!("existing/acme/{id}.json")
+        );
+        let shared = engine.expect_published(SHARED_EVENT_TYPE).await;
+        assert_eq!(shared.payload()["url"], "https://cloud.example.com/s/AbC");
+        assert_eq!(shared.payload()["share_id"], "17");
+        assert!(shared.payload()["expires"].is_string());
+
+        let base = "/remote.php/dav/files/alice/Reports";
+        let (method, path, _, _) = next(&mut received).await;
+        assert_eq!(
+            (method.as_str(), path),
+            ("MKCOL", format!("{base}/existing/"))
+        );
+        let (method, path, _, _) = next(&mut received).await;
+        assert_eq!(
+            (method.as_str(), path),
+            ("MKCOL", format!("{base}/existing/acme/"))
+        );
+        let (method, path, _, body) = next(&mut received).await;
+        assert_eq!(
+            (method.as_str(), path),
+            ("PUT", format!("{base}/existing/acme/{id}.json"))
+        );
+        assert_eq!(body, b"{\n  \"customer\": \"acme\"\n}\n");
+        let (method, path, _, form) = next(&mut received).await;
+        assert_eq!(
+            (method.as_str(), path.as_str()),
+            ("POST", "/ocs/v2.php/apps/files_sharing/api/v1/shares")
+        );
+        let form = String::from_utf8_lossy(&form).into_owned();
+        assert!(
+            form.starts_with(&format!(
+                "path=%2FReports%2Fexisting%2Facme%2F{id}.json&shareType=3&permissions=1&expireDate="
+            )),
+            "{form}"
+        );
+
+        // Known directories are not created again
+        engine
+            .inject_message(fixtures::message(
+                "report.ready",
+                json!({"customer": "acme"}),
+            ))
+            .await;
+        engine.expect_published(UPLOADED_EVENT_TYPE).await;
+        assert_eq!(next(&mut received).await.0, "PUT");
+
+        engine.close();
+        assert_eq!(run.await.ok(), Some(Ok(())));
+    }
+
+    #[tokio::test]
+    async fn large_files_are_uploaded_in_chunks() {
+        let (server, mut received) = server().await;
+        let dir = TempDir::new("webdav-sink-chunks");
+        std::fs::write(dir.path().join("export.csv"), b"a,b\n1,2\n3,4\n")
+            .unwrap_or_else(|e| panic!("{e}"));
+        let (mut engine, run) = spawn_sink(
+            SinkFixture::new("webdav_sink", "webdav"),
+            SinkArgs::default(),
+            handler(&server, dir.path(), "{name}", false),
+        );
+
+        engine
+            .inject_message(fixtures::message(
+                "export.finished",
+                json!({"file": "export.csv"}),
+            ))
+            .await;
+        let uploaded = engine.expect_published(UPLOADED_EVENT_TYPE).await;
+        assert_eq!(uploaded.payload()["size"], 12);
+
+        let destination = format!("{server}/remote.php/dav/files/alice/Reports/export.csv");
+        let (method, transfer, header, _) = next(&mut received).await;
+        assert_eq!(method, "MKCOL");
+        assert!(transfer.starts_with("/remote.php/dav/uploads/alice/emergent-"));
+        assert_eq!(header.as_deref(), Some(destination.as_str()));
+        let mut chunks = Vec::new();
+        for number in 1..=3 {
+            let (method, path, _, body) = next(&mut received).await;
+            assert_eq!(
+                (method.as_str(), path),
+                ("PUT", format!("{transfer}/{number}"))
+            );
+            chunks.extend(body);
+        }
+        assert_eq!(chunks, b"a,b\n1,2\n3,4\n");
+        let (method, path, header, _) = next(&mut received).await;
+        assert_eq!(
+            (method.as_str(), path),
+            ("MOVE", format!("{transfer}/.file"))
+        );
+        assert_eq!(header.as_deref(), Some(destination.as_str()));
+
+        engine.close();
+        assert_eq!(run.await.ok(), Some(Ok(())));
+    }
+
+    #[tokio::test]
+    async fn dry_run_reports_the_upload() {
+        let dir = TempDir::new("webdav-sink-dry");
+        std::fs::write(dir.path().join("big.bin"), [0u8; 10]).unwrap_or_else(|e| panic!("{e}"));
+        let args = SinkArgs {
+            dry_run: true,
+            ..Default::default()
+        };
+        let (mut engine, run) = spawn_sink(
+            SinkFixture::new("webdav_sink", "webdav"),
+            args,
+            handler("http://127.0.0.1:9", dir.path(), "{type}/{name}", true),
+        );
+        engine
+            .inject_message(fixtures::message(
+                "backup.finished",
+                json!({"file": "big.bin"}),
+            ))
+            .await;
+        let report = engine.expect_published("webdav.would_have").await;
+        let detail = &report.payload()["detail"];
+        assert_eq!(detail["path"], "backup.finished/big.bin");
+        assert_eq!(detail["size"], 10);
+        assert_eq!(detail["chunked"], true);
+        assert_eq!(detail["share"], true);
+
+        engine.close();
+        assert_eq!(run.await.ok(), Some(Ok(())));
+    }
+}
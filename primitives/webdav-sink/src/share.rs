@@ -0,0 +1,159 @@
+//! Public share links through Nextcloud's OCS Share API.
+//!
+//! The server and the folder uploads land in are read from the WebDAV URL,
+//! which for this must be a Nextcloud one (`…/remote.php/dav/files/USER/…`
+//! or `…/remote.php/webdav/…`). Links are read-only, optionally expire
+//! after `--share-expire-days` and optionally need `--share-password`.
+
+use crate::dav::encode_path;
+use primitive_common::errors::{ErrorCategory, HandlerError};
+use primitive_common::time;
+use reqwest::Client;
+use serde_json::Value;
+use std::time::Duration;
+
+/// `shareType` of a public link.
+const PUBLIC_LINK: &str = "3";
+
+/// A created share.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Share {
+    pub id: String,
+    pub url: String,
+    pub expires: Option<String>,
+}
+
+/// Creates share links on one Nextcloud server.
+pub struct Sharing {
+    pub client: Client,
+    /// The server's base URL.
+    pub server: String,
+    /// The folder of the WebDAV URL, relative to the user's files.
+    pub folder: String,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub expire_days: Option<u32>,
+    pub share_password: Option<String>,
+    pub timeout: Duration,
+}
+
+/// Split a Nextcloud WebDAV URL into the server and the folder it points
+/// at within the user's files.
+pub fn split_url(url: &str) -> Option<(String, String)> {
+    let url = url.trim_end_matches('/');
+    let (server, path) = url.split_once("/remote.php/")?;
+    let folder = if let Some(rest) = path.strip_prefix("dav/files/") {
+        // Skip the user
+        rest.split_once('/').map(|(_, folder)| folder).unwrap_or("")
+    } else {
+        path.strip_prefix("webdav")?.trim_start_matches('/')
+    };
+    Some((server.to_string(), folder.to_string()))
+}
+
+impl Sharing {
+    /// Share the upload at `path` (relative to the WebDAV URL).
+    pub async fn share(&self, path: &str) -> Result<Share, HandlerError> {
+        let path = match self.folder.as_str() {
+            "" => format!("/{path}"),
+            folder => format!("/{folder}/{path}"),
+        };
+        let url = format!(
+            "{}/ocs/v2.php/apps/files_sharing/api/v1/shares?format=json",
+            self.server
+        );
+        let target = format!("share {}", encode_path(&path));
+        let expire_date = self
+            .expire_days
+            .map(|days| time::date(time::now() + i64::from(days) * 86_400));
+        let mut form = vec![
+            ("path", path.clone()),
+            ("shareType", PUBLIC_LINK.to_string()),
+            ("permissions", "1".to_string()),
+        ];
+        if let Some(date) = &expire_date {
+            form.push(("expireDate", date.clone()));
+        }
+        if let Some(password) = &self.share_password {
+            form.push(("password", password.clone()));
+        }
+
+        let mut request = self
+            .client
+            .post(&url)
+            .timeout(self.timeout)
+            .header("OCS-APIRequest", "true")
+            .form(&form);
+        if let Some(username) = &self.username {
+            request = request.basic_auth(username, self.password.as_deref());
+        }
+        let response = request.send().await.map_err(|e| {
+            let category = if e.is_timeout() {
+                ErrorCategory::Timeout
+            } else {
+                ErrorCategory::Request
+            };
+            HandlerError::new(category, format!("{target}: {e}"))
+        })?;
+        let status = response.status();
+        let body: Value = response.json().await.unwrap_or_default();
+        let ocs = &body["ocs"];
+        if !status.is_success() {
+            let category = if status.is_server_error() {
+                ErrorCategory::Request
+            } else {
+                ErrorCategory::Rejected
+            };
+            let message = ocs["meta"]["message"].as_str().unwrap_or_default();
+            return Err(HandlerError::new(
+                category,
+                format!("{target}: HTTP {status} {message}")
+                    .trim_end()
+                    .to_string(),
+            ));
+        }
+        let link = ocs["data"]["url"].as_str().ok_or_else(|| {
+            HandlerError::new(
+                ErrorCategory::Parse,
+                format!("{target}: no url in response"),
+            )
+        })?;
+        let id = match &ocs["data"]["id"] {
+            Value::String(id) => id.clone(),
+            other => other.to_string(),
+        };
+        Ok(Share {
+            id,
+            url: link.to_string(),
+            expires: expire_date,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nextcloud_urls_name_the_server_and_folder() {
+        assert_eq!(
+            split_url("https://cloud.example.com/remote.php/dav/files/alice/Reports/"),
+            Some((
+                "https://cloud.example.com".to_string(),
+                "Reports".to_string()
+            ))
+        );
+        assert_eq!(
+            split_url("https://example.com/nextcloud/remote.php/dav/files/alice"),
+            Some(("https://example.com/nextcloud".to_string(), String::new()))
+        );
+        assert_eq!(
+            split_url("https://cloud.example.com/remote.php/webdav/Shared/Out"),
+            Some((
+                "https://cloud.example.com".to_string(),
+                "Shared/Out".to_string()
+            ))
+        );
+        assert_eq!(split_url("https://dav.example.com/files"), None);
+    }
+}
@@ -0,0 +1,271 @@
+//! What each event uploads, and where to.
+//!
+//! An event whose payload names a file (in `--file-field`, `file` by
+//! default) uploads that file, which must lie under `--root`; without
+//! `--root`, file references are refused. Any other event uploads its
+//! payload, rendered through `--template` or as indented JSON.
+//!
+//! The remote path comes from `--path`, relative to the WebDAV URL.
+//! `{field}` is replaced with the payload field at that dotted path, and
+//! `{type}`, `{id}`, `{date}` and `{time}` (UTC) are filled in by the
+//! sink, as are `{name}` (the file's name, or `{id}.{ext}` for a rendered
+//! payload) and `{ext}`. Substituted values cannot add directories: `/`
+//! in them becomes `-`, and a path with a `..` segment is refused.
+
+use primitive_common::key;
+use primitive_common::template::substitute;
+use primitive_common::time;
+use serde_json::Value;
+use std::path::{Path, PathBuf};
+
+/// Where uploads go unless `--path` says otherwise.
+pub const DEFAULT_PATH: &str = "{type}/{name}";
+
+/// The bytes to upload.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Body {
+    /// A local file, read when uploaded.
+    File(PathBuf),
+    /// A rendered payload.
+    Rendered(Vec<u8>),
+}
+
+/// One upload, not yet sent.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Upload {
+    /// Relative to the WebDAV URL, with `/` separators.
+    pub path: String,
+    pub body: Body,
+    pub size: u64,
+    pub content_type: &'static str,
+}
+
+/// A template payloads are rendered through.
+#[derive(Debug, Clone)]
+pub struct Template {
+    pub text: String,
+    /// The template file's extension, used for `{ext}`.
+    pub ext: String,
+}
+
+impl Template {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {e}", path.display()))?;
+        let ext = path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .unwrap_or("txt")
+            .to_string();
+        Ok(Self { text, ext })
+    }
+}
+
+/// The event an upload is made for.
+pub struct Vars<'a> {
+    pub message_type: &'a str,
+    pub id: &'a str,
+    /// Seconds since the Unix epoch.
+    pub time: i64,
+    pub payload: &'a Value,
+}
+
+impl Vars<'_> {
+    /// The value of `{name}` in a template.
+    fn get(&self, name: &str) -> String {
+        match name {
+            "type" => self.message_type.to_string(),
+            "id" => self.id.to_string(),
+            "date" => time::date(self.time),
+            "time" => time::format(self.time),
+            field => key::extract(self.payload, field).unwrap_or_default(),
+        }
+    }
+}
+
+fn content_type(ext: &str) -> &'static str {
+    match ext.to_ascii_lowercase().as_str() {
+        "json" => "application/json",
+        "md" | "markdown" => "text/markdown; charset=utf-8",
+        "html" | "htm" => "text/html; charset=utf-8",
+        "txt" | "log" => "text/plain; charset=utf-8",
+        "csv" => "text/csv; charset=utf-8",
+        "xml" => "application/xml",
+        "pdf" => "application/pdf",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "zip" => "application/zip",
+        _ => "application/octet-stream",
+    }
+}
+
+/// How events become uploads.
+#[derive(Debug, Clone)]
+pub struct Uploads {
+    /// Directory payload-referenced files must be under.
+    pub root: Option<PathBuf>,
+    /// Payload field naming a file to upload.
+    pub file_field: String,
+    pub template: Option<Template>,
+    /// Remote path template.
+    pub path: String,
+}
+
+impl Uploads {
+    /// The local file `reference` names, if it is a file under the root.
+    fn local_file(&self, reference: &str) -> Result<PathBuf, String> {
+        let Some(root) = &self.root else {
+            return Err(format!(
+                "payload names a file ({reference}) but no --root is set"
+            ));
+        };
+        let root = root
+            .canonicalize()
+            .map_err(|e| format!("{}: {e}", root.display()))?;
+        let file = root
+            .join(reference)
+            .canonicalize()
+            .map_err(|e| format!("{reference}: {e}"))?;
+        if !file.starts_with(&root) {
+            return Err(format!("{reference} is outside {}", root.display()));
+        }
+        if !file.is_file() {
+            return Err(format!("{reference} is not a file"));
+        }
+        Ok(file)
+    }
+
+    /// The upload for one event.
+    pub fn upload(&self, vars: &Vars) -> Result<Upload, String> {
+        let reference = key::lookup(vars.payload, &self.file_field).and_then(Value::as_str);
+        let (body, name, ext, size) = match reference {
+            Some(reference) => {
+                let file = self.local_file(reference)?;
+                let size = std::fs::metadata(&file)
+                    .map_err(|e| format!("{}: {e}", file.display()))?
+                    .len();
+                let name = file
+                    .file_name()
+                    .map(|name| name.to_string_lossy().into_owned())
+                    .unwrap_or_default();
+                let ext = file
+                    .extension()
+                    .map(|ext| ext.to_string_lossy().into_owned())
+                    .unwrap_or_default();
+                (Body::File(file), name, ext, size)
+            }
+            None => {
+                let (rendered, ext) = match &self.template {
+                    Some(template) => (
+                        substitute(&template.text, |name| match name {
+                            "payload" => {
+                                serde_json::to_string_pretty(vars.payload).unwrap_or_default()
+                            }
+                            name => vars.get(name),
+                        }),
+                        template.ext.clone(),
+                    ),
+                    None => (
+                        serde_json::to_string_pretty(vars.payload).unwrap_or_default() + "\n",
+                        "json".to_string(),
+                    ),
+                };
+                let bytes = rendered.into_bytes();
+                let size = bytes.len() as u64;
+                (
+                    Body::Rendered(bytes),
+                    format!("{}.{ext}", vars.id),
+                    ext,
+                    size,
+                )
+            }
+        };
+
+        let path = substitute(&self.path, |field| {
+            let value = match field {
+                "name" => name.clone(),
+                "ext" => ext.clone(),
+                field => vars.get(field),
+            };
+            value.replace(['/', '\\'], "-")
+        });
+        let segments: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+        if segments.is_empty() || segments.iter().any(|s| *s == "." || *s == "..") {
+            return Err(format!("invalid remote path '{path}'"));
+        }
+        Ok(Upload {
+            path: segments.join("/"),
+            body,
+            size,
+            content_type: content_type(&ext),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use emergent_testkit::fixtures::TempDir;
+    use serde_json::json;
+
+    #[test]
+    fn files_and_payloads_get_remote_paths() {
+        let dir = TempDir::new("webdav-sink-upload");
+        std::fs::create_dir_all(dir.path().join("reports")).unwrap_or_else(|e| panic!("{e}"));
+        std::fs::write(dir.path().join("reports/q2.pdf"), b"%PDF")
+            .unwrap_or_else(|e| panic!("{e}"));
+        let uploads = Uploads {
+            root: Some(dir.path().to_path_buf()),
+            file_field: "file".to_string(),
+            template: None,
+            path: "{type}/{date}/{customer}/{name}".to_string(),
+        };
+        let upload = |uploads: &Uploads, payload: Value| {
+            uploads.upload(&Vars {
+                message_type: "report.ready",
+                id: "msg_1",
+                time: 1_716_221_758,
+                payload: &payload,
+            })
+        };
+
+        let file = upload(
+            &uploads,
+            json!({"file": "reports/q2.pdf", "customer": "acme/eu"}),
+        );
+        assert_eq!(
+            file,
+            Ok(Upload {
+                path: "report.ready/2024-05-20/acme-eu/q2.pdf".to_string(),
+                body: Body::File(
+                    dir.path()
+                        .join("reports/q2.pdf")
+                        .canonicalize()
+                        .unwrap_or_else(|e| panic!("{e}"))
+                ),
+                size: 4,
+                content_type: "application/pdf",
+            })
+        );
+
+        let rendered =
+            upload(&uploads, json!({"customer": "acme"})).unwrap_or_else(|e| panic!("{e}"));
+        assert_eq!(rendered.path, "report.ready/2024-05-20/acme/msg_1.json");
+        assert_eq!(
+            rendered.body,
+            Body::Rendered(b"{\n  \"customer\": \"acme\"\n}\n".to_vec())
+        );
+
+        assert!(upload(&uploads, json!({"file": "../../etc/passwd"})).is_err());
+        assert!(upload(&uploads, json!({"file": "reports"})).is_err());
+        let unrooted = Uploads {
+            root: None,
+            ..uploads.clone()
+        };
+        assert!(upload(&unrooted, json!({"file": "reports/q2.pdf"})).is_err());
+        let escaping = Uploads {
+            path: "{type}/../{name}".to_string(),
+            ..uploads
+        };
+        assert!(upload(&escaping, json!({})).is_err());
+    }
+}
@@ -0,0 +1,67 @@
+//! Per-connection topic filters.
+//!
+//! A filter is a set of message type patterns, where `*` is any run of
+//! characters (`deploy.*`, `*.failed`). An empty filter lets every event
+//! through.
+
+use primitive_common::glob;
+use std::collections::BTreeSet;
+
+/// The message types a connection wants.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Filter(BTreeSet<String>);
+
+impl Filter {
+    /// A filter from a comma-separated list of patterns.
+    pub fn parse(list: &str) -> Self {
+        let mut filter = Self::default();
+        filter.add(list.split(','));
+        filter
+    }
+
+    pub fn add<'a>(&mut self, patterns: impl IntoIterator<Item = &'a str>) {
+        self.0.extend(
+            patterns
+                .into_iter()
+                .map(str::trim)
+                .filter(|p| !p.is_empty())
+                .map(str::to_string),
+        );
+    }
+
+    pub fn remove<'a>(&mut self, patterns: impl IntoIterator<Item = &'a str>) {
+        for pattern in patterns {
+            self.0.remove(pattern.trim());
+        }
+    }
+
+    pub fn matches(&self, message_type: &str) -> bool {
+        self.0.is_empty() || self.0.iter().any(|p| glob::matches(p, message_type))
+    }
+
+    pub fn patterns(&self) -> Vec<&str> {
+        self.0.iter().map(String::as_str).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn filters_match_globs() {
+        let mut filter = Filter::parse("deploy.*, *.failed,,");
+        assert_eq!(filter.patterns(), ["*.failed", "deploy.*"]);
+        assert!(filter.matches("deploy.started"));
+        assert!(filter.matches("backup.failed"));
+        assert!(!filter.matches("alert.fired"));
+
+        filter.add(["alert.fired"]);
+        filter.remove(["deploy.*"]);
+        assert!(filter.matches("alert.fired"));
+        assert!(!filter.matches("deploy.started"));
+
+        assert!(Filter::default().matches("anything"));
+        assert!(Filter::parse("").matches("anything"));
+    }
+}
@@ -0,0 +1,321 @@
+//! WebSocket Broadcast Sink - Live Events for Dashboards
+//!
+//! A Sink that serves a WebSocket endpoint and sends every event it
+//! receives to the connected clients, so a browser dashboard can follow
+//! the bus live instead of polling. Each connection chooses the message
+//! types it wants with globs, when it connects or later on (see
+//! [`server`] for the protocol).
+//!
+//! Events are fire-and-forget: a client that is not connected when an
+//! event arrives never sees it, and one that cannot keep up skips ahead
+//! and is told how many events it missed.
+//!
+//! # Examples
+//!
+//! ```bash
+//! ws-broadcast-sink -s 'deploy.*' -s 'alert.*' --port 8791 \
+//!   --token "$DASHBOARD_TOKEN" --allowed-origin https://dash.example.com
+//! ```
+//!
+//! ```js
+//! const ws = new WebSocket("wss://bus.example.com/ws?types=deploy.*&token=...");
+//! ws.onmessage = (e) => console.log(JSON.parse(e.data));
+//! ```
+
+pub mod filter;
+pub mod server;
+
+use clap::Parser;
+use emergent_client::EmergentMessage;
+use primitive_common::capabilities::VERSION;
+use primitive_common::doctor::Report;
+use primitive_common::errors::HandlerError;
+use primitive_common::{SinkArgs, SinkConfig, SinkContext, SinkHandler, run_sink};
+use serde_json::json;
+use server::{Hub, Settings};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// WebSocket Broadcast Sink — live events for dashboards.
+#[derive(Parser, Debug)]
+#[command(name = "ws_broadcast_sink", version = VERSION)]
+#[command(about = "Fan events out to WebSocket clients for live dashboards")]
+struct Args {
+    /// Message types to subscribe to.
+    #[arg(short, long = "subscribe", required = true)]
+    subscribe: Vec<String>,
+
+    /// Port the WebSocket endpoint listens on.
+    #[arg(short, long, env = "WS_BROADCAST_SINK_PORT", default_value = "8791")]
+    port: u16,
+
+    /// Host the WebSocket endpoint binds to.
+    #[arg(long, env = "WS_BROADCAST_SINK_HOST", default_value = "0.0.0.0")]
+    host: String,
+
+    /// Path of the WebSocket endpoint.
+    #[arg(long, env = "WS_BROADCAST_SINK_PATH", default_value = "/ws")]
+    path: String,
+
+    /// Token clients must pass as `?token=` or a bearer token.
+    #[arg(long, env = "WS_BROADCAST_SINK_TOKEN", hide_env_values = true)]
+    token: Option<String>,
+
+    /// `Origin` allowed to connect (repeatable); any if none is given.
+    #[arg(
+        long = "allowed-origin",
+        env = "WS_BROADCAST_SINK_ALLOWED_ORIGINS",
+        value_delimiter = ','
+    )]
+    allowed_origins: Vec<String>,
+
+    /// Most clients connected at once.
+    #[arg(
+        long,
+        env = "WS_BROADCAST_SINK_MAX_CONNECTIONS",
+        default_value = "1000"
+    )]
+    max_connections: usize,
+
+    /// Events a client may fall behind before it skips ahead.
+    #[arg(long, env = "WS_BROADCAST_SINK_BUFFER", default_value = "1024")]
+    buffer: usize,
+
+    /// Milliseconds between pings that keep idle connections open.
+    #[arg(
+        long,
+        env = "WS_BROADCAST_SINK_PING_INTERVAL",
+        default_value = "30000",
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    ping_interval: u64,
+
+    #[command(flatten)]
+    sink: SinkArgs,
+}
+
+/// Hands each event to the connected clients.
+struct WsBroadcastSink {
+    hub: Arc<Hub>,
+}
+
+impl SinkHandler for WsBroadcastSink {
+    async fn handle(
+        &self,
+        msg: &EmergentMessage,
+        ctx: &SinkContext<'_>,
+    ) -> Result<(), HandlerError> {
+        let message_type = msg.message_type.as_str();
+        let id = ctx.message_id().to_string();
+
+        if ctx.is_dry_run() {
+            let detail = json!({
+                "type": message_type,
+                "id": id,
+                "clients": self.hub.connections(),
+            });
+            ctx.would_have("broadcast", detail).await;
+            return Ok(());
+        }
+
+        self.hub.broadcast(message_type, &id, ctx.payload());
+        Ok(())
+    }
+
+    fn self_test(&self, report: &mut Report) {
+        report.check(
+            "clients",
+            Ok(format!("{} connected", self.hub.connections())),
+        );
+    }
+}
+
+/// Run the primitive with `args` as its command line (the first item is
+/// the program name, as with `std::env::args_os`).
+pub async fn run<I, T>(args: I) -> Result<(), Box<dyn std::error::Error>>
+where
+    I: IntoIterator<Item = T>,
+    T: Into<std::ffi::OsString> + Clone,
+{
+    let args = Args::parse_from(args);
+
+    let hub = Arc::new(Hub::new(Settings {
+        token: args.token.clone(),
+        allowed_origins: args.allowed_origins.clone(),
+        max_connections: args.max_connections,
+        buffer: args.buffer,
+        ping_interval: Duration::from_millis(args.ping_interval),
+    }));
+    let addr = format!("{}:{}", args.host, args.port);
+    let listener = match tokio::net::TcpListener::bind(&addr).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            eprintln!("Error: cannot listen on {addr}: {e}");
+            std::process::exit(1);
+        }
+    };
+    let app = Arc::clone(&hub).router(&args.path);
+    tokio::spawn(async move { axum::serve(listener, app).await });
+    eprintln!("WebSocket endpoint on ws://{addr}{}", args.path);
+
+    let config = SinkConfig {
+        name: "ws_broadcast_sink",
+        subscribe: &args.subscribe,
+        would_have_as: "ws_broadcast.would_have",
+        dead_letter_as: "ws_broadcast.dead_letter",
+        settings: &args,
+    };
+
+    run_sink(config, &args.sink, WsBroadcastSink { hub }).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use emergent_testkit::{SinkFixture, fixtures, spawn_sink};
+    use futures::{SinkExt, StreamExt};
+    use serde_json::Value;
+    use tokio_tungstenite::tungstenite::Message;
+
+    type Client = tokio_tungstenite::WebSocketStream<
+        tokio_tungstenite::MaybeTlsStream<tokio::net::TcpStream>,
+    >;
+
+    async fn serve(hub: Arc<Hub>) -> String {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap_or_else(|e| panic!("bind: {e}"));
+        let addr = listener
+            .local_addr()
+            .unwrap_or_else(|e| panic!("local_addr: {e}"));
+        tokio::spawn(async move { axum::serve(listener, hub.router("/ws")).await });
+        format!("ws://{addr}/ws")
+    }
+
+    async fn receive(client: &mut Client) -> Value {
+        let next = tokio::time::timeout(Duration::from_secs(5), client.next()).await;
+        match next {
+            Ok(Some(Ok(Message::Text(text)))) => {
+                serde_json::from_str(text.as_str()).unwrap_or_else(|e| panic!("{e}"))
+            }
+            other => panic!("expected a text frame, got {other:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn events_reach_clients_whose_filters_match() {
+        let hub = Arc::new(Hub::new(Settings {
+            token: Some("t0k".to_string()),
+            allowed_origins: Vec::new(),
+            max_connections: 2,
+            buffer: 16,
+            ping_interval: Duration::from_secs(30),
+        }));
+        let url = serve(Arc::clone(&hub)).await;
+
+        let denied = tokio_tungstenite::connect_async(url.as_str()).await;
+        assert!(denied.is_err());
+
+        let connect = |query: &'static str| {
+            let url = format!("{url}?token=t0k{query}");
+            async move {
+                let (client, _) = tokio_tungstenite::connect_async(url)
+                    .await
+                    .unwrap_or_else(|e| panic!("connect: {e}"));
+                client
+            }
+        };
+        let mut deploys = connect("&types=deploy.*").await;
+        let mut everything = connect("").await;
+        assert_eq!(
+            receive(&mut deploys).await,
+            json!({"subscribed": ["deploy.*"]})
+        );
+        assert_eq!(receive(&mut everything).await, json!({"subscribed": []}));
+        assert_eq!(hub.connections(), 2);
+        let full = tokio_tungstenite::connect_async(format!("{url}?token=t0k")).await;
+        assert!(full.is_err());
+
+        let (mut engine, run) = spawn_sink(
+            SinkFixture::new("ws_broadcast_sink", "ws_broadcast"),
+            SinkArgs::default(),
+            WsBroadcastSink {
+                hub: Arc::clone(&hub),
+            },
+        );
+        engine
+            .inject_message(fixtures::message("alert.fired", json!({"host": "web1"})))
+            .await;
+        engine
+            .inject_message(fixtures::message(
+                "deploy.started",
+                json!({"service": "billing-api"}),
+            ))
+            .await;
+
+        let alert = receive(&mut everything).await;
+        assert_eq!(alert["type"], "alert.fired");
+        assert_eq!(alert["payload"], json!({"host": "web1"}));
+        assert_eq!(receive(&mut everything).await["type"], "deploy.started");
+        let deploy = receive(&mut deploys).await;
+        assert_eq!(deploy["type"], "deploy.started");
+        assert_eq!(deploy["payload"]["service"], "billing-api");
+
+        // Switch the first client over to alerts
+        let change = json!({"subscribe": ["alert.*"], "unsubscribe": ["deploy.*"]});
+        deploys
+            .send(Message::text(change.to_string()))
+            .await
+            .unwrap_or_else(|e| panic!("send: {e}"));
+        assert_eq!(
+            receive(&mut deploys).await,
+            json!({"subscribed": ["alert.*"]})
+        );
+        engine
+            .inject_message(fixtures::message("deploy.finished", json!({})))
+            .await;
+        engine
+            .inject_message(fixtures::message("alert.resolved", json!({})))
+            .await;
+        assert_eq!(receive(&mut deploys).await["type"], "alert.resolved");
+
+        engine.close();
+        assert_eq!(run.await.ok(), Some(Ok(())));
+    }
+
+    #[tokio::test]
+    async fn dry_run_reports_instead_of_broadcasting() {
+        let hub = Arc::new(Hub::new(Settings {
+            token: None,
+            allowed_origins: vec!["https://dash.example.com".to_string()],
+            max_connections: 10,
+            buffer: 16,
+            ping_interval: Duration::from_secs(30),
+        }));
+        let url = serve(Arc::clone(&hub)).await;
+        // No Origin header, so not an allowed one
+        assert!(
+            tokio_tungstenite::connect_async(url.as_str())
+                .await
+                .is_err()
+        );
+
+        let (mut engine, run) = spawn_sink(
+            SinkFixture::new("ws_broadcast_sink", "ws_broadcast"),
+            SinkArgs {
+                dry_run: true,
+                ..SinkArgs::default()
+            },
+            WsBroadcastSink { hub },
+        );
+        engine
+            .inject_message(fixtures::message("deploy.started", json!({})))
+            .await;
+        let report = engine.expect_published("ws_broadcast.would_have").await;
+        assert_eq!(report.payload()["detail"]["type"], "deploy.started");
+        assert_eq!(report.payload()["detail"]["clients"], 0);
+
+        engine.close();
+        assert_eq!(run.await.ok(), Some(Ok(())));
+    }
+}
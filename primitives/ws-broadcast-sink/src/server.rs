@@ -0,0 +1,246 @@
+//! The WebSocket endpoint and the fan-out behind it.
+//!
+//! Every event is serialised once as `{"type", "id", "payload"}` and sent
+//! to each connection whose [`Filter`] matches its type. A connection picks
+//! its types with `?types=deploy.*,alert.*` when it connects, and changes
+//! them by sending `{"subscribe": [...]}` or `{"unsubscribe": [...]}`; the
+//! server answers each of these, and greets every new connection, with
+//! `{"subscribed": [...]}`. A client that falls too far behind is told how
+//! many events it missed with `{"lagged": n}`.
+//!
+//! With `--token`, clients pass it as `?token=` (browsers cannot set
+//! headers on a WebSocket) or as `Authorization: Bearer <token>`.
+
+use crate::filter::Filter;
+use axum::Router;
+use axum::body::Bytes;
+use axum::extract::ws::{Message, Utf8Bytes, WebSocket, WebSocketUpgrade};
+use axum::extract::{Query, State};
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use serde::Deserialize;
+use serde_json::{Value, json};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+use tokio::sync::broadcast::{self, error::RecvError};
+
+/// One event, serialised once for every connection.
+#[derive(Debug)]
+struct Frame {
+    message_type: String,
+    text: Utf8Bytes,
+}
+
+/// How the endpoint admits and keeps connections.
+#[derive(Debug, Clone)]
+pub struct Settings {
+    pub token: Option<String>,
+    /// `Origin` headers allowed to connect; empty allows any.
+    pub allowed_origins: Vec<String>,
+    pub max_connections: usize,
+    /// Events a connection may fall behind before it skips ahead.
+    pub buffer: usize,
+    pub ping_interval: Duration,
+}
+
+/// The connected clients and the channel events reach them by.
+pub struct Hub {
+    sender: broadcast::Sender<Arc<Frame>>,
+    settings: Settings,
+    connections: AtomicUsize,
+}
+
+/// `?token=&types=` on the upgrade request.
+#[derive(Debug, Default, Deserialize)]
+struct Params {
+    token: Option<String>,
+    types: Option<String>,
+}
+
+/// Counts a connection for as long as it lives.
+struct Slot(Arc<Hub>);
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        self.0.connections.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl Hub {
+    pub fn new(settings: Settings) -> Self {
+        let (sender, _) = broadcast::channel(settings.buffer.max(1));
+        Self {
+            sender,
+            settings,
+            connections: AtomicUsize::new(0),
+        }
+    }
+
+    /// Serve the endpoint at `path`.
+    pub fn router(self: Arc<Self>, path: &str) -> Router {
+        Router::new().route(path, get(upgrade)).with_state(self)
+    }
+
+    /// Clients currently connected.
+    pub fn connections(&self) -> usize {
+        self.connections.load(Ordering::SeqCst)
+    }
+
+    /// Send an event to every interested client; returns how many
+    /// connections it was offered to.
+    pub fn broadcast(&self, message_type: &str, id: &str, payload: &Value) -> usize {
+        let text = json!({ "type": message_type, "id": id, "payload": payload }).to_string();
+        let frame = Arc::new(Frame {
+            message_type: message_type.to_string(),
+            text: text.into(),
+        });
+        // Sending fails only when nobody is connected
+        self.sender.send(frame).unwrap_or(0)
+    }
+
+    fn authorized(&self, headers: &HeaderMap, params: &Params) -> bool {
+        let Some(token) = &self.settings.token else {
+            return true;
+        };
+        let bearer = headers
+            .get("authorization")
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.strip_prefix("Bearer "));
+        params.token.as_deref() == Some(token.as_str()) || bearer == Some(token.as_str())
+    }
+
+    fn origin_allowed(&self, headers: &HeaderMap) -> bool {
+        if self.settings.allowed_origins.is_empty() {
+            return true;
+        }
+        let origin = headers.get("origin").and_then(|v| v.to_str().ok());
+        origin.is_some_and(|o| self.settings.allowed_origins.iter().any(|a| a == o))
+    }
+
+    /// Claim a connection slot, unless all are taken.
+    fn claim(self: &Arc<Self>) -> Option<Slot> {
+        let taken = self.connections.fetch_add(1, Ordering::SeqCst);
+        let slot = Slot(Arc::clone(self));
+        (taken < self.settings.max_connections).then_some(slot)
+    }
+}
+
+async fn upgrade(
+    State(hub): State<Arc<Hub>>,
+    headers: HeaderMap,
+    Query(params): Query<Params>,
+    ws: WebSocketUpgrade,
+) -> Response {
+    if !hub.authorized(&headers, &params) {
+        return (StatusCode::UNAUTHORIZED, "missing or wrong token").into_response();
+    }
+    if !hub.origin_allowed(&headers) {
+        return (StatusCode::FORBIDDEN, "origin not allowed").into_response();
+    }
+    let Some(slot) = hub.claim() else {
+        return (StatusCode::SERVICE_UNAVAILABLE, "too many connections").into_response();
+    };
+    let filter = params
+        .types
+        .as_deref()
+        .map(Filter::parse)
+        .unwrap_or_default();
+    // Subscribe before answering so no event published after the
+    // handshake is missed
+    let events = hub.sender.subscribe();
+    let ping_interval = hub.settings.ping_interval;
+    ws.on_upgrade(move |socket| serve(socket, events, filter, ping_interval, slot))
+}
+
+/// What a client may send to change its filter.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Control {
+    #[serde(default)]
+    subscribe: Vec<String>,
+    #[serde(default)]
+    unsubscribe: Vec<String>,
+}
+
+/// Apply a control message from the client and return the reply.
+fn control(filter: &mut Filter, text: &str) -> Value {
+    match serde_json::from_str::<Control>(text) {
+        Ok(control) => {
+            filter.add(control.subscribe.iter().map(String::as_str));
+            filter.remove(control.unsubscribe.iter().map(String::as_str));
+            subscribed(filter)
+        }
+        Err(e) => {
+            json!({ "error": format!("expected {{\"subscribe\": [...]}} or {{\"unsubscribe\": [...]}}: {e}") })
+        }
+    }
+}
+
+fn subscribed(filter: &Filter) -> Value {
+    json!({ "subscribed": filter.patterns() })
+}
+
+fn text(value: &Value) -> Message {
+    Message::Text(value.to_string().into())
+}
+
+async fn serve(
+    mut socket: WebSocket,
+    mut events: broadcast::Receiver<Arc<Frame>>,
+    mut filter: Filter,
+    ping_interval: Duration,
+    _slot: Slot,
+) {
+    if socket.send(text(&subscribed(&filter))).await.is_err() {
+        return;
+    }
+    let mut ping =
+        tokio::time::interval_at(tokio::time::Instant::now() + ping_interval, ping_interval);
+    loop {
+        let outgoing = tokio::select! {
+            event = events.recv() => match event {
+                Ok(frame) if filter.matches(&frame.message_type) => {
+                    Message::Text(frame.text.clone())
+                }
+                Ok(_) => continue,
+                Err(RecvError::Lagged(missed)) => text(&json!({ "lagged": missed })),
+                Err(RecvError::Closed) => break,
+            },
+            incoming = socket.recv() => match incoming {
+                Some(Ok(Message::Text(message))) => text(&control(&mut filter, message.as_str())),
+                Some(Ok(Message::Close(_)) | Err(_)) | None => break,
+                // Pongs, and pings, which are answered for us
+                Some(Ok(_)) => continue,
+            },
+            _ = ping.tick() => Message::Ping(Bytes::new()),
+        };
+        if socket.send(outgoing).await.is_err() {
+            break;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn control_messages_change_the_filter() {
+        let mut filter = Filter::parse("deploy.*");
+        assert_eq!(
+            control(
+                &mut filter,
+                r#"{"subscribe": ["alert.*"], "unsubscribe": ["deploy.*"]}"#
+            ),
+            json!({ "subscribed": ["alert.*"] })
+        );
+        assert!(filter.matches("alert.fired"));
+        assert!(!filter.matches("deploy.started"));
+
+        let reply = control(&mut filter, r#"{"types": ["x"]}"#);
+        assert!(reply["error"].is_string());
+        assert_eq!(filter.patterns(), ["alert.*"]);
+    }
+}
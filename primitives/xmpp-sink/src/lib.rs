@@ -0,0 +1,590 @@
+//! XMPP Sink - Send Events to Jabber Contacts and Rooms
+//!
+//! A Sink that stays logged in to an XMPP server and sends each payload as
+//! a message to a JID or a multi-user chat room:
+//!
+//! ```json
+//! {"room": "ops@conference.example.org", "text": "deploy of web1 finished"}
+//! ```
+//!
+//! `to` addresses a contact (a `chat` message) and `room` a room (a
+//! `groupchat` message); with neither, the message goes to `--to`, or else
+//! the first `--room`. The body is `text` (or `message`, `body`) when the
+//! payload has one, and otherwise `--template` with `{dotted.path}`
+//! placeholders filled from the payload. Rooms not joined at login with
+//! `--room` are joined on first use.
+//!
+//! The sink shows `--show` and `--status` as its presence, accepts
+//! contacts' subscription requests with `--accept-subscriptions`, answers
+//! the server's pings and sends a whitespace keepalive every
+//! `--keepalive` seconds. The connection is secured with STARTTLS unless
+//! `--tls` says otherwise, and logs in with SASL PLAIN (see [`session`]).
+//! When it drops, the sink reconnects with a doubling delay; messages
+//! arriving meanwhile fail, so the harness retries them.
+//!
+//! # Examples
+//!
+//! ```bash
+//! xmpp-sink -s alert.fired --jid alerts@example.org \
+//!   --password "$XMPP_PASSWORD" --room ops@conference.example.org \
+//!   --template '{severity}: {summary}'
+//! ```
+
+pub mod session;
+pub mod stanza;
+pub mod xml;
+
+use clap::Parser;
+use emergent_client::EmergentMessage;
+use primitive_common::capabilities::VERSION;
+use primitive_common::doctor::Report;
+use primitive_common::errors::{ErrorCategory, HandlerError};
+use primitive_common::template::render;
+use primitive_common::{SinkArgs, SinkConfig, SinkContext, SinkHandler, run_sink};
+use serde::Deserialize;
+use serde_json::{Value, json};
+use session::{Account, Tls, Writer};
+use stanza::{Jid, Show};
+use std::collections::HashSet;
+use std::time::Duration;
+use tokio::sync::{mpsc, oneshot};
+
+/// XMPP Sink — send events to Jabber contacts and rooms.
+#[derive(Parser, Debug)]
+#[command(name = "xmpp_sink", version = VERSION)]
+#[command(about = "Send events as XMPP messages to contacts or MUC rooms")]
+struct Args {
+    /// Message types to subscribe to.
+    #[arg(short, long = "subscribe", required = true)]
+    subscribe: Vec<String>,
+
+    /// Account to log in as (`user@domain[/resource]`).
+    #[arg(short, long, env = "XMPP_SINK_JID")]
+    jid: String,
+
+    /// Account password.
+    #[arg(long, env = "XMPP_SINK_PASSWORD", hide_env_values = true)]
+    password: String,
+
+    /// Server to connect to as `host[:port]` (default: the JID's domain).
+    #[arg(long, env = "XMPP_SINK_SERVER")]
+    server: Option<String>,
+
+    /// How the connection is secured.
+    #[arg(long, env = "XMPP_SINK_TLS", value_enum, default_value = "starttls")]
+    tls: Tls,
+
+    /// JID for payloads that name no recipient.
+    #[arg(short, long, env = "XMPP_SINK_TO")]
+    to: Option<String>,
+
+    /// Rooms to join on login; repeatable.
+    #[arg(short, long = "room", env = "XMPP_SINK_ROOMS", value_delimiter = ',')]
+    rooms: Vec<String>,
+
+    /// Nick in rooms (default: the JID's local part).
+    #[arg(short, long, env = "XMPP_SINK_NICK")]
+    nick: Option<String>,
+
+    /// Message body for payloads without text, with `{dotted.path}`
+    /// placeholders.
+    #[arg(long, env = "XMPP_SINK_TEMPLATE")]
+    template: Option<String>,
+
+    /// Availability shown to contacts.
+    #[arg(long, env = "XMPP_SINK_SHOW", value_enum, default_value = "online")]
+    show: Show,
+
+    /// Status text shown to contacts.
+    #[arg(long, env = "XMPP_SINK_STATUS")]
+    status: Option<String>,
+
+    /// Accept contacts' requests to see the sink's presence.
+    #[arg(long, env = "XMPP_SINK_ACCEPT_SUBSCRIPTIONS")]
+    accept_subscriptions: bool,
+
+    /// Seconds between whitespace keepalives.
+    #[arg(long, env = "XMPP_SINK_KEEPALIVE", default_value = "60")]
+    keepalive: u64,
+
+    /// Milliseconds before the first reconnect; doubles on each failure.
+    #[arg(long, env = "XMPP_SINK_RECONNECT_DELAY", default_value = "1000")]
+    reconnect_delay: u64,
+
+    /// Upper bound for the reconnect delay in milliseconds.
+    #[arg(long, env = "XMPP_SINK_MAX_RECONNECT_DELAY", default_value = "300000")]
+    max_reconnect_delay: u64,
+
+    #[command(flatten)]
+    sink: SinkArgs,
+}
+
+/// What the connection task needs to log in and stay present.
+#[derive(Debug, Clone)]
+struct Settings {
+    account: Account,
+    rooms: Vec<String>,
+    nick: String,
+    show: Show,
+    status: Option<String>,
+    accept_subscriptions: bool,
+    keepalive: Duration,
+    reconnect_delay: u64,
+    max_reconnect_delay: u64,
+}
+
+impl Settings {
+    fn from_args(args: &Args) -> Result<Self, String> {
+        let jid = Jid::parse(&args.jid)?;
+        Ok(Self {
+            nick: args.nick.clone().unwrap_or_else(|| jid.local.clone()),
+            account: Account {
+                jid,
+                password: args.password.clone(),
+                server: args.server.clone(),
+                tls: args.tls,
+            },
+            rooms: args.rooms.clone(),
+            show: args.show,
+            status: args.status.clone(),
+            accept_subscriptions: args.accept_subscriptions,
+            keepalive: Duration::from_secs(args.keepalive.max(1)),
+            reconnect_delay: args.reconnect_delay,
+            max_reconnect_delay: args.max_reconnect_delay,
+        })
+    }
+
+    /// The delay before reconnect number `attempt` (0-based).
+    fn reconnect_delay(&self, attempt: u32) -> Duration {
+        let factor = 1u64.checked_shl(attempt).unwrap_or(u64::MAX);
+        Duration::from_millis(
+            self.reconnect_delay
+                .saturating_mul(factor)
+                .min(self.max_reconnect_delay),
+        )
+    }
+}
+
+/// The addressing fields of a payload.
+#[derive(Debug, Deserialize)]
+struct Outgoing {
+    to: Option<String>,
+    room: Option<String>,
+    #[serde(alias = "message", alias = "body")]
+    text: Option<String>,
+}
+
+/// A message for the connection task to send, and where to report the
+/// outcome.
+struct Request {
+    to: String,
+    groupchat: bool,
+    stanza: String,
+    done: oneshot::Sender<Result<(), String>>,
+}
+
+/// Join `room` under the configured nick.
+async fn join(writer: &Writer, settings: &Settings, room: &str) -> Result<(), String> {
+    let occupant = format!("{room}/{}", settings.nick);
+    writer
+        .send(&stanza::presence(
+            settings.show,
+            settings.status.as_deref(),
+            Some(&occupant),
+            true,
+        ))
+        .await
+}
+
+/// Announce presence and join the configured rooms.
+async fn announce(writer: &Writer, settings: &Settings) -> Result<(), String> {
+    writer
+        .send(&stanza::presence(
+            settings.show,
+            settings.status.as_deref(),
+            None,
+            false,
+        ))
+        .await?;
+    for room in &settings.rooms {
+        join(writer, settings, room).await?;
+    }
+    Ok(())
+}
+
+/// Send `request`, joining its room first if need be.
+async fn send(
+    writer: &Writer,
+    settings: &Settings,
+    joined: &mut HashSet<String>,
+    request: &Request,
+) -> Result<(), String> {
+    let room = request.to.to_lowercase();
+    if request.groupchat && !joined.contains(&room) {
+        join(writer, settings, &request.to).await?;
+        joined.insert(room);
+    }
+    writer.send(&request.stanza).await
+}
+
+/// Keep a session up and send what arrives on `requests` over it.
+async fn connection(settings: Settings, mut requests: mpsc::Receiver<Request>) {
+    let address = settings.account.address();
+    let mut attempt = 0;
+    loop {
+        match session::connect(&settings.account).await {
+            Ok(mut session) => {
+                attempt = 0;
+                eprintln!("Connected to {address} as {}", session.jid);
+                let writer = session.writer();
+                let mut joined: HashSet<String> = settings
+                    .rooms
+                    .iter()
+                    .map(|room| room.to_lowercase())
+                    .collect();
+                // Reading answers the server's pings and notices a dropped session
+                let replies = writer.clone();
+                let accept_subscriptions = settings.accept_subscriptions;
+                let mut reader = tokio::spawn(async move {
+                    loop {
+                        let stanza = match session.next().await {
+                            Ok(stanza) => stanza,
+                            Err(e) => return e,
+                        };
+                        if stanza.name != "presence" {
+                            continue;
+                        }
+                        let from = stanza.attr("from").unwrap_or_default();
+                        match stanza.attr("type") {
+                            Some("subscribe") if accept_subscriptions => {
+                                let reply = stanza::presence_reply("subscribed", from);
+                                if let Err(e) = replies.send(&reply).await {
+                                    return e;
+                                }
+                            }
+                            Some("error") => eprintln!("Presence to {from} was refused"),
+                            _ => {}
+                        }
+                    }
+                });
+                let mut keepalive = tokio::time::interval(settings.keepalive);
+                keepalive.tick().await;
+                if let Err(e) = announce(&writer, &settings).await {
+                    eprintln!("Lost connection to {address}: {e}");
+                    reader.abort();
+                } else {
+                    loop {
+                        tokio::select! {
+                            lost = &mut reader => {
+                                let e = lost.unwrap_or_else(|e| e.to_string());
+                                eprintln!("Lost connection to {address}: {e}");
+                                break;
+                            }
+                            _ = keepalive.tick() => {
+                                if let Err(e) = writer.send(" ").await {
+                                    eprintln!("Lost connection to {address}: {e}");
+                                    reader.abort();
+                                    break;
+                                }
+                            }
+                            request = requests.recv() => {
+                                let Some(request) = request else {
+                                    let _ = writer
+                                        .send("<presence type='unavailable'/></stream:stream>")
+                                        .await;
+                                    reader.abort();
+                                    return;
+                                };
+                                let sent = send(&writer, &settings, &mut joined, &request).await;
+                                let failed = sent.is_err();
+                                let _ = request.done.send(sent);
+                                if failed {
+                                    reader.abort();
+                                    break;
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+            Err(e) => eprintln!("Failed to connect to {address}: {e}"),
+        }
+
+        // Fail what arrives while disconnected, so the harness retries it
+        let delay = tokio::time::sleep(settings.reconnect_delay(attempt));
+        tokio::pin!(delay);
+        attempt = attempt.saturating_add(1);
+        loop {
+            tokio::select! {
+                _ = &mut delay => break,
+                request = requests.recv() => match request {
+                    Some(request) => {
+                        let _ = request.done.send(Err(format!("not connected to {address}")));
+                    }
+                    None => return,
+                },
+            }
+        }
+    }
+}
+
+/// Hands each payload to the connection task.
+struct XmppSink {
+    account: Account,
+    to: Option<String>,
+    rooms: Vec<String>,
+    template: Option<String>,
+    requests: mpsc::Sender<Request>,
+}
+
+impl SinkHandler for XmppSink {
+    async fn handle(
+        &self,
+        _msg: &EmergentMessage,
+        ctx: &SinkContext<'_>,
+    ) -> Result<(), HandlerError> {
+        let payload: Value = ctx.decode()?;
+        let outgoing: Outgoing = ctx.decode()?;
+        let (to, groupchat) = match (outgoing.room, outgoing.to) {
+            (Some(room), _) => (room, true),
+            (None, Some(to)) => (to, false),
+            (None, None) => match (&self.to, self.rooms.first()) {
+                (Some(to), _) => (to.clone(), false),
+                (None, Some(room)) => (room.clone(), true),
+                (None, None) => {
+                    return Err(HandlerError::new(
+                        ErrorCategory::Parse,
+                        "payload names no recipient and there is no --to or --room",
+                    ));
+                }
+            },
+        };
+        if !to.contains('@') || to.contains(char::is_whitespace) {
+            return Err(HandlerError::new(
+                ErrorCategory::Parse,
+                format!("invalid recipient '{to}'"),
+            ));
+        }
+        let body = outgoing
+            .text
+            .or_else(|| {
+                self.template
+                    .as_deref()
+                    .map(|template| render(template, &payload))
+            })
+            .filter(|body| !body.trim().is_empty())
+            .ok_or_else(|| {
+                HandlerError::new(
+                    ErrorCategory::Parse,
+                    "payload has no text and there is no --template",
+                )
+            })?;
+
+        if ctx.is_dry_run() {
+            let detail = json!({
+                "to": to,
+                "type": if groupchat { "groupchat" } else { "chat" },
+                "body": body,
+            });
+            ctx.would_have("send", detail).await;
+            return Ok(());
+        }
+
+        let (done, outcome) = oneshot::channel();
+        let request = Request {
+            stanza: stanza::message(
+                &to,
+                groupchat,
+                &format!("emergent-{}", ctx.message_id()),
+                &body,
+            ),
+            to,
+            groupchat,
+            done,
+        };
+        let stopped = || HandlerError::new(ErrorCategory::Internal, "XMPP connection task stopped");
+        self.requests.send(request).await.map_err(|_| stopped())?;
+        outcome
+            .await
+            .map_err(|_| stopped())?
+            .map_err(|e| HandlerError::new(ErrorCategory::Request, e))
+    }
+
+    fn self_test(&self, report: &mut Report) {
+        self.account.self_test(report);
+    }
+}
+
+/// Run the primitive with `args` as its command line (the first item is
+/// the program name, as with `std::env::args_os`).
+pub async fn run<I, T>(args: I) -> Result<(), Box<dyn std::error::Error>>
+where
+    I: IntoIterator<Item = T>,
+    T: Into<std::ffi::OsString> + Clone,
+{
+    let args = Args::parse_from(args);
+    let settings = Settings::from_args(&args)?;
+
+    let config = SinkConfig {
+        name: "xmpp_sink",
+        subscribe: &args.subscribe,
+        would_have_as: "xmpp.would_have",
+        dead_letter_as: "xmpp.dead_letter",
+        settings: &args,
+    };
+    let (requests, pending) = mpsc::channel(1);
+    if !args.sink.dry_run && !args.sink.self_test {
+        tokio::spawn(connection(settings.clone(), pending));
+    }
+    let handler = XmppSink {
+        account: settings.account,
+        to: args.to.clone(),
+        rooms: args.rooms.clone(),
+        template: args.template.clone(),
+        requests,
+    };
+
+    run_sink(config, &args.sink, handler).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use emergent_testkit::{SinkFixture, fixtures, spawn_sink};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    /// A server that plays its side of a plaintext login and forwards
+    /// everything the client sends after it.
+    async fn server(listener: TcpListener, tx: mpsc::UnboundedSender<String>) {
+        let (mut socket, _) = listener
+            .accept()
+            .await
+            .unwrap_or_else(|e| panic!("accept: {e}"));
+        let header = "<?xml version='1.0'?><stream:stream xmlns='jabber:client' \
+                      xmlns:stream='http://etherx.jabber.org/streams' id='s1' version='1.0'>";
+        let replies = [
+            // Stream opened: offer PLAIN
+            (
+                "<stream:stream",
+                "<stream:features><mechanisms xmlns='urn:ietf:params:xml:ns:xmpp-sasl'>\
+                 <mechanism>PLAIN</mechanism></mechanisms></stream:features>",
+            ),
+            (
+                "</auth>",
+                "<success xmlns='urn:ietf:params:xml:ns:xmpp-sasl'/>",
+            ),
+            (
+                "<stream:stream",
+                "<stream:features><bind xmlns='urn:ietf:params:xml:ns:xmpp-bind'/></stream:features>",
+            ),
+            (
+                "</iq>",
+                "<iq type='result' id='bind'><bind xmlns='urn:ietf:params:xml:ns:xmpp-bind'>\
+                 <jid>alerts@example.org/emergent</jid></bind></iq>",
+            ),
+        ];
+        let mut received = String::new();
+        let mut buf = [0u8; 4096];
+        for (expect, reply) in replies {
+            while !received.contains(expect) {
+                let n = socket
+                    .read(&mut buf)
+                    .await
+                    .unwrap_or_else(|e| panic!("read: {e}"));
+                assert!(n > 0, "client hung up");
+                received.push_str(&String::from_utf8_lossy(&buf[..n]));
+            }
+            let (_, after) = received.split_once(expect).unwrap_or_default();
+            received = after.to_string();
+            if expect == "<stream:stream" {
+                let _ = socket.write_all(header.as_bytes()).await;
+            }
+            let _ = socket.write_all(reply.as_bytes()).await;
+        }
+        let _ = tx.send(received);
+        loop {
+            match socket.read(&mut buf).await {
+                Ok(0) | Err(_) => return,
+                Ok(n) => {
+                    let _ = tx.send(String::from_utf8_lossy(&buf[..n]).into_owned());
+                }
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn payloads_are_sent_to_contacts_and_rooms() {
+        let listener = TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap_or_else(|e| panic!("bind: {e}"));
+        let port = listener
+            .local_addr()
+            .unwrap_or_else(|e| panic!("local_addr: {e}"))
+            .port();
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        tokio::spawn(server(listener, tx));
+
+        let settings = Settings {
+            account: Account {
+                jid: Jid::parse("alerts@example.org").unwrap_or_else(|e| panic!("{e}")),
+                password: "secret".to_string(),
+                server: Some(format!("127.0.0.1:{port}")),
+                tls: Tls::None,
+            },
+            rooms: vec!["ops@conference.example.org".to_string()],
+            nick: "alerts".to_string(),
+            show: Show::Online,
+            status: None,
+            accept_subscriptions: false,
+            keepalive: Duration::from_secs(60),
+            reconnect_delay: 1000,
+            max_reconnect_delay: 60_000,
+        };
+        let (requests, pending) = mpsc::channel(1);
+        tokio::spawn(connection(settings.clone(), pending));
+        let handler = XmppSink {
+            account: settings.account,
+            to: None,
+            rooms: settings.rooms,
+            template: Some("{severity}: {summary}".to_string()),
+            requests,
+        };
+        let (mut engine, run) = spawn_sink(
+            SinkFixture::new("xmpp_sink", "xmpp"),
+            SinkArgs::default(),
+            handler,
+        );
+
+        engine
+            .inject_message(fixtures::message(
+                "alert.fired",
+                json!({"severity": "critical", "summary": "disk <5% on web1"}),
+            ))
+            .await;
+        engine
+            .inject_message(fixtures::message(
+                "alert.fired",
+                json!({"to": "oncall@example.org", "text": "please look at web1"}),
+            ))
+            .await;
+
+        let mut sent = String::new();
+        while !sent.contains("please look at web1") {
+            sent.push_str(&rx.recv().await.unwrap_or_else(|| panic!("server closed")));
+        }
+        let presence = sent.find("<presence>").unwrap_or_else(|| panic!("{sent}"));
+        let join = sent
+            .find("<presence to='ops@conference.example.org/alerts'>")
+            .unwrap_or_else(|| panic!("{sent}"));
+        let alert = sent
+            .find("<message to='ops@conference.example.org' type='groupchat' id='emergent-")
+            .unwrap_or_else(|| panic!("{sent}"));
+        assert!(presence < join && join < alert, "{sent}");
+        assert!(sent.contains("<body>critical: disk &lt;5% on web1</body>"));
+        assert!(sent.contains("<message to='oncall@example.org' type='chat'"));
+
+        engine.close();
+        assert_eq!(run.await.ok(), Some(Ok(())));
+    }
+}
@@ -0,0 +1,307 @@
+//! Connecting and logging in.
+//!
+//! [`connect`] opens the connection (STARTTLS on 5222 by default, direct
+//! TLS on 5223, or plaintext), authenticates with SASL PLAIN and binds a
+//! resource, returning a [`Session`] that answers the server's pings and
+//! queries by itself.
+
+use crate::stanza::{Jid, open_stream};
+use crate::xml::{Element, XmlReader};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use clap::ValueEnum;
+use primitive_common::doctor::Report;
+use quick_xml::escape::escape;
+use serde::{Deserialize, Serialize};
+use std::net::ToSocketAddrs;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt, BufReader, ReadHalf, WriteHalf};
+use tokio::net::TcpStream;
+use tokio::sync::Mutex;
+use tokio::time::timeout;
+use tokio_rustls::TlsConnector;
+use tokio_rustls::rustls::pki_types::ServerName;
+use tokio_rustls::rustls::{ClientConfig, RootCertStore, crypto};
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(30);
+const LOGIN_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// How the connection is secured.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Tls {
+    /// Upgrade a plaintext connection (port 5222).
+    Starttls,
+    /// TLS from the first byte (port 5223).
+    Direct,
+    /// No encryption; the password crosses the network in the clear.
+    None,
+}
+
+/// Who to log in as, and where.
+#[derive(Debug, Clone)]
+pub struct Account {
+    pub jid: Jid,
+    pub password: String,
+    /// `host[:port]` to connect to instead of the JID's domain.
+    pub server: Option<String>,
+    pub tls: Tls,
+}
+
+impl Account {
+    pub fn address(&self) -> String {
+        let port = match self.tls {
+            Tls::Direct => 5223,
+            Tls::Starttls | Tls::None => 5222,
+        };
+        match &self.server {
+            Some(server) if server.contains(':') => server.clone(),
+            Some(server) => format!("{server}:{port}"),
+            None => format!("{}:{port}", self.jid.domain),
+        }
+    }
+
+    /// Add a `--self-test` check that the server accepts connections.
+    pub fn self_test(&self, report: &mut Report) {
+        let address = self.address();
+        let reachable = address
+            .to_socket_addrs()
+            .map_err(|e| format!("{address}: {e}"))
+            .and_then(|mut addrs| addrs.next().ok_or_else(|| format!("{address}: no address")))
+            .and_then(|addr| {
+                std::net::TcpStream::connect_timeout(&addr, CONNECT_TIMEOUT)
+                    .map(|_| format!("{address} accepts connections"))
+                    .map_err(|e| format!("{address}: {e}"))
+            });
+        report.check("server", reachable);
+    }
+}
+
+trait Io: AsyncRead + AsyncWrite + Unpin + Send + Sync {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send + Sync> Io for T {}
+
+type Reader = XmlReader<BufReader<ReadHalf<Box<dyn Io>>>>;
+
+/// Writes to a session; clones share it.
+#[derive(Clone)]
+pub struct Writer {
+    writer: Arc<Mutex<WriteHalf<Box<dyn Io>>>>,
+}
+
+impl Writer {
+    fn new(writer: WriteHalf<Box<dyn Io>>) -> Self {
+        Self {
+            writer: Arc::new(Mutex::new(writer)),
+        }
+    }
+
+    pub async fn send(&self, xml: &str) -> Result<(), String> {
+        let mut writer = self.writer.lock().await;
+        writer
+            .write_all(xml.as_bytes())
+            .await
+            .map_err(|e| format!("write: {e}"))?;
+        writer.flush().await.map_err(|e| format!("write: {e}"))
+    }
+}
+
+/// A logged-in stream.
+pub struct Session {
+    reader: Reader,
+    writer: Writer,
+    /// The full JID the server bound.
+    pub jid: String,
+}
+
+impl Session {
+    pub fn writer(&self) -> Writer {
+        self.writer.clone()
+    }
+
+    /// The next stanza. Pings are answered and other queries refused
+    /// rather than returned; a closed stream is an `Err`.
+    pub async fn next(&mut self) -> Result<Element, String> {
+        loop {
+            let stanza = self.reader.next().await?;
+            if stanza.name == "error" {
+                let condition = stanza.children.first().map_or("", |c| c.name.as_str());
+                return Err(format!("stream error: {condition}"));
+            }
+            let query = stanza.name == "iq" && matches!(stanza.attr("type"), Some("get" | "set"));
+            if !query {
+                return Ok(stanza);
+            }
+            let id = escape(stanza.attr("id").unwrap_or_default()).into_owned();
+            let to = stanza
+                .attr("from")
+                .map(|from| format!(" to='{}'", escape(from)))
+                .unwrap_or_default();
+            let reply = if stanza.child("ping").is_some() {
+                format!("<iq type='result' id='{id}'{to}/>")
+            } else {
+                format!(
+                    "<iq type='error' id='{id}'{to}><error type='cancel'><service-unavailable \
+                     xmlns='urn:ietf:params:xml:ns:xmpp-stanzas'/></error></iq>"
+                )
+            };
+            self.writer.send(&reply).await?;
+        }
+    }
+}
+
+async fn tls(domain: &str, stream: Box<dyn Io>) -> Result<Box<dyn Io>, String> {
+    let roots = RootCertStore {
+        roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+    };
+    let config = ClientConfig::builder_with_provider(Arc::new(crypto::ring::default_provider()))
+        .with_safe_default_protocol_versions()
+        .map_err(|e| e.to_string())?
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    let name = ServerName::try_from(domain.to_string()).map_err(|e| format!("{domain}: {e}"))?;
+    let stream = TlsConnector::from(Arc::new(config))
+        .connect(name, stream)
+        .await
+        .map_err(|e| format!("TLS handshake with {domain}: {e}"))?;
+    Ok(Box::new(stream))
+}
+
+/// Open a stream and return the features the server offers on it.
+async fn features(reader: &mut Reader, writer: &Writer, domain: &str) -> Result<Element, String> {
+    writer.send(&open_stream(domain)).await?;
+    loop {
+        let element = reader.next().await?;
+        match element.name.as_str() {
+            "features" => return Ok(element),
+            "error" => {
+                let condition = element.children.first().map_or("", |c| c.name.as_str());
+                return Err(format!("stream error: {condition}"));
+            }
+            _ => {}
+        }
+    }
+}
+
+fn split(stream: Box<dyn Io>) -> (Reader, Writer) {
+    let (reader, writer) = tokio::io::split(stream);
+    (XmlReader::new(BufReader::new(reader)), Writer::new(writer))
+}
+
+async fn login(account: &Account) -> Result<Session, String> {
+    let domain = account.jid.domain.as_str();
+    let address = account.address();
+    let tcp = timeout(CONNECT_TIMEOUT, TcpStream::connect(&address))
+        .await
+        .map_err(|_| format!("{address}: timed out"))?
+        .map_err(|e| format!("{address}: {e}"))?;
+    let mut stream: Box<dyn Io> = Box::new(tcp);
+    match account.tls {
+        Tls::Direct => stream = tls(domain, stream).await?,
+        Tls::Starttls => {
+            let (mut reader, writer) = split(stream);
+            let offered = features(&mut reader, &writer, domain).await?;
+            if offered.child("starttls").is_none() {
+                return Err("server does not offer STARTTLS".to_string());
+            }
+            writer
+                .send("<starttls xmlns='urn:ietf:params:xml:ns:xmpp-tls'/>")
+                .await?;
+            if reader.next().await?.name != "proceed" {
+                return Err("server refused STARTTLS".to_string());
+            }
+            let Ok(writer) = Arc::try_unwrap(writer.writer) else {
+                return Err("STARTTLS: stream still in use".to_string());
+            };
+            let plain = reader
+                .into_inner()
+                .into_inner()
+                .unsplit(writer.into_inner());
+            stream = tls(domain, plain).await?;
+        }
+        Tls::None => {}
+    }
+
+    let (mut reader, writer) = split(stream);
+    let offered = features(&mut reader, &writer, domain).await?;
+    let mechanisms: Vec<&str> = offered
+        .child("mechanisms")
+        .map(|mechanisms| {
+            mechanisms
+                .children
+                .iter()
+                .map(|m| m.text.as_str())
+                .collect()
+        })
+        .unwrap_or_default();
+    if !mechanisms.contains(&"PLAIN") {
+        return Err(format!(
+            "server offers no SASL PLAIN (offers: {})",
+            mechanisms.join(", ")
+        ));
+    }
+    let credentials = BASE64.encode(format!("\0{}\0{}", account.jid.local, account.password));
+    writer
+        .send(&format!(
+            "<auth xmlns='urn:ietf:params:xml:ns:xmpp-sasl' mechanism='PLAIN'>{credentials}</auth>"
+        ))
+        .await?;
+    let outcome = reader.next().await?;
+    if outcome.name != "success" {
+        let condition = outcome.children.first().map_or("", |c| c.name.as_str());
+        return Err(format!("authentication failed: {condition}"));
+    }
+
+    let offered = features(&mut reader, &writer, domain).await?;
+    let resource = account.jid.resource.as_deref().unwrap_or("emergent");
+    writer
+        .send(&format!(
+            "<iq type='set' id='bind'><bind xmlns='urn:ietf:params:xml:ns:xmpp-bind'>\
+             <resource>{}</resource></bind></iq>",
+            escape(resource)
+        ))
+        .await?;
+    let jid = loop {
+        let reply = reader.next().await?;
+        if reply.name != "iq" || reply.attr("id") != Some("bind") {
+            continue;
+        }
+        if reply.attr("type") != Some("result") {
+            return Err("resource binding failed".to_string());
+        }
+        break reply
+            .child("bind")
+            .and_then(|bind| bind.child("jid"))
+            .map_or_else(|| account.jid.to_string(), |jid| jid.text.clone());
+    };
+    // RFC 3921 session establishment, for servers that still require it
+    if offered
+        .child("session")
+        .is_some_and(|session| session.child("optional").is_none())
+    {
+        writer
+            .send("<iq type='set' id='session'><session xmlns='urn:ietf:params:xml:ns:xmpp-session'/></iq>")
+            .await?;
+        loop {
+            let reply = reader.next().await?;
+            if reply.name == "iq" && reply.attr("id") == Some("session") {
+                break;
+            }
+        }
+    }
+    Ok(Session {
+        reader,
+        writer,
+        jid,
+    })
+}
+
+/// Connect, authenticate and bind a resource.
+pub async fn connect(account: &Account) -> Result<Session, String> {
+    let address = account.address();
+    timeout(LOGIN_TIMEOUT, login(account))
+        .await
+        .map_err(|_| format!("{address}: login timed out"))?
+        .map_err(|e| format!("{address}: {e}"))
+}
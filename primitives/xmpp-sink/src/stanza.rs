@@ -0,0 +1,141 @@
+//! Addresses and the stanzas the sink sends.
+
+use clap::ValueEnum;
+use quick_xml::escape::escape;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// `local@domain/resource`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Jid {
+    pub local: String,
+    pub domain: String,
+    pub resource: Option<String>,
+}
+
+impl Jid {
+    /// Parse an account JID; the local part is required.
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let (bare, resource) = match s.split_once('/') {
+            Some((bare, resource)) if !resource.is_empty() => (bare, Some(resource.to_string())),
+            _ => (s.trim_end_matches('/'), None),
+        };
+        match bare.split_once('@') {
+            Some((local, domain)) if !local.is_empty() && !domain.is_empty() => Ok(Self {
+                local: local.to_string(),
+                domain: domain.to_string(),
+                resource,
+            }),
+            _ => Err(format!("expected user@domain[/resource], got '{s}'")),
+        }
+    }
+}
+
+impl fmt::Display for Jid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}@{}", self.local, self.domain)?;
+        match &self.resource {
+            Some(resource) => write!(f, "/{resource}"),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Availability shown to contacts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Show {
+    Online,
+    Chat,
+    Away,
+    Xa,
+    Dnd,
+}
+
+/// The header opening a stream to `domain`.
+pub fn open_stream(domain: &str) -> String {
+    format!(
+        "<?xml version='1.0'?><stream:stream to='{}' version='1.0' xml:lang='en' \
+         xmlns='jabber:client' xmlns:stream='http://etherx.jabber.org/streams'>",
+        escape(domain)
+    )
+}
+
+/// Our presence, to everyone or to `to` (a room occupant JID when joining).
+pub fn presence(show: Show, status: Option<&str>, to: Option<&str>, join_room: bool) -> String {
+    let mut xml = String::from("<presence");
+    if let Some(to) = to {
+        xml.push_str(&format!(" to='{}'", escape(to)));
+    }
+    xml.push('>');
+    let show = match show {
+        Show::Online => None,
+        Show::Chat => Some("chat"),
+        Show::Away => Some("away"),
+        Show::Xa => Some("xa"),
+        Show::Dnd => Some("dnd"),
+    };
+    if let Some(show) = show {
+        xml.push_str(&format!("<show>{show}</show>"));
+    }
+    if let Some(status) = status {
+        xml.push_str(&format!("<status>{}</status>", escape(status)));
+    }
+    if join_room {
+        // Don't replay the room's history to us
+        xml.push_str("<x xmlns='http://jabber.org/protocol/muc'><history maxstanzas='0'/></x>");
+    }
+    xml.push_str("</presence>");
+    xml
+}
+
+/// A reply to a presence stanza of `kind` (`subscribed`, ...).
+pub fn presence_reply(kind: &str, to: &str) -> String {
+    format!("<presence type='{kind}' to='{}'/>", escape(to))
+}
+
+/// A `chat` message to a JID, or a `groupchat` message to a room.
+pub fn message(to: &str, groupchat: bool, id: &str, body: &str) -> String {
+    let kind = if groupchat { "groupchat" } else { "chat" };
+    format!(
+        "<message to='{}' type='{kind}' id='{}'><body>{}</body></message>",
+        escape(to),
+        escape(id),
+        escape(body)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stanzas_escape_what_they_carry() {
+        let jid = Jid::parse("alerts@example.org/emergent").unwrap_or_else(|e| panic!("{e}"));
+        assert_eq!(jid.local, "alerts");
+        assert_eq!(jid.to_string(), "alerts@example.org/emergent");
+        assert!(Jid::parse("example.org").is_err());
+
+        assert_eq!(
+            message(
+                "ops@conference.example.org",
+                true,
+                "m1",
+                "disk <90% & 'falling'"
+            ),
+            "<message to='ops@conference.example.org' type='groupchat' id='m1'>\
+             <body>disk &lt;90% &amp; &apos;falling&apos;</body></message>"
+        );
+        assert_eq!(
+            presence(
+                Show::Dnd,
+                Some("on call"),
+                Some("ops@conference.example.org/bot"),
+                true
+            ),
+            "<presence to='ops@conference.example.org/bot'><show>dnd</show>\
+             <status>on call</status><x xmlns='http://jabber.org/protocol/muc'>\
+             <history maxstanzas='0'/></x></presence>"
+        );
+    }
+}
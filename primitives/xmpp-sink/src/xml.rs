@@ -0,0 +1,184 @@
+//! Reading stanzas off an XML stream.
+//!
+//! An XMPP connection is one long XML document per direction: a
+//! `<stream:stream>` root whose children are the stanzas. [`XmlReader`]
+//! returns those children one at a time as small [`Element`] trees, and
+//! takes a reopened stream header (after STARTTLS or authentication) in
+//! its stride. Names are local names; namespaces are not checked.
+
+use quick_xml::Reader;
+use quick_xml::escape::resolve_predefined_entity;
+use quick_xml::events::{BytesStart, Event};
+use tokio::io::AsyncBufRead;
+
+/// One element with its attributes, children and text.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Element {
+    pub name: String,
+    pub attrs: Vec<(String, String)>,
+    pub children: Vec<Element>,
+    pub text: String,
+}
+
+impl Element {
+    pub fn attr(&self, name: &str) -> Option<&str> {
+        self.attrs
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn child(&self, name: &str) -> Option<&Element> {
+        self.children.iter().find(|child| child.name == name)
+    }
+}
+
+fn element(start: &BytesStart<'_>) -> Result<Element, String> {
+    let name = String::from_utf8_lossy(start.local_name().as_ref()).into_owned();
+    let mut attrs = Vec::new();
+    for attr in start.attributes() {
+        let attr = attr.map_err(|e| format!("bad attribute in <{name}>: {e}"))?;
+        let value = attr
+            .unescape_value()
+            .map_err(|e| format!("bad attribute in <{name}>: {e}"))?;
+        attrs.push((
+            String::from_utf8_lossy(attr.key.as_ref()).into_owned(),
+            value.into_owned(),
+        ));
+    }
+    Ok(Element {
+        name,
+        attrs,
+        ..Element::default()
+    })
+}
+
+/// Reads the stanzas of an incoming stream.
+pub struct XmlReader<R> {
+    reader: Reader<R>,
+    buf: Vec<u8>,
+}
+
+impl<R: AsyncBufRead + Unpin> XmlReader<R> {
+    pub fn new(inner: R) -> Self {
+        let mut reader = Reader::from_reader(inner);
+        // The root stays open for the whole connection
+        reader.config_mut().check_end_names = false;
+        Self {
+            reader,
+            buf: Vec::new(),
+        }
+    }
+
+    pub fn into_inner(self) -> R {
+        self.reader.into_inner()
+    }
+
+    /// The next stanza. A stream header is returned as an element named
+    /// `stream` without children; the end of the stream is an `Err`.
+    pub async fn next(&mut self) -> Result<Element, String> {
+        let mut stack: Vec<Element> = Vec::new();
+        loop {
+            self.buf.clear();
+            let event = self
+                .reader
+                .read_event_into_async(&mut self.buf)
+                .await
+                .map_err(|e| format!("XML: {e}"))?;
+            match event {
+                Event::Start(start) => {
+                    let element = element(&start)?;
+                    if stack.is_empty() && element.name == "stream" {
+                        return Ok(element);
+                    }
+                    stack.push(element);
+                }
+                Event::Empty(start) => {
+                    let element = element(&start)?;
+                    match stack.last_mut() {
+                        Some(parent) => parent.children.push(element),
+                        None => return Ok(element),
+                    }
+                }
+                Event::End(_) => {
+                    let Some(element) = stack.pop() else {
+                        return Err("stream closed by the server".to_string());
+                    };
+                    match stack.last_mut() {
+                        Some(parent) => parent.children.push(element),
+                        None => return Ok(element),
+                    }
+                }
+                Event::Text(text) => {
+                    if let Some(element) = stack.last_mut() {
+                        let text = text.xml_content().map_err(|e| format!("XML: {e}"))?;
+                        element.text.push_str(&text);
+                    }
+                }
+                Event::CData(data) => {
+                    if let Some(element) = stack.last_mut() {
+                        element.text.push_str(&String::from_utf8_lossy(&data));
+                    }
+                }
+                Event::GeneralRef(reference) => {
+                    if let Some(element) = stack.last_mut() {
+                        if let Some(c) = reference
+                            .resolve_char_ref()
+                            .map_err(|e| format!("XML: {e}"))?
+                        {
+                            element.text.push(c);
+                        } else {
+                            let name = reference.decode().map_err(|e| format!("XML: {e}"))?;
+                            let resolved = resolve_predefined_entity(&name)
+                                .ok_or_else(|| format!("XML: unknown entity &{name};"))?;
+                            element.text.push_str(resolved);
+                        }
+                    }
+                }
+                Event::Eof => return Err("connection closed".to_string()),
+                Event::Decl(_) | Event::PI(_) | Event::Comment(_) | Event::DocType(_) => {}
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn stanzas_are_read_one_at_a_time() {
+        let stream = concat!(
+            "<?xml version='1.0'?><stream:stream xmlns='jabber:client' ",
+            "xmlns:stream='http://etherx.jabber.org/streams' id='s1' version='1.0'>",
+            "<stream:features><mechanisms xmlns='urn:ietf:params:xml:ns:xmpp-sasl'>",
+            "<mechanism>PLAIN</mechanism></mechanisms></stream:features> ",
+            "<message from='ops@conference.example.org/alice' type='groupchat'>",
+            "<body>disk &lt;90% &amp; falling&#33;</body></message>",
+            "</stream:stream>",
+        );
+        let mut reader = XmlReader::new(stream.as_bytes());
+
+        let header = reader.next().await.unwrap_or_else(|e| panic!("{e}"));
+        assert_eq!(
+            (header.name.as_str(), header.attr("id")),
+            ("stream", Some("s1"))
+        );
+
+        let features = reader.next().await.unwrap_or_else(|e| panic!("{e}"));
+        let mechanism = features
+            .child("mechanisms")
+            .and_then(|mechanisms| mechanisms.child("mechanism"))
+            .map(|mechanism| mechanism.text.as_str());
+        assert_eq!(mechanism, Some("PLAIN"));
+
+        let message = reader.next().await.unwrap_or_else(|e| panic!("{e}"));
+        assert_eq!(message.attr("type"), Some("groupchat"));
+        assert_eq!(
+            message.child("body").map(|body| body.text.as_str()),
+            Some("disk <90% & falling!")
+        );
+
+        assert!(reader.next().await.is_err());
+    }
+}
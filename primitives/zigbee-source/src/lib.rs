@@ -0,0 +1,476 @@
+//! Zigbee Source - Normalized Home Automation Events
+//!
+//! A Source that subscribes to zigbee2mqtt on an MQTT broker and turns the
+//! device-specific states it publishes into normalized events (see
+//! [`normalize`]): `sensor.temperature`, `sensor.contact`, `sensor.motion`,
+//! `button.pressed` and `device.battery_low`. Each carries the device's
+//! friendly name, and its display name, room, IEEE address, model and
+//! vendor as far as they are known (see [`registry`]), so consumers never
+//! see which sensor brand reported what.
+//!
+//! Device states are read from `<base>/<friendly name>`; the device list
+//! from `<base>/bridge/devices`. A lost connection is re-established, and
+//! the subscription renewed, every few seconds.
+//!
+//! Sources are SILENT - they only produce domain messages.
+//! All lifecycle events are published by the engine.
+//!
+//! # Usage
+//!
+//! ```bash
+//! zigbee-source --broker mqtt://homeassistant.local:1883 \
+//!   --username emergent --password "$MQTT_PASSWORD" \
+//!   --devices /etc/emergent/zigbee-devices.yaml --battery-threshold 15
+//! ```
+//!
+//! On SIGTERM the connection is closed, with `--drain-timeout`
+//! milliseconds for the broker to be told.
+
+pub mod normalize;
+pub mod registry;
+
+use clap::Parser;
+use emergent_client::EmergentSource;
+use normalize::{EVENT_TYPES, Normalizer};
+use primitive_common::capabilities::{self, CapabilityArgs, Role, VERSION, capabilities_message};
+use primitive_common::doctor::Report;
+use primitive_common::source::{Outlet, SourceArgs};
+use registry::Registry;
+use rumqttc::{AsyncClient, Event, EventLoop, MqttOptions, Outgoing, Packet, QoS, Transport};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::signal::unix::{SignalKind, signal};
+
+/// Variables available to `--emit-type-template`.
+const TEMPLATE_VARIABLES: &[&str] = &["source"];
+
+/// Delay before a lost connection is retried.
+const RECONNECT: Duration = Duration::from_secs(5);
+
+/// Largest packet accepted; the device list of a large network runs to
+/// hundreds of kilobytes.
+const MAX_PACKET: usize = 16 * 1024 * 1024;
+
+/// Topics under the base that are not device states.
+const IGNORED_SUFFIXES: [&str; 4] = ["/set", "/get", "/availability", "/action"];
+
+/// zigbee2mqtt watcher that emits normalized sensor and button events.
+#[derive(Parser, Debug, Clone)]
+#[command(name = "zigbee-source", version = VERSION)]
+#[command(about = "Normalizes zigbee2mqtt device states into sensor, button and battery events")]
+struct Args {
+    /// MQTT broker as mqtt://HOST[:PORT] or mqtts://HOST[:PORT].
+    #[arg(
+        long,
+        env = "ZIGBEE_SOURCE_BROKER",
+        default_value = "mqtt://localhost:1883"
+    )]
+    broker: String,
+
+    /// MQTT user name.
+    #[arg(long, env = "ZIGBEE_SOURCE_USERNAME")]
+    username: Option<String>,
+
+    /// MQTT password.
+    #[arg(long, env = "ZIGBEE_SOURCE_PASSWORD", requires = "username")]
+    password: Option<String>,
+
+    /// MQTT client id.
+    #[arg(
+        long,
+        env = "ZIGBEE_SOURCE_CLIENT_ID",
+        default_value = "emergent-zigbee-source"
+    )]
+    client_id: String,
+
+    /// zigbee2mqtt's base topic.
+    #[arg(long, env = "ZIGBEE_SOURCE_BASE_TOPIC", default_value = "zigbee2mqtt")]
+    base_topic: String,
+
+    /// YAML or JSON file giving devices a display name and room, keyed by
+    /// IEEE address or friendly name.
+    #[arg(long, env = "ZIGBEE_SOURCE_DEVICES")]
+    devices: Option<PathBuf>,
+
+    /// Battery percentage at or below which `device.battery_low` is sent.
+    #[arg(long, env = "ZIGBEE_SOURCE_BATTERY_THRESHOLD", default_value = "20")]
+    battery_threshold: f64,
+
+    /// Seconds between MQTT keep-alives.
+    #[arg(long, env = "ZIGBEE_SOURCE_KEEP_ALIVE", default_value = "30")]
+    keep_alive: u64,
+
+    /// Verify external dependencies and exit without connecting to the engine.
+    #[arg(long)]
+    self_test: bool,
+
+    #[command(flatten)]
+    source: SourceArgs,
+
+    #[command(flatten)]
+    capabilities: CapabilityArgs,
+}
+
+/// MQTT options for `broker`.
+fn mqtt_options(args: &Args) -> Result<MqttOptions, String> {
+    let (tls, rest) = if let Some(rest) = args.broker.strip_prefix("mqtts://") {
+        (true, rest)
+    } else if let Some(rest) = args.broker.strip_prefix("mqtt://") {
+        (false, rest)
+    } else {
+        return Err(format!(
+            "'{}': expected mqtt://HOST[:PORT] or mqtts://HOST[:PORT]",
+            args.broker
+        ));
+    };
+    let rest = rest.trim_end_matches('/');
+    let (host, port) = match rest.rsplit_once(':') {
+        Some((host, port)) => (
+            host,
+            port.parse::<u16>()
+                .map_err(|_| format!("'{}': bad port", args.broker))?,
+        ),
+        None => (rest, if tls { 8883 } else { 1883 }),
+    };
+    if host.is_empty() {
+        return Err(format!("'{}': no host", args.broker));
+    }
+    let mut options = MqttOptions::new(&args.client_id, host, port);
+    options
+        .set_keep_alive(Duration::from_secs(args.keep_alive.max(5)))
+        .set_max_packet_size(MAX_PACKET, MAX_PACKET);
+    if tls {
+        options.set_transport(Transport::tls_with_default_config());
+    }
+    if let Some(username) = &args.username {
+        options.set_credentials(username, args.password.clone().unwrap_or_default());
+    }
+    Ok(options)
+}
+
+/// Turns zigbee2mqtt messages into events.
+struct Zigbee {
+    base: String,
+    registry: Registry,
+    normalizer: Normalizer,
+}
+
+impl Zigbee {
+    /// The events a message on `topic` gives rise to.
+    fn message(
+        &mut self,
+        topic: &str,
+        payload: &[u8],
+        retained: bool,
+    ) -> Vec<(&'static str, Value)> {
+        let Some(rest) = topic
+            .strip_prefix(self.base.as_str())
+            .and_then(|rest| rest.strip_prefix('/'))
+        else {
+            return Vec::new();
+        };
+        if rest == "bridge/devices" {
+            match serde_json::from_slice(payload) {
+                Ok(devices) => {
+                    let count = self.registry.update(&devices);
+                    eprintln!("Device list updated: {count} devices");
+                }
+                Err(e) => eprintln!("Ignoring device list: {e}"),
+            }
+            return Vec::new();
+        }
+        if rest.is_empty()
+            || rest.starts_with("bridge/")
+            || IGNORED_SUFFIXES.iter().any(|suffix| rest.ends_with(suffix))
+            || rest.contains("/set/")
+        {
+            return Vec::new();
+        }
+        // Plain-text states from legacy settings are not device states
+        let Ok(state @ Value::Object(_)) = serde_json::from_slice::<Value>(payload) else {
+            return Vec::new();
+        };
+        self.normalizer
+            .observe(rest, &state, retained)
+            .into_iter()
+            .map(|reading| {
+                let mut payload = self.registry.describe(rest);
+                if let Value::Object(fields) = reading.fields {
+                    payload.extend(fields);
+                }
+                (reading.event_type, Value::Object(payload))
+            })
+            .collect()
+    }
+}
+
+/// Publish the events a message on `topic` gives rise to; the outlet logs
+/// and reports those it loses.
+async fn forward(
+    outlet: &Outlet,
+    zigbee: &mut Zigbee,
+    topic: &str,
+    payload: &[u8],
+    retained: bool,
+) {
+    for (event_type, payload) in zigbee.message(topic, payload, retained) {
+        let _ = outlet.publish(event_type, &[], payload).await;
+    }
+}
+
+/// Wait for the broker to accept the connection.
+async fn connected(eventloop: &mut EventLoop) -> Result<(), String> {
+    loop {
+        match eventloop.poll().await {
+            Ok(Event::Incoming(Packet::ConnAck(_))) => return Ok(()),
+            Ok(_) => {}
+            Err(e) => return Err(e.to_string()),
+        }
+    }
+}
+
+/// Runs `--self-test` checks and exits.
+async fn self_test(args: &Args, options: MqttOptions, name: &str) -> ! {
+    let mut report = Report::new(name);
+    let (_client, mut eventloop) = AsyncClient::new(options, 10);
+    let connection = tokio::time::timeout(Duration::from_secs(10), connected(&mut eventloop))
+        .await
+        .unwrap_or_else(|_| Err("timed out".to_string()))
+        .map(|()| format!("connected to {}", args.broker));
+    report.check("broker", connection);
+    if let Some(path) = &args.devices {
+        let labels = Registry::load_labels(path).map(|labels| format!("{} devices", labels.len()));
+        report.check("devices", labels);
+    }
+    args.source.self_test(&mut report, TEMPLATE_VARIABLES);
+    report.finish()
+}
+
+/// Run the primitive with `args` as its command line (the first item is
+/// the program name, as with `std::env::args_os`).
+pub async fn run<I, T>(args: I) -> Result<(), Box<dyn std::error::Error>>
+where
+    I: IntoIterator<Item = T>,
+    T: Into<std::ffi::OsString> + Clone,
+{
+    let args = Args::parse_from(args);
+
+    // Get the source name from environment (set by engine) or use default
+    let name = std::env::var("EMERGENT_NAME").unwrap_or_else(|_| "zigbee-source".to_string());
+
+    let exit = |e: String| -> ! {
+        eprintln!("Error: {e}");
+        std::process::exit(1);
+    };
+    let options = mqtt_options(&args).unwrap_or_else(|e| exit(format!("--broker: {e}")));
+    if args.self_test {
+        self_test(&args, options, &name).await;
+    }
+    if let Err(e) = args.source.validate(TEMPLATE_VARIABLES) {
+        exit(e);
+    }
+    let labels = match &args.devices {
+        Some(path) => {
+            Registry::load_labels(path).unwrap_or_else(|e| exit(format!("--devices: {e}")))
+        }
+        None => HashMap::new(),
+    };
+    let base = args.base_topic.trim_end_matches('/').to_string();
+    let mut zigbee = Zigbee {
+        base: base.clone(),
+        registry: Registry::new(labels),
+        normalizer: Normalizer::new(args.battery_threshold),
+    };
+
+    let produces = args.source.produces(&EVENT_TYPES);
+    let produces: Vec<&str> = produces.iter().map(String::as_str).collect();
+    let descriptor = args
+        .capabilities
+        .describe(&name, Role::Source, &[], &produces, &args);
+    if args.capabilities.capabilities {
+        capabilities::print(&descriptor);
+    }
+
+    // Connect to the Emergent engine (silently - lifecycle events come from engine)
+    let source = match EmergentSource::connect(&name).await {
+        Ok(s) => s,
+        Err(e) => {
+            eprintln!("Failed to connect to Emergent engine: {e}");
+            std::process::exit(1);
+        }
+    };
+    let outlet = Arc::new(Outlet::new(source, &name, &args.source)?);
+    if args.capabilities.announce {
+        outlet.announce(capabilities_message(&descriptor)).await;
+    }
+    tokio::spawn(Arc::clone(&outlet).drain_every(args.source.spool.retry_interval()));
+
+    let (client, mut eventloop) = AsyncClient::new(options, 100);
+    let topic = format!("{base}/#");
+    let mut sigterm = signal(SignalKind::terminate())?;
+    loop {
+        let event = tokio::select! {
+            _ = sigterm.recv() => break,
+            event = eventloop.poll() => event,
+        };
+        match event {
+            Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                eprintln!("Connected to {}", args.broker);
+                // A clean session forgets subscriptions, so renew on every connect
+                if let Err(e) = client.subscribe(topic.as_str(), QoS::AtLeastOnce).await {
+                    eprintln!("Failed to subscribe to {topic}: {e}");
+                }
+            }
+            Ok(Event::Incoming(Packet::Publish(publish))) => {
+                let (topic, payload) = (&publish.topic, &publish.payload);
+                forward(&outlet, &mut zigbee, topic, payload, publish.retain).await;
+            }
+            Ok(_) => {}
+            Err(e) => {
+                eprintln!("MQTT connection to {} failed: {e}", args.broker);
+                tokio::select! {
+                    _ = sigterm.recv() => break,
+                    () = tokio::time::sleep(RECONNECT) => {}
+                }
+            }
+        }
+    }
+    // The DISCONNECT only goes out while the event loop is polled
+    let _ = client.disconnect().await;
+    let disconnect = async {
+        while let Ok(event) = eventloop.poll().await {
+            if matches!(event, Event::Outgoing(Outgoing::Disconnect)) {
+                break;
+            }
+        }
+    };
+    args.source.drain.drain("MQTT disconnect", disconnect).await;
+    outlet.disconnect().await;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use emergent_testkit::{MockEngine, fixtures};
+    use serde_json::json;
+
+    #[test]
+    fn states_become_events_with_device_details() {
+        let labels = HashMap::from([(
+            "0x00158d0001a2b3c4".to_string(),
+            registry::Label {
+                name: Some("Front door".to_string()),
+                room: Some("Hallway".to_string()),
+            },
+        )]);
+        let mut zigbee = Zigbee {
+            base: "zigbee2mqtt".to_string(),
+            registry: Registry::new(labels),
+            normalizer: Normalizer::new(20.0),
+        };
+        let devices = json!([{
+            "type": "EndDevice", "friendly_name": "hall/door", "ieee_address": "0x00158d0001a2b3c4",
+            "definition": {"model": "MCCGQ11LM", "vendor": "Aqara"},
+        }]);
+        let devices = devices.to_string();
+        assert!(
+            zigbee
+                .message("zigbee2mqtt/bridge/devices", devices.as_bytes(), true)
+                .is_empty()
+        );
+
+        let events = zigbee.message(
+            "zigbee2mqtt/hall/door",
+            br#"{"contact":false,"battery":91}"#,
+            false,
+        );
+        assert_eq!(events.len(), 1);
+        let (event_type, payload) = &events[0];
+        assert_eq!(*event_type, "sensor.contact");
+        assert_eq!(
+            *payload,
+            json!({
+                "device": "hall/door", "name": "Front door", "room": "Hallway",
+                "ieee_address": "0x00158d0001a2b3c4", "model": "MCCGQ11LM", "vendor": "Aqara",
+                "contact": false, "open": true,
+            })
+        );
+
+        for topic in [
+            "zigbee2mqtt/hall/door/set",
+            "zigbee2mqtt/hall/door/availability",
+            "zigbee2mqtt/bridge/state",
+            "other/hall/door",
+        ] {
+            assert!(
+                zigbee
+                    .message(topic, br#"{"contact":true}"#, false)
+                    .is_empty(),
+                "{topic}"
+            );
+        }
+        assert!(
+            zigbee
+                .message("zigbee2mqtt/hall/door", b"online", false)
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn brokers_parse() {
+        let args = |broker: &str| Args::parse_from(["zigbee-source", "--broker", broker]);
+        let options =
+            mqtt_options(&args("mqtts://broker.example.com")).unwrap_or_else(|e| panic!("{e}"));
+        assert_eq!(
+            options.broker_address(),
+            ("broker.example.com".to_string(), 8883)
+        );
+        let options =
+            mqtt_options(&args("mqtt://10.0.0.2:1884/")).unwrap_or_else(|e| panic!("{e}"));
+        assert_eq!(options.broker_address(), ("10.0.0.2".to_string(), 1884));
+        assert!(mqtt_options(&args("tcp://10.0.0.2")).is_err());
+    }
+
+    #[tokio::test]
+    async fn events_survive_the_engine_being_down() {
+        let dir = fixtures::TempDir::new("zigbee-source-spool");
+        let socket = dir.path().join("engine.sock");
+        let spool = dir.path().join("spool");
+        let args = Args::parse_from([
+            "zigbee-source",
+            "--spool-dir",
+            spool.to_str().unwrap_or_default(),
+        ]);
+        let connect = || async {
+            let source = EmergentSource::connect_to("zigbee-source", &socket)
+                .await
+                .unwrap_or_else(|e| panic!("connect: {e}"));
+            Outlet::new(source, "zigbee-source", &args.source)
+                .unwrap_or_else(|e| panic!("open spool: {e}"))
+        };
+        let mut zigbee = Zigbee {
+            base: "zigbee2mqtt".to_string(),
+            registry: Registry::new(HashMap::new()),
+            normalizer: Normalizer::new(20.0),
+        };
+
+        let mut engine = MockEngine::serve(&socket);
+        let outlet = connect().await;
+        engine.shut_down().await;
+        let state = br#"{"contact":false}"#;
+        forward(&outlet, &mut zigbee, "zigbee2mqtt/hall/door", state, false).await;
+        drop(outlet);
+
+        let mut engine = MockEngine::serve(&socket);
+        connect().await.drain_spool().await;
+        let published = engine.expect_published("sensor.contact").await;
+        assert_eq!(published.payload()["device"], "hall/door");
+        assert_eq!(published.payload()["open"], true);
+        engine.shut_down().await;
+    }
+}
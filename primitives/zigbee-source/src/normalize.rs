@@ -0,0 +1,258 @@
+//! Turning device states into normalized events.
+//!
+//! zigbee2mqtt publishes a device's whole state whenever any part of it
+//! changes, with attribute names that vary between devices. A
+//! [`Normalizer`] remembers what it last saw of each device and reports
+//! only what changed, as:
+//!
+//! - `sensor.temperature` — `temperature` in °C (with `humidity` in % and
+//!   `pressure` in hPa when the device reports them)
+//! - `sensor.contact` — `contact` (`true` when closed) and `open`
+//! - `sensor.motion` — `motion`, from `occupancy` or `presence`
+//! - `button.pressed` — `action` as the device names it, the `press`
+//!   (`single`, `double`, `triple`, `long` or `release`) and the `button`
+//!   number on multi-button remotes; from `action`, or the older `click`
+//! - `device.battery_low` — when `battery` falls to the threshold or
+//!   `battery_low` turns on; again only after it has recovered
+//!
+//! Retained states, which the broker replays on connecting, are recorded
+//! without being reported, so a restart does not repeat old events.
+
+use serde_json::{Value, json};
+use std::collections::HashMap;
+
+pub const TEMPERATURE_EVENT_TYPE: &str = "sensor.temperature";
+pub const CONTACT_EVENT_TYPE: &str = "sensor.contact";
+pub const MOTION_EVENT_TYPE: &str = "sensor.motion";
+pub const BUTTON_EVENT_TYPE: &str = "button.pressed";
+pub const BATTERY_LOW_EVENT_TYPE: &str = "device.battery_low";
+
+/// Every event type a [`Normalizer`] reports.
+pub const EVENT_TYPES: [&str; 5] = [
+    TEMPERATURE_EVENT_TYPE,
+    CONTACT_EVENT_TYPE,
+    MOTION_EVENT_TYPE,
+    BUTTON_EVENT_TYPE,
+    BATTERY_LOW_EVENT_TYPE,
+];
+
+/// A normalized event, before the device's details are added.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Reading {
+    pub event_type: &'static str,
+    pub fields: Value,
+}
+
+/// What was last seen of a device.
+#[derive(Debug, Default)]
+struct Last {
+    temperature: Option<f64>,
+    contact: Option<bool>,
+    motion: Option<bool>,
+    battery_low: Option<bool>,
+}
+
+/// The press and button number an action names, like `2_double`.
+pub fn press(action: &str) -> (&'static str, Option<u64>) {
+    let action = action.to_ascii_lowercase();
+    let press = if action.contains("triple") {
+        "triple"
+    } else if action.contains("double") {
+        "double"
+    } else if action.contains("release") {
+        "release"
+    } else if action.contains("hold") || action.contains("long") {
+        "long"
+    } else {
+        "single"
+    };
+    let button = action
+        .split(['_', '-'])
+        .find_map(|part| part.parse::<u64>().ok());
+    (press, button)
+}
+
+/// Tracks devices' states and reports changes.
+#[derive(Debug)]
+pub struct Normalizer {
+    /// Battery percentage at or below which a battery is low.
+    battery_threshold: f64,
+    devices: HashMap<String, Last>,
+}
+
+impl Normalizer {
+    pub fn new(battery_threshold: f64) -> Self {
+        Self {
+            battery_threshold,
+            devices: HashMap::new(),
+        }
+    }
+
+    /// The events `state`, published for `device`, gives rise to.
+    pub fn observe(&mut self, device: &str, state: &Value, retained: bool) -> Vec<Reading> {
+        let last = self.devices.entry(device.to_string()).or_default();
+        let mut readings = Vec::new();
+        let mut report = |event_type, fields| {
+            if !retained {
+                readings.push(Reading { event_type, fields });
+            }
+        };
+
+        if let Some(temperature) = state["temperature"].as_f64()
+            && last.temperature.replace(temperature) != Some(temperature)
+        {
+            let mut fields = json!({"temperature": temperature});
+            for extra in ["humidity", "pressure"] {
+                if let Some(value) = state[extra].as_f64() {
+                    fields[extra] = Value::from(value);
+                }
+            }
+            report(TEMPERATURE_EVENT_TYPE, fields);
+        }
+
+        if let Some(contact) = state["contact"].as_bool()
+            && last.contact.replace(contact) != Some(contact)
+        {
+            report(
+                CONTACT_EVENT_TYPE,
+                json!({"contact": contact, "open": !contact}),
+            );
+        }
+
+        let motion = state["occupancy"]
+            .as_bool()
+            .or_else(|| state["presence"].as_bool());
+        if let Some(motion) = motion
+            && last.motion.replace(motion) != Some(motion)
+        {
+            report(MOTION_EVENT_TYPE, json!({"motion": motion}));
+        }
+
+        let action = state["action"]
+            .as_str()
+            .or_else(|| state["click"].as_str())
+            .filter(|action| !action.is_empty());
+        if let Some(action) = action {
+            let (press, button) = press(action);
+            report(
+                BUTTON_EVENT_TYPE,
+                json!({"action": action, "press": press, "button": button}),
+            );
+        }
+
+        let battery = state["battery"].as_f64();
+        let low = match (battery, state["battery_low"].as_bool()) {
+            (_, Some(true)) => Some(true),
+            (Some(battery), _) => Some(battery <= self.battery_threshold),
+            (None, low) => low,
+        };
+        if let Some(low) = low
+            && last.battery_low.replace(low) != Some(low)
+            && low
+        {
+            report(
+                BATTERY_LOW_EVENT_TYPE,
+                json!({"battery": battery, "threshold": self.battery_threshold}),
+            );
+        }
+
+        readings
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn types(readings: &[Reading]) -> Vec<&'static str> {
+        readings.iter().map(|reading| reading.event_type).collect()
+    }
+
+    #[test]
+    fn only_changes_are_reported() {
+        let mut normalizer = Normalizer::new(20.0);
+
+        // Replayed on connecting: recorded, not reported
+        let state =
+            json!({"temperature": 21.5, "humidity": 40.1, "battery": 15, "linkquality": 90});
+        assert!(normalizer.observe("office", &state, true).is_empty());
+        let state =
+            json!({"temperature": 21.5, "humidity": 41.0, "battery": 15, "linkquality": 84});
+        assert!(normalizer.observe("office", &state, false).is_empty());
+
+        let state =
+            json!({"temperature": 21.9, "humidity": 41.0, "pressure": 1012.5, "battery": 14});
+        let readings = normalizer.observe("office", &state, false);
+        assert_eq!(
+            readings,
+            vec![Reading {
+                event_type: TEMPERATURE_EVENT_TYPE,
+                fields: json!({"temperature": 21.9, "humidity": 41.0, "pressure": 1012.5}),
+            }]
+        );
+
+        // A new battery, then a flat one again
+        assert!(
+            normalizer
+                .observe("office", &json!({"battery": 100}), false)
+                .is_empty()
+        );
+        let readings = normalizer.observe("office", &json!({"battery": 19}), false);
+        assert_eq!(
+            readings,
+            vec![Reading {
+                event_type: BATTERY_LOW_EVENT_TYPE,
+                fields: json!({"battery": 19.0, "threshold": 20.0}),
+            }]
+        );
+        assert!(
+            normalizer
+                .observe("office", &json!({"battery": 18}), false)
+                .is_empty()
+        );
+
+        let door = normalizer.observe(
+            "door",
+            &json!({"contact": false, "battery_low": true}),
+            false,
+        );
+        assert_eq!(types(&door), [CONTACT_EVENT_TYPE, BATTERY_LOW_EVENT_TYPE]);
+        assert_eq!(door[0].fields, json!({"contact": false, "open": true}));
+
+        let hallway = normalizer.observe("hallway", &json!({"occupancy": true}), false);
+        assert_eq!(hallway[0].fields, json!({"motion": true}));
+        assert!(
+            normalizer
+                .observe("hallway", &json!({"occupancy": true}), false)
+                .is_empty()
+        );
+        let hallway = normalizer.observe("hallway", &json!({"occupancy": false}), false);
+        assert_eq!(hallway[0].fields, json!({"motion": false}));
+    }
+
+    #[test]
+    fn button_actions_are_every_press() {
+        let mut normalizer = Normalizer::new(20.0);
+        for _ in 0..2 {
+            let readings = normalizer.observe("remote", &json!({"action": "2_double"}), false);
+            assert_eq!(
+                readings,
+                vec![Reading {
+                    event_type: BUTTON_EVENT_TYPE,
+                    fields: json!({"action": "2_double", "press": "double", "button": 2}),
+                }]
+            );
+        }
+        // The empty action zigbee2mqtt sends after each press
+        assert!(
+            normalizer
+                .observe("remote", &json!({"action": ""}), false)
+                .is_empty()
+        );
+
+        assert_eq!(press("single"), ("single", None));
+        assert_eq!(press("brightness_up_hold"), ("long", None));
+        assert_eq!(press("button_3_release"), ("release", Some(3)));
+        assert_eq!(press("long"), ("long", None));
+    }
+}
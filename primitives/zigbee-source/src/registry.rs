@@ -0,0 +1,153 @@
+//! Device names and rooms.
+//!
+//! zigbee2mqtt publishes its device list, retained, on
+//! `<base>/bridge/devices`; from it each friendly name is resolved to the
+//! device's IEEE address, model and vendor. A `--devices` file can give
+//! devices a display name and a room, keyed by IEEE address (which
+//! survives renames in zigbee2mqtt) or friendly name:
+//!
+//! ```yaml
+//! "0x00158d0001a2b3c4":
+//!   name: Front door
+//!   room: Hallway
+//! kitchen_sensor:
+//!   name: Kitchen temperature
+//!   room: Kitchen
+//! ```
+
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use std::path::Path;
+
+/// A device as zigbee2mqtt lists it.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Device {
+    pub ieee_address: String,
+    pub model: Option<String>,
+    pub vendor: Option<String>,
+    pub description: Option<String>,
+}
+
+/// A display name and room from the `--devices` file.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Label {
+    pub name: Option<String>,
+    pub room: Option<String>,
+}
+
+/// Known devices, by friendly name, and their labels.
+#[derive(Debug, Default)]
+pub struct Registry {
+    devices: HashMap<String, Device>,
+    labels: HashMap<String, Label>,
+}
+
+impl Registry {
+    pub fn new(labels: HashMap<String, Label>) -> Self {
+        Self {
+            devices: HashMap::new(),
+            labels,
+        }
+    }
+
+    /// Read a `--devices` file (YAML, or JSON).
+    pub fn load_labels(path: &Path) -> Result<HashMap<String, Label>, String> {
+        let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {e}", path.display()))?;
+        serde_yaml_ng::from_str(&text).map_err(|e| format!("{}: {e}", path.display()))
+    }
+
+    /// Replace the device list with a `bridge/devices` payload. Returns
+    /// how many devices it lists.
+    pub fn update(&mut self, devices: &Value) -> usize {
+        let text = |value: &Value| value.as_str().map(str::to_string);
+        self.devices = devices
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter(|device| device["type"] != "Coordinator")
+            .filter_map(|device| {
+                let friendly_name = device["friendly_name"].as_str()?;
+                let definition = &device["definition"];
+                Some((
+                    friendly_name.to_string(),
+                    Device {
+                        ieee_address: text(&device["ieee_address"]).unwrap_or_default(),
+                        model: text(&definition["model"]),
+                        vendor: text(&definition["vendor"]),
+                        description: text(&definition["description"]),
+                    },
+                ))
+            })
+            .collect();
+        self.devices.len()
+    }
+
+    /// What is known about `friendly_name`, as payload fields.
+    pub fn describe(&self, friendly_name: &str) -> Map<String, Value> {
+        let device = self.devices.get(friendly_name);
+        let label = device
+            .and_then(|device| self.labels.get(&device.ieee_address))
+            .or_else(|| self.labels.get(friendly_name));
+        let mut fields = Map::new();
+        fields.insert("device".to_string(), Value::from(friendly_name));
+        fields.insert(
+            "name".to_string(),
+            Value::from(
+                label
+                    .and_then(|label| label.name.as_deref())
+                    .unwrap_or(friendly_name),
+            ),
+        );
+        fields.insert(
+            "room".to_string(),
+            label.and_then(|label| label.room.clone()).into(),
+        );
+        fields.insert(
+            "ieee_address".to_string(),
+            device.map(|device| device.ieee_address.clone()).into(),
+        );
+        fields.insert(
+            "model".to_string(),
+            device.and_then(|device| device.model.clone()).into(),
+        );
+        fields.insert(
+            "vendor".to_string(),
+            device.and_then(|device| device.vendor.clone()).into(),
+        );
+        fields
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn devices_resolve_to_labels_by_address_or_name() {
+        let labels: HashMap<String, Label> = serde_yaml_ng::from_str(
+            "\"0x00158d0001a2b3c4\":\n  name: Front door\n  room: Hallway\nkitchen_sensor:\n  room: Kitchen\n",
+        )
+        .unwrap_or_else(|e| panic!("{e}"));
+        let mut registry = Registry::new(labels);
+        let listed = registry.update(&json!([
+            {"type": "Coordinator", "friendly_name": "Coordinator", "ieee_address": "0x00124b0000000000"},
+            {"type": "EndDevice", "friendly_name": "door_sensor", "ieee_address": "0x00158d0001a2b3c4",
+             "definition": {"model": "MCCGQ11LM", "vendor": "Aqara", "description": "Door and window sensor"}},
+        ]));
+        assert_eq!(listed, 1);
+
+        let door = registry.describe("door_sensor");
+        assert_eq!(door["name"], "Front door");
+        assert_eq!(door["room"], "Hallway");
+        assert_eq!(door["model"], "MCCGQ11LM");
+        assert_eq!(door["vendor"], "Aqara");
+
+        let kitchen = registry.describe("kitchen_sensor");
+        assert_eq!(kitchen["name"], "kitchen_sensor");
+        assert_eq!(kitchen["room"], "Kitchen");
+        assert_eq!(kitchen["ieee_address"], Value::Null);
+    }
+}